@@ -0,0 +1,168 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use micro_http::Request;
+
+/// `Authorization` header, which carries the API token.
+const AUTHORIZATION_HEADER: &str = "Authorization";
+/// Authentication scheme of the API token.
+const BEARER_SCHEME: &str = "Bearer";
+
+/// Errors associated with the API authentication token.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiTokenError {
+    /// The token cannot be read.
+    #[error("Cannot read the API token: {0}")]
+    Read(#[from] io::Error),
+    /// The token is empty or contains characters other than printable ASCII ones.
+    #[error(
+        "The API token must be a non-empty string of printable ASCII characters, without \
+         whitespace."
+    )]
+    Invalid,
+}
+
+/// Bearer token which every API request must carry in its `Authorization` header.
+///
+/// Clones share the same token, so rotating it through any of them affects all of them.
+#[derive(Clone)]
+pub struct ApiToken(Arc<Mutex<String>>);
+
+impl fmt::Debug for ApiToken {
+    // The token is a secret, so it is kept out of the logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiToken(..)")
+    }
+}
+
+fn validate(token: &str) -> Result<String, ApiTokenError> {
+    if token.is_empty() || !token.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(ApiTokenError::Invalid);
+    }
+    Ok(token.to_string())
+}
+
+// Compares the tokens in a time which only depends on their lengths, so that a client cannot
+// guess the token one character at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl ApiToken {
+    /// Creates a token from its value.
+    pub fn new(token: &str) -> Result<Self, ApiTokenError> {
+        Ok(ApiToken(Arc::new(Mutex::new(validate(token)?))))
+    }
+
+    /// Creates a token from the content of `reader`, ignoring the surrounding whitespace, such
+    /// as a trailing newline.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, ApiTokenError> {
+        let mut token = String::new();
+        reader.read_to_string(&mut token)?;
+        ApiToken::new(token.trim())
+    }
+
+    /// Replaces the token with `token`.
+    pub fn rotate(&self, token: &str) -> Result<(), ApiTokenError> {
+        let token = validate(token)?;
+        *self.0.lock().expect("Poisoned lock") = token;
+        Ok(())
+    }
+
+    /// Returns an `Authorization` header line carrying the current token.
+    pub fn header(&self) -> String {
+        format!(
+            "{}: {} {}",
+            AUTHORIZATION_HEADER,
+            BEARER_SCHEME,
+            self.0.lock().expect("Poisoned lock")
+        )
+    }
+
+    /// Checks whether `request` carries the current token.
+    pub(crate) fn authorizes(&self, request: &Request) -> bool {
+        let credentials = request
+            .headers
+            .custom_entries()
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(AUTHORIZATION_HEADER))
+            .and_then(|(_, value)| value.trim().split_once(' '));
+        match credentials {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case(BEARER_SCHEME) => {
+                let expected = self.0.lock().expect("Poisoned lock");
+                constant_time_eq(token.trim_start().as_bytes(), expected.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    use micro_http::HttpConnection;
+
+    use super::*;
+
+    fn request(headers: &str) -> Request {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(format!("GET / HTTP/1.1\r\n{}\r\n", headers).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        connection.pop_parsed_request().unwrap()
+    }
+
+    #[test]
+    fn test_new_api_token() {
+        assert!(ApiToken::new("s3cr3t-t0k3n").is_ok());
+        for invalid in ["", "two words", "tab\t", "caf\u{e9}"] {
+            assert!(
+                matches!(ApiToken::new(invalid), Err(ApiTokenError::Invalid)),
+                "{}",
+                invalid
+            );
+        }
+
+        let token = ApiToken::from_reader("  s3cr3t\n".as_bytes()).unwrap();
+        assert_eq!(token.header(), "Authorization: Bearer s3cr3t");
+        assert!(matches!(
+            ApiToken::from_reader("\n".as_bytes()),
+            Err(ApiTokenError::Invalid)
+        ));
+        assert_eq!(format!("{:?}", token), "ApiToken(..)");
+    }
+
+    #[test]
+    fn test_authorizes() {
+        let token = ApiToken::new("s3cr3t").unwrap();
+        assert!(token.authorizes(&request("Authorization: Bearer s3cr3t\r\n")));
+        assert!(token.authorizes(&request("authorization: bearer s3cr3t\r\n")));
+
+        assert!(!token.authorizes(&request("")));
+        assert!(!token.authorizes(&request("Authorization: Bearer s3cr3\r\n")));
+        assert!(!token.authorizes(&request("Authorization: Bearer s3cr3t0\r\n")));
+        assert!(!token.authorizes(&request("Authorization: Basic s3cr3t\r\n")));
+        assert!(!token.authorizes(&request("Authorization: s3cr3t\r\n")));
+        assert!(!token.authorizes(&request("X-Api-Token: s3cr3t\r\n")));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let token = ApiToken::new("s3cr3t").unwrap();
+        let clone = token.clone();
+        clone.rotate("n3w-s3cr3t").unwrap();
+        assert!(!token.authorizes(&request("Authorization: Bearer s3cr3t\r\n")));
+        assert!(token.authorizes(&request("Authorization: Bearer n3w-s3cr3t\r\n")));
+
+        assert!(matches!(clone.rotate(""), Err(ApiTokenError::Invalid)));
+        assert_eq!(token.header(), "Authorization: Bearer n3w-s3cr3t");
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use logger::{error, info, update_metric_with_elapsed_time, warn, IncMetric, METRICS};
+use micro_http::{Response, StatusCode};
+use seccompiler::BpfProgramRef;
+use utils::eventfd::EventFd;
+use vmm::rpc_interface::{ApiRequest, ApiResponse, ConcurrentApiController, VmmAction, VmmData};
+use vmm::vmm_config::instance_info::InstanceInfo;
+use vmm::vmm_config::snapshot::SnapshotType;
+
+use crate::parsed_request::ParsedRequest;
+use crate::ApiServer;
+
+/// Request to forward to the VMM on behalf of the API thread.
+#[derive(Debug)]
+pub(crate) struct VmmRequest {
+    pub vmm_action: Box<VmmAction>,
+    pub request_processing_start_us: u64,
+    pub timeout: Option<Duration>,
+    pub deprecated: bool,
+}
+
+/// Response to a `VmmRequest`, ready to be sent to the client.
+#[derive(Debug)]
+pub(crate) struct VmmReply {
+    pub response: Response,
+    /// Instance information reported by the VMM along the way, if any.
+    pub instance_info: Option<InstanceInfo>,
+}
+
+/// Forwards the API requests to the VMM and waits for its responses, on behalf of the API
+/// thread, which keeps serving the requests which do not need the VMM meanwhile.
+#[derive(Debug)]
+pub(crate) struct VmmDispatcher {
+    /// Sender which allows passing messages to the VMM.
+    api_request_sender: mpsc::Sender<ApiRequest>,
+    /// Receiver which collects messages from the VMM.
+    vmm_response_receiver: mpsc::Receiver<ApiResponse>,
+    /// FD on which we notify the VMM that we have sent at least one
+    /// `VmmRequest`.
+    to_vmm_fd: EventFd,
+    /// Number of VMM responses still to come for requests which timed out.
+    pending_vmm_responses: usize,
+    /// Receiver of the requests of the API thread.
+    vmm_request_receiver: mpsc::Receiver<VmmRequest>,
+    /// Sender of the replies to the API thread.
+    vmm_reply_sender: mpsc::Sender<VmmReply>,
+    /// FD on which we notify the API thread that we have sent at least one `VmmReply`.
+    to_api_fd: EventFd,
+    /// Serves the requests to the guest agent, which the VMM thread cannot wait for.
+    concurrent_controller: ConcurrentApiController,
+}
+
+impl VmmDispatcher {
+    pub fn new(
+        api_request_sender: mpsc::Sender<ApiRequest>,
+        vmm_response_receiver: mpsc::Receiver<ApiResponse>,
+        to_vmm_fd: EventFd,
+        vmm_request_receiver: mpsc::Receiver<VmmRequest>,
+        vmm_reply_sender: mpsc::Sender<VmmReply>,
+        to_api_fd: EventFd,
+        concurrent_controller: ConcurrentApiController,
+    ) -> Self {
+        VmmDispatcher {
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            pending_vmm_responses: 0,
+            vmm_request_receiver,
+            vmm_reply_sender,
+            to_api_fd,
+            concurrent_controller,
+        }
+    }
+
+    /// Serves the requests of the API thread until it goes down. The dispatcher is part of the
+    /// API, so it runs under the seccomp filter of the API thread.
+    pub fn run(mut self, seccomp_filter: BpfProgramRef) {
+        if let Err(err) = vmm::seccomp_filters::install_filter("api", seccomp_filter) {
+            panic!(
+                "Failed to set the requested seccomp filters on the API dispatcher thread: {}",
+                err
+            );
+        }
+
+        while let Ok(request) = self.vmm_request_receiver.recv() {
+            let reply = self.serve_vmm_request(request);
+            if self.vmm_reply_sender.send(reply).is_err() {
+                return;
+            }
+            if let Err(err) = self.to_api_fd.write(1) {
+                error!("Cannot notify the API thread of a VMM reply: {}", err);
+            }
+        }
+    }
+
+    // Collects the VMM responses of the requests which timed out, so that they are not taken for
+    // the response of the next request. Fails while the VMM is still busy with one of them.
+    fn collect_pending_vmm_responses(&mut self) -> Result<(), Response> {
+        while self.pending_vmm_responses > 0 {
+            match self.vmm_response_receiver.try_recv() {
+                Ok(vmm_outcome) => {
+                    self.pending_vmm_responses -= 1;
+                    match *vmm_outcome {
+                        Ok(_) => info!("A request which timed out was completed by the VMM."),
+                        Err(err) => warn!("A request which timed out failed: {}", err),
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    return Err(ApiServer::json_response(
+                        StatusCode::BadRequest,
+                        ApiServer::json_fault_message(
+                            "VmmBusy",
+                            "The VMM is still processing a request which timed out.",
+                        ),
+                    ))
+                }
+                Err(mpsc::TryRecvError::Disconnected) => panic!("VMM disconnected"),
+            }
+        }
+        Ok(())
+    }
+
+    fn serve_vmm_request(&mut self, request: VmmRequest) -> VmmReply {
+        let mut reply = self.serve_vmm_action_request(
+            request.vmm_action,
+            request.request_processing_start_us,
+            request.timeout,
+        );
+        if request.deprecated {
+            reply.response.set_deprecation();
+        }
+        reply
+    }
+
+    fn serve_vmm_action_request(
+        &mut self,
+        vmm_action: Box<VmmAction>,
+        request_processing_start_us: u64,
+        timeout: Option<Duration>,
+    ) -> VmmReply {
+        // The requests to the guest agent wait for it here, while the VMM thread keeps serving
+        // the vsock device.
+        if let Some(vmm_outcome) = self
+            .concurrent_controller
+            .handle_guest_agent_request(&vmm_action)
+        {
+            return VmmReply {
+                response: ParsedRequest::convert_to_response(&vmm_outcome),
+                instance_info: None,
+            };
+        }
+
+        if let Err(response) = self.collect_pending_vmm_responses() {
+            return VmmReply {
+                response,
+                instance_info: None,
+            };
+        }
+
+        let metric_with_action = match *vmm_action {
+            VmmAction::CreateSnapshot(ref params) => match params.snapshot_type {
+                SnapshotType::Full => Some((
+                    &METRICS.latencies_us.full_create_snapshot,
+                    "create full snapshot",
+                )),
+                SnapshotType::Diff => Some((
+                    &METRICS.latencies_us.diff_create_snapshot,
+                    "create diff snapshot",
+                )),
+            },
+            VmmAction::LoadSnapshot(_) => {
+                Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
+            }
+            VmmAction::Pause | VmmAction::PauseLite => {
+                Some((&METRICS.latencies_us.pause_vm, "pause vm"))
+            }
+            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
+            _ => None,
+        };
+
+        self.api_request_sender
+            .send(vmm_action)
+            .expect("Failed to send VMM message");
+        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+        let vmm_outcome = match timeout {
+            None => self.vmm_response_receiver.recv().expect("VMM disconnected"),
+            Some(timeout) => match self.vmm_response_receiver.recv_timeout(timeout) {
+                Ok(vmm_outcome) => vmm_outcome,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    METRICS.api_server.sync_vmm_send_timeout_count.inc();
+                    self.pending_vmm_responses += 1;
+                    let msg = format!(
+                        "The VMM did not complete the request within {} ms.",
+                        timeout.as_millis()
+                    );
+                    error!("{}", msg);
+                    return VmmReply {
+                        response: ApiServer::json_response(
+                            StatusCode::BadRequest,
+                            ApiServer::json_fault_message("Timeout", msg),
+                        ),
+                        instance_info: None,
+                    };
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => panic!("VMM disconnected"),
+            },
+        };
+        let vmm_outcome = *vmm_outcome;
+        let response = ParsedRequest::convert_to_response(&vmm_outcome);
+
+        if vmm_outcome.is_ok() {
+            if let Some((metric, action)) = metric_with_action {
+                let elapsed_time_us =
+                    update_metric_with_elapsed_time(metric, request_processing_start_us);
+                info!("'{}' API request took {} us.", action, elapsed_time_us);
+            }
+        }
+        let instance_info = match vmm_outcome {
+            Ok(VmmData::InstanceInformation(instance_info)) => Some(instance_info),
+            _ => None,
+        };
+        VmmReply {
+            response,
+            instance_info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use logger::StoreMetric;
+    use utils::time::ClockType;
+    use vmm::builder::StartMicrovmError;
+    use vmm::rpc_interface::VmmActionError;
+    use vmm::vmm_config::snapshot::CreateSnapshotParams;
+
+    use super::*;
+
+    fn dispatcher(
+        api_request_sender: mpsc::Sender<ApiRequest>,
+        vmm_response_receiver: mpsc::Receiver<ApiResponse>,
+        to_vmm_fd: EventFd,
+    ) -> VmmDispatcher {
+        let (_, vmm_request_receiver) = channel();
+        let (vmm_reply_sender, _) = channel();
+        VmmDispatcher::new(
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            vmm_request_receiver,
+            vmm_reply_sender,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ConcurrentApiController::default(),
+        )
+    }
+
+    #[test]
+    fn test_serve_vmm_action_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+
+        let mut dispatcher = dispatcher(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        to_api
+            .send(Box::new(Err(VmmActionError::StartMicrovm(
+                StartMicrovmError::MissingKernelConfig,
+            ))))
+            .unwrap();
+        let response = dispatcher
+            .serve_vmm_action_request(Box::new(VmmAction::StartMicroVm), 0, None)
+            .response;
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        // Since the vmm side is mocked out in this test, the call to serve_vmm_action_request can
+        // complete very fast (under 1us, the resolution of our metrics). In these cases, the
+        // latencies_us.pause_vm metric can be set to 0, failing the assertion below. By
+        // subtracting 1 we assure that the metric will always be set to at least 1 (if it gets set
+        // at all, which is what this test is trying to prove).
+        let start_time_us = utils::time::get_time_us(ClockType::Monotonic) - 1;
+        assert_eq!(METRICS.latencies_us.pause_vm.fetch(), 0);
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = dispatcher
+            .serve_vmm_action_request(Box::new(VmmAction::Pause), start_time_us, None)
+            .response;
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_ne!(METRICS.latencies_us.pause_vm.fetch(), 0);
+
+        assert_eq!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
+        to_api
+            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+            .unwrap();
+        let response = dispatcher
+            .serve_vmm_action_request(
+                Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
+                    snapshot_type: SnapshotType::Diff,
+                    snapshot_path: PathBuf::new(),
+                    mem_file_path: PathBuf::new(),
+                    version: None,
+                    timeout_ms: None,
+                })),
+                start_time_us,
+                None,
+            )
+            .response;
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        // The metric should not be updated if the request wasn't successful.
+        assert_eq!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = dispatcher
+            .serve_vmm_action_request(
+                Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
+                    snapshot_type: SnapshotType::Diff,
+                    snapshot_path: PathBuf::new(),
+                    mem_file_path: PathBuf::new(),
+                    version: None,
+                    timeout_ms: None,
+                })),
+                start_time_us,
+                None,
+            )
+            .response;
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_ne!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
+        assert_eq!(METRICS.latencies_us.full_create_snapshot.fetch(), 0);
+    }
+
+    #[test]
+    fn test_serve_vmm_action_request_timeout() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+
+        let mut dispatcher = dispatcher(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        let timeout_count = METRICS.api_server.sync_vmm_send_timeout_count.count();
+
+        // The VMM does not respond in time.
+        let response = dispatcher
+            .serve_vmm_action_request(
+                Box::new(VmmAction::StartMicroVm),
+                0,
+                Some(Duration::from_millis(10)),
+            )
+            .response;
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(
+            METRICS.api_server.sync_vmm_send_timeout_count.count(),
+            timeout_count + 1
+        );
+        let mut buf = Vec::new();
+        response.write_all(&mut buf).unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .contains(r#""fault_code":"Timeout""#));
+
+        // The following requests are rejected until the VMM is done with the late one.
+        let response = dispatcher
+            .serve_vmm_action_request(Box::new(VmmAction::Pause), 0, None)
+            .response;
+        let mut buf = Vec::new();
+        response.write_all(&mut buf).unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .contains(r#""fault_code":"VmmBusy""#));
+
+        // The late response is not taken for the response of the next request.
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        to_api
+            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+            .unwrap();
+        let response = dispatcher
+            .serve_vmm_action_request(Box::new(VmmAction::Pause), 0, None)
+            .response;
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(dispatcher.pending_vmm_responses, 0);
+    }
+
+    #[test]
+    fn test_serve_vmm_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+
+        let mut dispatcher = dispatcher(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let reply = dispatcher.serve_vmm_request(VmmRequest {
+            vmm_action: Box::new(VmmAction::GetVmInstanceInfo),
+            request_processing_start_us: 0,
+            timeout: None,
+            deprecated: true,
+        });
+        assert_eq!(reply.response.status(), StatusCode::OK);
+        let mut buf = Vec::new();
+        reply.response.write_all(&mut buf).unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("Deprecation"));
+        assert_eq!(reply.instance_info, Some(InstanceInfo::default()));
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let reply = dispatcher.serve_vmm_request(VmmRequest {
+            vmm_action: Box::new(VmmAction::Pause),
+            request_processing_start_us: 0,
+            timeout: None,
+            deprecated: false,
+        });
+        assert_eq!(reply.response.status(), StatusCode::NoContent);
+        let mut buf = Vec::new();
+        reply.response.write_all(&mut buf).unwrap();
+        assert!(!String::from_utf8(buf).unwrap().contains("Deprecation"));
+        assert!(reply.instance_info.is_none());
+    }
+}
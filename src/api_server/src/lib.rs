@@ -7,39 +7,65 @@
 //! and responding to the user.
 //! It is constructed on top of an HTTP Server that uses Unix Domain Sockets and `EPOLL` to
 //! handle multiple connections on the same thread.
+mod api_token;
+mod dispatcher;
 mod parsed_request;
 mod request;
 
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::io;
+use std::os::unix::io::AsRawFd;
 use std::sync::mpsc;
+use std::thread;
 
-use logger::{
-    debug, error, info, update_metric_with_elapsed_time, warn, ProcessTimeReporter, METRICS,
-};
+use logger::{debug, error, info, warn, IncMetric, ProcessTimeReporter, METRICS};
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, ServerError, ServerRequest,
     ServerResponse, StatusCode, Version,
 };
 use seccompiler::BpfProgramRef;
 use serde_json::json;
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use utils::eventfd::EventFd;
-use vmm::rpc_interface::{ApiRequest, ApiResponse, VmmAction, VmmData};
-use vmm::vmm_config::snapshot::SnapshotType;
+use vmm::rpc_interface::{
+    ApiRequest, ApiResponse, ConcurrentApiController, VmmAction, VmmActionError, VmmData,
+};
+use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
+use vmm::VmmError;
 
+pub use crate::api_token::{ApiToken, ApiTokenError};
+use crate::dispatcher::{VmmDispatcher, VmmReply, VmmRequest};
 use crate::parsed_request::{ParsedRequest, RequestAction};
 
+// Epoll data of the HTTP server, which waits for the requests on its own epoll.
+const SERVER_TOKEN: u64 = 0;
+// Epoll data of the FD on which the dispatcher notifies the replies.
+const VMM_REPLY_TOKEN: u64 = 1;
+
 /// Structure associated with the API server implementation.
 #[derive(Debug)]
 pub struct ApiServer {
-    /// Sender which allows passing messages to the VMM.
-    api_request_sender: mpsc::Sender<ApiRequest>,
-    /// Receiver which collects messages from the VMM.
-    vmm_response_receiver: mpsc::Receiver<ApiResponse>,
-    /// FD on which we notify the VMM that we have sent at least one
-    /// `VmmRequest`.
-    to_vmm_fd: EventFd,
+    /// Dispatcher of the requests to the VMM, until it is moved to its own thread.
+    dispatcher: Option<VmmDispatcher>,
+    /// Sender of the requests to the dispatcher.
+    vmm_request_sender: mpsc::Sender<VmmRequest>,
+    /// Receiver of the replies of the dispatcher.
+    vmm_reply_receiver: mpsc::Receiver<VmmReply>,
+    /// FD on which the dispatcher notifies that it has sent at least one `VmmReply`.
+    vmm_reply_fd: EventFd,
+    /// Requests waiting for a reply of the dispatcher, in the order they were sent to it.
+    in_flight: VecDeque<ServerRequest>,
+    /// Number of requests sent to the dispatcher which were not replied to yet.
+    pending_vmm_replies: usize,
+    /// Last instance information reported by the VMM.
+    instance_info: Option<InstanceInfo>,
     /// If this flag is set, the API thread will go down.
     shutdown_flag: bool,
+    /// Token which the requests must carry, if any.
+    api_token: Option<ApiToken>,
+    /// Serves the requests which do not need to wait for the VMM thread.
+    concurrent_controller: ConcurrentApiController,
 }
 
 impl ApiServer {
@@ -50,23 +76,48 @@ impl ApiServer {
         api_request_sender: mpsc::Sender<ApiRequest>,
         vmm_response_receiver: mpsc::Receiver<ApiResponse>,
         to_vmm_fd: EventFd,
+        api_token: Option<ApiToken>,
+        concurrent_controller: ConcurrentApiController,
     ) -> Self {
+        let (vmm_request_sender, vmm_request_receiver) = mpsc::channel();
+        let (vmm_reply_sender, vmm_reply_receiver) = mpsc::channel();
+        let vmm_reply_fd =
+            EventFd::new(libc::EFD_NONBLOCK).expect("Cannot create the VMM reply FD");
         ApiServer {
-            api_request_sender,
-            vmm_response_receiver,
-            to_vmm_fd,
+            dispatcher: Some(VmmDispatcher::new(
+                api_request_sender,
+                vmm_response_receiver,
+                to_vmm_fd,
+                vmm_request_receiver,
+                vmm_reply_sender,
+                vmm_reply_fd
+                    .try_clone()
+                    .expect("Cannot clone the VMM reply FD"),
+                concurrent_controller.clone(),
+            )),
+            vmm_request_sender,
+            vmm_reply_receiver,
+            vmm_reply_fd,
+            in_flight: VecDeque::new(),
+            pending_vmm_replies: 0,
+            instance_info: None,
             shutdown_flag: false,
+            api_token,
+            concurrent_controller,
         }
     }
 
     /// Runs the Api Server.
     ///
+    /// The requests to the VMM are served by a separate thread, so that the requests which do
+    /// not need the VMM are still answered while it is busy.
+    ///
     /// # Arguments
     ///
-    /// * `path` - the socket path on which the server will wait for requests.
-    /// * `start_time_us` - the timestamp for when the process was started in us.
-    /// * `start_time_cpu_us` - the timestamp for when the process was started in CPU us.
+    /// * `server` - the HTTP server on which the requests are received.
+    /// * `process_time_reporter` - reports the startup time of the process.
     /// * `seccomp_filter` - the seccomp filter to apply.
+    /// * `api_payload_limit` - the maximum size of the request payloads.
     pub fn run(
         &mut self,
         mut server: HttpServer,
@@ -82,10 +133,42 @@ impl ApiServer {
         // Store process CPU start time metric.
         process_time_reporter.report_cpu_start_time();
 
+        // The dispatcher installs the seccomp filter itself, before it serves any request. It is
+        // not joined, since it goes down along with the API thread, which holds the other end of
+        // its channels.
+        let dispatcher = self.dispatcher.take().expect("The API server already ran");
+        let dispatcher_seccomp_filter = seccomp_filter.to_vec();
+        thread::Builder::new()
+            .name("fc_api_vmm".to_owned())
+            .spawn(move || {
+                let _landlock_registration = vmm::landlock::register_thread();
+                dispatcher.run(&dispatcher_seccomp_filter)
+            })
+            .expect("API dispatcher thread spawn failed.");
+
+        // The HTTP server only waits for its own connections, so its epoll is nested in one which
+        // also waits for the replies of the dispatcher. It is created before the seccomp filter is
+        // installed, which does not allow `epoll_create1`.
+        let epoll = Epoll::new().expect("Cannot create the API epoll");
+        epoll
+            .ctl(
+                ControlOperation::Add,
+                server.epoll().as_raw_fd(),
+                EpollEvent::new(EventSet::IN, SERVER_TOKEN),
+            )
+            .expect("Cannot add the HTTP server to the API epoll");
+        epoll
+            .ctl(
+                ControlOperation::Add,
+                self.vmm_reply_fd.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, VMM_REPLY_TOKEN),
+            )
+            .expect("Cannot add the VMM reply FD to the API epoll");
+
         // Load seccomp filters on the API thread.
         // Execution panics if filters cannot be loaded, use --no-seccomp if skipping filters
         // altogether is the desired behaviour.
-        if let Err(err) = seccompiler::apply_filter(seccomp_filter) {
+        if let Err(err) = vmm::seccomp_filters::install_filter("api", seccomp_filter) {
             panic!(
                 "Failed to set the requested seccomp filters on the API thread: {}",
                 err
@@ -94,109 +177,199 @@ impl ApiServer {
 
         server.start_server().expect("Cannot start HTTP server");
 
+        let mut events = [EpollEvent::default(); 2];
         loop {
-            let request_vec = match server.requests() {
-                Ok(vec) => vec,
+            let count = match epoll.wait(-1, &mut events) {
+                Ok(count) => count,
+                // The wait is interrupted when the thread enforces the Landlock ruleset.
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 Err(err) => {
-                    // print request error, but keep server running
-                    error!("API Server error on retrieving incoming request: {}", err);
+                    error!("API Server error on waiting for events: {}", err);
                     continue;
                 }
             };
-            for server_request in request_vec {
-                let request_processing_start_us =
-                    utils::time::get_time_us(utils::time::ClockType::Monotonic);
-                // Use `self.handle_request()` as the processing callback.
-                let response = server_request
-                    .process(|request| self.handle_request(request, request_processing_start_us));
-                if let Err(err) = server.respond(response) {
-                    error!("API Server encountered an error on response: {}", err);
-                };
+            for event in &events[..count] {
+                match event.data() {
+                    SERVER_TOKEN => {
+                        if self.serve_requests(&mut server) {
+                            return;
+                        }
+                    }
+                    VMM_REPLY_TOKEN => {
+                        // The FD is read before the replies, so that none is left behind.
+                        let _ = self.vmm_reply_fd.read();
+                        self.send_vmm_replies(&mut server);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
 
-                let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
-                    - request_processing_start_us;
-                debug!("Total previous API call duration: {} us.", delta_us);
+    // Serves the requests which are ready. Returns whether the API thread must go down.
+    fn serve_requests(&mut self, server: &mut HttpServer) -> bool {
+        let request_vec = match server.requests() {
+            Ok(vec) => vec,
+            // The wait is interrupted when the thread enforces the Landlock ruleset.
+            Err(ServerError::IOError(err)) if err.kind() == io::ErrorKind::Interrupted => {
+                return false
+            }
+            Err(err) => {
+                // print request error, but keep server running
+                error!("API Server error on retrieving incoming request: {}", err);
+                return false;
+            }
+        };
+        for server_request in request_vec {
+            let request_processing_start_us =
+                utils::time::get_time_us(utils::time::ClockType::Monotonic);
+            match self.handle_request(server_request.inner(), request_processing_start_us) {
+                Some(response) => ApiServer::respond(server, &server_request, response),
+                // The response will come from the dispatcher.
+                None => self.in_flight.push_back(server_request),
+            }
 
-                if self.shutdown_flag {
-                    server.flush_outgoing_writes();
-                    debug!(
-                        "/shutdown-internal request received, API server thread now ending itself"
-                    );
-                    return;
-                }
+            let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
+                - request_processing_start_us;
+            debug!("Total previous API call duration: {} us.", delta_us);
+
+            if self.shutdown_flag {
+                server.flush_outgoing_writes();
+                debug!("/shutdown-internal request received, API server thread now ending itself");
+                return true;
+            }
+        }
+        false
+    }
+
+    fn respond(server: &mut HttpServer, server_request: &ServerRequest, response: Response) {
+        let mut response = Some(response);
+        // The closure is called exactly once.
+        let server_response = server_request.process(|_| response.take().unwrap());
+        if let Err(err) = server.respond(server_response) {
+            error!("API Server encountered an error on response: {}", err);
+        }
+    }
+
+    // Sends the replies of the dispatcher which are ready. They come in the order of the requests.
+    fn send_vmm_replies(&mut self, server: &mut HttpServer) {
+        while let Ok(reply) = self.vmm_reply_receiver.try_recv() {
+            self.pending_vmm_replies -= 1;
+            if reply.instance_info.is_some() {
+                self.instance_info = reply.instance_info;
             }
+            let server_request = self
+                .in_flight
+                .pop_front()
+                .expect("Received a VMM reply without a request");
+            ApiServer::respond(server, &server_request, reply.response);
+        }
+    }
+
+    // The requests which do not conflict with the state changes of the running microVM are
+    // answered right away. Otherwise, while the VMM is busy, the requests which only read the
+    // state of the microVM are answered from the last instance information it reported, so that
+    // the health checks are not held up by long operations, such as snapshot restores.
+    fn serve_without_vmm(&self, vmm_action: &VmmAction) -> Option<Response> {
+        if let Some(vmm_outcome) = self.concurrent_controller.handle_request(vmm_action) {
+            METRICS.api_server.concurrent_requests.inc();
+            return Some(ParsedRequest::convert_to_response(&vmm_outcome));
+        }
+        if self.pending_vmm_replies == 0 {
+            return None;
         }
+        let instance_info = self.instance_info.as_ref()?;
+        let vmm_outcome = match vmm_action {
+            VmmAction::GetVmInstanceInfo => Ok(VmmData::InstanceInformation(instance_info.clone())),
+            VmmAction::GetVmmVersion => Ok(VmmData::VmmVersion(instance_info.vmm_version.clone())),
+            // The metrics can only be flushed once the microVM is started.
+            VmmAction::FlushMetrics if instance_info.state != VmState::NotStarted => METRICS
+                .write()
+                .map(|_| VmmData::Empty)
+                .map_err(|err| VmmActionError::InternalVmm(VmmError::Metrics(err))),
+            _ => return None,
+        };
+        Some(ParsedRequest::convert_to_response(&vmm_outcome))
     }
 
     /// Handles an API request received through the associated socket.
+    ///
+    /// Returns `None` if the request was forwarded to the VMM, in which case the response is
+    /// sent once its reply comes back from the dispatcher thread.
     pub fn handle_request(
         &mut self,
         request: &Request,
         request_processing_start_us: u64,
-    ) -> Response {
+    ) -> Option<Response> {
+        if let Some(api_token) = &self.api_token {
+            if !api_token.authorizes(request) {
+                METRICS.api_server.unauthorized_requests.inc();
+                warn!("Rejected an API request without a valid authentication token.");
+                return Some(ApiServer::json_response(
+                    StatusCode::Unauthorized,
+                    ApiServer::json_fault_message("Unauthorized", "Missing or invalid API token."),
+                ));
+            }
+        }
+
         match ParsedRequest::try_from(request).map(|r| r.into_parts()) {
             Ok((req_action, mut parsing_info)) => {
+                let deprecation_message = parsing_info.take_deprecation_message();
+                if let Some(message) = &deprecation_message {
+                    warn!("{}", message);
+                }
                 let mut response = match req_action {
-                    RequestAction::Sync(vmm_action) => {
-                        self.serve_vmm_action_request(vmm_action, request_processing_start_us)
-                    }
+                    RequestAction::Sync(vmm_action) => match self.serve_without_vmm(&vmm_action) {
+                        Some(response) => response,
+                        None => {
+                            self.vmm_request_sender
+                                .send(VmmRequest {
+                                    vmm_action,
+                                    request_processing_start_us,
+                                    timeout: parsing_info.timeout(),
+                                    deprecated: deprecation_message.is_some(),
+                                })
+                                .expect("The API dispatcher thread is gone");
+                            self.pending_vmm_replies += 1;
+                            return None;
+                        }
+                    },
                     RequestAction::ShutdownInternal => {
                         self.shutdown_flag = true;
                         Response::new(Version::Http11, StatusCode::NoContent)
                     }
+                    RequestAction::RotateApiToken(token) => self.rotate_api_token(&token),
                 };
-                if let Some(message) = parsing_info.take_deprecation_message() {
-                    warn!("{}", message);
+                if deprecation_message.is_some() {
                     response.set_deprecation();
                 }
-                response
+                Some(response)
             }
             Err(err) => {
                 error!("{:?}", err);
-                err.into()
+                Some(err.into())
             }
         }
     }
 
-    fn serve_vmm_action_request(
-        &mut self,
-        vmm_action: Box<VmmAction>,
-        request_processing_start_us: u64,
-    ) -> Response {
-        let metric_with_action = match *vmm_action {
-            VmmAction::CreateSnapshot(ref params) => match params.snapshot_type {
-                SnapshotType::Full => Some((
-                    &METRICS.latencies_us.full_create_snapshot,
-                    "create full snapshot",
-                )),
-                SnapshotType::Diff => Some((
-                    &METRICS.latencies_us.diff_create_snapshot,
-                    "create diff snapshot",
-                )),
-            },
-            VmmAction::LoadSnapshot(_) => {
-                Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
-            }
-            VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
-            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
-            _ => None,
+    fn rotate_api_token(&self, token: &str) -> Response {
+        let result = match &self.api_token {
+            Some(api_token) => api_token.rotate(token).map_err(|err| err.to_string()),
+            None => Err("API authentication is not enabled.".to_string()),
         };
-
-        self.api_request_sender
-            .send(vmm_action)
-            .expect("Failed to send VMM message");
-        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
-        let vmm_outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
-        let response = ParsedRequest::convert_to_response(&vmm_outcome);
-
-        if vmm_outcome.is_ok() {
-            if let Some((metric, action)) = metric_with_action {
-                let elapsed_time_us =
-                    update_metric_with_elapsed_time(metric, request_processing_start_us);
-                info!("'{}' API request took {} us.", action, elapsed_time_us);
+        match result {
+            Ok(()) => {
+                info!("The API token was rotated.");
+                Response::new(Version::Http11, StatusCode::NoContent)
+            }
+            Err(msg) => {
+                error!("Cannot rotate the API token: {}", msg);
+                ApiServer::json_response(
+                    StatusCode::BadRequest,
+                    ApiServer::json_fault_message("ApiToken", msg),
+                )
             }
         }
-        response
     }
 
     /// An HTTP response which also includes a body.
@@ -206,8 +379,19 @@ impl ApiServer {
         response
     }
 
-    fn json_fault_message<T: AsRef<str> + serde::Serialize + Debug>(msg: T) -> String {
-        json!({ "fault_message": msg }).to_string()
+    /// The body of an error response. Clients should rely on the `code`, which is stable across
+    /// releases, while the `msg` is meant for humans and can change.
+    fn json_fault_message<T: AsRef<str> + serde::Serialize + Debug>(code: &str, msg: T) -> String {
+        json!({ "fault_code": code, "fault_message": msg }).to_string()
+    }
+
+    /// The body of an error response which also carries the parameters of the error.
+    fn json_fault_message_with_params<T: AsRef<str> + serde::Serialize + Debug>(
+        code: &str,
+        msg: T,
+        params: serde_json::Value,
+    ) -> String {
+        json!({ "fault_code": code, "fault_message": msg, "fault_params": params }).to_string()
     }
 }
 
@@ -219,15 +403,9 @@ mod tests {
     use std::sync::mpsc::channel;
     use std::thread;
 
-    use logger::StoreMetric;
     use micro_http::HttpConnection;
     use utils::tempfile::TempFile;
-    use utils::time::ClockType;
-    use vmm::builder::StartMicrovmError;
-    use vmm::rpc_interface::VmmActionError;
     use vmm::seccomp_filters::get_empty_filters;
-    use vmm::vmm_config::instance_info::InstanceInfo;
-    use vmm::vmm_config::snapshot::CreateSnapshotParams;
 
     use super::*;
     use crate::request::cpu_configuration::parse_put_cpu_config;
@@ -255,114 +433,188 @@ mod tests {
     }"#;
 
     #[test]
-    fn test_serve_vmm_action_request() {
+    fn test_handle_request() {
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
-        let (to_api, vmm_response_receiver) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
 
-        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
-        to_api
-            .send(Box::new(Err(VmmActionError::StartMicrovm(
-                StartMicrovmError::MissingKernelConfig,
-            ))))
+        let mut api_server = ApiServer::new(
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            None,
+            ConcurrentApiController::default(),
+        );
+
+        // Test an Actions request.
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /actions HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 49\r\n\r\n{ \
+                \"action_type\": \"Invalid\", \
+                \"payload\": \"string\" \
+                }",
+            )
             .unwrap();
-        let response = api_server.serve_vmm_action_request(Box::new(VmmAction::StartMicroVm), 0);
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        let response = api_server.handle_request(&req, 0).unwrap();
         assert_eq!(response.status(), StatusCode::BadRequest);
 
-        // Since the vmm side is mocked out in this test, the call to serve_vmm_action_request can
-        // complete very fast (under 1us, the resolution of our metrics). In these cases, the
-        // latencies_us.pause_vm metric can be set to 0, failing the assertion below. By
-        // subtracting 1 we assure that the metric will always be set to at least 1 (if it gets set
-        // at all, which is what this test is trying to prove).
-        let start_time_us = utils::time::get_time_us(ClockType::Monotonic) - 1;
-        assert_eq!(METRICS.latencies_us.pause_vm.fetch(), 0);
-        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
-        let response =
-            api_server.serve_vmm_action_request(Box::new(VmmAction::Pause), start_time_us);
-        assert_eq!(response.status(), StatusCode::NoContent);
-        assert_ne!(METRICS.latencies_us.pause_vm.fetch(), 0);
+        // Test a Get Info request, which is forwarded to the dispatcher.
+        sender.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(api_server.handle_request(&req, 0).is_none());
+        assert_eq!(api_server.pending_vmm_replies, 1);
 
-        assert_eq!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
-        to_api
-            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+        // Test erroneous request.
+        sender
+            .write_all(
+                b"GET /mmds HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
             .unwrap();
-        let response = api_server.serve_vmm_action_request(
-            Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
-                snapshot_type: SnapshotType::Diff,
-                snapshot_path: PathBuf::new(),
-                mem_file_path: PathBuf::new(),
-                version: None,
-            })),
-            start_time_us,
-        );
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        let response = api_server.handle_request(&req, 0).unwrap();
         assert_eq!(response.status(), StatusCode::BadRequest);
-        // The metric should not be updated if the request wasn't successful.
-        assert_eq!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
-
-        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
-        let response = api_server.serve_vmm_action_request(
-            Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
-                snapshot_type: SnapshotType::Diff,
-                snapshot_path: PathBuf::new(),
-                mem_file_path: PathBuf::new(),
-                version: None,
-            })),
-            start_time_us,
-        );
-        assert_eq!(response.status(), StatusCode::NoContent);
-        assert_ne!(METRICS.latencies_us.diff_create_snapshot.fetch(), 0);
-        assert_eq!(METRICS.latencies_us.full_create_snapshot.fetch(), 0);
     }
 
     #[test]
-    fn test_handle_request() {
+    fn test_serve_without_vmm() {
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
-        let (to_api, vmm_response_receiver) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            None,
+            ConcurrentApiController::default(),
+        );
 
-        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        // Without any request in flight, everything goes to the VMM.
+        assert!(api_server
+            .serve_without_vmm(&VmmAction::GetVmInstanceInfo)
+            .is_none());
 
-        // Test an Actions request.
+        // Without any instance information, everything goes to the VMM too.
+        api_server.pending_vmm_replies = 1;
+        assert!(api_server
+            .serve_without_vmm(&VmmAction::GetVmInstanceInfo)
+            .is_none());
+
+        api_server.instance_info = Some(InstanceInfo::default());
+        let response = api_server
+            .serve_without_vmm(&VmmAction::GetVmInstanceInfo)
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api_server
+            .serve_without_vmm(&VmmAction::GetVmmVersion)
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The metrics cannot be flushed before the microVM is started.
+        assert!(api_server
+            .serve_without_vmm(&VmmAction::FlushMetrics)
+            .is_none());
+        assert!(api_server.serve_without_vmm(&VmmAction::Pause).is_none());
+    }
+
+    #[test]
+    fn test_handle_request_api_token() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+
+        let api_token = ApiToken::new("s3cr3t").unwrap();
+        let mut api_server = ApiServer::new(
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            Some(api_token.clone()),
+            ConcurrentApiController::default(),
+        );
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
+
+        // Requests without the token are rejected before reaching the VMM.
+        let unauthorized_count = METRICS.api_server.unauthorized_requests.count();
+        sender.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        let response = api_server.handle_request(&req, 0).unwrap();
+        assert_eq!(response.status(), StatusCode::Unauthorized);
+        assert_eq!(
+            METRICS.api_server.unauthorized_requests.count(),
+            unauthorized_count + 1
+        );
+
+        sender
+            .write_all(b"GET / HTTP/1.1\r\nAuthorization: Bearer s3cr3t\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(api_server.handle_request(&req, 0).is_none());
+
+        // Rotate the token.
         sender
             .write_all(
-                b"PUT /actions HTTP/1.1\r\n\
+                b"PUT /api-token HTTP/1.1\r\n\
+                Authorization: Bearer s3cr3t\r\n\
                 Content-Type: application/json\r\n\
-                Content-Length: 49\r\n\r\n{ \
-                \"action_type\": \"Invalid\", \
-                \"payload\": \"string\" \
-                }",
+                Content-Length: 23\r\n\r\n{\"token\": \"n3w-s3cr3t\"}",
             )
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req, 0);
-        assert_eq!(response.status(), StatusCode::BadRequest);
+        let response = api_server.handle_request(&req, 0).unwrap();
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(api_token.header(), "Authorization: Bearer n3w-s3cr3t");
 
-        // Test a Get Info request.
-        to_api
-            .send(Box::new(Ok(VmmData::InstanceInformation(
-                InstanceInfo::default(),
-            ))))
+        // The previous token is no longer accepted.
+        sender
+            .write_all(b"GET / HTTP/1.1\r\nAuthorization: Bearer s3cr3t\r\n\r\n")
             .unwrap();
-        sender.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req, 0);
-        assert_eq!(response.status(), StatusCode::OK);
+        let response = api_server.handle_request(&req, 0).unwrap();
+        assert_eq!(response.status(), StatusCode::Unauthorized);
 
-        // Test erroneous request.
+        // Invalid tokens are rejected.
         sender
             .write_all(
-                b"GET /mmds HTTP/1.1\r\n\
+                b"PUT /api-token HTTP/1.1\r\n\
+                Authorization: Bearer n3w-s3cr3t\r\n\
                 Content-Type: application/json\r\n\
-                Content-Length: 2\r\n\r\n{}",
+                Content-Length: 13\r\n\r\n{\"token\": \"\"}",
             )
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req, 0);
+        let response = api_server.handle_request(&req, 0).unwrap();
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(api_token.header(), "Authorization: Bearer n3w-s3cr3t");
+    }
+
+    #[test]
+    fn test_rotate_api_token_disabled() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+
+        let api_server = ApiServer::new(
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+            None,
+            ConcurrentApiController::default(),
+        );
+        let response = api_server.rotate_api_token("s3cr3t");
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
@@ -395,18 +647,26 @@ mod tests {
         let mut tmp_socket = TempFile::new().unwrap();
         tmp_socket.remove().unwrap();
         let path_to_socket = tmp_socket.as_path().to_str().unwrap().to_owned();
-        let api_thread_path_to_socket = path_to_socket.clone();
+        let api_thread_path_to_socket = PathBuf::from(&path_to_socket);
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
         let (to_api, vmm_response_receiver) = channel();
         let seccomp_filters = get_empty_filters();
-        let server = HttpServer::new(PathBuf::from(api_thread_path_to_socket)).unwrap();
+        let server = HttpServer::new(api_thread_path_to_socket.clone()).unwrap();
         thread::Builder::new()
             .name("fc_api_test".to_owned())
             .spawn(move || {
-                ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd).run(
+                ApiServer::new(
+                    api_request_sender,
+                    vmm_response_receiver,
+                    to_vmm_fd,
+                    None,
+                    ConcurrentApiController::default(),
+                )
+                .run(
                     server,
+                    api_thread_path_to_socket,
                     ProcessTimeReporter::new(Some(1), Some(1), Some(1)),
                     seccomp_filters.get("api").unwrap(),
                     vmm::HTTP_MAX_PAYLOAD_SIZE,
@@ -437,19 +697,27 @@ mod tests {
         let mut tmp_socket = TempFile::new().unwrap();
         tmp_socket.remove().unwrap();
         let path_to_socket = tmp_socket.as_path().to_str().unwrap().to_owned();
-        let api_thread_path_to_socket = path_to_socket.clone();
+        let api_thread_path_to_socket = PathBuf::from(&path_to_socket);
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
         let (_to_api, vmm_response_receiver) = channel();
         let seccomp_filters = get_empty_filters();
 
-        let server = HttpServer::new(PathBuf::from(api_thread_path_to_socket)).unwrap();
+        let server = HttpServer::new(api_thread_path_to_socket.clone()).unwrap();
         thread::Builder::new()
             .name("fc_api_test".to_owned())
             .spawn(move || {
-                ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd).run(
+                ApiServer::new(
+                    api_request_sender,
+                    vmm_response_receiver,
+                    to_vmm_fd,
+                    None,
+                    ConcurrentApiController::default(),
+                )
+                .run(
                     server,
+                    api_thread_path_to_socket,
                     ProcessTimeReporter::new(Some(1), Some(1), Some(1)),
                     seccomp_filters.get("api").unwrap(),
                     50,
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::Debug;
+use std::time::Duration;
 
 use logger::{error, info, log_enabled, Level};
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -11,33 +12,60 @@ use vmm::rpc_interface::{VmmAction, VmmActionError};
 
 use super::VmmData;
 use crate::request::actions::parse_put_actions;
-use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
+use crate::request::api_token::parse_put_api_token;
+use crate::request::balloon::{
+    parse_get_balloon, parse_patch_balloon, parse_patch_balloon_hinting, parse_put_balloon,
+};
 use crate::request::boot_source::parse_put_boot_source;
+use crate::request::confidential::{parse_get_confidential, parse_put_confidential};
+use crate::request::coredump::parse_put_coredump;
 use crate::request::cpu_configuration::parse_put_cpu_config;
+use crate::request::deterministic_boot::parse_put_deterministic_boot;
 use crate::request::drive::{parse_patch_drive, parse_put_drive};
 use crate::request::entropy::parse_put_entropy;
+use crate::request::gdb::parse_put_gdb;
+use crate::request::guest_files::parse_put_guest_files;
+use crate::request::guest_freeze::{parse_patch_guest_freeze, parse_put_guest_freeze};
+use crate::request::health::parse_get_health;
+use crate::request::identity::parse_put_identity;
 use crate::request::instance_info::parse_get_instance_info;
-use crate::request::logger::parse_put_logger;
+use crate::request::io_threads::parse_put_io_threads;
+use crate::request::landlock::parse_put_landlock;
+use crate::request::logger::{parse_patch_logger, parse_put_logger};
 use crate::request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
+use crate::request::memory_hotplug::{
+    parse_get_memory_hotplug, parse_patch_memory_hotplug, parse_put_memory_hotplug,
+};
+use crate::request::memory_pressure::parse_put_memory_pressure;
 use crate::request::metrics::parse_put_metrics;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
 use crate::request::net::{parse_patch_net, parse_put_net};
-use crate::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
+use crate::request::seccomp::parse_get_seccomp;
+use crate::request::shared_dir::parse_put_shared_dir;
+use crate::request::smbios::parse_put_smbios;
+use crate::request::snapshot::{parse_patch_vm_state, parse_put_clone, parse_put_snapshot};
+use crate::request::stall_detection::parse_put_stall_detection;
+use crate::request::tpm::parse_put_tpm;
+use crate::request::validate::parse_put_validate;
 use crate::request::version::parse_get_version;
-use crate::request::vsock::parse_put_vsock;
+use crate::request::virtio_record::parse_put_virtio_record;
+use crate::request::vsock::{parse_get_vsock, parse_patch_vsock, parse_put_vsock};
+use crate::request::working_set::parse_put_working_set;
 use crate::ApiServer;
 
 #[derive(Debug)]
 pub(crate) enum RequestAction {
     Sync(Box<VmmAction>),
     ShutdownInternal, // !!! not an API, used by shutdown to thread::join the API thread
+    RotateApiToken(String),
 }
 
 #[derive(Debug, Default, PartialEq)]
 pub(crate) struct ParsingInfo {
     deprecation_message: Option<String>,
+    timeout: Option<Duration>,
 }
 
 impl ParsingInfo {
@@ -51,6 +79,14 @@ impl ParsingInfo {
     pub fn take_deprecation_message(&mut self) -> Option<String> {
         self.deprecation_message.take()
     }
+
+    pub fn set_timeout_ms(&mut self, timeout_ms: Option<u64>) {
+        self.timeout = timeout_ms.map(Duration::from_millis);
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
 }
 
 #[derive(Debug)]
@@ -77,41 +113,83 @@ impl TryFrom<&Request> for ParsedRequest {
 
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
-            (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.next()),
+            (Method::Get, "balloon", None) => {
+                parse_get_balloon(path_tokens.next(), path_tokens.next())
+            }
+            (Method::Get, "confidential", None) => parse_get_confidential(),
+            (Method::Get, "health", None) => parse_get_health(),
             (Method::Get, "version", None) => parse_get_version(),
             (Method::Get, "vm", None) if path_tokens.next() == Some("config") => {
-                Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
+                match path_tokens.next() {
+                    Some("full") => Ok(ParsedRequest::new_sync(VmmAction::ExportVmConfig)),
+                    _ => Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig)),
+                }
             }
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
+            (Method::Get, "memory-hotplug", None) => parse_get_memory_hotplug(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "seccomp", None) => parse_get_seccomp(),
+            (Method::Get, "vsock", None) => parse_get_vsock(),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
+            (Method::Put, "api-token", Some(body)) => parse_put_api_token(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
+            (Method::Put, "clone", Some(body)) => parse_put_clone(body),
+            (Method::Put, "confidential", Some(body)) => parse_put_confidential(body),
+            (Method::Put, "coredump", Some(body)) => parse_put_coredump(body),
             (Method::Put, "cpu-config", Some(body)) => parse_put_cpu_config(body),
+            (Method::Put, "deterministic-boot", Some(body)) => parse_put_deterministic_boot(body),
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.next()),
+            (Method::Put, "gdb", Some(body)) => parse_put_gdb(body),
+            (Method::Put, "guest-files", Some(body)) => {
+                parse_put_guest_files(body, path_tokens.next())
+            }
+            (Method::Put, "guest-freeze", Some(body)) => parse_put_guest_freeze(body),
+            (Method::Put, "identity", Some(body)) => parse_put_identity(body),
+            (Method::Put, "landlock", Some(body)) => parse_put_landlock(body),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
+            (Method::Put, "memory-hotplug", Some(body)) => parse_put_memory_hotplug(body),
+            (Method::Put, "memory-pressure", Some(body)) => parse_put_memory_pressure(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.next()),
             (Method::Put, "network-interfaces", Some(body)) => {
                 parse_put_net(body, path_tokens.next())
             }
+            (Method::Put, "shared-dirs", Some(body)) => {
+                parse_put_shared_dir(body, path_tokens.next())
+            }
             (Method::Put, "shutdown-internal", None) => {
                 Ok(ParsedRequest::new(RequestAction::ShutdownInternal))
             }
+            (Method::Put, "smbios", Some(body)) => parse_put_smbios(body),
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.next()),
+            (Method::Put, "stall-detection", Some(body)) => parse_put_stall_detection(body),
+            (Method::Put, "tpm", Some(body)) => parse_put_tpm(body),
+            (Method::Put, "validate", Some(body)) => parse_put_validate(body),
+            (Method::Put, "virtio-record", Some(body)) => parse_put_virtio_record(body),
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
+            (Method::Put, "working-set", Some(body)) => parse_put_working_set(body),
+            (Method::Put, "io-threads", Some(body)) => parse_put_io_threads(body),
             (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
+            // The free page hinting requests do not need a body, any is ignored.
+            (Method::Patch, "balloon", _) if path_tokens.clone().next() == Some("hinting") => {
+                parse_patch_balloon_hinting(path_tokens.nth(1))
+            }
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.next()),
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.next()),
+            (Method::Patch, "guest-freeze", Some(body)) => parse_patch_guest_freeze(body),
+            (Method::Patch, "logger", Some(body)) => parse_patch_logger(body),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
+            (Method::Patch, "memory-hotplug", Some(body)) => parse_patch_memory_hotplug(body),
             (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.next())
             }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
+            (Method::Patch, "vsock", Some(body)) => parse_patch_vsock(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
             (method, unknown_uri, _) => {
                 Err(Error::InvalidPathMethod(unknown_uri.to_string(), method))
@@ -173,12 +251,18 @@ impl ParsedRequest {
                 VmmData::BalloonConfig(balloon_config) => {
                     Self::success_response_with_data(balloon_config)
                 }
+                VmmData::BalloonHintingStatus(status) => Self::success_response_with_data(status),
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::ConfidentialInfo(info) => Self::success_response_with_data(info),
+                VmmData::Health(health) => Self::success_response_with_data(health),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
                 VmmData::FullVmConfig(config) => Self::success_response_with_data(config),
+                VmmData::MemoryHotplugStatus(status) => Self::success_response_with_data(status),
+                VmmData::SeccompFilters(filters) => Self::success_response_with_data(filters),
+                VmmData::VsockConfig(config) => Self::success_response_with_data(config),
             },
             Err(vmm_action_error) => {
                 let mut response = match vmm_action_error {
@@ -198,6 +282,7 @@ impl ParsedRequest {
                     }
                 };
                 response.set_body(Body::new(ApiServer::json_fault_message(
+                    fault_code(vmm_action_error),
                     vmm_action_error.to_string(),
                 )));
                 response
@@ -211,6 +296,54 @@ impl ParsedRequest {
     }
 }
 
+/// Returns the stable code of `err`, which is reported in the fault responses along with the
+/// message. Unlike the messages, the codes must not change between releases.
+fn fault_code(err: &VmmActionError) -> &'static str {
+    match err {
+        VmmActionError::BalloonConfig(_) => "BalloonConfig",
+        VmmActionError::BootSource(_) => "BootSource",
+        VmmActionError::Coredump(_) => "Coredump",
+        VmmActionError::CreateSnapshot(_) => "CreateSnapshot",
+        VmmActionError::ConfigureCpu(_) => "ConfigureCpu",
+        VmmActionError::Confidential(_) => "Confidential",
+        VmmActionError::DeterministicBoot(_) => "DeterministicBoot",
+        VmmActionError::DriveConfig(_) => "DriveConfig",
+        VmmActionError::EntropyDevice(_) => "EntropyDevice",
+        VmmActionError::Gdb(_) => "Gdb",
+        VmmActionError::GuestFileCopy(_) => "GuestFileCopy",
+        VmmActionError::GuestFiles(_) => "GuestFiles",
+        VmmActionError::GuestFreeze(_) => "GuestFreeze",
+        VmmActionError::GuestFreezeUpdate(_) => "GuestFreezeUpdate",
+        VmmActionError::Identity(_) => "Identity",
+        VmmActionError::InternalVmm(_) => "InternalVmm",
+        VmmActionError::IoThreads(_) => "IoThreads",
+        VmmActionError::Landlock(_) => "Landlock",
+        VmmActionError::LoadSnapshot(_) => "LoadSnapshot",
+        VmmActionError::Logger(_) => "Logger",
+        VmmActionError::MachineConfig(_) => "MachineConfig",
+        VmmActionError::MemoryHotplugConfig(_) => "MemoryHotplugConfig",
+        VmmActionError::MemoryPressure(_) => "MemoryPressure",
+        VmmActionError::Metrics(_) => "Metrics",
+        VmmActionError::Mmds(_) => "Mmds",
+        VmmActionError::MmdsConfig(_) => "MmdsConfig",
+        VmmActionError::MmdsLimitExceeded(_) => "MmdsLimitExceeded",
+        VmmActionError::NetworkConfig(_) => "NetworkConfig",
+        VmmActionError::NotSupported(_) => "NotSupported",
+        VmmActionError::OperationNotSupportedPostBoot => "OperationNotSupportedPostBoot",
+        VmmActionError::OperationNotSupportedPreBoot => "OperationNotSupportedPreBoot",
+        VmmActionError::SharedDir(_) => "SharedDir",
+        VmmActionError::Smbios(_) => "Smbios",
+        VmmActionError::StallDetection(_) => "StallDetection",
+        VmmActionError::StartMicrovm(_) => "StartMicrovm",
+        VmmActionError::Tpm(_) => "Tpm",
+        VmmActionError::UffdHandover(_) => "UffdHandover",
+        VmmActionError::ValidateVmConfig(_) => "ValidateVmConfig",
+        VmmActionError::VirtioRecord(_) => "VirtioRecord",
+        VmmActionError::VsockConfig(_) => "VsockConfig",
+        VmmActionError::WorkingSet(_) => "WorkingSet",
+    }
+}
+
 /// Helper function for writing the received API requests to the log.
 ///
 /// The `info` macro is used for logging.
@@ -228,7 +361,10 @@ fn log_received_api_request(api_description: String) {
 /// * `body` - body of the API request
 fn describe(method: Method, path: &str, body: Option<&Body>) -> String {
     match (path, body) {
-        ("/mmds", Some(_)) | (_, None) => format!("{:?} request on {:?}", method, path),
+        // The API token is a secret, so it is kept out of the logs.
+        ("/mmds", Some(_)) | ("/api-token", Some(_)) | (_, None) => {
+            format!("{:?} request on {:?}", method, path)
+        }
         ("/cpu-config", Some(payload_value)) => {
             // If the log level is at Debug or higher, include the CPU template in
             // the log line.
@@ -294,10 +430,43 @@ pub(crate) enum Error {
     SerdeJson(#[from] serde_json::Error),
 }
 
+impl Error {
+    // Stable code of the error, reported in the fault response along with the message.
+    fn fault_code(&self) -> &'static str {
+        match self {
+            Error::EmptyID => "EmptyId",
+            Error::Generic(_, _) => "InvalidRequest",
+            Error::InvalidID => "InvalidId",
+            Error::InvalidPathMethod(_, _) => "InvalidPathMethod",
+            Error::SerdeJson(_) => "InvalidJson",
+        }
+    }
+
+    // Parameters of the error, reported in the fault response if there are any.
+    fn fault_params(&self) -> Option<Value> {
+        match self {
+            Error::InvalidPathMethod(path, method) => Some(serde_json::json!({
+                "method": std::str::from_utf8(method.raw()).expect("Cannot convert from UTF-8"),
+                "path": path,
+            })),
+            Error::SerdeJson(err) => Some(serde_json::json!({
+                "line": err.line(),
+                "column": err.column(),
+            })),
+            Error::EmptyID | Error::Generic(_, _) | Error::InvalidID => None,
+        }
+    }
+}
+
 // It's convenient to turn errors into HTTP responses directly.
 impl From<Error> for Response {
     fn from(err: Error) -> Self {
-        let msg = ApiServer::json_fault_message(format!("{}", err));
+        let msg = match err.fault_params() {
+            Some(params) => {
+                ApiServer::json_fault_message_with_params(err.fault_code(), err.to_string(), params)
+            }
+            None => ApiServer::json_fault_message(err.fault_code(), err.to_string()),
+        };
         match err {
             Error::Generic(status, _) => ApiServer::json_response(status, msg),
             Error::EmptyID
@@ -332,17 +501,24 @@ pub mod tests {
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
     use vmm::cpu_config::templates::test_utils::build_test_template;
+    use vmm::devices::virtio::VirtioMemStatus;
     use vmm::resources::VmmConfig;
     use vmm::rpc_interface::VmmActionError;
-    use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
+    use vmm::seccomp_filters::SeccompFilterStatus;
+    use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonHintingStatus, BalloonStats};
+    use vmm::vmm_config::confidential::{
+        ConfidentialInfo, ConfidentialTechnology, DEFAULT_SEV_SNP_POLICY,
+    };
+    use vmm::vmm_config::health::Health;
     use vmm::vmm_config::instance_info::InstanceInfo;
     use vmm::vmm_config::machine_config::MachineConfig;
+    use vmm::vmm_config::vsock::VsockDeviceConfig;
 
     use super::*;
 
     impl PartialEq for ParsedRequest {
         fn eq(&self, other: &ParsedRequest) -> bool {
-            if self.parsing_info.deprecation_message != other.parsing_info.deprecation_message {
+            if self.parsing_info != other.parsing_info {
                 return false;
             }
 
@@ -493,7 +669,7 @@ pub mod tests {
         let response: Response =
             Error::Generic(StatusCode::BadRequest, "message".to_string()).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message("message");
+        let body = ApiServer::json_fault_message("InvalidRequest", "message");
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -501,7 +677,7 @@ pub mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = Error::EmptyID.into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message("The ID cannot be empty.");
+        let body = ApiServer::json_fault_message("EmptyId", "The ID cannot be empty.");
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -510,6 +686,7 @@ pub mod tests {
         let response: Response = Error::InvalidID.into();
         assert!(response.write_all(&mut buf).is_ok());
         let body = ApiServer::json_fault_message(
+            "InvalidId",
             "API Resource IDs can only contain alphanumeric characters and underscores.",
         );
         let expected_response = http_response(&body, 400);
@@ -519,11 +696,15 @@ pub mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = Error::InvalidPathMethod("path".to_string(), Method::Get).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message(format!(
-            "Invalid request method and/or path: {} {}.",
-            std::str::from_utf8(Method::Get.raw()).unwrap(),
-            "path"
-        ));
+        let body = ApiServer::json_fault_message_with_params(
+            "InvalidPathMethod",
+            format!(
+                "Invalid request method and/or path: {} {}.",
+                std::str::from_utf8(Method::Get.raw()).unwrap(),
+                "path"
+            ),
+            serde_json::json!({ "method": "GET", "path": "path" }),
+        );
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -532,9 +713,11 @@ pub mod tests {
         let serde_error = serde_json::Value::from_str("").unwrap_err();
         let response: Response = Error::SerdeJson(serde_error).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message(
+        let body = ApiServer::json_fault_message_with_params(
+            "InvalidJson",
             "An error occurred when deserializing the json body of a request: EOF while parsing a \
              value at line 1 column 0.",
+            serde_json::json!({ "line": 1, "column": 0 }),
         );
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
@@ -554,6 +737,10 @@ pub mod tests {
             describe(Method::Put, "path", Some(&Body::new("body"))),
             "Put request on \"path\" with body \"body\""
         );
+        assert_eq!(
+            describe(Method::Put, "/api-token", Some(&Body::new("body"))),
+            "Put request on \"/api-token\""
+        );
     }
 
     #[test]
@@ -565,9 +752,15 @@ pub mod tests {
                 VmmData::BalloonConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                VmmData::BalloonHintingStatus(status) => {
+                    http_response(&serde_json::to_string(status).unwrap(), 200)
+                }
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::ConfidentialInfo(info) => {
+                    http_response(&serde_json::to_string(info).unwrap(), 200)
+                }
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
@@ -575,16 +768,28 @@ pub mod tests {
                 VmmData::MachineConfiguration(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                VmmData::Health(health) => {
+                    http_response(&serde_json::to_string(health).unwrap(), 200)
+                }
+                VmmData::MemoryHotplugStatus(status) => {
+                    http_response(&serde_json::to_string(status).unwrap(), 200)
+                }
                 VmmData::MmdsValue(value) => {
                     http_response(&serde_json::to_string(value).unwrap(), 200)
                 }
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::SeccompFilters(filters) => {
+                    http_response(&serde_json::to_string(filters).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
                 ),
+                VmmData::VsockConfig(config) => {
+                    http_response(&serde_json::to_string(config).unwrap(), 200)
+                }
             };
             let response = ParsedRequest::convert_to_response(&data);
             assert!(response.write_all(&mut buf).is_ok());
@@ -592,22 +797,47 @@ pub mod tests {
         };
 
         verify_ok_response_with(VmmData::BalloonConfig(BalloonDeviceConfig::default()));
+        verify_ok_response_with(VmmData::BalloonHintingStatus(BalloonHintingStatus {
+            complete: true,
+            hinted_pages: 256,
+            hinted_mib: 1,
+        }));
         verify_ok_response_with(VmmData::BalloonStats(BalloonStats {
             swap_in: Some(1),
             swap_out: Some(1),
             ..Default::default()
         }));
+        verify_ok_response_with(VmmData::ConfidentialInfo(ConfidentialInfo {
+            technology: ConfidentialTechnology::SevSnp,
+            policy: DEFAULT_SEV_SNP_POLICY,
+            launch_digest: "00".repeat(48),
+        }));
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
+        verify_ok_response_with(VmmData::Health(Health::not_started()));
         verify_ok_response_with(VmmData::MachineConfiguration(MachineConfig::default()));
+        verify_ok_response_with(VmmData::MemoryHotplugStatus(VirtioMemStatus::default()));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
+        verify_ok_response_with(VmmData::SeccompFilters(
+            vec![SeccompFilterStatus::default()],
+        ));
+        verify_ok_response_with(VmmData::VsockConfig(VsockDeviceConfig {
+            vsock_id: None,
+            guest_cid: Some(3),
+            uds_path: "vsock.sock".to_string(),
+            clock_port: None,
+            cid_lock_dir: Some("/run/firecracker/cids".to_string()),
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
+        }));
 
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
         let mut buf = Cursor::new(vec![0]);
-        let json = ApiServer::json_fault_message(error.to_string());
+        let json = ApiServer::json_fault_message("StartMicrovm", error.to_string());
         let response = ParsedRequest::convert_to_response(&Err(error));
         response.write_all(&mut buf).unwrap();
 
@@ -651,6 +881,30 @@ pub mod tests {
         assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_balloon_hinting() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("PATCH", "/balloon/hinting/start", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+        sender
+            .write_all(http_request("GET", "/balloon/hinting/status", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+        sender
+            .write_all(http_request("PATCH", "/balloon/hinting/stop", Some("{}")).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_get_machine_config() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -675,6 +929,21 @@ pub mod tests {
         assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_health() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/health", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            vmm_action_from_request(ParsedRequest::try_from(&req).unwrap()),
+            VmmAction::GetHealth
+        );
+    }
+
     #[test]
     fn test_try_from_get_version() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -687,6 +956,55 @@ pub mod tests {
         assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_vm_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/config", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            vmm_action_from_request(ParsedRequest::try_from(&req).unwrap()),
+            VmmAction::GetFullVmConfig
+        );
+
+        sender
+            .write_all(http_request("GET", "/vm/config/full", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            vmm_action_from_request(ParsedRequest::try_from(&req).unwrap()),
+            VmmAction::ExportVmConfig
+        );
+    }
+
+    #[test]
+    fn test_try_from_get_vsock() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vsock", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_seccomp() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/seccomp", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_actions() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -729,6 +1047,280 @@ pub mod tests {
         assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_coredump() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"dump_path\": \"/tmp/vmcore\" }";
+        sender
+            .write_all(http_request("PUT", "/coredump", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_shared_dir() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"share_id\": \"share\", \"path_on_host\": \"/srv/share\", \
+                    \"mount_tag\": \"share\" }";
+        sender
+            .write_all(http_request("PUT", "/shared-dirs/share", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/memory-hotplug", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = "{ \"total_size_mib\": 1024, \"block_size_mib\": 2 }";
+        sender
+            .write_all(http_request("PUT", "/memory-hotplug", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = "{ \"requested_size_mib\": 512 }";
+        sender
+            .write_all(http_request("PATCH", "/memory-hotplug", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_gdb() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"socket_path\": \"/gdb.sock\" }";
+        sender
+            .write_all(http_request("PUT", "/gdb", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_landlock() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"allowed_paths\": [{ \"path\": \"/srv/snapshots\" }] }";
+        sender
+            .write_all(http_request("PUT", "/landlock", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_deterministic_boot() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"seed\": 42 }";
+        sender
+            .write_all(http_request("PUT", "/deterministic-boot", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_virtio_record() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"queues\": [{ \"device_id\": \"rootfs\", \"queue_index\": 0, \
+                    \"path\": \"rootfs.rec\" }] }";
+        sender
+            .write_all(http_request("PUT", "/virtio-record", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_working_set() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"sample_interval_s\": 30 }";
+        sender
+            .write_all(http_request("PUT", "/working-set", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_io_threads() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"thread_count\": 2 }";
+        sender
+            .write_all(http_request("PUT", "/io-threads", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_confidential() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/confidential", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = "{ \"technology\": \"sev-snp\", \"policy\": 196608 }";
+        sender
+            .write_all(http_request("PUT", "/confidential", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_tpm() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"socket_path\": \"/swtpm.sock\" }";
+        sender
+            .write_all(http_request("PUT", "/tpm", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_identity() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"rotate_on_restore\": true }";
+        sender
+            .write_all(http_request("PUT", "/identity", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_smbios() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"manufacturer\": \"ACME\" }";
+        sender
+            .write_all(http_request("PUT", "/smbios", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_guest_files() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = r#"{ "port": 52, "host_dirs": ["/srv"], "guest_dirs": ["/tmp"] }"#;
+        sender
+            .write_all(http_request("PUT", "/guest-files", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = r#"{ "direction": "ToGuest", "guest_path": "/tmp/a", "host_path": "/srv/a" }"#;
+        sender
+            .write_all(http_request("PUT", "/guest-files/copy", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_guest_freeze() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = r#"{ "port": 53, "timeout_ms": 5000 }"#;
+        sender
+            .write_all(http_request("PUT", "/guest-freeze", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = r#"{ "state": "Thawed" }"#;
+        sender
+            .write_all(http_request("PATCH", "/guest-freeze", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_memory_pressure() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"path\": \"/proc/pressure/memory\", \"action\": \"Pause\" }";
+        sender
+            .write_all(http_request("PUT", "/memory-pressure", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_stall_detection() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"timeout_ms\": 5000 }";
+        sender
+            .write_all(http_request("PUT", "/stall-detection", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_validate() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"boot-source\": { \"kernel_image_path\": \"/foo\" }, \"drives\": [] }";
+        sender
+            .write_all(http_request("PUT", "/validate", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_boot() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -881,6 +1473,15 @@ pub mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from(&req).is_ok());
+
+        let body = "{ \"backend_path\": \"foo\" }";
+        sender
+            .write_all(http_request("PUT", "/snapshot/uffd-handler", Some(body)).as_bytes())
+            .unwrap();
+
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
     #[test]
@@ -924,6 +1525,32 @@ pub mod tests {
         assert!(ParsedRequest::try_from(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_patch_vsock() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"uds_path\": \"string\" }";
+        sender
+            .write_all(http_request("PATCH", "/vsock", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_patch_logger() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"level\": \"Debug\" }";
+        sender
+            .write_all(http_request("PATCH", "/logger", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_patch_balloon() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
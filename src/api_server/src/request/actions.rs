@@ -6,15 +6,15 @@ use serde::{Deserialize, Serialize};
 
 use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
-#[cfg(target_arch = "aarch64")]
-use crate::request::StatusCode;
+use crate::request::{Body, StatusCode};
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
 // struct from the Serde deserialization process.
 #[derive(Debug, Deserialize, Serialize)]
 enum ActionType {
+    EmergencySnapshot,
+    FlushDrives,
     FlushMetrics,
     InstanceStart,
     SendCtrlAltDel,
@@ -26,6 +26,12 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    // Time, in milliseconds, after which the API stops waiting for the action.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    // Ids of the drives to flush. All of them are flushed if not set.
+    #[serde(default)]
+    drive_ids: Option<Vec<String>>,
 }
 
 pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
@@ -35,10 +41,37 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
         err
     })?;
 
-    match action_body.action_type {
-        ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
-        ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
-        ActionType::SendCtrlAltDel => {
+    if action_body.drive_ids.is_some()
+        && !matches!(action_body.action_type, ActionType::FlushDrives)
+    {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Drive ids can only be set for the FlushDrives action.".to_string(),
+        ));
+    }
+
+    match (action_body.action_type, action_body.timeout_ms) {
+        (ActionType::InstanceStart, timeout_ms) => {
+            let mut parsed_req = ParsedRequest::new_sync(VmmAction::StartMicroVm);
+            parsed_req.parsing_info().set_timeout_ms(timeout_ms);
+            Ok(parsed_req)
+        }
+        (_, Some(_)) => {
+            METRICS.put_api_requests.actions_fails.inc();
+            Err(Error::Generic(
+                StatusCode::BadRequest,
+                "A timeout can only be set for the InstanceStart action.".to_string(),
+            ))
+        }
+        (ActionType::FlushDrives, None) => Ok(ParsedRequest::new_sync(VmmAction::FlushDrives(
+            action_body.drive_ids,
+        ))),
+        (ActionType::FlushMetrics, None) => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
+        (ActionType::EmergencySnapshot, None) => {
+            Ok(ParsedRequest::new_sync(VmmAction::EmergencySnapshot))
+        }
+        (ActionType::SendCtrlAltDel, None) => {
             // SendCtrlAltDel not supported on aarch64.
             #[cfg(target_arch = "aarch64")]
             return Err(Error::Generic(
@@ -71,6 +104,24 @@ mod tests {
             assert!(result.unwrap().eq(&req));
         }
 
+        {
+            let json = r#"{
+                "action_type": "InstanceStart",
+                "timeout_ms": 1000
+            }"#;
+
+            let mut req: ParsedRequest = ParsedRequest::new_sync(VmmAction::StartMicroVm);
+            req.parsing_info().set_timeout_ms(Some(1000));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "timeout_ms": 1000
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
+
         #[cfg(target_arch = "x86_64")]
         {
             let json = r#"{
@@ -103,5 +154,43 @@ mod tests {
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
         }
+
+        {
+            let json = r#"{
+                "action_type": "FlushDrives"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::FlushDrives(None));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "FlushDrives",
+                "drive_ids": ["rootfs", "scratch"]
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::FlushDrives(Some(vec![
+                "rootfs".to_string(),
+                "scratch".to_string(),
+            ])));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "drive_ids": ["rootfs"]
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
+
+        {
+            let json = r#"{
+                "action_type": "EmergencySnapshot"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::EmergencySnapshot);
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+        }
     }
 }
@@ -0,0 +1,41 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Deserialize;
+
+use crate::parsed_request::{Error, ParsedRequest, RequestAction};
+use crate::request::Body;
+
+// Body of the PUT `/api-token` API call.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiTokenUpdate {
+    token: String,
+}
+
+pub(crate) fn parse_put_api_token(body: &Body) -> Result<ParsedRequest, Error> {
+    let update = serde_json::from_slice::<ApiTokenUpdate>(body.raw())?;
+    Ok(ParsedRequest::new(RequestAction::RotateApiToken(
+        update.token,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_api_token_request() {
+        assert!(parse_put_api_token(&Body::new("invalid_payload")).is_err());
+        assert!(parse_put_api_token(&Body::new("{}")).is_err());
+        assert!(parse_put_api_token(&Body::new(r#"{"token": "a", "foo": "b"}"#)).is_err());
+
+        let (action, _) = parse_put_api_token(&Body::new(r#"{"token": "n3w-s3cr3t"}"#))
+            .unwrap()
+            .into_parts();
+        match action {
+            RequestAction::RotateApiToken(token) => assert_eq!(token, "n3w-s3cr3t"),
+            _ => panic!("Invalid request"),
+        }
+    }
+}
@@ -10,16 +10,20 @@ use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
 
-pub(crate) fn parse_get_balloon(path_second_token: Option<&str>) -> Result<ParsedRequest, Error> {
-    match path_second_token {
-        Some(stats_path) => match stats_path {
-            "statistics" => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
-            _ => Err(Error::Generic(
-                StatusCode::BadRequest,
-                format!("Unrecognized GET request path `{}`.", stats_path),
-            )),
-        },
-        None => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonConfig)),
+pub(crate) fn parse_get_balloon(
+    path_second_token: Option<&str>,
+    path_third_token: Option<&str>,
+) -> Result<ParsedRequest, Error> {
+    match (path_second_token, path_third_token) {
+        (Some("statistics"), None) => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
+        (Some("hinting"), Some("status")) => {
+            Ok(ParsedRequest::new_sync(VmmAction::GetBalloonHintingStatus))
+        }
+        (Some(stats_path), _) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized GET request path `{}`.", stats_path),
+        )),
+        (None, _) => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonConfig)),
     }
 }
 
@@ -49,6 +53,22 @@ pub(crate) fn parse_patch_balloon(
     }
 }
 
+pub(crate) fn parse_patch_balloon_hinting(
+    path_third_token: Option<&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_third_token {
+        Some("start") => Ok(ParsedRequest::new_sync(VmmAction::StartBalloonHinting)),
+        Some("stop") => Ok(ParsedRequest::new_sync(VmmAction::StopBalloonHinting)),
+        _ => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "Unrecognized PATCH request path `hinting/{}`.",
+                path_third_token.unwrap_or("")
+            ),
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,11 +76,32 @@ mod tests {
 
     #[test]
     fn test_parse_get_balloon_request() {
-        assert!(parse_get_balloon(None).is_ok());
+        assert!(parse_get_balloon(None, None).is_ok());
+
+        assert!(parse_get_balloon(Some("unrelated"), None).is_err());
 
-        assert!(parse_get_balloon(Some("unrelated")).is_err());
+        assert!(parse_get_balloon(Some("statistics"), None).is_ok());
 
-        assert!(parse_get_balloon(Some("statistics")).is_ok());
+        assert!(parse_get_balloon(Some("hinting"), None).is_err());
+        assert!(parse_get_balloon(Some("statistics"), Some("status")).is_err());
+        assert_eq!(
+            vmm_action_from_request(parse_get_balloon(Some("hinting"), Some("status")).unwrap()),
+            VmmAction::GetBalloonHintingStatus
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_balloon_hinting_request() {
+        assert!(parse_patch_balloon_hinting(None).is_err());
+        assert!(parse_patch_balloon_hinting(Some("status")).is_err());
+        assert_eq!(
+            vmm_action_from_request(parse_patch_balloon_hinting(Some("start")).unwrap()),
+            VmmAction::StartBalloonHinting
+        );
+        assert_eq!(
+            vmm_action_from_request(parse_patch_balloon_hinting(Some("stop")).unwrap()),
+            VmmAction::StopBalloonHinting
+        );
     }
 
     #[test]
@@ -129,7 +170,29 @@ mod tests {
               }"#;
         #[allow(clippy::match_wild_err_arm)]
         match vmm_action_from_request(parse_patch_balloon(&Body::new(body), None).unwrap()) {
-            VmmAction::UpdateBalloon(balloon_cfg) => assert_eq!(balloon_cfg.amount_mib, 1),
+            VmmAction::UpdateBalloon(balloon_cfg) => assert_eq!(balloon_cfg.amount_mib, Some(1)),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+                "amount_bytes": 4096
+              }"#;
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_balloon(&Body::new(body), None).unwrap()) {
+            VmmAction::UpdateBalloon(balloon_cfg) => {
+                assert_eq!(balloon_cfg.amount_bytes, Some(4096))
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+                "usable_memory_bytes": 268435456
+              }"#;
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_balloon(&Body::new(body), None).unwrap()) {
+            VmmAction::UpdateBalloon(balloon_cfg) => {
+                assert_eq!(balloon_cfg.usable_memory_bytes, Some(256 << 20))
+            }
             _ => panic!("Test failed: Invalid parameters"),
         };
 
@@ -165,5 +228,12 @@ mod tests {
                 "stats_polling_interval_s": 0
             }"#;
         assert!(parse_put_balloon(&Body::new(body)).is_ok());
+
+        // PUT with a target in bytes.
+        let body = r#"{
+                "amount_bytes": 1052672,
+                "deflate_on_oom": true
+            }"#;
+        assert!(parse_put_balloon(&Body::new(body)).is_ok());
     }
 }
@@ -34,12 +34,56 @@ mod tests {
         let same_body = BootSourceConfig {
             kernel_image_path: String::from("/foo/bar"),
             initrd_path: Some(String::from("/bar/foo")),
+            initrd_paths: Vec::new(),
             boot_args: Some(String::from("foobar")),
+            pvh_firmware_path: None,
+            pvh_boot: false,
         };
         let result = parse_put_boot_source(&Body::new(body));
         assert!(result.is_ok());
         let parsed_req = result.unwrap_or_else(|_e| panic!("Failed test."));
 
         assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "pvh_firmware_path": "/foo/firmware"
+              }"#;
+        let same_body = BootSourceConfig {
+            kernel_image_path: String::new(),
+            initrd_path: None,
+            initrd_paths: Vec::new(),
+            boot_args: None,
+            pvh_firmware_path: Some(String::from("/foo/firmware")),
+            pvh_boot: false,
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bar",
+                "initrd_paths": ["/bar/foo", "/bar/baz"]
+              }"#;
+        let same_body = BootSourceConfig {
+            kernel_image_path: String::from("/foo/bar"),
+            initrd_path: None,
+            initrd_paths: vec![String::from("/bar/foo"), String::from("/bar/baz")],
+            boot_args: None,
+            pvh_firmware_path: None,
+            pvh_boot: false,
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bar",
+                "pvh_boot": true
+              }"#;
+        let same_body = BootSourceConfig {
+            kernel_image_path: String::from("/foo/bar"),
+            pvh_boot: true,
+            ..Default::default()
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
     }
 }
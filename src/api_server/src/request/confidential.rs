@@ -0,0 +1,76 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::confidential::ConfidentialConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_get_confidential() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetConfidentialInfo))
+}
+
+pub(crate) fn parse_put_confidential(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetConfidential(
+        serde_json::from_slice::<ConfidentialConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::confidential::{ConfidentialTechnology, DEFAULT_SEV_SNP_POLICY};
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_confidential_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_confidential().unwrap()),
+            VmmAction::GetConfidentialInfo
+        );
+    }
+
+    #[test]
+    fn test_parse_put_confidential_request() {
+        assert!(parse_put_confidential(&Body::new("invalid_payload")).is_err());
+
+        // PUT with an unknown technology.
+        let body = r#"{
+                "technology": "sev"
+              }"#;
+        assert!(parse_put_confidential(&Body::new(body)).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "technology": "sev-snp",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_confidential(&Body::new(body)).is_err());
+
+        // PUT with the policy left to its default.
+        let body = r#"{
+                "technology": "sev-snp"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_confidential(&Body::new(body)).unwrap()),
+            VmmAction::SetConfidential(ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY,
+            })
+        );
+
+        let body = r#"{
+                "technology": "tdx",
+                "policy": 0
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_confidential(&Body::new(body)).unwrap()),
+            VmmAction::SetConfidential(ConfidentialConfig {
+                technology: ConfidentialTechnology::Tdx,
+                policy: 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::coredump::CoredumpParams;
+
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_coredump(body: &Body) -> Result<ParsedRequest, Error> {
+    let params = serde_json::from_slice::<CoredumpParams>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::CreateCoredump(params)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_coredump_request() {
+        assert!(parse_put_coredump(&Body::new("invalid_payload")).is_err());
+        assert!(parse_put_coredump(&Body::new("{}")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+            "dump_path": "/tmp/vmcore",
+            "format": "elf"
+        }"#;
+        assert!(parse_put_coredump(&Body::new(body)).is_err());
+
+        let body = r#"{
+            "dump_path": "/tmp/vmcore"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_coredump(&Body::new(body)).unwrap()),
+            VmmAction::CreateCoredump(CoredumpParams {
+                dump_path: PathBuf::from("/tmp/vmcore"),
+            })
+        );
+    }
+}
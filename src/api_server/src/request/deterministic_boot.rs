@@ -0,0 +1,50 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::deterministic_boot::DeterministicBootConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_deterministic_boot(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetDeterministicBoot(
+        serde_json::from_slice::<DeterministicBootConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_deterministic_boot_request() {
+        assert!(parse_put_deterministic_boot(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "seed": 42,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_deterministic_boot(&Body::new(body)).is_err());
+
+        // Both fields are optional.
+        assert_eq!(
+            vmm_action_from_request(parse_put_deterministic_boot(&Body::new("{}")).unwrap()),
+            VmmAction::SetDeterministicBoot(DeterministicBootConfig::default())
+        );
+
+        let body = r#"{
+                "seed": 42,
+                "start_time_s": 1700000000
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_deterministic_boot(&Body::new(body)).unwrap()),
+            VmmAction::SetDeterministicBoot(DeterministicBootConfig {
+                seed: 42,
+                start_time_s: 1_700_000_000,
+            })
+        );
+    }
+}
@@ -67,14 +67,21 @@ pub(crate) fn parse_patch_drive(
     // Validate request - we need to have at least one parameter set:
     // - path_on_host
     // - rate_limiter
+    // - state
+    // - cache_type
+    // - direct_io
     if block_device_update_cfg.path_on_host.is_none()
         && block_device_update_cfg.rate_limiter.is_none()
+        && block_device_update_cfg.state.is_none()
+        && block_device_update_cfg.cache_type.is_none()
+        && block_device_update_cfg.direct_io.is_none()
     {
         METRICS.patch_api_requests.drive_fails.inc();
         return Err(Error::Generic(
             StatusCode::BadRequest,
             String::from(
-                "Please specify at least one property to patch: path_on_host, rate_limiter.",
+                "Please specify at least one property to patch: path_on_host, rate_limiter, \
+                 state, cache_type, direct_io.",
             ),
         ));
     }
@@ -86,6 +93,9 @@ pub(crate) fn parse_patch_drive(
 
 #[cfg(test)]
 mod tests {
+    use vmm::vmm_config::drive::CacheType;
+    use vmm::vmm_config::DeviceRunState;
+
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
@@ -199,6 +209,40 @@ mod tests {
         // Validate that updating both path and rate limiter succeds.
         assert!(parse_patch_drive(&Body::new(body), Some("foo")).is_ok());
 
+        let body = r#"{
+            "drive_id": "foo",
+            "state": "Paused"
+        }"#;
+        // Validate that updating just the state works.
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                assert_eq!(cfg.state, Some(DeviceRunState::Paused));
+                assert!(cfg.path_on_host.is_none());
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+            "drive_id": "foo",
+            "state": "Stopped"
+        }"#;
+        assert!(parse_patch_drive(&Body::new(body), Some("foo")).is_err());
+
+        let body = r#"{
+            "drive_id": "foo",
+            "cache_type": "Writethrough",
+            "direct_io": true
+        }"#;
+        // Validate that updating just the cache policy works.
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                assert_eq!(cfg.cache_type, Some(CacheType::Writethrough));
+                assert_eq!(cfg.direct_io, Some(true));
+                assert!(cfg.state.is_none());
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
         let body = r#"{
             "drive_id": "foo",
             "path_on_host": "/there",
@@ -245,6 +289,7 @@ mod tests {
                 "is_read_only": true,
                 "cache_type": "Unsafe",
                 "io_engine": "Sync",
+                "direct_io": false,
                 "rate_limiter": {
                     "bandwidth": {
                         "size": 0,
@@ -0,0 +1,62 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::gdb::GdbConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_gdb(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetGdb(
+        serde_json::from_slice::<GdbConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_gdb_request() {
+        assert!(parse_put_gdb(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "socket_path": "/gdb.sock",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_gdb(&Body::new(body)).is_err());
+
+        // PUT with an invalid TCP address.
+        let body = r#"{
+                "tcp_address": "1234"
+              }"#;
+        assert!(parse_put_gdb(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "socket_path": "/gdb.sock"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_gdb(&Body::new(body)).unwrap()),
+            VmmAction::SetGdb(GdbConfig {
+                socket_path: Some(PathBuf::from("/gdb.sock")),
+                tcp_address: None,
+            })
+        );
+
+        let body = r#"{
+                "tcp_address": "127.0.0.1:1234"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_gdb(&Body::new(body)).unwrap()),
+            VmmAction::SetGdb(GdbConfig {
+                socket_path: None,
+                tcp_address: Some("127.0.0.1:1234".parse().unwrap()),
+            })
+        );
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::guest_files::{GuestFileCopyParams, GuestFilesConfig};
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::{Body, Method};
+
+pub(crate) fn parse_put_guest_files(
+    body: &Body,
+    operation_from_path: Option<&str>,
+) -> Result<ParsedRequest, Error> {
+    match operation_from_path {
+        None => Ok(ParsedRequest::new_sync(VmmAction::SetGuestFiles(
+            serde_json::from_slice::<GuestFilesConfig>(body.raw())?,
+        ))),
+        Some("copy") => Ok(ParsedRequest::new_sync(VmmAction::CopyGuestFile(
+            serde_json::from_slice::<GuestFileCopyParams>(body.raw())?,
+        ))),
+        Some(operation) => Err(Error::InvalidPathMethod(
+            format!("/guest-files/{}", operation),
+            Method::Put,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use vmm::vmm_config::guest_files::GuestFileDirection;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_guest_files_request() {
+        assert!(parse_put_guest_files(&Body::new("invalid_payload"), None).is_err());
+
+        // PUT without the allowed directories.
+        let body = r#"{
+                "port": 52
+              }"#;
+        assert!(parse_put_guest_files(&Body::new(body), None).is_err());
+
+        let body = r#"{
+                "port": 52,
+                "host_dirs": ["/srv/outputs"],
+                "guest_dirs": ["/workspace"],
+                "allow_writes": true
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_guest_files(&Body::new(body), None).unwrap()),
+            VmmAction::SetGuestFiles(GuestFilesConfig {
+                port: 52,
+                host_dirs: vec![PathBuf::from("/srv/outputs")],
+                guest_dirs: vec![PathBuf::from("/workspace")],
+                allow_writes: true,
+                max_file_size_mib: 1024,
+                timeout_ms: 10_000,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_put_guest_files_copy_request() {
+        // PUT with unknown fields.
+        let body = r#"{
+                "direction": "FromGuest",
+                "guest_path": "/workspace/out.tar",
+                "host_path": "/srv/outputs/out.tar",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_guest_files(&Body::new(body), Some("copy")).is_err());
+
+        let body = r#"{
+                "direction": "FromGuest",
+                "guest_path": "/workspace/out.tar",
+                "host_path": "/srv/outputs/out.tar"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_guest_files(&Body::new(body), Some("copy")).unwrap()),
+            VmmAction::CopyGuestFile(GuestFileCopyParams {
+                direction: GuestFileDirection::FromGuest,
+                guest_path: PathBuf::from("/workspace/out.tar"),
+                host_path: PathBuf::from("/srv/outputs/out.tar"),
+            })
+        );
+
+        assert!(matches!(
+            parse_put_guest_files(&Body::new(body), Some("move")),
+            Err(Error::InvalidPathMethod(_, Method::Put))
+        ));
+    }
+}
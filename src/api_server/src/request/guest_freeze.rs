@@ -0,0 +1,69 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::guest_freeze::{GuestFreezeConfig, GuestFreezeUpdate};
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_guest_freeze(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetGuestFreeze(
+        serde_json::from_slice::<GuestFreezeConfig>(body.raw())?,
+    )))
+}
+
+pub(crate) fn parse_patch_guest_freeze(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateGuestFreeze(
+        serde_json::from_slice::<GuestFreezeUpdate>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::guest_freeze::GuestFreezeState;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_guest_freeze_request() {
+        assert!(parse_put_guest_freeze(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the port.
+        let body = r#"{
+                "timeout_ms": 5000
+              }"#;
+        assert!(parse_put_guest_freeze(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "port": 53
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_guest_freeze(&Body::new(body)).unwrap()),
+            VmmAction::SetGuestFreeze(GuestFreezeConfig {
+                port: 53,
+                timeout_ms: 10_000,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_guest_freeze_request() {
+        // PATCH with an unknown state.
+        let body = r#"{
+                "state": "Melted"
+              }"#;
+        assert!(parse_patch_guest_freeze(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "state": "Frozen"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_guest_freeze(&Body::new(body)).unwrap()),
+            VmmAction::UpdateGuestFreeze(GuestFreezeUpdate {
+                state: GuestFreezeState::Frozen,
+            })
+        );
+    }
+}
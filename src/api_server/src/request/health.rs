@@ -0,0 +1,26 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::rpc_interface::VmmAction;
+
+use crate::parsed_request::{Error, ParsedRequest};
+
+pub(crate) fn parse_get_health() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.health_count.inc();
+    Ok(ParsedRequest::new_sync(VmmAction::GetHealth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RequestAction;
+
+    #[test]
+    fn test_parse_get_health_request() {
+        match parse_get_health().unwrap().into_parts() {
+            (RequestAction::Sync(action), _) if *action == VmmAction::GetHealth => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
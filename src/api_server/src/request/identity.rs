@@ -0,0 +1,46 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::identity::IdentityConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_identity(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetIdentity(
+        serde_json::from_slice::<IdentityConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_identity_request() {
+        assert!(parse_put_identity(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "uuid": "01234567-89ab-cdef-0123-456789abcdef",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_identity(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "uuid": "01234567-89ab-cdef-0123-456789abcdef",
+                "signing_key_path": "/identity.key",
+                "rotate_on_restore": true
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_identity(&Body::new(body)).unwrap()),
+            VmmAction::SetIdentity(IdentityConfig {
+                uuid: Some("01234567-89ab-cdef-0123-456789abcdef".to_string()),
+                signing_key_path: Some("/identity.key".to_string()),
+                rotate_on_restore: true,
+            })
+        );
+    }
+}
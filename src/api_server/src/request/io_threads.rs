@@ -0,0 +1,57 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::io_threads::IoThreadsConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_io_threads(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetIoThreads(
+        serde_json::from_slice::<IoThreadsConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::io_threads::IoThreadAssignment;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_io_threads_request() {
+        assert!(parse_put_io_threads(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the thread count.
+        assert!(parse_put_io_threads(&Body::new("{}")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "thread_count": 2,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_io_threads(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "thread_count": 2,
+                "devices": [
+                    {
+                        "device_id": "rootfs",
+                        "thread": 1
+                    }
+                ]
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_io_threads(&Body::new(body)).unwrap()),
+            VmmAction::SetIoThreads(IoThreadsConfig {
+                thread_count: 2,
+                devices: vec![IoThreadAssignment {
+                    device_id: "rootfs".to_string(),
+                    thread: 1,
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::landlock::LandlockConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_landlock(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetLandlock(
+        serde_json::from_slice::<LandlockConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use vmm::vmm_config::landlock::LandlockAllowedPath;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_landlock_request() {
+        assert!(parse_put_landlock(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "allowed_paths": [],
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_landlock(&Body::new(body)).is_err());
+
+        // PUT without any allowed path.
+        assert_eq!(
+            vmm_action_from_request(parse_put_landlock(&Body::new("{}")).unwrap()),
+            VmmAction::SetLandlock(LandlockConfig::default())
+        );
+
+        let body = r#"{
+                "allowed_paths": [
+                    {"path": "/srv/snapshots"},
+                    {"path": "/srv/drives/data.ext4", "read_only": true}
+                ]
+              }"#;
+        let expected_config = LandlockConfig {
+            allowed_paths: vec![
+                LandlockAllowedPath {
+                    path: PathBuf::from("/srv/snapshots"),
+                    read_only: false,
+                },
+                LandlockAllowedPath {
+                    path: PathBuf::from("/srv/drives/data.ext4"),
+                    read_only: true,
+                },
+            ],
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_landlock(&Body::new(body)).unwrap()),
+            VmmAction::SetLandlock(expected_config)
+        );
+    }
+}
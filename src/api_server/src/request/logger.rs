@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::logger::LoggerConfig;
+use vmm::vmm_config::logger::{LoggerConfig, LoggerUpdateConfig};
 
 use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
@@ -18,11 +18,21 @@ pub(crate) fn parse_put_logger(body: &Body) -> Result<ParsedRequest, Error> {
     Ok(ParsedRequest::new_sync(VmmAction::ConfigureLogger(config)))
 }
 
+pub(crate) fn parse_patch_logger(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.patch_api_requests.logger_count.inc();
+    let update = serde_json::from_slice::<LoggerUpdateConfig>(body.raw()).map_err(|err| {
+        METRICS.patch_api_requests.logger_fails.inc();
+        err
+    })?;
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateLogger(update)))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
-    use vmm::vmm_config::logger::LoggerLevel;
+    use vmm::vmm_config::logger::{LoggerFormat, LoggerLevel, LoggerModuleFilter};
 
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
@@ -41,6 +51,9 @@ mod tests {
             level: LoggerLevel::Warning,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Text,
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         match vmm_action_from_request(parse_put_logger(&Body::new(body)).unwrap()) {
             VmmAction::ConfigureLogger(cfg) => assert_eq!(cfg, expected_cfg),
@@ -51,7 +64,8 @@ mod tests {
                 "log_path": "log",
                 "level": "DEBUG",
                 "show_level": false,
-                "show_log_origin": false
+                "show_log_origin": false,
+                "format": "Json"
               }"#;
 
         expected_cfg = LoggerConfig {
@@ -59,6 +73,9 @@ mod tests {
             level: LoggerLevel::Debug,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Json,
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         match vmm_action_from_request(parse_put_logger(&Body::new(body)).unwrap()) {
             VmmAction::ConfigureLogger(cfg) => assert_eq!(cfg, expected_cfg),
@@ -74,4 +91,38 @@ mod tests {
 
         assert!(parse_put_logger(&Body::new(invalid_body)).is_err());
     }
+
+    #[test]
+    fn test_parse_patch_logger_request() {
+        let body = r#"{
+                "level": "info",
+                "module_filters": [
+                    { "module": "devices::virtio::block", "level": "Debug" }
+                ]
+              }"#;
+        let expected_update = LoggerUpdateConfig {
+            level: Some(LoggerLevel::Info),
+            module_filters: Some(vec![LoggerModuleFilter {
+                module: String::from("devices::virtio::block"),
+                level: LoggerLevel::Debug,
+            }]),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_patch_logger(&Body::new(body)).unwrap()),
+            VmmAction::UpdateLogger(expected_update)
+        );
+
+        let body = r#"{ "module_filters": [] }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_logger(&Body::new(body)).unwrap()),
+            VmmAction::UpdateLogger(LoggerUpdateConfig {
+                level: None,
+                module_filters: Some(vec![]),
+            })
+        );
+
+        let body = r#"{ "log_path": "log" }"#;
+        assert!(parse_patch_logger(&Body::new(body)).is_err());
+        assert!(METRICS.patch_api_requests.logger_fails.count() > 0);
+    }
 }
@@ -47,6 +47,7 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, E
 #[cfg(test)]
 mod tests {
     use vmm::cpu_config::templates::StaticCpuTemplate;
+    use vmm::vmm_config::machine_config::{CpuBandwidth, MemLock, SchedPolicy, ThreadScheduling};
 
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
@@ -85,6 +86,15 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(false),
+            acpi: Some(false),
+            split_irqchip: Some(false),
+            gic_its: Some(false),
+            vcpu_scheduling: None,
+            io_scheduling: None,
+            cpu_bandwidth: None,
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -104,6 +114,15 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(true),
+            acpi: Some(false),
+            split_irqchip: Some(false),
+            gic_its: Some(false),
+            vcpu_scheduling: None,
+            io_scheduling: None,
+            cpu_bandwidth: None,
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -128,6 +147,15 @@ mod tests {
                 smt: Some(false),
                 cpu_template: Some(StaticCpuTemplate::T2),
                 track_dirty_pages: Some(true),
+                acpi: Some(false),
+                split_irqchip: Some(false),
+                gic_its: Some(false),
+                vcpu_scheduling: None,
+                io_scheduling: None,
+                cpu_bandwidth: None,
+                mmio_layout: None,
+                mem_backing_path: None,
+                mem_lock: None,
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -157,6 +185,15 @@ mod tests {
                 smt: Some(true),
                 cpu_template: Some(StaticCpuTemplate::None),
                 track_dirty_pages: Some(true),
+                acpi: Some(false),
+                split_irqchip: Some(false),
+                gic_its: Some(false),
+                vcpu_scheduling: None,
+                io_scheduling: None,
+                cpu_bandwidth: None,
+                mmio_layout: None,
+                mem_backing_path: None,
+                mem_lock: None,
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -169,6 +206,177 @@ mod tests {
         {
             assert!(parse_put_machine_config(&Body::new(body)).is_err());
         }
+
+        // 6. Test that enabling ACPI is successful on x86_64 while on aarch64, it is not.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "acpi": true
+          }"#;
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let expected_config = MachineConfigUpdate {
+                vcpu_count: Some(8),
+                mem_size_mib: Some(1024),
+                smt: Some(false),
+                cpu_template: Some(StaticCpuTemplate::None),
+                track_dirty_pages: Some(false),
+                acpi: Some(true),
+                split_irqchip: Some(false),
+                gic_its: Some(false),
+                vcpu_scheduling: None,
+                io_scheduling: None,
+                cpu_bandwidth: None,
+                mmio_layout: None,
+                mem_backing_path: None,
+                mem_lock: None,
+            };
+
+            match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+                VmmAction::UpdateVmConfiguration(config) => assert_eq!(config, expected_config),
+                _ => panic!("Test failed."),
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        }
+
+        // 7. Test that enabling the split irqchip is successful on x86_64 while on aarch64, it is
+        // not.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "split_irqchip": true
+          }"#;
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let expected_config = MachineConfigUpdate {
+                vcpu_count: Some(8),
+                mem_size_mib: Some(1024),
+                smt: Some(false),
+                cpu_template: Some(StaticCpuTemplate::None),
+                track_dirty_pages: Some(false),
+                acpi: Some(false),
+                split_irqchip: Some(true),
+                gic_its: Some(false),
+                vcpu_scheduling: None,
+                io_scheduling: None,
+                cpu_bandwidth: None,
+                mmio_layout: None,
+                mem_backing_path: None,
+                mem_lock: None,
+            };
+
+            match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+                VmmAction::UpdateVmConfiguration(config) => assert_eq!(config, expected_config),
+                _ => panic!("Test failed."),
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        }
+
+        // 8. Test the scheduling settings of the vCPU and VMM threads.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "vcpu_scheduling": { "policy": "idle" },
+            "io_scheduling": { "policy": "fifo", "priority": 10 },
+            "cpu_bandwidth": { "quota_us": 50000 }
+          }"#;
+        let expected_config = MachineConfigUpdate {
+            vcpu_count: Some(8),
+            mem_size_mib: Some(1024),
+            smt: Some(false),
+            cpu_template: Some(StaticCpuTemplate::None),
+            track_dirty_pages: Some(false),
+            acpi: Some(false),
+            split_irqchip: Some(false),
+            gic_its: Some(false),
+            vcpu_scheduling: Some(ThreadScheduling {
+                policy: SchedPolicy::Idle,
+                nice: 0,
+                priority: 0,
+            }),
+            io_scheduling: Some(ThreadScheduling {
+                policy: SchedPolicy::Fifo,
+                nice: 0,
+                priority: 10,
+            }),
+            cpu_bandwidth: Some(CpuBandwidth {
+                quota_us: Some(50000),
+                period_us: 100000,
+            }),
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
+        };
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVmConfiguration(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "vcpu_scheduling": { "policy": "deadline" }
+          }"#;
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
+
+        // 9. Test the memfd backing the guest memory.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "mem_backing_path": "/proc/self/fd/3"
+          }"#;
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVmConfiguration(config) => {
+                assert_eq!(config.mem_backing_path, Some("/proc/self/fd/3".to_string()))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 10. Test locking the guest memory.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "mem_lock": "hot"
+          }"#;
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVmConfiguration(config) => {
+                assert_eq!(config.mem_lock, Some(MemLock::Hot))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "mem_lock": "cold"
+          }"#;
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
+
+        // 11. Test that enabling the GIC ITS is successful on aarch64 while on x86_64, it is not.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "gic_its": true
+          }"#;
+
+        #[cfg(target_arch = "aarch64")]
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVmConfiguration(config) => assert_eq!(config.gic_its, Some(true)),
+            _ => panic!("Test failed."),
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
     }
 
     #[test]
@@ -205,6 +413,16 @@ mod tests {
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "vcpu_scheduling": { "policy": "batch", "nice": 10 }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "cpu_bandwidth": { "quota_us": 20000, "period_us": 100000 }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
         // 3. Check to see if an empty body returns an error.
         let body = r#"{}"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_err());
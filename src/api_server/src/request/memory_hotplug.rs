@@ -0,0 +1,103 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugSizeUpdate};
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_get_memory_hotplug() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetMemoryHotplugStatus))
+}
+
+pub(crate) fn parse_put_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryHotplugDevice(
+        serde_json::from_slice::<MemoryHotplugConfig>(body.raw())?,
+    )))
+}
+
+pub(crate) fn parse_patch_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateMemoryHotplugSize(
+        serde_json::from_slice::<MemoryHotplugSizeUpdate>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_memory_hotplug_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_memory_hotplug().unwrap()),
+            VmmAction::GetMemoryHotplugStatus
+        );
+    }
+
+    #[test]
+    fn test_parse_put_memory_hotplug_request() {
+        assert!(parse_put_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "total_size_mib": 1024,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        // PUT with the block size left to its default.
+        let body = r#"{
+                "total_size_mib": 1024
+              }"#;
+        let expected_config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap()),
+            VmmAction::SetMemoryHotplugDevice(expected_config)
+        );
+
+        let body = r#"{
+                "total_size_mib": 1024,
+                "block_size_mib": 128
+              }"#;
+        let expected_config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 128,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap()),
+            VmmAction::SetMemoryHotplugDevice(expected_config)
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_memory_hotplug_request() {
+        assert!(parse_patch_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PATCH with a negative size.
+        let body = r#"{
+                "requested_size_mib": -2
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        // PATCH that tries to update something other than the requested size.
+        let body = r#"{
+                "total_size_mib": 1024
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "requested_size_mib": 512
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_memory_hotplug(&Body::new(body)).unwrap()),
+            VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+                requested_size_mib: 512
+            })
+        );
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::memory_pressure::MemoryPressureConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_memory_pressure(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryPressure(
+        serde_json::from_slice::<MemoryPressureConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use vmm::vmm_config::memory_pressure::{MemoryPressureAction, MemoryPressureSource};
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_memory_pressure_request() {
+        assert!(parse_put_memory_pressure(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the action.
+        let body = r#"{
+                "path": "/proc/pressure/memory"
+              }"#;
+        assert!(parse_put_memory_pressure(&Body::new(body)).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "path": "/proc/pressure/memory",
+                "action": "Pause",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_memory_pressure(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "source": "MemoryEvents",
+                "path": "/sys/fs/cgroup/vms/memory.events",
+                "action": "InflateBalloon",
+                "balloon_step_mib": 128,
+                "balloon_max_mib": 1024,
+                "recovery_ms": 30000
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_memory_pressure(&Body::new(body)).unwrap()),
+            VmmAction::SetMemoryPressure(MemoryPressureConfig {
+                source: MemoryPressureSource::MemoryEvents,
+                path: PathBuf::from("/sys/fs/cgroup/vms/memory.events"),
+                stall_threshold_ms: 100,
+                window_ms: 1000,
+                action: MemoryPressureAction::InflateBalloon,
+                balloon_step_mib: 128,
+                balloon_max_mib: Some(1024),
+                recovery_ms: Some(30000),
+            })
+        );
+    }
+}
@@ -20,6 +20,7 @@ pub(crate) fn parse_put_metrics(body: &Body) -> Result<ParsedRequest, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
     use super::*;
@@ -28,11 +29,15 @@ mod tests {
     #[test]
     fn test_parse_put_metrics_request() {
         let body = r#"{
-                "metrics_path": "metrics"
+                "metrics_path": "metrics",
+                "instance_id": "vm-1",
+                "labels": { "pool": "a" }
               }"#;
 
         let expected_cfg = MetricsConfig {
             metrics_path: PathBuf::from("metrics"),
+            instance_id: Some(String::from("vm-1")),
+            labels: BTreeMap::from([(String::from("pool"), String::from("a"))]),
         };
         match vmm_action_from_request(parse_put_metrics(&Body::new(body)).unwrap()) {
             VmmAction::ConfigureMetrics(cfg) => assert_eq!(cfg, expected_cfg),
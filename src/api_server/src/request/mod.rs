@@ -2,20 +2,41 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod actions;
+pub mod api_token;
 pub mod balloon;
 pub mod boot_source;
+pub mod confidential;
+pub mod coredump;
 pub mod cpu_configuration;
+pub mod deterministic_boot;
 pub mod drive;
 pub mod entropy;
+pub mod gdb;
+pub mod guest_files;
+pub mod guest_freeze;
+pub mod health;
+pub mod identity;
 pub mod instance_info;
+pub mod io_threads;
+pub mod landlock;
 pub mod logger;
 pub mod machine_configuration;
+pub mod memory_hotplug;
+pub mod memory_pressure;
 pub mod metrics;
 pub mod mmds;
 pub mod net;
+pub mod seccomp;
+pub mod shared_dir;
+pub mod smbios;
 pub mod snapshot;
+pub mod stall_detection;
+pub mod tpm;
+pub mod validate;
 pub mod version;
+pub mod virtio_record;
 pub mod vsock;
+pub mod working_set;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
 };
@@ -75,6 +75,11 @@ pub(crate) fn parse_patch_net(
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
+    use vmm::vmm_config::net::PacketCaptureConfig;
+    use vmm::vmm_config::DeviceRunState;
+
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
@@ -97,7 +102,57 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
-        // 4. Serde error for invalid field (bytes instead of bandwidth).
+        // 4. Success case with a DHCP responder, whose lease time defaults to a day.
+        let body = r#"{
+                "iface_id": "foo",
+                "host_dev_name": "bar",
+                "dhcp": {
+                    "guest_ip": "172.16.0.2",
+                    "netmask": "255.255.255.252",
+                    "gateway": "172.16.0.1",
+                    "nameservers": ["1.1.1.1"],
+                    "dns_records": {"cache.internal": "172.16.0.1"}
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => {
+                let dhcp = netif.dhcp.unwrap();
+                assert_eq!(dhcp.guest_ip, Ipv4Addr::new(172, 16, 0, 2));
+                assert_eq!(dhcp.nameservers, vec![Ipv4Addr::new(1, 1, 1, 1)]);
+                assert_eq!(dhcp.hostname, None);
+                assert_eq!(dhcp.lease_time_s, 86400);
+                assert_eq!(
+                    dhcp.dns_records.get("cache.internal"),
+                    Some(&Ipv4Addr::new(172, 16, 0, 1))
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 5. Success case with the user-mode network stack, which needs no host device.
+        let body = r#"{
+                "iface_id": "foo",
+                "user_net": {
+                    "guest_ip": "10.0.2.15",
+                    "gateway": "10.0.2.2",
+                    "host_loopback_ports": [8080],
+                    "port_forwards": [{"host_port": 2222, "guest_port": 22}]
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::InsertNetworkDevice(netif) => {
+                assert_eq!(netif.host_dev_name, "");
+                let user_net = netif.user_net.unwrap();
+                assert_eq!(user_net.gateway, Ipv4Addr::new(10, 0, 2, 2));
+                assert_eq!(user_net.host_loopback_ports, vec![8080]);
+                assert_eq!(user_net.port_forwards[0].host_address, Ipv4Addr::LOCALHOST);
+                assert_eq!(user_net.port_forwards[0].host_port, 2222);
+                assert_eq!(user_net.port_forwards[0].guest_port, 22);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 6. Serde error for invalid field (bytes instead of bandwidth).
         let body = r#"
         {
             "iface_id": "foo",
@@ -139,7 +194,39 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
-        // 4. Serde error for invalid field (bytes instead of bandwidth).
+        // 4. Success case for a state update.
+        let body = r#"{
+                "iface_id": "foo",
+                "state": "Resumed"
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => {
+                assert_eq!(netif.state, Some(DeviceRunState::Resumed))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // Success case for a packet capture update.
+        let body = r#"{
+                "iface_id": "foo",
+                "capture": {
+                    "pcap_path": "foo.pcap",
+                    "max_size_bytes": 1048576
+                }
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some("foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => assert_eq!(
+                netif.capture,
+                Some(PacketCaptureConfig {
+                    pcap_path: Some(String::from("foo.pcap")),
+                    socket_path: None,
+                    max_size_bytes: Some(1048576),
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        // 5. Serde error for invalid field (bytes instead of bandwidth).
         let body = r#"
         {
             "iface_id": "foo",
@@ -0,0 +1,24 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+
+use crate::parsed_request::{Error, ParsedRequest};
+
+pub(crate) fn parse_get_seccomp() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetSeccompFilters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_seccomp_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_seccomp().unwrap()),
+            VmmAction::GetSeccompFilters
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::shared_dir::SharedDirConfig;
+
+use crate::parsed_request::{checked_id, Error, ParsedRequest};
+use crate::request::{Body, StatusCode};
+
+pub(crate) fn parse_put_shared_dir(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = match id_from_path {
+        Some(id) => checked_id(id)?,
+        None => return Err(Error::EmptyID),
+    };
+
+    let cfg = serde_json::from_slice::<SharedDirConfig>(body.raw())?;
+    if id != cfg.share_id {
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::InsertSharedDir(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_shared_dir_request() {
+        let body = r#"{
+            "share_id": "share",
+            "path_on_host": "/srv/share",
+            "mount_tag": "share"
+        }"#;
+        assert!(parse_put_shared_dir(&Body::new(body), None).is_err());
+        assert!(parse_put_shared_dir(&Body::new(body), Some("other")).is_err());
+        assert!(parse_put_shared_dir(&Body::new("invalid_payload"), Some("share")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+            "share_id": "share",
+            "path_on_host": "/srv/share",
+            "mount_tag": "share",
+            "cache": "always"
+        }"#;
+        assert!(parse_put_shared_dir(&Body::new(body), Some("share")).is_err());
+
+        let body = r#"{
+            "share_id": "share",
+            "path_on_host": "/srv/share",
+            "mount_tag": "share",
+            "is_read_only": true
+        }"#;
+        let expected_config = SharedDirConfig {
+            share_id: "share".to_string(),
+            path_on_host: PathBuf::from("/srv/share"),
+            mount_tag: "share".to_string(),
+            is_read_only: true,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_shared_dir(&Body::new(body), Some("share")).unwrap()),
+            VmmAction::InsertSharedDir(expected_config)
+        );
+    }
+}
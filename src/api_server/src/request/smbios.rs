@@ -0,0 +1,48 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::smbios::SmbiosConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_smbios(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetSmbios(
+        serde_json::from_slice::<SmbiosConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_smbios_request() {
+        assert!(parse_put_smbios(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "manufacturer": "ACME",
+                "asset_tag": "foo"
+              }"#;
+        assert!(parse_put_smbios(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "manufacturer": "ACME",
+                "product_name": "Rocket",
+                "version": "1.0",
+                "serial_number": "SN-42"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_smbios(&Body::new(body)).unwrap()),
+            VmmAction::SetSmbios(SmbiosConfig {
+                manufacturer: Some("ACME".to_string()),
+                product_name: Some("Rocket".to_string()),
+                version: Some("1.0".to_string()),
+                serial_number: Some("SN-42".to_string()),
+            })
+        );
+    }
+}
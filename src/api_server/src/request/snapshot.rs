@@ -4,8 +4,9 @@
 use logger::{IncMetric, METRICS};
 use serde::de::Error as DeserializeError;
 use vmm::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    CloneConfig, CommitSnapshotParams, CreateSnapshotParams, LoadSnapshotConfig,
+    LoadSnapshotParams, MemBackendConfig, MemBackendType, SuspendToDiskParams, UffdHandlerConfig,
+    UffdHandlerExitAction, Vm, VmState, VsockOverride,
 };
 
 use super::super::VmmAction;
@@ -21,6 +22,35 @@ pub const MISSING_FIELD: &str =
 /// Only specifying one of them is allowed.
 pub const TOO_MANY_FIELDS: &str =
     "too many fields: either `mem_backend` or `mem_file_path` exclusively is required";
+/// None of the `snapshot_path` or `manifest_path` fields has been specified.
+pub const MISSING_SNAPSHOT_PATH: &str =
+    "missing field: either `snapshot_path` or `manifest_path` is required";
+/// The `manifest_path` field has been specified along with the paths it provides.
+pub const MANIFEST_CONFLICT: &str = "too many fields: `manifest_path` is not to be used in \
+                                     conjunction with `snapshot_path`, `mem_file_path` or \
+                                     `mem_backend`";
+/// The `handler_exit_action` field has been specified for a backend that is not `Uffd`.
+pub const HANDLER_EXIT_ACTION_WITHOUT_UFFD: &str =
+    "`handler_exit_action` is only supported by the `Uffd` memory backend";
+/// Write-protect mode has been requested for a backend that is not `Uffd`.
+pub const WRITE_PROTECT_WITHOUT_UFFD: &str =
+    "`write_protect` is only supported by the `Uffd` memory backend";
+/// A readahead of the memory file has been requested for a backend that is not `File`.
+pub const READAHEAD_WITHOUT_FILE: &str =
+    "`readahead_mib_per_s` is only supported by the `File` memory backend";
+/// A readahead of the memory file has been requested with a null bandwidth.
+pub const NULL_READAHEAD_BANDWIDTH: &str = "`readahead_mib_per_s` must be positive";
+/// A cache of the guest memory has been specified for a backend that is not `Http`.
+pub const CACHE_WITHOUT_HTTP: &str = "`cache_path` is only supported by the `Http` memory backend";
+/// The `Fallback` handler exit action has been requested without a fallback memory file.
+pub const MISSING_FALLBACK_MEM_FILE: &str =
+    "missing field: `fallback_mem_file_path` is required by the `Fallback` handler exit action";
+/// A fallback memory file has been specified without the `Fallback` handler exit action.
+pub const UNEXPECTED_FALLBACK_MEM_FILE: &str =
+    "`fallback_mem_file_path` is only used by the `Fallback` handler exit action";
+/// The vsock override both sets a new socket path and defers the binding of the socket.
+pub const VSOCK_OVERRIDE_CONFLICT: &str =
+    "too many fields: either `uds_path` or `defer` exclusively is allowed in `vsock_override`";
 
 pub(crate) fn parse_put_snapshot(
     body: &Body,
@@ -28,10 +58,24 @@ pub(crate) fn parse_put_snapshot(
 ) -> Result<ParsedRequest, Error> {
     match request_type_from_path {
         Some(request_type) => match request_type {
-            "create" => Ok(ParsedRequest::new_sync(VmmAction::CreateSnapshot(
-                serde_json::from_slice::<CreateSnapshotParams>(body.raw())?,
-            ))),
+            "create" => {
+                let snapshot_params = serde_json::from_slice::<CreateSnapshotParams>(body.raw())?;
+                let timeout_ms = snapshot_params.timeout_ms;
+                let mut parsed_req =
+                    ParsedRequest::new_sync(VmmAction::CreateSnapshot(snapshot_params));
+                parsed_req.parsing_info().set_timeout_ms(timeout_ms);
+                Ok(parsed_req)
+            }
             "load" => parse_put_snapshot_load(body),
+            "commit" => Ok(ParsedRequest::new_sync(VmmAction::CommitSnapshot(
+                serde_json::from_slice::<CommitSnapshotParams>(body.raw())?,
+            ))),
+            "suspend" => Ok(ParsedRequest::new_sync(VmmAction::SuspendToDisk(
+                serde_json::from_slice::<SuspendToDiskParams>(body.raw())?,
+            ))),
+            "uffd-handler" => Ok(ParsedRequest::new_sync(VmmAction::HandoverUffdHandler(
+                serde_json::from_slice::<UffdHandlerConfig>(body.raw())?,
+            ))),
             _ => Err(Error::InvalidPathMethod(
                 format!("/snapshot/{}", request_type),
                 Method::Put,
@@ -49,21 +93,109 @@ pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error>
 
     match vm.state {
         VmState::Paused => Ok(ParsedRequest::new_sync(VmmAction::Pause)),
+        VmState::PausedLite => Ok(ParsedRequest::new_sync(VmmAction::PauseLite)),
         VmState::Resumed => Ok(ParsedRequest::new_sync(VmmAction::Resume)),
     }
 }
 
+// Checks the fields shared by `PUT /snapshot/load` and `PUT /clone`.
+fn check_load_params(
+    mem_backend: &MemBackendConfig,
+    vsock_override: Option<&VsockOverride>,
+) -> Result<(), Error> {
+    match (
+        &mem_backend.backend_type,
+        mem_backend.handler_exit_action,
+        &mem_backend.fallback_mem_file_path,
+    ) {
+        // The page fault handler is only there with the `Uffd` backend.
+        (MemBackendType::File | MemBackendType::Http, Some(_), _) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                HANDLER_EXIT_ACTION_WITHOUT_UFFD,
+            )))
+        }
+        // The fallback memory file goes together with the `Fallback` action.
+        (_, Some(UffdHandlerExitAction::Fallback), None) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                MISSING_FALLBACK_MEM_FILE,
+            )))
+        }
+        (_, None | Some(UffdHandlerExitAction::Pause), Some(_)) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                UNEXPECTED_FALLBACK_MEM_FILE,
+            )))
+        }
+        _ => {}
+    }
+
+    if mem_backend.write_protect && mem_backend.backend_type != MemBackendType::Uffd {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            WRITE_PROTECT_WITHOUT_UFFD,
+        )));
+    }
+
+    match mem_backend.readahead_mib_per_s {
+        Some(_) if mem_backend.backend_type != MemBackendType::File => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                READAHEAD_WITHOUT_FILE,
+            )))
+        }
+        Some(0) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                NULL_READAHEAD_BANDWIDTH,
+            )))
+        }
+        _ => {}
+    }
+
+    if mem_backend.cache_path.is_some() && mem_backend.backend_type != MemBackendType::Http {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            CACHE_WITHOUT_HTTP,
+        )));
+    }
+
+    if let Some(VsockOverride {
+        uds_path: Some(_),
+        defer: true,
+        ..
+    }) = vsock_override
+    {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            VSOCK_OVERRIDE_CONFLICT,
+        )));
+    }
+
+    Ok(())
+}
+
 fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
     let snapshot_config = serde_json::from_slice::<LoadSnapshotConfig>(body.raw())?;
 
-    match (&snapshot_config.mem_backend, &snapshot_config.mem_file_path) {
-        // Ensure `mem_file_path` and `mem_backend` fields are not present at the same time.
-        (Some(_), Some(_)) => {
-            return Err(Error::SerdeJson(serde_json::Error::custom(TOO_MANY_FIELDS)))
+    if snapshot_config.manifest_path.is_some() {
+        // The manifest lists all the files of the snapshot.
+        if snapshot_config.snapshot_path.is_some()
+            || snapshot_config.mem_backend.is_some()
+            || snapshot_config.mem_file_path.is_some()
+        {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                MANIFEST_CONFLICT,
+            )));
+        }
+    } else {
+        if snapshot_config.snapshot_path.is_none() {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                MISSING_SNAPSHOT_PATH,
+            )));
+        }
+        match (&snapshot_config.mem_backend, &snapshot_config.mem_file_path) {
+            // Ensure `mem_file_path` and `mem_backend` fields are not present at the same time.
+            (Some(_), Some(_)) => {
+                return Err(Error::SerdeJson(serde_json::Error::custom(TOO_MANY_FIELDS)))
+            }
+            // Ensure that one of `mem_file_path` or `mem_backend` fields is always specified.
+            (None, None) => return Err(Error::SerdeJson(serde_json::Error::custom(MISSING_FIELD))),
+            _ => {}
         }
-        // Ensure that one of `mem_file_path` or `mem_backend` fields is always specified.
-        (None, None) => return Err(Error::SerdeJson(serde_json::Error::custom(MISSING_FIELD))),
-        _ => {}
     }
 
     // Check for the presence of deprecated `mem_file_path` field and create
@@ -81,23 +213,41 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
         Some(backend_cfg) => backend_cfg,
         None => {
             MemBackendConfig {
-                // This is safe to unwrap() because we ensure above that one of the two:
-                // either `mem_file_path` or `mem_backend` field is always specified.
-                backend_path: snapshot_config.mem_file_path.unwrap(),
+                // Either `mem_file_path` is specified, or the path is read from the manifest
+                // when the snapshot is loaded.
+                backend_path: snapshot_config.mem_file_path.unwrap_or_default(),
                 backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             }
         }
     };
 
+    check_load_params(&mem_backend, snapshot_config.vsock_override.as_ref())?;
+
     let snapshot_params = LoadSnapshotParams {
-        snapshot_path: snapshot_config.snapshot_path,
+        snapshot_path: snapshot_config.snapshot_path.unwrap_or_default(),
         mem_backend,
         enable_diff_snapshots: snapshot_config.enable_diff_snapshots,
         resume_vm: snapshot_config.resume_vm,
+        timeout_ms: snapshot_config.timeout_ms,
+        vsock_override: snapshot_config.vsock_override,
+        restore_info: snapshot_config.restore_info,
+        network_overrides: Vec::new(),
+        drive_overrides: Vec::new(),
+        mmds: None,
+        vcpu_count: snapshot_config.vcpu_count,
+        manifest_path: snapshot_config.manifest_path,
+        mem_diff_layers: Vec::new(),
     };
 
     // Construct the `ParsedRequest` object.
+    let timeout_ms = snapshot_params.timeout_ms;
     let mut parsed_req = ParsedRequest::new_sync(VmmAction::LoadSnapshot(snapshot_params));
+    parsed_req.parsing_info().set_timeout_ms(timeout_ms);
 
     // If `mem_file_path` was present, set the deprecation message in `parsing_info`.
     if let Some(msg) = deprecation_message {
@@ -107,6 +257,36 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
     Ok(parsed_req)
 }
 
+pub(crate) fn parse_put_clone(body: &Body) -> Result<ParsedRequest, Error> {
+    let clone_config = serde_json::from_slice::<CloneConfig>(body.raw())?;
+    check_load_params(
+        &clone_config.mem_backend,
+        clone_config.vsock_override.as_ref(),
+    )?;
+
+    // A clone is a snapshot load which always resumes the microVM.
+    let snapshot_params = LoadSnapshotParams {
+        snapshot_path: clone_config.snapshot_path,
+        mem_backend: clone_config.mem_backend,
+        enable_diff_snapshots: clone_config.enable_diff_snapshots,
+        resume_vm: true,
+        timeout_ms: clone_config.timeout_ms,
+        vsock_override: clone_config.vsock_override,
+        restore_info: clone_config.restore_info,
+        network_overrides: clone_config.network_overrides,
+        drive_overrides: clone_config.drive_overrides,
+        mmds: clone_config.mmds,
+        vcpu_count: clone_config.vcpu_count,
+        manifest_path: None,
+        mem_diff_layers: Vec::new(),
+    };
+
+    let timeout_ms = snapshot_params.timeout_ms;
+    let mut parsed_req = ParsedRequest::new_sync(VmmAction::LoadSnapshot(snapshot_params));
+    parsed_req.parsing_info().set_timeout_ms(timeout_ms);
+    Ok(parsed_req)
+}
+
 #[cfg(test)]
 mod tests {
     use vmm::vmm_config::snapshot::{MemBackendConfig, MemBackendType, Version};
@@ -132,6 +312,7 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: Some(Version::new(0, 23, 0)),
+            timeout_ms: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap())
@@ -150,6 +331,7 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: None,
+            timeout_ms: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap())
@@ -158,6 +340,23 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "timeout_ms": 500
+              }"#;
+
+        expected_cfg.timeout_ms = Some(500);
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("create")).unwrap();
+        assert_eq!(
+            parsed_request.parsing_info().timeout(),
+            Some(std::time::Duration::from_millis(500))
+        );
+        match vmm_action_from_request(parsed_request) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
         let invalid_body = r#"{
                 "invalid_field": "foo",
                 "mem_file_path": "bar"
@@ -178,9 +377,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         };
 
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
@@ -194,6 +407,26 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "timeout_ms": 500
+              }"#;
+
+        expected_cfg.timeout_ms = Some(500);
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
+        assert_eq!(
+            parsed_request.parsing_info().timeout(),
+            Some(std::time::Duration::from_millis(500))
+        );
+        match vmm_action_from_request(parsed_request) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
         body = r#"{
                 "snapshot_path": "foo",
                 "mem_backend": {
@@ -208,9 +441,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: true,
             resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         };
 
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
@@ -237,9 +484,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::Uffd,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         };
 
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
@@ -263,9 +524,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         };
 
         let parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
@@ -347,14 +622,610 @@ mod tests {
                 .err()
                 .unwrap()
                 .to_string(),
-            "An error occurred when deserializing the json body of a request: missing field \
-             `snapshot_path` at line 6 column 15."
+            Error::SerdeJson(serde_json::Error::custom(MISSING_SNAPSHOT_PATH)).to_string()
         );
 
         assert!(parse_put_snapshot(&Body::new(body), Some("invalid")).is_err());
         assert!(parse_put_snapshot(&Body::new(body), None).is_err());
     }
 
+    #[test]
+    fn test_parse_put_snapshot_load_handler_exit_action() {
+        use std::path::PathBuf;
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "handler_exit_action": "Fallback",
+                    "fallback_mem_file_path": "baz"
+                }
+              }"#;
+        let expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_backend: MemBackendConfig {
+                backend_path: PathBuf::from("bar"),
+                backend_type: MemBackendType::Uffd,
+                handler_exit_action: Some(UffdHandlerExitAction::Fallback),
+                fallback_mem_file_path: Some(PathBuf::from("baz")),
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        };
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "handler_exit_action": "Pause"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.mem_backend.handler_exit_action,
+                Some(UffdHandlerExitAction::Pause)
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "handler_exit_action": "Pause"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(HANDLER_EXIT_ACTION_WITHOUT_UFFD))
+                .to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "handler_exit_action": "Fallback"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(MISSING_FALLBACK_MEM_FILE)).to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "handler_exit_action": "Pause",
+                    "fallback_mem_file_path": "baz"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(UNEXPECTED_FALLBACK_MEM_FILE)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_write_protect() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "write_protect": true
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert!(cfg.mem_backend.write_protect),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "write_protect": true
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(WRITE_PROTECT_WITHOUT_UFFD)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_readahead() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "readahead_mib_per_s": 200
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(cfg.mem_backend.readahead_mib_per_s, Some(200))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd",
+                    "readahead_mib_per_s": 200
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(READAHEAD_WITHOUT_FILE)).to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "readahead_mib_per_s": 0
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(NULL_READAHEAD_BANDWIDTH)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_manifest() {
+        let body = r#"{
+                "manifest_path": "/srv/snapshots/vm1/manifest.json",
+                "resume_vm": true
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(
+                    cfg.manifest_path.as_deref(),
+                    Some(std::path::Path::new("/srv/snapshots/vm1/manifest.json"))
+                );
+                assert_eq!(cfg.mem_backend.backend_type, MemBackendType::File);
+                assert!(cfg.resume_vm);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        for body in [
+            r#"{
+                "manifest_path": "manifest.json",
+                "snapshot_path": "foo"
+              }"#,
+            r#"{
+                "manifest_path": "manifest.json",
+                "mem_file_path": "bar"
+              }"#,
+            r#"{
+                "manifest_path": "manifest.json",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                }
+              }"#,
+        ] {
+            assert_eq!(
+                parse_put_snapshot(&Body::new(body), Some("load"))
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                Error::SerdeJson(serde_json::Error::custom(MANIFEST_CONFLICT)).to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_http() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "http://10.0.0.1:8080/snapshots/mem",
+                    "backend_type": "Http",
+                    "cache_path": "/srv/cache/mem"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(cfg.mem_backend.backend_type, MemBackendType::Http);
+                assert_eq!(
+                    cfg.mem_backend.cache_path.as_deref(),
+                    Some(std::path::Path::new("/srv/cache/mem"))
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "cache_path": "/srv/cache/mem"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(CACHE_WITHOUT_HTTP)).to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "http://10.0.0.1:8080/snapshots/mem",
+                    "backend_type": "Http",
+                    "handler_exit_action": "Pause"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(HANDLER_EXIT_ACTION_WITHOUT_UFFD))
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_vsock_override() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "vsock_override": {
+                    "uds_path": "v.sock"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.vsock_override,
+                Some(VsockOverride {
+                    uds_path: Some("v.sock".to_string()),
+                    ..Default::default()
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "vsock_override": {
+                    "defer": true
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert!(cfg.vsock_override.unwrap().defer),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "vsock_override": {
+                    "uds_path": "v.sock",
+                    "defer": true
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some("load"))
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(VSOCK_OVERRIDE_CONFLICT)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_restore_info() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "restore_info": {
+                    "ip": "10.0.0.2",
+                    "generation": 3
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                serde_json::Value::Object(cfg.restore_info.unwrap()),
+                serde_json::json!({"ip": "10.0.0.2", "generation": 3})
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        // The restore information must be an object.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "restore_info": "10.0.0.2"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some("load")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_load_vcpu_count() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "vcpu_count": 2
+              }"#;
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("load")).unwrap()) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg.vcpu_count, Some(2)),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "vcpu_count": 256
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some("load")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_clone() {
+        use std::path::PathBuf;
+
+        use vmm::vmm_config::snapshot::{DriveOverride, NetworkOverride};
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File"
+                },
+                "timeout_ms": 5000,
+                "network_overrides": [
+                    { "iface_id": "eth0", "host_dev_name": "tap1" }
+                ],
+                "drive_overrides": [
+                    { "drive_id": "scratch", "path_on_host": "overlay.ext4" }
+                ],
+                "mmds": { "task": "t1" },
+                "restore_info": { "generation": 2 }
+              }"#;
+        let expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_backend: MemBackendConfig {
+                backend_path: PathBuf::from("bar"),
+                backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: true,
+            timeout_ms: Some(5000),
+            vsock_override: None,
+            restore_info: serde_json::from_str(r#"{ "generation": 2 }"#).unwrap(),
+            network_overrides: vec![NetworkOverride {
+                iface_id: "eth0".to_string(),
+                host_dev_name: "tap1".to_string(),
+            }],
+            drive_overrides: vec![DriveOverride {
+                drive_id: "scratch".to_string(),
+                path_on_host: "overlay.ext4".to_string(),
+            }],
+            mmds: serde_json::from_str(r#"{ "task": "t1" }"#).unwrap(),
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        };
+        let mut parsed_request = parse_put_clone(&Body::new(body)).unwrap();
+        assert_eq!(
+            parsed_request.parsing_info().timeout(),
+            Some(std::time::Duration::from_millis(5000))
+        );
+        match vmm_action_from_request(parsed_request) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        // The memory backend is required, and checked as for a snapshot load.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar"
+              }"#;
+        assert!(parse_put_clone(&Body::new(body)).is_err());
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "File",
+                    "write_protect": true
+                }
+              }"#;
+        assert_eq!(
+            parse_put_clone(&Body::new(body)).err().unwrap().to_string(),
+            Error::SerdeJson(serde_json::Error::custom(WRITE_PROTECT_WITHOUT_UFFD)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_uffd_handler() {
+        use std::path::PathBuf;
+
+        let body = r#"{
+                "backend_path": "foo"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(
+                parse_put_snapshot(&Body::new(body), Some("uffd-handler")).unwrap()
+            ),
+            VmmAction::HandoverUffdHandler(UffdHandlerConfig {
+                backend_path: PathBuf::from("foo"),
+            })
+        );
+
+        // Unknown fields are rejected.
+        let body = r#"{
+                "backend_path": "foo",
+                "backend_type": "Uffd"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some("uffd-handler")).is_err());
+
+        // The path to the new page fault handler is mandatory.
+        assert!(parse_put_snapshot(&Body::new("{}"), Some("uffd-handler")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_commit() {
+        use vmm::vmm_config::snapshot::DriveOverride;
+
+        let body = r#"{
+                "drive_overrides": [
+                    {"drive_id": "scratch", "path_on_host": "scratch.ext4"}
+                ],
+                "vsock_uds_path": "v.sock",
+                "restore_info": {"task_id": "4c1f"}
+              }"#;
+        let expected_params = CommitSnapshotParams {
+            drive_overrides: vec![DriveOverride {
+                drive_id: "scratch".to_string(),
+                path_on_host: "scratch.ext4".to_string(),
+            }],
+            vsock_uds_path: Some("v.sock".to_string()),
+            mmds: None,
+            restore_info: Some(serde_json::from_str(r#"{"task_id": "4c1f"}"#).unwrap()),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("commit")).unwrap()),
+            VmmAction::CommitSnapshot(expected_params)
+        );
+
+        // All the parameters are optional.
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new("{}"), Some("commit")).unwrap()),
+            VmmAction::CommitSnapshot(CommitSnapshotParams::default())
+        );
+
+        // Network interfaces cannot be moved to another tap once restored.
+        let body = r#"{
+                "network_overrides": [{"iface_id": "eth0", "host_dev_name": "tap1"}]
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some("commit")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_suspend() {
+        use std::path::PathBuf;
+
+        use vmm::vmm_config::snapshot::SnapshotType;
+
+        let body = r#"{
+                "snapshot_type": "Diff",
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "marker_path": "baz"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("suspend")).unwrap()),
+            VmmAction::SuspendToDisk(SuspendToDiskParams {
+                snapshot_type: SnapshotType::Diff,
+                snapshot_path: PathBuf::from("foo"),
+                mem_file_path: PathBuf::from("bar"),
+                marker_path: PathBuf::from("baz"),
+            })
+        );
+
+        // The snapshot is full by default.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "marker_path": "baz"
+              }"#;
+        assert!(matches!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("suspend")).unwrap()),
+            VmmAction::SuspendToDisk(SuspendToDiskParams {
+                snapshot_type: SnapshotType::Full,
+                ..
+            })
+        ));
+
+        // The marker path is mandatory.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some("suspend")).is_err());
+    }
+
     #[test]
     fn test_parse_patch_vm_state() {
         let mut body = r#"{
@@ -365,6 +1236,14 @@ mod tests {
             .unwrap()
             .eq(&ParsedRequest::new_sync(VmmAction::Pause)));
 
+        body = r#"{
+                "state": "PausedLite"
+              }"#;
+
+        assert!(parse_patch_vm_state(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::PauseLite)));
+
         body = r#"{
                 "state": "Resumed"
               }"#;
@@ -0,0 +1,50 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::stall_detection::StallDetectionConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_stall_detection(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetStallDetection(
+        serde_json::from_slice::<StallDetectionConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_stall_detection_request() {
+        assert!(parse_put_stall_detection(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the timeout.
+        let body = r#"{
+                "capture_registers": true
+              }"#;
+        assert!(parse_put_stall_detection(&Body::new(body)).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "timeout_ms": 5000,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_stall_detection(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "timeout_ms": 5000,
+                "capture_registers": true
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_stall_detection(&Body::new(body)).unwrap()),
+            VmmAction::SetStallDetection(StallDetectionConfig {
+                timeout_ms: 5000,
+                capture_registers: true,
+            })
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::tpm::TpmConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_tpm(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetTpm(
+        serde_json::from_slice::<TpmConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_tpm_request() {
+        assert!(parse_put_tpm(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the socket path.
+        assert!(parse_put_tpm(&Body::new("{}")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "socket_path": "/swtpm.sock",
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_tpm(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "socket_path": "/swtpm.sock"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_tpm(&Body::new(body)).unwrap()),
+            VmmAction::SetTpm(TpmConfig {
+                socket_path: "/swtpm.sock".to_string()
+            })
+        );
+    }
+}
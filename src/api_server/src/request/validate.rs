@@ -0,0 +1,39 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::resources::VmmConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_validate(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::ValidateVmConfig(
+        serde_json::from_slice::<VmmConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_validate_request() {
+        assert!(parse_put_validate(&Body::new("invalid_payload")).is_err());
+
+        // The boot source is mandatory.
+        assert!(parse_put_validate(&Body::new("{}")).is_err());
+
+        let body = r#"{
+                "boot-source": {
+                    "kernel_image_path": "/foo/bar"
+                },
+                "drives": []
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_validate(&Body::new(body)).unwrap()),
+            VmmAction::ValidateVmConfig(serde_json::from_str(body).unwrap())
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::virtio_record::VirtioRecordConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_virtio_record(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetVirtioRecord(
+        serde_json::from_slice::<VirtioRecordConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::virtio_record::QueueRecordConfig;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_virtio_record_request() {
+        assert!(parse_put_virtio_record(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "queues": [],
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_virtio_record(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "queues": [
+                  {
+                    "device_id": "rootfs",
+                    "queue_index": 0,
+                    "path": "rootfs.rec",
+                    "max_size_bytes": 1048576
+                  }
+                ]
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_virtio_record(&Body::new(body)).unwrap()),
+            VmmAction::SetVirtioRecord(VirtioRecordConfig {
+                queues: vec![QueueRecordConfig {
+                    device_id: "rootfs".to_string(),
+                    queue_index: 0,
+                    path: "rootfs.rec".to_string(),
+                    max_size_bytes: Some(1_048_576),
+                }],
+            })
+        );
+    }
+}
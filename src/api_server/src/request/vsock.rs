@@ -2,12 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::vsock::VsockDeviceConfig;
+use vmm::vmm_config::vsock::{VsockDeviceConfig, VsockDeviceUpdateConfig};
 
 use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
 
+pub(crate) fn parse_get_vsock() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetVsockConfig))
+}
+
 pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.vsock_count.inc();
     let vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|err| {
@@ -33,10 +37,29 @@ pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
     Ok(parsed_req)
 }
 
+pub(crate) fn parse_patch_vsock(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.patch_api_requests.vsock_count.inc();
+    let update = serde_json::from_slice::<VsockDeviceUpdateConfig>(body.raw()).map_err(|err| {
+        METRICS.patch_api_requests.vsock_fails.inc();
+        err
+    })?;
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateVsockDevice(
+        update,
+    )))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parsed_request::tests::depr_action_from_req;
+    use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
+
+    #[test]
+    fn test_parse_get_vsock_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_vsock().unwrap()),
+            VmmAction::GetVsockConfig
+        );
+    }
 
     #[test]
     fn test_parse_put_vsock_request() {
@@ -46,6 +69,48 @@ mod tests {
               }"#;
         assert!(parse_put_vsock(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "clock_port": 123
+              }"#;
+        let expected_config = VsockDeviceConfig {
+            vsock_id: None,
+            guest_cid: Some(42),
+            uds_path: String::from("vsock.sock"),
+            clock_port: Some(123),
+            cid_lock_dir: None,
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_vsock(&Body::new(body)).unwrap()),
+            VmmAction::SetVsockDevice(expected_config)
+        );
+
+        let body = r#"{
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "listen_backlog": 16,
+                "max_connections": 64,
+                "idle_timeout_s": 300
+              }"#;
+        let expected_config = VsockDeviceConfig {
+            vsock_id: None,
+            guest_cid: Some(42),
+            uds_path: String::from("vsock.sock"),
+            clock_port: None,
+            cid_lock_dir: None,
+            listen_backlog: Some(16),
+            max_connections: Some(64),
+            idle_timeout_s: Some(300),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_vsock(&Body::new(body)).unwrap()),
+            VmmAction::SetVsockDevice(expected_config)
+        );
+
         let body = r#"{
                 "guest_cid": 42,
                 "invalid_field": false
@@ -53,6 +118,27 @@ mod tests {
         assert!(parse_put_vsock(&Body::new(body)).is_err());
     }
 
+    #[test]
+    fn test_parse_patch_vsock_request() {
+        let body = r#"{
+                "uds_path": "vsock.sock"
+              }"#;
+        let expected_config = VsockDeviceUpdateConfig {
+            uds_path: String::from("vsock.sock"),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_patch_vsock(&Body::new(body)).unwrap()),
+            VmmAction::UpdateVsockDevice(expected_config)
+        );
+
+        let body = r#"{
+                "uds_path": "vsock.sock",
+                "guest_cid": 42
+              }"#;
+        assert!(parse_patch_vsock(&Body::new(body)).is_err());
+        assert!(METRICS.patch_api_requests.vsock_fails.count() > 0);
+    }
+
     #[test]
     fn test_depr_vsock_id() {
         let body = r#"{
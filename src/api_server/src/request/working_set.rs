@@ -0,0 +1,45 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::vmm_config::working_set::WorkingSetConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_working_set(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetWorkingSet(
+        serde_json::from_slice::<WorkingSetConfig>(body.raw())?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_working_set_request() {
+        assert!(parse_put_working_set(&Body::new("invalid_payload")).is_err());
+
+        // PUT without the interval.
+        assert!(parse_put_working_set(&Body::new("{}")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "sample_interval_s": 30,
+                "foo": "bar"
+              }"#;
+        assert!(parse_put_working_set(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "sample_interval_s": 30
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_working_set(&Body::new(body)).unwrap()),
+            VmmAction::SetWorkingSet(WorkingSetConfig {
+                sample_interval_s: 30
+            })
+        );
+    }
+}
@@ -6,8 +6,11 @@
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 use std::{mem, ptr};
 
 use serde::Deserialize;
@@ -31,6 +34,38 @@ pub struct GuestRegionUffdMapping {
     pub size: usize,
     /// Offset in the backend file/buffer where the region contents are.
     pub offset: u64,
+    /// Index in the dirty page bitmap of the bit tracking the first page of the region.
+    /// Only set when the guest memory is write-protected.
+    #[serde(default)]
+    pub dirty_bitmap_offset: Option<u64>,
+    /// Bitmap of the pages of the region which only held zeros when the snapshot was created,
+    /// one bit per page.
+    #[serde(default)]
+    pub zero_pages: Vec<u64>,
+}
+
+// This is the same with the one used in src/vmm.
+/// This describes a range of guest memory that was held by the balloon device when the
+/// snapshot was taken. Faults in this range must be served with zeroed pages.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GuestRegionUffdRemovedRange {
+    /// Base host virtual address of the removed range.
+    pub base_host_virt_addr: u64,
+    /// Range size.
+    pub size: u64,
+}
+
+// `Uffd::copy` cannot populate pages write-protected, so the ioctl is issued directly.
+const UFFDIO_COPY: u64 = 0xc028_aa03;
+const UFFDIO_COPY_MODE_WP: u64 = 1 << 1;
+
+#[repr(C)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
 }
 
 #[derive(Debug)]
@@ -44,6 +79,12 @@ pub struct UffdPfHandler {
     mem_regions: Vec<MemRegion>,
     backing_buffer: *const u8,
     pub uffd: Uffd,
+    // Bitmap shared with Firecracker, in which the pages written by the guest are marked
+    // when the guest memory is write-protected.
+    dirty_bitmap: Option<*const AtomicU64>,
+    // Kept open for as long as the handler lives. Firecracker receives the page fault
+    // reports over this connection, and can be configured to react when the handler exits.
+    stream: UnixStream,
     // Not currently used but included to demonstrate how a page fault handler can
     // fetch Firecracker's PID in order to make it aware of any crashes/exits.
     _firecracker_pid: u32,
@@ -55,38 +96,78 @@ pub enum MemPageState {
     FromFile,
     Removed,
     Anonymous,
+    Zero,
 }
 
 impl UffdPfHandler {
-    pub fn from_unix_stream(stream: UnixStream, data: *const u8, size: usize) -> Self {
+    pub fn from_unix_stream(mut stream: UnixStream, data: *const u8, size: usize) -> Self {
         let mut message_buf = vec![0u8; 1024];
-        let (bytes_read, file) = stream
-            .recv_with_fd(&mut message_buf[..])
-            .expect("Cannot recv_with_fd");
+        let mut iovecs = [libc::iovec {
+            iov_base: message_buf.as_mut_ptr().cast(),
+            iov_len: message_buf.len(),
+        }];
+        // Firecracker sends the dirty page bitmap along with the uffd when the guest memory
+        // is write-protected.
+        let mut fds = [-1; 2];
+        let (bytes_read, fds_count) = unsafe {
+            stream
+                .recv_with_fds(&mut iovecs, &mut fds)
+                .expect("Cannot recv_with_fds")
+        };
         message_buf.resize(bytes_read, 0);
-
-        let body = String::from_utf8(message_buf).unwrap();
-        let file = file.expect("Uffd not passed through UDS!");
-
-        let mappings = serde_json::from_str::<Vec<GuestRegionUffdMapping>>(&body)
+        // Firecracker closes or shuts down its end of the socket once it sent everything,
+        // so read until the end
+        // to also get the ranges held by the balloon, if any.
+        stream
+            .read_to_end(&mut message_buf)
+            .expect("Cannot read from UDS");
+
+        assert!(fds_count > 0, "Uffd not passed through UDS!");
+
+        // The memory mappings are followed by the removed ranges when the balloon was
+        // inflated at snapshot time.
+        let mut messages =
+            serde_json::Deserializer::from_slice(&message_buf).into_iter::<serde_json::Value>();
+        let mappings = messages
+            .next()
+            .and_then(|message| message.ok())
+            .and_then(|message| serde_json::from_value::<Vec<GuestRegionUffdMapping>>(message).ok())
             .expect("Cannot deserialize memory mappings.");
+        let removed_ranges = match messages.next() {
+            Some(message) => serde_json::from_value::<Vec<GuestRegionUffdRemovedRange>>(
+                message.expect("Cannot read removed ranges."),
+            )
+            .expect("Cannot deserialize removed ranges."),
+            None => Vec::new(),
+        };
         let memsize: usize = mappings.iter().map(|r| r.size).sum();
 
         // Make sure memory size matches backing data size.
         assert_eq!(memsize, size);
 
-        let uffd = unsafe { Uffd::from_raw_fd(file.into_raw_fd()) };
+        let uffd = unsafe { Uffd::from_raw_fd(fds[0]) };
+        let dirty_bitmap = (fds_count > 1).then(|| map_dirty_bitmap(fds[1]));
 
-        let creds: libc::ucred = get_peer_process_credentials(stream);
+        let creds: libc::ucred = get_peer_process_credentials(&stream);
 
         let mem_regions = create_mem_regions(&mappings);
 
-        Self {
+        let mut handler = Self {
             mem_regions,
             backing_buffer: data,
             uffd,
+            dirty_bitmap,
+            stream,
             _firecracker_pid: creds.pid as u32,
+        };
+        for range in removed_ranges {
+            handler.update_mem_state_mappings(
+                range.base_host_virt_addr,
+                range.base_host_virt_addr + range.size,
+                &MemPageState::Removed,
+            );
         }
+        handler
     }
 
     pub fn update_mem_state_mappings(&mut self, start: u64, end: u64, state: &MemPageState) {
@@ -99,26 +180,97 @@ impl UffdPfHandler {
         }
     }
 
-    fn populate_from_file(&self, region: &MemRegion) -> (u64, u64) {
-        let src = self.backing_buffer as u64 + region.mapping.offset;
-        let start_addr = region.mapping.base_host_virt_addr;
-        let len = region.mapping.size;
-        // Populate whole region from backing mem-file.
-        // This offers an example of how memory can be loaded in RAM,
-        // however this can be adjusted to accommodate use case needs.
-        let ret = unsafe {
-            self.uffd
-                .copy(src as *const _, start_addr as *mut _, len, true)
-                .expect("Uffd copy failed")
+    fn mark_dirty(&self, addr: u64) {
+        let Some(dirty_bitmap) = self.dirty_bitmap else {
+            return;
         };
+        let page_size = get_page_size().unwrap() as u64;
 
-        // Make sure the UFFD copied some bytes.
-        assert!(ret > 0);
+        for region in self.mem_regions.iter() {
+            let region_start = region.mapping.base_host_virt_addr;
+            let region_end = region_start + region.mapping.size as u64;
+            if let (true, Some(offset)) = (
+                (region_start..region_end).contains(&addr),
+                region.mapping.dirty_bitmap_offset,
+            ) {
+                let bit = offset + (addr - region_start) / page_size;
+                let word = unsafe { &*dirty_bitmap.add((bit / 64) as usize) };
+                word.fetch_or(1 << (bit % 64), Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    fn populate_from_file(&self, region: &MemRegion) -> Vec<(u64, u64)> {
+        let page_size = get_page_size().unwrap() as u64;
+        let region_start = region.mapping.base_host_virt_addr;
+        let region_end = region_start + region.mapping.size as u64;
+        let mut populated = Vec::new();
+        // Pages of write-protected memory are populated write-protected, so that the guest
+        // writes to them are reported.
+        let mode = match region.mapping.dirty_bitmap_offset {
+            Some(_) => UFFDIO_COPY_MODE_WP,
+            None => 0,
+        };
+
+        // Populate whole region from backing mem-file, except for the pages removed by the
+        // balloon or already served. This offers an example of how memory can be loaded
+        // in RAM, however this can be adjusted to accommodate use case needs.
+        let mut addr = region_start;
+        while addr < region_end {
+            if !matches!(
+                region.page_states.get(&addr),
+                Some(MemPageState::Uninitialized)
+            ) {
+                addr += page_size;
+                continue;
+            }
+
+            let start_addr = addr;
+            while addr < region_end
+                && matches!(
+                    region.page_states.get(&addr),
+                    Some(MemPageState::Uninitialized)
+                )
+            {
+                addr += page_size;
+            }
+
+            let mut dst = start_addr;
+            while dst < addr {
+                let src = self.backing_buffer as u64 + region.mapping.offset + dst - region_start;
+                let mut copy = UffdioCopy {
+                    dst,
+                    src,
+                    len: addr - dst,
+                    mode,
+                    copy: 0,
+                };
+                unsafe { libc::ioctl(self.uffd.as_raw_fd(), UFFDIO_COPY as _, &mut copy) };
+                match copy.copy {
+                    // Make sure the UFFD copied some bytes.
+                    ret if ret > 0 => dst += ret as u64,
+                    // The page was populated by the handler we took over from.
+                    ret if ret == -(libc::EEXIST as i64) => dst += page_size,
+                    ret => panic!("Uffd copy failed: {}", ret),
+                }
+            }
 
-        return (start_addr, start_addr + len as u64);
+            populated.push((start_addr, addr));
+        }
+
+        populated
     }
 
     fn zero_out(&mut self, addr: u64) -> (u64, u64) {
+        let range = self.zero_page(addr);
+        // The page no longer matches the memory file.
+        self.mark_dirty(addr);
+
+        range
+    }
+
+    fn zero_page(&self, addr: u64) -> (u64, u64) {
         let page_size = get_page_size().unwrap();
 
         let ret = unsafe {
@@ -132,7 +284,19 @@ impl UffdPfHandler {
         return (addr, addr + page_size as u64);
     }
 
+    // Reports a served page fault to Firecracker, which records it in its metrics.
+    fn report_fault(&mut self, fault_start: Instant, bytes: u64) {
+        let report = serde_json::json!({
+            "latency_us": fault_start.elapsed().as_micros() as u64,
+            "bytes": bytes,
+        });
+        // Firecracker closes the connection once another handler took over, so the reports
+        // are best effort.
+        let _ = self.stream.write_all(format!("{}\n", report).as_bytes());
+    }
+
     pub fn serve_pf(&mut self, addr: *mut u8) {
+        let fault_start = Instant::now();
         let page_size = get_page_size().unwrap();
 
         // Find the start of the page that the current faulting address belongs to.
@@ -150,14 +314,28 @@ impl UffdPfHandler {
                 // 4. Anonymous - page was zeroed out -> this implies that more than one page fault
                 //    event was received. This can be a consequence of guest reclaiming back its
                 //    memory from the host (through balloon device)
+                // 5. Zero - the page only held zeros when the snapshot was created, so it is
+                //    zeroed out without reading the memory file
                 Some(MemPageState::Uninitialized) | Some(MemPageState::FromFile) => {
-                    let (start, end) = self.populate_from_file(region);
-                    self.update_mem_state_mappings(start, end, &MemPageState::FromFile);
+                    let mut bytes = 0;
+                    for (start, end) in self.populate_from_file(region) {
+                        bytes += end - start;
+                        self.update_mem_state_mappings(start, end, &MemPageState::FromFile);
+                    }
+                    self.report_fault(fault_start, bytes);
                     return;
                 }
                 Some(MemPageState::Removed) | Some(MemPageState::Anonymous) => {
                     let (start, end) = self.zero_out(fault_page_addr);
                     self.update_mem_state_mappings(start, end, &MemPageState::Anonymous);
+                    self.report_fault(fault_start, end - start);
+                    return;
+                }
+                Some(MemPageState::Zero) => {
+                    // The page still matches the memory file, so it is not dirty.
+                    let (start, end) = self.zero_page(fault_page_addr);
+                    self.update_mem_state_mappings(start, end, &MemPageState::FromFile);
+                    self.report_fault(fault_start, end - start);
                     return;
                 }
                 None => {
@@ -171,9 +349,43 @@ impl UffdPfHandler {
             addr
         );
     }
+
+    pub fn serve_wp_fault(&mut self, addr: *mut u8) {
+        let page_size = get_page_size().unwrap();
+
+        // Find the start of the page that the current faulting address belongs to.
+        let dst = (addr as usize & !(page_size as usize - 1)) as *mut libc::c_void;
+
+        // Mark the page as dirty before letting the guest write to it.
+        self.mark_dirty(dst as u64);
+        self.uffd
+            .remove_write_protection(dst, page_size, true)
+            .expect("Uffd remove write protection failed");
+    }
+}
+
+fn map_dirty_bitmap(fd: i32) -> *const AtomicU64 {
+    let file = unsafe { File::from_raw_fd(fd) };
+    let size = file.metadata().unwrap().len() as usize;
+
+    let ret = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if ret == libc::MAP_FAILED {
+        panic!("mmap failed");
+    }
+
+    ret as *const AtomicU64
 }
 
-fn get_peer_process_credentials(stream: UnixStream) -> libc::ucred {
+fn get_peer_process_credentials(stream: &UnixStream) -> libc::ucred {
     let mut creds: libc::ucred = libc::ucred {
         pid: 0,
         gid: 0,
@@ -207,9 +419,20 @@ fn create_mem_regions(mappings: &Vec<GuestRegionUffdMapping>) -> Vec<MemRegion>
         let end_addr = r.base_host_virt_addr + r.size as u64;
         let mut page_states = HashMap::new();
 
+        let mut page = 0;
         while addr < end_addr {
-            page_states.insert(addr, MemPageState::Uninitialized);
+            let is_zero = r
+                .zero_pages
+                .get(page / 64)
+                .map_or(false, |word| (word >> (page % 64)) & 1 != 0);
+            let state = if is_zero {
+                MemPageState::Zero
+            } else {
+                MemPageState::Uninitialized
+            };
+            page_states.insert(addr, state);
             addr += page_size as u64;
+            page += 1;
         }
         mem_regions.push(MemRegion {
             mapping,
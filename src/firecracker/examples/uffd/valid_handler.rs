@@ -44,9 +44,15 @@ fn main() {
             .expect("Failed to read uffd_msg")
             .expect("uffd_msg not ready");
 
-        // We expect to receive either a Page Fault or Removed
-        // event (if the balloon device is enabled).
+        // We expect to receive either a Page Fault (missing page or write to a
+        // write-protected page) or Removed event (if the balloon device is enabled).
         match event {
+            // Write to a write-protected page, when tracking the dirty pages.
+            userfaultfd::Event::Pagefault {
+                kind: userfaultfd::FaultKind::WriteProtected,
+                addr,
+                ..
+            } => uffd_handler.serve_wp_fault(addr as *mut u8),
             userfaultfd::Event::Pagefault { addr, .. } => uffd_handler.serve_pf(addr as *mut u8),
             userfaultfd::Event::Remove { start, end } => uffd_handler.update_mem_state_mappings(
                 start as *mut u8 as u64,
@@ -5,11 +5,12 @@ use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-use api_server::{ApiServer, HttpServer, ServerError};
+use api_server::{ApiServer, ApiToken, HttpServer, ServerError};
 use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
 use logger::{error, warn, ProcessTimeReporter};
 use seccompiler::BpfThreadMap;
@@ -17,11 +18,19 @@ use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
 use vmm::resources::VmResources;
 use vmm::rpc_interface::{
-    ApiRequest, ApiResponse, PrebootApiController, RuntimeApiController, VmmAction,
+    ApiRequest, ApiResponse, ConcurrentApiController, PrebootApiController, RuntimeApiController,
+    VmmAction,
 };
+use vmm::seccomp_filters::SeccompFilterInfo;
+use vmm::signal_handler::register_emergency_snapshot_handler;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::{EventManager, FcExitCode, Vmm};
 
+use crate::api_vsock::ApiVsockRelay;
+
+// How often the emergency snapshot is checked for while the microVM is paused.
+const EMERGENCY_SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, thiserror::Error)]
 pub enum ApiServerError {
     #[error("MicroVMStopped without an error: {0:?}")]
@@ -42,6 +51,9 @@ struct ApiServerAdapter {
     from_api: Receiver<ApiRequest>,
     to_api: Sender<ApiResponse>,
     controller: RuntimeApiController,
+    vmm: Arc<Mutex<Vmm>>,
+    // Notified when the emergency snapshot is requested through its signal.
+    emergency_snapshot_fd: Option<EventFd>,
 }
 
 impl ApiServerAdapter {
@@ -55,11 +67,21 @@ impl ApiServerAdapter {
         vmm: Arc<Mutex<Vmm>>,
         event_manager: &mut EventManager,
     ) -> FcExitCode {
+        let emergency_snapshot_fd = vm_resources
+            .emergency_snapshot
+            .as_ref()
+            .and_then(|config| config.signal)
+            .map(|signal| {
+                register_emergency_snapshot_handler(signal.signum())
+                    .expect("Cannot register the emergency snapshot signal handler.")
+            });
         let api_adapter = Arc::new(Mutex::new(Self {
             api_event_fd,
             from_api,
             to_api,
             controller: RuntimeApiController::new(vm_resources, vmm.clone()),
+            vmm: vmm.clone(),
+            emergency_snapshot_fd,
         }));
         event_manager.add_subscriber(api_adapter);
         loop {
@@ -80,6 +102,42 @@ impl ApiServerAdapter {
             .map_err(|_| ())
             .expect("one-shot channel closed");
     }
+
+    // Takes the emergency snapshot requested through its signal, if any. The microVM keeps
+    // running when the snapshot fails.
+    fn handle_emergency_snapshot(&mut self) {
+        let requested = self
+            .emergency_snapshot_fd
+            .as_ref()
+            .map_or(false, |event_fd| event_fd.read().is_ok());
+        if requested {
+            if let Err(err) = self.controller.handle_request(VmmAction::EmergencySnapshot) {
+                error!("Failed to take the emergency snapshot: {}", err);
+            }
+        }
+    }
+
+    // Waits for the next API request while the microVM is paused. Returns `None` if the
+    // emergency snapshot stopped the VMM in the meantime.
+    fn recv_while_paused(&mut self) -> Option<ApiRequest> {
+        if self.emergency_snapshot_fd.is_none() {
+            return Some(self.from_api.recv().expect("Error receiving API request."));
+        }
+        loop {
+            match self.from_api.recv_timeout(EMERGENCY_SNAPSHOT_POLL_INTERVAL) {
+                Ok(req) => return Some(req),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.handle_emergency_snapshot();
+                    if self.vmm.lock().unwrap().shutdown_exit_code().is_some() {
+                        return None;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!("The channel's sending half was disconnected. Cannot receive data.");
+                }
+            }
+        }
+    }
 }
 impl MutEventSubscriber for ApiServerAdapter {
     /// Handle a read event (EPOLLIN).
@@ -98,15 +156,21 @@ impl MutEventSubscriber for ApiServerAdapter {
                     // do blocking `recv`s on the `from_api` receiver in a loop, until we get
                     // unpaused. The device emulation is implicitly paused since we do not
                     // relinquish control to the event manager because we're not returning from
-                    // `process`.
+                    // `process`. Suspending the microVM to disk also ends this mode, since it
+                    // stops the VMM.
                     if request_is_pause {
-                        // This loop only attempts to process API requests, so things like the
-                        // metric flush timerfd handling are frozen as well.
+                        // This loop only attempts to process API requests and the emergency
+                        // snapshot, so things like the metric flush timerfd handling are frozen
+                        // as well.
                         loop {
-                            let req = self.from_api.recv().expect("Error receiving API request.");
+                            let Some(req) = self.recv_while_paused() else {
+                                break;
+                            };
                             let req_is_resume = *req == VmmAction::Resume;
                             self.handle_request(*req);
-                            if req_is_resume {
+                            if req_is_resume
+                                || self.vmm.lock().unwrap().shutdown_exit_code().is_some()
+                            {
                                 break;
                             }
                         }
@@ -119,6 +183,13 @@ impl MutEventSubscriber for ApiServerAdapter {
                     panic!("The channel's sending half was disconnected. Cannot receive data.");
                 }
             };
+        } else if self
+            .emergency_snapshot_fd
+            .as_ref()
+            .map_or(false, |event_fd| source == event_fd.as_raw_fd())
+            && event_set == EventSet::IN
+        {
+            self.handle_emergency_snapshot();
         } else {
             error!("Spurious EventManager event for handler: ApiServerAdapter");
         }
@@ -128,13 +199,20 @@ impl MutEventSubscriber for ApiServerAdapter {
         if let Err(err) = ops.add(Events::new(&self.api_event_fd, EventSet::IN)) {
             error!("Failed to register activate event: {}", err);
         }
+        if let Some(event_fd) = &self.emergency_snapshot_fd {
+            if let Err(err) = ops.add(Events::new(event_fd, EventSet::IN)) {
+                error!("Failed to register the emergency snapshot event: {}", err);
+            }
+        }
     }
 }
 
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn run_with_api(
     seccomp_filters: &mut BpfThreadMap,
+    seccomp_filters_info: Vec<SeccompFilterInfo>,
     config_json: Option<String>,
+    defer_start: bool,
     bind_path: PathBuf,
     instance_info: InstanceInfo,
     process_time_reporter: ProcessTimeReporter,
@@ -142,6 +220,8 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    api_token: Option<ApiToken>,
+    api_vsock_relay: Option<ApiVsockRelay>,
 ) -> Result<(), ApiServerError> {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -158,6 +238,9 @@ pub(crate) fn run_with_api(
     let api_seccomp_filter = seccomp_filters
         .remove("api")
         .expect("Missing seccomp filter for API thread.");
+    let api_vsock_seccomp_filter = seccomp_filters
+        .remove("api_vsock")
+        .expect("Missing seccomp filter for API vsock thread.");
 
     let server = match HttpServer::new(&bind_path) {
         Ok(s) => s,
@@ -170,11 +253,40 @@ pub(crate) fn run_with_api(
         }
     };
 
+    // The API socket is bound, so the vsock connections can be relayed to it. The relay thread is
+    // not joined, since it only forwards the connections and can go down along with the process.
+    if let Some(relay) = api_vsock_relay {
+        thread::Builder::new()
+            .name("fc_api_vsock".to_owned())
+            .spawn(move || {
+                let _landlock_registration = vmm::landlock::register_thread();
+                relay.run(&api_vsock_seccomp_filter)
+            })
+            .expect("API vsock thread spawn failed.");
+    }
+
+    // The clones share the token, so the shutdown request below carries the current one, even if
+    // it was rotated in the meantime.
+    let api_server_token = api_token.clone();
+    // Serves the requests of the API thread which do not wait for the VMM thread, once the
+    // microVM runs.
+    let concurrent_controller = ConcurrentApiController::default();
+    let api_concurrent_controller = concurrent_controller.clone();
+
     // Start the separate API thread.
     let api_thread = thread::Builder::new()
         .name("fc_api".to_owned())
         .spawn(move || {
-            ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd).run(
+            // The API threads are started before the Landlock ruleset is enforced.
+            let _landlock_registration = vmm::landlock::register_thread();
+            ApiServer::new(
+                to_vmm,
+                from_vmm,
+                to_vmm_event_fd,
+                api_server_token,
+                api_concurrent_controller,
+            )
+            .run(
                 server,
                 process_time_reporter,
                 &api_seccomp_filter,
@@ -189,8 +301,38 @@ pub(crate) fn run_with_api(
     let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
 
+    // Watch the resources used by the process, from before the microVM is built.
+    let resource_monitor = Arc::new(Mutex::new(super::resource_monitor::ResourceMonitor::new()));
+    event_manager.add_subscriber(resource_monitor.clone());
+    resource_monitor
+        .lock()
+        .expect("Poisoned lock")
+        .start(super::resource_monitor::MONITOR_PERIOD_MS);
+
     // Configure, build and start the microVM.
     let build_result = match config_json {
+        // Start the microVM configured from the file on the InstanceStart request, so that the
+        // API can change its resources in the meantime.
+        Some(json) if defer_start => {
+            VmResources::from_json(&json, &instance_info, mmds_size_limit, metadata_json)
+                .map_err(|err| {
+                    ApiServerError::BuildFromJson(crate::BuildFromJsonError::ParseFromJson(err))
+                })
+                .and_then(|mut vm_resources| {
+                    vm_resources.boot_timer = boot_timer_enabled;
+                    vm_resources.seccomp_filters = seccomp_filters_info;
+                    PrebootApiController::build_microvm_from_config(
+                        seccomp_filters,
+                        &mut event_manager,
+                        instance_info,
+                        vm_resources,
+                        &from_api,
+                        &to_api,
+                        &api_event_fd,
+                    )
+                    .map_err(ApiServerError::MicroVMStoppedWithError)
+                })
+        }
         Some(json) => super::build_microvm_from_json(
             seccomp_filters,
             &mut event_manager,
@@ -200,9 +342,14 @@ pub(crate) fn run_with_api(
             mmds_size_limit,
             metadata_json,
         )
+        .map(|(mut vm_resources, vmm)| {
+            vm_resources.seccomp_filters = seccomp_filters_info;
+            (vm_resources, vmm)
+        })
         .map_err(ApiServerError::BuildFromJson),
         None => PrebootApiController::build_microvm_from_requests(
             seccomp_filters,
+            seccomp_filters_info,
             &mut event_manager,
             instance_info,
             &from_api,
@@ -220,6 +367,7 @@ pub(crate) fn run_with_api(
             .lock()
             .expect("Poisoned lock")
             .start(super::metrics::WRITE_METRICS_PERIOD_MS);
+        concurrent_controller.attach(vmm.clone());
 
         ApiServerAdapter::run_microvm(
             api_event_fd,
@@ -241,7 +389,10 @@ pub(crate) fn run_with_api(
 
     // We also need to make sure the socket path is ready.
     let mut sock = UnixStream::connect(bind_path).unwrap();
-    sock.write_all(b"PUT /shutdown-internal HTTP/1.1\r\n\r\n")
+    let auth_header = api_token
+        .map(|token| format!("{}\r\n", token.header()))
+        .unwrap_or_default();
+    sock.write_all(format!("PUT /shutdown-internal HTTP/1.1\r\n{}\r\n", auth_header).as_bytes())
         .unwrap();
 
     // This call to thread::join() should block until the API thread has processed the
@@ -0,0 +1,364 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use logger::{error, info, warn};
+use seccompiler::BpfProgramRef;
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::syscall::SyscallReturnCode;
+
+// libc::AF_VSOCK is a c_int, while sockaddr_vm.svm_family is a sa_family_t.
+const AF_VSOCK: libc::sa_family_t = 40;
+// Maximum number of connections relayed at the same time, which is the number of connections
+// the API server accepts.
+const MAX_CONNECTIONS: usize = 10;
+// Size of the data read at once from a socket.
+const BUFFER_SIZE: usize = 4096;
+// Epoll data of the listening socket. The data of the connections is made of their id and of the
+// side of the connection.
+const LISTENER_TOKEN: u64 = u64::MAX;
+
+// Sides of a relayed connection.
+const VSOCK: usize = 0;
+const API: usize = 1;
+
+fn token(id: u64, side: usize) -> u64 {
+    (id << 1) | side as u64
+}
+
+fn set_nonblocking(file: &File) -> io::Result<()> {
+    let mut nonblocking: libc::c_int = 1;
+    // SAFETY: Safe because the descriptor is valid and FIONBIO reads a c_int.
+    SyscallReturnCode(unsafe { libc::ioctl(file.as_raw_fd(), libc::FIONBIO, &mut nonblocking) })
+        .into_empty_result()
+}
+
+// Listens for connections on the vsock `port` of the host, from any CID.
+fn listen(port: u32) -> io::Result<File> {
+    // SAFETY: Safe because the arguments are plain values.
+    let fd = SyscallReturnCode(unsafe {
+        libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
+    })
+    .into_result()?;
+    // SAFETY: Safe because the socket was just created and is exclusively owned by the file.
+    let listener = unsafe { File::from_raw_fd(fd) };
+
+    // SAFETY: Safe because sockaddr_vm is a plain structure, which is valid when zeroed.
+    let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = AF_VSOCK;
+    addr.svm_port = port;
+    addr.svm_cid = libc::VMADDR_CID_ANY;
+    // SAFETY: Safe because `addr` is a valid sockaddr_vm and its size is passed.
+    SyscallReturnCode(unsafe {
+        libc::bind(
+            listener.as_raw_fd(),
+            (&addr as *const libc::sockaddr_vm).cast(),
+            mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    })
+    .into_empty_result()?;
+    // SAFETY: Safe because the socket is valid.
+    SyscallReturnCode(unsafe { libc::listen(listener.as_raw_fd(), libc::SOMAXCONN) })
+        .into_empty_result()?;
+    Ok(listener)
+}
+
+// A vsock connection relayed to the API socket.
+struct Connection {
+    // The vsock socket and the API socket.
+    streams: [File; 2],
+    // Data read from each socket, which the other socket has not accepted yet.
+    pending: [Vec<u8>; 2],
+}
+
+impl Connection {
+    fn new(vsock: File, api: File) -> Self {
+        Connection {
+            streams: [vsock, api],
+            pending: [Vec::new(), Vec::new()],
+        }
+    }
+
+    // Events to watch on the socket `side`. A socket is only read once the data previously read
+    // from it was relayed, so that a peer which does not read its socket cannot make the relay
+    // buffer an unbounded amount of data.
+    fn interest(&self, side: usize) -> EventSet {
+        let mut events = EventSet::empty();
+        if self.pending[side].is_empty() {
+            events |= EventSet::IN;
+        }
+        if !self.pending[1 - side].is_empty() {
+            events |= EventSet::OUT;
+        }
+        events
+    }
+
+    // Relays the data after `events` occurred on the socket `side`. Returns false once either
+    // socket is closed, after relaying the data received from it as far as possible.
+    fn handle(&mut self, side: usize, events: EventSet) -> io::Result<bool> {
+        if events.contains(EventSet::OUT) {
+            self.flush(1 - side)?;
+        }
+        if events.intersects(EventSet::IN | EventSet::HANG_UP | EventSet::ERROR)
+            && self.pending[side].is_empty()
+        {
+            let mut buf = [0u8; BUFFER_SIZE];
+            match self.streams[side].read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(count) => {
+                    self.pending[side].extend_from_slice(&buf[..count]);
+                    self.flush(side)?;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(!events.intersects(EventSet::HANG_UP | EventSet::ERROR))
+    }
+
+    // Writes the data read from the socket `side` to the other socket, as far as it accepts it.
+    fn flush(&mut self, side: usize) -> io::Result<()> {
+        let pending = &mut self.pending[side];
+        let mut stream = &self.streams[1 - side];
+        while !pending.is_empty() {
+            match stream.write(pending) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(count) => {
+                    pending.drain(..count);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Relays the connections accepted on a vsock port of the host to the API socket, so that the API
+/// can be reached from other mount namespaces or from a management VM.
+pub(crate) struct ApiVsockRelay {
+    listener: File,
+    // CID of the only peer allowed to connect, if any.
+    peer_cid: Option<u32>,
+    api_sock_path: PathBuf,
+    epoll: Epoll,
+    connections: HashMap<u64, Connection>,
+    next_id: u64,
+}
+
+impl ApiVsockRelay {
+    /// Listens on the vsock `port` for connections to relay to the API socket at `api_sock_path`.
+    /// If `peer_cid` is set, the connections from other CIDs are rejected.
+    pub(crate) fn new(
+        port: u32,
+        peer_cid: Option<u32>,
+        api_sock_path: PathBuf,
+    ) -> io::Result<Self> {
+        let listener = listen(port)?;
+        let epoll = Epoll::new()?;
+        epoll.ctl(
+            ControlOperation::Add,
+            listener.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, LISTENER_TOKEN),
+        )?;
+        Ok(ApiVsockRelay {
+            listener,
+            peer_cid,
+            api_sock_path,
+            epoll,
+            connections: HashMap::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Relays the connections until an unrecoverable error occurs. The relay has its own seccomp
+    /// filter, since it is the only API thread connecting to the API socket.
+    pub(crate) fn run(mut self, seccomp_filter: BpfProgramRef) {
+        if let Err(err) = vmm::seccomp_filters::install_filter("api_vsock", seccomp_filter) {
+            panic!(
+                "Failed to set the requested seccomp filters on the API vsock thread: {}",
+                err
+            );
+        }
+
+        let mut events = vec![EpollEvent::default(); 2 * MAX_CONNECTIONS + 1];
+        loop {
+            let count = match self.epoll.wait(-1, &mut events) {
+                Ok(count) => count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    error!("API vsock relay stopped: {}", err);
+                    return;
+                }
+            };
+            for event in &events[..count] {
+                if event.data() == LISTENER_TOKEN {
+                    if let Err(err) = self.accept() {
+                        warn!("Cannot relay an API vsock connection: {}", err);
+                    }
+                } else {
+                    self.handle_event(event.data(), event.event_set());
+                }
+            }
+        }
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        // SAFETY: Safe because sockaddr_vm is a plain structure, which accept4() overwrites.
+        let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        let mut addr_len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        // SAFETY: Safe because `addr` is a valid sockaddr_vm and `addr_len` holds its size.
+        let fd = SyscallReturnCode(unsafe {
+            libc::accept4(
+                self.listener.as_raw_fd(),
+                (&mut addr as *mut libc::sockaddr_vm).cast(),
+                &mut addr_len,
+                libc::SOCK_CLOEXEC,
+            )
+        })
+        .into_result()?;
+        // SAFETY: Safe because the socket was just accepted and is exclusively owned by the file.
+        let vsock = unsafe { File::from_raw_fd(fd) };
+
+        if self.peer_cid.map_or(false, |cid| cid != addr.svm_cid) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("rejected a connection from CID {}", addr.svm_cid),
+            ));
+        }
+        if self.connections.len() >= MAX_CONNECTIONS {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many connections"));
+        }
+
+        let api = UnixStream::connect(&self.api_sock_path)?;
+        api.set_nonblocking(true)?;
+        // SAFETY: Safe because the descriptor is released by the stream, so the file owns it.
+        let api = unsafe { File::from_raw_fd(api.into_raw_fd()) };
+        set_nonblocking(&vsock)?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let connection = Connection::new(vsock, api);
+        for side in [VSOCK, API] {
+            self.epoll.ctl(
+                ControlOperation::Add,
+                connection.streams[side].as_raw_fd(),
+                EpollEvent::new(connection.interest(side), token(id, side)),
+            )?;
+        }
+        self.connections.insert(id, connection);
+        info!(
+            "Relaying an API connection from vsock CID {}.",
+            addr.svm_cid
+        );
+        Ok(())
+    }
+
+    fn handle_event(&mut self, data: u64, events: EventSet) {
+        let (id, side) = (data >> 1, (data & 1) as usize);
+        // The connection may have been closed by a previous event of the same batch.
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let result = connection.handle(side, events).and_then(|open| {
+            if open {
+                for side in [VSOCK, API] {
+                    self.epoll.ctl(
+                        ControlOperation::Modify,
+                        connection.streams[side].as_raw_fd(),
+                        EpollEvent::new(connection.interest(side), token(id, side)),
+                    )?;
+                }
+            }
+            Ok(open)
+        });
+        match result {
+            Ok(true) => (),
+            Ok(false) => {
+                // Closing the sockets removes them from the epoll.
+                self.connections.remove(&id);
+            }
+            Err(err) => {
+                warn!("API vsock connection closed: {}", err);
+                self.connections.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a connection between two socket pairs, along with the peer ends of the sockets.
+    fn connection() -> (Connection, UnixStream, UnixStream) {
+        let (vsock_peer, vsock) = UnixStream::pair().unwrap();
+        let (api_peer, api) = UnixStream::pair().unwrap();
+        vsock.set_nonblocking(true).unwrap();
+        api.set_nonblocking(true).unwrap();
+        // SAFETY: Safe because the descriptors are released by the streams.
+        let connection = unsafe {
+            Connection::new(
+                File::from_raw_fd(vsock.into_raw_fd()),
+                File::from_raw_fd(api.into_raw_fd()),
+            )
+        };
+        (connection, vsock_peer, api_peer)
+    }
+
+    #[test]
+    fn test_relay() {
+        let (mut connection, mut vsock_peer, mut api_peer) = connection();
+        assert_eq!(connection.interest(VSOCK), EventSet::IN);
+        assert_eq!(connection.interest(API), EventSet::IN);
+
+        vsock_peer.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.handle(VSOCK, EventSet::IN).unwrap());
+        let mut buf = [0u8; 18];
+        api_peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"GET / HTTP/1.1\r\n\r\n");
+
+        api_peer.write_all(b"HTTP/1.1 200\r\n").unwrap();
+        assert!(connection.handle(API, EventSet::IN).unwrap());
+        let mut buf = [0u8; 14];
+        vsock_peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"HTTP/1.1 200\r\n");
+
+        // Spurious events are ignored.
+        assert!(connection.handle(API, EventSet::IN).unwrap());
+
+        drop(vsock_peer);
+        assert!(!connection.handle(VSOCK, EventSet::IN).unwrap());
+    }
+
+    #[test]
+    fn test_relay_backpressure() {
+        let (mut connection, vsock_peer, mut api_peer) = connection();
+        vsock_peer.set_nonblocking(true).unwrap();
+
+        // Fill the vsock socket, until the data read from the API socket stays pending.
+        let data = [0u8; BUFFER_SIZE];
+        while connection.pending[API].is_empty() {
+            api_peer.write_all(&data).unwrap();
+            assert!(connection.handle(API, EventSet::IN).unwrap());
+        }
+        assert_eq!(connection.interest(API), EventSet::empty());
+        assert_eq!(connection.interest(VSOCK), EventSet::IN | EventSet::OUT);
+
+        // Drain the vsock socket, so that the pending data can be relayed.
+        let mut buf = [0u8; BUFFER_SIZE];
+        while (&vsock_peer).read(&mut buf).is_ok() {}
+        assert!(connection.handle(VSOCK, EventSet::OUT).unwrap());
+        assert!(connection.pending[API].is_empty());
+        assert_eq!(connection.interest(API), EventSet::IN);
+    }
+}
@@ -2,16 +2,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod api_server_adapter;
+mod api_vsock;
 mod metrics;
+mod resource_monitor;
 mod seccomp;
 
+use std::collections::BTreeMap;
 use std::fs::{self, File};
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::process::ExitCode;
 use std::sync::{Arc, Mutex};
 use std::{io, panic};
 
+use api_server::{ApiToken, ApiTokenError};
 use api_server_adapter::ApiServerError;
+use api_vsock::ApiVsockRelay;
 use event_manager::SubscriberOps;
 use logger::{error, info, ProcessTimeReporter, StoreMetric, LOGGER, METRICS};
 use seccomp::FilterError;
@@ -21,12 +27,15 @@ use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
 use vmm::builder::StartMicrovmError;
-use vmm::resources::VmResources;
+use vmm::resources::{ValidationErrors, VmResources, VmmConfig};
 use vmm::signal_handler::register_signal_handlers;
 use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
-use vmm::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError, LoggerLevel};
+use vmm::vmm_config::logger::{
+    init_logger, LoggerConfig, LoggerConfigError, LoggerFormat, LoggerLevel,
+};
 use vmm::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
+use vmm::vmm_config::open_file;
 use vmm::{EventManager, FcExitCode, HTTP_MAX_PAYLOAD_SIZE};
 
 use crate::seccomp::SeccompConfig;
@@ -47,8 +56,12 @@ enum MainError {
     ParseArguments(#[from] utils::arg_parser::Error),
     #[error("When printing Snapshot Data format: {0}")]
     PrintSnapshotDataFormat(#[from] SnapshotVersionError),
+    #[error("When validating the configuration file: {0}")]
+    ValidateConfig(#[from] ValidateConfigError),
     #[error("Invalid value for logger level: {0}.Possible values: [Error, Warning, Info, Debug]")]
     InvalidLogLevel(LoggerConfigError),
+    #[error("Invalid value for logger format: {0}. Possible values: [Text, Json]")]
+    InvalidLogFormat(LoggerConfigError),
     #[error("Could not initialize logger: {0}")]
     LoggerInitialization(LoggerConfigError),
     #[error("Could not initialize metrics: {0:?}")]
@@ -57,6 +70,12 @@ enum MainError {
     SeccompFilter(FilterError),
     #[error("Failed to resize fd table: {0}")]
     ResizeFdtable(ResizeFdTableError),
+    #[error("Invalid inherited file descriptor: {0}")]
+    InheritedFd(io::Error),
+    #[error("Could not load the API token: {0}")]
+    ApiToken(ApiTokenError),
+    #[error("Could not listen on the API vsock port: {0}")]
+    ApiVsock(io::Error),
     #[error("RunWithApiError error: {0}")]
     RunWithApi(ApiServerError),
     #[error("RunWithoutApiError error: {0}")]
@@ -73,29 +92,31 @@ enum ResizeFdTableError {
     Close(io::Error),
 }
 
-impl From<MainError> for ExitCode {
+impl From<MainError> for FcExitCode {
     fn from(value: MainError) -> Self {
-        let exit_code = match value {
+        match value {
             MainError::ParseArguments(_) => FcExitCode::ArgParsing,
             MainError::InvalidLogLevel(_) => FcExitCode::BadConfiguration,
+            MainError::InvalidLogFormat(_) => FcExitCode::BadConfiguration,
+            MainError::ValidateConfig(_) => FcExitCode::BadConfiguration,
             MainError::RunWithApi(ApiServerError::MicroVMStoppedWithoutError(code)) => code,
             MainError::RunWithApi(ApiServerError::MicroVMStoppedWithError(code)) => code,
             _ => FcExitCode::GenericError,
-        };
-
-        ExitCode::from(exit_code as u8)
+        }
     }
 }
 
 fn main() -> ExitCode {
     let result = main_exec();
-    if let Err(err) = result {
+    let exit_code = if let Err(err) = result {
         error!("{err}");
         eprintln!("Error: {err:?}");
-        ExitCode::from(err)
+        FcExitCode::from(err)
     } else {
-        ExitCode::SUCCESS
-    }
+        FcExitCode::Ok
+    };
+    vmm::shutdown_hooks::run(exit_code);
+    ExitCode::from(exit_code as u8)
 }
 
 fn main_exec() -> Result<(), MainError> {
@@ -145,6 +166,8 @@ fn main_exec() -> Result<(), MainError> {
         if let Err(err) = METRICS.write() {
             error!("Failed to write metrics while panicking: {}", err);
         }
+
+        vmm::shutdown_hooks::run(FcExitCode::UnexpectedError);
     }));
 
     let http_max_payload_size_str = HTTP_MAX_PAYLOAD_SIZE.to_string();
@@ -171,10 +194,67 @@ fn main_exec() -> Result<(), MainError> {
                      filter. For advanced users.",
                 ),
         )
+        .arg(
+            Argument::new("seccomp-filter-vmm")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which allows specifying the path to a custom seccomp \
+                     filter for the VMM thread, overriding the one of the other filters. For \
+                     advanced users.",
+                ),
+        )
+        .arg(
+            Argument::new("seccomp-filter-api")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which allows specifying the path to a custom seccomp \
+                     filter for the API thread, overriding the one of the other filters. For \
+                     advanced users.",
+                ),
+        )
+        .arg(
+            Argument::new("seccomp-filter-vcpu")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which allows specifying the path to a custom seccomp \
+                     filter for the vCPU threads, overriding the one of the other filters. For \
+                     advanced users.",
+                ),
+        )
+        .arg(
+            Argument::new("seccomp-filter-shared-dirs")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which allows specifying the path to a custom seccomp \
+                     filter for the thread serving the shared directories, overriding the one of \
+                     the other filters. For advanced users.",
+                ),
+        )
+        .arg(
+            Argument::new("seccomp-filter-api-vsock")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which allows specifying the path to a custom seccomp \
+                     filter for the thread relaying the API vsock connections, overriding the one \
+                     of the other filters. For advanced users.",
+                ),
+        )
         .arg(
             Argument::new("no-seccomp")
                 .takes_value(false)
-                .forbids(vec!["seccomp-filter"])
+                .forbids(vec![
+                    "seccomp-filter",
+                    "seccomp-filter-vmm",
+                    "seccomp-filter-api",
+                    "seccomp-filter-vcpu",
+                    "seccomp-filter-shared-dirs",
+                    "seccomp-filter-api-vsock",
+                ])
                 .help(
                     "Optional parameter which allows starting and using a microVM without seccomp \
                      filtering. Not recommended.",
@@ -193,6 +273,15 @@ fn main_exec() -> Result<(), MainError> {
         .arg(Argument::new("parent-cpu-time-us").takes_value(true).help(
             "Parent process CPU time (wall clock, microseconds). This parameter is optional.",
         ))
+        .arg(Argument::new("cpu-max-fd").takes_value(true).help(
+            "File descriptor of the cpu.max file of the microVM cgroup, inherited from the jailer, \
+             to which the CPU bandwidth of the machine configuration is written.",
+        ))
+        .arg(Argument::new("inherit-fd").allow_multiple(true).help(
+            "File descriptor inherited from the parent process which can be used as a boot, \
+             drive or memory image, referred to as /proc/self/fd/<fd>. This argument can be used \
+             multiple times to declare multiple file descriptors.",
+        ))
         .arg(
             Argument::new("config-file")
                 .takes_value(true)
@@ -212,6 +301,50 @@ fn main_exec() -> Result<(), MainError> {
                      active API socket.",
                 ),
         )
+        .arg(
+            Argument::new("validate-config")
+                .takes_value(false)
+                .requires("config-file")
+                .help(
+                    "Check the microVM configuration from the config file, print the errors \
+                     found and exit, without creating the microVM.",
+                ),
+        )
+        .arg(
+            Argument::new("defer-start")
+                .takes_value(false)
+                .requires("config-file")
+                .forbids(vec!["no-api"])
+                .help(
+                    "Optional parameter which defers starting the microVM configured from the \
+                     config file until an InstanceStart API request, so that its resources can \
+                     be changed through the API in the meantime.",
+                ),
+        )
+        .arg(
+            Argument::new("api-token-file")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Path to a file that contains the token which every API request must carry \
+                     as a bearer token.",
+                ),
+        )
+        .arg(
+            Argument::new("api-vsock-port")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Host vsock port on which the API is exposed, in addition to the API \
+                     socket.",
+                ),
+        )
+        .arg(
+            Argument::new("api-vsock-peer-cid")
+                .takes_value(true)
+                .requires("api-vsock-port")
+                .help("CID of the only peer allowed to connect to the API vsock port."),
+        )
         .arg(
             Argument::new("log-path")
                 .takes_value(true)
@@ -238,6 +371,13 @@ fn main_exec() -> Result<(), MainError> {
                     "Whether or not to include the file path and line number of the log's origin.",
                 ),
         )
+        .arg(
+            Argument::new("log-format")
+                .takes_value(true)
+                .requires("log-path")
+                .default_value("Text")
+                .help("Set the format of the logs: Text, or Json for one JSON object per line."),
+        )
         .arg(
             Argument::new("metrics-path")
                 .takes_value(true)
@@ -288,6 +428,19 @@ fn main_exec() -> Result<(), MainError> {
         return Ok(());
     }
 
+    // The inherited file descriptors are checked before Firecracker opens any file, so that none
+    // of its own descriptors can be mistaken for an inherited one.
+    let inherited_fds = arguments
+        .multiple_values("inherit-fd")
+        .unwrap_or_default()
+        .iter()
+        .map(|fd| {
+            fd.parse::<RawFd>()
+                .expect("'inherit-fd' parameter expected to be of 'i32' type.")
+        })
+        .collect::<Vec<_>>();
+    vmm::vmm_config::add_inherited_fds(&inherited_fds).map_err(MainError::InheritedFd)?;
+
     // Display warnings for any used deprecated parameters.
     // Currently unused since there are no deprecated parameters. Uncomment the line when
     // deprecating one.
@@ -306,18 +459,31 @@ fn main_exec() -> Result<(), MainError> {
 
     LOGGER.set_instance_id(instance_id.to_owned());
 
+    if arguments.flag_present("validate-config") {
+        // It's safe to unwrap here because the argument requires `config-file`.
+        let config_path = arguments.single_value("config-file").unwrap();
+        validate_config_file(config_path, &instance_info)?;
+        return Ok(());
+    }
+
     if let Some(log) = arguments.single_value("log-path") {
         // It's safe to unwrap here because the field's been provided with a default value.
         let level = arguments.single_value("level").unwrap().to_owned();
         let logger_level = LoggerLevel::from_string(level).map_err(MainError::InvalidLogLevel)?;
         let show_level = arguments.flag_present("show-level");
         let show_log_origin = arguments.flag_present("show-log-origin");
+        // It's safe to unwrap here because the field's been provided with a default value.
+        let format = arguments.single_value("log-format").unwrap().to_owned();
+        let format = LoggerFormat::from_string(format).map_err(MainError::InvalidLogFormat)?;
 
         let logger_config = LoggerConfig {
             log_path: PathBuf::from(log),
             level: logger_level,
             show_level,
             show_log_origin,
+            format,
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         init_logger(logger_config, &instance_info).map_err(MainError::LoggerInitialization)?;
     }
@@ -325,15 +491,27 @@ fn main_exec() -> Result<(), MainError> {
     if let Some(metrics_path) = arguments.single_value("metrics-path") {
         let metrics_config = MetricsConfig {
             metrics_path: PathBuf::from(metrics_path),
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         init_metrics(metrics_config).map_err(MainError::MetricsInitialization)?;
     }
 
-    let mut seccomp_filters: BpfThreadMap = SeccompConfig::from_args(
+    let thread_filters: Vec<(&str, &String)> = [
+        ("vmm", "seccomp-filter-vmm"),
+        ("api", "seccomp-filter-api"),
+        ("vcpu", "seccomp-filter-vcpu"),
+        ("shared_dirs", "seccomp-filter-shared-dirs"),
+        ("api_vsock", "seccomp-filter-api-vsock"),
+    ]
+    .into_iter()
+    .filter_map(|(category, arg)| arguments.single_value(arg).map(|path| (category, path)))
+    .collect();
+    let (mut seccomp_filters, seccomp_filters_info) = SeccompConfig::from_args(
         arguments.flag_present("no-seccomp"),
         arguments.single_value("seccomp-filter"),
     )
-    .and_then(seccomp::get_filters)
+    .and_then(|config| seccomp::get_thread_filters(config, &thread_filters))
     .map_err(MainError::SeccompFilter)?;
 
     let vmm_config_json = arguments
@@ -346,6 +524,15 @@ fn main_exec() -> Result<(), MainError> {
         .map(fs::read_to_string)
         .map(|x| x.expect("Unable to open or read from the mmds content file"));
 
+    if let Some(fd) = arguments.single_value("cpu-max-fd") {
+        let fd = fd
+            .parse::<RawFd>()
+            .expect("'cpu-max-fd' parameter expected to be of 'i32' type.");
+        // SAFETY: Safe because the jailer opened the cpu.max file for us and nothing else in this
+        // process owns the inherited fd.
+        vmm::cgroup::set_cpu_max_file(unsafe { File::from_raw_fd(fd) });
+    }
+
     let boot_timer_enabled = arguments.flag_present("boot-timer");
     let api_enabled = !arguments.flag_present("no-api");
     let api_payload_limit = arg_parser
@@ -393,9 +580,36 @@ fn main_exec() -> Result<(), MainError> {
         let process_time_reporter =
             ProcessTimeReporter::new(start_time_us, start_time_cpu_us, parent_cpu_time_us);
 
+        let api_token = arguments
+            .single_value("api-token-file")
+            .map(|path| {
+                open_file(path, false)
+                    .map_err(ApiTokenError::Read)
+                    .and_then(ApiToken::from_reader)
+            })
+            .transpose()
+            .map_err(MainError::ApiToken)?;
+
+        let api_vsock_peer_cid = arguments.single_value("api-vsock-peer-cid").map(|s| {
+            s.parse::<u32>()
+                .expect("'api-vsock-peer-cid' parameter expected to be of 'u32' type.")
+        });
+        let api_vsock_relay = arguments
+            .single_value("api-vsock-port")
+            .map(|s| {
+                let port = s
+                    .parse::<u32>()
+                    .expect("'api-vsock-port' parameter expected to be of 'u32' type.");
+                ApiVsockRelay::new(port, api_vsock_peer_cid, bind_path.clone())
+            })
+            .transpose()
+            .map_err(MainError::ApiVsock)?;
+
         api_server_adapter::run_with_api(
             &mut seccomp_filters,
+            seccomp_filters_info,
             vmm_config_json,
+            arguments.flag_present("defer-start"),
             bind_path,
             instance_info,
             process_time_reporter,
@@ -403,12 +617,14 @@ fn main_exec() -> Result<(), MainError> {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            api_token,
+            api_vsock_relay,
         )
         .map_err(MainError::RunWithApi)
     } else {
         let seccomp_filters: BpfThreadMap = seccomp_filters
             .into_iter()
-            .filter(|(k, _)| k != "api")
+            .filter(|(k, _)| k != "api" && k != "api_vsock")
             .collect();
         run_without_api(
             &seccomp_filters,
@@ -551,6 +767,36 @@ fn print_snapshot_data_format(snapshot_path: &str) -> Result<(), SnapshotVersion
     Ok(())
 }
 
+#[derive(Debug, thiserror::Error)]
+enum ValidateConfigError {
+    #[error("Unable to read the configuration file: {0}")]
+    ReadConfig(io::Error),
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(serde_json::Error),
+    #[error("{0}")]
+    InvalidConfig(ValidationErrors),
+}
+
+// Check the configuration file without creating the microVM, printing all the errors found.
+fn validate_config_file(
+    config_path: &str,
+    instance_info: &InstanceInfo,
+) -> Result<(), ValidateConfigError> {
+    let config_json = fs::read_to_string(config_path).map_err(ValidateConfigError::ReadConfig)?;
+    let vmm_config = serde_json::from_str::<VmmConfig>(&config_json)
+        .map_err(ValidateConfigError::InvalidJson)?;
+
+    if let Err(errors) = VmResources::validate_config(vmm_config, instance_info) {
+        for err in errors.0.iter() {
+            println!("{err}");
+        }
+        return Err(ValidateConfigError::InvalidConfig(errors));
+    }
+
+    println!("The configuration is valid.");
+    Ok(())
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum BuildFromJsonError {
     #[error("Configuration for VMM from one single json failed: {0}")]
@@ -608,6 +854,14 @@ fn run_without_api(
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
 
+    // Watch the resources used by the process, from before the microVM is built.
+    let resource_monitor = Arc::new(Mutex::new(resource_monitor::ResourceMonitor::new()));
+    event_manager.add_subscriber(resource_monitor.clone());
+    resource_monitor
+        .lock()
+        .expect("Poisoned lock")
+        .start(resource_monitor::MONITOR_PERIOD_MS);
+
     // Build the microVm. We can ignore VmResources since it's not used without api.
     let (_, vmm) = build_microvm_from_json(
         seccomp_filters,
@@ -0,0 +1,365 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Monitoring of the resources used by the Firecracker process, against its resource limits.
+//!
+//! Running out of file descriptors, threads or address space makes whichever syscall hits the
+//! limit first fail, e.g. with `EMFILE`, and the error rarely points back to the exhausted
+//! resource. The monitor periodically samples the usage of the process, exposes it as metrics,
+//! and warns when a resource gets close to its soft limit, before it is exhausted.
+
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+use std::{fmt, fs, io};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, info, warn, IncMetric, SharedStoreMetric, StoreMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+
+/// Resource usage sampling period.
+pub(crate) const MONITOR_PERIOD_MS: u64 = 1000;
+
+// Share of the soft limit, in percent, from which the usage of a resource is reported.
+const WARN_PERCENT: u64 = 80;
+// Share of the soft limit, in percent, below which a reported resource is fine again. It is lower
+// than `WARN_PERCENT`, so that a usage hovering around the threshold is not reported every time.
+const RECOVER_PERCENT: u64 = 70;
+
+/// A resource of the process, limited by a resource limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resource {
+    /// File descriptors, limited by `RLIMIT_NOFILE`.
+    OpenFiles,
+    /// Threads, limited by `RLIMIT_NPROC`. The limit counts the threads of all the processes of
+    /// the user, so the process can run out of threads before reaching it on its own.
+    Threads,
+    /// Virtual address space, in bytes, limited by `RLIMIT_AS`.
+    AddressSpace,
+}
+
+impl Resource {
+    const ALL: [Resource; 3] = [
+        Resource::OpenFiles,
+        Resource::Threads,
+        Resource::AddressSpace,
+    ];
+
+    // Name of the limit in `/proc/<pid>/limits`.
+    fn limit_name(self) -> &'static str {
+        match self {
+            Resource::OpenFiles => "Max open files",
+            Resource::Threads => "Max processes",
+            Resource::AddressSpace => "Max address space",
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::OpenFiles => write!(f, "open file descriptors"),
+            Resource::Threads => write!(f, "threads"),
+            Resource::AddressSpace => write!(f, "address space bytes"),
+        }
+    }
+}
+
+/// Resource usage of the process, along with its soft limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Sample {
+    open_fds: u64,
+    threads: u64,
+    address_space: u64,
+    rss: u64,
+    open_fds_limit: Option<u64>,
+    threads_limit: Option<u64>,
+    address_space_limit: Option<u64>,
+}
+
+impl Sample {
+    /// Samples the usage of the current process.
+    fn read() -> io::Result<Self> {
+        // The directory listing holds the file descriptor it is read through.
+        let open_fds = fs::read_dir("/proc/self/fd")?.count().saturating_sub(1) as u64;
+        let mut sample = Sample {
+            open_fds,
+            ..Default::default()
+        };
+        sample.parse_status(&fs::read_to_string("/proc/self/status")?);
+        sample.parse_limits(&fs::read_to_string("/proc/self/limits")?);
+        Ok(sample)
+    }
+
+    /// Reads the thread count and the memory usage from the contents of `/proc/<pid>/status`.
+    fn parse_status(&mut self, status: &str) {
+        for line in status.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            // The sizes are in kB, as in `VmSize:    123456 kB`.
+            let number = || {
+                value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| number.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            match key {
+                "Threads" => self.threads = number(),
+                "VmSize" => self.address_space = number() * 1024,
+                "VmRSS" => self.rss = number() * 1024,
+                _ => (),
+            }
+        }
+    }
+
+    /// Reads the soft limits from the contents of `/proc/<pid>/limits`. They are read on every
+    /// sample, since they can be changed from outside the process.
+    fn parse_limits(&mut self, limits: &str) {
+        for resource in Resource::ALL {
+            let limit = limits
+                .lines()
+                .find_map(|line| line.strip_prefix(resource.limit_name()))
+                .and_then(|values| values.split_whitespace().next())
+                .and_then(|soft| soft.parse::<u64>().ok());
+            match resource {
+                Resource::OpenFiles => self.open_fds_limit = limit,
+                Resource::Threads => self.threads_limit = limit,
+                Resource::AddressSpace => self.address_space_limit = limit,
+            }
+        }
+    }
+
+    /// Returns the usage of `resource`, and its soft limit if there is one.
+    fn usage(&self, resource: Resource) -> (u64, Option<u64>) {
+        match resource {
+            Resource::OpenFiles => (self.open_fds, self.open_fds_limit),
+            Resource::Threads => (self.threads, self.threads_limit),
+            Resource::AddressSpace => (self.address_space, self.address_space_limit),
+        }
+    }
+
+    fn store_metrics(&self) {
+        let metrics = &METRICS.resources;
+        let store = |metric: &SharedStoreMetric, value: u64| {
+            metric.store(usize::try_from(value).unwrap_or(usize::MAX))
+        };
+        store(&metrics.open_fds, self.open_fds);
+        store(&metrics.open_fds_limit, self.open_fds_limit.unwrap_or(0));
+        store(&metrics.threads, self.threads);
+        store(&metrics.threads_limit, self.threads_limit.unwrap_or(0));
+        store(&metrics.address_space_bytes, self.address_space);
+        store(
+            &metrics.address_space_limit_bytes,
+            self.address_space_limit.unwrap_or(0),
+        );
+        store(&metrics.rss_bytes, self.rss);
+    }
+}
+
+/// Periodically samples the resource usage of the process, and reports the resources close to
+/// their limits.
+#[derive(Debug)]
+pub(crate) struct ResourceMonitor {
+    timer: TimerFd,
+    // The resources reported close to their limits, and not back below `RECOVER_PERCENT` since.
+    reported: Vec<Resource>,
+    // Whether the last sample failed, so that a failure lasting for many samples, e.g. when
+    // `/proc` is not mounted in the jail, is only logged once.
+    failing: bool,
+}
+
+impl ResourceMonitor {
+    /// ResourceMonitor constructor. Can panic on `TimerFd` creation failure.
+    pub fn new() -> Self {
+        let timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .expect("Cannot create the resource monitor timer fd.");
+        ResourceMonitor {
+            timer,
+            reported: Vec::new(),
+            failing: false,
+        }
+    }
+
+    /// Starts sampling the resource usage every `interval_ms` milliseconds.
+    pub(crate) fn start(&mut self, interval_ms: u64) {
+        let timer_state = TimerState::Periodic {
+            current: Duration::from_millis(interval_ms),
+            interval: Duration::from_millis(interval_ms),
+        };
+        self.timer.set_state(timer_state, SetTimeFlags::Default);
+
+        // Sample straight away, so that the metrics are set from the first flush.
+        self.check();
+    }
+
+    fn check(&mut self) {
+        match Sample::read() {
+            Ok(sample) => {
+                self.failing = false;
+                self.observe(&sample);
+            }
+            Err(err) => {
+                METRICS.resources.sample_fails.inc();
+                if !self.failing {
+                    warn!("Failed to sample the resource usage: {}", err);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn observe(&mut self, sample: &Sample) {
+        sample.store_metrics();
+
+        let mut warned = false;
+        for resource in Resource::ALL {
+            let (used, Some(limit)) = sample.usage(resource) else {
+                self.reported.retain(|reported| *reported != resource);
+                continue;
+            };
+            let percent = used.saturating_mul(100) / limit.max(1);
+            let reported = self.reported.contains(&resource);
+            if percent >= WARN_PERCENT && !reported {
+                METRICS.resources.limit_warnings.inc();
+                warn!(
+                    "The process is running out of {}: {} used out of a limit of {} ({}%).",
+                    resource, used, limit, percent
+                );
+                self.reported.push(resource);
+                warned = true;
+            } else if percent < RECOVER_PERCENT && reported {
+                info!(
+                    "The process uses less than {}% of its {} limit again.",
+                    RECOVER_PERCENT, resource
+                );
+                self.reported.retain(|reported| *reported != resource);
+            }
+        }
+
+        // The process may not live until the next periodic flush of the metrics.
+        if warned {
+            if let Err(err) = METRICS.write() {
+                METRICS.logger.missed_metrics_count.inc();
+                error!("Failed to write metrics: {}", err);
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for ResourceMonitor {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if !EventSet::IN.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if source == self.timer.as_raw_fd() {
+            self.timer.read();
+            self.check();
+        } else {
+            error!("Spurious resource monitor event!");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!("Failed to register resource monitor event: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "Name:\tfirecracker\nVmSize:\t  204800 kB\nVmRSS:\t   51200 kB\n\
+                          Threads:\t12\n";
+    const LIMITS: &str = "\
+Limit                     Soft Limit           Hard Limit           Units
+Max processes             1000                 1000                 processes
+Max open files            100                  4096                 files
+Max address space         unlimited            unlimited            bytes
+";
+
+    #[test]
+    fn test_parse_sample() {
+        let mut sample = Sample {
+            open_fds: 7,
+            ..Default::default()
+        };
+        sample.parse_status(STATUS);
+        sample.parse_limits(LIMITS);
+        assert_eq!(
+            sample,
+            Sample {
+                open_fds: 7,
+                threads: 12,
+                address_space: 200 << 20,
+                rss: 50 << 20,
+                open_fds_limit: Some(100),
+                threads_limit: Some(1000),
+                address_space_limit: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_sample() {
+        let sample = Sample::read().unwrap();
+        assert!(sample.open_fds > 0);
+        assert!(sample.threads >= 1);
+        assert!(sample.rss > 0);
+        assert!(sample.address_space >= sample.rss);
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut monitor = ResourceMonitor::new();
+        let mut sample = Sample {
+            open_fds: 79,
+            open_fds_limit: Some(100),
+            threads: 12,
+            ..Default::default()
+        };
+        let warnings = METRICS.resources.limit_warnings.count();
+
+        monitor.observe(&sample);
+        assert!(monitor.reported.is_empty());
+        assert_eq!(METRICS.resources.open_fds.fetch(), 79);
+        assert_eq!(METRICS.resources.open_fds_limit.fetch(), 100);
+        assert_eq!(METRICS.resources.threads_limit.fetch(), 0);
+
+        // A resource close to its limit is reported once, until it goes back below the
+        // recovery threshold.
+        sample.open_fds = 80;
+        monitor.observe(&sample);
+        assert_eq!(monitor.reported, vec![Resource::OpenFiles]);
+        sample.open_fds = 95;
+        monitor.observe(&sample);
+        sample.open_fds = 75;
+        monitor.observe(&sample);
+        assert_eq!(monitor.reported, vec![Resource::OpenFiles]);
+        assert_eq!(METRICS.resources.limit_warnings.count(), warnings + 1);
+
+        sample.open_fds = 69;
+        monitor.observe(&sample);
+        assert!(monitor.reported.is_empty());
+        sample.open_fds = 99;
+        monitor.observe(&sample);
+        assert_eq!(METRICS.resources.limit_warnings.count(), warnings + 2);
+
+        // A resource without limit is never reported.
+        sample.open_fds_limit = None;
+        monitor.observe(&sample);
+        assert!(monitor.reported.is_empty());
+    }
+}
@@ -4,11 +4,12 @@ use std::fmt::Debug;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
+use std::sync::Arc;
 
-use seccompiler::{deserialize_binary, BpfThreadMap, DeserializationError};
-use vmm::seccomp_filters::get_empty_filters;
-
-const THREAD_CATEGORIES: [&str; 3] = ["vmm", "api", "vcpu"];
+use seccompiler::{deserialize_binary, BpfProgram, BpfThreadMap, DeserializationError};
+use vmm::seccomp_filters::{
+    get_empty_filters, SeccompFilterInfo, SeccompFilterSource, THREAD_CATEGORIES,
+};
 
 // This byte limit is passed to `bincode` to guard against a potential memory
 // allocation DOS caused by binary filters that are too large.
@@ -61,6 +62,15 @@ impl SeccompConfig {
             }
         }
     }
+
+    /// Origin of the filters described by this config.
+    pub fn source(&self) -> SeccompFilterSource {
+        match self {
+            SeccompConfig::None => SeccompFilterSource::None,
+            SeccompConfig::Advanced => SeccompFilterSource::Default,
+            SeccompConfig::Custom(_) => SeccompFilterSource::Custom,
+        }
+    }
 }
 
 /// Retrieve the appropriate filters, based on the SeccompConfig.
@@ -72,6 +82,46 @@ pub fn get_filters(config: SeccompConfig) -> Result<BpfThreadMap, FilterError> {
     }
 }
 
+/// Retrieve the appropriate filters, based on the SeccompConfig, then replace the filters of the
+/// thread categories in `thread_filters` with the custom filter from the matching file.
+///
+/// Returns the filters along with their description.
+pub fn get_thread_filters<T: AsRef<Path> + Debug>(
+    config: SeccompConfig,
+    thread_filters: &[(&str, T)],
+) -> Result<(BpfThreadMap, Vec<SeccompFilterInfo>), FilterError> {
+    let source = config.source();
+    let mut filters = get_filters(config)?;
+    let mut filters_info = SeccompFilterInfo::from_filters(&filters, source);
+
+    for (category, path) in thread_filters {
+        let file = File::open(path).map_err(FilterError::FileOpen)?;
+        let filter = get_custom_thread_filter(file, category)?;
+        if let Some(info) = filters_info
+            .iter_mut()
+            .find(|info| info.thread_category == *category)
+        {
+            info.source = SeccompFilterSource::Custom;
+            info.instructions = filter.len();
+        }
+        filters.insert(category.to_string(), filter);
+    }
+
+    Ok((filters, filters_info))
+}
+
+/// Retrieve the filter of `thread_category` from a custom filter file. The file may contain the
+/// filters of other thread categories too, which are ignored.
+fn get_custom_thread_filter<R: Read + Debug>(
+    reader: R,
+    thread_category: &str,
+) -> Result<Arc<BpfProgram>, FilterError> {
+    let mut map = deserialize_binary(BufReader::new(reader), DESERIALIZATION_BYTES_LIMIT)
+        .map_err(FilterError::Deserialization)?;
+    map.remove(thread_category)
+        .ok_or_else(|| FilterError::MissingThreadCategory(thread_category.to_string()))
+}
+
 /// Retrieve the default filters containing the syscall rules required by `Firecracker`
 /// to function. The binary file is generated via the `build.rs` script of this crate.
 fn get_default_filters() -> Result<BpfThreadMap, FilterError> {
@@ -90,8 +140,12 @@ fn get_custom_filters<R: Read + Debug>(reader: R) -> Result<BpfThreadMap, Filter
 }
 
 /// Return an error if the BpfThreadMap contains invalid thread categories.
+///
+/// A map missing the filter of the thread serving the shared directories uses the filter of the
+/// VMM thread in its place, as the VMM thread used to serve them. Likewise, a map missing the
+/// filter of the thread relaying the API vsock connections uses the filter of the API thread.
 fn filter_thread_categories(map: BpfThreadMap) -> Result<BpfThreadMap, FilterError> {
-    let (filters, invalid_filters): (BpfThreadMap, BpfThreadMap) = map
+    let (mut filters, invalid_filters): (BpfThreadMap, BpfThreadMap) = map
         .into_iter()
         .partition(|(k, _)| THREAD_CATEGORIES.contains(&k.as_str()));
     if !invalid_filters.is_empty() {
@@ -108,6 +162,17 @@ fn filter_thread_categories(map: BpfThreadMap) -> Result<BpfThreadMap, FilterErr
         return Err(FilterError::ThreadCategories(thread_categories_string));
     }
 
+    if !filters.contains_key("shared_dirs") {
+        if let Some(filter) = filters.get("vmm").cloned() {
+            filters.insert("shared_dirs".to_string(), filter);
+        }
+    }
+    if !filters.contains_key("api_vsock") {
+        if let Some(filter) = filters.get("api").cloned() {
+            filters.insert("api_vsock".to_string(), filter);
+        }
+    }
+
     for &category in THREAD_CATEGORIES.iter() {
         let category_string = category.to_string();
         if !filters.contains_key(&category_string) {
@@ -120,9 +185,9 @@ fn filter_thread_categories(map: BpfThreadMap) -> Result<BpfThreadMap, FilterErr
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::io::Write;
 
-    use seccompiler::BpfThreadMap;
+    use seccompiler::{sock_filter, BpfThreadMap};
     use utils::tempfile::TempFile;
 
     use super::*;
@@ -130,16 +195,20 @@ mod tests {
     #[test]
     fn test_get_filters() {
         let mut filters = get_empty_filters();
-        assert_eq!(filters.len(), 3);
+        assert_eq!(filters.len(), 5);
         assert!(filters.remove("vmm").is_some());
         assert!(filters.remove("api").is_some());
         assert!(filters.remove("vcpu").is_some());
+        assert!(filters.remove("shared_dirs").is_some());
+        assert!(filters.remove("api_vsock").is_some());
 
         let mut filters = get_empty_filters();
-        assert_eq!(filters.len(), 3);
+        assert_eq!(filters.len(), 5);
         assert_eq!(filters.remove("vmm").unwrap().len(), 0);
         assert_eq!(filters.remove("api").unwrap().len(), 0);
         assert_eq!(filters.remove("vcpu").unwrap().len(), 0);
+        assert_eq!(filters.remove("shared_dirs").unwrap().len(), 0);
+        assert_eq!(filters.remove("api_vsock").unwrap().len(), 0);
 
         let file = TempFile::new().unwrap().into_file();
 
@@ -153,8 +222,28 @@ mod tests {
         map.insert("vcpu".to_string(), Arc::new(vec![]));
         map.insert("vmm".to_string(), Arc::new(vec![]));
         map.insert("api".to_string(), Arc::new(vec![]));
+        map.insert("shared_dirs".to_string(), Arc::new(vec![]));
+        map.insert("api_vsock".to_string(), Arc::new(vec![]));
+
+        assert_eq!(filter_thread_categories(map).unwrap().len(), 5);
+
+        // the filters of the VMM and API threads stand in for the missing ones of the shared
+        // directories and of the API vsock relay
+        let mut map = BpfThreadMap::new();
+        map.insert("vcpu".to_string(), Arc::new(vec![]));
+        let allow = sock_filter {
+            code: 0x06,
+            jt: 0,
+            jf: 0,
+            k: 0x7fff_0000,
+        };
+        map.insert("vmm".to_string(), Arc::new(vec![allow]));
+        map.insert("api".to_string(), Arc::new(vec![allow, allow]));
 
-        assert_eq!(filter_thread_categories(map).unwrap().len(), 3);
+        let filters = filter_thread_categories(map).unwrap();
+        assert_eq!(filters.len(), 5);
+        assert_eq!(filters["shared_dirs"].len(), 1);
+        assert_eq!(filters["api_vsock"].len(), 2);
 
         // invalid categories
         let mut map = BpfThreadMap::new();
@@ -181,6 +270,66 @@ mod tests {
         }
     }
 
+    // Serializes a filter map holding a single filter, like seccompiler-bin does.
+    fn serialize_filter(thread_category: &str, filter: &[sock_filter]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&(thread_category.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(thread_category.as_bytes());
+        bytes.extend_from_slice(&(filter.len() as u64).to_le_bytes());
+        for instruction in filter {
+            bytes.extend_from_slice(&instruction.code.to_le_bytes());
+            bytes.push(instruction.jt);
+            bytes.push(instruction.jf);
+            bytes.extend_from_slice(&instruction.k.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_get_thread_filters() {
+        let allow = sock_filter {
+            code: 0x06,
+            jt: 0,
+            jf: 0,
+            k: 0x7fff_0000,
+        };
+        let file = TempFile::new().unwrap();
+        file.as_file()
+            .write_all(&serialize_filter("vcpu", &[allow, allow]))
+            .unwrap();
+
+        let (filters, filters_info) =
+            get_thread_filters(SeccompConfig::None, &[("vcpu", file.as_path())]).unwrap();
+        assert_eq!(filters.len(), 5);
+        assert_eq!(filters["vmm"].len(), 0);
+        assert_eq!(filters["api"].len(), 0);
+        assert_eq!(filters["vcpu"].len(), 2);
+        assert_eq!(filters["shared_dirs"].len(), 0);
+        assert_eq!(filters["api_vsock"].len(), 0);
+        assert_eq!(filters_info.len(), 5);
+        for info in filters_info {
+            if info.thread_category == "vcpu" {
+                assert_eq!(info.source, SeccompFilterSource::Custom);
+                assert_eq!(info.instructions, 2);
+            } else {
+                assert_eq!(info.source, SeccompFilterSource::None);
+                assert_eq!(info.instructions, 0);
+            }
+        }
+
+        // The file does not hold a filter for the thread category.
+        assert!(matches!(
+            get_thread_filters(SeccompConfig::None, &[("api", file.as_path())]),
+            Err(FilterError::MissingThreadCategory(category)) if category == "api"
+        ));
+
+        assert!(matches!(
+            get_thread_filters(SeccompConfig::None, &[("api", "invalid_path")]),
+            Err(FilterError::FileOpen(_))
+        ));
+    }
+
     #[test]
     fn test_seccomp_config() {
         assert!(matches!(
@@ -203,5 +352,16 @@ mod tests {
             SeccompConfig::from_args(false, Option::<&str>::None),
             Ok(SeccompConfig::Advanced)
         ));
+
+        assert_eq!(SeccompConfig::None.source(), SeccompFilterSource::None);
+        assert_eq!(
+            SeccompConfig::Advanced.source(),
+            SeccompFilterSource::Default
+        );
+        let file = TempFile::new().unwrap().into_file();
+        assert_eq!(
+            SeccompConfig::Custom(file).source(),
+            SeccompFilterSource::Custom
+        );
     }
 }
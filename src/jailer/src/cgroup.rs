@@ -185,6 +185,9 @@ pub trait Cgroup {
 
     // This function will assign the process associated with the pid to the respective cgroup.
     fn attach_pid(&self) -> Result<(), JailerError>;
+
+    // Path of the cgroup property file.
+    fn file_path(&self) -> PathBuf;
 }
 
 // If we call inherit_from_parent_aux(.../A/B/C, file, condition), the following will happen:
@@ -275,6 +278,69 @@ fn get_controller_from_filename(file: &str) -> Result<&str, JailerError> {
     Ok(v[0])
 }
 
+// Resource controller limits that can be set through dedicated jailer arguments on cgroup v2
+// hierarchies, as (argument name, cgroup file, value parser). Each parser returns the value to be
+// written into the cgroup file, or None if the argument value is invalid.
+pub const CGROUP_V2_LIMITS: [(&str, &str, fn(&str) -> Option<String>); 4] = [
+    ("cpu-max", "cpu.max", parse_cpu_max),
+    ("memory-max", "memory.max", parse_memory_max),
+    ("io-max", "io.max", parse_io_max),
+    ("pids-max", "pids.max", parse_pids_max),
+];
+
+// Parses a "max" or numeric cgroup v2 limit.
+fn parse_limit(value: &str) -> Option<String> {
+    if value == "max" || value.parse::<u64>().is_ok() {
+        Some(value.to_string())
+    } else {
+        None
+    }
+}
+
+// cpu.max format: <quota_us|max>[,<period_us>] (e.g 50000,100000).
+fn parse_cpu_max(value: &str) -> Option<String> {
+    match value.split_once(',') {
+        Some((quota, period)) => {
+            let period = period.parse::<u64>().ok()?;
+            Some(format!("{} {}", parse_limit(quota)?, period))
+        }
+        None => parse_limit(value),
+    }
+}
+
+// memory.max format: <bytes|max>, where bytes may use the K, M, G or T suffixes (e.g 512M).
+fn parse_memory_max(value: &str) -> Option<String> {
+    let digits = value.strip_suffix(['K', 'M', 'G', 'T']).unwrap_or(value);
+    digits.parse::<u64>().ok()?;
+    Some(value.to_string())
+}
+
+// io.max format: <major>:<minor> followed by one or more <key>=<bytes|ios|max> pairs separated by
+// ',', where key is one of rbps, wbps, riops and wiops (e.g 8:0,rbps=1048576,wiops=120).
+fn parse_io_max(value: &str) -> Option<String> {
+    let mut fields = value.split(',');
+    let (major, minor) = fields.next()?.split_once(':')?;
+    major.parse::<u32>().ok()?;
+    minor.parse::<u32>().ok()?;
+
+    let mut line = format!("{}:{}", major, minor);
+    let mut has_limit = false;
+    for field in fields {
+        let (key, limit) = field.split_once('=')?;
+        if !["rbps", "wbps", "riops", "wiops"].contains(&key) {
+            return None;
+        }
+        line.push_str(&format!(" {}={}", key, parse_limit(limit)?));
+        has_limit = true;
+    }
+    has_limit.then_some(line)
+}
+
+// pids.max format: <count|max>.
+fn parse_pids_max(value: &str) -> Option<String> {
+    parse_limit(value)
+}
+
 impl CgroupV1 {
     // Create a new cgroupsv1 controller
     pub fn new(
@@ -328,6 +394,10 @@ impl Cgroup for CgroupV1 {
 
         Ok(())
     }
+
+    fn file_path(&self) -> PathBuf {
+        self.base.location.join(&self.base.file)
+    }
 }
 
 impl CgroupV2 {
@@ -430,6 +500,10 @@ impl Cgroup for CgroupV2 {
 
         Ok(())
     }
+
+    fn file_path(&self) -> PathBuf {
+        self.0.location.join(&self.0.file)
+    }
 }
 
 #[cfg(test)]
@@ -725,6 +799,36 @@ mod tests {
         assert!(res == some_line);
     }
 
+    #[test]
+    fn test_parse_v2_limits() {
+        assert_eq!(parse_cpu_max("max").unwrap(), "max");
+        assert_eq!(parse_cpu_max("50000").unwrap(), "50000");
+        assert_eq!(parse_cpu_max("50000,100000").unwrap(), "50000 100000");
+        assert_eq!(parse_cpu_max("max,100000").unwrap(), "max 100000");
+        assert!(parse_cpu_max("50000,max").is_none());
+        assert!(parse_cpu_max("half").is_none());
+
+        assert_eq!(parse_memory_max("max").unwrap(), "max");
+        assert_eq!(parse_memory_max("1073741824").unwrap(), "1073741824");
+        assert_eq!(parse_memory_max("512M").unwrap(), "512M");
+        assert!(parse_memory_max("512MiB").is_none());
+        assert!(parse_memory_max("-1").is_none());
+
+        assert_eq!(parse_pids_max("max").unwrap(), "max");
+        assert_eq!(parse_pids_max("64").unwrap(), "64");
+        assert!(parse_pids_max("64M").is_none());
+
+        assert_eq!(
+            parse_io_max("8:0,rbps=1048576,wiops=max").unwrap(),
+            "8:0 rbps=1048576 wiops=max"
+        );
+        assert!(parse_io_max("8:0").is_none());
+        assert!(parse_io_max("8,rbps=1").is_none());
+        assert!(parse_io_max("8:0,rbps").is_none());
+        assert!(parse_io_max("8:0,xbps=1").is_none());
+        assert!(parse_io_max("8:0,rbps=fast").is_none());
+    }
+
     #[test]
     fn test_get_controller() {
         let mut file = "cpuset.cpu";
@@ -3,7 +3,8 @@
 
 use std::env;
 use std::ffi::CStr;
-use std::path::Path;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::ptr::null;
 
 use utils::syscall::SyscallReturnCode;
@@ -14,9 +15,41 @@ const OLD_ROOT_DIR_NAME_NUL_TERMINATED: &[u8] = b"old_root\0";
 const ROOT_DIR_NUL_TERMINATED: &[u8] = b"/\0";
 const CURRENT_DIR_NUL_TERMINATED: &[u8] = b".\0";
 
+// Bind mounts each of the host `devices` at the same location inside the jail, for when they cannot
+// be created with mknod (e.g. inside a user namespace). The current directory must be the jail
+// root.
+fn bind_mount_devices(devices: &[&str]) -> Result<(), JailerError> {
+    for device in devices {
+        let jail_device = PathBuf::from(device.trim_start_matches('/'));
+        if let Some(parent) = jail_device.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| JailerError::CreateDir(parent.to_path_buf(), err))?;
+        }
+        File::create(&jail_device)
+            .map_err(|err| JailerError::FileOpen(jail_device.clone(), err))?;
+
+        let host_device = to_cstring(device)?;
+        let jail_device = to_cstring(jail_device)?;
+        // SAFETY: Safe because we provide valid parameters.
+        SyscallReturnCode(unsafe {
+            libc::mount(
+                host_device.as_ptr(),
+                jail_device.as_ptr(),
+                null(),
+                libc::MS_BIND,
+                null(),
+            )
+        })
+        .into_empty_result()
+        .map_err(|err| JailerError::MountBindDevice(err, device.to_string()))?;
+    }
+    Ok(())
+}
+
 // This uses switching to a new mount namespace + pivot_root(), together with the regular chroot,
-// to provide a hardened jail (at least compared to only relying on chroot).
-pub fn chroot(path: &Path) -> Result<(), JailerError> {
+// to provide a hardened jail (at least compared to only relying on chroot). The host `devices` are
+// bind mounted inside the jail before the host root becomes unreachable.
+pub fn chroot(path: &Path, devices: &[&str]) -> Result<(), JailerError> {
     // We unshare into a new mount namespace.
     // SAFETY: The call is safe because we're invoking a C library
     // function with valid parameters.
@@ -64,6 +97,8 @@ pub fn chroot(path: &Path) -> Result<(), JailerError> {
     // Change current dir to the chroot dir, so we only need to handle relative paths from now on.
     env::set_current_dir(path).map_err(JailerError::SetCurrentDir)?;
 
+    bind_mount_devices(devices)?;
+
     // We use the CStr conversion to make sure the contents of the byte slice would be a
     // valid C string (and for the as_ptr() method).
     let old_root_dir = CStr::from_bytes_with_nul(OLD_ROOT_DIR_NAME_NUL_TERMINATED)
@@ -0,0 +1,250 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use utils::syscall::SyscallReturnCode;
+
+use super::{to_cstring, JailerError};
+
+// How a file is made available inside the jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChrootFilePolicy {
+    // Hard link the file, which must be on the same filesystem as the jail.
+    Hardlink,
+    // Copy the file.
+    Copy,
+    // Hard link the file, falling back to a copy if it is on another filesystem.
+    HardlinkOrCopy,
+}
+
+impl ChrootFilePolicy {
+    fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "hardlink" => Some(ChrootFilePolicy::Hardlink),
+            "copy" => Some(ChrootFilePolicy::Copy),
+            "hardlink-or-copy" => Some(ChrootFilePolicy::HardlinkOrCopy),
+            _ => None,
+        }
+    }
+}
+
+// File placed inside the jail before chrooting, such as a kernel image, a drive or a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChrootFile {
+    policy: ChrootFilePolicy,
+    host_path: PathBuf,
+    // Path relative to the jail root.
+    jail_path: PathBuf,
+}
+
+impl ChrootFile {
+    // Parses an entry following this format: <policy> <host_path> [<jail_path>]. Without an
+    // explicit jail path, the file is placed at the jail root, under its host file name.
+    pub fn parse(entry: &str) -> Result<Self, JailerError> {
+        let invalid = || JailerError::ChrootFileEntry(entry.to_string());
+        let fields = entry.split_whitespace().collect::<Vec<_>>();
+        let (policy, host_path, jail_path) = match fields[..] {
+            [policy, host_path] => {
+                let file_name = Path::new(host_path).file_name().ok_or_else(invalid)?;
+                (policy, host_path, Path::new(file_name))
+            }
+            [policy, host_path, jail_path] => (policy, host_path, Path::new(jail_path)),
+            _ => return Err(invalid()),
+        };
+        let policy = ChrootFilePolicy::parse(policy).ok_or_else(invalid)?;
+
+        // The file must end up inside the jail, whether the path is absolute or not.
+        let mut relative_path = PathBuf::new();
+        for component in jail_path.components() {
+            match component {
+                Component::Normal(name) => relative_path.push(name),
+                Component::RootDir | Component::CurDir => (),
+                Component::ParentDir | Component::Prefix(_) => return Err(invalid()),
+            }
+        }
+        if relative_path.as_os_str().is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(ChrootFile {
+            policy,
+            host_path: PathBuf::from(host_path),
+            jail_path: relative_path,
+        })
+    }
+
+    // Parses a file listing one entry per line. Empty lines and lines starting with '#' are
+    // ignored.
+    pub fn parse_list(path: &Path) -> Result<Vec<Self>, JailerError> {
+        fs::read_to_string(path)
+            .map_err(|err| JailerError::ReadToString(path.to_path_buf(), err))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ChrootFile::parse)
+            .collect()
+    }
+
+    // Places the file inside the jail, replacing any existing one, and hands it over to
+    // `uid`/`gid`. A hard linked file shares its ownership with the host file.
+    pub fn install(&self, chroot_dir: &Path, uid: u32, gid: u32) -> Result<(), JailerError> {
+        let metadata = fs::metadata(&self.host_path)
+            .map_err(|err| JailerError::FileOpen(self.host_path.clone(), err))?;
+        if !metadata.is_file() {
+            return Err(JailerError::NotAFile(self.host_path.clone()));
+        }
+
+        let dest_path = chroot_dir.join(&self.jail_path);
+        // Safe to unwrap since the jail path has at least one component.
+        let dest_dir = dest_path.parent().unwrap();
+        fs::create_dir_all(dest_dir)
+            .map_err(|err| JailerError::CreateDir(dest_dir.to_path_buf(), err))?;
+        if let Err(err) = fs::remove_file(&dest_path) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(JailerError::RemoveFile(dest_path, err));
+            }
+        }
+
+        let hard_link = || fs::hard_link(&self.host_path, &dest_path);
+        let copy = || {
+            fs::copy(&self.host_path, &dest_path)
+                .map(|_| ())
+                .map_err(|err| JailerError::Copy(self.host_path.clone(), dest_path.clone(), err))
+        };
+        let hard_link_err =
+            |err| JailerError::HardLink(self.host_path.clone(), dest_path.clone(), err);
+        match self.policy {
+            ChrootFilePolicy::Hardlink => hard_link().map_err(hard_link_err)?,
+            ChrootFilePolicy::Copy => copy()?,
+            ChrootFilePolicy::HardlinkOrCopy => match hard_link() {
+                Err(err) if err.raw_os_error() == Some(libc::EXDEV) => copy()?,
+                res => res.map_err(hard_link_err)?,
+            },
+        }
+
+        let dest_path_cstr = to_cstring(&dest_path)?;
+        // SAFETY: Safe because `dest_path_cstr` is null-terminated.
+        SyscallReturnCode(unsafe { libc::chown(dest_path_cstr.as_ptr(), uid, gid) })
+            .into_empty_result()
+            .map_err(|err| JailerError::ChangeFileOwner(dest_path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+
+    use utils::tempdir::TempDir;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_chroot_file() {
+        let file = ChrootFile::parse("hardlink /srv/vmlinux.bin").unwrap();
+        assert_eq!(file.policy, ChrootFilePolicy::Hardlink);
+        assert_eq!(file.host_path, PathBuf::from("/srv/vmlinux.bin"));
+        assert_eq!(file.jail_path, PathBuf::from("vmlinux.bin"));
+
+        let file = ChrootFile::parse("copy  /srv/rootfs.ext4\t/drives/rootfs.ext4").unwrap();
+        assert_eq!(file.policy, ChrootFilePolicy::Copy);
+        assert_eq!(file.jail_path, PathBuf::from("drives/rootfs.ext4"));
+
+        let file = ChrootFile::parse("hardlink-or-copy /srv/mem snapshot/./mem").unwrap();
+        assert_eq!(file.policy, ChrootFilePolicy::HardlinkOrCopy);
+        assert_eq!(file.jail_path, PathBuf::from("snapshot/mem"));
+
+        for invalid in [
+            "",
+            "hardlink",
+            "symlink /srv/vmlinux.bin",
+            "copy /srv/..",
+            "copy /srv/vmlinux.bin /",
+            "copy /srv/vmlinux.bin ../vmlinux.bin",
+            "copy /srv/vmlinux.bin /vmlinux.bin extra",
+        ] {
+            assert!(
+                matches!(
+                    ChrootFile::parse(invalid),
+                    Err(JailerError::ChrootFileEntry(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_chroot_file_list() {
+        let list = TempFile::new().unwrap();
+        list.as_file()
+            .write_all(b"# Kernel\nhardlink /srv/vmlinux.bin\n\n  copy /srv/rootfs.ext4 /rootfs\n")
+            .unwrap();
+        let files = ChrootFile::parse_list(list.as_path()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ChrootFile::parse("hardlink /srv/vmlinux.bin").unwrap(),
+                ChrootFile::parse("copy /srv/rootfs.ext4 /rootfs").unwrap(),
+            ]
+        );
+
+        list.as_file().write_all(b"link /srv/initrd\n").unwrap();
+        assert!(matches!(
+            ChrootFile::parse_list(list.as_path()),
+            Err(JailerError::ChrootFileEntry(_))
+        ));
+
+        assert!(matches!(
+            ChrootFile::parse_list(Path::new("/this/path/does/not/exist")),
+            Err(JailerError::ReadToString(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_install_chroot_file() {
+        let host_dir = TempDir::new().unwrap();
+        let chroot_dir = TempDir::new_in(host_dir.as_path()).unwrap();
+        let host_file = TempFile::new_in(host_dir.as_path()).unwrap();
+        host_file.as_file().write_all(b"kernel").unwrap();
+        let host_path = host_file.as_path().to_str().unwrap();
+        let host_ino = host_file.as_file().metadata().unwrap().ino();
+        let uid = unsafe { libc::geteuid() };
+        let gid = unsafe { libc::getegid() };
+
+        let install = |entry: &str| {
+            ChrootFile::parse(entry)
+                .unwrap()
+                .install(chroot_dir.as_path(), uid, gid)
+        };
+
+        install(&format!("hardlink {} /boot/vmlinux", host_path)).unwrap();
+        let metadata = fs::metadata(chroot_dir.as_path().join("boot/vmlinux")).unwrap();
+        assert_eq!(metadata.ino(), host_ino);
+        assert_eq!(metadata.uid(), uid);
+
+        // Existing files are replaced.
+        install(&format!("copy {} /boot/vmlinux", host_path)).unwrap();
+        let dest_path = chroot_dir.as_path().join("boot/vmlinux");
+        assert_ne!(fs::metadata(&dest_path).unwrap().ino(), host_ino);
+        assert_eq!(fs::read(&dest_path).unwrap(), b"kernel");
+
+        install(&format!("hardlink-or-copy {}", host_path)).unwrap();
+        let file_name = host_file.as_path().file_name().unwrap();
+        let metadata = fs::metadata(chroot_dir.as_path().join(file_name)).unwrap();
+        assert_eq!(metadata.ino(), host_ino);
+
+        assert!(matches!(
+            install("copy /this/path/does/not/exist"),
+            Err(JailerError::FileOpen(_, _))
+        ));
+        assert!(matches!(
+            install(&format!("copy {}", host_dir.as_path().to_str().unwrap())),
+            Err(JailerError::NotAFile(_))
+        ));
+    }
+}
@@ -5,7 +5,7 @@ use std::ffi::{CStr, OsString};
 use std::fs::{self, canonicalize, File, OpenOptions, Permissions};
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
@@ -15,10 +15,15 @@ use utils::arg_parser::Error::MissingValue;
 use utils::syscall::SyscallReturnCode;
 use utils::{arg_parser, validators};
 
-use crate::cgroup::{Cgroup, CgroupBuilder};
+use crate::cgroup::{Cgroup, CgroupBuilder, CGROUP_V2_LIMITS};
 use crate::chroot::chroot;
-use crate::resource_limits::{ResourceLimits, FSIZE_ARG, NO_FILE_ARG};
-use crate::JailerError;
+use crate::chroot_files::ChrootFile;
+use crate::network::{unshare_netns, HostNetNs, TapConfig, VethConfig};
+use crate::resource_limits::{ResourceLimits, FSIZE_ARG, MEMLOCK_ARG, NO_FILE_ARG};
+use crate::scheduling::Scheduling;
+use crate::supervisor::{self, CLONE_PIDFD};
+use crate::userns::{IdMap, UserNamespace};
+use crate::{parse_inherited_fd, JailerError};
 
 const STDIN_FILENO: libc::c_int = 0;
 const STDOUT_FILENO: libc::c_int = 1;
@@ -28,18 +33,21 @@ const STDERR_FILENO: libc::c_int = 2;
 // minor/major numbers are taken from
 // https://www.kernel.org/doc/html/latest/admin-guide/devices.html
 const DEV_KVM_WITH_NUL: &[u8] = b"/dev/kvm\0";
+const DEV_KVM: &str = "/dev/kvm";
 const DEV_KVM_MAJOR: u32 = 10;
 const DEV_KVM_MINOR: u32 = 232;
 
 // TUN/TAP device minor/major numbers are taken from
 // www.kernel.org/doc/Documentation/networking/tuntap.txt
 const DEV_NET_TUN_WITH_NUL: &[u8] = b"/dev/net/tun\0";
+const DEV_NET_TUN: &str = "/dev/net/tun";
 const DEV_NET_TUN_MAJOR: u32 = 10;
 const DEV_NET_TUN_MINOR: u32 = 200;
 
 // Random number generator device minor/major numbers are taken from
 // https://www.kernel.org/doc/Documentation/admin-guide/devices.txt
 const DEV_URANDOM_WITH_NUL: &[u8] = b"/dev/urandom\0";
+const DEV_URANDOM: &str = "/dev/urandom";
 const DEV_URANDOM_MAJOR: u32 = 1;
 const DEV_URANDOM_MINOR: u32 = 9;
 
@@ -51,8 +59,9 @@ const DEV_URANDOM_MINOR: u32 = 9;
 const FOLDER_HIERARCHY: [&[u8]; 4] = [b"/\0", b"/dev\0", b"/dev/net\0", b"/run\0"];
 const FOLDER_PERMISSIONS: u32 = 0o700;
 
-// When running with `--new-pid-ns` flag, the PID of the process running the exec_file differs
-// from jailer's and it is stored inside a dedicated file, prefixed with the below extension.
+// When running with `--new-pid-ns` or `--supervise` flag, the PID of the process running the
+// exec_file differs from jailer's and it is stored inside a dedicated file, prefixed with the
+// below extension.
 const PID_FILE_EXTENSION: &str = ".pid";
 
 // Helper function, since we'll use libc::dup2 a bunch of times for daemonization.
@@ -67,20 +76,24 @@ fn dup2(old_fd: libc::c_int, new_fd: libc::c_int) -> Result<(), JailerError> {
 // pid namespace, we will call clone with a NULL stack pointer. We can do this because we will
 // not use the CLONE_VM flag, this will result with the original stack replicated, in a similar
 // manner to the fork syscall. The libc wrapper prevents use of a NULL stack pointer, so we will
-// call the syscall directly.
-fn clone(child_stack: *mut libc::c_void, flags: libc::c_int) -> Result<libc::c_int, JailerError> {
+// call the syscall directly. With CLONE_PIDFD, the pidfd of the child is stored in `parent_tid`.
+fn clone(
+    child_stack: *mut libc::c_void,
+    flags: libc::c_int,
+    parent_tid: *mut libc::c_int,
+) -> Result<libc::c_int, JailerError> {
     // Clone parameters order is different between x86_64 and aarch64.
     #[cfg(target_arch = "x86_64")]
     // SAFETY: This is safe because we are using a library function with valid parameters.
     return SyscallReturnCode(unsafe {
-        libc::syscall(libc::SYS_clone, flags, child_stack, 0, 0, 0) as libc::c_int
+        libc::syscall(libc::SYS_clone, flags, child_stack, parent_tid, 0, 0) as libc::c_int
     })
     .into_result()
     .map_err(JailerError::Clone);
     #[cfg(target_arch = "aarch64")]
     // SAFETY: This is safe because we are using a library function with valid parameters.
     return SyscallReturnCode(unsafe {
-        libc::syscall(libc::SYS_clone, flags, child_stack, 0, 0, 0) as libc::c_int
+        libc::syscall(libc::SYS_clone, flags, child_stack, parent_tid, 0, 0) as libc::c_int
     })
     .into_result()
     .map_err(JailerError::Clone);
@@ -90,17 +103,28 @@ pub struct Env {
     id: String,
     chroot_dir: PathBuf,
     exec_file_path: PathBuf,
+    chroot_files: Vec<ChrootFile>,
     uid: u32,
     gid: u32,
     netns: Option<String>,
+    new_netns: bool,
+    taps: Vec<TapConfig>,
+    veths: Vec<VethConfig>,
     daemonize: bool,
     new_pid_ns: bool,
+    supervise: bool,
+    exit_status_fd: Option<RawFd>,
+    inherited_fds: Vec<RawFd>,
+    userns: Option<UserNamespace>,
     start_time_us: u64,
     start_time_cpu_us: u64,
     jailer_cpu_time_us: u64,
     extra_args: Vec<String>,
     cgroups: Vec<Box<dyn Cgroup>>,
+    cpu_max_path: Option<PathBuf>,
+    cpu_max_file: Option<File>,
     resource_limits: ResourceLimits,
+    scheduling: Scheduling,
 }
 
 impl fmt::Debug for Env {
@@ -109,11 +133,19 @@ impl fmt::Debug for Env {
             .field("id", &self.id)
             .field("chroot_dir", &self.chroot_dir)
             .field("exec_file_path", &self.exec_file_path)
+            .field("chroot_files", &self.chroot_files)
             .field("uid", &self.uid)
             .field("gid", &self.gid)
             .field("netns", &self.netns)
+            .field("new_netns", &self.new_netns)
+            .field("taps", &self.taps)
+            .field("veths", &self.veths)
             .field("daemonize", &self.daemonize)
             .field("new_pid_ns", &self.new_pid_ns)
+            .field("supervise", &self.supervise)
+            .field("exit_status_fd", &self.exit_status_fd)
+            .field("inherited_fds", &self.inherited_fds)
+            .field("userns", &self.userns)
             .field("start_time_us", &self.start_time_us)
             .field("jailer_cpu_time_us", &self.jailer_cpu_time_us)
             .field("extra_args", &self.extra_args)
@@ -125,7 +157,10 @@ impl fmt::Debug for Env {
                     .map(|b| b as *const _)
                     .collect::<Vec<_>>(),
             )
+            .field("cpu_max_path", &self.cpu_max_path)
+            .field("cpu_max_file", &self.cpu_max_file)
             .field("resource_limits", &self.resource_limits)
+            .field("scheduling", &self.scheduling)
             .finish()
     }
 }
@@ -163,6 +198,11 @@ impl Env {
         chroot_dir.push(id);
         chroot_dir.push("root");
 
+        let chroot_files = match arguments.single_value("chroot-files") {
+            Some(path) => ChrootFile::parse_list(Path::new(path))?,
+            None => Vec::new(),
+        };
+
         let uid_str = arguments
             .single_value("uid")
             .ok_or_else(|| JailerError::ArgumentParsing(MissingValue("uid".to_string())))?;
@@ -179,10 +219,59 @@ impl Env {
 
         let netns = arguments.single_value("netns").cloned();
 
+        let new_netns = arguments.flag_present("new-netns");
+
+        let taps = arguments
+            .multiple_values("tap")
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| TapConfig::parse(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let veths = arguments
+            .multiple_values("veth")
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| VethConfig::parse(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let daemonize = arguments.flag_present("daemonize");
 
         let new_pid_ns = arguments.flag_present("new-pid-ns");
 
+        let supervise = arguments.flag_present("supervise");
+
+        let exit_status_fd = arguments
+            .single_value("exit-status-fd")
+            .map(|value| parse_inherited_fd(value))
+            .transpose()?;
+
+        let inherited_fds = arguments
+            .multiple_values("inherit-fd")
+            .unwrap_or_default()
+            .iter()
+            .map(|value| parse_inherited_fd(value))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let userns = if arguments.flag_present("userns") {
+            let parse_id_maps = |arg| {
+                arguments
+                    .multiple_values(arg)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|map| IdMap::parse(map))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            Some(UserNamespace::new(
+                uid,
+                gid,
+                parse_id_maps("uid-map")?,
+                parse_id_maps("gid-map")?,
+            )?)
+        } else {
+            None
+        };
+
         // Optional arguments.
         let mut cgroups: Vec<Box<dyn Cgroup>> = Vec::new();
         let parent_cgroup = match arguments.single_value("parent-cgroup") {
@@ -230,26 +319,69 @@ impl Env {
             }
         }
 
+        // Resource controller limits with dedicated arguments, only available on cgroup v2.
+        let delegate_cpu_max = arguments.flag_present("delegate-cpu-max");
+        let mut cpu_max_path = None;
+        for (arg, file, parse) in CGROUP_V2_LIMITS {
+            let values = match arguments.multiple_values(arg) {
+                Some(values) => values,
+                None => match arguments.single_value(arg) {
+                    Some(value) => std::slice::from_ref(value),
+                    None => continue,
+                },
+            };
+            if cgroup_ver != 2 {
+                return Err(JailerError::CgroupLimitVersion(arg.to_string()));
+            }
+
+            let builder = cgroup_builder.get_or_insert(CgroupBuilder::new(cgroup_ver)?);
+            for value in values {
+                let value = parse(value)
+                    .ok_or_else(|| JailerError::CgroupLimit(arg.to_string(), value.to_string()))?;
+                let cgroup = builder.new_cgroup(file.to_string(), value, id, parent_cgroup)?;
+                if delegate_cpu_max && arg == "cpu-max" {
+                    cpu_max_path = Some(cgroup.file_path());
+                }
+                cgroups.push(cgroup);
+            }
+        }
+
         let mut resource_limits = ResourceLimits::default();
         if let Some(args) = arguments.multiple_values("resource-limit") {
             Env::parse_resource_limits(&mut resource_limits, args)?;
         }
 
+        let scheduling = Scheduling::parse(
+            arguments.single_value("sched-policy").map(String::as_str),
+            arguments.single_value("nice").map(String::as_str),
+        )?;
+
         Ok(Env {
             id: id.to_owned(),
             chroot_dir,
             exec_file_path,
+            chroot_files,
             uid,
             gid,
             netns,
+            new_netns,
+            taps,
+            veths,
             daemonize,
             new_pid_ns,
+            supervise,
+            exit_status_fd,
+            inherited_fds,
+            userns,
             start_time_us,
             start_time_cpu_us,
             jailer_cpu_time_us: 0,
             extra_args: arguments.extra_args(),
             cgroups,
+            cpu_max_path,
+            cpu_max_file: None,
             resource_limits,
+            scheduling,
         })
     }
 
@@ -303,6 +435,7 @@ impl Env {
             match name {
                 FSIZE_ARG => resource_limits.set_file_size(limit_value),
                 NO_FILE_ARG => resource_limits.set_no_file(limit_value),
+                MEMLOCK_ARG => resource_limits.set_memlock(limit_value),
                 _ => return Err(JailerError::ResLimitArgument(name.to_string())),
             }
         }
@@ -317,7 +450,11 @@ impl Env {
         // Duplicate the current process. The child process will belong to the previously created
         // PID namespace. The current process will not be moved into the newly created namespace,
         // but its first child will assume the role of init(1) in the new namespace.
-        let pid = clone(std::ptr::null_mut(), libc::CLONE_NEWPID)?;
+        let pid = clone(
+            std::ptr::null_mut(),
+            libc::CLONE_NEWPID,
+            std::ptr::null_mut(),
+        )?;
         match pid {
             0 => {
                 // Reset process start time.
@@ -335,6 +472,53 @@ impl Env {
         }
     }
 
+    fn exec_supervised(&mut self, chroot_exec_file: PathBuf) -> Result<(), JailerError> {
+        // Compute jailer's total CPU time up to the current time.
+        self.jailer_cpu_time_us =
+            utils::time::get_time_us(utils::time::ClockType::ProcessCpu) - self.start_time_cpu_us;
+
+        // Keep the signals to forward pending until the supervisor listens to them, as they
+        // could otherwise terminate the jailer right after the jailed process is started.
+        let old_signal_mask = supervisor::block_forwarded_signals()?;
+
+        let mut flags = CLONE_PIDFD;
+        if self.new_pid_ns {
+            flags |= libc::CLONE_NEWPID;
+        }
+        let mut pidfd: libc::c_int = -1;
+        let pid = clone(std::ptr::null_mut(), flags, &mut pidfd)?;
+        match pid {
+            0 => {
+                // Reset process start time.
+                self.start_time_cpu_us = 0;
+
+                // The exit status fd is only meant for the supervisor.
+                if let Some(fd) = self.exit_status_fd {
+                    // SAFETY: This is safe because the fd was inherited and is not used by the
+                    // child process.
+                    unsafe { libc::close(fd) };
+                }
+                supervisor::restore_signal_mask(&old_signal_mask)?;
+                Err(JailerError::Exec(self.exec_command(chroot_exec_file)))
+            }
+            child_pid => {
+                // SAFETY: This is safe because clone() just created the pidfd, which is
+                // exclusively owned by the file.
+                let pidfd = unsafe { File::from_raw_fd(pidfd) };
+                // SAFETY: This is safe because the fd was inherited from the parent process for
+                // the supervisor's exclusive use.
+                let exit_status = self
+                    .exit_status_fd
+                    .map(|fd| unsafe { File::from_raw_fd(fd) });
+
+                self.save_exec_file_pid(child_pid, chroot_exec_file)?;
+                supervisor::drop_privileges(self.uid(), self.gid())?;
+                let status = supervisor::supervise(&pidfd, exit_status)?;
+                std::process::exit(status.code())
+            }
+        }
+    }
+
     fn save_exec_file_pid(
         &mut self,
         pid: i32,
@@ -454,8 +638,27 @@ impl Env {
             .map_err(JailerError::SetNetNs)
     }
 
+    fn open_inherited(path: &Path) -> Result<File, JailerError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|err| JailerError::FileOpen(path.to_path_buf(), err))?;
+        // SAFETY: Safe because the fd is valid and owned by `file`.
+        SyscallReturnCode(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, 0) })
+            .into_empty_result()
+            .map_err(|err| JailerError::UnsetCloexec(path.to_path_buf(), err))?;
+        Ok(file)
+    }
+
     fn exec_command(&self, chroot_exec_file: PathBuf) -> io::Error {
-        Command::new(chroot_exec_file)
+        let mut command = Command::new(chroot_exec_file);
+        if let Some(ref cpu_max_file) = self.cpu_max_file {
+            command.args(["--cpu-max-fd", &cpu_max_file.as_raw_fd().to_string()]);
+        }
+        for fd in &self.inherited_fds {
+            command.args(["--inherit-fd", &fd.to_string()]);
+        }
+        command
             .args(["--id", &self.id])
             .args(["--start-time-us", &self.start_time_us.to_string()])
             .args(["--start-time-cpu-us", &self.start_time_cpu_us.to_string()])
@@ -559,17 +762,53 @@ impl Env {
     }
 
     pub fn run(mut self) -> Result<(), JailerError> {
+        // Enter the user namespace first, as the rest of the setup relies on the capabilities
+        // held inside it.
+        if let Some(ref userns) = self.userns {
+            userns.enter()?;
+        }
+
         let exec_file_name = self.copy_exec_to_chroot()?;
         let chroot_exec_file = PathBuf::from("/").join(exec_file_name);
 
-        // Join the specified network namespace, if applicable.
+        // Place the files the microVM needs inside the jail, while the host paths are still
+        // reachable.
+        for file in &self.chroot_files {
+            file.install(&self.chroot_dir, self.uid, self.gid)?;
+        }
+
+        // The host side of the veth pairs is placed in the network namespace the jailer was
+        // started in.
+        let host_netns = if self.veths.is_empty() {
+            None
+        } else {
+            Some(HostNetNs::open()?)
+        };
+
+        // Join the specified network namespace, or create a new one, if applicable.
         if let Some(ref path) = self.netns {
             Env::join_netns(path)?;
+        } else if self.new_netns {
+            unshare_netns()?;
+        }
+
+        // Create the tap devices in the network namespace of the microVM, while we still have
+        // the privileges to do so.
+        for tap in &self.taps {
+            tap.create(self.uid, self.gid)?;
+        }
+        if let Some(ref host_netns) = host_netns {
+            for veth in &self.veths {
+                veth.create(host_netns)?;
+            }
         }
 
         // Set limits on resources.
         self.resource_limits.install()?;
 
+        // Set the scheduling settings while we still have the privileges to raise them.
+        self.scheduling.install()?;
+
         // We have to setup cgroups at this point, because we can't do it anymore after chrooting.
         // cgroups are iterated two times as some cgroups may require others (e.g cpuset requires
         // cpuset.mems and cpuset.cpus) to be set before attaching any pid.
@@ -583,6 +822,12 @@ impl Env {
             cgroup.attach_pid().unwrap();
         }
 
+        // The cpu.max file is opened while the cgroup filesystem is still reachable and left
+        // open across exec, so that the jailed process can update its CPU bandwidth.
+        if let Some(ref path) = self.cpu_max_path {
+            self.cpu_max_file = Some(Env::open_inherited(path)?);
+        }
+
         // If daemonization was requested, open /dev/null before chrooting.
         let dev_null = if self.daemonize {
             Some(File::open("/dev/null").map_err(JailerError::OpenDevNull)?)
@@ -594,8 +839,14 @@ impl Env {
         #[cfg(target_arch = "aarch64")]
         self.copy_midr_el1_info()?;
 
-        // Jail self.
-        chroot(self.chroot_dir())?;
+        // Jail self. Device nodes cannot be created inside a user namespace, so the host devices
+        // are bind mounted inside the jail instead.
+        let bind_devices: &[&str] = if self.userns.is_some() {
+            &[DEV_KVM, DEV_NET_TUN, DEV_URANDOM]
+        } else {
+            &[]
+        };
+        chroot(self.chroot_dir(), bind_devices)?;
 
         // This will not only create necessary directories, but will also change ownership
         // for all of them.
@@ -603,28 +854,31 @@ impl Env {
             .iter()
             .try_for_each(|f| self.setup_jailed_folder(f))?;
 
-        // Here we are creating the /dev/kvm and /dev/net/tun devices inside the jailer.
-        // Following commands can be translated into bash like this:
-        // $: mkdir -p $chroot_dir/dev/net
-        // $: dev_net_tun_path={$chroot_dir}/"tun"
-        // $: mknod $dev_net_tun_path c 10 200
-        // www.kernel.org/doc/Documentation/networking/tuntap.txt specifies 10 and 200 as the major
-        // and minor for the /dev/net/tun device.
-        self.mknod_and_own_dev(DEV_NET_TUN_WITH_NUL, DEV_NET_TUN_MAJOR, DEV_NET_TUN_MINOR)?;
-        // Do the same for /dev/kvm with (major, minor) = (10, 232).
-        self.mknod_and_own_dev(DEV_KVM_WITH_NUL, DEV_KVM_MAJOR, DEV_KVM_MINOR)?;
-        // And for /dev/urandom with (major, minor) = (1, 9).
-        // If the device is not accessible on the host, output a warning to inform user that MMDS
-        // version 2 will not be available to use.
-        let _ = self
-            .mknod_and_own_dev(DEV_URANDOM_WITH_NUL, DEV_URANDOM_MAJOR, DEV_URANDOM_MINOR)
-            .map_err(|err| {
-                println!(
-                    "Warning! Could not create /dev/urandom device inside jailer: {}.",
-                    err
-                );
-                println!("MMDS version 2 will not be available to use.");
-            });
+        // Unless they were bind mounted from the host, create the devices inside the jail.
+        if self.userns.is_none() {
+            // Here we are creating the /dev/kvm and /dev/net/tun devices inside the jailer.
+            // Following commands can be translated into bash like this:
+            // $: mkdir -p $chroot_dir/dev/net
+            // $: dev_net_tun_path={$chroot_dir}/"tun"
+            // $: mknod $dev_net_tun_path c 10 200
+            // www.kernel.org/doc/Documentation/networking/tuntap.txt specifies 10 and 200 as the
+            // major and minor for the /dev/net/tun device.
+            self.mknod_and_own_dev(DEV_NET_TUN_WITH_NUL, DEV_NET_TUN_MAJOR, DEV_NET_TUN_MINOR)?;
+            // Do the same for /dev/kvm with (major, minor) = (10, 232).
+            self.mknod_and_own_dev(DEV_KVM_WITH_NUL, DEV_KVM_MAJOR, DEV_KVM_MINOR)?;
+            // And for /dev/urandom with (major, minor) = (1, 9).
+            // If the device is not accessible on the host, output a warning to inform user that
+            // MMDS version 2 will not be available to use.
+            let _ = self
+                .mknod_and_own_dev(DEV_URANDOM_WITH_NUL, DEV_URANDOM_MAJOR, DEV_URANDOM_MINOR)
+                .map_err(|err| {
+                    println!(
+                        "Warning! Could not create /dev/urandom device inside jailer: {}.",
+                        err
+                    );
+                    println!("MMDS version 2 will not be available to use.");
+                });
+        }
 
         // Daemonize before exec, if so required (when the dev_null variable != None).
         if let Some(dev_null) = dev_null {
@@ -640,8 +894,10 @@ impl Env {
             dup2(dev_null.as_raw_fd(), STDERR_FILENO)?;
         }
 
-        // If specified, exec the provided binary into a new PID namespace.
-        if self.new_pid_ns {
+        // If specified, keep supervising the provided binary, or exec it into a new PID namespace.
+        if self.supervise {
+            self.exec_supervised(chroot_exec_file)
+        } else if self.new_pid_ns {
             self.exec_into_new_pid_ns(chroot_exec_file)
         } else {
             Err(JailerError::Exec(self.exec_command(chroot_exec_file)))
@@ -1210,6 +1466,318 @@ mod tests {
         assert!(Env::new(&args, 0, 0).is_ok());
     }
 
+    #[test]
+    fn test_cgroup_v2_limits_parsing() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            cgroups: Vec::new(),
+            ..ArgVals::new()
+        };
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v2_mounts().is_ok());
+
+        let limit_args = |limits: &[&str], version: &str| {
+            let mut arg_vec = make_args(&arg_vals);
+            arg_vec.extend(["--cgroup-version", version].map(String::from));
+            arg_vec.extend(limits.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).unwrap();
+            args
+        };
+
+        let args = limit_args(
+            &[
+                "--cpu-max",
+                "50000,100000",
+                "--memory-max",
+                "512M",
+                "--pids-max",
+                "max",
+                "--io-max",
+                "8:0,rbps=1048576",
+                "--io-max",
+                "8:16,wiops=120",
+            ],
+            "2",
+        );
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.cgroups.len(), 5);
+
+        // Invalid values are rejected.
+        let args = limit_args(&["--memory-max", "512MiB"], "2");
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::CgroupLimit(arg, _)) if arg == "memory-max"
+        ));
+
+        // The cpu.max file is only delegated when requested.
+        assert!(env.cpu_max_path.is_none());
+        let args = limit_args(&["--cpu-max", "max", "--delegate-cpu-max"], "2");
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert!(env.cpu_max_path.unwrap().ends_with(
+            "pseudo_firecracker_exec_file/bd65600d-8669-4903-8a14-af88203add38/cpu.max"
+        ));
+        // Delegating the cpu.max file requires a CPU bandwidth limit.
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.extend(["--cgroup-version", "2", "--delegate-cpu-max"].map(String::from));
+        assert!(args.parse(&arg_vec).is_err());
+
+        // The limits are only supported on cgroup v2.
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+        let args = limit_args(&["--pids-max", "64"], "1");
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::CgroupLimitVersion(arg)) if arg == "pids-max"
+        ));
+    }
+
+    #[test]
+    fn test_network_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let network_args = |arg_vals: &ArgVals, extra: &[&str]| {
+            let mut arg_vec = make_args(arg_vals);
+            arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).map(|()| args)
+        };
+
+        let arg_vals = ArgVals {
+            netns: None,
+            ..ArgVals::new()
+        };
+        let args = network_args(
+            &arg_vals,
+            &[
+                "--new-netns",
+                "--tap",
+                "tap0,172.16.0.1/30",
+                "--tap",
+                "tap1",
+                "--veth",
+                "veth0,fc-veth0,10.0.0.2/24,bridge=br0",
+            ],
+        )
+        .unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert!(env.new_netns);
+        assert_eq!(
+            env.taps,
+            vec![
+                TapConfig::parse("tap0,172.16.0.1/30").unwrap(),
+                TapConfig::parse("tap1").unwrap()
+            ]
+        );
+        assert_eq!(
+            env.veths,
+            vec![VethConfig::parse("veth0,fc-veth0,10.0.0.2/24,bridge=br0").unwrap()]
+        );
+
+        // Invalid tap arguments are rejected.
+        let args = network_args(&arg_vals, &["--tap", "tap0,172.16.0.1"]).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::TapConfig(_))
+        ));
+        let args = network_args(&arg_vals, &["--veth", "veth0"]).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::VethConfig(_))
+        ));
+
+        // The host side of a veth pair cannot be created from a user namespace.
+        assert!(network_args(&arg_vals, &["--userns", "--veth", "veth0,fc-veth0"]).is_err());
+
+        // A new network namespace cannot be created when joining an existing one.
+        assert!(network_args(&ArgVals::new(), &["--new-netns"]).is_err());
+    }
+
+    #[test]
+    fn test_chroot_files_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let chroot_files_args = |list: &Path| {
+            let mut arg_vec = make_args(&ArgVals::new());
+            arg_vec.push("--chroot-files".to_string());
+            arg_vec.push(list.to_str().unwrap().to_string());
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).unwrap();
+            args
+        };
+
+        let env = create_env();
+        assert!(env.chroot_files.is_empty());
+
+        let list = TempFile::new().unwrap();
+        list.as_file()
+            .write_all(
+                b"hardlink /srv/vmlinux.bin
+copy /srv/rootfs.ext4 /rootfs
+",
+            )
+            .unwrap();
+        let env = Env::new(&chroot_files_args(list.as_path()), 0, 0).unwrap();
+        assert_eq!(
+            env.chroot_files,
+            vec![
+                ChrootFile::parse("hardlink /srv/vmlinux.bin").unwrap(),
+                ChrootFile::parse("copy /srv/rootfs.ext4 /rootfs").unwrap(),
+            ]
+        );
+
+        // Invalid entries are rejected.
+        list.as_file()
+            .write_all(
+                b"symlink /srv/initrd
+",
+            )
+            .unwrap();
+        assert!(matches!(
+            Env::new(&chroot_files_args(list.as_path()), 0, 0),
+            Err(JailerError::ChrootFileEntry(_))
+        ));
+    }
+
+    #[test]
+    fn test_supervise_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let supervise_args = |extra: &[&str]| {
+            let mut arg_vec = make_args(&ArgVals::new());
+            arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).map(|()| args)
+        };
+
+        let env = create_env();
+        assert!(!env.supervise);
+        assert!(env.exit_status_fd.is_none());
+
+        let env = Env::new(&supervise_args(&["--supervise"]).unwrap(), 0, 0).unwrap();
+        assert!(env.supervise);
+        assert!(env.exit_status_fd.is_none());
+
+        let args = supervise_args(&["--supervise", "--exit-status-fd", "3"]).unwrap();
+        assert_eq!(Env::new(&args, 0, 0).unwrap().exit_status_fd, Some(3));
+
+        let args = supervise_args(&["--supervise", "--exit-status-fd", "1"]).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::InheritedFd(_))
+        ));
+
+        // The exit status can only be reported by the supervisor.
+        assert!(supervise_args(&["--exit-status-fd", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_inherited_fds_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let inherit_fd_args = |extra: &[&str]| {
+            let mut arg_vec = make_args(&ArgVals::new());
+            arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).unwrap();
+            Env::new(&args, 0, 0)
+        };
+
+        assert!(create_env().inherited_fds.is_empty());
+
+        // The inherited fds are passed on to Firecracker in the given order.
+        let env = inherit_fd_args(&["--inherit-fd", "5", "--inherit-fd", "3"]).unwrap();
+        assert_eq!(env.inherited_fds, vec![5, 3]);
+
+        assert!(matches!(
+            inherit_fd_args(&["--inherit-fd", "0"]),
+            Err(JailerError::InheritedFd(_))
+        ));
+    }
+
+    #[test]
+    fn test_scheduling_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let scheduling_args = |extra: &[&str]| {
+            let mut arg_vec = make_args(&ArgVals::new());
+            arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).unwrap();
+            Env::new(&args, 0, 0)
+        };
+
+        assert_eq!(create_env().scheduling, Scheduling::default());
+
+        let env = scheduling_args(&["--sched-policy", "fifo,10", "--nice", "-5"]).unwrap();
+        assert_eq!(
+            env.scheduling,
+            Scheduling::parse(Some("fifo,10"), Some("-5")).unwrap()
+        );
+
+        assert!(matches!(
+            scheduling_args(&["--sched-policy", "fifo"]),
+            Err(JailerError::SchedPolicy(_))
+        ));
+        assert!(matches!(
+            scheduling_args(&["--nice", "20"]),
+            Err(JailerError::Nice(_))
+        ));
+    }
+
+    #[test]
+    fn test_userns_args_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let userns_args = |extra: &[&str]| {
+            let mut arg_vec = make_args(&ArgVals::new());
+            arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&arg_vec).map(|()| args)
+        };
+
+        let env = Env::new(&userns_args(&[]).unwrap(), 0, 0).unwrap();
+        assert!(env.userns.is_none());
+
+        let env = Env::new(&userns_args(&["--userns"]).unwrap(), 0, 0).unwrap();
+        assert_eq!(
+            env.userns,
+            Some(UserNamespace::new(1001, 1002, Vec::new(), Vec::new()).unwrap())
+        );
+
+        let args = userns_args(&[
+            "--userns",
+            "--uid-map",
+            "0:100000:65536",
+            "--gid-map",
+            "1002:1000:1",
+        ])
+        .unwrap();
+        assert!(Env::new(&args, 0, 0).unwrap().userns.is_some());
+
+        // The jailed uid must be mapped.
+        let args = userns_args(&["--userns", "--uid-map", "0:100000:1000"]).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::UserNsUnmappedId(_))
+        ));
+
+        // Mappings are only allowed together with a user namespace.
+        assert!(userns_args(&["--uid-map", "0:100000:65536"]).is_err());
+    }
+
     #[test]
     fn test_parse_resource_limits() {
         let mut resource_limits = ResourceLimits::default();
@@ -1268,7 +1836,7 @@ mod tests {
         }
 
         // Check valid cases
-        let resources = [FSIZE_ARG, NO_FILE_ARG];
+        let resources = [FSIZE_ARG, NO_FILE_ARG, MEMLOCK_ARG];
         for resource in resources.iter() {
             let arg = vec![resource.to_string() + "=4098"];
             Env::parse_resource_limits(&mut resource_limits, &arg).unwrap();
@@ -3,11 +3,11 @@
 
 use std::ffi::{CString, NulError, OsString};
 use std::fmt::{Debug, Display};
-use std::os::unix::prelude::AsRawFd;
+use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::{env as p_env, fs, io};
 
-use utils::arg_parser::{ArgParser, Argument, Error as ParsingError};
+use utils::arg_parser::{ArgParser, Argument, Arguments, Error as ParsingError};
 use utils::syscall::SyscallReturnCode;
 use utils::validators;
 
@@ -15,8 +15,13 @@ use crate::env::Env;
 
 mod cgroup;
 mod chroot;
+mod chroot_files;
 mod env;
+mod network;
 mod resource_limits;
+mod scheduling;
+mod supervisor;
+mod userns;
 
 const JAILER_VERSION: &str = env!("FIRECRACKER_VERSION");
 
@@ -44,12 +49,18 @@ pub enum JailerError {
     CgroupInvalidVersion(String),
     #[error("Parent cgroup path is invalid. Path should not be absolute or contain '..' or '.'")]
     CgroupInvalidParentPath(),
+    #[error("Invalid value for --{0}: {1}")]
+    CgroupLimit(String, String),
+    #[error("--{0} requires cgroup version 2")]
+    CgroupLimitVersion(String),
     #[error("Failed to change owner for {0:?}: {1}")]
     ChangeFileOwner(PathBuf, io::Error),
     #[error("Failed to chdir into chroot directory: {0}")]
     ChdirNewRoot(io::Error),
     #[error("Failed to change permissions on {0:?}: {1}")]
     Chmod(PathBuf, io::Error),
+    #[error("Invalid chroot file entry: {0}")]
+    ChrootFileEntry(String),
     #[error("Failed cloning into a new child process: {0}")]
     Clone(io::Error),
     #[error("Failed to close netns fd: {0}")]
@@ -58,18 +69,30 @@ pub enum JailerError {
     CloseDevNullFd(io::Error),
     #[error("Failed to call close range syscall: {0}")]
     CloseRange(io::Error),
+    #[error("Failed to configure tap device {0}: {1}")]
+    ConfigureTap(String, io::Error),
+    #[error("Failed to configure veth pair {0}: {1}")]
+    ConfigureVeth(String, io::Error),
     #[error("{}", format!("Failed to copy {:?} to {:?}: {}", .0, .1, .2).replace('\"', ""))]
     Copy(PathBuf, PathBuf, io::Error),
     #[error("{}", format!("Failed to create directory {:?}: {}", .0, .1).replace('\"', ""))]
     CreateDir(PathBuf, io::Error),
+    #[error("Failed to create tap device {0}: {1}")]
+    CreateTap(String, io::Error),
+    #[error("Failed to create veth pair {0}: {1}")]
+    CreateVeth(String, io::Error),
     #[error("Encountered interior \\0 while parsing a string")]
     CStringParsing(NulError),
     #[error("Failed to open directory {0}: {1}")]
     DirOpen(String, String),
+    #[error("Failed to drop the privileges of the supervisor: {0}")]
+    DropPrivileges(io::Error),
     #[error("Failed to duplicate fd: {0}")]
     Dup2(io::Error),
     #[error("Failed to exec into Firecracker: {0}")]
     Exec(io::Error),
+    #[error("Failed to report the exit status of the jailed process: {0}")]
+    ExitStatusWrite(io::Error),
     #[error(
         "Invalid filename. The filename of `--exec-file` option must contain \"firecracker\": {0}"
     )]
@@ -84,6 +107,12 @@ pub enum JailerError {
     GetOldFdFlags(io::Error),
     #[error("Invalid gid: {0}")]
     Gid(String),
+    #[error("{}", format!("Failed to hard link {:?} to {:?}: {}", .0, .1, .2).replace('\"', ""))]
+    HardLink(PathBuf, PathBuf, io::Error),
+    #[error("Failed to open the host network namespace: {0}")]
+    HostNetNs(io::Error),
+    #[error("Invalid inherited file descriptor: {0}")]
+    InheritedFd(String),
     #[error("Invalid instance ID: {0}")]
     InvalidInstanceId(validators::Error),
     #[error("{}", format!("File {:?} doesn't have a parent", .0).replace('\"', ""))]
@@ -94,8 +123,12 @@ pub enum JailerError {
     MknodDev(io::Error, &'static str),
     #[error("Failed to bind mount the jail root directory: {0}")]
     MountBind(io::Error),
+    #[error("Failed to bind mount {1} inside the jail: {0}")]
+    MountBindDevice(io::Error, String),
     #[error("Failed to change the propagation type to slave: {0}")]
     MountPropagationSlave(io::Error),
+    #[error("Invalid nice value: {0}")]
+    Nice(String),
     #[error("{}", format!("{:?} is not a file", .0).replace('\"', ""))]
     NotAFile(PathBuf),
     #[error("{}", format!("{:?} is not a directory", .0).replace('\"', ""))]
@@ -112,6 +145,8 @@ pub enum JailerError {
     ReadToString(PathBuf, io::Error),
     #[error("Regex failed: {0:?}")]
     RegEx(regex::Error),
+    #[error("{}", format!("Failed to remove file {:?}: {}", .0, .1).replace('\"', ""))]
+    RemoveFile(PathBuf, io::Error),
     #[error("Invalid resource argument: {0}")]
     ResLimitArgument(String),
     #[error("Invalid format for resources limits: {0}")]
@@ -120,14 +155,24 @@ pub enum JailerError {
     ResLimitValue(String, String),
     #[error("Failed to remove old jail root directory: {0}")]
     RmOldRootDir(io::Error),
+    #[error("Invalid scheduling policy: {0}")]
+    SchedPolicy(String),
     #[error("Failed to change current directory: {0}")]
     SetCurrentDir(io::Error),
     #[error("Failed to join network namespace: netns: {0}")]
     SetNetNs(io::Error),
+    #[error("Failed to set the nice value: {0}")]
+    SetPriority(io::Error),
     #[error("Failed to set limit for resource: {0}")]
     Setrlimit(String),
+    #[error("Failed to set the scheduling policy: {0}")]
+    SetScheduler(io::Error),
     #[error("Failed to daemonize: setsid: {0}")]
     SetSid(io::Error),
+    #[error("Failed to forward signals to the jailed process: {0}")]
+    SignalForwarding(io::Error),
+    #[error("Invalid tap device argument: {0}")]
+    TapConfig(String),
     #[error("Invalid uid: {0}")]
     Uid(String),
     #[error("Failed to unmount the old jail root: {0}")]
@@ -136,10 +181,22 @@ pub enum JailerError {
     UnexpectedListenerFd(i32),
     #[error("Failed to unshare into new mount namespace: {0}")]
     UnshareNewNs(io::Error),
-    #[error("Failed to unset the O_CLOEXEC flag on the socket fd: {0}")]
-    UnsetCloexec(io::Error),
+    #[error("Failed to set up a new network namespace: {0}")]
+    UnshareNetNs(io::Error),
+    #[error("Failed to unshare into new user namespace: {0}")]
+    UnshareUserNs(io::Error),
+    #[error("Invalid user namespace id mapping: {0}")]
+    UserNsIdMap(String),
+    #[error("The {0} of the jailed process is not mapped in the user namespace")]
+    UserNsUnmappedId(String),
+    #[error("Failed to unset the O_CLOEXEC flag on {0:?}: {1}")]
+    UnsetCloexec(PathBuf, io::Error),
     #[error("Slice contains invalid UTF-8 data : {0}")]
     UTF8Parsing(std::str::Utf8Error),
+    #[error("Invalid veth pair argument: {0}")]
+    VethConfig(String),
+    #[error("Failed to wait for the jailed process: {0}")]
+    WaitPidFd(io::Error),
     #[error("{}", format!("Failed to write to {:?}: {}", .0, .1).replace('\"', ""))]
     Write(PathBuf, io::Error),
 }
@@ -178,20 +235,98 @@ pub fn build_arg_parser() -> ArgParser<'static> {
                 .default_value("/srv/jailer")
                 .help("The base folder where chroot jails are located."),
         )
+        .arg(Argument::new("chroot-files").takes_value(true).help(
+            "Path to a file listing the files to place inside the jail before chrooting, such as \
+             the kernel image, initrd, drives or snapshot files. Each line follows this format: \
+             <policy> <host_path> [<jail_path>], where policy is one of hardlink, copy and \
+             hardlink-or-copy. The files are owned by uid and gid once inside the jail.",
+        ))
         .arg(
             Argument::new("netns")
                 .takes_value(true)
                 .help("Path to the network namespace this microVM should join."),
         )
+        .arg(
+            Argument::new("new-netns")
+                .takes_value(false)
+                .forbids(vec!["netns"])
+                .help(
+                    "Run the microVM in a new network namespace, with the loopback interface up.",
+                ),
+        )
+        .arg(Argument::new("tap").allow_multiple(true).help(
+            "Tap device created by the jailer before dropping privileges, and owned by the jailed \
+             process. It must follow this format: <name>[,<ipv4_addr>/<prefix_len>] (e.g \
+             tap0,172.16.0.1/30). The address is assigned to the host side of the tap device, \
+             which is then brought up. This argument can be used multiple times to create \
+             multiple tap devices.",
+        ))
+        .arg(
+            Argument::new("veth")
+                .allow_multiple(true)
+                .forbids(vec!["userns"])
+                .help(
+                    "Veth pair created by the jailer between the network namespace of the \
+                     microVM and the one the jailer was started in. It must follow this format: \
+                     <name>,<host_name>[,<ipv4_addr>/<prefix_len>][,bridge=<host_bridge>] (e.g \
+                     veth0,fc-veth0,10.0.0.2/24,bridge=br0). The address is assigned to the \
+                     microVM side, and the host side is attached to the host bridge. Both sides \
+                     are then brought up. This argument can be used multiple times to create \
+                     multiple veth pairs.",
+                ),
+        )
         .arg(Argument::new("daemonize").takes_value(false).help(
             "Daemonize the jailer before exec, by invoking setsid(), and redirecting the standard \
              I/O file descriptors to /dev/null.",
         ))
+        .arg(Argument::new("userns").takes_value(false).help(
+            "Set up the jail inside a new user namespace, so that the jailer does not need to run \
+             as root. The devices are bind mounted from the host instead of being created inside \
+             the jail.",
+        ))
+        .arg(
+            Argument::new("uid-map")
+                .allow_multiple(true)
+                .requires("userns")
+                .help(
+                    "User id mapping of the user namespace, following this format: \
+                     <inside_id>:<outside_id>:<count>. This argument can be used multiple times \
+                     to map multiple ranges. Defaults to mapping uid onto the effective user id \
+                     of the jailer.",
+                ),
+        )
+        .arg(
+            Argument::new("gid-map")
+                .allow_multiple(true)
+                .requires("userns")
+                .help(
+                    "Group id mapping of the user namespace, following this format: \
+                     <inside_id>:<outside_id>:<count>. This argument can be used multiple times \
+                     to map multiple ranges. Defaults to mapping gid onto the effective group id \
+                     of the jailer.",
+                ),
+        )
         .arg(
             Argument::new("new-pid-ns")
                 .takes_value(false)
                 .help("Exec into a new PID namespace."),
         )
+        .arg(Argument::new("supervise").takes_value(false).help(
+            "Keep the jailer running as the parent of the jailed process, instead of exec-ing \
+             into it. The jailer forwards the SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1 and \
+             SIGUSR2 signals it receives to the jailed process, and exits with its exit code once \
+             it terminates.",
+        ))
+        .arg(
+            Argument::new("exit-status-fd")
+                .takes_value(true)
+                .requires("supervise")
+                .help(
+                    "File descriptor inherited from the parent process, to which the jailer \
+                     writes how the jailed process terminated, either exit_code=<code> or \
+                     signal=<signo>.",
+                ),
+        )
         .arg(Argument::new("cgroup").allow_multiple(true).help(
             "Cgroup and value to be set by the jailer. It must follow this format: \
              <cgroup_file>=<value> (e.g cpu.shares=10). This argument can be used multiple times \
@@ -203,19 +338,61 @@ pub fn build_arg_parser() -> ArgParser<'static> {
              add multiple resource limits. Current available resource values are:\n\t\tfsize: The \
              maximum size in bytes for files created by the process.\n\t\tno-file: Specifies a \
              value one greater than the maximum file descriptor number that can be opened by this \
+             process.\n\t\tmemlock: The maximum size in bytes of the memory locked by the \
              process.",
         ))
+        .arg(Argument::new("sched-policy").takes_value(true).help(
+            "Scheduling policy inherited by all the threads of the jailed process, following this \
+             format: <policy>[,<priority>], where policy is one of other, batch, idle, fifo and \
+             rr. The fifo and rr real-time policies require a priority between 1 and 99 (e.g \
+             fifo,10).",
+        ))
+        .arg(Argument::new("nice").takes_value(true).help(
+            "Nice value inherited by all the threads of the jailed process, between -20 and 19.",
+        ))
         .arg(
             Argument::new("cgroup-version")
                 .takes_value(true)
                 .default_value("1")
                 .help("Select the cgroup version used by the jailer."),
         )
+        .arg(Argument::new("cpu-max").takes_value(true).help(
+            "CPU bandwidth limit written to cpu.max, following this format: \
+             <quota_us|max>[,<period_us>] (e.g 50000,100000). Requires cgroup version 2.",
+        ))
+        .arg(
+            Argument::new("delegate-cpu-max")
+                .takes_value(false)
+                .requires("cpu-max")
+                .help(
+                    "Pass the cpu.max file of the microVM cgroup to the jailed process with \
+                     --cpu-max-fd, so that it can update its CPU bandwidth from inside the jail.",
+                ),
+        )
+        .arg(Argument::new("memory-max").takes_value(true).help(
+            "Memory limit written to memory.max, in bytes, optionally suffixed with K, M, G or T \
+             (e.g 512M), or max. Requires cgroup version 2.",
+        ))
+        .arg(Argument::new("io-max").allow_multiple(true).help(
+            "Block device I/O limit written to io.max, following this format: \
+             <major>:<minor>,<key>=<value>[,<key>=<value>...], where key is one of rbps, wbps, \
+             riops and wiops (e.g 8:0,rbps=1048576,wiops=120). This argument can be used \
+             multiple times to limit multiple devices. Requires cgroup version 2.",
+        ))
+        .arg(Argument::new("pids-max").takes_value(true).help(
+            "Limit on the number of processes written to pids.max, or max. Requires cgroup \
+             version 2.",
+        ))
         .arg(
             Argument::new("parent-cgroup")
                 .takes_value(true)
                 .help("Parent cgroup in which the cgroup of this microvm will be placed."),
         )
+        .arg(Argument::new("inherit-fd").allow_multiple(true).help(
+            "File descriptor inherited from the parent process which is kept open for the jailed \
+             binary (e.g. a kernel image passed to Firecracker as /proc/self/fd/<fd>). This \
+             argument can be used multiple times to keep multiple file descriptors.",
+        ))
         .arg(
             Argument::new("version")
                 .takes_value(false)
@@ -244,14 +421,13 @@ pub fn readln_special<T: AsRef<Path> + Debug>(file_path: &T) -> Result<String, J
     Ok(line)
 }
 
-fn close_fds_by_close_range() -> Result<(), JailerError> {
-    // First try using the close_range syscall to close all open FDs in the range of 3..UINT_MAX
+fn close_range(first: libc::c_uint, last: libc::c_uint) -> Result<(), JailerError> {
     // SAFETY: if the syscall is not available then ENOSYS will be returned
     SyscallReturnCode(unsafe {
         libc::syscall(
             libc::SYS_close_range,
-            3,
-            libc::c_uint::MAX,
+            first,
+            last,
             libc::CLOSE_RANGE_UNSHARE,
         )
     } as libc::c_int)
@@ -259,7 +435,23 @@ fn close_fds_by_close_range() -> Result<(), JailerError> {
     .map_err(JailerError::CloseRange)
 }
 
-fn close_fds_by_reading_proc() -> Result<(), JailerError> {
+fn close_fds_by_close_range(keep_fds: &[RawFd]) -> Result<(), JailerError> {
+    // First try using the close_range syscall to close all open FDs in the range of 3..UINT_MAX,
+    // leaving out the ones which have to be kept. `keep_fds` is sorted and only holds FDs > 2.
+    let mut first: libc::c_uint = 3;
+    for &fd in keep_fds {
+        // The cast is safe because `keep_fds` only holds positive FDs.
+        #[allow(clippy::cast_sign_loss)]
+        let fd = fd as libc::c_uint;
+        if fd > first {
+            close_range(first, fd - 1)?;
+        }
+        first = fd + 1;
+    }
+    close_range(first, libc::c_uint::MAX)
+}
+
+fn close_fds_by_reading_proc(keep_fds: &[RawFd]) -> Result<(), JailerError> {
     // Calling this method means that close_range failed (we might be on kernel < 5.9).
     // We can't use std::fs::ReadDir here as under the hood we need access to the dirfd in order to
     // not close it twice
@@ -280,7 +472,7 @@ fn close_fds_by_reading_proc() -> Result<(), JailerError> {
 
         // If the entry is an INT entry, we go ahead and we treat it as an FD identifier.
         if let Ok(fd) = fd_str.parse::<i32>() {
-            if fd > 2 && fd != dirfd {
+            if fd > 2 && fd != dirfd && !keep_fds.contains(&fd) {
                 // SAFETY: Safe because close() cannot fail when passed a valid parameter.
                 unsafe { libc::close(fd) };
             }
@@ -289,27 +481,50 @@ fn close_fds_by_reading_proc() -> Result<(), JailerError> {
     Ok(())
 }
 
-// Closes all FDs other than 0 (STDIN), 1 (STDOUT) and 2 (STDERR)
-fn close_inherited_fds() -> Result<(), JailerError> {
+// Closes all FDs other than 0 (STDIN), 1 (STDOUT), 2 (STDERR) and the ones in `keep_fds`
+fn close_inherited_fds(keep_fds: &[RawFd]) -> Result<(), JailerError> {
     // The approach we take here is to firstly try to use the close_range syscall
     // which is available on kernels > 5.9.
     // We then fallback to using /proc/sef/fd to close open fds.
-    if close_fds_by_close_range().is_err() {
-        close_fds_by_reading_proc()?;
+    if close_fds_by_close_range(keep_fds).is_err() {
+        close_fds_by_reading_proc(keep_fds)?;
     }
     Ok(())
 }
 
-fn sanitize_process() -> Result<(), JailerError> {
+fn sanitize_process(keep_fds: &[RawFd]) -> Result<(), JailerError> {
     // First thing to do is make sure we don't keep any inherited FDs
-    // other that IN, OUT and ERR.
-    close_inherited_fds()?;
+    // other that IN, OUT, ERR and the ones explicitly passed on to the jailed binary.
+    close_inherited_fds(keep_fds)?;
 
     // Cleanup environment variables.
     clean_env_vars();
     Ok(())
 }
 
+/// Parses an inherited file descriptor, which cannot be one of the standard I/O ones.
+pub fn parse_inherited_fd(value: &str) -> Result<RawFd, JailerError> {
+    match value.parse::<RawFd>() {
+        Ok(fd) if fd > 2 => Ok(fd),
+        _ => Err(JailerError::InheritedFd(value.to_string())),
+    }
+}
+
+/// Parses the `--inherit-fd` and `--exit-status-fd` arguments into a sorted list of file
+/// descriptors.
+fn parse_inherited_fds(arguments: &Arguments) -> Result<Vec<RawFd>, JailerError> {
+    let mut fds = arguments
+        .multiple_values("inherit-fd")
+        .unwrap_or_default()
+        .iter()
+        .chain(arguments.single_value("exit-status-fd"))
+        .map(|value| parse_inherited_fd(value))
+        .collect::<Result<Vec<_>, _>>()?;
+    fds.sort_unstable();
+    fds.dedup();
+    Ok(fds)
+}
+
 fn clean_env_vars() {
     // Remove environment variables received from
     // the parent process so there are no leaks
@@ -343,15 +558,18 @@ fn main() -> Result<(), JailerError> {
 }
 
 fn main_exec() -> Result<(), JailerError> {
-    sanitize_process()
-        .unwrap_or_else(|err| panic!("Failed to sanitize the Jailer process: {}", err));
-
+    // Parsing the arguments does not open any file, so it is fine to do it before sanitizing
+    // the process. We need them to know which inherited FDs have to be kept.
     let mut arg_parser = build_arg_parser();
     arg_parser
         .parse_from_cmdline()
         .map_err(JailerError::ArgumentParsing)?;
     let arguments = arg_parser.arguments();
 
+    let inherited_fds = parse_inherited_fds(arguments)?;
+    sanitize_process(&inherited_fds)
+        .unwrap_or_else(|err| panic!("Failed to sanitize the Jailer process: {}", err));
+
     if arguments.flag_present("help") {
         println!("Jailer v{}\n", JAILER_VERSION);
         println!("{}\n", arg_parser.formatted_help());
@@ -391,7 +609,7 @@ mod tests {
 
     use super::*;
 
-    fn run_close_fds_test(test_fn: fn() -> Result<(), JailerError>) {
+    fn run_close_fds_test(test_fn: fn(&[RawFd]) -> Result<(), JailerError>) {
         let n = 100;
 
         let tmp_dir_path = format!(
@@ -407,11 +625,16 @@ mod tests {
             fds.push(maybe_file.unwrap().into_raw_fd());
         }
 
-        assert!(test_fn().is_ok());
+        // Keep a couple of adjacent FDs and a lone one open.
+        let keep_fds = [fds[10], fds[11], fds[50]];
+        assert!(test_fn(&keep_fds).is_ok());
 
         for fd in fds {
             let is_fd_opened = unsafe { libc::fcntl(fd, libc::F_GETFD) } == 0;
-            assert!(!is_fd_opened);
+            assert_eq!(is_fd_opened, keep_fds.contains(&fd));
+        }
+        for fd in keep_fds {
+            unsafe { libc::close(fd) };
         }
 
         assert!(fs::remove_dir_all(tmp_dir_path).is_ok());
@@ -448,6 +671,59 @@ mod tests {
         run_close_fds_test(sanitize_process);
     }
 
+    #[test]
+    fn test_parse_inherited_fds() {
+        let parse = |fds: &[&str]| {
+            let mut args = build_arg_parser().arguments().clone();
+            let mut arg_vec = vec![
+                "--binary-name",
+                "--id",
+                "1",
+                "--exec-file",
+                "/bin/firecracker",
+            ];
+            arg_vec.extend(["--uid", "0", "--gid", "0"]);
+            for fd in fds {
+                arg_vec.extend(["--inherit-fd", fd]);
+            }
+            args.parse(&arg_vec.into_iter().map(String::from).collect::<Vec<_>>())
+                .unwrap();
+            parse_inherited_fds(&args)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), Vec::<RawFd>::new());
+        assert_eq!(parse(&["9", "3", "9", "5"]).unwrap(), vec![3, 5, 9]);
+        assert_eq!(
+            format!("{}", parse(&["3", "2"]).unwrap_err()),
+            "Invalid inherited file descriptor: 2"
+        );
+        assert_eq!(
+            format!("{}", parse(&["fd"]).unwrap_err()),
+            "Invalid inherited file descriptor: fd"
+        );
+
+        // The exit status fd is kept open as well.
+        let mut args = build_arg_parser().arguments().clone();
+        let arg_vec = [
+            "--binary-name",
+            "--id",
+            "1",
+            "--exec-file",
+            "/bin/firecracker",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+            "--inherit-fd",
+            "5",
+            "--supervise",
+            "--exit-status-fd",
+            "4",
+        ];
+        args.parse(&arg_vec.map(String::from)).unwrap();
+        assert_eq!(parse_inherited_fds(&args).unwrap(), vec![4, 5]);
+    }
+
     #[test]
     fn test_clean_env_vars() {
         let env_vars: [&str; 5] = ["VAR1", "VAR2", "VAR3", "VAR4", "VAR5"];
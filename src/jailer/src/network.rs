@@ -0,0 +1,516 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::{io, mem};
+
+use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_val};
+use utils::syscall::SyscallReturnCode;
+use utils::{ioctl_ioc_nr, ioctl_iow_nr};
+
+use super::JailerError;
+
+const DEV_NET_TUN: &str = "/dev/net/tun";
+const HOST_NETNS: &str = "/proc/self/ns/net";
+const LOOPBACK_NAME: &str = "lo";
+// libc::AF_INET is a c_int, while sockaddr_in.sin_family is a sa_family_t.
+const AF_INET: libc::sa_family_t = 2;
+
+const TUNTAP: ::std::os::raw::c_uint = 84;
+ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETPERSIST, TUNTAP, 203, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETOWNER, TUNTAP, 204, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETGROUP, TUNTAP, 206, ::std::os::raw::c_int);
+
+// ioctl request numbers from include/uapi/linux/sockios.h.
+const SIOCGIFFLAGS: libc::c_ulong = 0x8913;
+const SIOCSIFFLAGS: libc::c_ulong = 0x8914;
+const SIOCSIFADDR: libc::c_ulong = 0x8916;
+const SIOCSIFNETMASK: libc::c_ulong = 0x891c;
+const SIOCGIFINDEX: libc::c_ulong = 0x8933;
+const SIOCBRADDIF: libc::c_ulong = 0x89a2;
+
+const IFF_UP: libc::c_short = 0x0001;
+// Same flags Firecracker uses when opening the tap device.
+const IFF_TAP: libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+const IFF_VNET_HDR: libc::c_short = 0x4000;
+
+// rtnetlink definitions from include/uapi/linux/{netlink,rtnetlink,if_link,veth}.h.
+const NETLINK_ROUTE: libc::c_int = 0;
+const NLMSG_ERROR: u16 = 2;
+const RTM_NEWLINK: u16 = 16;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_NET_NS_FD: u16 = 28;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+// Sizes of struct nlmsghdr, struct ifinfomsg and struct nlattr.
+const NLMSGHDR_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+
+#[repr(C)]
+union IfReqData {
+    flags: libc::c_short,
+    index: libc::c_int,
+    addr: libc::sockaddr_in,
+    // Size of the union in struct ifreq.
+    _pad: [u8; 24],
+}
+
+// Equivalent of struct ifreq from include/uapi/linux/if.h.
+#[repr(C)]
+struct IfReq {
+    name: [u8; libc::IFNAMSIZ],
+    data: IfReqData,
+}
+
+impl IfReq {
+    fn new(if_name: &str) -> Self {
+        let mut name = [0u8; libc::IFNAMSIZ];
+        // The name length is validated when parsing the arguments, which leaves room for the
+        // terminating nul byte.
+        name[..if_name.len()].copy_from_slice(if_name.as_bytes());
+        IfReq {
+            name,
+            data: IfReqData { _pad: [0u8; 24] },
+        }
+    }
+
+    fn with_flags(if_name: &str, flags: libc::c_short) -> Self {
+        let mut req = IfReq::new(if_name);
+        req.data.flags = flags;
+        req
+    }
+
+    fn with_addr(if_name: &str, addr: Ipv4Addr) -> Self {
+        let mut req = IfReq::new(if_name);
+        req.data.addr = libc::sockaddr_in {
+            sin_family: AF_INET,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from(addr).to_be(),
+            },
+            sin_zero: [0; 8],
+        };
+        req
+    }
+}
+
+// Runs an ioctl taking a struct ifreq.
+fn ifreq_ioctl(file: &File, request: libc::c_ulong, req: &mut IfReq) -> io::Result<()> {
+    // SAFETY: Safe because all the requests passed by the callers take a struct ifreq, which `req`
+    // has the layout of.
+    SyscallReturnCode(unsafe { ioctl_with_mut_ref(file, request, req) }).into_empty_result()
+}
+
+// Runs an ioctl taking an integer value.
+fn value_ioctl(file: &File, request: libc::c_ulong, value: u32) -> io::Result<()> {
+    // SAFETY: Safe because all the requests passed by the callers take an integer value.
+    SyscallReturnCode(unsafe { ioctl_with_val(file, request, libc::c_ulong::from(value)) })
+        .into_empty_result()
+}
+
+// Network address assigned to a tap device, in CIDR notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TapAddress {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl TapAddress {
+    // Parses an address following this format: <ipv4_addr>/<prefix_len>.
+    fn parse(cidr: &str) -> Option<Self> {
+        let (addr, prefix_len) = cidr.split_once('/')?;
+        let addr = addr.parse::<Ipv4Addr>().ok()?;
+        let prefix_len = prefix_len.parse::<u8>().ok()?;
+        if prefix_len > 32 {
+            return None;
+        }
+        Some(TapAddress { addr, prefix_len })
+    }
+
+    fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(
+            u32::MAX
+                .checked_shl(32 - u32::from(self.prefix_len))
+                .unwrap_or(0),
+        )
+    }
+
+    // Assigns the address to the `if_name` interface of the network namespace `sock` belongs to.
+    fn assign(&self, sock: &File, if_name: &str) -> io::Result<()> {
+        let mut req = IfReq::with_addr(if_name, self.addr);
+        ifreq_ioctl(sock, SIOCSIFADDR, &mut req)?;
+        let mut req = IfReq::with_addr(if_name, self.netmask());
+        ifreq_ioctl(sock, SIOCSIFNETMASK, &mut req)
+    }
+}
+
+// Interface names are limited to IFNAMSIZ bytes, including the terminating nul byte.
+fn valid_if_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < libc::IFNAMSIZ
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == ':' || c.is_whitespace() || c.is_control())
+}
+
+// Tap device created by the jailer for the microVM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TapConfig {
+    name: String,
+    address: Option<TapAddress>,
+}
+
+impl TapConfig {
+    // Parses a tap argument following this format: <name>[,<ipv4_addr>/<prefix_len>].
+    pub fn parse(arg: &str) -> Result<Self, JailerError> {
+        let invalid = || JailerError::TapConfig(arg.to_string());
+        let (name, address) = match arg.split_once(',') {
+            Some((name, cidr)) => (name, Some(TapAddress::parse(cidr).ok_or_else(invalid)?)),
+            None => (arg, None),
+        };
+        if !valid_if_name(name) {
+            return Err(invalid());
+        }
+
+        Ok(TapConfig {
+            name: name.to_string(),
+            address,
+        })
+    }
+
+    // Creates a persistent tap device owned by `uid`/`gid`, so that the jailed process can attach
+    // to it after dropping privileges, then assigns its address and brings it up.
+    pub fn create(&self, uid: u32, gid: u32) -> Result<(), JailerError> {
+        let tun = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(DEV_NET_TUN)
+            .map_err(|err| JailerError::CreateTap(self.name.clone(), err))?;
+
+        let mut req = IfReq::with_flags(&self.name, IFF_TAP | IFF_NO_PI | IFF_VNET_HDR);
+        ifreq_ioctl(&tun, TUNSETIFF(), &mut req)
+            .and_then(|()| value_ioctl(&tun, TUNSETOWNER(), uid))
+            .and_then(|()| value_ioctl(&tun, TUNSETGROUP(), gid))
+            .and_then(|()| value_ioctl(&tun, TUNSETPERSIST(), 1))
+            .map_err(|err| JailerError::CreateTap(self.name.clone(), err))?;
+
+        let configure = || {
+            let sock = inet_socket()?;
+            if let Some(address) = self.address {
+                address.assign(&sock, &self.name)?;
+            }
+            set_link_up(&sock, &self.name)
+        };
+        configure().map_err(|err| JailerError::ConfigureTap(self.name.clone(), err))
+    }
+}
+
+// Handles on the network namespace the jailer was started in, which holds the host side of the
+// veth pairs. The socket stays bound to that namespace after the jailer switches to another one.
+#[derive(Debug)]
+pub struct HostNetNs {
+    netns: File,
+    sock: File,
+}
+
+impl HostNetNs {
+    pub fn open() -> Result<Self, JailerError> {
+        let netns = File::open(HOST_NETNS).map_err(JailerError::HostNetNs)?;
+        let sock = inet_socket().map_err(JailerError::HostNetNs)?;
+        Ok(HostNetNs { netns, sock })
+    }
+}
+
+// Veth pair created by the jailer, connecting the network namespace of the microVM to the one the
+// jailer was started in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VethConfig {
+    name: String,
+    host_name: String,
+    address: Option<TapAddress>,
+    host_bridge: Option<String>,
+}
+
+impl VethConfig {
+    // Parses a veth argument following this format:
+    // <name>,<host_name>[,<ipv4_addr>/<prefix_len>][,bridge=<host_bridge>].
+    pub fn parse(arg: &str) -> Result<Self, JailerError> {
+        let invalid = || JailerError::VethConfig(arg.to_string());
+        let mut parts = arg.split(',');
+        let mut if_name = || {
+            parts
+                .next()
+                .filter(|name| valid_if_name(name))
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        };
+        let name = if_name()?;
+        let host_name = if_name()?;
+
+        let mut address = None;
+        let mut host_bridge = None;
+        for part in parts {
+            match part.strip_prefix("bridge=") {
+                Some(bridge) if host_bridge.is_none() && valid_if_name(bridge) => {
+                    host_bridge = Some(bridge.to_string())
+                }
+                None if address.is_none() => {
+                    address = Some(TapAddress::parse(part).ok_or_else(invalid)?)
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(VethConfig {
+            name,
+            host_name,
+            address,
+            host_bridge,
+        })
+    }
+
+    // Creates the veth pair, with `name` in the current network namespace and `host_name` in the
+    // host one. The address is assigned to the microVM side, and the host side is attached to the
+    // host bridge, if any. Both sides are then brought up.
+    pub fn create(&self, host: &HostNetNs) -> Result<(), JailerError> {
+        new_veth(&self.name, &self.host_name, &host.netns)
+            .map_err(|err| JailerError::CreateVeth(self.name.clone(), err))?;
+
+        let configure = || {
+            let sock = inet_socket()?;
+            if let Some(address) = self.address {
+                address.assign(&sock, &self.name)?;
+            }
+            set_link_up(&sock, &self.name)?;
+            if let Some(ref bridge) = self.host_bridge {
+                attach_to_bridge(&host.sock, bridge, &self.host_name)?;
+            }
+            set_link_up(&host.sock, &self.host_name)
+        };
+        configure().map_err(|err| JailerError::ConfigureVeth(self.name.clone(), err))
+    }
+}
+
+// Appends a netlink attribute to `buf`, followed by the padding aligning it to 4 bytes.
+fn push_attr(buf: &mut Vec<u8>, attr_type: u16, payload: &[u8]) {
+    let len = u16::try_from(NLA_HDRLEN + payload.len()).expect("Netlink attribute too large");
+    buf.extend_from_slice(&len.to_ne_bytes());
+    buf.extend_from_slice(&attr_type.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+fn if_name_attr(if_name: &str) -> Vec<u8> {
+    let mut name = if_name.as_bytes().to_vec();
+    name.push(0);
+    name
+}
+
+// Builds the RTM_NEWLINK request creating a veth pair, with `name` in the network namespace of
+// the netlink socket and `peer_name` in the `peer_netns` one.
+fn veth_request(name: &str, peer_name: &str, peer_netns: libc::c_int) -> Vec<u8> {
+    // The peer is described by its own struct ifinfomsg, left zeroed, and link attributes.
+    let mut peer = vec![0u8; IFINFOMSG_LEN];
+    push_attr(&mut peer, IFLA_IFNAME, &if_name_attr(peer_name));
+    push_attr(&mut peer, IFLA_NET_NS_FD, &peer_netns.to_ne_bytes());
+    let mut info_data = Vec::new();
+    push_attr(&mut info_data, VETH_INFO_PEER, &peer);
+    let mut link_info = Vec::new();
+    push_attr(&mut link_info, IFLA_INFO_KIND, b"veth\0");
+    push_attr(&mut link_info, IFLA_INFO_DATA, &info_data);
+
+    let mut msg = vec![0u8; NLMSGHDR_LEN + IFINFOMSG_LEN];
+    push_attr(&mut msg, IFLA_IFNAME, &if_name_attr(name));
+    push_attr(&mut msg, IFLA_LINKINFO, &link_info);
+
+    let len = u32::try_from(msg.len()).expect("Netlink message too large");
+    let flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL;
+    msg[0..4].copy_from_slice(&len.to_ne_bytes());
+    msg[4..6].copy_from_slice(&RTM_NEWLINK.to_ne_bytes());
+    msg[6..8].copy_from_slice(&flags.to_ne_bytes());
+    // The sequence number is only checked by the sender, and the kernel fills in the port id.
+    msg[8..12].copy_from_slice(&1u32.to_ne_bytes());
+    msg
+}
+
+fn new_veth(name: &str, peer_name: &str, peer_netns: &File) -> io::Result<()> {
+    // SAFETY: Safe because the arguments are valid constants.
+    let fd = SyscallReturnCode(unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            NETLINK_ROUTE,
+        )
+    })
+    .into_result()?;
+    // SAFETY: Safe because the fd was just created and is exclusively owned by the file.
+    let mut sock = unsafe { File::from_raw_fd(fd) };
+
+    sock.write_all(&veth_request(name, peer_name, peer_netns.as_raw_fd()))?;
+
+    // The request is acknowledged by a NLMSG_ERROR message, holding a negated errno value that is
+    // 0 on success.
+    let mut ack = [0u8; 1024];
+    let len = sock.read(&mut ack)?;
+    if len < NLMSGHDR_LEN + 4 || ack[4..6] != NLMSG_ERROR.to_ne_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unexpected netlink response",
+        ));
+    }
+    let mut error = [0u8; 4];
+    error.copy_from_slice(&ack[NLMSGHDR_LEN..NLMSGHDR_LEN + 4]);
+    match i32::from_ne_bytes(error) {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(-errno)),
+    }
+}
+
+fn attach_to_bridge(sock: &File, bridge: &str, if_name: &str) -> io::Result<()> {
+    let mut req = IfReq::new(if_name);
+    ifreq_ioctl(sock, SIOCGIFINDEX, &mut req)?;
+    // SAFETY: Safe because SIOCGIFINDEX initialized the index.
+    let index = unsafe { req.data.index };
+    let mut req = IfReq::new(bridge);
+    req.data.index = index;
+    ifreq_ioctl(sock, SIOCBRADDIF, &mut req)
+}
+
+fn inet_socket() -> io::Result<File> {
+    // SAFETY: Safe because the arguments are valid constants.
+    let fd = SyscallReturnCode(unsafe {
+        libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0)
+    })
+    .into_result()?;
+    // SAFETY: Safe because the fd was just created and is exclusively owned by the file.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn set_link_up(sock: &File, if_name: &str) -> io::Result<()> {
+    let mut req = IfReq::new(if_name);
+    ifreq_ioctl(sock, SIOCGIFFLAGS, &mut req)?;
+    // SAFETY: Safe because SIOCGIFFLAGS initialized the flags.
+    let flags = unsafe { req.data.flags };
+    req.data.flags = flags | IFF_UP;
+    ifreq_ioctl(sock, SIOCSIFFLAGS, &mut req)
+}
+
+// Moves the jailer into a new network namespace, with the loopback interface up. The namespace,
+// and the tap devices created inside it, go away when the jailed process exits.
+pub fn unshare_netns() -> Result<(), JailerError> {
+    // SAFETY: Safe because we are passing a valid flag.
+    SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWNET) })
+        .into_empty_result()
+        .map_err(JailerError::UnshareNetNs)?;
+
+    let sock = inet_socket().map_err(JailerError::UnshareNetNs)?;
+    set_link_up(&sock, LOOPBACK_NAME).map_err(JailerError::UnshareNetNs)
+}
+
+// Keeps the size of the ifreq structure in sync with the kernel one.
+const _: () = assert!(mem::size_of::<IfReq>() == 40);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tap_config() {
+        let tap = TapConfig::parse("tap0").unwrap();
+        assert_eq!(tap.name, "tap0");
+        assert!(tap.address.is_none());
+
+        let tap = TapConfig::parse("tap0,172.16.0.1/30").unwrap();
+        let address = tap.address.unwrap();
+        assert_eq!(address.addr, Ipv4Addr::new(172, 16, 0, 1));
+        assert_eq!(address.netmask(), Ipv4Addr::new(255, 255, 255, 252));
+
+        let tap = TapConfig::parse("tap0,10.0.0.1/0").unwrap();
+        assert_eq!(tap.address.unwrap().netmask(), Ipv4Addr::new(0, 0, 0, 0));
+
+        for invalid in [
+            "",
+            ",172.16.0.1/30",
+            "a_very_long_tap_name",
+            "tap/0",
+            "tap0,172.16.0.1",
+            "tap0,172.16.0.1/33",
+            "tap0,172.16.0/30",
+            "tap0,fe80::1/64",
+        ] {
+            assert!(
+                matches!(TapConfig::parse(invalid), Err(JailerError::TapConfig(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_veth_config() {
+        let veth = VethConfig::parse("veth0,fc-veth0").unwrap();
+        assert_eq!(veth.name, "veth0");
+        assert_eq!(veth.host_name, "fc-veth0");
+        assert!(veth.address.is_none());
+        assert!(veth.host_bridge.is_none());
+
+        let veth = VethConfig::parse("veth0,fc-veth0,10.0.0.2/24,bridge=br0").unwrap();
+        assert_eq!(veth.address.unwrap().addr, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(veth.host_bridge.as_deref(), Some("br0"));
+        assert_eq!(
+            VethConfig::parse("veth0,fc-veth0,bridge=br0,10.0.0.2/24").unwrap(),
+            veth
+        );
+
+        for invalid in [
+            "",
+            "veth0",
+            "veth0,",
+            "veth0,fc-veth0,10.0.0.2",
+            "veth0,fc-veth0,10.0.0.2/24,10.0.0.3/24",
+            "veth0,fc-veth0,bridge=",
+            "veth0,fc-veth0,bridge=br0,bridge=br1",
+            "veth0,a_very_long_veth_name",
+        ] {
+            assert!(
+                matches!(VethConfig::parse(invalid), Err(JailerError::VethConfig(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_veth_request() {
+        let msg = veth_request("veth0", "fc-veth0", 7);
+        assert_eq!(msg.len() % 4, 0);
+        assert_eq!(&msg[0..4], &u32::try_from(msg.len()).unwrap().to_ne_bytes());
+        assert_eq!(&msg[4..6], &RTM_NEWLINK.to_ne_bytes());
+
+        // The attributes following the struct ifinfomsg are the name and the link info, which
+        // spans the rest of the message.
+        let attrs = &msg[NLMSGHDR_LEN + IFINFOMSG_LEN..];
+        assert_eq!(&attrs[0..2], &10u16.to_ne_bytes());
+        assert_eq!(&attrs[2..4], &IFLA_IFNAME.to_ne_bytes());
+        assert_eq!(&attrs[4..10], b"veth0\0");
+        let link_info = &attrs[12..];
+        assert_eq!(
+            &link_info[0..2],
+            &u16::try_from(link_info.len()).unwrap().to_ne_bytes()
+        );
+        assert_eq!(&link_info[2..4], &IFLA_LINKINFO.to_ne_bytes());
+        assert_eq!(&link_info[8..13], b"veth\0");
+    }
+}
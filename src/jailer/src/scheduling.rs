@@ -0,0 +1,152 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::mem;
+
+use utils::syscall::SyscallReturnCode;
+
+use super::JailerError;
+
+// Range of the nice values.
+const MIN_NICE: i32 = -20;
+const MAX_NICE: i32 = 19;
+// Range of the static priorities of the real-time policies.
+const MIN_RT_PRIORITY: i32 = 1;
+const MAX_RT_PRIORITY: i32 = 99;
+
+// Scheduling settings applied to the jailer before exec, so that every thread of the jailed
+// process inherits them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Scheduling {
+    // Scheduling policy along with its static priority.
+    policy: Option<(libc::c_int, libc::c_int)>,
+    nice: Option<i32>,
+}
+
+impl Scheduling {
+    // Parses the scheduling arguments. The policy follows this format: <policy>[,<priority>],
+    // where the priority is only allowed, and required, for the fifo and rr policies.
+    pub fn parse(policy: Option<&str>, nice: Option<&str>) -> Result<Self, JailerError> {
+        let policy = policy
+            .map(|arg| {
+                let invalid = || JailerError::SchedPolicy(arg.to_string());
+                let (name, priority) = match arg.split_once(',') {
+                    Some((name, priority)) => {
+                        let priority = priority.parse::<i32>().map_err(|_| invalid())?;
+                        (name, Some(priority))
+                    }
+                    None => (arg, None),
+                };
+                match (name, priority) {
+                    ("other", None) => Ok((libc::SCHED_OTHER, 0)),
+                    ("batch", None) => Ok((libc::SCHED_BATCH, 0)),
+                    ("idle", None) => Ok((libc::SCHED_IDLE, 0)),
+                    ("fifo", Some(priority)) | ("rr", Some(priority))
+                        if (MIN_RT_PRIORITY..=MAX_RT_PRIORITY).contains(&priority) =>
+                    {
+                        let policy = if name == "fifo" {
+                            libc::SCHED_FIFO
+                        } else {
+                            libc::SCHED_RR
+                        };
+                        Ok((policy, priority))
+                    }
+                    _ => Err(invalid()),
+                }
+            })
+            .transpose()?;
+
+        let nice = nice
+            .map(|arg| match arg.parse::<i32>() {
+                Ok(nice) if (MIN_NICE..=MAX_NICE).contains(&nice) => Ok(nice),
+                _ => Err(JailerError::Nice(arg.to_string())),
+            })
+            .transpose()?;
+
+        Ok(Scheduling { policy, nice })
+    }
+
+    pub fn install(&self) -> Result<(), JailerError> {
+        if let Some((policy, priority)) = self.policy {
+            // SAFETY: Safe because sched_param is a plain structure, which is valid when zeroed.
+            let mut param: libc::sched_param = unsafe { mem::zeroed() };
+            param.sched_priority = priority;
+            // SAFETY: Safe because `param` is a valid sched_param.
+            SyscallReturnCode(unsafe { libc::sched_setscheduler(0, policy, &param) })
+                .into_empty_result()
+                .map_err(JailerError::SetScheduler)?;
+        }
+        if let Some(nice) = self.nice {
+            // The jailer is single threaded at this point, so this applies to the whole process.
+            // SAFETY: Safe because the arguments are plain values.
+            SyscallReturnCode(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })
+                .into_empty_result()
+                .map_err(JailerError::SetPriority)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+
+    use super::*;
+
+    #[test]
+    fn test_parse_scheduling() {
+        assert_eq!(
+            Scheduling::parse(None, None).unwrap(),
+            Scheduling::default()
+        );
+        assert_eq!(
+            Scheduling::parse(Some("idle"), None).unwrap().policy,
+            Some((libc::SCHED_IDLE, 0))
+        );
+        assert_eq!(
+            Scheduling::parse(Some("rr,50"), Some("-5")).unwrap(),
+            Scheduling {
+                policy: Some((libc::SCHED_RR, 50)),
+                nice: Some(-5),
+            }
+        );
+
+        for invalid in [
+            "", "deadline", "other,1", "fifo", "fifo,0", "rr,100", "fifo,x",
+        ] {
+            assert!(
+                matches!(
+                    Scheduling::parse(Some(invalid), None),
+                    Err(JailerError::SchedPolicy(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+        for invalid in ["", "-21", "20", "low"] {
+            assert!(
+                matches!(
+                    Scheduling::parse(None, Some(invalid)),
+                    Err(JailerError::Nice(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_install_scheduling() {
+        std::thread::spawn(|| {
+            // Lowering the priority does not require any privilege.
+            Scheduling::parse(Some("batch"), Some("10"))
+                .unwrap()
+                .install()
+                .unwrap();
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_BATCH);
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, 10);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use utils::syscall::SyscallReturnCode;
+
+use super::JailerError;
+
+// Flag making clone() return a pidfd referring to the child, from include/uapi/linux/sched.h.
+pub const CLONE_PIDFD: libc::c_int = 0x1000;
+// pidfd_send_signal() syscall number, which is the same on x86_64 and aarch64.
+const SYS_PIDFD_SEND_SIGNAL: libc::c_long = 424;
+// waitid() id type for pidfds, from include/uapi/linux/wait.h.
+const P_PIDFD: libc::idtype_t = 3;
+
+// Signals the supervisor forwards to the jailed process.
+const FORWARDED_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+// How the jailed process terminated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    // The process exited with the given code.
+    Exited(i32),
+    // The process was killed by the given signal.
+    Signaled(i32),
+}
+
+impl ExitStatus {
+    // Exit code of the supervisor, which follows the shell convention for processes killed by a
+    // signal.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => *code,
+            ExitStatus::Signaled(signo) => 128 + signo,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitStatus::Exited(code) => write!(f, "exit_code={}", code),
+            ExitStatus::Signaled(signo) => write!(f, "signal={}", signo),
+        }
+    }
+}
+
+fn forwarded_signal_set() -> libc::sigset_t {
+    // SAFETY: Safe because sigset_t is a plain bitmask, which sigemptyset() initializes anyway.
+    let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+    // SAFETY: Safe because `set` is a valid signal set and the signals are valid.
+    unsafe {
+        libc::sigemptyset(&mut set);
+        for signo in FORWARDED_SIGNALS {
+            libc::sigaddset(&mut set, signo);
+        }
+    }
+    set
+}
+
+// Blocks the forwarded signals, so that the ones received before the supervisor listens to them
+// stay pending instead of terminating the jailer. Returns the previous signal mask.
+pub fn block_forwarded_signals() -> Result<libc::sigset_t, JailerError> {
+    let set = forwarded_signal_set();
+    // SAFETY: Safe because sigset_t is a plain bitmask, which sigprocmask() overwrites.
+    let mut old_set: libc::sigset_t = unsafe { mem::zeroed() };
+    // SAFETY: Safe because both signal sets are valid.
+    SyscallReturnCode(unsafe { libc::sigprocmask(libc::SIG_BLOCK, &set, &mut old_set) })
+        .into_empty_result()
+        .map_err(JailerError::SignalForwarding)?;
+    Ok(old_set)
+}
+
+// Restores the signal mask saved by `block_forwarded_signals()`, so that the jailed process does
+// not inherit the blocked signals.
+pub fn restore_signal_mask(old_set: &libc::sigset_t) -> Result<(), JailerError> {
+    // SAFETY: Safe because `old_set` is a valid signal set.
+    SyscallReturnCode(unsafe {
+        libc::sigprocmask(libc::SIG_SETMASK, old_set, std::ptr::null_mut())
+    })
+    .into_empty_result()
+    .map_err(JailerError::SignalForwarding)
+}
+
+// Switches the supervisor to the ids of the jailed process, as it has no use for its privileges
+// once the jailed process is started.
+pub fn drop_privileges(uid: u32, gid: u32) -> Result<(), JailerError> {
+    // SAFETY: Safe because a zero size with a null pointer drops all the supplementary groups.
+    // This fails without CAP_SETGID, in which case there are no groups to drop.
+    let _ = unsafe { libc::setgroups(0, std::ptr::null()) };
+    // SAFETY: Safe because it's a library function taking a plain value.
+    SyscallReturnCode(unsafe { libc::setgid(gid) })
+        .into_empty_result()
+        .map_err(JailerError::DropPrivileges)?;
+    // SAFETY: Safe because it's a library function taking a plain value.
+    SyscallReturnCode(unsafe { libc::setuid(uid) })
+        .into_empty_result()
+        .map_err(JailerError::DropPrivileges)
+}
+
+fn forward_signal(pidfd: &File, signal_fd: &File) -> io::Result<()> {
+    // SAFETY: Safe because signalfd_siginfo is a plain structure, which read() overwrites.
+    let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+    // SAFETY: Safe because `info` is large enough to hold the data read from the signalfd.
+    SyscallReturnCode(unsafe {
+        libc::read(
+            signal_fd.as_raw_fd(),
+            (&mut info as *mut libc::signalfd_siginfo).cast(),
+            mem::size_of::<libc::signalfd_siginfo>(),
+        )
+    } as libc::c_int)
+    .into_empty_result()?;
+
+    // Signalling through the pidfd cannot reach another process reusing the pid of the jailed
+    // process. The only expected failure is the jailed process having exited already, which the
+    // supervisor finds out through the pidfd right after.
+    // SAFETY: Safe because `pidfd` is a valid pidfd and a null siginfo is allowed.
+    let _ = unsafe {
+        libc::syscall(
+            SYS_PIDFD_SEND_SIGNAL,
+            pidfd.as_raw_fd(),
+            info.ssi_signo,
+            std::ptr::null::<libc::siginfo_t>(),
+            0u32,
+        )
+    };
+    Ok(())
+}
+
+fn wait_exit_status(pidfd: &File) -> io::Result<ExitStatus> {
+    // The pidfd was returned by clone(), so it is not negative.
+    let id = libc::id_t::try_from(pidfd.as_raw_fd()).unwrap();
+    // SAFETY: Safe because siginfo_t is a plain structure, which waitid() overwrites.
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    // SAFETY: Safe because `pidfd` is a valid pidfd and `info` is a valid siginfo_t.
+    SyscallReturnCode(unsafe { libc::waitid(P_PIDFD, id, &mut info, libc::WEXITED) })
+        .into_empty_result()?;
+    // SAFETY: Safe because waitid() filled in the status of a terminated child.
+    let status = unsafe { info.si_status() };
+    match info.si_code {
+        libc::CLD_EXITED => Ok(ExitStatus::Exited(status)),
+        _ => Ok(ExitStatus::Signaled(status)),
+    }
+}
+
+// Forwards the signals received by the supervisor to the jailed process referred to by `pidfd`,
+// until it terminates. The exit status of the jailed process is then written to `exit_status`,
+// if any. The forwarded signals must be blocked by the caller.
+pub fn supervise(pidfd: &File, exit_status: Option<File>) -> Result<ExitStatus, JailerError> {
+    let set = forwarded_signal_set();
+    // SAFETY: Safe because `set` is a valid signal set.
+    let signal_fd = SyscallReturnCode(unsafe { libc::signalfd(-1, &set, libc::SFD_CLOEXEC) })
+        .into_result()
+        .map_err(JailerError::SignalForwarding)?;
+    // SAFETY: Safe because the signalfd was just created and is exclusively owned by the file.
+    let signal_fd = unsafe { File::from_raw_fd(signal_fd) };
+
+    let mut poll_fds = [
+        libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: signal_fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        // SAFETY: Safe because `poll_fds` holds valid pollfd structures, and its length is passed.
+        let res = SyscallReturnCode(unsafe {
+            libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, -1)
+        })
+        .into_empty_result();
+        match res {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res.map_err(JailerError::WaitPidFd)?,
+        }
+
+        if poll_fds[1].revents & libc::POLLIN != 0 {
+            forward_signal(pidfd, &signal_fd).map_err(JailerError::SignalForwarding)?;
+        }
+        // The pidfd becomes readable once the jailed process has terminated.
+        if poll_fds[0].revents & libc::POLLIN != 0 {
+            break;
+        }
+    }
+
+    let status = wait_exit_status(pidfd).map_err(JailerError::WaitPidFd)?;
+    if let Some(mut exit_status) = exit_status {
+        writeln!(exit_status, "{}", status).map_err(JailerError::ExitStatusWrite)?;
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+
+    use std::io::Read;
+    use std::os::unix::io::RawFd;
+
+    use super::*;
+
+    // pidfd_open() syscall number, which is the same on x86_64 and aarch64.
+    const SYS_PIDFD_OPEN: libc::c_long = 434;
+
+    // Forks a child process which runs `child`, then returns a pidfd referring to it.
+    fn spawn_child(child: fn() -> !) -> File {
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            child();
+        }
+        assert!(pid > 0);
+        let pidfd = unsafe { libc::syscall(SYS_PIDFD_OPEN, pid, 0u32) };
+        assert!(pidfd >= 0);
+        unsafe { File::from_raw_fd(RawFd::try_from(pidfd).unwrap()) }
+    }
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(ExitStatus::Exited(3).code(), 3);
+        assert_eq!(ExitStatus::Exited(3).to_string(), "exit_code=3");
+        assert_eq!(ExitStatus::Signaled(libc::SIGKILL).code(), 137);
+        assert_eq!(ExitStatus::Signaled(libc::SIGKILL).to_string(), "signal=9");
+    }
+
+    #[test]
+    fn test_supervise() {
+        let pidfd = spawn_child(|| unsafe { libc::_exit(3) });
+        let (mut reader, writer) = pipe();
+        assert_eq!(
+            supervise(&pidfd, Some(writer)).unwrap(),
+            ExitStatus::Exited(3)
+        );
+        let mut report = String::new();
+        reader.read_to_string(&mut report).unwrap();
+        assert_eq!(report, "exit_code=3\n");
+
+        let pidfd = spawn_child(|| unsafe {
+            libc::kill(libc::getpid(), libc::SIGKILL);
+            libc::_exit(0)
+        });
+        assert_eq!(
+            supervise(&pidfd, None).unwrap(),
+            ExitStatus::Signaled(libc::SIGKILL)
+        );
+    }
+
+    #[test]
+    fn test_signal_mask() {
+        std::thread::spawn(|| {
+            let old_set = block_forwarded_signals().unwrap();
+            let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+            unsafe { libc::sigprocmask(libc::SIG_BLOCK, std::ptr::null(), &mut set) };
+            assert_eq!(unsafe { libc::sigismember(&set, libc::SIGTERM) }, 1);
+
+            restore_signal_mask(&old_set).unwrap();
+            unsafe { libc::sigprocmask(libc::SIG_BLOCK, std::ptr::null(), &mut set) };
+            assert_eq!(unsafe { libc::sigismember(&set, libc::SIGTERM) }, 0);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::path::PathBuf;
+
+use utils::syscall::SyscallReturnCode;
+
+use super::JailerError;
+
+const PROC_SELF_SETGROUPS: &str = "/proc/self/setgroups";
+const PROC_SELF_UID_MAP: &str = "/proc/self/uid_map";
+const PROC_SELF_GID_MAP: &str = "/proc/self/gid_map";
+
+// Mapping of a range of user or group ids between a user namespace and its parent namespace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdMap {
+    inside: u32,
+    outside: u32,
+    count: u32,
+}
+
+impl IdMap {
+    // Parses an id mapping following this format: <inside_id>:<outside_id>:<count>.
+    pub fn parse(arg: &str) -> Result<Self, JailerError> {
+        let invalid = || JailerError::UserNsIdMap(arg.to_string());
+        let fields = arg
+            .split(':')
+            .map(|field| field.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match fields[..] {
+            [inside, outside, count]
+                if count > 0
+                    && inside.checked_add(count - 1).is_some()
+                    && outside.checked_add(count - 1).is_some() =>
+            {
+                Ok(IdMap {
+                    inside,
+                    outside,
+                    count,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        id >= self.inside && id - self.inside < self.count
+    }
+}
+
+// Writes the id mappings into a /proc/self/{uid,gid}_map file. The kernel requires all the
+// mappings to be written at once.
+fn write_id_maps(path: &str, maps: &[IdMap]) -> Result<(), JailerError> {
+    let content = maps
+        .iter()
+        .map(|map| format!("{} {} {}\n", map.inside, map.outside, map.count))
+        .collect::<String>();
+    fs::write(path, content).map_err(|err| JailerError::Write(PathBuf::from(path), err))
+}
+
+// User namespace the jailer moves into before setting up the jail, so that the setup does not
+// require real root privileges.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserNamespace {
+    uid_maps: Vec<IdMap>,
+    gid_maps: Vec<IdMap>,
+}
+
+impl UserNamespace {
+    // Creates the user namespace configuration. Without explicit mappings, `uid` and `gid` are
+    // mapped onto the effective user and group ids of the jailer, which is the only mapping an
+    // unprivileged process can set up.
+    pub fn new(
+        uid: u32,
+        gid: u32,
+        uid_maps: Vec<IdMap>,
+        gid_maps: Vec<IdMap>,
+    ) -> Result<Self, JailerError> {
+        let default_map = |inside, outside| IdMap {
+            inside,
+            outside,
+            count: 1,
+        };
+        let uid_maps = if uid_maps.is_empty() {
+            // SAFETY: Always safe.
+            vec![default_map(uid, unsafe { libc::geteuid() })]
+        } else {
+            uid_maps
+        };
+        let gid_maps = if gid_maps.is_empty() {
+            // SAFETY: Always safe.
+            vec![default_map(gid, unsafe { libc::getegid() })]
+        } else {
+            gid_maps
+        };
+
+        // The jailed process switches to `uid` and `gid`, which must exist in the namespace.
+        if !uid_maps.iter().any(|map| map.contains(uid)) {
+            return Err(JailerError::UserNsUnmappedId(format!("uid {}", uid)));
+        }
+        if !gid_maps.iter().any(|map| map.contains(gid)) {
+            return Err(JailerError::UserNsUnmappedId(format!("gid {}", gid)));
+        }
+
+        Ok(UserNamespace { uid_maps, gid_maps })
+    }
+
+    // Moves the jailer into a new user namespace, in which it holds all capabilities, and sets up
+    // the id mappings. Supplementary groups cannot be changed inside the namespace.
+    pub fn enter(&self) -> Result<(), JailerError> {
+        // SAFETY: Safe because we are passing a valid flag.
+        SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWUSER) })
+            .into_empty_result()
+            .map_err(JailerError::UnshareUserNs)?;
+
+        // Unprivileged processes have to deny setgroups before writing the gid mappings.
+        fs::write(PROC_SELF_SETGROUPS, "deny")
+            .map_err(|err| JailerError::Write(PathBuf::from(PROC_SELF_SETGROUPS), err))?;
+        write_id_maps(PROC_SELF_UID_MAP, &self.uid_maps)?;
+        write_id_maps(PROC_SELF_GID_MAP, &self.gid_maps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_map() {
+        assert_eq!(
+            IdMap::parse("0:100000:65536").unwrap(),
+            IdMap {
+                inside: 0,
+                outside: 100_000,
+                count: 65536
+            }
+        );
+
+        for invalid in [
+            "",
+            "0:1000",
+            "0:1000:1:1",
+            "0:1000:0",
+            "a:1000:1",
+            "-1:1000:1",
+            "0:4294967295:2",
+        ] {
+            assert!(
+                matches!(IdMap::parse(invalid), Err(JailerError::UserNsIdMap(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_user_namespace_mappings() {
+        // SAFETY: Always safe.
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let userns = UserNamespace::new(1001, 1002, Vec::new(), Vec::new()).unwrap();
+        assert_eq!(
+            userns.uid_maps,
+            vec![IdMap {
+                inside: 1001,
+                outside: euid,
+                count: 1
+            }]
+        );
+        assert_eq!(
+            userns.gid_maps,
+            vec![IdMap {
+                inside: 1002,
+                outside: egid,
+                count: 1
+            }]
+        );
+
+        let maps = vec![IdMap::parse("0:100000:65536").unwrap()];
+        assert!(UserNamespace::new(1001, 1002, maps.clone(), maps.clone()).is_ok());
+
+        let maps = vec![IdMap::parse("0:100000:1000").unwrap()];
+        assert!(matches!(
+            UserNamespace::new(1001, 0, maps.clone(), maps.clone()),
+            Err(JailerError::UserNsUnmappedId(_))
+        ));
+        assert!(matches!(
+            UserNamespace::new(0, 1002, maps.clone(), maps),
+            Err(JailerError::UserNsUnmappedId(_))
+        ));
+    }
+}
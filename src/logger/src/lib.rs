@@ -19,8 +19,9 @@ pub use crate::logger::{LoggerError, LOGGER};
 #[cfg(target_arch = "aarch64")]
 pub use crate::metrics::RTCDeviceMetrics;
 pub use crate::metrics::{
-    IncMetric, MetricsError, ProcessTimeReporter, SerialDeviceMetrics, SharedIncMetric,
-    SharedStoreMetric, StoreMetric, METRICS,
+    DriveLatencyMetrics, IncMetric, MetricsError, MetricsLabels, NetInterfaceMetrics,
+    ProcessTimeReporter, SeccompThreadMetrics, SerialDeviceMetrics, SharedIncMetric,
+    SharedMaxMetric, SharedStoreMetric, StoreMetric, VcpuExitMetrics, METRICS,
 };
 
 /// Alias for `std::io::LineWriter<std::fs::File>`.
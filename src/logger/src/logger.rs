@@ -76,14 +76,15 @@
 //! Logs can be flushed either to stdout/stderr or to a byte-oriented sink (File, FIFO, Ring Buffer
 //! etc).
 
+use std::collections::BTreeMap;
 use std::fmt::{self, Debug};
 use std::io::{sink, stdout, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, RwLock};
-use std::{result, thread};
+use std::{cmp, result, thread};
 
 use lazy_static::lazy_static;
-use log::{max_level, set_logger, set_max_level, LevelFilter, Log, Metadata, Record};
+use log::{max_level, set_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record};
 use utils::time::LocalTime;
 
 use super::extract_guard;
@@ -117,7 +118,13 @@ pub struct Logger {
     show_level: AtomicBool,
     show_file_path: AtomicBool,
     show_line_numbers: AtomicBool,
+    json_format: AtomicBool,
     instance_id: RwLock<String>,
+    labels: RwLock<BTreeMap<String, String>>,
+    // The highest level of the modules without a filter.
+    max_level: RwLock<LevelFilter>,
+    // The highest level of the modules with a filter, along with their submodules.
+    module_filters: RwLock<Vec<(String, LevelFilter)>>,
 }
 
 impl fmt::Debug for Logger {
@@ -128,7 +135,11 @@ impl fmt::Debug for Logger {
             .field("show_level", &self.show_level)
             .field("show_file_path", &self.show_file_path)
             .field("show_line_numbers", &self.show_line_numbers)
+            .field("json_format", &self.json_format)
             .field("instance_id", &self.instance_id)
+            .field("labels", &self.labels)
+            .field("max_level", &self.max_level)
+            .field("module_filters", &self.module_filters)
             .finish()
     }
 }
@@ -142,7 +153,11 @@ impl Logger {
             show_level: AtomicBool::new(true),
             show_line_numbers: AtomicBool::new(true),
             show_file_path: AtomicBool::new(true),
+            json_format: AtomicBool::new(false),
             instance_id: RwLock::new(String::new()),
+            labels: RwLock::new(BTreeMap::new()),
+            max_level: RwLock::new(DEFAULT_MAX_LEVEL),
+            module_filters: RwLock::new(Vec::new()),
         }
     }
 
@@ -158,6 +173,10 @@ impl Logger {
         self.show_line_numbers.load(Ordering::Relaxed)
     }
 
+    fn json_format(&self) -> bool {
+        self.json_format.load(Ordering::Relaxed)
+    }
+
     /// Enables or disables including the level in the log message's tag portion.
     ///
     /// # Arguments
@@ -221,6 +240,38 @@ impl Logger {
         self
     }
 
+    /// Enables or disables writing each log message as a JSON object, on its own line, instead
+    /// of the human readable format. The object holds the `timestamp`, `level`, `module`,
+    /// `message`, `vm_id` and `thread` of the message, along with its `file` and `line` when the
+    /// origin is included.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - Boolean deciding whether to write the log messages as JSON objects.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::ops::Deref;
+    ///
+    /// use logger::{warn, LOGGER};
+    ///
+    /// let l = LOGGER.deref();
+    /// l.set_json_format(true);
+    /// assert!(l.configure(Some("MY-INSTANCE".to_string())).is_ok());
+    /// warn!("A warning log message");
+    /// ```
+    /// The code above will more or less print:
+    /// ```bash
+    /// {"file":"logger/src/lib.rs","level":"WARN","line":290,"message":"A warning log message",
+    /// "module":"rust_out","thread":"main","timestamp":"2018-11-07T05:34:25.180751152",
+    /// "vm_id":"MY-INSTANCE"}
+    /// ```
+    pub fn set_json_format(&self, option: bool) -> &Self {
+        self.json_format.store(option, Ordering::Relaxed);
+        self
+    }
+
     /// Sets the ID for this logger session.
     pub fn set_instance_id(&self, instance_id: String) -> &Self {
         let mut guard = extract_guard(self.instance_id.write());
@@ -228,6 +279,15 @@ impl Logger {
         self
     }
 
+    /// Sets the labels written in each log message, e.g. the host pool the microVM runs in, to
+    /// attribute it regardless of the file it is written to. In the human readable format, they
+    /// follow the tag of the message, as `[key=value,...]`.
+    pub fn set_labels(&self, labels: BTreeMap<String, String>) -> &Self {
+        let mut guard = extract_guard(self.labels.write());
+        *guard = labels;
+        self
+    }
+
     /// Explicitly sets the max log level for the Logger.
     /// The default level is WARN. So, ERROR and WARN statements will be shown (i.e. all that is
     /// bigger than the level code).
@@ -254,10 +314,66 @@ impl Logger {
     /// message
     /// ```
     pub fn set_max_level(&self, level: LevelFilter) -> &Self {
-        set_max_level(level);
+        *extract_guard(self.max_level.write()) = level;
+        self.update_max_level();
+        self
+    }
+
+    /// Sets the max log level of the given modules, along with their submodules, overriding the
+    /// max level of the Logger. The modules are designated by their path, e.g.
+    /// `vmm::devices::virtio::block`, in which the name of the crate can be left out. When
+    /// several filters apply to a module, the one of the innermost module wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - The modules and their highest log level. They replace the previous filters.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::ops::Deref;
+    ///
+    /// use logger::{debug, LOGGER};
+    ///
+    /// let l = LOGGER.deref();
+    /// l.set_max_level(log::LevelFilter::Warn);
+    /// l.set_module_filters(vec![(
+    ///     "devices::virtio::block".to_string(),
+    ///     log::LevelFilter::Debug,
+    /// )]);
+    /// assert!(l.configure(Some("MY-INSTANCE".to_string())).is_ok());
+    /// debug!("A debug message, not shown as it does not come from the block device");
+    /// ```
+    pub fn set_module_filters(&self, filters: Vec<(String, LevelFilter)>) -> &Self {
+        *extract_guard(self.module_filters.write()) = filters;
+        self.update_max_level();
         self
     }
 
+    // Sets the max level of the log crate, which discards the entries above it, to the highest
+    // level of the Logger and its module filters.
+    fn update_max_level(&self) {
+        let filters = extract_guard(self.module_filters.read());
+        let level = filters
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(*extract_guard(self.max_level.read()), cmp::max);
+        set_max_level(level);
+    }
+
+    // Returns the max log level of the module with the given path.
+    fn module_max_level(&self, module_path: &str) -> LevelFilter {
+        let filters = extract_guard(self.module_filters.read());
+        filters
+            .iter()
+            .filter(|(module, _)| is_in_module(module_path, module))
+            .max_by_key(|(module, _)| module.len())
+            .map_or_else(
+                || *extract_guard(self.max_level.read()),
+                |(_, level)| *level,
+            )
+    }
+
     /// Get the current thread's name.
     fn get_thread_name(&self) -> String {
         thread::current().name().unwrap_or("-").to_string()
@@ -290,7 +406,47 @@ impl Logger {
             }
         }
 
-        format!("[{}]", prefix.join(":"))
+        let labels = extract_guard(self.labels.read());
+        if labels.is_empty() {
+            format!("[{}]", prefix.join(":"))
+        } else {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            format!("[{}] [{}]", prefix.join(":"), labels.join(","))
+        }
+    }
+
+    /// Creates the JSON object of the log statement, based on the logger settings.
+    fn create_json_object(&self, record: &Record) -> String {
+        let mut object = serde_json::json!({
+            "timestamp": LocalTime::now().to_string(),
+            "level": record.level().as_str(),
+            "module": record.target(),
+            "message": record.args().to_string(),
+            "vm_id": extract_guard(self.instance_id.read()).as_str(),
+            "thread": self.get_thread_name(),
+        });
+
+        let labels = extract_guard(self.labels.read());
+        if !labels.is_empty() {
+            object["labels"] = serde_json::json!(*labels);
+        }
+
+        if self.show_file_path() {
+            if let Some(file) = record.file() {
+                object["file"] = file.into();
+            }
+        }
+
+        if self.show_line_numbers() {
+            if let Some(line) = record.line() {
+                object["line"] = line.into();
+            }
+        }
+
+        object.to_string()
     }
 
     /// if the max level hasn't been configured yet, set it to default
@@ -371,7 +527,19 @@ impl Logger {
             })
             .map_err(LoggerError::Init)?;
 
-        self.write_log(&header);
+        if self.json_format() {
+            // The header is written like the log messages, so that each line is a JSON object.
+            let header = self.create_json_object(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target(module_path!())
+                    .args(format_args!("{}", header))
+                    .build(),
+            );
+            self.write_log(&header);
+        } else {
+            self.write_log(&header);
+        }
 
         Ok(())
     }
@@ -409,20 +577,42 @@ pub enum LoggerError {
     Init(init::Error),
 }
 
+// Whether the module with the given path is `module` or one of its submodules, where the name of
+// the crate can be left out of `module`.
+fn is_in_module(module_path: &str, module: &str) -> bool {
+    let starts_with_module = |path: &str| {
+        path.strip_prefix(module)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+    };
+    starts_with_module(module_path)
+        || module_path
+            .split_once("::")
+            .map_or(false, |(_, path)| starts_with_module(path))
+}
+
 /// Implements the "Log" trait from the externally used "log" crate.
 impl Log for Logger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        // No filtering beyond what the log crate already does based on level.
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Without module filters, there is no filtering beyond what the log crate already does
+        // based on level.
+        extract_guard(self.module_filters.read()).is_empty()
+            || metadata.level() <= self.module_max_level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
-        let msg = format!(
-            "{} {} {}",
-            LocalTime::now(),
-            self.create_prefix(record),
-            record.args()
-        );
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let msg = if self.json_format() {
+            self.create_json_object(record)
+        } else {
+            format!(
+                "{} {} {}",
+                LocalTime::now(),
+                self.create_prefix(record),
+                record.args()
+            )
+        };
         self.write_log(&msg);
     }
 
@@ -496,8 +686,13 @@ mod tests {
         }
 
         fn mock_log(&self, level: Level, msg: &str) {
+            self.mock_log_from("", level, msg);
+        }
+
+        fn mock_log_from(&self, target: &str, level: Level, msg: &str) {
             self.log(
                 &log::Record::builder()
+                    .target(target)
                     .level(level)
                     .args(format_args!("{}", msg))
                     .file(Some(LOG_SOURCE))
@@ -656,6 +851,21 @@ mod tests {
         let logger = Logger::mock_new();
         let mut reader = logger.mock_init();
         let crnt_thread_name = logger.get_thread_name();
+        // Test with labels.
+        logger.set_labels(BTreeMap::from([
+            ("pool".to_string(), "a".to_string()),
+            ("zone".to_string(), "b".to_string()),
+        ]));
+        logger.mock_log(Level::Info, "labeled");
+        validate_log(
+            &mut Box::new(&mut reader),
+            &format!(
+                "[TEST-INSTANCE-ID:{}:INFO:logger.rs:0] [pool=a,zone=b] labeled\n",
+                crnt_thread_name
+            ),
+        );
+        logger.set_labels(BTreeMap::new());
+
         // Test with empty instance id.
         logger.set_instance_id("".to_string());
 
@@ -755,6 +965,111 @@ mod tests {
         assert!(r.is_ok());
     }
 
+    #[test]
+    fn test_module_filters() {
+        assert!(is_in_module("vmm::devices::virtio::block", "vmm::devices"));
+        assert!(is_in_module(
+            "vmm::devices::virtio::block",
+            "devices::virtio::block"
+        ));
+        assert!(is_in_module(
+            "vmm::devices::virtio::block::device",
+            "devices::virtio"
+        ));
+        assert!(!is_in_module(
+            "vmm::devices::virtio::block",
+            "virtio::block"
+        ));
+        assert!(!is_in_module(
+            "vmm::devices::virtio::balloon",
+            "devices::virtio::ball"
+        ));
+        assert!(!is_in_module("vmm::devices", "devices::virtio"));
+
+        let logger = Logger::mock_new();
+        let mut reader = logger.mock_init();
+        let crnt_thread_name = logger.get_thread_name();
+        // The filters are set directly, as the max level of the log crate is shared by the tests.
+        *logger.module_filters.write().unwrap() = vec![
+            ("devices::virtio".to_string(), LevelFilter::Error),
+            ("devices::virtio::block".to_string(), LevelFilter::Debug),
+        ];
+        assert_eq!(
+            logger.module_max_level("vmm::devices::virtio::net"),
+            LevelFilter::Error
+        );
+        assert_eq!(
+            logger.module_max_level("vmm::devices::virtio::block::device"),
+            LevelFilter::Debug
+        );
+        assert_eq!(logger.module_max_level("vmm::builder"), DEFAULT_MAX_LEVEL);
+
+        logger.mock_log_from("vmm::devices::virtio::net", Level::Warn, "net");
+        let mut log = Vec::new();
+        reader.read_to_end(&mut log).unwrap();
+        assert!(log.is_empty());
+
+        logger.mock_log_from("vmm::devices::virtio::block::device", Level::Debug, "block");
+        validate_log(
+            &mut Box::new(&mut reader),
+            &format!(
+                "[TEST-INSTANCE-ID:{}:DEBUG:logger.rs:0] block\n",
+                crnt_thread_name
+            ),
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        let logger = Logger::mock_new();
+        let mut reader = logger.mock_init();
+        let crnt_thread_name = logger.get_thread_name();
+        logger.set_json_format(true);
+
+        let read_object = |reader: &mut LogReader| {
+            let mut log = String::new();
+            reader.read_to_string(&mut log).unwrap();
+            assert!(log.ends_with('\n'));
+            assert_eq!(log.lines().count(), 1);
+            serde_json::from_str::<serde_json::Value>(&log).unwrap()
+        };
+
+        logger.mock_log_from("vmm::builder", Level::Warn, "a \"quoted\" message");
+        let object = read_object(&mut reader);
+        assert_eq!(object["level"], "WARN");
+        assert_eq!(object["module"], "vmm::builder");
+        assert_eq!(object["message"], "a \"quoted\" message");
+        assert_eq!(object["vm_id"], TEST_INSTANCE_ID);
+        assert_eq!(object["thread"], crnt_thread_name.as_str());
+        assert_eq!(object["file"], LOG_SOURCE);
+        assert_eq!(object["line"], LOG_LINE);
+        assert!(object["timestamp"].is_string());
+
+        logger.set_include_origin(false, false);
+        logger.mock_log(Level::Error, "error");
+        let object = read_object(&mut reader);
+        assert_eq!(object["level"], "ERROR");
+        assert!(object.get("file").is_none());
+        assert!(object.get("line").is_none());
+        assert!(object.get("labels").is_none());
+
+        logger.set_labels(BTreeMap::from([("pool".to_string(), "a".to_string())]));
+        logger.mock_log(Level::Error, "error");
+        let object = read_object(&mut reader);
+        assert_eq!(object["labels"], serde_json::json!({"pool": "a"}));
+
+        // The header is written as a JSON object as well.
+        let logger = Logger::mock_new();
+        logger.set_json_format(true);
+        let (writer, mut reader) = log_channel();
+        logger
+            .init(TEST_APP_HEADER.to_string(), Box::new(writer))
+            .unwrap();
+        let object = read_object(&mut reader);
+        assert_eq!(object["message"], TEST_APP_HEADER);
+        assert_eq!(object["level"], "INFO");
+    }
+
     #[test]
     fn test_static_logger() {
         log::set_max_level(log::LevelFilter::Info);
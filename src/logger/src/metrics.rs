@@ -61,14 +61,16 @@
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::Write;
 use std::ops::Deref;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Mutex, OnceLock};
 
 #[cfg(target_arch = "aarch64")]
 use log::warn;
+use serde::ser::{SerializeMap, SerializeSeq, SerializeStruct};
 use serde::{Serialize, Serializer};
 #[cfg(target_arch = "aarch64")]
 use vm_superio::rtc_pl031::RtcEvents;
@@ -86,9 +88,34 @@ pub static METRICS: Metrics<FirecrackerMetrics, FcLineWriter> =
 pub struct Metrics<T: Serialize, M: Write + Send> {
     // Metrics will get flushed here.
     metrics_buf: OnceLock<Mutex<M>>,
+    // Set when the last write of the metrics failed.
+    write_failed: AtomicBool,
+    // Written along with each flush of the metrics, if set.
+    labels: OnceLock<MetricsLabels>,
     pub app_metrics: T,
 }
 
+/// The fields written along with each flush of the metrics, which attribute them to a microVM
+/// regardless of the file they are written to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsLabels {
+    /// The ID of the microVM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// Labels of the microVM, e.g. the host pool it runs in.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+// The metrics of a flush, along with their labels.
+#[derive(Serialize)]
+struct LabeledMetrics<'a, T: Serialize> {
+    #[serde(flatten)]
+    labels: &'a MetricsLabels,
+    #[serde(flatten)]
+    metrics: &'a T,
+}
+
 impl<T: Serialize + Debug, M: Write + Send + Debug> Metrics<T, M> {
     /// Creates a new instance of the current metrics.
     // TODO: We need a better name than app_metrics (something that says that these are the actual
@@ -96,6 +123,8 @@ impl<T: Serialize + Debug, M: Write + Send + Debug> Metrics<T, M> {
     pub const fn new(app_metrics: T) -> Metrics<T, M> {
         Metrics {
             metrics_buf: OnceLock::new(),
+            write_failed: AtomicBool::new(false),
+            labels: OnceLock::new(),
             app_metrics,
         }
     }
@@ -118,6 +147,14 @@ impl<T: Serialize + Debug, M: Write + Send + Debug> Metrics<T, M> {
             .map_err(|_| MetricsError::AlreadyInitialized)
     }
 
+    /// Sets the labels written along with each flush of the metrics (once and only once). The
+    /// metrics have to serialize as a JSON object for the labels to be written along with them.
+    pub fn set_labels(&self, labels: MetricsLabels) -> Result<(), MetricsError> {
+        self.labels
+            .set(labels)
+            .map_err(|_| MetricsError::AlreadyInitialized)
+    }
+
     /// Writes metrics to the destination provided as argument upon initialization of the metrics.
     /// Upon failure, an error is returned if metrics system is initialized and metrics could not be
     /// written.
@@ -138,8 +175,31 @@ impl<T: Serialize + Debug, M: Write + Send + Debug> Metrics<T, M> {
     /// The alternative is to hold a Mutex over the entire function call, but this increases the
     /// known deadlock potential.
     pub fn write(&self) -> Result<bool, MetricsError> {
+        let res = self.write_to_buf();
+        self.write_failed.store(res.is_err(), Ordering::Relaxed);
+        res
+    }
+
+    /// Returns whether the destination of the metrics was provided.
+    pub fn is_initialized(&self) -> bool {
+        self.metrics_buf.get().is_some()
+    }
+
+    /// Returns whether the last write of the metrics failed.
+    pub fn last_write_failed(&self) -> bool {
+        self.write_failed.load(Ordering::Relaxed)
+    }
+
+    fn write_to_buf(&self) -> Result<bool, MetricsError> {
         if let Some(lock) = self.metrics_buf.get() {
-            match serde_json::to_string(&self.app_metrics) {
+            let res = match self.labels.get() {
+                Some(labels) => serde_json::to_string(&LabeledMetrics {
+                    labels,
+                    metrics: &self.app_metrics,
+                }),
+                None => serde_json::to_string(&self.app_metrics),
+            };
+            match res {
                 Ok(msg) => {
                     if let Ok(mut guard) = lock.lock() {
                         // No need to explicitly call flush because the underlying LineWriter
@@ -246,6 +306,27 @@ impl SharedStoreMetric {
     }
 }
 
+/// Representation of a metric that holds the highest value recorded since the last flush, and is
+/// expected to be updated from more than one thread.
+#[derive(Debug, Default)]
+pub struct SharedMaxMetric(AtomicUsize);
+impl SharedMaxMetric {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    /// Records `value`, which is kept if it is the highest one since the last flush.
+    pub fn record(&self, value: usize) {
+        self.0.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Returns the highest value recorded since the last flush.
+    pub fn fetch(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 impl IncMetric for SharedIncMetric {
     // While the order specified for this operation is still Relaxed, the actual instruction will
     // be an asm "LOCK; something" and thus atomic across multiple threads, simply because of the
@@ -292,6 +373,13 @@ impl Serialize for SharedStoreMetric {
     }
 }
 
+impl Serialize for SharedMaxMetric {
+    /// Resets the metric, the same way `SharedIncMetric` counters are reset on flush.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0.swap(0, Ordering::Relaxed) as u64)
+    }
+}
+
 /// Reporter object which computes the process wall time and
 /// process CPU time and populates the metric with the results.
 #[derive(Debug)]
@@ -358,6 +446,10 @@ pub struct ApiServerMetrics {
     pub sync_response_fails: SharedIncMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedIncMetric,
+    /// Number of API requests rejected for lacking a valid authentication token.
+    pub unauthorized_requests: SharedIncMetric,
+    /// Number of API requests served without waiting for the VMM thread.
+    pub concurrent_requests: SharedIncMetric,
 }
 impl ApiServerMetrics {
     /// Const default construction.
@@ -367,6 +459,8 @@ impl ApiServerMetrics {
             process_startup_time_cpu_us: SharedStoreMetric::new(),
             sync_response_fails: SharedIncMetric::new(),
             sync_vmm_send_timeout_count: SharedIncMetric::new(),
+            unauthorized_requests: SharedIncMetric::new(),
+            concurrent_requests: SharedIncMetric::new(),
         }
     }
 }
@@ -374,6 +468,8 @@ impl ApiServerMetrics {
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
 #[derive(Debug, Default, Serialize)]
 pub struct GetRequestsMetrics {
+    /// Number of GETs for getting the health of the microVM.
+    pub health_count: SharedIncMetric,
     /// Number of GETs for getting information on the instance.
     pub instance_info_count: SharedIncMetric,
     /// Number of GETs for getting status on attaching machine configuration.
@@ -387,6 +483,7 @@ impl GetRequestsMetrics {
     /// Const default construction.
     pub const fn new() -> Self {
         Self {
+            health_count: SharedIncMetric::new(),
             instance_info_count: SharedIncMetric::new(),
             machine_cfg_count: SharedIncMetric::new(),
             mmds_count: SharedIncMetric::new(),
@@ -486,6 +583,14 @@ pub struct PatchRequestsMetrics {
     pub mmds_count: SharedIncMetric,
     /// Number of failures in PATCHing an mmds.
     pub mmds_fails: SharedIncMetric,
+    /// Number of tries to PATCH a vsock device.
+    pub vsock_count: SharedIncMetric,
+    /// Number of failures in PATCHing a vsock device.
+    pub vsock_fails: SharedIncMetric,
+    /// Number of tries to PATCH the logger.
+    pub logger_count: SharedIncMetric,
+    /// Number of failures in PATCHing the logger.
+    pub logger_fails: SharedIncMetric,
 }
 impl PatchRequestsMetrics {
     /// Const default construction.
@@ -499,6 +604,10 @@ impl PatchRequestsMetrics {
             machine_cfg_fails: SharedIncMetric::new(),
             mmds_count: SharedIncMetric::new(),
             mmds_fails: SharedIncMetric::new(),
+            vsock_count: SharedIncMetric::new(),
+            vsock_fails: SharedIncMetric::new(),
+            logger_count: SharedIncMetric::new(),
+            logger_fails: SharedIncMetric::new(),
         }
     }
 }
@@ -536,6 +645,12 @@ pub struct BalloonDeviceMetrics {
     pub deflate_count: SharedIncMetric,
     /// Number of times when handling events on a balloon device failed.
     pub event_fails: SharedIncMetric,
+    /// Number of processes killed by the OOM killer of the guest, as reported in the balloon
+    /// statistics.
+    pub guest_oom_kills: SharedIncMetric,
+    /// Number of guest allocations which stalled on direct reclaim, as reported in the balloon
+    /// statistics.
+    pub guest_alloc_stalls: SharedIncMetric,
 }
 impl BalloonDeviceMetrics {
     /// Const default construction.
@@ -547,6 +662,8 @@ impl BalloonDeviceMetrics {
             stats_update_fails: SharedIncMetric::new(),
             deflate_count: SharedIncMetric::new(),
             event_fails: SharedIncMetric::new(),
+            guest_oom_kills: SharedIncMetric::new(),
+            guest_alloc_stalls: SharedIncMetric::new(),
         }
     }
 }
@@ -587,8 +704,23 @@ pub struct BlockDeviceMetrics {
     /// Number of rate limiter throttling events.
     pub rate_limiter_throttled_events: SharedIncMetric,
     /// Number of virtio events throttled because of the IO engine.
-    /// This happens when the io_uring submission queue is full.
+    /// This happens when the io_uring submission queue is full, or when a drive has as many
+    /// requests in flight as allowed.
     pub io_engine_throttled_events: SharedIncMetric,
+    /// Number of times a request could not be submitted because the io_uring instance of the
+    /// drive was full.
+    pub io_engine_ring_full_events: SharedIncMetric,
+    /// Number of times a drive stopped submitting requests because it reached its maximum
+    /// number of requests in flight.
+    pub in_flight_limit_events: SharedIncMetric,
+    /// Number of busy-poll windows which ended with requests made available by the guest.
+    pub busy_poll_hits: SharedIncMetric,
+    /// Number of busy-poll windows which expired before the guest made requests available.
+    pub busy_poll_misses: SharedIncMetric,
+    /// Number of interrupts notifying the guest of completed requests.
+    pub interrupt_count: SharedIncMetric,
+    /// Number of batches of completed requests whose interrupt was suppressed by the guest.
+    pub suppressed_interrupt_count: SharedIncMetric,
 }
 impl BlockDeviceMetrics {
     /// Const default construction.
@@ -611,7 +743,182 @@ impl BlockDeviceMetrics {
             write_count: SharedIncMetric::new(),
             rate_limiter_throttled_events: SharedIncMetric::new(),
             io_engine_throttled_events: SharedIncMetric::new(),
+            io_engine_ring_full_events: SharedIncMetric::new(),
+            in_flight_limit_events: SharedIncMetric::new(),
+            busy_poll_hits: SharedIncMetric::new(),
+            busy_poll_misses: SharedIncMetric::new(),
+            interrupt_count: SharedIncMetric::new(),
+            suppressed_interrupt_count: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Number of buckets of a `LatencyHistogram`. The last one counts the latencies of about 67
+/// seconds or more.
+pub const LATENCY_HISTOGRAM_BUCKETS: usize = 100;
+
+/// Histogram of latencies, in microseconds, serialized as the number of latencies recorded
+/// since the last flush, along with their 50th, 95th and 99th percentiles and their maximum.
+///
+/// The latencies below 4 microseconds have a bucket each, and every power of two above is split
+/// into 4 buckets, so that a percentile is at most 25% above the exact value. A percentile is
+/// reported as the highest latency of its bucket, capped by the maximum latency.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicUsize; LATENCY_HISTOGRAM_BUCKETS],
+    max_us: AtomicUsize,
+}
+impl LatencyHistogram {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        const BUCKET: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            buckets: [BUCKET; LATENCY_HISTOGRAM_BUCKETS],
+            max_us: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a latency of `latency_us` microseconds.
+    pub fn record(&self, latency_us: u64) {
+        self.buckets[Self::bucket_index(latency_us)].fetch_add(1, Ordering::Relaxed);
+        self.max_us.fetch_max(
+            usize::try_from(latency_us).unwrap_or(usize::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the number of latencies recorded since the last flush.
+    pub fn count(&self) -> usize {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn bucket_index(latency_us: u64) -> usize {
+        if latency_us < 4 {
+            return latency_us as usize;
+        }
+        // The power of two right below the latency, which is at least 2.
+        let octave = 63 - latency_us.leading_zeros() as usize;
+        let sub_bucket = ((latency_us >> (octave - 2)) & 3) as usize;
+        (4 * (octave - 1) + sub_bucket).min(LATENCY_HISTOGRAM_BUCKETS - 1)
+    }
+
+    // Highest latency counted in the bucket at `index`.
+    fn bucket_max(index: usize) -> u64 {
+        if index < 4 {
+            return index as u64;
+        }
+        let octave = index / 4 + 1;
+        ((5 + (index % 4) as u64) << (octave - 2)) - 1
+    }
+}
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Serialize for LatencyHistogram {
+    /// Resets the histogram, the same way `SharedIncMetric` counters are reset on flush.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut counts = [0usize; LATENCY_HISTOGRAM_BUCKETS];
+        for (count, bucket) in counts.iter_mut().zip(self.buckets.iter()) {
+            *count = bucket.swap(0, Ordering::Relaxed);
+        }
+        let max_us = self.max_us.swap(0, Ordering::Relaxed) as u64;
+        let total: usize = counts.iter().sum();
+
+        let percentile = |percent: usize| {
+            if total == 0 {
+                return 0;
+            }
+            // Rank of the percentile among the sorted latencies, starting from 1.
+            let rank = (total * percent + 99) / 100;
+            let mut seen = 0;
+            for (index, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    return Self::bucket_max(index).min(max_us);
+                }
+            }
+            max_us
+        };
+
+        let mut state = serializer.serialize_struct("LatencyHistogram", 5)?;
+        state.serialize_field("count", &(total as u64))?;
+        state.serialize_field("p50_us", &percentile(50))?;
+        state.serialize_field("p95_us", &percentile(95))?;
+        state.serialize_field("p99_us", &percentile(99))?;
+        state.serialize_field("max_us", &max_us)?;
+        state.end()
+    }
+}
+
+/// Latencies of the requests of a drive, from their submission by the guest to their completion.
+#[derive(Debug, Default, Serialize)]
+pub struct DriveLatencyMetrics {
+    #[serde(skip)]
+    drive_id: OnceLock<String>,
+    /// Latencies of the read requests.
+    pub read: LatencyHistogram,
+    /// Latencies of the write requests.
+    pub write: LatencyHistogram,
+    /// Latencies of the flush requests.
+    pub flush: LatencyHistogram,
+}
+impl DriveLatencyMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            drive_id: OnceLock::new(),
+            read: LatencyHistogram::new(),
+            write: LatencyHistogram::new(),
+            flush: LatencyHistogram::new(),
+        }
+    }
+}
+
+/// Highest number of drives whose request latencies are recorded.
+pub const MAX_DRIVE_LATENCY_METRICS: usize = 32;
+
+/// Request latencies of each drive, serialized as an object keyed by the drive ID.
+#[derive(Debug)]
+pub struct BlockLatencyMetrics {
+    drives: [DriveLatencyMetrics; MAX_DRIVE_LATENCY_METRICS],
+}
+impl BlockLatencyMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        const DRIVE: DriveLatencyMetrics = DriveLatencyMetrics::new();
+        Self {
+            drives: [DRIVE; MAX_DRIVE_LATENCY_METRICS],
+        }
+    }
+
+    /// Registers the drive with the given ID, so that its latencies are serialized, and returns
+    /// them. A drive registered again, e.g. when it is restored, gets the same latencies.
+    pub fn register(&self, drive_id: &str) -> Option<&DriveLatencyMetrics> {
+        self.drives.iter().find(|drive| {
+            let id = drive.drive_id.get_or_init(|| drive_id.to_string());
+            id == drive_id
+        })
+    }
+}
+impl Default for BlockLatencyMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Serialize for BlockLatencyMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for drive in &self.drives {
+            if let Some(id) = drive.drive_id.get() {
+                map.serialize_entry(id, drive)?;
+            }
         }
+        map.end()
     }
 }
 
@@ -771,6 +1078,40 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
+    /// Number of frames mirrored by the packet capture.
+    pub capture_frames: SharedIncMetric,
+    /// Number of frames the packet capture failed to mirror.
+    pub capture_fails: SharedIncMetric,
+    /// Number of DHCP replies sent to the guest by the internal responder.
+    pub dhcp_replies: SharedIncMetric,
+    /// Number of DNS replies sent to the guest by the internal responder.
+    pub dns_replies: SharedIncMetric,
+    /// Number of DHCP and DNS requests dropped by the internal responder.
+    pub responder_drops: SharedIncMetric,
+    /// Number of TCP connections relayed by the user-mode network stack.
+    pub user_net_tcp_conns: SharedIncMetric,
+    /// Number of UDP flows relayed by the user-mode network stack.
+    pub user_net_udp_flows: SharedIncMetric,
+    /// Number of frames of the guest dropped by the user-mode network stack.
+    pub user_net_drops: SharedIncMetric,
+    /// Number of failures of the user-mode network stack.
+    pub user_net_fails: SharedIncMetric,
+    /// Number of ARP and ICMP echo replies sent on behalf of the guest while it was paused.
+    pub pause_replies: SharedIncMetric,
+    /// Number of frames to the guest dropped while it was paused.
+    pub pause_drops: SharedIncMetric,
+    /// Number of busy-poll windows which ended with frames made available by the guest.
+    pub tx_busy_poll_hits: SharedIncMetric,
+    /// Number of busy-poll windows which expired before the guest made frames available.
+    pub tx_busy_poll_misses: SharedIncMetric,
+    /// Number of interrupts notifying the guest of received frames.
+    pub rx_interrupt_count: SharedIncMetric,
+    /// Number of interrupts notifying the guest of transmitted frames.
+    pub tx_interrupt_count: SharedIncMetric,
+    /// Number of batches of received frames whose interrupt was suppressed by the guest.
+    pub rx_suppressed_interrupt_count: SharedIncMetric,
+    /// Number of batches of transmitted frames whose interrupt was suppressed by the guest.
+    pub tx_suppressed_interrupt_count: SharedIncMetric,
 }
 impl NetDeviceMetrics {
     /// Const default construction.
@@ -803,10 +1144,125 @@ impl NetDeviceMetrics {
             tx_rate_limiter_event_count: SharedIncMetric::new(),
             tx_rate_limiter_throttled: SharedIncMetric::new(),
             tx_spoofed_mac_count: SharedIncMetric::new(),
+            capture_frames: SharedIncMetric::new(),
+            capture_fails: SharedIncMetric::new(),
+            dhcp_replies: SharedIncMetric::new(),
+            dns_replies: SharedIncMetric::new(),
+            responder_drops: SharedIncMetric::new(),
+            user_net_tcp_conns: SharedIncMetric::new(),
+            user_net_udp_flows: SharedIncMetric::new(),
+            user_net_drops: SharedIncMetric::new(),
+            user_net_fails: SharedIncMetric::new(),
+            pause_replies: SharedIncMetric::new(),
+            pause_drops: SharedIncMetric::new(),
+            tx_busy_poll_hits: SharedIncMetric::new(),
+            tx_busy_poll_misses: SharedIncMetric::new(),
+            rx_interrupt_count: SharedIncMetric::new(),
+            tx_interrupt_count: SharedIncMetric::new(),
+            rx_suppressed_interrupt_count: SharedIncMetric::new(),
+            tx_suppressed_interrupt_count: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Counters of a network interface, detailing where its frames are held back or dropped.
+#[derive(Debug, Default, Serialize)]
+pub struct NetInterfaceMetrics {
+    #[serde(skip)]
+    iface_id: OnceLock<String>,
+    /// Number of bytes written to the RX queue.
+    pub rx_bytes: SharedIncMetric,
+    /// Number of frames written to the RX queue.
+    pub rx_packets: SharedIncMetric,
+    /// Number of bytes read from the TX queue and written to the tap.
+    pub tx_bytes: SharedIncMetric,
+    /// Number of frames read from the TX queue and written to the tap.
+    pub tx_packets: SharedIncMetric,
+    /// Number of reads of the tap which found no frame to read.
+    pub tap_read_eagain: SharedIncMetric,
+    /// Number of frames of the guest which could not be written to the tap.
+    pub tap_write_fails: SharedIncMetric,
+    /// Number of times a frame was held back because the RX queue had no available buffer.
+    pub rx_ring_full: SharedIncMetric,
+    /// Number of frames dropped because the RX queue had no available buffer.
+    pub rx_ring_full_drops: SharedIncMetric,
+    /// Number of times a frame was held back by the RX rate limiter.
+    pub rx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of frames dropped because of the RX rate limiter.
+    pub rx_rate_limiter_drops: SharedIncMetric,
+    /// Number of times the TX queue was held back by the TX rate limiter.
+    pub tx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of times a frame was larger than the RX buffer of the guest it was written to.
+    pub rx_oversized_frames: SharedIncMetric,
+    /// Number of frames of the guest larger than the largest frame of the device.
+    pub tx_oversized_frames: SharedIncMetric,
+}
+impl NetInterfaceMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            iface_id: OnceLock::new(),
+            rx_bytes: SharedIncMetric::new(),
+            rx_packets: SharedIncMetric::new(),
+            tx_bytes: SharedIncMetric::new(),
+            tx_packets: SharedIncMetric::new(),
+            tap_read_eagain: SharedIncMetric::new(),
+            tap_write_fails: SharedIncMetric::new(),
+            rx_ring_full: SharedIncMetric::new(),
+            rx_ring_full_drops: SharedIncMetric::new(),
+            rx_rate_limiter_throttled: SharedIncMetric::new(),
+            rx_rate_limiter_drops: SharedIncMetric::new(),
+            tx_rate_limiter_throttled: SharedIncMetric::new(),
+            rx_oversized_frames: SharedIncMetric::new(),
+            tx_oversized_frames: SharedIncMetric::new(),
         }
     }
 }
 
+/// Highest number of network interfaces whose counters are reported.
+pub const MAX_NET_INTERFACE_METRICS: usize = 32;
+
+/// Counters of each network interface, serialized as an object keyed by the interface ID.
+#[derive(Debug)]
+pub struct NetInterfacesMetrics {
+    ifaces: [NetInterfaceMetrics; MAX_NET_INTERFACE_METRICS],
+}
+impl NetInterfacesMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        const IFACE: NetInterfaceMetrics = NetInterfaceMetrics::new();
+        Self {
+            ifaces: [IFACE; MAX_NET_INTERFACE_METRICS],
+        }
+    }
+
+    /// Registers the interface with the given ID, so that its counters are serialized, and
+    /// returns them. An interface registered again, e.g. when it is restored, gets the same
+    /// counters.
+    pub fn register(&self, iface_id: &str) -> Option<&NetInterfaceMetrics> {
+        self.ifaces.iter().find(|iface| {
+            let id = iface.iface_id.get_or_init(|| iface_id.to_string());
+            id == iface_id
+        })
+    }
+}
+impl Default for NetInterfacesMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Serialize for NetInterfacesMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for iface in &self.ifaces {
+            if let Some(id) = iface.iface_id.get() {
+                map.serialize_entry(id, iface)?;
+            }
+        }
+        map.end()
+    }
+}
+
 /// Performance metrics related for the moment only to snapshots.
 // These store the duration of creating/loading a snapshot and of
 // pausing/resuming the microVM.
@@ -905,17 +1361,50 @@ impl RtcEvents for &'static RTCDeviceMetrics {
     }
 }
 
+/// Metrics for the seccomp filter of a thread category.
+#[derive(Debug, Default, Serialize)]
+pub struct SeccompThreadMetrics {
+    /// Number of threads the filter was installed on.
+    pub installed_filters: SharedIncMetric,
+    /// Number of syscalls rejected by the filter.
+    pub num_faults: SharedIncMetric,
+}
+impl SeccompThreadMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            installed_filters: SharedIncMetric::new(),
+            num_faults: SharedIncMetric::new(),
+        }
+    }
+}
+
 /// Metrics for the seccomp filtering.
 #[derive(Debug, Default, Serialize)]
 pub struct SeccompMetrics {
     /// Number of errors inside the seccomp filtering.
     pub num_faults: SharedStoreMetric,
+    /// Metrics for the filter of the VMM thread.
+    pub vmm: SeccompThreadMetrics,
+    /// Metrics for the filter of the API thread.
+    pub api: SeccompThreadMetrics,
+    /// Metrics for the filter of the vCPU threads.
+    pub vcpu: SeccompThreadMetrics,
+    /// Metrics for the filter of the thread serving the shared directories.
+    pub shared_dirs: SeccompThreadMetrics,
+    /// Metrics for the filter of the thread relaying the API vsock connections.
+    pub api_vsock: SeccompThreadMetrics,
 }
 impl SeccompMetrics {
     /// Const default construction.
     pub const fn new() -> Self {
         Self {
             num_faults: SharedStoreMetric::new(),
+            vmm: SeccompThreadMetrics::new(),
+            api: SeccompThreadMetrics::new(),
+            vcpu: SeccompThreadMetrics::new(),
+            shared_dirs: SeccompThreadMetrics::new(),
+            api_vsock: SeccompThreadMetrics::new(),
         }
     }
 }
@@ -970,6 +1459,8 @@ pub struct SignalMetrics {
     pub sighup: SharedStoreMetric,
     /// Number of times that SIGILL was handled.
     pub sigill: SharedStoreMetric,
+    /// Number of times that the signal requesting the emergency snapshot was handled.
+    pub emergency_snapshot: SharedIncMetric,
 }
 impl SignalMetrics {
     /// Const default construction.
@@ -982,6 +1473,7 @@ impl SignalMetrics {
             sigpipe: SharedIncMetric::new(),
             sighup: SharedStoreMetric::new(),
             sigill: SharedStoreMetric::new(),
+            emergency_snapshot: SharedIncMetric::new(),
         }
     }
 }
@@ -999,6 +1491,10 @@ pub struct VcpuMetrics {
     pub exit_mmio_write: SharedIncMetric,
     /// Number of errors during this VCPU's run.
     pub failures: SharedIncMetric,
+    /// Number of times a vCPU was found stalled.
+    pub stalls: SharedIncMetric,
+    /// Number of KVM exits of each vCPU, by exit reason.
+    pub exits: VcpuExitsMetrics,
 }
 impl VcpuMetrics {
     /// Const default construction.
@@ -1009,10 +1505,106 @@ impl VcpuMetrics {
             exit_mmio_read: SharedIncMetric::new(),
             exit_mmio_write: SharedIncMetric::new(),
             failures: SharedIncMetric::new(),
+            stalls: SharedIncMetric::new(),
+            exits: VcpuExitsMetrics::new(),
         }
     }
 }
 
+/// Number of KVM exits of a vCPU, by exit reason.
+#[derive(Debug, Default, Serialize)]
+pub struct VcpuExitMetrics {
+    /// Number of exits for handling input IO.
+    pub io_in: SharedIncMetric,
+    /// Number of exits for handling output IO.
+    pub io_out: SharedIncMetric,
+    /// Number of exits for handling MMIO reads.
+    pub mmio_read: SharedIncMetric,
+    /// Number of exits for handling MMIO writes.
+    pub mmio_write: SharedIncMetric,
+    /// Number of exits because the guest halted the vCPU.
+    pub hlt: SharedIncMetric,
+    /// Number of exits because the guest triggered a shutdown.
+    pub shutdown: SharedIncMetric,
+    /// Number of exits for system events, such as a guest reset or power off.
+    pub system_event: SharedIncMetric,
+    /// Number of exits for debug events, such as breakpoints or single steps.
+    pub debug: SharedIncMetric,
+    /// Number of exits because the hardware failed to enter the guest.
+    pub fail_entry: SharedIncMetric,
+    /// Number of exits because of an internal KVM error.
+    pub internal_error: SharedIncMetric,
+    /// Number of exits for any other reason.
+    pub other: SharedIncMetric,
+    /// Number of times `KVM_RUN` was interrupted by a signal, to handle an event of the VMM.
+    pub interrupted: SharedIncMetric,
+}
+impl VcpuExitMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            io_in: SharedIncMetric::new(),
+            io_out: SharedIncMetric::new(),
+            mmio_read: SharedIncMetric::new(),
+            mmio_write: SharedIncMetric::new(),
+            hlt: SharedIncMetric::new(),
+            shutdown: SharedIncMetric::new(),
+            system_event: SharedIncMetric::new(),
+            debug: SharedIncMetric::new(),
+            fail_entry: SharedIncMetric::new(),
+            internal_error: SharedIncMetric::new(),
+            other: SharedIncMetric::new(),
+            interrupted: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Highest number of vCPUs whose exits are counted, which is the highest number of vCPUs
+/// Firecracker supports.
+pub const MAX_VCPU_EXIT_METRICS: usize = 1024;
+
+/// KVM exit counters of each vCPU, serialized as an array indexed by the vCPU index.
+#[derive(Debug)]
+pub struct VcpuExitsMetrics {
+    // Number of vCPUs registered, which is the length of the serialized array.
+    count: AtomicUsize,
+    vcpus: [VcpuExitMetrics; MAX_VCPU_EXIT_METRICS],
+}
+impl VcpuExitsMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        const VCPU: VcpuExitMetrics = VcpuExitMetrics::new();
+        Self {
+            count: AtomicUsize::new(0),
+            vcpus: [VCPU; MAX_VCPU_EXIT_METRICS],
+        }
+    }
+
+    /// Registers the vCPU with the given index, so that its counters are serialized, and returns
+    /// them.
+    pub fn register(&self, index: u16) -> Option<&VcpuExitMetrics> {
+        let index = usize::from(index);
+        let metrics = self.vcpus.get(index)?;
+        self.count.fetch_max(index + 1, Ordering::Relaxed);
+        Some(metrics)
+    }
+}
+impl Default for VcpuExitsMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Serialize for VcpuExitsMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let count = self.count.load(Ordering::Relaxed);
+        let mut seq = serializer.serialize_seq(Some(count))?;
+        for metrics in &self.vcpus[..count] {
+            seq.serialize_element(metrics)?;
+        }
+        seq.end()
+    }
+}
+
 /// Metrics specific to the machine manager as a whole.
 #[derive(Debug, Default, Serialize)]
 pub struct VmmMetrics {
@@ -1020,6 +1612,12 @@ pub struct VmmMetrics {
     pub device_events: SharedIncMetric,
     /// Metric for signaling a panic has occurred.
     pub panic_count: SharedStoreMetric,
+    /// Number of times the UFFD page fault handler exited while monitored.
+    pub uffd_handler_exits: SharedIncMetric,
+    /// Number of bytes of guest memory written during the last working set sampling interval.
+    pub working_set_bytes: SharedStoreMetric,
+    /// Time, in microseconds, from the boot request to the guest signaling it is ready.
+    pub guest_ready_time_us: SharedStoreMetric,
 }
 impl VmmMetrics {
     /// Const default construction.
@@ -1027,6 +1625,9 @@ impl VmmMetrics {
         Self {
             device_events: SharedIncMetric::new(),
             panic_count: SharedStoreMetric::new(),
+            uffd_handler_exits: SharedIncMetric::new(),
+            working_set_bytes: SharedStoreMetric::new(),
+            guest_ready_time_us: SharedStoreMetric::new(),
         }
     }
 }
@@ -1074,6 +1675,14 @@ pub struct VsockDeviceMetrics {
     pub tx_write_fails: SharedIncMetric,
     /// Number of times read() has failed.
     pub rx_read_fails: SharedIncMetric,
+    /// Number of guest connections served by the clock port.
+    pub clock_requests: SharedIncMetric,
+    /// Number of host "resync" commands answered.
+    pub resync_requests: SharedIncMetric,
+    /// Number of connections refused because the connection limit was reached.
+    pub conns_rejected: SharedIncMetric,
+    /// Number of connections killed because no data went through them for too long.
+    pub conns_idle_killed: SharedIncMetric,
 }
 impl VsockDeviceMetrics {
     /// Const default construction.
@@ -1099,6 +1708,10 @@ impl VsockDeviceMetrics {
             tx_flush_fails: SharedIncMetric::new(),
             tx_write_fails: SharedIncMetric::new(),
             rx_read_fails: SharedIncMetric::new(),
+            clock_requests: SharedIncMetric::new(),
+            resync_requests: SharedIncMetric::new(),
+            conns_rejected: SharedIncMetric::new(),
+            conns_idle_killed: SharedIncMetric::new(),
         }
     }
 }
@@ -1135,6 +1748,250 @@ impl EntropyDeviceMetrics {
     }
 }
 
+/// Memory hotplug device related metrics.
+#[derive(Debug, Default, Serialize)]
+pub struct MemoryHotplugDeviceMetrics {
+    /// Number of device activation failures.
+    pub activate_fails: SharedIncMetric,
+    /// Number of request queue event handling failures.
+    pub event_fails: SharedIncMetric,
+    /// Number of request queue events handled.
+    pub queue_event_count: SharedIncMetric,
+    /// Number of plug requests received from the guest.
+    pub plug_count: SharedIncMetric,
+    /// Number of plug requests which were refused or failed.
+    pub plug_fails: SharedIncMetric,
+    /// Number of unplug requests received from the guest.
+    pub unplug_count: SharedIncMetric,
+    /// Number of unplug requests which were refused or failed.
+    pub unplug_fails: SharedIncMetric,
+    /// Number of failures in releasing unplugged memory back to the host.
+    pub unplug_discard_fails: SharedIncMetric,
+}
+impl MemoryHotplugDeviceMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            activate_fails: SharedIncMetric::new(),
+            event_fails: SharedIncMetric::new(),
+            queue_event_count: SharedIncMetric::new(),
+            plug_count: SharedIncMetric::new(),
+            plug_fails: SharedIncMetric::new(),
+            unplug_count: SharedIncMetric::new(),
+            unplug_fails: SharedIncMetric::new(),
+            unplug_discard_fails: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Shared directory (virtio-9p) device related metrics.
+#[derive(Debug, Default, Serialize)]
+pub struct SharedDirDeviceMetrics {
+    /// Number of device activation failures.
+    pub activate_fails: SharedIncMetric,
+    /// Number of request queue event handling failures.
+    pub event_fails: SharedIncMetric,
+    /// Number of request queue events handled.
+    pub queue_event_count: SharedIncMetric,
+    /// Number of 9p requests received from the guest.
+    pub request_count: SharedIncMetric,
+    /// Number of 9p requests which failed.
+    pub request_fails: SharedIncMetric,
+    /// Number of bytes read from the shared directories.
+    pub read_bytes: SharedIncMetric,
+    /// Number of bytes written to the shared directories.
+    pub write_bytes: SharedIncMetric,
+}
+impl SharedDirDeviceMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            activate_fails: SharedIncMetric::new(),
+            event_fails: SharedIncMetric::new(),
+            queue_event_count: SharedIncMetric::new(),
+            request_count: SharedIncMetric::new(),
+            request_fails: SharedIncMetric::new(),
+            read_bytes: SharedIncMetric::new(),
+            write_bytes: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// TPM device related metrics.
+#[derive(Debug, Default, Serialize)]
+pub struct TpmDeviceMetrics {
+    /// Number of TPM commands executed.
+    pub command_count: SharedIncMetric,
+    /// Number of TPM commands which swtpm failed to execute.
+    pub command_fails: SharedIncMetric,
+}
+impl TpmDeviceMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            command_count: SharedIncMetric::new(),
+            command_fails: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Metrics related to the reaction to the host memory pressure.
+#[derive(Debug, Default, Serialize)]
+pub struct MemoryPressureMetrics {
+    /// Number of host memory pressure events.
+    pub events: SharedIncMetric,
+    /// Number of times the balloon was inflated to relieve the host.
+    pub balloon_inflations: SharedIncMetric,
+    /// Number of times the microVM was paused to relieve the host.
+    pub pauses: SharedIncMetric,
+    /// Number of times the action was undone once the pressure was gone.
+    pub recoveries: SharedIncMetric,
+    /// Number of actions which failed.
+    pub failures: SharedIncMetric,
+}
+impl MemoryPressureMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            events: SharedIncMetric::new(),
+            balloon_inflations: SharedIncMetric::new(),
+            pauses: SharedIncMetric::new(),
+            recoveries: SharedIncMetric::new(),
+            failures: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Metrics related to the resources used by the Firecracker process, against its limits.
+#[derive(Debug, Default, Serialize)]
+pub struct ResourceMetrics {
+    /// Number of open file descriptors.
+    pub open_fds: SharedStoreMetric,
+    /// Soft limit on the number of open file descriptors, 0 when unlimited.
+    pub open_fds_limit: SharedStoreMetric,
+    /// Number of threads.
+    pub threads: SharedStoreMetric,
+    /// Soft limit on the number of processes and threads of the user, 0 when unlimited.
+    pub threads_limit: SharedStoreMetric,
+    /// Size of the virtual address space, in bytes.
+    pub address_space_bytes: SharedStoreMetric,
+    /// Soft limit on the size of the virtual address space, in bytes, 0 when unlimited.
+    pub address_space_limit_bytes: SharedStoreMetric,
+    /// Resident memory, in bytes.
+    pub rss_bytes: SharedStoreMetric,
+    /// Number of times a resource got close to its limit.
+    pub limit_warnings: SharedIncMetric,
+    /// Number of failures to sample the resource usage.
+    pub sample_fails: SharedIncMetric,
+}
+impl ResourceMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            open_fds: SharedStoreMetric::new(),
+            open_fds_limit: SharedStoreMetric::new(),
+            threads: SharedStoreMetric::new(),
+            threads_limit: SharedStoreMetric::new(),
+            address_space_bytes: SharedStoreMetric::new(),
+            address_space_limit_bytes: SharedStoreMetric::new(),
+            rss_bytes: SharedStoreMetric::new(),
+            limit_warnings: SharedIncMetric::new(),
+            sample_fails: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Metrics related to the copy of files from and to the guest.
+#[derive(Debug, Default, Serialize)]
+pub struct GuestFilesMetrics {
+    /// Number of files copied from the guest.
+    pub copies_from_guest: SharedIncMetric,
+    /// Number of files copied to the guest.
+    pub copies_to_guest: SharedIncMetric,
+    /// Number of bytes copied from the guest.
+    pub bytes_from_guest: SharedIncMetric,
+    /// Number of bytes copied to the guest.
+    pub bytes_to_guest: SharedIncMetric,
+    /// Number of copies which failed.
+    pub failures: SharedIncMetric,
+}
+impl GuestFilesMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            copies_from_guest: SharedIncMetric::new(),
+            copies_to_guest: SharedIncMetric::new(),
+            bytes_from_guest: SharedIncMetric::new(),
+            bytes_to_guest: SharedIncMetric::new(),
+            failures: SharedIncMetric::new(),
+        }
+    }
+}
+
+/// Metrics related to the guest memory page faults served through UFFD, as reported by the
+/// page fault handler or recorded while fetching the guest memory from an HTTP server.
+#[derive(Debug, Default, Serialize)]
+pub struct UffdMetrics {
+    /// Number of page faults served by the page fault handler.
+    pub page_faults: SharedIncMetric,
+    /// Number of bytes populated in guest memory while serving page faults.
+    pub populated_bytes: SharedIncMetric,
+    /// Number of page faults served in less than 100 microseconds.
+    pub fault_latency_lt_100us: SharedIncMetric,
+    /// Number of page faults served in 100 microseconds to 1 millisecond.
+    pub fault_latency_lt_1ms: SharedIncMetric,
+    /// Number of page faults served in 1 to 10 milliseconds.
+    pub fault_latency_lt_10ms: SharedIncMetric,
+    /// Number of page faults served in 10 to 100 milliseconds.
+    pub fault_latency_lt_100ms: SharedIncMetric,
+    /// Number of page faults served in 100 milliseconds or more.
+    pub fault_latency_ge_100ms: SharedIncMetric,
+    /// Latency of the slowest page fault served since the last flush, in microseconds.
+    pub max_fault_latency_us: SharedMaxMetric,
+    /// Number of malformed page fault reports received from the page fault handler.
+    pub invalid_reports: SharedIncMetric,
+    /// Number of bytes of guest memory fetched from the HTTP server.
+    pub http_fetched_bytes: SharedIncMetric,
+    /// Number of bytes of guest memory read from the local cache instead of the HTTP server.
+    pub http_cached_bytes: SharedIncMetric,
+    /// Number of failures to fetch guest memory from the HTTP server.
+    pub http_fetch_failures: SharedIncMetric,
+}
+impl UffdMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            page_faults: SharedIncMetric::new(),
+            populated_bytes: SharedIncMetric::new(),
+            fault_latency_lt_100us: SharedIncMetric::new(),
+            fault_latency_lt_1ms: SharedIncMetric::new(),
+            fault_latency_lt_10ms: SharedIncMetric::new(),
+            fault_latency_lt_100ms: SharedIncMetric::new(),
+            fault_latency_ge_100ms: SharedIncMetric::new(),
+            max_fault_latency_us: SharedMaxMetric::new(),
+            invalid_reports: SharedIncMetric::new(),
+            http_fetched_bytes: SharedIncMetric::new(),
+            http_cached_bytes: SharedIncMetric::new(),
+            http_fetch_failures: SharedIncMetric::new(),
+        }
+    }
+
+    /// Records a page fault served in `latency_us` microseconds, which populated `bytes` bytes.
+    pub fn record_fault(&self, latency_us: usize, bytes: usize) {
+        self.page_faults.inc();
+        self.populated_bytes.add(bytes);
+        let bucket = match latency_us {
+            0..=99 => &self.fault_latency_lt_100us,
+            100..=999 => &self.fault_latency_lt_1ms,
+            1_000..=9_999 => &self.fault_latency_lt_10ms,
+            10_000..=99_999 => &self.fault_latency_lt_100ms,
+            _ => &self.fault_latency_ge_100ms,
+        };
+        bucket.inc();
+        self.max_fault_latency_us.record(latency_us);
+    }
+}
+
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
 #[derive(Debug, Default)]
 struct SerializeToUtcTimestampMs;
@@ -1164,6 +2021,8 @@ pub struct FirecrackerMetrics {
     pub balloon: BalloonDeviceMetrics,
     /// A block device's related metrics.
     pub block: BlockDeviceMetrics,
+    /// Request latencies of each drive.
+    pub block_latencies: BlockLatencyMetrics,
     /// Metrics related to deprecated API calls.
     pub deprecated_api: DeprecatedApiMetrics,
     /// Metrics related to API GET requests.
@@ -1178,6 +2037,8 @@ pub struct FirecrackerMetrics {
     pub mmds: MmdsMetrics,
     /// A network device's related metrics.
     pub net: NetDeviceMetrics,
+    /// Counters of each network interface.
+    pub net_ifaces: NetInterfacesMetrics,
     /// Metrics related to API PATCH requests.
     pub patch_api_requests: PatchRequestsMetrics,
     /// Metrics related to API PUT requests.
@@ -1199,6 +2060,20 @@ pub struct FirecrackerMetrics {
     pub vsock: VsockDeviceMetrics,
     /// Metrics related to virtio-rng entropy device.
     pub entropy: EntropyDeviceMetrics,
+    /// Metrics related to the virtio-mem memory hotplug device.
+    pub memory_hotplug: MemoryHotplugDeviceMetrics,
+    /// Metrics related to the virtio-9p shared directory devices.
+    pub shared_dirs: SharedDirDeviceMetrics,
+    /// Metrics related to the TPM device.
+    pub tpm: TpmDeviceMetrics,
+    /// Metrics related to the page faults served through UFFD.
+    pub uffd: UffdMetrics,
+    /// Metrics related to the reaction to the host memory pressure.
+    pub memory_pressure: MemoryPressureMetrics,
+    /// Metrics related to the copy of files from and to the guest.
+    pub guest_files: GuestFilesMetrics,
+    /// Metrics related to the resources used by the process, against its limits.
+    pub resources: ResourceMetrics,
 }
 impl FirecrackerMetrics {
     /// Const default construction.
@@ -1208,6 +2083,7 @@ impl FirecrackerMetrics {
             api_server: ApiServerMetrics::new(),
             balloon: BalloonDeviceMetrics::new(),
             block: BlockDeviceMetrics::new(),
+            block_latencies: BlockLatencyMetrics::new(),
             deprecated_api: DeprecatedApiMetrics::new(),
             get_api_requests: GetRequestsMetrics::new(),
             i8042: I8042DeviceMetrics::new(),
@@ -1215,6 +2091,7 @@ impl FirecrackerMetrics {
             logger: LoggerSystemMetrics::new(),
             mmds: MmdsMetrics::new(),
             net: NetDeviceMetrics::new(),
+            net_ifaces: NetInterfacesMetrics::new(),
             patch_api_requests: PatchRequestsMetrics::new(),
             put_api_requests: PutRequestsMetrics::new(),
             #[cfg(target_arch = "aarch64")]
@@ -1226,6 +2103,13 @@ impl FirecrackerMetrics {
             signals: SignalMetrics::new(),
             vsock: VsockDeviceMetrics::new(),
             entropy: EntropyDeviceMetrics::new(),
+            memory_hotplug: MemoryHotplugDeviceMetrics::new(),
+            shared_dirs: SharedDirDeviceMetrics::new(),
+            tpm: TpmDeviceMetrics::new(),
+            uffd: UffdMetrics::new(),
+            memory_pressure: MemoryPressureMetrics::new(),
+            guest_files: GuestFilesMetrics::new(),
+            resources: ResourceMetrics::new(),
         }
     }
 }
@@ -1259,6 +2143,69 @@ mod tests {
         assert!(m.init(LineWriter::new(f.into_file())).is_err());
     }
 
+    #[derive(Debug)]
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(ErrorKind::BrokenPipe, "write"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_status() {
+        let m = Metrics::<_, FailingWriter>::new(SharedIncMetric::new());
+        assert!(!m.is_initialized());
+        assert!(!m.write().unwrap());
+        assert!(!m.last_write_failed());
+
+        m.init(FailingWriter).unwrap();
+        assert!(m.is_initialized());
+        assert!(m.write().is_err());
+        assert!(m.last_write_failed());
+    }
+
+    #[test]
+    fn test_labels() {
+        #[derive(Debug, Serialize)]
+        struct TestMetrics {
+            count: SharedIncMetric,
+        }
+        let m = Metrics::<_, Vec<u8>>::new(TestMetrics {
+            count: SharedIncMetric::new(),
+        });
+        m.init(Vec::new()).unwrap();
+        m.set_labels(MetricsLabels {
+            instance_id: Some("vm-1".to_string()),
+            labels: BTreeMap::from([("pool".to_string(), "a".to_string())]),
+        })
+        .unwrap();
+        assert!(m.set_labels(MetricsLabels::default()).is_err());
+
+        m.count.add(2);
+        m.write().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&m.metrics_buf.get().unwrap().lock().unwrap()).unwrap(),
+            "{\"instance_id\":\"vm-1\",\"labels\":{\"pool\":\"a\"},\"count\":2}\n"
+        );
+
+        // The empty labels are left out.
+        let m = Metrics::<_, Vec<u8>>::new(TestMetrics {
+            count: SharedIncMetric::new(),
+        });
+        m.init(Vec::new()).unwrap();
+        m.set_labels(MetricsLabels::default()).unwrap();
+        m.write().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&m.metrics_buf.get().unwrap().lock().unwrap()).unwrap(),
+            "{\"count\":0}\n"
+        );
+    }
+
     #[test]
     fn test_shared_inc_metric() {
         let metric = Arc::new(SharedIncMetric::default());
@@ -1302,6 +2249,130 @@ mod tests {
         assert_eq!(1, m1.fetch());
     }
 
+    #[test]
+    fn test_shared_max_metric() {
+        let m = SharedMaxMetric::default();
+        m.record(3);
+        m.record(7);
+        m.record(5);
+        assert_eq!(m.fetch(), 7);
+
+        // Flushing resets the metric.
+        assert_eq!(serde_json::to_string(&m).unwrap(), "7");
+        assert_eq!(m.fetch(), 0);
+    }
+
+    #[test]
+    fn test_uffd_metrics() {
+        let m = UffdMetrics::new();
+        m.record_fault(50, 4096);
+        m.record_fault(100, 4096);
+        m.record_fault(150_000, 8192);
+
+        assert_eq!(m.page_faults.count(), 3);
+        assert_eq!(m.populated_bytes.count(), 16384);
+        assert_eq!(m.fault_latency_lt_100us.count(), 1);
+        assert_eq!(m.fault_latency_lt_1ms.count(), 1);
+        assert_eq!(m.fault_latency_lt_10ms.count(), 0);
+        assert_eq!(m.fault_latency_lt_100ms.count(), 0);
+        assert_eq!(m.fault_latency_ge_100ms.count(), 1);
+        assert_eq!(m.max_fault_latency_us.fetch(), 150_000);
+    }
+
+    #[test]
+    fn test_vcpu_exits_metrics() {
+        let m = VcpuExitsMetrics::new();
+        assert_eq!(serde_json::to_string(&m).unwrap(), "[]");
+
+        m.register(1).unwrap().mmio_read.add(2);
+        assert!(m.register(MAX_VCPU_EXIT_METRICS as u16).is_none());
+        let s = serde_json::to_value(&m).unwrap();
+        let vcpus = s.as_array().unwrap();
+        assert_eq!(vcpus.len(), 2);
+        assert_eq!(vcpus[0]["mmio_read"], 0);
+        assert_eq!(vcpus[1]["mmio_read"], 2);
+
+        // Flushing resets the counters.
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(s[1]["mmio_read"], 0);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        // The bucket of every latency holds it.
+        for latency_us in (0..10_000).chain([1 << 20, (1 << 20) + 12_345, 1 << 25]) {
+            let index = LatencyHistogram::bucket_index(latency_us);
+            assert!(latency_us <= LatencyHistogram::bucket_max(index));
+            assert!(index == 0 || latency_us > LatencyHistogram::bucket_max(index - 1));
+        }
+        assert_eq!(
+            LatencyHistogram::bucket_index(u64::MAX),
+            LATENCY_HISTOGRAM_BUCKETS - 1
+        );
+
+        let m = LatencyHistogram::new();
+        assert_eq!(
+            serde_json::to_string(&m).unwrap(),
+            r#"{"count":0,"p50_us":0,"p95_us":0,"p99_us":0,"max_us":0}"#
+        );
+        for _ in 0..90 {
+            m.record(100);
+        }
+        for _ in 0..9 {
+            m.record(2_000);
+        }
+        m.record(30_000);
+        assert_eq!(m.count(), 100);
+        // The percentiles are rounded up to the highest latency of their bucket.
+        assert_eq!(
+            serde_json::to_string(&m).unwrap(),
+            r#"{"count":100,"p50_us":111,"p95_us":2047,"p99_us":2047,"max_us":30000}"#
+        );
+
+        // Flushing resets the histogram.
+        assert_eq!(m.count(), 0);
+    }
+
+    #[test]
+    fn test_block_latency_metrics() {
+        let m = BlockLatencyMetrics::new();
+        assert_eq!(serde_json::to_string(&m).unwrap(), "{}");
+
+        let rootfs = m.register("rootfs").unwrap();
+        rootfs.read.record(10);
+        let scratch = m.register("scratch").unwrap();
+        assert!(std::ptr::eq(m.register("rootfs").unwrap(), rootfs));
+        assert!(!std::ptr::eq(rootfs, scratch));
+        for i in 2..MAX_DRIVE_LATENCY_METRICS {
+            m.register(&format!("drive{}", i)).unwrap();
+        }
+        assert!(m.register("extra").is_none());
+
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(s.as_object().unwrap().len(), MAX_DRIVE_LATENCY_METRICS);
+        assert_eq!(s["rootfs"]["read"]["count"], 1);
+        assert_eq!(s["rootfs"]["read"]["p99_us"], 10);
+        assert_eq!(s["scratch"]["write"]["count"], 0);
+    }
+
+    #[test]
+    fn test_net_interfaces_metrics() {
+        let m = NetInterfacesMetrics::new();
+        assert_eq!(serde_json::to_string(&m).unwrap(), "{}");
+
+        m.register("eth0").unwrap().rx_ring_full_drops.add(2);
+        let eth1 = m.register("eth1").unwrap();
+        assert!(std::ptr::eq(m.register("eth1").unwrap(), eth1));
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(s.as_object().unwrap().len(), 2);
+        assert_eq!(s["eth0"]["rx_ring_full_drops"], 2);
+        assert_eq!(s["eth1"]["rx_ring_full_drops"], 0);
+
+        // Flushing resets the counters.
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(s["eth0"]["rx_ring_full_drops"], 0);
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
@@ -119,6 +119,9 @@ pub(crate) fn make_syscall_table(map: &mut HashMap<String, i64>) {
     map.insert("kexec_load".to_string(), 104);
     map.insert("keyctl".to_string(), 219);
     map.insert("kill".to_string(), 129);
+    map.insert("landlock_add_rule".to_string(), 445);
+    map.insert("landlock_create_ruleset".to_string(), 444);
+    map.insert("landlock_restrict_self".to_string(), 446);
     map.insert("lgetxattr".to_string(), 9);
     map.insert("linkat".to_string(), 37);
     map.insert("listen".to_string(), 201);
@@ -143,6 +143,9 @@ pub(crate) fn make_syscall_table(map: &mut HashMap<String, i64>) {
     map.insert("kexec_load".to_string(), 246);
     map.insert("keyctl".to_string(), 250);
     map.insert("kill".to_string(), 62);
+    map.insert("landlock_add_rule".to_string(), 445);
+    map.insert("landlock_create_ruleset".to_string(), 444);
+    map.insert("landlock_restrict_self".to_string(), 446);
     map.insert("lchown".to_string(), 94);
     map.insert("lgetxattr".to_string(), 192);
     map.insert("linkat".to_string(), 265);
@@ -4,9 +4,12 @@
 use std::path::PathBuf;
 
 use clap::Subcommand;
+#[cfg(target_arch = "aarch64")]
 use clap_num::maybe_hex;
+#[cfg(target_arch = "aarch64")]
 use vmm::arch::aarch64::regs::Aarch64RegisterVec;
-use vmm::persist::MicrovmState;
+use vmm::persist::{check_snapshot_translation, MicrovmState, SnapshotTranslationError};
+use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
 
 use crate::utils::{open_vmstate, save_vmstate, UtilsError};
 
@@ -14,11 +17,22 @@ use crate::utils::{open_vmstate, save_vmstate, UtilsError};
 pub enum EditVmStateError {
     #[error("{0}")]
     Utils(#[from] UtilsError),
+    #[error("No device with id {0} in the vmstate")]
+    DeviceNotFound(String),
+    #[error("No block device with id {0} in the vmstate")]
+    BlockDeviceNotFound(String),
+    #[error("No net device with id {0} in the vmstate")]
+    NetDeviceNotFound(String),
+    #[error("Cannot translate Firecracker version {0} to a snapshot data version")]
+    InvalidVersion(String),
+    #[error("{0}")]
+    Translation(#[from] SnapshotTranslationError),
 }
 
 #[derive(Debug, Subcommand)]
 pub enum EditVmStateSubCommand {
     /// Remove registers from vcpu states.
+    #[cfg(target_arch = "aarch64")]
     RemoveRegs {
         /// Set of registers to remove.
         /// Values should be registers ids as the are defined in KVM.
@@ -31,10 +45,65 @@ pub enum EditVmStateSubCommand {
         #[arg(short, long)]
         output_path: PathBuf,
     },
+    /// Remove a device from device states.
+    RemoveDevice {
+        /// Id of the device, e.g. the drive id of a block device.
+        #[arg(short, long)]
+        device_id: String,
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+        /// Path of output file.
+        #[arg(short, long)]
+        output_path: PathBuf,
+    },
+    /// Change the file backing a block device.
+    SetDiskPath {
+        /// Id of the block device.
+        #[arg(short, long)]
+        device_id: String,
+        /// Path of the file backing the device after restore.
+        #[arg(short = 'p', long)]
+        disk_path: String,
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+        /// Path of output file.
+        #[arg(short, long)]
+        output_path: PathBuf,
+    },
+    /// Change the tap a net device is attached to.
+    SetTapName {
+        /// Id of the net device.
+        #[arg(short, long)]
+        device_id: String,
+        /// Name of the tap the device is attached to after restore.
+        #[arg(short = 't', long)]
+        tap_name: String,
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+        /// Path of output file.
+        #[arg(short, long)]
+        output_path: PathBuf,
+    },
+    /// Save the vmstate in the format of another Firecracker version.
+    SetVersion {
+        /// Firecracker version the output file is loaded by, e.g. 1.4.0.
+        #[arg(short = 't', long)]
+        target_version: String,
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+        /// Path of output file.
+        #[arg(short, long)]
+        output_path: PathBuf,
+    },
 }
 
 pub fn edit_vmstate_command(command: EditVmStateSubCommand) -> Result<(), EditVmStateError> {
     match command {
+        #[cfg(target_arch = "aarch64")]
         EditVmStateSubCommand::RemoveRegs {
             regs,
             vmstate_path,
@@ -42,6 +111,34 @@ pub fn edit_vmstate_command(command: EditVmStateSubCommand) -> Result<(), EditVm
         } => edit(&vmstate_path, &output_path, |state| {
             remove_regs(state, &regs)
         })?,
+        EditVmStateSubCommand::RemoveDevice {
+            device_id,
+            vmstate_path,
+            output_path,
+        } => edit(&vmstate_path, &output_path, |state| {
+            remove_device(state, &device_id)
+        })?,
+        EditVmStateSubCommand::SetDiskPath {
+            device_id,
+            disk_path,
+            vmstate_path,
+            output_path,
+        } => edit(&vmstate_path, &output_path, |state| {
+            set_disk_path(state, &device_id, &disk_path)
+        })?,
+        EditVmStateSubCommand::SetTapName {
+            device_id,
+            tap_name,
+            vmstate_path,
+            output_path,
+        } => edit(&vmstate_path, &output_path, |state| {
+            set_tap_name(state, &device_id, &tap_name)
+        })?,
+        EditVmStateSubCommand::SetVersion {
+            target_version,
+            vmstate_path,
+            output_path,
+        } => set_version(&vmstate_path, &output_path, &target_version)?,
     }
     Ok(())
 }
@@ -57,6 +154,28 @@ fn edit(
     Ok(())
 }
 
+fn set_version(
+    vmstate_path: &PathBuf,
+    output_path: &PathBuf,
+    target_version: &str,
+) -> Result<(), EditVmStateError> {
+    let version = snapshot_version(target_version)?;
+    let (microvm_state, source_version) = open_vmstate(vmstate_path)?;
+    check_snapshot_translation(&microvm_state, source_version, &VERSION_MAP)?;
+    save_vmstate(microvm_state, output_path, version)?;
+    Ok(())
+}
+
+fn snapshot_version(fc_version: &str) -> Result<u16, EditVmStateError> {
+    let fc_version_stripped = fc_version.strip_prefix('v').unwrap_or(fc_version);
+    FC_VERSION_TO_SNAP_VERSION
+        .iter()
+        .find(|(key, _)| key.to_string() == fc_version_stripped)
+        .map(|(_, &version)| version)
+        .ok_or_else(|| EditVmStateError::InvalidVersion(fc_version.to_string()))
+}
+
+#[cfg(target_arch = "aarch64")]
 fn remove_regs(
     mut state: MicrovmState,
     remove_regs: &[u64],
@@ -88,10 +207,85 @@ fn remove_regs(
     Ok(state)
 }
 
+fn remove_device(
+    mut state: MicrovmState,
+    device_id: &str,
+) -> Result<MicrovmState, EditVmStateError> {
+    let devices = &mut state.device_states;
+    let count = devices.block_devices.len() + devices.net_devices.len();
+    devices
+        .block_devices
+        .retain(|dev| dev.device_id != device_id);
+    devices.net_devices.retain(|dev| dev.device_id != device_id);
+    let mut removed = devices.block_devices.len() + devices.net_devices.len() != count;
+
+    if matches!(&devices.vsock_device, Some(dev) if dev.device_id == device_id) {
+        devices.vsock_device = None;
+        removed = true;
+    }
+    if matches!(&devices.balloon_device, Some(dev) if dev.device_id == device_id) {
+        devices.balloon_device = None;
+        removed = true;
+    }
+    if matches!(&devices.entropy_device, Some(dev) if dev.device_id == device_id) {
+        devices.entropy_device = None;
+        removed = true;
+    }
+    if matches!(&devices.memory_hotplug_device, Some(dev) if dev.device_id == device_id) {
+        devices.memory_hotplug_device = None;
+        removed = true;
+    }
+
+    if !removed {
+        return Err(EditVmStateError::DeviceNotFound(device_id.to_string()));
+    }
+    println!("Device {device_id}: removed");
+    Ok(state)
+}
+
+fn set_disk_path(
+    mut state: MicrovmState,
+    device_id: &str,
+    disk_path: &str,
+) -> Result<MicrovmState, EditVmStateError> {
+    let device = state
+        .device_states
+        .block_devices
+        .iter_mut()
+        .find(|dev| dev.device_id == device_id)
+        .ok_or_else(|| EditVmStateError::BlockDeviceNotFound(device_id.to_string()))?;
+    println!(
+        "Block device {device_id}: {} -> {disk_path}",
+        device.device_state.disk_path()
+    );
+    device.device_state.set_disk_path(disk_path.to_string());
+    Ok(state)
+}
+
+fn set_tap_name(
+    mut state: MicrovmState,
+    device_id: &str,
+    tap_name: &str,
+) -> Result<MicrovmState, EditVmStateError> {
+    let device = state
+        .device_states
+        .net_devices
+        .iter_mut()
+        .find(|dev| dev.device_id == device_id)
+        .ok_or_else(|| EditVmStateError::NetDeviceNotFound(device_id.to_string()))?;
+    println!(
+        "Net device {device_id}: {} -> {tap_name}",
+        device.device_state.tap_if_name()
+    );
+    device.device_state.set_tap_if_name(tap_name.to_string());
+    Ok(state)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(target_arch = "aarch64")]
     #[test]
     fn test_remove_regs() {
         const KVM_REG_SIZE_U8: u64 = 0;
@@ -151,6 +345,7 @@ mod tests {
         assert_eq!(new_state.vcpu_states[0].regs, expected_vcpu_state.regs);
     }
 
+    #[cfg(target_arch = "aarch64")]
     #[test]
     fn test_remove_non_existed_regs() {
         const KVM_REG_SIZE_U8: u64 = 0;
@@ -192,4 +387,36 @@ mod tests {
 
         assert_eq!(new_state.vcpu_states[0].regs, state.vcpu_states[0].regs);
     }
+    #[test]
+    fn test_remove_missing_device() {
+        let state = MicrovmState::default();
+        assert!(matches!(
+            remove_device(state, "eth0"),
+            Err(EditVmStateError::DeviceNotFound(id)) if id == "eth0"
+        ));
+
+        let state = MicrovmState::default();
+        assert!(matches!(
+            set_disk_path(state, "rootfs", "/rootfs.ext4"),
+            Err(EditVmStateError::BlockDeviceNotFound(id)) if id == "rootfs"
+        ));
+
+        let state = MicrovmState::default();
+        assert!(matches!(
+            set_tap_name(state, "eth0", "tap1"),
+            Err(EditVmStateError::NetDeviceNotFound(id)) if id == "eth0"
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_version() {
+        use vmm::version_map::{FC_V1_4_SNAP_VERSION, FC_V1_5_SNAP_VERSION};
+
+        assert_eq!(snapshot_version("1.4.0").unwrap(), FC_V1_4_SNAP_VERSION);
+        assert_eq!(snapshot_version("v1.5.0").unwrap(), FC_V1_5_SNAP_VERSION);
+        assert!(matches!(
+            snapshot_version("0.1.0"),
+            Err(EditVmStateError::InvalidVersion(version)) if version == "0.1.0"
+        ));
+    }
 }
@@ -4,6 +4,7 @@
 use std::path::PathBuf;
 
 use clap::Subcommand;
+use serde_json::{json, Value};
 use vmm::persist::MicrovmState;
 use vmm::version_map::FC_VERSION_TO_SNAP_VERSION;
 
@@ -15,6 +16,8 @@ pub enum InfoVmStateError {
     InvalidVersion(u16),
     #[error("{0}")]
     Utils(#[from] UtilsError),
+    #[error("Cannot serialize the vmstate: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Subcommand)]
@@ -25,6 +28,12 @@ pub enum InfoVmStateSubCommand {
         #[arg(short, long)]
         vmstate_path: PathBuf,
     },
+    /// Print the vmstate as JSON: the VM info, the memory layout and the devices.
+    Dump {
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+    },
     /// Print info about vcpu states.
     #[cfg(target_arch = "aarch64")]
     VcpuStates {
@@ -37,6 +46,7 @@ pub enum InfoVmStateSubCommand {
 pub fn info_vmstate_command(command: InfoVmStateSubCommand) -> Result<(), InfoVmStateError> {
     match command {
         InfoVmStateSubCommand::Version { vmstate_path } => info(&vmstate_path, info_version)?,
+        InfoVmStateSubCommand::Dump { vmstate_path } => info(&vmstate_path, info_dump)?,
         #[cfg(target_arch = "aarch64")]
         InfoVmStateSubCommand::VcpuStates { vmstate_path } => {
             info(&vmstate_path, info_vcpu_states)?
@@ -54,17 +64,104 @@ fn info(
     Ok(())
 }
 
-fn info_version(_: &MicrovmState, version: u16) -> Result<(), InfoVmStateError> {
-    match FC_VERSION_TO_SNAP_VERSION
+fn fc_version(version: u16) -> Result<String, InfoVmStateError> {
+    FC_VERSION_TO_SNAP_VERSION
         .iter()
         .find(|(_, &v)| v == version)
-    {
-        Some((key, _)) => {
-            println!("v{key}");
-            Ok(())
-        }
-        None => Err(InfoVmStateError::InvalidVersion(version)),
+        .map(|(key, _)| format!("v{key}"))
+        .ok_or(InfoVmStateError::InvalidVersion(version))
+}
+
+fn info_version(_: &MicrovmState, version: u16) -> Result<(), InfoVmStateError> {
+    println!("{}", fc_version(version)?);
+    Ok(())
+}
+
+fn info_dump(state: &MicrovmState, version: u16) -> Result<(), InfoVmStateError> {
+    let dump = dump_vmstate(state, version)?;
+    println!("{}", serde_json::to_string_pretty(&dump)?);
+    Ok(())
+}
+
+// Describes a device and its MMIO slot.
+macro_rules! device_json {
+    ($type_:expr, $id:expr, $device_info:expr) => {
+        json!({
+            "type": $type_,
+            "id": $id,
+            "addr": $device_info.addr,
+            "len": $device_info.len,
+            "irqs": $device_info.irqs,
+        })
+    };
+}
+
+// The KVM state of the VM and of the vCPUs is left out: it is only meaningful to KVM, and
+// `vcpu-states` prints the registers on aarch64.
+fn dump_vmstate(state: &MicrovmState, version: u16) -> Result<Value, InfoVmStateError> {
+    let devices = &state.device_states;
+    let mut device_list = Vec::new();
+    #[cfg(target_arch = "aarch64")]
+    for dev in devices.legacy_devices.iter() {
+        device_list.push(device_json!(
+            "legacy",
+            &dev.type_.to_string(),
+            &dev.device_info
+        ));
     }
+    for dev in devices.block_devices.iter() {
+        let mut value = device_json!("block", &dev.device_id, &dev.device_info);
+        value["disk_path"] = json!(dev.device_state.disk_path());
+        device_list.push(value);
+    }
+    for dev in devices.net_devices.iter() {
+        let mut value = device_json!("net", &dev.device_id, &dev.device_info);
+        value["tap_if_name"] = json!(dev.device_state.tap_if_name());
+        device_list.push(value);
+    }
+    if let Some(dev) = &devices.vsock_device {
+        device_list.push(device_json!("vsock", &dev.device_id, &dev.device_info));
+    }
+    if let Some(dev) = &devices.balloon_device {
+        device_list.push(device_json!("balloon", &dev.device_id, &dev.device_info));
+    }
+    if let Some(dev) = &devices.entropy_device {
+        device_list.push(device_json!("entropy", &dev.device_id, &dev.device_info));
+    }
+    if let Some(dev) = &devices.memory_hotplug_device {
+        device_list.push(device_json!(
+            "memory-hotplug",
+            &dev.device_id,
+            &dev.device_info
+        ));
+    }
+
+    let memory_regions: Vec<Value> = state
+        .memory_state
+        .regions
+        .iter()
+        .map(|region| {
+            json!({
+                "base_address": region.base_address,
+                "size": region.size,
+                "offset": region.offset,
+                "zero_pages": region
+                    .zero_pages
+                    .iter()
+                    .map(|word| word.count_ones())
+                    .sum::<u32>(),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "version": fc_version(version)?,
+        "vm_info": serde_json::to_value(&state.vm_info)?,
+        "memory_regions": memory_regions,
+        "vcpu_count": state.vcpu_states.len(),
+        "devices": device_list,
+        "mmds_version": devices.mmds_version.as_ref().map(|v| format!("{v:?}")),
+    }))
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -87,3 +184,42 @@ fn info_vcpu_states(state: &MicrovmState, _: u16) -> Result<(), InfoVmStateError
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use vmm::memory_snapshot::{GuestMemoryRegionState, GuestMemoryState};
+    use vmm::version_map::FC_V1_5_SNAP_VERSION;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_vmstate() {
+        let state = MicrovmState {
+            memory_state: GuestMemoryState {
+                regions: vec![GuestMemoryRegionState {
+                    base_address: 0,
+                    size: 0x1000,
+                    offset: 0,
+                    zero_pages: vec![0b1],
+                }],
+            },
+            ..Default::default()
+        };
+
+        let dump = dump_vmstate(&state, FC_V1_5_SNAP_VERSION).unwrap();
+        assert_eq!(dump["version"], "v1.5.0");
+        assert_eq!(dump["vm_info"]["mem_size_mib"], 0);
+        assert_eq!(
+            dump["memory_regions"],
+            json!([{"base_address": 0, "size": 0x1000, "offset": 0, "zero_pages": 1}])
+        );
+        assert_eq!(dump["vcpu_count"], 0);
+        assert_eq!(dump["devices"], json!([]));
+        assert_eq!(dump["mmds_version"], Value::Null);
+
+        assert!(matches!(
+            dump_vmstate(&state, u16::MAX),
+            Err(InfoVmStateError::InvalidVersion(u16::MAX))
+        ));
+    }
+}
@@ -8,8 +8,6 @@ use snapshot::Snapshot;
 use vmm::persist::MicrovmState;
 use vmm::version_map::VERSION_MAP;
 
-// Some errors are only used in aarch64 code
-#[allow(unused)]
 #[derive(Debug, thiserror::Error)]
 pub enum UtilsError {
     #[error("Can not open snapshot file: {0}")]
@@ -24,7 +22,6 @@ pub enum UtilsError {
     VmStateSave(snapshot::Error),
 }
 
-#[allow(unused)]
 pub fn open_vmstate(snapshot_path: &PathBuf) -> Result<(MicrovmState, u16), UtilsError> {
     let version_map = VERSION_MAP.clone();
     let mut snapshot_reader = File::open(snapshot_path).map_err(UtilsError::VmStateFileOpen)?;
@@ -33,8 +30,6 @@ pub fn open_vmstate(snapshot_path: &PathBuf) -> Result<(MicrovmState, u16), Util
     Snapshot::load(&mut snapshot_reader, snapshot_len, version_map).map_err(UtilsError::VmStateLoad)
 }
 
-// This method is used only in aarch64 code so far
-#[allow(unused)]
 pub fn save_vmstate(
     microvm_state: MicrovmState,
     output_path: &PathBuf,
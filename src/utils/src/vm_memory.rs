@@ -113,6 +113,23 @@ fn build_guarded_region(
 pub fn create_guest_memory(
     regions: &[(Option<FileOffset>, GuestAddress, usize)],
     track_dirty_pages: bool,
+) -> std::result::Result<GuestMemoryMmap, Error> {
+    create_guest_memory_with_file_flags(regions, libc::MAP_PRIVATE, track_dirty_pages)
+}
+
+/// Helper for creating the guest memory, in which the file-backed regions are shared mappings,
+/// so that the guest writes go to the files. The anonymous regions stay private.
+pub fn create_shared_guest_memory(
+    regions: &[(Option<FileOffset>, GuestAddress, usize)],
+    track_dirty_pages: bool,
+) -> std::result::Result<GuestMemoryMmap, Error> {
+    create_guest_memory_with_file_flags(regions, libc::MAP_SHARED, track_dirty_pages)
+}
+
+fn create_guest_memory_with_file_flags(
+    regions: &[(Option<FileOffset>, GuestAddress, usize)],
+    file_flags: i32,
+    track_dirty_pages: bool,
 ) -> std::result::Result<GuestMemoryMmap, Error> {
     let prot = libc::PROT_READ | libc::PROT_WRITE;
     let mut mmap_regions = Vec::with_capacity(regions.len());
@@ -120,7 +137,7 @@ pub fn create_guest_memory(
     for region in regions {
         let flags = match region.0 {
             None => libc::MAP_NORESERVE | libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-            Some(_) => libc::MAP_NORESERVE | libc::MAP_PRIVATE,
+            Some(_) => libc::MAP_NORESERVE | file_flags,
         };
 
         let mmap_region =
@@ -653,6 +670,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_create_shared_guest_memory() {
+        let region_size = 0x10000;
+        let mut file = TempFile::new().unwrap().into_file();
+        file.set_len(region_size as u64 * 2).unwrap();
+        let regions = vec![
+            (
+                Some(FileOffset::new(file.try_clone().unwrap(), 0)),
+                GuestAddress(0x0),
+                region_size,
+            ),
+            (
+                Some(FileOffset::new(
+                    file.try_clone().unwrap(),
+                    region_size as u64,
+                )),
+                GuestAddress(0x10000),
+                region_size,
+            ),
+            (None, GuestAddress(0x20000), region_size),
+        ];
+
+        let guest_memory = create_shared_guest_memory(&regions, false).unwrap();
+        guest_memory.iter().for_each(|region| {
+            validate_guard_region(region);
+            let shared = region.flags() & libc::MAP_SHARED != 0;
+            assert_eq!(shared, region.file_offset().is_some());
+        });
+
+        // The guest writes reach the file.
+        guest_memory
+            .write_obj(0xabcd_u32, GuestAddress(0x10000))
+            .unwrap();
+        let mut buf = [0u8; 4];
+        file.seek(std::io::SeekFrom::Start(region_size as u64))
+            .unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(u32::from_ne_bytes(buf), 0xabcd);
+    }
+
     #[test]
     fn test_mark_dirty_mem() {
         let page_size = crate::get_page_size().unwrap();
@@ -0,0 +1,123 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Feeds a recording of a virtio queue back into the device model, to reproduce offline a bug
+//! seen in production.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::Parser;
+use vmm::devices::virtio::record::{RecordError, RecordReader};
+use vmm::devices::virtio::{Block, BlockError, Entropy, EntropyError, TYPE_BLOCK, TYPE_RNG};
+use vmm::rate_limiter::RateLimiter;
+use vmm::vmm_config::drive::{CacheType, FileEngineType, IoDepthLimits, PageCacheHints};
+
+mod replay;
+
+use replay::{replay, ReplayError};
+
+#[derive(Debug, thiserror::Error)]
+enum VirtioReplayError {
+    #[error("Cannot open the recording: {0}")]
+    OpenRecording(std::io::Error),
+    #[error("{0}")]
+    Record(#[from] RecordError),
+    #[error("Replaying a block device requires --disk-path.")]
+    MissingDisk,
+    #[error("Cannot create the block device: {0:?}")]
+    CreateBlock(BlockError),
+    #[error("Cannot create the entropy device: {0}")]
+    CreateEntropy(EntropyError),
+    #[error("Replaying devices of type {0} is not supported.")]
+    UnsupportedDevice(u32),
+    #[error("{0}")]
+    Replay(#[from] ReplayError),
+    #[error("The replay differs from the recording in {0} places.")]
+    Mismatches(usize),
+}
+
+#[derive(Debug, Parser)]
+#[command(version = format!("v{}", env!("FIRECRACKER_VERSION")))]
+struct Cli {
+    /// Path to the recording of the queue.
+    #[arg(short, long)]
+    recording_path: PathBuf,
+    /// Path to the disk image of a block device. The replay writes to it, so it should be a
+    /// copy of the image as it was when the recording started.
+    #[arg(short, long)]
+    disk_path: Option<String>,
+    /// Seed of an entropy device recorded in deterministic boot mode.
+    #[arg(short, long)]
+    seed: Option<u64>,
+    /// Only compare the chains used by the device and their length, not the bytes written.
+    #[arg(long)]
+    ignore_data: bool,
+}
+
+fn main_exec() -> Result<(), VirtioReplayError> {
+    let cli = Cli::parse();
+
+    let file = File::open(&cli.recording_path).map_err(VirtioReplayError::OpenRecording)?;
+    let mut reader = RecordReader::new(BufReader::new(file))?;
+    let header = reader.header().clone();
+    println!(
+        "Replaying queue {} of device {} (type {}).",
+        header.queue_index, header.device_id, header.device_type
+    );
+
+    let compare_data = !cli.ignore_data;
+    let summary = match header.device_type {
+        TYPE_BLOCK => {
+            let disk_path = cli.disk_path.ok_or(VirtioReplayError::MissingDisk)?;
+            let block = Block::new(
+                header.device_id.clone(),
+                None,
+                CacheType::Writeback,
+                disk_path,
+                false,
+                false,
+                RateLimiter::default(),
+                FileEngineType::Sync,
+                false,
+                PageCacheHints::default(),
+                IoDepthLimits::default(),
+            )
+            .map_err(VirtioReplayError::CreateBlock)?;
+            replay(Arc::new(Mutex::new(block)), &mut reader, compare_data)?
+        }
+        TYPE_RNG => {
+            let mut entropy =
+                Entropy::new(RateLimiter::default()).map_err(VirtioReplayError::CreateEntropy)?;
+            if let Some(seed) = cli.seed {
+                entropy.set_seed(seed);
+            }
+            replay(Arc::new(Mutex::new(entropy)), &mut reader, compare_data)?
+        }
+        device_type => return Err(VirtioReplayError::UnsupportedDevice(device_type)),
+    };
+
+    println!(
+        "Replayed {} chains, compared {} used chains.",
+        summary.chains, summary.used
+    );
+    for mismatch in summary.mismatches.iter() {
+        println!("{}", mismatch);
+    }
+    if !summary.mismatches.is_empty() {
+        return Err(VirtioReplayError::Mismatches(summary.mismatches.len()));
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), VirtioReplayError> {
+    let result = main_exec();
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        Err(e)
+    } else {
+        Ok(())
+    }
+}
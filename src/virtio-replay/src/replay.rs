@@ -0,0 +1,311 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use event_manager::{MutEventSubscriber, SubscriberOps};
+use fc_utils::vm_memory::{
+    create_guest_memory, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap,
+};
+use vmm::devices::virtio::record::{RecordError, RecordEvent, RecordReader, RecordedDescriptor};
+use vmm::devices::virtio::{ActivateError, VirtioDevice};
+use vmm::EventManager;
+
+// Flag of the write-only descriptors, from the virtio specification.
+const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+// Time given to the device to use a chain, in milliseconds.
+const USED_TIMEOUT_MS: i32 = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("{0}")]
+    Record(#[from] RecordError),
+    #[error("Cannot create the guest memory: {0}")]
+    CreateGuestMemory(fc_utils::vm_memory::Error),
+    #[error("Cannot access the guest memory: {0}")]
+    GuestMemory(#[from] GuestMemoryError),
+    #[error("The recording does not start with the layout of the queue.")]
+    MissingLayout,
+    #[error("The device has no queue {0}.")]
+    InvalidQueue(u16),
+    #[error("Cannot activate the device: {0:?}")]
+    Activate(ActivateError),
+    #[error("Cannot run the device: {0:?}")]
+    EventManager(event_manager::Error),
+    #[error("Cannot notify the device: {0}")]
+    Notify(io::Error),
+}
+
+/// A difference between the recording and the replay.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The device did not use a chain it used in the recording. The replay stops there.
+    MissingUsed { event: usize, index: u16 },
+    /// The device used another chain, or wrote another number of bytes in it.
+    Used {
+        event: usize,
+        expected: (u16, u32),
+        actual: (u16, u32),
+    },
+    /// The device wrote other bytes in the chain.
+    Data {
+        event: usize,
+        index: u16,
+        offset: usize,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::MissingUsed { event, index } => {
+                write!(f, "event {event}: chain {index} was not used")
+            }
+            Mismatch::Used {
+                event,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "event {event}: expected chain {} with {} bytes, got chain {} with {} bytes",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+            Mismatch::Data {
+                event,
+                index,
+                offset,
+            } => write!(
+                f,
+                "event {event}: the bytes written in chain {index} differ from offset {offset}"
+            ),
+        }
+    }
+}
+
+/// Outcome of a replay.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    /// Number of chains made available to the device.
+    pub chains: usize,
+    /// Number of used chains compared with the recording.
+    pub used: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+// The queue of the replayed device, as seen by the driver.
+#[derive(Debug)]
+struct DriverQueue {
+    size: u16,
+    desc_table: GuestAddress,
+    avail_ring: GuestAddress,
+    used_ring: GuestAddress,
+    next_avail: u16,
+    next_used: u16,
+}
+
+impl DriverQueue {
+    fn add_chain(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        descriptors: &[RecordedDescriptor],
+    ) -> Result<(), GuestMemoryError> {
+        for desc in descriptors.iter() {
+            let entry = GuestAddress(self.desc_table.0 + 16 * u64::from(desc.index));
+            mem.write_obj(desc.addr, entry)?;
+            mem.write_obj(desc.len, GuestAddress(entry.0 + 8))?;
+            mem.write_obj(desc.flags, GuestAddress(entry.0 + 12))?;
+            mem.write_obj(desc.next, GuestAddress(entry.0 + 14))?;
+            if !desc.data.is_empty() {
+                mem.write_slice(&desc.data, GuestAddress(desc.addr))?;
+            }
+        }
+
+        let head = descriptors.first().map_or(0, |desc| desc.index);
+        let slot = u64::from(self.next_avail % self.size);
+        mem.write_obj(head, GuestAddress(self.avail_ring.0 + 4 + 2 * slot))?;
+        self.next_avail = self.next_avail.wrapping_add(1);
+        mem.write_obj(self.next_avail, GuestAddress(self.avail_ring.0 + 2))
+    }
+
+    fn used_idx(&self, mem: &GuestMemoryMmap) -> Result<u16, GuestMemoryError> {
+        mem.read_obj(GuestAddress(self.used_ring.0 + 2))
+    }
+
+    fn pop_used(&mut self, mem: &GuestMemoryMmap) -> Result<(u16, u32), GuestMemoryError> {
+        let slot = u64::from(self.next_used % self.size);
+        let elem = GuestAddress(self.used_ring.0 + 4 + 8 * slot);
+        let index: u32 = mem.read_obj(elem)?;
+        let len: u32 = mem.read_obj(GuestAddress(elem.0 + 4))?;
+        self.next_used = self.next_used.wrapping_add(1);
+        // The head index of a chain always fits in 16 bits.
+        Ok((index as u16, len))
+    }
+}
+
+fn read_written(
+    mem: &GuestMemoryMmap,
+    writable: &[(u64, u32)],
+    len: usize,
+) -> Result<Vec<u8>, GuestMemoryError> {
+    let mut data = Vec::new();
+    for (addr, buf_len) in writable.iter() {
+        let count = std::cmp::min(len - data.len(), *buf_len as usize);
+        let start = data.len();
+        data.resize(start + count, 0);
+        mem.read_slice(&mut data[start..], GuestAddress(*addr))?;
+        if data.len() == len {
+            break;
+        }
+    }
+    Ok(data)
+}
+
+/// Feeds the chains of `reader` to `device` and compares the chains it uses with the recording.
+/// The bytes written by the device are only compared if `compare_data` is set.
+pub fn replay<T, R>(
+    device: Arc<Mutex<T>>,
+    reader: &mut RecordReader<R>,
+    compare_data: bool,
+) -> Result<ReplaySummary, ReplayError>
+where
+    T: VirtioDevice + MutEventSubscriber + 'static,
+    R: Read,
+{
+    let mut summary = ReplaySummary::default();
+    let regions: Vec<_> = reader
+        .header()
+        .mem_regions
+        .iter()
+        .map(|(start, size)| (None, GuestAddress(*start), *size as usize))
+        .collect();
+    let mem = create_guest_memory(&regions, false).map_err(ReplayError::CreateGuestMemory)?;
+    let queue_index = reader.header().queue_index;
+
+    let mut queue = match reader.next_event()? {
+        Some(RecordEvent::Layout {
+            size,
+            desc_table,
+            avail_ring,
+            used_ring,
+            notif_suppression,
+        }) => {
+            let mut locked_device = device.lock().expect("Poisoned lock");
+            let avail_features = locked_device.avail_features();
+            locked_device.set_acked_features(avail_features);
+            let device_queue = locked_device
+                .queues_mut()
+                .get_mut(usize::from(queue_index))
+                .ok_or(ReplayError::InvalidQueue(queue_index))?;
+            device_queue.size = size;
+            device_queue.ready = true;
+            device_queue.desc_table = GuestAddress(desc_table);
+            device_queue.avail_ring = GuestAddress(avail_ring);
+            device_queue.used_ring = GuestAddress(used_ring);
+            if notif_suppression {
+                device_queue.enable_notif_suppression();
+            }
+            DriverQueue {
+                size,
+                desc_table: GuestAddress(desc_table),
+                avail_ring: GuestAddress(avail_ring),
+                used_ring: GuestAddress(used_ring),
+                next_avail: 0,
+                next_used: 0,
+            }
+        }
+        Some(_) => return Err(ReplayError::MissingLayout),
+        None => return Ok(summary),
+    };
+
+    let mut event_manager = EventManager::new().map_err(ReplayError::EventManager)?;
+    event_manager.add_subscriber(device.clone());
+    device
+        .lock()
+        .expect("Poisoned lock")
+        .activate(mem.clone())
+        .map_err(ReplayError::Activate)?;
+    event_manager
+        .run_with_timeout(0)
+        .map_err(ReplayError::EventManager)?;
+
+    // Write-only buffers of the chains in flight, by head index.
+    let mut writable: HashMap<u16, Vec<(u64, u32)>> = HashMap::new();
+    // Set when the device returned its last chain, which is then still available.
+    let mut undone = false;
+    let mut event_count = 1;
+    while let Some(event) = reader.next_event()? {
+        event_count += 1;
+        match event {
+            RecordEvent::Layout { .. } => (),
+            RecordEvent::Pop(descriptors) => {
+                if std::mem::take(&mut undone) {
+                    continue;
+                }
+                queue.add_chain(&mem, &descriptors)?;
+                let head = descriptors.first().map_or(0, |desc| desc.index);
+                writable.insert(
+                    head,
+                    descriptors
+                        .iter()
+                        .filter(|desc| desc.flags & VIRTQ_DESC_F_WRITE != 0)
+                        .map(|desc| (desc.addr, desc.len))
+                        .collect(),
+                );
+                summary.chains += 1;
+                device.lock().expect("Poisoned lock").queue_events()[usize::from(queue_index)]
+                    .write(1)
+                    .map_err(ReplayError::Notify)?;
+            }
+            RecordEvent::UndoPop => undone = true,
+            RecordEvent::Used { index, len, data } => {
+                while queue.used_idx(&mem)? == queue.next_used {
+                    let count = event_manager
+                        .run_with_timeout(USED_TIMEOUT_MS)
+                        .map_err(ReplayError::EventManager)?;
+                    if count == 0 {
+                        summary.mismatches.push(Mismatch::MissingUsed {
+                            event: event_count,
+                            index,
+                        });
+                        return Ok(summary);
+                    }
+                }
+
+                summary.used += 1;
+                let actual = queue.pop_used(&mem)?;
+                if actual != (index, len) {
+                    summary.mismatches.push(Mismatch::Used {
+                        event: event_count,
+                        expected: (index, len),
+                        actual,
+                    });
+                    continue;
+                }
+
+                let buffers = writable.remove(&index).unwrap_or_default();
+                if compare_data {
+                    let written = read_written(&mem, &buffers, data.len())?;
+                    if let Some(offset) = written.iter().zip(data.iter()).position(|(a, b)| a != b)
+                    {
+                        summary.mismatches.push(Mismatch::Data {
+                            event: event_count,
+                            index,
+                            offset,
+                        });
+                    } else if written.len() != data.len() {
+                        summary.mismatches.push(Mismatch::Data {
+                            event: event_count,
+                            index,
+                            offset: written.len(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
@@ -9,9 +9,7 @@ use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt::Debug;
 
-use utils::vm_memory::{
-    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
-};
+use utils::vm_memory::{Address, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 use vm_fdt::{Error as VmFdtError, FdtWriter, FdtWriterNode};
 
 use super::super::{DeviceType, InitrdConfig};
@@ -23,6 +21,8 @@ use super::gic::GICDevice;
 const GIC_PHANDLE: u32 = 1;
 // This is a value for uniquely identifying the FDT node containing the clock definition.
 const CLOCK_PHANDLE: u32 = 2;
+// This is a value for uniquely identifying the FDT node declaring the ITS (MSI controller).
+const ITS_PHANDLE: u32 = 3;
 // You may be wondering why this big value?
 // This phandle is used to uniquely identify the FDT nodes containing cache information. Each cpu
 // can have a variable number of caches, some of these caches may be shared with other cpus.
@@ -70,6 +70,7 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<InitrdConfig>,
+    vm_uuid: Option<&str>,
 ) -> Result<Vec<u8>, FdtError> {
     // Allocate stuff necessary for storing the blob.
     let mut fdt_writer = FdtWriter::new()?;
@@ -90,7 +91,7 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     fdt_writer.property_u32("interrupt-parent", GIC_PHANDLE)?;
     create_cpu_nodes(&mut fdt_writer, &vcpu_mpidr)?;
     create_memory_node(&mut fdt_writer, guest_mem)?;
-    create_chosen_node(&mut fdt_writer, cmdline, initrd)?;
+    create_chosen_node(&mut fdt_writer, cmdline, initrd, vm_uuid)?;
     create_gic_node(&mut fdt_writer, gic_device)?;
     create_timer_node(&mut fdt_writer)?;
     create_clock_node(&mut fdt_writer)?;
@@ -215,7 +216,8 @@ fn create_cpu_nodes(fdt: &mut FdtWriter, vcpu_mpidr: &[u64]) -> Result<(), FdtEr
 }
 
 fn create_memory_node(fdt: &mut FdtWriter, guest_mem: &GuestMemoryMmap) -> Result<(), FdtError> {
-    let mem_size = guest_mem.last_addr().raw_value() - super::layout::DRAM_MEM_START + 1;
+    // Only the boot RAM is described here, hotplugged memory is advertised by its virtio-mem device.
+    let mem_size = super::dram_last_addr(guest_mem).raw_value() - super::layout::DRAM_MEM_START + 1;
     // See https://github.com/torvalds/linux/blob/master/Documentation/devicetree/booting-without-of.txt#L960
     // for an explanation of this.
     let mem_reg_prop = &[super::layout::DRAM_MEM_START, mem_size];
@@ -232,6 +234,7 @@ fn create_chosen_node(
     fdt: &mut FdtWriter,
     cmdline: CString,
     initrd: &Option<InitrdConfig>,
+    vm_uuid: Option<&str>,
 ) -> Result<(), FdtError> {
     let chosen = fdt.begin_node("chosen")?;
     // Workaround to be able to reuse an existing property_*() method; in property_string() method,
@@ -249,6 +252,11 @@ fn create_chosen_node(
         )?;
     }
 
+    // Without SMBIOS tables, the guest reads the UUID of the microVM from the device tree.
+    if let Some(vm_uuid) = vm_uuid {
+        fdt.property_string("firecracker,vm-uuid", vm_uuid)?;
+    }
+
     fdt.end_node(chosen)?;
 
     Ok(())
@@ -275,6 +283,18 @@ fn create_gic_node(fdt: &mut FdtWriter, gic_device: &GICDevice) -> Result<(), Fd
     ];
 
     fdt.property_array_u32("interrupts", &gic_intr)?;
+
+    if let Some(its_properties) = gic_device.its_properties() {
+        // See https://www.kernel.org/doc/Documentation/devicetree/bindings/interrupt-controller/arm%2Cgic-v3.txt
+        let its = fdt.begin_node("msic")?;
+        fdt.property_string("compatible", "arm,gic-v3-its")?;
+        fdt.property_null("msi-controller")?;
+        fdt.property_u32("#msi-cells", 1)?;
+        fdt.property_array_u64("reg", &its_properties)?;
+        fdt.property_u32("phandle", ITS_PHANDLE)?;
+        fdt.end_node(its)?;
+    }
+
     fdt.end_node(interrupt)?;
 
     Ok(())
@@ -388,6 +408,21 @@ fn create_rtc_node<T: DeviceInfoForFDT + Clone + Debug>(
     Ok(())
 }
 
+fn create_tpm_node<T: DeviceInfoForFDT + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> Result<(), FdtError> {
+    // Driver requirements:
+    // https://elixir.bootlin.com/linux/latest/source/Documentation/devicetree/bindings/tpm/tcg,tpm-tis-mmio.yaml
+    // The device does not implement interrupts, so the driver polls it.
+    let tpm = fdt.begin_node(&format!("tpm@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "tcg,tpm-tis-mmio")?;
+    fdt.property_array_u64("reg", &[dev_info.addr(), dev_info.length()])?;
+    fdt.end_node(tpm)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     fdt: &mut FdtWriter,
     dev_info: &HashMap<(DeviceType, String), T, S>,
@@ -400,6 +435,7 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
             DeviceType::BootTimer => (), // since it's not a real device
             DeviceType::Rtc => create_rtc_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
+            DeviceType::Tpm => create_tpm_node(fdt, info)?,
             DeviceType::Virtio(_) => {
                 ordered_virtio_device.push(info);
             }
@@ -489,6 +525,7 @@ mod tests {
             &dev_info,
             &gic,
             &None,
+            None,
         )
         .is_ok())
     }
@@ -515,6 +552,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &None,
+            None,
         )
         .unwrap();
 
@@ -578,6 +616,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &Some(initrd),
+            None,
         )
         .unwrap();
 
@@ -613,4 +652,34 @@ mod tests {
             format!("{:?}", generated_fdt)
         );
     }
+
+    #[test]
+    fn test_create_fdt_with_vm_uuid() {
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let mem = utils::vm_memory::test_utils::create_anon_guest_memory(&regions, false)
+            .expect("Cannot initialize memory");
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1, None).unwrap();
+
+        let dtb_bytes = create_fdt(
+            &mem,
+            vec![0],
+            CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            &gic,
+            &None,
+            Some("01234567-89ab-cdef-0123-456789abcdef"),
+        )
+        .unwrap();
+
+        let fdt = device_tree::DeviceTree::load(&dtb_bytes).unwrap();
+        assert_eq!(
+            fdt.find("/chosen")
+                .unwrap()
+                .prop_str("firecracker,vm-uuid")
+                .unwrap(),
+            "01234567-89ab-cdef-0123-456789abcdef"
+        );
+    }
 }
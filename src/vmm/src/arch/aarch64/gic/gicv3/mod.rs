@@ -8,13 +8,17 @@ use kvm_ioctls::{DeviceFd, VmFd};
 use crate::arch::aarch64::gic::{GicError, GicState};
 
 #[derive(Debug)]
-pub struct GICv3(super::GIC);
+pub struct GICv3 {
+    gic: super::GIC,
+    /// The file descriptor for the KVM ITS device, if one was created.
+    its_fd: Option<DeviceFd>,
+}
 
 impl std::ops::Deref for GICv3 {
     type Target = super::GIC;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.gic
     }
 }
 
@@ -24,6 +28,7 @@ impl GICv3 {
     const SZ_64K: u64 = 0x0001_0000;
     const KVM_VGIC_V3_DIST_SIZE: u64 = GICv3::SZ_64K;
     const KVM_VGIC_V3_REDIST_SIZE: u64 = (2 * GICv3::SZ_64K);
+    const KVM_VGIC_V3_ITS_SIZE: u64 = (2 * GICv3::SZ_64K);
 
     // Device trees specific constants
     const ARCH_GIC_V3_MAINT_IRQ: u32 = 9;
@@ -48,6 +53,11 @@ impl GICv3 {
         vcpu_count * GICv3::KVM_VGIC_V3_REDIST_SIZE
     }
 
+    /// Get the address of the ITS, placed right below the GIC redistributors.
+    fn get_its_addr(vcpu_count: u64) -> u64 {
+        GICv3::get_redists_addr(vcpu_count) - GICv3::KVM_VGIC_V3_ITS_SIZE
+    }
+
     pub const VERSION: u32 = kvm_bindings::kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_V3;
 
     pub fn fdt_compatibility(&self) -> &str {
@@ -58,26 +68,77 @@ impl GICv3 {
         GICv3::ARCH_GIC_V3_MAINT_IRQ
     }
 
+    /// Returns the file descriptor of the ITS device, if there is one
+    pub fn its_device_fd(&self) -> Option<&DeviceFd> {
+        self.its_fd.as_ref()
+    }
+
+    /// Returns the address and size of the ITS, if there is one
+    pub fn its_properties(&self) -> Option<[u64; 2]> {
+        self.its_fd.as_ref().map(|_| {
+            [
+                GICv3::get_its_addr(self.vcpu_count),
+                GICv3::KVM_VGIC_V3_ITS_SIZE,
+            ]
+        })
+    }
+
     /// Create the GIC device object
     pub fn create_device(fd: DeviceFd, vcpu_count: u64) -> Self {
-        GICv3(super::GIC {
-            fd,
-            properties: [
-                GICv3::get_dist_addr(),
-                GICv3::get_dist_size(),
-                GICv3::get_redists_addr(vcpu_count),
-                GICv3::get_redists_size(vcpu_count),
-            ],
-            vcpu_count,
-        })
+        GICv3 {
+            gic: super::GIC {
+                fd,
+                properties: [
+                    GICv3::get_dist_addr(),
+                    GICv3::get_dist_size(),
+                    GICv3::get_redists_addr(vcpu_count),
+                    GICv3::get_redists_size(vcpu_count),
+                ],
+                vcpu_count,
+            },
+            its_fd: None,
+        }
+    }
+
+    /// Create an ITS (Interrupt Translation Service) for this GIC, which allows the guest to
+    /// use MSIs (delivered as LPIs).
+    ///
+    /// KVM allows creating the ITS after the GIC was initialized.
+    pub fn create_its(&mut self, vm: &VmFd) -> Result<(), GicError> {
+        let mut its_device = kvm_bindings::kvm_create_device {
+            type_: kvm_bindings::kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_ITS,
+            fd: 0,
+            flags: 0,
+        };
+        let its_fd = vm
+            .create_device(&mut its_device)
+            .map_err(GicError::CreateITS)?;
+
+        Self::set_device_attribute(
+            &its_fd,
+            kvm_bindings::KVM_DEV_ARM_VGIC_GRP_ADDR,
+            u64::from(kvm_bindings::KVM_VGIC_ITS_ADDR_TYPE),
+            &GICv3::get_its_addr(self.vcpu_count) as *const u64 as u64,
+            0,
+        )?;
+        Self::set_device_attribute(
+            &its_fd,
+            kvm_bindings::KVM_DEV_ARM_VGIC_GRP_CTRL,
+            u64::from(kvm_bindings::KVM_DEV_ARM_VGIC_CTRL_INIT),
+            0,
+            0,
+        )?;
+
+        self.its_fd = Some(its_fd);
+        Ok(())
     }
 
     pub fn save_device(&self, mpidrs: &[u64]) -> Result<GicState, GicError> {
-        regs::save_state(&self.fd, mpidrs)
+        regs::save_state(&self.fd, self.its_fd.as_ref(), mpidrs)
     }
 
     pub fn restore_device(&self, mpidrs: &[u64], state: &GicState) -> Result<(), GicError> {
-        regs::restore_state(&self.fd, mpidrs, state)
+        regs::restore_state(&self.fd, self.its_fd.as_ref(), mpidrs, state)
     }
 
     pub fn init_device_attributes(gic_device: &Self) -> Result<(), GicError> {
@@ -0,0 +1,106 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::*;
+use kvm_ioctls::DeviceFd;
+
+use crate::arch::aarch64::gic::regs::{GicRegState, ItsState, SimpleReg, VgicRegEngine};
+use crate::arch::aarch64::gic::GicError;
+
+// ITS registers that we want to save/restore.
+// KVM always accesses them through a 64-bit value, regardless of their actual width.
+const GITS_CTLR: SimpleReg = SimpleReg::new(0x0000, 8);
+const GITS_IIDR: SimpleReg = SimpleReg::new(0x0004, 8);
+const GITS_CBASER: SimpleReg = SimpleReg::new(0x0080, 8);
+const GITS_CWRITER: SimpleReg = SimpleReg::new(0x0088, 8);
+const GITS_CREADR: SimpleReg = SimpleReg::new(0x0090, 8);
+const GITS_BASER: SimpleReg = SimpleReg::new(0x0100, 64);
+
+// List with the ITS registers that we will be restoring before the ITS tables, in the order
+// required by KVM (see Documentation/virt/kvm/devices/arm-vgic-its.rst). GITS_CTLR is
+// restored last, once the tables are restored, as it may enable the ITS.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+static VGIC_ITS_REGS: &[SimpleReg] = &[
+    GITS_IIDR,
+    GITS_BASER,
+    GITS_CBASER,
+    GITS_CREADR,
+    GITS_CWRITER,
+];
+
+struct ItsRegEngine {}
+
+impl VgicRegEngine for ItsRegEngine {
+    type Reg = SimpleReg;
+    type RegChunk = u64;
+
+    fn group() -> u32 {
+        KVM_DEV_ARM_VGIC_GRP_ITS_REGS
+    }
+}
+
+fn set_its_ctrl(fd: &DeviceFd, attr: u32) -> Result<(), GicError> {
+    let ctrl_attr = kvm_device_attr {
+        group: KVM_DEV_ARM_VGIC_GRP_CTRL,
+        attr: u64::from(attr),
+        addr: 0,
+        flags: 0,
+    };
+    fd.set_device_attr(&ctrl_attr)
+        .map_err(|err| GicError::DeviceAttribute(err, true, KVM_DEV_ARM_VGIC_GRP_CTRL))
+}
+
+/// Flushes the ITS tables into guest RAM and saves the ITS registers.
+pub(crate) fn get_its_regs(fd: &DeviceFd) -> Result<ItsState, GicError> {
+    set_its_ctrl(fd, KVM_DEV_ARM_ITS_SAVE_TABLES)?;
+
+    Ok(ItsState {
+        regs: ItsRegEngine::get_regs_data(fd, Box::new(VGIC_ITS_REGS.iter()), 0)?,
+        ctlr: ItsRegEngine::get_reg_data(fd, &GITS_CTLR, 0)?,
+    })
+}
+
+/// Restores the ITS registers and reloads the ITS tables from guest RAM.
+pub(crate) fn set_its_regs(fd: &DeviceFd, state: &ItsState) -> Result<(), GicError> {
+    ItsRegEngine::set_regs_data(fd, Box::new(VGIC_ITS_REGS.iter()), &state.regs, 0)?;
+    set_its_ctrl(fd, KVM_DEV_ARM_ITS_RESTORE_TABLES)?;
+    ItsRegEngine::set_reg_data(fd, &GITS_CTLR, &state.ctlr, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+    use std::os::unix::io::AsRawFd;
+
+    use kvm_ioctls::Kvm;
+
+    use super::*;
+    use crate::arch::aarch64::gic::{create_gic, GICVersion};
+
+    #[test]
+    fn test_access_its_regs() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let _ = vm.create_vcpu(0).unwrap();
+        let mut gic = create_gic(&vm, 1, Some(GICVersion::GICV3)).expect("Cannot create gic");
+        gic.create_its(&vm).unwrap();
+        let its_fd = gic.its_device_fd().unwrap();
+
+        let state = get_its_regs(its_fd).unwrap();
+        // GITS_IIDR, the 8 GITS_BASER<n>, GITS_CBASER, GITS_CREADR and GITS_CWRITER.
+        assert_eq!(
+            state.regs.iter().map(|reg| reg.chunks.len()).sum::<usize>(),
+            12
+        );
+        assert!(set_its_regs(its_fd, &state).is_ok());
+
+        unsafe { libc::close(its_fd.as_raw_fd()) };
+
+        let res = get_its_regs(its_fd);
+        assert!(res.is_err());
+        assert_eq!(
+            format!("{:?}", res.unwrap_err()),
+            "DeviceAttribute(Error(9), true, 4)"
+        );
+    }
+}
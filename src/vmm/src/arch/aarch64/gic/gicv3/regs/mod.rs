@@ -3,6 +3,7 @@
 
 mod dist_regs;
 mod icc_regs;
+mod its_regs;
 mod redist_regs;
 
 use kvm_ioctls::DeviceFd;
@@ -10,8 +11,12 @@ use kvm_ioctls::DeviceFd;
 use crate::arch::aarch64::gic::regs::{GicState, GicVcpuState};
 use crate::arch::aarch64::gic::GicError;
 
-/// Save the state of the GIC device.
-pub fn save_state(fd: &DeviceFd, mpidrs: &[u64]) -> Result<GicState, GicError> {
+/// Save the state of the GIC device and of its ITS, if there is one.
+pub fn save_state(
+    fd: &DeviceFd,
+    its_fd: Option<&DeviceFd>,
+    mpidrs: &[u64],
+) -> Result<GicState, GicError> {
     // Flush redistributors pending tables to guest RAM.
     super::save_pending_tables(fd)?;
 
@@ -26,11 +31,17 @@ pub fn save_state(fd: &DeviceFd, mpidrs: &[u64]) -> Result<GicState, GicError> {
     Ok(GicState {
         dist: dist_regs::get_dist_regs(fd)?,
         gic_vcpu_states: vcpu_states,
+        its: its_fd.map(its_regs::get_its_regs).transpose()?,
     })
 }
 
-/// Restore the state of the GIC device.
-pub fn restore_state(fd: &DeviceFd, mpidrs: &[u64], state: &GicState) -> Result<(), GicError> {
+/// Restore the state of the GIC device and of its ITS, if there is one.
+pub fn restore_state(
+    fd: &DeviceFd,
+    its_fd: Option<&DeviceFd>,
+    mpidrs: &[u64],
+    state: &GicState,
+) -> Result<(), GicError> {
     dist_regs::set_dist_regs(fd, &state.dist)?;
 
     if mpidrs.len() != state.gic_vcpu_states.len() {
@@ -41,6 +52,15 @@ pub fn restore_state(fd: &DeviceFd, mpidrs: &[u64], state: &GicState) -> Result<
         icc_regs::set_icc_regs(fd, *mpidr, &vcpu_state.icc)?;
     }
 
+    // The ITS is restored last, as restoring its tables relies on the redistributors'
+    // GICR_PROPBASER and GICR_PENDBASER registers.
+    match (its_fd, &state.its) {
+        (Some(its_fd), Some(its_state)) => its_regs::set_its_regs(its_fd, its_state)?,
+        (None, Some(_)) => return Err(GicError::MissingIts),
+        // A snapshot taken without an ITS never exposed one to the guest.
+        (_, None) => (),
+    }
+
     Ok(())
 }
 
@@ -59,7 +79,7 @@ mod tests {
         let gic_fd = gic.device_fd();
 
         let mpidr = vec![1];
-        let res = save_state(gic_fd, &mpidr);
+        let res = save_state(gic_fd, None, &mpidr);
         // We will receive an error if trying to call before creating vcpu.
         assert!(res.is_err());
         assert_eq!(
@@ -73,7 +93,7 @@ mod tests {
         let gic = create_gic(&vm, 1, Some(GICVersion::GICV3)).expect("Cannot create gic");
         let gic_fd = gic.device_fd();
 
-        let vm_state = save_state(gic_fd, &mpidr).unwrap();
+        let vm_state = save_state(gic_fd, None, &mpidr).unwrap();
         let val: u32 = 0;
         let gicd_statusr_off = 0x0010u64;
         let mut gic_dist_attr = kvm_bindings::kvm_device_attr {
@@ -91,7 +111,27 @@ mod tests {
 
         assert_eq!(gicd_statusr.chunks[0], val);
         assert_eq!(vm_state.dist.len(), 12);
-        assert!(restore_state(gic_fd, &mpidr, &vm_state).is_ok());
-        assert!(restore_state(gic_fd, &[1, 2], &vm_state).is_err());
+        assert!(restore_state(gic_fd, None, &mpidr, &vm_state).is_ok());
+        assert!(restore_state(gic_fd, None, &[1, 2], &vm_state).is_err());
+    }
+
+    #[test]
+    fn test_vm_save_restore_state_with_its() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let _vcpu = vm.create_vcpu(0).unwrap();
+        let mut gic = create_gic(&vm, 1, Some(GICVersion::GICV3)).expect("Cannot create gic");
+        gic.create_its(&vm).unwrap();
+        let gic_fd = gic.device_fd();
+        let its_fd = gic.its_device_fd();
+
+        let mpidr = vec![1];
+        let vm_state = save_state(gic_fd, its_fd, &mpidr).unwrap();
+        assert!(vm_state.its.is_some());
+        assert!(restore_state(gic_fd, its_fd, &mpidr, &vm_state).is_ok());
+        assert_eq!(
+            restore_state(gic_fd, None, &mpidr, &vm_state).unwrap_err(),
+            GicError::MissingIts
+        );
     }
 }
@@ -8,7 +8,7 @@ mod regs;
 use gicv2::GICv2;
 use gicv3::GICv3;
 use kvm_ioctls::{DeviceFd, VmFd};
-pub use regs::GicState;
+pub use regs::{GicState, ItsState};
 
 use super::layout;
 
@@ -47,6 +47,9 @@ pub enum GicError {
     /// Error while calling KVM ioctl for setting up the global interrupt controller.
     #[error("Error while calling KVM ioctl for setting up the global interrupt controller: {0}")]
     CreateGIC(kvm_ioctls::Error),
+    /// Error while calling KVM ioctl for setting up the interrupt translation service.
+    #[error("Error while calling KVM ioctl for setting up the interrupt translation service: {0}")]
+    CreateITS(kvm_ioctls::Error),
     /// Error while setting or getting device attributes for the GIC.
     #[error("Error while setting or getting device attributes for the GIC: {0}, {1}, {2}")]
     DeviceAttribute(kvm_ioctls::Error, bool, u32),
@@ -58,6 +61,12 @@ pub enum GicError {
     /// The VgicSysRegsState is invalid
     #[error("The VgicSysRegsState is invalid.")]
     InvalidVgicSysRegState,
+    /// The GicState holds an ITS state, but the GIC has no ITS
+    #[error("The GicState holds an ITS state, but the GIC has no ITS.")]
+    MissingIts,
+    /// The GIC does not support an ITS
+    #[error("The GIC does not support an ITS.")]
+    ItsNotSupported,
 }
 
 /// List of implemented GICs.
@@ -65,7 +74,7 @@ pub enum GicError {
 pub enum GICVersion {
     /// Legacy version.
     GICV2,
-    /// GICV3 without ITS.
+    /// GICV3, with an optional ITS.
     GICV3,
 }
 
@@ -74,7 +83,7 @@ pub enum GICVersion {
 pub enum GICDevice {
     /// Legacy version.
     V2(GICv2),
-    /// GICV3 without ITS.
+    /// GICV3, with an optional ITS.
     V3(GICv3),
 }
 impl GICDevice {
@@ -118,6 +127,30 @@ impl GICDevice {
         }
     }
 
+    /// Returns the file descriptor of the ITS device, if there is one
+    pub fn its_device_fd(&self) -> Option<&DeviceFd> {
+        match self {
+            Self::V2(_) => None,
+            Self::V3(x) => x.its_device_fd(),
+        }
+    }
+
+    /// Returns the address and size of the ITS, if there is one
+    pub fn its_properties(&self) -> Option<[u64; 2]> {
+        match self {
+            Self::V2(_) => None,
+            Self::V3(x) => x.its_properties(),
+        }
+    }
+
+    /// Creates an ITS for the device. Only supported on GICv3.
+    pub fn create_its(&mut self, vm: &VmFd) -> Result<(), GicError> {
+        match self {
+            Self::V2(_) => Err(GicError::ItsNotSupported),
+            Self::V3(x) => x.create_its(vm),
+        }
+    }
+
     /// Returns the GIC version of the device
     pub fn version(&self) -> u32 {
         match self {
@@ -183,4 +216,25 @@ mod tests {
         let vm = kvm.create_vm().unwrap();
         assert!(create_gic(&vm, 1, None).is_ok());
     }
+
+    #[test]
+    fn test_create_its() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let mut gic = create_gic(&vm, 1, None).unwrap();
+        assert!(gic.its_device_fd().is_none());
+        assert!(gic.its_properties().is_none());
+
+        match gic.create_its(&vm) {
+            Ok(()) => {
+                assert!(gic.its_device_fd().is_some());
+                let [its_addr, its_size] = gic.its_properties().unwrap();
+                // The ITS sits right below the redistributors.
+                assert_eq!(its_addr + its_size, gic.device_properties()[2]);
+            }
+            // GICv2 hosts, or GICv3 hosts without an ITS.
+            Err(GicError::ItsNotSupported) | Err(GicError::CreateITS(_)) => (),
+            Err(err) => panic!("Unexpected error: {}", err),
+        }
+    }
 }
@@ -31,6 +31,20 @@ pub struct GicState {
     pub dist: Vec<GicRegState<u32>>,
     /// The state of the vcpu interfaces.
     pub gic_vcpu_states: Vec<GicVcpuState>,
+    /// The state of the ITS, if the GIC has one.
+    #[version(start = 2)]
+    pub its: Option<ItsState>,
+}
+
+/// Structure used for serializing the state of the GICv3 ITS registers.
+///
+/// The ITS tables themselves live in guest memory, so they are part of the memory snapshot.
+#[derive(Debug, Versionize)]
+pub struct ItsState {
+    /// The state of the registers restored before the ITS tables.
+    pub regs: Vec<GicRegState<u64>>,
+    /// The state of the GITS_CTLR register, restored after the ITS tables.
+    pub ctlr: GicRegState<u64>,
 }
 
 /// Structure used for serializing the state of the GIC registers for a specific vCPU.
@@ -17,7 +17,7 @@ use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt::Debug;
 
-use utils::vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap};
+use utils::vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 pub use self::fdt::DeviceInfoForFDT;
 use self::gic::GICDevice;
@@ -44,6 +44,22 @@ pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
     vec![(GuestAddress(layout::DRAM_MEM_START), dram_size)]
 }
 
+/// Returns the guest physical range reserved for memory hotplug, placed after `mem_size` bytes of
+/// boot RAM, or `None` if a range of `hotplug_size` bytes does not fit the address space.
+pub fn hotplug_memory_region(
+    mem_size: usize,
+    hotplug_size: usize,
+) -> Option<(GuestAddress, usize)> {
+    let dram_end = layout::DRAM_MEM_START.checked_add(mem_size as u64)?;
+    let start = dram_end.checked_add(layout::HOTPLUG_MEM_ALIGNMENT - 1)?
+        & !(layout::HOTPLUG_MEM_ALIGNMENT - 1);
+    let end = start.checked_add(hotplug_size as u64)?;
+    if hotplug_size == 0 || end > layout::DRAM_MEM_START + layout::DRAM_MEM_MAX_SIZE {
+        return None;
+    }
+    Some((GuestAddress(start), hotplug_size))
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
 /// For aarch64, we only setup the FDT.
 ///
@@ -55,6 +71,7 @@ pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
 /// * `device_info` - A hashmap containing the attached devices for building FDT device nodes.
 /// * `gic_device` - The GIC device.
 /// * `initrd` - Information about an optional initrd.
+/// * `vm_uuid` - The optional UUID of the microVM.
 pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     guest_mem: &GuestMemoryMmap,
     cmdline_cstring: CString,
@@ -62,6 +79,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<super::InitrdConfig>,
+    vm_uuid: Option<&str>,
 ) -> Result<(), ConfigurationError> {
     fdt::create_fdt(
         guest_mem,
@@ -70,6 +88,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
         device_info,
         gic_device,
         initrd,
+        vm_uuid,
     )?;
     Ok(())
 }
@@ -97,13 +116,21 @@ pub fn initrd_load_addr(
     }
 }
 
+// Auxiliary function to get the last address of the boot RAM. The memory hotplug region, if any,
+// lives past it and is not usable before the guest plugs it.
+fn dram_last_addr(mem: &GuestMemoryMmap) -> GuestAddress {
+    mem.find_region(GuestAddress(layout::DRAM_MEM_START))
+        .map(|region| region.last_addr())
+        .unwrap_or_else(|| mem.last_addr())
+}
+
 // Auxiliary function to get the address where the device tree blob is loaded.
 fn get_fdt_addr(mem: &GuestMemoryMmap) -> u64 {
     // If the memory allocated is smaller than the size allocated for the FDT,
     // we return the start of the DRAM so that
     // we allow the code to try and load the FDT.
 
-    if let Some(addr) = mem.last_addr().checked_sub(layout::FDT_MAX_SIZE as u64 - 1) {
+    if let Some(addr) = dram_last_addr(mem).checked_sub(layout::FDT_MAX_SIZE as u64 - 1) {
         if mem.address_in_range(addr) {
             return addr.raw_value();
         }
@@ -148,5 +175,29 @@ mod tests {
         let mem = utils::vm_memory::test_utils::create_anon_guest_memory(&regions, false)
             .expect("Cannot initialize memory");
         assert_eq!(get_fdt_addr(&mem), 0x1000 + layout::DRAM_MEM_START);
+
+        // The FDT is placed at the end of the boot RAM, before the memory hotplug region.
+        let mut regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        regions.push(hotplug_memory_region(layout::FDT_MAX_SIZE + 0x1000, 0x10_0000).unwrap());
+        let mem = utils::vm_memory::test_utils::create_anon_guest_memory(&regions, false)
+            .expect("Cannot initialize memory");
+        assert_eq!(get_fdt_addr(&mem), 0x1000 + layout::DRAM_MEM_START);
+    }
+
+    #[test]
+    fn test_hotplug_memory_region() {
+        assert_eq!(
+            hotplug_memory_region(1 << 29, 1 << 30),
+            Some((GuestAddress(layout::DRAM_MEM_START + (1 << 30)), 1 << 30))
+        );
+        assert_eq!(
+            hotplug_memory_region(1 << 30, 1 << 29),
+            Some((GuestAddress(layout::DRAM_MEM_START + (1 << 30)), 1 << 29))
+        );
+        assert_eq!(hotplug_memory_region(1 << 30, 0), None);
+        assert_eq!(
+            hotplug_memory_region(1 << 30, layout::DRAM_MEM_MAX_SIZE as usize),
+            None
+        );
     }
 }
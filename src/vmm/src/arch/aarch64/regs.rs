@@ -103,8 +103,10 @@ arm64_sys_reg!(MIDR_EL1, 3, 0, 0, 0, 0);
 // ID registers that represent cpu capabilities.
 // Needed for static cpu templates.
 arm64_sys_reg!(ID_AA64PFR0_EL1, 3, 0, 0, 4, 0);
+arm64_sys_reg!(ID_AA64PFR1_EL1, 3, 0, 0, 4, 1);
 arm64_sys_reg!(ID_AA64ISAR0_EL1, 3, 0, 0, 6, 0);
 arm64_sys_reg!(ID_AA64ISAR1_EL1, 3, 0, 0, 6, 1);
+arm64_sys_reg!(ID_AA64ISAR2_EL1, 3, 0, 0, 6, 2);
 arm64_sys_reg!(ID_AA64MMFR2_EL1, 3, 0, 0, 7, 2);
 
 // EL0 Virtual Timer Registers
@@ -81,7 +81,7 @@ pub fn get_manufacturer_id_from_host() -> Result<u32, VcpuError> {
 /// * `mem` - Reserved DRAM for current VM.
 pub fn setup_boot_regs(
     vcpufd: &VcpuFd,
-    cpu_id: u8,
+    cpu_id: u16,
     boot_ip: u64,
     mem: &GuestMemoryMmap,
 ) -> Result<(), VcpuError> {
@@ -41,6 +41,8 @@ pub enum DeviceType {
     Rtc,
     /// Device Type: BootTimer.
     BootTimer,
+    /// Device Type: TPM.
+    Tpm,
 }
 
 /// Type for passing information about the initrd in the guest memory.
@@ -52,6 +54,25 @@ pub struct InitrdConfig {
     pub size: usize,
 }
 
+/// Types of boot protocols supported to start the guest kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootProtocol {
+    /// Linux 64-bit boot protocol on x86_64, Linux arm64 boot protocol on aarch64.
+    LinuxBoot,
+    /// PVH boot protocol.
+    #[cfg(target_arch = "x86_64")]
+    PvhBoot,
+}
+
+/// Address where the guest starts executing, along with the protocol used to boot it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    /// Address in guest memory of the first instruction executed by the boot vCPU.
+    pub entry_addr: utils::vm_memory::GuestAddress,
+    /// Protocol used to boot the guest kernel.
+    pub protocol: BootProtocol,
+}
+
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
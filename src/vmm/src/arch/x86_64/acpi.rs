@@ -0,0 +1,763 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use utils::vm_memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::layout::{ACPI_RSDP_START, ACPI_SLEEP_PORT, SMBIOS_START};
+use crate::arch::DeviceType;
+
+// OEM identification shared by all the tables.
+const OEM_ID: [u8; 6] = *b"FIRECK";
+const OEM_REVISION: u32 = 1;
+const CREATOR_ID: [u8; 4] = *b"FCAT";
+const CREATOR_REVISION: u32 = 1;
+
+// Size of the common System Description Table header.
+const SDT_HEADER_SIZE: usize = 36;
+// Offset of the checksum byte in the System Description Table header.
+const SDT_CHECKSUM_OFFSET: usize = 9;
+// Size of an ACPI 2.0+ Root System Description Pointer.
+const RSDP_SIZE: usize = 36;
+// Number of bytes covered by the legacy (ACPI 1.0) RSDP checksum.
+const RSDP_V1_SIZE: usize = 20;
+// Size of an ACPI 6.x Fixed ACPI Description Table.
+const FADT_SIZE: usize = 276;
+
+// Offsets of the FADT fields that we populate.
+const FADT_FLAGS_OFFSET: usize = 112;
+const FADT_IAPC_BOOT_ARCH_OFFSET: usize = 109;
+const FADT_MINOR_VERSION_OFFSET: usize = 131;
+const FADT_X_DSDT_OFFSET: usize = 140;
+const FADT_SLEEP_CONTROL_REG_OFFSET: usize = 244;
+const FADT_SLEEP_STATUS_REG_OFFSET: usize = 256;
+
+// FADT flags, see ACPI 6.5 section 5.2.9, table 5.10.
+const FADT_F_HW_REDUCED_ACPI: u32 = 1 << 20;
+// IA-PC boot architecture flags, see ACPI 6.5 section 5.2.9.3, table 5.11.
+const IAPC_BOOT_ARCH_8042: u16 = 1 << 1;
+const IAPC_BOOT_ARCH_VGA_NOT_PRESENT: u16 = 1 << 2;
+const IAPC_BOOT_ARCH_MSI_NOT_SUPPORTED: u16 = 1 << 3;
+const IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+
+// Generic Address Structure address space for system I/O.
+const GAS_SYSTEM_IO: u8 = 1;
+// Generic Address Structure byte access size.
+const GAS_ACCESS_BYTE: u8 = 1;
+
+// Physical address of the local APIC as seen by every vCPU.
+const APIC_DEFAULT_PHYS_BASE: u32 = 0xfee0_0000;
+// Physical address of the IOAPIC.
+const IO_APIC_DEFAULT_PHYS_BASE: u32 = 0xfec0_0000;
+// MADT flag signaling that dual 8259 PICs are also present.
+const MADT_PCAT_COMPAT: u32 = 1;
+// MADT interrupt controller structure types.
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LOCAL_APIC_NMI: u8 = 4;
+const MADT_LOCAL_X2APIC: u8 = 9;
+const MADT_LOCAL_X2APIC_NMI: u8 = 10;
+// Processor UID matching all the processors in the NMI structures.
+const MADT_ALL_PROCESSORS: u32 = u32::MAX;
+// TPM2 table fields, see the TCG ACPI specification, section 8.3.
+const TPM2_PLATFORM_CLASS_CLIENT: u16 = 0;
+const TPM2_START_METHOD_MMIO: u32 = 6;
+
+/// Sleep type advertised to the guest for the S5 (soft off) state. The guest writes it,
+/// together with `SLP_EN`, to the sleep control register when powering off.
+pub const S5_SLEEP_TYPE: u8 = 5;
+
+// Hardware ids of the devices described in the DSDT.
+const VIRTIO_MMIO_HID: &str = "LNRO0005";
+const COM_HID: &str = "PNP0501";
+const I8042_HID: &str = "PNP0303";
+const TPM_HID: &str = "MSFT0101";
+
+// Legacy devices registered on the PIO bus by the `PortIODeviceManager`.
+const COM1_PORT: u16 = 0x3f8;
+const COM1_PORT_SIZE: u8 = 0x8;
+const COM1_GSI: u32 = 4;
+const I8042_DATA_PORT: u16 = 0x60;
+const I8042_COMMAND_PORT: u16 = 0x64;
+const I8042_GSI: u32 = 1;
+
+/// Errors thrown while setting up the ACPI tables.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The ACPI tables do not fit in the BIOS area below the SMBIOS tables.
+    TablesTooLarge,
+    /// Failure to write an ACPI table to guest memory.
+    WriteTable,
+    /// Failure to write the Root System Description Pointer to guest memory.
+    WriteRsdp,
+}
+
+/// Trait for devices to be described to the guest through the DSDT.
+pub trait DeviceInfoForAcpi {
+    /// Returns the address where this device will be loaded.
+    fn addr(&self) -> u64;
+    /// Returns the associated interrupt for this device.
+    fn irq(&self) -> u32;
+    /// Returns the amount of memory that needs to be reserved for this device.
+    fn length(&self) -> u64;
+}
+
+fn compute_checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x));
+    0u8.wrapping_sub(sum)
+}
+
+/// Helper for building a System Description Table.
+#[derive(Debug)]
+struct Sdt {
+    data: Vec<u8>,
+}
+
+impl Sdt {
+    fn new(signature: [u8; 4], revision: u8, table_id: [u8; 8]) -> Self {
+        let mut data = Vec::with_capacity(SDT_HEADER_SIZE);
+        data.extend_from_slice(&signature);
+        // The length is filled in by `finish`.
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.push(revision);
+        // The checksum is filled in by `finish`.
+        data.push(0);
+        data.extend_from_slice(&OEM_ID);
+        data.extend_from_slice(&table_id);
+        data.extend_from_slice(&OEM_REVISION.to_le_bytes());
+        data.extend_from_slice(&CREATOR_ID);
+        data.extend_from_slice(&CREATOR_REVISION.to_le_bytes());
+        Sdt { data }
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.data.len() as u32;
+        self.write_at(4, &len.to_le_bytes());
+        self.data[SDT_CHECKSUM_OFFSET] = compute_checksum(&self.data);
+        self.data
+    }
+}
+
+// Generic Address Structure describing a byte wide I/O port.
+fn gas_io_port(port: u64) -> Vec<u8> {
+    let mut gas = vec![GAS_SYSTEM_IO, 8, 0, GAS_ACCESS_BYTE];
+    gas.extend_from_slice(&port.to_le_bytes());
+    gas
+}
+
+// Minimal AML encoders for the objects we need in the DSDT.
+// See ACPI 6.5 section 20.2 for the grammar.
+mod aml {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const NAME_OP: u8 = 0x08;
+    const BYTE_PREFIX: u8 = 0x0a;
+    const WORD_PREFIX: u8 = 0x0b;
+    const DWORD_PREFIX: u8 = 0x0c;
+    const STRING_PREFIX: u8 = 0x0d;
+    const QWORD_PREFIX: u8 = 0x0e;
+    const SCOPE_OP: u8 = 0x10;
+    const BUFFER_OP: u8 = 0x11;
+    const PACKAGE_OP: u8 = 0x12;
+    const EXT_OP_PREFIX: u8 = 0x5b;
+    const DEVICE_OP: u8 = 0x82;
+
+    // Resource descriptor tags, see ACPI 6.5 section 6.4.
+    const IO_PORT_DESCRIPTOR: u8 = 0x47;
+    const END_TAG: u8 = 0x79;
+    const MEMORY32_FIXED_DESCRIPTOR: u8 = 0x86;
+    const EXTENDED_IRQ_DESCRIPTOR: u8 = 0x89;
+
+    /// Encodes the length of a package. The encoded value includes the bytes
+    /// used by the encoding itself.
+    pub fn pkg_length(content_len: usize) -> Vec<u8> {
+        if content_len + 1 < 1 << 6 {
+            vec![(content_len + 1) as u8]
+        } else if content_len + 2 < 1 << 12 {
+            let len = content_len + 2;
+            vec![(1 << 6) | (len & 0xf) as u8, (len >> 4) as u8]
+        } else if content_len + 3 < 1 << 20 {
+            let len = content_len + 3;
+            vec![
+                (2 << 6) | (len & 0xf) as u8,
+                (len >> 4) as u8,
+                (len >> 12) as u8,
+            ]
+        } else {
+            let len = content_len + 4;
+            vec![
+                (3 << 6) | (len & 0xf) as u8,
+                (len >> 4) as u8,
+                (len >> 12) as u8,
+                (len >> 20) as u8,
+            ]
+        }
+    }
+
+    pub fn integer(value: u64) -> Vec<u8> {
+        match value {
+            0 => vec![ZERO_OP],
+            1 => vec![ONE_OP],
+            v if v <= u64::from(u8::MAX) => vec![BYTE_PREFIX, v as u8],
+            v if v <= u64::from(u16::MAX) => {
+                let mut bytes = vec![WORD_PREFIX];
+                bytes.extend_from_slice(&(v as u16).to_le_bytes());
+                bytes
+            }
+            v if v <= u64::from(u32::MAX) => {
+                let mut bytes = vec![DWORD_PREFIX];
+                bytes.extend_from_slice(&(v as u32).to_le_bytes());
+                bytes
+            }
+            v => {
+                let mut bytes = vec![QWORD_PREFIX];
+                bytes.extend_from_slice(&v.to_le_bytes());
+                bytes
+            }
+        }
+    }
+
+    pub fn string(value: &str) -> Vec<u8> {
+        let mut bytes = vec![STRING_PREFIX];
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    pub fn name(name: &[u8; 4], value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![NAME_OP];
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn with_pkg_length(opcode: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = opcode.to_vec();
+        bytes.extend_from_slice(&pkg_length(content.len()));
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    pub fn scope(name: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut content = name.to_vec();
+        content.extend_from_slice(children);
+        with_pkg_length(&[SCOPE_OP], &content)
+    }
+
+    pub fn device(name: &[u8; 4], children: &[u8]) -> Vec<u8> {
+        let mut content = name.to_vec();
+        content.extend_from_slice(children);
+        with_pkg_length(&[EXT_OP_PREFIX, DEVICE_OP], &content)
+    }
+
+    pub fn package(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut content = vec![elements.len() as u8];
+        for element in elements {
+            content.extend_from_slice(element);
+        }
+        with_pkg_length(&[PACKAGE_OP], &content)
+    }
+
+    pub fn buffer(data: &[u8]) -> Vec<u8> {
+        let mut content = integer(data.len() as u64);
+        content.extend_from_slice(data);
+        with_pkg_length(&[BUFFER_OP], &content)
+    }
+
+    /// Wraps resource descriptors into a `ResourceTemplate` buffer.
+    pub fn resource_template(descriptors: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for descriptor in descriptors {
+            data.extend_from_slice(descriptor);
+        }
+        // A zero checksum means that the resource template is treated as valid.
+        data.extend_from_slice(&[END_TAG, 0]);
+        buffer(&data)
+    }
+
+    pub fn memory32_fixed(base: u32, len: u32) -> Vec<u8> {
+        // Read-write memory range.
+        let mut bytes = vec![MEMORY32_FIXED_DESCRIPTOR, 9, 0, 1];
+        bytes.extend_from_slice(&base.to_le_bytes());
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    pub fn io_port(base: u16, len: u8) -> Vec<u8> {
+        // 16-bit decode, fixed base with byte alignment.
+        let mut bytes = vec![IO_PORT_DESCRIPTOR, 1];
+        bytes.extend_from_slice(&base.to_le_bytes());
+        bytes.extend_from_slice(&base.to_le_bytes());
+        bytes.extend_from_slice(&[1, len]);
+        bytes
+    }
+
+    pub fn extended_irq(irq: u32) -> Vec<u8> {
+        // Consumer, edge triggered, active high, exclusive interrupt.
+        let mut bytes = vec![EXTENDED_IRQ_DESCRIPTOR, 6, 0, 0b11, 1];
+        bytes.extend_from_slice(&irq.to_le_bytes());
+        bytes
+    }
+}
+
+fn create_dsdt<T: DeviceInfoForAcpi + Clone + Debug, S: std::hash::BuildHasher>(
+    device_info: &HashMap<(DeviceType, String), T, S>,
+) -> Vec<u8> {
+    let mut devices = Vec::new();
+
+    // Sort the virtio devices by address so that the table contents are deterministic.
+    let mut virtio_devices: Vec<&T> = device_info
+        .iter()
+        .filter(|((device_type, _), _)| matches!(device_type, DeviceType::Virtio(_)))
+        .map(|(_, info)| info)
+        .collect();
+    virtio_devices.sort_by_key(|info| info.addr());
+
+    for (index, info) in virtio_devices.into_iter().enumerate() {
+        let name = format!("V{:03X}", index);
+        let mut children = aml::name(b"_HID", &aml::string(VIRTIO_MMIO_HID));
+        children.extend(aml::name(b"_UID", &aml::integer(index as u64)));
+        children.extend(aml::name(
+            b"_CRS",
+            &aml::resource_template(&[
+                aml::memory32_fixed(info.addr() as u32, info.length() as u32),
+                aml::extended_irq(info.irq()),
+            ]),
+        ));
+        devices.extend(aml::device(
+            name.as_bytes().try_into().expect("Invalid AML name"),
+            &children,
+        ));
+    }
+
+    let mut com1 = aml::name(b"_HID", &aml::string(COM_HID));
+    com1.extend(aml::name(b"_UID", &aml::integer(1)));
+    com1.extend(aml::name(
+        b"_CRS",
+        &aml::resource_template(&[
+            aml::io_port(COM1_PORT, COM1_PORT_SIZE),
+            aml::extended_irq(COM1_GSI),
+        ]),
+    ));
+    devices.extend(aml::device(b"COM1", &com1));
+
+    let mut i8042 = aml::name(b"_HID", &aml::string(I8042_HID));
+    i8042.extend(aml::name(
+        b"_CRS",
+        &aml::resource_template(&[
+            aml::io_port(I8042_DATA_PORT, 1),
+            aml::io_port(I8042_COMMAND_PORT, 1),
+            aml::extended_irq(I8042_GSI),
+        ]),
+    ));
+    devices.extend(aml::device(b"PS2K", &i8042));
+
+    if let Some(info) = tpm_info(device_info) {
+        // The TPM does not use interrupts, so the driver polls it.
+        let mut tpm = aml::name(b"_HID", &aml::string(TPM_HID));
+        tpm.extend(aml::name(
+            b"_CRS",
+            &aml::resource_template(&[aml::memory32_fixed(
+                info.addr() as u32,
+                info.length() as u32,
+            )]),
+        ));
+        devices.extend(aml::device(b"TPM0", &tpm));
+    }
+
+    let mut dsdt = Sdt::new(*b"DSDT", 2, *b"FCVMDSDT");
+    dsdt.append(&aml::scope(b"\\_SB_", &devices));
+    dsdt.append(&aml::name(
+        b"_S5_",
+        &aml::package(&[aml::integer(u64::from(S5_SLEEP_TYPE))]),
+    ));
+    dsdt.finish()
+}
+
+fn create_fadt(dsdt_addr: GuestAddress) -> Vec<u8> {
+    let mut fadt = Sdt::new(*b"FACP", 6, *b"FCVMFADT");
+    fadt.append(&[0; FADT_SIZE - SDT_HEADER_SIZE]);
+    fadt.write_at(
+        FADT_IAPC_BOOT_ARCH_OFFSET,
+        &(IAPC_BOOT_ARCH_8042
+            | IAPC_BOOT_ARCH_VGA_NOT_PRESENT
+            | IAPC_BOOT_ARCH_MSI_NOT_SUPPORTED
+            | IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT)
+            .to_le_bytes(),
+    );
+    // There is no PM hardware emulated, so we only expose the hardware-reduced ACPI
+    // interface, with sleep states driven through the sleep control register.
+    fadt.write_at(FADT_FLAGS_OFFSET, &FADT_F_HW_REDUCED_ACPI.to_le_bytes());
+    fadt.write_at(FADT_MINOR_VERSION_OFFSET, &[5]);
+    fadt.write_at(FADT_X_DSDT_OFFSET, &dsdt_addr.raw_value().to_le_bytes());
+    fadt.write_at(FADT_SLEEP_CONTROL_REG_OFFSET, &gas_io_port(ACPI_SLEEP_PORT));
+    fadt.write_at(FADT_SLEEP_STATUS_REG_OFFSET, &gas_io_port(ACPI_SLEEP_PORT));
+    fadt.finish()
+}
+
+fn create_madt(num_cpus: u16) -> Vec<u8> {
+    let mut madt = Sdt::new(*b"APIC", 6, *b"FCVMMADT");
+    madt.append(&APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    madt.append(&MADT_PCAT_COMPAT.to_le_bytes());
+
+    for cpu_id in 0..num_cpus {
+        match u8::try_from(cpu_id) {
+            // Processor UID, APIC ID and the `Enabled` flag.
+            Ok(apic_id) if apic_id < u8::MAX => {
+                madt.append(&[MADT_LOCAL_APIC, 8, apic_id, apic_id]);
+                madt.append(&1u32.to_le_bytes());
+            }
+            // The APIC IDs from 0xff on are described by local x2APIC structures: reserved bytes,
+            // x2APIC ID, the `Enabled` flag and processor UID.
+            _ => {
+                madt.append(&[MADT_LOCAL_X2APIC, 16, 0, 0]);
+                madt.append(&u32::from(cpu_id).to_le_bytes());
+                madt.append(&1u32.to_le_bytes());
+                madt.append(&u32::from(cpu_id).to_le_bytes());
+            }
+        }
+    }
+
+    madt.append(&[MADT_IO_APIC, 12, super::ioapic_id(num_cpus), 0]);
+    madt.append(&IO_APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    // Global system interrupt base.
+    madt.append(&0u32.to_le_bytes());
+
+    // NMI connected to LINT1 on all the local APICs.
+    madt.append(&[MADT_LOCAL_APIC_NMI, 6, 0xff, 0, 0, 1]);
+    if num_cpus > u16::from(u8::MAX) {
+        // Same for the local x2APICs: flags, processor UID, LINT1 and reserved bytes.
+        madt.append(&[MADT_LOCAL_X2APIC_NMI, 12, 0, 0]);
+        madt.append(&MADT_ALL_PROCESSORS.to_le_bytes());
+        madt.append(&[1, 0, 0, 0]);
+    }
+
+    madt.finish()
+}
+
+fn create_tpm2() -> Vec<u8> {
+    let mut tpm2 = Sdt::new(*b"TPM2", 4, *b"FCVMTPM2");
+    tpm2.append(&TPM2_PLATFORM_CLASS_CLIENT.to_le_bytes());
+    // Reserved.
+    tpm2.append(&[0; 2]);
+    // The FIFO interface has no control area.
+    tpm2.append(&0u64.to_le_bytes());
+    tpm2.append(&TPM2_START_METHOD_MMIO.to_le_bytes());
+    tpm2.finish()
+}
+
+fn tpm_info<T, S: std::hash::BuildHasher>(
+    device_info: &HashMap<(DeviceType, String), T, S>,
+) -> Option<&T> {
+    device_info
+        .iter()
+        .find(|((device_type, _), _)| *device_type == DeviceType::Tpm)
+        .map(|(_, info)| info)
+}
+
+fn create_xsdt(tables: &[GuestAddress]) -> Vec<u8> {
+    let mut xsdt = Sdt::new(*b"XSDT", 1, *b"FCVMXSDT");
+    for table in tables {
+        xsdt.append(&table.raw_value().to_le_bytes());
+    }
+    xsdt.finish()
+}
+
+fn create_rsdp(xsdt_addr: GuestAddress) -> Vec<u8> {
+    let mut rsdp = Vec::with_capacity(RSDP_SIZE);
+    rsdp.extend_from_slice(b"RSD PTR ");
+    // The legacy checksum is filled in below.
+    rsdp.push(0);
+    rsdp.extend_from_slice(&OEM_ID);
+    // Revision 2 means that the XSDT address is valid.
+    rsdp.push(2);
+    // No RSDT is provided.
+    rsdp.extend_from_slice(&0u32.to_le_bytes());
+    rsdp.extend_from_slice(&(RSDP_SIZE as u32).to_le_bytes());
+    rsdp.extend_from_slice(&xsdt_addr.raw_value().to_le_bytes());
+    // The extended checksum is filled in below.
+    rsdp.push(0);
+    rsdp.extend_from_slice(&[0; 3]);
+
+    rsdp[8] = compute_checksum(&rsdp[..RSDP_V1_SIZE]);
+    rsdp[32] = compute_checksum(&rsdp);
+    rsdp
+}
+
+// Writes `table` at `addr` and returns the 8-byte aligned address following it.
+fn write_table(
+    mem: &GuestMemoryMmap,
+    addr: GuestAddress,
+    table: &[u8],
+) -> Result<GuestAddress, AcpiError> {
+    let end = addr
+        .checked_add(table.len() as u64)
+        .ok_or(AcpiError::TablesTooLarge)?;
+    if end.raw_value() > SMBIOS_START {
+        return Err(AcpiError::TablesTooLarge);
+    }
+    mem.write_slice(table, addr)
+        .map_err(|_| AcpiError::WriteTable)?;
+    Ok(GuestAddress((end.raw_value() + 7) & !7))
+}
+
+/// Creates the ACPI tables describing this microVM and writes them to guest memory.
+///
+/// The Root System Description Pointer is placed at `ACPI_RSDP_START`, followed by the
+/// DSDT, FADT, MADT, TPM2 when a TPM is registered, and XSDT, all of them inside the BIOS
+/// area below 1MiB.
+///
+/// # Arguments
+///
+/// * `mem` - The memory to be used by the guest.
+/// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `device_info` - The MMIO devices registered for this microVM.
+pub fn setup_acpi<T: DeviceInfoForAcpi + Clone + Debug, S: std::hash::BuildHasher>(
+    mem: &GuestMemoryMmap,
+    num_cpus: u16,
+    device_info: &HashMap<(DeviceType, String), T, S>,
+) -> Result<(), AcpiError> {
+    let rsdp_addr = GuestAddress(ACPI_RSDP_START);
+    let dsdt_addr = rsdp_addr.unchecked_add(RSDP_SIZE as u64 + 4);
+    let fadt_addr = write_table(mem, dsdt_addr, &create_dsdt(device_info))?;
+    let madt_addr = write_table(mem, fadt_addr, &create_fadt(dsdt_addr))?;
+    let mut xsdt_addr = write_table(mem, madt_addr, &create_madt(num_cpus))?;
+    let mut tables = vec![fadt_addr, madt_addr];
+    if tpm_info(device_info).is_some() {
+        tables.push(xsdt_addr);
+        xsdt_addr = write_table(mem, xsdt_addr, &create_tpm2())?;
+    }
+    write_table(mem, xsdt_addr, &create_xsdt(&tables))?;
+
+    mem.write_slice(&create_rsdp(xsdt_addr), rsdp_addr)
+        .map_err(|_| AcpiError::WriteRsdp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::layout::HIMEM_START;
+
+    #[derive(Clone, Debug)]
+    struct MmioDeviceInfo {
+        addr: u64,
+        irq: u32,
+    }
+
+    impl DeviceInfoForAcpi for MmioDeviceInfo {
+        fn addr(&self) -> u64 {
+            self.addr
+        }
+        fn irq(&self) -> u32 {
+            self.irq
+        }
+        fn length(&self) -> u64 {
+            0x1000
+        }
+    }
+
+    fn device_info() -> HashMap<(DeviceType, String), MmioDeviceInfo> {
+        let mut device_info = HashMap::new();
+        device_info.insert(
+            (DeviceType::Virtio(2), "root".to_string()),
+            MmioDeviceInfo {
+                addr: 0xd000_0000,
+                irq: 5,
+            },
+        );
+        device_info.insert(
+            (DeviceType::Virtio(1), "eth0".to_string()),
+            MmioDeviceInfo {
+                addr: 0xd000_1000,
+                irq: 6,
+            },
+        );
+        device_info.insert(
+            (DeviceType::BootTimer, "BootTimer".to_string()),
+            MmioDeviceInfo {
+                addr: 0xd000_2000,
+                irq: 0,
+            },
+        );
+        device_info
+    }
+
+    fn read_table(mem: &GuestMemoryMmap, addr: GuestAddress) -> Vec<u8> {
+        let len: u32 = mem.read_obj(addr.unchecked_add(4)).unwrap();
+        let mut table = vec![0; len as usize];
+        mem.read_slice(&mut table, addr).unwrap();
+        assert_eq!(compute_checksum(&table), 0);
+        table
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn find_table(mem: &GuestMemoryMmap, signature: &[u8; 4]) -> Option<Vec<u8>> {
+        let mut rsdp = [0u8; RSDP_SIZE];
+        mem.read_slice(&mut rsdp, GuestAddress(ACPI_RSDP_START))
+            .unwrap();
+        let xsdt = read_table(mem, GuestAddress(read_u64(&rsdp, 24)));
+        xsdt[SDT_HEADER_SIZE..]
+            .chunks(8)
+            .map(|entry| read_table(mem, GuestAddress(read_u64(entry, 0))))
+            .find(|table| &table[..4] == signature)
+    }
+
+    fn create_memory() -> GuestMemoryMmap {
+        utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), HIMEM_START as usize)],
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pkg_length() {
+        assert_eq!(aml::pkg_length(0), vec![0x01]);
+        assert_eq!(aml::pkg_length(0x3e), vec![0x3f]);
+        assert_eq!(aml::pkg_length(0x3f), vec![0x41, 0x04]);
+        assert_eq!(aml::pkg_length(0xffd), vec![0x4f, 0xff]);
+        assert_eq!(aml::pkg_length(0xffe), vec![0x81, 0x00, 0x01]);
+        assert_eq!(aml::pkg_length(0x10_0000), vec![0xc4, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_integer() {
+        assert_eq!(aml::integer(0), vec![0x00]);
+        assert_eq!(aml::integer(1), vec![0x01]);
+        assert_eq!(aml::integer(5), vec![0x0a, 0x05]);
+        assert_eq!(aml::integer(0x3f8), vec![0x0b, 0xf8, 0x03]);
+        assert_eq!(
+            aml::integer(0xd000_0000),
+            vec![0x0c, 0x00, 0x00, 0x00, 0xd0]
+        );
+        assert_eq!(
+            aml::integer(1 << 32),
+            vec![0x0e, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_rsdp_checksum() {
+        let mem = create_memory();
+        setup_acpi(&mem, 1, &device_info()).unwrap();
+
+        let mut rsdp = [0u8; RSDP_SIZE];
+        mem.read_slice(&mut rsdp, GuestAddress(ACPI_RSDP_START))
+            .unwrap();
+        assert_eq!(&rsdp[..8], b"RSD PTR ");
+        assert_eq!(compute_checksum(&rsdp[..RSDP_V1_SIZE]), 0);
+        assert_eq!(compute_checksum(&rsdp), 0);
+    }
+
+    #[test]
+    fn test_tables() {
+        let mem = create_memory();
+        setup_acpi(&mem, 2, &device_info()).unwrap();
+
+        let fadt = find_table(&mem, b"FACP").unwrap();
+        assert_eq!(fadt.len(), FADT_SIZE);
+        let dsdt = read_table(&mem, GuestAddress(read_u64(&fadt, FADT_X_DSDT_OFFSET)));
+        assert_eq!(&dsdt[..4], b"DSDT");
+
+        // Only the virtio devices are described, in address order.
+        let dsdt_contains = |needle: &[u8]| dsdt.windows(needle.len()).any(|w| w == needle);
+        assert!(dsdt_contains(b"V000"));
+        assert!(dsdt_contains(b"V001"));
+        assert!(!dsdt_contains(b"V002"));
+        assert!(dsdt_contains(&aml::memory32_fixed(0xd000_0000, 0x1000)));
+        assert!(dsdt_contains(&aml::memory32_fixed(0xd000_1000, 0x1000)));
+        assert!(!dsdt_contains(&aml::memory32_fixed(0xd000_2000, 0x1000)));
+        assert!(dsdt_contains(b"_S5_"));
+        assert!(!dsdt_contains(b"TPM0"));
+        assert!(find_table(&mem, b"TPM2").is_none());
+
+        let madt = find_table(&mem, b"APIC").unwrap();
+        // Header, LAPIC address and flags, 2 local APICs, 1 IOAPIC and 1 local APIC NMI.
+        assert_eq!(madt.len(), SDT_HEADER_SIZE + 8 + 2 * 8 + 12 + 6);
+    }
+
+    #[test]
+    fn test_tpm() {
+        let mem = create_memory();
+        let mut device_info = device_info();
+        device_info.insert(
+            (DeviceType::Tpm, "Tpm".to_string()),
+            MmioDeviceInfo {
+                addr: 0xd000_3000,
+                irq: 0,
+            },
+        );
+        setup_acpi(&mem, 1, &device_info).unwrap();
+
+        let fadt = find_table(&mem, b"FACP").unwrap();
+        let dsdt = read_table(&mem, GuestAddress(read_u64(&fadt, FADT_X_DSDT_OFFSET)));
+        let dsdt_contains = |needle: &[u8]| dsdt.windows(needle.len()).any(|w| w == needle);
+        assert!(dsdt_contains(b"TPM0"));
+        assert!(dsdt_contains(&aml::string(TPM_HID)));
+        assert!(dsdt_contains(&aml::memory32_fixed(0xd000_3000, 0x1000)));
+
+        let tpm2 = find_table(&mem, b"TPM2").unwrap();
+        assert_eq!(tpm2.len(), SDT_HEADER_SIZE + 16);
+        assert_eq!(tpm2[8], 4);
+        assert_eq!(
+            &tpm2[SDT_HEADER_SIZE + 12..],
+            &TPM2_START_METHOD_MMIO.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_cpu_entry_count() {
+        let mem = create_memory();
+        for num_cpus in 1..=crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS {
+            setup_acpi(&mem, num_cpus, &device_info()).unwrap();
+
+            let madt = find_table(&mem, b"APIC").unwrap();
+            let mut offset = SDT_HEADER_SIZE + 8;
+            let mut apic_ids = Vec::new();
+            let mut x2apic_nmi = false;
+            while offset < madt.len() {
+                match madt[offset] {
+                    MADT_LOCAL_APIC => apic_ids.push(u32::from(madt[offset + 3])),
+                    MADT_LOCAL_X2APIC => {
+                        apic_ids.push(u32::from_le_bytes(
+                            madt[offset + 4..offset + 8].try_into().unwrap(),
+                        ));
+                    }
+                    MADT_LOCAL_X2APIC_NMI => x2apic_nmi = true,
+                    _ => (),
+                }
+                offset += usize::from(madt[offset + 1]);
+            }
+            assert_eq!(offset, madt.len());
+            // The APIC IDs are the vCPU indexes.
+            assert_eq!(apic_ids, (0..u32::from(num_cpus)).collect::<Vec<_>>());
+            assert_eq!(x2apic_nmi, num_cpus > 255);
+        }
+    }
+
+    #[test]
+    fn test_not_enough_memory() {
+        let mem = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), ACPI_RSDP_START as usize)],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            setup_acpi(&mem, 1, &device_info()),
+            Err(AcpiError::WriteTable)
+        );
+    }
+}
@@ -15,6 +15,17 @@ pub const CMDLINE_START: u64 = 0x20000;
 /// Kernel command line maximum size.
 pub const CMDLINE_MAX_SIZE: usize = 2048;
 
+/// Address of the ACPI Root System Description Pointer. It must sit in the
+/// 0xE0000-0xFFFFF BIOS area, which is where the guest kernel scans for it.
+pub const ACPI_RSDP_START: u64 = 0x000e_0000;
+
+/// Address of the SMBIOS entry point. The ACPI tables must end below it, and the guest kernel
+/// scans the 0xF0000-0xFFFFF area for it.
+pub const SMBIOS_START: u64 = 0x000f_0000;
+
+/// I/O port used as the ACPI sleep control and status register.
+pub const ACPI_SLEEP_PORT: u64 = 0x600;
+
 /// Start of the high memory.
 pub const HIMEM_START: u64 = 0x0010_0000; // 1 MB.
 
@@ -24,8 +35,25 @@ pub const IRQ_BASE: u32 = 5;
 /// Last usable IRQ ID for virtio device interrupts on x86_64.
 pub const IRQ_MAX: u32 = 23;
 
+/// Address of the IOAPIC registers, emulated by Firecracker when the split irqchip is enabled.
+pub const IOAPIC_START: u64 = 0xfec0_0000;
+/// Size of the IOAPIC registers area.
+pub const IOAPIC_SIZE: u64 = 0x1000;
+/// Number of pins of the IOAPIC, one per IRQ.
+pub const IOAPIC_NUM_PINS: usize = 24;
+
 /// Address for the TSS setup.
 pub const KVM_TSS_ADDRESS: u64 = 0xfffb_d000;
 
+/// Location of the PVH `hvm_start_info` structure.
+pub const PVH_INFO_START: u64 = 0x6000;
+
+/// Location of the PVH module list, right after the `hvm_start_info` structure.
+pub const MODLIST_START: u64 = 0x6040;
+
+/// Location of the PVH memory map. The zero page is not used when booting with
+/// PVH, so the memory map takes its place.
+pub const MEMMAP_START: u64 = 0x7000;
+
 /// The 'zero page', a.k.a linux kernel bootparams.
 pub const ZERO_PAGE_START: u64 = 0x7000;
@@ -5,6 +5,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+/// Logic for generating the ACPI tables describing the microVM.
+pub mod acpi;
 /// Logic for handling x86_64 CPU models.
 pub mod cpu_model;
 mod gdt;
@@ -17,13 +19,19 @@ mod mptable;
 pub mod msr;
 /// Logic for configuring x86_64 registers.
 pub mod regs;
+/// Logic for generating the SMBIOS tables identifying the microVM.
+pub mod smbios;
 
 use linux_loader::configurator::linux::LinuxBootConfigurator;
+use linux_loader::configurator::pvh::PvhBootConfigurator;
 use linux_loader::configurator::{BootConfigurator, BootParams};
 use linux_loader::loader::bootparam::boot_params;
+use linux_loader::loader::elf::start_info::{
+    hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info,
+};
 use utils::vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
-use crate::arch::InitrdConfig;
+use crate::arch::{BootProtocol, InitrdConfig};
 
 // Value taken from https://elixir.bootlin.com/linux/v5.10.68/source/arch/x86/include/uapi/asm/e820.h#L31
 const E820_RAM: u32 = 1;
@@ -37,8 +45,14 @@ pub enum ConfigurationError {
     MpTableSetup(mptable::MptableError),
     /// Error writing the zero page of guest memory.
     ZeroPageSetup,
+    /// Error writing the PVH start info to guest memory.
+    StartInfoSetup,
     /// Failed to compute initrd address.
     InitrdAddress,
+    /// Error writing the ACPI tables to memory.
+    AcpiSetup(acpi::AcpiError),
+    /// Error writing the SMBIOS tables to memory.
+    SmbiosSetup(smbios::SmbiosError),
 }
 
 // Where BIOS/VGA magic would live on a real PC.
@@ -50,22 +64,56 @@ pub const MEM_32BIT_GAP_SIZE: u64 = 768 << 20;
 pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
 /// The size of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_SIZE: u64 = MEM_32BIT_GAP_SIZE;
+/// Largest guest memory region. KVM limits each memory slot to 2^31 - 1 pages, so the memory
+/// past 4 GiB is split in regions of at most this size.
+const MAX_MEM_REGION_SIZE: usize = 1 << 42;
+/// Highest number of vCPUs whose APIC IDs fit in the 8-bit xAPIC IDs, 0xff being the broadcast
+/// one. The vCPUs past it have x2APIC IDs, which are only described by the ACPI MADT.
+pub const MAX_XAPIC_VCPUS: u16 = 254;
+/// Highest number of vCPUs of the microVMs configured with neither ACPI nor the split irqchip.
+pub const MAX_LEGACY_VCPUS: u16 = 32;
+
+/// Returns the ID of the IOAPIC, which follows the local APIC IDs of the `num_cpus` vCPUs when
+/// it fits in 8 bits.
+pub fn ioapic_id(num_cpus: u16) -> u8 {
+    u8::try_from(num_cpus + 1).unwrap_or(u8::MAX)
+}
+
+/// Returns the width of the guest physical addresses, which KVM sets to the host one.
+pub fn guest_phys_addr_bits() -> u8 {
+    // SAFETY: Safe because all the x86_64 processors implement the 0x80000008 CPUID function,
+    // which reports the physical address width in EAX[7:0].
+    (unsafe { std::arch::x86_64::__cpuid(0x8000_0008) }.eax & 0xff) as u8
+}
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
 /// carve out at the end of 32bit address space.
 pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    // It's safe to cast MMIO_MEM_START to usize because it fits in a u32 variable
+    arch_memory_regions_with_gap(size, MMIO_MEM_START)
+}
+
+/// Returns a Vec of the valid memory addresses, when the carve out at the end of 32bit address
+/// space starts at `gap_start`.
+pub fn arch_memory_regions_with_gap(size: usize, gap_start: u64) -> Vec<(GuestAddress, usize)> {
+    // It's safe to cast gap_start to usize because it fits in a u32 variable
     // (It points to an address in the 32 bit space).
-    match size.checked_sub(MMIO_MEM_START as usize) {
+    match size.checked_sub(gap_start as usize) {
         // case1: guest memory fits before the gap
         None | Some(0) => vec![(GuestAddress(0), size)],
         // case2: guest memory extends beyond the gap
-        Some(remaining) => vec![
-            (GuestAddress(0), MMIO_MEM_START as usize),
-            (GuestAddress(FIRST_ADDR_PAST_32BITS), remaining),
-        ],
+        Some(mut remaining) => {
+            let mut regions = vec![(GuestAddress(0), gap_start as usize)];
+            let mut start = FIRST_ADDR_PAST_32BITS;
+            while remaining > 0 {
+                let region_size = remaining.min(MAX_MEM_REGION_SIZE);
+                regions.push((GuestAddress(start), region_size));
+                start += region_size as u64;
+                remaining -= region_size;
+            }
+            regions
+        }
     }
 }
 
@@ -102,24 +150,56 @@ pub fn initrd_load_addr(
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `initrd` - Information about where the ramdisk image was loaded in the `guest_mem`.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `boot_prot` - Boot protocol that will be used to boot the guest.
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     initrd: &Option<InitrdConfig>,
-    num_cpus: u8,
+    num_cpus: u16,
+    boot_prot: BootProtocol,
+) -> Result<(), ConfigurationError> {
+    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM. The MP table only
+    // holds xAPIC IDs, so larger guests are only described by the ACPI tables.
+    if num_cpus <= MAX_XAPIC_VCPUS {
+        mptable::setup_mptable(guest_mem, num_cpus)?;
+    }
+
+    match boot_prot {
+        BootProtocol::LinuxBoot => {
+            configure_64bit_boot(guest_mem, cmdline_addr, cmdline_size, initrd)
+        }
+        BootProtocol::PvhBoot => configure_pvh(guest_mem, cmdline_addr, initrd),
+    }
+}
+
+/// Returns the (address, size) pairs of the guest RAM areas that are reported to the guest.
+fn ram_regions(guest_mem: &GuestMemoryMmap) -> Vec<(u64, u64)> {
+    let mut regions = vec![(0, EBDA_START)];
+
+    // The guest memory is made of the region below the 32bit gap, whose size depends on the MMIO
+    // layout, and of the region past 4 GiB. The former is only reported from the high memory on.
+    for region in guest_mem.iter() {
+        let start = std::cmp::max(region.start_addr().raw_value(), layout::HIMEM_START);
+        let end = region.last_addr().raw_value() + 1;
+        if start < end {
+            regions.push((start, end - start));
+        }
+    }
+
+    regions
+}
+
+fn configure_64bit_boot(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    cmdline_size: usize,
+    initrd: &Option<InitrdConfig>,
 ) -> Result<(), ConfigurationError> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
-    let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
-
-    let himem_start = GuestAddress(layout::HIMEM_START);
-
-    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
-    mptable::setup_mptable(guest_mem, num_cpus)?;
 
     let mut params = boot_params::default();
 
@@ -134,38 +214,8 @@ pub fn configure_system(
         params.hdr.ramdisk_size = initrd_config.size as u32;
     }
 
-    add_e820_entry(&mut params, 0, EBDA_START, E820_RAM)?;
-
-    let last_addr = guest_mem.last_addr();
-    if last_addr < end_32bit_gap_start {
-        add_e820_entry(
-            &mut params,
-            himem_start.raw_value(),
-            // it's safe to use unchecked_offset_from because
-            // mem_end > himem_start
-            last_addr.unchecked_offset_from(himem_start) + 1,
-            E820_RAM,
-        )?;
-    } else {
-        add_e820_entry(
-            &mut params,
-            himem_start.raw_value(),
-            // it's safe to use unchecked_offset_from because
-            // end_32bit_gap_start > himem_start
-            end_32bit_gap_start.unchecked_offset_from(himem_start),
-            E820_RAM,
-        )?;
-
-        if last_addr > first_addr_past_32bits {
-            add_e820_entry(
-                &mut params,
-                first_addr_past_32bits.raw_value(),
-                // it's safe to use unchecked_offset_from because
-                // mem_end > first_addr_past_32bits
-                last_addr.unchecked_offset_from(first_addr_past_32bits) + 1,
-                E820_RAM,
-            )?;
-        }
+    for (addr, size) in ram_regions(guest_mem) {
+        add_e820_entry(&mut params, addr, size, E820_RAM)?;
     }
 
     LinuxBootConfigurator::write_bootparams(
@@ -175,6 +225,60 @@ pub fn configure_system(
     .map_err(|_| ConfigurationError::ZeroPageSetup)
 }
 
+fn configure_pvh(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    initrd: &Option<InitrdConfig>,
+) -> Result<(), ConfigurationError> {
+    // See https://xenbits.xen.org/docs/unstable/misc/pvh.html.
+    const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336e_c578;
+
+    let memmap: Vec<hvm_memmap_table_entry> = ram_regions(guest_mem)
+        .into_iter()
+        .map(|(addr, size)| hvm_memmap_table_entry {
+            addr,
+            size,
+            type_: E820_RAM,
+            reserved: 0,
+        })
+        .collect();
+
+    // The initrd is passed to the kernel as the first (and only) module.
+    let modules: Vec<hvm_modlist_entry> = initrd
+        .iter()
+        .map(|initrd_config| hvm_modlist_entry {
+            paddr: initrd_config.address.raw_value(),
+            size: initrd_config.size as u64,
+            ..Default::default()
+        })
+        .collect();
+
+    // Version 1 of the start info is the first one with the memory map.
+    let start_info = hvm_start_info {
+        magic: XEN_HVM_START_MAGIC_VALUE,
+        version: 1,
+        cmdline_paddr: cmdline_addr.raw_value(),
+        nr_modules: modules.len() as u32,
+        modlist_paddr: if modules.is_empty() {
+            0
+        } else {
+            layout::MODLIST_START
+        },
+        memmap_paddr: layout::MEMMAP_START,
+        memmap_entries: memmap.len() as u32,
+        ..Default::default()
+    };
+
+    let mut boot_params = BootParams::new(&start_info, GuestAddress(layout::PVH_INFO_START));
+    boot_params.set_sections(&memmap, GuestAddress(layout::MEMMAP_START));
+    if !modules.is_empty() {
+        boot_params.set_modules(&modules, GuestAddress(layout::MODLIST_START));
+    }
+
+    PvhBootConfigurator::write_bootparams::<GuestMemoryMmap>(&boot_params, guest_mem)
+        .map_err(|_| ConfigurationError::StartInfoSetup)
+}
+
 /// Add an e820 region to the e820 map.
 /// Returns Ok(()) if successful, or an error if there is no space left in the map.
 fn add_e820_entry(
@@ -198,6 +302,7 @@ fn add_e820_entry(
 #[cfg(test)]
 mod tests {
     use linux_loader::loader::bootparam::boot_e820_entry;
+    use utils::vm_memory::Bytes;
 
     use super::*;
 
@@ -217,6 +322,62 @@ mod tests {
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn regions_multi_tb() {
+        // The memory past 4 GiB is split to fit in the KVM memory slots.
+        let size = 10usize << 40;
+        let regions = arch_memory_regions(size);
+        assert_eq!(
+            regions,
+            vec![
+                (GuestAddress(0), MMIO_MEM_START as usize),
+                (GuestAddress(1u64 << 32), MAX_MEM_REGION_SIZE),
+                (
+                    GuestAddress((1u64 << 32) + (1u64 << 42)),
+                    MAX_MEM_REGION_SIZE
+                ),
+                (
+                    GuestAddress((1u64 << 32) + (2u64 << 42)),
+                    size - MMIO_MEM_START as usize - 2 * MAX_MEM_REGION_SIZE
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ioapic_id() {
+        assert_eq!(ioapic_id(1), 2);
+        assert_eq!(ioapic_id(MAX_XAPIC_VCPUS), 255);
+        assert_eq!(ioapic_id(1024), 255);
+    }
+
+    #[test]
+    fn regions_with_gap() {
+        // A 3 GiB gap only leaves 1 GiB of memory below it.
+        let gap_start = 1u64 << 30;
+        let regions = arch_memory_regions_with_gap(1usize << 29, gap_start);
+        assert_eq!(regions, vec![(GuestAddress(0), 1usize << 29)]);
+
+        let regions = arch_memory_regions_with_gap(3usize << 29, gap_start);
+        assert_eq!(
+            regions,
+            vec![
+                (GuestAddress(0), 1usize << 30),
+                (GuestAddress(1u64 << 32), 1usize << 29)
+            ]
+        );
+
+        let gm = utils::vm_memory::test_utils::create_anon_guest_memory(&regions, false).unwrap();
+        assert_eq!(
+            ram_regions(&gm),
+            vec![
+                (0, EBDA_START),
+                (layout::HIMEM_START, gap_start - layout::HIMEM_START),
+                (1u64 << 32, 1u64 << 29)
+            ]
+        );
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
@@ -225,33 +386,125 @@ mod tests {
             false,
         )
         .unwrap();
-        let config_err = configure_system(&gm, GuestAddress(0), 0, &None, 1);
+        let config_err =
+            configure_system(&gm, GuestAddress(0), 0, &None, 1, BootProtocol::LinuxBoot);
         assert!(config_err.is_err());
         assert_eq!(
             config_err.unwrap_err(),
             super::ConfigurationError::MpTableSetup(mptable::MptableError::NotEnoughMemory)
         );
+        // Guests with x2APIC IDs have no MP table.
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            MAX_XAPIC_VCPUS + 1,
+            BootProtocol::LinuxBoot,
+        )
+        .unwrap();
 
         // Now assigning some memory that falls before the 32bit memory hole.
         let mem_size = 128 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = utils::vm_memory::test_utils::create_anon_guest_memory(&arch_mem_regions, false)
             .unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+        )
+        .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = utils::vm_memory::test_utils::create_anon_guest_memory(&arch_mem_regions, false)
             .unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+        )
+        .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = utils::vm_memory::test_utils::create_anon_guest_memory(&arch_mem_regions, false)
             .unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_system_configuration_pvh() {
+        let no_vcpus = 4;
+        let mem_size = 128 << 20;
+        let arch_mem_regions = arch_memory_regions(mem_size);
+        let gm = utils::vm_memory::test_utils::create_anon_guest_memory(&arch_mem_regions, false)
+            .unwrap();
+
+        // Without an initrd no module is passed to the guest.
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            0,
+            &None,
+            no_vcpus,
+            BootProtocol::PvhBoot,
+        )
+        .unwrap();
+        let start_info: hvm_start_info = gm.read_obj(GuestAddress(layout::PVH_INFO_START)).unwrap();
+        assert_eq!(start_info.magic, 0x336e_c578);
+        assert_eq!(start_info.cmdline_paddr, layout::CMDLINE_START);
+        assert_eq!(start_info.nr_modules, 0);
+        assert_eq!(start_info.modlist_paddr, 0);
+        assert_eq!(start_info.memmap_paddr, layout::MEMMAP_START);
+        assert_eq!(start_info.memmap_entries, 2);
+
+        let memmap: hvm_memmap_table_entry = gm
+            .read_obj(GuestAddress(
+                layout::MEMMAP_START + std::mem::size_of::<hvm_memmap_table_entry>() as u64,
+            ))
+            .unwrap();
+        assert_eq!(memmap.addr, layout::HIMEM_START);
+        assert_eq!(memmap.size, mem_size as u64 - layout::HIMEM_START);
+        assert_eq!(memmap.type_, E820_RAM);
+
+        // The initrd is passed as the first module.
+        let initrd = InitrdConfig {
+            address: GuestAddress(0x100_0000),
+            size: 0x1000,
+        };
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            0,
+            &Some(initrd),
+            no_vcpus,
+            BootProtocol::PvhBoot,
+        )
+        .unwrap();
+        let start_info: hvm_start_info = gm.read_obj(GuestAddress(layout::PVH_INFO_START)).unwrap();
+        assert_eq!(start_info.nr_modules, 1);
+        assert_eq!(start_info.modlist_paddr, layout::MODLIST_START);
+        let module: hvm_modlist_entry = gm.read_obj(GuestAddress(layout::MODLIST_START)).unwrap();
+        assert_eq!(module.paddr, 0x100_0000);
+        assert_eq!(module.size, 0x1000);
     }
 
     #[test]
@@ -124,7 +124,7 @@ fn mpf_intel_compute_checksum(v: &mpspec::mpf_intel) -> u8 {
     (!checksum).wrapping_add(1)
 }
 
-fn compute_mp_size(num_cpus: u8) -> usize {
+fn compute_mp_size(num_cpus: u16) -> usize {
     mem::size_of::<MpfIntelWrapper>()
         + mem::size_of::<MpcTableWrapper>()
         + mem::size_of::<MpcCpuWrapper>() * (num_cpus as usize)
@@ -135,7 +135,7 @@ fn compute_mp_size(num_cpus: u8) -> usize {
 }
 
 /// Performs setup of the MP table for the given `num_cpus`.
-pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<(), MptableError> {
+pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u16) -> Result<(), MptableError> {
     if u32::from(num_cpus) > MAX_SUPPORTED_CPUS {
         return Err(MptableError::TooManyCpus);
     }
@@ -146,7 +146,7 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<(), MptableE
     let mp_size = compute_mp_size(num_cpus);
 
     let mut checksum: u8 = 0;
-    let ioapicid: u8 = num_cpus + 1;
+    let ioapicid: u8 = super::ioapic_id(num_cpus);
 
     // The checked_add here ensures the all of the following base_mp.unchecked_add's will be without
     // overflow.
@@ -181,7 +181,8 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<(), MptableE
 
     {
         let size = mem::size_of::<MpcCpuWrapper>() as u64;
-        for cpu_id in 0..num_cpus {
+        // The number of CPUs was checked to fit in the 8-bit APIC IDs.
+        for cpu_id in 0..num_cpus as u8 {
             let mut mpc_cpu = MpcCpuWrapper(mpspec::mpc_cpu::default());
             mpc_cpu.0.type_ = mpspec::MP_PROCESSOR as u8;
             mpc_cpu.0.apicid = cpu_id;
@@ -395,13 +396,13 @@ mod tests {
         let mem = utils::vm_memory::test_utils::create_guest_memory_unguarded(
             &[(
                 GuestAddress(MPTABLE_START),
-                compute_mp_size(MAX_SUPPORTED_CPUS as u8),
+                compute_mp_size(MAX_SUPPORTED_CPUS as u16),
             )],
             false,
         )
         .unwrap();
 
-        for i in 0..MAX_SUPPORTED_CPUS as u8 {
+        for i in 0..MAX_SUPPORTED_CPUS as u16 {
             setup_mptable(&mem, i).unwrap();
 
             let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
@@ -433,12 +434,12 @@ mod tests {
     fn cpu_entry_count_max() {
         let cpus = MAX_SUPPORTED_CPUS + 1;
         let mem = utils::vm_memory::test_utils::create_guest_memory_unguarded(
-            &[(GuestAddress(MPTABLE_START), compute_mp_size(cpus as u8))],
+            &[(GuestAddress(MPTABLE_START), compute_mp_size(cpus as u16))],
             false,
         )
         .unwrap();
 
-        let result = setup_mptable(&mem, cpus as u8).unwrap_err();
+        let result = setup_mptable(&mem, cpus as u16).unwrap_err();
         assert_eq!(result, MptableError::TooManyCpus);
     }
 }
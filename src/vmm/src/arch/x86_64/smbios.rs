@@ -0,0 +1,356 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use utils::vm_memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::layout::{HIMEM_START, SMBIOS_START};
+
+// Version of the SMBIOS specification the tables follow.
+const SMBIOS_MAJOR_VERSION: u8 = 3;
+const SMBIOS_MINOR_VERSION: u8 = 2;
+// Size of the SMBIOS 3.0 (64-bit) entry point structure.
+const ENTRY_POINT_SIZE: usize = 24;
+// Offset of the checksum byte in the entry point structure.
+const ENTRY_POINT_CHECKSUM_OFFSET: usize = 5;
+// Offset of the structure table, relative to the entry point. The table is paragraph aligned.
+const STRUCTURE_TABLE_OFFSET: u64 = 0x20;
+
+// Structure types, see SMBIOS 3.2 section 6.1.2, table 3.
+const TYPE_BIOS_INFORMATION: u8 = 0;
+const TYPE_SYSTEM_INFORMATION: u8 = 1;
+const TYPE_END_OF_TABLE: u8 = 127;
+// Formatted sizes of the structures that we populate.
+const BIOS_INFORMATION_SIZE: u8 = 0x18;
+const SYSTEM_INFORMATION_SIZE: u8 = 0x1b;
+const END_OF_TABLE_SIZE: u8 = 4;
+
+// BIOS characteristics, see SMBIOS 3.2 section 7.1.1, table 7.
+const BIOS_CHARACTERISTICS_NOT_SUPPORTED: u64 = 1 << 3;
+// BIOS characteristics extension byte 2, see SMBIOS 3.2 section 7.1.2.2, table 9.
+const BIOS_CHARACTERISTICS_EXT2_VIRTUAL_MACHINE: u8 = 1 << 4;
+// Release numbers reported when the BIOS does not support them.
+const BIOS_RELEASE_UNKNOWN: u8 = 0xff;
+// System wake-up type, see SMBIOS 3.2 section 7.2.2, table 12.
+const WAKE_UP_TYPE_POWER_SWITCH: u8 = 6;
+
+// Strings describing the firmware, which Firecracker does not run.
+const BIOS_VENDOR: &str = "Firecracker";
+const BIOS_VERSION: &str = "0";
+
+/// Errors thrown while setting up the SMBIOS tables.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SmbiosError {
+    /// The SMBIOS tables do not fit in the BIOS area below high memory.
+    TablesTooLarge,
+    /// Failure to write the SMBIOS structure table to guest memory.
+    WriteTables,
+    /// Failure to write the SMBIOS entry point to guest memory.
+    WriteEntryPoint,
+}
+
+/// Identification of the system, as exposed to the guest through the System Information
+/// structure.
+#[derive(Debug, Default)]
+pub struct SystemInfo<'a> {
+    /// Manufacturer of the system.
+    pub manufacturer: Option<&'a str>,
+    /// Product name of the system.
+    pub product_name: Option<&'a str>,
+    /// Version of the system.
+    pub version: Option<&'a str>,
+    /// Serial number of the system.
+    pub serial_number: Option<&'a str>,
+    /// UUID of the system, in the byte order of its canonical text representation.
+    pub uuid: [u8; 16],
+}
+
+fn compute_checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x));
+    0u8.wrapping_sub(sum)
+}
+
+/// Helper for building an SMBIOS structure, made of a formatted area followed by its strings.
+#[derive(Debug)]
+struct Structure {
+    data: Vec<u8>,
+    strings: Vec<u8>,
+    string_count: u8,
+}
+
+impl Structure {
+    fn new(structure_type: u8, length: u8, handle: u16) -> Self {
+        let mut data = Vec::with_capacity(usize::from(length));
+        data.push(structure_type);
+        data.push(length);
+        data.extend_from_slice(&handle.to_le_bytes());
+        Structure {
+            data,
+            strings: Vec::new(),
+            string_count: 0,
+        }
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Appends the index of `string` in the string set, 0 standing for no string.
+    fn append_string(&mut self, string: Option<&str>) {
+        match string {
+            Some(string) => {
+                self.strings.extend_from_slice(string.as_bytes());
+                self.strings.push(0);
+                self.string_count += 1;
+                self.data.push(self.string_count);
+            }
+            None => self.data.push(0),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        debug_assert_eq!(self.data.len(), usize::from(self.data[1]));
+        self.data.append(&mut self.strings);
+        // The string set ends with a double NUL, even when it is empty.
+        if self.string_count == 0 {
+            self.data.push(0);
+        }
+        self.data.push(0);
+        self.data
+    }
+}
+
+fn create_bios_information(handle: u16) -> Vec<u8> {
+    let mut bios = Structure::new(TYPE_BIOS_INFORMATION, BIOS_INFORMATION_SIZE, handle);
+    bios.append_string(Some(BIOS_VENDOR));
+    bios.append_string(Some(BIOS_VERSION));
+    // No BIOS starting address segment.
+    bios.append(&0u16.to_le_bytes());
+    // No release date.
+    bios.append_string(None);
+    // No BIOS ROM.
+    bios.append(&[0]);
+    bios.append(&BIOS_CHARACTERISTICS_NOT_SUPPORTED.to_le_bytes());
+    bios.append(&[0, BIOS_CHARACTERISTICS_EXT2_VIRTUAL_MACHINE]);
+    // System BIOS and embedded controller firmware release numbers.
+    bios.append(&[BIOS_RELEASE_UNKNOWN; 4]);
+    bios.finish()
+}
+
+fn create_system_information(handle: u16, info: &SystemInfo) -> Vec<u8> {
+    let mut system = Structure::new(TYPE_SYSTEM_INFORMATION, SYSTEM_INFORMATION_SIZE, handle);
+    system.append_string(info.manufacturer);
+    system.append_string(info.product_name);
+    system.append_string(info.version);
+    system.append_string(info.serial_number);
+    // The first three fields of the UUID are stored in little endian, see SMBIOS 3.2
+    // section 7.2.1.
+    let uuid = &info.uuid;
+    system.append(&[
+        uuid[3], uuid[2], uuid[1], uuid[0], uuid[5], uuid[4], uuid[7], uuid[6],
+    ]);
+    system.append(&uuid[8..]);
+    system.append(&[WAKE_UP_TYPE_POWER_SWITCH]);
+    // No SKU number nor family.
+    system.append_string(None);
+    system.append_string(None);
+    system.finish()
+}
+
+fn create_end_of_table(handle: u16) -> Vec<u8> {
+    Structure::new(TYPE_END_OF_TABLE, END_OF_TABLE_SIZE, handle).finish()
+}
+
+fn create_entry_point(table_addr: GuestAddress, table_size: u32) -> Vec<u8> {
+    let mut entry_point = Vec::with_capacity(ENTRY_POINT_SIZE);
+    entry_point.extend_from_slice(b"_SM3_");
+    // The checksum is filled in below.
+    entry_point.push(0);
+    entry_point.push(ENTRY_POINT_SIZE as u8);
+    entry_point.push(SMBIOS_MAJOR_VERSION);
+    entry_point.push(SMBIOS_MINOR_VERSION);
+    // Specification docrev.
+    entry_point.push(0);
+    // Entry point revision 1, for the SMBIOS 3.0 entry point.
+    entry_point.push(1);
+    // Reserved.
+    entry_point.push(0);
+    entry_point.extend_from_slice(&table_size.to_le_bytes());
+    entry_point.extend_from_slice(&table_addr.raw_value().to_le_bytes());
+
+    entry_point[ENTRY_POINT_CHECKSUM_OFFSET] = compute_checksum(&entry_point);
+    entry_point
+}
+
+/// Creates the SMBIOS tables identifying this microVM and writes them to guest memory.
+///
+/// The SMBIOS 3.0 entry point is placed at `SMBIOS_START`, followed by the BIOS Information,
+/// System Information and End-of-Table structures, all of them inside the BIOS area below 1MiB.
+/// Calling it again overwrites the tables, which is how the guest sees a new identity.
+///
+/// # Arguments
+///
+/// * `mem` - The memory to be used by the guest.
+/// * `info` - The identification of the system.
+pub fn setup_smbios(mem: &GuestMemoryMmap, info: &SystemInfo) -> Result<(), SmbiosError> {
+    let entry_point_addr = GuestAddress(SMBIOS_START);
+    let table_addr = entry_point_addr.unchecked_add(STRUCTURE_TABLE_OFFSET);
+
+    let mut table = create_bios_information(0);
+    table.append(&mut create_system_information(1, info));
+    table.append(&mut create_end_of_table(2));
+    if table_addr.raw_value() + table.len() as u64 > HIMEM_START {
+        return Err(SmbiosError::TablesTooLarge);
+    }
+    mem.write_slice(&table, table_addr)
+        .map_err(|_| SmbiosError::WriteTables)?;
+
+    // The table size fits in a u32 as the whole table is below 1MiB.
+    mem.write_slice(
+        &create_entry_point(table_addr, table.len() as u32),
+        entry_point_addr,
+    )
+    .map_err(|_| SmbiosError::WriteEntryPoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd,
+        0xef,
+    ];
+
+    fn create_memory() -> GuestMemoryMmap {
+        utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), HIMEM_START as usize)],
+            false,
+        )
+        .unwrap()
+    }
+
+    // Reads the structure table through the entry point, checking its checksum.
+    fn read_table(mem: &GuestMemoryMmap) -> Vec<u8> {
+        let mut entry_point = [0u8; ENTRY_POINT_SIZE];
+        mem.read_slice(&mut entry_point, GuestAddress(SMBIOS_START))
+            .unwrap();
+        assert_eq!(&entry_point[..5], b"_SM3_");
+        assert_eq!(usize::from(entry_point[6]), ENTRY_POINT_SIZE);
+        assert_eq!(compute_checksum(&entry_point), 0);
+
+        let size = u32::from_le_bytes(entry_point[12..16].try_into().unwrap());
+        let addr = u64::from_le_bytes(entry_point[16..24].try_into().unwrap());
+        assert_eq!(addr % 16, 0);
+        let mut table = vec![0; size as usize];
+        mem.read_slice(&mut table, GuestAddress(addr)).unwrap();
+        table
+    }
+
+    // Splits the structure table into its formatted areas and string sets.
+    fn parse_structures(table: &[u8]) -> Vec<(&[u8], Vec<&str>)> {
+        let mut structures = Vec::new();
+        let mut offset = 0;
+        while offset < table.len() {
+            let formatted = &table[offset..offset + usize::from(table[offset + 1])];
+            offset += formatted.len();
+            let mut strings = Vec::new();
+            if table[offset] == 0 {
+                offset += 1;
+            }
+            while table[offset] != 0 {
+                let len = table[offset..].iter().position(|&b| b == 0).unwrap();
+                strings.push(std::str::from_utf8(&table[offset..offset + len]).unwrap());
+                offset += len + 1;
+            }
+            offset += 1;
+            structures.push((formatted, strings));
+        }
+        structures
+    }
+
+    #[test]
+    fn test_structure_strings() {
+        let mut structure = Structure::new(TYPE_END_OF_TABLE, END_OF_TABLE_SIZE, 7);
+        assert_eq!(structure.finish(), vec![TYPE_END_OF_TABLE, 4, 7, 0, 0, 0]);
+
+        structure = Structure::new(0x80, 7, 0);
+        structure.append_string(Some("foo"));
+        structure.append_string(None);
+        structure.append_string(Some("bar"));
+        assert_eq!(
+            structure.finish(),
+            vec![0x80, 7, 0, 0, 1, 0, 2, b'f', b'o', b'o', 0, b'b', b'a', b'r', 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_tables() {
+        let mem = create_memory();
+        let info = SystemInfo {
+            manufacturer: Some("ACME"),
+            product_name: Some("Rocket"),
+            version: None,
+            serial_number: Some("SN-42"),
+            uuid: UUID,
+        };
+        setup_smbios(&mem, &info).unwrap();
+
+        let table = read_table(&mem);
+        let structures = parse_structures(&table);
+        assert_eq!(structures.len(), 3);
+
+        let (bios, strings) = &structures[0];
+        assert_eq!(bios[0], TYPE_BIOS_INFORMATION);
+        assert_eq!(bios.len(), usize::from(BIOS_INFORMATION_SIZE));
+        assert_eq!(strings, &vec![BIOS_VENDOR, BIOS_VERSION]);
+        assert_eq!(bios[19], BIOS_CHARACTERISTICS_EXT2_VIRTUAL_MACHINE);
+
+        let (system, strings) = &structures[1];
+        assert_eq!(system[0], TYPE_SYSTEM_INFORMATION);
+        assert_eq!(system.len(), usize::from(SYSTEM_INFORMATION_SIZE));
+        assert_eq!(strings, &vec!["ACME", "Rocket", "SN-42"]);
+        assert_eq!(&system[4..8], &[1, 2, 0, 3]);
+        assert_eq!(
+            &system[8..24],
+            &[
+                0x67, 0x45, 0x23, 0x01, 0xab, 0x89, 0xef, 0xcd, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+                0xcd, 0xef
+            ]
+        );
+
+        let (end, strings) = &structures[2];
+        assert_eq!(end[0], TYPE_END_OF_TABLE);
+        assert!(strings.is_empty());
+    }
+
+    #[test]
+    fn test_overwrite_tables() {
+        let mem = create_memory();
+        let mut info = SystemInfo {
+            serial_number: Some("a-rather-long-serial-number"),
+            ..Default::default()
+        };
+        setup_smbios(&mem, &info).unwrap();
+
+        info.serial_number = Some("short");
+        info.uuid = UUID;
+        setup_smbios(&mem, &info).unwrap();
+        let table = read_table(&mem);
+        let structures = parse_structures(&table);
+        assert_eq!(structures[1].1, vec!["short"]);
+        assert_eq!(&structures[1].0[16..24], &UUID[8..]);
+    }
+
+    #[test]
+    fn test_not_enough_memory() {
+        let mem = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), SMBIOS_START as usize)],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            setup_smbios(&mem, &SystemInfo::default()),
+            Err(SmbiosError::WriteTables)
+        );
+    }
+}
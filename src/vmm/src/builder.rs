@@ -6,7 +6,10 @@
 #[cfg(target_arch = "x86_64")]
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::fs::File;
 use std::io::{self, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
 use event_manager::{MutEventSubscriber, SubscriberOps};
@@ -14,21 +17,30 @@ use libc::EFD_NONBLOCK;
 use linux_loader::cmdline::Cmdline as LoaderKernelCmdline;
 #[cfg(target_arch = "x86_64")]
 use linux_loader::loader::elf::Elf as Loader;
+#[cfg(target_arch = "x86_64")]
+use linux_loader::loader::elf::PvhBootCapability;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::PE as Loader;
-use linux_loader::loader::KernelLoader;
+use linux_loader::loader::{KernelLoader, KernelLoaderResult};
 use log::error;
 use seccompiler::BpfThreadMap;
 use snapshot::Persist;
 use userfaultfd::Uffd;
 use utils::eventfd::EventFd;
+use utils::syscall::SyscallReturnCode;
 use utils::time::TimestampUs;
-use utils::vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, ReadVolatile};
+use utils::vm_memory::{
+    Address, FileOffset, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+    ReadVolatile,
+};
 #[cfg(target_arch = "aarch64")]
 use vm_superio::Rtc;
 use vm_superio::Serial;
 
-use crate::arch::InitrdConfig;
+use crate::arch::{BootProtocol, EntryPoint, InitrdConfig};
+use crate::cgroup::{apply_cpu_bandwidth, CpuBandwidthError};
+#[cfg(target_arch = "x86_64")]
+use crate::confidential::{ConfidentialError, ConfidentialLaunch};
 #[cfg(target_arch = "aarch64")]
 use crate::construct_kvm_mpidrs;
 use crate::cpu_config::templates::{
@@ -40,22 +52,53 @@ use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::persist::MMIODevManagerConstructorArgs;
 use crate::devices::legacy::serial::SerialOut;
+#[cfg(target_arch = "x86_64")]
+use crate::devices::legacy::IoApic;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::RTCDevice;
 use crate::devices::legacy::{EventFdTrigger, SerialEventsWrapper, SerialWrapper};
+use crate::devices::tpm::{Swtpm, Tpm, TpmError, INIT_FLAG_DELETE_VOLATILE};
+use crate::devices::virtio::net::TapError;
+use crate::devices::virtio::record::{QueueRecorder, RecordError, RecordHeader, SharedRecorder};
 use crate::devices::virtio::{
-    Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend,
+    Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, VirtioMem, Vsock, VsockUnixBackend,
+    P9, TYPE_9P, TYPE_BLOCK, TYPE_NET,
 };
 use crate::devices::BusDevice;
+#[cfg(target_arch = "x86_64")]
+use crate::gdb::{GdbError, GdbServer};
+use crate::identity::publish_identity;
+use crate::io_threads::{IoThreadError, IoThreadsBuilder};
+use crate::landlock::LandlockError;
+use crate::memory_lock::{lock_guest_memory, MemLockError, MemoryRange};
+use crate::memory_pressure::{MemoryPressureError, MemoryPressureMonitor};
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::resources::VmResources;
+use crate::stall_detector::StallDetector;
 use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::confidential::ConfidentialConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::identity::IdentityConfig;
+use crate::vmm_config::identity::IdentityConfigError;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{MachineConfigUpdate, VmConfig, VmConfigError};
+use crate::vmm_config::landlock::LandlockConfig;
+use crate::vmm_config::machine_config::{
+    MachineConfigUpdate, MmioLayout, VmConfig, VmConfigError, MAX_SUPPORTED_VCPUS,
+};
+use crate::vmm_config::open_file;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::smbios::SmbiosConfig;
+use crate::vmm_config::tpm::TpmConfig;
+use crate::vmm_config::virtio_record::VirtioRecordConfig;
+use crate::vstate::dirty_ring::{enable_dirty_rings, DirtyRings};
 use crate::vstate::vcpu::{Vcpu, VcpuConfig};
 use crate::vstate::vm::Vm;
+use crate::working_set::WorkingSetSampler;
 use crate::{device_manager, EventManager, RestoreVcpusError, Vmm, VmmError};
 
+/// Alignment of each initrd image when several of them are loaded concatenated.
+const INITRD_ALIGNMENT: usize = 4;
+
 /// Errors associated with starting the instance.
 #[derive(Debug, thiserror::Error)]
 pub enum StartMicrovmError {
@@ -65,6 +108,10 @@ pub enum StartMicrovmError {
     /// This error is thrown by the minimal boot loader implementation.
     #[error("System configuration error: {0:?}")]
     ConfigureSystem(crate::arch::ConfigurationError),
+    /// Cannot launch the confidential microVM.
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot launch the confidential microVM: {0}")]
+    Confidential(ConfidentialError),
     /// Error using CPU template to configure vCPUs
     #[error("Failed to create guest config: {0:?}")]
     CreateGuestConfig(#[from] GuestConfigError),
@@ -79,9 +126,23 @@ pub enum StartMicrovmError {
     #[cfg(target_arch = "x86_64")]
     #[error("Error creating legacy device: {0}")]
     CreateLegacyDevice(device_manager::legacy::LegacyDeviceError),
+    /// Cannot load the PVH firmware image.
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot load PVH firmware image: {}", format!("{}", .0).replace('\"', ""))]
+    PvhFirmwareLoader(linux_loader::loader::Error),
+    /// The PVH firmware image does not advertise a PVH entry point.
+    #[cfg(target_arch = "x86_64")]
+    #[error("The PVH firmware image does not provide a PVH entry point.")]
+    PvhFirmwareMissingEntry,
     /// Memory regions are overlapping or mmap fails.
     #[error("Invalid Memory Configuration: {}", format!("{:?}", .0).replace('\"', ""))]
     GuestMemoryMmap(utils::vm_memory::Error),
+    /// The memfd backing the guest memory cannot be used.
+    #[error("Invalid guest memory backing: {0}")]
+    MemBacking(#[from] MemBackingError),
+    /// The guest memory cannot be locked in host memory.
+    #[error("Error locking the guest memory: {0}")]
+    MemLock(MemLockError),
     /// Cannot load initrd due to an invalid memory configuration.
     #[error("Cannot load initrd due to an invalid memory configuration.")]
     InitrdLoad,
@@ -94,6 +155,10 @@ pub enum StartMicrovmError {
     /// Failed to get CPU template.
     #[error("Failed to get CPU template: {0}")]
     GetCpuTemplate(#[from] GetCpuTemplateError),
+    /// Cannot start the GDB stub.
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot start the GDB stub: {0}")]
+    Gdb(GdbError),
     /// The kernel command line is invalid.
     #[error("Invalid kernel command line: {0}")]
     KernelCmdline(String),
@@ -103,6 +168,13 @@ pub enum StartMicrovmError {
         format!("{}", .0).replace('\"', "")
     )]
     KernelLoader(linux_loader::loader::Error),
+    /// The kernel image does not advertise the requested PVH entry point.
+    #[cfg(target_arch = "x86_64")]
+    #[error("The kernel image does not provide a PVH entry point.")]
+    KernelMissingPvhEntry,
+    /// Cannot enforce the Landlock ruleset.
+    #[error("Cannot enforce the Landlock ruleset: {0}")]
+    Landlock(LandlockError),
     /// Cannot load command line string.
     #[error("Cannot load command line string: {}", format!("{}", .0).replace('\"', ""))]
     LoadCommandline(linux_loader::loader::Error),
@@ -136,6 +208,93 @@ pub enum StartMicrovmError {
     /// Failed to create an Entropy device
     #[error("Cannot create the entropy device: {0}")]
     CreateEntropyDevice(crate::devices::virtio::rng::EntropyError),
+    /// Failed to create the memory hotplug device.
+    #[error("Cannot create the memory hotplug device: {0}")]
+    CreateMemoryHotplugDevice(crate::devices::virtio::mem::VirtioMemError),
+    /// The memory hotplug region does not fit in the guest physical address space.
+    #[error("The memory hotplug region does not fit in the guest physical address space.")]
+    MemoryHotplugRegion,
+    /// Cannot apply the scheduling settings of the vCPU, VMM or I/O threads.
+    #[error("Cannot apply the thread scheduling settings: {0}")]
+    ThreadScheduling(io::Error),
+    /// Cannot start the I/O threads.
+    #[error("Cannot start the I/O threads: {0}")]
+    IoThreads(IoThreadError),
+    /// Cannot apply the CPU bandwidth of the microVM.
+    #[error("Cannot apply the CPU bandwidth: {0}")]
+    CpuBandwidth(CpuBandwidthError),
+    /// Cannot create the vCPU stall detector.
+    #[error("Cannot create the vCPU stall detector: {0}")]
+    StallDetection(io::Error),
+    /// The device of a recorded virtio queue does not exist or has no such queue.
+    #[error("Cannot record queue {1} of device {0}: no such queue.")]
+    VirtioRecordQueue(String, u16),
+    /// Cannot create the recording of a virtio queue.
+    #[error("Cannot create the virtio recording: {0}")]
+    VirtioRecord(RecordError),
+    /// Cannot create the working set sampler.
+    #[error("Cannot create the working set sampler: {0}")]
+    WorkingSet(io::Error),
+    /// Cannot load the key signing the identity document.
+    #[error("{0}")]
+    Identity(IdentityConfigError),
+    /// Cannot publish the identity document in MMDS.
+    #[error("Cannot publish the identity document in MMDS: {0}")]
+    IdentityDocument(mmds::data_store::Error),
+    /// Cannot connect the TPM device to swtpm.
+    #[error("Cannot create the TPM device: {0}")]
+    Tpm(TpmError),
+    /// The TPM device is configured while ACPI is disabled.
+    #[cfg(target_arch = "x86_64")]
+    #[error("The TPM device requires ACPI to be enabled in the machine configuration.")]
+    TpmWithoutAcpi,
+    /// More than 32 vCPUs are configured while both ACPI and the split irqchip are disabled.
+    #[cfg(target_arch = "x86_64")]
+    #[error(
+        "More than 32 vCPUs require ACPI or the split irqchip to be enabled in the machine \
+         configuration."
+    )]
+    VcpusWithoutAcpiOrSplitIrqchip,
+    /// More than 254 vCPUs are configured while ACPI or the split irqchip is disabled.
+    #[cfg(target_arch = "x86_64")]
+    #[error(
+        "More than 254 vCPUs require both ACPI and the split irqchip to be enabled in the machine \
+         configuration."
+    )]
+    X2apicWithoutAcpiOrSplitIrqchip,
+    /// KVM cannot create as many vCPUs as configured.
+    #[error("Cannot create {0} vCPUs, KVM supports at most {1}.")]
+    TooManyVcpus(u16, usize),
+    /// The guest memory does not fit in the physical address space of the vCPUs.
+    #[cfg(target_arch = "x86_64")]
+    #[error("The guest memory does not fit in the {0}-bit physical address space of the vCPUs.")]
+    GuestMemoryTooLarge(u8),
+    /// Cannot make the taps of the network interfaces non-persistent.
+    #[error("Cannot make the taps removable on exit: {0}")]
+    RemoveTaps(TapError),
+    /// Cannot create the host memory pressure monitor.
+    #[error("Cannot create the memory pressure monitor: {0}")]
+    MemoryPressure(MemoryPressureError),
+}
+
+/// Errors associated with the memfd backing the guest memory.
+#[derive(Debug, thiserror::Error)]
+pub enum MemBackingError {
+    /// The memfd cannot be opened.
+    #[error("Cannot open the memfd: {0}")]
+    Open(io::Error),
+    /// The seals of the file cannot be read, which happens when it is not a memfd.
+    #[error("Cannot get the seals of the memfd: {0}")]
+    GetSeals(io::Error),
+    /// The memfd can be resized, or cannot be written.
+    #[error(
+        "The memfd must be sealed with F_SEAL_SHRINK and F_SEAL_GROW, and not with F_SEAL_WRITE \
+         nor F_SEAL_FUTURE_WRITE, but its seals are {0:#x}."
+    )]
+    InvalidSeals(i32),
+    /// The size of the memfd does not match the memory size.
+    #[error("The memfd is {0} bytes large, but the guest memory is {1} bytes large.")]
+    InvalidSize(u64, u64),
 }
 
 /// It's convenient to automatically convert `linux_loader::cmdline::Error`s
@@ -147,14 +306,20 @@ impl std::convert::From<linux_loader::cmdline::Error> for StartMicrovmError {
 }
 
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
+#[allow(clippy::too_many_arguments)]
 fn create_vmm_and_vcpus(
     instance_info: &InstanceInfo,
     event_manager: &mut EventManager,
     guest_memory: GuestMemoryMmap,
     uffd: Option<Uffd>,
     track_dirty_pages: bool,
-    vcpu_count: u8,
+    vcpu_count: u16,
     kvm_capabilities: Vec<KvmCapability>,
+    confidential: Option<&ConfidentialConfig>,
+    acpi: bool,
+    split_irqchip: bool,
+    gic_its: bool,
+    mmio_layout: &MmioLayout,
 ) -> Result<(Vmm, Vec<Vcpu>), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
@@ -163,9 +328,29 @@ fn create_vmm_and_vcpus(
     let mut vm = Vm::new(kvm_capabilities)
         .map_err(VmmError::Vm)
         .map_err(StartMicrovmError::Internal)?;
+    if usize::from(vcpu_count) > vm.max_vcpus() {
+        return Err(TooManyVcpus(vcpu_count, vm.max_vcpus()));
+    }
     vm.memory_init(&guest_memory, track_dirty_pages)
         .map_err(VmmError::Vm)
         .map_err(StartMicrovmError::Internal)?;
+    // The memory encryption has to be initialized before the vCPUs are created.
+    #[cfg(target_arch = "x86_64")]
+    let confidential = confidential
+        .map(|config| ConfidentialLaunch::start(vm.fd(), &guest_memory, config))
+        .transpose()
+        .map_err(Confidential)?;
+    // The configuration of confidential microVMs is rejected on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    debug_assert!(confidential.is_none());
+    // The dirty rings have to be enabled before the vCPUs are created.
+    let dirty_ring_entries = if track_dirty_pages {
+        enable_dirty_rings(vm.fd())
+            .map_err(|err| VmmError::DirtyRings(err.into()))
+            .map_err(Internal)?
+    } else {
+        None
+    };
 
     let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
         .map_err(VmmError::EventFd)
@@ -174,18 +359,34 @@ fn create_vmm_and_vcpus(
     // Instantiate the MMIO device manager.
     // 'mmio_base' address has to be an address which is protected by the kernel
     // and is architectural specific.
-    let mmio_device_manager = MMIODeviceManager::new(
-        crate::arch::MMIO_MEM_START,
-        crate::arch::MMIO_MEM_SIZE,
-        (crate::arch::IRQ_BASE, crate::arch::IRQ_MAX),
-    )
-    .map_err(StartMicrovmError::RegisterMmioDevice)?;
+    #[allow(unused_mut)]
+    let mut mmio_device_manager = MMIODeviceManager::with_layout(mmio_layout)
+        .map_err(StartMicrovmError::RegisterMmioDevice)?;
 
     // For x86_64 we need to create the interrupt controller before calling `KVM_CREATE_VCPUS`
     // while on aarch64 we need to do it the other way around.
     #[cfg(target_arch = "x86_64")]
-    let (vcpus, pio_device_manager) = {
-        setup_interrupt_controller(&mut vm)?;
+    let (mut vcpus, pio_device_manager) = {
+        if split_irqchip {
+            vm.setup_split_irqchip()
+                .map_err(VmmError::Vm)
+                .map_err(Internal)?;
+            // The APIC IDs past the xAPIC ones are only reachable through 32-bit x2APIC IDs.
+            if vcpu_count > crate::arch::x86_64::MAX_XAPIC_VCPUS {
+                vm.enable_x2apic_api()
+                    .map_err(VmmError::Vm)
+                    .map_err(Internal)?;
+            }
+            // The IOAPIC ID is the same as in the MP table and the MADT.
+            mmio_device_manager
+                .register_mmio_ioapic(IoApic::new(
+                    vm.shared_fd(),
+                    crate::arch::x86_64::ioapic_id(vcpu_count),
+                ))
+                .map_err(RegisterMmioDevice)?;
+        } else {
+            setup_interrupt_controller(&mut vm)?;
+        }
         let vcpus = create_vcpus(&vm, vcpu_count, &vcpus_exit_evt).map_err(Internal)?;
 
         // Make stdout non blocking.
@@ -205,7 +406,7 @@ fn create_vmm_and_vcpus(
         let pio_device_manager = {
             // TODO Remove these unwraps.
             let mut pio_dev_mgr = PortIODeviceManager::new(serial_device, reset_evt).unwrap();
-            pio_dev_mgr.register_devices(vm.fd()).unwrap();
+            pio_dev_mgr.register_devices(vm.fd(), acpi).unwrap();
             pio_dev_mgr
         };
 
@@ -217,12 +418,31 @@ fn create_vmm_and_vcpus(
     // was already initialized.
     // Search for `kvm_arch_vcpu_create` in arch/arm/kvm/arm.c.
     #[cfg(target_arch = "aarch64")]
-    let vcpus = {
+    let mut vcpus = {
         let vcpus = create_vcpus(&vm, vcpu_count, &vcpus_exit_evt).map_err(Internal)?;
-        setup_interrupt_controller(&mut vm, vcpu_count)?;
+        setup_interrupt_controller(&mut vm, vcpu_count, gic_its)?;
         vcpus
     };
 
+    let dirty_rings = match dirty_ring_entries {
+        Some(entries) => {
+            let dirty_rings = DirtyRings::new(
+                vm.fd(),
+                vcpus.iter().map(|vcpu| &vcpu.kvm_vcpu.fd),
+                entries,
+                &guest_memory,
+            )
+            .map_err(VmmError::DirtyRings)
+            .map_err(Internal)?;
+            let dirty_rings = Arc::new(dirty_rings);
+            for vcpu in vcpus.iter_mut() {
+                vcpu.set_dirty_rings(dirty_rings.clone());
+            }
+            Some(dirty_rings)
+        }
+        None => None,
+    };
+
     let vmm = Vmm {
         events_observer: Some(std::io::stdin()),
         instance_info: instance_info.clone(),
@@ -230,8 +450,19 @@ fn create_vmm_and_vcpus(
         vm,
         guest_memory,
         uffd,
+        uffd_handover: None,
         vcpus_handles: Vec::new(),
+        parked_vcpu_states: Vec::new(),
         vcpus_exit_evt,
+        dirty_log_samples: Default::default(),
+        dirty_rings,
+        guest_ready: Default::default(),
+        mem_lock: None,
+        io_threads: Default::default(),
+        guest_files: None,
+        guest_freeze: None,
+        #[cfg(target_arch = "x86_64")]
+        confidential,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
@@ -256,13 +487,50 @@ pub fn build_microvm_for_boot(
     // Timestamp for measuring microVM boot duration.
     let request_ts = TimestampUs::default();
 
+    // The resources acquired from here on are cleaned up even if the boot fails.
+    if let Some(shutdown_hooks) = &vm_resources.shutdown_hooks {
+        crate::shutdown_hooks::install(shutdown_hooks);
+    }
+
     let boot_config = vm_resources
         .boot_source_builder()
         .ok_or(MissingKernelConfig)?;
 
+    // The vCPUs past the xAPIC ones are only described by the MADT, and can only be the
+    // destination of the IOAPIC interrupts when the IOAPIC is emulated in userspace.
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources.vm_config.vcpu_count > crate::arch::x86_64::MAX_XAPIC_VCPUS
+        && !(vm_resources.vm_config.acpi && vm_resources.vm_config.split_irqchip)
+    {
+        return Err(X2apicWithoutAcpiOrSplitIrqchip);
+    }
+    // Without them, the vCPU count stays limited as it was before the x2APIC support.
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources.vm_config.vcpu_count > crate::arch::x86_64::MAX_LEGACY_VCPUS
+        && !vm_resources.vm_config.acpi
+        && !vm_resources.vm_config.split_irqchip
+    {
+        return Err(VcpusWithoutAcpiOrSplitIrqchip);
+    }
+
     let track_dirty_pages = vm_resources.track_dirty_pages();
-    let guest_memory = create_guest_memory(vm_resources.vm_config.mem_size_mib, track_dirty_pages)?;
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
+    let mmio_layout = vm_resources.vm_config.mmio_layout.unwrap_or_default();
+    let hotplug_region = memory_hotplug_region(vm_resources)?;
+    let mem_backing = vm_resources
+        .vm_config
+        .mem_backing_path
+        .as_deref()
+        .map(|path| open_mem_backing(path, vm_resources.vm_config.mem_size_mib << 20))
+        .transpose()?;
+    let guest_memory = create_guest_memory(
+        vm_resources.vm_config.mem_size_mib,
+        &mmio_layout,
+        hotplug_region,
+        mem_backing,
+        track_dirty_pages,
+    )?;
+    #[cfg_attr(target_arch = "aarch64", allow(unused_variables))]
+    let (entry_point, kernel_region) = load_kernel(boot_config, &guest_memory)?;
     let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
@@ -270,15 +538,24 @@ pub fn build_microvm_for_boot(
 
     let cpu_template = vm_resources.vm_config.cpu_template.get_cpu_template()?;
 
+    // The working set is sampled from the KVM dirty log.
     let (mut vmm, mut vcpus) = create_vmm_and_vcpus(
         instance_info,
         event_manager,
         guest_memory,
         None,
-        track_dirty_pages,
+        track_dirty_pages || vm_resources.working_set.is_some(),
         vm_resources.vm_config.vcpu_count,
         cpu_template.kvm_capabilities.clone(),
+        vm_resources.confidential.as_ref(),
+        vm_resources.vm_config.acpi,
+        vm_resources.vm_config.split_irqchip,
+        vm_resources.vm_config.gic_its,
+        &mmio_layout,
     )?;
+    lock_vmm_memory(&mut vmm, vm_resources, None).map_err(MemLock)?;
+    let mut io_threads =
+        IoThreadsBuilder::new(vm_resources.io_threads.as_ref()).map_err(IoThreads)?;
 
     // The boot timer device needs to be the first device attached in order
     // to maintain the same MMIO address referenced in the documentation
@@ -296,12 +573,14 @@ pub fn build_microvm_for_boot(
         &mut boot_cmdline,
         vm_resources.block.list.iter(),
         event_manager,
+        &mut io_threads,
     )?;
     attach_net_devices(
         &mut vmm,
         &mut boot_cmdline,
         vm_resources.net_builder.iter(),
         event_manager,
+        &mut io_threads,
     )?;
 
     if let Some(unix_vsock) = vm_resources.vsock.get() {
@@ -309,22 +588,131 @@ pub fn build_microvm_for_boot(
     }
 
     if let Some(entropy) = vm_resources.entropy.get() {
+        if let Some(deterministic_boot) = &vm_resources.deterministic_boot {
+            entropy
+                .lock()
+                .expect("Poisoned lock")
+                .set_seed(deterministic_boot.seed);
+        }
         attach_entropy_device(&mut vmm, &mut boot_cmdline, entropy, event_manager)?;
     }
 
+    attach_shared_dirs(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.shared_dirs.iter(),
+        &mut io_threads,
+    )?;
+
+    if let (Some(region), Some(hotplug_config)) = (hotplug_region, &vm_resources.memory_hotplug) {
+        attach_memory_hotplug_device(
+            &mut vmm,
+            &mut boot_cmdline,
+            region,
+            hotplug_config.block_size_mib,
+            event_manager,
+        )?;
+    }
+
+    if let Some(virtio_record_config) = &vm_resources.virtio_record {
+        attach_virtio_recorders(&vmm, virtio_record_config)?;
+    }
+
+    if let Some(tpm_config) = &vm_resources.tpm {
+        // The TPM is only described to x86_64 guests through ACPI.
+        #[cfg(target_arch = "x86_64")]
+        if !vm_resources.vm_config.acpi {
+            return Err(TpmWithoutAcpi);
+        }
+        attach_tpm_device(&mut vmm, tpm_config)?;
+    }
+
     #[cfg(target_arch = "aarch64")]
-    attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
+    {
+        // The start time was validated to fit in the 32-bit RTC counter.
+        let rtc_time = vm_resources
+            .deterministic_boot
+            .as_ref()
+            .map(|config| u32::try_from(config.start_time_s).unwrap());
+        attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline, rtc_time)
+            .map_err(Internal)?;
+    }
 
     configure_system_for_boot(
         &vmm,
         vcpus.as_mut(),
-        &vm_resources.vm_config,
+        vm_resources,
         &cpu_template,
-        entry_addr,
+        entry_point,
         &initrd,
         boot_cmdline,
     )?;
 
+    // The memory written by Firecracker is encrypted once the vCPU registers are set, since the
+    // launch flow encrypts them as well: the system area below the kernel, the kernel and the
+    // initrd.
+    #[cfg(target_arch = "x86_64")]
+    if let Some(confidential) = vmm.confidential.as_mut() {
+        let mut measured_ranges = vec![
+            (
+                GuestAddress(0),
+                crate::arch::x86_64::layout::HIMEM_START as usize,
+            ),
+            kernel_region,
+        ];
+        if let Some(initrd) = &initrd {
+            measured_ranges.push((initrd.address, initrd.size));
+        }
+        confidential
+            .finish(vmm.vm.fd(), &vmm.guest_memory, &measured_ranges)
+            .map_err(Confidential)?;
+    }
+
+    // Bind the socket of the GDB stub before the Landlock ruleset forbids creating it.
+    #[cfg(target_arch = "x86_64")]
+    let gdb_server = match &vm_resources.gdb {
+        Some(gdb_config) => {
+            let gdb_server = GdbServer::bind(gdb_config).map_err(Gdb)?;
+            for vcpu in vcpus.iter_mut() {
+                vcpu.set_gdb_notifier(gdb_server.notifier());
+            }
+            Some(gdb_server)
+        }
+        None => None,
+    };
+
+    // The signing key is read before the Landlock ruleset forbids it.
+    if let (Some(identity), Some(mmds)) = (&vm_resources.identity, &vm_resources.mmds) {
+        let signing_key = identity.signing_key().map_err(Identity)?;
+        publish_identity(
+            &mut mmds.lock().expect("Poisoned lock"),
+            identity,
+            signing_key.as_ref(),
+            &instance_info.id,
+        )
+        .map_err(IdentityDocument)?;
+    }
+
+    if let Some(shutdown_hooks) = &vm_resources.shutdown_hooks {
+        if shutdown_hooks.remove_taps {
+            make_taps_transient(&vmm).map_err(RemoveTaps)?;
+        }
+    }
+
+    // Enforce the Landlock ruleset before spawning the vcpu threads, so that they inherit it.
+    if let Some(landlock_config) = &vm_resources.landlock {
+        apply_landlock_ruleset(&vmm, landlock_config).map_err(Landlock)?;
+    }
+
+    // Start the guest clock from zero, so that it does not depend on the host uptime.
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources.deterministic_boot.is_some() {
+        vmm.vm
+            .reset_clock()
+            .map_err(VmmError::Vm)
+            .map_err(Internal)?;
+    }
+
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
     vmm.start_vcpus(
         vcpus,
@@ -336,19 +724,63 @@ pub fn build_microvm_for_boot(
     .map_err(VmmError::VcpuStart)
     .map_err(Internal)?;
 
+    let vmm_seccomp_filter = seccomp_filters
+        .get("vmm")
+        .ok_or_else(|| MissingSeccompFilters("vmm".to_string()))?;
+    let shared_dirs_seccomp_filter = seccomp_filters
+        .get("shared_dirs")
+        .ok_or_else(|| MissingSeccompFilters("shared_dirs".to_string()))?;
+    // Like the vcpu threads, the I/O threads inherit the Landlock ruleset, and install their
+    // seccomp filter themselves.
+    vmm.io_threads = io_threads
+        .start(
+            vmm_seccomp_filter.clone(),
+            shared_dirs_seccomp_filter.clone(),
+        )
+        .map_err(IoThreads)?;
+    vmm.guest_files = vm_resources.guest_files.clone();
+    vmm.guest_freeze = vm_resources.guest_freeze.clone();
+
+    apply_thread_scheduling(&vmm, &vm_resources.vm_config).map_err(ThreadScheduling)?;
+    if let Some(cpu_bandwidth) = &vm_resources.vm_config.cpu_bandwidth {
+        apply_cpu_bandwidth(cpu_bandwidth).map_err(CpuBandwidth)?;
+    }
+
+    let vmm = Arc::new(Mutex::new(vmm));
+
+    // The stub thread is spawned before the VMM seccomp filter forbids it, and installs the
+    // filter itself.
+    #[cfg(target_arch = "x86_64")]
+    if let Some(gdb_server) = gdb_server {
+        gdb_server
+            .start(vmm.clone(), vmm_seccomp_filter.clone())
+            .map_err(Gdb)?;
+    }
+
+    // The detector timer is created before the VMM seccomp filter forbids it.
+    if let Some(config) = &vm_resources.stall_detection {
+        let detector = StallDetector::new(vmm.clone(), config.clone()).map_err(StallDetection)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(detector)));
+    }
+    if let Some(config) = &vm_resources.working_set {
+        let sampler = WorkingSetSampler::new(vmm.clone(), config).map_err(WorkingSet)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(sampler)));
+    }
+    // The PSI trigger is registered before the VMM seccomp filter forbids it.
+    if let Some(config) = &vm_resources.memory_pressure {
+        let monitor =
+            MemoryPressureMonitor::new(vmm.clone(), config.clone()).map_err(MemoryPressure)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(monitor)));
+    }
+
     // Load seccomp filters for the VMM thread.
     // Execution panics if filters cannot be loaded, use --no-seccomp if skipping filters
     // altogether is the desired behaviour.
     // Keep this as the last step before resuming vcpus.
-    seccompiler::apply_filter(
-        seccomp_filters
-            .get("vmm")
-            .ok_or_else(|| MissingSeccompFilters("vmm".to_string()))?,
-    )
-    .map_err(VmmError::SeccompFilters)
-    .map_err(Internal)?;
+    crate::seccomp_filters::install_filter("vmm", vmm_seccomp_filter)
+        .map_err(VmmError::SeccompFilters)
+        .map_err(Internal)?;
 
-    let vmm = Arc::new(Mutex::new(vmm));
     event_manager.add_subscriber(vmm.clone());
 
     Ok(vmm)
@@ -369,6 +801,11 @@ pub fn build_and_boot_microvm(
 ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
     let vmm = build_microvm_for_boot(instance_info, vm_resources, event_manager, seccomp_filters)?;
 
+    // With a GDB stub, the microVM waits for the debugger to let it run.
+    if vm_resources.gdb.is_some() {
+        return Ok(vmm);
+    }
+
     // The vcpus start off in the `Paused` state, let them run.
     vmm.lock()
         .unwrap()
@@ -384,9 +821,9 @@ pub enum BuildMicrovmFromSnapshotError {
     /// Failed to create microVM and vCPUs.
     #[error("Failed to create microVM and vCPUs: {0}")]
     CreateMicrovmAndVcpus(#[from] StartMicrovmError),
-    /// Only 255 vCPU state are supported, but {0} states where given.
-    #[error("Only 255 vCPU state are supported, but {0} states where given.")]
-    TooManyVCPUs(usize),
+    /// The snapshot holds more vCPU states than supported.
+    #[error("Only {1} vCPU states are supported, but {0} states were given.")]
+    TooManyVCPUs(usize, u16),
     /// Could not access KVM.
     #[error("Could not access KVM: {0}")]
     KvmAccess(#[from] utils::errno::Error),
@@ -428,36 +865,109 @@ pub enum BuildMicrovmFromSnapshotError {
     /// Failed to apply VMM secccomp filter.
     #[error("Failed to apply VMM secccomp filter: {0}")]
     SeccompFiltersInternal(#[from] seccompiler::InstallationError),
+    /// Failed to enforce the Landlock ruleset.
+    #[error("Failed to enforce the Landlock ruleset: {0}")]
+    Landlock(#[from] LandlockError),
+    /// Failed to apply the scheduling settings of the vCPU, VMM or I/O threads.
+    #[error("Failed to apply the thread scheduling settings: {0}")]
+    ThreadScheduling(io::Error),
+    /// Failed to start the I/O threads.
+    #[error("Failed to start the I/O threads: {0}")]
+    IoThreads(IoThreadError),
+    /// Failed to apply the CPU bandwidth of the microVM.
+    #[error("Failed to apply the CPU bandwidth: {0}")]
+    CpuBandwidth(CpuBandwidthError),
+    /// Failed to create the vCPU stall detector.
+    #[error("Failed to create the vCPU stall detector: {0}")]
+    StallDetection(io::Error),
+    /// Failed to create the working set sampler.
+    #[error("Failed to create the working set sampler: {0}")]
+    WorkingSet(io::Error),
+    /// Failed to lock the guest memory in host memory.
+    #[error("Failed to lock the guest memory: {0}")]
+    MemLock(MemLockError),
+    /// Failed to write the SMBIOS tables with the new UUID.
+    #[cfg(target_arch = "x86_64")]
+    #[error("Failed to write the SMBIOS tables: {0:?}")]
+    SmbiosSetup(crate::arch::x86_64::smbios::SmbiosError),
+    /// The number of vCPUs to run is not between 1 and the number of vCPUs in the snapshot.
+    #[error("Cannot run {0} vCPUs out of the {1} vCPUs of the snapshot.")]
+    InvalidVcpuCount(u16, u16),
+    /// A vCPU left out of the running ones was not taken offline by the guest.
+    #[error("vCPU {0} cannot be parked, the guest did not take it offline.")]
+    OnlineVcpu(usize),
+    /// Failed to make the taps of the network interfaces non-persistent.
+    #[error("Failed to make the taps removable on exit: {0}")]
+    RemoveTaps(TapError),
+    /// Failed to create the host memory pressure monitor.
+    #[error("Failed to create the memory pressure monitor: {0}")]
+    MemoryPressure(MemoryPressureError),
 }
 
 /// Builds and starts a microVM based on the provided MicrovmState.
 ///
 /// An `Arc` reference of the built `Vmm` is also plugged in the `EventManager`, while another
 /// is returned.
+///
+/// When `online_vcpus` is set, only that many vcpus are run. The others are restored but stay
+/// parked, and must have been taken offline by the guest before the snapshot was created.
 #[allow(clippy::too_many_arguments)]
 pub fn build_microvm_from_snapshot(
     instance_info: &InstanceInfo,
     event_manager: &mut EventManager,
-    microvm_state: MicrovmState,
+    mut microvm_state: MicrovmState,
     guest_memory: GuestMemoryMmap,
     uffd: Option<Uffd>,
     track_dirty_pages: bool,
+    online_vcpus: Option<u16>,
     seccomp_filters: &BpfThreadMap,
     vm_resources: &mut VmResources,
 ) -> Result<Arc<Mutex<Vmm>>, BuildMicrovmFromSnapshotError> {
-    let vcpu_count = u8::try_from(microvm_state.vcpu_states.len()).map_err(|_| {
-        BuildMicrovmFromSnapshotError::TooManyVCPUs(microvm_state.vcpu_states.len())
-    })?;
+    let vcpu_count = u16::try_from(microvm_state.vcpu_states.len())
+        .ok()
+        .filter(|&vcpu_count| vcpu_count <= MAX_SUPPORTED_VCPUS)
+        .ok_or(BuildMicrovmFromSnapshotError::TooManyVCPUs(
+            microvm_state.vcpu_states.len(),
+            MAX_SUPPORTED_VCPUS,
+        ))?;
+    let online_vcpus = online_vcpus.unwrap_or(vcpu_count);
+    if online_vcpus == 0 || online_vcpus > vcpu_count {
+        return Err(BuildMicrovmFromSnapshotError::InvalidVcpuCount(
+            online_vcpus,
+            vcpu_count,
+        ));
+    }
+    let online_vcpus = usize::from(online_vcpus);
+    if let Some(index) = microvm_state.vcpu_states[online_vcpus..]
+        .iter()
+        .position(|state| !state.is_offline())
+    {
+        return Err(BuildMicrovmFromSnapshotError::OnlineVcpu(
+            online_vcpus + index,
+        ));
+    }
 
-    // Build Vmm.
+    // The ITS is only created when the snapshot holds its state, so that snapshots of microVMs
+    // without one can be restored on hosts which do not support it.
+    #[cfg(target_arch = "aarch64")]
+    let gic_its = microvm_state.vm_state.gic.its.is_some();
+    #[cfg(target_arch = "x86_64")]
+    let gic_its = false;
+
+    // Build Vmm. The working set is sampled from the KVM dirty log.
     let (mut vmm, mut vcpus) = create_vmm_and_vcpus(
         instance_info,
         event_manager,
         guest_memory.clone(),
         uffd,
-        track_dirty_pages,
+        track_dirty_pages || vm_resources.working_set.is_some(),
         vcpu_count,
         microvm_state.vm_state.kvm_cap_modifiers.clone(),
+        None,
+        microvm_state.vm_info.acpi,
+        false,
+        gic_its,
+        &microvm_state.vm_info.mmio_layout.unwrap_or_default(),
     )?;
 
     #[cfg(target_arch = "x86_64")]
@@ -502,22 +1012,64 @@ pub fn build_microvm_from_snapshot(
     #[cfg(target_arch = "x86_64")]
     vmm.vm.restore_state(&microvm_state.vm_state)?;
 
+    // The parked vcpus keep existing in KVM, but no thread runs them. Their states are saved
+    // again in the snapshots of this microVM.
+    vcpus.truncate(online_vcpus);
+    vmm.parked_vcpu_states = microvm_state.vcpu_states.split_off(online_vcpus);
+
     vm_resources.update_vm_config(&MachineConfigUpdate {
         vcpu_count: Some(vcpu_count),
         mem_size_mib: Some(microvm_state.vm_info.mem_size_mib as usize),
         smt: Some(microvm_state.vm_info.smt),
         cpu_template: Some(microvm_state.vm_info.cpu_template),
         track_dirty_pages: Some(track_dirty_pages),
+        acpi: Some(microvm_state.vm_info.acpi),
+        split_irqchip: None,
+        gic_its: Some(gic_its),
+        vcpu_scheduling: None,
+        io_scheduling: None,
+        cpu_bandwidth: None,
+        mmio_layout: microvm_state.vm_info.mmio_layout,
+        mem_backing_path: None,
+        mem_lock: microvm_state.vm_info.mem_lock,
     })?;
 
     // Restore the boot source config paths.
     vm_resources.set_boot_source_config(microvm_state.vm_info.boot_source);
+    vm_resources.smbios = microvm_state.vm_info.smbios;
+    // The SMBIOS tables already in guest memory are overwritten when the identity of the restored
+    // microVM has another UUID. The device tree of aarch64 guests is not updated, as the guest
+    // may have reclaimed its memory.
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources
+        .identity
+        .as_ref()
+        .map(|identity| &identity.uuid)
+        != microvm_state
+            .vm_info
+            .identity
+            .as_ref()
+            .map(|identity| &identity.uuid)
+    {
+        setup_smbios(
+            vmm.guest_memory(),
+            vm_resources.smbios.as_ref(),
+            vm_resources.identity.as_ref(),
+        )
+        .map_err(BuildMicrovmFromSnapshotError::SmbiosSetup)?;
+    }
+    // The guest does not signal it is ready again after a restore.
+    vmm.guest_ready
+        .store(microvm_state.vm_info.guest_ready, Ordering::Relaxed);
 
     // Restore devices states.
+    let mut io_threads = IoThreadsBuilder::new(vm_resources.io_threads.as_ref())
+        .map_err(BuildMicrovmFromSnapshotError::IoThreads)?;
     let mmio_ctor_args = MMIODevManagerConstructorArgs {
         mem: guest_memory,
         vm: vmm.vm.fd(),
         event_manager,
+        io_threads: &mut io_threads,
         for_each_restored_device: VmResources::update_from_restored_device,
         vm_resources,
         instance_id: &instance_info.id,
@@ -527,6 +1079,25 @@ pub fn build_microvm_from_snapshot(
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
             .map_err(MicrovmStateError::RestoreDevices)?;
     vmm.emulate_serial_init()?;
+    // The devices are restored first, as they tell whether the guest memory can be reclaimed.
+    lock_vmm_memory(
+        &mut vmm,
+        vm_resources,
+        microvm_state.vm_info.hot_memory.as_deref(),
+    )
+    .map_err(BuildMicrovmFromSnapshotError::MemLock)?;
+
+    if let Some(shutdown_hooks) = &vm_resources.shutdown_hooks {
+        crate::shutdown_hooks::install(shutdown_hooks);
+        if shutdown_hooks.remove_taps {
+            make_taps_transient(&vmm).map_err(BuildMicrovmFromSnapshotError::RemoveTaps)?;
+        }
+    }
+
+    // Enforce the Landlock ruleset before spawning the vcpu threads, so that they inherit it.
+    if let Some(landlock_config) = &vm_resources.landlock {
+        apply_landlock_ruleset(&vmm, landlock_config)?;
+    }
 
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
     vmm.start_vcpus(
@@ -537,12 +1108,51 @@ pub fn build_microvm_from_snapshot(
             .clone(),
     )?;
 
+    // Like the vcpu threads, the I/O threads inherit the Landlock ruleset, and install their
+    // seccomp filter themselves. MicroVMs with shared directories cannot be snapshotted, so there
+    // is no thread serving them.
+    vmm.io_threads = io_threads
+        .start(
+            seccomp_filters
+                .get("vmm")
+                .ok_or(BuildMicrovmFromSnapshotError::MissingVmmSeccompFilters)?
+                .clone(),
+            Arc::default(),
+        )
+        .map_err(BuildMicrovmFromSnapshotError::IoThreads)?;
+    vmm.guest_files = vm_resources.guest_files.clone();
+    vmm.guest_freeze = vm_resources.guest_freeze.clone();
+
+    apply_thread_scheduling(&vmm, &vm_resources.vm_config)
+        .map_err(BuildMicrovmFromSnapshotError::ThreadScheduling)?;
+    if let Some(cpu_bandwidth) = &vm_resources.vm_config.cpu_bandwidth {
+        apply_cpu_bandwidth(cpu_bandwidth).map_err(BuildMicrovmFromSnapshotError::CpuBandwidth)?;
+    }
+
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager.add_subscriber(vmm.clone());
 
+    // The detector timer is created before the VMM seccomp filter forbids it.
+    if let Some(config) = &vm_resources.stall_detection {
+        let detector = StallDetector::new(vmm.clone(), config.clone())
+            .map_err(BuildMicrovmFromSnapshotError::StallDetection)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(detector)));
+    }
+    if let Some(config) = &vm_resources.working_set {
+        let sampler = WorkingSetSampler::new(vmm.clone(), config)
+            .map_err(BuildMicrovmFromSnapshotError::WorkingSet)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(sampler)));
+    }
+    if let Some(config) = &vm_resources.memory_pressure {
+        let monitor = MemoryPressureMonitor::new(vmm.clone(), config.clone())
+            .map_err(BuildMicrovmFromSnapshotError::MemoryPressure)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(monitor)));
+    }
+
     // Load seccomp filters for the VMM thread.
     // Keep this as the last step of the building process.
-    seccompiler::apply_filter(
+    crate::seccomp_filters::install_filter(
+        "vmm",
         seccomp_filters
             .get("vmm")
             .ok_or(BuildMicrovmFromSnapshotError::MissingVmmSeccompFilters)?,
@@ -551,35 +1161,206 @@ pub fn build_microvm_from_snapshot(
     Ok(vmm)
 }
 
-/// Creates GuestMemory of `mem_size_mib` MiB in size.
+// Restricts the filesystem access of the VMM thread, and of the threads it spawns afterwards, to
+// the files backing the block devices, to the shared directories and to the paths allowed by the
+// Landlock configuration. Files which are already open, like the guest memory file, the tap
+// devices or the sockets, stay usable.
+fn apply_landlock_ruleset(vmm: &Vmm, config: &LandlockConfig) -> Result<(), LandlockError> {
+    let mut ruleset = config.ruleset();
+    vmm.mmio_device_manager
+        .for_each_virtio_device(|virtio_type, _, _, device| {
+            if virtio_type == TYPE_BLOCK {
+                let locked_device = device.lock().expect("Poisoned lock");
+                let block = locked_device.as_any().downcast_ref::<Block>().unwrap();
+                ruleset.allow(block.file_path(), block.is_read_only());
+            } else if virtio_type == TYPE_9P {
+                let locked_device = device.lock().expect("Poisoned lock");
+                let p9 = locked_device.as_any().downcast_ref::<P9>().unwrap();
+                ruleset.allow_tree(p9.shared_dir(), p9.is_read_only());
+            }
+            Ok::<(), ()>(())
+        })
+        .expect("Unexpected error while iterating over the virtio devices");
+    ruleset.restrict_self()
+}
+
+// Makes the taps backing the network interfaces non-persistent, so that the host kernel removes
+// them once the last file descriptor attached to them is closed. Firecracker holds one until it
+// exits, for whatever reason, even when it is killed.
+fn make_taps_transient(vmm: &Vmm) -> Result<(), TapError> {
+    vmm.mmio_device_manager
+        .for_each_virtio_device(|virtio_type, _, _, device| {
+            if virtio_type != TYPE_NET {
+                return Ok(());
+            }
+            let locked_device = device.lock().expect("Poisoned lock");
+            let net = locked_device.as_any().downcast_ref::<Net>().unwrap();
+            // The user-mode network stack is not backed by a tap.
+            if net.user_net().is_some() {
+                return Ok(());
+            }
+            net.tap.set_persist(false)
+        })
+}
+
+// Applies the configured scheduling settings to the vcpu threads, which must have been started
+// already, and to the calling VMM thread. The API thread keeps the settings Firecracker was
+// started with.
+fn apply_thread_scheduling(vmm: &Vmm, vm_config: &VmConfig) -> Result<(), io::Error> {
+    if let Some(vcpu_scheduling) = &vm_config.vcpu_scheduling {
+        for handle in vmm.vcpus_handles.iter() {
+            vcpu_scheduling.apply(handle.tid())?;
+        }
+    }
+    if let Some(io_scheduling) = &vm_config.io_scheduling {
+        io_scheduling.apply(0)?;
+        for tid in vmm.io_threads.tids() {
+            io_scheduling.apply(*tid)?;
+        }
+    }
+    Ok(())
+}
+
+// Computes the guest physical range reserved for the memory hotplug device, if configured.
+#[cfg(target_arch = "aarch64")]
+fn memory_hotplug_region(
+    vm_resources: &VmResources,
+) -> Result<Option<(GuestAddress, usize)>, StartMicrovmError> {
+    vm_resources
+        .memory_hotplug
+        .as_ref()
+        .map(|config| {
+            crate::arch::aarch64::hotplug_memory_region(
+                vm_resources.vm_config.mem_size_mib << 20,
+                config.total_size_mib << 20,
+            )
+            .ok_or(StartMicrovmError::MemoryHotplugRegion)
+        })
+        .transpose()
+}
+
+// Memory hotplug is not supported on x86_64, so there is no region to reserve.
+#[cfg(target_arch = "x86_64")]
+fn memory_hotplug_region(
+    _vm_resources: &VmResources,
+) -> Result<Option<(GuestAddress, usize)>, StartMicrovmError> {
+    Ok(None)
+}
+
+/// Locks the guest memory of `vmm` in host memory when `vm_resources` requests it. The hot
+/// memory of snapshots is locked upfront.
+fn lock_vmm_memory(
+    vmm: &mut Vmm,
+    vm_resources: &VmResources,
+    hot_memory: Option<&[MemoryRange]>,
+) -> Result<(), MemLockError> {
+    let Some(mem_lock) = vm_resources.vm_config.mem_lock else {
+        return Ok(());
+    };
+    // Discarding guest pages fails on locked memory.
+    if vm_resources.balloon.get().is_some() || vm_resources.memory_hotplug.is_some() {
+        return Err(MemLockError::ReclaimableMemory);
+    }
+    lock_guest_memory(vmm.guest_memory(), mem_lock, hot_memory)?;
+    vmm.mem_lock = Some(mem_lock);
+    Ok(())
+}
+
+/// Opens the memfd at `path` to back the guest memory, checking that it is `mem_size` bytes
+/// large and that it cannot be resized, which would leave the guest memory without backing.
+fn open_mem_backing(path: &str, mem_size: usize) -> Result<File, MemBackingError> {
+    let file = open_file(path, true).map_err(MemBackingError::Open)?;
+
+    // SAFETY: `fcntl` does not access memory and the descriptor is valid.
+    let seals = SyscallReturnCode(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) })
+        .into_result()
+        .map_err(MemBackingError::GetSeals)?;
+    let required_seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+    let forbidden_seals = libc::F_SEAL_WRITE | libc::F_SEAL_FUTURE_WRITE;
+    if seals & required_seals != required_seals || seals & forbidden_seals != 0 {
+        return Err(MemBackingError::InvalidSeals(seals));
+    }
+
+    let size = file.metadata().map_err(MemBackingError::Open)?.len();
+    if size != mem_size as u64 {
+        return Err(MemBackingError::InvalidSize(size, mem_size as u64));
+    }
+    Ok(file)
+}
+
+/// Creates GuestMemory of `mem_size_mib` MiB in size, laid out around the MMIO region of
+/// `mmio_layout` and followed by the optional memory hotplug region.
+///
+/// The memory is anonymous, unless `mem_backing` is set, in which case it is a shared mapping of
+/// that memfd. The hotplug region is always anonymous.
+#[cfg_attr(target_arch = "aarch64", allow(unused_variables))]
 pub fn create_guest_memory(
     mem_size_mib: usize,
+    mmio_layout: &MmioLayout,
+    hotplug_region: Option<(GuestAddress, usize)>,
+    mem_backing: Option<File>,
     track_dirty_pages: bool,
 ) -> Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
+    #[cfg(target_arch = "x86_64")]
+    let arch_mem_regions =
+        crate::arch::x86_64::arch_memory_regions_with_gap(mem_size, mmio_layout.mmio_region().0);
+    // The MMIO region is below the memory on aarch64, so the memory layout does not depend on it.
+    #[cfg(target_arch = "aarch64")]
     let arch_mem_regions = crate::arch::arch_memory_regions(mem_size);
+    // The regions are laid out one after the other in the memfd.
+    let mem_backing = mem_backing.map(Arc::new);
+    let mut backing_offset = 0;
+    let mut regions = arch_mem_regions
+        .into_iter()
+        .map(|(addr, size)| {
+            let file_offset = mem_backing.as_ref().map(|file| {
+                let file_offset = FileOffset::from_arc(Arc::clone(file), backing_offset);
+                backing_offset += size as u64;
+                file_offset
+            });
+            (file_offset, addr, size)
+        })
+        .collect::<Vec<_>>();
+    // The hotplug region is mapped upfront, but it is not backed by host memory until the guest
+    // plugs and touches it.
+    regions.extend(hotplug_region.map(|(addr, size)| (None, addr, size)));
+    // The vCPUs cannot address the memory past their physical address width.
+    #[cfg(target_arch = "x86_64")]
+    {
+        let phys_addr_bits = crate::arch::x86_64::guest_phys_addr_bits();
+        let mem_end = regions
+            .last()
+            .map_or(0, |(_, addr, size)| addr.raw_value() + *size as u64);
+        if mem_end > 1 << phys_addr_bits {
+            return Err(StartMicrovmError::GuestMemoryTooLarge(phys_addr_bits));
+        }
+    }
 
-    utils::vm_memory::create_guest_memory(
-        &arch_mem_regions
-            .iter()
-            .map(|(addr, size)| (None, *addr, *size))
-            .collect::<Vec<_>>()[..],
-        track_dirty_pages,
-    )
-    .map_err(StartMicrovmError::GuestMemoryMmap)
+    utils::vm_memory::create_shared_guest_memory(&regions, track_dirty_pages)
+        .map_err(StartMicrovmError::GuestMemoryMmap)
 }
 
+/// Loads the kernel, or the PVH firmware image, and returns its entry point along with the guest
+/// memory range it occupies.
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
-) -> Result<GuestAddress, StartMicrovmError> {
+) -> Result<(EntryPoint, (GuestAddress, usize)), StartMicrovmError> {
+    #[cfg(target_arch = "x86_64")]
+    if let Some(pvh_firmware_file) = boot_config.pvh_firmware_file.as_ref() {
+        return load_pvh_firmware(pvh_firmware_file, guest_memory);
+    }
+
     let mut kernel_file = boot_config
         .kernel_file
+        .as_ref()
+        .ok_or(StartMicrovmError::MissingKernelConfig)?
         .try_clone()
         .map_err(|err| StartMicrovmError::Internal(VmmError::KernelFile(err)))?;
 
     #[cfg(target_arch = "x86_64")]
-    let entry_addr = Loader::load::<std::fs::File, GuestMemoryMmap>(
+    let kernel_load_result = Loader::load::<std::fs::File, GuestMemoryMmap>(
         guest_memory,
         None,
         &mut kernel_file,
@@ -587,8 +1368,24 @@ fn load_kernel(
     )
     .map_err(StartMicrovmError::KernelLoader)?;
 
+    // The PVH entry point is only used when requested, since the kernels which advertise one
+    // also support the Linux boot protocol, which existing setups keep booting them through.
+    #[cfg(target_arch = "x86_64")]
+    if boot_config.pvh_boot {
+        return match kernel_load_result.pvh_boot_cap {
+            PvhBootCapability::PvhEntryPresent(pvh_entry_addr) => Ok((
+                EntryPoint {
+                    entry_addr: pvh_entry_addr,
+                    protocol: BootProtocol::PvhBoot,
+                },
+                image_region(&kernel_load_result),
+            )),
+            _ => Err(StartMicrovmError::KernelMissingPvhEntry),
+        };
+    }
+
     #[cfg(target_arch = "aarch64")]
-    let entry_addr = Loader::load::<std::fs::File, GuestMemoryMmap>(
+    let kernel_load_result = Loader::load::<std::fs::File, GuestMemoryMmap>(
         guest_memory,
         Some(GuestAddress(crate::arch::get_kernel_start())),
         &mut kernel_file,
@@ -596,7 +1393,54 @@ fn load_kernel(
     )
     .map_err(StartMicrovmError::KernelLoader)?;
 
-    Ok(entry_addr.kernel_load)
+    Ok((
+        EntryPoint {
+            entry_addr: kernel_load_result.kernel_load,
+            protocol: BootProtocol::LinuxBoot,
+        },
+        image_region(&kernel_load_result),
+    ))
+}
+
+// Guest memory range in which an image was loaded.
+fn image_region(load_result: &KernelLoaderResult) -> (GuestAddress, usize) {
+    let size = load_result.kernel_end - load_result.kernel_load.raw_value();
+    (load_result.kernel_load, size as usize)
+}
+
+/// Loads a PVH firmware image and returns its PVH entry point, along with the guest memory range
+/// it occupies.
+///
+/// PVH firmware images are loaded the same way as an ELF kernel, but are only bootable through
+/// the PVH entry point since they are responsible for setting up the rest of the boot flow. Raw
+/// UEFI images, which start executing from the reset vector, are not supported.
+#[cfg(target_arch = "x86_64")]
+fn load_pvh_firmware(
+    pvh_firmware_file: &std::fs::File,
+    guest_memory: &GuestMemoryMmap,
+) -> Result<(EntryPoint, (GuestAddress, usize)), StartMicrovmError> {
+    let mut pvh_firmware_file = pvh_firmware_file
+        .try_clone()
+        .map_err(|err| StartMicrovmError::Internal(VmmError::KernelFile(err)))?;
+
+    let pvh_firmware_load_result = Loader::load::<std::fs::File, GuestMemoryMmap>(
+        guest_memory,
+        None,
+        &mut pvh_firmware_file,
+        Some(GuestAddress(crate::arch::get_kernel_start())),
+    )
+    .map_err(StartMicrovmError::PvhFirmwareLoader)?;
+
+    match pvh_firmware_load_result.pvh_boot_cap {
+        PvhBootCapability::PvhEntryPresent(entry_addr) => Ok((
+            EntryPoint {
+                entry_addr,
+                protocol: BootProtocol::PvhBoot,
+            },
+            image_region(&pvh_firmware_load_result),
+        )),
+        _ => Err(StartMicrovmError::PvhFirmwareMissingEntry),
+    }
 }
 
 fn load_initrd_from_config(
@@ -605,56 +1449,83 @@ fn load_initrd_from_config(
 ) -> Result<Option<InitrdConfig>, StartMicrovmError> {
     use self::StartMicrovmError::InitrdRead;
 
-    Ok(match &boot_cfg.initrd_file {
-        Some(f) => Some(load_initrd(
-            vm_memory,
-            &mut f.try_clone().map_err(InitrdRead)?,
-        )?),
-        None => None,
-    })
+    if boot_cfg.initrd_files.is_empty() {
+        return Ok(None);
+    }
+
+    let mut images = boot_cfg
+        .initrd_files
+        .iter()
+        .map(|f| f.try_clone())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(InitrdRead)?;
+    Ok(Some(load_initrd(vm_memory, &mut images)?))
 }
 
-/// Loads the initrd from a file into the given memory slice.
+/// Loads the initrd images from files into the given memory slice, one after the other.
 ///
 /// * `vm_memory` - The guest memory the initrd is written to.
-/// * `image` - The initrd image.
+/// * `images` - The initrd images.
+///
+/// Every image but the last one is followed by zero padding so that the next one starts 4-byte
+/// aligned, as the kernel expects when unpacking concatenated archives.
 ///
 /// Returns the result of initrd loading
 fn load_initrd<F: Debug>(
     vm_memory: &GuestMemoryMmap,
-    image: &mut F,
+    images: &mut [F],
 ) -> Result<InitrdConfig, StartMicrovmError>
 where
     F: ReadVolatile + Seek,
 {
     use self::StartMicrovmError::{InitrdLoad, InitrdRead};
 
-    let size: usize;
-    // Get the image size
-    match image.seek(SeekFrom::End(0)) {
-        Err(err) => return Err(InitrdRead(err)),
-        Ok(0) => {
-            return Err(InitrdRead(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Initrd image seek returned a size of zero",
-            )))
-        }
-        Ok(s) => size = s as usize,
-    };
-    // Go back to the image start
-    image.seek(SeekFrom::Start(0)).map_err(InitrdRead)?;
+    // Get the image sizes and the offset of each image in the loaded initrd.
+    let mut layout = Vec::with_capacity(images.len());
+    let mut size: usize = 0;
+    for image in images.iter_mut() {
+        let image_size = match image.seek(SeekFrom::End(0)) {
+            Err(err) => return Err(InitrdRead(err)),
+            Ok(0) => {
+                return Err(InitrdRead(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Initrd image seek returned a size of zero",
+                )))
+            }
+            Ok(s) => s as usize,
+        };
+        // Go back to the image start
+        image.seek(SeekFrom::Start(0)).map_err(InitrdRead)?;
+
+        let offset = (size + INITRD_ALIGNMENT - 1) & !(INITRD_ALIGNMENT - 1);
+        layout.push((offset, image_size));
+        size = offset + image_size;
+    }
 
     // Get the target address
     let address = crate::arch::initrd_load_addr(vm_memory, size).map_err(|_| InitrdLoad)?;
 
-    // Load the image into memory
-    let mut slice = vm_memory
+    // Load the images into memory
+    let slice = vm_memory
         .get_slice(GuestAddress(address), size)
         .map_err(|_| InitrdLoad)?;
 
-    image
-        .read_exact_volatile(&mut slice)
-        .map_err(|_| InitrdLoad)?;
+    let mut end = 0;
+    for (image, (offset, image_size)) in images.iter_mut().zip(layout) {
+        // The guest memory may not be zeroed when it is backed by a file or a memfd.
+        if offset > end {
+            slice
+                .subslice(end, offset - end)
+                .map_err(|_| InitrdLoad)?
+                .copy_from(&[0u8; INITRD_ALIGNMENT]);
+        }
+        end = offset + image_size;
+
+        let mut image_slice = slice.subslice(offset, image_size).map_err(|_| InitrdLoad)?;
+        image
+            .read_exact_volatile(&mut image_slice)
+            .map_err(|_| InitrdLoad)?;
+    }
 
     Ok(InitrdConfig {
         address: GuestAddress(address),
@@ -672,8 +1543,12 @@ pub fn setup_interrupt_controller(vm: &mut Vm) -> Result<(), StartMicrovmError>
 
 /// Sets up the irqchip for a aarch64 microVM.
 #[cfg(target_arch = "aarch64")]
-pub fn setup_interrupt_controller(vm: &mut Vm, vcpu_count: u8) -> Result<(), StartMicrovmError> {
-    vm.setup_irqchip(vcpu_count)
+pub fn setup_interrupt_controller(
+    vm: &mut Vm,
+    vcpu_count: u16,
+    its: bool,
+) -> Result<(), StartMicrovmError> {
+    vm.setup_irqchip(vcpu_count, its)
         .map_err(VmmError::Vm)
         .map_err(StartMicrovmError::Internal)
 }
@@ -706,6 +1581,7 @@ fn attach_legacy_devices_aarch64(
     event_manager: &mut EventManager,
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
+    rtc_time: Option<u32>,
 ) -> Result<(), VmmError> {
     // Serial device setup.
     let cmdline_contains_console = cmdline
@@ -727,13 +1603,64 @@ fn attach_legacy_devices_aarch64(
             .map_err(VmmError::RegisterMMIODevice)?;
     }
 
-    let rtc = RTCDevice(Rtc::with_events(&logger::METRICS.rtc));
+    let mut rtc = RTCDevice(Rtc::with_events(&logger::METRICS.rtc));
+    if let Some(time_s) = rtc_time {
+        rtc.set_time(time_s);
+    }
     vmm.mmio_device_manager
         .register_mmio_rtc(rtc, None)
         .map_err(VmmError::RegisterMMIODevice)
 }
 
-fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> Result<Vec<Vcpu>, VmmError> {
+/// Attaches a recorder to each virtio queue selected in `config`.
+fn attach_virtio_recorders(
+    vmm: &Vmm,
+    config: &VirtioRecordConfig,
+) -> Result<(), StartMicrovmError> {
+    let mem_regions: Vec<(u64, u64)> = vmm
+        .guest_memory()
+        .iter()
+        .map(|region| (region.start_addr().raw_value(), region.len()))
+        .collect();
+
+    for queue_config in config.queues.iter() {
+        let mut attached = false;
+        vmm.mmio_device_manager
+            .for_each_virtio_device(|device_type, device_id, _, device| {
+                if attached || *device_id != queue_config.device_id {
+                    return Ok(());
+                }
+                let mut device = device.lock().expect("Poisoned lock");
+                let Some(queue) = device
+                    .queues_mut()
+                    .get_mut(usize::from(queue_config.queue_index))
+                else {
+                    return Ok(());
+                };
+                let header = RecordHeader {
+                    device_type,
+                    device_id: device_id.clone(),
+                    queue_index: queue_config.queue_index,
+                    mem_regions: mem_regions.clone(),
+                };
+                let recorder = QueueRecorder::new(queue_config, &header)
+                    .map_err(StartMicrovmError::VirtioRecord)?;
+                queue.set_recorder(SharedRecorder::new(recorder));
+                attached = true;
+                Ok(())
+            })?;
+
+        if !attached {
+            return Err(StartMicrovmError::VirtioRecordQueue(
+                queue_config.device_id.clone(),
+                queue_config.queue_index,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn create_vcpus(vm: &Vm, vcpu_count: u16, exit_evt: &EventFd) -> Result<Vec<Vcpu>, VmmError> {
     let mut vcpus = Vec::with_capacity(vcpu_count as usize);
     for cpu_idx in 0..vcpu_count {
         let exit_evt = exit_evt.try_clone().map_err(VmmError::EventFd)?;
@@ -748,9 +1675,9 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> Result<Vec<Vcpu>
 pub fn configure_system_for_boot(
     vmm: &Vmm,
     vcpus: &mut [Vcpu],
-    vm_config: &VmConfig,
+    vm_resources: &VmResources,
     cpu_template: &CustomCpuTemplate,
-    entry_addr: GuestAddress,
+    entry_point: EntryPoint,
     initrd: &Option<InitrdConfig>,
     boot_cmdline: LoaderKernelCmdline,
 ) -> Result<(), StartMicrovmError> {
@@ -792,15 +1719,19 @@ pub fn configure_system_for_boot(
     let cpu_config = CpuConfiguration::apply_template(cpu_config, cpu_template)?;
 
     let vcpu_config = VcpuConfig {
-        vcpu_count: vm_config.vcpu_count,
-        smt: vm_config.smt,
+        vcpu_count: vm_resources.vm_config.vcpu_count,
+        smt: vm_resources.vm_config.smt,
         cpu_config,
     };
 
     // Configure vCPUs with normalizing and setting the generated CPU configuration.
+    #[cfg(target_arch = "x86_64")]
+    let kernel_entry = entry_point;
+    #[cfg(target_arch = "aarch64")]
+    let kernel_entry = entry_point.entry_addr;
     for vcpu in vcpus.iter_mut() {
         vcpu.kvm_vcpu
-            .configure(vmm.guest_memory(), entry_addr, &vcpu_config)
+            .configure(vmm.guest_memory(), kernel_entry, &vcpu_config)
             .map_err(VmmError::VcpuConfigure)
             .map_err(Internal)?;
     }
@@ -824,9 +1755,28 @@ pub fn configure_system_for_boot(
             utils::vm_memory::GuestAddress(crate::arch::x86_64::layout::CMDLINE_START),
             cmdline_size,
             initrd,
-            vcpus.len() as u8,
+            vcpus.len() as u16,
+            entry_point.protocol,
         )
         .map_err(ConfigureSystem)?;
+        if vm_resources.vm_config.acpi {
+            crate::arch::x86_64::acpi::setup_acpi(
+                &vmm.guest_memory,
+                vcpus.len() as u16,
+                vmm.mmio_device_manager.get_device_info(),
+            )
+            .map_err(crate::arch::ConfigurationError::AcpiSetup)
+            .map_err(ConfigureSystem)?;
+        }
+        if vm_resources.smbios.is_some() || vm_resources.identity.is_some() {
+            setup_smbios(
+                &vmm.guest_memory,
+                vm_resources.smbios.as_ref(),
+                vm_resources.identity.as_ref(),
+            )
+            .map_err(crate::arch::ConfigurationError::SmbiosSetup)
+            .map_err(ConfigureSystem)?;
+        }
     }
     #[cfg(target_arch = "aarch64")]
     {
@@ -842,6 +1792,10 @@ pub fn configure_system_for_boot(
             vmm.mmio_device_manager.get_device_info(),
             vmm.vm.get_irqchip(),
             initrd,
+            vm_resources
+                .identity
+                .as_ref()
+                .and_then(|identity| identity.uuid.as_deref()),
         )
         .map_err(ConfigureSystem)?;
     }
@@ -856,9 +1810,31 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
     device: Arc<Mutex<T>>,
     cmdline: &mut LoaderKernelCmdline,
 ) -> Result<(), StartMicrovmError> {
-    use self::StartMicrovmError::*;
-
     event_manager.add_subscriber(device.clone());
+    register_virtio_device(vmm, id, device, cmdline)
+}
+
+/// Attaches a VirtioDevice device to the device manager, and to the event manager of the I/O
+/// thread it is assigned to, if any.
+fn attach_io_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
+    event_manager: &mut EventManager,
+    io_threads: &mut IoThreadsBuilder,
+    vmm: &mut Vmm,
+    id: String,
+    device: Arc<Mutex<T>>,
+    cmdline: &mut LoaderKernelCmdline,
+) -> Result<(), StartMicrovmError> {
+    io_threads.add_subscriber(&id, device.clone(), event_manager);
+    register_virtio_device(vmm, id, device, cmdline)
+}
+
+fn register_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
+    vmm: &mut Vmm,
+    id: String,
+    device: Arc<Mutex<T>>,
+    cmdline: &mut LoaderKernelCmdline,
+) -> Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
 
     // The device mutex mustn't be locked here otherwise it will deadlock.
     let device = MmioTransport::new(vmm.guest_memory().clone(), device);
@@ -868,13 +1844,35 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
         .map(|_| ())
 }
 
+/// Writes the SMBIOS tables identifying the microVM with the strings of `smbios` and the UUID of
+/// `identity`.
+#[cfg(target_arch = "x86_64")]
+fn setup_smbios(
+    mem: &GuestMemoryMmap,
+    smbios: Option<&SmbiosConfig>,
+    identity: Option<&IdentityConfig>,
+) -> Result<(), crate::arch::x86_64::smbios::SmbiosError> {
+    let default_smbios = SmbiosConfig::default();
+    let smbios = smbios.unwrap_or(&default_smbios);
+    crate::arch::x86_64::smbios::setup_smbios(
+        mem,
+        &crate::arch::x86_64::smbios::SystemInfo {
+            manufacturer: smbios.manufacturer.as_deref(),
+            product_name: smbios.product_name.as_deref(),
+            version: smbios.version.as_deref(),
+            serial_number: smbios.serial_number.as_deref(),
+            uuid: identity.map(IdentityConfig::uuid_bytes).unwrap_or_default(),
+        },
+    )
+}
+
 pub(crate) fn attach_boot_timer_device(
     vmm: &mut Vmm,
     request_ts: TimestampUs,
 ) -> Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
-    let boot_timer = crate::devices::pseudo::BootTimer::new(request_ts);
+    let boot_timer = crate::devices::pseudo::BootTimer::new(request_ts, vmm.guest_ready.clone());
 
     vmm.mmio_device_manager
         .register_mmio_boot_timer(boot_timer)
@@ -883,6 +1881,18 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+fn attach_tpm_device(vmm: &mut Vmm, tpm_config: &TpmConfig) -> Result<(), StartMicrovmError> {
+    // Any volatile state left by a snapshot of another microVM is discarded once loaded, and
+    // the guest resets the TPM when it boots.
+    let swtpm = Swtpm::connect(&tpm_config.socket_path, INIT_FLAG_DELETE_VOLATILE)
+        .map_err(StartMicrovmError::Tpm)?;
+    vmm.mmio_device_manager
+        .register_mmio_tpm(Tpm::new(swtpm), None)
+        .map_err(StartMicrovmError::RegisterMmioDevice)?;
+
+    Ok(())
+}
+
 fn attach_entropy_device(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -898,11 +1908,33 @@ fn attach_entropy_device(
     attach_virtio_device(event_manager, vmm, id, entropy_device.clone(), cmdline)
 }
 
+fn attach_memory_hotplug_device(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    region: (GuestAddress, usize),
+    block_size_mib: usize,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let (addr, size) = region;
+    let device = VirtioMem::new(addr, size as u64, (block_size_mib as u64) << 20, false)
+        .map_err(StartMicrovmError::CreateMemoryHotplugDevice)?;
+    let id = device.id().to_string();
+
+    attach_virtio_device(
+        event_manager,
+        vmm,
+        id,
+        Arc::new(Mutex::new(device)),
+        cmdline,
+    )
+}
+
 fn attach_block_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Block>>> + Debug>(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
     blocks: I,
     event_manager: &mut EventManager,
+    io_threads: &mut IoThreadsBuilder,
 ) -> Result<(), StartMicrovmError> {
     for block in blocks {
         let id = {
@@ -921,7 +1953,7 @@ fn attach_block_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Block>>> + Debug>(
             locked.id().clone()
         };
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, block.clone(), cmdline)?;
+        attach_io_virtio_device(event_manager, io_threads, vmm, id, block.clone(), cmdline)?;
     }
     Ok(())
 }
@@ -931,11 +1963,45 @@ fn attach_net_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Net>>> + Debug>(
     cmdline: &mut LoaderKernelCmdline,
     net_devices: I,
     event_manager: &mut EventManager,
+    io_threads: &mut IoThreadsBuilder,
 ) -> Result<(), StartMicrovmError> {
     for net_device in net_devices {
-        let id = net_device.lock().expect("Poisoned lock").id().clone();
+        let (id, user_net) = {
+            let net = net_device.lock().expect("Poisoned lock");
+            (net.id().clone(), net.user_net().cloned())
+        };
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline)?;
+        attach_io_virtio_device(
+            event_manager,
+            io_threads,
+            vmm,
+            id.clone(),
+            net_device.clone(),
+            cmdline,
+        )?;
+        // The user-mode network stack serving the device runs on the same event loop.
+        if let Some(user_net) = user_net {
+            io_threads.add_subscriber(&id, user_net, event_manager);
+        }
+    }
+    Ok(())
+}
+
+/// Attaches the shared directories to the device manager, and to the event manager of the thread
+/// serving them.
+fn attach_shared_dirs<'a, I: Iterator<Item = &'a Arc<Mutex<P9>>> + Debug>(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    shared_dirs: I,
+    io_threads: &mut IoThreadsBuilder,
+) -> Result<(), StartMicrovmError> {
+    for shared_dir in shared_dirs {
+        let id = shared_dir.lock().expect("Poisoned lock").id().to_string();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        io_threads
+            .add_shared_dir_subscriber(shared_dir.clone())
+            .map_err(StartMicrovmError::IoThreads)?;
+        register_virtio_device(vmm, id, shared_dir.clone(), cmdline)?;
     }
     Ok(())
 }
@@ -979,23 +2045,32 @@ pub(crate) fn set_stdout_nonblocking() {
 #[cfg(test)]
 pub mod tests {
     use std::io::Write;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::FromRawFd;
 
     use linux_loader::cmdline::Cmdline;
     use mmds::data_store::{Mmds, MmdsVersion};
     use mmds::ns::MmdsNetworkStack;
     use utils::tempfile::TempFile;
-    use utils::vm_memory::GuestMemory;
+    use utils::vm_memory::{Bytes, GuestMemory};
 
     use super::*;
     use crate::arch::DeviceType;
     use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
     use crate::devices::virtio::vsock::VSOCK_DEV_ID;
-    use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_RNG, TYPE_VSOCK};
+    use crate::devices::virtio::{
+        IO_URING_NUM_ENTRIES, TYPE_BALLOON, TYPE_BLOCK, TYPE_RNG, TYPE_VSOCK,
+    };
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType, FileEngineType};
+    use crate::vmm_config::drive::{
+        BlockBuilder, BlockDeviceConfig, CacheType, FadvisePolicy, FileEngineType,
+    };
     use crate::vmm_config::entropy::{EntropyDeviceBuilder, EntropyDeviceConfig};
+    use crate::vmm_config::instance_info::VmState;
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::shared_dir::{SharedDirBuilder, SharedDirConfig};
+    use crate::vmm_config::virtio_record::QueueRecordConfig;
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
 
@@ -1063,7 +2138,8 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, &MmioLayout::default(), None, None, false).unwrap();
 
         let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(VmmError::EventFd)
@@ -1096,7 +2172,7 @@ pub mod tests {
         {
             let exit_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
             let _vcpu = Vcpu::new(1, &vm, exit_evt).unwrap();
-            setup_interrupt_controller(&mut vm, 1).unwrap();
+            setup_interrupt_controller(&mut vm, 1, false).unwrap();
         }
 
         Vmm {
@@ -1106,8 +2182,19 @@ pub mod tests {
             vm,
             guest_memory,
             uffd: None,
+            uffd_handover: None,
             vcpus_handles: Vec::new(),
+            parked_vcpu_states: Vec::new(),
             vcpus_exit_evt,
+            dirty_log_samples: Default::default(),
+            dirty_rings: None,
+            guest_ready: Default::default(),
+            mem_lock: None,
+            io_threads: Default::default(),
+            guest_files: None,
+            guest_freeze: None,
+            #[cfg(target_arch = "x86_64")]
+            confidential: None,
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
@@ -1139,11 +2226,25 @@ pub mod tests {
                 cache_type: custom_block_cfg.cache_type,
                 rate_limiter: None,
                 file_engine_type: FileEngineType::default(),
+                direct_io: false,
+                fadvise: FadvisePolicy::default(),
+                readahead_kib: 0,
+                io_uring_entries: IO_URING_NUM_ENTRIES,
+                max_in_flight_requests: 0,
+                busy_poll_us: 0,
+                batch_size: 0,
             };
             block_dev_configs.insert(block_device_config).unwrap();
         }
 
-        attach_block_devices(vmm, cmdline, block_dev_configs.list.iter(), event_manager).unwrap();
+        attach_block_devices(
+            vmm,
+            cmdline,
+            block_dev_configs.list.iter(),
+            event_manager,
+            &mut IoThreadsBuilder::new(None).unwrap(),
+        )
+        .unwrap();
         block_files
     }
 
@@ -1156,7 +2257,13 @@ pub mod tests {
         let mut net_builder = NetBuilder::new();
         net_builder.build(net_config).unwrap();
 
-        let res = attach_net_devices(vmm, cmdline, net_builder.iter(), event_manager);
+        let res = attach_net_devices(
+            vmm,
+            cmdline,
+            net_builder.iter(),
+            event_manager,
+            &mut IoThreadsBuilder::new(None).unwrap(),
+        );
         assert!(res.is_ok());
     }
 
@@ -1177,7 +2284,14 @@ pub mod tests {
             Arc::new(Mutex::new(mmds)),
         );
 
-        attach_net_devices(vmm, cmdline, net_builder.iter(), event_manager).unwrap();
+        attach_net_devices(
+            vmm,
+            cmdline,
+            net_builder.iter(),
+            event_manager,
+            &mut IoThreadsBuilder::new(None).unwrap(),
+        )
+        .unwrap();
     }
 
     pub(crate) fn insert_vsock_device(
@@ -1269,13 +2383,49 @@ pub mod tests {
         #[cfg(target_arch = "aarch64")]
         let gm = create_guest_mem_with_size(mem_size + crate::arch::aarch64::layout::FDT_MAX_SIZE);
 
-        let res = load_initrd(&gm, &mut tempfile);
+        let res = load_initrd(&gm, &mut [tempfile]);
         assert!(res.is_ok());
         let initrd = res.unwrap();
         assert!(gm.address_in_range(initrd.address));
         assert_eq!(initrd.size, image.len());
     }
 
+    #[test]
+    fn test_load_initrd_concatenated() {
+        use utils::vm_memory::Bytes;
+
+        let images: [&[u8]; 3] = [&[1, 2, 3], &[4, 5, 6, 7, 8], &[9]];
+        let mut files = images
+            .iter()
+            .map(|image| {
+                let mut file = TempFile::new().unwrap().into_file();
+                file.write_all(image).unwrap();
+                file
+            })
+            .collect::<Vec<_>>();
+
+        #[cfg(target_arch = "x86_64")]
+        let gm = create_guest_mem_with_size(crate::arch::PAGE_SIZE * 2);
+
+        #[cfg(target_arch = "aarch64")]
+        let gm = create_guest_mem_with_size(
+            crate::arch::PAGE_SIZE * 2 + crate::arch::aarch64::layout::FDT_MAX_SIZE,
+        );
+
+        let initrd = load_initrd(&gm, &mut files).unwrap();
+        assert_eq!(initrd.size, 13);
+
+        let mut loaded = [0u8; 13];
+        gm.read_slice(&mut loaded, initrd.address).unwrap();
+        assert_eq!(loaded, [1, 2, 3, 0, 4, 5, 6, 7, 8, 0, 0, 0, 9]);
+
+        // The padding is written even when the guest memory holds something else already.
+        gm.write_slice(&[0xff; 13], initrd.address).unwrap();
+        let initrd = load_initrd(&gm, &mut files).unwrap();
+        gm.read_slice(&mut loaded, initrd.address).unwrap();
+        assert_eq!(loaded, [1, 2, 3, 0, 4, 5, 6, 7, 8, 0, 0, 0, 9]);
+    }
+
     #[test]
     fn test_load_initrd_no_memory() {
         let gm = create_guest_mem_with_size(79);
@@ -1283,7 +2433,7 @@ pub mod tests {
         let tempfile = TempFile::new().unwrap();
         let mut tempfile = tempfile.into_file();
         tempfile.write_all(&image).unwrap();
-        let res = load_initrd(&gm, &mut tempfile);
+        let res = load_initrd(&gm, &mut [tempfile]);
         assert!(res.is_err());
         assert_eq!(
             StartMicrovmError::InitrdLoad.to_string(),
@@ -1302,7 +2452,7 @@ pub mod tests {
             image.len() * 2,
         );
 
-        let res = load_initrd(&gm, &mut tempfile);
+        let res = load_initrd(&gm, &mut [tempfile]);
         assert!(res.is_err());
         assert_eq!(
             StartMicrovmError::InitrdLoad.to_string(),
@@ -1310,27 +2460,148 @@ pub mod tests {
         );
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_load_kernel_pvh_boot() {
+        use crate::utilities::mock_resources::kernel_image_path;
+        use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig};
+
+        let gm = create_guest_mem_with_size(128 << 20);
+        let mut boot_src_cfg = BootSourceConfig {
+            kernel_image_path: kernel_image_path(None),
+            ..Default::default()
+        };
+
+        // The Linux boot protocol is used unless PVH is requested.
+        let (entry_point, _) = load_kernel(&BootConfig::new(&boot_src_cfg).unwrap(), &gm).unwrap();
+        assert_eq!(entry_point.protocol, BootProtocol::LinuxBoot);
+
+        // The test kernel does not advertise a PVH entry point.
+        boot_src_cfg.pvh_boot = true;
+        assert!(matches!(
+            load_kernel(&BootConfig::new(&boot_src_cfg).unwrap(), &gm),
+            Err(StartMicrovmError::KernelMissingPvhEntry)
+        ));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_load_pvh_firmware_invalid_image() {
+        let gm = create_guest_mem_with_size(128 << 20);
+        let tempfile = TempFile::new().unwrap();
+        let mut tempfile = tempfile.into_file();
+        tempfile.write_all(&make_test_bin()).unwrap();
+
+        assert!(matches!(
+            load_pvh_firmware(&tempfile, &gm),
+            Err(StartMicrovmError::PvhFirmwareLoader(_))
+        ));
+    }
+
     #[test]
     fn test_create_guest_memory() {
         let mem_size = 4096 * 2;
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, &MmioLayout::default(), None, None, false).unwrap();
             assert!(!is_dirty_tracking_enabled(&guest_memory));
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, &MmioLayout::default(), None, None, true).unwrap();
             assert!(is_dirty_tracking_enabled(&guest_memory));
         }
+
+        // Case 3: create guest memory with a memory hotplug region
+        #[cfg(target_arch = "aarch64")]
+        {
+            let region =
+                crate::arch::aarch64::hotplug_memory_region(mem_size << 20, 1 << 30).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, &MmioLayout::default(), Some(region), None, false)
+                    .unwrap();
+            assert_eq!(guest_memory.num_regions(), 2);
+            assert!(guest_memory.address_in_range(region.0));
+        }
+    }
+
+    fn create_memfd(size: u64, seals: i32) -> File {
+        // SAFETY: The name is a valid C string and the return code is checked.
+        let fd = unsafe {
+            libc::memfd_create(
+                b"guest_mem\0".as_ptr().cast(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        assert!(fd >= 0);
+        // SAFETY: `fd` was just created, so it is valid and owned by nobody else.
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size).unwrap();
+        // SAFETY: `fcntl` does not access memory and the descriptor is valid.
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) }, 0);
+        file
+    }
+
+    #[test]
+    fn test_open_mem_backing() {
+        let mem_size_mib = 2;
+        let mem_size = mem_size_mib << 20;
+        let resize_seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+        let path = |file: &File| {
+            crate::vmm_config::add_inherited_fds(&[file.as_raw_fd()]).unwrap();
+            format!("/proc/self/fd/{}", file.as_raw_fd())
+        };
+
+        // The guest memory is a shared mapping of the memfd.
+        let memfd = create_memfd(mem_size as u64, resize_seals);
+        let mem_backing = open_mem_backing(&path(&memfd), mem_size).unwrap();
+        let guest_memory = create_guest_memory(
+            mem_size_mib,
+            &MmioLayout::default(),
+            None,
+            Some(mem_backing),
+            false,
+        )
+        .unwrap();
+        guest_memory
+            .write_obj(0xabcd_u32, GuestAddress(0x1000))
+            .unwrap();
+        let mut buf = [0u8; 4];
+        memfd.read_exact_at(&mut buf, 0x1000).unwrap();
+        assert_eq!(u32::from_ne_bytes(buf), 0xabcd);
+
+        // The memfd must not be resizable.
+        let memfd = create_memfd(mem_size as u64, libc::F_SEAL_SHRINK);
+        assert!(matches!(
+            open_mem_backing(&path(&memfd), mem_size),
+            Err(MemBackingError::InvalidSeals(_))
+        ));
+        // The memfd must be writable.
+        let memfd = create_memfd(mem_size as u64, resize_seals | libc::F_SEAL_WRITE);
+        assert!(matches!(
+            open_mem_backing(&path(&memfd), mem_size),
+            Err(MemBackingError::InvalidSeals(_))
+        ));
+        // The memfd must be as large as the guest memory.
+        let memfd = create_memfd(mem_size as u64 / 2, resize_seals);
+        assert!(matches!(
+            open_mem_backing(&path(&memfd), mem_size),
+            Err(MemBackingError::InvalidSize(_, _))
+        ));
+        // Regular files cannot be sealed.
+        let file = TempFile::new().unwrap();
+        assert!(open_mem_backing(file.as_path().to_str().unwrap(), mem_size).is_err());
     }
 
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, &MmioLayout::default(), None, None, false).unwrap();
 
         #[allow(unused_mut)]
         let mut vm = Vm::new(vec![]).unwrap();
@@ -1355,6 +2626,12 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1559,6 +2836,46 @@ pub mod tests {
             .mmio_device_manager
             .get_device(DeviceType::BootTimer, &DeviceType::BootTimer.to_string())
             .is_some());
+
+        // The guest signaling it is ready flips the state of the running microVM.
+        vmm.instance_info.state = VmState::Running;
+        vmm.get_bus_device(DeviceType::BootTimer, &DeviceType::BootTimer.to_string())
+            .unwrap()
+            .lock()
+            .unwrap()
+            .boot_timer_mut()
+            .unwrap()
+            .bus_write(0, &[124]);
+        assert_eq!(vmm.instance_info().state, VmState::GuestReady);
+        assert_eq!(vmm.instance_info().state.to_string(), "Running:GuestReady");
+        vmm.instance_info.state = VmState::Paused;
+        assert_eq!(vmm.instance_info().state, VmState::Paused);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_setup_smbios() {
+        use utils::vm_memory::Bytes;
+
+        let vmm = default_vmm();
+        let smbios = SmbiosConfig {
+            serial_number: Some("SN-42".to_string()),
+            ..Default::default()
+        };
+        let identity = IdentityConfig {
+            uuid: Some("01234567-89ab-cdef-0123-456789abcdef".to_string()),
+            ..Default::default()
+        };
+        setup_smbios(vmm.guest_memory(), Some(&smbios), Some(&identity)).unwrap();
+
+        let mut anchor = [0u8; 5];
+        vmm.guest_memory()
+            .read_slice(
+                &mut anchor,
+                GuestAddress(crate::arch::x86_64::layout::SMBIOS_START),
+            )
+            .unwrap();
+        assert_eq!(&anchor, b"_SM3_");
     }
 
     #[test]
@@ -1568,8 +2885,10 @@ pub mod tests {
 
         let balloon_config = BalloonDeviceConfig {
             amount_mib: 0,
+            amount_bytes: None,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1599,6 +2918,80 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn test_attach_virtio_recorders() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        insert_entropy_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            EntropyDeviceConfig::default(),
+        );
+
+        let file = TempFile::new().unwrap();
+        let queue_config = |device_id: &str, queue_index| QueueRecordConfig {
+            device_id: device_id.to_string(),
+            queue_index,
+            path: file.as_path().to_str().unwrap().to_string(),
+            max_size_bytes: None,
+        };
+
+        let config = VirtioRecordConfig {
+            queues: vec![queue_config(ENTROPY_DEV_ID, 0)],
+        };
+        attach_virtio_recorders(&vmm, &config).unwrap();
+        vmm.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_RNG, ENTROPY_DEV_ID, |entropy: &mut Entropy| {
+                assert!(entropy.queues()[0].recorder.is_some());
+                Ok(())
+            })
+            .unwrap();
+
+        let config = VirtioRecordConfig {
+            queues: vec![queue_config(ENTROPY_DEV_ID, 1)],
+        };
+        assert!(matches!(
+            attach_virtio_recorders(&vmm, &config),
+            Err(StartMicrovmError::VirtioRecordQueue(_, 1))
+        ));
+
+        let config = VirtioRecordConfig {
+            queues: vec![queue_config("foo", 0)],
+        };
+        assert!(matches!(
+            attach_virtio_recorders(&vmm, &config),
+            Err(StartMicrovmError::VirtioRecordQueue(_, 0))
+        ));
+    }
+
+    #[test]
+    fn test_attach_shared_dirs() {
+        let mut io_threads = IoThreadsBuilder::new(None).unwrap();
+        let mut vmm = default_vmm();
+        let dir = TempDir::new().unwrap();
+
+        let mut builder = SharedDirBuilder::new();
+        builder
+            .build(SharedDirConfig {
+                share_id: "share".to_string(),
+                path_on_host: dir.as_path().to_path_buf(),
+                mount_tag: "share".to_string(),
+                is_read_only: false,
+            })
+            .unwrap();
+
+        let mut cmdline = default_kernel_cmdline();
+        assert!(
+            attach_shared_dirs(&mut vmm, &mut cmdline, builder.iter(), &mut io_threads).is_ok()
+        );
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_9P), "share")
+            .is_some());
+    }
+
     #[test]
     fn test_attach_vsock_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -0,0 +1,79 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! CPU bandwidth control through the cgroup of the microVM.
+//!
+//! A jailed Firecracker cannot reach the cgroup filesystem. The jailer opens the `cpu.max` file of
+//! the cgroup of the microVM before chrooting and passes it down with `--cpu-max-fd`, so that the
+//! CPU bandwidth of the machine configuration can be written to it at boot and after.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::vmm_config::machine_config::CpuBandwidth;
+
+// The `cpu.max` file of the cgroup of the microVM, when passed to Firecracker.
+static CPU_MAX_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Errors associated with the CPU bandwidth of the microVM cgroup.
+#[derive(Debug, thiserror::Error)]
+pub enum CpuBandwidthError {
+    /// The `cpu.max` file was not passed to Firecracker.
+    #[error(
+        "The cpu.max file of the microVM cgroup was not passed to Firecracker with --cpu-max-fd."
+    )]
+    MissingCpuMaxFile,
+    /// The `cpu.max` file could not be written.
+    #[error("Cannot write the cpu.max file of the microVM cgroup: {0}")]
+    WriteCpuMax(io::Error),
+}
+
+/// Sets the `cpu.max` file of the cgroup of the microVM, to which the CPU bandwidth is written.
+pub fn set_cpu_max_file(file: File) {
+    *CPU_MAX_FILE.lock().expect("Poisoned lock") = Some(file);
+}
+
+/// Writes `bandwidth` to the `cpu.max` file of the cgroup of the microVM.
+pub fn apply_cpu_bandwidth(bandwidth: &CpuBandwidth) -> Result<(), CpuBandwidthError> {
+    let mut cpu_max_file = CPU_MAX_FILE.lock().expect("Poisoned lock");
+    let file = cpu_max_file
+        .as_mut()
+        .ok_or(CpuBandwidthError::MissingCpuMaxFile)?;
+    write_cpu_max(file, bandwidth).map_err(CpuBandwidthError::WriteCpuMax)
+}
+
+// The cgroup filesystem parses each write on its own, so the line is written at once.
+fn write_cpu_max<W: Write>(writer: &mut W, bandwidth: &CpuBandwidth) -> io::Result<()> {
+    let line = bandwidth.cpu_max_line();
+    let written = writer.write(line.as_bytes())?;
+    if written != line.len() {
+        return Err(io::Error::from(io::ErrorKind::WriteZero));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_cpu_max() {
+        let mut buf = Vec::new();
+        let bandwidth = CpuBandwidth {
+            quota_us: Some(25000),
+            period_us: 50000,
+        };
+        write_cpu_max(&mut buf, &bandwidth).unwrap();
+        assert_eq!(buf, b"25000 50000\n");
+
+        // A partial write would be parsed as a different bandwidth.
+        let mut short_buf = [0u8; 4];
+        assert_eq!(
+            write_cpu_max(&mut &mut short_buf[..], &bandwidth)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::WriteZero
+        );
+    }
+}
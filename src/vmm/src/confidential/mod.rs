@@ -0,0 +1,170 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+mod sev_snp;
+
+use kvm_ioctls::VmFd;
+use utils::vm_memory::{GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+use self::sev_snp::SnpLaunch;
+use crate::arch::PAGE_SIZE;
+use crate::vmm_config::confidential::{
+    ConfidentialConfig, ConfidentialInfo, ConfidentialTechnology,
+};
+
+/// Size of the launch digest, a SHA-384 hash.
+pub const LAUNCH_DIGEST_SIZE: usize = 48;
+
+/// Errors associated with the launch of confidential microVMs.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfidentialError {
+    /// The technology cannot launch guests yet.
+    #[error("Launching {0} guests is not supported.")]
+    NotSupported(ConfidentialTechnology),
+    /// Failed to open the device of the secure processor.
+    #[error("Cannot open /dev/sev: {0}")]
+    OpenSevDevice(std::io::Error),
+    /// A memory encryption command failed.
+    #[error("The {command} command failed: {error} (firmware error {fw_error:#x})")]
+    Command {
+        /// Name of the command.
+        command: &'static str,
+        /// Error returned by KVM.
+        error: kvm_ioctls::Error,
+        /// Error code returned by the firmware of the secure processor.
+        fw_error: u32,
+    },
+    /// Failed to register the guest memory as encrypted.
+    #[error("Cannot register the guest memory as encrypted: {0}")]
+    RegisterMemory(kvm_ioctls::Error),
+    /// Failed to access the guest memory.
+    #[error("Cannot access the guest memory: {0}")]
+    GuestMemory(GuestMemoryError),
+}
+
+/// Launch of a confidential microVM, whose memory is encrypted by the hardware.
+#[derive(Debug)]
+pub struct ConfidentialLaunch {
+    config: ConfidentialConfig,
+    snp: SnpLaunch,
+    launch_digest: Option<[u8; LAUNCH_DIGEST_SIZE]>,
+}
+
+impl ConfidentialLaunch {
+    /// Initializes the memory encryption of the VM and registers its memory as encrypted.
+    ///
+    /// Has to be called before the vCPUs are created.
+    pub fn start(
+        vm_fd: &VmFd,
+        guest_memory: &GuestMemoryMmap,
+        config: &ConfidentialConfig,
+    ) -> Result<Self, ConfidentialError> {
+        match config.technology {
+            ConfidentialTechnology::SevSnp => Ok(ConfidentialLaunch {
+                config: config.clone(),
+                snp: SnpLaunch::start(vm_fd, guest_memory, config.policy)?,
+                launch_digest: None,
+            }),
+            // TDX needs its own VM type and vCPU initialization, which are not implemented yet.
+            ConfidentialTechnology::Tdx => Err(ConfidentialError::NotSupported(config.technology)),
+        }
+    }
+
+    /// Encrypts and measures the guest memory `ranges`, rounded to whole pages, then finishes the
+    /// launch.
+    ///
+    /// Has to be called once the registers of the vCPUs are set, since they get encrypted too.
+    pub fn finish(
+        &mut self,
+        vm_fd: &VmFd,
+        guest_memory: &GuestMemoryMmap,
+        ranges: &[(GuestAddress, usize)],
+    ) -> Result<(), ConfidentialError> {
+        let ranges = page_ranges(ranges);
+        self.launch_digest = Some(self.snp.finish(vm_fd, guest_memory, &ranges)?);
+        Ok(())
+    }
+
+    /// Returns the information about the launch, once it is finished.
+    pub fn info(&self) -> Option<ConfidentialInfo> {
+        self.launch_digest.map(|launch_digest| ConfidentialInfo {
+            technology: self.config.technology,
+            policy: self.config.policy,
+            launch_digest: hex(&launch_digest),
+        })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Rounds the ranges to whole pages, sorts them and merges the overlapping ones, so that each
+// page is measured once and in the order of the guest physical addresses.
+fn page_ranges(ranges: &[(GuestAddress, usize)]) -> Vec<(GuestAddress, usize)> {
+    let page_mask = PAGE_SIZE as u64 - 1;
+    let mut bounds: Vec<(u64, u64)> = ranges
+        .iter()
+        .filter(|(_, len)| *len > 0)
+        .map(|(addr, len)| {
+            let start = addr.0 & !page_mask;
+            let end = (addr.0 + *len as u64 + page_mask) & !page_mask;
+            (start, end)
+        })
+        .collect();
+    bounds.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(bounds.len());
+    for (start, end) in bounds {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(start, end)| (GuestAddress(start), (end - start) as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_ranges() {
+        assert_eq!(page_ranges(&[]), vec![]);
+        assert_eq!(
+            page_ranges(&[
+                (GuestAddress(0x10_0000), 0x1801),
+                (GuestAddress(0x2000), 0),
+                (GuestAddress(0), 0x10_0000),
+                (GuestAddress(0x10_1000), 0x1000),
+                (GuestAddress(0x20_0800), 0x100),
+            ]),
+            vec![
+                (GuestAddress(0), 0x10_2000),
+                (GuestAddress(0x20_0000), 0x1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tdx_not_supported() {
+        let kvm = kvm_ioctls::Kvm::new().unwrap();
+        let vm_fd = kvm.create_vm().unwrap();
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), 0x1000)],
+            false,
+        )
+        .unwrap();
+        let config = ConfidentialConfig {
+            technology: ConfidentialTechnology::Tdx,
+            policy: 0,
+        };
+        assert!(matches!(
+            ConfidentialLaunch::start(&vm_fd, &guest_memory, &config),
+            Err(ConfidentialError::NotSupported(ConfidentialTechnology::Tdx))
+        ));
+    }
+}
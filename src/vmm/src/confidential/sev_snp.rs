@@ -0,0 +1,240 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+
+use aws_lc_rs::digest::{digest, SHA384};
+use kvm_bindings::{kvm_enc_region, kvm_sev_cmd};
+use kvm_ioctls::VmFd;
+use utils::vm_memory::{
+    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+};
+
+use super::{ConfidentialError, LAUNCH_DIGEST_SIZE};
+use crate::arch::PAGE_SIZE;
+
+// Device of the AMD secure processor, which runs the SEV firmware.
+const SEV_DEVICE: &str = "/dev/sev";
+
+// Commands of `KVM_MEMORY_ENCRYPT_OP` launching SEV-SNP guests. They are part of the SEV-SNP
+// host support of the AMD kernel branches, which kvm-bindings does not describe.
+const KVM_SEV_SNP_INIT: u32 = 22;
+const KVM_SEV_SNP_LAUNCH_START: u32 = 23;
+const KVM_SEV_SNP_LAUNCH_UPDATE: u32 = 24;
+const KVM_SEV_SNP_LAUNCH_FINISH: u32 = 25;
+
+// Page type of the pages whose contents are encrypted and measured.
+const PAGE_TYPE_NORMAL: u8 = 1;
+// Size of the PAGE_INFO structure hashed to extend the launch digest.
+const PAGE_INFO_SIZE: u16 = 0x70;
+const PAGE_SHIFT: u64 = 12;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SnpInit {
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SnpLaunchStart {
+    policy: u64,
+    ma_uaddr: u64,
+    ma_en: u8,
+    imi_en: u8,
+    gosvw: [u8; 16],
+    pad: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SnpLaunchUpdate {
+    start_gfn: u64,
+    uaddr: u64,
+    len: u32,
+    imi_page: u8,
+    page_type: u8,
+    vmpl3_perms: u8,
+    vmpl2_perms: u8,
+    vmpl1_perms: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SnpLaunchFinish {
+    id_block_uaddr: u64,
+    id_auth_uaddr: u64,
+    id_block_en: u8,
+    auth_key_en: u8,
+    host_data: [u8; 32],
+    pad: [u8; 6],
+}
+
+/// Launch of an SEV-SNP guest, driven through KVM by the firmware of the secure processor.
+#[derive(Debug)]
+pub(super) struct SnpLaunch {
+    sev: File,
+}
+
+impl SnpLaunch {
+    /// Initializes SEV-SNP for the VM, starts the launch with the guest `policy`, and registers
+    /// the guest memory as encrypted.
+    pub(super) fn start(
+        vm_fd: &VmFd,
+        guest_memory: &GuestMemoryMmap,
+        policy: u64,
+    ) -> Result<Self, ConfidentialError> {
+        let sev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(SEV_DEVICE)
+            .map_err(ConfidentialError::OpenSevDevice)?;
+        let launch = SnpLaunch { sev };
+
+        launch.command(vm_fd, "SNP_INIT", KVM_SEV_SNP_INIT, &mut SnpInit::default())?;
+        launch.command(
+            vm_fd,
+            "SNP_LAUNCH_START",
+            KVM_SEV_SNP_LAUNCH_START,
+            &mut SnpLaunchStart {
+                policy,
+                ..Default::default()
+            },
+        )?;
+
+        for region in guest_memory.iter() {
+            let enc_region = kvm_enc_region {
+                addr: region.as_ptr() as u64,
+                size: region.len(),
+            };
+            vm_fd
+                .register_enc_memory_region(&enc_region)
+                .map_err(ConfidentialError::RegisterMemory)?;
+        }
+        Ok(launch)
+    }
+
+    /// Encrypts and measures the pages of `ranges` in place, then finishes the launch.
+    ///
+    /// Returns the launch digest of the encrypted pages.
+    pub(super) fn finish(
+        &self,
+        vm_fd: &VmFd,
+        guest_memory: &GuestMemoryMmap,
+        ranges: &[(GuestAddress, usize)],
+    ) -> Result<[u8; LAUNCH_DIGEST_SIZE], ConfidentialError> {
+        let mut launch_digest = [0; LAUNCH_DIGEST_SIZE];
+        let mut page = [0; PAGE_SIZE];
+
+        for &(start, len) in ranges {
+            for offset in (0..len).step_by(PAGE_SIZE) {
+                let addr = start.unchecked_add(offset as u64);
+                // The page is measured before the firmware encrypts it in place.
+                guest_memory
+                    .read_slice(&mut page, addr)
+                    .map_err(ConfidentialError::GuestMemory)?;
+                launch_digest = measure_page(&launch_digest, addr.raw_value(), &page);
+
+                let uaddr = guest_memory
+                    .get_host_address(addr)
+                    .map_err(ConfidentialError::GuestMemory)?;
+                self.command(
+                    vm_fd,
+                    "SNP_LAUNCH_UPDATE",
+                    KVM_SEV_SNP_LAUNCH_UPDATE,
+                    &mut SnpLaunchUpdate {
+                        start_gfn: addr.raw_value() >> PAGE_SHIFT,
+                        uaddr: uaddr as u64,
+                        len: PAGE_SIZE as u32,
+                        page_type: PAGE_TYPE_NORMAL,
+                        ..Default::default()
+                    },
+                )?;
+            }
+        }
+
+        // KVM encrypts and measures the VMSA of each vCPU before the launch is finished.
+        self.command(
+            vm_fd,
+            "SNP_LAUNCH_FINISH",
+            KVM_SEV_SNP_LAUNCH_FINISH,
+            &mut SnpLaunchFinish::default(),
+        )?;
+        Ok(launch_digest)
+    }
+
+    fn command<T>(
+        &self,
+        vm_fd: &VmFd,
+        name: &'static str,
+        id: u32,
+        data: &mut T,
+    ) -> Result<(), ConfidentialError> {
+        let mut cmd = kvm_sev_cmd {
+            id,
+            data: data as *mut T as u64,
+            sev_fd: self.sev.as_raw_fd() as u32,
+            ..Default::default()
+        };
+        vm_fd
+            .encrypt_op_sev(&mut cmd)
+            .map_err(|error| ConfidentialError::Command {
+                command: name,
+                error,
+                fw_error: cmd.error,
+            })
+    }
+}
+
+// Extends the launch digest with a page the way the firmware does when it encrypts the page: the
+// new digest is the SHA-384 hash of the PAGE_INFO structure describing the page.
+fn measure_page(
+    launch_digest: &[u8; LAUNCH_DIGEST_SIZE],
+    gpa: u64,
+    contents: &[u8],
+) -> [u8; LAUNCH_DIGEST_SIZE] {
+    let mut page_info = Vec::with_capacity(usize::from(PAGE_INFO_SIZE));
+    page_info.extend_from_slice(launch_digest);
+    page_info.extend_from_slice(digest(&SHA384, contents).as_ref());
+    page_info.extend_from_slice(&PAGE_INFO_SIZE.to_le_bytes());
+    page_info.push(PAGE_TYPE_NORMAL);
+    // The IMI page flag, the permissions of VMPLs 3 to 1 and a reserved byte.
+    page_info.extend_from_slice(&[0; 5]);
+    page_info.extend_from_slice(&gpa.to_le_bytes());
+
+    let mut next = [0; LAUNCH_DIGEST_SIZE];
+    next.copy_from_slice(digest(&SHA384, &page_info).as_ref());
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confidential::hex;
+
+    #[test]
+    fn test_measure_page() {
+        let launch_digest = measure_page(&[0; LAUNCH_DIGEST_SIZE], 0x1000, &[0; PAGE_SIZE]);
+        assert_eq!(
+            hex(&launch_digest),
+            "e6e4d78904d9d6b33c0cbfd121998fd2d11744dae606b3aee35e2a45d6869b60\
+             b35c63928a9232bef72d6be386f48ec6"
+        );
+        let launch_digest = measure_page(&launch_digest, 0x2000, &[0xff; PAGE_SIZE]);
+        assert_eq!(
+            hex(&launch_digest),
+            "c509b3791d809bb4735501f0dbe5e3f5929fe7207943b62b75c79ae50fe68f2b\
+             56c2ec3de627798ec287850376bc1fa0"
+        );
+    }
+
+    #[test]
+    fn test_launch_structs() {
+        // The structures are passed to KVM as is.
+        assert_eq!(std::mem::size_of::<SnpInit>(), 8);
+        assert_eq!(std::mem::size_of::<SnpLaunchStart>(), 40);
+        assert_eq!(std::mem::size_of::<SnpLaunchUpdate>(), 32);
+        assert_eq!(std::mem::size_of::<SnpLaunchFinish>(), 56);
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dumps the guest memory and the vCPU registers of a paused microVM in the ELF core format,
+//! which debuggers like `crash` or `gdb` load directly.
+//!
+//! The dump holds a `PT_NOTE` segment with the registers of each vCPU, followed by one `PT_LOAD`
+//! segment per guest memory region. The guest physical address of a region is used both as the
+//! physical and as the virtual address of its segment.
+
+use std::fs::OpenOptions;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use log::info;
+use utils::vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use crate::memory_snapshot::{SnapshotMemory, SnapshotMemoryError};
+use crate::persist::MicrovmStateError;
+use crate::vmm_config::coredump::CoredumpParams;
+use crate::vstate::vcpu::VcpuState;
+use crate::Vmm;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+// The guest memory starts on a page boundary, so that the readers can map the segments.
+const MEMORY_ALIGN: usize = 4096;
+
+const ET_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const EM_X86_64: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RWX: u32 = 0x7;
+const NT_PRSTATUS: u32 = 1;
+
+// Size of the fields of `struct elf_prstatus` preceding the general purpose registers, and
+// offset of `pr_pid` among them.
+const PRSTATUS_REGS_OFFSET: usize = 112;
+const PRSTATUS_PID_OFFSET: usize = 32;
+
+/// Errors associated with dumping the guest memory.
+#[derive(Debug, thiserror::Error)]
+pub enum CoredumpError {
+    /// Failed to save the vCPU registers.
+    #[error("Cannot save the vCPU registers: {0}")]
+    VcpuStates(MicrovmStateError),
+    /// Failed to write the dump file.
+    #[error("Cannot perform {0} on the dump file: {1}")]
+    DumpFile(&'static str, io::Error),
+    /// Failed to write the guest memory to the dump file.
+    #[error("Cannot write the guest memory to the dump file: {0}")]
+    Memory(SnapshotMemoryError),
+}
+
+/// Writes the guest memory and the registers of the vCPUs of `vmm` to `params.dump_path`. The
+/// microVM must be paused.
+pub fn create_coredump(vmm: &mut Vmm, params: &CoredumpParams) -> Result<(), CoredumpError> {
+    use self::CoredumpError::*;
+
+    let vcpu_states = vmm.save_vcpu_states().map_err(VcpuStates)?;
+    let mut notes = Vec::new();
+    for (index, state) in vcpu_states.iter().enumerate() {
+        // The dump readers number the CPUs like the threads of a process, starting at 1.
+        put_vcpu_notes(&mut notes, u32::try_from(index + 1).unwrap(), state);
+    }
+    let (headers, memory_offset) = elf_headers(vmm.guest_memory(), notes.len());
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&params.dump_path)
+        .map_err(|err| DumpFile("open", err))?;
+    file.write_all(&headers)
+        .and_then(|()| file.write_all(&notes))
+        .map_err(|err| DumpFile("write", err))?;
+    file.seek(SeekFrom::Start(memory_offset as u64))
+        .map_err(|err| DumpFile("seek", err))?;
+    vmm.guest_memory().dump(&mut file).map_err(Memory)?;
+    file.flush().map_err(|err| DumpFile("flush", err))?;
+    file.sync_all().map_err(|err| DumpFile("sync_all", err))?;
+
+    info!(
+        "Dumped the guest memory and {} vCPUs to {:?}.",
+        vcpu_states.len(),
+        params.dump_path
+    );
+    Ok(())
+}
+
+// Builds the ELF header and the program headers of a dump of `guest_memory` with `notes_len`
+// bytes of notes, and returns them along with the file offset of the guest memory.
+fn elf_headers(guest_memory: &GuestMemoryMmap, notes_len: usize) -> (Vec<u8>, usize) {
+    let phnum = guest_memory.num_regions() + 1;
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let memory_offset = (notes_offset + notes_len + MEMORY_ALIGN - 1) / MEMORY_ALIGN * MEMORY_ALIGN;
+
+    let mut buf = Vec::with_capacity(notes_offset);
+    // e_ident: 64 bit, little endian, current version.
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+    buf.resize(16, 0);
+    buf.extend_from_slice(&ET_CORE.to_le_bytes());
+    #[cfg(target_arch = "x86_64")]
+    buf.extend_from_slice(&EM_X86_64.to_le_bytes());
+    #[cfg(target_arch = "aarch64")]
+    buf.extend_from_slice(&EM_AARCH64.to_le_bytes());
+    // e_version
+    buf.extend_from_slice(&1u32.to_le_bytes());
+    // e_entry
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    // e_phoff
+    buf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+    // e_shoff
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    // e_flags
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    // e_ehsize and e_phentsize
+    buf.extend_from_slice(&u16::try_from(ELF_HEADER_SIZE).unwrap().to_le_bytes());
+    buf.extend_from_slice(&u16::try_from(PROGRAM_HEADER_SIZE).unwrap().to_le_bytes());
+    // e_phnum. The guest memory is made of a handful of regions.
+    buf.extend_from_slice(&u16::try_from(phnum).unwrap().to_le_bytes());
+    // e_shentsize, e_shnum and e_shstrndx: there are no sections.
+    buf.resize(ELF_HEADER_SIZE, 0);
+
+    put_program_header(&mut buf, PT_NOTE, 0, notes_offset, 0, notes_len);
+    let mut offset = memory_offset;
+    for region in guest_memory.iter() {
+        let len = usize::try_from(region.len()).unwrap();
+        put_program_header(
+            &mut buf,
+            PT_LOAD,
+            PF_RWX,
+            offset,
+            region.start_addr().0,
+            len,
+        );
+        offset += len;
+    }
+    (buf, memory_offset)
+}
+
+fn put_program_header(
+    buf: &mut Vec<u8>,
+    p_type: u32,
+    p_flags: u32,
+    offset: usize,
+    addr: u64,
+    len: usize,
+) {
+    buf.extend_from_slice(&p_type.to_le_bytes());
+    buf.extend_from_slice(&p_flags.to_le_bytes());
+    buf.extend_from_slice(&(offset as u64).to_le_bytes());
+    // p_vaddr and p_paddr
+    buf.extend_from_slice(&addr.to_le_bytes());
+    buf.extend_from_slice(&addr.to_le_bytes());
+    // p_filesz and p_memsz
+    buf.extend_from_slice(&(len as u64).to_le_bytes());
+    buf.extend_from_slice(&(len as u64).to_le_bytes());
+    // p_align
+    buf.extend_from_slice(&0u64.to_le_bytes());
+}
+
+fn put_note(buf: &mut Vec<u8>, name: &str, note_type: u32, desc: &[u8]) {
+    // The name is NUL terminated, and both the name and the descriptor are padded to 4 bytes.
+    buf.extend_from_slice(&u32::try_from(name.len() + 1).unwrap().to_le_bytes());
+    buf.extend_from_slice(&u32::try_from(desc.len()).unwrap().to_le_bytes());
+    buf.extend_from_slice(&note_type.to_le_bytes());
+    buf.extend_from_slice(name.as_bytes());
+    buf.push(0);
+    buf.resize((buf.len() + 3) & !3, 0);
+    buf.extend_from_slice(desc);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+// Encodes a `struct elf_prstatus` reporting the general purpose registers `regs` for the thread
+// `pid`.
+fn prstatus(pid: u32, regs: &[u64]) -> Vec<u8> {
+    let mut desc = vec![0u8; PRSTATUS_REGS_OFFSET];
+    desc[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4].copy_from_slice(&pid.to_le_bytes());
+    for reg in regs {
+        desc.extend_from_slice(&reg.to_le_bytes());
+    }
+    // pr_fpvalid and the trailing padding.
+    desc.resize(desc.len() + 8, 0);
+    desc
+}
+
+#[cfg(target_arch = "x86_64")]
+fn put_vcpu_notes(buf: &mut Vec<u8>, pid: u32, state: &VcpuState) {
+    use kvm_bindings::{kvm_dtable, kvm_segment};
+
+    // Index of the MSR holding the GS base swapped in by `swapgs`.
+    const MSR_KERNEL_GS_BASE: u32 = 0xc000_0102;
+    // Version and size of the `QEMUCPUState` note, which `crash` reads the control registers
+    // from.
+    const QEMU_CPU_STATE_VERSION: u32 = 1;
+    const QEMU_CPU_STATE_SIZE: u32 = 440;
+
+    let regs = &state.regs;
+    let sregs = &state.sregs;
+
+    // The registers in the order of `struct user_regs_struct`.
+    let user_regs = [
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        // orig_rax
+        0,
+        regs.rip,
+        u64::from(sregs.cs.selector),
+        regs.rflags,
+        regs.rsp,
+        u64::from(sregs.ss.selector),
+        sregs.fs.base,
+        sregs.gs.base,
+        u64::from(sregs.ds.selector),
+        u64::from(sregs.es.selector),
+        u64::from(sregs.fs.selector),
+        u64::from(sregs.gs.selector),
+    ];
+    put_note(buf, "CORE", NT_PRSTATUS, &prstatus(pid, &user_regs));
+
+    let put_segment = |desc: &mut Vec<u8>, segment: &kvm_segment| {
+        // The flags are laid out like in the high word of a segment descriptor.
+        let flags = u32::from(segment.type_) << 8
+            | u32::from(segment.s) << 12
+            | u32::from(segment.dpl) << 13
+            | u32::from(segment.present) << 15
+            | u32::from(segment.avl) << 20
+            | u32::from(segment.l) << 21
+            | u32::from(segment.db) << 22
+            | u32::from(segment.g) << 23;
+        desc.extend_from_slice(&u32::from(segment.selector).to_le_bytes());
+        desc.extend_from_slice(&segment.limit.to_le_bytes());
+        desc.extend_from_slice(&flags.to_le_bytes());
+        desc.extend_from_slice(&0u32.to_le_bytes());
+        desc.extend_from_slice(&segment.base.to_le_bytes());
+    };
+    let put_dtable = |desc: &mut Vec<u8>, dtable: &kvm_dtable| {
+        desc.extend_from_slice(&0u32.to_le_bytes());
+        desc.extend_from_slice(&u32::from(dtable.limit).to_le_bytes());
+        desc.extend_from_slice(&0u64.to_le_bytes());
+        desc.extend_from_slice(&dtable.base.to_le_bytes());
+    };
+    let kernel_gs_base = state
+        .saved_msrs
+        .iter()
+        .flat_map(|msrs| msrs.as_slice())
+        .find(|entry| entry.index == MSR_KERNEL_GS_BASE)
+        .map_or(0, |entry| entry.data);
+
+    let mut desc = Vec::with_capacity(QEMU_CPU_STATE_SIZE as usize);
+    desc.extend_from_slice(&QEMU_CPU_STATE_VERSION.to_le_bytes());
+    desc.extend_from_slice(&QEMU_CPU_STATE_SIZE.to_le_bytes());
+    for reg in [
+        regs.rax,
+        regs.rbx,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.rsp,
+        regs.rbp,
+        regs.r8,
+        regs.r9,
+        regs.r10,
+        regs.r11,
+        regs.r12,
+        regs.r13,
+        regs.r14,
+        regs.r15,
+        regs.rip,
+        regs.rflags,
+    ] {
+        desc.extend_from_slice(&reg.to_le_bytes());
+    }
+    for segment in [
+        &sregs.cs, &sregs.ds, &sregs.es, &sregs.fs, &sregs.gs, &sregs.ss, &sregs.ldt, &sregs.tr,
+    ] {
+        put_segment(&mut desc, segment);
+    }
+    put_dtable(&mut desc, &sregs.gdt);
+    put_dtable(&mut desc, &sregs.idt);
+    for reg in [
+        sregs.cr0,
+        0,
+        sregs.cr2,
+        sregs.cr3,
+        sregs.cr4,
+        kernel_gs_base,
+    ] {
+        desc.extend_from_slice(&reg.to_le_bytes());
+    }
+    put_note(buf, "QEMU", 0, &desc);
+}
+
+#[cfg(target_arch = "aarch64")]
+fn put_vcpu_notes(buf: &mut Vec<u8>, pid: u32, state: &VcpuState) {
+    use kvm_bindings::{kvm_regs, user_pt_regs, KVM_REG_ARM64, KVM_REG_ARM_CORE, KVM_REG_SIZE_U64};
+
+    use crate::arch::aarch64::regs::{arm64_core_reg_id, offset__of};
+
+    // Mode of a vCPU running at EL1 on the SP_EL1 stack.
+    const PSR_MODE_MASK: u64 = 0xf;
+    const PSR_MODE_EL1H: u64 = 0x5;
+
+    let core_reg = |offset: usize| {
+        let id = arm64_core_reg_id!(KVM_REG_SIZE_U64, offset);
+        state
+            .regs
+            .iter()
+            .find(|reg| reg.id == id)
+            .map_or(0, |reg| reg.value::<u64, 8>())
+    };
+    let kreg_off = offset__of!(kvm_regs, regs);
+    let pstate = core_reg(kreg_off + offset__of!(user_pt_regs, pstate));
+    // Report the stack pointer in use, which is SP_EL1 when the guest kernel is running.
+    let sp = if pstate & PSR_MODE_MASK == PSR_MODE_EL1H {
+        core_reg(offset__of!(kvm_regs, sp_el1))
+    } else {
+        core_reg(kreg_off + offset__of!(user_pt_regs, sp))
+    };
+
+    // The registers in the order of `struct user_pt_regs`.
+    let mut user_regs = Vec::with_capacity(34);
+    for index in 0..31 {
+        user_regs.push(core_reg(
+            kreg_off + offset__of!(user_pt_regs, regs) + index * std::mem::size_of::<u64>(),
+        ));
+    }
+    user_regs.push(sp);
+    user_regs.push(core_reg(kreg_off + offset__of!(user_pt_regs, pc)));
+    user_regs.push(pstate);
+    put_note(buf, "CORE", NT_PRSTATUS, &prstatus(pid, &user_regs));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use utils::tempfile::TempFile;
+    use utils::vm_memory::GuestAddress;
+
+    use super::*;
+    use crate::builder::tests::default_vmm;
+
+    #[test]
+    fn test_put_note() {
+        let mut buf = Vec::new();
+        put_note(&mut buf, "CORE", NT_PRSTATUS, &[1, 2, 3, 4, 5]);
+        assert_eq!(
+            buf,
+            [
+                5, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0, b'C', b'O', b'R', b'E', 0, 0, 0, 0, 1, 2, 3, 4,
+                5, 0, 0, 0
+            ]
+        );
+
+        let desc = prstatus(3, &[7; 27]);
+        assert_eq!(desc.len(), PRSTATUS_REGS_OFFSET + 27 * 8 + 8);
+        assert_eq!(desc[PRSTATUS_PID_OFFSET], 3);
+        assert_eq!(desc[PRSTATUS_REGS_OFFSET], 7);
+    }
+
+    #[test]
+    fn test_elf_headers() {
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), 0x1000), (GuestAddress(0x10000), 0x2000)],
+            false,
+        )
+        .unwrap();
+        let (headers, memory_offset) = elf_headers(&guest_memory, 100);
+        assert_eq!(headers.len(), ELF_HEADER_SIZE + 3 * PROGRAM_HEADER_SIZE);
+        assert_eq!(memory_offset, MEMORY_ALIGN);
+        assert_eq!(&headers[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([headers[16], headers[17]]), ET_CORE);
+        // e_phnum
+        assert_eq!(u16::from_le_bytes([headers[56], headers[57]]), 3);
+
+        // The segment of the second region follows the first one, at its guest address.
+        let phdr = &headers[ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE..];
+        assert_eq!(u32::from_le_bytes(phdr[..4].try_into().unwrap()), PT_LOAD);
+        assert_eq!(
+            u64::from_le_bytes(phdr[8..16].try_into().unwrap()),
+            (MEMORY_ALIGN + 0x1000) as u64
+        );
+        assert_eq!(
+            u64::from_le_bytes(phdr[16..24].try_into().unwrap()),
+            0x10000
+        );
+        assert_eq!(u64::from_le_bytes(phdr[32..40].try_into().unwrap()), 0x2000);
+    }
+
+    #[test]
+    fn test_create_coredump() {
+        let mut vmm = default_vmm();
+        let dump_file = TempFile::new().unwrap();
+        let params = CoredumpParams {
+            dump_path: dump_file.as_path().to_path_buf(),
+        };
+        create_coredump(&mut vmm, &params).unwrap();
+
+        // Without vCPUs, the dump holds no notes.
+        let (headers, memory_offset) = elf_headers(vmm.guest_memory(), 0);
+        let mut file = File::open(dump_file.as_path()).unwrap();
+        let mut dump_headers = vec![0u8; headers.len()];
+        file.read_exact(&mut dump_headers).unwrap();
+        assert_eq!(dump_headers, headers);
+        assert_eq!(
+            file.metadata().unwrap().len(),
+            memory_offset as u64 + vmm.guest_memory().iter().map(|r| r.len()).sum::<u64>()
+        );
+    }
+}
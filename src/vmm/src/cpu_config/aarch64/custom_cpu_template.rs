@@ -9,7 +9,7 @@ use serde::de::Error;
 use serde::{Deserialize, Serialize};
 
 use crate::arch::aarch64::regs::{reg_size, RegSize};
-use crate::cpu_config::aarch64::static_cpu_templates::v1n1;
+use crate::cpu_config::aarch64::static_cpu_templates::{v1n1, v2n1};
 use crate::cpu_config::templates::{
     CpuTemplateType, GetCpuTemplate, GetCpuTemplateError, KvmCapability, RegisterValueFilter,
     StaticCpuTemplate,
@@ -24,6 +24,7 @@ impl GetCpuTemplate for Option<CpuTemplateType> {
                 CpuTemplateType::Static(template) => match template {
                     // TODO: Check if the CPU model is Neoverse-V1.
                     StaticCpuTemplate::V1N1 => Ok(Cow::Owned(v1n1::v1n1())),
+                    StaticCpuTemplate::V2N1 => Ok(Cow::Owned(v2n1::v2n1())),
                     other => Err(GetCpuTemplateError::InvalidStaticCpuTemplate(*other)),
                 },
             },
@@ -139,6 +140,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_cpu_template_with_v2n1_static_template() {
+        // Test `get_cpu_template()` when V2N1 static CPU template is specified. The owned
+        // `CustomCpuTemplate` should be returned.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::V2N1));
+        assert_eq!(
+            cpu_template.get_cpu_template().unwrap(),
+            Cow::Owned(v2n1::v2n1())
+        );
+    }
+
     #[test]
     fn test_get_cpu_tempalte_with_none_static_template() {
         // Test `get_cpu_template()` when no static CPU template is provided.
@@ -7,6 +7,8 @@ use versionize_derive::Versionize;
 
 /// Module with V1N1 CPU template for aarch64
 pub mod v1n1;
+/// Module with V2N1 CPU template for aarch64
+pub mod v2n1;
 
 /// Templates available for configuring the supported ARM CPU types.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Versionize)]
@@ -22,6 +24,8 @@ pub enum StaticCpuTemplate {
     /// No CPU template is used.
     #[default]
     None,
+    /// Template to mask Neoverse-V2 as Neoverse-N1
+    V2N1,
 }
 
 impl StaticCpuTemplate {
@@ -35,6 +39,7 @@ impl std::fmt::Display for StaticCpuTemplate {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             StaticCpuTemplate::V1N1 => write!(f, "V1N1"),
+            StaticCpuTemplate::V2N1 => write!(f, "V2N1"),
             StaticCpuTemplate::None => write!(f, "None"),
             _ => write!(f, "None"),
         }
@@ -48,7 +53,10 @@ mod tests {
 
     #[test]
     fn verify_consistency_with_json_templates() {
-        let static_templates = [(v1n1::v1n1(), "aarch64_v1n1.json")];
+        let static_templates = [
+            (v1n1::v1n1(), "aarch64_v1n1.json"),
+            (v2n1::v2n1(), "aarch64_v2n1.json"),
+        ];
 
         for (hardcoded_template, filename) in static_templates {
             let json_template = get_json_template(filename);
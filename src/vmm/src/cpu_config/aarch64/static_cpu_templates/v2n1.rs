@@ -0,0 +1,126 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::arch::aarch64::regs::{
+    ID_AA64ISAR0_EL1, ID_AA64ISAR1_EL1, ID_AA64ISAR2_EL1, ID_AA64MMFR2_EL1, ID_AA64PFR0_EL1,
+    ID_AA64PFR1_EL1,
+};
+use crate::cpu_config::aarch64::custom_cpu_template::{CustomCpuTemplate, RegisterModifier};
+use crate::cpu_config::templates::RegisterValueFilter;
+
+// Arm Armv8-A Architecture Registers documentation
+// https://developer.arm.com/documentation/ddi0595/2021-12/AArch64-Registers?lang=en
+
+/// Template to mask Neoverse-V2 as Neoverse-N1
+/// Masks: dgh, asimdfhm, bf16, dcpodp, flagm, i8mm, sha3, sha512, sm3, sm4
+/// sve, sve2 (and its extensions), uscat, fcma, jscvt, dit, ilrcpc, rng,
+/// paca, pacg, bti, mte, frint, sb, wfxt
+///
+/// Since it also masks everything masked by V1N1, a guest started with this
+/// template sees the same features on Neoverse-V1 and Neoverse-V2 hosts.
+pub fn v2n1() -> CustomCpuTemplate {
+    CustomCpuTemplate {
+        reg_modifiers: vec![
+            RegisterModifier {
+                // Disabling sve CPU feature. Setting to 0b0000.
+                // This disables sve, sve2 and all their extensions.
+                // sve occupies bits [35:32] in ID_AA64PFR0_EL1.
+                //
+                // Disabling dit CPU feature. Setting to 0b0000.
+                // dit occupies bits [51:48] in ID_AA64PFR0_EL1.
+                addr: ID_AA64PFR0_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0x000F000F00000000,
+                    value: 0x0000000000000000,
+                },
+            },
+            RegisterModifier {
+                // Disabling bti CPU feature. Setting to 0b0000.
+                // bt occupies bits [3:0] in ID_AA64PFR1_EL1.
+                //
+                // Disabling mte CPU feature. Setting to 0b0000.
+                // mte occupies bits [11:8] in ID_AA64PFR1_EL1.
+                addr: ID_AA64PFR1_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0x0000000000000F0F,
+                    value: 0x0000000000000000,
+                },
+            },
+            RegisterModifier {
+                // Same as for V1N1.
+                // Disabling sha3, sm3, sm4, asimdfhm (fhm), flagm (ts) and rng (rndr) CPU
+                // features. Setting them to 0b0000.
+                // Disabling sha512 CPU feature. Setting sha2 to 0b0001.
+                addr: ID_AA64ISAR0_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0xF0FF0FFF0000F000,
+                    value: 0x0000000000001000,
+                },
+            },
+            RegisterModifier {
+                // Disabling dcpodp (dpb) CPU feature. Setting to 0b0001.
+                // dpb occupies bits [3:0] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling paca CPU feature. Setting apa and api to 0b0000.
+                // apa occupies bits [7:4] in ID_AA64ISAR1_EL1.
+                // api occupies bits [11:8] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling jscvt CPU feature. Setting to 0b0000.
+                // jscvt occupies bits [15:12] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling fcma CPU feature. Setting to 0b0000.
+                // fcma occupies bits [19:16] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling ilrcpc CPU feature. Setting to 0b0001.
+                // lrcpc occupies bits [23:20] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling pacg CPU feature. Setting gpa and gpi to 0b0000.
+                // gpa occupies bits [27:24] in ID_AA64ISAR1_EL1.
+                // gpi occupies bits [31:28] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling frint CPU feature. Setting to 0b0000.
+                // frintts occupies bits [35:32] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling sb CPU feature. Setting to 0b0000.
+                // sb occupies bits [39:36] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling specres CPU feature. Setting to 0b0000.
+                // specres occupies bits [43:40] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling bf16 CPU feature. Setting to 0b0000.
+                // bf16 occupies bits [47:44] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling dgh CPU feature. Setting to 0b0000.
+                // dgh occupies bits [51:48] in ID_AA64ISAR1_EL1.
+                //
+                // Disabling i8mm CPU feature. Setting to 0b0000.
+                // i8mm occupies bits [55:52] in ID_AA64ISAR1_EL1.
+                addr: ID_AA64ISAR1_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0x00FFFFFFFFFFFFFF,
+                    value: 0x0000000000100001,
+                },
+            },
+            RegisterModifier {
+                // Neoverse-N1 implements none of the features described by ID_AA64ISAR2_EL1
+                // (wfxt, rpres, the QARMA3 pointer authentication algorithm, mops, bc, ...).
+                // Setting the whole register to 0.
+                addr: ID_AA64ISAR2_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0xFFFFFFFFFFFFFFFF,
+                    value: 0x0000000000000000,
+                },
+            },
+            RegisterModifier {
+                // Disable uscat (at) CPU feature. Setting to 0b0000.
+                // at occupies bits [35:32] in ID_AA64MMFR2_EL1.
+                addr: ID_AA64MMFR2_EL1,
+                bitmap: RegisterValueFilter {
+                    filter: 0x0000000F00000000,
+                    value: 0x0000000000000000,
+                },
+            },
+        ],
+        ..Default::default()
+    }
+}
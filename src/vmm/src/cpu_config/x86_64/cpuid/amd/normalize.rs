@@ -3,7 +3,7 @@
 
 use crate::cpu_config::x86_64::cpuid::common::{get_vendor_id_from_host, GetCpuidError};
 use crate::cpu_config::x86_64::cpuid::normalize::{
-    get_range, set_bit, set_range, CheckedAssignError,
+    get_range, set_bit, set_range, CheckedAssignError, MAX_CPUS_PER_PACKAGE,
 };
 use crate::cpu_config::x86_64::cpuid::{
     cpuid, cpuid_count, CpuidEntry, CpuidKey, CpuidRegisters, CpuidTrait, KvmCpuidFlags,
@@ -97,6 +97,9 @@ pub enum ExtendedApicIdError {
     /// Failed to set `threads_per_compute_unit`.
     #[error("Failed to set `threads_per_compute_unit`: {0}")]
     ThreadPerComputeUnit(CheckedAssignError),
+    /// Failed to set `node_id`.
+    #[error("Failed to set `node_id`: {0}")]
+    NodeId(CheckedAssignError),
 }
 
 // We use this 2nd implementation so we can conveniently define functions only used within
@@ -117,8 +120,8 @@ impl super::AmdCpuid {
     pub fn normalize(
         &mut self,
         // The index of the current logical CPU in the range [0..cpu_count].
-        cpu_index: u8,
-        // The total number of logical CPUs.
+        cpu_index: u16,
+        // The number of logical CPUs in the package.
         cpu_count: u8,
         // The number of logical CPUs per core.
         cpus_per_core: u8,
@@ -253,8 +256,7 @@ impl super::AmdCpuid {
         /// This value allows at most 64 logical threads within a package.
         const THREAD_ID_MAX_SIZE: u32 = 7;
 
-        // We don't support more then 128 threads right now.
-        // It's safe to put them all on the same processor.
+        // A package holds at most 128 threads, the remaining ones go to the next packages.
         let leaf_80000008 = self
             .get_mut(&CpuidKey::leaf(0x80000008))
             .ok_or(FeatureEntryError::MissingLeaf0x80000008)?;
@@ -352,7 +354,7 @@ impl super::AmdCpuid {
     #[allow(clippy::unwrap_used, clippy::unwrap_in_result)]
     fn update_extended_apic_id_entry(
         &mut self,
-        cpu_index: u8,
+        cpu_index: u16,
         cpus_per_core: u8,
     ) -> Result<(), ExtendedApicIdError> {
         /// 1 node per processor.
@@ -366,8 +368,15 @@ impl super::AmdCpuid {
         // logical CPU 2 -> core id: 1
         // logical CPU 3 -> core id: 1
         //
+        // The core ids restart from 0 in every package of `MAX_CPUS_PER_PACKAGE` logical CPUs.
+        //
         // SAFETY: We know `cpus_per_core != 0` therefore this is always safe.
-        let core_id = u32::from(cpu_index.checked_div(cpus_per_core).unwrap());
+        let core_id = u32::from(
+            (cpu_index % MAX_CPUS_PER_PACKAGE)
+                .checked_div(u16::from(cpus_per_core))
+                .unwrap(),
+        );
+        let node_id = u32::from(cpu_index / MAX_CPUS_PER_PACKAGE);
 
         let leaf_8000001e = self
             .get_mut(&CpuidKey::leaf(0x8000001e))
@@ -416,8 +425,9 @@ impl super::AmdCpuid {
         //
         // node_id: 0..8,
         //
-        // Put all the cpus in the same node.
-        set_range(&mut leaf_8000001e.result.ecx, 0..8, 0).unwrap();
+        // Each package holds a single node.
+        set_range(&mut leaf_8000001e.result.ecx, 0..8, node_id)
+            .map_err(ExtendedApicIdError::NodeId)?;
 
         Ok(())
     }
@@ -482,4 +492,27 @@ mod tests {
             0
         );
     }
+
+    #[test]
+    fn test_update_extended_apic_id_entry_packages() {
+        // The logical CPUs are spread over packages of 128 CPUs, each holding a single node.
+        let mut cpuid = AmdCpuid(BTreeMap::from([(
+            CpuidKey {
+                leaf: 0x8000001e,
+                subleaf: 0x0,
+            },
+            CpuidEntry {
+                flags: KvmCpuidFlags::EMPTY,
+                result: CpuidRegisters::default(),
+            },
+        )]));
+        cpuid.update_extended_apic_id_entry(301, 2).unwrap();
+        let result = &cpuid.get(&CpuidKey::leaf(0x8000001e)).unwrap().result;
+        assert_eq!(result.eax, 301);
+        // Compute unit id: (301 % 128) / 2.
+        assert_eq!(result.ebx & 0xff, 22);
+        assert_eq!((result.ebx >> 8) & 0xff, 1);
+        // Node id: 301 / 128.
+        assert_eq!(result.ecx & 0xff, 2);
+    }
 }
@@ -85,8 +85,8 @@ impl super::IntelCpuid {
     pub fn normalize(
         &mut self,
         // The index of the current logical CPU in the range [0..cpu_count].
-        _cpu_index: u8,
-        // The total number of logical CPUs.
+        _cpu_index: u16,
+        // The number of logical CPUs in the package.
         cpu_count: u8,
         // The number of logical CPUs per core.
         cpus_per_core: u8,
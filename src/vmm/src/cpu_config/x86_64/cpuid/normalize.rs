@@ -182,6 +182,10 @@ const fn mask(range: std::ops::Range<u8>) -> u32 {
     !front & back
 }
 
+/// The maximum number of logical CPUs in a package. It matches the APIC ID shift of the core
+/// level in leaf 0xB, above which the logical CPUs are placed in the next package.
+pub(crate) const MAX_CPUS_PER_PACKAGE: u16 = 1 << 7;
+
 // We use this 2nd implementation so we can conveniently define functions only used within
 // `normalize`.
 #[allow(clippy::multiple_inherent_impl)]
@@ -198,28 +202,33 @@ impl super::Cpuid {
     pub fn normalize(
         &mut self,
         // The index of the current logical CPU in the range [0..cpu_count].
-        cpu_index: u8,
+        cpu_index: u16,
         // The total number of logical CPUs.
-        cpu_count: u8,
+        cpu_count: u16,
         // The number of bits needed to enumerate logical CPUs per core.
         cpu_bits: u8,
     ) -> Result<(), NormalizeCpuidError> {
         let cpus_per_core = 1u8
             .checked_shl(u32::from(cpu_bits))
             .ok_or(NormalizeCpuidError::CpuBits(cpu_bits))?;
+        // The logical CPUs are spread over packages of at most `MAX_CPUS_PER_PACKAGE` CPUs, so
+        // the number of logical CPUs in a package always fits in a `u8`.
+        let package_cpu_count = std::cmp::min(cpu_count, MAX_CPUS_PER_PACKAGE) as u8;
         self.update_vendor_id()?;
-        self.update_feature_info_entry(cpu_index, cpu_count)?;
-        self.update_extended_topology_entry(cpu_index, cpu_count, cpu_bits, cpus_per_core)?;
+        self.update_feature_info_entry(cpu_index, package_cpu_count)?;
+        self.update_extended_topology_entry(cpu_index, package_cpu_count, cpu_bits, cpus_per_core)?;
         self.update_extended_cache_features()?;
 
         // Apply manufacturer specific modifications.
         match self {
             // Apply Intel specific modifications.
             Self::Intel(intel_cpuid) => {
-                intel_cpuid.normalize(cpu_index, cpu_count, cpus_per_core)?;
+                intel_cpuid.normalize(cpu_index, package_cpu_count, cpus_per_core)?;
             }
             // Apply AMD specific modifications.
-            Self::Amd(amd_cpuid) => amd_cpuid.normalize(cpu_index, cpu_count, cpus_per_core)?,
+            Self::Amd(amd_cpuid) => {
+                amd_cpuid.normalize(cpu_index, package_cpu_count, cpus_per_core)?;
+            }
         }
 
         Ok(())
@@ -244,7 +253,7 @@ impl super::Cpuid {
     // Update feature information entry
     fn update_feature_info_entry(
         &mut self,
-        cpu_index: u8,
+        cpu_index: u16,
         cpu_count: u8,
     ) -> Result<(), FeatureInformationError> {
         // Flush a cache line size.
@@ -281,10 +290,10 @@ impl super::Cpuid {
         // Initial APIC ID.
         //
         // The 8-bit initial APIC ID in EBX[31:24] is replaced by the 32-bit x2APIC ID,
-        // available in Leaf 0BH and Leaf 1FH.
+        // available in Leaf 0BH and Leaf 1FH. Only its low 8 bits fit here.
         //
         // initial_apic_id: 24..32,
-        set_range(&mut leaf_1.result.ebx, 24..32, u32::from(cpu_index))
+        set_range(&mut leaf_1.result.ebx, 24..32, u32::from(cpu_index & 0xff))
             .map_err(FeatureInformationError::InitialApicId)?;
 
         // CLFLUSH line size (Value ∗ 8 = cache line size in bytes; used also by CLFLUSHOPT).
@@ -328,7 +337,7 @@ impl super::Cpuid {
     /// Update extended topology entry
     fn update_extended_topology_entry(
         &mut self,
-        cpu_index: u8,
+        cpu_index: u16,
         cpu_count: u8,
         cpu_bits: u8,
         cpus_per_core: u8,
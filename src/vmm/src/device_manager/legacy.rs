@@ -30,7 +30,7 @@ pub enum LegacyDeviceError {
 }
 
 /// The `PortIODeviceManager` is a wrapper that is used for registering legacy devices
-/// on an I/O Bus. It currently manages the uart and i8042 devices.
+/// on an I/O Bus. It currently manages the uart, i8042 and ACPI sleep control devices.
 /// The `LegacyDeviceManger` should be initialized only by using the constructor.
 #[derive(Debug)]
 pub struct PortIODeviceManager {
@@ -39,6 +39,8 @@ pub struct PortIODeviceManager {
     pub stdio_serial: Arc<Mutex<BusDevice>>,
     // BusDevice::I8042Device
     pub i8042: Arc<Mutex<BusDevice>>,
+    // BusDevice::AcpiPmDevice
+    pub acpi_pm: Arc<Mutex<BusDevice>>,
 
     // Communication event on ports 1 & 3.
     pub com_evt_1_3: EventFdTrigger,
@@ -70,6 +72,8 @@ impl PortIODeviceManager {
     const I8042_KDB_DATA_REGISTER_ADDRESS: u64 = 0x060;
     /// i8042 keyboard data register size.
     const I8042_KDB_DATA_REGISTER_SIZE: u64 = 0x5;
+    /// ACPI sleep control register size.
+    const ACPI_SLEEP_REGISTER_SIZE: u64 = 0x1;
 
     /// Create a new DeviceManager handling legacy devices (uart, i8042).
     pub fn new(
@@ -89,6 +93,10 @@ impl PortIODeviceManager {
         let com_evt_2_4 = EventFdTrigger::new(EventFd::new(EFD_NONBLOCK)?);
         let kbd_evt = EventFd::new(libc::EFD_NONBLOCK)?;
 
+        // Powering off through ACPI stops the microVM the same way an i8042 reset does.
+        let acpi_pm = Arc::new(Mutex::new(BusDevice::AcpiPmDevice(
+            crate::devices::legacy::AcpiPmDevice::new(i8042_reset_evfd.try_clone()?),
+        )));
         let i8042 = Arc::new(Mutex::new(BusDevice::I8042Device(
             crate::devices::legacy::I8042Device::new(i8042_reset_evfd, kbd_evt.try_clone()?),
         )));
@@ -97,14 +105,16 @@ impl PortIODeviceManager {
             io_bus,
             stdio_serial: serial,
             i8042,
+            acpi_pm,
             com_evt_1_3,
             com_evt_2_4,
             kbd_evt,
         })
     }
 
-    /// Register supported legacy devices.
-    pub fn register_devices(&mut self, vm_fd: &VmFd) -> Result<(), LegacyDeviceError> {
+    /// Register supported legacy devices. The ACPI sleep control register is only described
+    /// to the guest by the ACPI tables, so it is only registered when `acpi` is set.
+    pub fn register_devices(&mut self, vm_fd: &VmFd, acpi: bool) -> Result<(), LegacyDeviceError> {
         let serial_2_4 = Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
             serial: Serial::with_events(
                 self.com_evt_2_4.try_clone()?.try_clone()?,
@@ -150,6 +160,13 @@ impl PortIODeviceManager {
             Self::I8042_KDB_DATA_REGISTER_ADDRESS,
             Self::I8042_KDB_DATA_REGISTER_SIZE,
         )?;
+        if acpi {
+            self.io_bus.insert(
+                self.acpi_pm.clone(),
+                crate::arch::x86_64::layout::ACPI_SLEEP_PORT,
+                Self::ACPI_SLEEP_REGISTER_SIZE,
+            )?;
+        }
 
         vm_fd
             .register_irqfd(&self.com_evt_1_3, Self::COM_EVT_1_3_GSI)
@@ -178,17 +195,8 @@ mod tests {
     use super::*;
     use crate::Vm;
 
-    #[test]
-    fn test_register_legacy_devices() {
-        let guest_mem = utils::vm_memory::test_utils::create_anon_guest_memory(
-            &[(GuestAddress(0x0), 0x1000)],
-            false,
-        )
-        .unwrap();
-        let mut vm = Vm::new(vec![]).unwrap();
-        vm.memory_init(&guest_mem, false).unwrap();
-        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
-        let mut ldm = PortIODeviceManager::new(
+    fn legacy_device_manager() -> PortIODeviceManager {
+        PortIODeviceManager::new(
             Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
                 serial: Serial::with_events(
                     EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
@@ -201,7 +209,33 @@ mod tests {
             }))),
             EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_register_legacy_devices() {
+        let guest_mem = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0x0), 0x1000)],
+            false,
+        )
         .unwrap();
-        assert!(ldm.register_devices(vm.fd()).is_ok());
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.memory_init(&guest_mem, false).unwrap();
+        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
+
+        let mut ldm = legacy_device_manager();
+        assert!(ldm.register_devices(vm.fd(), false).is_ok());
+        // The ACPI sleep control register is only registered along with the ACPI tables.
+        assert!(ldm
+            .io_bus
+            .get_device(crate::arch::x86_64::layout::ACPI_SLEEP_PORT)
+            .is_none());
+
+        let mut ldm = legacy_device_manager();
+        assert!(ldm.register_devices(vm.fd(), true).is_ok());
+        assert!(ldm
+            .io_bus
+            .get_device(crate::arch::x86_64::layout::ACPI_SLEEP_PORT)
+            .is_some());
     }
 }
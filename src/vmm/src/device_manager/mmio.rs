@@ -20,16 +20,22 @@ use vm_allocator::{AddressAllocator, AllocPolicy, IdAllocator};
 
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::DeviceInfoForFDT;
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::acpi::DeviceInfoForAcpi;
 use crate::arch::DeviceType;
 use crate::arch::DeviceType::Virtio;
+#[cfg(target_arch = "x86_64")]
+use crate::devices::legacy::IoApic;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::RTCDevice;
 use crate::devices::pseudo::BootTimer;
+use crate::devices::tpm::Tpm;
 use crate::devices::virtio::{
-    Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
-    TYPE_RNG, TYPE_VSOCK,
+    Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, VirtioMem, Vsock, VsockUnixBackend,
+    TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_RNG, TYPE_VSOCK,
 };
 use crate::devices::BusDevice;
+use crate::vmm_config::machine_config::MmioLayout;
 
 /// Errors for MMIO device manager.
 #[derive(Debug, thiserror::Error)]
@@ -61,6 +67,9 @@ pub enum MmioError {
     /// Registering an IRQ FD failed.
     #[error("Failed to register irqfd: {0}")]
     RegisterIrqFd(kvm_ioctls::Error),
+    /// All the device slots of the MMIO layout are in use.
+    #[error("All the {0} MMIO device slots are in use.")]
+    SlotsExhausted(u32),
 }
 
 /// This represents the size of the mmio device specified to the kernel as a cmdline option
@@ -88,6 +97,14 @@ pub struct MMIODeviceManager {
     pub(crate) irq_allocator: IdAllocator,
     pub(crate) address_allocator: AddressAllocator,
     pub(crate) id_to_dev_info: HashMap<(DeviceType, String), MMIODeviceInfo>,
+    // Size of the MMIO range allocated to each device.
+    slot_size: u64,
+    // Maximum number of devices, past the number of IRQ lines of which the lines are shared.
+    slot_count: Option<u32>,
+    used_slots: u32,
+    irq_range: (u32, u32),
+    // Number of IRQ lines handed out once all of them were in use.
+    shared_irqs: u32,
 }
 
 impl MMIODeviceManager {
@@ -103,27 +120,65 @@ impl MMIODeviceManager {
                 .map_err(MmioError::Allocator)?,
             bus: crate::devices::Bus::new(),
             id_to_dev_info: HashMap::new(),
+            slot_size: MMIO_LEN,
+            slot_count: None,
+            used_slots: 0,
+            irq_range: (irq_start, irq_end),
+            shared_irqs: 0,
         })
     }
 
+    /// Create a new DeviceManager whose devices are laid out according to `layout`.
+    pub fn with_layout(layout: &MmioLayout) -> Result<MMIODeviceManager, MmioError> {
+        let (mmio_base, mmio_size) = layout.mmio_region();
+        let mut device_manager = Self::new(
+            mmio_base,
+            mmio_size,
+            (crate::arch::IRQ_BASE, crate::arch::IRQ_MAX),
+        )?;
+        device_manager.slot_size = layout.slot_size;
+        device_manager.slot_count = layout.slot_count;
+        Ok(device_manager)
+    }
+
     /// Allocates resources for a new device to be added.
     fn allocate_mmio_resources(&mut self, irq_count: u32) -> Result<MMIODeviceInfo, MmioError> {
+        if let Some(slot_count) = self.slot_count {
+            if self.used_slots >= slot_count {
+                return Err(MmioError::SlotsExhausted(slot_count));
+            }
+        }
         let irqs = (0..irq_count)
-            .map(|_| self.irq_allocator.allocate_id())
-            .collect::<vm_allocator::Result<_>>()
-            .map_err(MmioError::Allocator)?;
+            .map(|_| self.allocate_irq())
+            .collect::<Result<_, _>>()?;
         let device_info = MMIODeviceInfo {
             addr: self
                 .address_allocator
-                .allocate(MMIO_LEN, MMIO_LEN, AllocPolicy::FirstMatch)
+                .allocate(self.slot_size, self.slot_size, AllocPolicy::FirstMatch)
                 .map_err(MmioError::Allocator)?
                 .start(),
-            len: MMIO_LEN,
+            len: self.slot_size,
             irqs,
         };
+        self.used_slots += 1;
         Ok(device_info)
     }
 
+    /// Allocates an IRQ line. Once all of them are in use, the lines are shared in turn when
+    /// the slot count is set, which is supported by the virtio-mmio driver of the guest.
+    fn allocate_irq(&mut self) -> Result<u32, MmioError> {
+        match self.irq_allocator.allocate_id() {
+            Ok(irq) => Ok(irq),
+            Err(_) if self.slot_count.is_some() => {
+                let (irq_start, irq_end) = self.irq_range;
+                let irq = irq_start + self.shared_irqs % (irq_end - irq_start + 1);
+                self.shared_irqs += 1;
+                Ok(irq)
+            }
+            Err(err) => Err(MmioError::Allocator(err)),
+        }
+    }
+
     /// Register a device at some MMIO address.
     fn register_mmio_device(
         &mut self,
@@ -298,6 +353,49 @@ impl MMIODeviceManager {
         )
     }
 
+    /// Register a TPM device at the specified MMIO configuration if given as parameter,
+    /// otherwise allocate new MMIO resources for it. The TPM does not use interrupts.
+    pub fn register_mmio_tpm(
+        &mut self,
+        tpm: Tpm,
+        device_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<(), MmioError> {
+        let device_info = match device_info_opt {
+            Some(device_info) => device_info,
+            None => self.allocate_mmio_resources(0)?,
+        };
+
+        let identifier = (DeviceType::Tpm, DeviceType::Tpm.to_string());
+        self.register_mmio_device(
+            identifier,
+            device_info,
+            Arc::new(Mutex::new(BusDevice::Tpm(tpm))),
+        )
+    }
+
+    /// Register the userspace IOAPIC at its architectural address. It is described to the guest
+    /// by the MP table and the MADT, so it is not tracked along with the other devices.
+    #[cfg(target_arch = "x86_64")]
+    pub fn register_mmio_ioapic(&mut self, ioapic: IoApic) -> Result<(), MmioError> {
+        use crate::arch::x86_64::layout::{IOAPIC_SIZE, IOAPIC_START};
+
+        // Reserve the range so that no other device is allocated on top of the IOAPIC.
+        self.address_allocator
+            .allocate(
+                IOAPIC_SIZE,
+                IOAPIC_SIZE,
+                AllocPolicy::ExactMatch(IOAPIC_START),
+            )
+            .map_err(MmioError::Allocator)?;
+        self.bus
+            .insert(
+                Arc::new(Mutex::new(BusDevice::IoApic(ioapic))),
+                IOAPIC_START,
+                IOAPIC_SIZE,
+            )
+            .map_err(MmioError::BusInsert)
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -449,6 +547,37 @@ impl MMIODeviceManager {
                             entropy.process_virtio_queues();
                         }
                     }
+                    TYPE_MEM => {
+                        let mem = virtio.as_mut_any().downcast_mut::<VirtioMem>().unwrap();
+                        if mem.is_activated() {
+                            info!("kick memory hotplug {id}.");
+                            mem.process_virtio_queues();
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(())
+            });
+    }
+
+    /// Keeps the net and vsock devices serving the host while the vCPUs are paused, or stops
+    /// doing so when they run again.
+    pub fn set_pause_lite(&self, pause_lite: bool) {
+        let _: Result<(), MmioError> =
+            self.for_each_virtio_device(|virtio_type, _id, _info, dev| {
+                let mut virtio = dev.lock().expect("Poisoned lock");
+                match virtio_type {
+                    TYPE_NET => {
+                        let net = virtio.as_mut_any().downcast_mut::<Net>().unwrap();
+                        net.set_pause_lite(pause_lite);
+                    }
+                    TYPE_VSOCK => {
+                        let vsock = virtio
+                            .as_mut_any()
+                            .downcast_mut::<Vsock<VsockUnixBackend>>()
+                            .unwrap();
+                        vsock.set_pause_lite(pause_lite);
+                    }
                     _ => (),
                 }
                 Ok(())
@@ -469,6 +598,19 @@ impl DeviceInfoForFDT for MMIODeviceInfo {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+impl DeviceInfoForAcpi for MMIODeviceInfo {
+    fn addr(&self) -> u64 {
+        self.addr
+    }
+    fn irq(&self) -> u32 {
+        self.irqs[0]
+    }
+    fn length(&self) -> u64 {
+        self.len
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::AtomicUsize;
@@ -601,13 +743,66 @@ mod tests {
         #[cfg(target_arch = "x86_64")]
         assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
         #[cfg(target_arch = "aarch64")]
-        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, false).is_ok());
 
         assert!(device_manager
             .register_virtio_test_device(vm.fd(), guest_mem, dummy, &mut cmdline, "dummy")
             .is_ok());
     }
 
+    #[test]
+    fn test_register_tpm() {
+        let mut device_manager = MMIODeviceManager::new(
+            0xd000_0000,
+            crate::arch::MMIO_MEM_SIZE,
+            (crate::arch::IRQ_BASE, crate::arch::IRQ_MAX),
+        )
+        .unwrap();
+        let (tpm, _mock) = crate::devices::tpm::tests::default_tpm();
+        device_manager.register_mmio_tpm(tpm, None).unwrap();
+
+        let device_info = device_manager
+            .get_device_info()
+            .get(&(DeviceType::Tpm, DeviceType::Tpm.to_string()))
+            .unwrap();
+        assert_eq!(device_info.addr, 0xd000_0000);
+        assert!(device_info.irqs.is_empty());
+        assert!(device_manager
+            .get_device(DeviceType::Tpm, &DeviceType::Tpm.to_string())
+            .unwrap()
+            .lock()
+            .unwrap()
+            .tpm_ref()
+            .is_some());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_register_ioapic() {
+        use crate::arch::x86_64::layout::IOAPIC_START;
+
+        let vm = Vm::new(vec![]).unwrap();
+        let mut device_manager = MMIODeviceManager::new(
+            crate::arch::MMIO_MEM_START,
+            crate::arch::MMIO_MEM_SIZE,
+            (crate::arch::IRQ_BASE, crate::arch::IRQ_MAX),
+        )
+        .unwrap();
+        device_manager
+            .register_mmio_ioapic(IoApic::new(vm.shared_fd(), 2))
+            .unwrap();
+
+        let (offset, device) = device_manager.bus.get_device(IOAPIC_START + 0x10).unwrap();
+        assert_eq!(offset, 0x10);
+        assert!(device.lock().unwrap().ioapic_ref().is_some());
+        // The IOAPIC is not one of the devices described to the guest through MMIO.
+        assert!(device_manager.get_device_info().is_empty());
+        // Its range is reserved.
+        assert!(device_manager
+            .register_mmio_ioapic(IoApic::new(vm.shared_fd(), 2))
+            .is_err());
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
@@ -630,7 +825,7 @@ mod tests {
         #[cfg(target_arch = "x86_64")]
         assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
         #[cfg(target_arch = "aarch64")]
-        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, false).is_ok());
 
         for _i in crate::arch::IRQ_BASE..=crate::arch::IRQ_MAX {
             device_manager
@@ -685,7 +880,7 @@ mod tests {
         #[cfg(target_arch = "x86_64")]
         assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
         #[cfg(target_arch = "aarch64")]
-        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, false).is_ok());
 
         let mut device_manager = MMIODeviceManager::new(
             0xd000_0000,
@@ -777,4 +972,37 @@ mod tests {
         );
         assert!(device_manager.allocate_mmio_resources(0).is_ok());
     }
+
+    #[test]
+    fn test_layout_slot_allocation() {
+        let irq_count = crate::arch::IRQ_MAX - crate::arch::IRQ_BASE + 1;
+        let layout = MmioLayout {
+            slot_size: 0x10000,
+            slot_count: Some(irq_count + 2),
+            gap_size_mib: None,
+        };
+        let mut device_manager = MMIODeviceManager::with_layout(&layout).unwrap();
+
+        let device_info = device_manager.allocate_mmio_resources(1).unwrap();
+        assert_eq!(device_info.addr, crate::arch::MMIO_MEM_START);
+        assert_eq!(device_info.len, 0x10000);
+        assert_eq!(device_info.irqs, vec![crate::arch::IRQ_BASE]);
+        for _ in 1..irq_count {
+            device_manager.allocate_mmio_resources(1).unwrap();
+        }
+        // Once all the IRQ lines are in use, they are shared.
+        let device_info = device_manager.allocate_mmio_resources(1).unwrap();
+        assert_eq!(
+            device_info.addr,
+            crate::arch::MMIO_MEM_START + 0x10000 * u64::from(irq_count)
+        );
+        assert_eq!(device_info.irqs, vec![crate::arch::IRQ_BASE]);
+        let device_info = device_manager.allocate_mmio_resources(1).unwrap();
+        assert_eq!(device_info.irqs, vec![crate::arch::IRQ_BASE + 1]);
+
+        assert_eq!(
+            format!("{}", device_manager.allocate_mmio_resources(0).unwrap_err()),
+            format!("All the {} MMIO device slots are in use.", irq_count + 2)
+        );
+    }
 }
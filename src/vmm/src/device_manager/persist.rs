@@ -19,10 +19,16 @@ use vm_allocator::AllocPolicy;
 use super::mmio::*;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::DeviceType;
+use crate::devices::tpm::persist::TpmState;
+use crate::devices::tpm::{Tpm, TpmError};
 use crate::devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use crate::devices::virtio::balloon::{Balloon, BalloonError};
 use crate::devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
 use crate::devices::virtio::block::{Block, BlockError};
+use crate::devices::virtio::mem::persist::{
+    VirtioMemConstructorArgs, VirtioMemPersistError as MemoryHotplugError, VirtioMemState,
+};
+use crate::devices::virtio::mem::VirtioMem;
 use crate::devices::virtio::net::persist::{
     NetConstructorArgs, NetPersistError as NetError, NetState,
 };
@@ -37,8 +43,9 @@ use crate::devices::virtio::vsock::persist::{
 };
 use crate::devices::virtio::vsock::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
 use crate::devices::virtio::{
-    MmioTransport, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG, TYPE_VSOCK,
+    MmioTransport, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_RNG, TYPE_VSOCK,
 };
+use crate::io_threads::IoThreadsBuilder;
 use crate::resources::VmResources;
 use crate::vmm_config::mmds::MmdsConfigError;
 use crate::EventManager;
@@ -57,6 +64,8 @@ pub enum DevicePersistError {
     VsockUnixBackend(VsockUnixBackendError),
     MmdsConfig(MmdsConfigError),
     Entropy(EntropyError),
+    MemoryHotplug(MemoryHotplugError),
+    Tpm(TpmError),
 }
 
 /// Holds the state of a balloon device connected to the MMIO space.
@@ -129,6 +138,30 @@ pub struct ConnectedEntropyState {
     pub device_info: MMIODeviceInfo,
 }
 
+/// Holds the state of a memory hotplug device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Debug, Clone, Versionize)]
+pub struct ConnectedMemoryHotplugState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: VirtioMemState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+}
+
+/// Holds the state of a TPM device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Debug, Clone, Versionize)]
+pub struct ConnectedTpmState {
+    /// Device state.
+    pub device_state: TpmState,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+}
+
 /// Holds the state of a legacy device connected to the MMIO space.
 #[cfg(target_arch = "aarch64")]
 #[derive(Debug, Clone, Versionize)]
@@ -187,6 +220,12 @@ pub struct DeviceStates {
     /// Entropy device state.
     #[version(start = 4, ser_fn = "entropy_serialize")]
     pub entropy_device: Option<ConnectedEntropyState>,
+    /// Memory hotplug device state.
+    #[version(start = 5, ser_fn = "memory_hotplug_serialize")]
+    pub memory_hotplug_device: Option<ConnectedMemoryHotplugState>,
+    /// TPM device state.
+    #[version(start = 5, ser_fn = "tpm_serialize")]
+    pub tpm_device: Option<ConnectedTpmState>,
 }
 
 /// A type used to extract the concrete Arc<Mutex<T>> for each of the device types when restoring
@@ -198,6 +237,7 @@ pub enum SharedDeviceType {
     Balloon(Arc<Mutex<Balloon>>),
     Vsock(Arc<Mutex<Vsock<VsockUnixBackend>>>),
     Entropy(Arc<Mutex<Entropy>>),
+    MemoryHotplug(Arc<Mutex<VirtioMem>>),
 }
 
 impl DeviceStates {
@@ -231,12 +271,33 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    fn memory_hotplug_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 5 && self.memory_hotplug_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not support persisting the virtio-mem device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn tpm_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 5 && self.tpm_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not support persisting the TPM device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
     pub mem: GuestMemoryMmap,
     pub vm: &'a VmFd,
     pub event_manager: &'a mut EventManager,
+    pub io_threads: &'a mut IoThreadsBuilder,
     pub for_each_restored_device: fn(&mut VmResources, SharedDeviceType),
     pub vm_resources: &'a mut VmResources,
     pub instance_id: &'a str,
@@ -247,6 +308,7 @@ impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
             .field("mem", &self.mem)
             .field("vm", &self.vm)
             .field("event_manager", &"?")
+            .field("io_threads", &self.io_threads)
             .field("for_each_restored_device", &"?")
             .field("vm_resources", &self.vm_resources)
             .field("instance_id", &self.instance_id)
@@ -269,6 +331,8 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             legacy_devices: Vec::new(),
             mmds_version: None,
             entropy_device: None,
+            memory_hotplug_device: None,
+            tpm_device: None,
         };
         let _: Result<(), ()> = self.for_each_device(|devtype, devid, device_info, bus_dev| {
             if *devtype == crate::arch::DeviceType::BootTimer {
@@ -276,6 +340,18 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 return Ok(());
             }
 
+            if *devtype == crate::arch::DeviceType::Tpm {
+                let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
+                states.tpm_device = Some(ConnectedTpmState {
+                    device_state: locked_bus_dev
+                        .tpm_ref()
+                        .expect("Unexpected device type")
+                        .save(),
+                    device_info: device_info.clone(),
+                });
+                return Ok(());
+            }
+
             #[cfg(target_arch = "aarch64")]
             {
                 if *devtype == DeviceType::Serial || *devtype == DeviceType::Rtc {
@@ -376,6 +452,19 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         device_info: device_info.clone(),
                     });
                 }
+                TYPE_MEM => {
+                    let mem = locked_device
+                        .as_mut_any()
+                        .downcast_mut::<VirtioMem>()
+                        .unwrap();
+
+                    states.memory_hotplug_device = Some(ConnectedMemoryHotplugState {
+                        device_id: devid.clone(),
+                        device_state: mem.save(),
+                        transport_state,
+                        device_info: device_info.clone(),
+                    });
+                }
                 _ => unreachable!(),
             };
 
@@ -388,10 +477,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
-        let mut dev_manager = MMIODeviceManager::new(
-            crate::arch::MMIO_MEM_START,
-            crate::arch::MMIO_MEM_SIZE,
-            (crate::arch::IRQ_BASE, crate::arch::IRQ_MAX),
+        let mut dev_manager = MMIODeviceManager::with_layout(
+            &constructor_args
+                .vm_resources
+                .vm_config
+                .mmio_layout
+                .unwrap_or_default(),
         )
         .map_err(Self::Error::DeviceManager)?;
         let mem = &constructor_args.mem;
@@ -410,8 +501,8 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                     dev_manager
                         .address_allocator
                         .allocate(
-                            MMIO_LEN,
-                            MMIO_LEN,
+                            state.device_info.len,
+                            state.device_info.len,
                             AllocPolicy::ExactMatch(state.device_info.addr),
                         )
                         .map_err(|e| {
@@ -431,8 +522,8 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                     dev_manager
                         .address_allocator
                         .allocate(
-                            MMIO_LEN,
-                            MMIO_LEN,
+                            state.device_info.len,
+                            state.device_info.len,
                             AllocPolicy::ExactMatch(state.device_info.addr),
                         )
                         .map_err(|e| {
@@ -443,8 +534,10 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             }
         }
 
+        // The subscriber is `None` for the devices which can be assigned to an I/O thread, they are
+        // subscribed by the caller.
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
-                                  as_subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
+                                  as_subscriber: Option<Arc<Mutex<dyn MutEventSubscriber>>>,
                                   id: &String,
                                   state: &MmioTransportState,
                                   device_info: &MMIODeviceInfo,
@@ -470,8 +563,8 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             dev_manager
                 .address_allocator
                 .allocate(
-                    MMIO_LEN,
-                    MMIO_LEN,
+                    device_info.len,
+                    device_info.len,
                     AllocPolicy::ExactMatch(device_info.addr),
                 )
                 .map_err(|e| {
@@ -480,7 +573,9 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             dev_manager.register_mmio_virtio(vm, id.clone(), mmio_transport, device_info)?;
 
-            event_manager.add_subscriber(as_subscriber);
+            if let Some(as_subscriber) = as_subscriber {
+                event_manager.add_subscriber(as_subscriber);
+            }
             Ok(())
         };
 
@@ -497,7 +592,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                Some(device),
                 &balloon_state.device_id,
                 &balloon_state.transport_state,
                 &balloon_state.device_info,
@@ -518,12 +613,17 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                None,
                 &block_state.device_id,
                 &block_state.transport_state,
                 &block_state.device_info,
                 constructor_args.event_manager,
             )?;
+            constructor_args.io_threads.add_subscriber(
+                &block_state.device_id,
+                device,
+                constructor_args.event_manager,
+            );
         }
 
         // If the snapshot has the mmds version persisted, initialise the data store with it.
@@ -561,14 +661,27 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 SharedDeviceType::Network(device.clone()),
             );
 
+            let user_net = device.lock().expect("Poisoned lock").user_net().cloned();
             restore_helper(
                 device.clone(),
-                device,
+                None,
                 &net_state.device_id,
                 &net_state.transport_state,
                 &net_state.device_info,
                 constructor_args.event_manager,
             )?;
+            constructor_args.io_threads.add_subscriber(
+                &net_state.device_id,
+                device,
+                constructor_args.event_manager,
+            );
+            if let Some(user_net) = user_net {
+                constructor_args.io_threads.add_subscriber(
+                    &net_state.device_id,
+                    user_net,
+                    constructor_args.event_manager,
+                );
+            }
         }
 
         if let Some(vsock_state) = &state.vsock_device {
@@ -591,7 +704,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                Some(device),
                 &vsock_state.device_id,
                 &vsock_state.transport_state,
                 &vsock_state.device_info,
@@ -614,7 +727,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                Some(device),
                 &entropy_state.device_id,
                 &entropy_state.transport_state,
                 &entropy_state.device_info,
@@ -622,6 +735,44 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        if let Some(memory_hotplug_state) = &state.memory_hotplug_device {
+            let ctor_args = VirtioMemConstructorArgs { mem: mem.clone() };
+
+            let device = Arc::new(Mutex::new(VirtioMem::restore(
+                ctor_args,
+                &memory_hotplug_state.device_state,
+            )?));
+
+            (constructor_args.for_each_restored_device)(
+                constructor_args.vm_resources,
+                SharedDeviceType::MemoryHotplug(device.clone()),
+            );
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &memory_hotplug_state.device_id,
+                &memory_hotplug_state.transport_state,
+                &memory_hotplug_state.device_info,
+                constructor_args.event_manager,
+            )?;
+        }
+
+        if let Some(tpm_state) = &state.tpm_device {
+            let tpm = Tpm::restore((), &tpm_state.device_state)?;
+            dev_manager
+                .address_allocator
+                .allocate(
+                    tpm_state.device_info.len,
+                    tpm_state.device_info.len,
+                    AllocPolicy::ExactMatch(tpm_state.device_info.addr),
+                )
+                .map_err(|e| {
+                    DevicePersistError::DeviceManager(super::mmio::MmioError::Allocator(e))
+                })?;
+            dev_manager.register_mmio_tpm(tpm, Some(tpm_state.device_info.clone()))?;
+        }
+
         Ok(dev_manager)
     }
 }
@@ -668,12 +819,19 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedTpmState {
+        fn eq(&self, other: &ConnectedTpmState) -> bool {
+            self.device_state == other.device_state && self.device_info == other.device_info
+        }
+    }
+
     impl PartialEq for DeviceStates {
         fn eq(&self, other: &DeviceStates) -> bool {
             self.balloon_device == other.balloon_device
                 && self.block_devices == other.block_devices
                 && self.net_devices == other.net_devices
                 && self.vsock_device == other.vsock_device
+                && self.tpm_device == other.tpm_device
         }
     }
 
@@ -728,8 +886,10 @@ mod tests {
             // Add a balloon device.
             let balloon_cfg = BalloonDeviceConfig {
                 amount_mib: 123,
+                amount_bytes: None,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 1,
+                free_page_hinting: false,
             };
             insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
             // Add a block device.
@@ -750,6 +910,12 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                capture: None,
+                dhcp: None,
+                user_net: None,
+                pause_responder: None,
+                busy_poll_us: None,
+                batch_size: None,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -762,8 +928,13 @@ mod tests {
             let vsock_dev_id = "vsock";
             let vsock_config = VsockDeviceConfig {
                 vsock_id: Some(vsock_dev_id.to_string()),
-                guest_cid: 3,
+                guest_cid: Some(3),
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                clock_port: None,
+                cid_lock_dir: None,
+                listen_backlog: None,
+                max_connections: None,
+                idle_timeout_s: None,
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
 
@@ -850,6 +1021,7 @@ mod tests {
             mem: vmm.guest_memory().clone(),
             vm: vmm.vm.fd(),
             event_manager: &mut event_manager,
+            io_threads: &mut IoThreadsBuilder::new(None).unwrap(),
             for_each_restored_device: VmResources::update_from_restored_device,
             vm_resources,
             instance_id: "microvm-id",
@@ -873,7 +1045,14 @@ mod tests {
       "is_read_only": true,
       "cache_type": "Unsafe",
       "rate_limiter": null,
-      "io_engine": "Sync"
+      "io_engine": "Sync",
+      "direct_io": false,
+      "fadvise": "Normal",
+      "readahead_kib": 0,
+      "io_uring_entries": 128,
+      "max_in_flight_requests": 0,
+      "busy_poll_us": 0,
+      "batch_size": 0
     }}
   ],
   "boot-source": {{
@@ -886,7 +1065,10 @@ mod tests {
     "vcpu_count": 1,
     "mem_size_mib": 128,
     "smt": false,
-    "track_dirty_pages": false
+    "track_dirty_pages": false,
+    "acpi": false,
+    "split_irqchip": false,
+    "gic_its": false
   }},
   "metrics": null,
   "mmds-config": {{
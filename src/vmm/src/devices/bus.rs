@@ -55,18 +55,26 @@ use event_manager::{EventOps, Events, MutEventSubscriber};
 
 #[cfg(target_arch = "aarch64")]
 use super::legacy::RTCDevice;
+#[cfg(target_arch = "x86_64")]
+use super::legacy::{AcpiPmDevice, IoApic};
 use super::legacy::{I8042Device, SerialDevice};
 use super::pseudo::BootTimer;
+use super::tpm::Tpm;
 use super::virtio::MmioTransport;
 
 #[derive(Debug)]
 pub enum BusDevice {
+    #[cfg(target_arch = "x86_64")]
+    AcpiPmDevice(AcpiPmDevice),
     I8042Device(I8042Device),
+    #[cfg(target_arch = "x86_64")]
+    IoApic(IoApic),
     #[cfg(target_arch = "aarch64")]
     RTCDevice(RTCDevice),
     BootTimer(BootTimer),
     MmioTransport(MmioTransport),
     Serial(SerialDevice<std::io::Stdin>),
+    Tpm(Tpm),
     #[cfg(test)]
     Dummy(DummyDevice),
     #[cfg(test)]
@@ -103,12 +111,26 @@ impl ConstantDevice {
 }
 
 impl BusDevice {
+    #[cfg(target_arch = "x86_64")]
+    pub fn acpi_pm_device_ref(&self) -> Option<&AcpiPmDevice> {
+        match self {
+            Self::AcpiPmDevice(x) => Some(x),
+            _ => None,
+        }
+    }
     pub fn i8042_device_ref(&self) -> Option<&I8042Device> {
         match self {
             Self::I8042Device(x) => Some(x),
             _ => None,
         }
     }
+    #[cfg(target_arch = "x86_64")]
+    pub fn ioapic_ref(&self) -> Option<&IoApic> {
+        match self {
+            Self::IoApic(x) => Some(x),
+            _ => None,
+        }
+    }
     #[cfg(target_arch = "aarch64")]
     pub fn rtc_device_ref(&self) -> Option<&RTCDevice> {
         match self {
@@ -134,13 +156,33 @@ impl BusDevice {
             _ => None,
         }
     }
+    pub fn tpm_ref(&self) -> Option<&Tpm> {
+        match self {
+            Self::Tpm(x) => Some(x),
+            _ => None,
+        }
+    }
 
+    #[cfg(target_arch = "x86_64")]
+    pub fn acpi_pm_device_mut(&mut self) -> Option<&mut AcpiPmDevice> {
+        match self {
+            Self::AcpiPmDevice(x) => Some(x),
+            _ => None,
+        }
+    }
     pub fn i8042_device_mut(&mut self) -> Option<&mut I8042Device> {
         match self {
             Self::I8042Device(x) => Some(x),
             _ => None,
         }
     }
+    #[cfg(target_arch = "x86_64")]
+    pub fn ioapic_mut(&mut self) -> Option<&mut IoApic> {
+        match self {
+            Self::IoApic(x) => Some(x),
+            _ => None,
+        }
+    }
     #[cfg(target_arch = "aarch64")]
     pub fn rtc_device_mut(&mut self) -> Option<&mut RTCDevice> {
         match self {
@@ -166,15 +208,26 @@ impl BusDevice {
             _ => None,
         }
     }
+    pub fn tpm_mut(&mut self) -> Option<&mut Tpm> {
+        match self {
+            Self::Tpm(x) => Some(x),
+            _ => None,
+        }
+    }
 
     pub fn read(&mut self, offset: u64, data: &mut [u8]) {
         match self {
+            #[cfg(target_arch = "x86_64")]
+            Self::AcpiPmDevice(x) => x.bus_read(offset, data),
             Self::I8042Device(x) => x.bus_read(offset, data),
+            #[cfg(target_arch = "x86_64")]
+            Self::IoApic(x) => x.bus_read(offset, data),
             #[cfg(target_arch = "aarch64")]
             Self::RTCDevice(x) => x.bus_read(offset, data),
             Self::BootTimer(x) => x.bus_read(offset, data),
             Self::MmioTransport(x) => x.bus_read(offset, data),
             Self::Serial(x) => x.bus_read(offset, data),
+            Self::Tpm(x) => x.bus_read(offset, data),
             #[cfg(test)]
             Self::Dummy(x) => x.bus_read(offset, data),
             #[cfg(test)]
@@ -184,12 +237,17 @@ impl BusDevice {
 
     pub fn write(&mut self, offset: u64, data: &[u8]) {
         match self {
+            #[cfg(target_arch = "x86_64")]
+            Self::AcpiPmDevice(x) => x.bus_write(offset, data),
             Self::I8042Device(x) => x.bus_write(offset, data),
+            #[cfg(target_arch = "x86_64")]
+            Self::IoApic(x) => x.bus_write(offset, data),
             #[cfg(target_arch = "aarch64")]
             Self::RTCDevice(x) => x.bus_write(offset, data),
             Self::BootTimer(x) => x.bus_write(offset, data),
             Self::MmioTransport(x) => x.bus_write(offset, data),
             Self::Serial(x) => x.bus_write(offset, data),
+            Self::Tpm(x) => x.bus_write(offset, data),
             #[cfg(test)]
             Self::Dummy(x) => x.bus_write(offset, data),
             #[cfg(test)]
@@ -0,0 +1,68 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use utils::eventfd::EventFd;
+
+use crate::arch::x86_64::acpi::S5_SLEEP_TYPE;
+
+// Layout of the sleep control register, see ACPI 6.5 section 4.8.3.7.
+const SLP_TYP_SHIFT: u8 = 2;
+const SLP_TYP_MASK: u8 = 0b111;
+const SLP_EN: u8 = 1 << 5;
+
+/// ACPI sleep control register of a hardware-reduced ACPI platform.
+///
+/// The guest powers off by writing the S5 sleep type together with `SLP_EN` to this
+/// register, which stops the microVM the same way an i8042 reset does.
+#[derive(Debug)]
+pub struct AcpiPmDevice {
+    /// Event used to signal that the guest requested a shutdown.
+    shutdown_evt: EventFd,
+}
+
+impl AcpiPmDevice {
+    /// Constructs an ACPI sleep control device that will signal the given event on shutdown.
+    pub fn new(shutdown_evt: EventFd) -> AcpiPmDevice {
+        AcpiPmDevice { shutdown_evt }
+    }
+
+    pub fn bus_read(&mut self, _offset: u64, _data: &mut [u8]) {}
+
+    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+        // Only handle byte length instructions at a zero offset.
+        if data.len() != 1 || offset != 0 {
+            return;
+        }
+
+        let sleep_type = (data[0] >> SLP_TYP_SHIFT) & SLP_TYP_MASK;
+        if data[0] & SLP_EN != 0 && sleep_type == S5_SLEEP_TYPE {
+            log::info!("Guest requested ACPI shutdown.");
+            if let Err(err) = self.shutdown_evt.write(1) {
+                log::error!("Failed to trigger ACPI shutdown event: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acpi_shutdown() {
+        let mut acpi_pm = AcpiPmDevice::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let shutdown_evt = acpi_pm.shutdown_evt.try_clone().unwrap();
+        let s5 = (S5_SLEEP_TYPE << SLP_TYP_SHIFT) | SLP_EN;
+
+        // Writes of the wrong size, at the wrong offset, without `SLP_EN` or for other
+        // sleep states are ignored.
+        acpi_pm.bus_write(0, &[s5, 0]);
+        acpi_pm.bus_write(1, &[s5]);
+        acpi_pm.bus_write(0, &[S5_SLEEP_TYPE << SLP_TYP_SHIFT]);
+        acpi_pm.bus_write(0, &[(3 << SLP_TYP_SHIFT) | SLP_EN]);
+        assert!(shutdown_evt.read().is_err());
+
+        acpi_pm.bus_write(0, &[s5]);
+        assert_eq!(shutdown_evt.read().unwrap(), 1);
+    }
+}
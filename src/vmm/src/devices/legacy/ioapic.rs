@@ -0,0 +1,296 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use kvm_bindings::{
+    kvm_irq_routing, kvm_irq_routing_entry, kvm_irq_routing_msi, KVM_IRQ_ROUTING_MSI,
+};
+use kvm_ioctls::VmFd;
+use log::error;
+
+use crate::arch::x86_64::layout::IOAPIC_NUM_PINS;
+
+// Offsets of the register select and register window, see the 82093AA IOAPIC datasheet.
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+// Registers accessed through the register window.
+const IOAPICID: u32 = 0x00;
+const IOAPICVER: u32 = 0x01;
+const IOAPICARB: u32 = 0x02;
+const IOREDTBL: u32 = 0x10;
+
+// Version of the 82093AA, which is also the one reported by the in-kernel IOAPIC of KVM.
+const IOAPIC_VERSION: u32 = 0x11;
+const IOAPICID_MASK: u32 = 0x0f00_0000;
+const IOAPICID_SHIFT: u32 = 24;
+const MAX_REDIRECTION_ENTRY_SHIFT: u32 = 16;
+
+// Fields of the redirection table entries.
+const VECTOR_MASK: u64 = 0xff;
+const DELIVERY_MODE_SHIFT: u64 = 8;
+const DELIVERY_MODE_MASK: u64 = 0b111;
+const DEST_MODE_SHIFT: u64 = 11;
+const DELIVERY_STATUS: u64 = 1 << 12;
+const REMOTE_IRR: u64 = 1 << 14;
+const TRIGGER_MODE_SHIFT: u64 = 15;
+const MASKED: u64 = 1 << 16;
+// Bits 8 to 14 of the x2APIC ID of the destination, set by the guests using the extended
+// destination ID of KVM to reach more than 255 vCPUs.
+const EXT_DEST_SHIFT: u64 = 49;
+const EXT_DEST_MASK: u64 = 0x7f;
+const DEST_SHIFT: u64 = 56;
+// The delivery status and remote IRR bits are read-only.
+const READ_ONLY_BITS: u64 = DELIVERY_STATUS | REMOTE_IRR;
+
+// Layout of the MSI address and data, see the Intel SDM, Volume 3, section 11.11.
+const MSI_ADDRESS_BASE: u32 = 0xfee0_0000;
+const MSI_ADDRESS_DEST_SHIFT: u32 = 12;
+const MSI_ADDRESS_DEST_MODE_SHIFT: u32 = 2;
+const MSI_ADDRESS_EXT_DEST_SHIFT: u32 = 5;
+const MSI_DATA_DELIVERY_MODE_SHIFT: u32 = 8;
+const MSI_DATA_TRIGGER_MODE_SHIFT: u32 = 15;
+
+/// IOAPIC emulated in userspace, used along with the split irqchip of KVM.
+///
+/// The devices keep injecting their interrupts through irqfds: KVM routes the GSI of each
+/// unmasked pin to the MSI described by its redirection table entry, so the IOAPIC is only
+/// involved when the guest programs it. All the interrupts of the devices are edge triggered,
+/// so the remote IRR of the pins is never set.
+#[derive(Debug)]
+pub struct IoApic {
+    vm_fd: Arc<VmFd>,
+    // Value of the IOAPICID register.
+    id: u32,
+    // Register accessed through the register window.
+    ioregsel: u32,
+    redirection_table: [u64; IOAPIC_NUM_PINS],
+}
+
+impl IoApic {
+    /// Constructs an IOAPIC with the given APIC ID, all its pins being masked.
+    pub fn new(vm_fd: Arc<VmFd>, id: u8) -> IoApic {
+        IoApic {
+            vm_fd,
+            id: u32::from(id) << IOAPICID_SHIFT,
+            ioregsel: 0,
+            redirection_table: [MASKED; IOAPIC_NUM_PINS],
+        }
+    }
+
+    pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
+        // Only 32-bit accesses are supported.
+        if data.len() != 4 {
+            return;
+        }
+        let value = match offset {
+            IOREGSEL => self.ioregsel,
+            IOWIN => self.read_register(self.ioregsel),
+            _ => return,
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+        let Ok(bytes) = <[u8; 4]>::try_from(data) else {
+            return;
+        };
+        let value = u32::from_le_bytes(bytes);
+        match offset {
+            IOREGSEL => self.ioregsel = value & 0xff,
+            IOWIN => self.write_register(self.ioregsel, value),
+            _ => (),
+        }
+    }
+
+    fn read_register(&self, index: u32) -> u32 {
+        match index {
+            IOAPICID | IOAPICARB => self.id,
+            IOAPICVER => {
+                IOAPIC_VERSION | ((IOAPIC_NUM_PINS as u32 - 1) << MAX_REDIRECTION_ENTRY_SHIFT)
+            }
+            _ => match redirection_entry(index) {
+                Some((pin, true)) => (self.redirection_table[pin] >> 32) as u32,
+                Some((pin, false)) => self.redirection_table[pin] as u32,
+                None => 0,
+            },
+        }
+    }
+
+    fn write_register(&mut self, index: u32, value: u32) {
+        if index == IOAPICID {
+            self.id = value & IOAPICID_MASK;
+            return;
+        }
+        let Some((pin, high)) = redirection_entry(index) else {
+            return;
+        };
+
+        let (value, mask) = if high {
+            (u64::from(value) << 32, 0xffff_ffff_0000_0000)
+        } else {
+            (u64::from(value), 0xffff_ffff)
+        };
+        let writable = mask & !READ_ONLY_BITS;
+        let old = self.redirection_table[pin];
+        let new = (old & !writable) | (value & writable);
+        self.redirection_table[pin] = new;
+
+        // The routes of masked pins are not installed, so they only change when an entry which
+        // is or was unmasked changes.
+        if new != old && old & new & MASKED == 0 {
+            self.update_routes();
+        }
+    }
+
+    // Replaces the GSI routing table of the VM with the routes of the unmasked pins.
+    fn update_routes(&self) {
+        let routes: Vec<kvm_irq_routing_entry> = self
+            .redirection_table
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| *entry & MASKED == 0)
+            .map(|(pin, entry)| msi_route(pin as u32, *entry))
+            .collect();
+        if let Err(err) = set_gsi_routing(&self.vm_fd, &routes) {
+            error!("Failed to update the routes of the IOAPIC: {}", err);
+        }
+    }
+}
+
+// Returns the pin of the redirection table entry accessed through the register `index`, and
+// whether it is the high half of the entry.
+fn redirection_entry(index: u32) -> Option<(usize, bool)> {
+    let offset = usize::try_from(index.checked_sub(IOREDTBL)?).ok()?;
+    let pin = offset / 2;
+    (pin < IOAPIC_NUM_PINS).then_some((pin, offset % 2 == 1))
+}
+
+// Route of the GSI of `pin` to the MSI described by its redirection table `entry`.
+fn msi_route(pin: u32, entry: u64) -> kvm_irq_routing_entry {
+    let vector = (entry & VECTOR_MASK) as u32;
+    let delivery_mode = ((entry >> DELIVERY_MODE_SHIFT) & DELIVERY_MODE_MASK) as u32;
+    let dest_mode = ((entry >> DEST_MODE_SHIFT) & 1) as u32;
+    let trigger_mode = ((entry >> TRIGGER_MODE_SHIFT) & 1) as u32;
+    let destination = (entry >> DEST_SHIFT) as u32;
+    let ext_destination = ((entry >> EXT_DEST_SHIFT) & EXT_DEST_MASK) as u32;
+
+    let mut route = kvm_irq_routing_entry {
+        gsi: pin,
+        type_: KVM_IRQ_ROUTING_MSI,
+        ..Default::default()
+    };
+    route.u.msi = kvm_irq_routing_msi {
+        address_lo: MSI_ADDRESS_BASE
+            | (destination << MSI_ADDRESS_DEST_SHIFT)
+            | (ext_destination << MSI_ADDRESS_EXT_DEST_SHIFT)
+            | (dest_mode << MSI_ADDRESS_DEST_MODE_SHIFT),
+        address_hi: 0,
+        data: vector
+            | (delivery_mode << MSI_DATA_DELIVERY_MODE_SHIFT)
+            | (trigger_mode << MSI_DATA_TRIGGER_MODE_SHIFT),
+        ..Default::default()
+    };
+    route
+}
+
+// Replaces the GSI routing table of the VM with `routes`.
+fn set_gsi_routing(
+    vm_fd: &VmFd,
+    routes: &[kvm_irq_routing_entry],
+) -> Result<(), kvm_ioctls::Error> {
+    // The entries follow the header of `kvm_irq_routing`, so the buffer is made of enough
+    // headers to hold them too.
+    let header_size = std::mem::size_of::<kvm_irq_routing>();
+    let entries_size = std::mem::size_of_val(routes);
+    let mut buffer: Vec<kvm_irq_routing> = std::iter::repeat_with(kvm_irq_routing::default)
+        .take(1 + (entries_size + header_size - 1) / header_size)
+        .collect();
+    buffer[0].nr = routes.len() as u32;
+    // SAFETY: Safe because the buffer holds `routes.len()` entries after the header.
+    unsafe { buffer[0].entries.as_mut_slice(routes.len()) }.copy_from_slice(routes);
+    vm_fd.set_gsi_routing(&buffer[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vstate::vm::tests::setup_vm;
+
+    fn write_register(ioapic: &mut IoApic, index: u32, value: u32) {
+        ioapic.bus_write(IOREGSEL, &index.to_le_bytes());
+        ioapic.bus_write(IOWIN, &value.to_le_bytes());
+    }
+
+    fn read_register(ioapic: &mut IoApic, index: u32) -> u32 {
+        let mut data = [0; 4];
+        ioapic.bus_write(IOREGSEL, &index.to_le_bytes());
+        ioapic.bus_read(IOWIN, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_ioapic_registers() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_split_irqchip().unwrap();
+        let mut ioapic = IoApic::new(vm.shared_fd(), 3);
+
+        assert_eq!(read_register(&mut ioapic, IOAPICID), 0x0300_0000);
+        assert_eq!(read_register(&mut ioapic, IOAPICVER), 0x0017_0011);
+        write_register(&mut ioapic, IOAPICID, 0xffff_ffff);
+        assert_eq!(read_register(&mut ioapic, IOAPICARB), 0x0f00_0000);
+        // The version is read-only.
+        write_register(&mut ioapic, IOAPICVER, 0);
+        assert_eq!(read_register(&mut ioapic, IOAPICVER), 0x0017_0011);
+
+        // The pins start masked.
+        assert_eq!(read_register(&mut ioapic, IOREDTBL), 0x0001_0000);
+        // Route pin 4 to vector 0x24 of the APIC 1.
+        write_register(&mut ioapic, IOREDTBL + 9, 0x0100_0000);
+        write_register(&mut ioapic, IOREDTBL + 8, 0x0000_5024);
+        assert_eq!(read_register(&mut ioapic, IOREDTBL + 9), 0x0100_0000);
+        // The delivery status and remote IRR bits are read-only.
+        assert_eq!(read_register(&mut ioapic, IOREDTBL + 8), 0x0000_0024);
+        assert_eq!(ioapic.redirection_table[4], 0x0100_0000_0000_0024);
+
+        // Registers past the redirection table read as zero.
+        assert_eq!(read_register(&mut ioapic, IOREDTBL + 48), 0);
+
+        // Only 32-bit accesses are handled.
+        let mut data = [0xff; 2];
+        ioapic.bus_read(IOWIN, &mut data);
+        assert_eq!(data, [0xff; 2]);
+        ioapic.bus_write(IOREGSEL, &[0; 2]);
+        assert_eq!(ioapic.ioregsel, IOREDTBL + 48);
+    }
+
+    #[test]
+    fn test_msi_route() {
+        // Vector 0x30, lowest priority, logical destination 0x2, level triggered.
+        let route = msi_route(5, 0x0200_0000_0000_8930);
+        assert_eq!(route.gsi, 5);
+        assert_eq!(route.type_, KVM_IRQ_ROUTING_MSI);
+        // SAFETY: Safe because the route is an MSI one.
+        let msi = unsafe { route.u.msi };
+        assert_eq!(msi.address_lo, 0xfee0_2004);
+        assert_eq!(msi.address_hi, 0);
+        assert_eq!(msi.data, 0x8130);
+
+        // Vector 0x31, fixed, physical destination 0x1ff, edge triggered.
+        let route = msi_route(6, 0xff02_0000_0000_0031);
+        // SAFETY: Safe because the route is an MSI one.
+        let msi = unsafe { route.u.msi };
+        assert_eq!(msi.address_lo, 0xfeef_f020);
+        assert_eq!(msi.data, 0x31);
+    }
+
+    #[test]
+    fn test_redirection_entry() {
+        assert_eq!(redirection_entry(IOAPICARB), None);
+        assert_eq!(redirection_entry(IOREDTBL), Some((0, false)));
+        assert_eq!(redirection_entry(IOREDTBL + 1), Some((0, true)));
+        assert_eq!(redirection_entry(IOREDTBL + 47), Some((23, true)));
+        assert_eq!(redirection_entry(IOREDTBL + 48), None);
+    }
+}
@@ -6,7 +6,11 @@
 // found in the THIRD-PARTY file.
 
 //! Implements legacy devices (UART, RTC etc).
+#[cfg(target_arch = "x86_64")]
+mod acpi_pm;
 mod i8042;
+#[cfg(target_arch = "x86_64")]
+mod ioapic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 pub mod serial;
@@ -17,7 +21,11 @@ use std::ops::Deref;
 use utils::eventfd::EventFd;
 use vm_superio::Trigger;
 
+#[cfg(target_arch = "x86_64")]
+pub use self::acpi_pm::AcpiPmDevice;
 pub use self::i8042::{I8042Device, I8042Error as I8042DeviceError};
+#[cfg(target_arch = "x86_64")]
+pub use self::ioapic::IoApic;
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTCDevice;
 pub use self::serial::{
@@ -5,6 +5,9 @@ use std::convert::TryInto;
 
 use logger::{warn, IncMetric, RTCDeviceMetrics, METRICS};
 
+// Offset of the PL031 load register.
+const RTCLR: u16 = 0x008;
+
 /// Wrapper over vm_superio's RTC implementation.
 #[derive(Debug)]
 pub struct RTCDevice(pub vm_superio::Rtc<&'static RTCDeviceMetrics>);
@@ -25,6 +28,11 @@ impl std::ops::DerefMut for RTCDevice {
 
 // Implements Bus functions for AMBA PL031 RTC device
 impl RTCDevice {
+    /// Sets the time reported by the RTC, in seconds since the epoch.
+    pub fn set_time(&mut self, time_s: u32) {
+        self.write(RTCLR, &time_s.to_le_bytes())
+    }
+
     pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
         if data.len() == 4 {
             // read() function from RTC implementation expects a slice of
@@ -78,6 +86,18 @@ mod tests {
         assert_eq!(error_count_after - error_count_before, 1);
     }
 
+    #[test]
+    fn test_rtc_set_time() {
+        static TEST_RTC_DEVICE_METRICS: RTCDeviceMetrics = RTCDeviceMetrics::new();
+        let mut rtc_pl031 = RTCDevice(Rtc::with_events(&TEST_RTC_DEVICE_METRICS));
+        let mut data = [0; 4];
+
+        rtc_pl031.set_time(1000);
+        rtc_pl031.bus_read(0x000, &mut data);
+        let time = u32::from_le_bytes(data);
+        assert!(time == 1000 || time == 1001);
+    }
+
     #[test]
     fn test_rtc_invalid_buf_len() {
         static TEST_RTC_INVALID_BUF_LEN_METRICS: RTCDeviceMetrics = RTCDeviceMetrics::new();
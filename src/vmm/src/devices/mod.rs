@@ -12,6 +12,7 @@ use std::io;
 pub mod bus;
 pub mod legacy;
 pub mod pseudo;
+pub mod tpm;
 pub mod virtio;
 
 pub use bus::{Bus, BusDevice, BusError};
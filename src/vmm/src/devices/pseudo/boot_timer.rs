@@ -1,14 +1,20 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use logger::{StoreMetric, METRICS};
 use utils::time::TimestampUs;
 
 const MAGIC_VALUE_SIGNAL_GUEST_BOOT_COMPLETE: u8 = 123;
+const MAGIC_VALUE_SIGNAL_GUEST_READY: u8 = 124;
 
-/// Pseudo device to record the kernel boot time.
+/// Pseudo device to record the kernel boot time and the moment the guest is ready.
 #[derive(Debug)]
 pub struct BootTimer {
     start_ts: TimestampUs,
+    guest_ready: Arc<AtomicBool>,
 }
 
 impl BootTimer {
@@ -30,14 +36,35 @@ impl BootTimer {
                 boot_time_cpu_us,
                 boot_time_cpu_us / 1000
             );
+        } else if data[0] == MAGIC_VALUE_SIGNAL_GUEST_READY
+            && !self.guest_ready.swap(true, Ordering::Relaxed)
+        {
+            let ready_time_us = TimestampUs::default().time_us - self.start_ts.time_us;
+            log::info!(
+                "Guest-ready-time = {:>6} us {} ms",
+                ready_time_us,
+                ready_time_us / 1000
+            );
+            METRICS
+                .vmm
+                .guest_ready_time_us
+                .store(usize::try_from(ready_time_us).unwrap_or(usize::MAX));
+            // Consumers waiting on the guest are told right away.
+            if let Err(err) = METRICS.write() {
+                log::error!("Failed to write metrics: {}", err);
+            }
         }
     }
     pub fn bus_read(&mut self, _offset: u64, _data: &[u8]) {}
 }
 
 impl BootTimer {
-    /// Create a device at a certain point in time.
-    pub fn new(start_ts: TimestampUs) -> BootTimer {
-        BootTimer { start_ts }
+    /// Create a device at a certain point in time, which sets `guest_ready` when the guest
+    /// signals it is ready.
+    pub fn new(start_ts: TimestampUs, guest_ready: Arc<AtomicBool>) -> BootTimer {
+        BootTimer {
+            start_ts,
+            guest_ready,
+        }
     }
 }
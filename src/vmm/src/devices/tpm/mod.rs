@@ -0,0 +1,458 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates a TPM 2.0 with the FIFO interface of the TCG PC Client Platform TPM Profile (PTP)
+//! specification, executing the commands of the guest in swtpm.
+
+pub mod persist;
+mod swtpm;
+
+use std::io;
+
+use log::error;
+use logger::{IncMetric, METRICS};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+pub use self::swtpm::{Swtpm, INIT_FLAG_DELETE_VOLATILE};
+
+/// Size of the header of the TPM commands and responses.
+pub const TPM_HEADER_SIZE: usize = 10;
+/// Size of the largest TPM command or response.
+pub const TPM_BUFFER_SIZE: usize = 4096;
+
+// Registers of locality 0, the only one implemented.
+const REG_ACCESS: u64 = 0x00;
+const REG_INT_ENABLE: u64 = 0x08;
+const REG_INTF_CAPABILITY: u64 = 0x14;
+const REG_STS: u64 = 0x18;
+const REG_DATA_FIFO: u64 = 0x24;
+const REG_INTERFACE_ID: u64 = 0x30;
+const REG_XDATA_FIFO: u64 = 0x80;
+const REG_DID_VID: u64 = 0xf00;
+const REG_RID: u64 = 0xf04;
+
+// The data FIFO is accessed through 4 bytes, the extended one through 64 bytes.
+const DATA_FIFO_LEN: u64 = 4;
+const XDATA_FIFO_LEN: u64 = 64;
+
+const ACCESS_TPM_ESTABLISHMENT: u32 = 1 << 0;
+const ACCESS_REQUEST_USE: u32 = 1 << 1;
+const ACCESS_ACTIVE_LOCALITY: u32 = 1 << 5;
+const ACCESS_VALID: u32 = 1 << 7;
+
+const STS_RESPONSE_RETRY: u32 = 1 << 1;
+const STS_EXPECT: u32 = 1 << 3;
+const STS_DATA_AVAIL: u32 = 1 << 4;
+const STS_GO: u32 = 1 << 5;
+const STS_COMMAND_READY: u32 = 1 << 6;
+const STS_VALID: u32 = 1 << 7;
+const STS_BURST_COUNT_SHIFT: u32 = 8;
+const STS_FAMILY_TPM2: u32 = 1 << 26;
+
+// 64 bytes transfers and interface version 1.3 for TPM 2.0, without interrupts.
+const INTF_CAPABILITY: u32 = (3 << 9) | (3 << 28);
+// FIFO interface, selected and locked.
+const INTERFACE_ID: u32 = (1 << 13) | (1 << 19);
+// Same vendor and device ids as the TPM emulated by QEMU, backed by swtpm as well.
+const VENDOR_ID: u32 = 0x1014;
+const DEVICE_ID: u32 = 0x0001;
+const REVISION_ID: u32 = 0x01;
+
+// Response to a command which swtpm failed to execute: TPM_ST_NO_SESSIONS, size 10,
+// TPM_RC_FAILURE.
+const FAILURE_RESPONSE: [u8; TPM_HEADER_SIZE] = [0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0x01, 0x01];
+
+/// Errors associated with the TPM device.
+#[derive(Debug, thiserror::Error)]
+pub enum TpmError {
+    /// Cannot connect to the control socket of swtpm.
+    #[error("Cannot connect to the swtpm socket {0}: {1}")]
+    Connect(String, io::Error),
+    /// A control command of swtpm cannot be sent or its result received.
+    #[error("Cannot send the swtpm control command {0}: {1}")]
+    Control(&'static str, io::Error),
+    /// A control command of swtpm failed.
+    #[error("The swtpm control command {0} failed with result {1:#x}.")]
+    ControlResult(&'static str, u32),
+    /// The channel through which the TPM commands are exchanged failed.
+    #[error("Cannot exchange the TPM command with swtpm: {0}")]
+    DataChannel(io::Error),
+    /// The size of a TPM response is invalid.
+    #[error("swtpm sent a response of invalid size {0}.")]
+    InvalidResponse(u32),
+}
+
+/// States of the interface, see section 5.5.2 of the PTP specification. The command is executed
+/// as the guest writes `tpmGo`, so the `Execution` state is never observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Versionize)]
+pub enum TisState {
+    /// No command is being processed.
+    Idle,
+    /// The TPM is ready to receive a command.
+    Ready,
+    /// The TPM is receiving a command.
+    Reception,
+    /// The response of the last command is available.
+    Completion,
+}
+
+/// TPM 2.0 device, accessed through the FIFO interface of locality 0.
+#[derive(Debug)]
+pub struct Tpm {
+    swtpm: Swtpm,
+    locality_active: bool,
+    state: TisState,
+    // Command being received, or response of the last command.
+    buffer: Vec<u8>,
+    // Number of bytes of the response read by the guest.
+    read_offset: usize,
+    int_enable: u32,
+}
+
+impl Tpm {
+    /// Creates a TPM device executing the commands in `swtpm`.
+    pub fn new(swtpm: Swtpm) -> Tpm {
+        Tpm {
+            swtpm,
+            locality_active: false,
+            state: TisState::Idle,
+            buffer: Vec::with_capacity(TPM_BUFFER_SIZE),
+            read_offset: 0,
+            int_enable: 0,
+        }
+    }
+
+    /// Has swtpm store the volatile state of the TPM, see `Swtpm::store_volatile`.
+    pub fn store_volatile(&mut self) -> Result<(), TpmError> {
+        self.swtpm.store_volatile()
+    }
+
+    pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let addr = offset + i as u64;
+            *byte = if is_fifo(addr) {
+                self.read_fifo()
+            } else {
+                // Registers are little endian, and may be read partially.
+                (self.register(addr & !3) >> ((addr & 3) * 8)) as u8
+            };
+        }
+    }
+
+    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+        if is_fifo(offset) {
+            for byte in data {
+                self.write_fifo(*byte);
+            }
+            return;
+        }
+
+        // Registers are little endian, and may be written partially.
+        let shift = (offset & 3) * 8;
+        let (value, mask) =
+            data.iter()
+                .take(4)
+                .enumerate()
+                .fold((0u64, 0u64), |(value, mask), (i, byte)| {
+                    (value | u64::from(*byte) << (8 * i), mask | 0xff << (8 * i))
+                });
+        let (value, mask) = ((value << shift) as u32, (mask << shift) as u32);
+        match offset & !3 {
+            REG_ACCESS => self.write_access(value),
+            REG_INT_ENABLE => self.int_enable = (self.int_enable & !mask) | value,
+            REG_STS if self.locality_active => self.write_status(value),
+            _ => (),
+        }
+    }
+
+    fn register(&self, reg: u64) -> u32 {
+        match reg {
+            REG_ACCESS => {
+                // The establishment bit is cleared once a dynamic root of trust is established,
+                // which never happens.
+                let mut access = ACCESS_VALID | ACCESS_TPM_ESTABLISHMENT;
+                if self.locality_active {
+                    access |= ACCESS_ACTIVE_LOCALITY;
+                }
+                access
+            }
+            REG_INT_ENABLE => self.int_enable,
+            REG_INTF_CAPABILITY => INTF_CAPABILITY,
+            REG_STS => self.status(),
+            REG_INTERFACE_ID => INTERFACE_ID,
+            REG_DID_VID => (DEVICE_ID << 16) | VENDOR_ID,
+            REG_RID => REVISION_ID,
+            _ => 0,
+        }
+    }
+
+    fn status(&self) -> u32 {
+        let (flags, burst_count) = match self.state {
+            TisState::Idle => (0, 0),
+            TisState::Ready => (STS_COMMAND_READY, TPM_BUFFER_SIZE),
+            TisState::Reception => {
+                let flags = if self.expects_data() { STS_EXPECT } else { 0 };
+                (flags, TPM_BUFFER_SIZE - self.buffer.len())
+            }
+            TisState::Completion => {
+                let available = self.buffer.len() - self.read_offset;
+                let flags = if available > 0 { STS_DATA_AVAIL } else { 0 };
+                (flags, available)
+            }
+        };
+        // The burst count is a 16-bit field, larger than the buffer.
+        STS_FAMILY_TPM2 | STS_VALID | flags | ((burst_count as u32) << STS_BURST_COUNT_SHIFT)
+    }
+
+    // Whether the command being received is shorter than the size in its header.
+    fn expects_data(&self) -> bool {
+        if self.buffer.len() >= TPM_BUFFER_SIZE {
+            return false;
+        }
+        if self.buffer.len() < TPM_HEADER_SIZE {
+            return true;
+        }
+        let size = u32::from_be_bytes([
+            self.buffer[2],
+            self.buffer[3],
+            self.buffer[4],
+            self.buffer[5],
+        ]);
+        (self.buffer.len() as u64) < u64::from(size)
+    }
+
+    fn write_access(&mut self, value: u32) {
+        if value & ACCESS_REQUEST_USE != 0 {
+            self.locality_active = true;
+        } else if value & ACCESS_ACTIVE_LOCALITY != 0 {
+            self.locality_active = false;
+        }
+    }
+
+    fn write_status(&mut self, value: u32) {
+        if value & STS_COMMAND_READY != 0 {
+            // Aborts the command being received, or discards the last response.
+            self.state = TisState::Ready;
+            self.buffer.clear();
+            self.read_offset = 0;
+        } else if value & STS_GO != 0 {
+            if self.state == TisState::Reception && !self.expects_data() {
+                self.execute();
+            }
+        } else if value & STS_RESPONSE_RETRY != 0 && self.state == TisState::Completion {
+            self.read_offset = 0;
+        }
+    }
+
+    fn write_fifo(&mut self, byte: u8) {
+        if !self.locality_active {
+            return;
+        }
+        match self.state {
+            TisState::Ready => {
+                self.state = TisState::Reception;
+                self.buffer.push(byte);
+            }
+            TisState::Reception if self.buffer.len() < TPM_BUFFER_SIZE => self.buffer.push(byte),
+            _ => (),
+        }
+    }
+
+    fn read_fifo(&mut self) -> u8 {
+        if !self.locality_active || self.state != TisState::Completion {
+            return 0xff;
+        }
+        match self.buffer.get(self.read_offset) {
+            Some(byte) => {
+                self.read_offset += 1;
+                *byte
+            }
+            None => 0xff,
+        }
+    }
+
+    // Executes the command received, on the vCPU thread which wrote `tpmGo`.
+    fn execute(&mut self) {
+        METRICS.tpm.command_count.inc();
+        self.buffer = match self.swtpm.execute(&self.buffer) {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Failed to execute the TPM command: {}", err);
+                METRICS.tpm.command_fails.inc();
+                FAILURE_RESPONSE.to_vec()
+            }
+        };
+        self.read_offset = 0;
+        self.state = TisState::Completion;
+    }
+}
+
+fn is_fifo(addr: u64) -> bool {
+    (REG_DATA_FIFO..REG_DATA_FIFO + DATA_FIFO_LEN).contains(&addr)
+        || (REG_XDATA_FIFO..REG_XDATA_FIFO + XDATA_FIFO_LEN).contains(&addr)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::swtpm::tests::{disconnected_swtpm, tpm_command, MockSwtpm};
+    use super::*;
+
+    fn read_u8(tpm: &mut Tpm, offset: u64) -> u8 {
+        let mut data = [0];
+        tpm.bus_read(offset, &mut data);
+        data[0]
+    }
+
+    fn read_u32(tpm: &mut Tpm, offset: u64) -> u32 {
+        let mut data = [0; 4];
+        tpm.bus_read(offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_u32(tpm: &mut Tpm, offset: u64, value: u32) {
+        tpm.bus_write(offset, &value.to_le_bytes());
+    }
+
+    fn burst_count(tpm: &mut Tpm) -> usize {
+        let mut data = [0; 2];
+        tpm.bus_read(REG_STS + 1, &mut data);
+        usize::from(u16::from_le_bytes(data))
+    }
+
+    pub(crate) fn default_tpm() -> (Tpm, MockSwtpm) {
+        let mock = MockSwtpm::new();
+        let swtpm = Swtpm::connect(&mock.socket_path, INIT_FLAG_DELETE_VOLATILE).unwrap();
+        (Tpm::new(swtpm), mock)
+    }
+
+    // Sends `command` and reads its response the way the Linux driver does.
+    fn transmit(tpm: &mut Tpm, command: &[u8]) -> Vec<u8> {
+        write_u32(tpm, REG_STS, STS_COMMAND_READY);
+        assert_ne!(read_u32(tpm, REG_STS) & STS_COMMAND_READY, 0);
+        for (i, byte) in command.iter().enumerate() {
+            assert!(burst_count(tpm) > 0);
+            tpm.bus_write(REG_DATA_FIFO, &[*byte]);
+            let expect = read_u32(tpm, REG_STS) & STS_EXPECT != 0;
+            assert_eq!(expect, i + 1 < command.len());
+        }
+        write_u32(tpm, REG_STS, STS_GO);
+
+        let mut response = Vec::new();
+        while read_u32(tpm, REG_STS) & STS_DATA_AVAIL != 0 {
+            let mut chunk = vec![0; burst_count(tpm).min(64)];
+            tpm.bus_read(REG_XDATA_FIFO, &mut chunk);
+            response.extend(chunk);
+        }
+        write_u32(tpm, REG_STS, STS_COMMAND_READY);
+        response
+    }
+
+    #[test]
+    fn test_registers() {
+        let (mut tpm, _mock) = default_tpm();
+        assert_eq!(read_u8(&mut tpm, REG_ACCESS), 0x81);
+        assert_eq!(read_u32(&mut tpm, REG_INTF_CAPABILITY), INTF_CAPABILITY);
+        assert_eq!(read_u32(&mut tpm, REG_INTERFACE_ID), INTERFACE_ID);
+        assert_eq!(read_u32(&mut tpm, REG_DID_VID), 0x0001_1014);
+        assert_eq!(read_u8(&mut tpm, REG_RID), 1);
+        assert_eq!(read_u32(&mut tpm, 0x40), 0);
+        assert_eq!(read_u32(&mut tpm, REG_STS), STS_FAMILY_TPM2 | STS_VALID);
+
+        write_u32(&mut tpm, REG_INT_ENABLE, 0x8000_0007);
+        tpm.bus_write(REG_INT_ENABLE + 3, &[0]);
+        assert_eq!(read_u32(&mut tpm, REG_INT_ENABLE), 7);
+
+        // The locality is requested, then relinquished.
+        tpm.bus_write(REG_ACCESS, &[ACCESS_REQUEST_USE as u8]);
+        assert_eq!(read_u8(&mut tpm, REG_ACCESS), 0xa1);
+        tpm.bus_write(REG_ACCESS, &[ACCESS_ACTIVE_LOCALITY as u8]);
+        assert_eq!(read_u8(&mut tpm, REG_ACCESS), 0x81);
+    }
+
+    #[test]
+    fn test_inactive_locality() {
+        let (mut tpm, _mock) = default_tpm();
+        write_u32(&mut tpm, REG_STS, STS_COMMAND_READY);
+        assert_eq!(tpm.state, TisState::Idle);
+        tpm.bus_write(REG_DATA_FIFO, &[1]);
+        assert!(tpm.buffer.is_empty());
+        assert_eq!(read_u8(&mut tpm, REG_DATA_FIFO), 0xff);
+    }
+
+    #[test]
+    fn test_command() {
+        let (mut tpm, mock) = default_tpm();
+        tpm.bus_write(REG_ACCESS, &[ACCESS_REQUEST_USE as u8]);
+
+        for len in [TPM_HEADER_SIZE, 100, TPM_BUFFER_SIZE] {
+            let command = tpm_command(len);
+            let response = transmit(&mut tpm, &command);
+            let mut body = command[TPM_HEADER_SIZE..].to_vec();
+            body.reverse();
+            assert_eq!(response[..TPM_HEADER_SIZE], command[..TPM_HEADER_SIZE]);
+            assert_eq!(response[TPM_HEADER_SIZE..], body);
+        }
+        assert_eq!(tpm.state, TisState::Ready);
+        // Only the initialization went through the control channel.
+        drop(tpm);
+        assert_eq!(mock.control_log().len(), 2);
+    }
+
+    #[test]
+    fn test_response_retry() {
+        let (mut tpm, _mock) = default_tpm();
+        tpm.bus_write(REG_ACCESS, &[ACCESS_REQUEST_USE as u8]);
+        write_u32(&mut tpm, REG_STS, STS_COMMAND_READY);
+        let command = tpm_command(20);
+        tpm.bus_write(REG_XDATA_FIFO, &command);
+        write_u32(&mut tpm, REG_STS, STS_GO);
+        assert_eq!(tpm.state, TisState::Completion);
+        assert_eq!(burst_count(&mut tpm), 20);
+
+        // The response is read again from its start.
+        let mut first = [0; 8];
+        tpm.bus_read(REG_DATA_FIFO, &mut first[..4]);
+        tpm.bus_read(REG_DATA_FIFO, &mut first[4..]);
+        assert_eq!(burst_count(&mut tpm), 12);
+        write_u32(&mut tpm, REG_STS, STS_RESPONSE_RETRY);
+        assert_eq!(burst_count(&mut tpm), 20);
+        let mut again = [0; 8];
+        tpm.bus_read(REG_XDATA_FIFO, &mut again);
+        assert_eq!(first, again);
+
+        // Further writes to the FIFO are ignored until the TPM is ready again.
+        tpm.bus_write(REG_DATA_FIFO, &[0]);
+        assert_eq!(tpm.buffer.len(), 20);
+    }
+
+    #[test]
+    fn test_incomplete_command() {
+        let (mut tpm, _mock) = default_tpm();
+        tpm.bus_write(REG_ACCESS, &[ACCESS_REQUEST_USE as u8]);
+        write_u32(&mut tpm, REG_STS, STS_COMMAND_READY);
+        let command = tpm_command(20);
+        tpm.bus_write(REG_DATA_FIFO, &command[..4]);
+        tpm.bus_write(REG_DATA_FIFO, &command[4..12]);
+        assert_eq!(tpm.state, TisState::Reception);
+        assert_ne!(read_u32(&mut tpm, REG_STS) & STS_EXPECT, 0);
+        assert_eq!(burst_count(&mut tpm), TPM_BUFFER_SIZE - 12);
+
+        // The command is not executed before it is complete, and can be aborted.
+        write_u32(&mut tpm, REG_STS, STS_GO);
+        assert_eq!(tpm.state, TisState::Reception);
+        write_u32(&mut tpm, REG_STS, STS_COMMAND_READY);
+        assert_eq!(tpm.state, TisState::Ready);
+        assert!(tpm.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_failed_command() {
+        let mut tpm = Tpm::new(disconnected_swtpm());
+        tpm.bus_write(REG_ACCESS, &[ACCESS_REQUEST_USE as u8]);
+        let fails = METRICS.tpm.command_fails.count();
+        let response = transmit(&mut tpm, &tpm_command(20));
+        assert_eq!(response, FAILURE_RESPONSE);
+        assert!(METRICS.tpm.command_fails.count() > fails);
+    }
+}
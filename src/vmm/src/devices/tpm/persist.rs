@@ -0,0 +1,89 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring the TPM device.
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::{Swtpm, TisState, Tpm, TpmError};
+
+/// State of the TPM interface. The state of the TPM itself is kept by swtpm.
+#[derive(Debug, Clone, PartialEq, Eq, Versionize)]
+pub struct TpmState {
+    socket_path: String,
+    locality_active: bool,
+    state: TisState,
+    buffer: Vec<u8>,
+    read_offset: u64,
+    int_enable: u32,
+}
+
+impl Persist<'_> for Tpm {
+    type State = TpmState;
+    type ConstructorArgs = ();
+    type Error = TpmError;
+
+    fn save(&self) -> Self::State {
+        TpmState {
+            socket_path: self.swtpm.socket_path().to_string(),
+            locality_active: self.locality_active,
+            state: self.state,
+            buffer: self.buffer.clone(),
+            read_offset: self.read_offset as u64,
+            int_enable: self.int_enable,
+        }
+    }
+
+    fn restore(_: Self::ConstructorArgs, state: &Self::State) -> Result<Self, Self::Error> {
+        // swtpm loads the volatile state stored when the snapshot was created.
+        let swtpm = Swtpm::connect(&state.socket_path, 0)?;
+        let read_offset = usize::try_from(state.read_offset)
+            .unwrap_or(usize::MAX)
+            .min(state.buffer.len());
+        Ok(Tpm {
+            swtpm,
+            locality_active: state.locality_active,
+            state: state.state,
+            buffer: state.buffer.clone(),
+            read_offset,
+            int_enable: state.int_enable,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::swtpm::tests::{tpm_command, MockSwtpm};
+    use super::super::tests::default_tpm;
+    use super::*;
+
+    #[test]
+    fn test_persistence() {
+        let (mut tpm, _mock) = default_tpm();
+        tpm.locality_active = true;
+        tpm.state = TisState::Completion;
+        tpm.buffer = tpm_command(20);
+        tpm.read_offset = 4;
+        tpm.int_enable = 7;
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        tpm.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // The restored device connects to the swtpm listening at the saved path.
+        let mock = MockSwtpm::new();
+        let mut state = TpmState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        state.socket_path = mock.socket_path.clone();
+        let restored = Tpm::restore((), &state).unwrap();
+        assert_eq!(restored.swtpm.socket_path(), mock.socket_path);
+        assert!(restored.locality_active);
+        assert_eq!(restored.state, TisState::Completion);
+        assert_eq!(restored.buffer, tpm.buffer);
+        assert_eq!(restored.read_offset, 4);
+        assert_eq!(restored.int_enable, 7);
+    }
+}
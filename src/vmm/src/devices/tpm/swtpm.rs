@@ -0,0 +1,275 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client of the control channel of swtpm, see `man swtpm-ioctls` for the protocol.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use utils::sock_ctrl_msg::ScmSocket;
+
+use super::{TpmError, TPM_BUFFER_SIZE, TPM_HEADER_SIZE};
+
+// Control commands, sent big-endian and answered with a big-endian result code.
+const CMD_INIT: u32 = 2;
+const CMD_STORE_VOLATILE: u32 = 10;
+const CMD_SET_DATAFD: u32 = 16;
+
+/// Flag of `CMD_INIT` deleting the volatile state once it is loaded.
+pub const INIT_FLAG_DELETE_VOLATILE: u32 = 1;
+
+/// Emulated TPM running in a swtpm process, started with `swtpm socket --tpm2 --ctrl
+/// type=unixio,path=<socket_path>` and no `--server` option.
+#[derive(Debug)]
+pub struct Swtpm {
+    socket_path: String,
+    control: UnixStream,
+    // End of the socket pair through which the TPM commands are exchanged.
+    data: UnixStream,
+}
+
+impl Swtpm {
+    /// Connects to swtpm and initializes the TPM with `init_flags`.
+    pub fn connect(socket_path: &str, init_flags: u32) -> Result<Swtpm, TpmError> {
+        let control = UnixStream::connect(socket_path)
+            .map_err(|err| TpmError::Connect(socket_path.to_string(), err))?;
+        let (data, swtpm_data) = UnixStream::pair().map_err(TpmError::DataChannel)?;
+        control
+            .send_with_fds(
+                &[&CMD_SET_DATAFD.to_be_bytes()[..]],
+                &[swtpm_data.as_raw_fd()],
+            )
+            .map_err(|err| TpmError::Control("CMD_SET_DATAFD", io::Error::from(err)))?;
+
+        let mut swtpm = Swtpm {
+            socket_path: socket_path.to_string(),
+            control,
+            data,
+        };
+        swtpm.read_control_result("CMD_SET_DATAFD")?;
+        swtpm.control_command("CMD_INIT", CMD_INIT, &init_flags.to_be_bytes())?;
+        Ok(swtpm)
+    }
+
+    /// Path of the control socket of swtpm.
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    /// Has swtpm write the volatile state of the TPM next to its permanent state, so that a new
+    /// swtpm process started on a copy of the state directory resumes the TPM as it is now.
+    pub fn store_volatile(&mut self) -> Result<(), TpmError> {
+        self.control_command("CMD_STORE_VOLATILE", CMD_STORE_VOLATILE, &[])
+    }
+
+    /// Executes a TPM command and returns its response.
+    pub fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>, TpmError> {
+        self.data
+            .write_all(command)
+            .map_err(TpmError::DataChannel)?;
+
+        let mut response = vec![0; TPM_HEADER_SIZE];
+        self.data
+            .read_exact(&mut response)
+            .map_err(TpmError::DataChannel)?;
+        // The header is a 16-bit tag followed by the 32-bit size of the whole response.
+        let size = u32::from_be_bytes([response[2], response[3], response[4], response[5]]);
+        let size = usize::try_from(size).map_err(|_| TpmError::InvalidResponse(size))?;
+        if !(TPM_HEADER_SIZE..=TPM_BUFFER_SIZE).contains(&size) {
+            return Err(TpmError::InvalidResponse(size as u32));
+        }
+        response.resize(size, 0);
+        self.data
+            .read_exact(&mut response[TPM_HEADER_SIZE..])
+            .map_err(TpmError::DataChannel)?;
+        Ok(response)
+    }
+
+    fn control_command(
+        &mut self,
+        name: &'static str,
+        command: u32,
+        payload: &[u8],
+    ) -> Result<(), TpmError> {
+        let mut request = command.to_be_bytes().to_vec();
+        request.extend_from_slice(payload);
+        self.control
+            .write_all(&request)
+            .map_err(|err| TpmError::Control(name, err))?;
+        self.read_control_result(name)
+    }
+
+    fn read_control_result(&mut self, name: &'static str) -> Result<(), TpmError> {
+        let mut result = [0; 4];
+        self.control
+            .read_exact(&mut result)
+            .map_err(|err| TpmError::Control(name, err))?;
+        match u32::from_be_bytes(result) {
+            0 => Ok(()),
+            code => Err(TpmError::ControlResult(name, code)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    /// Control commands received by `MockSwtpm`, with their payload.
+    pub(crate) type ControlLog = Vec<(u32, Vec<u8>)>;
+
+    /// Minimal swtpm, answering the commands it receives with their bytes reversed.
+    pub(crate) struct MockSwtpm {
+        _dir: TempDir,
+        pub(crate) socket_path: String,
+        thread: JoinHandle<ControlLog>,
+    }
+
+    impl MockSwtpm {
+        pub(crate) fn new() -> MockSwtpm {
+            let dir = TempDir::new().unwrap();
+            let socket_path = dir.as_path().join("swtpm.sock");
+            let listener = UnixListener::bind(&socket_path).unwrap();
+            let thread = std::thread::spawn(move || {
+                let (control, _) = listener.accept().unwrap();
+                serve(control)
+            });
+            MockSwtpm {
+                _dir: dir,
+                socket_path: socket_path.to_str().unwrap().to_string(),
+                thread,
+            }
+        }
+
+        /// Waits for the client to disconnect, and returns the control commands it sent.
+        pub(crate) fn control_log(self) -> ControlLog {
+            self.thread.join().unwrap()
+        }
+    }
+
+    fn serve(mut control: UnixStream) -> ControlLog {
+        let mut log = Vec::new();
+        let mut command = [0u8; 4];
+        let mut iovecs = [libc::iovec {
+            iov_base: command.as_mut_ptr().cast(),
+            iov_len: command.len(),
+        }];
+        let mut fds = [-1; 1];
+        // SAFETY: The iovec describes `command`, which outlives the call.
+        let (_, fd_count) = unsafe { control.recv_with_fds(&mut iovecs, &mut fds) }.unwrap();
+        assert_eq!(fd_count, 1);
+        assert_eq!(u32::from_be_bytes(command), CMD_SET_DATAFD);
+        log.push((CMD_SET_DATAFD, Vec::new()));
+        // SAFETY: The file descriptor was just received, and nothing else owns it.
+        let data = unsafe { <UnixStream as std::os::unix::io::FromRawFd>::from_raw_fd(fds[0]) };
+        control.write_all(&0u32.to_be_bytes()).unwrap();
+
+        let data_thread = std::thread::spawn(move || serve_data(data));
+        while control.read_exact(&mut command).is_ok() {
+            let command = u32::from_be_bytes(command);
+            let mut payload = vec![0; if command == CMD_INIT { 4 } else { 0 }];
+            control.read_exact(&mut payload).unwrap();
+            log.push((command, payload));
+            control.write_all(&0u32.to_be_bytes()).unwrap();
+        }
+        data_thread.join().unwrap();
+        log
+    }
+
+    fn serve_data(mut data: UnixStream) {
+        let mut command = vec![0u8; TPM_BUFFER_SIZE];
+        loop {
+            match data.read(&mut command) {
+                Ok(0) | Err(_) => return,
+                Ok(len) => {
+                    let mut response = command[..len].to_vec();
+                    response[TPM_HEADER_SIZE..].reverse();
+                    data.write_all(&response).unwrap();
+                }
+            }
+        }
+    }
+
+    // Builds a command of `len` bytes, with a valid header.
+    pub(crate) fn tpm_command(len: usize) -> Vec<u8> {
+        let mut command: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        command[2..6].copy_from_slice(&(len as u32).to_be_bytes());
+        command
+    }
+
+    // Builds a client whose swtpm is gone.
+    pub(crate) fn disconnected_swtpm() -> Swtpm {
+        let (control, _) = UnixStream::pair().unwrap();
+        let (data, _) = UnixStream::pair().unwrap();
+        Swtpm {
+            socket_path: String::new(),
+            control,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_swtpm() {
+        let mock = MockSwtpm::new();
+        let mut swtpm = Swtpm::connect(&mock.socket_path, INIT_FLAG_DELETE_VOLATILE).unwrap();
+        assert_eq!(swtpm.socket_path(), mock.socket_path);
+
+        let command = tpm_command(20);
+        let response = swtpm.execute(&command).unwrap();
+        assert_eq!(response[..TPM_HEADER_SIZE], command[..TPM_HEADER_SIZE]);
+        let mut body = command[TPM_HEADER_SIZE..].to_vec();
+        body.reverse();
+        assert_eq!(response[TPM_HEADER_SIZE..], body);
+
+        swtpm.store_volatile().unwrap();
+        drop(swtpm);
+        assert_eq!(
+            mock.control_log(),
+            vec![
+                (CMD_SET_DATAFD, Vec::new()),
+                (CMD_INIT, INIT_FLAG_DELETE_VOLATILE.to_be_bytes().to_vec()),
+                (CMD_STORE_VOLATILE, Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_swtpm_invalid_response() {
+        let mock = MockSwtpm::new();
+        let mut swtpm = Swtpm::connect(&mock.socket_path, 0).unwrap();
+        // The mock echoes the size of the command, which is larger than a TPM buffer.
+        let mut command = tpm_command(TPM_HEADER_SIZE);
+        command[2..6].copy_from_slice(&(TPM_BUFFER_SIZE as u32 + 1).to_be_bytes());
+        assert!(matches!(
+            swtpm.execute(&command),
+            Err(TpmError::InvalidResponse(size)) if size == TPM_BUFFER_SIZE as u32 + 1
+        ));
+    }
+
+    #[test]
+    fn test_swtpm_disconnected() {
+        let mut swtpm = disconnected_swtpm();
+        assert!(matches!(
+            swtpm.execute(&tpm_command(20)),
+            Err(TpmError::DataChannel(_))
+        ));
+        assert!(matches!(
+            swtpm.store_volatile(),
+            Err(TpmError::Control("CMD_STORE_VOLATILE", _))
+        ));
+    }
+
+    #[test]
+    fn test_swtpm_connect_error() {
+        assert!(matches!(
+            Swtpm::connect("/no/such/socket", 0),
+            Err(TpmError::Connect(_, _))
+        ));
+    }
+}
@@ -1,13 +1,14 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{cmp, fmt};
 
-use log::error;
+use log::{error, warn};
 use logger::{IncMetric, METRICS};
 use serde::Serialize;
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
@@ -16,15 +17,18 @@ use utils::vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap
 use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
 
 use super::super::{ActivateError, DeviceState, Queue, VirtioDevice, TYPE_BALLOON};
-use super::util::{compact_page_frame_numbers, remove_range};
+use super::util::{compact_page_frame_numbers, insert_pfn_range, remove_pfn_range, remove_range};
 use super::{
-    BALLOON_DEV_ID, BALLOON_NUM_QUEUES, BALLOON_QUEUE_SIZES, DEFLATE_INDEX, INFLATE_INDEX,
+    BALLOON_DEV_ID, BALLOON_NUM_QUEUES, BALLOON_QUEUE_SIZES, DEFLATE_INDEX,
+    FREE_PAGE_HINT_CMD_ID_DONE, FREE_PAGE_HINT_CMD_ID_STOP, FREE_PAGE_HINT_INDEX, INFLATE_INDEX,
     MAX_PAGES_IN_DESC, MAX_PAGE_COMPACT_BUFFER, MIB_TO_4K_PAGES, STATS_INDEX,
-    VIRTIO_BALLOON_F_DEFLATE_ON_OOM, VIRTIO_BALLOON_F_STATS_VQ, VIRTIO_BALLOON_PFN_SHIFT,
-    VIRTIO_BALLOON_S_AVAIL, VIRTIO_BALLOON_S_CACHES, VIRTIO_BALLOON_S_HTLB_PGALLOC,
-    VIRTIO_BALLOON_S_HTLB_PGFAIL, VIRTIO_BALLOON_S_MAJFLT, VIRTIO_BALLOON_S_MEMFREE,
-    VIRTIO_BALLOON_S_MEMTOT, VIRTIO_BALLOON_S_MINFLT, VIRTIO_BALLOON_S_SWAP_IN,
-    VIRTIO_BALLOON_S_SWAP_OUT,
+    VIRTIO_BALLOON_F_DEFLATE_ON_OOM, VIRTIO_BALLOON_F_FREE_PAGE_HINT, VIRTIO_BALLOON_F_PAGE_POISON,
+    VIRTIO_BALLOON_F_STATS_VQ, VIRTIO_BALLOON_PFN_SHIFT, VIRTIO_BALLOON_S_ALLOC_STALL,
+    VIRTIO_BALLOON_S_ASYNC_RECLAIM, VIRTIO_BALLOON_S_ASYNC_SCAN, VIRTIO_BALLOON_S_AVAIL,
+    VIRTIO_BALLOON_S_CACHES, VIRTIO_BALLOON_S_DIRECT_RECLAIM, VIRTIO_BALLOON_S_DIRECT_SCAN,
+    VIRTIO_BALLOON_S_HTLB_PGALLOC, VIRTIO_BALLOON_S_HTLB_PGFAIL, VIRTIO_BALLOON_S_MAJFLT,
+    VIRTIO_BALLOON_S_MEMFREE, VIRTIO_BALLOON_S_MEMTOT, VIRTIO_BALLOON_S_MINFLT,
+    VIRTIO_BALLOON_S_OOM_KILL, VIRTIO_BALLOON_S_SWAP_IN, VIRTIO_BALLOON_S_SWAP_OUT,
 };
 use crate::devices::virtio::balloon::BalloonError;
 use crate::devices::virtio::{IrqTrigger, IrqType};
@@ -32,10 +36,10 @@ use crate::devices::virtio::{IrqTrigger, IrqType};
 const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
 const SIZE_OF_STAT: usize = std::mem::size_of::<BalloonStat>();
 
-fn mib_to_pages(amount_mib: u32) -> Result<u32, BalloonError> {
-    amount_mib
-        .checked_mul(MIB_TO_4K_PAGES)
-        .ok_or(BalloonError::TooManyPagesRequested)
+// The target is rounded down to whole 4K pages.
+fn bytes_to_pages(amount_bytes: u64) -> Result<u32, BalloonError> {
+    u32::try_from(amount_bytes >> VIRTIO_BALLOON_PFN_SHIFT)
+        .map_err(|_| BalloonError::TooManyPagesRequested)
 }
 
 fn pages_to_mib(amount_pages: u32) -> u32 {
@@ -47,6 +51,8 @@ fn pages_to_mib(amount_pages: u32) -> u32 {
 pub(crate) struct ConfigSpace {
     pub num_pages: u32,
     pub actual_pages: u32,
+    pub free_page_hint_cmd_id: u32,
+    pub poison_val: u32,
 }
 
 // SAFETY: Safe because ConfigSpace only contains plain data.
@@ -67,12 +73,27 @@ unsafe impl ByteValued for BalloonStat {}
 /// Holds configuration details for the balloon device.
 #[derive(Clone, Default, Debug, PartialEq, Eq, Serialize)]
 pub struct BalloonConfig {
-    /// Target size.
+    /// Target size, rounded down to MiB.
     pub amount_mib: u32,
+    /// Target size in bytes.
+    pub amount_bytes: u64,
     /// Whether or not to ask for pages back.
     pub deflate_on_oom: bool,
     /// Interval of time in seconds at which the balloon statistics are updated.
     pub stats_polling_interval_s: u16,
+    /// Whether or not the guest can be asked to report its free pages.
+    pub free_page_hinting: bool,
+}
+
+/// Holds the progress of the latest free page hinting run.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize)]
+pub struct BalloonHintingStatus {
+    /// Whether the guest reported all its free pages.
+    pub complete: bool,
+    /// The number of 4K pages reported by the guest, and discarded by the device.
+    pub hinted_pages: u64,
+    /// The number of MiB reported by the guest, and discarded by the device.
+    pub hinted_mib: u64,
 }
 
 /// BalloonStats holds statistics returned from the stats_queue.
@@ -123,6 +144,24 @@ pub struct BalloonStats {
     /// in the guest.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hugetlb_failures: Option<u64>,
+    /// The number of processes killed by the OOM killer of the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oom_kills: Option<u64>,
+    /// The number of allocations which stalled on direct reclaim in the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alloc_stalls: Option<u64>,
+    /// The number of pages scanned by the background reclaim of the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub async_scans: Option<u64>,
+    /// The number of pages scanned by the direct reclaim of the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct_scans: Option<u64>,
+    /// The number of pages reclaimed in the background by the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub async_reclaims: Option<u64>,
+    /// The number of pages reclaimed directly by the allocations of the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct_reclaims: Option<u64>,
 }
 
 impl BalloonStats {
@@ -139,6 +178,12 @@ impl BalloonStats {
             VIRTIO_BALLOON_S_CACHES => self.disk_caches = val,
             VIRTIO_BALLOON_S_HTLB_PGALLOC => self.hugetlb_allocations = val,
             VIRTIO_BALLOON_S_HTLB_PGFAIL => self.hugetlb_failures = val,
+            VIRTIO_BALLOON_S_OOM_KILL => self.oom_kills = val,
+            VIRTIO_BALLOON_S_ALLOC_STALL => self.alloc_stalls = val,
+            VIRTIO_BALLOON_S_ASYNC_SCAN => self.async_scans = val,
+            VIRTIO_BALLOON_S_DIRECT_SCAN => self.direct_scans = val,
+            VIRTIO_BALLOON_S_ASYNC_RECLAIM => self.async_reclaims = val,
+            VIRTIO_BALLOON_S_DIRECT_RECLAIM => self.direct_reclaims = val,
             _ => {
                 return Err(BalloonError::MalformedPayload);
             }
@@ -148,6 +193,15 @@ impl BalloonStats {
     }
 }
 
+// Number of events counted by the guest between two statistics updates. A counter lower than
+// before was reset by a reboot of the guest.
+fn counter_delta(previous: Option<u64>, current: Option<u64>) -> u64 {
+    match (previous, current) {
+        (Some(previous), Some(current)) if current >= previous => current - previous,
+        (_, current) => current.unwrap_or(0),
+    }
+}
+
 /// Virtio balloon device.
 pub struct Balloon {
     // Virtio fields.
@@ -172,6 +226,18 @@ pub struct Balloon {
     pub(crate) latest_stats: BalloonStats,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
+    // The page ranges currently held by the balloon, as a map from the first page frame
+    // number of each range to its length in pages.
+    pub(crate) removed_ranges: BTreeMap<u64, u64>,
+    // The command id of the latest free page hinting run. The guest ignores a run which reuses
+    // the command id of the previous one.
+    pub(crate) hint_cmd_id: u32,
+    // The last command id sent by the guest on the free page hinting queue.
+    pub(crate) hint_cmd_id_received: u32,
+    // Whether the guest reported all its free pages for the current command id.
+    pub(crate) hint_complete: bool,
+    // The number of 4K pages reported for the current command id.
+    pub(crate) hinted_pages: u64,
 }
 
 // TODO Use `#[derive(Debug)]` when a new release of
@@ -193,6 +259,11 @@ impl fmt::Debug for Balloon {
             .field("stats_desc_index", &self.stats_desc_index)
             .field("latest_stats", &self.latest_stats)
             .field("pfn_buffer", &self.pfn_buffer)
+            .field("removed_ranges", &self.removed_ranges)
+            .field("hint_cmd_id", &self.hint_cmd_id)
+            .field("hint_cmd_id_received", &self.hint_cmd_id_received)
+            .field("hint_complete", &self.hint_complete)
+            .field("hinted_pages", &self.hinted_pages)
             .finish()
     }
 }
@@ -200,12 +271,16 @@ impl fmt::Debug for Balloon {
 impl Balloon {
     /// Instantiate a new balloon device.
     pub fn new(
-        amount_mib: u32,
+        amount_bytes: u64,
         deflate_on_oom: bool,
         stats_polling_interval_s: u16,
+        free_page_hinting: bool,
         restored: bool,
     ) -> Result<Balloon, BalloonError> {
-        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+        // The device does not touch the pages it holds, so the guest can always tell it the
+        // value it poisons its free pages with.
+        let mut avail_features =
+            (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BALLOON_F_PAGE_POISON);
 
         if deflate_on_oom {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
@@ -215,16 +290,24 @@ impl Balloon {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
         }
 
+        if free_page_hinting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT;
+        }
+
         let queue_evts = [
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
         ];
 
         let mut queues: Vec<Queue> = BALLOON_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
-        // The VirtIO specification states that the statistics queue should
-        // not be present at all if the statistics are not enabled.
+        // The VirtIO specification states that the statistics and the free page hinting queues
+        // should not be present at all if the features are not enabled.
+        if !free_page_hinting {
+            let _ = queues.remove(FREE_PAGE_HINT_INDEX);
+        }
         if stats_polling_interval_s == 0 {
             let _ = queues.remove(STATS_INDEX);
         }
@@ -236,8 +319,10 @@ impl Balloon {
             avail_features,
             acked_features: 0u64,
             config_space: ConfigSpace {
-                num_pages: mib_to_pages(amount_mib)?,
+                num_pages: bytes_to_pages(amount_bytes)?,
                 actual_pages: 0,
+                free_page_hint_cmd_id: 0,
+                poison_val: 0,
             },
             queue_evts,
             queues,
@@ -250,6 +335,11 @@ impl Balloon {
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
+            removed_ranges: BTreeMap::new(),
+            hint_cmd_id: FREE_PAGE_HINT_CMD_ID_STOP,
+            hint_cmd_id_received: FREE_PAGE_HINT_CMD_ID_STOP,
+            hint_complete: false,
+            hinted_pages: 0,
         })
     }
 
@@ -274,6 +364,13 @@ impl Balloon {
         self.process_stats_queue()
     }
 
+    pub(crate) fn process_free_page_hint_queue_event(&mut self) -> Result<(), BalloonError> {
+        self.queue_evts[self.free_page_hint_index()]
+            .read()
+            .map_err(BalloonError::EventFd)?;
+        self.process_free_page_hint_queue()
+    }
+
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
         self.stats_timer.read();
         self.trigger_stats_update()
@@ -353,12 +450,17 @@ impl Balloon {
                 let guest_addr =
                     GuestAddress(u64::from(page_frame_number) << VIRTIO_BALLOON_PFN_SHIFT);
 
-                if let Err(err) = remove_range(
+                match remove_range(
                     mem,
                     (guest_addr, u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT),
                     self.restored,
                 ) {
-                    error!("Error removing memory range: {:?}", err);
+                    Ok(()) => insert_pfn_range(
+                        &mut self.removed_ranges,
+                        u64::from(page_frame_number),
+                        u64::from(range_len),
+                    ),
+                    Err(err) => error!("Error removing memory range: {:?}", err),
                 }
             }
         }
@@ -379,6 +481,33 @@ impl Balloon {
         let mut needs_interrupt = false;
 
         while let Some(head) = queue.pop(mem) {
+            let len = head.len as usize;
+            // The deflated pages are handed back to the guest as they are, they only need to
+            // be dropped from the ranges held by the balloon.
+            if !head.is_write_only()
+                && len % SIZE_OF_U32 == 0
+                && len <= MAX_PAGES_IN_DESC * SIZE_OF_U32
+            {
+                let mut pfns = Vec::with_capacity(len / SIZE_OF_U32);
+                for index in (0..len).step_by(SIZE_OF_U32) {
+                    let addr = head
+                        .addr
+                        .checked_add(index as u64)
+                        .ok_or(BalloonError::MalformedDescriptor)?;
+                    pfns.push(
+                        mem.read_obj::<u32>(addr)
+                            .map_err(|_| BalloonError::MalformedDescriptor)?,
+                    );
+                }
+                for (page_frame_number, range_len) in compact_page_frame_numbers(&mut pfns) {
+                    remove_pfn_range(
+                        &mut self.removed_ranges,
+                        u64::from(page_frame_number),
+                        u64::from(range_len),
+                    );
+                }
+            }
+
             queue
                 .add_used(mem, head.index, 0)
                 .map_err(BalloonError::Queue)?;
@@ -396,6 +525,8 @@ impl Balloon {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         METRICS.balloon.stats_updates_count.inc();
+        let oom_kills = self.latest_stats.oom_kills;
+        let alloc_stalls = self.latest_stats.alloc_stalls;
 
         while let Some(head) = self.queues[STATS_INDEX].pop(mem) {
             if let Some(prev_stats_desc) = self.stats_desc_index {
@@ -427,9 +558,88 @@ impl Balloon {
             self.stats_desc_index = Some(head.index);
         }
 
+        self.report_memory_pressure(oom_kills, alloc_stalls);
         Ok(())
     }
 
+    pub(crate) fn process_free_page_hint_queue(&mut self) -> Result<(), BalloonError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let index = self.free_page_hint_index();
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[index].pop(mem) {
+            if !head.is_write_only() {
+                // The guest tells which command it is answering before reporting its free
+                // pages, and sends the stop command id once it reported all of them.
+                if head.len as usize == SIZE_OF_U32 {
+                    let cmd_id = mem
+                        .read_obj::<u32>(head.addr)
+                        .map_err(|_| BalloonError::MalformedDescriptor)?;
+                    if cmd_id == FREE_PAGE_HINT_CMD_ID_STOP
+                        && self.hint_cmd_id_received == self.config_space.free_page_hint_cmd_id
+                    {
+                        self.hint_complete = true;
+                    }
+                    self.hint_cmd_id_received = cmd_id;
+                } else {
+                    error!("balloon: malformed free page hinting command id, skipping.");
+                }
+            } else if self.hint_cmd_id_received == self.config_space.free_page_hint_cmd_id
+                && self.hint_cmd_id_received > FREE_PAGE_HINT_CMD_ID_DONE
+            {
+                // The guest does not touch the pages it reports until the device hands them
+                // back with the done command id, so they can be discarded right away.
+                let len = u64::from(head.len);
+                match remove_range(mem, (head.addr, len), self.restored) {
+                    Ok(()) => self.hinted_pages += len >> VIRTIO_BALLOON_PFN_SHIFT,
+                    Err(err) => error!("Error removing hinted memory range: {:?}", err),
+                }
+            }
+
+            // The pages are only reported, the device never writes to the buffers.
+            self.queues[index]
+                .add_used(mem, head.index, 0)
+                .map_err(BalloonError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Reports the OOM kills and the stalled allocations counted by the guest since the previous
+    // statistics update. The metrics are written right away, so that the host learns about them
+    // without waiting for the periodic metrics flush.
+    fn report_memory_pressure(&self, oom_kills: Option<u64>, alloc_stalls: Option<u64>) {
+        let oom_kills = counter_delta(oom_kills, self.latest_stats.oom_kills);
+        let alloc_stalls = counter_delta(alloc_stalls, self.latest_stats.alloc_stalls);
+        if oom_kills == 0 && alloc_stalls == 0 {
+            return;
+        }
+
+        if oom_kills != 0 {
+            warn!("The guest OOM killer killed {} processes.", oom_kills);
+            METRICS.balloon.guest_oom_kills.add(oom_kills as usize);
+        }
+        if alloc_stalls != 0 {
+            warn!(
+                "{} guest memory allocations stalled on direct reclaim.",
+                alloc_stalls
+            );
+            METRICS
+                .balloon
+                .guest_alloc_stalls
+                .add(alloc_stalls as usize);
+        }
+        if let Err(err) = METRICS.write() {
+            error!("Failed to write metrics: {}", err);
+        }
+    }
+
     pub(crate) fn signal_used_queue(&self) -> Result<(), BalloonError> {
         self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|err| {
             METRICS.balloon.event_fails.inc();
@@ -441,6 +651,9 @@ impl Balloon {
     pub fn process_virtio_queues(&mut self) {
         let _ = self.process_inflate();
         let _ = self.process_deflate_queue();
+        if self.free_page_hinting() {
+            let _ = self.process_free_page_hint_queue();
+        }
     }
 
     /// Provides the ID of this balloon device.
@@ -465,10 +678,10 @@ impl Balloon {
         }
     }
 
-    /// Update the target size of the balloon.
-    pub fn update_size(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
+    /// Update the target size of the balloon, in bytes.
+    pub fn update_size(&mut self, amount_bytes: u64) -> Result<(), BalloonError> {
         if self.is_activated() {
-            self.config_space.num_pages = mib_to_pages(amount_mib)?;
+            self.config_space.num_pages = bytes_to_pages(amount_bytes)?;
             self.irq_trigger
                 .trigger_irq(IrqType::Config)
                 .map_err(BalloonError::InterruptError)
@@ -503,6 +716,57 @@ impl Balloon {
             .set_state(timer_state, SetTimeFlags::Default);
     }
 
+    /// Ask the guest to report its free pages, which the device discards as they come. The
+    /// guest keeps the reported pages until the hinting is stopped.
+    pub fn start_free_page_hinting(&mut self) -> Result<(), BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::FreePageHintingDisabled);
+        }
+        if !self.is_activated() {
+            return Err(BalloonError::DeviceNotActive);
+        }
+
+        // Every run uses a new command id, the reserved ones are skipped on wrap around.
+        self.hint_cmd_id = self
+            .hint_cmd_id
+            .wrapping_add(1)
+            .max(FREE_PAGE_HINT_CMD_ID_DONE + 1);
+        self.config_space.free_page_hint_cmd_id = self.hint_cmd_id;
+        self.hint_complete = false;
+        self.hinted_pages = 0;
+        self.irq_trigger
+            .trigger_irq(IrqType::Config)
+            .map_err(BalloonError::InterruptError)
+    }
+
+    /// Hand the reported pages back to the guest.
+    pub fn stop_free_page_hinting(&mut self) -> Result<(), BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::FreePageHintingDisabled);
+        }
+        if !self.is_activated() {
+            return Err(BalloonError::DeviceNotActive);
+        }
+
+        self.config_space.free_page_hint_cmd_id = FREE_PAGE_HINT_CMD_ID_DONE;
+        self.irq_trigger
+            .trigger_irq(IrqType::Config)
+            .map_err(BalloonError::InterruptError)
+    }
+
+    /// Retrieve the progress of the latest free page hinting run.
+    pub fn free_page_hinting_status(&self) -> Result<BalloonHintingStatus, BalloonError> {
+        if !self.free_page_hinting() {
+            return Err(BalloonError::FreePageHintingDisabled);
+        }
+
+        Ok(BalloonHintingStatus {
+            complete: self.hint_complete,
+            hinted_pages: self.hinted_pages,
+            hinted_mib: self.hinted_pages / u64::from(MIB_TO_4K_PAGES),
+        })
+    }
+
     /// Obtain the number of 4K pages the device is currently holding.
     pub fn num_pages(&self) -> u32 {
         self.config_space.num_pages
@@ -521,6 +785,10 @@ impl Balloon {
         self.stats_polling_interval_s
     }
 
+    pub fn free_page_hinting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT) != 0
+    }
+
     /// Retrieve latest stats for the balloon device.
     pub fn latest_stats(&mut self) -> Option<&BalloonStats> {
         if self.stats_enabled() {
@@ -538,15 +806,38 @@ impl Balloon {
     pub fn config(&self) -> BalloonConfig {
         BalloonConfig {
             amount_mib: self.size_mb(),
+            amount_bytes: u64::from(self.config_space.num_pages) << VIRTIO_BALLOON_PFN_SHIFT,
             deflate_on_oom: self.deflate_on_oom(),
             stats_polling_interval_s: self.stats_polling_interval_s(),
+            free_page_hinting: self.free_page_hinting(),
         }
     }
 
+    /// Returns the guest memory ranges, as (address, length in bytes) pairs, currently held by
+    /// the balloon.
+    pub(crate) fn removed_ranges(&self) -> impl Iterator<Item = (GuestAddress, u64)> + '_ {
+        self.removed_ranges.iter().map(|(start_pfn, num_pages)| {
+            (
+                GuestAddress(start_pfn << VIRTIO_BALLOON_PFN_SHIFT),
+                num_pages << VIRTIO_BALLOON_PFN_SHIFT,
+            )
+        })
+    }
+
     pub(crate) fn stats_enabled(&self) -> bool {
         self.stats_polling_interval_s > 0
     }
 
+    // The free page hinting queue comes right after the statistics queue, which is only present
+    // when the statistics are enabled.
+    pub(crate) fn free_page_hint_index(&self) -> usize {
+        if self.stats_enabled() {
+            FREE_PAGE_HINT_INDEX
+        } else {
+            STATS_INDEX
+        }
+    }
+
     pub(crate) fn set_stats_desc_index(&mut self, stats_desc_index: Option<u16>) {
         self.stats_desc_index = stats_desc_index;
     }
@@ -612,8 +903,8 @@ impl VirtioDevice for Balloon {
         let end = start.and_then(|s| s.checked_add(data.len()));
         let Some(dst) = start
             .zip(end)
-            .and_then(|(start, end)| config_space_bytes.get_mut(start..end)) else
-        {
+            .and_then(|(start, end)| config_space_bytes.get_mut(start..end))
+        else {
             error!("Failed to write config space");
             return;
         };
@@ -699,6 +990,12 @@ pub(crate) mod tests {
             disk_caches: Some(0),
             hugetlb_allocations: Some(0),
             hugetlb_failures: Some(0),
+            oom_kills: Some(0),
+            alloc_stalls: Some(0),
+            async_scans: Some(0),
+            direct_scans: Some(0),
+            async_reclaims: Some(0),
+            direct_reclaims: Some(0),
         };
 
         let mut stat = BalloonStat {
@@ -735,6 +1032,24 @@ pub(crate) mod tests {
         stat.tag = VIRTIO_BALLOON_S_HTLB_PGFAIL;
         stats.update_with_stat(&stat).unwrap();
         assert_eq!(stats.hugetlb_failures, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_OOM_KILL;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.oom_kills, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_ALLOC_STALL;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.alloc_stalls, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_ASYNC_SCAN;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.async_scans, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_DIRECT_SCAN;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.direct_scans, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_ASYNC_RECLAIM;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.async_reclaims, Some(1));
+        stat.tag = VIRTIO_BALLOON_S_DIRECT_RECLAIM;
+        stats.update_with_stat(&stat).unwrap();
+        assert_eq!(stats.direct_reclaims, Some(1));
     }
 
     #[test]
@@ -742,52 +1057,73 @@ pub(crate) mod tests {
         // Test all feature combinations.
         for deflate_on_oom in vec![true, false].iter() {
             for stats_interval in vec![0, 1].iter() {
-                let mut balloon = Balloon::new(0, *deflate_on_oom, *stats_interval, false).unwrap();
-                assert_eq!(balloon.device_type(), TYPE_BALLOON);
-
-                let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
-                    | (u64::from(*deflate_on_oom) << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
-                    | ((u64::from(*stats_interval)) << VIRTIO_BALLOON_F_STATS_VQ);
-
-                assert_eq!(balloon.avail_features_by_page(0), features as u32);
-                assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
-                for i in 2..10 {
-                    assert_eq!(balloon.avail_features_by_page(i), 0u32);
-                }
+                for free_page_hinting in vec![true, false].iter() {
+                    let mut balloon = Balloon::new(
+                        0,
+                        *deflate_on_oom,
+                        *stats_interval,
+                        *free_page_hinting,
+                        false,
+                    )
+                    .unwrap();
+                    assert_eq!(balloon.device_type(), TYPE_BALLOON);
+                    // The statistics and the free page hinting queues only exist when enabled.
+                    assert_eq!(
+                        balloon.queues().len(),
+                        2 + *stats_interval as usize + usize::from(*free_page_hinting)
+                    );
+
+                    let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+                        | (1u64 << VIRTIO_BALLOON_F_PAGE_POISON)
+                        | (u64::from(*deflate_on_oom) << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
+                        | ((u64::from(*stats_interval)) << VIRTIO_BALLOON_F_STATS_VQ)
+                        | (u64::from(*free_page_hinting) << VIRTIO_BALLOON_F_FREE_PAGE_HINT);
+
+                    assert_eq!(balloon.avail_features_by_page(0), features as u32);
+                    assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
+                    for i in 2..10 {
+                        assert_eq!(balloon.avail_features_by_page(i), 0u32);
+                    }
 
-                for i in 0..10 {
-                    balloon.ack_features_by_page(i, u32::MAX);
+                    for i in 0..10 {
+                        balloon.ack_features_by_page(i, u32::MAX);
+                    }
+                    // Only present features should be acknowledged.
+                    assert_eq!(balloon.acked_features, features);
                 }
-                // Only present features should be acknowledged.
-                assert_eq!(balloon.acked_features, features);
             }
         }
     }
 
     #[test]
     fn test_virtio_read_config() {
-        let balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let balloon = Balloon::new(0x10 << 20, true, 0, false, false).unwrap();
 
         let cfg = BalloonConfig {
             amount_mib: 16,
+            amount_bytes: 0x100_0000,
             deflate_on_oom: true,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
         };
         assert_eq!(balloon.config(), cfg);
 
         let mut actual_config_space = [0u8; BALLOON_CONFIG_SPACE_SIZE];
         balloon.read_config(0, &mut actual_config_space);
-        // The first 4 bytes are num_pages, the last 4 bytes are actual_pages.
-        // The config space is little endian.
+        // The first 4 bytes are num_pages, the next 4 bytes are actual_pages, followed by
+        // free_page_hint_cmd_id and poison_val. The config space is little endian.
         // 0x10 MB in the constructor corresponds to 0x1000 pages in the
         // config space.
-        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] =
-            [0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] = [
+            0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
         assert_eq!(actual_config_space, expected_config_space);
 
         // Invalid read.
-        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] =
-            [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] = [
+            0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf, 0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf,
+        ];
         actual_config_space = expected_config_space;
         balloon.read_config(
             BALLOON_CONFIG_SPACE_SIZE as u64 + 1,
@@ -800,10 +1136,12 @@ pub(crate) mod tests {
 
     #[test]
     fn test_virtio_write_config() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
 
-        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] = [
+            0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xaa, 0xaa,
+            0xaa, 0xaa,
+        ];
         balloon.write_config(0, &expected_config_space);
 
         let mut actual_config_space = [0u8; BALLOON_CONFIG_SPACE_SIZE];
@@ -812,7 +1150,7 @@ pub(crate) mod tests {
 
         // Invalid write.
         let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
-        balloon.write_config(5, &new_config_space);
+        balloon.write_config(9, &new_config_space);
         // Make sure nothing got written.
         balloon.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
@@ -826,7 +1164,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_invalid_request() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         // Only initialize the inflate queue to demonstrate invalid request handling.
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
@@ -885,7 +1223,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_inflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
@@ -938,12 +1276,14 @@ pub(crate) mod tests {
             for i in 0..0x1000 {
                 assert_eq!(mem.read_obj::<u8>(GuestAddress((1 << 12) + i)).unwrap(), 0);
             }
+            // Check that the page is tracked as held by the balloon.
+            assert_eq!(balloon.removed_ranges, BTreeMap::from([(1, 1)]));
         }
     }
 
     #[test]
     fn test_deflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let defq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
@@ -967,6 +1307,8 @@ pub(crate) mod tests {
 
         // Happy case.
         {
+            balloon.removed_ranges = BTreeMap::from([(1, 2)]);
+            mem.write_obj::<u32>(0x1, GuestAddress(page_addr)).unwrap();
             set_request(&defq, 1, page_addr, SIZE_OF_U32 as u32, VIRTQ_DESC_F_NEXT);
             check_metric_after_block!(
                 METRICS.balloon.deflate_count,
@@ -974,12 +1316,106 @@ pub(crate) mod tests {
                 invoke_handler_for_queue_event(&mut balloon, DEFLATE_INDEX)
             );
             check_request_completion(&defq, 1);
+
+            // Check that the deflated page is no longer tracked as held by the balloon.
+            assert_eq!(balloon.removed_ranges, BTreeMap::from([(2, 1)]));
+        }
+    }
+
+    #[test]
+    fn test_free_page_hinting() {
+        // The device only accepts hinting requests when the feature is enabled and active.
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
+        assert!(matches!(
+            balloon.start_free_page_hinting(),
+            Err(BalloonError::FreePageHintingDisabled)
+        ));
+        assert!(matches!(
+            balloon.free_page_hinting_status(),
+            Err(BalloonError::FreePageHintingDisabled)
+        ));
+        let mut balloon = Balloon::new(0, true, 0, true, false).unwrap();
+        assert!(matches!(
+            balloon.start_free_page_hinting(),
+            Err(BalloonError::DeviceNotActive)
+        ));
+
+        // Without the statistics, the free page hinting queue takes their index.
+        assert_eq!(balloon.free_page_hint_index(), STATS_INDEX);
+        let mem = default_mem();
+        let hintq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(STATS_INDEX, hintq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        balloon.start_free_page_hinting().unwrap();
+        assert!(balloon.irq_trigger.has_pending_irq(IrqType::Config));
+        let cmd_id = balloon.config_space.free_page_hint_cmd_id;
+        assert!(cmd_id > FREE_PAGE_HINT_CMD_ID_DONE);
+
+        // The guest answers with the command id.
+        let cmd_addr = 0x100;
+        mem.write_obj::<u32>(cmd_id, GuestAddress(cmd_addr))
+            .unwrap();
+        set_request(&hintq, 0, cmd_addr, SIZE_OF_U32 as u32, 0);
+        invoke_handler_for_queue_event(&mut balloon, STATS_INDEX);
+        check_request_completion(&hintq, 0);
+
+        // Then reports a free page, which is discarded.
+        let page_addr = 0x2000;
+        for i in 0..0x1000 {
+            mem.write_obj::<u8>(1, GuestAddress(page_addr + i)).unwrap();
+        }
+        set_request(&hintq, 1, page_addr, 0x1000, VIRTQ_DESC_F_WRITE);
+        invoke_handler_for_queue_event(&mut balloon, STATS_INDEX);
+        check_request_completion(&hintq, 1);
+        for i in 0..0x1000 {
+            assert_eq!(mem.read_obj::<u8>(GuestAddress(page_addr + i)).unwrap(), 0);
+        }
+        assert_eq!(
+            balloon.free_page_hinting_status().unwrap(),
+            BalloonHintingStatus {
+                complete: false,
+                hinted_pages: 1,
+                hinted_mib: 0,
+            }
+        );
+
+        // The run is complete once the guest sends the stop command id.
+        mem.write_obj::<u32>(FREE_PAGE_HINT_CMD_ID_STOP, GuestAddress(cmd_addr))
+            .unwrap();
+        set_request(&hintq, 2, cmd_addr, SIZE_OF_U32 as u32, 0);
+        invoke_handler_for_queue_event(&mut balloon, STATS_INDEX);
+        check_request_completion(&hintq, 2);
+        assert!(balloon.free_page_hinting_status().unwrap().complete);
+
+        // Pages reported outside of a run are left alone.
+        balloon.stop_free_page_hinting().unwrap();
+        assert_eq!(
+            balloon.config_space.free_page_hint_cmd_id,
+            FREE_PAGE_HINT_CMD_ID_DONE
+        );
+        for i in 0..0x1000 {
+            mem.write_obj::<u8>(1, GuestAddress(page_addr + i)).unwrap();
         }
+        set_request(&hintq, 3, page_addr, 0x1000, VIRTQ_DESC_F_WRITE);
+        invoke_handler_for_queue_event(&mut balloon, STATS_INDEX);
+        check_request_completion(&hintq, 3);
+        for i in 0..0x1000 {
+            assert_eq!(mem.read_obj::<u8>(GuestAddress(page_addr + i)).unwrap(), 1);
+        }
+
+        // A new run starts from scratch, with a new command id.
+        balloon.start_free_page_hinting().unwrap();
+        assert_ne!(balloon.config_space.free_page_hint_cmd_id, cmd_id);
+        assert_eq!(
+            balloon.free_page_hinting_status().unwrap(),
+            BalloonHintingStatus::default()
+        );
     }
 
     #[test]
     fn test_stats() {
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         let mem = default_mem();
         let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(STATS_INDEX, statsq.create_queue());
@@ -1058,9 +1494,71 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_memory_pressure_stats() {
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
+        let mem = default_mem();
+        let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(STATS_INDEX, statsq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        let page_addr = 0x100;
+        let mut send_stats = |idx: usize, oom_kills: u64, alloc_stalls: u64| {
+            let oom_kill_stat = BalloonStat {
+                tag: VIRTIO_BALLOON_S_OOM_KILL,
+                val: oom_kills,
+            };
+            let alloc_stall_stat = BalloonStat {
+                tag: VIRTIO_BALLOON_S_ALLOC_STALL,
+                val: alloc_stalls,
+            };
+            mem.write_obj::<BalloonStat>(oom_kill_stat, GuestAddress(page_addr))
+                .unwrap();
+            mem.write_obj::<BalloonStat>(
+                alloc_stall_stat,
+                GuestAddress(page_addr + SIZE_OF_STAT as u64),
+            )
+            .unwrap();
+            set_request(
+                &statsq,
+                idx,
+                page_addr,
+                2 * SIZE_OF_STAT as u32,
+                VIRTQ_DESC_F_NEXT,
+            );
+            balloon.queue_events()[STATS_INDEX].write(1).unwrap();
+            balloon.process_stats_queue_event().unwrap();
+            // Give the descriptor back to the driver.
+            balloon.process_stats_timer_event().unwrap();
+        };
+
+        // The first update reports the events counted since the guest booted.
+        check_metric_after_block!(METRICS.balloon.guest_oom_kills, 2, {
+            check_metric_after_block!(METRICS.balloon.guest_alloc_stalls, 10, {
+                send_stats(0, 2, 10);
+            });
+        });
+
+        // Later updates only report the new events.
+        check_metric_after_block!(METRICS.balloon.guest_oom_kills, 3, {
+            check_metric_after_block!(METRICS.balloon.guest_alloc_stalls, 0, {
+                send_stats(1, 5, 10);
+            });
+        });
+
+        // Lower counters were reset by a reboot of the guest.
+        check_metric_after_block!(METRICS.balloon.guest_oom_kills, 1, {
+            check_metric_after_block!(METRICS.balloon.guest_alloc_stalls, 4, {
+                send_stats(2, 1, 4);
+            });
+        });
+        assert_eq!(balloon.latest_stats.oom_kills, Some(1));
+        assert_eq!(balloon.latest_stats.alloc_stalls, Some(4));
+    }
+
     #[test]
     fn test_process_balloon_queues() {
-        let mut balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0x10 << 20, true, 0, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         let defq = VirtQueue::new(GuestAddress(0), &mem, 16);
@@ -1074,7 +1572,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_update_stats_interval() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         assert_eq!(
@@ -1083,7 +1581,7 @@ pub(crate) mod tests {
         );
         assert!(balloon.update_stats_polling_interval(0).is_ok());
 
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         assert_eq!(
@@ -1096,7 +1594,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_num_pages() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         // Assert that we can't update an inactive device.
         assert!(balloon.update_size(1).is_err());
         // Switch the state to active.
@@ -1115,9 +1613,9 @@ pub(crate) mod tests {
         balloon.update_actual_pages(0x1234);
         balloon.update_num_pages(0x100);
         assert_eq!(balloon.num_pages(), 0x100);
-        assert!(balloon.update_size(16).is_ok());
+        assert!(balloon.update_size(16 << 20).is_ok());
 
-        let mut actual_config = vec![0; BALLOON_CONFIG_SPACE_SIZE];
+        let mut actual_config = vec![0; 8];
         balloon.read_config(0, &mut actual_config);
         assert_eq!(actual_config, vec![0x0, 0x10, 0x0, 0x0, 0x34, 0x12, 0, 0]);
         assert_eq!(balloon.num_pages(), 0x1000);
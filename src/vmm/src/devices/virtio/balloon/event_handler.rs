@@ -19,6 +19,12 @@ impl Balloon {
         if let Err(err) = ops.add(Events::new(&self.queue_evts[DEFLATE_INDEX], EventSet::IN)) {
             error!("Failed to register deflate queue event: {}", err);
         }
+        if self.free_page_hinting() {
+            let hint_evt = &self.queue_evts[self.free_page_hint_index()];
+            if let Err(err) = ops.add(Events::new(hint_evt, EventSet::IN)) {
+                error!("Failed to register free page hinting queue event: {}", err);
+            }
+        }
         if self.stats_enabled() {
             if let Err(err) = ops.add(Events::new(&self.queue_evts[STATS_INDEX], EventSet::IN)) {
                 error!("Failed to register stats queue event: {}", err);
@@ -64,7 +70,14 @@ impl MutEventSubscriber for Balloon {
         if self.is_activated() {
             let virtq_inflate_ev_fd = self.queue_evts[INFLATE_INDEX].as_raw_fd();
             let virtq_deflate_ev_fd = self.queue_evts[DEFLATE_INDEX].as_raw_fd();
-            let virtq_stats_ev_fd = self.queue_evts[STATS_INDEX].as_raw_fd();
+            // The statistics queue is only there when the statistics are enabled, its index
+            // goes to the free page hinting queue otherwise.
+            let virtq_stats_ev_fd = self
+                .stats_enabled()
+                .then(|| self.queue_evts[STATS_INDEX].as_raw_fd());
+            let virtq_hint_ev_fd = self
+                .free_page_hinting()
+                .then(|| self.queue_evts[self.free_page_hint_index()].as_raw_fd());
             let stats_timer_fd = self.stats_timer.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
@@ -76,9 +89,12 @@ impl MutEventSubscriber for Balloon {
                 _ if source == virtq_deflate_ev_fd => self
                     .process_deflate_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
-                _ if source == virtq_stats_ev_fd => self
+                _ if Some(source) == virtq_stats_ev_fd => self
                     .process_stats_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
+                _ if Some(source) == virtq_hint_ev_fd => self
+                    .process_free_page_hint_queue_event()
+                    .unwrap_or_else(report_balloon_event_fail),
                 _ if source == stats_timer_fd => self
                     .process_stats_timer_event()
                     .unwrap_or_else(report_balloon_event_fail),
@@ -122,7 +138,7 @@ pub mod tests {
     #[test]
     fn test_event_handler() {
         let mut event_manager = EventManager::new().unwrap();
-        let mut balloon = Balloon::new(0, true, 10, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 10, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
@@ -7,26 +7,28 @@ pub mod device;
 mod event_handler;
 pub mod persist;
 pub mod test_utils;
-mod util;
+pub(crate) mod util;
 
 use utils::vm_memory::GuestMemoryError;
 
-pub use self::device::{Balloon, BalloonConfig, BalloonStats};
+pub use self::device::{Balloon, BalloonConfig, BalloonHintingStatus, BalloonStats};
 use crate::devices::virtio::FIRECRACKER_MAX_QUEUE_SIZE;
 
 /// Device ID used in MMIO device identification.
 /// Because Balloon is unique per-vm, this ID can be hardcoded.
 pub const BALLOON_DEV_ID: &str = "balloon";
 /// The size of the config space.
-pub const BALLOON_CONFIG_SPACE_SIZE: usize = 8;
+pub const BALLOON_CONFIG_SPACE_SIZE: usize = 16;
 /// Number of virtio queues.
-pub const BALLOON_NUM_QUEUES: usize = 3;
+pub const BALLOON_NUM_QUEUES: usize = 4;
 /// Virtio queue sizes, in number of descriptor chain heads.
-//  There are 3 queues for a virtio device (in this order): RX, TX, Event
+//  There are 4 queues for a virtio device (in this order): inflate, deflate, stats, free page
+//  hinting
 pub const BALLOON_QUEUE_SIZES: [u16; BALLOON_NUM_QUEUES] = [
     FIRECRACKER_MAX_QUEUE_SIZE,
     FIRECRACKER_MAX_QUEUE_SIZE,
     FIRECRACKER_MAX_QUEUE_SIZE,
+    FIRECRACKER_MAX_QUEUE_SIZE,
 ];
 // Number of 4K pages in a MiB.
 pub const MIB_TO_4K_PAGES: u32 = 256;
@@ -43,10 +45,20 @@ pub const INFLATE_INDEX: usize = 0;
 pub const DEFLATE_INDEX: usize = 1;
 /// The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const STATS_INDEX: usize = 2;
+/// The index of the free page hinting queue from Balloon device queues/queues_evts vector, when
+/// the statistics are enabled. It takes the index of the statistics queue otherwise.
+pub const FREE_PAGE_HINT_INDEX: usize = 3;
 
 // The feature bitmap for virtio balloon.
 const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1; // Enable statistics.
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2; // Deflate balloon on OOM.
+const VIRTIO_BALLOON_F_FREE_PAGE_HINT: u32 = 3; // Report free pages on request.
+const VIRTIO_BALLOON_F_PAGE_POISON: u32 = 4; // Guest is using page poisoning.
+
+// The command ids reserved by the free page hinting. The device asks the guest to stop reporting
+// pages with the first one, and to reuse the pages it reported with the second one.
+const FREE_PAGE_HINT_CMD_ID_STOP: u32 = 0;
+const FREE_PAGE_HINT_CMD_ID_DONE: u32 = 1;
 
 // The statistics tags.
 const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
@@ -59,6 +71,12 @@ const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
 const VIRTIO_BALLOON_S_CACHES: u16 = 7;
 const VIRTIO_BALLOON_S_HTLB_PGALLOC: u16 = 8;
 const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
+const VIRTIO_BALLOON_S_OOM_KILL: u16 = 10;
+const VIRTIO_BALLOON_S_ALLOC_STALL: u16 = 11;
+const VIRTIO_BALLOON_S_ASYNC_SCAN: u16 = 12;
+const VIRTIO_BALLOON_S_DIRECT_SCAN: u16 = 13;
+const VIRTIO_BALLOON_S_ASYNC_RECLAIM: u16 = 14;
+const VIRTIO_BALLOON_S_DIRECT_RECLAIM: u16 = 15;
 
 /// Balloon device related errors.
 #[derive(Debug)]
@@ -81,8 +99,16 @@ pub enum BalloonError {
     MalformedPayload,
     /// Error restoring the balloon device queues.
     QueueRestoreError,
+    /// The snapshot offers features which this balloon device does not implement.
+    UnsupportedFeatures(u64),
+    /// The snapshot acknowledges features which were not offered by the balloon device.
+    UnofferedFeaturesAcked(u64),
+    /// The snapshot holds a page poison value without the page poison feature negotiated.
+    PagePoisonNotNegotiated(u32),
     /// Received stats querry when stats are disabled.
     StatisticsDisabled,
+    /// Received a free page hinting request when the free page hinting is disabled.
+    FreePageHintingDisabled,
     /// Statistics cannot be enabled/disabled after activation.
     StatisticsStateChange,
     /// Amount of pages requested cannot fit in `u32`.
@@ -9,8 +9,8 @@ use std::time::Duration;
 
 use snapshot::Persist;
 use timerfd::{SetTimeFlags, TimerState};
-use utils::vm_memory::GuestMemoryMmap;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use utils::vm_memory::{GuestAddress, GuestMemoryMmap};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use super::*;
@@ -25,6 +25,32 @@ use crate::devices::virtio::{DeviceState, FIRECRACKER_MAX_QUEUE_SIZE, TYPE_BALLO
 pub struct BalloonConfigSpaceState {
     num_pages: u32,
     actual_pages: u32,
+    #[version(start = 2, ser_fn = "poison_val_serialize")]
+    poison_val: u32,
+    #[version(start = 2, ser_fn = "free_page_hint_cmd_id_serialize")]
+    free_page_hint_cmd_id: u32,
+}
+
+impl BalloonConfigSpaceState {
+    fn poison_val_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // The guest relies on the value it wrote once, at probe time.
+        if target_version < 2 && self.poison_val != 0 {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the balloon page poison value.".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn free_page_hint_cmd_id_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // The guest holds the pages it reported until it is told the hinting is done.
+        if target_version < 2 && self.free_page_hint_cmd_id > FREE_PAGE_HINT_CMD_ID_DONE {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the balloon free page hinting.".to_owned(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 /// Information about the balloon stats that are saved
@@ -42,6 +68,18 @@ pub struct BalloonStatsState {
     disk_caches: Option<u64>,
     hugetlb_allocations: Option<u64>,
     hugetlb_failures: Option<u64>,
+    #[version(start = 2)]
+    oom_kills: Option<u64>,
+    #[version(start = 2)]
+    alloc_stalls: Option<u64>,
+    #[version(start = 2)]
+    async_scans: Option<u64>,
+    #[version(start = 2)]
+    direct_scans: Option<u64>,
+    #[version(start = 2)]
+    async_reclaims: Option<u64>,
+    #[version(start = 2)]
+    direct_reclaims: Option<u64>,
 }
 
 impl BalloonStatsState {
@@ -57,6 +95,12 @@ impl BalloonStatsState {
             disk_caches: stats.disk_caches,
             hugetlb_allocations: stats.hugetlb_allocations,
             hugetlb_failures: stats.hugetlb_failures,
+            oom_kills: stats.oom_kills,
+            alloc_stalls: stats.alloc_stalls,
+            async_scans: stats.async_scans,
+            direct_scans: stats.direct_scans,
+            async_reclaims: stats.async_reclaims,
+            direct_reclaims: stats.direct_reclaims,
         }
     }
 
@@ -76,10 +120,25 @@ impl BalloonStatsState {
             disk_caches: self.disk_caches,
             hugetlb_allocations: self.hugetlb_allocations,
             hugetlb_failures: self.hugetlb_failures,
+            oom_kills: self.oom_kills,
+            alloc_stalls: self.alloc_stalls,
+            async_scans: self.async_scans,
+            direct_scans: self.direct_scans,
+            async_reclaims: self.async_reclaims,
+            direct_reclaims: self.direct_reclaims,
         }
     }
 }
 
+/// Information about a range of guest pages held by the balloon
+/// that is saved at snapshot.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Debug, Clone, Versionize)]
+pub struct BalloonRemovedRangeState {
+    start_pfn: u64,
+    num_pages: u64,
+}
+
 /// Information about the balloon that are saved
 /// at snapshot.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -90,6 +149,33 @@ pub struct BalloonState {
     latest_stats: BalloonStatsState,
     config_space: BalloonConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(start = 2, default_fn = "default_removed_ranges")]
+    pub(crate) removed_ranges: Vec<BalloonRemovedRangeState>,
+    #[version(start = 2)]
+    hint_cmd_id: u32,
+    #[version(start = 2)]
+    hint_cmd_id_received: u32,
+    #[version(start = 2)]
+    hint_complete: bool,
+    #[version(start = 2)]
+    hinted_pages: u64,
+}
+
+impl BalloonState {
+    fn default_removed_ranges(_: u16) -> Vec<BalloonRemovedRangeState> {
+        Vec::new()
+    }
+
+    /// Returns the guest memory ranges, as (address, length in bytes) pairs, that the
+    /// balloon held when the snapshot was taken.
+    pub fn removed_ranges(&self) -> impl Iterator<Item = (GuestAddress, u64)> + '_ {
+        self.removed_ranges.iter().map(|range| {
+            (
+                GuestAddress(range.start_pfn << VIRTIO_BALLOON_PFN_SHIFT),
+                range.num_pages << VIRTIO_BALLOON_PFN_SHIFT,
+            )
+        })
+    }
 }
 
 /// Auxiliary structure for creating a device when resuming from a snapshot.
@@ -112,8 +198,22 @@ impl Persist<'_> for Balloon {
             config_space: BalloonConfigSpaceState {
                 num_pages: self.config_space.num_pages,
                 actual_pages: self.config_space.actual_pages,
+                poison_val: self.config_space.poison_val,
+                free_page_hint_cmd_id: self.config_space.free_page_hint_cmd_id,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            removed_ranges: self
+                .removed_ranges
+                .iter()
+                .map(|(&start_pfn, &num_pages)| BalloonRemovedRangeState {
+                    start_pfn,
+                    num_pages,
+                })
+                .collect(),
+            hint_cmd_id: self.hint_cmd_id,
+            hint_cmd_id_received: self.hint_cmd_id_received,
+            hint_complete: self.hint_complete,
+            hinted_pages: self.hinted_pages,
         }
     }
 
@@ -123,14 +223,36 @@ impl Persist<'_> for Balloon {
     ) -> Result<Self, Self::Error> {
         // We can safely create the balloon with arbitrary flags and
         // num_pages because we will overwrite them after.
-        let mut balloon = Balloon::new(0, false, state.stats_polling_interval_s, true)?;
+        let mut balloon = Balloon::new(0, true, state.stats_polling_interval_s, true, true)?;
+
+        // A guest which negotiated features this device does not implement, or a page poison
+        // value which is then lost, ends up with corrupted memory.
+        let virtio_state = &state.virtio_state;
+        let unsupported_features = virtio_state.avail_features & !balloon.avail_features;
+        if unsupported_features != 0 {
+            return Err(Self::Error::UnsupportedFeatures(unsupported_features));
+        }
+        let unoffered_features = virtio_state.acked_features & !virtio_state.avail_features;
+        if unoffered_features != 0 {
+            return Err(Self::Error::UnofferedFeaturesAcked(unoffered_features));
+        }
+        if state.config_space.poison_val != 0
+            && virtio_state.acked_features & (1u64 << VIRTIO_BALLOON_F_PAGE_POISON) == 0
+        {
+            return Err(Self::Error::PagePoisonNotNegotiated(
+                state.config_space.poison_val,
+            ));
+        }
 
         let mut num_queues = BALLOON_NUM_QUEUES;
-        // As per the virtio 1.1 specification, the statistics queue
-        // should not exist if the statistics are not enabled.
+        // As per the virtio 1.1 specification, the statistics and the free page hinting
+        // queues should not exist if the features are not enabled.
         if state.stats_polling_interval_s == 0 {
             num_queues -= 1;
         }
+        if virtio_state.avail_features & (1u64 << VIRTIO_BALLOON_F_FREE_PAGE_HINT) == 0 {
+            num_queues -= 1;
+        }
         balloon.queues = state
             .virtio_state
             .build_queues_checked(
@@ -148,7 +270,18 @@ impl Persist<'_> for Balloon {
         balloon.config_space = ConfigSpace {
             num_pages: state.config_space.num_pages,
             actual_pages: state.config_space.actual_pages,
+            free_page_hint_cmd_id: state.config_space.free_page_hint_cmd_id,
+            poison_val: state.config_space.poison_val,
         };
+        balloon.hint_cmd_id = state.hint_cmd_id;
+        balloon.hint_cmd_id_received = state.hint_cmd_id_received;
+        balloon.hint_complete = state.hint_complete;
+        balloon.hinted_pages = state.hinted_pages;
+        balloon.removed_ranges = state
+            .removed_ranges
+            .iter()
+            .map(|range| (range.start_pfn, range.num_pages))
+            .collect();
 
         if state.virtio_state.activated {
             balloon.device_state = DeviceState::Activated(constructor_args.mem);
@@ -174,6 +307,7 @@ impl Persist<'_> for Balloon {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::sync::atomic::Ordering;
 
     use super::*;
@@ -188,7 +322,7 @@ mod tests {
         let version_map = VersionMap::new();
 
         // Create and save the balloon device.
-        let balloon = Balloon::new(0x42, false, 2, false).unwrap();
+        let balloon = Balloon::new(0x42 << 20, false, 2, false, false).unwrap();
 
         <Balloon as Persist>::save(&balloon)
             .serialize(&mut mem.as_mut_slice(), &version_map, 1)
@@ -221,4 +355,179 @@ mod tests {
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
     }
+
+    #[test]
+    fn test_persistence_features() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BalloonConfigSpaceState::type_id(), 2);
+
+        let mut balloon = Balloon::new(0x42 << 20, false, 0, false, false).unwrap();
+        balloon.acked_features = balloon.avail_features;
+        balloon.config_space.poison_val = 0xaaaa_aaaa;
+        let state = <Balloon as Persist>::save(&balloon);
+
+        // The page poison value is saved starting with version 2.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.config_space, balloon.config_space);
+        assert_eq!(restored_balloon.acked_features, balloon.acked_features);
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        // Features this device does not implement are rejected.
+        let mut bad_state = state.clone();
+        bad_state.virtio_state.avail_features |= 1u64 << 5;
+        assert!(matches!(
+            Balloon::restore(BalloonConstructorArgs { mem: default_mem() }, &bad_state),
+            Err(BalloonError::UnsupportedFeatures(features)) if features == 1u64 << 5
+        ));
+
+        // So are features acknowledged by the guest without being offered.
+        let mut bad_state = state.clone();
+        bad_state.virtio_state.acked_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+        assert!(matches!(
+            Balloon::restore(BalloonConstructorArgs { mem: default_mem() }, &bad_state),
+            Err(BalloonError::UnofferedFeaturesAcked(features))
+                if features == 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM
+        ));
+
+        // And page poison values without page poisoning.
+        let mut bad_state = state;
+        bad_state.virtio_state.acked_features &= !(1u64 << VIRTIO_BALLOON_F_PAGE_POISON);
+        assert!(matches!(
+            Balloon::restore(BalloonConstructorArgs { mem: default_mem() }, &bad_state),
+            Err(BalloonError::PagePoisonNotNegotiated(0xaaaa_aaaa))
+        ));
+    }
+
+    #[test]
+    fn test_persistence_removed_ranges() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BalloonState::type_id(), 2);
+
+        let mut balloon = Balloon::new(0x42 << 20, false, 0, false, false).unwrap();
+        balloon.removed_ranges = BTreeMap::from([(1, 2), (8, 4)]);
+        let state = <Balloon as Persist>::save(&balloon);
+        assert_eq!(
+            state.removed_ranges().collect::<Vec<_>>(),
+            vec![
+                (GuestAddress(0x1000), 0x2000),
+                (GuestAddress(0x8000), 0x4000)
+            ]
+        );
+
+        // The removed ranges are saved starting with version 2.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.removed_ranges, balloon.removed_ranges);
+
+        // Older versions do not know about them.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_balloon.removed_ranges.is_empty());
+    }
+
+    #[test]
+    fn test_persistence_memory_pressure_stats() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BalloonStatsState::type_id(), 2);
+
+        let mut balloon = Balloon::new(0x42 << 20, false, 1, false, false).unwrap();
+        balloon.latest_stats.oom_kills = Some(3);
+        balloon.latest_stats.alloc_stalls = Some(7);
+        let state = <Balloon as Persist>::save(&balloon);
+
+        // The memory pressure counters are saved starting with version 2.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.latest_stats.oom_kills, Some(3));
+        assert_eq!(restored_balloon.latest_stats.alloc_stalls, Some(7));
+
+        // Older versions do not know about them.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.latest_stats.oom_kills, None);
+        assert_eq!(restored_balloon.latest_stats.alloc_stalls, None);
+    }
+
+    #[test]
+    fn test_persistence_free_page_hinting() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BalloonState::type_id(), 2)
+            .set_type_version(BalloonConfigSpaceState::type_id(), 2);
+
+        let mut balloon = Balloon::new(0x42 << 20, false, 0, true, false).unwrap();
+        balloon.config_space.free_page_hint_cmd_id = 5;
+        balloon.hint_cmd_id = 5;
+        balloon.hint_cmd_id_received = 5;
+        balloon.hint_complete = true;
+        balloon.hinted_pages = 0x100;
+        let state = <Balloon as Persist>::save(&balloon);
+
+        // The free page hinting state is saved starting with version 2.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_balloon.free_page_hinting());
+        assert_eq!(restored_balloon.queues(), balloon.queues());
+        assert_eq!(restored_balloon.config_space, balloon.config_space);
+        assert_eq!(restored_balloon.hint_cmd_id, balloon.hint_cmd_id);
+        assert_eq!(
+            restored_balloon.free_page_hinting_status().unwrap(),
+            balloon.free_page_hinting_status().unwrap()
+        );
+
+        // Older versions cannot tell the guest to reuse the pages it reported.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+    }
 }
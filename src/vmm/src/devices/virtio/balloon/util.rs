@@ -1,7 +1,8 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io;
+use std::collections::BTreeMap;
+use std::{cmp, io};
 
 use utils::vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
@@ -64,6 +65,59 @@ pub(crate) fn compact_page_frame_numbers(v: &mut [u32]) -> Vec<(u32, u32)> {
     result
 }
 
+/// Adds the `len` pages starting at `start_pfn` to `ranges`, a map from the first page
+/// frame number of each range to its length. Overlapping and adjacent ranges are merged.
+pub(crate) fn insert_pfn_range(ranges: &mut BTreeMap<u64, u64>, start_pfn: u64, len: u64) {
+    let mut start = start_pfn;
+    let mut end = start_pfn + len;
+
+    // Merge with the range starting at or before `start`, if they touch.
+    if let Some((&prev_start, &prev_len)) = ranges.range(..=start).next_back() {
+        if prev_start + prev_len >= start {
+            ranges.remove(&prev_start);
+            start = prev_start;
+            end = cmp::max(end, prev_start + prev_len);
+        }
+    }
+
+    // Merge with all the ranges starting inside or right after the new one.
+    while let Some((&next_start, &next_len)) = ranges.range(start..=end).next() {
+        ranges.remove(&next_start);
+        end = cmp::max(end, next_start + next_len);
+    }
+
+    ranges.insert(start, end - start);
+}
+
+/// Removes the `len` pages starting at `start_pfn` from `ranges`, splitting the ranges that
+/// are only partially covered.
+pub(crate) fn remove_pfn_range(ranges: &mut BTreeMap<u64, u64>, start_pfn: u64, len: u64) {
+    let end = start_pfn + len;
+
+    // Trim the range starting before `start_pfn`, keeping what is left on both sides.
+    if let Some((&prev_start, &prev_len)) = ranges.range(..start_pfn).next_back() {
+        let prev_end = prev_start + prev_len;
+        if prev_end > start_pfn {
+            ranges.insert(prev_start, start_pfn - prev_start);
+            if prev_end > end {
+                ranges.insert(end, prev_end - end);
+            }
+        }
+    }
+
+    // Drop the ranges starting inside the removed one, keeping their tails.
+    let covered: Vec<(u64, u64)> = ranges
+        .range(start_pfn..end)
+        .map(|(&start, &len)| (start, len))
+        .collect();
+    for (start, len) in covered {
+        ranges.remove(&start);
+        if start + len > end {
+            ranges.insert(end, start + len - end);
+        }
+    }
+}
+
 pub(crate) fn remove_range(
     guest_memory: &GuestMemoryMmap,
     range: (GuestAddress, u64),
@@ -82,7 +136,10 @@ pub(crate) fn remove_range(
         // Mmap a new anonymous region over the present one in order to create a hole.
         // This workaround is (only) needed after resuming from a snapshot because the guest memory
         // is mmaped from file as private and there is no `madvise` flag that works for this case.
-        if restored {
+        // Memory restored through userfaultfd is anonymous, so it does not need the workaround.
+        // Remapping it would also unregister the range from userfaultfd behind the back of the
+        // page fault handler, which would no longer be able to serve it.
+        if restored && region.file_offset().is_some() {
             // SAFETY: The address and length are known to be valid.
             let ret = unsafe {
                 libc::mmap(
@@ -99,11 +156,17 @@ pub(crate) fn remove_range(
             }
         };
 
-        // Madvise the region in order to mark it as not used.
+        // Madvise the region in order to mark it as not used. The pages of a shared mapping,
+        // such as the memfd backing the guest memory, are only freed by punching a hole in it.
+        let advice = if region.flags() & libc::MAP_SHARED != 0 {
+            libc::MADV_REMOVE
+        } else {
+            libc::MADV_DONTNEED
+        };
         // SAFETY: The address and length are known to be valid.
         let ret = unsafe {
             let range_len = range_len as usize;
-            libc::madvise(phys_address.cast(), range_len, libc::MADV_DONTNEED)
+            libc::madvise(phys_address.cast(), range_len, advice)
         };
         if ret < 0 {
             return Err(RemoveRegionError::MadviseFail(io::Error::last_os_error()));
@@ -118,8 +181,12 @@ pub(crate) fn remove_range(
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::FromRawFd;
 
-    use utils::vm_memory::Bytes;
+    use utils::tempfile::TempFile;
+    use utils::vm_memory::{create_guest_memory, create_shared_guest_memory, Bytes, FileOffset};
 
     use super::*;
 
@@ -176,6 +243,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_insert_pfn_range() {
+        let mut ranges = BTreeMap::new();
+
+        insert_pfn_range(&mut ranges, 10, 10);
+        insert_pfn_range(&mut ranges, 30, 10);
+        assert_eq!(ranges, BTreeMap::from([(10, 10), (30, 10)]));
+
+        // Adjacent ranges are merged.
+        insert_pfn_range(&mut ranges, 20, 5);
+        assert_eq!(ranges, BTreeMap::from([(10, 15), (30, 10)]));
+
+        // Overlapping ranges are merged, even across several existing ranges.
+        insert_pfn_range(&mut ranges, 5, 30);
+        assert_eq!(ranges, BTreeMap::from([(5, 35)]));
+
+        // Ranges already covered do not change anything.
+        insert_pfn_range(&mut ranges, 12, 3);
+        assert_eq!(ranges, BTreeMap::from([(5, 35)]));
+
+        insert_pfn_range(&mut ranges, 0, 1);
+        assert_eq!(ranges, BTreeMap::from([(0, 1), (5, 35)]));
+    }
+
+    #[test]
+    fn test_remove_pfn_range() {
+        let mut ranges = BTreeMap::from([(0, 10), (20, 10), (40, 10)]);
+
+        // Removing pages that are not in any range does nothing.
+        remove_pfn_range(&mut ranges, 10, 10);
+        assert_eq!(ranges, BTreeMap::from([(0, 10), (20, 10), (40, 10)]));
+
+        // Removing the middle of a range splits it.
+        remove_pfn_range(&mut ranges, 3, 4);
+        assert_eq!(ranges, BTreeMap::from([(0, 3), (7, 3), (20, 10), (40, 10)]));
+
+        // Removing across several ranges trims the ends and drops the ranges in between.
+        remove_pfn_range(&mut ranges, 8, 35);
+        assert_eq!(ranges, BTreeMap::from([(0, 3), (7, 1), (43, 7)]));
+
+        remove_pfn_range(&mut ranges, 0, 50);
+        assert!(ranges.is_empty());
+    }
+
     #[test]
     fn test_remove_range() {
         let page_size: usize = 0x1000;
@@ -220,7 +331,17 @@ mod tests {
     #[test]
     fn test_remove_range_on_restored() {
         let page_size: usize = 0x1000;
-        let mem = single_region_mem(2 * page_size);
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(2 * page_size as u64).unwrap();
+        let mem = create_guest_memory(
+            &[(
+                Some(FileOffset::new(file, 0)),
+                GuestAddress(0),
+                2 * page_size,
+            )],
+            false,
+        )
+        .unwrap();
 
         // Fill the memory with ones.
         let ones = vec![1u8; 2 * page_size];
@@ -258,6 +379,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remove_range_on_restored_anonymous() {
+        // Guest memory restored through userfaultfd is anonymous, so no hole is mmaped over it.
+        let page_size: usize = 0x1000;
+        let mem = single_region_mem(2 * page_size);
+
+        let ones = vec![1u8; 2 * page_size];
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+
+        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), true).is_ok());
+        let mut actual_page = vec![0u8; page_size];
+        mem.read(actual_page.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+
+        // Only `madvise` is called, so it is the one that fails on an unaligned address.
+        assert_match!(
+            remove_range(&mem, (GuestAddress(0x20), page_size as u64), true).unwrap_err(),
+            RemoveRegionError::MadviseFail(_)
+        );
+    }
+
+    #[test]
+    fn test_remove_range_on_memfd() {
+        // The pages of guest memory backed by a memfd are removed from the memfd.
+        let page_size: usize = 0x1000;
+        // SAFETY: The name is a valid C string and the return code is checked.
+        let fd = unsafe { libc::memfd_create(b"guest_mem\0".as_ptr().cast(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        // SAFETY: `fd` was just created, so it is valid and owned by nobody else.
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(2 * page_size as u64).unwrap();
+        let mem = create_shared_guest_memory(
+            &[(
+                Some(FileOffset::new(file.try_clone().unwrap(), 0)),
+                GuestAddress(0),
+                2 * page_size,
+            )],
+            false,
+        )
+        .unwrap();
+
+        let ones = vec![1u8; 2 * page_size];
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+
+        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), false).is_ok());
+        let mut actual_page = vec![0u8; page_size];
+        file.read_exact_at(&mut actual_page, 0).unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+        file.read_exact_at(&mut actual_page, page_size as u64)
+            .unwrap();
+        assert_eq!(vec![1u8; page_size], actual_page);
+    }
+
     /// -------------------------------------
     /// BEGIN PROPERTY BASED TESTING
     use proptest::prelude::*;
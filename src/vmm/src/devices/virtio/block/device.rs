@@ -6,16 +6,18 @@
 // found in the THIRD-PARTY file.
 
 use std::cmp;
+use std::collections::BTreeSet;
 use std::convert::From;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
-use std::path::PathBuf;
+use std::os::unix::io::AsRawFd;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use block_io::FileEngine;
-use logger::{error, warn, IncMetric, METRICS};
+use logger::{error, warn, DriveLatencyMetrics, IncMetric, METRICS};
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
@@ -29,11 +31,12 @@ use super::super::{ActivateError, DeviceState, Queue, VirtioDevice, TYPE_BLOCK};
 use super::io::async_io;
 use super::request::*;
 use super::{
-    io as block_io, BlockError, BLOCK_CONFIG_SPACE_SIZE, BLOCK_QUEUE_SIZES, SECTOR_SHIFT,
-    SECTOR_SIZE,
+    io as block_io, BlockError, BLOCK_CONFIG_SPACE_SIZE, BLOCK_QUEUE_SIZES, IO_URING_NUM_ENTRIES,
+    SECTOR_SHIFT, SECTOR_SIZE,
 };
 use crate::devices::virtio::{IrqTrigger, IrqType};
 use crate::rate_limiter::{BucketUpdate, RateLimiter};
+use crate::vmm_config::open_file_with_flags;
 
 /// Configuration options for disk caching.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
@@ -46,6 +49,10 @@ pub enum CacheType {
     /// flush requests coming from the guest will be performed using
     /// `fsync`.
     Writeback,
+    /// Flushing mechanic will not be advertised to the guest driver, which
+    /// then considers the writes durable once completed: the backing file is
+    /// opened with `O_DSYNC`.
+    Writethrough,
 }
 
 /// The engine file type, either Sync or Async (through io_uring).
@@ -73,10 +80,113 @@ impl FileEngineType {
     }
 }
 
+/// Access pattern of the guest to the backing file, advised to the host kernel through
+/// `posix_fadvise`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FadvisePolicy {
+    /// No advice: the host kernel applies its default read-ahead.
+    #[default]
+    Normal,
+    /// The guest reads the file sequentially: the host kernel doubles its read-ahead window.
+    Sequential,
+    /// The guest reads the file randomly: the host kernel disables its read-ahead.
+    Random,
+    /// The guest reads the data once: it is dropped from the host page cache after each read.
+    DontNeed,
+}
+
+/// Hints about the use of the host page cache by a block device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageCacheHints {
+    /// Access pattern advised for the whole backing file.
+    pub fadvise: FadvisePolicy,
+    /// Size, in KiB, of the data following each read of the guest which the host kernel is asked
+    /// to read ahead. 0 leaves the read-ahead to the host kernel.
+    pub readahead_kib: u32,
+}
+
+impl PageCacheHints {
+    fn advise(file: &File, offset: u64, len: u64, advice: libc::c_int) -> std::io::Result<()> {
+        // SAFETY: `posix_fadvise` does not access memory and fails with `EBADF` on an invalid
+        // descriptor.
+        let ret = unsafe {
+            libc::posix_fadvise(
+                file.as_raw_fd(),
+                offset as libc::off_t,
+                len as libc::off_t,
+                advice,
+            )
+        };
+        // The error number is returned instead of being set in `errno`.
+        match ret {
+            0 => Ok(()),
+            errno => Err(std::io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    /// Advises the access pattern of the whole backing file, once it is opened.
+    fn apply(&self, file: &File) -> std::io::Result<()> {
+        let advice = match self.fadvise {
+            FadvisePolicy::Normal | FadvisePolicy::DontNeed => libc::POSIX_FADV_NORMAL,
+            FadvisePolicy::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            FadvisePolicy::Random => libc::POSIX_FADV_RANDOM,
+        };
+        Self::advise(file, 0, 0, advice)
+    }
+
+    /// Asks the host kernel to read ahead the data following a read of the guest.
+    pub(crate) fn before_read(&self, file: &File, offset: u64, len: u32) {
+        if self.readahead_kib != 0 {
+            // The hints are best effort, so their errors are ignored.
+            let _ = Self::advise(
+                file,
+                offset + u64::from(len),
+                u64::from(self.readahead_kib) << 10,
+                libc::POSIX_FADV_WILLNEED,
+            );
+        }
+    }
+
+    /// Drops the data read by the guest from the host page cache, if the policy says so.
+    pub(crate) fn after_read(&self, file: &File, offset: u64, len: u32) {
+        if self.fadvise == FadvisePolicy::DontNeed {
+            let _ = Self::advise(file, offset, u64::from(len), libc::POSIX_FADV_DONTNEED);
+        }
+    }
+}
+
+/// Limits on the requests a block device submits to its `Async` IO engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoDepthLimits {
+    /// Number of entries of the io_uring instance of the device.
+    pub ring_entries: u16,
+    /// Maximum number of requests in flight at once. 0 only bounds them by the ring size.
+    pub max_in_flight: u16,
+}
+
+impl Default for IoDepthLimits {
+    fn default() -> Self {
+        Self {
+            ring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight: 0,
+        }
+    }
+}
+
+impl IoDepthLimits {
+    /// Whether `in_flight` requests are enough to stop submitting new ones.
+    fn is_reached(&self, in_flight: usize) -> bool {
+        self.max_in_flight != 0 && in_flight >= usize::from(self.max_in_flight)
+    }
+}
+
 /// Helper object for setting up all `Block` fields derived from its backing file.
 #[derive(Debug)]
 pub(crate) struct DiskProperties {
     cache_type: CacheType,
+    direct_io: bool,
+    page_cache_hints: PageCacheHints,
+    io_depth_limits: IoDepthLimits,
     file_path: String,
     file_engine: FileEngine<PendingRequest>,
     nsectors: u64,
@@ -89,12 +199,20 @@ impl DiskProperties {
         is_disk_read_only: bool,
         cache_type: CacheType,
         file_engine_type: FileEngineType,
+        direct_io: bool,
+        page_cache_hints: PageCacheHints,
+        io_depth_limits: IoDepthLimits,
     ) -> Result<Self, BlockError> {
-        let mut disk_image = OpenOptions::new()
-            .read(true)
-            .write(!is_disk_read_only)
-            .open(PathBuf::from(&disk_image_path))
-            .map_err(|x| BlockError::BackingFile(x, disk_image_path.clone()))?;
+        let mut custom_flags = 0;
+        if cache_type == CacheType::Writethrough && !is_disk_read_only {
+            custom_flags |= libc::O_DSYNC;
+        }
+        if direct_io {
+            custom_flags |= libc::O_DIRECT;
+        }
+        let mut disk_image =
+            open_file_with_flags(&disk_image_path, !is_disk_read_only, custom_flags)
+                .map_err(|x| BlockError::BackingFile(x, disk_image_path.clone()))?;
         let disk_size = disk_image
             .seek(SeekFrom::End(0))
             .map_err(|x| BlockError::BackingFile(x, disk_image_path.clone()))?;
@@ -109,13 +227,27 @@ impl DiskProperties {
             );
         }
 
+        if let Err(err) = page_cache_hints.apply(&disk_image) {
+            warn!(
+                "Failed to advise the access pattern of {}: {}",
+                disk_image_path, err
+            );
+        }
+
         Ok(Self {
             cache_type,
+            direct_io,
+            page_cache_hints,
+            io_depth_limits,
             nsectors: disk_size >> SECTOR_SHIFT,
             image_id: Self::build_disk_image_id(&disk_image),
             file_path: disk_image_path,
-            file_engine: FileEngine::from_file(disk_image, file_engine_type)
-                .map_err(BlockError::FileEngine)?,
+            file_engine: FileEngine::from_file(
+                disk_image,
+                file_engine_type,
+                u32::from(io_depth_limits.ring_entries),
+            )
+            .map_err(BlockError::FileEngine)?,
         })
     }
 
@@ -189,6 +321,30 @@ impl DiskProperties {
     pub fn cache_type(&self) -> CacheType {
         self.cache_type
     }
+
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    pub fn page_cache_hints(&self) -> PageCacheHints {
+        self.page_cache_hints
+    }
+
+    pub fn io_depth_limits(&self) -> IoDepthLimits {
+        self.io_depth_limits
+    }
+
+    /// Gives the host kernel the hints about a read of the guest, before it is submitted.
+    pub fn before_read(&self, offset: u64, len: u32) {
+        self.page_cache_hints
+            .before_read(self.file_engine.file(), offset, len);
+    }
+
+    /// Gives the host kernel the hints about a read of the guest, once it is completed.
+    pub fn after_read(&self, offset: u64, len: u32) {
+        self.page_cache_hints
+            .after_read(self.file_engine.file(), offset, len);
+    }
 }
 
 /// Virtio device for exposing block level read/write operations on a host file.
@@ -215,6 +371,20 @@ pub struct Block {
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
     is_io_engine_throttled: bool,
+    is_paused: bool,
+    // Heads of the descriptor chains of the requests submitted to the IO engine, which are not
+    // completed yet.
+    pub(crate) in_flight_requests: BTreeSet<u16>,
+    // Heads of the descriptor chains of the requests which were in flight when the snapshot
+    // was created, and are submitted again when the restored device is first kicked.
+    pub(crate) restored_requests: Vec<u16>,
+    // Time the queue is busy-polled for new requests before waiting for the driver to notify it.
+    busy_poll: Duration,
+    // Number of completed requests after which the driver is notified, even if more requests
+    // are being processed. 0 only notifies it once all the requests at hand are processed.
+    batch_size: u16,
+    // Latencies of the requests of the device, unless too many devices record them already.
+    pub(crate) latency_metrics: Option<&'static DriveLatencyMetrics>,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
@@ -243,12 +413,18 @@ impl Block {
         is_disk_root: bool,
         rate_limiter: RateLimiter,
         file_engine_type: FileEngineType,
+        direct_io: bool,
+        page_cache_hints: PageCacheHints,
+        io_depth_limits: IoDepthLimits,
     ) -> Result<Block, BlockError> {
         let disk_properties = DiskProperties::new(
             disk_image_path,
             is_disk_read_only,
             cache_type,
             file_engine_type,
+            direct_io,
+            page_cache_hints,
+            io_depth_limits,
         )?;
 
         let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
@@ -265,6 +441,11 @@ impl Block {
 
         let queues = BLOCK_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
+        let latency_metrics = METRICS.block_latencies.register(&id);
+        if latency_metrics.is_none() {
+            warn!("The request latencies of drive {} are not recorded.", id);
+        }
+
         Ok(Block {
             id,
             root_device: is_disk_root,
@@ -280,6 +461,12 @@ impl Block {
             irq_trigger: IrqTrigger::new().map_err(BlockError::IrqTrigger)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(BlockError::EventFd)?,
             is_io_engine_throttled: false,
+            is_paused: false,
+            in_flight_requests: BTreeSet::new(),
+            restored_requests: Vec::new(),
+            busy_poll: Duration::ZERO,
+            batch_size: 0,
+            latency_metrics,
         })
     }
 
@@ -296,13 +483,15 @@ impl Block {
             METRICS.block.rate_limiter_throttled_events.inc();
         } else if self.is_io_engine_throttled {
             METRICS.block.io_engine_throttled_events.inc();
-        } else {
+        } else if !self.is_paused {
+            // A paused device processes its queue once it is resumed.
             self.process_virtio_queues();
         }
     }
 
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
+        self.process_restored_requests();
         self.process_queue(0);
     }
 
@@ -310,26 +499,43 @@ impl Block {
         METRICS.block.rate_limiter_event_count.inc();
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
-        if self.rate_limiter.event_handler().is_ok() {
+        if self.rate_limiter.event_handler().is_ok() && !self.is_paused {
             self.process_queue(0);
         }
     }
 
+    // The guest is notified once `batch_size` requests are completed, and by the callers once
+    // they have processed all the requests at hand. A zero `batch_size` leaves the notification
+    // to the callers.
     fn add_used_descriptor(
         queue: &mut Queue,
         index: u16,
         len: u32,
         mem: &GuestMemoryMmap,
         irq_trigger: &IrqTrigger,
+        batch_size: u16,
     ) {
         queue.add_used(mem, index, len).unwrap_or_else(|err| {
             error!("Failed to add available descriptor head {}: {}", index, err)
         });
 
+        if batch_size != 0 && queue.num_added() >= batch_size {
+            Self::signal_used_queue(queue, mem, irq_trigger);
+        }
+    }
+
+    fn signal_used_queue(queue: &mut Queue, mem: &GuestMemoryMmap, irq_trigger: &IrqTrigger) {
+        if queue.num_added() == 0 {
+            return;
+        }
+
         if queue.prepare_kick(mem) {
+            METRICS.block.interrupt_count.inc();
             irq_trigger.trigger_irq(IrqType::Vring).unwrap_or_else(|_| {
                 METRICS.block.event_fails.inc();
             });
+        } else {
+            METRICS.block.suppressed_interrupt_count.inc();
         }
     }
 
@@ -341,58 +547,157 @@ impl Block {
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
 
-        while let Some(head) = queue.pop_or_enable_notification(mem) {
-            let processing_result = match Request::parse(&head, mem, self.disk.nsectors()) {
-                Ok(request) => {
-                    if request.rate_limit(&mut self.rate_limiter) {
-                        // Stop processing the queue and return this descriptor chain to the
-                        // avail ring, for later processing.
+        loop {
+            while let Some(head) = queue.pop_or_enable_notification(mem) {
+                if self
+                    .disk
+                    .io_depth_limits
+                    .is_reached(self.in_flight_requests.len())
+                {
+                    // Resume processing the queue once the IO engine completes some requests,
+                    // like when its ring is full.
+                    queue.undo_pop();
+                    METRICS.block.in_flight_limit_events.inc();
+                    self.is_io_engine_throttled = true;
+                    break;
+                }
+
+                let processing_result = match Request::parse(&head, mem, self.disk.nsectors()) {
+                    Ok(request) => {
+                        if request.rate_limit(&mut self.rate_limiter) {
+                            // Stop processing the queue and return this descriptor chain to
+                            // the avail ring, for later processing.
+                            queue.undo_pop();
+                            METRICS.block.rate_limiter_throttled_events.inc();
+                            break;
+                        }
+
+                        used_any = true;
+                        request.process(&mut self.disk, head.index, mem, self.latency_metrics)
+                    }
+                    Err(err) => {
+                        error!("Failed to parse available descriptor chain: {:?}", err);
+                        METRICS.block.execute_fails.inc();
+                        ProcessingResult::Executed(FinishedRequest {
+                            num_bytes_to_mem: 0,
+                            desc_idx: head.index,
+                        })
+                    }
+                };
+
+                match processing_result {
+                    ProcessingResult::Submitted => {
+                        self.in_flight_requests.insert(head.index);
+                    }
+                    ProcessingResult::Throttled => {
                         queue.undo_pop();
-                        METRICS.block.rate_limiter_throttled_events.inc();
+                        METRICS.block.io_engine_ring_full_events.inc();
+                        self.is_io_engine_throttled = true;
                         break;
                     }
+                    ProcessingResult::Executed(finished) => {
+                        Self::add_used_descriptor(
+                            queue,
+                            head.index,
+                            finished.num_bytes_to_mem,
+                            mem,
+                            &self.irq_trigger,
+                            self.batch_size,
+                        );
+                    }
+                }
+            }
 
-                    used_any = true;
-                    request.process(&mut self.disk, head.index, mem)
+            if let FileEngine::Async(engine) = &mut self.disk.file_engine {
+                if let Err(err) = engine.kick_submission_queue() {
+                    error!("BlockError submitting pending block requests: {:?}", err);
                 }
+            }
+            Self::signal_used_queue(queue, mem, &self.irq_trigger);
+
+            // Completions are only processed once the thread is back in the event loop, so the
+            // queue is not polled while requests are in flight.
+            if self.busy_poll.is_zero()
+                || self.is_io_engine_throttled
+                || self.rate_limiter.is_blocked()
+                || !self.in_flight_requests.is_empty()
+            {
+                break;
+            }
+            if !queue.poll_avail(mem, self.busy_poll) {
+                METRICS.block.busy_poll_misses.inc();
+                break;
+            }
+            METRICS.block.busy_poll_hits.inc();
+        }
+
+        if !used_any {
+            METRICS.block.no_avail_buffer.inc();
+        }
+    }
+
+    // Submits again the requests which were in flight when the snapshot was created. Their
+    // descriptor chains were popped from the queue before it was saved, so they are only
+    // reachable from their head index.
+    fn process_restored_requests(&mut self) {
+        if self.restored_requests.is_empty() {
+            return;
+        }
+
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let queue = &mut self.queues[0];
+        let mut restored_requests = std::mem::take(&mut self.restored_requests).into_iter();
+
+        // The requests were already accounted for by the rate limiter before the snapshot.
+        while let Some(desc_idx) = restored_requests.next() {
+            let processing_result = match queue
+                .chain_at(mem, desc_idx)
+                .ok_or(BlockError::DescriptorChainTooShort)
+                .and_then(|head| Request::parse(&head, mem, self.disk.nsectors()))
+            {
+                Ok(request) => request.process(&mut self.disk, desc_idx, mem, self.latency_metrics),
                 Err(err) => {
-                    error!("Failed to parse available descriptor chain: {:?}", err);
+                    error!("Failed to parse restored descriptor chain: {:?}", err);
                     METRICS.block.execute_fails.inc();
                     ProcessingResult::Executed(FinishedRequest {
                         num_bytes_to_mem: 0,
-                        desc_idx: head.index,
+                        desc_idx,
                     })
                 }
             };
 
             match processing_result {
-                ProcessingResult::Submitted => {}
+                ProcessingResult::Submitted => {
+                    self.in_flight_requests.insert(desc_idx);
+                }
                 ProcessingResult::Throttled => {
-                    queue.undo_pop();
+                    METRICS.block.io_engine_ring_full_events.inc();
+                    // Submit the remaining requests once the IO engine completes some.
+                    self.restored_requests =
+                        std::iter::once(desc_idx).chain(restored_requests).collect();
                     self.is_io_engine_throttled = true;
                     break;
                 }
                 ProcessingResult::Executed(finished) => {
                     Self::add_used_descriptor(
                         queue,
-                        head.index,
+                        desc_idx,
                         finished.num_bytes_to_mem,
                         mem,
                         &self.irq_trigger,
+                        self.batch_size,
                     );
                 }
             }
         }
+        Self::signal_used_queue(queue, mem, &self.irq_trigger);
 
         if let FileEngine::Async(engine) = self.disk.file_engine_mut() {
             if let Err(err) = engine.kick_submission_queue() {
-                error!("BlockError submitting pending block requests: {:?}", err);
+                error!("BlockError submitting restored block requests: {:?}", err);
             }
         }
-
-        if !used_any {
-            METRICS.block.no_avail_buffer.inc();
-        }
     }
 
     fn process_async_completion_queue(&mut self) {
@@ -401,6 +706,7 @@ impl Block {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         let queue = &mut self.queues[0];
+        let page_cache_hints = self.disk.page_cache_hints;
 
         loop {
             match engine.pop(mem) {
@@ -412,6 +718,9 @@ impl Block {
                 Ok(Some(cqe)) => {
                     let res = cqe.result();
                     let user_data = cqe.user_data();
+                    if let (Ok(count), Some(offset)) = (&res, user_data.read_offset()) {
+                        page_cache_hints.after_read(engine.file(), offset, *count);
+                    }
 
                     let (pending, res) = match res {
                         Ok(count) => (user_data, Ok(count)),
@@ -423,6 +732,7 @@ impl Block {
                         ),
                     };
                     let finished = pending.finish(mem, res);
+                    self.in_flight_requests.remove(&finished.desc_idx);
 
                     Self::add_used_descriptor(
                         queue,
@@ -430,10 +740,12 @@ impl Block {
                         finished.num_bytes_to_mem,
                         mem,
                         &self.irq_trigger,
+                        self.batch_size,
                     );
                 }
             }
         }
+        Self::signal_used_queue(queue, mem, &self.irq_trigger);
     }
 
     pub fn process_async_completion_event(&mut self) {
@@ -446,19 +758,28 @@ impl Block {
 
             if self.is_io_engine_throttled {
                 self.is_io_engine_throttled = false;
-                self.process_queue(0);
+                if !self.is_paused {
+                    self.process_virtio_queues();
+                }
             }
         }
     }
 
     /// Update the backing file and the config space of the block device.
+    ///
+    /// The requests in flight are completed against the previous backing file, and flushed to
+    /// it, before it is swapped, while the following ones go to the new backing file.
     pub fn update_disk_image(&mut self, disk_image_path: String) -> Result<(), BlockError> {
         let disk_properties = DiskProperties::new(
             disk_image_path,
             self.is_read_only(),
             self.cache_type(),
             self.file_engine_type(),
+            self.direct_io(),
+            self.page_cache_hints(),
+            self.io_depth_limits(),
         )?;
+        self.quiesce();
         self.disk = disk_properties;
         self.config_space = self.disk.virtio_block_config_space();
 
@@ -474,6 +795,26 @@ impl Block {
         self.rate_limiter.update_buckets(bytes, ops);
     }
 
+    /// Stops processing the queue, once the in-flight requests are completed and the data is
+    /// flushed to the backing file, so that the file can be swapped.
+    pub fn pause(&mut self) {
+        self.is_paused = true;
+        self.quiesce();
+    }
+
+    /// Processes the requests queued while the device was paused, and the following ones.
+    pub fn resume(&mut self) {
+        self.is_paused = false;
+        if self.is_activated() && !self.rate_limiter.is_blocked() && !self.is_io_engine_throttled {
+            self.process_virtio_queues();
+        }
+    }
+
+    /// Specifies if this block device is paused.
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
     /// Provides the ID of this block device.
     pub fn id(&self) -> &String {
         &self.id
@@ -504,11 +845,49 @@ impl Block {
         self.disk.cache_type()
     }
 
+    /// Specifies if the backing file is accessed with direct I/O, bypassing the host page cache.
+    pub fn direct_io(&self) -> bool {
+        self.disk.direct_io()
+    }
+
+    /// Provides the hints given to the host kernel about the use of its page cache.
+    pub fn page_cache_hints(&self) -> PageCacheHints {
+        self.disk.page_cache_hints()
+    }
+
+    /// Provides the limits on the requests submitted to the `Async` IO engine.
+    pub fn io_depth_limits(&self) -> IoDepthLimits {
+        self.disk.io_depth_limits()
+    }
+
     /// Provides non-mutable reference to this device's rate limiter.
     pub fn rate_limiter(&self) -> &RateLimiter {
         &self.rate_limiter
     }
 
+    /// Provides the time the queue is busy-polled for new requests.
+    pub fn busy_poll(&self) -> Duration {
+        self.busy_poll
+    }
+
+    /// Sets the time the queue is busy-polled for new requests, before waiting for the driver
+    /// to notify the device. A zero duration disables the polling.
+    pub fn set_busy_poll(&mut self, busy_poll: Duration) {
+        self.busy_poll = busy_poll;
+    }
+
+    /// Provides the number of completed requests after which the guest is notified.
+    pub fn batch_size(&self) -> u16 {
+        self.batch_size
+    }
+
+    /// Sets the number of completed requests after which the guest is notified, even if more
+    /// requests are being processed. 0 only notifies the guest once all the requests at hand are
+    /// processed.
+    pub fn set_batch_size(&mut self, batch_size: u16) {
+        self.batch_size = batch_size;
+    }
+
     /// Retrieve the file engine type.
     pub fn file_engine_type(&self) -> FileEngineType {
         match self.disk.file_engine() {
@@ -523,16 +902,53 @@ impl Block {
         }
     }
 
+    /// Completes the requests in flight and flushes the backing file to the host storage, as a
+    /// flush request of the guest would, whatever the cache type of the device.
+    pub fn flush(&mut self) -> Result<(), BlockError> {
+        let res = self.disk.file_engine_mut().drain_and_flush(false);
+        if self.is_activated() {
+            if let FileEngine::Async(_engine) = self.disk.file_engine_mut() {
+                self.process_async_completion_queue();
+            }
+        }
+        res.map_err(BlockError::FileEngine)
+    }
+
+    // Completes the requests in flight and flushes them to the backing file.
+    fn quiesce(&mut self) {
+        if !self.is_activated() {
+            return;
+        }
+
+        if let Err(err) = self.flush() {
+            error!("Failed to drain ops and flush block data: {:?}", err);
+        }
+    }
+
     /// Prepare device for being snapshotted.
+    ///
+    /// The requests in flight are completed, without flushing the backing file, which the guest
+    /// did not ask for. The requests which cannot be completed are saved with the device, and
+    /// submitted again once it is restored.
     pub fn prepare_save(&mut self) {
         if !self.is_activated() {
             return;
         }
 
-        self.drain_and_flush(false);
-        if let FileEngine::Async(_engine) = self.disk.file_engine_mut() {
+        if let FileEngine::Async(engine) = self.disk.file_engine_mut() {
+            if let Err(err) = engine.drain(false) {
+                error!("Failed to complete the block requests in flight: {:?}", err);
+            }
             self.process_async_completion_queue();
         }
+        if !self.in_flight_requests.is_empty() {
+            warn!(
+                "{} block requests of device {} are still in flight, and will be submitted \
+                 again once the snapshot is loaded.",
+                self.in_flight_requests.len(),
+                self.id
+            );
+        }
     }
 }
 
@@ -627,7 +1043,7 @@ impl VirtioDevice for Block {
 impl Drop for Block {
     fn drop(&mut self) {
         match self.disk.cache_type {
-            CacheType::Unsafe => {
+            CacheType::Unsafe | CacheType::Writethrough => {
                 if let Err(err) = self.disk.file_engine_mut().drain(true) {
                     error!("Failed to drain ops on drop: {:?}", err);
                 }
@@ -674,6 +1090,9 @@ mod tests {
             true,
             CacheType::Unsafe,
             default_engine_type_for_kv(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
         )
         .unwrap();
 
@@ -692,6 +1111,9 @@ mod tests {
             true,
             CacheType::Unsafe,
             default_engine_type_for_kv(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
         )
         .is_err());
     }
@@ -717,6 +1139,42 @@ mod tests {
         assert_eq!(block.acked_features, features);
     }
 
+    #[test]
+    fn test_cache_type_features() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let new_block = |cache_type| {
+            Block::new(
+                "test".to_string(),
+                None,
+                cache_type,
+                path.clone(),
+                false,
+                false,
+                RateLimiter::default(),
+                FileEngineType::Sync,
+                false,
+                PageCacheHints::default(),
+                IoDepthLimits::default(),
+            )
+            .unwrap()
+        };
+
+        // Only the write-through cache type does not advertise the flush feature.
+        let block = new_block(CacheType::Writeback);
+        assert!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH) != 0);
+        let block = new_block(CacheType::Writethrough);
+        assert!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH) == 0);
+        assert_eq!(block.cache_type(), CacheType::Writethrough);
+        assert!(!block.direct_io());
+
+        // The backing file is opened with `O_DSYNC`.
+        // SAFETY: `fcntl` does not access memory and the descriptor is valid.
+        let flags = unsafe { libc::fcntl(block.disk.file().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_DSYNC, libc::O_DSYNC);
+    }
+
     #[test]
     fn test_virtio_read_config() {
         let block = default_block(default_engine_type_for_kv());
@@ -1499,6 +1957,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_in_flight_limit() {
+        skip_if_io_uring_unsupported!();
+
+        let mut block = default_block(FileEngineType::Async);
+        block.disk.io_depth_limits.max_in_flight = 8;
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, IO_URING_NUM_ENTRIES * 4);
+        block.activate(mem.clone()).unwrap();
+
+        // Only the first 8 requests are submitted.
+        add_flush_requests_batch(&mut block, &vq, 10);
+        check_metric_after_block!(
+            &METRICS.block.in_flight_limit_events,
+            1,
+            simulate_queue_event(&mut block, Some(false))
+        );
+        assert!(block.is_io_engine_throttled);
+        assert_eq!(block.in_flight_requests.len(), 8);
+
+        // The remaining requests are submitted once the first ones are completed.
+        simulate_async_completion_event(&mut block, true);
+        assert!(!block.is_io_engine_throttled);
+        check_flush_requests_batch(8, &vq);
+        assert_eq!(block.in_flight_requests.len(), 2);
+        simulate_async_completion_event(&mut block, true);
+        check_flush_requests_batch(10, &vq);
+    }
+
+    #[test]
+    fn test_busy_poll() {
+        let mut block = default_block(FileEngineType::Sync);
+        block.set_busy_poll(Duration::from_micros(100));
+        assert_eq!(block.busy_poll(), Duration::from_micros(100));
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // The queue is polled once the requests are completed, until the window expires.
+        add_flush_requests_batch(&mut block, &vq, 3);
+        check_metric_after_block!(
+            &METRICS.block.busy_poll_misses,
+            1,
+            simulate_queue_event(&mut block, Some(true))
+        );
+        check_flush_requests_batch(3, &vq);
+
+        // The queue is not polled while it is rate limited.
+        block.rate_limiter = RateLimiter::new(0, 0, 0, 1, 0, 10000).unwrap();
+        add_flush_requests_batch(&mut block, &vq, 3);
+        check_metric_after_block!(
+            &METRICS.block.busy_poll_misses,
+            0,
+            simulate_queue_event(&mut block, None)
+        );
+    }
+
+    #[test]
+    fn test_batch_size() {
+        let mut block = default_block(FileEngineType::Sync);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // The guest is notified once the whole queue is processed.
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, Some(true));
+        check_flush_requests_batch(5, &vq);
+        assert_eq!(block.irq_trigger.irq_evt.read().unwrap(), 1);
+
+        // The guest is notified after each batch, and once the queue is processed.
+        block.set_batch_size(2);
+        assert_eq!(block.batch_size(), 2);
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, Some(true));
+        check_flush_requests_batch(5, &vq);
+        assert_eq!(block.irq_trigger.irq_evt.read().unwrap(), 3);
+    }
+
     #[test]
     fn test_prepare_save() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1516,6 +2055,94 @@ mod tests {
         check_flush_requests_batch(5, &vq);
     }
 
+    #[test]
+    fn test_latency_metrics() {
+        for engine in [FileEngineType::Sync, default_engine_type_for_kv()] {
+            let mut block = default_block(engine);
+            // The global latencies are shared with the other tests.
+            let metrics: &'static DriveLatencyMetrics = Box::leak(Box::default());
+            block.latency_metrics = Some(metrics);
+
+            let mem = default_mem();
+            let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+            block.activate(mem.clone()).unwrap();
+
+            // The latencies are recorded once the requests are completed, for either engine.
+            add_flush_requests_batch(&mut block, &vq, 3);
+            simulate_queue_event(&mut block, None);
+            block.prepare_save();
+            check_flush_requests_batch(3, &vq);
+            assert_eq!(metrics.flush.count(), 3);
+            assert_eq!(metrics.read.count(), 0);
+            assert_eq!(metrics.write.count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_process_restored_requests() {
+        let mut block = default_block(default_engine_type_for_kv());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // The requests were popped from the queue before the snapshot was created, so the
+        // restored queue has no available chains.
+        add_flush_requests_batch(&mut block, &vq, 3);
+        vq.avail.idx.set(0);
+        block.restored_requests = vec![0, 2, 4];
+        block.process_virtio_queues();
+        block.prepare_save();
+
+        check_flush_requests_batch(3, &vq);
+        assert!(block.restored_requests.is_empty());
+        assert!(block.in_flight_requests.is_empty());
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut block = default_block(default_engine_type_for_kv());
+        // A device which is not activated has no requests in flight.
+        block.flush().unwrap();
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // The requests in flight are completed before the backing file is flushed.
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, None);
+        block.flush().unwrap();
+        check_flush_requests_batch(5, &vq);
+        assert!(block.in_flight_requests.is_empty());
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let mut block = default_block(default_engine_type_for_kv());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // Pausing completes the pending requests.
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, None);
+        block.pause();
+        assert!(block.is_paused());
+        check_flush_requests_batch(5, &vq);
+
+        // The requests queued while paused are only processed once resumed.
+        add_flush_requests_batch(&mut block, &vq, 3);
+        simulate_queue_event(&mut block, None);
+        assert_eq!(vq.used.idx.get(), 0);
+        block.resume();
+        assert!(!block.is_paused());
+        // Wait for the async requests, if any.
+        block.prepare_save();
+        check_flush_requests_batch(3, &vq);
+    }
+
     #[test]
     fn test_bandwidth_rate_limiter() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1685,4 +2312,31 @@ mod tests {
         );
         assert_eq!(block.disk.image_id, id.as_slice());
     }
+
+    #[test]
+    fn test_update_disk_image_completes_pending_requests() {
+        let mut block = default_block(default_engine_type_for_kv());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // The requests in flight are completed before the backing file is swapped.
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, None);
+        let f = TempFile::new().unwrap();
+        block
+            .update_disk_image(String::from(f.as_path().to_str().unwrap()))
+            .unwrap();
+        check_flush_requests_batch(5, &vq);
+
+        // A failed swap leaves the previous backing file in place.
+        assert!(block
+            .update_disk_image("invalid-disk-path".to_string())
+            .is_err());
+        assert_eq!(
+            block.disk.file().metadata().unwrap().st_ino(),
+            metadata(f.as_path()).unwrap().st_ino()
+        );
+    }
 }
@@ -11,7 +11,6 @@ use utils::eventfd::EventFd;
 use utils::vm_memory::{mark_dirty_mem, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 use crate::devices::virtio::block::io::UserDataError;
-use crate::devices::virtio::block::IO_URING_NUM_ENTRIES;
 use crate::io_uring::operation::{Cqe, OpCode, Operation};
 use crate::io_uring::restriction::Restriction;
 use crate::io_uring::{IoUring, IoUringError};
@@ -65,12 +64,12 @@ impl<T: Debug> WrappedUserData<T> {
 }
 
 impl<T: Debug> AsyncFileEngine<T> {
-    pub fn from_file(file: File) -> Result<AsyncFileEngine<T>, AsyncIoError> {
+    pub fn from_file(file: File, ring_entries: u32) -> Result<AsyncFileEngine<T>, AsyncIoError> {
         log_dev_preview_warning("Async file IO", Option::None);
 
         let completion_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(AsyncIoError::EventFd)?;
         let ring = IoUring::new(
-            u32::from(IO_URING_NUM_ENTRIES),
+            ring_entries,
             vec![&file],
             vec![
                 // Make sure we only allow operations on pre-registered fds.
@@ -92,7 +91,6 @@ impl<T: Debug> AsyncFileEngine<T> {
         })
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
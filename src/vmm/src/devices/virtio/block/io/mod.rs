@@ -57,9 +57,12 @@ pub enum FileEngine<T> {
 }
 
 impl<T: Debug> FileEngine<T> {
+    /// Creates an engine of type `engine_type` for `file`. An `Async` engine uses an io_uring
+    /// instance of `ring_entries` entries.
     pub fn from_file(
         file: File,
         engine_type: FileEngineType,
+        ring_entries: u32,
     ) -> Result<FileEngine<T>, BlockIoError> {
         if !engine_type
             .is_supported()
@@ -69,13 +72,12 @@ impl<T: Debug> FileEngine<T> {
         }
         match engine_type {
             FileEngineType::Async => Ok(FileEngine::Async(
-                AsyncFileEngine::from_file(file).map_err(BlockIoError::Async)?,
+                AsyncFileEngine::from_file(file, ring_entries).map_err(BlockIoError::Async)?,
             )),
             FileEngineType::Sync => Ok(FileEngine::Sync(SyncFileEngine::from_file(file))),
         }
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         match self {
             FileEngine::Async(engine) => engine.file(),
@@ -195,8 +197,10 @@ pub mod tests {
     use super::*;
     use crate::devices::virtio::block::device::FileEngineType;
     use crate::devices::virtio::block::request::PendingRequest;
+    use crate::devices::virtio::block::IO_URING_NUM_ENTRIES;
 
     const FILE_LEN: u32 = 1024;
+    const RING_ENTRIES: u32 = IO_URING_NUM_ENTRIES as u32;
     // 2 pages of memory should be enough to test read/write ops and also dirty tracking.
     const MEM_LEN: usize = 8192;
 
@@ -269,7 +273,8 @@ pub mod tests {
         assert!(matches!(
             FileEngine::<PendingRequest>::from_file(
                 TempFile::new().unwrap().into_file(),
-                FileEngineType::Async
+                FileEngineType::Async,
+                RING_ENTRIES
             ),
             Err(BlockIoError::UnsupportedEngine(FileEngineType::Async))
         ));
@@ -280,17 +285,17 @@ pub mod tests {
         // Check invalid file
         let mem = create_mem();
         let file = unsafe { File::from_raw_fd(-2) };
-        let mut engine = FileEngine::from_file(file, FileEngineType::Sync).unwrap();
-        let res = engine.read(0, &mem, GuestAddress(0), 0, ());
-        assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Seek(_e)));
-        let res = engine.write(0, &mem, GuestAddress(0), 0, ());
-        assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Seek(_e)));
+        let mut engine = FileEngine::from_file(file, FileEngineType::Sync, RING_ENTRIES).unwrap();
+        let res = engine.read(0, &mem, GuestAddress(0), 1, ());
+        assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Transfer(_e)));
+        let res = engine.write(0, &mem, GuestAddress(0), 1, ());
+        assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Transfer(_e)));
         let res = engine.flush(());
         assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::SyncAll(_e)));
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
-        let mut engine = FileEngine::from_file(file, FileEngineType::Sync).unwrap();
+        let mut engine = FileEngine::from_file(file, FileEngineType::Sync, RING_ENTRIES).unwrap();
 
         let data = utils::rand::rand_alphanumerics(FILE_LEN as usize)
             .as_bytes()
@@ -364,11 +369,12 @@ pub mod tests {
 
         // Check invalid file
         let file = unsafe { File::from_raw_fd(-2) };
-        assert!(FileEngine::<()>::from_file(file, FileEngineType::Async).is_err());
+        assert!(FileEngine::<()>::from_file(file, FileEngineType::Async, RING_ENTRIES).is_err());
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
-        let mut engine = FileEngine::<()>::from_file(file, FileEngineType::Async).unwrap();
+        let mut engine =
+            FileEngine::<()>::from_file(file, FileEngineType::Async, RING_ENTRIES).unwrap();
 
         let data = utils::rand::rand_alphanumerics(FILE_LEN as usize)
             .as_bytes()
@@ -2,16 +2,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
 
 use utils::vm_memory::{
-    GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, ReadVolatile, WriteVolatile,
+    Bitmap, BitmapSlice, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
+    ReadVolatile, VolatileMemoryError, VolatileSlice, WriteVolatile,
 };
 
 #[derive(Debug)]
 pub enum SyncIoError {
     Flush(std::io::Error),
-    Seek(std::io::Error),
     SyncAll(std::io::Error),
     Transfer(GuestMemoryError),
 }
@@ -29,7 +30,6 @@ impl SyncFileEngine {
         SyncFileEngine { file }
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
@@ -41,11 +41,9 @@ impl SyncFileEngine {
         addr: GuestAddress,
         count: u32,
     ) -> Result<u32, SyncIoError> {
-        self.file
-            .seek(SeekFrom::Start(offset))
-            .map_err(SyncIoError::Seek)?;
+        let mut file = FileAt::new(&self.file, offset);
         mem.get_slice(addr, count as usize)
-            .and_then(|mut slice| Ok(self.file.read_exact_volatile(&mut slice)?))
+            .and_then(|mut slice| Ok(file.read_exact_volatile(&mut slice)?))
             .map_err(SyncIoError::Transfer)?;
         Ok(count)
     }
@@ -57,11 +55,9 @@ impl SyncFileEngine {
         addr: GuestAddress,
         count: u32,
     ) -> Result<u32, SyncIoError> {
-        self.file
-            .seek(SeekFrom::Start(offset))
-            .map_err(SyncIoError::Seek)?;
+        let mut file = FileAt::new(&self.file, offset);
         mem.get_slice(addr, count as usize)
-            .and_then(|slice| Ok(self.file.write_all_volatile(&slice)?))
+            .and_then(|slice| Ok(file.write_all_volatile(&slice)?))
             .map_err(SyncIoError::Transfer)?;
         Ok(count)
     }
@@ -73,3 +69,77 @@ impl SyncFileEngine {
         self.file.sync_all().map_err(SyncIoError::SyncAll)
     }
 }
+
+/// Accesses a file from a given offset without moving the offset of its open file description,
+/// which can be shared with other devices (e.g. when several drives are backed by the same
+/// inherited file descriptor).
+#[derive(Debug)]
+struct FileAt<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl<'a> FileAt<'a> {
+    fn new(file: &'a File, offset: u64) -> Self {
+        FileAt { file, offset }
+    }
+
+    fn offset(&self) -> Result<libc::off_t, VolatileMemoryError> {
+        libc::off_t::try_from(self.offset).map_err(|_| {
+            VolatileMemoryError::IOError(std::io::Error::from_raw_os_error(libc::EOVERFLOW))
+        })
+    }
+}
+
+impl ReadVolatile for FileAt<'_> {
+    fn read_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &mut VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let offset = self.offset()?;
+        let guard = buf.ptr_guard_mut();
+        let dst = guard.as_ptr().cast::<libc::c_void>();
+
+        // SAFETY: The file descriptor is valid as long as `self.file` is. The memory pointed to by
+        // `dst` is valid for writes of length `buf.len()` by the invariants upheld by the
+        // constructor of `VolatileSlice`.
+        let bytes_read = unsafe { libc::pread(self.file.as_raw_fd(), dst, buf.len(), offset) };
+
+        if bytes_read < 0 {
+            // We don't know if a partial read might have happened, so mark everything as dirty
+            buf.bitmap().mark_dirty(0, buf.len());
+            return Err(VolatileMemoryError::IOError(std::io::Error::last_os_error()));
+        }
+        // The cast is safe because `bytes_read` is not negative.
+        #[allow(clippy::cast_sign_loss)]
+        let bytes_read = bytes_read as usize;
+        buf.bitmap().mark_dirty(0, bytes_read);
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl WriteVolatile for FileAt<'_> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let offset = self.offset()?;
+        let guard = buf.ptr_guard();
+        let src = guard.as_ptr().cast::<libc::c_void>();
+
+        // SAFETY: The file descriptor is valid as long as `self.file` is. The memory pointed to by
+        // `src` is valid for reads of length `buf.len()` by the invariants upheld by the
+        // constructor of `VolatileSlice`.
+        let bytes_written = unsafe { libc::pwrite(self.file.as_raw_fd(), src, buf.len(), offset) };
+
+        if bytes_written < 0 {
+            return Err(VolatileMemoryError::IOError(std::io::Error::last_os_error()));
+        }
+        // The cast is safe because `bytes_written` is not negative.
+        #[allow(clippy::cast_sign_loss)]
+        let bytes_written = bytes_written as usize;
+        self.offset += bytes_written as u64;
+        Ok(bytes_written)
+    }
+}
@@ -5,6 +5,7 @@
 
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use logger::warn;
 use snapshot::Persist;
@@ -14,7 +15,9 @@ use versionize_derive::Versionize;
 use virtio_gen::virtio_blk::VIRTIO_BLK_F_RO;
 
 use super::*;
-use crate::devices::virtio::block::device::FileEngineType;
+use crate::devices::virtio::block::device::{
+    FadvisePolicy, FileEngineType, IoDepthLimits, PageCacheHints,
+};
 use crate::devices::virtio::persist::VirtioDeviceState;
 use crate::devices::virtio::{DeviceState, FIRECRACKER_MAX_QUEUE_SIZE, TYPE_BLOCK};
 use crate::rate_limiter::persist::RateLimiterState;
@@ -31,6 +34,22 @@ pub enum CacheTypeState {
     /// flush requests coming from the guest will be performed using
     /// `fsync`.
     Writeback,
+    /// Flushing mechanic will not be advertised to the guest driver and the
+    /// backing file is opened with `O_DSYNC`.
+    #[version(start = 2, default_fn = "default_writethrough")]
+    Writethrough,
+}
+
+impl CacheTypeState {
+    fn default_writethrough(&self, _target_version: u16) -> VersionizeResult<CacheTypeState> {
+        // Writes are still durable once completed with a flush after each of them, which the
+        // guest driver only sends when the writeback mechanic is advertised.
+        warn!(
+            "Target version does not implement the \"writethrough\" cache type. Defaulting to \
+             \"writeback\" mode."
+        );
+        Ok(CacheTypeState::Writeback)
+    }
 }
 
 impl From<CacheType> for CacheTypeState {
@@ -38,6 +57,7 @@ impl From<CacheType> for CacheTypeState {
         match cache_type {
             CacheType::Unsafe => CacheTypeState::Unsafe,
             CacheType::Writeback => CacheTypeState::Writeback,
+            CacheType::Writethrough => CacheTypeState::Writethrough,
         }
     }
 }
@@ -47,6 +67,7 @@ impl From<CacheTypeState> for CacheType {
         match cache_type_state {
             CacheTypeState::Unsafe => CacheType::Unsafe,
             CacheTypeState::Writeback => CacheType::Writeback,
+            CacheTypeState::Writethrough => CacheType::Writethrough,
         }
     }
 }
@@ -82,6 +103,43 @@ impl From<FileEngineTypeState> for FileEngineType {
     }
 }
 
+/// Holds info about block's advised access pattern. Gets saved in snapshot.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Versionize)]
+pub enum FadvisePolicyState {
+    /// No advice.
+    #[default]
+    Normal,
+    /// Sequential accesses.
+    Sequential,
+    /// Random accesses.
+    Random,
+    /// Data read once.
+    DontNeed,
+}
+
+impl From<FadvisePolicy> for FadvisePolicyState {
+    fn from(fadvise: FadvisePolicy) -> Self {
+        match fadvise {
+            FadvisePolicy::Normal => FadvisePolicyState::Normal,
+            FadvisePolicy::Sequential => FadvisePolicyState::Sequential,
+            FadvisePolicy::Random => FadvisePolicyState::Random,
+            FadvisePolicy::DontNeed => FadvisePolicyState::DontNeed,
+        }
+    }
+}
+
+impl From<FadvisePolicyState> for FadvisePolicy {
+    fn from(fadvise_state: FadvisePolicyState) -> Self {
+        match fadvise_state {
+            FadvisePolicyState::Normal => FadvisePolicy::Normal,
+            FadvisePolicyState::Sequential => FadvisePolicy::Sequential,
+            FadvisePolicyState::Random => FadvisePolicy::Random,
+            FadvisePolicyState::DontNeed => FadvisePolicy::DontNeed,
+        }
+    }
+}
+
 /// Holds info about the block device. Gets saved in snapshot.
 // NOTICE: Any changes to this structure require a snapshot version bump.
 #[derive(Debug, Clone, Versionize)]
@@ -93,7 +151,7 @@ pub struct BlockState {
         ser_fn = "block_cache_type_ser",
         default_fn = "default_cache_type_flush"
     )]
-    cache_type: CacheTypeState,
+    pub(crate) cache_type: CacheTypeState,
     root_device: bool,
     disk_path: String,
     virtio_state: VirtioDeviceState,
@@ -102,10 +160,42 @@ pub struct BlockState {
     // We don't need to specify a `ser_fn` for the `file_engine_type` since snapshots created in
     // v1.0 are incompatible with older FC versions (due to incompatible notification suppression
     // feature).
-    file_engine_type: FileEngineTypeState,
+    pub(crate) file_engine_type: FileEngineTypeState,
+    #[version(start = 4, ser_fn = "in_flight_requests_ser")]
+    // Heads of the descriptor chains of the requests which could not be completed before the
+    // snapshot was created.
+    pub(crate) in_flight_requests: Vec<u16>,
+    #[version(start = 4, ser_fn = "direct_io_ser")]
+    pub(crate) direct_io: bool,
+    // The page cache hints only affect the host, so they are dropped silently by older versions.
+    #[version(start = 4)]
+    fadvise: FadvisePolicyState,
+    #[version(start = 4)]
+    readahead_kib: u32,
+    // Older versions restore the device with the default limits.
+    #[version(start = 4, default_fn = "default_io_uring_entries")]
+    io_uring_entries: u16,
+    #[version(start = 4)]
+    max_in_flight_requests: u16,
+    // Older versions wait for the guest notifications, without polling the queue.
+    #[version(start = 4)]
+    busy_poll_us: u32,
+    // Older versions notify the guest once the queue is processed.
+    #[version(start = 4)]
+    batch_size: u16,
 }
 
 impl BlockState {
+    /// Returns the path of the file backing the device.
+    pub fn disk_path(&self) -> &str {
+        &self.disk_path
+    }
+
+    /// Sets the path of the file the device is restored with.
+    pub fn set_disk_path(&mut self, disk_path: String) {
+        self.disk_path = disk_path;
+    }
+
     fn block_cache_type_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
         if target_version < 3 && self.cache_type != CacheTypeState::Unsafe {
             warn!(
@@ -120,6 +210,32 @@ impl BlockState {
     fn default_cache_type_flush(_source_version: u16) -> CacheTypeState {
         CacheTypeState::Unsafe
     }
+
+    fn default_io_uring_entries(_source_version: u16) -> u16 {
+        IO_URING_NUM_ENTRIES
+    }
+
+    fn in_flight_requests_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && !self.in_flight_requests.is_empty() {
+            warn!(
+                "Target version does not implement the block requests in flight. They will not \
+                 be completed."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn direct_io_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.direct_io {
+            warn!(
+                "Target version does not implement direct I/O. The backing file will be accessed \
+                 through the host page cache."
+            );
+        }
+
+        Ok(())
+    }
 }
 
 /// Auxiliary structure for creating a device when resuming from a snapshot.
@@ -145,6 +261,19 @@ impl Persist<'_> for Block {
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
             file_engine_type: FileEngineTypeState::from(self.file_engine_type()),
+            in_flight_requests: self
+                .in_flight_requests
+                .iter()
+                .chain(self.restored_requests.iter())
+                .copied()
+                .collect(),
+            direct_io: self.direct_io(),
+            fadvise: self.page_cache_hints().fadvise.into(),
+            readahead_kib: self.page_cache_hints().readahead_kib,
+            io_uring_entries: self.io_depth_limits().ring_entries,
+            max_in_flight_requests: self.io_depth_limits().max_in_flight,
+            busy_poll_us: u32::try_from(self.busy_poll().as_micros()).unwrap_or(u32::MAX),
+            batch_size: self.batch_size(),
         }
     }
 
@@ -153,6 +282,14 @@ impl Persist<'_> for Block {
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
         let is_disk_read_only = state.virtio_state.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0;
+        let page_cache_hints = PageCacheHints {
+            fadvise: state.fadvise.into(),
+            readahead_kib: state.readahead_kib,
+        };
+        let io_depth_limits = IoDepthLimits {
+            ring_entries: state.io_uring_entries,
+            max_in_flight: state.max_in_flight_requests,
+        };
         let rate_limiter =
             RateLimiter::restore((), &state.rate_limiter_state).map_err(BlockError::RateLimiter)?;
 
@@ -165,6 +302,9 @@ impl Persist<'_> for Block {
             state.root_device,
             rate_limiter,
             state.file_engine_type.into(),
+            state.direct_io,
+            page_cache_hints,
+            io_depth_limits,
         )
         .or_else(|err| match err {
             BlockError::FileEngine(io::BlockIoError::UnsupportedEngine(FileEngineType::Async)) => {
@@ -186,6 +326,9 @@ impl Persist<'_> for Block {
                     state.root_device,
                     rate_limiter,
                     FileEngineType::Sync,
+                    state.direct_io,
+                    page_cache_hints,
+                    io_depth_limits,
                 )
             }
             other_err => Err(other_err),
@@ -204,9 +347,12 @@ impl Persist<'_> for Block {
             Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         block.avail_features = state.virtio_state.avail_features;
         block.acked_features = state.virtio_state.acked_features;
+        block.set_busy_poll(Duration::from_micros(u64::from(state.busy_poll_us)));
+        block.set_batch_size(state.batch_size);
 
         if state.virtio_state.activated {
             block.device_state = DeviceState::Activated(constructor_args.mem);
+            block.restored_requests = state.in_flight_requests.clone();
         }
 
         Ok(block)
@@ -233,12 +379,17 @@ mod tests {
             CacheTypeState::Writeback,
             CacheTypeState::from(CacheType::Writeback)
         );
+        assert_eq!(
+            CacheTypeState::Writethrough,
+            CacheTypeState::from(CacheType::Writethrough)
+        );
     }
 
     #[test]
     fn test_cache_type_state_into() {
         assert_eq!(CacheType::Unsafe, CacheTypeState::Unsafe.into());
         assert_eq!(CacheType::Writeback, CacheTypeState::Writeback.into());
+        assert_eq!(CacheType::Writethrough, CacheTypeState::Writethrough.into());
     }
 
     #[test]
@@ -269,6 +420,9 @@ mod tests {
             false,
             RateLimiter::default(),
             FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
         )
         .unwrap();
 
@@ -323,6 +477,9 @@ mod tests {
                 // Need to use Sync because it will otherwise return an error.
                 // We'll overwrite the state instead.
                 FileEngineType::Sync,
+                false,
+                PageCacheHints::default(),
+                IoDepthLimits::default(),
             )
             .unwrap();
 
@@ -366,6 +523,9 @@ mod tests {
             false,
             RateLimiter::default(),
             FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
         )
         .unwrap();
         let guest_mem = default_mem();
@@ -399,4 +559,262 @@ mod tests {
         // Test that block specific fields are the same.
         assert_eq!(restored_block.disk.file_path(), block.disk.file_path());
     }
+
+    #[test]
+    fn test_persist_in_flight_requests() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
+        )
+        .unwrap();
+        block.activate(default_mem()).unwrap();
+        block.in_flight_requests.insert(4);
+        block.restored_requests = vec![2];
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // The requests in flight are submitted again by the restored device.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.restored_requests, vec![4, 2]);
+        assert!(restored_block.in_flight_requests.is_empty());
+
+        // Older versions do not save them.
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_block.restored_requests.is_empty());
+    }
+
+    #[test]
+    fn test_persist_writethrough_direct_io() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Writethrough,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
+        )
+        .unwrap();
+        let mut block_state = <Block as Persist>::save(&block);
+        // The backing file is not opened again, as not every filesystem supports direct I/O.
+        block_state.direct_io = true;
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 3);
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4)
+            .set_type_version(CacheTypeState::type_id(), 2);
+
+        let mut mem = vec![0; 4096];
+        block_state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 3)
+            .unwrap();
+        let restored_state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 3).unwrap();
+        assert_eq!(restored_state.cache_type, CacheTypeState::Writethrough);
+        assert!(restored_state.direct_io);
+
+        // Older versions fall back to the writeback cache type, without direct I/O.
+        block_state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state.cache_type, CacheTypeState::Writeback);
+        assert!(!restored_state.direct_io);
+    }
+
+    #[test]
+    fn test_persist_page_cache_hints() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let page_cache_hints = PageCacheHints {
+            fadvise: FadvisePolicy::DontNeed,
+            readahead_kib: 128,
+        };
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            FileEngineType::default(),
+            false,
+            page_cache_hints,
+            IoDepthLimits::default(),
+        )
+        .unwrap();
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.page_cache_hints(), page_cache_hints);
+
+        // Older versions restore the device without hints.
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.page_cache_hints(), PageCacheHints::default());
+    }
+
+    #[test]
+    fn test_persist_io_depth_limits() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let io_depth_limits = IoDepthLimits {
+            ring_entries: 32,
+            max_in_flight: 8,
+        };
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            io_depth_limits,
+        )
+        .unwrap();
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.io_depth_limits(), io_depth_limits);
+
+        // Older versions restore the device with the default limits.
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.io_depth_limits(), IoDepthLimits::default());
+    }
+
+    #[test]
+    fn test_persist_queue_tuning() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
+        )
+        .unwrap();
+        block.set_busy_poll(Duration::from_micros(50));
+        block.set_batch_size(8);
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.busy_poll(), Duration::from_micros(50));
+        assert_eq!(restored_block.batch_size(), 8);
+
+        // Older versions restore the device without polling nor batching.
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.busy_poll(), Duration::ZERO);
+        assert_eq!(restored_block.batch_size(), 0);
+    }
 }
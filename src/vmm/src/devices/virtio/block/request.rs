@@ -7,7 +7,8 @@
 
 use std::convert::From;
 
-use logger::{error, IncMetric, METRICS};
+use logger::{error, DriveLatencyMetrics, IncMetric, METRICS};
+use utils::time::{get_time_us, ClockType};
 use utils::vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 pub use virtio_gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
@@ -91,12 +92,24 @@ impl Status {
 #[derive(Debug)]
 pub struct PendingRequest {
     r#type: RequestType,
+    offset: u64,
     data_len: u32,
     status_addr: GuestAddress,
     desc_idx: u16,
+    // Time the request was submitted, in microseconds, and where its latency is recorded.
+    start_us: u64,
+    latency_metrics: Option<&'static DriveLatencyMetrics>,
 }
 
 impl PendingRequest {
+    /// Offset in the backing file of the data read by the request, if it is a read.
+    pub(crate) fn read_offset(&self) -> Option<u64> {
+        match self.r#type {
+            RequestType::In => Some(self.offset),
+            _ => None,
+        }
+    }
+
     fn write_status_and_finish(self, status: &Status, mem: &GuestMemoryMmap) -> FinishedRequest {
         let (num_bytes_to_mem, status_code) = match status {
             Status::Ok { num_bytes_to_mem } => (*num_bytes_to_mem, VIRTIO_BLK_S_OK),
@@ -137,6 +150,7 @@ impl PendingRequest {
     }
 
     pub fn finish(self, mem: &GuestMemoryMmap, res: Result<u32, IoErr>) -> FinishedRequest {
+        self.record_latency();
         let status = match (res, self.r#type) {
             (Ok(transferred_data_len), RequestType::In) => {
                 let status = Status::from_data(self.data_len, transferred_data_len, true);
@@ -172,6 +186,19 @@ impl PendingRequest {
 
         self.write_status_and_finish(&status, mem)
     }
+
+    fn record_latency(&self) {
+        let Some(metrics) = self.latency_metrics else {
+            return;
+        };
+        let histogram = match self.r#type {
+            RequestType::In => &metrics.read,
+            RequestType::Out => &metrics.write,
+            RequestType::Flush => &metrics.flush,
+            RequestType::GetDeviceID | RequestType::Unsupported(_) => return,
+        };
+        histogram.record(get_time_us(ClockType::Monotonic).saturating_sub(self.start_us));
+    }
 }
 
 /// The request header represents the mandatory fields of each block device request.
@@ -340,30 +367,43 @@ impl Request {
         self.sector << SECTOR_SHIFT
     }
 
-    fn to_pending_request(&self, desc_idx: u16) -> PendingRequest {
+    fn to_pending_request(
+        &self,
+        desc_idx: u16,
+        latency_metrics: Option<&'static DriveLatencyMetrics>,
+    ) -> PendingRequest {
         PendingRequest {
             r#type: self.r#type,
+            offset: self.offset(),
             data_len: self.data_len,
             status_addr: self.status_addr,
             desc_idx,
+            start_us: get_time_us(ClockType::Monotonic),
+            latency_metrics,
         }
     }
 
+    /// Executes the request, or submits it to the IO engine. Its latency is recorded in
+    /// `latency_metrics` once it is completed.
     pub(crate) fn process(
         self,
         disk: &mut DiskProperties,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
+        latency_metrics: Option<&'static DriveLatencyMetrics>,
     ) -> ProcessingResult {
-        let pending = self.to_pending_request(desc_idx);
+        let pending = self.to_pending_request(desc_idx, latency_metrics);
         let res = match self.r#type {
-            RequestType::In => disk.file_engine_mut().read(
-                self.offset(),
-                mem,
-                self.data_addr,
-                self.data_len,
-                pending,
-            ),
+            RequestType::In => {
+                disk.before_read(self.offset(), self.data_len);
+                disk.file_engine_mut().read(
+                    self.offset(),
+                    mem,
+                    self.data_addr,
+                    self.data_len,
+                    pending,
+                )
+            }
             RequestType::Out => disk.file_engine_mut().write(
                 self.offset(),
                 mem,
@@ -387,6 +427,9 @@ impl Request {
         match res {
             Ok(block_io::FileEngineOk::Submitted) => ProcessingResult::Submitted,
             Ok(block_io::FileEngineOk::Executed(res)) => {
+                if let Some(offset) = res.user_data.read_offset() {
+                    disk.after_read(offset, res.count);
+                }
                 ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count)))
             }
             Err(err) => {
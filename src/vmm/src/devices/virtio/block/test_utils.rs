@@ -12,7 +12,7 @@ use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
 use utils::tempfile::TempFile;
 use utils::vm_memory::{Bytes, GuestAddress};
 
-use crate::devices::virtio::block::device::FileEngineType;
+use crate::devices::virtio::block::device::{FileEngineType, IoDepthLimits, PageCacheHints};
 #[cfg(test)]
 use crate::devices::virtio::block::io::FileEngine;
 use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
@@ -56,6 +56,9 @@ pub fn default_block_with_path(path: String, file_engine_type: FileEngineType) -
         false,
         rate_limiter,
         file_engine_type,
+        false,
+        PageCacheHints::default(),
+        IoDepthLimits::default(),
     )
     .unwrap()
 }
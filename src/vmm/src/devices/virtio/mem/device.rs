@@ -0,0 +1,808 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::{cmp, mem};
+
+use logger::{debug, error, IncMetric, METRICS};
+use serde::Serialize;
+use utils::eventfd::EventFd;
+use utils::vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+
+use super::{
+    MEM_DEV_ID, MEM_NUM_QUEUES, MEM_QUEUE, VIRTIO_MEM_REQ_PLUG, VIRTIO_MEM_REQ_STATE,
+    VIRTIO_MEM_REQ_UNPLUG, VIRTIO_MEM_REQ_UNPLUG_ALL, VIRTIO_MEM_RESP_ACK, VIRTIO_MEM_RESP_ERROR,
+    VIRTIO_MEM_RESP_NACK, VIRTIO_MEM_STATE_MIXED, VIRTIO_MEM_STATE_PLUGGED,
+    VIRTIO_MEM_STATE_UNPLUGGED,
+};
+use crate::devices::virtio::balloon::util::remove_range;
+use crate::devices::virtio::balloon::RemoveRegionError;
+use crate::devices::virtio::device::{IrqTrigger, IrqType};
+use crate::devices::virtio::{
+    ActivateError, DescriptorChain, DeviceState, Queue, QueueError, VirtioDevice,
+    FIRECRACKER_MAX_QUEUE_SIZE, TYPE_MEM,
+};
+
+// Blocks are discarded from the host mapping when unplugged, so they must span whole pages.
+const MIN_BLOCK_SIZE: u64 = 4096;
+
+/// Memory hotplug device related errors.
+#[derive(Debug, thiserror::Error)]
+pub enum VirtioMemError {
+    /// The block size is not a power of two multiple of the page size.
+    #[error("The block size must be a power of two of at least 4 KiB: {0}")]
+    InvalidBlockSize(u64),
+    /// The hotplug region is empty or not aligned to the block size.
+    #[error("The hotplug region must be non-empty and aligned to the block size.")]
+    InvalidRegion,
+    /// The requested size is not a multiple of the block size.
+    #[error("The requested size must be a multiple of the block size: {0}")]
+    InvalidRequestedSize(u64),
+    /// The requested size does not fit in the hotplug region.
+    #[error("The requested size exceeds the size of the hotplug region: {0}")]
+    RequestedSizeTooLarge(u64),
+    /// EventFd error.
+    #[error("Error while handling an Event file descriptor: {0}")]
+    EventFd(#[from] io::Error),
+    /// Guest gave us bad memory addresses.
+    #[error("Bad guest memory buffer: {0}")]
+    GuestMemory(#[from] GuestMemoryError),
+    /// Received error while sending an interrupt.
+    #[error("Failed to send an interrupt: {0}")]
+    Interrupt(io::Error),
+    /// Guest gave us a malformed descriptor.
+    #[error("Received a malformed descriptor.")]
+    MalformedDescriptor,
+    /// Error while processing the virt queues.
+    #[error("Error while processing the virtio queue: {0}")]
+    Queue(#[from] QueueError),
+}
+
+/// The configuration space of a virtio-mem device, as defined in the virtio 1.2 specification,
+/// section 5.15.4.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) struct ConfigSpace {
+    pub block_size: u64,
+    pub node_id: u16,
+    pub padding: [u8; 6],
+    pub addr: u64,
+    pub region_size: u64,
+    pub usable_region_size: u64,
+    pub plugged_size: u64,
+    pub requested_size: u64,
+}
+
+// SAFETY: Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+/// A request placed by the driver on the request queue.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct Request {
+    req_type: u16,
+    padding: [u16; 3],
+    addr: u64,
+    nb_blocks: u16,
+    padding_1: [u16; 3],
+}
+
+// SAFETY: Safe because Request only contains plain data.
+unsafe impl ByteValued for Request {}
+
+/// The response written back by the device for each request.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct Response {
+    resp_type: u16,
+    padding: [u16; 3],
+    state: u16,
+}
+
+// SAFETY: Safe because Response only contains plain data.
+unsafe impl ByteValued for Response {}
+
+impl Response {
+    fn new(resp_type: u16) -> Self {
+        Response {
+            resp_type,
+            ..Default::default()
+        }
+    }
+}
+
+/// Summary of the memory hotplug device state, as exposed through the API.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VirtioMemStatus {
+    /// Size of the memory hotplug region, in MiB.
+    pub total_size_mib: u64,
+    /// Granularity at which the guest (un)plugs memory, in MiB.
+    pub block_size_mib: u64,
+    /// Amount of hotplugged memory currently in use by the guest, in MiB.
+    pub plugged_size_mib: u64,
+    /// Amount of hotplugged memory the guest has been asked to use, in MiB.
+    pub requested_size_mib: u64,
+}
+
+/// Virtio device exposing a range of guest physical memory that can be plugged and unplugged
+/// by the guest, block by block, at the request of the host.
+#[derive(Debug)]
+pub struct VirtioMem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: [EventFd; MEM_NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+    pub(crate) irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    pub(crate) restored: bool,
+    // Plugged state of every block in the hotplug region.
+    pub(crate) plugged_blocks: Vec<bool>,
+}
+
+impl VirtioMem {
+    /// Instantiate a new memory hotplug device, managing the `region_size` bytes of guest memory
+    /// starting at `addr`.
+    pub fn new(
+        addr: GuestAddress,
+        region_size: u64,
+        block_size: u64,
+        restored: bool,
+    ) -> Result<VirtioMem, VirtioMemError> {
+        if !block_size.is_power_of_two() || block_size < MIN_BLOCK_SIZE {
+            return Err(VirtioMemError::InvalidBlockSize(block_size));
+        }
+        if region_size == 0 || region_size % block_size != 0 || addr.0 % block_size != 0 {
+            return Err(VirtioMemError::InvalidRegion);
+        }
+
+        let queues: Vec<Queue> = vec![Queue::new(FIRECRACKER_MAX_QUEUE_SIZE); MEM_NUM_QUEUES];
+        let num_blocks =
+            usize::try_from(region_size / block_size).map_err(|_| VirtioMemError::InvalidRegion)?;
+
+        // VIRTIO_MEM_F_UNPLUGGED_INACCESSIBLE is not offered, as unplugged blocks stay mapped and
+        // readable by the guest.
+        Ok(VirtioMem {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace {
+                block_size,
+                addr: addr.0,
+                region_size,
+                usable_region_size: region_size,
+                ..Default::default()
+            },
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            queues,
+            queue_evts: [EventFd::new(libc::EFD_NONBLOCK)?],
+            device_state: DeviceState::Inactive,
+            irq_trigger: IrqTrigger::new()?,
+            restored,
+            plugged_blocks: vec![false; num_blocks],
+        })
+    }
+
+    /// Provides the ID of this memory hotplug device.
+    pub fn id(&self) -> &str {
+        MEM_DEV_ID
+    }
+
+    /// Guest physical address at which the hotplug region starts.
+    pub fn addr(&self) -> GuestAddress {
+        GuestAddress(self.config_space.addr)
+    }
+
+    /// Size of the hotplug region, in bytes.
+    pub fn region_size(&self) -> u64 {
+        self.config_space.region_size
+    }
+
+    /// Granularity at which the guest (un)plugs memory, in bytes.
+    pub fn block_size(&self) -> u64 {
+        self.config_space.block_size
+    }
+
+    /// Amount of memory the guest has been asked to plug, in bytes.
+    pub fn requested_size(&self) -> u64 {
+        self.config_space.requested_size
+    }
+
+    /// Amount of memory currently plugged by the guest, in bytes.
+    pub fn plugged_size(&self) -> u64 {
+        self.config_space.plugged_size
+    }
+
+    /// Asks the guest to grow or shrink the amount of plugged memory to `requested_size` bytes.
+    pub fn update_requested_size(&mut self, requested_size: u64) -> Result<(), VirtioMemError> {
+        if requested_size % self.block_size() != 0 {
+            return Err(VirtioMemError::InvalidRequestedSize(requested_size));
+        }
+        if requested_size > self.config_space.usable_region_size {
+            return Err(VirtioMemError::RequestedSizeTooLarge(requested_size));
+        }
+
+        self.config_space.requested_size = requested_size;
+        // A driver that is not up yet will pick up the new size when reading the config space.
+        if self.is_activated() {
+            self.irq_trigger
+                .trigger_irq(IrqType::Config)
+                .map_err(VirtioMemError::Interrupt)?;
+        }
+        Ok(())
+    }
+
+    /// Provides a summary of the device state.
+    pub fn status(&self) -> VirtioMemStatus {
+        VirtioMemStatus {
+            total_size_mib: self.region_size() >> 20,
+            block_size_mib: self.block_size() >> 20,
+            plugged_size_mib: self.plugged_size() >> 20,
+            requested_size_mib: self.requested_size() >> 20,
+        }
+    }
+
+    // Translates a guest supplied range into a range of block indexes, if valid.
+    fn block_range(&self, addr: u64, nb_blocks: u16) -> Option<Range<usize>> {
+        let offset = addr.checked_sub(self.config_space.addr)?;
+        if nb_blocks == 0 || offset % self.block_size() != 0 {
+            return None;
+        }
+        let first = usize::try_from(offset / self.block_size()).ok()?;
+        let last = first.checked_add(usize::from(nb_blocks))?;
+        if last > self.plugged_blocks.len() {
+            return None;
+        }
+        Some(first..last)
+    }
+
+    fn discard(&self, mem: &GuestMemoryMmap, addr: u64, len: u64) -> Result<(), RemoveRegionError> {
+        remove_range(mem, (GuestAddress(addr), len), self.restored)
+    }
+
+    fn handle_plug(&mut self, request: &Request) -> Response {
+        let Some(blocks) = self.block_range(request.addr, request.nb_blocks) else {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        };
+        if self.plugged_blocks[blocks.clone()]
+            .iter()
+            .any(|plugged| *plugged)
+        {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        let size = u64::from(request.nb_blocks) * self.block_size();
+        if self.config_space.plugged_size + size > self.config_space.requested_size {
+            return Response::new(VIRTIO_MEM_RESP_NACK);
+        }
+
+        self.plugged_blocks[blocks].fill(true);
+        self.config_space.plugged_size += size;
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn handle_unplug(&mut self, mem: &GuestMemoryMmap, request: &Request) -> Response {
+        let Some(blocks) = self.block_range(request.addr, request.nb_blocks) else {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        };
+        if !self.plugged_blocks[blocks.clone()]
+            .iter()
+            .all(|plugged| *plugged)
+        {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        let size = u64::from(request.nb_blocks) * self.block_size();
+        if let Err(err) = self.discard(mem, request.addr, size) {
+            error!("virtio-mem: Failed to discard unplugged memory: {:?}", err);
+            METRICS.memory_hotplug.unplug_discard_fails.inc();
+        }
+
+        self.plugged_blocks[blocks].fill(false);
+        self.config_space.plugged_size -= size;
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn handle_unplug_all(&mut self, mem: &GuestMemoryMmap) -> Response {
+        if let Err(err) = self.discard(mem, self.config_space.addr, self.region_size()) {
+            error!("virtio-mem: Failed to discard unplugged memory: {:?}", err);
+            METRICS.memory_hotplug.unplug_discard_fails.inc();
+        }
+
+        self.plugged_blocks.fill(false);
+        self.config_space.plugged_size = 0;
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn handle_state(&self, request: &Request) -> Response {
+        let Some(blocks) = self.block_range(request.addr, request.nb_blocks) else {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        };
+
+        let blocks = &self.plugged_blocks[blocks];
+        let state = if blocks.iter().all(|plugged| *plugged) {
+            VIRTIO_MEM_STATE_PLUGGED
+        } else if blocks.iter().all(|plugged| !*plugged) {
+            VIRTIO_MEM_STATE_UNPLUGGED
+        } else {
+            VIRTIO_MEM_STATE_MIXED
+        };
+        Response {
+            state,
+            ..Response::new(VIRTIO_MEM_RESP_ACK)
+        }
+    }
+
+    fn handle_request(&mut self, mem: &GuestMemoryMmap, request: &Request) -> Response {
+        debug!(
+            "virtio-mem: request type {} for {} blocks at {:#x}",
+            request.req_type, request.nb_blocks, request.addr
+        );
+        match request.req_type {
+            VIRTIO_MEM_REQ_PLUG => {
+                METRICS.memory_hotplug.plug_count.inc();
+                let response = self.handle_plug(request);
+                if response.resp_type != VIRTIO_MEM_RESP_ACK {
+                    METRICS.memory_hotplug.plug_fails.inc();
+                }
+                response
+            }
+            VIRTIO_MEM_REQ_UNPLUG => {
+                METRICS.memory_hotplug.unplug_count.inc();
+                let response = self.handle_unplug(mem, request);
+                if response.resp_type != VIRTIO_MEM_RESP_ACK {
+                    METRICS.memory_hotplug.unplug_fails.inc();
+                }
+                response
+            }
+            VIRTIO_MEM_REQ_UNPLUG_ALL => {
+                METRICS.memory_hotplug.unplug_count.inc();
+                self.handle_unplug_all(mem)
+            }
+            VIRTIO_MEM_REQ_STATE => self.handle_state(request),
+            _ => Response::new(VIRTIO_MEM_RESP_ERROR),
+        }
+    }
+
+    // Parses a request chain, returning the request and the address of the response buffer.
+    fn parse_request(
+        mem: &GuestMemoryMmap,
+        head: &DescriptorChain,
+    ) -> Result<(Request, GuestAddress), VirtioMemError> {
+        if head.is_write_only() || (head.len as usize) < mem::size_of::<Request>() {
+            return Err(VirtioMemError::MalformedDescriptor);
+        }
+        let request = mem.read_obj::<Request>(head.addr)?;
+
+        let resp_desc = head
+            .next_descriptor()
+            .ok_or(VirtioMemError::MalformedDescriptor)?;
+        if !resp_desc.is_write_only() || (resp_desc.len as usize) < mem::size_of::<Response>() {
+            return Err(VirtioMemError::MalformedDescriptor);
+        }
+
+        Ok((request, resp_desc.addr))
+    }
+
+    pub(crate) fn process_mem_queue_event(&mut self) -> Result<(), VirtioMemError> {
+        self.queue_evts[MEM_QUEUE].read()?;
+        self.process_mem_queue()
+    }
+
+    pub(crate) fn process_mem_queue(&mut self) -> Result<(), VirtioMemError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        // The memory is cloned (which only clones the region handles) so that requests can
+        // update the device state while the queue is borrowed.
+        let mem = self.device_state.mem().unwrap().clone();
+        METRICS.memory_hotplug.queue_event_count.inc();
+
+        let mut used_any = false;
+        while let Some(head) = self.queues[MEM_QUEUE].pop(&mem) {
+            let index = head.index;
+
+            let len = match Self::parse_request(&mem, &head) {
+                Ok((request, resp_addr)) => {
+                    let response = self.handle_request(&mem, &request);
+                    mem.write_obj(response, resp_addr)?;
+                    // Unwrapping is safe since the response is only a few bytes long.
+                    u32::try_from(mem::size_of::<Response>()).unwrap()
+                }
+                Err(err) => {
+                    error!("virtio-mem: Failed to parse request: {:?}", err);
+                    METRICS.memory_hotplug.event_fails.inc();
+                    0
+                }
+            };
+
+            self.queues[MEM_QUEUE].add_used(&mem, index, len)?;
+            used_any = true;
+        }
+
+        if used_any {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<(), VirtioMemError> {
+        self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|err| {
+            METRICS.memory_hotplug.event_fails.inc();
+            VirtioMemError::Interrupt(err)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_mem_queue();
+    }
+
+    pub(crate) fn set_irq_status(&mut self, status: usize) {
+        self.irq_trigger.irq_status = Arc::new(AtomicUsize::new(status));
+    }
+
+    pub(crate) fn activate_evt(&self) -> &EventFd {
+        &self.activate_evt
+    }
+}
+
+impl VirtioDevice for VirtioMem {
+    fn device_type(&self) -> u32 {
+        TYPE_MEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The virtio-mem configuration space is read-only for the driver.
+        error!("virtio-mem: Guest attempted to write the read-only config space");
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        self.device_state = DeviceState::Activated(mem);
+        if self.activate_evt.write(1).is_err() {
+            error!("virtio-mem: Cannot write to activate_evt");
+            METRICS.memory_hotplug.activate_fails.inc();
+            self.device_state = DeviceState::Inactive;
+            return Err(ActivateError::BadActivate);
+        }
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::vm_memory::test_utils::create_anon_guest_memory;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::devices::virtio::test_utils::VirtQueue;
+    use crate::devices::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REGION_ADDR: u64 = 0x10_0000;
+    const BLOCK_SIZE: u64 = 0x1000;
+    const REGION_SIZE: u64 = 4 * BLOCK_SIZE;
+    const REQUEST_ADDR: u64 = 0x1000;
+    const RESPONSE_ADDR: u64 = 0x2000;
+
+    fn test_mem() -> GuestMemoryMmap {
+        create_anon_guest_memory(
+            &[
+                (GuestAddress(0), 0x10000),
+                (GuestAddress(REGION_ADDR), REGION_SIZE as usize),
+            ],
+            false,
+        )
+        .unwrap()
+    }
+
+    fn activated_device<'a>(mem: &'a GuestMemoryMmap) -> (VirtioMem, VirtQueue<'a>) {
+        let mut dev =
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, BLOCK_SIZE, false).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), mem, 16);
+        dev.queues[MEM_QUEUE] = vq.create_queue();
+        dev.activate(mem.clone()).unwrap();
+        (dev, vq)
+    }
+
+    // Places a request on the queue, processes it and returns the device response.
+    fn send_request(
+        dev: &mut VirtioMem,
+        vq: &VirtQueue,
+        idx: u16,
+        req_type: u16,
+        addr: u64,
+        nb_blocks: u16,
+    ) -> Response {
+        let mem = vq.memory();
+        let request = Request {
+            req_type,
+            addr,
+            nb_blocks,
+            ..Default::default()
+        };
+        mem.write_obj(request, GuestAddress(REQUEST_ADDR)).unwrap();
+        vq.dtable[0].set(
+            REQUEST_ADDR,
+            mem::size_of::<Request>() as u32,
+            VIRTQ_DESC_F_NEXT,
+            1,
+        );
+        vq.dtable[1].set(
+            RESPONSE_ADDR,
+            mem::size_of::<Response>() as u32,
+            VIRTQ_DESC_F_WRITE,
+            0,
+        );
+        vq.avail.ring[usize::from(idx)].set(0);
+        vq.avail.idx.set(idx + 1);
+
+        dev.process_mem_queue().unwrap();
+        vq.check_used_elem(idx, 0, mem::size_of::<Response>() as u32);
+        mem.read_obj(GuestAddress(RESPONSE_ADDR)).unwrap()
+    }
+
+    #[test]
+    fn test_struct_sizes() {
+        assert_eq!(mem::size_of::<ConfigSpace>(), 56);
+        assert_eq!(mem::size_of::<Request>(), 24);
+        assert_eq!(mem::size_of::<Response>(), 10);
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, 0x1800, false),
+            Err(VirtioMemError::InvalidBlockSize(0x1800))
+        ));
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, 0x800, false),
+            Err(VirtioMemError::InvalidBlockSize(0x800))
+        ));
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), 0, BLOCK_SIZE, false),
+            Err(VirtioMemError::InvalidRegion)
+        ));
+        assert!(matches!(
+            VirtioMem::new(
+                GuestAddress(REGION_ADDR + 1),
+                REGION_SIZE,
+                BLOCK_SIZE,
+                false
+            ),
+            Err(VirtioMemError::InvalidRegion)
+        ));
+
+        let dev =
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, BLOCK_SIZE, false).unwrap();
+        assert_eq!(dev.device_type(), TYPE_MEM);
+        assert_eq!(dev.id(), MEM_DEV_ID);
+        assert_eq!(dev.avail_features(), 1 << VIRTIO_F_VERSION_1);
+        assert_eq!(dev.plugged_blocks.len(), 4);
+        assert!(!dev.is_activated());
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut dev =
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, BLOCK_SIZE, false).unwrap();
+        dev.update_requested_size(2 * BLOCK_SIZE).unwrap();
+
+        let mut config = ConfigSpace::default();
+        dev.read_config(0, config.as_mut_slice());
+        assert_eq!(config.block_size, BLOCK_SIZE);
+        assert_eq!(config.addr, REGION_ADDR);
+        assert_eq!(config.region_size, REGION_SIZE);
+        assert_eq!(config.usable_region_size, REGION_SIZE);
+        assert_eq!(config.plugged_size, 0);
+        assert_eq!(config.requested_size, 2 * BLOCK_SIZE);
+
+        // Reads past the end of the config space are ignored.
+        let mut data = [0xffu8; 8];
+        dev.read_config(56, &mut data);
+        assert_eq!(data, [0xffu8; 8]);
+
+        // The config space is read-only for the driver.
+        dev.write_config(48, &[0u8; 8]);
+        assert_eq!(dev.requested_size(), 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_update_requested_size() {
+        let mem = test_mem();
+        let (mut dev, _vq) = activated_device(&mem);
+
+        assert!(matches!(
+            dev.update_requested_size(BLOCK_SIZE + 1),
+            Err(VirtioMemError::InvalidRequestedSize(_))
+        ));
+        assert!(matches!(
+            dev.update_requested_size(REGION_SIZE + BLOCK_SIZE),
+            Err(VirtioMemError::RequestedSizeTooLarge(_))
+        ));
+        assert!(!dev.irq_trigger.has_pending_irq(IrqType::Config));
+
+        dev.update_requested_size(REGION_SIZE).unwrap();
+        assert_eq!(dev.requested_size(), REGION_SIZE);
+        assert!(dev.irq_trigger.has_pending_irq(IrqType::Config));
+    }
+
+    #[test]
+    fn test_plug_unplug() {
+        let mem = test_mem();
+        let (mut dev, vq) = activated_device(&mem);
+        dev.update_requested_size(2 * BLOCK_SIZE).unwrap();
+
+        // Requests outside the region are rejected.
+        let resp = send_request(&mut dev, &vq, 0, VIRTIO_MEM_REQ_PLUG, 0, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        let resp = send_request(&mut dev, &vq, 1, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 5);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        // Plugging beyond the requested size is refused.
+        check_metric_after_block!(
+            METRICS.memory_hotplug.plug_fails,
+            1,
+            assert_eq!(
+                send_request(&mut dev, &vq, 2, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 3).resp_type,
+                VIRTIO_MEM_RESP_NACK
+            )
+        );
+
+        let resp = send_request(&mut dev, &vq, 3, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 2);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(dev.plugged_size(), 2 * BLOCK_SIZE);
+        assert!(dev.irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // Plugging an already plugged block is an error.
+        let resp = send_request(&mut dev, &vq, 4, VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        let resp = send_request(&mut dev, &vq, 5, VIRTIO_MEM_REQ_STATE, REGION_ADDR, 2);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_PLUGGED);
+        let resp = send_request(&mut dev, &vq, 6, VIRTIO_MEM_REQ_STATE, REGION_ADDR, 3);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_MIXED);
+        let resp = send_request(
+            &mut dev,
+            &vq,
+            7,
+            VIRTIO_MEM_REQ_STATE,
+            REGION_ADDR + 2 * BLOCK_SIZE,
+            2,
+        );
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_UNPLUGGED);
+
+        // Unplugging discards the memory backing the blocks.
+        mem.write_obj(0xffu8, GuestAddress(REGION_ADDR + BLOCK_SIZE))
+            .unwrap();
+        let resp = send_request(
+            &mut dev,
+            &vq,
+            8,
+            VIRTIO_MEM_REQ_UNPLUG,
+            REGION_ADDR + BLOCK_SIZE,
+            1,
+        );
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(dev.plugged_size(), BLOCK_SIZE);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(REGION_ADDR + BLOCK_SIZE))
+                .unwrap(),
+            0
+        );
+
+        // Unplugging blocks which are not plugged is an error.
+        let resp = send_request(&mut dev, &vq, 9, VIRTIO_MEM_REQ_UNPLUG, REGION_ADDR, 2);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        let resp = send_request(&mut dev, &vq, 10, VIRTIO_MEM_REQ_UNPLUG_ALL, 0, 0);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(dev.plugged_size(), 0);
+        assert!(dev.plugged_blocks.iter().all(|plugged| !*plugged));
+
+        // Unknown requests are rejected.
+        let resp = send_request(&mut dev, &vq, 11, 0xff, REGION_ADDR, 1);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut dev = VirtioMem::new(GuestAddress(1 << 30), 64 << 20, 2 << 20, false).unwrap();
+        dev.update_requested_size(16 << 20).unwrap();
+        dev.config_space.plugged_size = 8 << 20;
+
+        assert_eq!(
+            dev.status(),
+            VirtioMemStatus {
+                total_size_mib: 64,
+                block_size_mib: 2,
+                plugged_size_mib: 8,
+                requested_size_mib: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let mem = test_mem();
+        let (mut dev, vq) = activated_device(&mem);
+
+        // The request descriptor must not be write-only.
+        vq.dtable[0].set(
+            REQUEST_ADDR,
+            mem::size_of::<Request>() as u32,
+            VIRTQ_DESC_F_WRITE,
+            0,
+        );
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        check_metric_after_block!(
+            METRICS.memory_hotplug.event_fails,
+            1,
+            dev.process_mem_queue().unwrap()
+        );
+        vq.check_used_elem(0, 0, 0);
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a virtio-mem device, used to hot(un)plug guest memory at runtime.
+
+pub mod device;
+mod event_handler;
+pub mod persist;
+
+pub use self::device::{VirtioMem, VirtioMemError, VirtioMemStatus};
+
+/// Device ID used in MMIO device identification.
+/// Because the memory hotplug device is unique per-vm, this ID can be hardcoded.
+pub const MEM_DEV_ID: &str = "mem";
+
+pub(crate) const MEM_NUM_QUEUES: usize = 1;
+
+pub(crate) const MEM_QUEUE: usize = 0;
+
+// The request types.
+const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+const VIRTIO_MEM_REQ_STATE: u16 = 3;
+
+// The response types.
+const VIRTIO_MEM_RESP_ACK: u16 = 0;
+const VIRTIO_MEM_RESP_NACK: u16 = 1;
+const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+
+// The block states reported to a `VIRTIO_MEM_REQ_STATE` request.
+const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+const VIRTIO_MEM_STATE_MIXED: u16 = 2;
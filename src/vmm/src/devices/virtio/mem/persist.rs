@@ -0,0 +1,154 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring memory hotplug devices.
+
+use snapshot::Persist;
+use utils::vm_memory::{GuestAddress, GuestMemoryMmap};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::device::VirtioMem;
+use super::{VirtioMemError, MEM_NUM_QUEUES};
+use crate::devices::virtio::persist::PersistError as VirtioStateError;
+use crate::devices::virtio::{
+    DeviceState, VirtioDeviceState, FIRECRACKER_MAX_QUEUE_SIZE, TYPE_MEM,
+};
+
+/// Information about the memory hotplug device that are saved at snapshot.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct VirtioMemState {
+    virtio_state: VirtioDeviceState,
+    addr: u64,
+    region_size: u64,
+    block_size: u64,
+    requested_size: u64,
+    plugged_blocks: Vec<bool>,
+}
+
+/// Auxiliary structure for creating a device when resuming from a snapshot.
+#[derive(Debug)]
+pub struct VirtioMemConstructorArgs {
+    /// Pointer to guest memory.
+    pub mem: GuestMemoryMmap,
+}
+
+/// Errors triggered when trying to construct a memory hotplug device from a snapshot.
+#[derive(Debug, derive_more::From)]
+pub enum VirtioMemPersistError {
+    /// Failed to create the device.
+    CreateDevice(VirtioMemError),
+    /// The saved block map does not match the hotplug region.
+    InvalidBlockMap,
+    /// Failed to restore the virtio state.
+    VirtioState(VirtioStateError),
+}
+
+impl Persist<'_> for VirtioMem {
+    type State = VirtioMemState;
+    type ConstructorArgs = VirtioMemConstructorArgs;
+    type Error = VirtioMemPersistError;
+
+    fn save(&self) -> Self::State {
+        VirtioMemState {
+            virtio_state: VirtioDeviceState::from_device(self),
+            addr: self.addr().0,
+            region_size: self.region_size(),
+            block_size: self.block_size(),
+            requested_size: self.requested_size(),
+            plugged_blocks: self.plugged_blocks.clone(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        // We can safely create the device with `restored` set, since the guest memory has been
+        // mapped from the snapshot file.
+        let mut dev = VirtioMem::new(
+            GuestAddress(state.addr),
+            state.region_size,
+            state.block_size,
+            true,
+        )?;
+        if state.plugged_blocks.len() != dev.plugged_blocks.len() {
+            return Err(VirtioMemPersistError::InvalidBlockMap);
+        }
+
+        dev.queues = state.virtio_state.build_queues_checked(
+            &constructor_args.mem,
+            TYPE_MEM,
+            MEM_NUM_QUEUES,
+            FIRECRACKER_MAX_QUEUE_SIZE,
+        )?;
+        dev.avail_features = state.virtio_state.avail_features;
+        dev.acked_features = state.virtio_state.acked_features;
+        dev.set_irq_status(state.virtio_state.interrupt_status);
+
+        let plugged_count = state
+            .plugged_blocks
+            .iter()
+            .filter(|plugged| **plugged)
+            .count();
+        dev.plugged_blocks = state.plugged_blocks.clone();
+        dev.config_space.plugged_size = plugged_count as u64 * state.block_size;
+        dev.config_space.requested_size = state.requested_size;
+
+        if state.virtio_state.activated {
+            dev.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(dev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::test_utils::default_mem;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let mut dev = VirtioMem::new(GuestAddress(1 << 30), 64 << 20, 2 << 20, false).unwrap();
+        dev.update_requested_size(8 << 20).unwrap();
+        dev.plugged_blocks[1] = true;
+        dev.plugged_blocks[2] = true;
+
+        // Create and save the device state.
+        <VirtioMem as Persist>::save(&dev)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the device state.
+        let restored = VirtioMem::restore(
+            VirtioMemConstructorArgs { mem: guest_mem },
+            &VirtioMemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.device_type(), TYPE_MEM);
+        assert!(restored.restored);
+        assert_eq!(restored.acked_features(), dev.acked_features());
+        assert_eq!(restored.avail_features(), dev.avail_features());
+        assert_eq!(restored.config_space.addr, dev.config_space.addr);
+        assert_eq!(restored.region_size(), dev.region_size());
+        assert_eq!(restored.block_size(), dev.block_size());
+        assert_eq!(restored.requested_size(), 8 << 20);
+        assert_eq!(restored.plugged_size(), 4 << 20);
+        assert_eq!(restored.plugged_blocks, dev.plugged_blocks);
+        assert_eq!(
+            restored.interrupt_status().load(Ordering::Relaxed),
+            dev.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored.is_activated(), dev.is_activated());
+    }
+}
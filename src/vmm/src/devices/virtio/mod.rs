@@ -14,10 +14,13 @@ pub mod balloon;
 pub mod block;
 pub mod device;
 mod iovec;
+pub mod mem;
 mod mmio;
 pub mod net;
+pub mod p9;
 pub mod persist;
 mod queue;
+pub mod record;
 pub mod rng;
 pub mod test_utils;
 pub mod vsock;
@@ -25,8 +28,10 @@ pub mod vsock;
 pub use self::balloon::*;
 pub use self::block::*;
 pub use self::device::*;
+pub use self::mem::*;
 pub use self::mmio::*;
 pub use self::net::*;
+pub use self::p9::*;
 pub use self::persist::*;
 pub use self::queue::*;
 pub use self::rng::*;
@@ -59,6 +64,10 @@ pub const TYPE_BLOCK: u32 = 2;
 pub const TYPE_RNG: u32 = 4;
 /// Virtio balloon device ID.
 pub const TYPE_BALLOON: u32 = 5;
+/// Virtio 9p device ID.
+pub const TYPE_9P: u32 = 9;
+/// Virtio memory device ID.
+pub const TYPE_MEM: u32 = 24;
 
 /// Offset from the base MMIO address of a virtio device used by the guest to notify the device of
 /// queue events.
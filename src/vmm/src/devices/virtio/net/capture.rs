@@ -0,0 +1,270 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mirroring of the frames exchanged by a network device, to debug the guest connectivity
+//! without capturing on the host tap.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+
+use log::warn;
+use logger::{IncMetric, METRICS};
+use utils::time::{get_time_us, ClockType};
+
+use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::net::MAX_BUFFER_SIZE;
+use crate::vmm_config::net::PacketCaptureConfig;
+
+// Magic number of the pcap files with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+// Link type of the Ethernet frames.
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// Errors associated with the packet capture of a network device.
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    /// Both a pcap file and a socket are configured.
+    #[error("Either `pcap_path` or `socket_path` exclusively is allowed.")]
+    ConflictingOutputs,
+    /// Cannot create the pcap file.
+    #[error("Cannot create the pcap file: {0}")]
+    CreatePcap(io::Error),
+    /// Cannot connect to the capture socket.
+    #[error("Cannot connect to the capture socket: {0}")]
+    ConnectSocket(io::Error),
+}
+
+#[derive(Debug)]
+enum CaptureOutput {
+    // Frames are appended to a pcap file.
+    Pcap(File),
+    // Frames are sent to a Unix datagram socket, one per datagram.
+    Socket(UnixDatagram),
+}
+
+/// Mirrors the frames of a network device to a pcap file or a Unix datagram socket.
+#[derive(Debug)]
+pub struct PacketCapture {
+    config: PacketCaptureConfig,
+    output: CaptureOutput,
+    // Number of bytes written to the output.
+    size: u64,
+    // Set once the size limit is reached, after which no frame is captured anymore.
+    full: bool,
+    buf: Vec<u8>,
+}
+
+impl PacketCapture {
+    /// Opens the output of `config`. Returns `None` if it has no output, which disables the
+    /// capture.
+    pub fn new(config: PacketCaptureConfig) -> Result<Option<Self>, CaptureError> {
+        let output = match (&config.pcap_path, &config.socket_path) {
+            (Some(_), Some(_)) => return Err(CaptureError::ConflictingOutputs),
+            (None, None) => return Ok(None),
+            (Some(path), None) => {
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .map_err(CaptureError::CreatePcap)?;
+                file.write_all(&pcap_header())
+                    .map_err(CaptureError::CreatePcap)?;
+                CaptureOutput::Pcap(file)
+            }
+            (None, Some(path)) => {
+                let socket = UnixDatagram::unbound()
+                    .and_then(|socket| socket.connect(path).map(|()| socket))
+                    .and_then(|socket| socket.set_nonblocking(true).map(|()| socket))
+                    .map_err(CaptureError::ConnectSocket)?;
+                CaptureOutput::Socket(socket)
+            }
+        };
+        let size = match output {
+            CaptureOutput::Pcap(_) => PCAP_HEADER_LEN as u64,
+            CaptureOutput::Socket(_) => 0,
+        };
+
+        Ok(Some(PacketCapture {
+            config,
+            output,
+            size,
+            full: false,
+            buf: Vec::new(),
+        }))
+    }
+
+    /// Returns the configuration the capture was opened with.
+    pub fn config(&self) -> &PacketCaptureConfig {
+        &self.config
+    }
+
+    /// Mirrors a frame, stripped of its VNET header.
+    pub fn record(&mut self, frame: &[u8]) {
+        self.record_with(frame.len(), |buf| buf.copy_from_slice(frame));
+    }
+
+    /// Mirrors the frame held by `iovec`, starting at `offset` to skip its VNET header.
+    pub fn record_iovec(&mut self, iovec: &IoVecBuffer, offset: usize) {
+        let len = iovec.len().saturating_sub(offset);
+        self.record_with(len, |buf| {
+            iovec.read_at(buf, offset);
+        });
+    }
+
+    fn record_with<F: FnOnce(&mut [u8])>(&mut self, len: usize, fill: F) {
+        if self.full {
+            return;
+        }
+
+        let header_len = match self.output {
+            CaptureOutput::Pcap(_) => PCAP_RECORD_HEADER_LEN,
+            CaptureOutput::Socket(_) => 0,
+        };
+        let record_len = (header_len + len) as u64;
+        if let Some(max_size) = self.config.max_size_bytes {
+            if self.size + record_len > max_size {
+                warn!(
+                    "The packet capture reached its size limit of {} bytes and is stopped.",
+                    max_size
+                );
+                self.full = true;
+                return;
+            }
+        }
+
+        self.buf.clear();
+        self.buf.resize(header_len + len, 0);
+        if header_len > 0 {
+            self.buf[..header_len].copy_from_slice(&pcap_record_header(len));
+        }
+        fill(&mut self.buf[header_len..]);
+
+        let result = match &mut self.output {
+            CaptureOutput::Pcap(file) => file.write_all(&self.buf),
+            CaptureOutput::Socket(socket) => socket.send(&self.buf).map(|_| ()),
+        };
+        match result {
+            Ok(()) => {
+                self.size += record_len;
+                METRICS.net.capture_frames.inc();
+            }
+            // The frames the reader is too slow to receive are dropped.
+            Err(_) => METRICS.net.capture_fails.inc(),
+        }
+    }
+}
+
+fn pcap_header() -> [u8; PCAP_HEADER_LEN] {
+    let mut header = [0u8; PCAP_HEADER_LEN];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_ne_bytes());
+    // Version 2.4.
+    header[4..6].copy_from_slice(&2u16.to_ne_bytes());
+    header[6..8].copy_from_slice(&4u16.to_ne_bytes());
+    // The timestamps are in UTC and the time zone and accuracy fields are left to 0.
+    header[16..20].copy_from_slice(&(MAX_BUFFER_SIZE as u32).to_ne_bytes());
+    header[20..24].copy_from_slice(&PCAP_LINKTYPE_ETHERNET.to_ne_bytes());
+    header
+}
+
+fn pcap_record_header(len: usize) -> [u8; PCAP_RECORD_HEADER_LEN] {
+    let now_us = get_time_us(ClockType::Real);
+    let mut header = [0u8; PCAP_RECORD_HEADER_LEN];
+    header[0..4].copy_from_slice(&((now_us / 1_000_000) as u32).to_ne_bytes());
+    header[4..8].copy_from_slice(&((now_us % 1_000_000) as u32).to_ne_bytes());
+    // The frames are captured whole.
+    header[8..12].copy_from_slice(&(len as u32).to_ne_bytes());
+    header[12..16].copy_from_slice(&(len as u32).to_ne_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn pcap_config(path: &str, max_size_bytes: Option<u64>) -> PacketCaptureConfig {
+        PacketCaptureConfig {
+            pcap_path: Some(path.to_string()),
+            socket_path: None,
+            max_size_bytes,
+        }
+    }
+
+    #[test]
+    fn test_capture_config() {
+        assert!(PacketCapture::new(PacketCaptureConfig::default())
+            .unwrap()
+            .is_none());
+
+        let config = PacketCaptureConfig {
+            pcap_path: Some("foo.pcap".to_string()),
+            socket_path: Some("foo.sock".to_string()),
+            max_size_bytes: None,
+        };
+        assert!(matches!(
+            PacketCapture::new(config),
+            Err(CaptureError::ConflictingOutputs)
+        ));
+
+        let config = pcap_config("/invalid/path/foo.pcap", None);
+        assert!(matches!(
+            PacketCapture::new(config),
+            Err(CaptureError::CreatePcap(_))
+        ));
+    }
+
+    #[test]
+    fn test_capture_pcap() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        // Room for the header and two 10 bytes frames.
+        let max_size = (PCAP_HEADER_LEN + 2 * (PCAP_RECORD_HEADER_LEN + 10)) as u64;
+        let mut capture = PacketCapture::new(pcap_config(path, Some(max_size)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(capture.config(), &pcap_config(path, Some(max_size)));
+
+        capture.record(&[1u8; 10]);
+        capture.record(&[2u8; 10]);
+        // Over the size limit.
+        capture.record(&[3u8; 1]);
+        assert!(capture.full);
+
+        let content = fs::read(path).unwrap();
+        assert_eq!(content.len() as u64, max_size);
+        assert_eq!(content[..PCAP_HEADER_LEN], pcap_header());
+        let record = &content[PCAP_HEADER_LEN..PCAP_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 10];
+        assert_eq!(record[8..12], 10u32.to_ne_bytes());
+        assert_eq!(record[12..16], 10u32.to_ne_bytes());
+        assert_eq!(record[PCAP_RECORD_HEADER_LEN..], [1u8; 10]);
+        assert_eq!(content[content.len() - 10..], [2u8; 10]);
+    }
+
+    #[test]
+    fn test_capture_socket() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap().to_string();
+        fs::remove_file(&path).unwrap();
+        let reader = UnixDatagram::bind(&path).unwrap();
+
+        let config = PacketCaptureConfig {
+            pcap_path: None,
+            socket_path: Some(path.clone()),
+            max_size_bytes: None,
+        };
+        let mut capture = PacketCapture::new(config).unwrap().unwrap();
+        capture.record(&[1u8, 2, 3]);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.recv(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [1u8, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -9,13 +9,14 @@ use std::io::{Read, Write};
 use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{cmp, mem};
 
 use dumbo::pdu::arp::ETH_IPV4_FRAME_LEN;
 use dumbo::pdu::ethernet::{EthernetFrame, PAYLOAD_OFFSET};
 use libc::EAGAIN;
 use log::{error, warn};
-use logger::{IncMetric, METRICS};
+use logger::{IncMetric, NetInterfaceMetrics, METRICS};
 use mmds::data_store::Mmds;
 use mmds::ns::MmdsNetworkStack;
 use utils::eventfd::EventFd;
@@ -33,7 +34,11 @@ use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenType};
 const FRAME_HEADER_MAX_LEN: usize = PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN;
 
 use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::net::capture::PacketCapture;
+use crate::devices::virtio::net::dhcp::DhcpResponder;
+use crate::devices::virtio::net::pause_responder::PauseResponder;
 use crate::devices::virtio::net::tap::Tap;
+use crate::devices::virtio::net::usernet::UserNet;
 use crate::devices::virtio::net::{
     NetError, NetQueue, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
 };
@@ -41,6 +46,7 @@ use crate::devices::virtio::{
     ActivateError, DescriptorChain, DeviceState, IrqTrigger, IrqType, Queue, VirtioDevice, TYPE_NET,
 };
 use crate::devices::{report_net_event_fail, DeviceError};
+use crate::vmm_config::net::UserNetConfig;
 
 #[derive(Debug)]
 enum FrontendError {
@@ -102,7 +108,7 @@ unsafe impl ByteValued for ConfigSpace {}
 pub struct Net {
     pub(crate) id: String,
 
-    /// The backend for this device: a tap.
+    /// The backend for this device: a tap, or a socket of the user-mode network stack.
     pub tap: Tap,
 
     pub(crate) avail_features: u64,
@@ -129,11 +135,36 @@ pub struct Net {
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
 
+    is_paused: bool,
+
     /// The MMDS stack corresponding to this interface.
     /// Only if MMDS transport has been associated with it.
     pub mmds_ns: Option<MmdsNetworkStack>,
+
+    /// The mirroring of the frames exchanged with the guest, if enabled.
+    capture: Option<PacketCapture>,
+
+    /// The DHCP server and DNS stub answering the guest, if enabled.
+    dhcp: Option<DhcpResponder>,
+
+    /// The user-mode network stack serving this device in place of a tap, if any.
+    user_net: Option<Arc<Mutex<UserNet>>>,
+
+    /// The replies sent on behalf of the guest while it is paused, if enabled.
+    pause_responder: Option<PauseResponder>,
+    /// Whether the vCPUs are paused while the device keeps serving the tap.
+    pause_lite: bool,
+    /// The time the TX queue is busy-polled for new frames.
+    busy_poll: Duration,
+    /// The number of used descriptor chains of a queue after which the guest is notified.
+    batch_size: u16,
+    /// The counters of this interface.
+    pub(crate) metrics: &'static NetInterfaceMetrics,
 }
 
+// The counters of the interfaces registered once all the slots of the metrics are taken.
+static UNREPORTED_METRICS: NetInterfaceMetrics = NetInterfaceMetrics::new();
+
 impl Net {
     /// Create a new virtio network device with the given TAP interface.
     pub fn new_with_tap(
@@ -167,6 +198,11 @@ impl Net {
             queues.push(Queue::new(size));
         }
 
+        let metrics = METRICS.net_ifaces.register(&id).unwrap_or_else(|| {
+            warn!("The counters of network interface {} are not reported", id);
+            &UNREPORTED_METRICS
+        });
+
         Ok(Net {
             id,
             tap,
@@ -185,7 +221,16 @@ impl Net {
             guest_mac,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(NetError::EventFd)?,
+            is_paused: false,
             mmds_ns: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            pause_lite: false,
+            busy_poll: Duration::ZERO,
+            batch_size: 0,
+            metrics,
         })
     }
 
@@ -212,6 +257,20 @@ impl Net {
         Self::new_with_tap(id, tap, guest_mac, rx_rate_limiter, tx_rate_limiter)
     }
 
+    /// Create a new virtio network device served by the user-mode network stack.
+    pub fn new_with_user_net(
+        id: String,
+        config: UserNetConfig,
+        guest_mac: Option<MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self, NetError> {
+        let (user_net, tap) = UserNet::new(config, guest_mac).map_err(NetError::UserNet)?;
+        let mut net = Self::new_with_tap(id, tap, guest_mac, rx_rate_limiter, tx_rate_limiter)?;
+        net.user_net = Some(Arc::new(Mutex::new(user_net)));
+        Ok(net)
+    }
+
     /// Provides the ID of this net device.
     pub fn id(&self) -> &String {
         &self.id
@@ -257,6 +316,65 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Provides the packet capture of this net device, if enabled.
+    pub fn capture(&self) -> Option<&PacketCapture> {
+        self.capture.as_ref()
+    }
+
+    /// Replaces the packet capture of this net device. `None` stops the capture.
+    pub fn set_capture(&mut self, capture: Option<PacketCapture>) {
+        self.capture = capture;
+    }
+
+    /// Provides the DHCP responder of this net device, if enabled.
+    pub fn dhcp(&self) -> Option<&DhcpResponder> {
+        self.dhcp.as_ref()
+    }
+
+    /// Replaces the DHCP responder of this net device. `None` disables it.
+    pub fn set_dhcp(&mut self, dhcp: Option<DhcpResponder>) {
+        self.dhcp = dhcp;
+    }
+
+    /// Provides the user-mode network stack serving this net device, if any. It must be
+    /// registered with the event manager along with the device.
+    pub fn user_net(&self) -> Option<&Arc<Mutex<UserNet>>> {
+        self.user_net.as_ref()
+    }
+
+    /// Provides the pause responder of this net device, if enabled.
+    pub fn pause_responder(&self) -> Option<&PauseResponder> {
+        self.pause_responder.as_ref()
+    }
+
+    /// Replaces the pause responder of this net device. `None` disables it.
+    pub fn set_pause_responder(&mut self, pause_responder: Option<PauseResponder>) {
+        self.pause_responder = pause_responder;
+    }
+
+    /// Provides the time the TX queue is busy-polled for new frames.
+    pub fn busy_poll(&self) -> Duration {
+        self.busy_poll
+    }
+
+    /// Sets the time the TX queue is busy-polled for new frames, before waiting for the driver
+    /// to notify the device. A zero duration disables the polling.
+    pub fn set_busy_poll(&mut self, busy_poll: Duration) {
+        self.busy_poll = busy_poll;
+    }
+
+    /// Provides the number of used descriptor chains after which the guest is notified.
+    pub fn batch_size(&self) -> u16 {
+        self.batch_size
+    }
+
+    /// Sets the number of used descriptor chains of a queue after which the guest is notified,
+    /// even if more frames are being processed. 0 only notifies the guest once all the frames at
+    /// hand are processed.
+    pub fn set_batch_size(&mut self, batch_size: u16) {
+        self.batch_size = batch_size;
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -266,18 +384,54 @@ impl Net {
             NetQueue::Tx => &mut self.queues[TX_INDEX],
         };
 
+        Self::notify_guest(queue, queue_type, mem, &self.irq_trigger)
+    }
+
+    // Signals the used descriptor chains of `queue` to the guest, unless it suppressed the
+    // notification.
+    fn notify_guest(
+        queue: &mut Queue,
+        queue_type: NetQueue,
+        mem: &GuestMemoryMmap,
+        irq_trigger: &IrqTrigger,
+    ) -> Result<(), DeviceError> {
+        let (interrupt_count, suppressed_interrupt_count) = match queue_type {
+            NetQueue::Rx => (
+                &METRICS.net.rx_interrupt_count,
+                &METRICS.net.rx_suppressed_interrupt_count,
+            ),
+            NetQueue::Tx => (
+                &METRICS.net.tx_interrupt_count,
+                &METRICS.net.tx_suppressed_interrupt_count,
+            ),
+        };
+        let num_added = queue.num_added();
+
         if queue.prepare_kick(mem) {
-            self.irq_trigger
-                .trigger_irq(IrqType::Vring)
-                .map_err(|err| {
-                    METRICS.net.event_fails.inc();
-                    DeviceError::FailedSignalingIrq(err)
-                })?;
+            interrupt_count.inc();
+            irq_trigger.trigger_irq(IrqType::Vring).map_err(|err| {
+                METRICS.net.event_fails.inc();
+                DeviceError::FailedSignalingIrq(err)
+            })?;
+        } else if num_added != 0 {
+            suppressed_interrupt_count.inc();
         }
 
         Ok(())
     }
 
+    // Notifies the guest once a full batch of descriptor chains of the queue is used.
+    fn signal_full_batch(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
+        let index = match queue_type {
+            NetQueue::Rx => RX_INDEX,
+            NetQueue::Tx => TX_INDEX,
+        };
+        if self.batch_size != 0 && self.queues[index].num_added() >= self.batch_size {
+            self.signal_used_queue(queue_type)?;
+        }
+        Ok(())
+    }
+
     // Helper function to consume one op with `size` bytes from a rate limiter
     fn rate_limiter_consume_op(rate_limiter: &mut RateLimiter, size: u64) -> bool {
         if !rate_limiter.consume(1, TokenType::Ops) {
@@ -304,6 +458,7 @@ impl Net {
     fn rate_limited_rx_single_frame(&mut self) -> bool {
         if !Self::rate_limiter_consume_op(&mut self.rx_rate_limiter, self.rx_bytes_read as u64) {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            self.metrics.rx_rate_limiter_throttled.inc();
             return false;
         }
 
@@ -329,6 +484,7 @@ impl Net {
         mem: &GuestMemoryMmap,
         data: &[u8],
         head: DescriptorChain,
+        metrics: &NetInterfaceMetrics,
     ) -> Result<(), FrontendError> {
         let mut chunk = data;
         let mut next_descriptor = Some(head);
@@ -357,6 +513,8 @@ impl Net {
             if chunk.is_empty() {
                 METRICS.net.rx_bytes_count.add(data.len());
                 METRICS.net.rx_packets_count.inc();
+                metrics.rx_bytes.add(data.len());
+                metrics.rx_packets.inc();
                 return Ok(());
             }
 
@@ -364,6 +522,7 @@ impl Net {
         }
 
         warn!("Receiving buffer is too small to hold frame of current size");
+        metrics.rx_oversized_frames.inc();
         Err(FrontendError::DescriptorChainTooSmall)
     }
 
@@ -373,8 +532,10 @@ impl Net {
         let mem = self.device_state.mem().unwrap();
 
         let queue = &mut self.queues[RX_INDEX];
+        let metrics = self.metrics;
         let head_descriptor = queue.pop_or_enable_notification(mem).ok_or_else(|| {
             METRICS.net.no_rx_avail_buffer.inc();
+            metrics.rx_ring_full.inc();
             FrontendError::EmptyQueue
         })?;
         let head_index = head_descriptor.index;
@@ -383,6 +544,7 @@ impl Net {
             mem,
             &self.rx_frame_buf[..self.rx_bytes_read],
             head_descriptor,
+            metrics,
         );
         // Mark the descriptor chain as used. If an error occurred, skip the descriptor chain.
         let used_len = if result.is_err() {
@@ -419,16 +581,20 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS, then to the DHCP responder, and if neither accepts it,
+    // sends it on the host TAP.
     //
-    // Returns whether MMDS consumed the frame.
+    // Returns whether MMDS or the DHCP responder consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
+        dhcp: Option<&mut DhcpResponder>,
         rate_limiter: &mut RateLimiter,
         headers: &mut [u8],
         frame_iovec: &IoVecBuffer,
         tap: &mut Tap,
         guest_mac: Option<MacAddr>,
+        metrics: &NetInterfaceMetrics,
     ) -> Result<bool, NetError> {
         // Read the frame headers from the IoVecBuffer. This will return None
         // if the frame_iovec is empty.
@@ -461,6 +627,20 @@ impl Net {
             }
         }
 
+        if let Some(dhcp) = dhcp {
+            if dhcp.is_responder_frame(headers) {
+                let mut frame = vec![0u8; frame_iovec.len() - vnet_hdr_len()];
+                // Ok to unwrap here, because we are passing a buffer that has the exact size
+                // of the `IoVecBuffer` minus the VNET headers.
+                frame_iovec.read_at(&mut frame, vnet_hdr_len()).unwrap();
+                dhcp.detour_frame(&frame);
+
+                // Like MMDS frames, the requests to the responder are not rate limited.
+                Self::rate_limiter_replenish_op(rate_limiter, frame_iovec.len() as u64);
+                return Ok(true);
+            }
+        }
+
         // This frame goes to the TAP.
 
         // Check for guest MAC spoofing.
@@ -477,16 +657,20 @@ impl Net {
                 METRICS.net.tx_bytes_count.add(frame_iovec.len());
                 METRICS.net.tx_packets_count.inc();
                 METRICS.net.tx_count.inc();
+                metrics.tx_bytes.add(frame_iovec.len());
+                metrics.tx_packets.inc();
             }
             Err(err) => {
                 error!("Failed to write to tap: {:?}", err);
                 METRICS.net.tap_write_fails.inc();
+                metrics.tap_write_fails.inc();
             }
         };
         Ok(false)
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
+    // We currently prioritize packets from the MMDS, then from the DHCP responder, over regular
+    // network packets.
     fn read_from_mmds_or_tap(&mut self) -> Result<usize, NetError> {
         if let Some(ns) = self.mmds_ns.as_mut() {
             if let Some(len) =
@@ -500,9 +684,47 @@ impl Net {
             }
         }
 
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            if let Some(len) =
+                dhcp.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
+
         self.read_tap().map_err(NetError::IO)
     }
 
+    // Answers the frame read in `self.rx_frame_buf` on behalf of the paused guest, if the pause
+    // responder handles it.
+    //
+    // Returns whether the frame was answered.
+    fn reply_while_paused(&mut self) -> bool {
+        let (Some(responder), Some(guest_mac)) = (self.pause_responder.as_ref(), self.guest_mac)
+        else {
+            return false;
+        };
+        let Ok(frame) = frame_bytes_from_buf(&self.rx_frame_buf[..self.rx_bytes_read]) else {
+            return false;
+        };
+        // The replies are never longer than the requests.
+        let mut reply = vec![0u8; self.rx_bytes_read];
+        let Some(len) = responder.reply(guest_mac, frame, &mut reply[vnet_hdr_len()..]) else {
+            return false;
+        };
+
+        init_vnet_hdr(&mut reply);
+        match self.tap.write(&reply[..vnet_hdr_len() + len.get()]) {
+            Ok(_) => METRICS.net.pause_replies.inc(),
+            Err(err) => {
+                error!("Failed to write to tap: {:?}", err);
+                METRICS.net.tap_write_fails.inc();
+            }
+        }
+        true
+    }
+
     fn process_rx(&mut self) -> Result<(), DeviceError> {
         // Read as many frames as possible.
         loop {
@@ -510,16 +732,30 @@ impl Net {
                 Ok(count) => {
                     self.rx_bytes_read = count;
                     METRICS.net.rx_count.inc();
+                    if self.pause_lite && self.reply_while_paused() {
+                        continue;
+                    }
+                    if let Some(capture) = self.capture.as_mut() {
+                        capture.record(&self.rx_frame_buf[vnet_hdr_len().min(count)..count]);
+                    }
                     if !self.rate_limited_rx_single_frame() {
+                        // The paused guest cannot make room for the frame, which is dropped so
+                        // that the tap keeps being drained.
+                        if self.pause_lite {
+                            METRICS.net.pause_drops.inc();
+                            self.count_rx_drop();
+                            continue;
+                        }
                         self.rx_deferred_frame = true;
                         break;
                     }
+                    self.signal_full_batch(NetQueue::Rx)?;
                 }
                 Err(NetError::IO(err)) => {
                     // The tap device is non-blocking, so any error aside from EAGAIN is
                     // unexpected.
                     match err.raw_os_error() {
-                        Some(err) if err == EAGAIN => (),
+                        Some(err) if err == EAGAIN => self.metrics.tap_read_eagain.inc(),
                         _ => {
                             error!("Failed to read tap: {:?}", err);
                             METRICS.net.tap_read_fails.inc();
@@ -560,62 +796,92 @@ impl Net {
     }
 
     fn process_tx(&mut self) -> Result<(), DeviceError> {
-        // This is safe since we checked in the event handler that the device is activated.
-        let mem = self.device_state.mem().unwrap();
-
         // The MMDS network stack works like a state machine, based on synchronous calls, and
         // without being added to any event loop. If any frame is accepted by the MMDS, we also
         // trigger a process_rx() which checks if there are any new frames to be sent, starting
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
         let mut used_any = false;
-        let tx_queue = &mut self.queues[TX_INDEX];
 
-        while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
-            let head_index = head.index;
-            // Parse IoVecBuffer from descriptor head
-            let buffer = match IoVecBuffer::from_descriptor_chain(mem, head) {
-                Ok(buffer) => buffer,
-                Err(_) => {
-                    METRICS.net.tx_fails.inc();
-                    tx_queue
-                        .add_used(mem, head_index, 0)
-                        .map_err(DeviceError::QueueError)?;
-                    continue;
+        loop {
+            // This is safe since we checked in the event handler that the device is activated.
+            let mem = self.device_state.mem().unwrap();
+            let tx_queue = &mut self.queues[TX_INDEX];
+
+            while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
+                let head_index = head.index;
+                // Parse IoVecBuffer from descriptor head
+                let buffer = match IoVecBuffer::from_descriptor_chain(mem, head) {
+                    Ok(buffer) => buffer,
+                    Err(_) => {
+                        METRICS.net.tx_fails.inc();
+                        tx_queue
+                            .add_used(mem, head_index, 0)
+                            .map_err(DeviceError::QueueError)?;
+                        continue;
+                    }
+                };
+                if !Self::rate_limiter_consume_op(&mut self.tx_rate_limiter, buffer.len() as u64) {
+                    tx_queue.undo_pop();
+                    METRICS.net.tx_rate_limiter_throttled.inc();
+                    self.metrics.tx_rate_limiter_throttled.inc();
+                    break;
+                }
+                if buffer.len() > MAX_BUFFER_SIZE {
+                    self.metrics.tx_oversized_frames.inc();
                 }
-            };
-            if !Self::rate_limiter_consume_op(&mut self.tx_rate_limiter, buffer.len() as u64) {
-                tx_queue.undo_pop();
-                METRICS.net.tx_rate_limiter_throttled.inc();
-                break;
-            }
 
-            let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
-                self.mmds_ns.as_mut(),
-                &mut self.tx_rate_limiter,
-                &mut self.tx_frame_headers,
-                &buffer,
-                &mut self.tap,
-                self.guest_mac,
-            )
-            .unwrap_or(false);
-            if frame_consumed_by_mmds && !self.rx_deferred_frame {
-                // MMDS consumed this frame/request, let's also try to process the response.
-                process_rx_for_mmds = true;
+                if let Some(capture) = self.capture.as_mut() {
+                    capture.record_iovec(&buffer, vnet_hdr_len());
+                }
+
+                let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
+                    self.mmds_ns.as_mut(),
+                    self.dhcp.as_mut(),
+                    &mut self.tx_rate_limiter,
+                    &mut self.tx_frame_headers,
+                    &buffer,
+                    &mut self.tap,
+                    self.guest_mac,
+                    self.metrics,
+                )
+                .unwrap_or(false);
+                if frame_consumed_by_mmds && !self.rx_deferred_frame {
+                    // MMDS or the DHCP responder consumed this frame/request, let's also try
+                    // to process the response.
+                    process_rx_for_mmds = true;
+                }
+
+                tx_queue
+                    .add_used(mem, head_index, 0)
+                    .map_err(DeviceError::QueueError)?;
+                used_any = true;
+
+                if self.batch_size != 0 && tx_queue.num_added() >= self.batch_size {
+                    Self::notify_guest(tx_queue, NetQueue::Tx, mem, &self.irq_trigger)?;
+                }
             }
 
-            tx_queue
-                .add_used(mem, head_index, 0)
-                .map_err(DeviceError::QueueError)?;
-            used_any = true;
+            self.signal_used_queue(NetQueue::Tx)?;
+
+            // The replies of the MMDS are sent once the frames are processed, so the queue is not
+            // polled while they are pending.
+            if self.busy_poll.is_zero() || self.tx_rate_limiter.is_blocked() || process_rx_for_mmds
+            {
+                break;
+            }
+            let mem = self.device_state.mem().unwrap();
+            if !self.queues[TX_INDEX].poll_avail(mem, self.busy_poll) {
+                METRICS.net.tx_busy_poll_misses.inc();
+                break;
+            }
+            METRICS.net.tx_busy_poll_hits.inc();
         }
 
         if !used_any {
             METRICS.net.no_tx_avail_buffer.inc();
         }
 
-        self.signal_used_queue(NetQueue::Tx)?;
-
         // An incoming frame for the MMDS may trigger the transmission of a new message.
         if process_rx_for_mmds {
             self.process_rx()
@@ -636,6 +902,62 @@ impl Net {
         self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
     }
 
+    /// Stops exchanging frames with the tap, once the frames already queued by the guest are
+    /// sent, so that the tap can be re-plumbed.
+    pub fn pause(&mut self) {
+        if self.is_activated() && !self.is_paused && !self.tx_rate_limiter.is_blocked() {
+            self.process_tx().unwrap_or_else(report_net_event_fail);
+        }
+        self.is_paused = true;
+    }
+
+    /// Exchanges the frames queued while the device was paused, and the following ones.
+    pub fn resume(&mut self) {
+        if !self.is_paused {
+            return;
+        }
+        self.is_paused = false;
+        if self.is_activated() {
+            // The tap events are edge triggered, so the frames received meanwhile are only read
+            // now.
+            self.process_tap_rx_event();
+            if !self.tx_rate_limiter.is_blocked() {
+                self.process_tx().unwrap_or_else(report_net_event_fail);
+            }
+        }
+    }
+
+    /// Specifies if this net device is paused.
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    // Counts a frame dropped because the guest has no room for it, or because of the RX rate
+    // limiter.
+    fn count_rx_drop(&self) {
+        if self.rx_rate_limiter.is_blocked() {
+            self.metrics.rx_rate_limiter_drops.inc();
+        } else {
+            self.metrics.rx_ring_full_drops.inc();
+        }
+    }
+
+    /// Keeps draining the tap while the vCPUs are paused, answering the frames the pause
+    /// responder handles and dropping the ones the guest has no room for, instead of deferring
+    /// them until the guest runs again.
+    pub fn set_pause_lite(&mut self, pause_lite: bool) {
+        self.pause_lite = pause_lite;
+        if pause_lite && self.is_activated() && !self.is_paused {
+            // The deferred frame would hold the tap back until the guest runs again.
+            if mem::take(&mut self.rx_deferred_frame) {
+                METRICS.net.pause_drops.inc();
+                self.count_rx_drop();
+            }
+            // The tap events are edge triggered, so the frames already queued are read now.
+            self.process_tap_rx_event();
+        }
+    }
+
     #[cfg(not(test))]
     fn read_tap(&mut self) -> std::io::Result<usize> {
         self.tap.read(&mut self.rx_frame_buf)
@@ -659,7 +981,7 @@ impl Net {
             METRICS.net.event_fails.inc();
         } else if self.rx_rate_limiter.is_blocked() {
             METRICS.net.rx_rate_limiter_throttled.inc();
-        } else {
+        } else if !self.is_paused {
             // If the limiter is not blocked, resume the receiving of bytes.
             self.resume_rx().unwrap_or_else(report_net_event_fail);
         }
@@ -670,6 +992,18 @@ impl Net {
         let mem = self.device_state.mem().unwrap();
         METRICS.net.rx_tap_event_count.inc();
 
+        // The tap is read once the device is resumed.
+        if self.is_paused {
+            return;
+        }
+
+        // While the guest is paused, the tap is drained regardless of the RX queue and of the
+        // rate limiter.
+        if self.pause_lite {
+            self.process_rx().unwrap_or_else(report_net_event_fail);
+            return;
+        }
+
         // While there are no available RX queue buffers and there's a deferred_frame
         // don't process any more incoming. Otherwise start processing a frame. In the
         // process the deferred_frame flag will be set in order to avoid freezing the
@@ -705,12 +1039,11 @@ impl Net {
         if let Err(err) = self.queue_evts[TX_INDEX].read() {
             error!("Failed to get tx queue event: {:?}", err);
             METRICS.net.event_fails.inc();
-        } else if !self.tx_rate_limiter.is_blocked()
-        // If the limiter is not blocked, continue transmitting bytes.
-        {
-            self.process_tx().unwrap_or_else(report_net_event_fail);
-        } else {
+        } else if self.tx_rate_limiter.is_blocked() {
             METRICS.net.tx_rate_limiter_throttled.inc();
+        } else if !self.is_paused {
+            // If the limiter is not blocked, continue transmitting bytes.
+            self.process_tx().unwrap_or_else(report_net_event_fail);
         }
     }
 
@@ -720,6 +1053,7 @@ impl Net {
         // and restart processing the queue.
 
         match self.rx_rate_limiter.event_handler() {
+            Ok(_) if self.is_paused => (),
             Ok(_) => {
                 // There might be enough budget now to receive the frame.
                 self.resume_rx().unwrap_or_else(report_net_event_fail);
@@ -736,6 +1070,7 @@ impl Net {
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
         match self.tx_rate_limiter.event_handler() {
+            Ok(_) if self.is_paused => (),
             Ok(_) => {
                 // There might be enough budget now to send the frame.
                 self.process_tx().unwrap_or_else(report_net_event_fail);
@@ -814,8 +1149,8 @@ impl VirtioDevice for Net {
         let end = start.and_then(|s| s.checked_add(data.len()));
         let Some(dst) = start
             .zip(end)
-            .and_then(|(start, end)| config_space_bytes.get_mut(start..end)) else
-        {
+            .and_then(|(start, end)| config_space_bytes.get_mut(start..end))
+        else {
             error!("Failed to write config space");
             METRICS.net.cfg_fails.inc();
             return;
@@ -856,7 +1191,9 @@ pub mod tests {
     use std::{io, mem, thread};
 
     use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-    use dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use dumbo::pdu::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+    use dumbo::pdu::udp::UdpDatagram;
     use logger::{IncMetric, METRICS};
     use utils::net::mac::MAC_ADDR_LEN;
     use utils::vm_memory::{Address, GuestMemory};
@@ -881,6 +1218,7 @@ pub mod tests {
         Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTQ_DESC_F_WRITE,
     };
     use crate::rate_limiter::{RateLimiter, TokenBucket, TokenType};
+    use crate::vmm_config::net::{DhcpConfig, PauseResponderConfig};
 
     impl Net {
         pub(crate) fn read_tap(&mut self) -> io::Result<usize> {
@@ -1440,6 +1778,187 @@ pub mod tests {
         assert_eq!(&buf[..600], &frame_2[..600]);
     }
 
+    #[test]
+    fn test_tx_busy_poll() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().set_busy_poll(Duration::from_micros(100));
+        assert_eq!(th.net().busy_poll(), Duration::from_micros(100));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        let desc_list = [(0, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, 1000);
+
+        // The queue is polled once the frame is sent, until the window expires.
+        check_metric_after_block!(
+            METRICS.net.tx_busy_poll_misses,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        th.txq.check_used_elem(0, 0, 0);
+        let mut buf = vec![0; 1000];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert_eq!(&buf[..1000], &frame[..1000]);
+    }
+
+    #[test]
+    fn test_tx_batch_size() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().set_batch_size(2);
+        assert_eq!(th.net().batch_size(), 2);
+
+        // Send 5 malformed frames, which are used right away.
+        for index in 0..5 {
+            th.add_desc_chain(NetQueue::Tx, 0, &[(index, 0, 0)]);
+        }
+        th.net().queue_evts[TX_INDEX].read().unwrap();
+        th.net().process_tx().unwrap();
+
+        // The guest is notified after the 2nd and 4th frames, and once all of them are used.
+        assert_eq!(th.txq.used.idx.get(), 5);
+        assert_eq!(th.net().irq_trigger.irq_evt.read().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_packet_capture() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        let socket_file = utils::tempfile::TempFile::new().unwrap();
+        let socket_path = socket_file.as_path().to_str().unwrap().to_string();
+        std::fs::remove_file(&socket_path).unwrap();
+        let reader = std::os::unix::net::UnixDatagram::bind(&socket_path).unwrap();
+        let capture = PacketCapture::new(crate::vmm_config::net::PacketCaptureConfig {
+            socket_path: Some(socket_path.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        th.net().set_capture(capture);
+
+        // A frame sent by the guest.
+        let desc_list = [(0, 100, 0), (1, 200, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let tx_frame = th.write_tx_frame(&desc_list, 300);
+        // A frame received by the guest.
+        th.add_desc_chain(NetQueue::Rx, 1000, &[(0, 500, VIRTQ_DESC_F_WRITE)]);
+        let rx_frame = inject_tap_tx_frame(&th.net(), 200);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert_eq!(th.rxq.used.idx.get(), 1);
+
+        // The frames are mirrored without their VNET header.
+        reader.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 1000];
+        let mut frames = vec![];
+        for _ in 0..2 {
+            let len = reader.recv(&mut buf).unwrap();
+            frames.push(buf[..len].to_vec());
+        }
+        assert!(frames.contains(&tx_frame[vnet_hdr_len()..300].to_vec()));
+        assert!(frames.contains(&rx_frame[vnet_hdr_len()..].to_vec()));
+
+        // Once stopped, the frames are not mirrored anymore.
+        th.net().set_capture(None);
+        assert!(th.net().capture().is_none());
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+
+        // The frames queued by the guest are sent before pausing.
+        let desc_list = [(0, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        th.net().pause();
+        assert!(th.net().is_paused());
+        assert_eq!(th.txq.used.idx.get(), 1);
+
+        // No frame is exchanged while paused.
+        let desc_list = [(1, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 200, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 1000, VIRTQ_DESC_F_WRITE)]);
+        let frame = inject_tap_tx_frame(&th.net(), 200);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert_eq!(th.rxq.used.idx.get(), 0);
+
+        // The frames queued meanwhile are exchanged once resumed.
+        th.net().resume();
+        assert!(!th.net().is_paused());
+        assert_eq!(th.txq.used.idx.get(), 2);
+        assert_eq!(th.rxq.used.idx.get(), 1);
+        th.rxq.check_used_elem(0, 0, frame.len() as u32);
+        th.rxq.dtable[0].check_data(&frame);
+    }
+
+    #[test]
+    fn test_pause_lite() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        let guest_mac = *th.net().guest_mac().unwrap();
+        let guest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let peer_mac = MacAddr::from_str("22:22:22:22:22:22").unwrap();
+        th.net()
+            .set_pause_responder(Some(PauseResponder::new(PauseResponderConfig {
+                guest_ip,
+                icmp_echo: false,
+            })));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let (arp_request, arp_len) =
+            create_arp_request(peer_mac, Ipv4Addr::new(10, 0, 0, 1), guest_mac, guest_ip);
+
+        // While the guest is paused, the frames it has no room for are dropped, and the ARP
+        // requests are answered on its behalf.
+        th.net().set_pause_lite(true);
+        tap_traffic_simulator.push_tx_packet(&[0xab; 100]);
+        tap_traffic_simulator.push_tx_packet(&arp_request[vnet_hdr_len()..arp_len]);
+        let drops = METRICS.net.pause_drops.count();
+        check_metric_after_block!(
+            METRICS.net.pause_replies,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert!(METRICS.net.pause_drops.count() > drops);
+        assert!(!th.net().rx_deferred_frame);
+        assert_eq!(th.rxq.used.idx.get(), 0);
+
+        let mut buf = [0u8; 1000];
+        let mut answered = false;
+        while !answered && tap_traffic_simulator.pop_rx_packet(&mut buf) {
+            let eth = EthernetFrame::from_bytes(&buf[..]).unwrap();
+            if eth.ethertype() != ETHERTYPE_ARP {
+                continue;
+            }
+            let arp = EthIPv4ArpFrame::from_bytes_unchecked(&eth.payload()[..ETH_IPV4_FRAME_LEN]);
+            assert_eq!(eth.dst_mac(), peer_mac);
+            assert_eq!(arp.sha(), guest_mac);
+            assert_eq!(arp.spa(), guest_ip);
+            answered = true;
+        }
+        assert!(answered);
+
+        // Once the guest runs again, the frames it has no room for are deferred.
+        th.net().set_pause_lite(false);
+        tap_traffic_simulator.push_tx_packet(&arp_request[vnet_hdr_len()..arp_len]);
+        check_metric_after_block!(
+            METRICS.net.pause_replies,
+            0,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert!(th.net().rx_deferred_frame);
+    }
+
     fn create_arp_request(
         src_mac: MacAddr,
         src_ip: Ipv4Addr,
@@ -1494,11 +2013,13 @@ pub mod tests {
             1,
             assert!(Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                None,
                 &mut net.tx_rate_limiter,
                 &mut headers,
                 &buffer,
                 &mut net.tap,
                 Some(src_mac),
+                net.metrics,
             )
             .unwrap())
         );
@@ -1511,6 +2032,75 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_dhcp_detour_and_injection() {
+        let mut net = default_net();
+        let gateway = Ipv4Addr::new(10, 0, 0, 1);
+        let dhcp = DhcpResponder::new(DhcpConfig {
+            guest_ip: Ipv4Addr::new(10, 0, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway,
+            nameservers: vec![],
+            hostname: None,
+            lease_time_s: 3600,
+            dns_records: Default::default(),
+        })
+        .unwrap();
+        net.set_dhcp(Some(dhcp));
+
+        // A DHCPDISCOVER broadcast by the guest.
+        let mut request = vec![1u8, 1, 6, 0];
+        request.resize(236, 0);
+        request.extend_from_slice(&[99, 130, 83, 99, 53, 1, 1, 255]);
+        let mut frame_buf = [0u8; MAX_BUFFER_SIZE];
+        let mut eth = EthernetFrame::write_incomplete(
+            frame_bytes_from_buf_mut(&mut frame_buf).unwrap(),
+            MacAddr::from([0xff; MAC_ADDR_LEN]),
+            MacAddr::from_str("11:11:11:11:11:11").unwrap(),
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut ip = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+        )
+        .unwrap();
+        let udp_len =
+            UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), &request)
+                .unwrap()
+                .finalize(68, 67, None)
+                .len();
+        let ip_len = ip
+            .with_payload_len_unchecked(usize::from(udp_len), true)
+            .len();
+        let frame_len = vnet_hdr_len() + eth.with_payload_len_unchecked(ip_len).len();
+        let buffer = IoVecBuffer::from(&frame_buf[..frame_len]);
+        let mut headers = vec![0; frame_hdr_len()];
+
+        // The request is consumed by the responder.
+        assert!(Net::write_to_mmds_or_tap(
+            net.mmds_ns.as_mut(),
+            net.dhcp.as_mut(),
+            &mut net.tx_rate_limiter,
+            &mut headers,
+            &buffer,
+            &mut net.tap,
+            None,
+            net.metrics,
+        )
+        .unwrap());
+
+        // And its offer is injected before the tap frames.
+        let len = net.read_from_mmds_or_tap().unwrap();
+        let reply = EthernetFrame::from_bytes(&net.rx_frame_buf[vnet_hdr_len()..len]).unwrap();
+        assert_eq!(reply.dst_mac(), MacAddr::from([0xff; MAC_ADDR_LEN]));
+        let ip = IPv4Packet::from_bytes(reply.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), gateway);
+        assert_eq!(ip.destination_address(), Ipv4Addr::BROADCAST);
+    }
+
     #[test]
     fn test_mac_spoofing_detection() {
         let mut net = default_net();
@@ -1531,11 +2121,13 @@ pub mod tests {
             0,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                None,
                 &mut net.tx_rate_limiter,
                 &mut headers,
                 &buffer,
                 &mut net.tap,
                 Some(guest_mac),
+                net.metrics,
             )
         );
 
@@ -1545,11 +2137,13 @@ pub mod tests {
             1,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                None,
                 &mut net.tx_rate_limiter,
                 &mut headers,
                 &buffer,
                 &mut net.tap,
                 Some(not_guest_mac),
+                net.metrics,
             )
         );
     }
@@ -1657,6 +2251,45 @@ pub mod tests {
         assert!(!th.net().rx_deferred_frame);
     }
 
+    #[test]
+    fn test_interface_metrics() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        // The counters of the registered interfaces are shared with the other tests.
+        let metrics: &'static NetInterfaceMetrics = Box::leak(Box::default());
+        th.net().metrics = metrics;
+
+        // The frame is held back, as there is no buffer in the RX queue.
+        let frame = inject_tap_tx_frame(&th.net(), 1000);
+        th.simulate_event(NetEvent::Tap);
+        assert!(th.net().rx_deferred_frame);
+        assert_eq!(metrics.rx_ring_full.count(), 1);
+        assert_eq!(metrics.rx_packets.count(), 0);
+
+        // And delivered once there is one.
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+        th.simulate_event(NetEvent::RxQueue);
+        assert!(!th.net().rx_deferred_frame);
+        assert_eq!(metrics.rx_packets.count(), 1);
+        assert_eq!(metrics.rx_bytes.count(), frame.len());
+
+        // A frame larger than the buffer of the guest.
+        let _ = inject_tap_tx_frame(&th.net(), 1000);
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 100, VIRTQ_DESC_F_WRITE)]);
+        th.simulate_event(NetEvent::Tap);
+        assert_eq!(metrics.rx_oversized_frames.count(), 1);
+        assert_eq!(metrics.rx_packets.count(), 1);
+
+        // The frames of the guest are counted once written to the tap.
+        let desc_list = [(0, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let tx_frame = th.write_tx_frame(&desc_list, 1000);
+        th.simulate_event(NetEvent::TxQueue);
+        assert_eq!(metrics.tx_packets.count(), 1);
+        assert_eq!(metrics.tx_bytes.count(), tx_frame.len());
+    }
+
     #[test]
     fn test_rx_rate_limiter_handling() {
         let mut th = TestHelper::get_default();
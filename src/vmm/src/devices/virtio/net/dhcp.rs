@@ -0,0 +1,915 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Static DHCP server and DNS stub answering the guest from its network device, so that the
+//! guest network is configured without kernel command line arguments or a DHCP server on the
+//! host.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+use dumbo::pdu::udp::{UdpDatagram, UDP_HEADER_SIZE};
+use logger::{IncMetric, METRICS};
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+use crate::vmm_config::net::DhcpConfig;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DNS_PORT: u16 = 53;
+
+// Length of an IPv4 header without options.
+const IPV4_MIN_HEADER_LEN: usize = 20;
+
+// Length of the fixed fields of a BOOTP message, which the DHCP magic cookie follows.
+const BOOTP_FIXED_LEN: usize = 236;
+// Minimum length of a BOOTP message, which some clients still expect.
+const BOOTP_MIN_LEN: usize = 300;
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_OPTIONS_OFFSET: usize = BOOTP_FIXED_LEN + DHCP_MAGIC_COOKIE.len();
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_HOSTNAME: u8 = 12;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPINFORM: u8 = 8;
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_FLAG_RESPONSE: u16 = 0x8000;
+const DNS_FLAG_AUTHORITATIVE: u16 = 0x0400;
+const DNS_FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const DNS_OPCODE_SHIFT: u16 = 11;
+const DNS_RCODE_FORMERR: u16 = 1;
+const DNS_RCODE_NOTIMP: u16 = 4;
+const DNS_RCODE_REFUSED: u16 = 5;
+const DNS_TYPE_A: u16 = 1;
+const DNS_CLASS_IN: u16 = 1;
+// Pointer to the name of the question, which directly follows the header.
+const DNS_QUESTION_NAME_PTR: u16 = 0xc000 | DNS_HEADER_LEN as u16;
+// TTL of the answers of the DNS stub.
+const DNS_TTL_S: u32 = 60;
+const DNS_MAX_LABEL_LEN: usize = 63;
+const DNS_MAX_NAME_LEN: usize = 253;
+
+// Maximum number of nameservers fitting in the DHCP option, along with the DNS stub.
+const MAX_NAMESERVERS: usize = 62;
+// MAC address the replies to broadcast requests are sent from.
+const RESPONDER_MAC: [u8; 6] = [0x06, 0x01, 0x23, 0x45, 0x67, 0x02];
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+// Maximum number of replies waiting to be received by the guest.
+const MAX_PENDING_REPLIES: usize = 16;
+
+/// Errors associated with the configuration of the DHCP responder.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DhcpError {
+    /// The netmask is not made of contiguous leading ones.
+    #[error("The netmask {0} is not contiguous.")]
+    InvalidNetmask(Ipv4Addr),
+    /// The gateway is not in the guest subnet.
+    #[error("The gateway {0} is not in the subnet of the guest address.")]
+    GatewayOutsideSubnet(Ipv4Addr),
+    /// The guest and the gateway have the same address.
+    #[error("The guest address cannot be the gateway address.")]
+    GuestIpIsGateway,
+    /// More nameservers than the DHCP option can hold.
+    #[error("Too many nameservers, at most {} are allowed.", MAX_NAMESERVERS)]
+    TooManyNameservers,
+    /// The host name is not a valid DNS name.
+    #[error("Invalid host name: {0}")]
+    InvalidHostname(String),
+    /// The name of a DNS record is not a valid DNS name.
+    #[error("Invalid DNS record name: {0}")]
+    InvalidRecordName(String),
+}
+
+// UDP datagram exchanged with the guest, along with its addressing.
+#[derive(Debug, PartialEq, Eq)]
+struct UdpFrame {
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    payload: Vec<u8>,
+}
+
+// DHCP options of a request the responder cares about.
+#[derive(Debug, Default)]
+struct DhcpOptions {
+    message_type: Option<u8>,
+    requested_ip: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+}
+
+/// Answers the DHCP requests of the guest with a static lease, and the DNS queries sent to the
+/// gateway with static records.
+#[derive(Debug)]
+pub struct DhcpResponder {
+    config: DhcpConfig,
+    // DNS records, keyed by their lowercase name without trailing dot.
+    records: BTreeMap<String, Ipv4Addr>,
+    pending_replies: VecDeque<UdpFrame>,
+}
+
+impl DhcpResponder {
+    /// Creates a responder serving `config`, once validated.
+    pub fn new(config: DhcpConfig) -> Result<Self, DhcpError> {
+        let netmask = u32::from(config.netmask);
+        if netmask.leading_ones() + netmask.trailing_zeros() != 32 {
+            return Err(DhcpError::InvalidNetmask(config.netmask));
+        }
+        if u32::from(config.gateway) & netmask != u32::from(config.guest_ip) & netmask {
+            return Err(DhcpError::GatewayOutsideSubnet(config.gateway));
+        }
+        if config.gateway == config.guest_ip {
+            return Err(DhcpError::GuestIpIsGateway);
+        }
+        if config.nameservers.len() > MAX_NAMESERVERS {
+            return Err(DhcpError::TooManyNameservers);
+        }
+        if let Some(hostname) = &config.hostname {
+            if !is_valid_name(hostname) {
+                return Err(DhcpError::InvalidHostname(hostname.clone()));
+            }
+        }
+
+        let mut records = BTreeMap::new();
+        for (name, addr) in &config.dns_records {
+            if !is_valid_name(name) {
+                return Err(DhcpError::InvalidRecordName(name.clone()));
+            }
+            records.insert(normalize_name(name), *addr);
+        }
+
+        Ok(DhcpResponder {
+            config,
+            records,
+            pending_replies: VecDeque::new(),
+        })
+    }
+
+    /// Returns the configuration served by the responder.
+    pub fn config(&self) -> &DhcpConfig {
+        &self.config
+    }
+
+    /// Checks whether the frame starting with the `src` headers is a request for the responder:
+    /// a DHCP request, or a DNS query sent to the gateway when the DNS stub has records.
+    pub fn is_responder_frame(&self, src: &[u8]) -> bool {
+        match speculative_udp_destination(src) {
+            Some((addr, DHCP_SERVER_PORT)) => {
+                addr == Ipv4Addr::BROADCAST || addr == self.config.gateway
+            }
+            Some((addr, DNS_PORT)) => !self.records.is_empty() && addr == self.config.gateway,
+            _ => false,
+        }
+    }
+
+    /// Handles a request for the responder, which the caller checked with `is_responder_frame`.
+    ///
+    /// Returns whether a reply was queued for the guest.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        match self.answer(src) {
+            Some(reply) if self.pending_replies.len() < MAX_PENDING_REPLIES => {
+                self.pending_replies.push_back(reply);
+                true
+            }
+            _ => {
+                METRICS.net.responder_drops.inc();
+                false
+            }
+        }
+    }
+
+    /// Writes the next reply for the guest to `buf`, if any, and returns its length.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let reply = self.pending_replies.pop_front()?;
+        let len = write_udp_frame(&reply, buf);
+        match (len, reply.src_port) {
+            (Some(_), DNS_PORT) => METRICS.net.dns_replies.inc(),
+            (Some(_), _) => METRICS.net.dhcp_replies.inc(),
+            (None, _) => METRICS.net.responder_drops.inc(),
+        }
+        len
+    }
+
+    fn answer(&self, src: &[u8]) -> Option<UdpFrame> {
+        let eth = EthernetFrame::from_bytes(src).ok()?;
+        let packet = eth.payload();
+        if eth.ethertype() != ETHERTYPE_IPV4 || packet.len() < IPV4_MIN_HEADER_LEN {
+            return None;
+        }
+        // Short frames are padded past the end of the packet.
+        let total_len = usize::from(IPv4Packet::from_bytes_unchecked(packet).total_len());
+        let ip = IPv4Packet::from_bytes(packet.get(..total_len)?, true).ok()?;
+        if ip.protocol() != PROTOCOL_UDP {
+            return None;
+        }
+        // The guest may offload the UDP checksum, which is then left incomplete.
+        let udp = UdpDatagram::from_bytes(ip.payload(), None).ok()?;
+        let payload_len = usize::from(udp.len()).checked_sub(UDP_HEADER_SIZE)?;
+        let request = udp.payload().get(..payload_len)?;
+
+        // Replies to unicast requests come from the MAC address the guest resolved.
+        let src_mac = if eth.dst_mac() == MacAddr::from(BROADCAST_MAC) {
+            MacAddr::from(RESPONDER_MAC)
+        } else {
+            eth.dst_mac()
+        };
+
+        match udp.destination_port() {
+            DHCP_SERVER_PORT => {
+                let (payload, unicast_addr) = self.answer_dhcp(request)?;
+                let (dst_mac, dst_addr) = match unicast_addr {
+                    Some(addr) => (eth.src_mac(), addr),
+                    None => (MacAddr::from(BROADCAST_MAC), Ipv4Addr::BROADCAST),
+                };
+                Some(UdpFrame {
+                    src_mac,
+                    dst_mac,
+                    src_addr: self.config.gateway,
+                    dst_addr,
+                    src_port: DHCP_SERVER_PORT,
+                    dst_port: DHCP_CLIENT_PORT,
+                    payload,
+                })
+            }
+            DNS_PORT => Some(UdpFrame {
+                src_mac,
+                dst_mac: eth.src_mac(),
+                src_addr: ip.destination_address(),
+                dst_addr: ip.source_address(),
+                src_port: DNS_PORT,
+                dst_port: udp.source_port(),
+                payload: self.answer_dns(request)?,
+            }),
+            _ => None,
+        }
+    }
+
+    // Answers a DHCP request. Returns the reply, along with its destination address when it is
+    // not broadcast.
+    fn answer_dhcp(&self, request: &[u8]) -> Option<(Vec<u8>, Option<Ipv4Addr>)> {
+        if request.len() < DHCP_OPTIONS_OFFSET
+            || request[0] != BOOTREQUEST
+            || request[1] != HTYPE_ETHERNET
+            || usize::from(request[2]) != MAC_ADDR_LEN
+            || request[BOOTP_FIXED_LEN..DHCP_OPTIONS_OFFSET] != DHCP_MAGIC_COOKIE
+        {
+            return None;
+        }
+        let options = parse_dhcp_options(&request[DHCP_OPTIONS_OFFSET..]);
+        let ciaddr = read_ipv4(&request[12..16]);
+
+        let message_type = match options.message_type? {
+            DHCPDISCOVER => DHCPOFFER,
+            DHCPREQUEST => {
+                // The guest selected the offer of another server.
+                if options
+                    .server_id
+                    .map_or(false, |id| id != self.config.gateway)
+                {
+                    return None;
+                }
+                if options.requested_ip.unwrap_or(ciaddr) == self.config.guest_ip {
+                    DHCPACK
+                } else {
+                    DHCPNAK
+                }
+            }
+            DHCPINFORM => DHCPACK,
+            // The releases and declines need no answer, as the lease is static.
+            _ => return None,
+        };
+        let inform = options.message_type == Some(DHCPINFORM);
+
+        let mut reply = Vec::with_capacity(BOOTP_MIN_LEN);
+        reply.extend_from_slice(&[BOOTREPLY, HTYPE_ETHERNET, request[2], 0]);
+        // Transaction ID, then the seconds elapsed which the server leaves to 0.
+        reply.extend_from_slice(&request[4..8]);
+        reply.extend_from_slice(&[0, 0]);
+        // Flags and client address.
+        reply.extend_from_slice(&request[10..16]);
+        let yiaddr = if message_type == DHCPNAK || inform {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            self.config.guest_ip
+        };
+        reply.extend_from_slice(&yiaddr.octets());
+        // Next server address.
+        reply.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets());
+        // Relay address and client hardware address.
+        reply.extend_from_slice(&request[24..44]);
+        // Server host name and boot file name.
+        reply.resize(BOOTP_FIXED_LEN, 0);
+        reply.extend_from_slice(&DHCP_MAGIC_COOKIE);
+
+        push_option(&mut reply, OPTION_MESSAGE_TYPE, &[message_type]);
+        push_option(&mut reply, OPTION_SERVER_ID, &self.config.gateway.octets());
+        if message_type != DHCPNAK {
+            if !inform {
+                push_option(
+                    &mut reply,
+                    OPTION_LEASE_TIME,
+                    &self.config.lease_time_s.to_be_bytes(),
+                );
+            }
+            push_option(
+                &mut reply,
+                OPTION_SUBNET_MASK,
+                &self.config.netmask.octets(),
+            );
+            push_option(&mut reply, OPTION_ROUTER, &self.config.gateway.octets());
+            let nameservers: Vec<u8> = self.nameservers().flat_map(|addr| addr.octets()).collect();
+            if !nameservers.is_empty() {
+                push_option(&mut reply, OPTION_DNS_SERVERS, &nameservers);
+            }
+            if let Some(hostname) = &self.config.hostname {
+                push_option(&mut reply, OPTION_HOSTNAME, hostname.as_bytes());
+            }
+        }
+        reply.push(OPTION_END);
+        if reply.len() < BOOTP_MIN_LEN {
+            reply.resize(BOOTP_MIN_LEN, OPTION_PAD);
+        }
+
+        // A NAK is broadcast, since the client may not have a valid address.
+        let unicast_addr =
+            (ciaddr != Ipv4Addr::UNSPECIFIED && message_type != DHCPNAK).then_some(ciaddr);
+        Some((reply, unicast_addr))
+    }
+
+    // The nameservers advertised to the guest, starting with the DNS stub if it has records.
+    fn nameservers(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        let stub = (!self.records.is_empty()).then_some(self.config.gateway);
+        stub.into_iter()
+            .chain(self.config.nameservers.iter().copied())
+    }
+
+    // Answers a DNS query. The names without a record are refused, so that the guest resolver
+    // moves on to the next nameserver.
+    fn answer_dns(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < DNS_HEADER_LEN {
+            return None;
+        }
+        let flags = u16::from_be_bytes([query[2], query[3]]);
+        if flags & DNS_FLAG_RESPONSE != 0 {
+            return None;
+        }
+        let opcode = (flags >> DNS_OPCODE_SHIFT) & 0xf;
+        let reply_flags =
+            DNS_FLAG_RESPONSE | (opcode << DNS_OPCODE_SHIFT) | (flags & DNS_FLAG_RECURSION_DESIRED);
+
+        let mut reply = Vec::with_capacity(query.len() + 16);
+        reply.extend_from_slice(&query[..2]);
+        let question_count = u16::from_be_bytes([query[4], query[5]]);
+        let question = if opcode == 0 && question_count == 1 {
+            parse_dns_question(query)
+        } else {
+            None
+        };
+        let (name, qtype, qclass, question_end) = match question {
+            Some(question) => question,
+            None => {
+                let rcode = if opcode == 0 {
+                    DNS_RCODE_FORMERR
+                } else {
+                    DNS_RCODE_NOTIMP
+                };
+                reply.extend_from_slice(&(reply_flags | rcode).to_be_bytes());
+                reply.resize(DNS_HEADER_LEN, 0);
+                return Some(reply);
+            }
+        };
+
+        let Some(addr) = self.records.get(&name) else {
+            reply.extend_from_slice(&(reply_flags | DNS_RCODE_REFUSED).to_be_bytes());
+            reply.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+            reply.extend_from_slice(&query[DNS_HEADER_LEN..question_end]);
+            return Some(reply);
+        };
+        // The other types of the name exist but have no record.
+        let answer_count: u16 = (qtype == DNS_TYPE_A && qclass == DNS_CLASS_IN).into();
+        reply.extend_from_slice(&(reply_flags | DNS_FLAG_AUTHORITATIVE).to_be_bytes());
+        reply.extend_from_slice(&1u16.to_be_bytes());
+        reply.extend_from_slice(&answer_count.to_be_bytes());
+        reply.extend_from_slice(&[0, 0, 0, 0]);
+        reply.extend_from_slice(&query[DNS_HEADER_LEN..question_end]);
+        if answer_count > 0 {
+            reply.extend_from_slice(&DNS_QUESTION_NAME_PTR.to_be_bytes());
+            reply.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+            reply.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+            reply.extend_from_slice(&DNS_TTL_S.to_be_bytes());
+            reply.extend_from_slice(&4u16.to_be_bytes());
+            reply.extend_from_slice(&addr.octets());
+        }
+        Some(reply)
+    }
+}
+
+// Returns the destination address and UDP port of the IPv4 packet held by `src`, reading only
+// its headers.
+fn speculative_udp_destination(src: &[u8]) -> Option<(Ipv4Addr, u16)> {
+    let eth = EthernetFrame::from_bytes(src).ok()?;
+    let packet = eth.payload();
+    if eth.ethertype() != ETHERTYPE_IPV4 || packet.len() < IPV4_MIN_HEADER_LEN {
+        return None;
+    }
+    let ip = IPv4Packet::from_bytes_unchecked(packet);
+    if ip.protocol() != PROTOCOL_UDP {
+        return None;
+    }
+    let header_len = ip.header_len();
+    let udp = packet.get(header_len..header_len + UDP_HEADER_SIZE)?;
+    let port = UdpDatagram::from_bytes_unchecked(udp).destination_port();
+    Some((ip.destination_address(), port))
+}
+
+fn parse_dhcp_options(mut options: &[u8]) -> DhcpOptions {
+    let mut parsed = DhcpOptions::default();
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPTION_PAD => {
+                options = rest;
+                continue;
+            }
+            OPTION_END => break,
+            _ => (),
+        }
+        let Some((&len, rest)) = rest.split_first() else {
+            break;
+        };
+        let Some(value) = rest.get(..usize::from(len)) else {
+            break;
+        };
+        match (code, value.len()) {
+            (OPTION_MESSAGE_TYPE, 1) => parsed.message_type = Some(value[0]),
+            (OPTION_REQUESTED_IP, 4) => parsed.requested_ip = Some(read_ipv4(value)),
+            (OPTION_SERVER_ID, 4) => parsed.server_id = Some(read_ipv4(value)),
+            _ => (),
+        }
+        options = &rest[value.len()..];
+    }
+    parsed
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u8, value: &[u8]) {
+    // The values are bounded by the configuration checks.
+    buf.push(code);
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value);
+}
+
+// Parses the single question of a DNS query. Returns its normalized name, type and class, along
+// with the offset at which it ends.
+fn parse_dns_question(query: &[u8]) -> Option<(String, u16, u16, usize)> {
+    let mut labels = Vec::new();
+    let mut offset = DNS_HEADER_LEN;
+    loop {
+        let len = usize::from(*query.get(offset)?);
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // Questions do not use compression, so longer labels are invalid.
+        if len > DNS_MAX_LABEL_LEN {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(query.get(offset..offset + len)?));
+        offset += len;
+    }
+    let name = labels.join(".").to_ascii_lowercase();
+    if name.len() > DNS_MAX_NAME_LEN {
+        return None;
+    }
+    let fields = query.get(offset..offset + 4)?;
+    let qtype = u16::from_be_bytes([fields[0], fields[1]]);
+    let qclass = u16::from_be_bytes([fields[2], fields[3]]);
+    Some((name, qtype, qclass, offset + 4))
+}
+
+fn read_ipv4(bytes: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= DNS_MAX_NAME_LEN
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= DNS_MAX_LABEL_LEN
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+fn write_udp_frame(frame: &UdpFrame, buf: &mut [u8]) -> Option<NonZeroUsize> {
+    let mut eth =
+        EthernetFrame::write_incomplete(buf, frame.dst_mac, frame.src_mac, ETHERTYPE_IPV4).ok()?;
+    let mut ip = IPv4Packet::write_header(
+        eth.inner_mut().payload_mut(),
+        PROTOCOL_UDP,
+        frame.src_addr,
+        frame.dst_addr,
+    )
+    .ok()?;
+    let udp_len =
+        UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), &frame.payload)
+            .ok()?
+            .finalize(
+                frame.src_port,
+                frame.dst_port,
+                Some((frame.src_addr, frame.dst_addr)),
+            )
+            .len();
+    let ip_len = ip
+        .with_payload_len_unchecked(usize::from(udp_len), true)
+        .len();
+    NonZeroUsize::new(eth.with_payload_len_unchecked(ip_len).len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const GUEST_MAC: &str = "12:34:56:78:9a:bc";
+    const TAP_MAC: &str = "aa:bb:cc:dd:ee:ff";
+
+    fn config() -> DhcpConfig {
+        DhcpConfig {
+            guest_ip: Ipv4Addr::new(192, 168, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(192, 168, 0, 1),
+            nameservers: vec![Ipv4Addr::new(8, 8, 8, 8)],
+            hostname: Some(String::from("guest")),
+            lease_time_s: 3600,
+            dns_records: BTreeMap::from([(
+                String::from("Build.Internal."),
+                Ipv4Addr::new(10, 0, 0, 1),
+            )]),
+        }
+    }
+
+    fn request_frame(dst_addr: Ipv4Addr, dst_port: u16, payload: Vec<u8>) -> Vec<u8> {
+        let broadcast = dst_addr == Ipv4Addr::BROADCAST;
+        let frame = UdpFrame {
+            src_mac: MacAddr::from_str(GUEST_MAC).unwrap(),
+            dst_mac: if broadcast {
+                MacAddr::from(BROADCAST_MAC)
+            } else {
+                MacAddr::from_str(TAP_MAC).unwrap()
+            },
+            src_addr: if broadcast {
+                Ipv4Addr::UNSPECIFIED
+            } else {
+                Ipv4Addr::new(192, 168, 0, 2)
+            },
+            dst_addr,
+            src_port: if dst_port == DNS_PORT {
+                40000
+            } else {
+                DHCP_CLIENT_PORT
+            },
+            dst_port,
+            payload,
+        };
+        let mut buf = [0u8; 1514];
+        let len = write_udp_frame(&frame, &mut buf).unwrap().get();
+        // Short frames are padded.
+        buf[..len.max(60)].to_vec()
+    }
+
+    fn dhcp_request(message_type: u8, ciaddr: Ipv4Addr, options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut request = vec![BOOTREQUEST, HTYPE_ETHERNET, 6, 0, 1, 2, 3, 4];
+        request.resize(12, 0);
+        request.extend_from_slice(&ciaddr.octets());
+        request.resize(28, 0);
+        request.extend_from_slice(MacAddr::from_str(GUEST_MAC).unwrap().get_bytes());
+        request.resize(BOOTP_FIXED_LEN, 0);
+        request.extend_from_slice(&DHCP_MAGIC_COOKIE);
+        push_option(&mut request, OPTION_MESSAGE_TYPE, &[message_type]);
+        for (code, value) in options {
+            push_option(&mut request, *code, value);
+        }
+        request.push(OPTION_END);
+        request
+    }
+
+    fn dns_query(name: &str, qtype: u16) -> Vec<u8> {
+        // ID 0x1234, recursion desired, one question.
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        query
+    }
+
+    // Sends `request` to the responder and returns its reply, if any.
+    fn exchange(responder: &mut DhcpResponder, request: &[u8]) -> Option<UdpFrame> {
+        assert!(responder.is_responder_frame(request));
+        responder.detour_frame(request);
+        let mut buf = [0u8; 1514];
+        let len = responder.write_next_frame(&mut buf)?.get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        let udp = UdpDatagram::from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        )
+        .unwrap();
+        Some(UdpFrame {
+            src_mac: eth.src_mac(),
+            dst_mac: eth.dst_mac(),
+            src_addr: ip.source_address(),
+            dst_addr: ip.destination_address(),
+            src_port: udp.source_port(),
+            dst_port: udp.destination_port(),
+            payload: udp.payload().to_vec(),
+        })
+    }
+
+    // Returns the value of the `code` option of a DHCP reply.
+    fn reply_option(reply: &[u8], code: u8) -> Option<Vec<u8>> {
+        let mut options = &reply[DHCP_OPTIONS_OFFSET..];
+        while let [option, len, rest @ ..] = options {
+            if *option == OPTION_END {
+                break;
+            }
+            let (value, rest) = rest.split_at(usize::from(*len));
+            if *option == code {
+                return Some(value.to_vec());
+            }
+            options = rest;
+        }
+        None
+    }
+
+    #[test]
+    fn test_dhcp_config() {
+        assert!(DhcpResponder::new(config()).is_ok());
+
+        let mut invalid = config();
+        invalid.netmask = Ipv4Addr::new(255, 0, 255, 0);
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::InvalidNetmask(Ipv4Addr::new(255, 0, 255, 0))
+        );
+
+        let mut invalid = config();
+        invalid.gateway = Ipv4Addr::new(192, 168, 1, 1);
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::GatewayOutsideSubnet(Ipv4Addr::new(192, 168, 1, 1))
+        );
+
+        let mut invalid = config();
+        invalid.gateway = invalid.guest_ip;
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::GuestIpIsGateway
+        );
+
+        let mut invalid = config();
+        invalid.nameservers = vec![Ipv4Addr::new(1, 1, 1, 1); MAX_NAMESERVERS + 1];
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::TooManyNameservers
+        );
+
+        let mut invalid = config();
+        invalid.hostname = Some(String::from("guest name"));
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::InvalidHostname(String::from("guest name"))
+        );
+
+        let mut invalid = config();
+        invalid.dns_records = BTreeMap::from([(String::from("a..b"), Ipv4Addr::LOCALHOST)]);
+        assert_eq!(
+            DhcpResponder::new(invalid).unwrap_err(),
+            DhcpError::InvalidRecordName(String::from("a..b"))
+        );
+    }
+
+    #[test]
+    fn test_dhcp_lease() {
+        let mut responder = DhcpResponder::new(config()).unwrap();
+
+        let discover = dhcp_request(DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        let offer = exchange(
+            &mut responder,
+            &request_frame(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT, discover),
+        )
+        .unwrap();
+        assert_eq!(offer.src_mac, MacAddr::from(RESPONDER_MAC));
+        assert_eq!(offer.dst_mac, MacAddr::from(BROADCAST_MAC));
+        assert_eq!(offer.src_addr, Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(offer.dst_addr, Ipv4Addr::BROADCAST);
+        assert_eq!((offer.src_port, offer.dst_port), (67, 68));
+        let payload = &offer.payload;
+        assert_eq!(payload.len(), BOOTP_MIN_LEN);
+        assert_eq!(payload[0], BOOTREPLY);
+        assert_eq!(payload[4..8], [1, 2, 3, 4]);
+        assert_eq!(payload[16..20], [192, 168, 0, 2]);
+        assert_eq!(
+            payload[28..34],
+            *MacAddr::from_str(GUEST_MAC).unwrap().get_bytes()
+        );
+        assert_eq!(
+            reply_option(payload, OPTION_MESSAGE_TYPE).unwrap(),
+            [DHCPOFFER]
+        );
+        assert_eq!(
+            reply_option(payload, OPTION_SERVER_ID).unwrap(),
+            [192, 168, 0, 1]
+        );
+        assert_eq!(
+            reply_option(payload, OPTION_LEASE_TIME).unwrap(),
+            3600u32.to_be_bytes()
+        );
+        assert_eq!(
+            reply_option(payload, OPTION_SUBNET_MASK).unwrap(),
+            [255, 255, 255, 0]
+        );
+        assert_eq!(
+            reply_option(payload, OPTION_ROUTER).unwrap(),
+            [192, 168, 0, 1]
+        );
+        // The DNS stub comes first.
+        assert_eq!(
+            reply_option(payload, OPTION_DNS_SERVERS).unwrap(),
+            [192, 168, 0, 1, 8, 8, 8, 8]
+        );
+        assert_eq!(reply_option(payload, OPTION_HOSTNAME).unwrap(), b"guest");
+
+        let request = dhcp_request(
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                (OPTION_REQUESTED_IP, &[192, 168, 0, 2]),
+                (OPTION_SERVER_ID, &[192, 168, 0, 1]),
+            ],
+        );
+        let ack = exchange(
+            &mut responder,
+            &request_frame(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT, request),
+        )
+        .unwrap();
+        assert_eq!(
+            reply_option(&ack.payload, OPTION_MESSAGE_TYPE).unwrap(),
+            [DHCPACK]
+        );
+        assert_eq!(ack.payload[16..20], [192, 168, 0, 2]);
+
+        // A renewal is unicast, and so is its answer.
+        let renew = dhcp_request(DHCPREQUEST, Ipv4Addr::new(192, 168, 0, 2), &[]);
+        let ack = exchange(
+            &mut responder,
+            &request_frame(Ipv4Addr::new(192, 168, 0, 1), DHCP_SERVER_PORT, renew),
+        )
+        .unwrap();
+        assert_eq!(
+            reply_option(&ack.payload, OPTION_MESSAGE_TYPE).unwrap(),
+            [DHCPACK]
+        );
+        assert_eq!(ack.src_mac, MacAddr::from_str(TAP_MAC).unwrap());
+        assert_eq!(ack.dst_mac, MacAddr::from_str(GUEST_MAC).unwrap());
+        assert_eq!(ack.dst_addr, Ipv4Addr::new(192, 168, 0, 2));
+
+        // Another address is refused.
+        let request = dhcp_request(
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_REQUESTED_IP, &[192, 168, 0, 3])],
+        );
+        let nak = exchange(
+            &mut responder,
+            &request_frame(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT, request),
+        )
+        .unwrap();
+        assert_eq!(
+            reply_option(&nak.payload, OPTION_MESSAGE_TYPE).unwrap(),
+            [DHCPNAK]
+        );
+        assert_eq!(nak.payload[16..20], [0, 0, 0, 0]);
+        assert!(reply_option(&nak.payload, OPTION_LEASE_TIME).is_none());
+
+        // The offer of another server is left alone.
+        let request = dhcp_request(
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                (OPTION_REQUESTED_IP, &[10, 0, 0, 2]),
+                (OPTION_SERVER_ID, &[10, 0, 0, 1]),
+            ],
+        );
+        assert!(exchange(
+            &mut responder,
+            &request_frame(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT, request),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_dns_stub() {
+        let mut responder = DhcpResponder::new(config()).unwrap();
+        let gateway = Ipv4Addr::new(192, 168, 0, 1);
+
+        let query = dns_query("build.INTERNAL", DNS_TYPE_A);
+        let reply = exchange(
+            &mut responder,
+            &request_frame(gateway, DNS_PORT, query.clone()),
+        )
+        .unwrap();
+        assert_eq!(reply.src_addr, gateway);
+        assert_eq!(reply.dst_addr, Ipv4Addr::new(192, 168, 0, 2));
+        assert_eq!((reply.src_port, reply.dst_port), (DNS_PORT, 40000));
+        let payload = &reply.payload;
+        assert_eq!(payload[..2], [0x12, 0x34]);
+        assert_eq!(payload[2..4], [0x85, 0x00]);
+        // One question and one answer.
+        assert_eq!(payload[4..8], [0, 1, 0, 1]);
+        assert_eq!(
+            payload[DNS_HEADER_LEN..query.len()],
+            query[DNS_HEADER_LEN..]
+        );
+        assert_eq!(payload[payload.len() - 4..], [10, 0, 0, 1]);
+
+        // The name has no record of this type.
+        let query = dns_query("build.internal", 28);
+        let reply = exchange(&mut responder, &request_frame(gateway, DNS_PORT, query)).unwrap();
+        assert_eq!(reply.payload[2..8], [0x85, 0x00, 0, 1, 0, 0]);
+
+        // Unknown names are refused.
+        let query = dns_query("example.com", DNS_TYPE_A);
+        let reply = exchange(&mut responder, &request_frame(gateway, DNS_PORT, query)).unwrap();
+        assert_eq!(reply.payload[2..8], [0x81, 0x05, 0, 1, 0, 0]);
+
+        // Without records, the queries go to the tap.
+        let mut no_records = config();
+        no_records.dns_records.clear();
+        let responder = DhcpResponder::new(no_records).unwrap();
+        let query = dns_query("build.internal", DNS_TYPE_A);
+        assert!(!responder.is_responder_frame(&request_frame(gateway, DNS_PORT, query)));
+        assert_eq!(
+            responder.nameservers().collect::<Vec<_>>(),
+            [Ipv4Addr::new(8, 8, 8, 8)]
+        );
+    }
+
+    #[test]
+    fn test_pending_replies() {
+        let mut responder = DhcpResponder::new(config()).unwrap();
+        let discover = request_frame(
+            Ipv4Addr::BROADCAST,
+            DHCP_SERVER_PORT,
+            dhcp_request(DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]),
+        );
+        for _ in 0..MAX_PENDING_REPLIES {
+            assert!(responder.detour_frame(&discover));
+        }
+        assert!(!responder.detour_frame(&discover));
+
+        let mut buf = [0u8; 1514];
+        for _ in 0..MAX_PENDING_REPLIES {
+            assert!(responder.write_next_frame(&mut buf).is_some());
+        }
+        assert!(responder.write_next_frame(&mut buf).is_none());
+
+        // Frames for other destinations are not the responder's.
+        let other = request_frame(Ipv4Addr::new(192, 168, 0, 1), 80, vec![0; 8]);
+        assert!(!responder.is_responder_frame(&other));
+        assert!(!responder.is_responder_frame(&[0u8; 10]));
+    }
+}
@@ -17,11 +17,15 @@ pub const RX_INDEX: usize = 0;
 /// The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
 
+pub mod capture;
 pub mod device;
+pub mod dhcp;
 mod event_handler;
+pub mod pause_responder;
 pub mod persist;
 mod tap;
 pub mod test_utils;
+pub mod usernet;
 
 pub use tap::{Tap, TapError};
 
@@ -58,4 +62,7 @@ pub enum NetError {
     /// The VNET header is missing from the frame
     #[error("The VNET header is missing from the frame")]
     VnetHeaderMissing,
+    /// Creating the user-mode network stack failed
+    #[error("Creating the user-mode network stack failed: {0}")]
+    UserNet(usernet::UserNetError),
 }
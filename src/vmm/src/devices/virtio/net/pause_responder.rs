@@ -0,0 +1,293 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replies to the ARP and ICMP echo requests for the guest while its vCPUs are paused, so that
+//! the hosts probing it, like load balancers, do not consider it gone during a brief pause.
+
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+
+use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::IPv4Packet;
+use utils::net::mac::MacAddr;
+
+use crate::vmm_config::net::PauseResponderConfig;
+
+const PROTOCOL_ICMP: u8 = 0x01;
+// The "more fragments" bit of the IPv4 flags.
+const IPV4_FLAG_MF: u8 = 0x01;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_HEADER_LEN: usize = 8;
+const ICMP_CHECKSUM_OFFSET: usize = 2;
+// TTL of the echo replies, as set by Linux.
+const ICMP_REPLY_TTL: u8 = 64;
+
+// Length of an IPv4 header without options.
+const IPV4_MIN_HEADER_LEN: usize = 20;
+
+/// Answers the host on behalf of a paused guest.
+#[derive(Debug)]
+pub struct PauseResponder {
+    config: PauseResponderConfig,
+}
+
+impl PauseResponder {
+    /// Creates a responder answering for the guest address of `config`.
+    pub fn new(config: PauseResponderConfig) -> Self {
+        PauseResponder { config }
+    }
+
+    /// Returns the configuration of the responder.
+    pub fn config(&self) -> &PauseResponderConfig {
+        &self.config
+    }
+
+    /// Writes to `buf` the reply of the guest with the `guest_mac` address to the `src` frame,
+    /// if the responder answers it, and returns its length.
+    pub fn reply(&self, guest_mac: MacAddr, src: &[u8], buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let eth = EthernetFrame::from_bytes(src).ok()?;
+        match eth.ethertype() {
+            ETHERTYPE_ARP => self.reply_arp(guest_mac, &eth, buf),
+            ETHERTYPE_IPV4 if self.config.icmp_echo => self.reply_icmp_echo(guest_mac, &eth, buf),
+            _ => None,
+        }
+    }
+
+    fn reply_arp(
+        &self,
+        guest_mac: MacAddr,
+        eth: &EthernetFrame<&[u8]>,
+        buf: &mut [u8],
+    ) -> Option<NonZeroUsize> {
+        // Short frames are padded past the end of the request.
+        let arp =
+            EthIPv4ArpFrame::request_from_bytes(eth.payload().get(..ETH_IPV4_FRAME_LEN)?).ok()?;
+        if arp.tpa() != self.config.guest_ip {
+            return None;
+        }
+
+        let mut reply =
+            EthernetFrame::write_incomplete(buf, arp.sha(), guest_mac, ETHERTYPE_ARP).ok()?;
+        let arp_len = EthIPv4ArpFrame::write_reply(
+            reply
+                .inner_mut()
+                .payload_mut()
+                .get_mut(..ETH_IPV4_FRAME_LEN)?,
+            guest_mac,
+            self.config.guest_ip,
+            arp.sha(),
+            arp.spa(),
+        )
+        .ok()?
+        .len();
+        NonZeroUsize::new(reply.with_payload_len_unchecked(arp_len).len())
+    }
+
+    fn reply_icmp_echo(
+        &self,
+        guest_mac: MacAddr,
+        eth: &EthernetFrame<&[u8]>,
+        buf: &mut [u8],
+    ) -> Option<NonZeroUsize> {
+        let packet = eth.payload();
+        if packet.len() < IPV4_MIN_HEADER_LEN {
+            return None;
+        }
+        // Short frames are padded past the end of the packet.
+        let total_len = usize::from(IPv4Packet::from_bytes_unchecked(packet).total_len());
+        let packet = packet.get(..total_len)?;
+        let ip = IPv4Packet::from_bytes(packet, true).ok()?;
+        let (flags, fragment_offset) = ip.flags_and_fragment_offset();
+        if ip.protocol() != PROTOCOL_ICMP
+            || ip.destination_address() != self.config.guest_ip
+            || flags & IPV4_FLAG_MF != 0
+            || fragment_offset != 0
+        {
+            return None;
+        }
+        let icmp = ip.payload();
+        if icmp.len() < ICMP_HEADER_LEN || icmp[0] != ICMP_ECHO_REQUEST || icmp[1] != 0 {
+            return None;
+        }
+
+        // The reply echoes the identifier, sequence number and data of the request.
+        let mut reply =
+            EthernetFrame::write_incomplete(buf, eth.src_mac(), guest_mac, ETHERTYPE_IPV4).ok()?;
+        reply
+            .inner_mut()
+            .payload_mut()
+            .get_mut(..total_len)?
+            .copy_from_slice(packet);
+        let mut reply_ip =
+            IPv4Packet::from_bytes_unchecked(reply.inner_mut().payload_mut().get_mut(..total_len)?);
+        let header_len = ip.header_len();
+        reply_ip
+            .set_source_address(self.config.guest_ip)
+            .set_destination_address(ip.source_address())
+            .set_ttl(ICMP_REPLY_TTL)
+            .set_header_checksum(0);
+        let ip_checksum = reply_ip.compute_checksum_unchecked(header_len);
+        reply_ip.set_header_checksum(ip_checksum);
+
+        let reply_icmp = reply_ip.payload_mut_unchecked(header_len);
+        reply_icmp[0] = ICMP_ECHO_REPLY;
+        reply_icmp[ICMP_CHECKSUM_OFFSET..ICMP_CHECKSUM_OFFSET + 2].fill(0);
+        let icmp_checksum = internet_checksum(reply_icmp);
+        reply_icmp[ICMP_CHECKSUM_OFFSET..ICMP_CHECKSUM_OFFSET + 2]
+            .copy_from_slice(&icmp_checksum.to_be_bytes());
+
+        NonZeroUsize::new(reply.with_payload_len_unchecked(total_len).len())
+    }
+}
+
+// Computes the one's complement checksum of `bytes`, as used by ICMP.
+fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum = bytes
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    // The loop above folded the sum into 16 bits.
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const GUEST_MAC: &str = "12:34:56:78:9a:bc";
+    const PEER_MAC: &str = "aa:bb:cc:dd:ee:ff";
+    const GUEST_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 2);
+    const PEER_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+
+    fn responder(icmp_echo: bool) -> PauseResponder {
+        PauseResponder::new(PauseResponderConfig {
+            guest_ip: GUEST_IP,
+            icmp_echo,
+        })
+    }
+
+    fn arp_request(tpa: Ipv4Addr) -> Vec<u8> {
+        let mut buf = [0u8; 60];
+        let mut eth = EthernetFrame::write_incomplete(
+            buf.as_mut(),
+            MacAddr::from_bytes_unchecked(&[0xff; 6]),
+            MacAddr::from_str(PEER_MAC).unwrap(),
+            ETHERTYPE_ARP,
+        )
+        .unwrap();
+        EthIPv4ArpFrame::write_request(
+            &mut eth.inner_mut().payload_mut()[..ETH_IPV4_FRAME_LEN],
+            MacAddr::from_str(PEER_MAC).unwrap(),
+            PEER_IP,
+            MacAddr::from_bytes_unchecked(&[0; 6]),
+            tpa,
+        )
+        .unwrap();
+        // The request is padded to the minimum frame length.
+        buf.to_vec()
+    }
+
+    fn echo_request(dst_addr: Ipv4Addr, data: &[u8]) -> Vec<u8> {
+        let mut buf = [0u8; 1514];
+        let mut eth = EthernetFrame::write_incomplete(
+            buf.as_mut(),
+            MacAddr::from_str(GUEST_MAC).unwrap(),
+            MacAddr::from_str(PEER_MAC).unwrap(),
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut ip = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_ICMP,
+            PEER_IP,
+            dst_addr,
+        )
+        .unwrap();
+        let icmp = ip.inner_mut().payload_mut();
+        icmp[..ICMP_HEADER_LEN].copy_from_slice(&[ICMP_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0, 1]);
+        icmp[ICMP_HEADER_LEN..ICMP_HEADER_LEN + data.len()].copy_from_slice(data);
+        let checksum = internet_checksum(&icmp[..ICMP_HEADER_LEN + data.len()]);
+        icmp[ICMP_CHECKSUM_OFFSET..ICMP_CHECKSUM_OFFSET + 2]
+            .copy_from_slice(&checksum.to_be_bytes());
+        let ip_len = ip
+            .with_payload_len_unchecked(ICMP_HEADER_LEN + data.len(), true)
+            .len();
+        let len = eth.with_payload_len_unchecked(ip_len).len();
+        buf[..len.max(60)].to_vec()
+    }
+
+    #[test]
+    fn test_reply_arp() {
+        let guest_mac = MacAddr::from_str(GUEST_MAC).unwrap();
+        let mut buf = [0u8; 1514];
+
+        let len = responder(false)
+            .reply(guest_mac, &arp_request(GUEST_IP), &mut buf)
+            .unwrap()
+            .get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.dst_mac(), MacAddr::from_str(PEER_MAC).unwrap());
+        assert_eq!(eth.src_mac(), guest_mac);
+        assert_eq!(eth.ethertype(), ETHERTYPE_ARP);
+        let arp = EthIPv4ArpFrame::from_bytes_unchecked(eth.payload());
+        assert_eq!(arp.operation(), dumbo::pdu::arp::OPER_REPLY);
+        assert_eq!(arp.sha(), guest_mac);
+        assert_eq!(arp.spa(), GUEST_IP);
+        assert_eq!(arp.tha(), MacAddr::from_str(PEER_MAC).unwrap());
+        assert_eq!(arp.tpa(), PEER_IP);
+
+        // The requests for other addresses are not answered.
+        assert!(responder(false)
+            .reply(
+                guest_mac,
+                &arp_request(Ipv4Addr::new(192, 168, 0, 3)),
+                &mut buf
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_reply_icmp_echo() {
+        let guest_mac = MacAddr::from_str(GUEST_MAC).unwrap();
+        let request = echo_request(GUEST_IP, b"keepalive");
+        let mut buf = [0u8; 1514];
+
+        // The echo requests are only answered when enabled.
+        assert!(responder(false)
+            .reply(guest_mac, &request, &mut buf)
+            .is_none());
+
+        let len = responder(true)
+            .reply(guest_mac, &request, &mut buf)
+            .unwrap()
+            .get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.dst_mac(), MacAddr::from_str(PEER_MAC).unwrap());
+        assert_eq!(eth.src_mac(), guest_mac);
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), GUEST_IP);
+        assert_eq!(ip.destination_address(), PEER_IP);
+        assert_eq!(ip.ttl(), ICMP_REPLY_TTL);
+        let icmp = ip.payload();
+        assert_eq!(icmp[0], ICMP_ECHO_REPLY);
+        assert_eq!(&icmp[4..8], &[0x12, 0x34, 0, 1]);
+        assert_eq!(&icmp[ICMP_HEADER_LEN..], b"keepalive");
+        assert_eq!(internet_checksum(icmp), 0);
+
+        // The requests for other addresses are not answered.
+        assert!(responder(true)
+            .reply(
+                guest_mac,
+                &echo_request(Ipv4Addr::new(192, 168, 0, 3), b""),
+                &mut buf
+            )
+            .is_none());
+    }
+}
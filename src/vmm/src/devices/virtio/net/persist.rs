@@ -4,8 +4,10 @@
 //! Defines the structures needed for saving/restoring net devices.
 
 use std::io;
+use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use log::warn;
 use mmds::data_store::Mmds;
@@ -18,11 +20,14 @@ use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 
 use super::device::Net;
+use super::dhcp::{DhcpError, DhcpResponder};
+use super::pause_responder::PauseResponder;
 use super::NET_NUM_QUEUES;
 use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
 use crate::devices::virtio::{DeviceState, FIRECRACKER_MAX_QUEUE_SIZE, TYPE_NET};
 use crate::rate_limiter::persist::RateLimiterState;
 use crate::rate_limiter::RateLimiter;
+use crate::vmm_config::net::{DhcpConfig, PauseResponderConfig, PortForwardConfig, UserNetConfig};
 
 /// Information about the network config's that are saved
 /// at snapshot.
@@ -61,6 +66,159 @@ impl NetConfigSpaceState {
     }
 }
 
+/// Information about a DNS record of the DHCP responder that is saved
+/// at snapshot.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DnsRecordState {
+    name: String,
+    addr: u32,
+}
+
+/// Information about the DHCP responder of a network device that is saved
+/// at snapshot.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DhcpResponderState {
+    guest_ip: u32,
+    netmask: u32,
+    gateway: u32,
+    nameservers: Vec<u32>,
+    hostname: Option<String>,
+    lease_time_s: u32,
+    dns_records: Vec<DnsRecordState>,
+}
+
+impl From<&DhcpConfig> for DhcpResponderState {
+    fn from(config: &DhcpConfig) -> Self {
+        DhcpResponderState {
+            guest_ip: config.guest_ip.into(),
+            netmask: config.netmask.into(),
+            gateway: config.gateway.into(),
+            nameservers: config.nameservers.iter().copied().map(u32::from).collect(),
+            hostname: config.hostname.clone(),
+            lease_time_s: config.lease_time_s,
+            dns_records: config
+                .dns_records
+                .iter()
+                .map(|(name, addr)| DnsRecordState {
+                    name: name.clone(),
+                    addr: (*addr).into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&DhcpResponderState> for DhcpConfig {
+    fn from(state: &DhcpResponderState) -> Self {
+        DhcpConfig {
+            guest_ip: state.guest_ip.into(),
+            netmask: state.netmask.into(),
+            gateway: state.gateway.into(),
+            nameservers: state
+                .nameservers
+                .iter()
+                .copied()
+                .map(Ipv4Addr::from)
+                .collect(),
+            hostname: state.hostname.clone(),
+            lease_time_s: state.lease_time_s,
+            dns_records: state
+                .dns_records
+                .iter()
+                .map(|record| (record.name.clone(), record.addr.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Information about a port forward of the user-mode network stack that is
+/// saved at snapshot.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PortForwardState {
+    host_address: u32,
+    host_port: u16,
+    guest_port: u16,
+}
+
+/// Information about the user-mode network stack of a network device that is
+/// saved at snapshot. The connections it relayed are not.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct UserNetState {
+    guest_ip: u32,
+    gateway: u32,
+    host_loopback_ports: Vec<u16>,
+    port_forwards: Vec<PortForwardState>,
+}
+
+impl From<&UserNetConfig> for UserNetState {
+    fn from(config: &UserNetConfig) -> Self {
+        UserNetState {
+            guest_ip: config.guest_ip.into(),
+            gateway: config.gateway.into(),
+            host_loopback_ports: config.host_loopback_ports.clone(),
+            port_forwards: config
+                .port_forwards
+                .iter()
+                .map(|forward| PortForwardState {
+                    host_address: forward.host_address.into(),
+                    host_port: forward.host_port,
+                    guest_port: forward.guest_port,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&UserNetState> for UserNetConfig {
+    fn from(state: &UserNetState) -> Self {
+        UserNetConfig {
+            guest_ip: state.guest_ip.into(),
+            gateway: state.gateway.into(),
+            host_loopback_ports: state.host_loopback_ports.clone(),
+            port_forwards: state
+                .port_forwards
+                .iter()
+                .map(|forward| PortForwardConfig {
+                    host_address: forward.host_address.into(),
+                    host_port: forward.host_port,
+                    guest_port: forward.guest_port,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Information about the pause responder of a network device that is saved
+/// at snapshot.
+#[derive(Debug, Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PauseResponderState {
+    guest_ip: u32,
+    icmp_echo: bool,
+}
+
+impl From<&PauseResponderConfig> for PauseResponderState {
+    fn from(config: &PauseResponderConfig) -> Self {
+        PauseResponderState {
+            guest_ip: config.guest_ip.into(),
+            icmp_echo: config.icmp_echo,
+        }
+    }
+}
+
+impl From<&PauseResponderState> for PauseResponderConfig {
+    fn from(state: &PauseResponderState) -> Self {
+        PauseResponderConfig {
+            guest_ip: state.guest_ip.into(),
+            icmp_echo: state.icmp_echo,
+        }
+    }
+}
+
 /// Information about the network device that are saved
 /// at snapshot.
 #[derive(Debug, Clone, Versionize)]
@@ -74,6 +232,45 @@ pub struct NetState {
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    /// The DHCP responder, which is restored along with the guest lease.
+    #[version(start = 2, default_fn = "default_dhcp")]
+    pub dhcp: Option<DhcpResponderState>,
+    /// The user-mode network stack serving the device in place of a tap.
+    #[version(start = 2, default_fn = "default_user_net")]
+    pub user_net: Option<UserNetState>,
+    /// The replies sent on behalf of the guest while it is paused.
+    #[version(start = 2, default_fn = "default_pause_responder")]
+    pub pause_responder: Option<PauseResponderState>,
+    /// The time, in microseconds, the TX queue is busy-polled for new frames.
+    #[version(start = 2)]
+    pub busy_poll_us: u32,
+    /// The number of used descriptor chains of a queue after which the guest is notified.
+    #[version(start = 2)]
+    pub batch_size: u16,
+}
+
+impl NetState {
+    /// Returns the name of the tap the device is attached to.
+    pub fn tap_if_name(&self) -> &str {
+        &self.tap_if_name
+    }
+
+    /// Sets the name of the tap the device is attached to when restored.
+    pub fn set_tap_if_name(&mut self, tap_if_name: String) {
+        self.tap_if_name = tap_if_name;
+    }
+
+    fn default_dhcp(_: u16) -> Option<DhcpResponderState> {
+        None
+    }
+
+    fn default_user_net(_: u16) -> Option<UserNetState> {
+        None
+    }
+
+    fn default_pause_responder(_: u16) -> Option<PauseResponderState> {
+        None
+    }
 }
 
 /// Auxiliary structure for creating a device when resuming from a snapshot.
@@ -96,6 +293,8 @@ pub enum NetPersistError {
     VirtioState(VirtioStateError),
     /// Indicator that no MMDS is associated with this device.
     NoMmdsDataStore,
+    /// Failed to restore the DHCP responder.
+    Dhcp(DhcpError),
 }
 
 impl Persist<'_> for Net {
@@ -115,6 +314,15 @@ impl Persist<'_> for Net {
                 guest_mac: Default::default(),
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            dhcp: self.dhcp().map(|dhcp| dhcp.config().into()),
+            user_net: self
+                .user_net()
+                .map(|user_net| user_net.lock().expect("Poisoned lock").config().into()),
+            pause_responder: self
+                .pause_responder()
+                .map(|responder| responder.config().into()),
+            busy_poll_us: u32::try_from(self.busy_poll().as_micros()).unwrap_or(u32::MAX),
+            batch_size: self.batch_size(),
         }
     }
 
@@ -125,13 +333,24 @@ impl Persist<'_> for Net {
         // RateLimiter::restore() can fail at creating a timerfd.
         let rx_rate_limiter = RateLimiter::restore((), &state.rx_rate_limiter_state)?;
         let tx_rate_limiter = RateLimiter::restore((), &state.tx_rate_limiter_state)?;
-        let mut net = Net::new(
-            state.id.clone(),
-            &state.tap_if_name,
-            state.config_space.guest_mac_v2,
-            rx_rate_limiter,
-            tx_rate_limiter,
-        )?;
+        // The user-mode network stack listens on its forwarded ports again, but the connections
+        // it relayed are lost.
+        let mut net = match &state.user_net {
+            Some(user_net) => Net::new_with_user_net(
+                state.id.clone(),
+                user_net.into(),
+                state.config_space.guest_mac_v2,
+                rx_rate_limiter,
+                tx_rate_limiter,
+            )?,
+            None => Net::new(
+                state.id.clone(),
+                &state.tap_if_name,
+                state.config_space.guest_mac_v2,
+                rx_rate_limiter,
+                tx_rate_limiter,
+            )?,
+        };
 
         // We trust the MMIODeviceManager::restore to pass us an MMDS data store reference if
         // there is at least one net device having the MMDS NS present and/or the mmds version was
@@ -150,6 +369,22 @@ impl Persist<'_> for Net {
             );
         }
 
+        net.set_dhcp(
+            state
+                .dhcp
+                .as_ref()
+                .map(|dhcp| DhcpResponder::new(dhcp.into()))
+                .transpose()?,
+        );
+        net.set_pause_responder(
+            state
+                .pause_responder
+                .as_ref()
+                .map(|responder| PauseResponder::new(responder.into())),
+        );
+        net.set_busy_poll(Duration::from_micros(u64::from(state.busy_poll_us)));
+        net.set_batch_size(state.batch_size);
+
         net.queues = state.virtio_state.build_queues_checked(
             &constructor_args.mem,
             TYPE_NET,
@@ -256,4 +491,141 @@ mod tests {
         // data store. This will return an error.
         validate_save_and_restore(default_net(), None);
     }
+
+    #[test]
+    fn test_persistence_dhcp() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let config = DhcpConfig {
+            guest_ip: Ipv4Addr::new(10, 0, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(10, 0, 0, 1),
+            nameservers: vec![Ipv4Addr::new(1, 1, 1, 1)],
+            hostname: Some(String::from("guest")),
+            lease_time_s: 600,
+            dns_records: [(String::from("host.internal"), Ipv4Addr::new(10, 0, 0, 1))].into(),
+        };
+        let mut net = default_net_no_mmds();
+        net.set_dhcp(Some(DhcpResponder::new(config.clone()).unwrap()));
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_mem(),
+                mmds: None,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_net.dhcp().unwrap().config(), &config);
+    }
+
+    #[test]
+    fn test_persistence_pause_responder() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let config = PauseResponderConfig {
+            guest_ip: Ipv4Addr::new(10, 0, 0, 2),
+            icmp_echo: true,
+        };
+        let mut net = default_net_no_mmds();
+        net.set_pause_responder(Some(PauseResponder::new(config.clone())));
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_mem(),
+                mmds: None,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_net.pause_responder().unwrap().config(), &config);
+    }
+
+    #[test]
+    fn test_persistence_queue_tuning() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let mut net = default_net_no_mmds();
+        net.set_busy_poll(Duration::from_micros(50));
+        net.set_batch_size(8);
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_mem(),
+                mmds: None,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_net.busy_poll(), Duration::from_micros(50));
+        assert_eq!(restored_net.batch_size(), 8);
+    }
+
+    #[test]
+    fn test_persistence_user_net() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let config = UserNetConfig {
+            guest_ip: Ipv4Addr::new(10, 0, 2, 15),
+            gateway: Ipv4Addr::new(10, 0, 2, 2),
+            host_loopback_ports: vec![8080],
+            port_forwards: vec![],
+        };
+        let net = Net::new_with_user_net(
+            String::from("user0"),
+            config.clone(),
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+        )
+        .unwrap();
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_mem(),
+                mmds: None,
+            },
+            &state,
+        )
+        .unwrap();
+        let user_net = restored_net.user_net().unwrap().lock().unwrap();
+        assert_eq!(user_net.config(), &config);
+        assert_eq!(restored_net.iface_name(), "");
+    }
 }
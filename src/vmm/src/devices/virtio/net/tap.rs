@@ -9,7 +9,8 @@ use std::fmt::{self, Debug};
 use std::fs::File;
 use std::io::{Error as IoError, Read, Write};
 use std::os::raw::*;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
 
 use net_gen::ifreq;
 use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
@@ -44,10 +45,14 @@ pub enum TapError {
     /// Error while setting size of the vnet header
     #[error("Error while setting size of the vnet header: {0}")]
     SetSizeOfVnetHdr(IoError),
+    /// Error while setting the persistence of the tap interface
+    #[error("Error while setting the persistence of the tap interface: {0}")]
+    SetPersist(IoError),
 }
 
 const TUNTAP: ::std::os::raw::c_uint = 84;
 ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETPERSIST, TUNTAP, 203, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
 
@@ -162,6 +167,18 @@ impl Tap {
         })
     }
 
+    /// Wrap one end of a Unix datagram socket pair, through which the frames are exchanged in
+    /// place of a tap interface. The socket has no interface name.
+    pub(crate) fn from_socket(socket: UnixDatagram) -> Tap {
+        Tap {
+            tap_file: File::from(OwnedFd::from(socket)),
+            if_name: [b'\0'; IFACE_NAME_MAX_LEN],
+
+            #[cfg(test)]
+            mocks: Mocks::default(),
+        }
+    }
+
     /// Retrieve the interface's name as a str.
     pub fn if_name_as_str(&self) -> &str {
         let len = self
@@ -192,6 +209,17 @@ impl Tap {
         Ok(())
     }
 
+    /// Set whether the tap interface outlives the file descriptors attached to it. The host
+    /// kernel removes a non-persistent tap interface once the last of them is closed.
+    pub fn set_persist(&self, persist: bool) -> Result<(), TapError> {
+        // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
+        if unsafe { ioctl_with_val(&self.tap_file, TUNSETPERSIST(), c_ulong::from(persist)) } < 0 {
+            return Err(TapError::SetPersist(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     /// Write an `IoVecBuffer` to tap
     pub(crate) fn write_iovec(&mut self, buffer: &IoVecBuffer) -> Result<usize, IoError> {
         let iovcnt = i32::try_from(buffer.iovec_count()).unwrap();
@@ -292,6 +320,7 @@ pub mod tests {
         let tap = Tap::open_named("").unwrap();
         tap.set_vnet_hdr_size(16).unwrap();
         tap.set_offload(0).unwrap();
+        tap.set_persist(false).unwrap();
 
         let faulty_tap = Tap {
             tap_file: unsafe { File::from_raw_fd(-2) },
@@ -306,6 +335,10 @@ pub mod tests {
             faulty_tap.set_offload(0).unwrap_err().to_string(),
             TapError::SetOffloadFlags(IoError::from_raw_os_error(9)).to_string()
         );
+        assert_eq!(
+            faulty_tap.set_persist(false).unwrap_err().to_string(),
+            TapError::SetPersist(IoError::from_raw_os_error(9)).to_string()
+        );
     }
 
     #[test]
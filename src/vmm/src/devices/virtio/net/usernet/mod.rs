@@ -0,0 +1,1105 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! User-mode network stack serving a net device in place of a tap, for hosts where no tap can
+//! be created. It relays the TCP connections and UDP datagrams of the guest through host
+//! sockets, and forwards host TCP ports to the guest.
+//!
+//! The device exchanges its frames with the stack through a Unix datagram socket pair, whose
+//! device end stands for the tap. The guest only reaches the host loopback interface through the
+//! ports of its gateway the configuration relays there. Other protocols, such as ICMP, are not
+//! relayed.
+
+mod tcp;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, PAYLOAD_OFFSET};
+use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_TCP, PROTOCOL_UDP};
+use dumbo::pdu::tcp::{Flags as TcpFlags, TcpSegment};
+use dumbo::pdu::udp::{UdpDatagram, UDP_HEADER_SIZE};
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use log::{error, warn};
+use logger::{IncMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::net::mac::MacAddr;
+use utils::time::{get_time_ms, ClockType};
+
+use self::tcp::TcpFlow;
+use super::device::vnet_hdr_len;
+use super::{Tap, MAX_BUFFER_SIZE};
+use crate::vmm_config::net::UserNetConfig;
+
+// MAC address the stack answers the ARP requests of the guest with.
+const GATEWAY_MAC: [u8; 6] = [0x06, 0x01, 0x23, 0x45, 0x67, 0x03];
+// Length of an IPv4 header without options.
+const IPV4_MIN_HEADER_LEN: usize = 20;
+// Maximum number of TCP connections and UDP flows relayed at once.
+const MAX_TCP_FLOWS: usize = 1024;
+const MAX_UDP_FLOWS: usize = 256;
+// Delay after which an idle UDP flow is dropped.
+const UDP_FLOW_TIMEOUT_MS: u64 = 120_000;
+// Period of the timer driving the retransmissions and the expiry of the UDP flows.
+const TIMER_PERIOD_MS: u64 = 250;
+// Maximum number of frames waiting for the device, besides the TCP segments which are only
+// written once the device can take them.
+const MAX_PENDING_FRAMES: usize = 256;
+// Maximum number of events, and of frames or datagrams read per event.
+const EVENTS_BATCH_LEN: usize = 64;
+// First port of the range the forwarded connections come from.
+const EPHEMERAL_PORT_MIN: u16 = 49152;
+
+/// Errors associated with the user-mode network stack.
+#[derive(Debug, thiserror::Error)]
+pub enum UserNetError {
+    /// The guest address is the gateway address.
+    #[error("The guest address is the gateway address: {0}")]
+    GuestIpIsGateway(Ipv4Addr),
+    /// A port forward has a zero port.
+    #[error("The ports of a port forward cannot be 0")]
+    InvalidPort,
+    /// Cannot listen on a forwarded host port.
+    #[error("Cannot listen on {0}: {1}")]
+    Listen(SocketAddrV4, io::Error),
+    /// Cannot create the socket pair of the device.
+    #[error("Cannot create the socket pair of the device: {0}")]
+    SocketPair(io::Error),
+    /// Cannot create or register with the epoll fd.
+    #[error("Epoll error: {0}")]
+    Epoll(io::Error),
+    /// Cannot create the timer.
+    #[error("Cannot create the timer: {0}")]
+    Timer(io::Error),
+}
+
+// Connection or flow of the guest, by the guest port and the address it exchanges with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    guest_port: u16,
+    remote: SocketAddrV4,
+}
+
+// What a file descriptor polled by the stack stands for.
+#[derive(Debug, Clone, Copy)]
+enum Endpoint {
+    Device,
+    Timer,
+    Listener(usize),
+    Tcp(FlowKey),
+    Udp(FlowKey),
+}
+
+#[derive(Debug)]
+struct UdpFlow {
+    socket: UdpSocket,
+    last_active_ms: u64,
+}
+
+/// User-mode network stack serving a net device.
+#[derive(Debug)]
+pub struct UserNet {
+    config: UserNetConfig,
+    guest_mac: Option<MacAddr>,
+    // Nested epoll fd the sockets of the stack are polled under.
+    epoll: Epoll,
+    // Stack end of the socket pair of the device.
+    socket: UnixDatagram,
+    // Whether the socket of the device is full, so it is polled for room.
+    socket_full: bool,
+    socket_polled: EventSet,
+    timer: TimerFd,
+    timer_armed: bool,
+    listeners: Vec<TcpListener>,
+    endpoints: HashMap<RawFd, Endpoint>,
+    tcp_flows: HashMap<FlowKey, TcpFlow>,
+    udp_flows: HashMap<FlowKey, UdpFlow>,
+    // Frames waiting for room in the socket of the device.
+    pending_frames: VecDeque<Vec<u8>>,
+    next_port: u16,
+    rx_buf: Vec<u8>,
+    tx_buf: Vec<u8>,
+}
+
+impl UserNet {
+    /// Creates the stack, along with the tap standing for the device end of its socket pair.
+    pub fn new(
+        config: UserNetConfig,
+        guest_mac: Option<MacAddr>,
+    ) -> Result<(Self, Tap), UserNetError> {
+        if config.guest_ip == config.gateway {
+            return Err(UserNetError::GuestIpIsGateway(config.guest_ip));
+        }
+        let mut listeners = Vec::with_capacity(config.port_forwards.len());
+        for forward in &config.port_forwards {
+            if forward.host_port == 0 || forward.guest_port == 0 {
+                return Err(UserNetError::InvalidPort);
+            }
+            let addr = SocketAddrV4::new(forward.host_address, forward.host_port);
+            let listener = TcpListener::bind(addr)
+                .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+                .map_err(|err| UserNetError::Listen(addr, err))?;
+            listeners.push(listener);
+        }
+
+        let (device_end, socket) = UnixDatagram::pair().map_err(UserNetError::SocketPair)?;
+        device_end
+            .set_nonblocking(true)
+            .and_then(|()| socket.set_nonblocking(true))
+            .map_err(UserNetError::SocketPair)?;
+        let timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(UserNetError::Timer)?;
+
+        let mut user_net = UserNet {
+            config,
+            guest_mac,
+            epoll: Epoll::new().map_err(UserNetError::Epoll)?,
+            socket,
+            socket_full: false,
+            socket_polled: EventSet::IN,
+            timer,
+            timer_armed: false,
+            listeners,
+            endpoints: HashMap::new(),
+            tcp_flows: HashMap::new(),
+            udp_flows: HashMap::new(),
+            pending_frames: VecDeque::new(),
+            next_port: EPHEMERAL_PORT_MIN,
+            rx_buf: vec![0u8; MAX_BUFFER_SIZE],
+            tx_buf: vec![0u8; MAX_BUFFER_SIZE],
+        };
+        user_net
+            .register(user_net.socket.as_raw_fd(), Endpoint::Device, EventSet::IN)
+            .and_then(|()| {
+                user_net.register(user_net.timer.as_raw_fd(), Endpoint::Timer, EventSet::IN)
+            })
+            .map_err(UserNetError::Epoll)?;
+        for index in 0..user_net.listeners.len() {
+            let fd = user_net.listeners[index].as_raw_fd();
+            user_net
+                .register(fd, Endpoint::Listener(index), EventSet::IN)
+                .map_err(UserNetError::Epoll)?;
+        }
+
+        Ok((user_net, Tap::from_socket(device_end)))
+    }
+
+    /// Provides the configuration of the stack.
+    pub fn config(&self) -> &UserNetConfig {
+        &self.config
+    }
+
+    fn register(&mut self, fd: RawFd, endpoint: Endpoint, evset: EventSet) -> io::Result<()> {
+        self.epoll.ctl(
+            ControlOperation::Add,
+            fd,
+            EpollEvent::new(evset, u64::try_from(fd).unwrap()),
+        )?;
+        self.endpoints.insert(fd, endpoint);
+        Ok(())
+    }
+
+    fn deregister(&mut self, fd: RawFd) {
+        if self.endpoints.remove(&fd).is_some() {
+            if let Err(err) = self
+                .epoll
+                .ctl(ControlOperation::Delete, fd, EpollEvent::default())
+            {
+                warn!(
+                    "user-mode network stack: cannot deregister fd {}: {}",
+                    fd, err
+                );
+            }
+        }
+    }
+
+    // Handles the events which occurred under the nested epoll fd.
+    fn process_events(&mut self) {
+        let mut events = vec![EpollEvent::new(EventSet::empty(), 0); EVENTS_BATCH_LEN];
+        let count = match self.epoll.wait(0, events.as_mut_slice()) {
+            Ok(count) => count,
+            Err(err) => {
+                error!("user-mode network stack: failed to consume events: {}", err);
+                METRICS.net.user_net_fails.inc();
+                return;
+            }
+        };
+        let now_ms = get_time_ms(ClockType::Monotonic);
+        for event in &events[..count] {
+            // It's ok to unwrap here, since the events are filled in by `epoll::wait()`.
+            let evset = EventSet::from_bits(event.events).unwrap();
+            // Endpoints removed by a previous event of the batch are gone.
+            match self.endpoints.get(&event.fd()).copied() {
+                Some(Endpoint::Device) => self.handle_device_event(evset, now_ms),
+                Some(Endpoint::Timer) => self.handle_timer(now_ms),
+                Some(Endpoint::Listener(index)) => self.accept_forwarded(index, now_ms),
+                Some(Endpoint::Tcp(key)) => {
+                    if let Some(flow) = self.tcp_flows.get_mut(&key) {
+                        flow.handle_host_event(evset);
+                    }
+                }
+                Some(Endpoint::Udp(key)) => self.receive_udp(key, now_ms),
+                None => (),
+            }
+        }
+        self.flush(now_ms);
+    }
+
+    fn handle_device_event(&mut self, evset: EventSet, now_ms: u64) {
+        if evset.contains(EventSet::OUT) {
+            self.socket_full = false;
+        }
+        if !evset.contains(EventSet::IN) {
+            return;
+        }
+        let mut buf = mem::take(&mut self.rx_buf);
+        for _ in 0..EVENTS_BATCH_LEN {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => {
+                    if self.handle_frame(&buf[..len], now_ms).is_none() {
+                        METRICS.net.user_net_drops.inc();
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!(
+                        "user-mode network stack: failed to receive a frame: {}",
+                        err
+                    );
+                    METRICS.net.user_net_fails.inc();
+                    break;
+                }
+            }
+        }
+        self.rx_buf = buf;
+    }
+
+    fn handle_frame(&mut self, buf: &[u8], now_ms: u64) -> Option<()> {
+        let eth = EthernetFrame::from_bytes(buf.get(vnet_hdr_len()..)?).ok()?;
+        self.guest_mac = Some(eth.src_mac());
+        match eth.ethertype() {
+            ETHERTYPE_ARP => self.answer_arp(eth.payload()),
+            ETHERTYPE_IPV4 => self.handle_ipv4(eth.payload(), now_ms),
+            _ => None,
+        }
+    }
+
+    fn answer_arp(&mut self, payload: &[u8]) -> Option<()> {
+        let arp = EthIPv4ArpFrame::request_from_bytes(payload.get(..ETH_IPV4_FRAME_LEN)?).ok()?;
+        // Every address but the one of the guest is reached through the stack.
+        if arp.tpa() == self.config.guest_ip {
+            return None;
+        }
+        let mut frame = vec![0u8; vnet_hdr_len() + PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN];
+        let mut eth = EthernetFrame::write_incomplete(
+            &mut frame[vnet_hdr_len()..],
+            arp.sha(),
+            MacAddr::from(GATEWAY_MAC),
+            ETHERTYPE_ARP,
+        )
+        .ok()?;
+        EthIPv4ArpFrame::write_reply(
+            eth.inner_mut().payload_mut(),
+            MacAddr::from(GATEWAY_MAC),
+            arp.tpa(),
+            arp.sha(),
+            arp.spa(),
+        )
+        .ok()?;
+        self.queue_frame(frame);
+        Some(())
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8], now_ms: u64) -> Option<()> {
+        if packet.len() < IPV4_MIN_HEADER_LEN {
+            return None;
+        }
+        // Short frames are padded past the end of the packet.
+        let total_len = usize::from(IPv4Packet::from_bytes_unchecked(packet).total_len());
+        let ip = IPv4Packet::from_bytes(packet.get(..total_len)?, false).ok()?;
+        let dst_addr = ip.destination_address();
+        if ip.source_address() != self.config.guest_ip
+            || dst_addr.is_broadcast()
+            || dst_addr.is_multicast()
+            || dst_addr.is_unspecified()
+        {
+            return None;
+        }
+        match ip.protocol() {
+            PROTOCOL_TCP => self.handle_tcp(ip.payload(), dst_addr, now_ms),
+            PROTOCOL_UDP => self.handle_udp(ip.payload(), dst_addr, now_ms),
+            _ => None,
+        }
+    }
+
+    fn handle_tcp(&mut self, bytes: &[u8], dst_addr: Ipv4Addr, now_ms: u64) -> Option<()> {
+        // The guest may offload the checksum, which is then left incomplete.
+        let segment = TcpSegment::from_bytes(bytes, None).ok()?;
+        let key = FlowKey {
+            guest_port: segment.source_port(),
+            remote: SocketAddrV4::new(dst_addr, segment.destination_port()),
+        };
+        if let Some(flow) = self.tcp_flows.get_mut(&key) {
+            flow.receive_segment(&segment, now_ms);
+            return Some(());
+        }
+
+        let flags = segment.flags_after_ns();
+        if flags.contains(TcpFlags::RST) {
+            return Some(());
+        }
+        if flags.contains(TcpFlags::SYN)
+            && !flags.contains(TcpFlags::ACK)
+            && self.tcp_flows.len() < MAX_TCP_FLOWS
+        {
+            if let Some(Ok(stream)) = self.host_destination(key.remote).map(connect_nonblocking) {
+                METRICS.net.user_net_tcp_conns.inc();
+                let iss = utils::rand::xor_pseudo_rng_u32();
+                let flow = TcpFlow::connect(stream, &segment, iss, now_ms);
+                self.tcp_flows.insert(key, flow);
+                return Some(());
+            }
+        }
+
+        // The segment is refused.
+        let mut frame = vec![0u8; MAX_BUFFER_SIZE];
+        let guest_ip = self.config.guest_ip;
+        let len = write_ipv4_frame(
+            &mut frame,
+            self.guest_mac?,
+            dst_addr,
+            guest_ip,
+            PROTOCOL_TCP,
+            |buf| tcp::write_reset(buf, &segment, dst_addr, guest_ip),
+        )?;
+        frame.truncate(len);
+        self.queue_frame(frame);
+        Some(())
+    }
+
+    fn handle_udp(&mut self, bytes: &[u8], dst_addr: Ipv4Addr, now_ms: u64) -> Option<()> {
+        // The guest may offload the checksum, which is then left incomplete.
+        let datagram = UdpDatagram::from_bytes(bytes, None).ok()?;
+        let payload_len = usize::from(datagram.len()).checked_sub(UDP_HEADER_SIZE)?;
+        let payload = datagram.payload().get(..payload_len)?;
+        let key = FlowKey {
+            guest_port: datagram.source_port(),
+            remote: SocketAddrV4::new(dst_addr, datagram.destination_port()),
+        };
+        if !self.udp_flows.contains_key(&key) {
+            let destination = self.host_destination(key.remote)?;
+            if let Err(err) = self.open_udp_flow(key, destination, now_ms) {
+                warn!("user-mode network stack: cannot open a UDP flow: {}", err);
+                return None;
+            }
+        }
+        let flow = self.udp_flows.get_mut(&key)?;
+        flow.last_active_ms = now_ms;
+        // Datagrams the host cannot take right away are dropped, as they would be on a wire.
+        flow.socket.send(payload).ok().map(|_| ())
+    }
+
+    fn open_udp_flow(
+        &mut self,
+        key: FlowKey,
+        destination: SocketAddrV4,
+        now_ms: u64,
+    ) -> io::Result<()> {
+        if self.udp_flows.len() >= MAX_UDP_FLOWS {
+            // Make room by dropping the least recently active flow.
+            let oldest = self
+                .udp_flows
+                .iter()
+                .min_by_key(|(_, flow)| flow.last_active_ms)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.close_udp_flow(oldest);
+            }
+        }
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_nonblocking(true)?;
+        socket.connect(destination)?;
+        self.register(socket.as_raw_fd(), Endpoint::Udp(key), EventSet::IN)?;
+        METRICS.net.user_net_udp_flows.inc();
+        self.udp_flows.insert(
+            key,
+            UdpFlow {
+                socket,
+                last_active_ms: now_ms,
+            },
+        );
+        Ok(())
+    }
+
+    fn close_udp_flow(&mut self, key: FlowKey) {
+        if let Some(flow) = self.udp_flows.remove(&key) {
+            self.deregister(flow.socket.as_raw_fd());
+        }
+    }
+
+    fn receive_udp(&mut self, key: FlowKey, now_ms: u64) {
+        let Some(guest_mac) = self.guest_mac else {
+            return;
+        };
+        let guest_ip = self.config.guest_ip;
+        let mut buf = mem::take(&mut self.rx_buf);
+        for _ in 0..EVENTS_BATCH_LEN {
+            let Some(flow) = self.udp_flows.get_mut(&key) else {
+                break;
+            };
+            let len = match flow.socket.recv(&mut buf) {
+                Ok(len) => len,
+                // Errors, such as a refused datagram, are reported once.
+                Err(_) => break,
+            };
+            flow.last_active_ms = now_ms;
+            let payload = &buf[..len];
+            let mut frame =
+                vec![
+                    0u8;
+                    vnet_hdr_len() + PAYLOAD_OFFSET + IPV4_MIN_HEADER_LEN + UDP_HEADER_SIZE + len
+                ];
+            let frame_len = write_ipv4_frame(
+                &mut frame,
+                guest_mac,
+                *key.remote.ip(),
+                guest_ip,
+                PROTOCOL_UDP,
+                |buf| {
+                    let datagram = UdpDatagram::write_incomplete_datagram(buf, payload)
+                        .ok()?
+                        .finalize(
+                            key.remote.port(),
+                            key.guest_port,
+                            Some((*key.remote.ip(), guest_ip)),
+                        );
+                    Some(usize::from(datagram.len()))
+                },
+            );
+            match frame_len {
+                Some(frame_len) => {
+                    frame.truncate(frame_len);
+                    self.queue_frame(frame);
+                }
+                None => METRICS.net.user_net_drops.inc(),
+            }
+        }
+        self.rx_buf = buf;
+    }
+
+    fn accept_forwarded(&mut self, index: usize, now_ms: u64) {
+        loop {
+            let stream = match self.listeners[index].accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!(
+                        "user-mode network stack: cannot accept a connection: {}",
+                        err
+                    );
+                    METRICS.net.user_net_fails.inc();
+                    break;
+                }
+            };
+            // Refused connections are closed as the stream is dropped.
+            if self.tcp_flows.len() >= MAX_TCP_FLOWS || stream.set_nonblocking(true).is_err() {
+                METRICS.net.user_net_fails.inc();
+                continue;
+            }
+            let Some(key) = self.allocate_forward_key(self.config.port_forwards[index].guest_port)
+            else {
+                METRICS.net.user_net_fails.inc();
+                continue;
+            };
+            METRICS.net.user_net_tcp_conns.inc();
+            let iss = utils::rand::xor_pseudo_rng_u32();
+            self.tcp_flows
+                .insert(key, TcpFlow::accept(stream, iss, now_ms));
+        }
+    }
+
+    // Picks the gateway port a forwarded connection comes from.
+    fn allocate_forward_key(&mut self, guest_port: u16) -> Option<FlowKey> {
+        for _ in EPHEMERAL_PORT_MIN..=u16::MAX {
+            let key = FlowKey {
+                guest_port,
+                remote: SocketAddrV4::new(self.config.gateway, self.next_port),
+            };
+            self.next_port = self.next_port.checked_add(1).unwrap_or(EPHEMERAL_PORT_MIN);
+            if !self.tcp_flows.contains_key(&key) {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    fn handle_timer(&mut self, now_ms: u64) {
+        self.timer.read();
+        for flow in self.tcp_flows.values_mut() {
+            flow.on_timer(now_ms);
+        }
+        let expired: Vec<FlowKey> = self
+            .udp_flows
+            .iter()
+            .filter(|(_, flow)| now_ms.saturating_sub(flow.last_active_ms) >= UDP_FLOW_TIMEOUT_MS)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            self.close_udp_flow(key);
+        }
+    }
+
+    // Returns the host address the traffic of the guest to `remote` is relayed to, if any. The
+    // ports of the gateway allowed by the configuration are relayed to the host loopback
+    // interface, which the guest cannot reach otherwise. Neither can it reach the addresses
+    // which the host takes for its own (the unspecified address connects to the host itself),
+    // the broadcast and multicast groups, nor the link-local services such as the cloud
+    // metadata servers.
+    fn host_destination(&self, remote: SocketAddrV4) -> Option<SocketAddrV4> {
+        let ip = remote.ip();
+        if *ip == self.config.gateway {
+            if self.config.host_loopback_ports.contains(&remote.port()) {
+                Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, remote.port()))
+            } else {
+                None
+            }
+        } else if ip.is_loopback()
+            || ip.is_unspecified()
+            || ip.is_broadcast()
+            || ip.is_multicast()
+            || ip.is_link_local()
+        {
+            None
+        } else {
+            Some(remote)
+        }
+    }
+
+    fn queue_frame(&mut self, frame: Vec<u8>) {
+        if self.pending_frames.len() < MAX_PENDING_FRAMES {
+            self.pending_frames.push_back(frame);
+        } else {
+            METRICS.net.user_net_drops.inc();
+        }
+    }
+
+    // Sends the frames for the device until its socket is full, then updates what is polled.
+    fn flush(&mut self, now_ms: u64) {
+        while !self.socket_full {
+            if let Some(frame) = self.pending_frames.front() {
+                match self.socket.send(frame) {
+                    Ok(_) => (),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        self.socket_full = true;
+                        break;
+                    }
+                    Err(err) => {
+                        error!("user-mode network stack: failed to send a frame: {}", err);
+                        METRICS.net.user_net_fails.inc();
+                    }
+                }
+                self.pending_frames.pop_front();
+            } else if !self.send_tcp_segments(now_ms) {
+                break;
+            }
+        }
+
+        let done: Vec<FlowKey> = self
+            .tcp_flows
+            .iter()
+            .filter(|(_, flow)| flow.is_done())
+            .map(|(key, _)| *key)
+            .collect();
+        for key in done {
+            if let Some(flow) = self.tcp_flows.remove(&key) {
+                self.deregister(flow.stream().as_raw_fd());
+            }
+        }
+        let keys: Vec<FlowKey> = self.tcp_flows.keys().copied().collect();
+        for key in keys {
+            self.update_tcp_interest(key);
+        }
+        self.update_socket_interest();
+        self.update_timer();
+    }
+
+    // Sends a segment of every TCP connection which has one, and returns whether any did.
+    fn send_tcp_segments(&mut self, now_ms: u64) -> bool {
+        let Some(guest_mac) = self.guest_mac else {
+            return false;
+        };
+        let guest_ip = self.config.guest_ip;
+        let mut sent = false;
+        for (key, flow) in self.tcp_flows.iter_mut() {
+            if self.socket_full {
+                break;
+            }
+            if !flow.has_output() {
+                continue;
+            }
+            let guest = SocketAddrV4::new(guest_ip, key.guest_port);
+            let Some(len) = write_ipv4_frame(
+                &mut self.tx_buf,
+                guest_mac,
+                *key.remote.ip(),
+                guest_ip,
+                PROTOCOL_TCP,
+                |buf| flow.write_next_segment(buf, key.remote, guest, now_ms),
+            ) else {
+                continue;
+            };
+            sent = true;
+            match self.socket.send(&self.tx_buf[..len]) {
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    // The segment goes out first once there is room.
+                    self.socket_full = true;
+                    self.pending_frames.push_front(self.tx_buf[..len].to_vec());
+                }
+                Err(err) => {
+                    error!("user-mode network stack: failed to send a frame: {}", err);
+                    METRICS.net.user_net_fails.inc();
+                }
+            }
+        }
+        sent
+    }
+
+    // Polls the host socket of a TCP connection for what it waits on, if anything.
+    fn update_tcp_interest(&mut self, key: FlowKey) {
+        let Some(flow) = self.tcp_flows.get_mut(&key) else {
+            return;
+        };
+        let interest = flow.interest();
+        if interest == flow.polled {
+            return;
+        }
+        let fd = flow.stream().as_raw_fd();
+        let operation = if flow.polled.is_empty() {
+            self.endpoints.insert(fd, Endpoint::Tcp(key));
+            ControlOperation::Add
+        } else if interest.is_empty() {
+            self.endpoints.remove(&fd);
+            ControlOperation::Delete
+        } else {
+            ControlOperation::Modify
+        };
+        if let Err(err) = self.epoll.ctl(
+            operation,
+            fd,
+            EpollEvent::new(interest, u64::try_from(fd).unwrap()),
+        ) {
+            error!(
+                "user-mode network stack: cannot poll a TCP connection: {}",
+                err
+            );
+            METRICS.net.user_net_fails.inc();
+        }
+        flow.polled = interest;
+    }
+
+    fn update_socket_interest(&mut self) {
+        let interest = if self.socket_full {
+            EventSet::IN | EventSet::OUT
+        } else {
+            EventSet::IN
+        };
+        if interest == self.socket_polled {
+            return;
+        }
+        let fd = self.socket.as_raw_fd();
+        if let Err(err) = self.epoll.ctl(
+            ControlOperation::Modify,
+            fd,
+            EpollEvent::new(interest, u64::try_from(fd).unwrap()),
+        ) {
+            error!(
+                "user-mode network stack: cannot poll the device socket: {}",
+                err
+            );
+            METRICS.net.user_net_fails.inc();
+        }
+        self.socket_polled = interest;
+    }
+
+    // The timer only runs while there are connections or flows.
+    fn update_timer(&mut self) {
+        let active = !self.tcp_flows.is_empty() || !self.udp_flows.is_empty();
+        if active == self.timer_armed {
+            return;
+        }
+        let state = if active {
+            let period = Duration::from_millis(TIMER_PERIOD_MS);
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            }
+        } else {
+            TimerState::Disarmed
+        };
+        self.timer.set_state(state, SetTimeFlags::Default);
+        self.timer_armed = active;
+    }
+}
+
+impl MutEventSubscriber for UserNet {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        if event.fd() == self.epoll.as_raw_fd() {
+            self.process_events();
+        } else {
+            warn!(
+                "user-mode network stack: Spurious event received: {:?}",
+                event.fd()
+            );
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.epoll, EventSet::IN)) {
+            error!("Failed to register the user-mode network stack: {}", err);
+        }
+    }
+}
+
+// Writes an IPv4 frame for the guest to `buf`, preceded by the vnet header, with the payload
+// `write_payload` writes. Returns the length of the frame.
+fn write_ipv4_frame<F>(
+    buf: &mut [u8],
+    guest_mac: MacAddr,
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+    protocol: u8,
+    write_payload: F,
+) -> Option<usize>
+where
+    F: FnOnce(&mut [u8]) -> Option<usize>,
+{
+    let (vnet_hdr, frame) = buf.split_at_mut(vnet_hdr_len());
+    vnet_hdr.fill(0);
+    let mut eth = EthernetFrame::write_incomplete(
+        frame,
+        guest_mac,
+        MacAddr::from(GATEWAY_MAC),
+        ETHERTYPE_IPV4,
+    )
+    .ok()?;
+    let mut ip =
+        IPv4Packet::write_header(eth.inner_mut().payload_mut(), protocol, src_addr, dst_addr)
+            .ok()?;
+    let payload_len = write_payload(ip.inner_mut().payload_mut())?;
+    let ip_len = ip.with_payload_len_unchecked(payload_len, true).len();
+    Some(vnet_hdr_len() + eth.with_payload_len_unchecked(ip_len).len())
+}
+
+// Opens a TCP connection to `addr` without waiting for it to complete. The completion is
+// reported by the socket becoming writable.
+fn connect_nonblocking(addr: SocketAddrV4) -> io::Result<TcpStream> {
+    // SAFETY: Safe because the arguments are valid and the return value is checked.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The fd was just opened and nothing else owns it.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let sockaddr = libc::sockaddr_in {
+        sin_family: u16::try_from(libc::AF_INET).unwrap(),
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: Safe because the address is a valid `sockaddr_in` of the given length, and the
+    // return value is checked.
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            std::ptr::addr_of!(sockaddr).cast::<libc::sockaddr>(),
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(err);
+        }
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::str::FromStr;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::vmm_config::net::PortForwardConfig;
+
+    const GUEST_MAC: &str = "12:34:56:78:9a:bc";
+    const GUEST_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+    const GUEST_PORT: u16 = 40000;
+
+    fn config(port_forwards: Vec<PortForwardConfig>) -> UserNetConfig {
+        UserNetConfig {
+            guest_ip: GUEST_IP,
+            gateway: GATEWAY,
+            host_loopback_ports: vec![],
+            port_forwards,
+        }
+    }
+
+    fn guest_frame(ethertype: u16, write_payload: impl FnOnce(&mut [u8]) -> usize) -> Vec<u8> {
+        let mut buf = vec![0u8; 2048];
+        let mut eth = EthernetFrame::write_incomplete(
+            &mut buf[vnet_hdr_len()..],
+            MacAddr::from(GATEWAY_MAC),
+            MacAddr::from_str(GUEST_MAC).unwrap(),
+            ethertype,
+        )
+        .unwrap();
+        let len = write_payload(eth.inner_mut().payload_mut());
+        let len = eth.with_payload_len_unchecked(len).len();
+        buf.truncate(vnet_hdr_len() + len);
+        buf
+    }
+
+    fn guest_tcp_frame(port: u16, seq: u32, ack: u32, flags: TcpFlags, payload: &[u8]) -> Vec<u8> {
+        guest_frame(ETHERTYPE_IPV4, |buf| {
+            let mut ip = IPv4Packet::write_header(buf, PROTOCOL_TCP, GUEST_IP, GATEWAY).unwrap();
+            let len = TcpSegment::write_segment::<[u8]>(
+                ip.inner_mut().payload_mut(),
+                GUEST_PORT,
+                port,
+                seq,
+                ack,
+                flags,
+                65535,
+                None,
+                u16::MAX,
+                (!payload.is_empty()).then_some((payload, payload.len())),
+                Some((GUEST_IP, GATEWAY)),
+            )
+            .unwrap()
+            .len();
+            ip.with_payload_len_unchecked(len, true).len()
+        })
+    }
+
+    // Processes the events of the stack until it sends a frame to the device.
+    fn next_frame(user_net: &mut UserNet, tap: &mut Tap) -> Vec<u8> {
+        let start = Instant::now();
+        let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+        loop {
+            match tap.read(&mut buf) {
+                Ok(len) => {
+                    buf.truncate(len);
+                    return buf;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    assert!(start.elapsed() < Duration::from_secs(5));
+                    user_net.process_events();
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    // Parses the TCP segment of a frame for the guest.
+    fn tcp_segment(frame: &[u8]) -> (u32, u32, TcpFlags, Vec<u8>) {
+        let eth = EthernetFrame::from_bytes(&frame[vnet_hdr_len()..]).unwrap();
+        assert_eq!(eth.dst_mac(), MacAddr::from_str(GUEST_MAC).unwrap());
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.destination_address(), GUEST_IP);
+        let segment = TcpSegment::from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        )
+        .unwrap();
+        assert_eq!(segment.destination_port(), GUEST_PORT);
+        (
+            segment.sequence_number(),
+            segment.ack_number(),
+            segment.flags_after_ns(),
+            segment.payload().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let mut cfg = config(vec![]);
+        cfg.guest_ip = GATEWAY;
+        assert!(matches!(
+            UserNet::new(cfg, None),
+            Err(UserNetError::GuestIpIsGateway(_))
+        ));
+
+        let cfg = config(vec![PortForwardConfig {
+            host_address: Ipv4Addr::LOCALHOST,
+            host_port: 0,
+            guest_port: 80,
+        }]);
+        assert!(matches!(
+            UserNet::new(cfg, None),
+            Err(UserNetError::InvalidPort)
+        ));
+    }
+
+    #[test]
+    fn test_arp() {
+        let (mut user_net, mut tap) = UserNet::new(config(vec![]), None).unwrap();
+        let request = guest_frame(ETHERTYPE_ARP, |buf| {
+            EthIPv4ArpFrame::write_request(
+                buf,
+                MacAddr::from_str(GUEST_MAC).unwrap(),
+                GUEST_IP,
+                MacAddr::from([0; 6]),
+                GATEWAY,
+            )
+            .unwrap()
+            .len()
+        });
+        tap.write_all(&request).unwrap();
+
+        let reply = next_frame(&mut user_net, &mut tap);
+        let eth = EthernetFrame::from_bytes(&reply[vnet_hdr_len()..]).unwrap();
+        assert_eq!(eth.ethertype(), ETHERTYPE_ARP);
+        let arp = EthIPv4ArpFrame::from_bytes_unchecked(eth.payload());
+        assert_eq!(arp.sha(), MacAddr::from(GATEWAY_MAC));
+        assert_eq!(arp.spa(), GATEWAY);
+        assert_eq!(arp.tpa(), GUEST_IP);
+    }
+
+    #[test]
+    fn test_outbound_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut cfg = config(vec![]);
+        cfg.host_loopback_ports = vec![port];
+        let (mut user_net, mut tap) = UserNet::new(cfg, None).unwrap();
+
+        // The connection to the gateway reaches the host loopback interface.
+        tap.write_all(&guest_tcp_frame(port, 100, 0, TcpFlags::SYN, &[]))
+            .unwrap();
+        let (iss, ack, flags, _) = tcp_segment(&next_frame(&mut user_net, &mut tap));
+        assert_eq!(flags, TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(ack, 101);
+        let (mut host, _) = listener.accept().unwrap();
+
+        tap.write_all(&guest_tcp_frame(
+            port,
+            101,
+            iss.wrapping_add(1),
+            TcpFlags::ACK | TcpFlags::PSH,
+            b"ping",
+        ))
+        .unwrap();
+        let (_, ack, _, _) = tcp_segment(&next_frame(&mut user_net, &mut tap));
+        assert_eq!(ack, 105);
+        let mut buf = [0u8; 4];
+        host.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        host.write_all(b"pong").unwrap();
+        let (seq, _, _, payload) = tcp_segment(&next_frame(&mut user_net, &mut tap));
+        assert_eq!(seq, iss.wrapping_add(1));
+        assert_eq!(payload, b"pong");
+
+        // Segments of unknown connections are reset.
+        tap.write_all(&guest_tcp_frame(port + 1, 7, 8, TcpFlags::ACK, &[]))
+            .unwrap();
+        let (seq, _, flags, _) = tcp_segment(&next_frame(&mut user_net, &mut tap));
+        assert_eq!(flags, TcpFlags::RST);
+        assert_eq!(seq, 8);
+
+        // The connections to the other ports of the gateway are refused.
+        tap.write_all(&guest_tcp_frame(port + 1, 7, 0, TcpFlags::SYN, &[]))
+            .unwrap();
+        let (_, ack, flags, _) = tcp_segment(&next_frame(&mut user_net, &mut tap));
+        assert_eq!(flags, TcpFlags::RST | TcpFlags::ACK);
+        assert_eq!(ack, 8);
+    }
+
+    #[test]
+    fn test_host_destination() {
+        let mut cfg = config(vec![]);
+        cfg.host_loopback_ports = vec![8080];
+        let (user_net, _tap) = UserNet::new(cfg, None).unwrap();
+
+        assert_eq!(
+            user_net.host_destination(SocketAddrV4::new(GATEWAY, 8080)),
+            Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8080))
+        );
+        assert_eq!(
+            user_net.host_destination(SocketAddrV4::new(GATEWAY, 22)),
+            None
+        );
+        // The host loopback interface cannot be reached directly.
+        assert_eq!(
+            user_net.host_destination(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 53), 8080)),
+            None
+        );
+        // Neither can the host through the unspecified address, the broadcast and multicast
+        // groups, nor the link-local services.
+        for ip in [
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+            Ipv4Addr::new(224, 0, 0, 251),
+            Ipv4Addr::new(239, 255, 255, 250),
+            Ipv4Addr::new(169, 254, 169, 254),
+        ] {
+            assert_eq!(
+                user_net.host_destination(SocketAddrV4::new(ip, 8080)),
+                None,
+                "{} is reachable",
+                ip
+            );
+        }
+        let remote = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 53);
+        assert_eq!(user_net.host_destination(remote), Some(remote));
+    }
+
+    #[test]
+    fn test_port_forward() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let forward = PortForwardConfig {
+            host_address: Ipv4Addr::LOCALHOST,
+            host_port: port,
+            guest_port: 8080,
+        };
+        let (mut user_net, mut tap) = UserNet::new(
+            config(vec![forward]),
+            Some(MacAddr::from_str(GUEST_MAC).unwrap()),
+        )
+        .unwrap();
+
+        let _host = TcpStream::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).unwrap();
+        let frame = next_frame(&mut user_net, &mut tap);
+        let eth = EthernetFrame::from_bytes(&frame[vnet_hdr_len()..]).unwrap();
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), GATEWAY);
+        let segment = TcpSegment::from_bytes(ip.payload(), None).unwrap();
+        assert_eq!(segment.destination_port(), 8080);
+        assert!(segment.source_port() >= EPHEMERAL_PORT_MIN);
+        assert_eq!(segment.flags_after_ns(), TcpFlags::SYN);
+    }
+}
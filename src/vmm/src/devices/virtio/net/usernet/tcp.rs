@@ -0,0 +1,653 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TCP connections relayed by the user-mode network stack between the guest and host sockets.
+//!
+//! The link to the guest neither loses nor reorders segments, so the guest side only paces the
+//! data to the window of the guest, and sends it again after a timeout for the segments the
+//! guest itself dropped, e.g. when short of memory.
+
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpStream};
+use std::num::Wrapping;
+
+use dumbo::pdu::tcp::{Flags as TcpFlags, TcpSegment};
+use utils::epoll::EventSet;
+
+/// Maximum segment size announced to the guest, which fits in an Ethernet frame.
+pub(super) const MSS: u16 = 1460;
+// MSS assumed for the guest when its SYN carries no MSS option.
+const DEFAULT_GUEST_MSS: u16 = 536;
+// Data read from the host and not acknowledged by the guest yet.
+const SEND_BUFFER_LEN: usize = 256 * 1024;
+// Data received from the guest and not written to the host yet. This is also the largest
+// window, which is not scaled.
+const RECEIVE_BUFFER_LEN: usize = 65535;
+// Delay after which the segments the guest did not acknowledge are sent again.
+pub(super) const RETRANSMIT_TIMEOUT_MS: u64 = 1000;
+// Number of retransmissions after which the connection is reset.
+const MAX_RETRANSMITS: u32 = 8;
+// Largest chunk read from the host at once.
+const READ_CHUNK_LEN: usize = 16 * 1024;
+
+// Whether the sequence number `a` comes before `b`.
+fn seq_lt(a: Wrapping<u32>, b: Wrapping<u32>) -> bool {
+    let distance = (b - a).0;
+    distance != 0 && distance < 1 << 31
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Opened by the guest, while the host socket connects.
+    Connecting,
+    /// Opened by the guest and connected on the host, the SYN-ACK is for the guest.
+    SynReceived,
+    /// Opened from the host, the SYN is for the guest.
+    SynSent,
+    /// Both sides are connected.
+    Established,
+    /// Reset by either side.
+    Closed,
+}
+
+/// A TCP connection of the guest, relayed to a host socket.
+#[derive(Debug)]
+pub(super) struct TcpFlow {
+    stream: TcpStream,
+    state: State,
+    // Sequence number of the SYN sent to the guest.
+    iss: Wrapping<u32>,
+    // Oldest sequence number the guest did not acknowledge.
+    snd_una: Wrapping<u32>,
+    // Next sequence number sent to the guest.
+    snd_nxt: Wrapping<u32>,
+    // Highest sequence number sent to the guest, which `snd_nxt` goes back from on retransmission.
+    snd_max: Wrapping<u32>,
+    // Window of the guest, from `snd_una`.
+    snd_wnd: u32,
+    // Sequence number of the FIN sent to the guest, if any.
+    fin_seq: Option<Wrapping<u32>>,
+    guest_mss: u16,
+    // Next sequence number expected from the guest.
+    rcv_nxt: Wrapping<u32>,
+    // Last window announced to the guest.
+    rcv_wnd: u32,
+    // Data read from the host which the guest did not acknowledge, starting after the SYN.
+    to_guest: VecDeque<u8>,
+    // Data received from the guest and not written to the host yet.
+    to_host: VecDeque<u8>,
+    // Whether the host stopped sending.
+    host_eof: bool,
+    // Whether the host hung up, so its socket only reports what is left to read.
+    host_hup: bool,
+    // Whether the guest stopped sending.
+    guest_fin: bool,
+    // Whether the host socket was shut down for writing after the FIN of the guest.
+    host_shutdown: bool,
+    ack_pending: bool,
+    rst_pending: bool,
+    // Time of the last acknowledgement progress, which retransmissions are timed from.
+    last_progress_ms: u64,
+    retransmits: u32,
+    /// Events the host socket is polled for.
+    pub polled: EventSet,
+}
+
+impl TcpFlow {
+    fn new(stream: TcpStream, state: State, iss: u32, now_ms: u64) -> Self {
+        let iss = Wrapping(iss);
+        TcpFlow {
+            stream,
+            state,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd: 0,
+            fin_seq: None,
+            guest_mss: DEFAULT_GUEST_MSS,
+            rcv_nxt: Wrapping(0),
+            rcv_wnd: 0,
+            to_guest: VecDeque::new(),
+            to_host: VecDeque::new(),
+            host_eof: false,
+            host_hup: false,
+            guest_fin: false,
+            host_shutdown: false,
+            ack_pending: false,
+            rst_pending: false,
+            last_progress_ms: now_ms,
+            retransmits: 0,
+            polled: EventSet::empty(),
+        }
+    }
+
+    /// Starts relaying the connection the guest opened with `syn`, while `stream` connects.
+    pub fn connect(stream: TcpStream, syn: &TcpSegment<&[u8]>, iss: u32, now_ms: u64) -> Self {
+        let mut flow = Self::new(stream, State::Connecting, iss, now_ms);
+        flow.rcv_nxt = Wrapping(syn.sequence_number()) + Wrapping(1);
+        flow.snd_wnd = u32::from(syn.window_size());
+        flow.guest_mss = guest_mss(syn);
+        flow
+    }
+
+    /// Starts relaying a connection accepted on the host, by opening it to the guest.
+    pub fn accept(stream: TcpStream, iss: u32, now_ms: u64) -> Self {
+        Self::new(stream, State::SynSent, iss, now_ms)
+    }
+
+    /// Provides the host socket of the connection.
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Whether the connection is over on both sides, so it can be dropped.
+    pub fn is_done(&self) -> bool {
+        let fin_acked = self.fin_seq.map_or(false, |seq| seq_lt(seq, self.snd_una));
+        (self.state == State::Closed && !self.rst_pending)
+            || (self.guest_fin && self.host_shutdown && fin_acked)
+    }
+
+    /// Whether the connection has a segment for the guest.
+    pub fn has_output(&self) -> bool {
+        match self.state {
+            State::Connecting | State::Closed => self.rst_pending,
+            State::SynSent | State::SynReceived => self.rst_pending || self.snd_nxt == self.iss,
+            State::Established => {
+                self.rst_pending
+                    || self.ack_pending
+                    || self.sendable_len() > 0
+                    || (self.host_eof && self.fin_seq.is_none())
+            }
+        }
+    }
+
+    /// Events the host socket must be polled for, empty when the connection waits on the guest.
+    pub fn interest(&self) -> EventSet {
+        let wants_read = !self.host_eof && self.to_guest.len() < SEND_BUFFER_LEN;
+        match self.state {
+            State::Closed => EventSet::empty(),
+            State::Connecting => EventSet::OUT,
+            // A hung up socket keeps on reporting it, so it is only polled when it can be read.
+            _ if self.host_hup && !wants_read => EventSet::empty(),
+            _ => {
+                let mut evset = EventSet::empty();
+                if wants_read {
+                    evset |= EventSet::IN;
+                }
+                if !self.to_host.is_empty() {
+                    evset |= EventSet::OUT;
+                }
+                evset
+            }
+        }
+    }
+
+    /// Handles the events of the host socket.
+    pub fn handle_host_event(&mut self, evset: EventSet) {
+        if self.state == State::Connecting {
+            // Connection failures are reported as errors on the socket.
+            match self.stream.take_error() {
+                Ok(None) => self.state = State::SynReceived,
+                _ => self.rst_pending = true,
+            }
+            return;
+        }
+        if evset.contains(EventSet::ERROR) {
+            self.rst_pending = true;
+            return;
+        }
+        if evset.contains(EventSet::HANG_UP) {
+            self.host_hup = true;
+        }
+        if evset.intersects(EventSet::IN | EventSet::HANG_UP) {
+            self.read_host();
+        }
+        if evset.intersects(EventSet::OUT | EventSet::HANG_UP) {
+            self.write_host();
+        }
+    }
+
+    /// Handles a segment the guest sent on the connection.
+    pub fn receive_segment(&mut self, segment: &TcpSegment<&[u8]>, now_ms: u64) {
+        let flags = segment.flags_after_ns();
+        if flags.contains(TcpFlags::RST) {
+            self.state = State::Closed;
+            self.rst_pending = false;
+            return;
+        }
+        let mut seq = Wrapping(segment.sequence_number());
+        match self.state {
+            // The guest sent its SYN again.
+            State::Connecting | State::Closed => return,
+            State::SynSent => {
+                if !flags.contains(TcpFlags::SYN | TcpFlags::ACK)
+                    || Wrapping(segment.ack_number()) != self.iss + Wrapping(1)
+                {
+                    self.rst_pending = true;
+                    return;
+                }
+                seq += Wrapping(1);
+                self.rcv_nxt = seq;
+                self.guest_mss = guest_mss(segment);
+                self.state = State::Established;
+                self.ack_pending = true;
+            }
+            State::SynReceived => {
+                if flags.contains(TcpFlags::SYN) {
+                    // The guest sent its SYN again, so does the SYN-ACK follow.
+                    self.snd_nxt = self.iss;
+                    return;
+                }
+                if !flags.contains(TcpFlags::ACK) {
+                    return;
+                }
+                self.state = State::Established;
+            }
+            State::Established => {
+                if flags.contains(TcpFlags::SYN) {
+                    self.ack_pending = true;
+                    return;
+                }
+            }
+        }
+
+        if flags.contains(TcpFlags::ACK) {
+            self.process_ack(
+                Wrapping(segment.ack_number()),
+                segment.window_size(),
+                now_ms,
+            );
+        }
+        if self.state != State::Established {
+            return;
+        }
+
+        let payload = segment.payload();
+        if seq_lt(self.rcv_nxt, seq) {
+            // Data past a gap is never expected from the guest, so it is only acknowledged.
+            self.ack_pending = true;
+            return;
+        }
+        // Skip the data the guest sent again.
+        let skip = min(
+            usize::try_from((self.rcv_nxt - seq).0).unwrap_or(usize::MAX),
+            payload.len(),
+        );
+        let data = &payload[skip..];
+        let accepted = min(data.len(), RECEIVE_BUFFER_LEN - self.to_host.len());
+        self.to_host.extend(&data[..accepted]);
+        self.rcv_nxt += Wrapping(accepted as u32);
+        if !payload.is_empty() {
+            self.ack_pending = true;
+        }
+
+        seq += Wrapping(payload.len() as u32);
+        if flags.contains(TcpFlags::FIN) && seq == self.rcv_nxt && !self.guest_fin {
+            self.guest_fin = true;
+            self.rcv_nxt += Wrapping(1);
+            self.ack_pending = true;
+        } else if flags.contains(TcpFlags::FIN) {
+            self.ack_pending = true;
+        }
+        self.write_host();
+    }
+
+    /// Writes the next segment for the guest to `buf`, and returns its length.
+    pub fn write_next_segment(
+        &mut self,
+        buf: &mut [u8],
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        now_ms: u64,
+    ) -> Option<usize> {
+        let mut mss_option = None;
+        let mut payload_len = 0;
+        let flags = if self.rst_pending {
+            self.rst_pending = false;
+            self.state = State::Closed;
+            TcpFlags::RST | TcpFlags::ACK
+        } else {
+            match self.state {
+                State::Connecting | State::Closed => return None,
+                State::SynSent | State::SynReceived if self.snd_nxt == self.iss => {
+                    mss_option = Some(MSS);
+                    if self.state == State::SynSent {
+                        TcpFlags::SYN
+                    } else {
+                        TcpFlags::SYN | TcpFlags::ACK
+                    }
+                }
+                State::SynSent | State::SynReceived => return None,
+                State::Established => {
+                    payload_len = self.sendable_len();
+                    if payload_len > 0 {
+                        TcpFlags::ACK | TcpFlags::PSH
+                    } else if self.host_eof && self.fin_seq.is_none() {
+                        self.fin_seq = Some(self.snd_nxt);
+                        TcpFlags::FIN | TcpFlags::ACK
+                    } else if self.ack_pending {
+                        TcpFlags::ACK
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        };
+
+        let window = self.receive_window();
+        let offset = usize::try_from((self.snd_nxt - self.data_seq()).0).unwrap_or(0);
+        let offset = min(offset, self.to_guest.len());
+        let data = &self.to_guest.make_contiguous()[offset..];
+        let len = TcpSegment::write_segment::<[u8]>(
+            buf,
+            src.port(),
+            dst.port(),
+            self.snd_nxt.0,
+            self.rcv_nxt.0,
+            flags,
+            window,
+            mss_option,
+            u16::MAX,
+            (payload_len > 0).then_some((data, payload_len)),
+            Some((*src.ip(), *dst.ip())),
+        )
+        .ok()?
+        .len();
+
+        if flags.contains(TcpFlags::RST) {
+            return Some(len);
+        }
+        if self.snd_una == self.snd_max {
+            // A new flight starts, which retransmissions are timed from.
+            self.last_progress_ms = now_ms;
+        }
+        let mut seq_len = payload_len as u32;
+        if flags.intersects(TcpFlags::SYN | TcpFlags::FIN) {
+            seq_len += 1;
+        }
+        self.snd_nxt += Wrapping(seq_len);
+        if seq_lt(self.snd_max, self.snd_nxt) {
+            self.snd_max = self.snd_nxt;
+        }
+        self.rcv_wnd = u32::from(window);
+        self.ack_pending = false;
+        Some(len)
+    }
+
+    /// Sends the segments the guest did not acknowledge in time again, and gives up on the
+    /// connection after too many attempts.
+    pub fn on_timer(&mut self, now_ms: u64) {
+        if matches!(self.state, State::Connecting | State::Closed)
+            || self.snd_una == self.snd_max
+            || now_ms.saturating_sub(self.last_progress_ms) < RETRANSMIT_TIMEOUT_MS
+        {
+            return;
+        }
+        if self.retransmits >= MAX_RETRANSMITS {
+            self.rst_pending = true;
+            return;
+        }
+        self.retransmits += 1;
+        self.last_progress_ms = now_ms;
+        self.snd_nxt = self.snd_una;
+        if self.fin_seq.map_or(false, |seq| !seq_lt(seq, self.snd_nxt)) {
+            self.fin_seq = None;
+        }
+    }
+
+    // Sequence number of the first byte of `to_guest`, which follows the SYN.
+    fn data_seq(&self) -> Wrapping<u32> {
+        if self.snd_una == self.iss {
+            self.iss + Wrapping(1)
+        } else {
+            self.snd_una
+        }
+    }
+
+    // Number of bytes which can be sent in the next segment.
+    fn sendable_len(&self) -> usize {
+        let offset = usize::try_from((self.snd_nxt - self.data_seq()).0).unwrap_or(usize::MAX);
+        let unsent = self.to_guest.len().saturating_sub(offset);
+        let window_end = self.snd_una + Wrapping(self.snd_wnd);
+        let room = if seq_lt(self.snd_nxt, window_end) {
+            (window_end - self.snd_nxt).0 as usize
+        } else {
+            0
+        };
+        min(min(unsent, room), usize::from(self.guest_mss))
+    }
+
+    fn receive_window(&self) -> u16 {
+        u16::try_from(RECEIVE_BUFFER_LEN - self.to_host.len()).unwrap_or(u16::MAX)
+    }
+
+    fn process_ack(&mut self, ack: Wrapping<u32>, window: u16, now_ms: u64) {
+        // Acknowledgements of data which was never sent are ignored.
+        if seq_lt(self.snd_max, ack) || seq_lt(ack, self.snd_una) {
+            return;
+        }
+        self.snd_wnd = u32::from(window);
+        let mut acked = (ack - self.snd_una).0 as usize;
+        if acked == 0 {
+            return;
+        }
+        if self.snd_una == self.iss {
+            // The SYN is acknowledged.
+            acked -= 1;
+        }
+        let drained = min(acked, self.to_guest.len());
+        self.to_guest.drain(..drained);
+        self.snd_una = ack;
+        if seq_lt(self.snd_nxt, self.snd_una) {
+            self.snd_nxt = self.snd_una;
+        }
+        self.last_progress_ms = now_ms;
+        self.retransmits = 0;
+    }
+
+    fn read_host(&mut self) {
+        let mut buf = [0u8; READ_CHUNK_LEN];
+        while !self.host_eof && self.to_guest.len() < SEND_BUFFER_LEN {
+            let len = min(buf.len(), SEND_BUFFER_LEN - self.to_guest.len());
+            match self.stream.read(&mut buf[..len]) {
+                Ok(0) => self.host_eof = true,
+                Ok(count) => self.to_guest.extend(&buf[..count]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.rst_pending = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn write_host(&mut self) {
+        while !self.to_host.is_empty() {
+            let (data, _) = self.to_host.as_slices();
+            match self.stream.write(data) {
+                Ok(count) => {
+                    self.to_host.drain(..count);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.rst_pending = true;
+                    return;
+                }
+            }
+        }
+        // Update the window of the guest once it reopened significantly.
+        if u32::from(self.receive_window()) >= self.rcv_wnd + 2 * u32::from(MSS)
+            && self.rcv_wnd < (RECEIVE_BUFFER_LEN / 2) as u32
+        {
+            self.ack_pending = true;
+        }
+        if self.guest_fin && self.to_host.is_empty() && !self.host_shutdown {
+            // The host may already be gone, in which case the FIN of the guest is moot.
+            let _ = self.stream.shutdown(Shutdown::Write);
+            self.host_shutdown = true;
+        }
+    }
+}
+
+fn guest_mss(segment: &TcpSegment<&[u8]>) -> u16 {
+    segment
+        .parse_mss_option_unchecked(segment.header_len())
+        .ok()
+        .flatten()
+        .map_or(DEFAULT_GUEST_MSS, |mss| min(mss.get(), MSS))
+}
+
+/// Writes a reset of the connection of a segment the stack has no connection for to `buf`,
+/// and returns its length.
+pub(super) fn write_reset(
+    buf: &mut [u8],
+    segment: &TcpSegment<&[u8]>,
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+) -> Option<usize> {
+    let flags = segment.flags_after_ns();
+    let mut seg_len = segment.payload_len() as u32;
+    if flags.intersects(TcpFlags::SYN | TcpFlags::FIN) {
+        seg_len += 1;
+    }
+    // A reset is acceptable with the sequence number the segment acknowledges, if any.
+    let (seq, reset_flags) = if flags.contains(TcpFlags::ACK) {
+        (segment.ack_number(), TcpFlags::RST)
+    } else {
+        (0, TcpFlags::RST | TcpFlags::ACK)
+    };
+    let segment = TcpSegment::write_segment::<[u8]>(
+        buf,
+        segment.destination_port(),
+        segment.source_port(),
+        seq,
+        segment.sequence_number().wrapping_add(seg_len),
+        reset_flags,
+        0,
+        None,
+        u16::MAX,
+        None,
+        Some((src_addr, dst_addr)),
+    )
+    .ok()?;
+    Some(segment.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    const GUEST: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 15), 40000);
+    const REMOTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 2), 8080);
+
+    fn guest_segment(seq: u32, ack: u32, flags: TcpFlags, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; 100 + payload.len()];
+        let len = TcpSegment::write_segment::<[u8]>(
+            buf.as_mut_slice(),
+            GUEST.port(),
+            REMOTE.port(),
+            seq,
+            ack,
+            flags,
+            10000,
+            None,
+            u16::MAX,
+            (!payload.is_empty()).then_some((payload, payload.len())),
+            None,
+        )
+        .unwrap()
+        .len();
+        buf.truncate(len);
+        buf
+    }
+
+    fn next_segment(flow: &mut TcpFlow) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; 2000];
+        let len = flow.write_next_segment(&mut buf, REMOTE, GUEST, 0)?;
+        buf.truncate(len);
+        Some(buf)
+    }
+
+    #[test]
+    fn test_accepted_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut host = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut flow = TcpFlow::accept(stream, 1000, 0);
+
+        // The connection opens to the guest with a SYN.
+        let syn = next_segment(&mut flow).unwrap();
+        let syn = TcpSegment::from_bytes(syn.as_slice(), None).unwrap();
+        assert_eq!(syn.flags_after_ns(), TcpFlags::SYN);
+        assert_eq!(syn.sequence_number(), 1000);
+        assert!(next_segment(&mut flow).is_none());
+
+        let syn_ack = guest_segment(5000, 1001, TcpFlags::SYN | TcpFlags::ACK, &[]);
+        flow.receive_segment(
+            &TcpSegment::from_bytes(syn_ack.as_slice(), None).unwrap(),
+            0,
+        );
+        let ack = next_segment(&mut flow).unwrap();
+        let ack = TcpSegment::from_bytes(ack.as_slice(), None).unwrap();
+        assert_eq!(ack.flags_after_ns(), TcpFlags::ACK);
+        assert_eq!(ack.ack_number(), 5001);
+
+        // Data flows from the guest to the host.
+        let data = guest_segment(5001, 1001, TcpFlags::ACK | TcpFlags::PSH, b"hello");
+        flow.receive_segment(&TcpSegment::from_bytes(data.as_slice(), None).unwrap(), 0);
+        let mut buf = [0u8; 5];
+        host.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        let ack = next_segment(&mut flow).unwrap();
+        let ack = TcpSegment::from_bytes(ack.as_slice(), None).unwrap();
+        assert_eq!(ack.ack_number(), 5006);
+
+        // And from the host to the guest.
+        host.write_all(b"world").unwrap();
+        host.shutdown(Shutdown::Write).unwrap();
+        while !flow.host_eof {
+            flow.handle_host_event(EventSet::IN);
+        }
+        let data = next_segment(&mut flow).unwrap();
+        let data = TcpSegment::from_bytes(data.as_slice(), None).unwrap();
+        assert_eq!(data.sequence_number(), 1001);
+        assert_eq!(data.payload(), b"world");
+        let fin = next_segment(&mut flow).unwrap();
+        let fin = TcpSegment::from_bytes(fin.as_slice(), None).unwrap();
+        assert_eq!(fin.flags_after_ns(), TcpFlags::FIN | TcpFlags::ACK);
+        assert_eq!(fin.sequence_number(), 1006);
+
+        // Without acknowledgement, the data is sent again after the timeout.
+        flow.on_timer(RETRANSMIT_TIMEOUT_MS);
+        let data = next_segment(&mut flow).unwrap();
+        let data = TcpSegment::from_bytes(data.as_slice(), None).unwrap();
+        assert_eq!(data.sequence_number(), 1001);
+        assert_eq!(data.payload(), b"world");
+        assert!(next_segment(&mut flow).is_some());
+
+        let fin = guest_segment(5006, 1007, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        flow.receive_segment(&TcpSegment::from_bytes(fin.as_slice(), None).unwrap(), 0);
+        assert!(flow.is_done());
+        assert_eq!(host.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let syn = guest_segment(7, 0, TcpFlags::SYN, &[]);
+        let syn = TcpSegment::from_bytes(syn.as_slice(), None).unwrap();
+        let mut buf = [0u8; 100];
+        let len = write_reset(&mut buf, &syn, *REMOTE.ip(), *GUEST.ip()).unwrap();
+        let rst = TcpSegment::from_bytes(&buf[..len], Some((*REMOTE.ip(), *GUEST.ip()))).unwrap();
+        assert_eq!(rst.flags_after_ns(), TcpFlags::RST | TcpFlags::ACK);
+        assert_eq!(rst.ack_number(), 8);
+        assert_eq!(rst.destination_port(), GUEST.port());
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::{cmp, fs};
+
+use logger::{error, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+use utils::vm_memory::{Bytes, GuestMemoryError, GuestMemoryMmap};
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+
+use super::server::{Server, MAX_MSIZE};
+use super::{MAX_MOUNT_TAG_LEN, P9_NUM_QUEUES, P9_QUEUE, VIRTIO_9P_MOUNT_TAG};
+use crate::devices::virtio::device::{IrqTrigger, IrqType};
+use crate::devices::virtio::{
+    ActivateError, DescriptorChain, DeviceState, Queue, QueueError, VirtioDevice,
+    FIRECRACKER_MAX_QUEUE_SIZE, TYPE_9P,
+};
+
+/// 9p device related errors.
+#[derive(Debug, thiserror::Error)]
+pub enum P9Error {
+    /// The mount tag is empty, too long or contains characters other than printable ASCII ones.
+    #[error(
+        "The mount tag must be a non-empty string of at most {MAX_MOUNT_TAG_LEN} printable ASCII \
+         characters, without whitespace: {0}"
+    )]
+    InvalidMountTag(String),
+    /// The shared directory cannot be accessed.
+    #[error("Cannot access the shared directory {0:?}: {1}")]
+    SharedDir(PathBuf, io::Error),
+    /// The shared path is not a directory.
+    #[error("The shared path is not a directory: {0:?}")]
+    NotADirectory(PathBuf),
+    /// EventFd error.
+    #[error("Error while handling an Event file descriptor: {0}")]
+    EventFd(#[from] io::Error),
+    /// Guest gave us bad memory addresses.
+    #[error("Bad guest memory buffer: {0}")]
+    GuestMemory(#[from] GuestMemoryError),
+    /// Received error while sending an interrupt.
+    #[error("Failed to send an interrupt: {0}")]
+    Interrupt(io::Error),
+    /// Guest gave us a malformed descriptor chain.
+    #[error("Received a malformed descriptor chain.")]
+    MalformedDescriptor,
+    /// Guest gave us a request too short to be answered.
+    #[error("Received a malformed request.")]
+    MalformedRequest,
+    /// The reply does not fit in the buffers provided by the guest.
+    #[error("The reply does not fit in the guest buffers.")]
+    ReplyTooLarge,
+    /// Error while processing the virt queues.
+    #[error("Error while processing the virtio queue: {0}")]
+    Queue(#[from] QueueError),
+}
+
+/// Virtio device sharing a host directory with the guest, which mounts it by its tag.
+#[derive(Debug)]
+pub struct P9 {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    // The length of the mount tag, followed by the tag.
+    pub(crate) config_space: Vec<u8>,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: [EventFd; P9_NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+    pub(crate) irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    pub(crate) id: String,
+    pub(crate) mount_tag: String,
+    server: Server,
+}
+
+impl P9 {
+    /// Creates a 9p device sharing the `shared_dir` host directory, which the guest mounts by
+    /// `mount_tag`.
+    pub fn new(
+        id: String,
+        mount_tag: String,
+        shared_dir: PathBuf,
+        read_only: bool,
+    ) -> Result<P9, P9Error> {
+        if mount_tag.is_empty()
+            || mount_tag.len() > MAX_MOUNT_TAG_LEN
+            || !mount_tag.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(P9Error::InvalidMountTag(mount_tag));
+        }
+        let metadata =
+            fs::metadata(&shared_dir).map_err(|err| P9Error::SharedDir(shared_dir.clone(), err))?;
+        if !metadata.is_dir() {
+            return Err(P9Error::NotADirectory(shared_dir));
+        }
+        let server = Server::new(shared_dir.clone(), read_only)
+            .map_err(|err| P9Error::SharedDir(shared_dir, err))?;
+
+        // Unwrapping is safe since the tag length is bounded above.
+        let mut config_space = u16::try_from(mount_tag.len())
+            .unwrap()
+            .to_le_bytes()
+            .to_vec();
+        config_space.extend_from_slice(mount_tag.as_bytes());
+
+        Ok(P9 {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_9P_MOUNT_TAG,
+            acked_features: 0u64,
+            config_space,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            queues: vec![Queue::new(FIRECRACKER_MAX_QUEUE_SIZE); P9_NUM_QUEUES],
+            queue_evts: [EventFd::new(libc::EFD_NONBLOCK)?],
+            device_state: DeviceState::Inactive,
+            irq_trigger: IrqTrigger::new()?,
+            id,
+            mount_tag,
+            server,
+        })
+    }
+
+    /// Provides the ID of this 9p device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Tag the guest mounts the shared directory by.
+    pub fn mount_tag(&self) -> &str {
+        &self.mount_tag
+    }
+
+    /// Host directory shared with the guest.
+    pub fn shared_dir(&self) -> &Path {
+        self.server.root()
+    }
+
+    /// Whether the guest is refused to modify the shared directory.
+    pub fn is_read_only(&self) -> bool {
+        self.server.is_read_only()
+    }
+
+    // Handles the request carried by the descriptor chain, returning the length of the reply.
+    // Requests are laid out in the device-readable descriptors, and replies in the
+    // device-writable ones which follow them.
+    fn handle_chain(
+        server: &mut Server,
+        mem: &GuestMemoryMmap,
+        head: DescriptorChain,
+    ) -> Result<u32, P9Error> {
+        let mut request = Vec::new();
+        let mut reply_bufs = Vec::new();
+        let mut next_desc = Some(head);
+        while let Some(desc) = next_desc {
+            let len = desc.len as usize;
+            if desc.is_write_only() {
+                reply_bufs.push((desc.addr, len));
+            } else {
+                if !reply_bufs.is_empty() || request.len() + len > MAX_MSIZE as usize {
+                    return Err(P9Error::MalformedDescriptor);
+                }
+                let start = request.len();
+                request.resize(start + len, 0);
+                mem.read_slice(&mut request[start..], desc.addr)?;
+            }
+            next_desc = desc.next_descriptor();
+        }
+
+        let reply = server.handle(&request).ok_or(P9Error::MalformedRequest)?;
+        let mut written = 0;
+        for (addr, len) in reply_bufs {
+            if written == reply.len() {
+                break;
+            }
+            let chunk = cmp::min(len, reply.len() - written);
+            mem.write_slice(&reply[written..written + chunk], addr)?;
+            written += chunk;
+        }
+        if written < reply.len() {
+            return Err(P9Error::ReplyTooLarge);
+        }
+        // Unwrapping is safe since replies are bounded by the negotiated message size.
+        Ok(u32::try_from(written).unwrap())
+    }
+
+    pub(crate) fn process_p9_queue_event(&mut self) -> Result<(), P9Error> {
+        self.queue_evts[P9_QUEUE].read()?;
+        self.process_p9_queue()
+    }
+
+    pub(crate) fn process_p9_queue(&mut self) -> Result<(), P9Error> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        METRICS.shared_dirs.queue_event_count.inc();
+
+        let mut used_any = false;
+        while let Some(head) = self.queues[P9_QUEUE].pop(mem) {
+            let index = head.index;
+
+            let len = match Self::handle_chain(&mut self.server, mem, head) {
+                Ok(len) => len,
+                Err(err) => {
+                    error!("virtio-9p: Failed to handle request: {:?}", err);
+                    METRICS.shared_dirs.event_fails.inc();
+                    0
+                }
+            };
+
+            self.queues[P9_QUEUE].add_used(mem, index, len)?;
+            used_any = true;
+        }
+
+        if used_any {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<(), P9Error> {
+        self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|err| {
+            METRICS.shared_dirs.event_fails.inc();
+            P9Error::Interrupt(err)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_p9_queue();
+    }
+
+    pub(crate) fn activate_evt(&self) -> &EventFd {
+        &self.activate_evt
+    }
+}
+
+impl VirtioDevice for P9 {
+    fn device_type(&self) -> u32 {
+        TYPE_9P
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_len = self.config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&self.config_space[offset as usize..cmp::min(end, config_len) as usize])
+                .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The virtio-9p configuration space is read-only for the driver.
+        error!("virtio-9p: Guest attempted to write the read-only config space");
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        self.device_state = DeviceState::Activated(mem);
+        if self.activate_evt.write(1).is_err() {
+            error!("virtio-9p: Cannot write to activate_evt");
+            METRICS.shared_dirs.activate_fails.inc();
+            self.device_state = DeviceState::Inactive;
+            return Err(ActivateError::BadActivate);
+        }
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempdir::TempDir;
+    use utils::vm_memory::GuestAddress;
+
+    use super::super::protocol::{HEADER_SIZE, TVERSION};
+    use super::super::server::tests::request;
+    use super::*;
+    use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::devices::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REQUEST_ADDR: u64 = 0x1000;
+    const REPLY_ADDR: u64 = 0x2000;
+
+    fn version_request() -> Vec<u8> {
+        request(TVERSION, |w| {
+            w.put_u32(8192);
+            w.put_str("9P2000.L");
+        })
+    }
+
+    #[test]
+    fn test_new() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().to_path_buf();
+
+        let long_tag = "a".repeat(MAX_MOUNT_TAG_LEN + 1);
+        for tag in ["", "two words", "caf\u{e9}", long_tag.as_str()] {
+            assert!(matches!(
+                P9::new("fs".to_string(), tag.to_string(), path.clone(), false),
+                Err(P9Error::InvalidMountTag(_))
+            ));
+        }
+        assert!(matches!(
+            P9::new(
+                "fs".to_string(),
+                "tag".to_string(),
+                path.join("missing"),
+                false
+            ),
+            Err(P9Error::SharedDir(_, _))
+        ));
+        let file = path.join("file");
+        fs::write(&file, b"").unwrap();
+        assert!(matches!(
+            P9::new("fs".to_string(), "tag".to_string(), file, false),
+            Err(P9Error::NotADirectory(_))
+        ));
+
+        let dev = P9::new("fs".to_string(), "share".to_string(), path.clone(), true).unwrap();
+        assert_eq!(dev.device_type(), TYPE_9P);
+        assert_eq!(dev.id(), "fs");
+        assert_eq!(dev.mount_tag(), "share");
+        assert_eq!(dev.shared_dir(), path);
+        assert!(dev.is_read_only());
+        assert_eq!(
+            dev.avail_features(),
+            1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_9P_MOUNT_TAG
+        );
+
+        let mut config = [0u8; 7];
+        dev.read_config(0, &mut config);
+        assert_eq!(config, [5, 0, b's', b'h', b'a', b'r', b'e']);
+        let mut config = [0u8; 4];
+        dev.read_config(4, &mut config);
+        assert_eq!(config, [b'a', b'r', b'e', 0]);
+    }
+
+    #[test]
+    fn test_process_queue() {
+        let dir = TempDir::new().unwrap();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let mut dev = P9::new(
+            "fs".to_string(),
+            "share".to_string(),
+            dir.as_path().to_path_buf(),
+            false,
+        )
+        .unwrap();
+        dev.queues[P9_QUEUE] = vq.create_queue();
+        dev.activate(mem.clone()).unwrap();
+
+        // The request and the reply are both split in two descriptors.
+        let request = version_request();
+        mem.write_slice(&request, GuestAddress(REQUEST_ADDR))
+            .unwrap();
+        vq.dtable[0].set(REQUEST_ADDR, 4, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(
+            REQUEST_ADDR + 4,
+            request.len() as u32 - 4,
+            VIRTQ_DESC_F_NEXT,
+            2,
+        );
+        vq.dtable[2].set(REPLY_ADDR, 8, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 3);
+        vq.dtable[3].set(REPLY_ADDR + 8, 0x100, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        dev.process_p9_queue().unwrap();
+        let reply_len = HEADER_SIZE + 4 + 2 + "9P2000.L".len();
+        vq.check_used_elem(0, 0, reply_len as u32);
+        let mut reply = vec![0u8; reply_len];
+        mem.read_slice(&mut reply, GuestAddress(REPLY_ADDR))
+            .unwrap();
+        assert_eq!(&reply[..5], &[reply_len as u8, 0, 0, 0, TVERSION + 1]);
+        assert_eq!(&reply[reply_len - 8..], b"9P2000.L");
+
+        // A reply which does not fit in the guest buffers is dropped.
+        vq.dtable[2].set(REPLY_ADDR, 8, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[1].set(0);
+        vq.avail.idx.set(2);
+        dev.process_p9_queue().unwrap();
+        vq.check_used_elem(1, 0, 0);
+
+        // Requests must precede the reply buffers.
+        vq.dtable[0].set(REPLY_ADDR, 0x100, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(REQUEST_ADDR, request.len() as u32, 0, 0);
+        vq.avail.ring[2].set(0);
+        vq.avail.idx.set(3);
+        dev.process_p9_queue().unwrap();
+        vq.check_used_elem(2, 0, 0);
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use log::{error, warn};
+use logger::{IncMetric, METRICS};
+use utils::epoll::EventSet;
+
+use super::{P9, P9_QUEUE};
+use crate::devices::virtio::VirtioDevice;
+
+impl P9 {
+    fn register_runtime_events(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.queue_evts[P9_QUEUE], EventSet::IN)) {
+            error!("virtio-9p: Failed to register queue event: {err}");
+        }
+    }
+
+    fn register_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(self.activate_evt(), EventSet::IN)) {
+            error!("virtio-9p: Failed to register activate event: {err}");
+        }
+    }
+
+    fn process_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = self.activate_evt().read() {
+            error!("virtio-9p: Failed to consume activate event: {err}");
+        }
+
+        // Register runtime events
+        self.register_runtime_events(ops);
+
+        // Remove activate event
+        if let Err(err) = ops.remove(Events::new(self.activate_evt(), EventSet::IN)) {
+            error!("virtio-9p: Failed to un-register activate event: {err}");
+        }
+    }
+}
+
+impl MutEventSubscriber for P9 {
+    fn init(&mut self, ops: &mut EventOps) {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point),
+        //  - on device restore from snapshot.
+        if self.is_activated() {
+            self.register_runtime_events(ops);
+        } else {
+            self.register_activate_event(ops);
+        }
+    }
+
+    fn process(&mut self, events: Events, ops: &mut EventOps) {
+        let event_set = events.event_set();
+        let source = events.fd();
+
+        if !event_set.contains(EventSet::IN) {
+            warn!("virtio-9p: Received unknown event: {event_set:?} from source {source}");
+            return;
+        }
+
+        if !self.is_activated() {
+            warn!("virtio-9p: The device is not activated yet. Spurious event received: {source}");
+            return;
+        }
+
+        if source == self.queue_evts[P9_QUEUE].as_raw_fd() {
+            if let Err(err) = self.process_p9_queue_event() {
+                error!("virtio-9p: Failed to process queue event: {err}");
+                METRICS.shared_dirs.event_fails.inc();
+            }
+        } else if source == self.activate_evt().as_raw_fd() {
+            self.process_activate_event(ops)
+        } else {
+            warn!("virtio-9p: Unknown event received: {source}");
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a virtio-9p device, sharing a host directory with the guest over the 9P2000.L
+//! protocol.
+
+pub mod device;
+mod event_handler;
+mod protocol;
+mod server;
+
+pub use self::device::{P9Error, P9};
+
+pub(crate) const P9_NUM_QUEUES: usize = 1;
+
+pub(crate) const P9_QUEUE: usize = 0;
+
+/// Maximum length of the tag the guest mounts the shared directory by.
+pub const MAX_MOUNT_TAG_LEN: usize = 32;
+
+// The feature bitmap for virtio-9p.
+// The device exposes its mount tag in the configuration space.
+const VIRTIO_9P_MOUNT_TAG: u32 = 0;
@@ -0,0 +1,243 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encoding and decoding of the 9P2000.L messages.
+//!
+//! Every message starts with a `size[4] type[1] tag[2]` header, followed by the fields of the
+//! message. Integers are little endian and strings are prefixed by their 2 bytes length.
+
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+
+/// Size of the header shared by all the messages.
+pub(crate) const HEADER_SIZE: usize = 7;
+/// Size of an encoded qid.
+pub(crate) const QID_SIZE: usize = 13;
+
+// Message types of the 9P2000.L dialect. The reply to a `T` message has the type of the request
+// plus one.
+pub(crate) const RLERROR: u8 = 7;
+pub(crate) const TSTATFS: u8 = 8;
+pub(crate) const TLOPEN: u8 = 12;
+pub(crate) const TLCREATE: u8 = 14;
+pub(crate) const TSYMLINK: u8 = 16;
+pub(crate) const TRENAME: u8 = 20;
+pub(crate) const TREADLINK: u8 = 22;
+pub(crate) const TGETATTR: u8 = 24;
+pub(crate) const TSETATTR: u8 = 26;
+pub(crate) const TREADDIR: u8 = 40;
+pub(crate) const TFSYNC: u8 = 50;
+pub(crate) const TLOCK: u8 = 52;
+pub(crate) const TGETLOCK: u8 = 54;
+pub(crate) const TLINK: u8 = 70;
+pub(crate) const TMKDIR: u8 = 72;
+pub(crate) const TRENAMEAT: u8 = 74;
+pub(crate) const TUNLINKAT: u8 = 76;
+pub(crate) const TVERSION: u8 = 100;
+pub(crate) const TATTACH: u8 = 104;
+pub(crate) const TFLUSH: u8 = 108;
+pub(crate) const TWALK: u8 = 110;
+pub(crate) const TREAD: u8 = 116;
+pub(crate) const TWRITE: u8 = 118;
+pub(crate) const TCLUNK: u8 = 120;
+pub(crate) const TREMOVE: u8 = 122;
+
+// Qid types.
+const QTDIR: u8 = 0x80;
+const QTSYMLINK: u8 = 0x02;
+const QTFILE: u8 = 0x00;
+
+/// Unique identifier of a file on the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Qid {
+    pub qid_type: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+impl Qid {
+    /// Identifies the file of type `mode` with the inode number `ino`.
+    pub fn new(mode: u32, ino: u64) -> Self {
+        let qid_type = match mode & libc::S_IFMT {
+            libc::S_IFDIR => QTDIR,
+            libc::S_IFLNK => QTSYMLINK,
+            _ => QTFILE,
+        };
+        Qid {
+            qid_type,
+            version: 0,
+            path: ino,
+        }
+    }
+}
+
+impl From<&Metadata> for Qid {
+    fn from(metadata: &Metadata) -> Self {
+        Qid::new(metadata.mode(), metadata.ino())
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::from_raw_os_error(libc::EINVAL)
+}
+
+/// Decodes the fields of a message.
+#[derive(Debug)]
+pub(crate) struct WireReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        WireReader { buf }
+    }
+
+    /// Takes the next `len` bytes of the message.
+    pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(malformed());
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        // Unwrapping is safe since `bytes()` returns a slice of the requested length.
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = usize::from(self.u16()?);
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| malformed())
+    }
+}
+
+/// Encodes a reply.
+#[derive(Debug)]
+pub(crate) struct WireWriter {
+    buf: Vec<u8>,
+}
+
+impl WireWriter {
+    /// Starts a reply of type `msg_type` to the request tagged with `tag`.
+    pub fn new(msg_type: u8, tag: u16) -> Self {
+        let mut writer = WireWriter {
+            buf: Vec::with_capacity(HEADER_SIZE),
+        };
+        // The size is only known once the whole reply is written.
+        writer.put_u32(0);
+        writer.put_u8(msg_type);
+        writer.put_u16(tag);
+        writer
+    }
+
+    /// Number of bytes written so far, header included.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.put_bytes(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.put_bytes(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.put_bytes(&value.to_le_bytes());
+    }
+
+    /// Writes `value`, which the callers keep under `u16::MAX` bytes.
+    pub fn put_str(&mut self, value: &str) {
+        // Names and targets sent back come from the host filesystem, where they are limited to
+        // PATH_MAX bytes.
+        self.put_u16(u16::try_from(value.len()).unwrap_or(u16::MAX));
+        self.put_bytes(&value.as_bytes()[..value.len().min(usize::from(u16::MAX))]);
+    }
+
+    pub fn put_qid(&mut self, qid: &Qid) {
+        self.put_u8(qid.qid_type);
+        self.put_u32(qid.version);
+        self.put_u64(qid.path);
+    }
+
+    /// Completes the reply and returns its encoding.
+    pub fn finish(mut self) -> Vec<u8> {
+        // Replies are bounded by the negotiated message size, which fits in 32 bits.
+        let size = u32::try_from(self.buf.len()).unwrap();
+        self.buf[..4].copy_from_slice(&size.to_le_bytes());
+        self.buf
+    }
+}
+
+/// Size of the encoding of `name` in a message.
+pub(crate) fn str_size(name: &str) -> usize {
+    2 + name.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_reader() {
+        let buf = [
+            1, 2, 0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 2, 0, b'o', b'k', 9,
+        ];
+        let mut reader = WireReader::new(&buf);
+        assert_eq!(reader.u8().unwrap(), 1);
+        assert_eq!(reader.u16().unwrap(), 2);
+        assert_eq!(reader.u32().unwrap(), 3);
+        assert_eq!(reader.u64().unwrap(), 4);
+        assert_eq!(reader.string().unwrap(), "ok");
+        assert_eq!(reader.u16().unwrap_err().raw_os_error(), Some(libc::EINVAL));
+        assert_eq!(reader.u8().unwrap(), 9);
+
+        // Strings must be valid UTF-8 and fit in the message.
+        assert!(WireReader::new(&[1, 0, 0xff]).string().is_err());
+        assert!(WireReader::new(&[3, 0, b'a']).string().is_err());
+    }
+
+    #[test]
+    fn test_wire_writer() {
+        let mut writer = WireWriter::new(RLERROR, 0x1234);
+        assert_eq!(writer.len(), HEADER_SIZE);
+        writer.put_str("ok");
+        writer.put_qid(&Qid {
+            qid_type: QTDIR,
+            version: 1,
+            path: 2,
+        });
+        assert_eq!(writer.len(), HEADER_SIZE + str_size("ok") + QID_SIZE);
+        assert_eq!(
+            writer.finish(),
+            [
+                24, 0, 0, 0, RLERROR, 0x34, 0x12, 2, 0, b'o', b'k', QTDIR, 1, 0, 0, 0, 2, 0, 0, 0,
+                0, 0, 0, 0
+            ]
+        );
+    }
+}
@@ -0,0 +1,1387 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A 9P2000.L file server exporting a host directory.
+//!
+//! Each fid holds a descriptor of the directory containing its file, opened with `O_PATH`, along
+//! with the name of the file in it, and the requests reach the file through the `*at()` calls.
+//! The directories are opened one component at a time from the shared directory, never following
+//! symbolic links, so the guest cannot reach files outside of the shared directory, even through
+//! the symbolic links it creates itself in place of the directories it walked through.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::{File, Metadata, OpenOptions, Permissions};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use logger::{debug, IncMetric, METRICS};
+
+use super::protocol::*;
+
+/// Largest message size the server accepts to negotiate.
+pub(crate) const MAX_MSIZE: u32 = 512 << 10;
+// Smallest message size allowing the replies with the largest fixed size to fit.
+const MIN_MSIZE: u32 = 4096;
+// Version of the protocol implemented by the server.
+const VERSION_9P2000_L: &str = "9P2000.L";
+// Version string replied when the client asks for an unsupported version.
+const VERSION_UNKNOWN: &str = "unknown";
+// Maximum number of names in a walk request.
+const MAX_WALK_NAMES: usize = 16;
+// Size of the fields preceding the data in `Rread` and `Rreaddir`.
+const IO_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
+// Flags of `Tlopen` and `Tlcreate`, which are independent of the guest architecture.
+const P9_DOTL_ACCMODE: u32 = 0o3;
+const P9_DOTL_WRONLY: u32 = 0o1;
+const P9_DOTL_RDWR: u32 = 0o2;
+const P9_DOTL_CREATE: u32 = 0o100;
+const P9_DOTL_EXCL: u32 = 0o200;
+const P9_DOTL_TRUNC: u32 = 0o1000;
+const P9_DOTL_APPEND: u32 = 0o2000;
+
+// Fields of `Rgetattr` filled in by the server.
+const P9_GETATTR_BASIC: u64 = 0x0000_07ff;
+
+// Fields to update in `Tsetattr`.
+const P9_SETATTR_MODE: u32 = 0x0000_0001;
+const P9_SETATTR_UID: u32 = 0x0000_0002;
+const P9_SETATTR_GID: u32 = 0x0000_0004;
+const P9_SETATTR_SIZE: u32 = 0x0000_0008;
+const P9_SETATTR_ATIME: u32 = 0x0000_0010;
+const P9_SETATTR_MTIME: u32 = 0x0000_0020;
+const P9_SETATTR_ATIME_SET: u32 = 0x0000_0080;
+const P9_SETATTR_MTIME_SET: u32 = 0x0000_0100;
+
+// Flag of `Tunlinkat` asking to remove a directory.
+const P9_DOTL_AT_REMOVEDIR: u32 = 0x200;
+
+// Lock replies.
+const P9_LOCK_SUCCESS: u8 = 0;
+const P9_LOCK_TYPE_UNLCK: u8 = 2;
+
+fn errno(code: i32) -> io::Error {
+    io::Error::from_raw_os_error(code)
+}
+
+// Converts the return code of a libc call into a result.
+fn check_ret(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn cstring(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| errno(libc::EINVAL))
+}
+
+// Checks that `name` designates an entry of a directory.
+fn check_name(name: &str) -> io::Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(['/', '\0']) => Ok(()),
+        _ => Err(errno(libc::EINVAL)),
+    }
+}
+
+// Opens the entry `name` of the directory `dir`, without following it if it is a symbolic link.
+fn open_at(dir: &File, name: &CStr, flags: libc::c_int, mode: u32) -> io::Result<File> {
+    // SAFETY: Safe because `name` is a valid C string, and the result is checked.
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            mode,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: Safe because `fd` was just opened, and nothing else owns it.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// Lists the names of the entries of the directory opened as `dir`, except `.` and `..`.
+fn read_dir_names(dir: File) -> io::Result<Vec<CString>> {
+    let fd = dir.into_raw_fd();
+    // SAFETY: Safe because `fd` is an open descriptor, which the stream owns once created.
+    let stream = unsafe { libc::fdopendir(fd) };
+    if stream.is_null() {
+        let err = io::Error::last_os_error();
+        // SAFETY: Safe because the stream was not created, so `fd` is still owned by nothing else.
+        drop(unsafe { File::from_raw_fd(fd) });
+        return Err(err);
+    }
+
+    let mut names = Vec::new();
+    let result = loop {
+        // `readdir()` only sets errno on failure, telling it apart from the end of the stream.
+        // SAFETY: Safe because errno is thread local.
+        unsafe { *libc::__errno_location() = 0 };
+        // SAFETY: Safe because `stream` is a valid directory stream.
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            let err = io::Error::last_os_error();
+            break match err.raw_os_error() {
+                Some(0) => Ok(names),
+                _ => Err(err),
+            };
+        }
+        // SAFETY: Safe because `entry` points to a valid entry, with a nul terminated name.
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name.to_bytes() != b"." && name.to_bytes() != b".." {
+            names.push(name.to_owned());
+        }
+    };
+    // SAFETY: Safe because `stream` is a valid directory stream, which is not used afterwards.
+    unsafe { libc::closedir(stream) };
+    result
+}
+
+/// Location of a file in the shared directory.
+#[derive(Clone, Debug)]
+struct Location {
+    // Path of the file, relative to the shared directory, from which its parent is found again.
+    path: PathBuf,
+    // Directory containing the file, opened with `O_PATH`. The shared directory contains itself.
+    dir: Arc<File>,
+    // Name of the file in `dir`, which is `.` for the shared directory.
+    name: CString,
+}
+
+impl Location {
+    fn is_root(&self) -> bool {
+        self.path.as_os_str().is_empty()
+    }
+
+    fn open(&self, flags: libc::c_int, mode: u32) -> io::Result<File> {
+        open_at(&self.dir, &self.name, flags, mode)
+    }
+
+    // Metadata of the file itself, even when it is a symbolic link.
+    fn metadata(&self) -> io::Result<Metadata> {
+        self.open(libc::O_PATH, 0)?.metadata()
+    }
+
+    // Locates the entry `name` of the directory at this location. Symbolic links are not
+    // directories when they are not followed, so they are never gone through.
+    fn child(&self, name: &str) -> io::Result<Location> {
+        let dir = self.open(libc::O_PATH | libc::O_DIRECTORY, 0)?;
+        Ok(Location {
+            path: self.path.join(name),
+            dir: Arc::new(dir),
+            name: cstring(name)?,
+        })
+    }
+}
+
+/// State of a file the client refers to through a fid.
+#[derive(Debug)]
+struct Fid {
+    location: Location,
+    // The file, once opened by `Tlopen` or `Tlcreate`.
+    file: Option<File>,
+    // Entries of the directory, listed by the first `Treaddir` of a directory.
+    entries: Vec<(String, Qid, u8)>,
+}
+
+impl Fid {
+    fn new(location: Location) -> Self {
+        Fid {
+            location,
+            file: None,
+            entries: Vec::new(),
+        }
+    }
+
+    fn file(&self) -> io::Result<&File> {
+        self.file.as_ref().ok_or_else(|| errno(libc::EBADF))
+    }
+}
+
+/// Serves the 9P2000.L requests of a client, against a host directory.
+#[derive(Debug)]
+pub(crate) struct Server {
+    root: PathBuf,
+    // The shared directory, opened with `O_PATH`.
+    root_dir: Arc<File>,
+    read_only: bool,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Server {
+    /// Creates a server exporting the `root` host directory.
+    pub fn new(root: PathBuf, read_only: bool) -> io::Result<Self> {
+        let root_dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(&root)?;
+        Ok(Server {
+            root,
+            root_dir: Arc::new(root_dir),
+            read_only,
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+        })
+    }
+
+    /// Host directory exported by the server.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether the server refuses the requests which modify the exported directory.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Handles a request, returning the encoded reply. Requests which are too short to even carry
+    /// a tag get no reply.
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let mut reader = WireReader::new(request);
+        let (_size, msg_type, tag) = (reader.u32().ok()?, reader.u8().ok()?, reader.u16().ok()?);
+        METRICS.shared_dirs.request_count.inc();
+
+        let mut writer = WireWriter::new(msg_type.wrapping_add(1), tag);
+        match self.dispatch(msg_type, &mut reader, &mut writer) {
+            Ok(()) => Some(writer.finish()),
+            Err(err) => {
+                debug!("9p: Request of type {} failed: {}", msg_type, err);
+                METRICS.shared_dirs.request_fails.inc();
+                let mut writer = WireWriter::new(RLERROR, tag);
+                writer.put_u32(u32::try_from(err.raw_os_error().unwrap_or(libc::EIO)).unwrap_or(0));
+                Some(writer.finish())
+            }
+        }
+    }
+
+    fn dispatch(&mut self, msg_type: u8, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        match msg_type {
+            TVERSION => self.version(r, w),
+            TATTACH => self.attach(r, w),
+            TWALK => self.walk(r, w),
+            TCLUNK => self.clunk(r),
+            TREMOVE => self.remove(r),
+            TFLUSH => {
+                // Requests are handled synchronously, so there is never one left to flush.
+                r.u16().map(drop)
+            }
+            TLOPEN => self.lopen(r, w),
+            TLCREATE => self.lcreate(r, w),
+            TREAD => self.read(r, w),
+            TWRITE => self.write(r, w),
+            TGETATTR => self.getattr(r, w),
+            TSETATTR => self.setattr(r),
+            TREADDIR => self.readdir(r, w),
+            TSTATFS => self.statfs(r, w),
+            TFSYNC => self.fsync(r),
+            TMKDIR => self.mkdir(r, w),
+            TSYMLINK => self.symlink(r, w),
+            TREADLINK => self.readlink(r, w),
+            TLINK => self.link(r),
+            TRENAME => self.rename(r),
+            TRENAMEAT => self.renameat(r),
+            TUNLINKAT => self.unlinkat(r),
+            TLOCK => self.lock(r, w),
+            TGETLOCK => self.getlock(r, w),
+            // Device nodes, extended attributes and authentication are not supported, like the
+            // messages of the other dialects.
+            _ => Err(errno(libc::EOPNOTSUPP)),
+        }
+    }
+
+    fn root_location(&self) -> Location {
+        Location {
+            path: PathBuf::new(),
+            dir: self.root_dir.clone(),
+            name: CString::new(".").unwrap(),
+        }
+    }
+
+    // Locates the file at `path`, relative to the shared directory, one component at a time.
+    fn locate(&self, path: &Path) -> io::Result<Location> {
+        let mut location = self.root_location();
+        for name in path.iter() {
+            location = location.child(name.to_str().ok_or_else(|| errno(libc::EINVAL))?)?;
+        }
+        Ok(location)
+    }
+
+    fn fid(&self, fid: u32) -> io::Result<&Fid> {
+        self.fids.get(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn fid_mut(&mut self, fid: u32) -> io::Result<&mut Fid> {
+        self.fids.get_mut(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            Err(errno(libc::EROFS))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Locates the entry `name` of the directory designated by `dfid`.
+    fn child_location(&self, dfid: u32, name: &str) -> io::Result<Location> {
+        check_name(name)?;
+        self.fid(dfid)?.location.child(name)
+    }
+
+    fn version(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let msize = r.u32()?;
+        let version = r.string()?;
+        if msize < MIN_MSIZE {
+            return Err(errno(libc::EINVAL));
+        }
+
+        // A new session starts, dropping the state of the previous one.
+        self.fids.clear();
+        self.msize = msize.min(MAX_MSIZE);
+        w.put_u32(self.msize);
+        if version.starts_with(VERSION_9P2000_L) {
+            w.put_str(VERSION_9P2000_L);
+        } else {
+            w.put_str(VERSION_UNKNOWN);
+        }
+        Ok(())
+    }
+
+    fn attach(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let _afid = r.u32()?;
+        let _uname = r.string()?;
+        let _aname = r.string()?;
+        let _n_uname = r.u32()?;
+        if self.fids.contains_key(&fid) {
+            return Err(errno(libc::EBADF));
+        }
+
+        w.put_qid(&Qid::from(&self.root_dir.metadata()?));
+        self.fids.insert(fid, Fid::new(self.root_location()));
+        Ok(())
+    }
+
+    fn walk(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let newfid = r.u32()?;
+        let nwname = usize::from(r.u16()?);
+        if nwname > MAX_WALK_NAMES {
+            return Err(errno(libc::EINVAL));
+        }
+        let names = (0..nwname)
+            .map(|_| r.string())
+            .collect::<io::Result<Vec<String>>>()?;
+        if newfid != fid && self.fids.contains_key(&newfid) {
+            return Err(errno(libc::EBADF));
+        }
+
+        let mut location = self.fid(fid)?.location.clone();
+        let mut qids = Vec::with_capacity(nwname);
+        for name in &names {
+            match self.walk_one(&location, name) {
+                Ok((next, qid)) => {
+                    location = next;
+                    qids.push(qid);
+                }
+                // Only the failure of the first step is an error. Otherwise, the client learns how
+                // far the walk went from the number of qids.
+                Err(err) if qids.is_empty() => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        w.put_u16(u16::try_from(qids.len()).unwrap());
+        for qid in &qids {
+            w.put_qid(qid);
+        }
+        // Walking a fid to itself without names leaves it untouched, along with its open file.
+        if qids.len() == nwname && (newfid != fid || nwname != 0) {
+            self.fids.insert(newfid, Fid::new(location));
+        }
+        Ok(())
+    }
+
+    // Walks from the directory at `location` to its entry `name`.
+    fn walk_one(&self, location: &Location, name: &str) -> io::Result<(Location, Qid)> {
+        if !location.metadata()?.is_dir() {
+            return Err(errno(libc::ENOTDIR));
+        }
+        let next = match name {
+            // The parent of the shared directory is the shared directory itself.
+            ".." => self.locate(location.path.parent().unwrap_or(Path::new("")))?,
+            "." => location.clone(),
+            _ => {
+                check_name(name)?;
+                location.child(name)?
+            }
+        };
+        let qid = Qid::from(&next.metadata()?);
+        Ok((next, qid))
+    }
+
+    fn clunk(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let fid = r.u32()?;
+        self.fids
+            .remove(&fid)
+            .map(drop)
+            .ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn remove(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let fid = r.u32()?;
+        // The fid is clunked even if the removal fails.
+        let fid = self.fids.remove(&fid).ok_or_else(|| errno(libc::EBADF))?;
+        self.check_writable()?;
+        if fid.location.is_root() {
+            return Err(errno(libc::EBUSY));
+        }
+        let flags = if fid.location.metadata()?.is_dir() {
+            libc::AT_REMOVEDIR
+        } else {
+            0
+        };
+        Self::unlink(&fid.location, flags)
+    }
+
+    fn unlink(location: &Location, flags: libc::c_int) -> io::Result<()> {
+        // SAFETY: Safe because the name is a valid C string.
+        check_ret(unsafe {
+            libc::unlinkat(location.dir.as_raw_fd(), location.name.as_ptr(), flags)
+        })
+    }
+
+    // Opens the file at `location` with the `flags` of a `Tlopen` or `Tlcreate` request.
+    fn open(&self, location: &Location, flags: u32, mode: Option<u32>) -> io::Result<File> {
+        let accmode = flags & P9_DOTL_ACCMODE;
+        let writes = accmode == P9_DOTL_WRONLY || accmode == P9_DOTL_RDWR;
+        if writes || flags & (P9_DOTL_TRUNC | P9_DOTL_CREATE) != 0 {
+            self.check_writable()?;
+        }
+
+        // Opening a FIFO must not block the thread serving the requests.
+        let mut open_flags = libc::O_NONBLOCK
+            | match accmode {
+                P9_DOTL_WRONLY => libc::O_WRONLY,
+                P9_DOTL_RDWR => libc::O_RDWR,
+                _ => libc::O_RDONLY,
+            };
+        if writes && flags & P9_DOTL_APPEND != 0 {
+            open_flags |= libc::O_APPEND;
+        }
+        if writes && flags & P9_DOTL_TRUNC != 0 {
+            open_flags |= libc::O_TRUNC;
+        }
+        if mode.is_some() {
+            // The creation flags are passed as is, since the file may be created read-only.
+            open_flags |= libc::O_CREAT;
+            if flags & P9_DOTL_EXCL != 0 {
+                open_flags |= libc::O_EXCL;
+            }
+        }
+        location.open(open_flags, mode.unwrap_or(0) & 0o7777)
+    }
+
+    fn lopen(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let flags = r.u32()?;
+
+        let location = &self.fid(fid)?.location;
+        let metadata = location.metadata()?;
+        // Directories are listed through `Treaddir`, so there is nothing to open.
+        let file = if metadata.is_dir() {
+            if flags & P9_DOTL_ACCMODE != 0 {
+                return Err(errno(libc::EISDIR));
+            }
+            None
+        } else {
+            Some(self.open(location, flags, None)?)
+        };
+
+        let entry = self.fid_mut(fid)?;
+        entry.file = file;
+        entry.entries.clear();
+        w.put_qid(&Qid::from(&metadata));
+        // Let the client derive the I/O size from the message size.
+        w.put_u32(0);
+        Ok(())
+    }
+
+    fn lcreate(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let name = r.string()?;
+        let flags = r.u32()?;
+        let mode = r.u32()?;
+        let _gid = r.u32()?;
+        self.check_writable()?;
+
+        let location = self.child_location(fid, &name)?;
+        let file = self.open(&location, flags | P9_DOTL_CREATE, Some(mode))?;
+        let qid = Qid::from(&file.metadata()?);
+
+        // The fid now designates the new file.
+        let entry = self.fid_mut(fid)?;
+        entry.location = location;
+        entry.file = Some(file);
+        entry.entries.clear();
+        w.put_qid(&qid);
+        w.put_u32(0);
+        Ok(())
+    }
+
+    fn read(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()?;
+
+        let len = (count as usize).min(self.msize as usize - IO_HEADER_SIZE);
+        let mut buf = vec![0u8; len];
+        let read = self.fid(fid)?.file()?.read_at(&mut buf, offset)?;
+        METRICS.shared_dirs.read_bytes.add(read);
+        w.put_u32(u32::try_from(read).unwrap());
+        w.put_bytes(&buf[..read]);
+        Ok(())
+    }
+
+    fn write(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()?;
+        let data = r.bytes(count as usize)?;
+        self.check_writable()?;
+
+        let written = self.fid(fid)?.file()?.write_at(data, offset)?;
+        METRICS.shared_dirs.write_bytes.add(written);
+        w.put_u32(u32::try_from(written).unwrap());
+        Ok(())
+    }
+
+    fn getattr(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let _request_mask = r.u64()?;
+
+        let entry = self.fid(fid)?;
+        // An open file may have been unlinked since.
+        let metadata = match &entry.file {
+            Some(file) => file.metadata()?,
+            None => entry.location.metadata()?,
+        };
+        w.put_u64(P9_GETATTR_BASIC);
+        w.put_qid(&Qid::from(&metadata));
+        w.put_u32(metadata.mode());
+        w.put_u32(metadata.uid());
+        w.put_u32(metadata.gid());
+        w.put_u64(metadata.nlink());
+        w.put_u64(metadata.rdev());
+        w.put_u64(metadata.size());
+        w.put_u64(metadata.blksize());
+        w.put_u64(metadata.blocks());
+        for (sec, nsec) in [
+            (metadata.atime(), metadata.atime_nsec()),
+            (metadata.mtime(), metadata.mtime_nsec()),
+            (metadata.ctime(), metadata.ctime_nsec()),
+        ] {
+            // Timestamps are sent with the bit pattern of their signed values.
+            w.put_u64(sec as u64);
+            w.put_u64(nsec as u64);
+        }
+        // Birth time, generation and data version are not reported.
+        for _ in 0..4 {
+            w.put_u64(0);
+        }
+        Ok(())
+    }
+
+    fn setattr(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let fid = r.u32()?;
+        let valid = r.u32()?;
+        let mode = r.u32()?;
+        let uid = r.u32()?;
+        let gid = r.u32()?;
+        let size = r.u64()?;
+        let atime = (r.u64()?, r.u64()?);
+        let mtime = (r.u64()?, r.u64()?);
+        self.check_writable()?;
+
+        let entry = self.fid(fid)?;
+        let location = &entry.location;
+        let metadata = location.metadata()?;
+
+        if valid & P9_SETATTR_MODE != 0 {
+            // Changing the mode of a symbolic link would change the mode of its target instead.
+            // The guest cannot replace the file with one in the meantime, since its requests are
+            // served one at a time.
+            if metadata.file_type().is_symlink() {
+                return Err(errno(libc::EOPNOTSUPP));
+            }
+            match &entry.file {
+                Some(file) => file.set_permissions(Permissions::from_mode(mode & 0o7777))?,
+                None => {
+                    // SAFETY: Safe because the name is a valid C string.
+                    check_ret(unsafe {
+                        libc::fchmodat(
+                            location.dir.as_raw_fd(),
+                            location.name.as_ptr(),
+                            mode & 0o7777,
+                            0,
+                        )
+                    })?
+                }
+            }
+        }
+        if valid & (P9_SETATTR_UID | P9_SETATTR_GID) != 0 {
+            let uid = if valid & P9_SETATTR_UID != 0 {
+                uid
+            } else {
+                u32::MAX
+            };
+            let gid = if valid & P9_SETATTR_GID != 0 {
+                gid
+            } else {
+                u32::MAX
+            };
+            // SAFETY: Safe because the name is a valid C string, and an id of -1 is left
+            // unchanged.
+            check_ret(unsafe {
+                libc::fchownat(
+                    location.dir.as_raw_fd(),
+                    location.name.as_ptr(),
+                    uid,
+                    gid,
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            })?;
+        }
+        if valid & P9_SETATTR_SIZE != 0 {
+            match &entry.file {
+                Some(file) => file.set_len(size)?,
+                None => self.open(location, P9_DOTL_WRONLY, None)?.set_len(size)?,
+            }
+        }
+        if valid & (P9_SETATTR_ATIME | P9_SETATTR_MTIME) != 0 {
+            let timespec = |set: u32, explicit: u32, (sec, nsec): (u64, u64)| {
+                let mut time = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                };
+                if valid & explicit != 0 {
+                    // Timestamps are received with the bit pattern of their signed values.
+                    time.tv_sec = sec as libc::time_t;
+                    time.tv_nsec = nsec as libc::c_long;
+                } else if valid & set != 0 {
+                    time.tv_nsec = libc::UTIME_NOW;
+                }
+                time
+            };
+            let times = [
+                timespec(P9_SETATTR_ATIME, P9_SETATTR_ATIME_SET, atime),
+                timespec(P9_SETATTR_MTIME, P9_SETATTR_MTIME_SET, mtime),
+            ];
+            // SAFETY: Safe because the name is a valid C string and `times` holds two timestamps.
+            check_ret(unsafe {
+                libc::utimensat(
+                    location.dir.as_raw_fd(),
+                    location.name.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    // Lists the directory at `location`, including the `.` and `..` entries.
+    fn list_dir(&self, location: &Location) -> io::Result<Vec<(String, Qid, u8)>> {
+        let dir_qid = Qid::from(&location.metadata()?);
+        let parent = self.locate(location.path.parent().unwrap_or(Path::new("")))?;
+        let parent_qid = Qid::from(&parent.metadata()?);
+        let mut entries = vec![
+            (".".to_string(), dir_qid, libc::DT_DIR),
+            ("..".to_string(), parent_qid, libc::DT_DIR),
+        ];
+
+        let dir = location.open(libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        for name in read_dir_names(dir.try_clone()?)? {
+            // Entries which are not valid UTF-8 cannot be represented in the protocol.
+            let Ok(utf8_name) = name.to_str() else {
+                continue;
+            };
+            // SAFETY: Safe because the structure only holds plain data.
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            // SAFETY: Safe because the name is a valid C string and `stat` is a valid structure.
+            let ret = unsafe {
+                libc::fstatat(
+                    dir.as_raw_fd(),
+                    name.as_ptr(),
+                    &mut stat,
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            // The entry may have been removed in the meantime.
+            if ret < 0 {
+                continue;
+            }
+            let dirent_type = match stat.st_mode & libc::S_IFMT {
+                libc::S_IFDIR => libc::DT_DIR,
+                libc::S_IFLNK => libc::DT_LNK,
+                libc::S_IFREG => libc::DT_REG,
+                _ => libc::DT_UNKNOWN,
+            };
+            entries.push((
+                utf8_name.to_string(),
+                Qid::new(stat.st_mode, stat.st_ino),
+                dirent_type,
+            ));
+        }
+        Ok(entries)
+    }
+
+    fn readdir(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()?;
+
+        // The directory is listed once per pass of the client, which then walks through it by
+        // offset. An offset is the index of the next entry.
+        if offset == 0 {
+            let entries = self.list_dir(&self.fid(fid)?.location)?;
+            self.fid_mut(fid)?.entries = entries;
+        }
+        let entry = self.fid(fid)?;
+        let limit = (count as usize).min(self.msize as usize - IO_HEADER_SIZE);
+        let skip = usize::try_from(offset).unwrap_or(usize::MAX);
+
+        // The entries are encoded apart, since their size precedes them in the reply.
+        let mut data = WireWriter::new(0, 0);
+        for (index, (name, qid, dirent_type)) in entry.entries.iter().enumerate().skip(skip) {
+            let size = QID_SIZE + 8 + 1 + str_size(name);
+            if data.len() - HEADER_SIZE + size > limit {
+                break;
+            }
+            data.put_qid(qid);
+            data.put_u64(index as u64 + 1);
+            data.put_u8(*dirent_type);
+            data.put_str(name);
+        }
+
+        let data = data.finish();
+        let data = &data[HEADER_SIZE..];
+        w.put_u32(u32::try_from(data.len()).unwrap());
+        w.put_bytes(data);
+        Ok(())
+    }
+
+    // The fields have different widths depending on the architecture and the C library.
+    #[allow(clippy::useless_conversion, clippy::unnecessary_cast)]
+    fn statfs(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let file = self.fid(fid)?.location.open(libc::O_PATH, 0)?;
+
+        // SAFETY: Safe because the structure only holds plain data.
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: Safe because `file` is open and `stat` is a valid statfs structure.
+        check_ret(unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) })?;
+
+        w.put_u32(stat.f_type as u32);
+        w.put_u32(stat.f_bsize as u32);
+        w.put_u64(stat.f_blocks as u64);
+        w.put_u64(stat.f_bfree as u64);
+        w.put_u64(stat.f_bavail as u64);
+        w.put_u64(stat.f_files as u64);
+        w.put_u64(stat.f_ffree as u64);
+        // The filesystem id is not reported.
+        w.put_u64(0);
+        w.put_u32(stat.f_namelen as u32);
+        Ok(())
+    }
+
+    fn fsync(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let fid = r.u32()?;
+        let datasync = r.u32()?;
+
+        let entry = self.fid(fid)?;
+        // Directories are not opened, and there is nothing to flush for them.
+        match &entry.file {
+            Some(file) if datasync != 0 => file.sync_data(),
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    fn mkdir(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let mode = r.u32()?;
+        let _gid = r.u32()?;
+        self.check_writable()?;
+
+        let location = self.child_location(dfid, &name)?;
+        // SAFETY: Safe because the name is a valid C string.
+        check_ret(unsafe {
+            libc::mkdirat(
+                location.dir.as_raw_fd(),
+                location.name.as_ptr(),
+                mode & 0o7777,
+            )
+        })?;
+        w.put_qid(&Qid::from(&location.metadata()?));
+        Ok(())
+    }
+
+    fn symlink(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let target = r.string()?;
+        let _gid = r.u32()?;
+        self.check_writable()?;
+
+        // The target is stored as is: links are never followed by the server.
+        let location = self.child_location(dfid, &name)?;
+        let target = cstring(&target)?;
+        // SAFETY: Safe because both names are valid C strings.
+        check_ret(unsafe {
+            libc::symlinkat(
+                target.as_ptr(),
+                location.dir.as_raw_fd(),
+                location.name.as_ptr(),
+            )
+        })?;
+        w.put_qid(&Qid::from(&location.metadata()?));
+        Ok(())
+    }
+
+    fn readlink(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+
+        let location = &self.fid(fid)?.location;
+        let mut target = vec![0u8; usize::try_from(libc::PATH_MAX).unwrap()];
+        // SAFETY: Safe because the name is a valid C string, and `target` is large enough for the
+        // length passed along.
+        let len = unsafe {
+            libc::readlinkat(
+                location.dir.as_raw_fd(),
+                location.name.as_ptr(),
+                target.as_mut_ptr().cast(),
+                target.len(),
+            )
+        };
+        target.truncate(usize::try_from(len).map_err(|_| io::Error::last_os_error())?);
+        let target = String::from_utf8(target).map_err(|_| errno(libc::EINVAL))?;
+        w.put_str(&target);
+        Ok(())
+    }
+
+    fn link(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let dfid = r.u32()?;
+        let fid = r.u32()?;
+        let name = r.string()?;
+        self.check_writable()?;
+
+        let target = &self.fid(fid)?.location;
+        let location = self.child_location(dfid, &name)?;
+        // SAFETY: Safe because both names are valid C strings.
+        check_ret(unsafe {
+            libc::linkat(
+                target.dir.as_raw_fd(),
+                target.name.as_ptr(),
+                location.dir.as_raw_fd(),
+                location.name.as_ptr(),
+                0,
+            )
+        })
+    }
+
+    // Renames the file at `from` to `to`, updating the fids designating it or its descendants.
+    // The descendants keep the descriptors of their directories, which follow the renamed one.
+    fn rename_location(&mut self, from: &Location, to: &Location) -> io::Result<()> {
+        if from.is_root() {
+            return Err(errno(libc::EBUSY));
+        }
+        // SAFETY: Safe because both names are valid C strings.
+        check_ret(unsafe {
+            libc::renameat(
+                from.dir.as_raw_fd(),
+                from.name.as_ptr(),
+                to.dir.as_raw_fd(),
+                to.name.as_ptr(),
+            )
+        })?;
+        for fid in self.fids.values_mut() {
+            if fid.location.path == from.path {
+                fid.location = to.clone();
+            } else if let Ok(suffix) = fid.location.path.strip_prefix(&from.path) {
+                fid.location.path = to.path.join(suffix);
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let fid = r.u32()?;
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        self.check_writable()?;
+
+        let from = self.fid(fid)?.location.clone();
+        let to = self.child_location(dfid, &name)?;
+        self.rename_location(&from, &to)
+    }
+
+    fn renameat(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let olddirfid = r.u32()?;
+        let oldname = r.string()?;
+        let newdirfid = r.u32()?;
+        let newname = r.string()?;
+        self.check_writable()?;
+
+        let from = self.child_location(olddirfid, &oldname)?;
+        let to = self.child_location(newdirfid, &newname)?;
+        self.rename_location(&from, &to)
+    }
+
+    fn unlinkat(&mut self, r: &mut WireReader) -> io::Result<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let flags = r.u32()?;
+        self.check_writable()?;
+
+        let location = self.child_location(dfid, &name)?;
+        if flags & P9_DOTL_AT_REMOVEDIR != 0 {
+            Self::unlink(&location, libc::AT_REMOVEDIR)
+        } else {
+            Self::unlink(&location, 0)
+        }
+    }
+
+    fn lock(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        self.fid(fid)?;
+        // Locks only matter between the processes of the guest, which the guest kernel already
+        // arbitrates, so they are always granted.
+        w.put_u8(P9_LOCK_SUCCESS);
+        Ok(())
+    }
+
+    fn getlock(&mut self, r: &mut WireReader, w: &mut WireWriter) -> io::Result<()> {
+        let fid = r.u32()?;
+        let _lock_type = r.u8()?;
+        let start = r.u64()?;
+        let length = r.u64()?;
+        let proc_id = r.u32()?;
+        let client_id = r.string()?;
+        self.fid(fid)?;
+
+        // No lock is ever held on the host.
+        w.put_u8(P9_LOCK_TYPE_UNLCK);
+        w.put_u64(start);
+        w.put_u64(length);
+        w.put_u32(proc_id);
+        w.put_str(&client_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    // Encodes a request of type `msg_type`, tagged with 1, with the fields written by `fill`.
+    pub(crate) fn request(msg_type: u8, fill: impl FnOnce(&mut WireWriter)) -> Vec<u8> {
+        let mut writer = WireWriter::new(msg_type, 1);
+        fill(&mut writer);
+        writer.finish()
+    }
+
+    // Handles the request, returning the type and the body of the reply.
+    fn call(
+        server: &mut Server,
+        msg_type: u8,
+        fill: impl FnOnce(&mut WireWriter),
+    ) -> (u8, Vec<u8>) {
+        let reply = server.handle(&request(msg_type, fill)).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(reply[..4].try_into().unwrap()) as usize,
+            reply.len()
+        );
+        assert_eq!(&reply[5..7], &[1, 0]);
+        (reply[4], reply[HEADER_SIZE..].to_vec())
+    }
+
+    // Handles the request, expecting it to fail with `code`.
+    fn call_err(server: &mut Server, msg_type: u8, code: i32, fill: impl FnOnce(&mut WireWriter)) {
+        let (reply_type, body) = call(server, msg_type, fill);
+        assert_eq!(reply_type, RLERROR);
+        assert_eq!(WireReader::new(&body).u32().unwrap(), code as u32);
+    }
+
+    fn attached_server(dir: &TempDir, read_only: bool) -> Server {
+        let mut server = Server::new(dir.as_path().to_path_buf(), read_only).unwrap();
+        let (reply_type, body) = call(&mut server, TVERSION, |w| {
+            w.put_u32(8192);
+            w.put_str("9P2000.L");
+        });
+        assert_eq!(reply_type, TVERSION + 1);
+        let mut reader = WireReader::new(&body);
+        assert_eq!(reader.u32().unwrap(), 8192);
+        assert_eq!(reader.string().unwrap(), VERSION_9P2000_L);
+
+        let (reply_type, _) = call(&mut server, TATTACH, |w| {
+            w.put_u32(0);
+            w.put_u32(u32::MAX);
+            w.put_str("root");
+            w.put_str("");
+            w.put_u32(0);
+        });
+        assert_eq!(reply_type, TATTACH + 1);
+        server
+    }
+
+    fn walk(server: &mut Server, fid: u32, newfid: u32, names: &[&str]) -> (u8, Vec<u8>) {
+        call(server, TWALK, |w| {
+            w.put_u32(fid);
+            w.put_u32(newfid);
+            w.put_u16(names.len() as u16);
+            for name in names {
+                w.put_str(name);
+            }
+        })
+    }
+
+    #[test]
+    fn test_version() {
+        let dir = TempDir::new().unwrap();
+        let mut server = Server::new(dir.as_path().to_path_buf(), false).unwrap();
+
+        let (_, body) = call(&mut server, TVERSION, |w| {
+            w.put_u32(u32::MAX);
+            w.put_str("9P2000.u");
+        });
+        let mut reader = WireReader::new(&body);
+        assert_eq!(reader.u32().unwrap(), MAX_MSIZE);
+        assert_eq!(reader.string().unwrap(), VERSION_UNKNOWN);
+
+        call_err(&mut server, TVERSION, libc::EINVAL, |w| {
+            w.put_u32(128);
+            w.put_str("9P2000.L");
+        });
+        // Requests too short to carry a tag get no reply.
+        assert!(server.handle(&[0, 0, 0]).is_none());
+        // Truncated requests and unknown ones are refused.
+        call_err(&mut server, TVERSION, libc::EINVAL, |w| w.put_u16(1));
+        // Extended attributes are not supported.
+        const TXATTRWALK: u8 = 30;
+        call_err(&mut server, TXATTRWALK, libc::EOPNOTSUPP, |_| ());
+        call_err(&mut server, 255, libc::EOPNOTSUPP, |_| ());
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.as_path().join("sub")).unwrap();
+        fs::write(dir.as_path().join("sub/file"), b"data").unwrap();
+        std::os::unix::fs::symlink("/", dir.as_path().join("escape")).unwrap();
+        let mut server = attached_server(&dir, false);
+
+        let (reply_type, body) = walk(&mut server, 0, 1, &["sub", "file"]);
+        assert_eq!(reply_type, TWALK + 1);
+        assert_eq!(body.len(), 2 + 2 * QID_SIZE);
+        assert_eq!(server.fid(1).unwrap().location.path, Path::new("sub/file"));
+
+        // A partial walk reports the qids of the steps which succeeded, without creating the fid.
+        let (_, body) = walk(&mut server, 0, 2, &["sub", "missing"]);
+        assert_eq!(body.len(), 2 + QID_SIZE);
+        assert!(server.fid(2).is_err());
+        call_err(&mut server, TWALK, libc::ENOENT, |w| {
+            w.put_u32(0);
+            w.put_u32(2);
+            w.put_u16(1);
+            w.put_str("missing");
+        });
+
+        // The walk cannot leave the shared directory.
+        walk(&mut server, 0, 2, &["..", "sub", "..", ".."]);
+        assert_eq!(server.fid(2).unwrap().location.path, Path::new(""));
+        let (_, body) = walk(&mut server, 0, 3, &["escape", "etc"]);
+        assert_eq!(body.len(), 2 + QID_SIZE);
+        assert!(server.fid(3).is_err());
+        call_err(&mut server, TWALK, libc::EINVAL, |w| {
+            w.put_u32(0);
+            w.put_u32(3);
+            w.put_u16(1);
+            w.put_str("sub/file");
+        });
+
+        // Fids in use cannot be reused, until clunked.
+        call_err(&mut server, TWALK, libc::EBADF, |w| {
+            w.put_u32(0);
+            w.put_u32(1);
+            w.put_u16(0);
+        });
+        call(&mut server, TCLUNK, |w| w.put_u32(1));
+        call_err(&mut server, TCLUNK, libc::EBADF, |w| w.put_u32(1));
+        let (_, body) = walk(&mut server, 0, 1, &[]);
+        assert_eq!(body, [0, 0]);
+    }
+
+    #[test]
+    fn test_replaced_directory() {
+        let dir = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        fs::create_dir(dir.as_path().join("sub")).unwrap();
+        fs::write(dir.as_path().join("sub/file"), b"data").unwrap();
+        fs::write(outside.as_path().join("file"), b"secret").unwrap();
+        let mut server = attached_server(&dir, false);
+        walk(&mut server, 0, 1, &["sub", "file"]);
+        walk(&mut server, 0, 2, &["sub"]);
+
+        // The directory the fids were walked through is replaced by a link to another one.
+        fs::rename(dir.as_path().join("sub"), dir.as_path().join("moved")).unwrap();
+        std::os::unix::fs::symlink(outside.as_path(), dir.as_path().join("sub")).unwrap();
+
+        // The fids keep designating the files they were walked to.
+        call(&mut server, TLOPEN, |w| {
+            w.put_u32(1);
+            w.put_u32(0);
+        });
+        let (_, body) = call(&mut server, TREAD, |w| {
+            w.put_u32(1);
+            w.put_u64(0);
+            w.put_u32(100);
+        });
+        let mut reader = WireReader::new(&body);
+        assert_eq!(reader.u32().unwrap(), 4);
+        assert_eq!(reader.bytes(4).unwrap(), b"data");
+        // The link is not followed through the fids designating the replaced directory by name.
+        call_err(&mut server, TUNLINKAT, libc::ENOTDIR, |w| {
+            w.put_u32(2);
+            w.put_str("file");
+            w.put_u32(0);
+        });
+        assert!(outside.as_path().join("file").exists());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.as_path().join("file"), b"hello").unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", dir.as_path().join("link")).unwrap();
+        let mut server = attached_server(&dir, false);
+
+        walk(&mut server, 0, 1, &["file"]);
+        call(&mut server, TLOPEN, |w| {
+            w.put_u32(1);
+            w.put_u32(P9_DOTL_RDWR);
+        });
+        let (_, body) = call(&mut server, TWRITE, |w| {
+            w.put_u32(1);
+            w.put_u64(5);
+            w.put_u32(6);
+            w.put_bytes(b" world");
+        });
+        assert_eq!(WireReader::new(&body).u32().unwrap(), 6);
+        let (_, body) = call(&mut server, TREAD, |w| {
+            w.put_u32(1);
+            w.put_u64(0);
+            w.put_u32(100);
+        });
+        let mut reader = WireReader::new(&body);
+        assert_eq!(reader.u32().unwrap(), 11);
+        assert_eq!(reader.bytes(11).unwrap(), b"hello world");
+
+        // Symbolic links are not followed when opening files.
+        walk(&mut server, 0, 2, &["link"]);
+        call_err(&mut server, TLOPEN, libc::ELOOP, |w| {
+            w.put_u32(2);
+            w.put_u32(0);
+        });
+        let (_, body) = call(&mut server, TREADLINK, |w| w.put_u32(2));
+        assert_eq!(WireReader::new(&body).string().unwrap(), "/etc/hostname");
+
+        // A new file is created and opened through the fid of its directory.
+        walk(&mut server, 0, 3, &[]);
+        call(&mut server, TLCREATE, |w| {
+            w.put_u32(3);
+            w.put_str("new");
+            w.put_u32(P9_DOTL_WRONLY | P9_DOTL_EXCL);
+            w.put_u32(0o640);
+            w.put_u32(0);
+        });
+        call(&mut server, TWRITE, |w| {
+            w.put_u32(3);
+            w.put_u64(0);
+            w.put_u32(3);
+            w.put_bytes(b"new");
+        });
+        assert_eq!(fs::read(dir.as_path().join("new")).unwrap(), b"new");
+        assert_eq!(
+            fs::metadata(dir.as_path().join("new")).unwrap().mode() & 0o777,
+            0o640
+        );
+    }
+
+    #[test]
+    fn test_directories() {
+        let dir = TempDir::new().unwrap();
+        let mut server = attached_server(&dir, false);
+
+        call(&mut server, TMKDIR, |w| {
+            w.put_u32(0);
+            w.put_str("sub");
+            w.put_u32(0o755);
+            w.put_u32(0);
+        });
+        walk(&mut server, 0, 1, &["sub"]);
+        call(&mut server, TSYMLINK, |w| {
+            w.put_u32(1);
+            w.put_str("link");
+            w.put_str("../target");
+            w.put_u32(0);
+        });
+        call_err(&mut server, TMKDIR, libc::EINVAL, |w| {
+            w.put_u32(0);
+            w.put_str("..");
+            w.put_u32(0o755);
+            w.put_u32(0);
+        });
+
+        call(&mut server, TLOPEN, |w| {
+            w.put_u32(1);
+            w.put_u32(0);
+        });
+        let (_, body) = call(&mut server, TREADDIR, |w| {
+            w.put_u32(1);
+            w.put_u64(0);
+            w.put_u32(1000);
+        });
+        let mut reader = WireReader::new(&body);
+        let count = reader.u32().unwrap() as usize;
+        let mut names = Vec::new();
+        while names.len() < 3 {
+            reader.bytes(QID_SIZE).unwrap();
+            assert_eq!(reader.u64().unwrap(), names.len() as u64 + 1);
+            reader.u8().unwrap();
+            names.push(reader.string().unwrap());
+        }
+        assert_eq!(names, [".", "..", "link"]);
+        assert_eq!(count, body.len() - 4);
+
+        // The listing resumes from the offset.
+        let (_, body) = call(&mut server, TREADDIR, |w| {
+            w.put_u32(1);
+            w.put_u64(3);
+            w.put_u32(1000);
+        });
+        assert_eq!(body, [0, 0, 0, 0]);
+
+        // Renaming the directory updates the fids designating it.
+        call(&mut server, TRENAMEAT, |w| {
+            w.put_u32(0);
+            w.put_str("sub");
+            w.put_u32(0);
+            w.put_str("renamed");
+        });
+        assert_eq!(server.fid(1).unwrap().location.path, Path::new("renamed"));
+        call_err(&mut server, TUNLINKAT, libc::ENOTEMPTY, |w| {
+            w.put_u32(0);
+            w.put_str("renamed");
+            w.put_u32(P9_DOTL_AT_REMOVEDIR);
+        });
+        call(&mut server, TUNLINKAT, |w| {
+            w.put_u32(1);
+            w.put_str("link");
+            w.put_u32(0);
+        });
+        call(&mut server, TREMOVE, |w| w.put_u32(1));
+        assert!(!dir.as_path().join("renamed").exists());
+        assert!(server.fid(1).is_err());
+    }
+
+    #[test]
+    fn test_getattr_setattr() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.as_path().join("file"), b"hello").unwrap();
+        let mut server = attached_server(&dir, false);
+
+        walk(&mut server, 0, 1, &["file"]);
+        call(&mut server, TSETATTR, |w| {
+            w.put_u32(1);
+            w.put_u32(P9_SETATTR_MODE | P9_SETATTR_SIZE | P9_SETATTR_MTIME | P9_SETATTR_MTIME_SET);
+            w.put_u32(0o600);
+            w.put_u32(0);
+            w.put_u32(0);
+            w.put_u64(2);
+            w.put_u64(0);
+            w.put_u64(0);
+            w.put_u64(1_000_000);
+            w.put_u64(0);
+        });
+        let metadata = fs::metadata(dir.as_path().join("file")).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata.mtime(), 1_000_000);
+
+        let (_, body) = call(&mut server, TGETATTR, |w| {
+            w.put_u32(1);
+            w.put_u64(P9_GETATTR_BASIC);
+        });
+        let mut reader = WireReader::new(&body);
+        assert_eq!(reader.u64().unwrap(), P9_GETATTR_BASIC);
+        reader.bytes(QID_SIZE).unwrap();
+        assert_eq!(reader.u32().unwrap(), metadata.mode());
+        reader.bytes(4 + 4 + 8 + 8).unwrap();
+        assert_eq!(reader.u64().unwrap(), 2);
+
+        let (_, body) = call(&mut server, TSTATFS, |w| w.put_u32(0));
+        assert_eq!(body.len(), 4 + 4 + 5 * 8 + 8 + 4);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.as_path().join("file"), b"hello").unwrap();
+        let mut server = attached_server(&dir, true);
+
+        walk(&mut server, 0, 1, &["file"]);
+        for flags in [P9_DOTL_WRONLY, P9_DOTL_RDWR, P9_DOTL_TRUNC] {
+            call_err(&mut server, TLOPEN, libc::EROFS, |w| {
+                w.put_u32(1);
+                w.put_u32(flags);
+            });
+        }
+        call(&mut server, TLOPEN, |w| {
+            w.put_u32(1);
+            w.put_u32(0);
+        });
+        call_err(&mut server, TWRITE, libc::EROFS, |w| {
+            w.put_u32(1);
+            w.put_u64(0);
+            w.put_u32(1);
+            w.put_u8(0);
+        });
+        call_err(&mut server, TMKDIR, libc::EROFS, |w| {
+            w.put_u32(0);
+            w.put_str("sub");
+            w.put_u32(0o755);
+            w.put_u32(0);
+        });
+        call_err(&mut server, TUNLINKAT, libc::EROFS, |w| {
+            w.put_u32(0);
+            w.put_str("file");
+            w.put_u32(0);
+        });
+        assert!(dir.as_path().join("file").exists());
+    }
+}
@@ -86,6 +86,7 @@ impl Persist<'_> for Queue {
             next_used: state.next_used,
             uses_notif_suppression: false,
             num_added: state.num_added,
+            recorder: None,
         })
     }
 }
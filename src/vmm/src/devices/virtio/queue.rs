@@ -8,17 +8,23 @@
 use std::cmp::min;
 use std::num::Wrapping;
 use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, Instant};
 
 use log::error;
 use utils::vm_memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
 };
 
+use super::record::SharedRecorder;
+
 pub(super) const VIRTQ_DESC_F_NEXT: u16 = 0x1;
 pub(super) const VIRTQ_DESC_F_WRITE: u16 = 0x2;
 
 /// Max size of virtio queues offered by firecracker's virtio devices.
-pub(super) const FIRECRACKER_MAX_QUEUE_SIZE: u16 = 256;
+pub const FIRECRACKER_MAX_QUEUE_SIZE: u16 = 256;
+
+/// Max time, in microseconds, a virtio queue is busy-polled for new descriptors.
+pub const MAX_BUSY_POLL_US: u32 = 1000;
 
 // GuestMemoryMmap::read_obj_from_addr() will be used to fetch the descriptor,
 // which has an explicit constraint that the entire descriptor doesn't
@@ -206,6 +212,9 @@ pub struct Queue {
     pub(crate) uses_notif_suppression: bool,
     /// The number of added used buffers since last guest kick
     pub(crate) num_added: Wrapping<u16>,
+
+    /// Records the chains popped from and added to the queue, if recording is enabled
+    pub(crate) recorder: Option<SharedRecorder>,
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -223,9 +232,15 @@ impl Queue {
             next_used: Wrapping(0),
             uses_notif_suppression: false,
             num_added: Wrapping(0),
+            recorder: None,
         }
     }
 
+    /// Records the chains popped from and added to the queue from now on.
+    pub fn set_recorder(&mut self, recorder: SharedRecorder) {
+        self.recorder = Some(recorder);
+    }
+
     /// Maximum size of the queue.
     pub fn get_max_size(&self) -> u16 {
         self.max_size
@@ -401,18 +416,32 @@ impl Queue {
             .read_obj(self.avail_ring.unchecked_add(u64::from(index_offset)))
             .unwrap();
 
-        DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index).map(
-            |dc| {
-                self.next_avail += Wrapping(1);
-                dc
-            },
-        )
+        let chain =
+            DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index)?;
+        self.next_avail += Wrapping(1);
+        if let Some(recorder) = &self.recorder {
+            recorder.record_pop(self, &chain);
+        }
+        Some(chain)
+    }
+
+    /// Returns the descriptor chain whose head is at `index` in the descriptor table, such as a
+    /// chain popped before the queue was saved in a snapshot.
+    pub fn chain_at<'b>(
+        &self,
+        mem: &'b GuestMemoryMmap,
+        index: u16,
+    ) -> Option<DescriptorChain<'b>> {
+        DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), index)
     }
 
     /// Undo the effects of the last `self.pop()` call.
     /// The caller can use this, if it was unable to consume the last popped descriptor chain.
     pub fn undo_pop(&mut self) {
         self.next_avail -= Wrapping(1);
+        if let Some(recorder) = &self.recorder {
+            recorder.record_undo_pop();
+        }
     }
 
     /// Puts an available descriptor head into the used ring for use by the guest.
@@ -449,7 +478,12 @@ impl Queue {
 
         let next_used_addr = used_ring.unchecked_add(2);
         mem.write_obj(self.next_used.0, next_used_addr)
-            .map_err(QueueError::UsedRing)
+            .map_err(QueueError::UsedRing)?;
+
+        if let Some(recorder) = &self.recorder {
+            recorder.record_used(mem, desc_index, len);
+        }
+        Ok(())
     }
 
     /// Fetch the available ring index (`virtq_avail->idx`) from guest memory.
@@ -527,11 +561,44 @@ impl Queue {
         self.next_avail.0 == self.avail_idx(mem).0
     }
 
+    /// Busy-poll the available ring for up to `timeout`, instead of waiting for the driver to
+    /// notify the device. Returns true if one or more descriptors can be consumed. Otherwise,
+    /// notifications are enabled again, like with `try_enable_notification`.
+    pub fn poll_avail(&mut self, mem: &GuestMemoryMmap, timeout: Duration) -> bool {
+        debug_assert!(self.is_layout_valid(mem));
+
+        // The driver notifies the device when `avail_idx` moves past `avail_event`. Setting it
+        // behind `next_avail` suppresses the notifications for the whole polling window.
+        if self.uses_notif_suppression {
+            self.set_avail_event((self.next_avail - Wrapping(1)).0, mem);
+            fence(Ordering::SeqCst);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !self.is_empty(mem) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            std::hint::spin_loop();
+        }
+
+        !self.try_enable_notification(mem)
+    }
+
     /// Enable notification suppression.
     pub fn enable_notif_suppression(&mut self) {
         self.uses_notif_suppression = true;
     }
 
+    /// Returns the number of descriptor chains added to the used ring since the last call to
+    /// `prepare_kick`.
+    pub fn num_added(&self) -> u16 {
+        self.num_added.0
+    }
+
     /// Check if we need to kick the guest.
     ///
     /// Please note this method has side effects: once it returns `true`, it considers the
@@ -544,6 +611,7 @@ impl Queue {
 
         // If the device doesn't use notification suppression, always return true
         if !self.uses_notif_suppression {
+            self.num_added = Wrapping(0);
             return true;
         }
 
@@ -1215,6 +1283,12 @@ mod tests {
         q.enable_notif_suppression();
         assert!(q.pop_or_enable_notification(m).is_none());
         assert_eq!(q.avail_event(m), 2);
+
+        // The popped chains can be walked again from their head index.
+        let d = q.chain_at(m, 2).unwrap();
+        assert_eq!(d.index, 2);
+        assert_eq!(d.next_descriptor().unwrap().index, 3);
+        assert!(q.chain_at(m, 16).is_none());
     }
 
     #[test]
@@ -1375,6 +1449,7 @@ mod tests {
                         vq.avail.event.set(used_event);
                         q.num_added = Wrapping(num_added);
                         assert!(q.prepare_kick(m));
+                        assert_eq!(q.num_added(), 0);
                     }
                 }
             }
@@ -1437,6 +1512,27 @@ mod tests {
         assert_eq!(q.avail_event(m), 1);
     }
 
+    #[test]
+    fn test_poll_avail() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+
+        q.ready = true;
+        q.enable_notif_suppression();
+        vq.dtable[0].set(0x1000_u64, 0x1000, 0, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        // A descriptor chain is already available.
+        assert!(q.poll_avail(m, Duration::ZERO));
+        assert!(q.pop(m).is_some());
+
+        // The window expires on an empty queue, and the notifications are enabled again.
+        assert!(!q.poll_avail(m, Duration::from_micros(10)));
+        assert_eq!(q.avail_event(m), 1);
+    }
+
     #[test]
     fn test_queue_error_display() {
         let err = UsedRing(GuestMemoryError::InvalidGuestAddress(GuestAddress(0)));
@@ -0,0 +1,607 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording of the descriptor chains consumed and produced on a virtio queue, so that the
+//! I/O seen by a device model can be fed back into it offline by the `virtio-replay` tool.
+//!
+//! A recording starts with a header describing the device and the guest memory layout, followed
+//! by one event per queue operation. All the integers are little endian.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use utils::vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::queue::{DescriptorChain, Queue, VIRTQ_DESC_F_WRITE};
+use crate::vmm_config::virtio_record::QueueRecordConfig;
+
+/// Magic number at the start of a recording.
+pub const RECORD_MAGIC: [u8; 8] = *b"FCVIOREC";
+/// Version of the recording format.
+pub const RECORD_VERSION: u32 = 1;
+
+const EVENT_LAYOUT: u8 = 0;
+const EVENT_POP: u8 = 1;
+const EVENT_UNDO_POP: u8 = 2;
+const EVENT_USED: u8 = 3;
+
+/// Errors associated with the recording of a virtio queue.
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    /// Cannot create the recording file.
+    #[error("Cannot create the recording file: {0}")]
+    Create(io::Error),
+    /// Cannot read the recording.
+    #[error("Cannot read the recording: {0}")]
+    Read(io::Error),
+    /// The file is not a recording.
+    #[error("The file is not a virtio recording.")]
+    InvalidMagic,
+    /// The recording was made with an unsupported version of the format.
+    #[error("Unsupported recording version: {0}.")]
+    UnsupportedVersion(u32),
+    /// The recording holds an unknown event.
+    #[error("Unknown recording event: {0}.")]
+    InvalidEvent(u8),
+}
+
+/// Describes the recorded queue and the guest memory the chains refer to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// Virtio type of the device.
+    pub device_type: u32,
+    /// Id of the device.
+    pub device_id: String,
+    /// Index of the queue in the device.
+    pub queue_index: u16,
+    /// Guest memory regions, as (start address, size) pairs.
+    pub mem_regions: Vec<(u64, u64)>,
+}
+
+/// A descriptor of a recorded chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedDescriptor {
+    /// Index of the descriptor in the descriptor table.
+    pub index: u16,
+    /// Guest physical address of the buffer.
+    pub addr: u64,
+    /// Length of the buffer.
+    pub len: u32,
+    /// Flags of the descriptor.
+    pub flags: u16,
+    /// Index of the next descriptor of the chain.
+    pub next: u16,
+    /// Content of the buffer when the chain was popped. Empty for write-only buffers.
+    pub data: Vec<u8>,
+}
+
+/// An operation on the recorded queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordEvent {
+    /// Layout of the queue, recorded before the first chain.
+    Layout {
+        /// Size of the queue selected by the driver.
+        size: u16,
+        /// Guest physical address of the descriptor table.
+        desc_table: u64,
+        /// Guest physical address of the available ring.
+        avail_ring: u64,
+        /// Guest physical address of the used ring.
+        used_ring: u64,
+        /// Whether VIRTIO_F_RING_EVENT_IDX was negotiated.
+        notif_suppression: bool,
+    },
+    /// The device popped a chain from the available ring.
+    Pop(Vec<RecordedDescriptor>),
+    /// The device returned the last chain it popped to the available ring.
+    UndoPop,
+    /// The device put a chain in the used ring.
+    Used {
+        /// Index of the head descriptor of the chain.
+        index: u16,
+        /// Number of bytes the device wrote in the chain.
+        len: u32,
+        /// Bytes the device wrote in the write-only buffers of the chain.
+        data: Vec<u8>,
+    },
+}
+
+fn put_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+impl RecordHeader {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&RECORD_MAGIC);
+        buf.extend_from_slice(&RECORD_VERSION.to_le_bytes());
+        buf.extend_from_slice(&self.device_type.to_le_bytes());
+        buf.extend_from_slice(&self.queue_index.to_le_bytes());
+        put_bytes(buf, self.device_id.as_bytes());
+        buf.extend_from_slice(&(self.mem_regions.len() as u32).to_le_bytes());
+        for (start, size) in self.mem_regions.iter() {
+            buf.extend_from_slice(&start.to_le_bytes());
+            buf.extend_from_slice(&size.to_le_bytes());
+        }
+    }
+}
+
+impl RecordEvent {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            RecordEvent::Layout {
+                size,
+                desc_table,
+                avail_ring,
+                used_ring,
+                notif_suppression,
+            } => {
+                buf.push(EVENT_LAYOUT);
+                buf.extend_from_slice(&size.to_le_bytes());
+                buf.extend_from_slice(&desc_table.to_le_bytes());
+                buf.extend_from_slice(&avail_ring.to_le_bytes());
+                buf.extend_from_slice(&used_ring.to_le_bytes());
+                buf.push(u8::from(*notif_suppression));
+            }
+            RecordEvent::Pop(descriptors) => {
+                buf.push(EVENT_POP);
+                buf.extend_from_slice(&(descriptors.len() as u16).to_le_bytes());
+                for desc in descriptors.iter() {
+                    buf.extend_from_slice(&desc.index.to_le_bytes());
+                    buf.extend_from_slice(&desc.addr.to_le_bytes());
+                    buf.extend_from_slice(&desc.len.to_le_bytes());
+                    buf.extend_from_slice(&desc.flags.to_le_bytes());
+                    buf.extend_from_slice(&desc.next.to_le_bytes());
+                    put_bytes(buf, &desc.data);
+                }
+            }
+            RecordEvent::UndoPop => buf.push(EVENT_UNDO_POP),
+            RecordEvent::Used { index, len, data } => {
+                buf.push(EVENT_USED);
+                buf.extend_from_slice(&index.to_le_bytes());
+                buf.extend_from_slice(&len.to_le_bytes());
+                put_bytes(buf, data);
+            }
+        }
+    }
+}
+
+/// Records the operations on a virtio queue to a file.
+#[derive(Debug)]
+pub struct QueueRecorder {
+    file: File,
+    max_size_bytes: Option<u64>,
+    // Number of bytes written to the file.
+    size: u64,
+    // Set once the size limit is reached or a write fails, after which nothing is recorded.
+    stopped: bool,
+    layout_recorded: bool,
+    // Write-only buffers of the chains in flight, by head index, to record what the device wrote
+    // in them once they are used.
+    writable: HashMap<u16, Vec<(GuestAddress, u32)>>,
+    buf: Vec<u8>,
+}
+
+impl QueueRecorder {
+    /// Creates the recording file of `config` and writes `header` to it.
+    pub fn new(config: &QueueRecordConfig, header: &RecordHeader) -> Result<Self, RecordError> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&config.path)
+            .map_err(RecordError::Create)?;
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        file.write_all(&buf).map_err(RecordError::Create)?;
+
+        Ok(QueueRecorder {
+            file,
+            max_size_bytes: config.max_size_bytes,
+            size: buf.len() as u64,
+            stopped: false,
+            layout_recorded: false,
+            writable: HashMap::new(),
+            buf,
+        })
+    }
+
+    fn record(&mut self, event: &RecordEvent) {
+        if self.stopped {
+            return;
+        }
+
+        self.buf.clear();
+        event.encode(&mut self.buf);
+        let len = self.buf.len() as u64;
+        if let Some(max_size) = self.max_size_bytes {
+            if self.size + len > max_size {
+                warn!(
+                    "The virtio recording reached its size limit of {} bytes and is stopped.",
+                    max_size
+                );
+                self.stopped = true;
+                return;
+            }
+        }
+
+        match self.file.write_all(&self.buf) {
+            Ok(()) => self.size += len,
+            Err(err) => {
+                warn!("Failed to write the virtio recording, stopping it: {}", err);
+                self.stopped = true;
+            }
+        }
+    }
+
+    fn record_pop(&mut self, queue: &Queue, head: &DescriptorChain) {
+        if !self.layout_recorded {
+            self.layout_recorded = true;
+            self.record(&RecordEvent::Layout {
+                size: queue.actual_size(),
+                desc_table: queue.desc_table.0,
+                avail_ring: queue.avail_ring.0,
+                used_ring: queue.used_ring.0,
+                notif_suppression: queue.uses_notif_suppression,
+            });
+        }
+
+        let mut descriptors = Vec::new();
+        let mut writable = Vec::new();
+        let mut record_desc = |desc: &DescriptorChain| {
+            let mut data = Vec::new();
+            if desc.flags & VIRTQ_DESC_F_WRITE != 0 {
+                writable.push((desc.addr, desc.len));
+            } else {
+                data.resize(desc.len as usize, 0);
+                // A buffer out of the guest memory is recorded empty, the device rejects it.
+                if desc.mem.read_slice(&mut data, desc.addr).is_err() {
+                    data.clear();
+                }
+            }
+            descriptors.push(RecordedDescriptor {
+                index: desc.index,
+                addr: desc.addr.0,
+                len: desc.len,
+                flags: desc.flags,
+                next: desc.next,
+                data,
+            });
+        };
+        record_desc(head);
+        let mut next = head.next_descriptor();
+        while let Some(desc) = next {
+            record_desc(&desc);
+            next = desc.next_descriptor();
+        }
+
+        self.writable.insert(head.index, writable);
+        self.record(&RecordEvent::Pop(descriptors));
+    }
+
+    fn record_used(&mut self, mem: &GuestMemoryMmap, index: u16, len: u32) {
+        let mut data = Vec::new();
+        let mut remaining = len as usize;
+        for (addr, buf_len) in self.writable.remove(&index).unwrap_or_default() {
+            if remaining == 0 {
+                break;
+            }
+            let count = std::cmp::min(remaining, buf_len as usize);
+            let start = data.len();
+            data.resize(start + count, 0);
+            if mem.read_slice(&mut data[start..], addr).is_err() {
+                data.truncate(start);
+                break;
+            }
+            remaining -= count;
+        }
+        self.record(&RecordEvent::Used { index, len, data });
+    }
+}
+
+/// A recorder attached to a queue. The copies of a queue share its recorder.
+#[derive(Clone, Debug)]
+pub struct SharedRecorder(Arc<Mutex<QueueRecorder>>);
+
+impl SharedRecorder {
+    /// Wraps `recorder` to attach it to a queue.
+    pub fn new(recorder: QueueRecorder) -> Self {
+        SharedRecorder(Arc::new(Mutex::new(recorder)))
+    }
+
+    pub(crate) fn record_pop(&self, queue: &Queue, head: &DescriptorChain) {
+        self.0
+            .lock()
+            .expect("Poisoned lock")
+            .record_pop(queue, head);
+    }
+
+    pub(crate) fn record_undo_pop(&self) {
+        self.0
+            .lock()
+            .expect("Poisoned lock")
+            .record(&RecordEvent::UndoPop);
+    }
+
+    pub(crate) fn record_used(&self, mem: &GuestMemoryMmap, index: u16, len: u32) {
+        self.0
+            .lock()
+            .expect("Poisoned lock")
+            .record_used(mem, index, len);
+    }
+}
+
+impl PartialEq for SharedRecorder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedRecorder {}
+
+/// Reads a recording written by a [`QueueRecorder`].
+#[derive(Debug)]
+pub struct RecordReader<R: Read> {
+    reader: R,
+    header: RecordHeader,
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], RecordError> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(RecordError::Read)?;
+    Ok(buf)
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, RecordError> {
+    read_array(reader).map(u16::from_le_bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, RecordError> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, RecordError> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, RecordError> {
+    let len = read_u32(reader)?;
+    let mut data = Vec::new();
+    reader
+        .take(u64::from(len))
+        .read_to_end(&mut data)
+        .map_err(RecordError::Read)?;
+    if data.len() != len as usize {
+        return Err(RecordError::Read(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(data)
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Reads the header of the recording.
+    pub fn new(mut reader: R) -> Result<Self, RecordError> {
+        if read_array::<R, 8>(&mut reader)? != RECORD_MAGIC {
+            return Err(RecordError::InvalidMagic);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != RECORD_VERSION {
+            return Err(RecordError::UnsupportedVersion(version));
+        }
+        let device_type = read_u32(&mut reader)?;
+        let queue_index = read_u16(&mut reader)?;
+        let device_id = String::from_utf8_lossy(&read_bytes(&mut reader)?).into_owned();
+        let region_count = read_u32(&mut reader)?;
+        let mut mem_regions = Vec::new();
+        for _ in 0..region_count {
+            mem_regions.push((read_u64(&mut reader)?, read_u64(&mut reader)?));
+        }
+
+        Ok(RecordReader {
+            reader,
+            header: RecordHeader {
+                device_type,
+                device_id,
+                queue_index,
+                mem_regions,
+            },
+        })
+    }
+
+    /// Returns the header of the recording.
+    pub fn header(&self) -> &RecordHeader {
+        &self.header
+    }
+
+    /// Reads the next event of the recording, or `None` at the end of the recording.
+    pub fn next_event(&mut self) -> Result<Option<RecordEvent>, RecordError> {
+        let mut tag = [0u8; 1];
+        match self.reader.read_exact(&mut tag) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(RecordError::Read(err)),
+        }
+
+        let reader = &mut self.reader;
+        let event = match tag[0] {
+            EVENT_LAYOUT => RecordEvent::Layout {
+                size: read_u16(reader)?,
+                desc_table: read_u64(reader)?,
+                avail_ring: read_u64(reader)?,
+                used_ring: read_u64(reader)?,
+                notif_suppression: read_array::<R, 1>(reader)?[0] != 0,
+            },
+            EVENT_POP => {
+                let count = read_u16(reader)?;
+                let mut descriptors = Vec::new();
+                for _ in 0..count {
+                    descriptors.push(RecordedDescriptor {
+                        index: read_u16(reader)?,
+                        addr: read_u64(reader)?,
+                        len: read_u32(reader)?,
+                        flags: read_u16(reader)?,
+                        next: read_u16(reader)?,
+                        data: read_bytes(reader)?,
+                    });
+                }
+                RecordEvent::Pop(descriptors)
+            }
+            EVENT_UNDO_POP => RecordEvent::UndoPop,
+            EVENT_USED => RecordEvent::Used {
+                index: read_u16(reader)?,
+                len: read_u32(reader)?,
+                data: read_bytes(reader)?,
+            },
+            tag => return Err(RecordError::InvalidEvent(tag)),
+        };
+        Ok(Some(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::devices::virtio::{TYPE_BLOCK, VIRTQ_DESC_F_NEXT};
+
+    fn record_config(path: &str, max_size_bytes: Option<u64>) -> QueueRecordConfig {
+        QueueRecordConfig {
+            device_id: "rootfs".to_string(),
+            queue_index: 0,
+            path: path.to_string(),
+            max_size_bytes,
+        }
+    }
+
+    fn record_header() -> RecordHeader {
+        RecordHeader {
+            device_type: TYPE_BLOCK,
+            device_id: "rootfs".to_string(),
+            queue_index: 0,
+            mem_regions: vec![(0, 0x10000)],
+        }
+    }
+
+    #[test]
+    fn test_record_queue() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+        let recorder = QueueRecorder::new(&record_config(path, None), &record_header()).unwrap();
+        q.set_recorder(SharedRecorder::new(recorder));
+
+        // A chain made of a readable and a writable buffer.
+        vq.dtable[0].set(0x1000, 4, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x2000, 8, VIRTQ_DESC_F_WRITE, 0);
+        m.write_slice(&[1, 2, 3, 4], GuestAddress(0x1000)).unwrap();
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        let head = q.pop(m).unwrap();
+        assert_eq!(head.index, 0);
+        q.undo_pop();
+        let head = q.pop(m).unwrap();
+        m.write_slice(&[5, 6, 7], GuestAddress(0x2000)).unwrap();
+        q.add_used(m, head.index, 3).unwrap();
+
+        let mut reader = RecordReader::new(fs::File::open(path).unwrap()).unwrap();
+        assert_eq!(reader.header(), &record_header());
+        assert_eq!(
+            reader.next_event().unwrap(),
+            Some(RecordEvent::Layout {
+                size: 16,
+                desc_table: vq.dtable_start().0,
+                avail_ring: vq.avail_start().0,
+                used_ring: vq.used_start().0,
+                notif_suppression: false,
+            })
+        );
+        let pop = RecordEvent::Pop(vec![
+            RecordedDescriptor {
+                index: 0,
+                addr: 0x1000,
+                len: 4,
+                flags: VIRTQ_DESC_F_NEXT,
+                next: 1,
+                data: vec![1, 2, 3, 4],
+            },
+            RecordedDescriptor {
+                index: 1,
+                addr: 0x2000,
+                len: 8,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+                data: vec![],
+            },
+        ]);
+        assert_eq!(reader.next_event().unwrap(), Some(pop.clone()));
+        assert_eq!(reader.next_event().unwrap(), Some(RecordEvent::UndoPop));
+        assert_eq!(reader.next_event().unwrap(), Some(pop));
+        assert_eq!(
+            reader.next_event().unwrap(),
+            Some(RecordEvent::Used {
+                index: 0,
+                len: 3,
+                data: vec![5, 6, 7],
+            })
+        );
+        assert_eq!(reader.next_event().unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_size_limit() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        let mut header = Vec::new();
+        record_header().encode(&mut header);
+        // Room for the header and a single undo event.
+        let max_size = header.len() as u64 + 1;
+        let mut recorder =
+            QueueRecorder::new(&record_config(path, Some(max_size)), &record_header()).unwrap();
+
+        recorder.record(&RecordEvent::UndoPop);
+        recorder.record(&RecordEvent::UndoPop);
+        assert!(recorder.stopped);
+        assert_eq!(fs::metadata(path).unwrap().len(), max_size);
+    }
+
+    #[test]
+    fn test_read_invalid_recording() {
+        assert!(matches!(
+            RecordReader::new(&b"NOTAREC!"[..]),
+            Err(RecordError::InvalidMagic)
+        ));
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&RECORD_MAGIC);
+        buf.extend_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(
+            RecordReader::new(&buf[..]),
+            Err(RecordError::UnsupportedVersion(2))
+        ));
+
+        let mut buf = Vec::new();
+        record_header().encode(&mut buf);
+        buf.push(42);
+        let mut reader = RecordReader::new(&buf[..]).unwrap();
+        assert!(matches!(
+            reader.next_event(),
+            Err(RecordError::InvalidEvent(42))
+        ));
+
+        // A truncated event.
+        let mut buf = Vec::new();
+        record_header().encode(&mut buf);
+        buf.extend_from_slice(&[EVENT_USED, 0]);
+        let mut reader = RecordReader::new(&buf[..]).unwrap();
+        assert!(matches!(reader.next_event(), Err(RecordError::Read(_))));
+    }
+}
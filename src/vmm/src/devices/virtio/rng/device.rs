@@ -5,7 +5,7 @@ use std::io;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use aws_lc_rs::rand;
+use aws_lc_rs::{hmac, rand};
 use logger::{debug, error, IncMetric, METRICS};
 use utils::eventfd::EventFd;
 use utils::vm_memory::{GuestMemoryError, GuestMemoryMmap};
@@ -22,6 +22,9 @@ use crate::rate_limiter::{RateLimiter, TokenType};
 
 pub const ENTROPY_DEV_ID: &str = "rng";
 
+// Length of the HMAC-SHA256 tags, which make up the bytes of `SeededRng`.
+const SEEDED_BLOCK_LEN: usize = 32;
+
 #[derive(Debug, thiserror::Error)]
 pub enum EntropyError {
     #[error("Error while handling an Event file descriptor: {0}")]
@@ -32,6 +35,31 @@ pub enum EntropyError {
     Random(#[from] aws_lc_rs::error::Unspecified),
 }
 
+/// Deterministic source of the bytes served by the device: the HMAC-SHA256 tags of a counter,
+/// keyed by a seed.
+#[derive(Debug)]
+struct SeededRng {
+    key: hmac::Key,
+    counter: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        SeededRng {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &seed.to_le_bytes()),
+            counter: 0,
+        }
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(SEEDED_BLOCK_LEN) {
+            let tag = hmac::sign(&self.key, &self.counter.to_le_bytes());
+            chunk.copy_from_slice(&tag.as_ref()[..chunk.len()]);
+            self.counter += 1;
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Entropy {
     // VirtIO fields
@@ -47,6 +75,7 @@ pub struct Entropy {
 
     // Device specific fields
     rate_limiter: RateLimiter,
+    seeded_rng: Option<SeededRng>,
 }
 
 impl Entropy {
@@ -74,6 +103,7 @@ impl Entropy {
             queue_events,
             irq_trigger,
             rate_limiter,
+            seeded_rng: None,
         })
     }
 
@@ -81,6 +111,12 @@ impl Entropy {
         ENTROPY_DEV_ID
     }
 
+    /// Serves the bytes of a pseudo-random generator seeded with `seed`, instead of the ones of
+    /// the host, so that the guest reads the same bytes on every boot.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seeded_rng = Some(SeededRng::new(seed));
+    }
+
     fn signal_used_queue(&self) -> Result<(), DeviceError> {
         debug!("entropy: raising IRQ");
         self.irq_trigger
@@ -106,17 +142,23 @@ impl Entropy {
         rate_limiter.manual_replenish(bytes, TokenType::Bytes);
     }
 
-    fn handle_one(&self, iovec: &mut IoVecBufferMut) -> Result<u32, EntropyError> {
+    fn handle_one(
+        seeded_rng: &mut Option<SeededRng>,
+        iovec: &mut IoVecBufferMut,
+    ) -> Result<u32, EntropyError> {
         // If guest provided us with an empty buffer just return directly
         if iovec.len() == 0 {
             return Ok(0);
         }
 
         let mut rand_bytes = vec![0; iovec.len()];
-        rand::fill(&mut rand_bytes).map_err(|err| {
-            METRICS.entropy.host_rng_fails.inc();
-            err
-        })?;
+        match seeded_rng {
+            Some(seeded_rng) => seeded_rng.fill(&mut rand_bytes),
+            None => rand::fill(&mut rand_bytes).map_err(|err| {
+                METRICS.entropy.host_rng_fails.inc();
+                err
+            })?,
+        }
 
         // It is ok to unwrap here. We are writing `iovec.len()` bytes at offset 0.
         Ok(iovec.write_at(&rand_bytes, 0).unwrap().try_into().unwrap())
@@ -148,7 +190,7 @@ impl Entropy {
                         break;
                     }
 
-                    self.handle_one(&mut iovec).unwrap_or_else(|err| {
+                    Self::handle_one(&mut self.seeded_rng, &mut iovec).unwrap_or_else(|err| {
                         error!("entropy: {err}");
                         METRICS.entropy.entropy_event_fails.inc();
                         0
@@ -436,7 +478,24 @@ mod tests {
         // This should succeed, we should have one more descriptor
         let desc = entropy_dev.queues_mut()[RNG_QUEUE].pop(&mem).unwrap();
         let mut iovec = IoVecBufferMut::from_descriptor_chain(&mem, desc).unwrap();
-        assert!(entropy_dev.handle_one(&mut iovec).is_ok());
+        assert!(Entropy::handle_one(&mut entropy_dev.seeded_rng, &mut iovec).is_ok());
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        let mut bytes = [[0u8; 40]; 3];
+        SeededRng::new(42).fill(&mut bytes[0]);
+        SeededRng::new(42).fill(&mut bytes[1]);
+        SeededRng::new(43).fill(&mut bytes[2]);
+        assert_eq!(bytes[0], bytes[1]);
+        assert_ne!(bytes[0], bytes[2]);
+
+        // The stream carries on across the requests of the guest.
+        let mut rng = SeededRng::new(42);
+        let mut split = [0u8; 40];
+        rng.fill(&mut split[..SEEDED_BLOCK_LEN]);
+        rng.fill(&mut split[SEEDED_BLOCK_LEN..]);
+        assert_eq!(split, bytes[0]);
     }
 
     #[test]
@@ -137,6 +137,9 @@ pub struct VsockConnection<S: VsockConnectionBackend> {
     /// Instant when this connection should be scheduled for immediate termination, due to some
     /// timeout condition having been fulfilled.
     expiry: Option<Instant>,
+    /// Instant when data last went through this connection, in either direction, or when it
+    /// was created.
+    last_activity: Instant,
 }
 
 impl<S> VsockChannel for VsockConnection<S>
@@ -235,6 +238,7 @@ where
                         // length of the read data.
                         pkt.set_op(uapi::VSOCK_OP_RW).set_len(read_cnt as u32);
                         METRICS.vsock.rx_bytes_count.add(read_cnt);
+                        self.last_activity = Instant::now();
                     }
                     self.rx_cnt += Wrapping(pkt.len());
                     self.last_fwd_cnt_to_peer = self.fwd_cnt;
@@ -319,6 +323,7 @@ where
                     self.kill();
                     return Ok(());
                 }
+                self.last_activity = Instant::now();
 
                 // We might've just consumed some data. If that's the case, we might need to
                 // update the peer on our buffer space situation, so that it can keep sending
@@ -521,6 +526,7 @@ where
             last_fwd_cnt_to_peer: Wrapping(0),
             pending_rx: PendingRxSet::from(PendingRx::Response),
             expiry: None,
+            last_activity: Instant::now(),
         }
     }
 
@@ -547,6 +553,7 @@ where
             last_fwd_cnt_to_peer: Wrapping(0),
             pending_rx: PendingRxSet::from(PendingRx::Request),
             expiry: None,
+            last_activity: Instant::now(),
         }
     }
 
@@ -573,6 +580,11 @@ where
         self.expiry
     }
 
+    /// Get the instant when data last went through this connection, or when it was created.
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
     /// Schedule the connection to be forcefully terminated ASAP (i.e. the next time the
     /// connection is asked to yield a packet, via `recv_pkt()`).
     pub fn kill(&mut self) {
@@ -67,6 +67,8 @@ pub struct Vsock<B> {
     // continuous triggers from happening before the device gets activated.
     pub(crate) activate_evt: EventFd,
     pub(crate) device_state: DeviceState,
+    // Set while the vCPUs are paused, to hold the packets of the backend until the guest runs.
+    pause_lite: bool,
 }
 
 // TODO: Detect / handle queue deadlock:
@@ -100,6 +102,7 @@ where
             irq_trigger: IrqTrigger::new().map_err(VsockError::EventFd)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VsockError::EventFd)?,
             device_state: DeviceState::Inactive,
+            pause_lite: false,
         })
     }
 
@@ -127,6 +130,11 @@ where
         &self.backend
     }
 
+    /// Mutably access the backend behind the device.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> Result<(), DeviceError> {
@@ -136,11 +144,27 @@ where
             .map_err(DeviceError::FailedSignalingIrq)
     }
 
+    /// Holds the packets of the backend while the vCPUs are paused. The connection requests of
+    /// the host are then accepted and queued, instead of expiring before the guest can answer.
+    pub fn set_pause_lite(&mut self, pause_lite: bool) {
+        self.pause_lite = pause_lite;
+        if !pause_lite
+            && self.device_state.is_activated()
+            && self.backend.has_pending_rx()
+            && self.process_rx()
+        {
+            self.signal_used_queue().unwrap_or_default();
+        }
+    }
+
     /// Walk the driver-provided RX queue buffers and attempt to fill them up with any data that we
     /// have pending. Return `true` if descriptors have been added to the used ring, and `false`
     /// otherwise.
     pub fn process_rx(&mut self) -> bool {
         debug!("vsock: process_rx()");
+        if self.pause_lite {
+            return false;
+        }
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
@@ -214,6 +214,7 @@ mod tests {
     use super::*;
     use crate::devices::virtio::vsock::packet::VSOCK_PKT_HDR_SIZE;
     use crate::devices::virtio::vsock::test_utils::{EventHandlerContext, TestContext};
+    use crate::devices::virtio::IrqType;
 
     #[test]
     fn test_txq_event() {
@@ -354,6 +355,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pause_lite() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.mock_activate(test_ctx.mem.clone());
+
+        // The pending packets are held while the vCPUs are paused.
+        ctx.device.set_pause_lite(true);
+        ctx.device.backend.set_pending_rx(true);
+        ctx.signal_rxq_event();
+        assert_eq!(ctx.guest_rxvq.used.idx.get(), 0);
+
+        // And given to the guest once they run again.
+        ctx.device.set_pause_lite(false);
+        assert_eq!(ctx.guest_rxvq.used.idx.get(), 1);
+        assert!(ctx.device.irq_trigger.has_pending_irq(IrqType::Vring));
+    }
+
     #[test]
     fn test_evq_event() {
         // Test case: spurious EVQ_EVENT.
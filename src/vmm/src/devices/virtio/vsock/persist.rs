@@ -4,6 +4,9 @@
 //! Defines state and support structures for persisting Vsock devices and backends.
 
 use std::fmt::Debug;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
@@ -47,8 +50,74 @@ pub enum VsockBackendState {
 // NOTICE: Any changes to this structure require a snapshot version bump.
 #[derive(Debug, Clone, Versionize)]
 pub struct VsockUdsState {
-    /// The path for the UDS socket.
+    /// The path for the UDS socket, empty if the backend is not bound.
     pub(crate) path: String,
+    /// The port on which the guest can read the host clock.
+    #[version(start = 2, default_fn = "default_clock_port")]
+    pub(crate) clock_port: Option<u32>,
+    /// The guest ports which accepted host-initiated connections.
+    #[version(start = 2, default_fn = "default_listening_ports")]
+    pub(crate) listening_ports: Vec<u32>,
+    /// The connections open when the snapshot was taken, which the restored device drops.
+    #[version(start = 2, default_fn = "default_connections")]
+    pub(crate) connections: Vec<VsockConnectionState>,
+    /// The backlog of the host-side Unix socket, if the default one is not used.
+    #[version(start = 2, default_fn = "default_listen_backlog")]
+    pub(crate) listen_backlog: Option<u32>,
+    /// The maximum number of connections.
+    #[version(start = 2, default_fn = "default_max_connections")]
+    pub(crate) max_connections: u32,
+    /// The time, in milliseconds, after which a connection through which no data went is
+    /// killed.
+    #[version(start = 2, default_fn = "default_idle_timeout_ms")]
+    pub(crate) idle_timeout_ms: Option<u64>,
+}
+
+impl VsockUdsState {
+    fn default_clock_port(_: u16) -> Option<u32> {
+        None
+    }
+
+    fn default_listening_ports(_: u16) -> Vec<u32> {
+        Vec::new()
+    }
+
+    fn default_connections(_: u16) -> Vec<VsockConnectionState> {
+        Vec::new()
+    }
+
+    fn default_listen_backlog(_: u16) -> Option<u32> {
+        None
+    }
+
+    fn default_max_connections(_: u16) -> u32 {
+        VsockUnixBackend::MAX_CONNECTIONS as u32
+    }
+
+    fn default_idle_timeout_ms(_: u16) -> Option<u64> {
+        None
+    }
+}
+
+/// A connection of the Vsock Unix Backend, as saved in a snapshot.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Versionize)]
+pub struct VsockConnectionState {
+    /// The host-side port of the connection.
+    pub(crate) host_port: u32,
+    /// The guest-side port of the connection.
+    pub(crate) guest_port: u32,
+    /// Whether the connection was initiated by the host.
+    pub(crate) host_initiated: bool,
+}
+
+impl VsockBackendState {
+    /// Sets the path the restored backend binds to. An empty path leaves it unbound.
+    pub(crate) fn set_uds_path(&mut self, path: String) {
+        match self {
+            VsockBackendState::Uds(uds_state) => uds_state.path = path,
+        }
+    }
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -75,6 +144,14 @@ impl Persist<'_> for VsockUnixBackend {
     fn save(&self) -> Self::State {
         VsockBackendState::Uds(VsockUdsState {
             path: self.host_sock_path.clone(),
+            clock_port: self.clock_port(),
+            listening_ports: self.listening_ports(),
+            connections: self.connections(),
+            listen_backlog: self.listen_backlog(),
+            max_connections: self.max_connections() as u32,
+            idle_timeout_ms: self
+                .idle_timeout()
+                .map(|timeout| timeout.as_millis() as u64),
         })
     }
 
@@ -82,11 +159,41 @@ impl Persist<'_> for VsockUnixBackend {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
-        match state {
-            VsockBackendState::Uds(uds_state) => Ok(VsockUnixBackend::new(
-                constructor_args.cid,
-                uds_state.path.clone(),
-            )?),
+        let VsockBackendState::Uds(uds_state) = state;
+        let mut backend = if uds_state.path.is_empty() {
+            VsockUnixBackend::new_unbound(constructor_args.cid)?
+        } else {
+            remove_stale_socket(&uds_state.path);
+            VsockUnixBackend::new(constructor_args.cid, uds_state.path.clone())?
+        };
+        backend.set_clock_port(uds_state.clock_port);
+        backend.set_listen_backlog(uds_state.listen_backlog)?;
+        backend.set_max_connections(uds_state.max_connections as usize);
+        backend.set_idle_timeout(
+            uds_state
+                .idle_timeout_ms
+                .map(std::time::Duration::from_millis),
+        );
+        backend.set_resync_state(
+            uds_state.listening_ports.clone(),
+            uds_state.connections.clone(),
+        );
+        Ok(backend)
+    }
+}
+
+// Removes the socket left at `path` by the microVM the snapshot was taken from, so that the
+// restored backend can bind to the same path. A socket something still listens on is kept.
+fn remove_stale_socket(path: &str) {
+    let is_socket = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if !is_socket {
+        return;
+    }
+    if let Err(err) = UnixStream::connect(path) {
+        if err.kind() == io::ErrorKind::ConnectionRefused {
+            let _ = std::fs::remove_file(path);
         }
     }
 }
@@ -153,6 +260,12 @@ pub(crate) mod tests {
         fn save(&self) -> Self::State {
             VsockBackendState::Uds(VsockUdsState {
                 path: "test".to_owned(),
+                clock_port: None,
+                listening_ports: Vec::new(),
+                connections: Vec::new(),
+                listen_backlog: None,
+                max_connections: 0,
+                idle_timeout_ms: None,
             })
         }
 
@@ -243,4 +356,65 @@ pub(crate) mod tests {
         restored_device.read_config(2, &mut data);
         assert_eq!(data, [0u8, 1, 2, 3, 4, 5, 6, 7]);
     }
+
+    #[test]
+    fn test_restore_unix_backend() {
+        let path = utils::tempfile::TempFile::new_with_prefix("/tmp/vsock_restore")
+            .unwrap()
+            .as_path()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let ctor_args = || VsockUdsConstructorArgs { cid: 3 };
+        let mut state = VsockBackendState::Uds(VsockUdsState {
+            path: path.clone(),
+            clock_port: Some(123),
+            listening_ports: vec![52],
+            connections: vec![VsockConnectionState {
+                host_port: 1 << 30,
+                guest_port: 52,
+                host_initiated: true,
+            }],
+            listen_backlog: Some(16),
+            max_connections: 8,
+            idle_timeout_ms: Some(60_000),
+        });
+
+        // The socket of a live backend is kept.
+        let backend = VsockUnixBackend::restore(ctor_args(), &state).unwrap();
+        assert!(VsockUnixBackend::restore(ctor_args(), &state).is_err());
+
+        // The socket left behind by a backend which is gone is replaced.
+        drop(backend);
+        let backend = VsockUnixBackend::restore(ctor_args(), &state).unwrap();
+        assert_eq!(backend.host_sock_path(), path);
+        assert_eq!(backend.clock_port(), Some(123));
+        assert_eq!(backend.listen_backlog(), Some(16));
+        assert_eq!(backend.max_connections(), 8);
+        assert_eq!(
+            backend.idle_timeout(),
+            Some(std::time::Duration::from_secs(60))
+        );
+        // The saved connections are dropped, and reported to the host as such.
+        assert_eq!(backend.listening_ports(), vec![52]);
+        assert!(backend.connections().is_empty());
+        assert_eq!(
+            backend.resync_report(),
+            "RESYNC 1\nLISTENING 52\nDROPPED HOST 1073741824 52\nEND\n"
+        );
+        drop(backend);
+
+        // An empty path leaves the backend unbound, until it is bound explicitly.
+        state.set_uds_path(String::new());
+        let mut backend = VsockUnixBackend::restore(ctor_args(), &state).unwrap();
+        assert!(!backend.is_bound());
+        assert_eq!(backend.clock_port(), Some(123));
+        backend.bind(path.clone()).unwrap();
+        assert!(backend.is_bound());
+        assert!(matches!(
+            backend.bind(path.clone()),
+            Err(VsockUnixBackendError::AlreadyBound)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -16,7 +16,8 @@ pub use muxer::VsockMuxer as VsockUnixBackend;
 use crate::devices::virtio::vsock::csm::VsockConnectionBackend;
 
 mod defs {
-    /// Maximum number of established connections that we can handle.
+    /// Maximum number of established connections that we can handle. A lower limit can be
+    /// configured.
     pub const MAX_CONNECTIONS: usize = 1023;
 
     /// Size of the muxer RX packet queue.
@@ -39,12 +40,20 @@ pub enum VsockUnixBackendError {
     UnixAccept(std::io::Error),
     /// Error binding to the host-side Unix socket.
     UnixBind(std::io::Error),
+    /// Error setting the backlog of the host-side Unix socket.
+    UnixListen(std::io::Error),
     /// Error connecting to a host-side Unix socket.
     UnixConnect(std::io::Error),
     /// Error reading from host-side Unix socket.
     UnixRead(std::io::Error),
+    /// Error writing to a host-side Unix socket.
+    UnixWrite(std::io::Error),
     /// Muxer connection limit reached.
     TooManyConnections,
+    /// The muxer is already bound to a host-side Unix socket.
+    AlreadyBound,
+    /// Error creating the timer closing the idle connections.
+    IdleTimer(std::io::Error),
 }
 
 type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
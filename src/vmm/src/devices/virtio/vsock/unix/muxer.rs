@@ -17,33 +17,38 @@
 ///    (leading to the termination of an existing connection). All other packets, though, must
 ///    belong to an existing connection and, as such, the muxer simply forwards them.
 /// 2. Event dispatcher
-///    There are three event categories that the vsock backend is interested it:
+///    There are four event categories that the vsock backend is interested it:
 ///    1. A new host-initiated connection is ready to be accepted from the listening host Unix
 ///       socket;
 ///    2. Data is available for reading from a newly-accepted host-initiated connection (i.e.
 ///       the host is ready to issue a vsock connection request, informing us of the
-///       destination port to which it wants to connect);
+///       destination port to which it wants to connect, or to ask which guest ports were
+///       listening and which connections were dropped when the microVM was restored);
 ///    3. Some event was triggered for a connected Unix socket, that belongs to a
 ///       `VsockConnection`.
+///    4. The timer closing the idle connections expired.
 ///    The muxer gets notified about all of these events, because, as a `VsockEpollListener`
 ///    implementor, it gets to register a nested epoll FD into the main VMM epolling loop. All
 ///    other pollable FDs are then registered under this nested epoll FD.
 ///    To route all these events to their handlers, the muxer uses another `HashMap` object,
 ///    mapping `RawFd`s to `EpollListener`s.
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Debug;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use log::{debug, error, info, warn};
 use logger::{IncMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use utils::vm_memory::GuestMemoryMmap;
 
 use super::super::csm::ConnState;
 use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
+use super::super::persist::VsockConnectionState;
 use super::super::{VsockBackend, VsockChannel, VsockEpollListener, VsockError};
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
@@ -78,6 +83,8 @@ enum EpollListener {
     /// A listener interested in reading host "connect <port>" commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// A listener interested in the expiry of the timer closing the idle connections.
+    IdleTimer,
 }
 
 /// The vsock connection multiplexer.
@@ -96,10 +103,12 @@ pub struct VsockMuxer {
     rxq: MuxerRxQ,
     /// A queue used for terminating connections that are taking too long to shut down.
     killq: MuxerKillQ,
-    /// The Unix socket, through which host-initiated connections are accepted.
-    host_sock: UnixListener,
+    /// The Unix socket, through which host-initiated connections are accepted. It is `None`
+    /// while the muxer is not bound yet, as when a snapshot is restored with a deferred vsock.
+    host_sock: Option<UnixListener>,
     /// The file system path of the host-side Unix socket. This is used to figure out the path
-    /// to Unix sockets listening on specific ports. I.e. "<this path>_<port number>".
+    /// to Unix sockets listening on specific ports. I.e. "<this path>_<port number>". It is empty
+    /// while the muxer is not bound.
     pub(crate) host_sock_path: String,
     /// The nested epoll event set, used to register epoll listeners.
     epoll: Epoll,
@@ -108,6 +117,32 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The port on which the muxer itself serves the host clock to the guest, if any.
+    clock_port: Option<u32>,
+    /// The guest ports which accepted host-initiated connections.
+    listening_ports: BTreeSet<u32>,
+    /// The connections dropped when the microVM was restored from a snapshot.
+    dropped_connections: Vec<VsockConnectionState>,
+    /// The backlog of the host-side Unix socket, if the default one is not used.
+    listen_backlog: Option<u32>,
+    /// The maximum number of connections, at most `defs::MAX_CONNECTIONS`.
+    max_connections: usize,
+    /// The time after which a connection through which no data went is killed, if any.
+    idle_timeout: Option<Duration>,
+    /// The timer looking for idle connections, armed while `idle_timeout` is set.
+    idle_timer: TimerFd,
+}
+
+/// Version of the report answering a host "resync" command.
+const RESYNC_VERSION: u32 = 1;
+
+/// A command read from a newly-accepted host-initiated connection.
+#[derive(Debug, PartialEq, Eq)]
+enum LocalRequest {
+    /// Connect to the given guest port.
+    Connect(u32),
+    /// Report the listening guest ports and the dropped connections, then close.
+    Resync,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -236,6 +271,14 @@ impl VsockChannel for VsockMuxer {
         // However, if this is an RST, we have to forcefully terminate the connection, so
         // there's no point in forwarding it the packet.
         if pkt.op() == uapi::VSOCK_OP_RST {
+            // A guest port refusing a host-initiated connection no longer listens.
+            if self
+                .conn_map
+                .get(&conn_key)
+                .map_or(false, |conn| conn.state() == ConnState::LocalInit)
+            {
+                self.listening_ports.remove(&conn_key.peer_port);
+            }
             self.remove_connection(conn_key);
             return Ok(());
         }
@@ -303,18 +346,25 @@ impl VsockEpollListener for VsockMuxer {
 impl VsockBackend for VsockMuxer {}
 
 impl VsockMuxer {
+    /// The highest maximum number of connections the muxer can be configured with.
+    pub const MAX_CONNECTIONS: usize = defs::MAX_CONNECTIONS;
+
     /// Muxer constructor.
     pub fn new(cid: u64, host_sock_path: String) -> Result<Self, VsockUnixBackendError> {
-        // Open/bind on the host Unix socket, so we can accept host-initiated
-        // connections.
-        let host_sock = UnixListener::bind(&host_sock_path)
-            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
-            .map_err(VsockUnixBackendError::UnixBind)?;
+        let mut muxer = Self::new_unbound(cid)?;
+        muxer.bind(host_sock_path)?;
+        Ok(muxer)
+    }
 
+    /// Creates a muxer which is not bound to a host-side Unix socket yet. Until it is bound, the
+    /// host cannot initiate connections and the guest-initiated ones are reset.
+    pub fn new_unbound(cid: u64) -> Result<Self, VsockUnixBackendError> {
+        let idle_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .map_err(VsockUnixBackendError::IdleTimer)?;
         let mut muxer = Self {
             cid,
-            host_sock,
-            host_sock_path,
+            host_sock: None,
+            host_sock_path: String::new(),
             epoll: Epoll::new().map_err(VsockUnixBackendError::EpollFdCreate)?,
             rxq: MuxerRxQ::new(),
             conn_map: HashMap::with_capacity(defs::MAX_CONNECTIONS),
@@ -322,11 +372,57 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            clock_port: None,
+            listening_ports: BTreeSet::new(),
+            dropped_connections: Vec::new(),
+            listen_backlog: None,
+            max_connections: defs::MAX_CONNECTIONS,
+            idle_timeout: None,
+            idle_timer,
         };
+        muxer.add_listener(muxer.idle_timer.as_raw_fd(), EpollListener::IdleTimer)?;
+        Ok(muxer)
+    }
+
+    /// Binds an unbound muxer to the host-side Unix socket at `host_sock_path`.
+    pub fn bind(&mut self, host_sock_path: String) -> Result<(), VsockUnixBackendError> {
+        if self.host_sock.is_some() {
+            return Err(VsockUnixBackendError::AlreadyBound);
+        }
+
+        // Open/bind on the host Unix socket, so we can accept host-initiated
+        // connections.
+        let host_sock = UnixListener::bind(&host_sock_path)
+            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+            .map_err(VsockUnixBackendError::UnixBind)?;
+        if let Some(backlog) = self.listen_backlog {
+            Self::set_backlog(&host_sock, backlog)?;
+        }
 
         // Listen on the host initiated socket, for incoming connections.
-        muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
-        Ok(muxer)
+        self.add_listener(host_sock.as_raw_fd(), EpollListener::HostSock)?;
+        self.host_sock = Some(host_sock);
+        self.host_sock_path = host_sock_path;
+        Ok(())
+    }
+
+    // Sets the backlog of the listening `host_sock`. Listening again on a socket only updates its
+    // backlog.
+    fn set_backlog(host_sock: &UnixListener, backlog: u32) -> Result<(), VsockUnixBackendError> {
+        // The kernel caps the backlog to `net.core.somaxconn` anyway.
+        let backlog = i32::try_from(backlog).unwrap_or(i32::MAX);
+        // SAFETY: The file descriptor is valid, since `host_sock` is open.
+        if unsafe { libc::listen(host_sock.as_raw_fd(), backlog) } < 0 {
+            return Err(VsockUnixBackendError::UnixListen(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the muxer is bound to a host-side Unix socket.
+    pub fn is_bound(&self) -> bool {
+        self.host_sock.is_some()
     }
 
     /// Return the file system path of the host-side Unix socket.
@@ -334,6 +430,134 @@ impl VsockMuxer {
         &self.host_sock_path
     }
 
+    /// Sets the port on which the guest can read the host clock. The guest connections to this
+    /// port are served by the muxer, instead of a host-side Unix socket.
+    pub fn set_clock_port(&mut self, clock_port: Option<u32>) {
+        self.clock_port = clock_port;
+    }
+
+    /// Returns the port on which the guest can read the host clock.
+    pub fn clock_port(&self) -> Option<u32> {
+        self.clock_port
+    }
+
+    /// Sets the backlog of the host-side Unix socket, right away if the muxer is bound. Without
+    /// a backlog, the sockets bound from then on get the default one.
+    pub fn set_listen_backlog(
+        &mut self,
+        backlog: Option<u32>,
+    ) -> Result<(), VsockUnixBackendError> {
+        if let (Some(host_sock), Some(backlog)) = (&self.host_sock, backlog) {
+            Self::set_backlog(host_sock, backlog)?;
+        }
+        self.listen_backlog = backlog;
+        Ok(())
+    }
+
+    /// Returns the backlog of the host-side Unix socket, if the default one is not used.
+    pub fn listen_backlog(&self) -> Option<u32> {
+        self.listen_backlog
+    }
+
+    /// Sets the maximum number of connections, capped to `Self::MAX_CONNECTIONS`. The
+    /// connections beyond it are refused: the guest ones are reset and the host ones closed.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections.min(defs::MAX_CONNECTIONS);
+    }
+
+    /// Returns the maximum number of connections.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Sets the time after which the connections through which no data went are killed. The
+    /// idle connections are looked for every `idle_timeout`, so a connection can stay idle for
+    /// up to twice as long before being killed.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        let state = match idle_timeout {
+            Some(timeout) => TimerState::Periodic {
+                current: timeout,
+                interval: timeout,
+            },
+            None => TimerState::Disarmed,
+        };
+        self.idle_timer.set_state(state, SetTimeFlags::Default);
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Returns the time after which the connections through which no data went are killed.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Returns the guest ports which accepted host-initiated connections, in ascending order.
+    pub fn listening_ports(&self) -> Vec<u32> {
+        self.listening_ports.iter().copied().collect()
+    }
+
+    /// Returns the open connections, leaving out the ones served by the clock port.
+    pub fn connections(&self) -> Vec<VsockConnectionState> {
+        let mut connections: Vec<_> = self
+            .conn_map
+            .keys()
+            .map(|key| VsockConnectionState {
+                host_port: key.local_port,
+                guest_port: key.peer_port,
+                host_initiated: self.local_port_set.contains(&key.local_port),
+            })
+            .filter(|conn| conn.host_initiated || self.clock_port != Some(conn.host_port))
+            .collect();
+        connections.sort_by_key(|conn| (conn.host_port, conn.guest_port));
+        connections
+    }
+
+    /// Sets what a host "resync" command reports: the guest ports which were listening, and the
+    /// connections dropped when the microVM was restored.
+    pub fn set_resync_state(
+        &mut self,
+        listening_ports: Vec<u32>,
+        dropped_connections: Vec<VsockConnectionState>,
+    ) {
+        // The host ports of the dropped connections are not handed out again right away, so
+        // that a new connection cannot be mistaken for a dropped one.
+        if let Some(port) = dropped_connections
+            .iter()
+            .filter(|conn| conn.host_initiated)
+            .map(|conn| conn.host_port)
+            .max()
+        {
+            self.local_port_last = self.local_port_last.max(port);
+        }
+        self.listening_ports = listening_ports.into_iter().collect();
+        self.dropped_connections = dropped_connections;
+    }
+
+    /// Builds the answer to a host "resync" command:
+    /// - `RESYNC <version>`;
+    /// - `LISTENING <guest port>` for every guest port which accepted a host connection;
+    /// - `DROPPED <HOST|GUEST> <host port> <guest port>` for every connection dropped on
+    ///   restore, along with the side which initiated it;
+    /// - `END`.
+    pub fn resync_report(&self) -> String {
+        use std::fmt::Write as _;
+
+        let mut report = String::new();
+        let _ = writeln!(report, "RESYNC {}", RESYNC_VERSION);
+        for port in &self.listening_ports {
+            let _ = writeln!(report, "LISTENING {}", port);
+        }
+        for conn in &self.dropped_connections {
+            let initiator = if conn.host_initiated { "HOST" } else { "GUEST" };
+            let _ = writeln!(
+                report,
+                "DROPPED {} {} {}",
+                initiator, conn.host_port, conn.guest_port
+            );
+        }
+        report.push_str("END\n");
+        report
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, event_set: EventSet) {
         debug!(
@@ -357,14 +581,19 @@ impl VsockMuxer {
 
             // A new host-initiated connection is ready to be accepted.
             Some(EpollListener::HostSock) => {
-                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                let limit_reached = self.check_connection_limit().is_err();
+                // The listener is only registered once the muxer is bound.
+                let Some(host_sock) = &self.host_sock else {
+                    return;
+                };
+                if limit_reached {
                     // If we're already maxed-out on connections, we'll just accept and
                     // immediately discard this potentially new one.
                     warn!("vsock: connection limit reached; refusing new host connection");
-                    self.host_sock.accept().map(|_| 0).unwrap_or(0);
+                    host_sock.accept().map(|_| 0).unwrap_or(0);
                     return;
                 }
-                self.host_sock
+                host_sock
                     .accept()
                     .map_err(VsockUnixBackendError::UnixAccept)
                     .and_then(|(stream, _)| {
@@ -386,32 +615,38 @@ impl VsockMuxer {
             }
 
             // Data is ready to be read from a host-initiated connection. That would be the
-            // "connect" command that we're expecting.
+            // "connect" or "resync" command that we're expecting.
             Some(EpollListener::LocalStream(_)) => {
                 if let Some(EpollListener::LocalStream(mut stream)) = self.remove_listener(fd) {
-                    Self::read_local_stream_port(&mut stream)
-                        .map(|peer_port| (self.allocate_local_port(), peer_port))
-                        .and_then(|(local_port, peer_port)| {
-                            self.add_connection(
-                                ConnMapKey {
-                                    local_port,
-                                    peer_port,
-                                },
-                                MuxerConnection::new_local_init(
-                                    stream,
-                                    uapi::VSOCK_HOST_CID,
-                                    self.cid,
-                                    local_port,
-                                    peer_port,
-                                ),
-                            )
+                    Self::read_local_request(&mut stream)
+                        .and_then(|request| match request {
+                            LocalRequest::Connect(peer_port) => {
+                                let local_port = self.allocate_local_port();
+                                self.add_connection(
+                                    ConnMapKey {
+                                        local_port,
+                                        peer_port,
+                                    },
+                                    MuxerConnection::new_local_init(
+                                        stream,
+                                        uapi::VSOCK_HOST_CID,
+                                        self.cid,
+                                        local_port,
+                                        peer_port,
+                                    ),
+                                )
+                            }
+                            LocalRequest::Resync => self.send_resync_report(stream),
                         })
                         .unwrap_or_else(|err| {
-                            info!("vsock: error adding local-init connection: {:?}", err);
+                            info!("vsock: error handling local request: {:?}", err);
                         })
                 }
             }
 
+            // The idle connections are looked for periodically.
+            Some(EpollListener::IdleTimer) => self.kill_idle_connections(),
+
             _ => {
                 info!(
                     "vsock: unexpected event: fd={:?}, evset={:?}",
@@ -422,13 +657,14 @@ impl VsockMuxer {
         }
     }
 
-    /// Parse a host "connect" command, and extract the destination vsock port.
-    fn read_local_stream_port(stream: &mut UnixStream) -> Result<u32, VsockUnixBackendError> {
+    /// Parse a host command: either "connect", along with the destination vsock port, or
+    /// "resync".
+    fn read_local_request(stream: &mut UnixStream) -> Result<LocalRequest, VsockUnixBackendError> {
         let mut buf = [0u8; 32];
 
         // This is the minimum number of bytes that we should be able to read, when parsing a
-        // valid connection request. I.e. `b"connect 0\n".len()`.
-        const MIN_READ_LEN: usize = 10;
+        // valid request. I.e. `b"resync\n".len()`.
+        const MIN_READ_LEN: usize = 7;
 
         // Bring in the minimum number of bytes that we should be able to read.
         stream
@@ -450,47 +686,54 @@ impl VsockMuxer {
             .map_err(|_| VsockUnixBackendError::InvalidPortRequest)?
             .split_whitespace();
 
-        word_iter
-            .next()
-            .ok_or(VsockUnixBackendError::InvalidPortRequest)
-            .and_then(|word| {
-                if word.to_lowercase() == "connect" {
-                    Ok(())
-                } else {
-                    Err(VsockUnixBackendError::InvalidPortRequest)
-                }
-            })
-            .and_then(|_| {
-                word_iter
-                    .next()
-                    .ok_or(VsockUnixBackendError::InvalidPortRequest)
-            })
-            .and_then(|word| {
-                word.parse::<u32>()
-                    .map_err(|_| VsockUnixBackendError::InvalidPortRequest)
-            })
-            .map_err(|_| VsockUnixBackendError::InvalidPortRequest)
+        match word_iter.next().map(str::to_lowercase).as_deref() {
+            Some("connect") => word_iter
+                .next()
+                .and_then(|word| word.parse::<u32>().ok())
+                .map(LocalRequest::Connect)
+                .ok_or(VsockUnixBackendError::InvalidPortRequest),
+            Some("resync") => Ok(LocalRequest::Resync),
+            _ => Err(VsockUnixBackendError::InvalidPortRequest),
+        }
     }
 
-    /// Add a new connection to the active connection pool.
-    fn add_connection(
-        &mut self,
-        key: ConnMapKey,
-        conn: MuxerConnection,
-    ) -> Result<(), VsockUnixBackendError> {
+    /// Answer a host "resync" command. The connection is closed right after.
+    fn send_resync_report(&self, mut stream: UnixStream) -> Result<(), VsockUnixBackendError> {
+        // The report is small enough for the socket buffer, so the write does not block.
+        stream
+            .write_all(self.resync_report().as_bytes())
+            .map_err(VsockUnixBackendError::UnixWrite)?;
+        METRICS.vsock.resync_requests.inc();
+        Ok(())
+    }
+
+    /// Check whether there is room for a new connection, counting the refused connection in
+    /// the metrics when there is none.
+    fn check_connection_limit(&mut self) -> Result<(), VsockUnixBackendError> {
         // We might need to make room for this new connection, so let's sweep the kill queue
         // first.  It's fine to do this here because:
         // - unless the kill queue is out of sync, this is a pretty inexpensive operation; and
         // - we are under no pressure to respect any accurate timing for connection termination.
         self.sweep_killq();
 
-        if self.conn_map.len() >= defs::MAX_CONNECTIONS {
+        if self.conn_map.len() >= self.max_connections {
             info!(
                 "vsock: muxer connection limit reached ({})",
-                defs::MAX_CONNECTIONS
+                self.max_connections
             );
+            METRICS.vsock.conns_rejected.inc();
             return Err(VsockUnixBackendError::TooManyConnections);
         }
+        Ok(())
+    }
+
+    /// Add a new connection to the active connection pool.
+    fn add_connection(
+        &mut self,
+        key: ConnMapKey,
+        conn: MuxerConnection,
+    ) -> Result<(), VsockUnixBackendError> {
+        self.check_connection_limit()?;
 
         self.add_listener(
             conn.as_raw_fd(),
@@ -551,6 +794,7 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => EventSet::IN,
             EpollListener::HostSock => EventSet::IN,
+            EpollListener::IdleTimer => EventSet::IN,
         };
 
         self.epoll
@@ -612,6 +856,21 @@ impl VsockMuxer {
     /// connection object will be created and added to the connection pool. On failure, a new
     /// RST packet will be scheduled for delivery to the guest.
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
+        if self.clock_port == Some(pkt.dst_port()) {
+            self.handle_clock_request_pkt(pkt);
+            return;
+        }
+        // No host-side socket can listen on the port until the muxer is bound.
+        if !self.is_bound() {
+            self.enq_rst(pkt.dst_port(), pkt.src_port());
+            return;
+        }
+        // The connection is refused before a host-side socket is opened for it, so that a guest
+        // flooding the device with connection requests cannot exhaust the VMM file descriptors.
+        if self.check_connection_limit().is_err() {
+            self.enq_rst(pkt.dst_port(), pkt.src_port());
+            return;
+        }
         let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
 
         UnixStream::connect(port_path)
@@ -636,6 +895,43 @@ impl VsockMuxer {
             .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
     }
 
+    /// Handle a connection request to the clock port.
+    ///
+    /// The current host time is written to one end of a new Unix socket pair, which is then
+    /// closed, and the other end backs the connection, as if it came from a host-side Unix
+    /// socket. The guest thus reads a single line, `<seconds>.<nanoseconds>` since the Unix
+    /// epoch, followed by the end of the stream.
+    fn handle_clock_request_pkt(&mut self, pkt: &VsockPacket) {
+        UnixStream::pair()
+            .and_then(|(stream, mut clock_stream)| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                writeln!(clock_stream, "{}.{:09}", now.as_secs(), now.subsec_nanos())?;
+                stream.set_nonblocking(true)?;
+                Ok(stream)
+            })
+            .map_err(VsockUnixBackendError::UnixConnect)
+            .and_then(|stream| {
+                self.add_connection(
+                    ConnMapKey {
+                        local_port: pkt.dst_port(),
+                        peer_port: pkt.src_port(),
+                    },
+                    MuxerConnection::new_peer_init(
+                        stream,
+                        uapi::VSOCK_HOST_CID,
+                        self.cid,
+                        pkt.dst_port(),
+                        pkt.src_port(),
+                        pkt.buf_alloc(),
+                    ),
+                )
+            })
+            .map(|_| METRICS.vsock.clock_requests.inc())
+            .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
+    }
+
     /// Perform an action that might mutate a connection's state.
     ///
     /// This is used as shorthand for repetitive tasks that need to be performed after a
@@ -657,6 +953,7 @@ impl VsockMuxer {
             // If this is a host-initiated connection that has just become established, we'll have
             // to send an ack message to the host end.
             if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
+                self.listening_ports.insert(key.peer_port);
                 let msg = format!("OK {}\n", key.local_port);
                 match conn.send_bytes_raw(msg.as_bytes()) {
                     Ok(written) if written == msg.len() => (),
@@ -768,6 +1065,35 @@ impl VsockMuxer {
         }
     }
 
+    /// Kill the connections through which no data went for longer than the idle timeout.
+    fn kill_idle_connections(&mut self) {
+        self.idle_timer.read();
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        let now = Instant::now();
+        let idle_keys: Vec<ConnMapKey> = self
+            .conn_map
+            .iter()
+            .filter(|(_, conn)| {
+                // The connections being set up or shut down are killed by the kill queue when
+                // they take too long.
+                conn.state() != ConnState::Killed
+                    && conn.expiry().is_none()
+                    && now.duration_since(conn.last_activity()) >= idle_timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in idle_keys {
+            debug!(
+                "vsock: killing idle connection (lp={}, pp={})",
+                key.local_port, key.peer_port
+            );
+            METRICS.vsock.conns_idle_killed.inc();
+            self.kill_connection(key);
+        }
+    }
+
     /// Enqueue an RST packet into `self.rxq`.
     ///
     /// Enqueue errors aren't propagated up the call chain, since there is nothing we can do to
@@ -1238,6 +1564,215 @@ mod tests {
         assert_eq!(stream.read(buf.as_mut_slice()).unwrap(), 0);
     }
 
+    #[test]
+    fn test_clock_port() {
+        const CLOCK_PORT: u32 = 123;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("clock_port");
+        ctx.muxer.set_clock_port(Some(CLOCK_PORT));
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        // The connection is accepted without a host-side listener.
+        ctx.init_pkt(CLOCK_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), CLOCK_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+
+        // The guest reads the host time.
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        let len = ctx.pkt.len() as usize;
+        let buf = test_utils::read_packet_data(&ctx.pkt, &ctx._vsock_test_ctx.mem, len);
+        let reply = String::from_utf8(buf).unwrap();
+        let (secs, nanos) = reply.strip_suffix('\n').unwrap().split_once('.').unwrap();
+        assert_eq!(nanos.len(), 9);
+        let secs: u64 = secs.parse().unwrap();
+        assert!(secs >= before.as_secs() && secs <= before.as_secs() + 1);
+
+        // Then the stream ends.
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_SHUTDOWN);
+
+        // The other ports are still relayed to the host-side Unix sockets.
+        ctx.init_pkt(CLOCK_PORT + 1, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+    }
+
+    #[test]
+    fn test_resync() {
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("resync");
+        let (_stream, local_port) = ctx.local_connect(PEER_PORT);
+        assert_eq!(ctx.muxer.listening_ports(), vec![PEER_PORT]);
+        assert_eq!(
+            ctx.muxer.connections(),
+            vec![VsockConnectionState {
+                host_port: local_port,
+                guest_port: PEER_PORT,
+                host_initiated: true,
+            }]
+        );
+
+        // A guest port refusing a host connection is no longer reported as listening.
+        let mut stream = UnixStream::connect(ctx.muxer.host_sock_path.clone()).unwrap();
+        ctx.notify_muxer();
+        stream
+            .write_all(format!("connect {}\n", PEER_PORT).as_bytes())
+            .unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+        let refused_port = ctx.pkt.src_port();
+        ctx.init_pkt(refused_port, PEER_PORT, uapi::VSOCK_OP_RST);
+        ctx.send();
+        assert!(ctx.muxer.listening_ports().is_empty());
+
+        // The state of a restored muxer is reported to the host, which is then disconnected.
+        ctx.muxer.set_resync_state(
+            vec![PEER_PORT],
+            vec![
+                VsockConnectionState {
+                    host_port: local_port + 10,
+                    guest_port: PEER_PORT,
+                    host_initiated: true,
+                },
+                VsockConnectionState {
+                    host_port: 52,
+                    guest_port: 2048,
+                    host_initiated: false,
+                },
+            ],
+        );
+        let mut stream = UnixStream::connect(ctx.muxer.host_sock_path.clone()).unwrap();
+        ctx.notify_muxer();
+        stream.write_all(b"RESYNC\n").unwrap();
+        ctx.notify_muxer();
+        let mut report = String::new();
+        stream.read_to_string(&mut report).unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "RESYNC 1\nLISTENING {}\nDROPPED HOST {} {}\nDROPPED GUEST 52 2048\nEND\n",
+                PEER_PORT,
+                local_port + 10,
+                PEER_PORT
+            )
+        );
+        assert!(!ctx.muxer.has_pending_rx());
+
+        // The host ports of the dropped connections are not handed out again right away.
+        ctx.muxer.allocate_local_port();
+        assert_eq!(ctx.muxer.local_port_last, local_port + 11);
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("connection_limit");
+        let mut listener = ctx.create_local_listener(LOCAL_PORT);
+        ctx.muxer
+            .set_max_connections(VsockMuxer::MAX_CONNECTIONS + 1);
+        assert_eq!(ctx.muxer.max_connections(), VsockMuxer::MAX_CONNECTIONS);
+        ctx.muxer.set_max_connections(1);
+        let conns_rejected = METRICS.vsock.conns_rejected.count();
+
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        let _stream = listener.accept();
+
+        // A guest connection beyond the limit is reset, without connecting to the host socket.
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT + 1, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT + 1);
+        assert!(listener.sock.accept().is_err());
+        assert_eq!(METRICS.vsock.conns_rejected.count(), conns_rejected + 1);
+
+        // A host connection beyond the limit is closed right away.
+        let mut stream = UnixStream::connect(ctx.muxer.host_sock_path.clone()).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.count_epoll_listeners(), (0, 1));
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(METRICS.vsock.conns_rejected.count(), conns_rejected + 2);
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+    }
+
+    #[test]
+    fn test_listen_backlog() {
+        let mut ctx = MuxerTestContext::new("listen_backlog");
+        assert_eq!(ctx.muxer.listen_backlog(), None);
+        ctx.muxer.set_listen_backlog(Some(1)).unwrap();
+        assert_eq!(ctx.muxer.listen_backlog(), Some(1));
+
+        // The backlog is applied to the socket bound later on.
+        let mut muxer = VsockMuxer::new_unbound(PEER_CID).unwrap();
+        muxer.set_listen_backlog(Some(u32::MAX)).unwrap();
+        let path = get_file("listen_backlog_unbound");
+        muxer.bind(path.clone()).unwrap();
+        assert_eq!(muxer.listen_backlog(), Some(u32::MAX));
+        UnixStream::connect(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+        const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(50);
+
+        let mut ctx = MuxerTestContext::new("idle_timeout");
+        let mut listener = ctx.create_local_listener(LOCAL_PORT);
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        let mut stream = listener.accept();
+        let conns_idle_killed = METRICS.vsock.conns_idle_killed.count();
+
+        // Without an idle timeout, idle connections are kept.
+        assert_eq!(ctx.muxer.idle_timeout(), None);
+        std::thread::sleep(IDLE_TIMEOUT * 2);
+        ctx.notify_muxer();
+        assert!(!ctx.muxer.has_pending_rx());
+
+        ctx.muxer.set_idle_timeout(Some(IDLE_TIMEOUT));
+        assert_eq!(ctx.muxer.idle_timeout(), Some(IDLE_TIMEOUT));
+        std::thread::sleep(IDLE_TIMEOUT * 2);
+        ctx.notify_muxer();
+
+        // The idle connection is reset, and its host-side stream closed.
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert_eq!(
+            METRICS.vsock.conns_idle_killed.count(),
+            conns_idle_killed + 1
+        );
+        let mut buf = [0u8; 1];
+        stream.set_nonblocking(false).unwrap();
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+        ctx.muxer.set_idle_timeout(None);
+        assert_eq!(ctx.muxer.idle_timeout(), None);
+    }
+
     #[test]
     fn test_muxer_rxq() {
         let mut ctx = MuxerTestContext::new("muxer_rxq");
@@ -0,0 +1,255 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+mod packet;
+mod session;
+mod target;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use kvm_bindings::kvm_debug_exit_arch;
+use log::{error, info, warn};
+use seccompiler::BpfProgram;
+use utils::eventfd::EventFd;
+
+use self::session::Session;
+use self::target::VmmTarget;
+use crate::vmm_config::gdb::GdbConfig;
+use crate::Vmm;
+
+// Exceptions reported by the debug exits.
+const DB_VECTOR: u32 = 1;
+const BP_VECTOR: u32 = 3;
+// Bits of DR6 telling which of the hardware breakpoints was hit.
+const DR6_BREAKPOINTS: u64 = 0xf;
+
+/// Errors associated with the GDB stub.
+#[derive(Debug, thiserror::Error)]
+pub enum GdbError {
+    /// Cannot bind the socket the debugger connects to.
+    #[error("Cannot bind the GDB stub socket: {0}")]
+    Bind(io::Error),
+    /// Cannot create the event signaling the debug exits.
+    #[error("Cannot create the GDB stub event: {0}")]
+    EventFd(io::Error),
+    /// Cannot spawn the thread of the stub.
+    #[error("Cannot spawn the GDB stub thread: {0}")]
+    Spawn(io::Error),
+}
+
+/// Why a vCPU stopped and handed control over to the debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The vCPU executed an `int3` instruction.
+    SwBreak,
+    /// The vCPU hit a hardware breakpoint.
+    HwBreak,
+    /// The vCPU executed a single instruction.
+    Step,
+}
+
+impl From<&kvm_debug_exit_arch> for StopReason {
+    fn from(debug: &kvm_debug_exit_arch) -> Self {
+        match debug.exception {
+            BP_VECTOR => StopReason::SwBreak,
+            DB_VECTOR if debug.dr6 & DR6_BREAKPOINTS != 0 => StopReason::HwBreak,
+            _ => StopReason::Step,
+        }
+    }
+}
+
+/// Hands the debug exits of the vCPUs over to the GDB stub.
+#[derive(Debug, Clone)]
+pub struct StopNotifier {
+    sender: Sender<(u16, StopReason)>,
+    event: Arc<EventFd>,
+}
+
+impl StopNotifier {
+    /// Tells the stub that a vCPU stopped, the vCPU then waits for the stub in the paused state.
+    pub fn notify(&self, vcpu: u16, reason: StopReason) {
+        // The stub thread lives as long as the process.
+        let _ = self.sender.send((vcpu, reason));
+        if let Err(err) = self.event.write(1) {
+            error!("Failed to signal the GDB stub: {}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// A connection with the debugger.
+#[derive(Debug)]
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Unix(stream) => stream.as_raw_fd(),
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+/// GDB remote serial protocol stub, serving one debugger at a time.
+///
+/// The stub pauses the microVM when a debugger connects and every time the guest stops, and
+/// resumes it when the debugger continues or detaches.
+#[derive(Debug)]
+pub struct GdbServer {
+    listener: Listener,
+    stop_sender: Sender<(u16, StopReason)>,
+    stop_receiver: Receiver<(u16, StopReason)>,
+    stop_event: Arc<EventFd>,
+}
+
+impl GdbServer {
+    /// Binds the socket the debugger connects to.
+    pub fn bind(config: &GdbConfig) -> Result<Self, GdbError> {
+        let listener = match (&config.socket_path, config.tcp_address) {
+            (Some(path), _) => UnixListener::bind(path).map(Listener::Unix),
+            (None, Some(address)) => TcpListener::bind(address).map(Listener::Tcp),
+            (None, None) => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+        .map_err(GdbError::Bind)?;
+        let stop_event = EventFd::new(libc::EFD_NONBLOCK).map_err(GdbError::EventFd)?;
+        let (stop_sender, stop_receiver) = channel();
+        Ok(GdbServer {
+            listener,
+            stop_sender,
+            stop_receiver,
+            stop_event: Arc::new(stop_event),
+        })
+    }
+
+    /// Returns a notifier for a vCPU to hand its debug exits over to the stub.
+    pub fn notifier(&self) -> StopNotifier {
+        StopNotifier {
+            sender: self.stop_sender.clone(),
+            event: self.stop_event.clone(),
+        }
+    }
+
+    /// Serves the debuggers on a thread of its own, which installs the VMM seccomp filter.
+    pub fn start(
+        self,
+        vmm: Arc<Mutex<Vmm>>,
+        seccomp_filter: Arc<BpfProgram>,
+    ) -> Result<(), GdbError> {
+        thread::Builder::new()
+            .name("fc_gdb".to_string())
+            .spawn(move || {
+                if let Err(err) = crate::seccomp_filters::install_filter("vmm", &seccomp_filter) {
+                    panic!("Failed to set the GDB stub seccomp filters: {}", err);
+                }
+                self.run(vmm);
+            })
+            .map(|_| ())
+            .map_err(GdbError::Spawn)
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match &self.listener {
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+        }
+    }
+
+    fn run(self, vmm: Arc<Mutex<Vmm>>) {
+        loop {
+            let stream = match self.accept() {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("Failed to accept a GDB connection: {}", err);
+                    continue;
+                }
+            };
+            info!("GDB connected, pausing the microVM.");
+            let mut session = Session::new(
+                VmmTarget::new(vmm.clone()),
+                stream,
+                &self.stop_receiver,
+                &self.stop_event,
+            );
+            if let Err(err) = session.run() {
+                warn!("GDB connection failed: {}", err);
+            }
+            // Whatever ended the session, leave the guest running without the debugger.
+            if let Err(err) = session.detach() {
+                error!("Failed to detach GDB from the microVM: {}", err);
+            }
+            info!("GDB disconnected, the microVM is resumed.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_reason() {
+        let debug = |exception, dr6| kvm_debug_exit_arch {
+            exception,
+            dr6,
+            ..Default::default()
+        };
+        assert_eq!(StopReason::from(&debug(3, 0)), StopReason::SwBreak);
+        assert_eq!(StopReason::from(&debug(1, 0x4002)), StopReason::HwBreak);
+        assert_eq!(StopReason::from(&debug(1, 0x4000)), StopReason::Step);
+    }
+
+    #[test]
+    fn test_notifier() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let config = GdbConfig {
+            socket_path: Some(tmp_dir.as_path().join("gdb.sock")),
+            tcp_address: None,
+        };
+        let server = GdbServer::bind(&config).unwrap();
+        // The socket is already bound.
+        assert!(matches!(GdbServer::bind(&config), Err(GdbError::Bind(_))));
+
+        server.notifier().notify(1, StopReason::Step);
+        assert_eq!(server.stop_event.read().unwrap(), 1);
+        assert_eq!(server.stop_receiver.try_recv(), Ok((1, StopReason::Step)));
+    }
+}
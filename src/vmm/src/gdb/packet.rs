@@ -0,0 +1,229 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Framing of the GDB remote serial protocol packets.
+//!
+//! A packet is sent as `$<data>#<checksum>`, where the checksum is the modulo 256 sum of the
+//! data bytes written as two hex digits. The receiver acknowledges every packet with `+`, or
+//! asks for a retransmission with `-`. A lone `0x03` byte interrupts the running target.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Byte sent by the debugger to interrupt the guest.
+const INTERRUPT: u8 = 0x03;
+
+/// Largest packet the stub accepts, advertised to the debugger.
+pub(crate) const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// What the debugger sent.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Incoming {
+    /// A packet with a valid checksum, without its framing.
+    Packet(Vec<u8>),
+    /// A request to interrupt the guest.
+    Interrupt,
+}
+
+/// A connection with the debugger.
+#[derive(Debug)]
+pub(crate) struct Connection<S> {
+    stream: S,
+    // Bytes read from the stream but not handled yet.
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Wraps a stream connected to the debugger.
+    pub(crate) fn new(stream: S) -> Self {
+        Connection {
+            stream,
+            buffer: Vec::with_capacity(MAX_PACKET_SIZE),
+            pos: 0,
+        }
+    }
+
+    /// Whether bytes were already read from the stream and wait to be handled.
+    pub(crate) fn has_buffered_data(&self) -> bool {
+        self.pos < self.buffer.len()
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if !self.has_buffered_data() {
+            self.buffer.resize(MAX_PACKET_SIZE, 0);
+            let len = self.stream.read(&mut self.buffer)?;
+            self.buffer.truncate(len);
+            self.pos = 0;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+        }
+        let byte = self.buffer[self.pos];
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Reads the next packet or interrupt request, acknowledging packets as they come.
+    ///
+    /// Stray acknowledgements and packets with a bad checksum are skipped.
+    pub(crate) fn read_incoming(&mut self) -> io::Result<Incoming> {
+        loop {
+            match self.read_byte()? {
+                INTERRUPT => return Ok(Incoming::Interrupt),
+                b'$' => {
+                    let mut data = Vec::new();
+                    let mut sum = 0u8;
+                    loop {
+                        match self.read_byte()? {
+                            b'#' => break,
+                            byte => {
+                                sum = sum.wrapping_add(byte);
+                                data.push(byte);
+                            }
+                        }
+                    }
+                    let checksum = [self.read_byte()?, self.read_byte()?];
+                    if parse_hex(&checksum) == Some(u64::from(sum)) {
+                        self.stream.write_all(b"+")?;
+                        return Ok(Incoming::Packet(data));
+                    }
+                    self.stream.write_all(b"-")?;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Sends a packet and waits for the debugger to acknowledge it.
+    pub(crate) fn write_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        let mut sum = 0u8;
+        for &byte in data {
+            // These bytes delimit packets, so they are escaped in the data.
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+                sum = sum.wrapping_add(b'}').wrapping_add(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+                sum = sum.wrapping_add(byte);
+            }
+        }
+        packet.push(b'#');
+        packet.extend_from_slice(format!("{:02x}", sum).as_bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            self.stream.flush()?;
+            // Anything else than a retransmission request counts as an acknowledgement.
+            if self.read_byte()? != b'-' {
+                if self.buffer[self.pos - 1] != b'+' {
+                    self.pos -= 1;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for Connection<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// Encodes `data` as lowercase hex digits.
+pub(crate) fn to_hex(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|byte| format!("{:02x}", byte).into_bytes())
+        .collect()
+}
+
+/// Decodes pairs of hex digits.
+pub(crate) fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| parse_hex(pair).and_then(|value| u8::try_from(value).ok()))
+        .collect()
+}
+
+/// Parses a big-endian hex number, as used for addresses and lengths.
+pub(crate) fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    std::str::from_utf8(hex)
+        .ok()
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), b"00ab10".to_vec());
+        assert_eq!(from_hex(b"00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex(b"00AB"), Some(vec![0x00, 0xab]));
+        assert_eq!(from_hex(b"0ab"), None);
+        assert_eq!(from_hex(b"zz"), None);
+        assert_eq!(parse_hex(b"ffffffff81000000"), Some(0xffff_ffff_8100_0000));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+        assert_eq!(parse_hex(b"-1"), None);
+    }
+
+    #[test]
+    fn test_read_incoming() {
+        let (mut gdb, stub) = UnixStream::pair().unwrap();
+        let mut conn = Connection::new(stub);
+
+        // Acknowledgements are skipped and packets with a bad checksum are rejected.
+        gdb.write_all(b"+$g#00$g#67\x03").unwrap();
+        assert_eq!(
+            conn.read_incoming().unwrap(),
+            Incoming::Packet(b"g".to_vec())
+        );
+        assert_eq!(conn.read_incoming().unwrap(), Incoming::Interrupt);
+        let mut acks = [0u8; 2];
+        gdb.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+
+        drop(gdb);
+        assert_eq!(
+            conn.read_incoming().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_write_packet() {
+        let (mut gdb, stub) = UnixStream::pair().unwrap();
+        let mut conn = Connection::new(stub);
+
+        // A retransmission request gets the packet sent again.
+        gdb.write_all(b"-+").unwrap();
+        conn.write_packet(b"OK").unwrap();
+        let mut sent = [0u8; 12];
+        gdb.read_exact(&mut sent).unwrap();
+        assert_eq!(&sent, b"$OK#9a$OK#9a");
+
+        // Delimiters are escaped, and a packet sent in place of the acknowledgement is kept.
+        gdb.write_all(b"$?#3f").unwrap();
+        conn.write_packet(b"#").unwrap();
+        let mut sent = [0u8; 6];
+        gdb.read_exact(&mut sent).unwrap();
+        assert_eq!(&sent, b"$}\x03#80");
+        assert_eq!(
+            conn.read_incoming().unwrap(),
+            Incoming::Packet(b"?".to_vec())
+        );
+    }
+}
@@ -0,0 +1,832 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::Receiver;
+
+use kvm_bindings::{
+    kvm_guest_debug, kvm_regs, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_HW_BP, KVM_GUESTDBG_USE_SW_BP,
+};
+use utils::eventfd::EventFd;
+
+use super::packet::{from_hex, parse_hex, to_hex, Connection, Incoming, MAX_PACKET_SIZE};
+use super::target::{for_each_page, translate, Target, TargetError};
+use super::StopReason;
+
+const INT3: u8 = 0xcc;
+// Number of address registers (DR0 to DR3) for the hardware breakpoints.
+const HW_BREAKPOINTS: usize = 4;
+// Signals reported in the stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+// The general purpose registers followed by `rip`, in the order of the `g` packets.
+const GPR_COUNT: usize = 17;
+// Size of the registers written by a `G` packet: the general purpose registers and `eflags`.
+const WRITABLE_REGS_SIZE: usize = GPR_COUNT * 8 + 4;
+
+/// Errors ending a debugging session.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SessionError {
+    /// The connection with the debugger failed.
+    #[error("Connection error: {0}")]
+    Connection(#[from] io::Error),
+    /// The guest could not be stopped or resumed.
+    #[error("Cannot control the guest: {0}")]
+    Target(#[from] TargetError),
+}
+
+// Errors of the individual commands, reported to the debugger.
+#[derive(Debug)]
+enum CommandError {
+    Malformed,
+    NoBreakpointSlot,
+    Target(TargetError),
+}
+
+impl From<TargetError> for CommandError {
+    fn from(err: TargetError) -> Self {
+        CommandError::Target(err)
+    }
+}
+
+impl CommandError {
+    fn reply(&self) -> Vec<u8> {
+        match self {
+            // EINVAL
+            CommandError::Malformed => b"E16".to_vec(),
+            // ENOSPC
+            CommandError::NoBreakpointSlot => b"E1c".to_vec(),
+            // EFAULT
+            CommandError::Target(
+                TargetError::Memory(_) | TargetError::NotMapped(_) | TargetError::PagingMode,
+            ) => b"E0e".to_vec(),
+            CommandError::Target(_) => b"E01".to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+}
+
+#[derive(Debug)]
+enum Action {
+    Reply(Vec<u8>),
+    Resume(Resume),
+    Detach,
+    Kill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    // The debugger just connected.
+    Attached,
+    // The debugger interrupted the guest.
+    Interrupted,
+    // A vCPU stopped on its own.
+    Vcpu(StopReason),
+}
+
+/// A debugging session, from the connection of the debugger to its detaching.
+///
+/// The guest runs in the all-stop mode: every vCPU is paused while the debugger has control.
+/// Threads are the vCPUs, numbered from 1.
+#[derive(Debug)]
+pub(crate) struct Session<'a, T, S> {
+    target: T,
+    conn: Connection<S>,
+    stops: &'a Receiver<(u16, StopReason)>,
+    stop_event: &'a EventFd,
+    // vCPU the register accesses, memory accesses and steps apply to.
+    vcpu: u16,
+    last_stop: Stop,
+    // Software breakpoints by guest virtual address, with their guest physical address and the
+    // byte replaced by `int3`.
+    sw_breakpoints: HashMap<u64, (u64, u8)>,
+    hw_breakpoints: [Option<u64>; HW_BREAKPOINTS],
+}
+
+impl<'a, T: Target, S: Read + Write + AsRawFd> Session<'a, T, S> {
+    pub(crate) fn new(
+        target: T,
+        stream: S,
+        stops: &'a Receiver<(u16, StopReason)>,
+        stop_event: &'a EventFd,
+    ) -> Self {
+        Session {
+            target,
+            conn: Connection::new(stream),
+            stops,
+            stop_event,
+            vcpu: 0,
+            last_stop: Stop::Attached,
+            sw_breakpoints: HashMap::new(),
+            hw_breakpoints: [None; HW_BREAKPOINTS],
+        }
+    }
+
+    /// Serves the debugger until it detaches or disconnects.
+    pub(crate) fn run(&mut self) -> Result<(), SessionError> {
+        self.target.pause()?;
+        self.drain_stops();
+
+        loop {
+            let packet = match self.conn.read_incoming()? {
+                Incoming::Packet(packet) => packet,
+                // The guest is already stopped.
+                Incoming::Interrupt => continue,
+            };
+            match self.handle_packet(&packet) {
+                Action::Reply(reply) => self.conn.write_packet(&reply)?,
+                Action::Resume(resume) => {
+                    self.resume(resume)?;
+                    self.wait_for_stop(resume)?;
+                    let reply = self.stop_reply();
+                    self.conn.write_packet(&reply)?;
+                }
+                Action::Detach => {
+                    self.conn.write_packet(b"OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            }
+        }
+    }
+
+    /// Removes the breakpoints and lets the guest run freely.
+    pub(crate) fn detach(&mut self) -> Result<(), TargetError> {
+        // The connection may have failed while the guest was running.
+        self.target.pause()?;
+        self.drain_stops();
+        let sw_breakpoints: Vec<_> = self.sw_breakpoints.drain().collect();
+        for (_, (phys, byte)) in sw_breakpoints {
+            self.target.write_memory(phys, &[byte])?;
+        }
+        self.hw_breakpoints = [None; HW_BREAKPOINTS];
+        for vcpu in 0..self.target.vcpu_count() {
+            self.target
+                .set_guest_debug(vcpu, kvm_guest_debug::default())?;
+        }
+        self.target.resume(None)
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) -> Action {
+        let (command, args) = match packet.split_first() {
+            Some((&command, args)) => (command, args),
+            None => return Action::Reply(Vec::new()),
+        };
+        let result = match command {
+            b'?' => Ok(self.stop_reply()),
+            b'g' => self.read_registers(),
+            b'G' => self.write_registers(args),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'c' | b's' => {
+                let resume = if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+                match self.set_resume_address(args) {
+                    Ok(()) => return Action::Resume(resume),
+                    Err(err) => Err(err),
+                }
+            }
+            b'H' => self.set_thread(args),
+            b'T' => self.parse_thread(args).map(|_| b"OK".to_vec()),
+            b'Z' => self.insert_breakpoint(args),
+            b'z' => self.remove_breakpoint(args),
+            b'q' => Ok(self.query(args)),
+            b'D' => return Action::Detach,
+            b'k' => return Action::Kill,
+            // An empty reply tells the debugger the command is not supported.
+            _ => Ok(Vec::new()),
+        };
+        Action::Reply(result.unwrap_or_else(|err| err.reply()))
+    }
+
+    fn stop_reply(&self) -> Vec<u8> {
+        let (signal, kind) = match self.last_stop {
+            Stop::Attached | Stop::Vcpu(StopReason::Step) => (SIGTRAP, ""),
+            Stop::Interrupted => (SIGINT, ""),
+            Stop::Vcpu(StopReason::SwBreak) => (SIGTRAP, "swbreak:;"),
+            Stop::Vcpu(StopReason::HwBreak) => (SIGTRAP, "hwbreak:;"),
+        };
+        format!("T{:02x}thread:{:x};{}", signal, self.vcpu + 1, kind).into_bytes()
+    }
+
+    fn query(&self, args: &[u8]) -> Vec<u8> {
+        if args.starts_with(b"Supported") {
+            format!("PacketSize={:x};swbreak+;hwbreak+", MAX_PACKET_SIZE).into_bytes()
+        } else if args == b"Attached" {
+            b"1".to_vec()
+        } else if args == b"C" {
+            format!("QC{:x}", self.vcpu + 1).into_bytes()
+        } else if args == b"fThreadInfo" {
+            let threads: Vec<_> = (1..=self.target.vcpu_count())
+                .map(|thread| format!("{:x}", thread))
+                .collect();
+            format!("m{}", threads.join(",")).into_bytes()
+        } else if args == b"sThreadInfo" {
+            b"l".to_vec()
+        } else if let Some(thread) = args.strip_prefix(b"ThreadExtraInfo,") {
+            match self.parse_thread(thread) {
+                Ok(vcpu) => to_hex(format!("vCPU {}", vcpu).as_bytes()),
+                Err(err) => err.reply(),
+            }
+        } else if args.starts_with(b"Symbol:") {
+            b"OK".to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Parses a thread id into the index of the vCPU.
+    fn parse_thread(&self, thread: &[u8]) -> Result<u16, CommandError> {
+        match parse_hex(thread).and_then(|thread| u16::try_from(thread).ok()) {
+            Some(thread) if (1..=self.target.vcpu_count()).contains(&thread) => Ok(thread - 1),
+            _ => Err(CommandError::Malformed),
+        }
+    }
+
+    fn set_thread(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        match args {
+            [b'g' | b'c', thread @ ..] => {
+                // `-1` and `0` stand for all threads and any thread.
+                if thread != b"-1" && thread != b"0" {
+                    self.vcpu = self.parse_thread(thread)?;
+                }
+                Ok(b"OK".to_vec())
+            }
+            _ => Err(CommandError::Malformed),
+        }
+    }
+
+    fn read_registers(&mut self) -> Result<Vec<u8>, CommandError> {
+        let (mut regs, sregs) = self.target.regs(self.vcpu)?;
+        let mut data = Vec::with_capacity(WRITABLE_REGS_SIZE + 6 * 4);
+        for reg in gprs(&mut regs) {
+            data.extend_from_slice(&reg.to_le_bytes());
+        }
+        let eflags = u32::try_from(regs.rflags & u64::from(u32::MAX)).unwrap();
+        data.extend_from_slice(&eflags.to_le_bytes());
+        for segment in [sregs.cs, sregs.ss, sregs.ds, sregs.es, sregs.fs, sregs.gs] {
+            data.extend_from_slice(&u32::from(segment.selector).to_le_bytes());
+        }
+        // The floating point and vector registers are left out, the debugger then considers
+        // them unavailable.
+        Ok(to_hex(&data))
+    }
+
+    fn write_registers(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        let data = from_hex(args).ok_or(CommandError::Malformed)?;
+        if data.len() < WRITABLE_REGS_SIZE {
+            return Err(CommandError::Malformed);
+        }
+        let (mut regs, _) = self.target.regs(self.vcpu)?;
+        for (reg, value) in gprs(&mut regs).into_iter().zip(data.chunks_exact(8)) {
+            *reg = u64::from_le_bytes(value.try_into().unwrap());
+        }
+        let eflags = &data[GPR_COUNT * 8..WRITABLE_REGS_SIZE];
+        regs.rflags = u64::from(u32::from_le_bytes(eflags.try_into().unwrap()));
+        // The segment selectors are ignored, changing them alone would not reload the segments.
+        self.target.set_regs(self.vcpu, regs)?;
+        Ok(b"OK".to_vec())
+    }
+
+    fn read_memory(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        let (addr, len) = parse_addr_len(args)?;
+        // The reply holds two hex digits per byte.
+        let len = len.min(MAX_PACKET_SIZE / 2 - 4);
+        let (_, sregs) = self.target.regs(self.vcpu)?;
+        let mut buf = vec![0u8; len];
+        for_each_page(
+            &mut self.target,
+            &sregs,
+            addr,
+            len,
+            |target, phys, range| target.read_memory(phys, &mut buf[range]),
+        )?;
+        Ok(to_hex(&buf))
+    }
+
+    fn write_memory(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        let colon = args
+            .iter()
+            .position(|&byte| byte == b':')
+            .ok_or(CommandError::Malformed)?;
+        let (addr, len) = parse_addr_len(&args[..colon])?;
+        let data = from_hex(&args[colon + 1..]).ok_or(CommandError::Malformed)?;
+        if data.len() != len {
+            return Err(CommandError::Malformed);
+        }
+        let (_, sregs) = self.target.regs(self.vcpu)?;
+        for_each_page(
+            &mut self.target,
+            &sregs,
+            addr,
+            len,
+            |target, phys, range| target.write_memory(phys, &data[range]),
+        )?;
+        Ok(b"OK".to_vec())
+    }
+
+    // `c` and `s` optionally take the address to resume at.
+    fn set_resume_address(&mut self, args: &[u8]) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return Ok(());
+        }
+        let addr = parse_hex(args).ok_or(CommandError::Malformed)?;
+        let (mut regs, _) = self.target.regs(self.vcpu)?;
+        regs.rip = addr;
+        self.target.set_regs(self.vcpu, regs)?;
+        Ok(())
+    }
+
+    // Parses the `type,addr,kind` arguments of the breakpoint commands.
+    fn parse_breakpoint(args: &[u8]) -> Result<(u8, u64), CommandError> {
+        let mut fields = args.split(|&byte| byte == b',');
+        let kind = fields.next().and_then(parse_hex);
+        let addr = fields.next().and_then(parse_hex);
+        match (kind, addr, fields.next()) {
+            (Some(kind), Some(addr), Some(_)) => Ok((u8::try_from(kind).unwrap_or(u8::MAX), addr)),
+            _ => Err(CommandError::Malformed),
+        }
+    }
+
+    fn insert_breakpoint(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        match Self::parse_breakpoint(args)? {
+            (0, addr) => {
+                if !self.sw_breakpoints.contains_key(&addr) {
+                    let (_, sregs) = self.target.regs(self.vcpu)?;
+                    let phys = translate(&mut self.target, &sregs, addr)?;
+                    let mut byte = [0u8];
+                    self.target.read_memory(phys, &mut byte)?;
+                    self.target.write_memory(phys, &[INT3])?;
+                    self.sw_breakpoints.insert(addr, (phys, byte[0]));
+                }
+                Ok(b"OK".to_vec())
+            }
+            (1, addr) => {
+                if !self.hw_breakpoints.contains(&Some(addr)) {
+                    let slot = self
+                        .hw_breakpoints
+                        .iter_mut()
+                        .find(|slot| slot.is_none())
+                        .ok_or(CommandError::NoBreakpointSlot)?;
+                    *slot = Some(addr);
+                }
+                Ok(b"OK".to_vec())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn remove_breakpoint(&mut self, args: &[u8]) -> Result<Vec<u8>, CommandError> {
+        match Self::parse_breakpoint(args)? {
+            (0, addr) => {
+                if let Some((phys, byte)) = self.sw_breakpoints.remove(&addr) {
+                    self.target.write_memory(phys, &[byte])?;
+                }
+                Ok(b"OK".to_vec())
+            }
+            (1, addr) => {
+                for slot in self.hw_breakpoints.iter_mut() {
+                    if *slot == Some(addr) {
+                        *slot = None;
+                    }
+                }
+                Ok(b"OK".to_vec())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn guest_debug(&self, step: bool) -> kvm_guest_debug {
+        let mut debug = kvm_guest_debug::default();
+        if !self.sw_breakpoints.is_empty() {
+            debug.control |= KVM_GUESTDBG_USE_SW_BP;
+        }
+        for (idx, addr) in self.hw_breakpoints.iter().enumerate() {
+            if let Some(addr) = addr {
+                debug.control |= KVM_GUESTDBG_USE_HW_BP;
+                debug.arch.debugreg[idx] = *addr;
+                // Globally enable the breakpoint in DR7. The condition and length bits are left
+                // to 0, which breaks on the execution of the instruction.
+                debug.arch.debugreg[7] |= 1 << (idx * 2 + 1);
+            }
+        }
+        if step {
+            debug.control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+        if debug.control != 0 {
+            debug.control |= KVM_GUESTDBG_ENABLE;
+        }
+        debug
+    }
+
+    fn resume(&mut self, resume: Resume) -> Result<(), TargetError> {
+        for vcpu in 0..self.target.vcpu_count() {
+            let debug = self.guest_debug(resume == Resume::Step && vcpu == self.vcpu);
+            self.target.set_guest_debug(vcpu, debug)?;
+        }
+        match resume {
+            Resume::Continue => self.target.resume(None),
+            // The other vCPUs stay paused while one steps.
+            Resume::Step => self.target.resume(Some(self.vcpu)),
+        }
+    }
+
+    // Waits for a vCPU to stop or for the debugger to interrupt the guest, then pauses the
+    // guest.
+    fn wait_for_stop(&mut self, resume: Resume) -> Result<(), SessionError> {
+        loop {
+            let buffered = self.conn.has_buffered_data();
+            // Bytes already read from the connection would not wake the poll up.
+            let (conn_ready, stop_ready) = self.poll(!buffered)?;
+            let conn_ready = conn_ready || buffered;
+
+            if stop_ready {
+                let _ = self.stop_event.read();
+                if let Ok((vcpu, reason)) = self.stops.try_recv() {
+                    self.target.pause()?;
+                    // The other vCPUs which stopped in the meantime hit their breakpoint
+                    // again once resumed.
+                    self.drain_stops();
+                    if reason == StopReason::SwBreak && !self.owns_breakpoint(vcpu)? {
+                        // The guest executed an `int3` of its own, which it has to handle.
+                        self.target.inject_breakpoint(vcpu)?;
+                        self.resume(resume)?;
+                        continue;
+                    }
+                    self.vcpu = vcpu;
+                    self.last_stop = Stop::Vcpu(reason);
+                    return Ok(());
+                }
+            }
+
+            // Packets other than interrupts are not expected while the guest runs.
+            if conn_ready && self.conn.read_incoming()? == Incoming::Interrupt {
+                self.target.pause()?;
+                self.drain_stops();
+                self.last_stop = Stop::Interrupted;
+                return Ok(());
+            }
+        }
+    }
+
+    fn owns_breakpoint(&mut self, vcpu: u16) -> Result<bool, TargetError> {
+        let (regs, _) = self.target.regs(vcpu)?;
+        Ok(self.sw_breakpoints.contains_key(&regs.rip))
+    }
+
+    fn drain_stops(&self) {
+        let _ = self.stop_event.read();
+        while self.stops.try_recv().is_ok() {}
+    }
+
+    // Checks whether the debugger sent data and whether a vCPU stopped, waiting for either if
+    // `block` is set.
+    fn poll(&self, block: bool) -> io::Result<(bool, bool)> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.conn.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.stop_event.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: Safe because the array holds as many valid `pollfd` as the count passed, and
+        // the return value is checked.
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), 2, if block { -1 } else { 0 }) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok((false, false));
+            }
+            return Err(err);
+        }
+        Ok((fds[0].revents != 0, fds[1].revents != 0))
+    }
+}
+
+// The general purpose registers and `rip`, in the order of the `g` packets.
+fn gprs(regs: &mut kvm_regs) -> [&mut u64; GPR_COUNT] {
+    [
+        &mut regs.rax,
+        &mut regs.rbx,
+        &mut regs.rcx,
+        &mut regs.rdx,
+        &mut regs.rsi,
+        &mut regs.rdi,
+        &mut regs.rbp,
+        &mut regs.rsp,
+        &mut regs.r8,
+        &mut regs.r9,
+        &mut regs.r10,
+        &mut regs.r11,
+        &mut regs.r12,
+        &mut regs.r13,
+        &mut regs.r14,
+        &mut regs.r15,
+        &mut regs.rip,
+    ]
+}
+
+// Parses the `addr,len` arguments of the memory commands.
+fn parse_addr_len(args: &[u8]) -> Result<(u64, usize), CommandError> {
+    let mut fields = args.splitn(2, |&byte| byte == b',');
+    let addr = fields.next().and_then(parse_hex);
+    let len = fields
+        .next()
+        .and_then(parse_hex)
+        .and_then(|len| usize::try_from(len).ok());
+    match (addr, len) {
+        (Some(addr), Some(len)) => Ok((addr, len)),
+        _ => Err(CommandError::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::gdb::target::tests::{long_mode_sregs, map_page, MockTarget};
+    use crate::gdb::StopNotifier;
+
+    // Sends a packet the way the debugger does and returns the reply.
+    fn exchange(gdb: &mut UnixStream, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        gdb.write_all(format!("${}#{:02x}", packet, sum).as_bytes())
+            .unwrap();
+        read_reply(gdb)
+    }
+
+    fn read_reply(gdb: &mut UnixStream) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0u8];
+        // Skip the acknowledgement and the start of the packet.
+        while byte[0] != b'$' {
+            gdb.read_exact(&mut byte).unwrap();
+        }
+        loop {
+            gdb.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        gdb.read_exact(&mut checksum).unwrap();
+        gdb.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn session<'a>(
+        target: MockTarget,
+        stops: &'a Receiver<(u16, StopReason)>,
+        stop_event: &'a EventFd,
+    ) -> (Session<'a, MockTarget, UnixStream>, UnixStream) {
+        let (gdb, stub) = UnixStream::pair().unwrap();
+        (Session::new(target, stub, stops, stop_event), gdb)
+    }
+
+    fn reply(session: &mut Session<MockTarget, UnixStream>, packet: &[u8]) -> String {
+        match session.handle_packet(packet) {
+            Action::Reply(reply) => String::from_utf8(reply).unwrap(),
+            action => panic!("Unexpected action {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        let (_, stops) = channel();
+        let stop_event = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (mut session, _gdb) = session(MockTarget::new(2, 0), &stops, &stop_event);
+
+        assert_eq!(
+            reply(&mut session, b"qSupported:multiprocess+;swbreak+"),
+            "PacketSize=1000;swbreak+;hwbreak+"
+        );
+        assert_eq!(reply(&mut session, b"qfThreadInfo"), "m1,2");
+        assert_eq!(reply(&mut session, b"qsThreadInfo"), "l");
+        assert_eq!(reply(&mut session, b"qC"), "QC1");
+        assert_eq!(reply(&mut session, b"qAttached"), "1");
+        assert_eq!(
+            reply(&mut session, b"qThreadExtraInfo,2"),
+            String::from_utf8(to_hex(b"vCPU 1")).unwrap()
+        );
+        assert_eq!(reply(&mut session, b"qOffsets"), "");
+        assert_eq!(reply(&mut session, b"vMustReplyEmpty"), "");
+        assert_eq!(reply(&mut session, b"?"), "T05thread:1;");
+
+        // Thread selection.
+        assert_eq!(reply(&mut session, b"T2"), "OK");
+        assert_eq!(reply(&mut session, b"T3"), "E16");
+        assert_eq!(reply(&mut session, b"Hg2"), "OK");
+        assert_eq!(reply(&mut session, b"Hc-1"), "OK");
+        assert_eq!(reply(&mut session, b"qC"), "QC2");
+        assert_eq!(reply(&mut session, b"Hg0"), "OK");
+        assert_eq!(reply(&mut session, b"qC"), "QC2");
+        assert_eq!(reply(&mut session, b"Hg3"), "E16");
+        assert_eq!(reply(&mut session, b"Hx1"), "E16");
+
+        assert!(matches!(session.handle_packet(b"D"), Action::Detach));
+        assert!(matches!(session.handle_packet(b"k"), Action::Kill));
+    }
+
+    #[test]
+    fn test_registers() {
+        let (_, stops) = channel();
+        let stop_event = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut target = MockTarget::new(2, 0);
+        target.regs[1].0.rax = 0x1122_3344_5566_7788;
+        target.regs[1].0.rip = 0xffff_ffff_8100_0000;
+        target.regs[1].0.rflags = 0x246;
+        target.regs[1].1.cs.selector = 0x10;
+        let (mut session, _gdb) = session(target, &stops, &stop_event);
+
+        assert_eq!(reply(&mut session, b"Hg2"), "OK");
+        let regs = from_hex(reply(&mut session, b"g").as_bytes()).unwrap();
+        assert_eq!(regs.len(), WRITABLE_REGS_SIZE + 6 * 4);
+        assert_eq!(regs[..8], 0x1122_3344_5566_7788u64.to_le_bytes());
+        assert_eq!(regs[16 * 8..17 * 8], 0xffff_ffff_8100_0000u64.to_le_bytes());
+        assert_eq!(regs[17 * 8..17 * 8 + 4], 0x246u32.to_le_bytes());
+        assert_eq!(regs[17 * 8 + 4..17 * 8 + 8], 0x10u32.to_le_bytes());
+
+        let mut new_regs = regs.clone();
+        new_regs[8..16].copy_from_slice(&42u64.to_le_bytes());
+        new_regs[17 * 8..17 * 8 + 4].copy_from_slice(&0x202u32.to_le_bytes());
+        let mut packet = b"G".to_vec();
+        packet.extend(to_hex(&new_regs));
+        assert_eq!(reply(&mut session, &packet), "OK");
+        let (regs, _) = session.target.regs(1).unwrap();
+        assert_eq!(regs.rax, 0x1122_3344_5566_7788);
+        assert_eq!(regs.rbx, 42);
+        assert_eq!(regs.rflags, 0x202);
+
+        assert_eq!(reply(&mut session, b"G0011"), "E16");
+        assert_eq!(reply(&mut session, b"Gzz"), "E16");
+
+        // Resuming at a given address.
+        assert!(matches!(
+            session.handle_packet(b"cffffffff81000010"),
+            Action::Resume(Resume::Continue)
+        ));
+        assert_eq!(session.target.regs(1).unwrap().0.rip, 0xffff_ffff_8100_0010);
+        assert!(matches!(
+            session.handle_packet(b"s"),
+            Action::Resume(Resume::Step)
+        ));
+    }
+
+    #[test]
+    fn test_memory() {
+        let (_, stops) = channel();
+        let stop_event = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut target = MockTarget::new(1, 0x10_0000);
+        target.regs[0].1 = long_mode_sregs(0x1000);
+        map_page(&mut target, 0x40_0000, 0x9000);
+        map_page(&mut target, 0x40_1000, 0x8000);
+        target.memory[0x9ffe..0xa000].copy_from_slice(&[0x11, 0x22]);
+        target.memory[0x8000..0x8002].copy_from_slice(&[0x33, 0x44]);
+        let (mut session, _gdb) = session(target, &stops, &stop_event);
+
+        assert_eq!(reply(&mut session, b"m400ffe,4"), "11223344");
+        assert_eq!(reply(&mut session, b"M400fff,2:aabb"), "OK");
+        assert_eq!(session.target.memory[0x9fff], 0xaa);
+        assert_eq!(session.target.memory[0x8000], 0xbb);
+
+        // Unmapped memory.
+        assert_eq!(reply(&mut session, b"m402000,1"), "E0e");
+        assert_eq!(reply(&mut session, b"M402000,1:00"), "E0e");
+        // Malformed commands.
+        assert_eq!(reply(&mut session, b"m400000"), "E16");
+        assert_eq!(reply(&mut session, b"M400000,2:aa"), "E16");
+        assert_eq!(reply(&mut session, b"M400000,1"), "E16");
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (_, stops) = channel();
+        let stop_event = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut target = MockTarget::new(2, 0x10_0000);
+        target.regs[0].1 = long_mode_sregs(0x1000);
+        map_page(&mut target, 0x40_0000, 0x9000);
+        target.memory[0x9010] = 0x90;
+        let (mut session, _gdb) = session(target, &stops, &stop_event);
+
+        assert_eq!(session.guest_debug(false).control, 0);
+
+        // Software breakpoints patch the guest memory.
+        assert_eq!(reply(&mut session, b"Z0,400010,1"), "OK");
+        assert_eq!(session.target.memory[0x9010], INT3);
+        assert_eq!(reply(&mut session, b"Z0,400010,1"), "OK");
+        assert_eq!(reply(&mut session, b"Z0,402000,1"), "E0e");
+        let debug = session.guest_debug(false);
+        assert_eq!(debug.control, KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP);
+
+        // Hardware breakpoints use the debug registers.
+        for addr in 0..4 {
+            assert_eq!(
+                reply(&mut session, format!("Z1,{:x},1", 0x1000 + addr).as_bytes()),
+                "OK"
+            );
+        }
+        assert_eq!(reply(&mut session, b"Z1,1002,1"), "OK");
+        assert_eq!(reply(&mut session, b"Z1,2000,1"), "E1c");
+        let debug = session.guest_debug(true);
+        assert_eq!(
+            debug.control,
+            KVM_GUESTDBG_ENABLE
+                | KVM_GUESTDBG_USE_SW_BP
+                | KVM_GUESTDBG_USE_HW_BP
+                | KVM_GUESTDBG_SINGLESTEP
+        );
+        assert_eq!(debug.arch.debugreg[..4], [0x1000, 0x1001, 0x1002, 0x1003]);
+        assert_eq!(debug.arch.debugreg[7], 0xaa);
+        assert_eq!(reply(&mut session, b"z1,1001,1"), "OK");
+        assert_eq!(session.guest_debug(false).arch.debugreg[7], 0xa2);
+
+        // Watchpoints are not supported.
+        assert_eq!(reply(&mut session, b"Z2,1000,4"), "");
+        assert_eq!(reply(&mut session, b"Z0,400010"), "E16");
+
+        // Stepping only applies to the current vCPU.
+        session.resume(Resume::Step).unwrap();
+        assert_eq!(session.target.resumed, vec![Some(0)]);
+        assert_ne!(
+            session.target.guest_debug[0].control & KVM_GUESTDBG_SINGLESTEP,
+            0
+        );
+        assert_eq!(
+            session.target.guest_debug[1].control & KVM_GUESTDBG_SINGLESTEP,
+            0
+        );
+
+        assert_eq!(reply(&mut session, b"z0,400010,1"), "OK");
+        assert_eq!(session.target.memory[0x9010], 0x90);
+
+        // Detaching removes all the breakpoints and resumes the guest.
+        assert_eq!(reply(&mut session, b"Z0,400010,1"), "OK");
+        session.detach().unwrap();
+        assert_eq!(session.target.memory[0x9010], 0x90);
+        assert_eq!(session.target.guest_debug[0].control, 0);
+        assert_eq!(session.target.resumed, vec![Some(0), None]);
+        assert!(!session.target.paused);
+    }
+
+    #[test]
+    fn test_run() {
+        let (sender, stops) = channel();
+        let stop_event = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let notifier = StopNotifier {
+            sender,
+            event: stop_event.clone(),
+        };
+        let mut target = MockTarget::new(2, 0x10_0000);
+        target.regs[0].1 = long_mode_sregs(0x1000);
+        map_page(&mut target, 0x40_0000, 0x9000);
+        // vCPU 0 is on an `int3` of the guest, vCPU 1 on the breakpoint of the debugger.
+        target.regs[0].0.rip = 0x40_0020;
+        target.regs[1].0.rip = 0x40_0010;
+        let (mut session, mut gdb) = session(target, &stops, &stop_event);
+
+        let debugger = thread::spawn(move || {
+            assert_eq!(exchange(&mut gdb, "?"), "T05thread:1;");
+            assert_eq!(exchange(&mut gdb, "Z0,400010,1"), "OK");
+
+            gdb.write_all(b"$c#63").unwrap();
+            notifier.notify(1, StopReason::SwBreak);
+            assert_eq!(read_reply(&mut gdb), "T05thread:2;swbreak:;");
+            assert_eq!(exchange(&mut gdb, "qC"), "QC2");
+
+            // A breakpoint of the guest itself is injected back instead of being reported.
+            gdb.write_all(b"$c#63").unwrap();
+            notifier.notify(0, StopReason::SwBreak);
+            gdb.write_all(&[0x03]).unwrap();
+            assert_eq!(read_reply(&mut gdb), "T02thread:2;");
+
+            assert_eq!(exchange(&mut gdb, "D"), "OK");
+        });
+
+        session.run().unwrap();
+        debugger.join().unwrap();
+        assert_eq!(session.target.injected, vec![0]);
+        assert!(session.target.paused);
+        assert_eq!(session.target.resumed, vec![None, None, None]);
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use kvm_bindings::{kvm_guest_debug, kvm_regs, kvm_sregs};
+use utils::vm_memory::{Bytes, GuestAddress};
+
+use crate::vstate::vcpu::{VcpuEvent, VcpuResponse};
+use crate::{Vmm, RECV_TIMEOUT_SEC};
+
+const PAGE_SIZE: u64 = 0x1000;
+// Bits of the page table entries.
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+// Bits of the control registers.
+const CR0_PG: u64 = 1 << 31;
+const CR4_LA57: u64 = 1 << 12;
+const EFER_LMA: u64 = 1 << 10;
+
+/// Errors of the operations the debugger performs on the guest.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub(crate) enum TargetError {
+    /// The vCPU does not exist.
+    #[error("No vCPU with index {0}")]
+    NoVcpu(u16),
+    /// The vCPU did not carry the operation out.
+    #[error("vCPU {0} failed the operation: {1}")]
+    Vcpu(u16, String),
+    /// The guest physical address is not backed by the guest memory.
+    #[error("Guest physical address {0:#x} is not backed by memory")]
+    Memory(u64),
+    /// The guest virtual address is not mapped.
+    #[error("Guest virtual address {0:#x} is not mapped")]
+    NotMapped(u64),
+    /// The guest translates addresses in a paging mode other than long mode.
+    #[error("Only the long mode paging is supported")]
+    PagingMode,
+}
+
+/// The operations the debugger performs on the guest.
+pub(crate) trait Target {
+    /// Number of vCPUs.
+    fn vcpu_count(&self) -> u16;
+    /// Pauses all the vCPUs.
+    fn pause(&mut self) -> Result<(), TargetError>;
+    /// Resumes either all the vCPUs or only one of them.
+    fn resume(&mut self, vcpu: Option<u16>) -> Result<(), TargetError>;
+    /// Reads the registers of a paused vCPU.
+    fn regs(&mut self, vcpu: u16) -> Result<(kvm_regs, kvm_sregs), TargetError>;
+    /// Writes the general purpose registers of a paused vCPU.
+    fn set_regs(&mut self, vcpu: u16, regs: kvm_regs) -> Result<(), TargetError>;
+    /// Sets the guest debug state of a paused vCPU.
+    fn set_guest_debug(&mut self, vcpu: u16, debug: kvm_guest_debug) -> Result<(), TargetError>;
+    /// Injects back the breakpoint exception a paused vCPU stopped on.
+    fn inject_breakpoint(&mut self, vcpu: u16) -> Result<(), TargetError>;
+    /// Reads guest physical memory.
+    fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), TargetError>;
+    /// Writes guest physical memory.
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), TargetError>;
+}
+
+/// Translates a guest virtual address with the page tables the vCPU currently uses.
+pub(crate) fn translate<T: Target>(
+    target: &mut T,
+    sregs: &kvm_sregs,
+    addr: u64,
+) -> Result<u64, TargetError> {
+    if sregs.cr0 & CR0_PG == 0 {
+        return Ok(addr);
+    }
+    if sregs.efer & EFER_LMA == 0 {
+        return Err(TargetError::PagingMode);
+    }
+
+    let levels = if sregs.cr4 & CR4_LA57 != 0 { 5 } else { 4 };
+    let mut table = sregs.cr3 & PTE_ADDR_MASK;
+    for level in (1..=levels).rev() {
+        let shift = 12 + 9 * (level - 1);
+        let mut entry = [0u8; 8];
+        target.read_memory(table + ((addr >> shift) & 0x1ff) * 8, &mut entry)?;
+        let entry = u64::from_le_bytes(entry);
+        if entry & PTE_PRESENT == 0 {
+            return Err(TargetError::NotMapped(addr));
+        }
+        // 1 GiB and 2 MiB pages end the walk early.
+        if (level == 2 || level == 3) && entry & PTE_PAGE_SIZE != 0 {
+            let offset_mask = (1u64 << shift) - 1;
+            return Ok((entry & PTE_ADDR_MASK & !offset_mask) | (addr & offset_mask));
+        }
+        table = entry & PTE_ADDR_MASK;
+    }
+    Ok(table | (addr & (PAGE_SIZE - 1)))
+}
+
+/// Splits an access to guest virtual memory along the page boundaries, as consecutive virtual
+/// pages need not be backed by consecutive physical pages.
+///
+/// Calls `access` with the physical address and the range of the buffer of each chunk.
+pub(crate) fn for_each_page<T, F>(
+    target: &mut T,
+    sregs: &kvm_sregs,
+    addr: u64,
+    len: usize,
+    mut access: F,
+) -> Result<(), TargetError>
+where
+    T: Target,
+    F: FnMut(&mut T, u64, std::ops::Range<usize>) -> Result<(), TargetError>,
+{
+    let mut done = 0;
+    while done < len {
+        let virt = addr.wrapping_add(done as u64);
+        let in_page = usize::try_from(PAGE_SIZE - (virt & (PAGE_SIZE - 1))).unwrap();
+        let chunk = in_page.min(len - done);
+        let phys = translate(target, sregs, virt)?;
+        access(target, phys, done..done + chunk)?;
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// Debugs the guest of a [`Vmm`].
+#[derive(Debug)]
+pub(crate) struct VmmTarget {
+    vmm: Arc<Mutex<Vmm>>,
+    vcpu_count: u16,
+}
+
+impl VmmTarget {
+    pub(crate) fn new(vmm: Arc<Mutex<Vmm>>) -> Self {
+        let vcpu_count = u16::try_from(vmm.lock().expect("Poisoned lock").vcpus_handles.len())
+            .expect("Too many vCPUs");
+        VmmTarget { vmm, vcpu_count }
+    }
+
+    fn vmm(&self) -> MutexGuard<Vmm> {
+        self.vmm.lock().expect("Poisoned lock")
+    }
+
+    // Sends an event to a vCPU and waits for its response.
+    fn vcpu_request(&self, vcpu: u16, event: VcpuEvent) -> Result<VcpuResponse, TargetError> {
+        let vmm = self.vmm();
+        let handle = vmm
+            .vcpus_handles
+            .get(usize::from(vcpu))
+            .ok_or(TargetError::NoVcpu(vcpu))?;
+        handle
+            .send_event(event)
+            .map_err(|err| TargetError::Vcpu(vcpu, err.to_string()))?;
+        match handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC) {
+            Ok(VcpuResponse::Error(err)) => Err(TargetError::Vcpu(vcpu, err.to_string())),
+            Ok(VcpuResponse::NotAllowed(reason)) => Err(TargetError::Vcpu(vcpu, reason)),
+            Ok(response) => Ok(response),
+            Err(err) => Err(TargetError::Vcpu(vcpu, err.to_string())),
+        }
+    }
+
+    fn vcpu_update(&self, vcpu: u16, event: VcpuEvent) -> Result<(), TargetError> {
+        match self.vcpu_request(vcpu, event)? {
+            VcpuResponse::Updated => Ok(()),
+            response => Err(TargetError::Vcpu(vcpu, format!("{:?}", response))),
+        }
+    }
+}
+
+impl Target for VmmTarget {
+    fn vcpu_count(&self) -> u16 {
+        self.vcpu_count
+    }
+
+    fn pause(&mut self) -> Result<(), TargetError> {
+        self.vmm()
+            .pause_vm()
+            .map_err(|err| TargetError::Vcpu(0, err.to_string()))
+    }
+
+    fn resume(&mut self, vcpu: Option<u16>) -> Result<(), TargetError> {
+        match vcpu {
+            None => self
+                .vmm()
+                .resume_vm()
+                .map_err(|err| TargetError::Vcpu(0, err.to_string())),
+            Some(vcpu) => match self.vcpu_request(vcpu, VcpuEvent::Resume)? {
+                VcpuResponse::Resumed => Ok(()),
+                response => Err(TargetError::Vcpu(vcpu, format!("{:?}", response))),
+            },
+        }
+    }
+
+    fn regs(&mut self, vcpu: u16) -> Result<(kvm_regs, kvm_sregs), TargetError> {
+        match self.vcpu_request(vcpu, VcpuEvent::GetRegs)? {
+            VcpuResponse::Regs(regs, sregs) => Ok((*regs, *sregs)),
+            response => Err(TargetError::Vcpu(vcpu, format!("{:?}", response))),
+        }
+    }
+
+    fn set_regs(&mut self, vcpu: u16, regs: kvm_regs) -> Result<(), TargetError> {
+        self.vcpu_update(vcpu, VcpuEvent::SetRegs(Box::new(regs)))
+    }
+
+    fn set_guest_debug(&mut self, vcpu: u16, debug: kvm_guest_debug) -> Result<(), TargetError> {
+        self.vcpu_update(vcpu, VcpuEvent::SetGuestDebug(Box::new(debug)))
+    }
+
+    fn inject_breakpoint(&mut self, vcpu: u16) -> Result<(), TargetError> {
+        self.vcpu_update(vcpu, VcpuEvent::InjectBreakpoint)
+    }
+
+    fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), TargetError> {
+        self.vmm()
+            .guest_memory()
+            .read_slice(buf, GuestAddress(addr))
+            .map_err(|_| TargetError::Memory(addr))
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), TargetError> {
+        self.vmm()
+            .guest_memory()
+            .write_slice(data, GuestAddress(addr))
+            .map_err(|_| TargetError::Memory(addr))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A guest with flat memory and vCPUs which stop as soon as they are resumed.
+    #[derive(Debug)]
+    pub(crate) struct MockTarget {
+        pub memory: Vec<u8>,
+        pub regs: Vec<(kvm_regs, kvm_sregs)>,
+        pub guest_debug: Vec<kvm_guest_debug>,
+        pub paused: bool,
+        pub resumed: Vec<Option<u16>>,
+        pub injected: Vec<u16>,
+    }
+
+    impl MockTarget {
+        pub(crate) fn new(vcpu_count: u16, mem_size: usize) -> Self {
+            MockTarget {
+                memory: vec![0; mem_size],
+                regs: vec![Default::default(); usize::from(vcpu_count)],
+                guest_debug: vec![Default::default(); usize::from(vcpu_count)],
+                paused: false,
+                resumed: Vec::new(),
+                injected: Vec::new(),
+            }
+        }
+
+        fn check_vcpu(&self, vcpu: u16) -> Result<usize, TargetError> {
+            if usize::from(vcpu) >= self.regs.len() {
+                return Err(TargetError::NoVcpu(vcpu));
+            }
+            Ok(usize::from(vcpu))
+        }
+
+        fn range(&self, addr: u64, len: usize) -> Result<std::ops::Range<usize>, TargetError> {
+            let start = usize::try_from(addr).map_err(|_| TargetError::Memory(addr))?;
+            match start.checked_add(len) {
+                Some(end) if end <= self.memory.len() => Ok(start..end),
+                _ => Err(TargetError::Memory(addr)),
+            }
+        }
+    }
+
+    impl Target for MockTarget {
+        fn vcpu_count(&self) -> u16 {
+            u16::try_from(self.regs.len()).unwrap()
+        }
+
+        fn pause(&mut self) -> Result<(), TargetError> {
+            self.paused = true;
+            Ok(())
+        }
+
+        fn resume(&mut self, vcpu: Option<u16>) -> Result<(), TargetError> {
+            self.paused = false;
+            self.resumed.push(vcpu);
+            Ok(())
+        }
+
+        fn regs(&mut self, vcpu: u16) -> Result<(kvm_regs, kvm_sregs), TargetError> {
+            let vcpu = self.check_vcpu(vcpu)?;
+            Ok(self.regs[vcpu])
+        }
+
+        fn set_regs(&mut self, vcpu: u16, regs: kvm_regs) -> Result<(), TargetError> {
+            let vcpu = self.check_vcpu(vcpu)?;
+            self.regs[vcpu].0 = regs;
+            Ok(())
+        }
+
+        fn set_guest_debug(
+            &mut self,
+            vcpu: u16,
+            debug: kvm_guest_debug,
+        ) -> Result<(), TargetError> {
+            let vcpu = self.check_vcpu(vcpu)?;
+            self.guest_debug[vcpu] = debug;
+            Ok(())
+        }
+
+        fn inject_breakpoint(&mut self, vcpu: u16) -> Result<(), TargetError> {
+            self.check_vcpu(vcpu)?;
+            self.injected.push(vcpu);
+            Ok(())
+        }
+
+        fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), TargetError> {
+            let range = self.range(addr, buf.len())?;
+            buf.copy_from_slice(&self.memory[range]);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), TargetError> {
+            let range = self.range(addr, data.len())?;
+            self.memory[range].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    /// Long mode registers using 4-level page tables rooted at `cr3`.
+    pub(crate) fn long_mode_sregs(cr3: u64) -> kvm_sregs {
+        kvm_sregs {
+            cr0: CR0_PG | 1,
+            cr3,
+            cr4: 1 << 5,
+            efer: EFER_LMA | (1 << 8),
+            ..Default::default()
+        }
+    }
+
+    /// Maps a virtual page in the 4-level page tables rooted at 0x1000, which use the pages
+    /// starting at 0x2000 as tables.
+    pub(crate) fn map_page(target: &mut MockTarget, virt: u64, phys: u64) {
+        let mut table = 0x1000;
+        let mut next_table = 0x2000;
+        for level in (1..=4).rev() {
+            let shift = 12 + 9 * (level - 1);
+            let entry_addr = table + ((virt >> shift) & 0x1ff) * 8;
+            let mut entry = [0u8; 8];
+            target.read_memory(entry_addr, &mut entry).unwrap();
+            let mut entry = u64::from_le_bytes(entry);
+            if level == 1 {
+                entry = phys | PTE_PRESENT;
+            } else if entry & PTE_PRESENT == 0 {
+                // Find a free page for the next table.
+                while target.memory[usize::try_from(next_table).unwrap()..][..4096]
+                    .iter()
+                    .any(|&byte| byte != 0)
+                {
+                    next_table += PAGE_SIZE;
+                }
+                entry = next_table | PTE_PRESENT;
+                next_table += PAGE_SIZE;
+            }
+            target
+                .write_memory(entry_addr, &entry.to_le_bytes())
+                .unwrap();
+            table = entry & PTE_ADDR_MASK;
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut target = MockTarget::new(1, 0x10_0000);
+
+        // Without paging, the addresses are physical.
+        let sregs = kvm_sregs::default();
+        assert_eq!(translate(&mut target, &sregs, 0x1234), Ok(0x1234));
+
+        // Paging outside of long mode is not supported.
+        let mut sregs = long_mode_sregs(0x1000);
+        sregs.efer = 0;
+        assert_eq!(
+            translate(&mut target, &sregs, 0x1234),
+            Err(TargetError::PagingMode)
+        );
+
+        let sregs = long_mode_sregs(0x1000);
+        map_page(&mut target, 0xffff_ffff_8100_0000, 0x8000);
+        assert_eq!(
+            translate(&mut target, &sregs, 0xffff_ffff_8100_0123),
+            Ok(0x8123)
+        );
+        assert_eq!(
+            translate(&mut target, &sregs, 0xffff_ffff_8100_1000),
+            Err(TargetError::NotMapped(0xffff_ffff_8100_1000))
+        );
+
+        // A 2 MiB page, mapped by the page directory entry.
+        let pd = {
+            let mut entry = [0u8; 8];
+            let pml4e = 0x1000 + ((0x4000_0000_0000u64 >> 39) & 0x1ff) * 8;
+            map_page(&mut target, 0x4000_0000_0000, 0);
+            target.read_memory(pml4e, &mut entry).unwrap();
+            let pdpt = u64::from_le_bytes(entry) & PTE_ADDR_MASK;
+            target.read_memory(pdpt, &mut entry).unwrap();
+            u64::from_le_bytes(entry) & PTE_ADDR_MASK
+        };
+        target
+            .write_memory(
+                pd + 8,
+                &(0x20_0000 | PTE_PAGE_SIZE | PTE_PRESENT).to_le_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            translate(&mut target, &sregs, 0x4000_0030_0042),
+            Ok(0x30_0042)
+        );
+    }
+
+    #[test]
+    fn test_for_each_page() {
+        let mut target = MockTarget::new(1, 0x10_0000);
+        let sregs = long_mode_sregs(0x1000);
+        // Two consecutive virtual pages backed by swapped physical pages.
+        map_page(&mut target, 0x40_0000, 0x9000);
+        map_page(&mut target, 0x40_1000, 0x8000);
+
+        let mut chunks = Vec::new();
+        for_each_page(&mut target, &sregs, 0x40_0ff0, 0x20, |_, phys, range| {
+            chunks.push((phys, range));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(chunks, vec![(0x9ff0, 0..0x10), (0x8000, 0x10..0x20)]);
+
+        assert_eq!(
+            for_each_page(&mut target, &sregs, 0x40_1ff0, 0x20, |_, _, _| Ok(())),
+            Err(TargetError::NotMapped(0x40_2000))
+        );
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Copy of files from and to the guest, through an agent listening on a vsock port.
+//!
+//! The copies are served by the API thread, which connects to the host socket of the vsock
+//! device like any other host process, while the VMM thread keeps serving the device. Once the
+//! vsock handshake is done, each connection carries a single copy, made of text lines ending
+//! with `\n` and of the raw contents of the file:
+//!
+//! - `FCFILE/1 READ <guest path>` asks for a file of the guest. The agent answers with
+//!   `OK <size>`, followed by the `size` bytes of the file, or with `ERR <message>`.
+//! - `FCFILE/1 WRITE <size> <guest path>`, followed by the `size` bytes of the file, asks the
+//!   agent to replace a file of the guest. The agent answers with `OK` once the file is written,
+//!   or with `ERR <message>`.
+//!
+//! The paths are checked against the allowed directories before any connection is made.
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use logger::{IncMetric, METRICS};
+
+use crate::vmm_config::guest_files::{GuestFileCopyParams, GuestFileDirection, GuestFilesConfig};
+
+/// Version of the protocol spoken with the guest agent, prefixing each request.
+pub const PROTOCOL_VERSION: &str = "FCFILE/1";
+// Longest line accepted from the vsock device or from the guest agent.
+const MAX_LINE_LEN: u64 = 4096;
+const COPY_BUF_LEN: usize = 64 << 10;
+
+/// Errors associated with the copy of guest files.
+#[derive(Debug, thiserror::Error)]
+pub enum GuestFilesError {
+    /// No guest files configuration was set before the microVM was started.
+    #[error("Copying guest files requires the guest files to be configured.")]
+    NotConfigured,
+    /// The microVM has no vsock device to reach the guest agent.
+    #[error("Copying guest files requires a vsock device.")]
+    VsockNotFound,
+    /// The copies to the guest are not allowed.
+    #[error("Copying files to the guest is not allowed.")]
+    WritesNotAllowed,
+    /// The path is out of the allowed directories.
+    #[error("The path {0:?} is not in an allowed directory.")]
+    PathNotAllowed(std::path::PathBuf),
+    /// The file exceeds the size limit.
+    #[error("The file is larger than the {0} MiB limit.")]
+    TooLarge(u64),
+    /// Cannot connect to the guest agent.
+    #[error("Cannot connect to the guest agent: {0}")]
+    Connect(io::Error),
+    /// The guest agent answered with an unexpected line.
+    #[error("Unexpected answer of the guest agent: {0:?}")]
+    Protocol(String),
+    /// The guest agent failed to copy the file.
+    #[error("The guest agent failed to copy the file: {0}")]
+    Agent(String),
+    /// Cannot open, read or write the file on the host.
+    #[error("Cannot access the host file: {0}")]
+    HostFile(io::Error),
+    /// The connection to the guest agent failed during the copy.
+    #[error("Cannot transfer the file: {0}")]
+    Transfer(io::Error),
+}
+
+/// Copies files from and to the guest through the host socket of the vsock device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestFileChannel {
+    config: GuestFilesConfig,
+    uds_path: String,
+}
+
+impl GuestFileChannel {
+    /// Creates a channel reaching the guest agent through the vsock device listening on
+    /// `uds_path`.
+    pub fn new(config: GuestFilesConfig, uds_path: String) -> Self {
+        GuestFileChannel { config, uds_path }
+    }
+
+    /// Copies a file as described by `params`, returning the size of the file.
+    pub fn copy(&self, params: &GuestFileCopyParams) -> Result<u64, GuestFilesError> {
+        let res = self.check(params).and_then(|()| {
+            let stream = self.connect()?;
+            match params.direction {
+                GuestFileDirection::FromGuest => copy_from_guest(
+                    stream,
+                    &params.guest_path,
+                    &params.host_path,
+                    self.max_file_size(),
+                ),
+                GuestFileDirection::ToGuest => copy_to_guest(
+                    stream,
+                    &params.host_path,
+                    &params.guest_path,
+                    self.max_file_size(),
+                ),
+            }
+        });
+        match (&res, params.direction) {
+            (Ok(size), GuestFileDirection::FromGuest) => {
+                METRICS.guest_files.copies_from_guest.inc();
+                METRICS.guest_files.bytes_from_guest.add(to_usize(*size));
+            }
+            (Ok(size), GuestFileDirection::ToGuest) => {
+                METRICS.guest_files.copies_to_guest.inc();
+                METRICS.guest_files.bytes_to_guest.add(to_usize(*size));
+            }
+            (Err(_), _) => METRICS.guest_files.failures.inc(),
+        }
+        res
+    }
+
+    fn check(&self, params: &GuestFileCopyParams) -> Result<(), GuestFilesError> {
+        if params.direction == GuestFileDirection::ToGuest && !self.config.allow_writes {
+            return Err(GuestFilesError::WritesNotAllowed);
+        }
+        if !self.config.guest_path_allowed(&params.guest_path) {
+            return Err(GuestFilesError::PathNotAllowed(params.guest_path.clone()));
+        }
+        if !self.config.host_path_allowed(&params.host_path) {
+            return Err(GuestFilesError::PathNotAllowed(params.host_path.clone()));
+        }
+        Ok(())
+    }
+
+    fn max_file_size(&self) -> u64 {
+        self.config.max_file_size_mib << 20
+    }
+
+    fn connect(&self) -> Result<BufReader<UnixStream>, GuestFilesError> {
+        connect_agent(&self.uds_path, self.config.port, self.config.timeout_ms)
+            .map_err(GuestFilesError::Connect)
+    }
+}
+
+/// Connects to a guest agent listening on the vsock `port`, as described in docs/vsock.md.
+/// Each read and write of the connection fails after `timeout_ms` milliseconds.
+pub(crate) fn connect_agent(
+    uds_path: &str,
+    port: u32,
+    timeout_ms: u64,
+) -> io::Result<BufReader<UnixStream>> {
+    let stream = UnixStream::connect(uds_path)?;
+    let timeout = Some(Duration::from_millis(timeout_ms));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let mut stream = BufReader::new(stream);
+    stream
+        .get_mut()
+        .write_all(format!("CONNECT {}\n", port).as_bytes())?;
+    // The device closes the connection if the guest does not accept it.
+    let line = read_line(&mut stream)?;
+    if !line.starts_with("OK ") {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("the guest agent does not listen on port {}", port),
+        ));
+    }
+    Ok(stream)
+}
+
+fn copy_from_guest<S: Read + Write>(
+    mut stream: BufReader<S>,
+    guest_path: &Path,
+    host_path: &Path,
+    max_size: u64,
+) -> Result<u64, GuestFilesError> {
+    send_request(stream.get_mut(), "READ", guest_path)?;
+    let size = match read_answer(&mut stream)? {
+        Some(size) => size
+            .parse::<u64>()
+            .map_err(|_| GuestFilesError::Protocol(format!("OK {}", size)))?,
+        None => return Err(GuestFilesError::Protocol("OK".to_string())),
+    };
+    if size > max_size {
+        return Err(GuestFilesError::TooLarge(max_size >> 20));
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(host_path)
+        .map_err(GuestFilesError::HostFile)?;
+    if let Err(err) = copy_bytes(
+        &mut stream,
+        &mut file,
+        size,
+        GuestFilesError::Transfer,
+        GuestFilesError::HostFile,
+    ) {
+        // A partial file would pass for the whole one.
+        let _ = std::fs::remove_file(host_path);
+        return Err(err);
+    }
+    Ok(size)
+}
+
+fn copy_to_guest<S: Read + Write>(
+    mut stream: BufReader<S>,
+    host_path: &Path,
+    guest_path: &Path,
+    max_size: u64,
+) -> Result<u64, GuestFilesError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(host_path)
+        .map_err(GuestFilesError::HostFile)?;
+    let size = file.metadata().map_err(GuestFilesError::HostFile)?.len();
+    if size > max_size {
+        return Err(GuestFilesError::TooLarge(max_size >> 20));
+    }
+
+    send_request(stream.get_mut(), &format!("WRITE {}", size), guest_path)?;
+    copy_bytes(
+        &mut file,
+        stream.get_mut(),
+        size,
+        GuestFilesError::HostFile,
+        GuestFilesError::Transfer,
+    )?;
+    match read_answer(&mut stream)? {
+        None => Ok(size),
+        Some(extra) => Err(GuestFilesError::Protocol(format!("OK {}", extra))),
+    }
+}
+
+fn send_request<W: Write>(
+    stream: &mut W,
+    request: &str,
+    path: &Path,
+) -> Result<(), GuestFilesError> {
+    let mut line = format!("{} {} ", PROTOCOL_VERSION, request).into_bytes();
+    line.extend_from_slice(path.as_os_str().as_bytes());
+    line.push(b'\n');
+    stream.write_all(&line).map_err(GuestFilesError::Transfer)
+}
+
+// Reads an `OK [<argument>]` or an `ERR <message>` answer of the guest agent.
+fn read_answer<R: BufRead>(stream: &mut R) -> Result<Option<String>, GuestFilesError> {
+    let line = read_line(stream).map_err(GuestFilesError::Transfer)?;
+    if line == "OK" {
+        Ok(None)
+    } else if let Some(argument) = line.strip_prefix("OK ") {
+        Ok(Some(argument.to_string()))
+    } else if let Some(message) = line.strip_prefix("ERR ") {
+        Err(GuestFilesError::Agent(message.to_string()))
+    } else {
+        Err(GuestFilesError::Protocol(line))
+    }
+}
+
+/// Reads a line sent by the vsock device or by a guest agent, without its `\n`.
+pub(crate) fn read_line<R: BufRead>(stream: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    stream.take(MAX_LINE_LEN).read_line(&mut line)?;
+    match line.strip_suffix('\n') {
+        Some(line) => Ok(line.to_string()),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the connection was closed before the end of the line",
+        )),
+    }
+}
+
+// Copies `size` bytes, mapping the errors of each side with `read_err` and `write_err`.
+// `io::copy` is not used, since it may pick the `sendfile` or `splice` syscalls, which the
+// seccomp filter of the API thread does not allow.
+fn copy_bytes<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+    read_err: fn(io::Error) -> GuestFilesError,
+    write_err: fn(io::Error) -> GuestFilesError,
+) -> Result<(), GuestFilesError> {
+    let mut buf = vec![0u8; COPY_BUF_LEN];
+    let mut left = size;
+    while left > 0 {
+        let len = buf.len().min(to_usize(left));
+        let read = match reader.read(&mut buf[..len]) {
+            Ok(0) => return Err(read_err(io::ErrorKind::UnexpectedEof.into())),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(read_err(err)),
+        };
+        writer.write_all(&buf[..read]).map_err(write_err)?;
+        left -= read as u64;
+    }
+    Ok(())
+}
+
+fn to_usize(value: u64) -> usize {
+    usize::try_from(value).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::thread;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    // Serves a single request as the guest agent would, returning the request line and the
+    // contents received along with it. The host may hang up early.
+    fn run_agent(
+        answer: &'static [u8],
+        contents_len: usize,
+    ) -> (UnixStream, thread::JoinHandle<(String, Vec<u8>)>) {
+        let (host, guest) = UnixStream::pair().unwrap();
+        let agent = thread::spawn(move || {
+            let mut guest = BufReader::new(guest);
+            let request = read_line(&mut guest).unwrap_or_default();
+            let mut contents = vec![0u8; contents_len];
+            let _ = guest.read_exact(&mut contents);
+            let _ = guest.get_mut().write_all(answer);
+            (request, contents)
+        });
+        (host, agent)
+    }
+
+    #[test]
+    fn test_copy_from_guest() {
+        let dir = TempDir::new().unwrap();
+        let host_path = dir.as_path().join("out");
+
+        let (host, agent) = run_agent(b"OK 5\nhello", 0);
+        let size = copy_from_guest(
+            BufReader::new(host),
+            Path::new("/work/out"),
+            &host_path,
+            1 << 20,
+        )
+        .unwrap();
+        assert_eq!(size, 5);
+        assert_eq!(agent.join().unwrap().0, "FCFILE/1 READ /work/out");
+        assert_eq!(std::fs::read(&host_path).unwrap(), b"hello");
+
+        // A truncated file is not left behind.
+        std::fs::remove_file(&host_path).unwrap();
+        let (host, _agent) = run_agent(b"OK 5\nhel", 0);
+        assert!(matches!(
+            copy_from_guest(
+                BufReader::new(host),
+                Path::new("/work/out"),
+                &host_path,
+                1 << 20
+            ),
+            Err(GuestFilesError::Transfer(_))
+        ));
+        assert!(!host_path.exists());
+
+        let (host, _agent) = run_agent(b"OK 2097152\n", 0);
+        assert!(matches!(
+            copy_from_guest(
+                BufReader::new(host),
+                Path::new("/work/out"),
+                &host_path,
+                1 << 20
+            ),
+            Err(GuestFilesError::TooLarge(1))
+        ));
+        assert!(!host_path.exists());
+
+        let (host, _agent) = run_agent(b"ERR No such file or directory\n", 0);
+        match copy_from_guest(
+            BufReader::new(host),
+            Path::new("/work/out"),
+            &host_path,
+            1 << 20,
+        ) {
+            Err(GuestFilesError::Agent(message)) => {
+                assert_eq!(message, "No such file or directory")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let (host, _agent) = run_agent(b"HELLO\n", 0);
+        assert!(matches!(
+            copy_from_guest(
+                BufReader::new(host),
+                Path::new("/work/out"),
+                &host_path,
+                1 << 20
+            ),
+            Err(GuestFilesError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn test_copy_to_guest() {
+        let dir = TempDir::new().unwrap();
+        let host_path = dir.as_path().join("in");
+        std::fs::write(&host_path, b"hello").unwrap();
+
+        let (host, agent) = run_agent(b"OK\n", 5);
+        let size = copy_to_guest(
+            BufReader::new(host),
+            &host_path,
+            Path::new("/work/in"),
+            1 << 20,
+        )
+        .unwrap();
+        assert_eq!(size, 5);
+        let (request, contents) = agent.join().unwrap();
+        assert_eq!(request, "FCFILE/1 WRITE 5 /work/in");
+        assert_eq!(contents, b"hello");
+
+        let (host, _agent) = run_agent(b"ERR Read-only file system\n", 5);
+        assert!(matches!(
+            copy_to_guest(
+                BufReader::new(host),
+                &host_path,
+                Path::new("/work/in"),
+                1 << 20
+            ),
+            Err(GuestFilesError::Agent(_))
+        ));
+
+        let (host, _agent) = run_agent(b"", 0);
+        assert!(matches!(
+            copy_to_guest(BufReader::new(host), &host_path, Path::new("/work/in"), 4),
+            Err(GuestFilesError::TooLarge(0))
+        ));
+
+        let (host, _agent) = run_agent(b"", 0);
+        assert!(matches!(
+            copy_to_guest(
+                BufReader::new(host),
+                &dir.as_path().join("missing"),
+                Path::new("/work/in"),
+                1 << 20
+            ),
+            Err(GuestFilesError::HostFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_copy() {
+        let channel = GuestFileChannel::new(
+            GuestFilesConfig {
+                port: 52,
+                host_dirs: vec![PathBuf::from("/srv/outputs")],
+                guest_dirs: vec![PathBuf::from("/workspace")],
+                allow_writes: false,
+                max_file_size_mib: 1,
+                timeout_ms: 1000,
+            },
+            "/srv/v.sock".to_string(),
+        );
+        let mut params = GuestFileCopyParams {
+            direction: GuestFileDirection::FromGuest,
+            guest_path: PathBuf::from("/workspace/out.tar"),
+            host_path: PathBuf::from("/srv/outputs/out.tar"),
+        };
+        channel.check(&params).unwrap();
+
+        params.direction = GuestFileDirection::ToGuest;
+        assert!(matches!(
+            channel.check(&params),
+            Err(GuestFilesError::WritesNotAllowed)
+        ));
+
+        params.direction = GuestFileDirection::FromGuest;
+        params.host_path = PathBuf::from("/etc/passwd");
+        assert!(matches!(
+            channel.check(&params),
+            Err(GuestFilesError::PathNotAllowed(_))
+        ));
+        // The copy fails before connecting to the guest agent.
+        assert!(matches!(
+            channel.copy(&params),
+            Err(GuestFilesError::PathNotAllowed(_))
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Freeze of the guest filesystems through an agent listening on a vsock port, so that the
+//! snapshots of the microVM and of its disks find them in a consistent state.
+//!
+//! The requests are served by the API thread, like the copies of guest files. Each connection
+//! carries a single request, made of a text line ending with `\n`:
+//!
+//! - `FCFREEZE/1 FREEZE` asks the agent to freeze the guest filesystems, for instance with the
+//!   `FIFREEZE` ioctl. The agent answers with `OK` once they are frozen, or with
+//!   `ERR <message>`.
+//! - `FCFREEZE/1 THAW` asks the agent to thaw the guest filesystems. The agent answers with
+//!   `OK` once they are thawed, or with `ERR <message>`.
+//!
+//! A failed freeze is followed by a thaw, since the agent may have frozen some of the
+//! filesystems, or may freeze them after the answer timed out.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use logger::{error, info};
+
+use crate::guest_files::{connect_agent, read_line};
+use crate::vmm_config::guest_freeze::GuestFreezeConfig;
+
+/// Version of the protocol spoken with the guest agent, prefixing each request.
+pub const PROTOCOL_VERSION: &str = "FCFREEZE/1";
+
+/// Errors associated with the freeze of the guest filesystems.
+#[derive(Debug, thiserror::Error)]
+pub enum GuestFreezeError {
+    /// No guest freeze configuration was set before the microVM was started.
+    #[error("Freezing the guest filesystems requires the guest freeze to be configured.")]
+    NotConfigured,
+    /// The microVM has no vsock device to reach the guest agent.
+    #[error("Freezing the guest filesystems requires a vsock device.")]
+    VsockNotFound,
+    /// Cannot connect to the guest agent.
+    #[error("Cannot connect to the guest agent: {0}")]
+    Connect(io::Error),
+    /// The guest agent did not answer in time, or the connection failed.
+    #[error("Cannot reach the guest agent: {0}")]
+    Transfer(io::Error),
+    /// The guest agent answered with an unexpected line.
+    #[error("Unexpected answer of the guest agent: {0:?}")]
+    Protocol(String),
+    /// The guest agent failed to freeze or to thaw the filesystems.
+    #[error("The guest agent failed to change the state of the filesystems: {0}")]
+    Agent(String),
+}
+
+/// Freezes and thaws the guest filesystems through the host socket of the vsock device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestFreezeChannel {
+    config: GuestFreezeConfig,
+    uds_path: String,
+}
+
+impl GuestFreezeChannel {
+    /// Creates a channel reaching the guest agent through the vsock device listening on
+    /// `uds_path`.
+    pub fn new(config: GuestFreezeConfig, uds_path: String) -> Self {
+        GuestFreezeChannel { config, uds_path }
+    }
+
+    /// Freezes the guest filesystems, thawing them back if the agent does not confirm it.
+    pub fn freeze(&self) -> Result<(), GuestFreezeError> {
+        let res = self.request("FREEZE");
+        match &res {
+            Ok(()) => info!("Froze the guest filesystems."),
+            // The agent was never reached, so it has nothing to thaw.
+            Err(GuestFreezeError::Connect(_)) => (),
+            Err(_) => {
+                if let Err(err) = self.request("THAW") {
+                    error!(
+                        "Cannot thaw the guest filesystems after a failed freeze: {}",
+                        err
+                    );
+                }
+            }
+        }
+        res
+    }
+
+    /// Thaws the guest filesystems.
+    pub fn thaw(&self) -> Result<(), GuestFreezeError> {
+        self.request("THAW")?;
+        info!("Thawed the guest filesystems.");
+        Ok(())
+    }
+
+    fn request(&self, request: &str) -> Result<(), GuestFreezeError> {
+        let mut stream = connect_agent(&self.uds_path, self.config.port, self.config.timeout_ms)
+            .map_err(GuestFreezeError::Connect)?;
+        send_request(&mut stream, request)
+    }
+}
+
+fn send_request(stream: &mut BufReader<UnixStream>, request: &str) -> Result<(), GuestFreezeError> {
+    stream
+        .get_mut()
+        .write_all(format!("{} {}\n", PROTOCOL_VERSION, request).as_bytes())
+        .map_err(GuestFreezeError::Transfer)?;
+    read_answer(stream)
+}
+
+// Reads an `OK` or an `ERR <message>` answer of the guest agent.
+fn read_answer<R: BufRead>(stream: &mut R) -> Result<(), GuestFreezeError> {
+    let line = read_line(stream).map_err(GuestFreezeError::Transfer)?;
+    if line == "OK" {
+        Ok(())
+    } else if let Some(message) = line.strip_prefix("ERR ") {
+        Err(GuestFreezeError::Agent(message.to_string()))
+    } else {
+        Err(GuestFreezeError::Protocol(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    // Serves the vsock handshake and then `answers.len()` requests as the guest agent would,
+    // one per connection, returning the request lines.
+    fn run_agent(
+        uds_path: &std::path::Path,
+        answers: &'static [&'static [u8]],
+    ) -> thread::JoinHandle<Vec<String>> {
+        let listener = UnixListener::bind(uds_path).unwrap();
+        thread::spawn(move || {
+            let mut requests = Vec::new();
+            for answer in answers {
+                let (stream, _) = listener.accept().unwrap();
+                let mut stream = BufReader::new(stream);
+                assert_eq!(read_line(&mut stream).unwrap(), "CONNECT 53");
+                stream.get_mut().write_all(b"OK 1073741824\n").unwrap();
+                requests.push(read_line(&mut stream).unwrap_or_default());
+                stream.get_mut().write_all(answer).unwrap();
+            }
+            requests
+        })
+    }
+
+    fn channel(uds_path: &std::path::Path) -> GuestFreezeChannel {
+        GuestFreezeChannel::new(
+            GuestFreezeConfig {
+                port: 53,
+                timeout_ms: 1000,
+            },
+            uds_path.to_str().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_freeze_thaw() {
+        let dir = TempDir::new().unwrap();
+        let uds_path = dir.as_path().join("v.sock");
+        let agent = run_agent(&uds_path, &[b"OK\n", b"OK\n"]);
+        let channel = channel(&uds_path);
+
+        channel.freeze().unwrap();
+        channel.thaw().unwrap();
+        assert_eq!(
+            agent.join().unwrap(),
+            vec!["FCFREEZE/1 FREEZE", "FCFREEZE/1 THAW"]
+        );
+    }
+
+    #[test]
+    fn test_failed_freeze() {
+        let dir = TempDir::new().unwrap();
+        let uds_path = dir.as_path().join("v.sock");
+
+        // No agent to connect to.
+        assert!(matches!(
+            channel(&uds_path).freeze(),
+            Err(GuestFreezeError::Connect(_))
+        ));
+
+        // The filesystems are thawed after a failed freeze.
+        let agent = run_agent(&uds_path, &[b"ERR Device or resource busy\n", b"OK\n"]);
+        match channel(&uds_path).freeze() {
+            Err(GuestFreezeError::Agent(message)) => {
+                assert_eq!(message, "Device or resource busy")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            agent.join().unwrap(),
+            vec!["FCFREEZE/1 FREEZE", "FCFREEZE/1 THAW"]
+        );
+    }
+
+    #[test]
+    fn test_read_answer() {
+        read_answer(&mut &b"OK\n"[..]).unwrap();
+        assert!(matches!(
+            read_answer(&mut &b"OK 1\n"[..]),
+            Err(GuestFreezeError::Protocol(_))
+        ));
+        assert!(matches!(
+            read_answer(&mut &b"ERR No agent\n"[..]),
+            Err(GuestFreezeError::Agent(_))
+        ));
+        assert!(matches!(
+            read_answer(&mut &b"OK"[..]),
+            Err(GuestFreezeError::Transfer(_))
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aws_lc_rs::signature::Ed25519KeyPair;
+use mmds::data_store::Mmds;
+use serde::Serialize;
+
+use crate::vmm_config::identity::IdentityConfig;
+
+/// MMDS key under which the identity document is published to the guest.
+pub const IDENTITY_MMDS_KEY: &str = "instance_identity";
+
+// Statement of the identity of the microVM, signed as its serialized form.
+#[derive(Debug, Serialize)]
+struct IdentityDocument<'a> {
+    uuid: &'a str,
+    instance_id: &'a str,
+    issued_at_s: u64,
+}
+
+/// Issues a new identity document and publishes it under `IDENTITY_MMDS_KEY`, along with its
+/// signature by `signing_key`. The rest of the data store is left untouched.
+///
+/// The document is a JSON string so that the guest checks the signature against the exact bytes
+/// which were signed.
+pub(crate) fn publish_identity(
+    mmds: &mut Mmds,
+    config: &IdentityConfig,
+    signing_key: Option<&Ed25519KeyPair>,
+    instance_id: &str,
+) -> Result<(), mmds::data_store::Error> {
+    let document = IdentityDocument {
+        uuid: config.uuid.as_deref().unwrap_or_default(),
+        instance_id,
+        issued_at_s: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default(),
+    };
+    // Serializing a struct of strings and integers cannot fail.
+    let document = serde_json::to_string(&document).unwrap();
+
+    let mut identity = serde_json::Map::new();
+    if let Some(signing_key) = signing_key {
+        let signature = signing_key.sign(document.as_bytes());
+        identity.insert(
+            "signature".to_string(),
+            serde_json::Value::String(base64::encode(signature.as_ref())),
+        );
+    }
+    identity.insert("document".to_string(), serde_json::Value::String(document));
+
+    let mut data = match mmds.data_store_value() {
+        serde_json::Value::Object(data) => data,
+        _ => serde_json::Map::new(),
+    };
+    data.insert(
+        IDENTITY_MMDS_KEY.to_string(),
+        serde_json::Value::Object(identity),
+    );
+    mmds.put_data(serde_json::Value::Object(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_lc_rs::signature::{KeyPair, UnparsedPublicKey, ED25519};
+    use serde_json::json;
+
+    use super::*;
+    use crate::vmm_config::identity::tests::signing_key_file;
+
+    fn identity(mmds: &Mmds) -> serde_json::Value {
+        mmds.data_store_value()[IDENTITY_MMDS_KEY].clone()
+    }
+
+    #[test]
+    fn test_publish_identity() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(json!({"foo": "bar"})).unwrap();
+        let config = IdentityConfig {
+            uuid: Some("01234567-89ab-cdef-0123-456789abcdef".to_string()),
+            ..Default::default()
+        };
+        publish_identity(&mut mmds, &config, None, "vm0").unwrap();
+
+        // The rest of the data store is kept.
+        assert_eq!(mmds.data_store_value()["foo"], "bar");
+        let identity = identity(&mmds);
+        assert!(identity.get("signature").is_none());
+        let document: serde_json::Value =
+            serde_json::from_str(identity["document"].as_str().unwrap()).unwrap();
+        assert_eq!(document["uuid"], "01234567-89ab-cdef-0123-456789abcdef");
+        assert_eq!(document["instance_id"], "vm0");
+        assert!(document["issued_at_s"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_publish_signed_identity() {
+        let key_file = signing_key_file();
+        let mut config = IdentityConfig {
+            signing_key_path: Some(key_file.as_path().to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        config.generate_uuid().unwrap();
+        let signing_key = config.signing_key().unwrap().unwrap();
+        let public_key = signing_key.public_key().as_ref().to_vec();
+
+        let mut mmds = Mmds::default();
+        publish_identity(&mut mmds, &config, Some(&signing_key), "vm0").unwrap();
+        let identity = identity(&mmds);
+        let document = identity["document"].as_str().unwrap();
+        let signature = base64::decode(identity["signature"].as_str().unwrap()).unwrap();
+        UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(document.as_bytes(), &signature)
+            .unwrap();
+        assert!(UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(b"{}", &signature)
+            .is_err());
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! I/O threads processing the events of the block and network devices.
+//!
+//! The events of the devices are processed by the VMM thread, so a busy device delays the events
+//! of all the others. The devices assigned to an I/O thread are subscribed to the event manager
+//! of that thread instead, which runs its own event loop. The I/O threads are paused while a
+//! snapshot is created, so that the devices do not touch the guest memory once their state is
+//! saved.
+//!
+//! The shared directories are served by a thread of their own, whose seccomp filter allows the
+//! syscalls modifying the host filesystem, which the filter of the VMM thread does not. It is
+//! never paused, as microVMs with shared directories cannot be snapshotted.
+
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::{fmt, io};
+
+use event_manager::{EventManager as BaseEventManager, EventOps, Events, MutEventSubscriber};
+use logger::error;
+use seccompiler::BpfProgram;
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+
+use crate::vmm_config::io_threads::IoThreadsConfig;
+use crate::EventManager;
+
+/// Event manager of an I/O thread. Its subscribers are moved to the thread, so they must be
+/// `Send`.
+pub type IoEventManager = BaseEventManager<Arc<Mutex<dyn MutEventSubscriber + Send>>>;
+
+// Time the I/O threads are given to finish processing their current event and pause.
+const PAUSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors associated with the I/O threads.
+#[derive(Debug, thiserror::Error)]
+pub enum IoThreadError {
+    /// The event manager of an I/O thread cannot be created.
+    #[error("Cannot create the event manager of an I/O thread: {0}")]
+    EventManager(event_manager::Error),
+    /// The pause event of an I/O thread cannot be created or signaled.
+    #[error("Cannot use the pause event of an I/O thread: {0}")]
+    PauseEvent(io::Error),
+    /// A device assigned to an I/O thread is not attached to the microVM.
+    #[error("The device {0} assigned to an I/O thread is not a block or network device.")]
+    UnknownDevice(String),
+    /// An I/O thread cannot be spawned.
+    #[error("Cannot spawn an I/O thread: {0}")]
+    Spawn(io::Error),
+    /// The I/O threads did not pause in time.
+    #[error("The I/O threads did not pause within {} seconds.", PAUSE_TIMEOUT.as_secs())]
+    PauseTimeout,
+}
+
+/// Distributes the devices between the event managers of the I/O threads and the VMM one, until
+/// the I/O threads are started.
+pub struct IoThreadsBuilder {
+    assignments: HashMap<String, usize>,
+    attached: HashSet<String>,
+    event_managers: Vec<IoEventManager>,
+    shared_dirs_event_manager: Option<IoEventManager>,
+}
+
+impl fmt::Debug for IoThreadsBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoThreadsBuilder")
+            .field("assignments", &self.assignments)
+            .field("attached", &self.attached)
+            .field("shared_dirs", &self.shared_dirs_event_manager.is_some())
+            .finish()
+    }
+}
+
+impl IoThreadsBuilder {
+    /// Creates the event managers of the I/O threads of `config`. There are none without it.
+    pub fn new(config: Option<&IoThreadsConfig>) -> Result<Self, IoThreadError> {
+        let (thread_count, assignments) = match config {
+            Some(config) => (
+                usize::from(config.thread_count),
+                config
+                    .devices
+                    .iter()
+                    .map(|assignment| {
+                        (assignment.device_id.clone(), usize::from(assignment.thread))
+                    })
+                    .collect(),
+            ),
+            None => (0, HashMap::new()),
+        };
+        let event_managers = (0..thread_count)
+            .map(|_| IoEventManager::new().map_err(IoThreadError::EventManager))
+            .collect::<Result<_, _>>()?;
+
+        Ok(IoThreadsBuilder {
+            assignments,
+            attached: HashSet::new(),
+            event_managers,
+            shared_dirs_event_manager: None,
+        })
+    }
+
+    /// Subscribes `subscriber`, which serves the device `device_id`, to the event manager of the
+    /// I/O thread the device is assigned to, or to `event_manager` when it is not assigned.
+    pub fn add_subscriber<T: MutEventSubscriber + Send + 'static>(
+        &mut self,
+        device_id: &str,
+        subscriber: Arc<Mutex<T>>,
+        event_manager: &mut EventManager,
+    ) {
+        match self.assignments.get(device_id) {
+            Some(&thread) => {
+                self.attached.insert(device_id.to_string());
+                self.event_managers[thread].add_subscriber(subscriber);
+            }
+            None => {
+                event_manager.add_subscriber(subscriber);
+            }
+        }
+    }
+
+    /// Subscribes `subscriber`, which serves a shared directory, to the event manager of the
+    /// thread serving the shared directories.
+    pub fn add_shared_dir_subscriber<T: MutEventSubscriber + Send + 'static>(
+        &mut self,
+        subscriber: Arc<Mutex<T>>,
+    ) -> Result<(), IoThreadError> {
+        let mut event_manager = match self.shared_dirs_event_manager.take() {
+            Some(event_manager) => event_manager,
+            None => IoEventManager::new().map_err(IoThreadError::EventManager)?,
+        };
+        event_manager.add_subscriber(subscriber);
+        self.shared_dirs_event_manager = Some(event_manager);
+        Ok(())
+    }
+
+    /// Spawns the I/O threads, which install `seccomp_filter` before running their event loop,
+    /// and the thread serving the shared directories, if any, which installs
+    /// `shared_dirs_seccomp_filter`.
+    pub fn start(
+        self,
+        seccomp_filter: Arc<BpfProgram>,
+        shared_dirs_seccomp_filter: Arc<BpfProgram>,
+    ) -> Result<IoThreads, IoThreadError> {
+        if let Some(device_id) = self
+            .assignments
+            .keys()
+            .find(|device_id| !self.attached.contains(*device_id))
+        {
+            return Err(IoThreadError::UnknownDevice(device_id.clone()));
+        }
+
+        let mut io_threads = IoThreads::default();
+        for (index, mut event_manager) in self.event_managers.into_iter().enumerate() {
+            let pause_event =
+                Arc::new(EventFd::new(libc::EFD_NONBLOCK).map_err(IoThreadError::PauseEvent)?);
+            event_manager.add_subscriber(Arc::new(Mutex::new(PauseHandler {
+                pause_event: pause_event.clone(),
+                state: io_threads.state.clone(),
+            })));
+
+            let tid = spawn_thread(
+                format!("fc_io {}", index),
+                "vmm",
+                seccomp_filter.clone(),
+                event_manager,
+            )?;
+            io_threads.pause_events.push(pause_event);
+            io_threads.tids.extend(tid);
+        }
+
+        if let Some(event_manager) = self.shared_dirs_event_manager {
+            let tid = spawn_thread(
+                "fc_9p".to_string(),
+                "shared_dirs",
+                shared_dirs_seccomp_filter,
+                event_manager,
+            )?;
+            io_threads.tids.extend(tid);
+        }
+        Ok(io_threads)
+    }
+}
+
+// Spawns a thread running the event loop of `event_manager`, once it installed `seccomp_filter`,
+// the filter of `thread_category`. Returns the thread id, unless the thread panicked before
+// sending it.
+fn spawn_thread(
+    name: String,
+    thread_category: &'static str,
+    seccomp_filter: Arc<BpfProgram>,
+    mut event_manager: IoEventManager,
+) -> Result<Option<libc::pid_t>, IoThreadError> {
+    let (tid_sender, tid_receiver) = channel();
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            // The thread id is read before the seccomp filter forbids gettid().
+            // SAFETY: Safe because gettid() takes no arguments and cannot fail.
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+            if let Err(err) =
+                crate::seccomp_filters::install_filter(thread_category, &seccomp_filter)
+            {
+                panic!("Failed to set the I/O thread seccomp filters: {}", err);
+            }
+            // The builder waits for the thread id.
+            let _ = tid_sender.send(tid as libc::pid_t);
+            loop {
+                event_manager
+                    .run()
+                    .expect("Failed to run the I/O thread event loop");
+            }
+        })
+        .map_err(IoThreadError::Spawn)?;
+
+    // The sender is only dropped without sending when the thread panics.
+    Ok(tid_receiver.recv().ok())
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: bool,
+    paused_threads: usize,
+}
+
+#[derive(Debug, Default)]
+struct SharedPauseState {
+    state: Mutex<PauseState>,
+    changed: Condvar,
+}
+
+/// Handle on the running I/O threads.
+#[derive(Clone, Debug, Default)]
+pub struct IoThreads {
+    pause_events: Vec<Arc<EventFd>>,
+    state: Arc<SharedPauseState>,
+    tids: Vec<libc::pid_t>,
+}
+
+impl IoThreads {
+    /// Returns the kernel thread ids of the I/O threads, and of the thread serving the shared
+    /// directories.
+    pub fn tids(&self) -> &[libc::pid_t] {
+        &self.tids
+    }
+
+    /// Pauses the I/O threads once they are done with their current event, until the returned
+    /// guard is dropped.
+    pub fn pause(&self) -> Result<IoThreadsPause, IoThreadError> {
+        let pause = IoThreadsPause {
+            io_threads: self.clone(),
+        };
+        pause
+            .io_threads
+            .state
+            .state
+            .lock()
+            .expect("Poisoned lock")
+            .paused = true;
+        for pause_event in &self.pause_events {
+            pause_event.write(1).map_err(IoThreadError::PauseEvent)?;
+        }
+
+        let (_, timeout) = self
+            .state
+            .changed
+            .wait_timeout_while(
+                self.state.state.lock().expect("Poisoned lock"),
+                PAUSE_TIMEOUT,
+                |state| state.paused_threads < self.pause_events.len(),
+            )
+            .expect("Poisoned lock");
+        if timeout.timed_out() {
+            return Err(IoThreadError::PauseTimeout);
+        }
+        Ok(pause)
+    }
+}
+
+/// Keeps the I/O threads paused until it is dropped.
+#[derive(Debug)]
+pub struct IoThreadsPause {
+    io_threads: IoThreads,
+}
+
+impl Drop for IoThreadsPause {
+    fn drop(&mut self) {
+        self.io_threads
+            .state
+            .state
+            .lock()
+            .expect("Poisoned lock")
+            .paused = false;
+        self.io_threads.state.changed.notify_all();
+    }
+}
+
+// Blocks its I/O thread while the I/O threads are paused.
+#[derive(Debug)]
+struct PauseHandler {
+    pause_event: Arc<EventFd>,
+    state: Arc<SharedPauseState>,
+}
+
+impl MutEventSubscriber for PauseHandler {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        if event.fd() != self.pause_event.as_raw_fd() || event.event_set() != EventSet::IN {
+            error!("Spurious EventManager event for handler: PauseHandler");
+            return;
+        }
+        let _ = self.pause_event.read();
+
+        let mut state = self.state.state.lock().expect("Poisoned lock");
+        if !state.paused {
+            return;
+        }
+        state.paused_threads += 1;
+        self.state.changed.notify_all();
+        state = self
+            .state
+            .changed
+            .wait_while(state, |state| state.paused)
+            .expect("Poisoned lock");
+        state.paused_threads -= 1;
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(self.pause_event.as_ref(), EventSet::IN)) {
+            error!("Failed to register the I/O thread pause event: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::vmm_config::io_threads::IoThreadAssignment;
+
+    #[derive(Debug)]
+    struct CountingSubscriber {
+        event: EventFd,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl MutEventSubscriber for CountingSubscriber {
+        fn process(&mut self, _: Events, _: &mut EventOps) {
+            let _ = self.event.read();
+            self.count.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn init(&mut self, ops: &mut EventOps) {
+            ops.add(Events::new(&self.event, EventSet::IN)).unwrap();
+        }
+    }
+
+    fn wait_for_count(count: &AtomicUsize, expected: usize) {
+        for _ in 0..100 {
+            if count.load(Ordering::SeqCst) == expected {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The count did not reach {}", expected);
+    }
+
+    #[test]
+    fn test_io_threads() {
+        let config = IoThreadsConfig {
+            thread_count: 2,
+            devices: vec![IoThreadAssignment {
+                device_id: "rootfs".to_string(),
+                thread: 1,
+            }],
+        };
+        let mut event_manager = EventManager::new().unwrap();
+        let mut builder = IoThreadsBuilder::new(Some(&config)).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = Arc::new(Mutex::new(CountingSubscriber {
+            event: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            count: count.clone(),
+        }));
+        builder.add_subscriber("rootfs", subscriber.clone(), &mut event_manager);
+        let io_threads = builder
+            .start(Arc::new(BpfProgram::new()), Arc::new(BpfProgram::new()))
+            .unwrap();
+        assert_eq!(io_threads.tids().len(), 2);
+
+        // The event is processed by the I/O thread, without running the VMM event loop.
+        subscriber.lock().unwrap().event.write(1).unwrap();
+        wait_for_count(&count, 1);
+
+        // The events are left pending while the I/O threads are paused.
+        let pause = io_threads.pause().unwrap();
+        subscriber.lock().unwrap().event.write(1).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        drop(pause);
+        wait_for_count(&count, 2);
+    }
+
+    #[test]
+    fn test_shared_dirs_thread() {
+        let mut builder = IoThreadsBuilder::new(None).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = Arc::new(Mutex::new(CountingSubscriber {
+            event: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            count: count.clone(),
+        }));
+        builder
+            .add_shared_dir_subscriber(subscriber.clone())
+            .unwrap();
+        let io_threads = builder
+            .start(Arc::new(BpfProgram::new()), Arc::new(BpfProgram::new()))
+            .unwrap();
+        assert_eq!(io_threads.tids().len(), 1);
+
+        subscriber.lock().unwrap().event.write(1).unwrap();
+        wait_for_count(&count, 1);
+
+        // The thread serving the shared directories is not paused.
+        let _pause = io_threads.pause().unwrap();
+        subscriber.lock().unwrap().event.write(1).unwrap();
+        wait_for_count(&count, 2);
+    }
+
+    #[test]
+    fn test_unassigned_devices() {
+        // Without configuration, the devices are subscribed to the VMM event manager.
+        let mut event_manager = EventManager::new().unwrap();
+        let mut builder = IoThreadsBuilder::new(None).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = Arc::new(Mutex::new(CountingSubscriber {
+            event: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            count: count.clone(),
+        }));
+        builder.add_subscriber("rootfs", subscriber.clone(), &mut event_manager);
+        subscriber.lock().unwrap().event.write(1).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        let io_threads = builder
+            .start(Arc::new(BpfProgram::new()), Arc::new(BpfProgram::new()))
+            .unwrap();
+        assert!(io_threads.tids().is_empty());
+        io_threads.pause().unwrap();
+
+        // A device assigned to an I/O thread must be attached.
+        let config = IoThreadsConfig {
+            thread_count: 1,
+            devices: vec![IoThreadAssignment {
+                device_id: "scratch".to_string(),
+                thread: 0,
+            }],
+        };
+        let builder = IoThreadsBuilder::new(Some(&config)).unwrap();
+        assert!(matches!(
+            builder.start(Arc::new(BpfProgram::new()), Arc::new(BpfProgram::new())),
+            Err(IoThreadError::UnknownDevice(device_id)) if device_id == "scratch"
+        ));
+    }
+}
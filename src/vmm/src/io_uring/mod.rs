@@ -13,7 +13,7 @@ pub mod restriction;
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::Error as IOError;
+use std::io::{Error as IOError, ErrorKind};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 
 use bindings::io_uring_params;
@@ -221,7 +221,20 @@ impl IoUring {
 
     /// Submit all operations and wait for their completion.
     pub fn submit_and_wait_all(&mut self) -> Result<u32, IoUringError> {
-        self.do_submit(self.num_ops)
+        let mut submitted = 0;
+        // `io_uring_enter` returns before the operations are completed when the wait is
+        // interrupted by a signal, so we wait again until they are all in the completion queue.
+        loop {
+            match self.do_submit(self.num_ops) {
+                Ok(count) => submitted += count,
+                Err(IoUringError::SQueue(SQueueError::Submit(err)))
+                    if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+            if self.pending_cqes()? >= self.num_ops {
+                return Ok(submitted);
+            }
+        }
     }
 
     /// Return the number of operations currently on the submission queue.
@@ -229,6 +242,11 @@ impl IoUring {
         self.squeue.pending().map_err(IoUringError::SQueue)
     }
 
+    /// Return the number of completed operations which were not popped yet.
+    pub fn pending_cqes(&self) -> Result<u32, IoUringError> {
+        self.cqueue.pending().map_err(IoUringError::CQueue)
+    }
+
     /// A total of the number of ops in the submission and completion queues, as well as the
     /// in-flight ops.
     pub fn num_ops(&self) -> u32 {
@@ -585,7 +603,9 @@ mod tests {
 
                     // Submit any left async ops and wait.
                     ring.submit_and_wait_all().unwrap();
+                    assert_eq!(ring.pending_cqes().unwrap(), ring.num_ops());
                     drain_cqueue(&mut ring);
+                    assert_eq!(ring.num_ops(), 0);
 
                     // Get the write result for async IO.
                     let mut async_result = [0u8; FILE_LEN];
@@ -72,6 +72,14 @@ impl CompletionQueue {
         self.count
     }
 
+    pub(crate) fn pending(&self) -> Result<u32, CQueueError> {
+        let ring = self.cqes.as_volatile_slice();
+        // get the cqe tail
+        let unmasked_tail = ring.load::<u32>(self.tail_off, Ordering::Acquire)?;
+
+        Ok((Wrapping(unmasked_tail) - self.unmasked_head).0)
+    }
+
     /// # Safety
     /// Unsafe because we reconstruct the `user_data` from a raw pointer passed by the kernel.
     /// It's up to the caller to make sure that `T` is the correct type of the `user_data`, that
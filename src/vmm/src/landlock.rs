@@ -0,0 +1,465 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{c_int, c_void, siginfo_t};
+use utils::signal::sigrtmin;
+use utils::syscall::SyscallReturnCode;
+
+// Landlock syscall numbers, which are the same on x86_64 and aarch64.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+// Filesystem access rights from include/uapi/linux/landlock.h, as of the first Landlock ABI.
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+// All the access rights the ruleset denies unless a rule grants them.
+const HANDLED_ACCESS_FS: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_READ_DIR
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM;
+
+/// Real-time signal, relative to `SIGRTMIN`, asking the registered threads to enforce the ruleset
+/// on themselves.
+pub const LANDLOCK_RTSIG_OFFSET: c_int = 1;
+// How long the registered threads have to enforce the ruleset.
+const REGISTERED_THREADS_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Threads started before the ruleset is enforced, which Landlock does not restrict on their own.
+static REGISTERED_THREADS: Mutex<Vec<libc::pthread_t>> = Mutex::new(Vec::new());
+// Ruleset the registered threads enforce when signaled, and how many of them succeeded or failed.
+static THREAD_RULESET_FD: AtomicI32 = AtomicI32::new(-1);
+static RESTRICTED_THREADS: AtomicUsize = AtomicUsize::new(0);
+static FAILED_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Errors associated with applying the Landlock ruleset.
+#[derive(Debug, thiserror::Error)]
+pub enum LandlockError {
+    /// The host kernel does not support Landlock.
+    #[error("Landlock is not supported by the host kernel: {0}")]
+    NotSupported(io::Error),
+    /// Failed to create the ruleset.
+    #[error("Failed to create the Landlock ruleset: {0}")]
+    CreateRuleset(io::Error),
+    /// Failed to open a path allowed by the ruleset.
+    #[error("Failed to open {0:?} for the Landlock ruleset: {1}")]
+    OpenPath(PathBuf, io::Error),
+    /// Failed to add a rule to the ruleset.
+    #[error("Failed to add a Landlock rule for {0:?}: {1}")]
+    AddRule(PathBuf, io::Error),
+    /// Failed to prevent the process from gaining new privileges.
+    #[error("Failed to set no_new_privs: {0}")]
+    NoNewPrivs(io::Error),
+    /// Failed to enforce the ruleset.
+    #[error("Failed to enforce the Landlock ruleset: {0}")]
+    RestrictSelf(io::Error),
+    /// Failed to enforce the ruleset on the registered threads.
+    #[error("Failed to enforce the Landlock ruleset on the registered threads: {0}")]
+    RestrictThreads(io::Error),
+}
+
+/// Registration of a thread started before the ruleset is enforced, such as the API threads. The
+/// registered threads are signaled to enforce the ruleset along with the calling thread. The
+/// thread is unregistered when the registration is dropped, which must happen on the thread.
+#[derive(Debug)]
+pub struct ThreadRegistration {
+    thread: libc::pthread_t,
+    // The registration is not `Send`, so that it is dropped by the thread it registers.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Registers the calling thread, until the returned registration is dropped.
+pub fn register_thread() -> ThreadRegistration {
+    // SAFETY: Safe because pthread_self() cannot fail.
+    let thread = unsafe { libc::pthread_self() };
+    REGISTERED_THREADS
+        .lock()
+        .expect("Poisoned lock")
+        .push(thread);
+    ThreadRegistration {
+        thread,
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for ThreadRegistration {
+    fn drop(&mut self) {
+        let mut threads = REGISTERED_THREADS.lock().expect("Poisoned lock");
+        if let Some(index) = threads.iter().position(|thread| *thread == self.thread) {
+            threads.swap_remove(index);
+        }
+    }
+}
+
+/// Set of filesystem paths the VMM keeps access to once the ruleset is enforced.
+#[derive(Debug, Default)]
+pub struct LandlockRuleset {
+    // Each rule holds the path, whether it is read-only and whether the guest manages the
+    // directory tree beneath it.
+    rules: Vec<(PathBuf, bool, bool)>,
+}
+
+impl LandlockRuleset {
+    /// Allows access to `path` and, if it is a directory, to everything beneath it.
+    pub fn allow<P: AsRef<Path>>(&mut self, path: P, read_only: bool) {
+        self.rules
+            .push((path.as_ref().to_path_buf(), read_only, false));
+    }
+
+    /// Allows access to the directory `path` and to everything beneath it. Unless `read_only`
+    /// is set, this also allows creating and removing directories and symbolic links, as
+    /// required by the shared directories.
+    pub fn allow_tree<P: AsRef<Path>>(&mut self, path: P, read_only: bool) {
+        self.rules
+            .push((path.as_ref().to_path_buf(), read_only, true));
+    }
+
+    /// Enforces the ruleset on the calling thread, on all the threads it spawns afterwards and on
+    /// the registered threads. Other threads started before this call are not restricted.
+    pub fn restrict_self(&self) -> Result<(), LandlockError> {
+        // SAFETY: Safe because a null attribute with a zero size only queries the ABI version.
+        SyscallReturnCode(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<LandlockRulesetAttr>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        })
+        .into_empty_result()
+        .map_err(LandlockError::NotSupported)?;
+
+        let attr = LandlockRulesetAttr {
+            handled_access_fs: HANDLED_ACCESS_FS,
+        };
+        // SAFETY: Safe because `attr` is a valid ruleset attribute of the given size.
+        let fd = SyscallReturnCode(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const LandlockRulesetAttr,
+                std::mem::size_of::<LandlockRulesetAttr>(),
+                0u32,
+            )
+        })
+        .into_result()
+        .map_err(LandlockError::CreateRuleset)?;
+        // SAFETY: Safe because the ruleset fd was just created and is exclusively owned by the
+        // file.
+        let ruleset = unsafe { File::from_raw_fd(i32::try_from(fd).unwrap()) };
+
+        for (path, read_only, tree) in &self.rules {
+            add_path_rule(&ruleset, path, *read_only, *tree)?;
+        }
+
+        // Landlock domains can only be enforced by processes that cannot gain new privileges.
+        // SAFETY: Safe because the arguments are valid constants.
+        SyscallReturnCode(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })
+            .into_empty_result()
+            .map_err(LandlockError::NoNewPrivs)?;
+
+        // SAFETY: Safe because `ruleset` is a valid Landlock ruleset fd.
+        SyscallReturnCode(unsafe {
+            libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset.as_raw_fd(), 0u32)
+        })
+        .into_empty_result()
+        .map_err(LandlockError::RestrictSelf)?;
+
+        restrict_registered_threads(&ruleset).map_err(LandlockError::RestrictThreads)
+    }
+}
+
+// Signals the registered threads to enforce `ruleset` on themselves, and waits for all of them to
+// do so. The registrations are locked meanwhile, so that none of the threads goes away.
+fn restrict_registered_threads(ruleset: &File) -> io::Result<()> {
+    let threads = REGISTERED_THREADS.lock().expect("Poisoned lock");
+    if threads.is_empty() {
+        return Ok(());
+    }
+
+    THREAD_RULESET_FD.store(ruleset.as_raw_fd(), Ordering::Release);
+    RESTRICTED_THREADS.store(0, Ordering::Release);
+    FAILED_THREADS.store(0, Ordering::Release);
+    let signal = sigrtmin() + LANDLOCK_RTSIG_OFFSET;
+    register_restrict_handler(signal)?;
+    for thread in threads.iter() {
+        // SAFETY: Safe because the registered threads are alive until they unregister, which the
+        // lock prevents.
+        let ret = unsafe { libc::pthread_kill(*thread, signal) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+    }
+
+    let deadline = Instant::now() + REGISTERED_THREADS_TIMEOUT;
+    let result = loop {
+        let restricted = RESTRICTED_THREADS.load(Ordering::Acquire);
+        let failed = FAILED_THREADS.load(Ordering::Acquire);
+        if failed > 0 {
+            break Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} threads failed to enforce it", failed),
+            ));
+        }
+        if restricted == threads.len() {
+            break Ok(());
+        }
+        if Instant::now() >= deadline {
+            break Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    THREAD_RULESET_FD.store(-1, Ordering::Release);
+    result
+}
+
+// The handler is registered with SA_RESTART, so that the system calls the registered threads are
+// blocked in are resumed once they enforced the ruleset.
+fn register_restrict_handler(signal: c_int) -> io::Result<()> {
+    // SAFETY: Safe because an all-zero sigaction is valid, and the used fields are set below.
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = restrict_thread_handler as libc::sighandler_t;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+    // SAFETY: Safe because `action` is a valid sigaction, whose handler is async-signal-safe.
+    SyscallReturnCode(unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) })
+        .into_empty_result()
+}
+
+extern "C" fn restrict_thread_handler(_num: c_int, _info: *mut siginfo_t, _unused: *mut c_void) {
+    // The handler only makes system calls and updates atomics, which are async-signal-safe. It
+    // preserves errno for the code it interrupts.
+    // SAFETY: Safe because errno is a thread local variable.
+    let errno = unsafe { *libc::__errno_location() };
+    let ruleset_fd = THREAD_RULESET_FD.load(Ordering::Acquire);
+    // SAFETY: Safe because the arguments are valid constants, and the ruleset fd is kept open
+    // until all the registered threads are done.
+    let restricted = unsafe {
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0
+            && libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset_fd, 0u32) == 0
+    };
+    if restricted {
+        RESTRICTED_THREADS.fetch_add(1, Ordering::AcqRel);
+    } else {
+        FAILED_THREADS.fetch_add(1, Ordering::AcqRel);
+    }
+    // SAFETY: Safe because errno is a thread local variable.
+    unsafe { *libc::__errno_location() = errno };
+}
+
+fn add_path_rule(
+    ruleset: &File,
+    path: &Path,
+    read_only: bool,
+    tree: bool,
+) -> Result<(), LandlockError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+        .open(path)
+        .map_err(|err| LandlockError::OpenPath(path.to_path_buf(), err))?;
+    let is_dir = file
+        .metadata()
+        .map_err(|err| LandlockError::OpenPath(path.to_path_buf(), err))?
+        .is_dir();
+
+    // Rules on files may only grant the rights that apply to files.
+    let mut allowed_access = match (is_dir, read_only) {
+        (false, true) => ACCESS_FS_READ_FILE,
+        (false, false) => ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE,
+        (true, true) => ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+        (true, false) => {
+            ACCESS_FS_READ_FILE
+                | ACCESS_FS_WRITE_FILE
+                | ACCESS_FS_READ_DIR
+                | ACCESS_FS_REMOVE_FILE
+                | ACCESS_FS_MAKE_REG
+        }
+    };
+    if is_dir && tree && !read_only {
+        allowed_access |= ACCESS_FS_REMOVE_DIR | ACCESS_FS_MAKE_DIR | ACCESS_FS_MAKE_SYM;
+    }
+    let attr = LandlockPathBeneathAttr {
+        allowed_access,
+        parent_fd: file.as_raw_fd(),
+    };
+
+    // SAFETY: Safe because `ruleset` is a valid Landlock ruleset fd and `attr` is a valid
+    // path beneath attribute.
+    SyscallReturnCode(unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const LandlockPathBeneathAttr,
+            0u32,
+        )
+    })
+    .into_empty_result()
+    .map_err(|err| LandlockError::AddRule(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempdir::TempDir;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    // The rulesets are enforced on the registered threads of all the tests.
+    static RESTRICT_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_add_path_rule_missing_path() {
+        let mut ruleset = LandlockRuleset::default();
+        ruleset.allow("/this/path/does/not/exist", true);
+        // The ruleset fails either on the missing path or on a host without Landlock.
+        assert!(matches!(
+            ruleset.restrict_self(),
+            Err(LandlockError::OpenPath(_, _)) | Err(LandlockError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_allow_tree() {
+        let dir = TempDir::new().unwrap();
+        let mut ruleset = LandlockRuleset::default();
+        ruleset.allow_tree(dir.as_path(), false);
+
+        let new_dir = dir.as_path().join("new_dir");
+        let new_link = dir.as_path().join("new_link");
+        let _lock = RESTRICT_LOCK.lock().unwrap();
+        std::thread::spawn(move || {
+            match ruleset.restrict_self() {
+                Err(LandlockError::NotSupported(_)) => return,
+                res => res.unwrap(),
+            }
+            std::fs::create_dir(&new_dir).unwrap();
+            std::os::unix::fs::symlink("new_dir", &new_link).unwrap();
+            std::fs::remove_file(&new_link).unwrap();
+            std::fs::remove_dir(&new_dir).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_restrict_self() {
+        let dir = TempDir::new().unwrap();
+        let allowed = TempFile::new_with_prefix(dir.as_path().join("allowed")).unwrap();
+        let denied = TempFile::new().unwrap();
+
+        let mut ruleset = LandlockRuleset::default();
+        ruleset.allow(allowed.as_path(), true);
+        ruleset.allow(dir.as_path(), false);
+
+        // Restrict a separate thread, so that the test harness keeps its filesystem access.
+        let allowed_path = allowed.as_path().to_path_buf();
+        let denied_path = denied.as_path().to_path_buf();
+        let new_file = dir.as_path().join("new_file");
+        let _lock = RESTRICT_LOCK.lock().unwrap();
+        std::thread::spawn(move || {
+            match ruleset.restrict_self() {
+                Err(LandlockError::NotSupported(_)) => return,
+                res => res.unwrap(),
+            }
+            assert!(File::open(&allowed_path).is_ok());
+            assert!(File::create(&new_file).is_ok());
+            assert_eq!(
+                std::fs::create_dir(new_file.with_file_name("new_dir"))
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EACCES)
+            );
+            assert_eq!(
+                File::open(&denied_path).unwrap_err().raw_os_error(),
+                Some(libc::EACCES)
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_restrict_registered_threads() {
+        let allowed = TempFile::new().unwrap();
+        let denied = TempFile::new().unwrap();
+        let mut ruleset = LandlockRuleset::default();
+        ruleset.allow(allowed.as_path(), true);
+
+        // The registered thread is started before the ruleset is enforced by another thread.
+        let _lock = RESTRICT_LOCK.lock().unwrap();
+        let (registered_sender, registered_receiver) = std::sync::mpsc::channel();
+        let (restricted_sender, restricted_receiver) = std::sync::mpsc::channel::<bool>();
+        let allowed_path = allowed.as_path().to_path_buf();
+        let denied_path = denied.as_path().to_path_buf();
+        let registered = std::thread::spawn(move || {
+            let _registration = register_thread();
+            registered_sender.send(()).unwrap();
+            if restricted_receiver.recv().unwrap() {
+                assert!(File::open(&allowed_path).is_ok());
+                assert_eq!(
+                    File::open(&denied_path).unwrap_err().raw_os_error(),
+                    Some(libc::EACCES)
+                );
+            }
+        });
+        registered_receiver.recv().unwrap();
+
+        let restricted = std::thread::spawn(move || match ruleset.restrict_self() {
+            Err(LandlockError::NotSupported(_)) => false,
+            res => {
+                res.unwrap();
+                true
+            }
+        })
+        .join()
+        .unwrap();
+        restricted_sender.send(restricted).unwrap();
+        registered.join().unwrap();
+    }
+}
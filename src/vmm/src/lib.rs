@@ -75,12 +75,38 @@ pub mod rate_limiter;
 
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
+/// CPU bandwidth control through the cgroup of the microVM.
+pub mod cgroup;
+/// Launch flow of confidential microVMs.
+#[cfg(target_arch = "x86_64")]
+pub mod confidential;
+/// Guest memory dumps in the ELF core format.
+pub mod coredump;
 /// Types for guest configuration.
 pub mod cpu_config;
 pub(crate) mod device_manager;
 /// Emulates virtual and hardware devices.
 #[allow(missing_docs)]
 pub mod devices;
+/// GDB remote serial protocol stub to debug the guest.
+#[cfg(target_arch = "x86_64")]
+pub mod gdb;
+/// Copy of files from and to the guest through a vsock agent.
+pub mod guest_files;
+/// Freeze of the guest filesystems through a vsock agent, before the snapshots.
+pub mod guest_freeze;
+/// Guest memory of the restored microVMs fetched on demand from an HTTP server.
+pub mod http_memory;
+/// Identity of the microVM exposed to the guest.
+pub mod identity;
+/// I/O threads processing the events of the block and network devices.
+pub mod io_threads;
+/// Landlock based sandboxing of the VMM filesystem access.
+pub mod landlock;
+/// Locking of the guest memory in host memory.
+pub mod memory_lock;
+/// Reaction to the memory pressure of the host.
+pub mod memory_pressure;
 pub mod memory_snapshot;
 /// Save/restore utilities.
 pub mod persist;
@@ -90,8 +116,14 @@ pub mod resources;
 pub mod rpc_interface;
 /// Seccomp filter utilities.
 pub mod seccomp_filters;
+/// Cleanup actions performed when Firecracker exits.
+pub mod shutdown_hooks;
 /// Signal handling utilities.
 pub mod signal_handler;
+/// Manifests listing the files of the layered snapshots.
+pub mod snapshot_manifest;
+/// Detection of the vCPUs which stop making progress.
+pub mod stall_detector;
 /// Utility functions for integration and benchmark testing
 pub mod utilities;
 /// microVM state versions.
@@ -100,10 +132,14 @@ pub mod version_map;
 pub mod vmm_config;
 /// Module with virtual state structs.
 pub mod vstate;
+/// Estimation of the guest working set.
+pub mod working_set;
 
 use std::collections::HashMap;
 use std::io;
 use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::Duration;
@@ -116,24 +152,44 @@ use userfaultfd::Uffd;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
-use utils::vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use utils::vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 use vstate::vcpu::{self, KvmVcpuConfigureError, StartThreadedError, VcpuSendEventError};
 
 use crate::arch::DeviceType;
+#[cfg(target_arch = "x86_64")]
+use crate::confidential::ConfidentialLaunch;
 use crate::cpu_config::templates::CpuConfiguration;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::devices::legacy::{IER_RDA_BIT, IER_RDA_OFFSET};
 use crate::devices::virtio::balloon::BalloonError;
+use crate::devices::virtio::mem::{VirtioMemStatus, MEM_DEV_ID};
+use crate::devices::virtio::net::capture::PacketCapture;
 use crate::devices::virtio::{
-    Balloon, BalloonConfig, BalloonStats, Block, Net, BALLOON_DEV_ID, TYPE_BALLOON, TYPE_BLOCK,
-    TYPE_NET,
+    Balloon, BalloonConfig, BalloonHintingStatus, BalloonStats, Block, Net, VirtioMem, Vsock,
+    VsockUnixBackend, BALLOON_DEV_ID, TYPE_9P, TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET,
+    TYPE_VSOCK, VSOCK_DEV_ID,
 };
+use crate::guest_files::{GuestFileChannel, GuestFilesError};
+use crate::guest_freeze::{GuestFreezeChannel, GuestFreezeError};
+use crate::io_threads::IoThreads;
+use crate::memory_lock::resident_ranges;
 use crate::memory_snapshot::SnapshotMemory;
-use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::persist::{
+    MicrovmState, MicrovmStateError, UffdDirtyBitmapError, UffdHandover, UffdHandoverError, VmInfo,
+};
 use crate::rate_limiter::BucketUpdate;
+use crate::vmm_config::balloon::BalloonConfigError;
+use crate::vmm_config::confidential::ConfidentialInfo;
+use crate::vmm_config::guest_files::GuestFilesConfig;
+use crate::vmm_config::guest_freeze::GuestFreezeConfig;
+use crate::vmm_config::health::{Health, HealthStatus, SubsystemHealth};
 use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::machine_config::MemLock;
+use crate::vmm_config::memory_hotplug::MemoryHotplugConfigError;
+use crate::vmm_config::DeviceRunState;
+use crate::vstate::dirty_ring::DirtyRings;
 use crate::vstate::vcpu::VcpuState;
 pub use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuEvent, VcpuHandle, VcpuResponse};
 pub use crate::vstate::vm::Vm;
@@ -174,6 +230,8 @@ pub enum FcExitCode {
     BadConfiguration = 152,
     /// Command line arguments parsing error.
     ArgParsing = 153,
+    /// Firecracker was shut down after suspending the microVM to disk.
+    Suspended = 158,
 }
 
 /// Timeout used in recv_timeout, when waiting for a vcpu response on
@@ -199,6 +257,9 @@ pub enum VmmError {
     /// Cannot fetch the KVM dirty bitmap.
     #[error("Error getting the KVM dirty bitmap. {0}")]
     DirtyBitmap(kvm_ioctls::Error),
+    /// Cannot set up the KVM dirty rings.
+    #[error("Cannot set up the KVM dirty rings: {0}")]
+    DirtyRings(io::Error),
     /// Cannot read from an Event file descriptor.
     #[error("Event fd error: {0}")]
     EventFd(io::Error),
@@ -227,6 +288,9 @@ pub enum VmmError {
     /// Cannot create Timer file descriptor.
     #[error("Error creating timer fd: {0}")]
     TimerFd(io::Error),
+    /// Cannot fetch the dirty pages tracked through UFFD.
+    #[error("Error getting the UFFD dirty bitmap: {0}")]
+    UffdDirtyBitmap(UffdDirtyBitmapError),
     /// Vcpu configuration error.
     #[error("Error configuring the vcpu for boot: {0}")]
     VcpuConfigure(KvmVcpuConfigureError),
@@ -341,12 +405,32 @@ pub struct Vmm {
     vm: Vm,
     guest_memory: GuestMemoryMmap,
     // Save UFFD in order to keep it open in the Firecracker process, as well.
-    // Since this field is never read again, we need to allow `dead_code`.
-    #[allow(dead_code)]
     uffd: Option<Uffd>,
+    // Used to hand the guest memory over to a new page fault handler.
+    uffd_handover: Option<UffdHandover>,
     vcpus_handles: Vec<VcpuHandle>,
+    // States of the vcpus left offline on restore, which no thread runs.
+    parked_vcpu_states: Vec<VcpuState>,
     // Used by Vcpus and devices to initiate teardown; Vmm should never write here.
     vcpus_exit_evt: EventFd,
+    // Pages read from the KVM dirty log by the working set sampler, kept for the next diff
+    // snapshot.
+    dirty_log_samples: Mutex<DirtyBitmap>,
+    // Used instead of the KVM dirty bitmap when the host supports them.
+    dirty_rings: Option<Arc<DirtyRings>>,
+    // Set by the boot timer device when the guest signals it is ready.
+    guest_ready: Arc<AtomicBool>,
+    // Part of the guest memory locked in host memory.
+    mem_lock: Option<MemLock>,
+    // Threads processing the events of the devices assigned to them.
+    io_threads: IoThreads,
+    // Access rules of the guest file copies, which go through the vsock device.
+    guest_files: Option<GuestFilesConfig>,
+    // Guest agent freezing the guest filesystems, reached through the vsock device.
+    guest_freeze: Option<GuestFreezeConfig>,
+    // Launch of the memory encryption of confidential microVMs.
+    #[cfg(target_arch = "x86_64")]
+    confidential: Option<ConfidentialLaunch>,
 
     // Guest VM devices.
     mmio_device_manager: MMIODeviceManager,
@@ -362,7 +446,93 @@ impl Vmm {
 
     /// Gets Vmm instance info.
     pub fn instance_info(&self) -> InstanceInfo {
-        self.instance_info.clone()
+        let mut instance_info = self.instance_info.clone();
+        if instance_info.state == VmState::Running && self.guest_ready.load(Ordering::Relaxed) {
+            instance_info.state = VmState::GuestReady;
+        }
+        instance_info
+    }
+
+    /// Reports the health of the subsystems of the microVM.
+    pub fn health(&self) -> Health {
+        Health::new(
+            self.vcpus_health(),
+            self.devices_health(),
+            persist::uffd_handler_health(self),
+            SubsystemHealth::metrics(),
+        )
+    }
+
+    fn vcpus_health(&self) -> SubsystemHealth {
+        let exited: Vec<String> = self
+            .vcpus_handles
+            .iter()
+            .enumerate()
+            .filter(|(_, handle)| !handle.is_running())
+            .map(|(idx, _)| idx.to_string())
+            .collect();
+        if !exited.is_empty() {
+            return SubsystemHealth::with_detail(
+                HealthStatus::Failed,
+                format!("Exited vCPUs: {}.", exited.join(", ")),
+            );
+        }
+        match self.instance_info.state {
+            VmState::NotStarted => SubsystemHealth::with_detail(
+                HealthStatus::Starting,
+                "The microVM is not started.".to_string(),
+            ),
+            VmState::Paused => SubsystemHealth::with_detail(
+                HealthStatus::Degraded,
+                "The microVM is paused.".to_string(),
+            ),
+            VmState::Running | VmState::GuestReady => SubsystemHealth::new(HealthStatus::Ok),
+        }
+    }
+
+    fn devices_health(&self) -> SubsystemHealth {
+        let mut not_activated = Vec::new();
+        let mut paused = Vec::new();
+        let _: Result<(), ()> =
+            self.mmio_device_manager
+                .for_each_virtio_device(|virtio_type, id, _info, device| {
+                    let device = device.lock().expect("Poisoned lock");
+                    if !device.is_activated() {
+                        not_activated.push(id.clone());
+                    }
+                    let is_paused = match virtio_type {
+                        TYPE_BLOCK => device
+                            .as_any()
+                            .downcast_ref::<Block>()
+                            .map_or(false, Block::is_paused),
+                        TYPE_NET => device
+                            .as_any()
+                            .downcast_ref::<Net>()
+                            .map_or(false, Net::is_paused),
+                        _ => false,
+                    };
+                    if is_paused {
+                        paused.push(id.clone());
+                    }
+                    Ok(())
+                });
+
+        if !paused.is_empty() {
+            SubsystemHealth::with_detail(
+                HealthStatus::Degraded,
+                format!("Paused devices: {}.", paused.join(", ")),
+            )
+        } else if !not_activated.is_empty() {
+            SubsystemHealth::with_detail(
+                HealthStatus::Starting,
+                format!(
+                    "Devices not activated by the guest: {}.",
+                    not_activated.join(", ")
+                ),
+            )
+        } else {
+            SubsystemHealth::new(HealthStatus::Ok)
+        }
     }
 
     /// Provides the Vmm shutdown exit code if there is one.
@@ -370,6 +540,17 @@ impl Vmm {
         self.shutdown_exit_code
     }
 
+    /// Returns the information about the launch of a confidential microVM, if it is one.
+    pub fn confidential_info(&self) -> Option<ConfidentialInfo> {
+        #[cfg(target_arch = "x86_64")]
+        return self
+            .confidential
+            .as_ref()
+            .and_then(ConfidentialLaunch::info);
+        #[cfg(target_arch = "aarch64")]
+        None
+    }
+
     /// Gets the specified bus device.
     pub fn get_bus_device(
         &self,
@@ -430,6 +611,7 @@ impl Vmm {
 
     /// Sends a resume command to the vCPUs.
     pub fn resume_vm(&mut self) -> Result<(), VmmError> {
+        self.mmio_device_manager.set_pause_lite(false);
         self.mmio_device_manager.kick_devices();
 
         // Send the events.
@@ -474,6 +656,15 @@ impl Vmm {
         Ok(())
     }
 
+    /// Pauses the vCPUs while the net and vsock devices keep serving the host: the pause
+    /// responders of the net devices answer on behalf of the guest, and the vsock connection
+    /// requests are queued until the guest runs again.
+    pub fn pause_vm_lite(&mut self) -> Result<(), VmmError> {
+        self.pause_vm()?;
+        self.mmio_device_manager.set_pause_lite(true);
+        Ok(())
+    }
+
     /// Returns a reference to the inner `GuestMemoryMmap` object.
     pub fn guest_memory(&self) -> &GuestMemoryMmap {
         &self.guest_memory
@@ -539,7 +730,32 @@ impl Vmm {
 
     /// Saves the state of a paused Microvm.
     pub fn save_state(&mut self, vm_info: &VmInfo) -> Result<MicrovmState, MicrovmStateError> {
-        use self::MicrovmStateError::SaveVmState;
+        use self::MicrovmStateError::{NotAllowed, SaveVmState};
+        // The guest memory and vCPU states are encrypted, and cannot be read back.
+        if self.confidential_info().is_some() {
+            return Err(NotAllowed(
+                "Snapshots are not supported for confidential microVMs".into(),
+            ));
+        }
+        // The state of the userspace IOAPIC is not part of the snapshots.
+        #[cfg(target_arch = "x86_64")]
+        if self.vm.split_irqchip() {
+            return Err(NotAllowed(
+                "Snapshots are not supported for microVMs with the split irqchip".into(),
+            ));
+        }
+        // The 9p server keeps its open files and fids outside of the device state, so they
+        // cannot be restored.
+        if self
+            .mmio_device_manager
+            .get_device_info()
+            .keys()
+            .any(|(device_type, _)| *device_type == DeviceType::Virtio(TYPE_9P))
+        {
+            return Err(NotAllowed(
+                "Snapshots are not supported for microVMs with shared directories".into(),
+            ));
+        }
         let vcpu_states = self.save_vcpu_states()?;
         let vm_state = {
             #[cfg(target_arch = "x86_64")]
@@ -553,12 +769,35 @@ impl Vmm {
                 self.vm.save_state(&mpidrs).map_err(SaveVmState)?
             }
         };
+        // The TPM is kept by swtpm, which persists it next to its own state.
+        if let Some(tpm) = self
+            .mmio_device_manager
+            .get_device(DeviceType::Tpm, &DeviceType::Tpm.to_string())
+        {
+            tpm.lock()
+                .expect("Poisoned lock")
+                .tpm_mut()
+                .expect("Unexpected device type")
+                .store_volatile()
+                .map_err(MicrovmStateError::StoreTpmState)?;
+        }
         let device_states = self.mmio_device_manager.save();
 
         let memory_state = self.guest_memory().describe();
+        // The guest memory locked on fault is locked upfront when the snapshot is loaded.
+        let hot_memory = match self.mem_lock {
+            Some(MemLock::Hot) => {
+                Some(resident_ranges(&self.guest_memory).map_err(MicrovmStateError::HotMemory)?)
+            }
+            _ => None,
+        };
 
         Ok(MicrovmState {
-            vm_info: vm_info.clone(),
+            vm_info: VmInfo {
+                guest_ready: self.guest_ready.load(Ordering::Relaxed),
+                hot_memory,
+                ..vm_info.clone()
+            },
             memory_state,
             vm_state,
             vcpu_states,
@@ -581,7 +820,7 @@ impl Vmm {
             .collect::<Result<Vec<VcpuResponse>, RecvTimeoutError>>()
             .map_err(|_| MicrovmStateError::UnexpectedVcpuResponse)?;
 
-        let vcpu_states = vcpu_responses
+        let mut vcpu_states = vcpu_responses
             .into_iter()
             .map(|response| match response {
                 VcpuResponse::SavedState(state) => Ok(*state),
@@ -591,6 +830,8 @@ impl Vmm {
             })
             .collect::<Result<Vec<VcpuState>, MicrovmStateError>>()?;
 
+        // The parked vcpus did not run since the restore, their states are unchanged.
+        vcpu_states.extend(self.parked_vcpu_states.iter().cloned());
         Ok(vcpu_states)
     }
 
@@ -622,8 +863,70 @@ impl Vmm {
         Ok(cpu_configs)
     }
 
-    /// Retrieves the KVM dirty bitmap for each of the guest's memory regions.
+    /// Retrieves the dirty bitmap for each of the guest's memory regions.
     pub fn get_dirty_bitmap(&self) -> Result<DirtyBitmap, VmmError> {
+        // When the guest memory is write-protected, the page fault handler tracks the pages
+        // written since the last call. KVM only logs the pages written by the guest, and is
+        // queried as well to reset its log.
+        let uffd_bitmap = persist::uffd_dirty_bitmap(self)
+            .transpose()
+            .map_err(VmmError::UffdDirtyBitmap)?;
+
+        let mut bitmap = self.read_dirty_log()?;
+
+        // The working set sampler resets the KVM log as well.
+        let samples = std::mem::take(&mut *self.dirty_log_samples.lock().expect("Poisoned lock"));
+        for (slot, sample) in samples {
+            if let Some(region_bitmap) = bitmap.get_mut(&slot) {
+                region_bitmap
+                    .iter_mut()
+                    .zip(sample)
+                    .for_each(|(word, sample_word)| *word |= sample_word);
+            }
+        }
+
+        if let Some(uffd_bitmap) = uffd_bitmap {
+            for (slot, uffd_region_bitmap) in uffd_bitmap {
+                if let Some(region_bitmap) = bitmap.get_mut(&slot) {
+                    region_bitmap
+                        .iter_mut()
+                        .zip(uffd_region_bitmap)
+                        .for_each(|(word, uffd_word)| *word |= uffd_word);
+                }
+            }
+        }
+        Ok(bitmap)
+    }
+
+    /// Counts the guest pages written since the last call, using the KVM dirty log.
+    ///
+    /// Reading the log resets it, so the pages are kept until the next call to
+    /// `get_dirty_bitmap`.
+    pub fn sample_dirty_pages(&self) -> Result<usize, VmmError> {
+        let mut samples = self.dirty_log_samples.lock().expect("Poisoned lock");
+        let mut pages = 0;
+        for (slot, bitmap) in self.read_dirty_log()? {
+            pages += bitmap
+                .iter()
+                .map(|word| word.count_ones() as usize)
+                .sum::<usize>();
+            let sample = samples.entry(slot).or_insert_with(|| vec![0; bitmap.len()]);
+            sample
+                .iter_mut()
+                .zip(bitmap)
+                .for_each(|(word, dirty_word)| *word |= dirty_word);
+        }
+        Ok(pages)
+    }
+
+    // Reads and resets the pages written by the guest, from the KVM dirty rings or bitmap.
+    fn read_dirty_log(&self) -> Result<DirtyBitmap, VmmError> {
+        if let Some(dirty_rings) = &self.dirty_rings {
+            return dirty_rings
+                .take_dirty_bitmap()
+                .map_err(VmmError::DirtyBitmap);
+        }
+
         let mut bitmap: DirtyBitmap = HashMap::new();
         self.guest_memory
             .iter()
@@ -683,6 +986,53 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Pauses or resumes the block device with id `drive_id`.
+    pub fn update_block_device_state(
+        &mut self,
+        drive_id: &str,
+        state: DeviceRunState,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                match state {
+                    DeviceRunState::Paused => block.pause(),
+                    DeviceRunState::Resumed => block.resume(),
+                }
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Completes the requests in flight of the block devices with ids `drive_ids`, or of all the
+    /// block devices if `None`, and flushes their backing files to the host storage.
+    pub fn flush_block_devices(&mut self, drive_ids: Option<&[String]>) -> Result<(), VmmError> {
+        let flush = |block: &mut Block| {
+            block
+                .flush()
+                .map_err(|err| format!("Cannot flush drive {}: {:?}", block.id(), err))
+        };
+        match drive_ids {
+            Some(drive_ids) => drive_ids.iter().try_for_each(|drive_id| {
+                self.mmio_device_manager
+                    .with_virtio_device_with_id(TYPE_BLOCK, drive_id, flush)
+            }),
+            None => self.mmio_device_manager.for_each_virtio_device(
+                |virtio_type, _id, _info, device| {
+                    if virtio_type != TYPE_BLOCK {
+                        return Ok(());
+                    }
+                    let mut device = device.lock().expect("Poisoned lock");
+                    let block = device
+                        .as_mut_any()
+                        .downcast_mut::<Block>()
+                        .ok_or(device_manager::mmio::MmioError::InvalidDeviceType)?;
+                    flush(block).map_err(device_manager::mmio::MmioError::InternalDeviceError)
+                },
+            ),
+        }
+        .map_err(VmmError::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for net device with `net_id` id.
     pub fn update_net_rate_limiters(
         &mut self,
@@ -700,6 +1050,82 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Replaces the packet capture of the net device with id `net_id`.
+    pub fn update_net_capture(
+        &mut self,
+        net_id: &str,
+        capture: Option<PacketCapture>,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.set_capture(capture);
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Pauses or resumes the net device with id `net_id`.
+    pub fn update_net_device_state(
+        &mut self,
+        net_id: &str,
+        state: DeviceRunState,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                match state {
+                    DeviceRunState::Paused => net.pause(),
+                    DeviceRunState::Resumed => net.resume(),
+                }
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Returns the channel copying files from and to the guest through the vsock device. The
+    /// socket path of the device is looked up on each call, since it can be updated at runtime.
+    pub fn guest_file_channel(&self) -> Result<GuestFileChannel, GuestFilesError> {
+        let config = self
+            .guest_files
+            .clone()
+            .ok_or(GuestFilesError::NotConfigured)?;
+        let uds_path = self
+            .vsock_uds_path()
+            .ok_or(GuestFilesError::VsockNotFound)?;
+        Ok(GuestFileChannel::new(config, uds_path))
+    }
+
+    /// Returns the channel freezing the guest filesystems through the vsock device.
+    pub fn guest_freeze_channel(&self) -> Result<GuestFreezeChannel, GuestFreezeError> {
+        let config = self
+            .guest_freeze
+            .clone()
+            .ok_or(GuestFreezeError::NotConfigured)?;
+        let uds_path = self
+            .vsock_uds_path()
+            .ok_or(GuestFreezeError::VsockNotFound)?;
+        Ok(GuestFreezeChannel::new(config, uds_path))
+    }
+
+    fn vsock_uds_path(&self) -> Option<String> {
+        let busdev = self.get_bus_device(DeviceType::Virtio(TYPE_VSOCK), VSOCK_DEV_ID)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let uds_path = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<Vsock<VsockUnixBackend>>()
+            .unwrap()
+            .backend()
+            .host_sock_path()
+            .to_string();
+        Some(uds_path)
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -752,11 +1178,43 @@ impl Vmm {
         }
     }
 
-    /// Updates configuration for the balloon device target size.
-    pub fn update_balloon_config(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
+    /// Returns the guest memory ranges, as (address, length in bytes) pairs, currently held by
+    /// the balloon device. The list is empty if there is no balloon device.
+    pub(crate) fn balloon_removed_ranges(&self) -> Vec<(GuestAddress, u64)> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .mmio_transport_ref()
+                .expect("Unexpected device type")
+                .device();
+
+            let ranges = virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .removed_ranges()
+                .collect();
+            ranges
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Hands the guest memory over to the page fault handler listening on `handler_path`, which
+    /// takes over serving the guest memory page faults from the current one.
+    pub fn handover_uffd_handler(&mut self, handler_path: &Path) -> Result<(), UffdHandoverError> {
+        persist::handover_uffd_handler(self, handler_path)
+    }
+
+    /// Updates configuration for the balloon device target size, in bytes.
+    pub fn update_balloon_config(&mut self, amount_bytes: u64) -> Result<(), BalloonError> {
         // The balloon cannot have a target size greater than the size of
         // the guest memory.
-        if u64::from(amount_mib) > mem_size_mib(self.guest_memory()) {
+        if amount_bytes > mem_size_mib(self.guest_memory()) << 20 {
             return Err(BalloonError::TooManyPagesRequested);
         }
 
@@ -776,7 +1234,7 @@ impl Vmm {
                     .as_mut_any()
                     .downcast_mut::<Balloon>()
                     .unwrap()
-                    .update_size(amount_mib)?;
+                    .update_size(amount_bytes)?;
 
                 Ok(())
             }
@@ -785,6 +1243,41 @@ impl Vmm {
         }
     }
 
+    /// Resizes the balloon, and the memory plugged through the memory hotplug device if present,
+    /// so that the guest can use `usable_bytes` bytes of memory.
+    ///
+    /// Memory beyond the boot memory is plugged block by block, so the balloon covers the part
+    /// of the last plugged block exceeding `usable_bytes`.
+    pub fn update_usable_memory(&mut self, usable_bytes: u64) -> Result<(), BalloonConfigError> {
+        let total_bytes = mem_size_mib(self.guest_memory()) << 20;
+        let (boot_bytes, plugged_bytes) = match self.memory_hotplug_status() {
+            Ok(status) => {
+                let hotplug_bytes = status.total_size_mib << 20;
+                let block_bytes = status.block_size_mib << 20;
+                let boot_bytes = total_bytes - hotplug_bytes;
+                let missing_bytes = usable_bytes.saturating_sub(boot_bytes);
+                let plugged_bytes = (missing_bytes + block_bytes - 1) / block_bytes * block_bytes;
+                if plugged_bytes > hotplug_bytes {
+                    return Err(BalloonConfigError::UsableMemoryTooLarge);
+                }
+                (boot_bytes, plugged_bytes)
+            }
+            Err(MemoryHotplugConfigError::DeviceNotFound) => (total_bytes, 0),
+            Err(err) => return Err(BalloonConfigError::MemoryHotplug(err)),
+        };
+        if usable_bytes > boot_bytes + plugged_bytes {
+            return Err(BalloonConfigError::UsableMemoryTooLarge);
+        }
+
+        // Make sure the balloon device exists before touching the hotplugged memory.
+        self.balloon_config()?;
+        if boot_bytes != total_bytes {
+            self.update_memory_hotplug_size(usize::try_from(plugged_bytes >> 20).unwrap())?;
+        }
+        self.update_balloon_config(boot_bytes + plugged_bytes - usable_bytes)?;
+        Ok(())
+    }
+
     /// Updates configuration for the balloon device as described in `balloon_stats_update`.
     pub fn update_balloon_stats_config(
         &mut self,
@@ -814,6 +1307,130 @@ impl Vmm {
         }
     }
 
+    /// Asks the guest to report its free pages to the balloon device, which discards them.
+    pub fn start_balloon_hinting(&mut self) -> Result<(), BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            {
+                let virtio_device = busdev
+                    .lock()
+                    .expect("Poisoned lock")
+                    .mmio_transport_ref()
+                    .expect("Unexpected device type")
+                    .device();
+
+                virtio_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_mut_any()
+                    .downcast_mut::<Balloon>()
+                    .unwrap()
+                    .start_free_page_hinting()?;
+            }
+            Ok(())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Hands the free pages reported to the balloon device back to the guest.
+    pub fn stop_balloon_hinting(&mut self) -> Result<(), BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            {
+                let virtio_device = busdev
+                    .lock()
+                    .expect("Poisoned lock")
+                    .mmio_transport_ref()
+                    .expect("Unexpected device type")
+                    .device();
+
+                virtio_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_mut_any()
+                    .downcast_mut::<Balloon>()
+                    .unwrap()
+                    .stop_free_page_hinting()?;
+            }
+            Ok(())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Returns the progress of the latest free page hinting run of the balloon device.
+    pub fn balloon_hinting_status(&self) -> Result<BalloonHintingStatus, BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .mmio_transport_ref()
+                .expect("Unexpected device type")
+                .device();
+
+            let status = virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .free_page_hinting_status()?;
+
+            Ok(status)
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Returns the current state of the memory hotplug device.
+    pub fn memory_hotplug_status(&self) -> Result<VirtioMemStatus, MemoryHotplugConfigError> {
+        let busdev = self
+            .get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .ok_or(MemoryHotplugConfigError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+
+        let status = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<VirtioMem>()
+            .unwrap()
+            .status();
+        Ok(status)
+    }
+
+    /// Asks the guest to plug `requested_size_mib` MiB of the memory hotplug region.
+    pub fn update_memory_hotplug_size(
+        &mut self,
+        requested_size_mib: usize,
+    ) -> Result<(), MemoryHotplugConfigError> {
+        let busdev = self
+            .get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .ok_or(MemoryHotplugConfigError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+
+        virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<VirtioMem>()
+            .unwrap()
+            .update_requested_size((requested_size_mib as u64) << 20)?;
+        Ok(())
+    }
+
     /// Signals Vmm to stop and exit.
     pub fn stop(&mut self, exit_code: FcExitCode) {
         // To avoid cycles, all teardown paths take the following route:
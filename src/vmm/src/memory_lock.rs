@@ -0,0 +1,276 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Locking of the guest memory in host memory.
+//!
+//! Locked guest memory is never swapped out, nor reclaimed under memory pressure, so the guest
+//! does not stall on the host page faults which would bring it back. It is also kept out of KSM,
+//! whose merged pages fault on write. With `MemLock::Hot`, the
+//! guest memory is locked when first touched (`MLOCK_ONFAULT`), and the pages resident when a
+//! snapshot is created are recorded in it, to be locked upfront when the snapshot is loaded.
+
+use std::io;
+
+use utils::get_page_size;
+use utils::vm_memory::{
+    GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, GuestMemoryRegion,
+};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use crate::vmm_config::machine_config::MemLock;
+
+// Not exported by the libc crate for musl.
+const MLOCK_ONFAULT: libc::c_ulong = 0x01;
+
+// Number of pages whose residency is queried at once, to bound the size of the `mincore` vector.
+const MINCORE_CHUNK_PAGES: usize = 1 << 18;
+
+/// Range of guest physical memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MemoryRange {
+    /// Guest physical address of the first byte of the range.
+    pub base_address: u64,
+    /// Size of the range, in bytes.
+    pub size: u64,
+}
+
+/// Errors associated with locking the guest memory.
+#[derive(Debug, thiserror::Error)]
+pub enum MemLockError {
+    /// The guest memory can be reclaimed by a balloon or a memory hotplug device, which is not
+    /// possible once it is locked.
+    #[error(
+        "The guest memory cannot be locked when a balloon or a memory hotplug device is used."
+    )]
+    ReclaimableMemory,
+    /// A hot range recorded in the snapshot is not in the guest memory.
+    #[error("Invalid hot memory range in the snapshot: {0}")]
+    HotMemory(GuestMemoryError),
+    /// Locking more memory would exceed the `RLIMIT_MEMLOCK` limit of the process.
+    #[error(
+        "Cannot lock {0} bytes of guest memory, the RLIMIT_MEMLOCK limit of the process is {1} \
+         bytes."
+    )]
+    Limit(u64, u64),
+    /// The limit of locked memory of the process cannot be read.
+    #[error("Cannot get the RLIMIT_MEMLOCK limit of the process: {0}")]
+    GetLimit(io::Error),
+    /// The guest memory cannot be locked.
+    #[error("Cannot lock the guest memory: {0}")]
+    Lock(io::Error),
+}
+
+/// Locks `guest_memory` in host memory as requested by `mem_lock`. With `MemLock::Hot`, the
+/// `hot_memory` ranges are populated and locked right away.
+pub fn lock_guest_memory(
+    guest_memory: &GuestMemoryMmap,
+    mem_lock: MemLock,
+    hot_memory: Option<&[MemoryRange]>,
+) -> Result<(), MemLockError> {
+    let total_size = guest_memory.iter().map(|region| region.len()).sum();
+    let flags = match mem_lock {
+        MemLock::All => 0,
+        MemLock::Hot => MLOCK_ONFAULT,
+    };
+    for region in guest_memory.iter() {
+        // Pages merged by KSM are shared with other processes, so writing them faults even when
+        // they are locked. This fails when the host kernel is built without KSM, which is fine.
+        // SAFETY: Opting out of KSM does not change the contents of the memory.
+        unsafe {
+            libc::madvise(
+                region.as_ptr().cast(),
+                region.len() as usize,
+                libc::MADV_UNMERGEABLE,
+            )
+        };
+        lock(region.as_ptr(), region.len() as usize, flags, total_size)?;
+    }
+
+    if mem_lock == MemLock::Hot {
+        for range in hot_memory.unwrap_or_default() {
+            let size = usize::try_from(range.size).map_err(|_| {
+                MemLockError::HotMemory(GuestMemoryError::InvalidGuestAddress(GuestAddress(
+                    range.base_address,
+                )))
+            })?;
+            let slice = guest_memory
+                .get_slice(GuestAddress(range.base_address), size)
+                .map_err(MemLockError::HotMemory)?;
+            // Locking the range again without `MLOCK_ONFAULT` populates it. The memory is only
+            // accounted once against the limit.
+            lock(slice.as_ptr(), slice.len(), 0, total_size)?;
+        }
+    }
+    Ok(())
+}
+
+fn lock(
+    addr: *const u8,
+    len: usize,
+    flags: libc::c_ulong,
+    total_size: u64,
+) -> Result<(), MemLockError> {
+    // SAFETY: Locking does not change the contents of the memory, and the range is mapped.
+    let ret = unsafe { libc::syscall(libc::SYS_mlock2, addr, len, flags) };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // `ENOMEM` is returned when the limit is exceeded, and `EPERM` when it is 0.
+        Some(libc::ENOMEM) | Some(libc::EPERM) => {
+            Err(MemLockError::Limit(total_size, memlock_limit()?))
+        }
+        _ => Err(MemLockError::Lock(err)),
+    }
+}
+
+fn memlock_limit() -> Result<u64, MemLockError> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `rlim` is a valid `rlimit` structure.
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) } < 0 {
+        return Err(MemLockError::GetLimit(io::Error::last_os_error()));
+    }
+    Ok(rlim.rlim_cur)
+}
+
+/// Returns the ranges of `guest_memory` resident in host memory, coalesced within each region.
+pub fn resident_ranges(guest_memory: &GuestMemoryMmap) -> io::Result<Vec<MemoryRange>> {
+    let page_size = get_page_size().map_err(|err| io::Error::from_raw_os_error(err.errno()))?;
+    let mut ranges: Vec<MemoryRange> = Vec::new();
+    let mut residency = Vec::new();
+
+    for region in guest_memory.iter() {
+        let region_ranges = ranges.len();
+        let region_pages = region.len() as usize / page_size;
+        let mut page = 0;
+        while page < region_pages {
+            let chunk_pages = (region_pages - page).min(MINCORE_CHUNK_PAGES);
+            residency.resize(chunk_pages, 0u8);
+            // SAFETY: The range is mapped, and `residency` holds a byte for each of its pages.
+            let ret = unsafe {
+                libc::mincore(
+                    region.as_ptr().add(page * page_size).cast(),
+                    chunk_pages * page_size,
+                    residency.as_mut_ptr(),
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            for (index, resident) in residency.iter().enumerate() {
+                if resident & 1 == 0 {
+                    continue;
+                }
+                let base_address = region.start_addr().0 + ((page + index) * page_size) as u64;
+                match ranges[region_ranges..].last_mut() {
+                    Some(last) if last.base_address + last.size == base_address => {
+                        last.size += page_size as u64;
+                    }
+                    _ => ranges.push(MemoryRange {
+                        base_address,
+                        size: page_size as u64,
+                    }),
+                }
+            }
+            page += chunk_pages;
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::vm_memory::{create_guest_memory, Bytes};
+
+    use super::*;
+
+    fn guest_memory() -> GuestMemoryMmap {
+        let page_size = get_page_size().unwrap();
+        create_guest_memory(
+            &[
+                (None, GuestAddress(0), 4 * page_size),
+                (None, GuestAddress(0x10_0000), 4 * page_size),
+            ],
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resident_ranges() {
+        let page_size = get_page_size().unwrap() as u64;
+        let guest_memory = guest_memory();
+        assert!(resident_ranges(&guest_memory).unwrap().is_empty());
+
+        // Adjacent pages are coalesced, but not across regions.
+        guest_memory
+            .write_obj(1u8, GuestAddress(page_size))
+            .unwrap();
+        guest_memory
+            .write_obj(1u8, GuestAddress(2 * page_size))
+            .unwrap();
+        guest_memory
+            .write_obj(1u8, GuestAddress(0x10_0000))
+            .unwrap();
+        assert_eq!(
+            resident_ranges(&guest_memory).unwrap(),
+            vec![
+                MemoryRange {
+                    base_address: page_size,
+                    size: 2 * page_size,
+                },
+                MemoryRange {
+                    base_address: 0x10_0000,
+                    size: page_size,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_guest_memory() {
+        let page_size = get_page_size().unwrap() as u64;
+        let guest_memory = guest_memory();
+        let hot_memory = [MemoryRange {
+            base_address: 0x10_0000 + page_size,
+            size: page_size,
+        }];
+
+        // The pages are only populated when they are locked without `MLOCK_ONFAULT`. The test
+        // locks a few pages, which fits in the default limit.
+        lock_guest_memory(&guest_memory, MemLock::Hot, Some(&hot_memory)).unwrap();
+        assert_eq!(resident_ranges(&guest_memory).unwrap(), hot_memory);
+
+        lock_guest_memory(&guest_memory, MemLock::All, None).unwrap();
+        assert_eq!(
+            resident_ranges(&guest_memory).unwrap(),
+            vec![
+                MemoryRange {
+                    base_address: 0,
+                    size: 4 * page_size,
+                },
+                MemoryRange {
+                    base_address: 0x10_0000,
+                    size: 4 * page_size,
+                },
+            ]
+        );
+
+        let invalid_memory = [MemoryRange {
+            base_address: 0x20_0000,
+            size: page_size,
+        }];
+        assert!(matches!(
+            lock_guest_memory(&guest_memory, MemLock::Hot, Some(&invalid_memory)),
+            Err(MemLockError::HotMemory(_))
+        ));
+    }
+}
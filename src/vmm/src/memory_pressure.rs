@@ -0,0 +1,321 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reaction to the memory pressure of the host.
+//!
+//! An out-of-band controller polling the memory usage of the host reacts in seconds, while the
+//! host OOM killer takes Firecracker down in milliseconds. The monitor is notified by the kernel
+//! instead, through a PSI trigger or the `memory.events` file of a cgroup, and relieves the host
+//! right away by inflating the balloon or by pausing the microVM. The action is undone once the
+//! pressure is gone for the recovery delay.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, io};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, info, warn, IncMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::memory_pressure::{
+    MemoryPressureAction, MemoryPressureConfig, MemoryPressureSource,
+};
+use crate::Vmm;
+
+// Counters of the `memory.events` file reporting that the cgroup hit its limits.
+const MEMORY_EVENTS_KEYS: [&str; 3] = ["high", "max", "oom"];
+
+/// Errors associated with the host memory pressure monitor.
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryPressureError {
+    /// Cannot open the memory pressure file.
+    #[error("Cannot open the memory pressure file: {0}")]
+    Open(io::Error),
+    /// Cannot register the PSI trigger.
+    #[error("Cannot register the PSI trigger: {0}")]
+    Trigger(io::Error),
+    /// Cannot create the recovery timer.
+    #[error("Cannot create the recovery timer: {0}")]
+    Timer(io::Error),
+    /// The balloon is inflated on pressure, but the microVM has no balloon device.
+    #[error("Inflating the balloon on memory pressure requires a balloon device.")]
+    BalloonNotFound,
+}
+
+// What the monitor did to relieve the host, to be undone on recovery.
+#[derive(Debug, Default, PartialEq, Eq)]
+enum Relief {
+    #[default]
+    None,
+    // The balloon was inflated from `initial_mib` to `target_mib`.
+    Balloon {
+        initial_mib: u32,
+        target_mib: u32,
+    },
+    Paused,
+}
+
+/// Relieves the host by inflating the balloon or by pausing the microVM when it is under memory
+/// pressure.
+pub struct MemoryPressureMonitor {
+    vmm: Arc<Mutex<Vmm>>,
+    config: MemoryPressureConfig,
+    file: File,
+    // Sum of the watched counters of the `memory.events` file at the last read.
+    last_events: u64,
+    recovery_timer: TimerFd,
+    relief: Relief,
+}
+
+impl fmt::Debug for MemoryPressureMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryPressureMonitor")
+            .field("config", &self.config)
+            .field("last_events", &self.last_events)
+            .field("relief", &self.relief)
+            .finish()
+    }
+}
+
+impl MemoryPressureMonitor {
+    /// Creates a monitor relieving the host on behalf of `vmm`.
+    pub fn new(
+        vmm: Arc<Mutex<Vmm>>,
+        config: MemoryPressureConfig,
+    ) -> Result<Self, MemoryPressureError> {
+        if config.action == MemoryPressureAction::InflateBalloon
+            && vmm.lock().expect("Poisoned lock").balloon_config().is_err()
+        {
+            return Err(MemoryPressureError::BalloonNotFound);
+        }
+
+        let (file, last_events) = match config.source {
+            MemoryPressureSource::Psi => {
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&config.path)
+                    .map_err(MemoryPressureError::Open)?;
+                // The kernel expects the trigger to be null-terminated.
+                let trigger = format!(
+                    "some {} {}\0",
+                    config.stall_threshold_ms * 1000,
+                    config.window_ms * 1000
+                );
+                file.write_all(trigger.as_bytes())
+                    .map_err(MemoryPressureError::Trigger)?;
+                (file, 0)
+            }
+            MemoryPressureSource::MemoryEvents => {
+                let file = File::open(&config.path).map_err(MemoryPressureError::Open)?;
+                let events = read_memory_events(&file).map_err(MemoryPressureError::Open)?;
+                (file, events)
+            }
+        };
+        let recovery_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .map_err(MemoryPressureError::Timer)?;
+
+        Ok(MemoryPressureMonitor {
+            vmm,
+            config,
+            file,
+            last_events,
+            recovery_timer,
+            relief: Relief::None,
+        })
+    }
+
+    fn on_pressure(&mut self) {
+        // The `memory.events` file also changes for the counters which are not watched.
+        if self.config.source == MemoryPressureSource::MemoryEvents {
+            match read_memory_events(&self.file) {
+                Ok(events) if events > self.last_events => self.last_events = events,
+                Ok(_) => return,
+                Err(err) => {
+                    error!("Failed to read the memory events of the host: {}", err);
+                    return;
+                }
+            }
+        }
+
+        METRICS.memory_pressure.events.inc();
+        warn!("The host is under memory pressure.");
+        self.relieve();
+        if let Some(recovery_ms) = self.config.recovery_ms {
+            self.recovery_timer.set_state(
+                TimerState::Oneshot(Duration::from_millis(recovery_ms)),
+                SetTimeFlags::Default,
+            );
+        }
+    }
+
+    fn relieve(&mut self) {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        match self.config.action {
+            MemoryPressureAction::InflateBalloon => {
+                let current_mib = match vmm.balloon_config() {
+                    Ok(config) => config.amount_mib,
+                    Err(err) => {
+                        METRICS.memory_pressure.failures.inc();
+                        error!("Failed to get the balloon target size: {:?}", err);
+                        return;
+                    }
+                };
+                let mem_size_mib =
+                    u32::try_from(crate::mem_size_mib(vmm.guest_memory())).unwrap_or(u32::MAX);
+                let max_mib = self
+                    .config
+                    .balloon_max_mib
+                    .map_or(mem_size_mib, |max_mib| max_mib.min(mem_size_mib));
+                let Some(target_mib) =
+                    next_balloon_target(current_mib, self.config.balloon_step_mib, max_mib)
+                else {
+                    return;
+                };
+                if let Err(err) = vmm.update_balloon_config(u64::from(target_mib) << 20) {
+                    METRICS.memory_pressure.failures.inc();
+                    error!("Failed to inflate the balloon: {:?}", err);
+                    return;
+                }
+                let initial_mib = match self.relief {
+                    Relief::Balloon { initial_mib, .. } => initial_mib,
+                    _ => current_mib,
+                };
+                self.relief = Relief::Balloon {
+                    initial_mib,
+                    target_mib,
+                };
+                METRICS.memory_pressure.balloon_inflations.inc();
+                warn!(
+                    "Inflated the balloon from {} MiB to {} MiB to relieve the host.",
+                    current_mib, target_mib
+                );
+            }
+            MemoryPressureAction::Pause => {
+                if vmm.instance_info.state != VmState::Running {
+                    return;
+                }
+                if let Err(err) = vmm.pause_vm_lite() {
+                    METRICS.memory_pressure.failures.inc();
+                    error!("Failed to pause the microVM: {}", err);
+                    return;
+                }
+                self.relief = Relief::Paused;
+                METRICS.memory_pressure.pauses.inc();
+                warn!("Paused the microVM to relieve the host.");
+            }
+        }
+    }
+
+    fn recover(&mut self) {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        // The action is not undone if the microVM was changed through the API in the meantime.
+        let res = match std::mem::take(&mut self.relief) {
+            Relief::None => return,
+            Relief::Balloon {
+                initial_mib,
+                target_mib,
+            } => match vmm.balloon_config() {
+                Ok(config) if config.amount_mib == target_mib => {
+                    info!(
+                        "The host memory pressure is gone, deflating the balloon to {} MiB.",
+                        initial_mib
+                    );
+                    vmm.update_balloon_config(u64::from(initial_mib) << 20)
+                        .map_err(|err| format!("{:?}", err))
+                }
+                _ => return,
+            },
+            Relief::Paused if vmm.instance_info.state == VmState::Paused => {
+                info!("The host memory pressure is gone, resuming the microVM.");
+                vmm.resume_vm().map_err(|err| err.to_string())
+            }
+            Relief::Paused => return,
+        };
+        match res {
+            Ok(()) => METRICS.memory_pressure.recoveries.inc(),
+            Err(err) => {
+                METRICS.memory_pressure.failures.inc();
+                error!("Failed to undo the memory pressure action: {}", err);
+            }
+        }
+    }
+}
+
+// Target size of the balloon inflated by `step_mib` from `current_mib`, if it can still grow.
+fn next_balloon_target(current_mib: u32, step_mib: u32, max_mib: u32) -> Option<u32> {
+    let target_mib = current_mib.saturating_add(step_mib).min(max_mib);
+    (target_mib > current_mib).then_some(target_mib)
+}
+
+// Sums the watched counters of the `memory.events` file. The file is read from the start, which
+// also acknowledges its last change.
+fn read_memory_events(file: &File) -> io::Result<u64> {
+    let mut buf = [0u8; 512];
+    let len = file.read_at(&mut buf, 0)?;
+    Ok(parse_memory_events(&String::from_utf8_lossy(&buf[..len])))
+}
+
+fn parse_memory_events(contents: &str) -> u64 {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(key, _)| MEMORY_EVENTS_KEYS.contains(key))
+        .filter_map(|(_, value)| value.trim().parse::<u64>().ok())
+        .sum()
+}
+
+impl MutEventSubscriber for MemoryPressureMonitor {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        let source = event.fd();
+        if source == self.file.as_raw_fd() && event.event_set().contains(EventSet::PRIORITY) {
+            self.on_pressure();
+        } else if source == self.recovery_timer.as_raw_fd() && event.event_set() == EventSet::IN {
+            self.recovery_timer.read();
+            self.recover();
+        } else {
+            error!("Spurious EventManager event for handler: MemoryPressureMonitor");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.file, EventSet::PRIORITY)) {
+            error!("Failed to register the memory pressure event: {}", err);
+        }
+        if let Err(err) = ops.add(Events::new(&self.recovery_timer, EventSet::IN)) {
+            error!(
+                "Failed to register the memory pressure recovery timer: {}",
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_events() {
+        let contents = "low 7\nhigh 3\nmax 2\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(parse_memory_events(contents), 6);
+        assert_eq!(parse_memory_events("low 7\n"), 0);
+        assert_eq!(parse_memory_events(""), 0);
+    }
+
+    #[test]
+    fn test_next_balloon_target() {
+        assert_eq!(next_balloon_target(0, 64, 1024), Some(64));
+        assert_eq!(next_balloon_target(1000, 64, 1024), Some(1024));
+        assert_eq!(next_balloon_target(1024, 64, 1024), None);
+        // A balloon set beyond the maximum through the API is not deflated.
+        assert_eq!(next_balloon_target(2048, 64, 1024), None);
+    }
+}
@@ -4,11 +4,13 @@
 //! Defines functionality for creating guest memory snapshots.
 
 use std::fs::File;
-use std::io::SeekFrom;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
 
+use utils::seek_hole::SeekHole;
 use utils::vm_memory::{
     Bitmap, FileOffset, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
-    GuestMemoryRegion, MemoryRegionAddress, WriteVolatile,
+    GuestMemoryRegion, GuestRegionMmap, MemoryRegionAddress, ReadVolatile, WriteVolatile,
 };
 use utils::{errno, get_page_size};
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -28,6 +30,19 @@ pub struct GuestMemoryRegionState {
     pub size: usize,
     /// Offset in file/buffer where the region is saved.
     pub offset: u64,
+    /// Bitmap of the pages of the region which only held zeros when the snapshot was created,
+    /// one bit per page. Empty when the pages were not checked.
+    #[version(start = 2)]
+    pub zero_pages: Vec<u64>,
+}
+
+impl GuestMemoryRegionState {
+    /// Whether the page at index `page` of the region is known to only hold zeros.
+    pub fn is_zero_page(&self, page: usize) -> bool {
+        self.zero_pages
+            .get(page / 64)
+            .map_or(false, |word| (word >> (page % 64)) & 1 != 0)
+    }
 }
 
 /// Describes guest memory regions and their snapshot file mappings.
@@ -53,6 +68,21 @@ where
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> Result<(), SnapshotMemoryError>;
+    /// Dumps all pages of GuestMemoryMmap but the zero pages recorded in `state` to a writer.
+    fn dump_non_zero<T: WriteVolatile + std::io::Seek>(
+        &self,
+        writer: &mut T,
+        state: &GuestMemoryState,
+    ) -> Result<(), SnapshotMemoryError>;
+    /// Records in `state` the pages of GuestMemoryMmap which only hold zeros.
+    fn mark_zero_pages(&self, state: &mut GuestMemoryState) -> Result<(), SnapshotMemoryError>;
+    /// Overlays the pages written in the diff memory file `file` on the GuestMemoryMmap
+    /// restored from the previous memory files of a snapshot, described by `state`.
+    fn apply_diff_layer(
+        &self,
+        file: &mut File,
+        state: &GuestMemoryState,
+    ) -> Result<(), SnapshotMemoryError>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -80,6 +110,70 @@ pub enum SnapshotMemoryError {
     /// Cannot dump memory.
     #[error("Cannot dump memory: {0:?}")]
     WriteMemory(#[from] GuestMemoryError),
+    /// Cannot map the zero pages.
+    #[error("Cannot map the zero pages: {0}")]
+    MapZeroPages(std::io::Error),
+    /// Cannot map a diff memory file over guest memory.
+    #[error("Cannot map the diff memory file: {0}")]
+    MapDiffLayer(std::io::Error),
+    /// Cannot read a diff memory file into guest memory.
+    #[error("Cannot read the diff memory file: {0:?}")]
+    ReadDiffLayer(GuestMemoryError),
+}
+
+// Shortest run of zero pages mapped from anonymous memory instead of the memory file on restore.
+// Each run splits the mapping of its region, so the shorter runs are still read from the file to
+// keep the number of mappings bounded.
+const MIN_ZERO_PAGES_RUN_LEN: usize = 2 << 20;
+
+// Shortest run of pages of a diff memory file mapped over the guest memory on restore. The shorter
+// runs are copied into the guest memory instead, for the same reason as the zero pages.
+const MIN_DIFF_LAYER_RUN_LEN: u64 = 2 << 20;
+
+// Maps anonymous memory over the long runs of zero pages of a region mapped from the memory
+// file, so that the faults on these pages are served without reading the file.
+fn map_zero_pages(
+    region: &GuestRegionMmap,
+    state: &GuestMemoryRegionState,
+) -> Result<(), SnapshotMemoryError> {
+    let page_size = get_page_size()?;
+    let pages = region.len() as usize / page_size;
+
+    let mut page = 0;
+    while page < pages {
+        if !state.is_zero_page(page) {
+            page += 1;
+            continue;
+        }
+        let first_page = page;
+        while page < pages && state.is_zero_page(page) {
+            page += 1;
+        }
+
+        let len = (page - first_page) * page_size;
+        if len < MIN_ZERO_PAGES_RUN_LEN {
+            continue;
+        }
+        // SAFETY: The range is part of the guest memory region, which was just mapped and is not
+        // accessed yet. The same flags as the anonymous guest memory are used.
+        let ret = unsafe {
+            libc::mmap(
+                region.as_ptr().add(first_page * page_size).cast(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_FIXED | libc::MAP_NORESERVE | libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(SnapshotMemoryError::MapZeroPages(
+                std::io::Error::last_os_error(),
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 impl SnapshotMemory for GuestMemoryMmap {
@@ -92,6 +186,7 @@ impl SnapshotMemory for GuestMemoryMmap {
                 base_address: region.start_addr().0,
                 size: region.len() as usize,
                 offset,
+                zero_pages: Vec::new(),
             });
 
             offset += region.len();
@@ -167,8 +262,142 @@ impl SnapshotMemory for GuestMemoryMmap {
             .map_err(SnapshotMemoryError::WriteMemory)
     }
 
+    /// Dumps all pages of GuestMemoryMmap but the zero pages recorded in `state` to a writer.
+    fn dump_non_zero<T: WriteVolatile + std::io::Seek>(
+        &self,
+        writer: &mut T,
+        state: &GuestMemoryState,
+    ) -> Result<(), SnapshotMemoryError> {
+        let mut writer_offset = 0;
+        let page_size = get_page_size()?;
+
+        for (region, region_state) in self.iter().zip(state.regions.iter()) {
+            let pages = region.len() as usize / page_size;
+            let mut page = 0;
+
+            while page < pages {
+                if region_state.is_zero_page(page) {
+                    page += 1;
+                    continue;
+                }
+                let batch_start = page;
+                while page < pages && !region_state.is_zero_page(page) {
+                    page += 1;
+                }
+
+                // The writer is sized for the whole guest memory, the zero pages are left as
+                // holes.
+                writer.seek(SeekFrom::Start(
+                    writer_offset + (batch_start * page_size) as u64,
+                ))?;
+                writer
+                    .write_all_volatile(&region.get_slice(
+                        MemoryRegionAddress((batch_start * page_size) as u64),
+                        (page - batch_start) * page_size,
+                    )?)
+                    .map_err(GuestMemoryError::from)?;
+            }
+            writer_offset += region.len();
+        }
+
+        Ok(())
+    }
+
+    /// Records in `state` the pages of GuestMemoryMmap which only hold zeros.
+    fn mark_zero_pages(&self, state: &mut GuestMemoryState) -> Result<(), SnapshotMemoryError> {
+        let page_size = get_page_size()?;
+
+        for (region, region_state) in self.iter().zip(state.regions.iter_mut()) {
+            let pages = region.len() as usize / page_size;
+            let mut zero_pages = vec![0u64; (pages + 63) / 64];
+
+            for page in 0..pages {
+                let slice =
+                    region.get_slice(MemoryRegionAddress((page * page_size) as u64), page_size)?;
+                // SAFETY: The slice covers a whole page of guest memory, which is aligned for
+                // `u64`. The guest memory is not written while the snapshot is created.
+                let words = unsafe {
+                    std::slice::from_raw_parts(slice.as_ptr().cast::<u64>(), page_size / 8)
+                };
+                if words.iter().all(|word| *word == 0) {
+                    zero_pages[page / 64] |= 1 << (page % 64);
+                }
+            }
+
+            region_state.zero_pages = zero_pages;
+        }
+
+        Ok(())
+    }
+
+    /// Overlays the pages written in the diff memory file `file` on the GuestMemoryMmap.
+    fn apply_diff_layer(
+        &self,
+        file: &mut File,
+        state: &GuestMemoryState,
+    ) -> Result<(), SnapshotMemoryError> {
+        let page_size = get_page_size()? as u64;
+        let file_len = file.metadata()?.len();
+
+        for (region, region_state) in self.iter().zip(state.regions.iter()) {
+            let region_end = region_state.offset + region.len();
+            let mut cursor = region_state.offset;
+
+            // The diff memory files are sparse: only the dirty pages hold data.
+            while let Some(data_start) = file.seek_data(cursor)? {
+                if data_start >= region_end {
+                    break;
+                }
+                let data_end = file.seek_hole(data_start)?.unwrap_or(file_len);
+                // The pages are written whole, so the extents only get wider when aligned.
+                let start = data_start - data_start % page_size;
+                let end = ((data_end + page_size - 1) / page_size * page_size).min(region_end);
+                let region_offset = start - region_state.offset;
+                let len = end - start;
+
+                if len >= MIN_DIFF_LAYER_RUN_LEN {
+                    // SAFETY: The range is part of the guest memory region, which was just
+                    // restored and is not accessed yet. The same flags as the guest memory
+                    // mapped from the memory file are used.
+                    let ret = unsafe {
+                        libc::mmap(
+                            region.as_ptr().add(region_offset as usize).cast(),
+                            len as usize,
+                            libc::PROT_READ | libc::PROT_WRITE,
+                            libc::MAP_FIXED | libc::MAP_NORESERVE | libc::MAP_PRIVATE,
+                            file.as_raw_fd(),
+                            start as libc::off_t,
+                        )
+                    };
+                    if ret == libc::MAP_FAILED {
+                        return Err(SnapshotMemoryError::MapDiffLayer(
+                            std::io::Error::last_os_error(),
+                        ));
+                    }
+                } else {
+                    file.seek(SeekFrom::Start(start))?;
+                    file.read_exact_volatile(
+                        &mut region
+                            .get_slice(MemoryRegionAddress(region_offset), len as usize)
+                            .map_err(SnapshotMemoryError::ReadDiffLayer)?,
+                    )
+                    .map_err(|err| SnapshotMemoryError::ReadDiffLayer(err.into()))?;
+                }
+                cursor = end;
+            }
+
+            // The pages copied from the diff memory file were not written by the guest.
+            if let Some(bitmap) = region.bitmap() {
+                bitmap.reset();
+            }
+        }
+
+        Ok(())
+    }
+
     /// Creates a GuestMemoryMmap backed by a `file` if present, otherwise backed
     /// by anonymous memory. Memory layout and ranges are described in `state` param.
+    /// The long runs of pages known to only hold zeros are not mapped from `file`.
     fn restore(
         file: Option<&File>,
         state: &GuestMemoryState,
@@ -184,15 +413,23 @@ impl SnapshotMemory for GuestMemoryMmap {
             regions.push((f, GuestAddress(region.base_address), region.size));
         }
 
-        utils::vm_memory::create_guest_memory(&regions, track_dirty_pages)
-            .map_err(SnapshotMemoryError::CreateMemory)
+        let guest_memory = utils::vm_memory::create_guest_memory(&regions, track_dirty_pages)?;
+        // The anonymous memory already reads as zeros.
+        if file.is_some() {
+            for (region, region_state) in guest_memory.iter().zip(state.regions.iter()) {
+                map_zero_pages(region, region_state)?;
+            }
+        }
+
+        Ok(guest_memory)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use std::io::{Read, Seek};
+    use std::io::{Read, Seek, Write};
+    use std::os::unix::fs::FileExt;
 
     use utils::get_page_size;
     use utils::tempfile::TempFile;
@@ -217,11 +454,13 @@ mod tests {
                     base_address: 0,
                     size: page_size,
                     offset: 0,
+                    zero_pages: Vec::new(),
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 2,
                     size: page_size,
                     offset: page_size as u64,
+                    zero_pages: Vec::new(),
                 },
             ],
         };
@@ -242,11 +481,13 @@ mod tests {
                     base_address: 0,
                     size: page_size * 3,
                     offset: 0,
+                    zero_pages: Vec::new(),
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 4,
                     size: page_size * 3,
                     offset: page_size as u64 * 3,
+                    zero_pages: Vec::new(),
                 },
             ],
         };
@@ -370,4 +611,165 @@ mod tests {
             assert_eq!(expected_first_region, diff_file_content);
         }
     }
+
+    #[test]
+    fn test_zero_pages() {
+        let page_size: usize = get_page_size().unwrap();
+        let region_size = MIN_ZERO_PAGES_RUN_LEN + page_size * 4;
+        let mem_regions = [(None, GuestAddress(0), region_size)];
+        let guest_memory = utils::vm_memory::create_guest_memory(&mem_regions[..], false).unwrap();
+
+        // Pages: [ones, zeros, ones, zeros.., ones], with a run of zero pages long enough to be
+        // mapped from anonymous memory on restore.
+        let ones = vec![1u8; page_size];
+        let last_page = region_size / page_size - 1;
+        for page in [0, 2, last_page] {
+            guest_memory
+                .write(&ones[..], GuestAddress((page * page_size) as u64))
+                .unwrap();
+        }
+
+        let mut memory_state = guest_memory.describe();
+        guest_memory.mark_zero_pages(&mut memory_state).unwrap();
+        let region_state = &memory_state.regions[0];
+        for page in 0..=last_page {
+            assert_eq!(
+                region_state.is_zero_page(page),
+                ![0, 2, last_page].contains(&page)
+            );
+        }
+        assert!(!region_state.is_zero_page(last_page + 1));
+
+        // Fill the memory file with twos, so that the pages read from the file are told apart.
+        let mut memory_file = TempFile::new().unwrap().into_file();
+        memory_file.write_all(&vec![2u8; region_size]).unwrap();
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(Some(&memory_file), &memory_state, false).unwrap();
+
+        let mut actual_page = vec![0u8; page_size];
+        // The short run of zero pages is read from the file.
+        restored_guest_memory
+            .read(actual_page.as_mut_slice(), GuestAddress(page_size as u64))
+            .unwrap();
+        assert_eq!(actual_page, vec![2u8; page_size]);
+        // The long one is not.
+        for page in 3..last_page {
+            restored_guest_memory
+                .read(
+                    actual_page.as_mut_slice(),
+                    GuestAddress((page * page_size) as u64),
+                )
+                .unwrap();
+            assert_eq!(actual_page, vec![0u8; page_size]);
+        }
+        restored_guest_memory
+            .read(
+                actual_page.as_mut_slice(),
+                GuestAddress((last_page * page_size) as u64),
+            )
+            .unwrap();
+        assert_eq!(actual_page, vec![2u8; page_size]);
+
+        // Without the bitmap, the whole memory is read from the file.
+        memory_state.regions[0].zero_pages.clear();
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(Some(&memory_file), &memory_state, false).unwrap();
+        restored_guest_memory
+            .read(
+                actual_page.as_mut_slice(),
+                GuestAddress(page_size as u64 * 3),
+            )
+            .unwrap();
+        assert_eq!(actual_page, vec![2u8; page_size]);
+    }
+
+    #[test]
+    fn test_apply_diff_layer() {
+        let page_size: usize = get_page_size().unwrap();
+        let long_run_len = MIN_DIFF_LAYER_RUN_LEN as usize;
+        let region_size = long_run_len + page_size * 4;
+        let mem_regions = [(None, GuestAddress(0), region_size)];
+        let guest_memory = utils::vm_memory::create_guest_memory(&mem_regions[..], false).unwrap();
+        let memory_state = guest_memory.describe();
+
+        let mut base_file = TempFile::new().unwrap().into_file();
+        base_file.write_all(&vec![1u8; region_size]).unwrap();
+        // Pages: [holes, twos, holes, threes.., holes], with a run of dirty pages long enough to
+        // be mapped from the diff memory file.
+        let mut diff_file = TempFile::new().unwrap().into_file();
+        diff_file.set_len(region_size as u64).unwrap();
+        diff_file
+            .write_all_at(&vec![2u8; page_size], page_size as u64)
+            .unwrap();
+        diff_file
+            .write_all_at(&vec![3u8; long_run_len], page_size as u64 * 3)
+            .unwrap();
+
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(Some(&base_file), &memory_state, true).unwrap();
+        restored_guest_memory
+            .apply_diff_layer(&mut diff_file, &memory_state)
+            .unwrap();
+
+        let mut actual_page = vec![0u8; page_size];
+        let last_page = region_size / page_size - 1;
+        for page in 0..=last_page {
+            let expected_byte = match page {
+                1 => 2,
+                _ if (3..last_page).contains(&page) => 3,
+                _ => 1,
+            };
+            restored_guest_memory
+                .read(
+                    actual_page.as_mut_slice(),
+                    GuestAddress((page * page_size) as u64),
+                )
+                .unwrap();
+            assert_eq!(actual_page, vec![expected_byte; page_size], "page {}", page);
+        }
+        // The pages of the diff memory file are not dirty.
+        let region = restored_guest_memory.iter().next().unwrap();
+        assert!(!region.bitmap().dirty_at(page_size));
+    }
+
+    #[test]
+    fn test_dump_non_zero() {
+        let page_size: usize = get_page_size().unwrap();
+        let mem_regions = [
+            (None, GuestAddress(0), page_size * 3),
+            (None, GuestAddress(page_size as u64 * 4), page_size * 2),
+        ];
+        let guest_memory = utils::vm_memory::create_guest_memory(&mem_regions[..], false).unwrap();
+
+        // Pages: [ones, zeros, ones] and [zeros, ones].
+        let ones = vec![1u8; page_size];
+        for addr in [0, page_size * 2, page_size * 5] {
+            guest_memory
+                .write(&ones[..], GuestAddress(addr as u64))
+                .unwrap();
+        }
+        let mut memory_state = guest_memory.describe();
+        guest_memory.mark_zero_pages(&mut memory_state).unwrap();
+
+        // Fill the memory file with twos, so that the pages left out are told apart.
+        let mut memory_file = TempFile::new().unwrap().into_file();
+        memory_file.write_all(&vec![2u8; page_size * 5]).unwrap();
+        guest_memory
+            .dump_non_zero(&mut memory_file, &memory_state)
+            .unwrap();
+
+        let twos = vec![2u8; page_size];
+        let expected_file_content = [
+            ones.as_slice(),
+            twos.as_slice(),
+            ones.as_slice(),
+            twos.as_slice(),
+            ones.as_slice(),
+        ]
+        .concat();
+        let mut file_content = Vec::new();
+        memory_file.seek(SeekFrom::Start(0)).unwrap();
+        memory_file.read_to_end(&mut file_content).unwrap();
+        assert_eq!(file_content, expected_file_content);
+    }
 }
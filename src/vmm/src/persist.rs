@@ -3,26 +3,44 @@
 
 //! Defines state structures for saving/restoring a Firecracker microVM.
 
+use std::any::TypeId;
 use std::fmt::Debug;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
-use std::os::unix::io::AsRawFd;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::net::UnixStream;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use event_manager::{EventOps, Events, MutEventSubscriber};
 use log::{error, info, warn};
-use seccompiler::BpfThreadMap;
+use logger::{IncMetric, METRICS};
+use mmds::data_store::Mmds;
+use seccompiler::{BpfProgram, BpfThreadMap};
 use semver::Version;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snapshot::Snapshot;
-use userfaultfd::{FeatureFlags, Uffd, UffdBuilder};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use userfaultfd::{FeatureFlags, RegisterMode, Uffd, UffdBuilder};
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+use utils::get_page_size;
 use utils::sock_ctrl_msg::ScmSocket;
-use utils::vm_memory::{GuestMemory, GuestMemoryMmap};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use utils::syscall::SyscallReturnCode;
+use utils::vm_memory::{
+    BitmapSlice, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+    VolatileMemoryError, VolatileSlice, WriteVolatile,
+};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::gic::GicState;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::vcpu::{get_manufacturer_id_from_host, get_manufacturer_id_from_state};
 use crate::builder::{self, BuildMicrovmFromSnapshotError};
@@ -31,24 +49,39 @@ use crate::cpu_config::templates::StaticCpuTemplate;
 use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 #[cfg(target_arch = "x86_64")]
 use crate::cpu_config::x86_64::cpuid::CpuidTrait;
-use crate::device_manager::persist::{DevicePersistError, DeviceStates};
-use crate::devices::virtio::TYPE_NET;
+use crate::device_manager::persist::{DevicePersistError, DeviceStates, MmdsVersionState};
+use crate::devices::virtio::balloon::persist::{
+    BalloonConfigSpaceState, BalloonState, BalloonStatsState,
+};
+use crate::devices::virtio::block::persist::{BlockState, CacheTypeState, FileEngineTypeState};
+use crate::devices::virtio::net::persist::{NetConfigSpaceState, NetState};
+use crate::devices::virtio::vsock::persist::{VsockBackendState, VsockUdsState};
+use crate::devices::virtio::{QueueState, TYPE_NET};
+use crate::http_memory::{guest_memory_from_http, HttpMemoryError};
+use crate::identity::publish_identity;
+use crate::io_threads::IoThreadError;
+use crate::memory_lock::MemoryRange;
 use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
 use crate::resources::VmResources;
-#[cfg(target_arch = "x86_64")]
-use crate::version_map::FC_V0_23_SNAP_VERSION;
 use crate::version_map::{
-    FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_V1_5_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION,
+    FC_MIN_TRANSLATED_SNAP_VERSION, FC_V0_24_SNAP_VERSION, FC_V0_25_SNAP_VERSION,
+    FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_V1_4_SNAP_VERSION, FC_V1_5_SNAP_VERSION,
+    FC_VERSION_TO_SNAP_VERSION,
 };
 use crate::vmm_config::boot_source::BootSourceConfig;
+use crate::vmm_config::health::{HealthStatus, SubsystemHealth};
+use crate::vmm_config::identity::{IdentityConfig, IdentityConfigError};
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS;
+use crate::vmm_config::machine_config::{MemLock, MmioLayout, MAX_SUPPORTED_VCPUS};
+use crate::vmm_config::smbios::SmbiosConfig;
 use crate::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType,
+    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType, SuspendToDiskParams,
+    UffdHandlerExitAction, UffdHandlerExitActionError,
 };
+use crate::vmm_config::vsock::{check_guest_cid, CidLock, VsockConfigError};
 use crate::vstate::vcpu::{VcpuSendEventError, VcpuState};
 use crate::vstate::vm::VmState;
-use crate::{mem_size_mib, memory_snapshot, vstate, EventManager, Vmm, VmmError};
+use crate::{mem_size_mib, memory_snapshot, vstate, DirtyBitmap, EventManager, Vmm, VmmError};
 
 #[cfg(target_arch = "x86_64")]
 const FC_V0_23_MAX_DEVICES: u32 = 11;
@@ -72,6 +105,27 @@ pub struct VmInfo {
     /// Boot source information.
     #[version(start = 2, default_fn = "def_boot_source", ser_fn = "ser_boot_source")]
     pub boot_source: BootSourceConfig,
+    /// Whether the guest signaled it is ready.
+    #[version(start = 3)]
+    pub guest_ready: bool,
+    /// SMBIOS configuration, holding the identification strings exposed to the guest.
+    #[version(start = 3, ser_fn = "ser_smbios")]
+    pub smbios: Option<SmbiosConfig>,
+    /// Identity configuration, holding the UUID exposed to the guest.
+    #[version(start = 3, ser_fn = "ser_identity")]
+    pub identity: Option<IdentityConfig>,
+    /// Layout of the MMIO devices, when it is not the default one.
+    #[version(start = 3, ser_fn = "ser_mmio_layout")]
+    pub mmio_layout: Option<MmioLayout>,
+    /// Part of the guest memory locked in host memory.
+    #[version(start = 3, ser_fn = "ser_mem_lock")]
+    pub mem_lock: Option<MemLock>,
+    /// Guest memory resident when the snapshot was created, locked upfront on restore.
+    #[version(start = 3, ser_fn = "ser_hot_memory")]
+    pub hot_memory: Option<Vec<MemoryRange>>,
+    /// Whether the ACPI tables are exposed to the guest.
+    #[version(start = 3, ser_fn = "ser_acpi")]
+    pub acpi: bool,
 }
 
 impl VmInfo {
@@ -107,6 +161,58 @@ impl VmInfo {
         warn!("Saving to older snapshot version, boot source information will not be saved.");
         Ok(())
     }
+
+    fn ser_smbios(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions do not include SMBIOS info.
+        if self.smbios.is_some() {
+            warn!("Saving to older snapshot version, SMBIOS information will not be saved.");
+        }
+        Ok(())
+    }
+
+    fn ser_identity(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions do not include identity info.
+        if self.identity.is_some() {
+            warn!("Saving to older snapshot version, identity information will not be saved.");
+        }
+        Ok(())
+    }
+
+    fn ser_mmio_layout(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions only support the default MMIO layout.
+        if self.mmio_layout.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not support persisting the MMIO layout.".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn ser_mem_lock(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions do not lock the guest memory.
+        if self.mem_lock.is_some() {
+            warn!("Saving to older snapshot version, the guest memory will not be locked.");
+        }
+        Ok(())
+    }
+
+    fn ser_hot_memory(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions do not include the hot memory.
+        if self.hot_memory.is_some() {
+            warn!("Saving to older snapshot version, the hot memory will not be saved.");
+        }
+        Ok(())
+    }
+
+    fn ser_acpi(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.4 and older versions do not expose ACPI tables to the guest.
+        if self.acpi {
+            return Err(VersionizeError::Semantic(
+                "Target version does not support ACPI.".to_owned(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl From<&VmResources> for VmInfo {
@@ -116,6 +222,13 @@ impl From<&VmResources> for VmInfo {
             smt: value.vm_config.smt,
             cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
             boot_source: value.boot_source_config().clone(),
+            guest_ready: false,
+            smbios: value.smbios.clone(),
+            identity: value.identity.clone(),
+            mmio_layout: value.vm_config.mmio_layout,
+            mem_lock: value.vm_config.mem_lock,
+            hot_memory: None,
+            acpi: value.vm_config.acpi,
         }
     }
 }
@@ -153,6 +266,26 @@ pub struct GuestRegionUffdMapping {
     pub size: usize,
     /// Offset in the backend file/buffer where the region contents are.
     pub offset: u64,
+    /// Index in the dirty page bitmap of the bit tracking the first page of the region.
+    /// Only set when the guest memory is write-protected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirty_bitmap_offset: Option<u64>,
+    /// Bitmap of the pages of the region which only held zeros when the snapshot was created,
+    /// one bit per page. The faults on these pages can be served without reading the backend.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub zero_pages: Vec<u64>,
+}
+
+/// This describes a range of guest memory that was held by the balloon device
+/// when the snapshot was taken. It is sent to the page fault handler after the
+/// region mappings, so that faults in this range are served with zeroed pages
+/// instead of the (stale) contents of the memory file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct GuestRegionUffdRemovedRange {
+    /// Base host virtual address of the removed range.
+    pub base_host_virt_addr: u64,
+    /// Range size.
+    pub size: u64,
 }
 
 /// Errors related to saving and restoring Microvm state.
@@ -182,6 +315,12 @@ pub enum MicrovmStateError {
     /// Failed to save VM state.
     #[error("Cannot save Vm state: {0:?}")]
     SaveVmState(vstate::vm::VmError),
+    /// Failed to get the guest memory resident in host memory.
+    #[error("Cannot get the resident guest memory: {0}")]
+    HotMemory(io::Error),
+    /// Failed to have swtpm store the volatile state of the TPM.
+    #[error("Cannot store the volatile state of the TPM: {0}")]
+    StoreTpmState(crate::devices::tpm::TpmError),
     /// Failed to send event.
     #[error("Cannot signal Vcpu: {0:?}")]
     SignalVcpu(VcpuSendEventError),
@@ -196,12 +335,15 @@ pub enum CreateSnapshotError {
     /// Failed to get dirty bitmap.
     #[error("Cannot get dirty bitmap: {0}")]
     DirtyBitmap(VmmError),
-    /// The virtio devices uses a features that is incompatible with older versions of Firecracker.
+    /// The microVM uses devices or features that the target version does not support.
     #[error(
-        "The virtio devices use a features that is incompatible with older versions of \
-         Firecracker: {0}"
+        "Cannot save the snapshot at Firecracker {0}, which does not support: {}.",
+        .1.join(", ")
     )]
-    IncompatibleVirtioFeature(&'static str),
+    IncompatibleVersion(String, Vec<String>),
+    /// The I/O threads could not be paused.
+    #[error("Cannot pause the I/O threads: {0}")]
+    IoThreads(IoThreadError),
     /// Invalid microVM version format
     #[error("Invalid microVM version format")]
     InvalidVersionFormat,
@@ -223,13 +365,15 @@ pub enum CreateSnapshotError {
     /// Failed to open the snapshot backing file.
     #[error("Cannot perform {0} on the snapshot backing file: {1}")]
     SnapshotBackingFile(&'static str, io::Error),
-    /// Number of devices exceeds the maximum supported devices for the snapshot data version.
-    #[cfg(target_arch = "x86_64")]
-    #[error(
-        "Too many devices attached: {0}. The maximum number allowed for the snapshot data version \
-         requested is {FC_V0_23_MAX_DEVICES}."
-    )]
-    TooManyDevices(usize),
+    /// Failed to write the suspend marker.
+    #[error("Cannot perform {0} on the suspend marker: {1}")]
+    SuspendMarker(&'static str, io::Error),
+    /// Failed to sync the directory of a snapshot file or of the suspend marker.
+    #[error("Cannot sync the directory of {0:?}: {1}")]
+    SyncDirectory(PathBuf, io::Error),
+    /// The snapshot was not created within the requested time.
+    #[error("The snapshot was not created within {0} ms.")]
+    TimedOut(u64),
 }
 
 /// Creates a Microvm snapshot.
@@ -239,14 +383,37 @@ pub fn create_snapshot(
     params: &CreateSnapshotParams,
     version_map: VersionMap,
 ) -> Result<(), CreateSnapshotError> {
+    let deadline = params.timeout_ms.map(|timeout_ms| {
+        (
+            timeout_ms,
+            Instant::now() + Duration::from_millis(timeout_ms),
+        )
+    });
+
     // Fail early from invalid target version.
-    let snapshot_data_version = get_snapshot_data_version(&params.version, &version_map, vmm)?;
+    let snapshot_data_version = get_snapshot_data_version(&params.version, &version_map)?;
 
-    let microvm_state = vmm
+    // The devices served by the I/O threads must not touch the guest memory between the saving
+    // of their state and the writing of the memory.
+    let _io_threads_pause = vmm
+        .io_threads
+        .pause()
+        .map_err(CreateSnapshotError::IoThreads)?;
+
+    let mut microvm_state = vmm
         .save_state(vm_info)
         .map_err(CreateSnapshotError::MicrovmState)?;
+    // The pages only holding zeros are recorded along with a full copy of the guest memory, so
+    // that they are neither written to the memory file nor read back from it on restore. This
+    // includes the free pages the guest reported to the balloon device.
+    if params.snapshot_type == SnapshotType::Full {
+        vmm.guest_memory()
+            .mark_zero_pages(&mut microvm_state.memory_state)
+            .map_err(CreateSnapshotError::Memory)?;
+        check_deadline(deadline)?;
+    }
 
-    extra_version_check(&microvm_state, snapshot_data_version)?;
+    check_snapshot_downgrade(vmm, &microvm_state, snapshot_data_version)?;
 
     snapshot_state_to_file(
         &microvm_state,
@@ -254,10 +421,63 @@ pub fn create_snapshot(
         snapshot_data_version,
         version_map,
     )?;
+    check_deadline(deadline)?;
+
+    snapshot_memory_to_file(
+        vmm,
+        &microvm_state.memory_state,
+        &params.mem_file_path,
+        &params.snapshot_type,
+        deadline.map(|(_, deadline)| deadline),
+    )
+    // Writing the memory is where most of the time goes, so an expired deadline is the likely
+    // cause of its failure.
+    .map_err(|err| check_deadline(deadline).err().unwrap_or(err))?;
+    check_deadline(deadline)
+}
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
+fn check_deadline(deadline: Option<(u64, Instant)>) -> Result<(), CreateSnapshotError> {
+    match deadline {
+        Some((timeout_ms, deadline)) if Instant::now() >= deadline => {
+            Err(CreateSnapshotError::TimedOut(timeout_ms))
+        }
+        _ => Ok(()),
+    }
+}
 
-    Ok(())
+// Largest amount of guest memory written at once, so that the deadline of the snapshot creation
+// is checked regularly.
+const MAX_MEMORY_WRITE_LEN: usize = 16 << 20;
+
+/// Writer of the guest memory which fails once its deadline is passed.
+#[derive(Debug)]
+struct DeadlineWriter<'a> {
+    file: &'a mut File,
+    deadline: Option<Instant>,
+}
+
+impl WriteVolatile for DeadlineWriter<'_> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        if self
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            return Err(VolatileMemoryError::IOError(io::Error::from(
+                io::ErrorKind::TimedOut,
+            )));
+        }
+        let len = buf.len().min(MAX_MEMORY_WRITE_LEN);
+        self.file.write_volatile(&buf.subslice(0, len)?)
+    }
+}
+
+impl Seek for DeadlineWriter<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
 }
 
 fn snapshot_state_to_file(
@@ -288,8 +508,10 @@ fn snapshot_state_to_file(
 
 fn snapshot_memory_to_file(
     vmm: &Vmm,
+    memory_state: &GuestMemoryState,
     mem_file_path: &Path,
     snapshot_type: &SnapshotType,
+    deadline: Option<Instant>,
 ) -> Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
     let mut file = OpenOptions::new()
@@ -304,14 +526,23 @@ fn snapshot_memory_to_file(
     file.set_len(mem_size_mib * 1024 * 1024)
         .map_err(|err| MemoryBackingFile("set_length", err))?;
 
+    let mut writer = DeadlineWriter {
+        file: &mut file,
+        deadline,
+    };
     match snapshot_type {
         SnapshotType::Diff => {
             let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
             vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
+                .dump_dirty(&mut writer, &dirty_bitmap)
                 .map_err(Memory)
         }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
+        // The file is sized for the whole guest memory, so the zero pages left out read as
+        // zeros.
+        SnapshotType::Full => vmm
+            .guest_memory()
+            .dump_non_zero(&mut writer, memory_state)
+            .map_err(Memory),
     }?;
     file.flush()
         .map_err(|err| MemoryBackingFile("flush", err))?;
@@ -319,63 +550,227 @@ fn snapshot_memory_to_file(
         .map_err(|err| MemoryBackingFile("sync_all", err))
 }
 
+/// Contents of the marker of a microVM suspended to disk.
+#[derive(Debug, Serialize)]
+struct SuspendMarker<'a> {
+    snapshot_type: &'a SnapshotType,
+    snapshot_path: &'a Path,
+    mem_file_path: &'a Path,
+    vmm_version: &'a str,
+}
+
+/// Writes the marker of a microVM suspended to disk, once its snapshot files are written. The
+/// marker is renamed in place after being synced, so it is never seen partially written, and its
+/// presence means that the snapshot files are durable.
+pub fn write_suspend_marker(
+    params: &SuspendToDiskParams,
+    vmm_version: &str,
+) -> Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    // The snapshot files are synced when written, only their directory entries are left.
+    sync_parent_dir(&params.snapshot_path)?;
+    sync_parent_dir(&params.mem_file_path)?;
+
+    let marker = SuspendMarker {
+        snapshot_type: &params.snapshot_type,
+        snapshot_path: &params.snapshot_path,
+        mem_file_path: &params.mem_file_path,
+        vmm_version,
+    };
+    let mut tmp_path = params.marker_path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .map_err(|err| SuspendMarker("open", err))?;
+    serde_json::to_writer(&mut file, &marker).map_err(|err| SuspendMarker("write", err.into()))?;
+    file.sync_all()
+        .map_err(|err| SuspendMarker("sync_all", err))?;
+    std::fs::rename(&tmp_path, &params.marker_path).map_err(|err| SuspendMarker("rename", err))?;
+    sync_parent_dir(&params.marker_path)
+}
+
+fn sync_parent_dir(path: &Path) -> Result<(), CreateSnapshotError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| CreateSnapshotError::SyncDirectory(dir.to_path_buf(), err))
+}
+
 /// Validate the microVM version and translate it to its corresponding snapshot data format.
 pub fn get_snapshot_data_version(
     maybe_fc_version: &Option<Version>,
     version_map: &VersionMap,
-    vmm: &Vmm,
 ) -> Result<u16, CreateSnapshotError> {
     let fc_version = match maybe_fc_version {
         None => return Ok(version_map.latest_version()),
         Some(version) => version,
     };
-    let data_version = *FC_VERSION_TO_SNAP_VERSION
+    FC_VERSION_TO_SNAP_VERSION
         .get(fc_version)
-        .ok_or(CreateSnapshotError::UnsupportedVersion)?;
-
-    #[cfg(target_arch = "x86_64")]
-    if data_version <= FC_V0_23_SNAP_VERSION {
-        validate_devices_number(vmm.mmio_device_manager.used_irqs_count())?;
-    }
-
-    if data_version < FC_V1_1_SNAP_VERSION {
-        vmm.mmio_device_manager
-            .for_each_virtio_device(|virtio_type, _id, _info, dev| {
-                // Incompatibility between current version and all versions smaller than 1.0.
-                // Also, incompatibility between v1.1 and v1.0 for VirtIO net device
-                if dev
-                    .lock()
-                    .expect("Poisoned lock")
-                    .has_feature(u64::from(VIRTIO_RING_F_EVENT_IDX))
-                    && (data_version < FC_V1_0_SNAP_VERSION || virtio_type == TYPE_NET)
-                {
-                    return Err(CreateSnapshotError::IncompatibleVirtioFeature(
-                        "notification suppression",
-                    ));
-                }
-                Ok(())
-            })?;
-    }
-
-    Ok(data_version)
+        .copied()
+        .ok_or(CreateSnapshotError::UnsupportedVersion)
 }
 
-/// Additional checks on snapshot version dependent on microvm saved state.
-pub fn extra_version_check(
+/// Checks that the microVM can be saved at snapshot data version `data_version` without losing
+/// state the restored microVM depends on.
+///
+/// The error lists every configured device and feature that the target version does not support,
+/// along with the Firecracker version which introduced it.
+pub fn check_snapshot_downgrade(
+    vmm: &Vmm,
     microvm_state: &MicrovmState,
-    version: u16,
+    data_version: u16,
 ) -> Result<(), CreateSnapshotError> {
-    // We forbid snapshots older than 1.5 if any additional capabilities are requested
-    if !microvm_state.vm_state.kvm_cap_modifiers.is_empty() && version < FC_V1_5_SNAP_VERSION {
-        return Err(CreateSnapshotError::UnsupportedVersion);
+    let mut unsupported = Vec::new();
+    let mut require = |since: u16, feature: String| {
+        if data_version < since {
+            unsupported.push(format!("{feature} (since {})", fc_version_name(since)));
+        }
+    };
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let device_count = vmm.mmio_device_manager.used_irqs_count();
+        if device_count > FC_V0_23_MAX_DEVICES as usize {
+            require(
+                FC_V0_24_SNAP_VERSION,
+                format!("{device_count} devices, more than {FC_V0_23_MAX_DEVICES}"),
+            );
+        }
     }
 
-    // We forbid snapshots older then 1.5 if any additional vcpu features are requested
+    vmm.mmio_device_manager
+        .for_each_virtio_device(|virtio_type, id, _info, dev| {
+            if dev
+                .lock()
+                .expect("Poisoned lock")
+                .has_feature(u64::from(VIRTIO_RING_F_EVENT_IDX))
+            {
+                // The net device only supports notification suppression since v1.1.
+                let since = match virtio_type {
+                    TYPE_NET => FC_V1_1_SNAP_VERSION,
+                    _ => FC_V1_0_SNAP_VERSION,
+                };
+                require(since, format!("notification suppression of device {id}"));
+            }
+            Ok::<(), CreateSnapshotError>(())
+        })?;
+
+    if !microvm_state.vm_state.kvm_cap_modifiers.is_empty() {
+        require(
+            FC_V1_5_SNAP_VERSION,
+            "KVM capabilities of the CPU template".to_string(),
+        );
+    }
     #[cfg(target_arch = "aarch64")]
-    if microvm_state.vcpu_states[0].kvi.is_some() && version < FC_V1_5_SNAP_VERSION {
-        return Err(CreateSnapshotError::UnsupportedVersion);
+    {
+        if microvm_state
+            .vcpu_states
+            .first()
+            .map_or(false, |vcpu_state| vcpu_state.kvi.is_some())
+        {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                "vCPU features of the CPU template".to_string(),
+            );
+        }
+        if microvm_state.vm_state.gic.its.is_some() {
+            require(FC_V1_5_SNAP_VERSION, "GIC ITS".to_string());
+        }
     }
-    Ok(())
+
+    let devices = &microvm_state.device_states;
+    for dev in devices.block_devices.iter() {
+        if dev.device_state.cache_type != CacheTypeState::Unsafe {
+            require(
+                FC_V0_25_SNAP_VERSION,
+                format!("cache type of block device {}", dev.device_id),
+            );
+        }
+        if dev.device_state.cache_type == CacheTypeState::Writethrough {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                format!("write-through cache of block device {}", dev.device_id),
+            );
+        }
+        if dev.device_state.direct_io {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                format!("direct I/O of block device {}", dev.device_id),
+            );
+        }
+        if dev.device_state.file_engine_type != FileEngineTypeState::Sync {
+            require(
+                FC_V1_0_SNAP_VERSION,
+                format!("io engine of block device {}", dev.device_id),
+            );
+        }
+        if !dev.device_state.in_flight_requests.is_empty() {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                format!("requests in flight of block device {}", dev.device_id),
+            );
+        }
+    }
+    for dev in devices.net_devices.iter() {
+        if dev.device_state.dhcp.is_some() {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                format!("DHCP responder of net device {}", dev.device_id),
+            );
+        }
+        if dev.device_state.user_net.is_some() {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                format!("user-mode network stack of net device {}", dev.device_id),
+            );
+        }
+    }
+    if let Some(dev) = &devices.vsock_device {
+        let VsockBackendState::Uds(uds_state) = &dev.device_state.backend;
+        if uds_state.clock_port.is_some() {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                "host clock port of the vsock device".to_string(),
+            );
+        }
+    }
+    if let Some(dev) = &devices.balloon_device {
+        require(FC_V0_24_SNAP_VERSION, "balloon device".to_string());
+        if !dev.device_state.removed_ranges.is_empty() {
+            require(
+                FC_V1_5_SNAP_VERSION,
+                "memory ranges removed by the balloon device".to_string(),
+            );
+        }
+    }
+    // The MMDS of older versions is restored as version 1.
+    if devices.mmds_version == Some(MmdsVersionState::V2) {
+        require(FC_V1_1_SNAP_VERSION, "MMDS version 2".to_string());
+    }
+    if devices.entropy_device.is_some() {
+        require(FC_V1_4_SNAP_VERSION, "entropy device".to_string());
+    }
+    if devices.memory_hotplug_device.is_some() {
+        require(FC_V1_5_SNAP_VERSION, "memory hotplug device".to_string());
+    }
+    if devices.tpm_device.is_some() {
+        require(FC_V1_5_SNAP_VERSION, "TPM device".to_string());
+    }
+
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    Err(CreateSnapshotError::IncompatibleVersion(
+        fc_version_name(data_version),
+        unsupported,
+    ))
 }
 
 /// Validates that snapshot CPU vendor matches the host CPU vendor.
@@ -484,6 +879,108 @@ pub fn snapshot_state_sanity_check(
     Ok(())
 }
 
+/// Error type for [`check_snapshot_translation`].
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SnapshotTranslationError {
+    /// The format of some of the state changed since the version of the snapshot.
+    #[error(
+        "Snapshots of Firecracker {0} are not translated to this version, only the ones of \
+         Firecracker {1} and newer. The format of this state changed since {0}: {}.",
+        .2.join(", ")
+    )]
+    Untranslatable(String, String, Vec<String>),
+}
+
+fn fc_version_name(data_version: u16) -> String {
+    FC_VERSION_TO_SNAP_VERSION
+        .iter()
+        .find(|(_, &version)| version == data_version)
+        .map_or_else(
+            || format!("snapshot data version {data_version}"),
+            |(fc_version, _)| format!("v{fc_version}"),
+        )
+}
+
+/// Checks that the state loaded from a snapshot of data version `source_version` was translated
+/// to the current version.
+///
+/// The snapshots of the Firecracker releases down to two minor versions older than this one are
+/// always translated. Older snapshots are only accepted if none of their state changed format
+/// since, and are otherwise rejected with the list of the VM state, vCPU states and devices that
+/// did.
+pub fn check_snapshot_translation(
+    microvm_state: &MicrovmState,
+    source_version: u16,
+    version_map: &VersionMap,
+) -> Result<(), SnapshotTranslationError> {
+    if source_version >= FC_MIN_TRANSLATED_SNAP_VERSION {
+        return Ok(());
+    }
+
+    let latest_version = version_map.latest_version();
+    let changed = |type_id: TypeId| {
+        version_map.get_type_version(source_version, type_id)
+            != version_map.get_type_version(latest_version, type_id)
+    };
+    let mut untranslatable = Vec::new();
+
+    #[cfg(target_arch = "aarch64")]
+    let vm_state_changed = changed(VmState::type_id()) || changed(GicState::type_id());
+    #[cfg(target_arch = "x86_64")]
+    let vm_state_changed = changed(VmState::type_id());
+    if vm_state_changed {
+        untranslatable.push("VM state".to_string());
+    }
+    if changed(VcpuState::type_id()) {
+        untranslatable.push("vCPU states".to_string());
+    }
+
+    // The queues of every virtio device are saved with the same format.
+    let queue_changed = changed(QueueState::type_id());
+    let devices = &microvm_state.device_states;
+    if queue_changed || changed(BlockState::type_id()) || changed(CacheTypeState::type_id()) {
+        for dev in devices.block_devices.iter() {
+            untranslatable.push(format!("block device {}", dev.device_id));
+        }
+    }
+    if queue_changed || changed(NetState::type_id()) || changed(NetConfigSpaceState::type_id()) {
+        for dev in devices.net_devices.iter() {
+            untranslatable.push(format!("net device {}", dev.device_id));
+        }
+    }
+    if queue_changed || changed(VsockUdsState::type_id()) {
+        if let Some(dev) = &devices.vsock_device {
+            untranslatable.push(format!("vsock device {}", dev.device_id));
+        }
+    }
+    if queue_changed
+        || changed(BalloonState::type_id())
+        || changed(BalloonStatsState::type_id())
+        || changed(BalloonConfigSpaceState::type_id())
+    {
+        if let Some(dev) = &devices.balloon_device {
+            untranslatable.push(format!("balloon device {}", dev.device_id));
+        }
+    }
+    if queue_changed {
+        if let Some(dev) = &devices.entropy_device {
+            untranslatable.push(format!("entropy device {}", dev.device_id));
+        }
+        if let Some(dev) = &devices.memory_hotplug_device {
+            untranslatable.push(format!("memory hotplug device {}", dev.device_id));
+        }
+    }
+
+    if untranslatable.is_empty() {
+        return Ok(());
+    }
+    Err(SnapshotTranslationError::Untranslatable(
+        fc_version_name(source_version),
+        fc_version_name(FC_MIN_TRANSLATED_SNAP_VERSION),
+        untranslatable,
+    ))
+}
+
 /// Error type for [`restore_from_snapshot`].
 #[derive(Debug, thiserror::Error)]
 pub enum RestoreFromSnapshotError {
@@ -493,12 +990,51 @@ pub enum RestoreFromSnapshotError {
     /// Invalid snapshot state.
     #[error("Invalid snapshot state: {0}")]
     Invalid(#[from] SnapShotStateSanityCheckError),
+    /// The snapshot state cannot be translated to the current version.
+    #[error("Cannot translate the snapshot state: {0}")]
+    Translation(#[from] SnapshotTranslationError),
     /// Failed to load guest memory
     #[error("Failed to load guest memory: {0}")]
     GuestMemory(#[from] RestoreFromSnapshotGuestMemoryError),
     /// Failed to build microVM from snapshot.
     #[error("Failed to build microVM from snapshot: {0}")]
     Build(#[from] BuildMicrovmFromSnapshotError),
+    /// The action taken if the page fault handler exits is invalid.
+    #[error("Invalid page fault handler exit action: {0}")]
+    HandlerExitAction(UffdHandlerExitActionError),
+    /// Failed to open the fallback memory file.
+    #[error("Failed to open the fallback memory file: {0}")]
+    FallbackMemFile(std::io::Error),
+    /// Failed to set up the readahead of the memory file.
+    #[error("Failed to set up the readahead of the memory file: {0}")]
+    Readahead(std::io::Error),
+    /// The vsock device is overridden, but the snapshot has none.
+    #[error("The snapshot has no vsock device to override.")]
+    NoVsockDevice,
+    /// Failed to assign the guest CID of the vsock device.
+    #[error("Failed to assign the guest CID: {0}")]
+    VsockCid(VsockConfigError),
+    /// MMDS contents or restore information are provided, but the snapshot has no MMDS.
+    #[error("The snapshot has no MMDS to populate.")]
+    NoMmds,
+    /// Failed to publish the restore information in MMDS.
+    #[error("Failed to publish the restore information in MMDS: {0}")]
+    RestoreInfo(mmds::data_store::Error),
+    /// Failed to renew the identity or to load its signing key.
+    #[error("{0}")]
+    Identity(IdentityConfigError),
+    /// Failed to publish the identity document in MMDS.
+    #[error("Failed to publish the identity document in MMDS: {0}")]
+    IdentityDocument(mmds::data_store::Error),
+    /// A network interface is overridden, but the snapshot has none with this id.
+    #[error("The snapshot has no network interface {0} to override.")]
+    UnknownNetworkInterface(String),
+    /// A drive is overridden, but the snapshot has none with this id.
+    #[error("The snapshot has no drive {0} to override.")]
+    UnknownDrive(String),
+    /// Failed to populate the MMDS data store.
+    #[error("Failed to populate the MMDS data store: {0}")]
+    Mmds(mmds::data_store::Error),
 }
 /// Sub-Error type for [`restore_from_snapshot`] to contain either [`GuestMemoryFromFileError`] or
 /// [`GuestMemoryFromUffdError`] within [`RestoreFromSnapshotError`].
@@ -510,6 +1046,9 @@ pub enum RestoreFromSnapshotGuestMemoryError {
     /// Error creating guest memory from uffd.
     #[error("Error creating guest memory from uffd: {0}")]
     Uffd(#[from] GuestMemoryFromUffdError),
+    /// Error creating guest memory fetched from an HTTP server.
+    #[error("Error creating guest memory fetched from an HTTP server: {0}")]
+    Http(#[from] HttpMemoryError),
 }
 
 /// Loads a Microvm snapshot producing a 'paused' Microvm.
@@ -521,42 +1060,281 @@ pub fn restore_from_snapshot(
     version_map: VersionMap,
     vm_resources: &mut VmResources,
 ) -> Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    // The API checks the exit action as well, but the VMM does not rely on it.
+    params
+        .mem_backend
+        .check_handler_exit_action()
+        .map_err(RestoreFromSnapshotError::HandlerExitAction)?;
+    let (mut microvm_state, source_version) =
+        snapshot_state_from_file(&params.snapshot_path, version_map.clone())?;
+    if source_version != version_map.latest_version() {
+        info!(
+            "Translating the snapshot of Firecracker {} to the current version.",
+            fc_version_name(source_version)
+        );
+    }
+    check_snapshot_translation(&microvm_state, source_version, &version_map)?;
 
     // Some sanity checks before building the microvm.
     snapshot_state_sanity_check(&microvm_state)?;
 
+    if let Some(cid_lock) = override_device_states(&mut microvm_state.device_states, params)? {
+        vm_resources.vsock.set_cid_lock(cid_lock);
+    }
+
+    // The restored microVM keeps the identity of the snapshotted one, unless another identity was
+    // configured before loading the snapshot or the UUID is renewed on every restore.
+    if vm_resources.identity.is_none() {
+        if let Some(mut identity) = microvm_state.vm_info.identity.clone() {
+            if identity.rotate_on_restore {
+                identity
+                    .generate_uuid()
+                    .map_err(RestoreFromSnapshotError::Identity)?;
+            }
+            vm_resources.identity = Some(identity);
+        }
+    }
+    // The signing key is loaded before the Landlock ruleset forbids reading it.
+    let signing_key = match &vm_resources.identity {
+        Some(identity) => identity
+            .signing_key()
+            .map_err(RestoreFromSnapshotError::Identity)?,
+        None => None,
+    };
+
     let mem_backend_path = &params.mem_backend.backend_path;
     let mem_state = &microvm_state.memory_state;
-    let track_dirty_pages = params.enable_diff_snapshots;
+    // With the write-protect mode, the dirty pages reported by the page fault handler are
+    // merged with the ones logged by KVM.
+    let track_dirty_pages = params.enable_diff_snapshots || params.mem_backend.write_protect;
+
+    // Open the fallback memory file before anything else, so that a wrong path is reported
+    // when loading the snapshot and not when the page fault handler exits.
+    let fallback_mem_file = params
+        .mem_backend
+        .fallback_mem_file_path
+        .as_ref()
+        .map(File::open)
+        .transpose()
+        .map_err(RestoreFromSnapshotError::FallbackMemFile)?;
+
+    let readahead = match params.mem_backend.readahead_mib_per_s {
+        Some(mib_per_s) if params.mem_backend.backend_type == MemBackendType::File => Some(
+            File::open(mem_backend_path)
+                .and_then(|mem_file| MemoryFileReadahead::new(mem_file, mib_per_s))
+                .map_err(RestoreFromSnapshotError::Readahead)?,
+        ),
+        _ => None,
+    };
 
-    let (guest_memory, uffd) = match params.mem_backend.backend_type {
+    // The threads serving the guest memory are started before the filters of the VMM thread
+    // forbid creating threads.
+    let vmm_filter = || {
+        seccomp_filters
+            .get("vmm")
+            .cloned()
+            .ok_or(RestoreFromSnapshotError::Build(
+                BuildMicrovmFromSnapshotError::MissingVmmSeccompFilters,
+            ))
+    };
+    let (guest_memory, uffd, uffd_handover, handler_monitor) = match params.mem_backend.backend_type
+    {
         MemBackendType::File => (
-            guest_memory_from_file(mem_backend_path, mem_state, track_dirty_pages)
-                .map_err(RestoreFromSnapshotGuestMemoryError::File)?,
+            guest_memory_from_file(
+                mem_backend_path,
+                &params.mem_diff_layers,
+                mem_state,
+                track_dirty_pages,
+            )
+            .map_err(RestoreFromSnapshotGuestMemoryError::File)?,
+            None,
+            None,
+            None,
+        ),
+        MemBackendType::Uffd => {
+            let fallback = match fallback_mem_file {
+                Some(mem_file) => Some((mem_file, vmm_filter()?)),
+                None => None,
+            };
+            guest_memory_from_uffd(
+                mem_backend_path,
+                mem_state,
+                track_dirty_pages,
+                // We enable the UFFD_FEATURE_EVENT_REMOVE feature only if a balloon device
+                // is present in the microVM state.
+                microvm_state
+                    .device_states
+                    .balloon_device
+                    .as_ref()
+                    .map(|balloon| &balloon.device_state),
+                params.mem_backend.handler_exit_action,
+                fallback,
+                params.mem_backend.write_protect,
+            )
+            .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?
+        }
+        MemBackendType::Http => (
+            guest_memory_from_http(
+                &mem_backend_path.to_string_lossy(),
+                params.mem_backend.cache_path.as_deref(),
+                mem_state,
+                track_dirty_pages,
+                vmm_filter()?,
+            )
+            .map_err(RestoreFromSnapshotGuestMemoryError::Http)?,
+            None,
+            None,
             None,
         ),
-        MemBackendType::Uffd => guest_memory_from_uffd(
-            mem_backend_path,
-            mem_state,
-            track_dirty_pages,
-            // We enable the UFFD_FEATURE_EVENT_REMOVE feature only if a balloon device
-            // is present in the microVM state.
-            microvm_state.device_states.balloon_device.is_some(),
-        )
-        .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?,
     };
-    builder::build_microvm_from_snapshot(
+    let vmm = builder::build_microvm_from_snapshot(
         instance_info,
         event_manager,
         microvm_state,
         guest_memory,
         uffd,
         track_dirty_pages,
+        params.vcpu_count,
         seccomp_filters,
         vm_resources,
     )
-    .map_err(RestoreFromSnapshotError::Build)
+    .map_err(RestoreFromSnapshotError::Build)?;
+    vmm.lock().expect("Poisoned lock").uffd_handover = uffd_handover;
+
+    if let Some(data) = &params.mmds {
+        vm_resources
+            .locked_mmds_or_default()
+            .put_data(serde_json::Value::Object(data.clone()))
+            .map_err(RestoreFromSnapshotError::Mmds)?;
+    }
+    if let Some(restore_info) = &params.restore_info {
+        publish_restore_info(&mut vm_resources.locked_mmds_or_default(), restore_info)
+            .map_err(RestoreFromSnapshotError::RestoreInfo)?;
+    }
+    // The identity document is issued after the MMDS contents are replaced, so that it is kept.
+    if let (Some(identity), Some(mmds)) = (&vm_resources.identity, &vm_resources.mmds) {
+        publish_identity(
+            &mut mmds.lock().expect("Poisoned lock"),
+            identity,
+            signing_key.as_ref(),
+            &instance_info.id,
+        )
+        .map_err(RestoreFromSnapshotError::IdentityDocument)?;
+    }
+
+    if let Some(mut handler_monitor) = handler_monitor {
+        handler_monitor.vmm = Some(vmm.clone());
+        event_manager.add_subscriber(Arc::new(Mutex::new(handler_monitor)));
+    }
+    if let Some(readahead) = readahead {
+        event_manager.add_subscriber(Arc::new(Mutex::new(readahead)));
+    }
+
+    Ok(vmm)
+}
+
+// Applies the device overrides of `params` to the snapshot state, before the devices are
+// restored. Returns the lock taken on the guest CID, if any.
+fn override_device_states(
+    device_states: &mut DeviceStates,
+    params: &LoadSnapshotParams,
+) -> Result<Option<CidLock>, RestoreFromSnapshotError> {
+    let mut cid_lock = None;
+    if let Some(vsock_override) = &params.vsock_override {
+        let vsock_state = device_states
+            .vsock_device
+            .as_mut()
+            .ok_or(RestoreFromSnapshotError::NoVsockDevice)?;
+        let backend_state = &mut vsock_state.device_state.backend;
+        // A deferred backend is restored unbound, which the empty path stands for.
+        if vsock_override.defer {
+            backend_state.set_uds_path(String::new());
+        } else if let Some(uds_path) = &vsock_override.uds_path {
+            backend_state.set_uds_path(uds_path.clone());
+        }
+
+        // The guest reads the new CID when it handles the transport reset event sent when the
+        // snapshot was taken.
+        let frontend_state = &mut vsock_state.device_state.frontend;
+        if let Some(dir) = &vsock_override.cid_lock_dir {
+            let lock = match vsock_override.guest_cid {
+                Some(cid) => CidLock::acquire(dir, Some(cid)),
+                // The clones of a microVM are restored with its CID, which only one of them keeps.
+                None => {
+                    let snapshot_cid = u32::try_from(frontend_state.cid).unwrap_or(u32::MAX);
+                    CidLock::acquire(dir, Some(snapshot_cid)).or_else(|err| match err {
+                        VsockConfigError::CidInUse(cid) => {
+                            warn!("The guest CID {} is used by another microVM.", cid);
+                            CidLock::acquire(dir, None)
+                        }
+                        err => Err(err),
+                    })
+                }
+            }
+            .map_err(RestoreFromSnapshotError::VsockCid)?;
+            frontend_state.cid = u64::from(lock.cid());
+            cid_lock = Some(lock);
+        } else if let Some(cid) = vsock_override.guest_cid {
+            check_guest_cid(cid).map_err(RestoreFromSnapshotError::VsockCid)?;
+            frontend_state.cid = u64::from(cid);
+        }
+    }
+
+    for net_override in &params.network_overrides {
+        let net_state = device_states
+            .net_devices
+            .iter_mut()
+            .find(|dev| dev.device_id == net_override.iface_id)
+            .ok_or_else(|| {
+                RestoreFromSnapshotError::UnknownNetworkInterface(net_override.iface_id.clone())
+            })?;
+        net_state
+            .device_state
+            .set_tap_if_name(net_override.host_dev_name.clone());
+    }
+
+    for drive_override in &params.drive_overrides {
+        let block_state = device_states
+            .block_devices
+            .iter_mut()
+            .find(|dev| dev.device_id == drive_override.drive_id)
+            .ok_or_else(|| {
+                RestoreFromSnapshotError::UnknownDrive(drive_override.drive_id.clone())
+            })?;
+        block_state
+            .device_state
+            .set_disk_path(drive_override.path_on_host.clone());
+    }
+
+    let has_mmds = device_states
+        .net_devices
+        .iter()
+        .any(|dev| dev.device_state.mmds_ns.is_some());
+    if (params.mmds.is_some() || params.restore_info.is_some()) && !has_mmds {
+        return Err(RestoreFromSnapshotError::NoMmds);
+    }
+
+    Ok(cid_lock)
+}
+
+/// MMDS key under which the restore information is published to the guest.
+pub const RESTORE_INFO_MMDS_KEY: &str = "restore_info";
+
+// Publishes the restore information under `RESTORE_INFO_MMDS_KEY`, replacing the one of a previous
+// restore. The rest of the data store is left untouched.
+pub(crate) fn publish_restore_info(
+    mmds: &mut Mmds,
+    restore_info: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(), mmds::data_store::Error> {
+    let mut data = match mmds.data_store_value() {
+        serde_json::Value::Object(data) => data,
+        _ => serde_json::Map::new(),
+    };
+    data.insert(
+        RESTORE_INFO_MMDS_KEY.to_string(),
+        serde_json::Value::Object(restore_info.clone()),
+    );
+    mmds.put_data(serde_json::Value::Object(data))
 }
 
 /// Error type for [`snapshot_state_from_file`]
@@ -576,14 +1354,13 @@ pub enum SnapshotStateFromFileError {
 fn snapshot_state_from_file(
     snapshot_path: &Path,
     version_map: VersionMap,
-) -> Result<MicrovmState, SnapshotStateFromFileError> {
+) -> Result<(MicrovmState, u16), SnapshotStateFromFileError> {
     let mut snapshot_reader =
         File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
     let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotStateFromFileError::Meta)?;
     let snapshot_len = metadata.len() as usize;
-    let (state, _) = Snapshot::load(&mut snapshot_reader, snapshot_len, version_map)
-        .map_err(SnapshotStateFromFileError::Load)?;
-    Ok(state)
+    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map)
+        .map_err(SnapshotStateFromFileError::Load)
 }
 
 /// Error type for [`guest_memory_from_file`].
@@ -599,11 +1376,16 @@ pub enum GuestMemoryFromFileError {
 
 fn guest_memory_from_file(
     mem_file_path: &Path,
+    diff_layer_paths: &[PathBuf],
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
 ) -> Result<GuestMemoryMmap, GuestMemoryFromFileError> {
     let mem_file = File::open(mem_file_path)?;
     let guest_mem = GuestMemoryMmap::restore(Some(&mem_file), mem_state, track_dirty_pages)?;
+    for diff_layer_path in diff_layer_paths {
+        let mut diff_layer = File::open(diff_layer_path)?;
+        guest_mem.apply_diff_layer(&mut diff_layer, mem_state)?;
+    }
     Ok(guest_mem)
 }
 
@@ -619,29 +1401,89 @@ pub enum GuestMemoryFromUffdError {
     /// Failed to register memory address range with the userfaultfd object.
     #[error("Failed to register memory address range with the userfaultfd object: {0}")]
     Register(userfaultfd::Error),
+    /// Failed to create the dirty page bitmap.
+    #[error("Failed to create the dirty page bitmap: {0}")]
+    DirtyBitmap(#[from] UffdDirtyBitmapError),
     /// Failed to connect to UDS Unix stream.
     #[error("Failed to connect to UDS Unix stream: {0}")]
     Connect(#[from] std::io::Error),
     /// Failed to send file descriptor.
     #[error("Failed to sends file descriptor: {0}")]
     Send(#[from] utils::errno::Error),
+    /// Failed to send the ranges held by the balloon.
+    #[error("Failed to send the ranges held by the balloon: {0}")]
+    SendRemovedRanges(std::io::Error),
+    /// Failed to shut down the writing half of the UDS Unix stream.
+    #[error("Failed to shut down the writing half of the UDS Unix stream: {0}")]
+    Shutdown(std::io::Error),
+    /// Failed to set up the page fault handler monitor.
+    #[error("Failed to set up the page fault handler monitor: {0}")]
+    Monitor(std::io::Error),
+    /// Failed to start the thread serving the guest memory from the fallback memory file.
+    #[error("Failed to start the thread serving the fallback memory file: {0}")]
+    Fallback(std::io::Error),
+    /// A range held by the balloon is outside of guest memory.
+    #[error("Balloon range at {0:#x} is outside of guest memory.")]
+    RemovedRange(u64),
+}
+
+// Translates the ranges held by the balloon into host virtual address ranges.
+fn uffd_removed_ranges(
+    guest_memory: &GuestMemoryMmap,
+    ranges: impl Iterator<Item = (GuestAddress, u64)>,
+) -> Result<Vec<GuestRegionUffdRemovedRange>, GuestMemoryFromUffdError> {
+    ranges
+        .map(|(addr, size)| {
+            // The balloon only removes ranges that fit in a single region.
+            let size_usize = usize::try_from(size).unwrap_or(usize::MAX);
+            if guest_memory.get_slice(addr, size_usize).is_err() {
+                return Err(GuestMemoryFromUffdError::RemovedRange(addr.0));
+            }
+            let host_addr = guest_memory
+                .get_host_address(addr)
+                .map_err(|_| GuestMemoryFromUffdError::RemovedRange(addr.0))?;
+            Ok(GuestRegionUffdRemovedRange {
+                base_host_virt_addr: host_addr as u64,
+                size,
+            })
+        })
+        .collect()
 }
 
 fn guest_memory_from_uffd(
     mem_uds_path: &Path,
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
-    enable_balloon: bool,
-) -> Result<(GuestMemoryMmap, Option<Uffd>), GuestMemoryFromUffdError> {
+    balloon_state: Option<&BalloonState>,
+    handler_exit_action: Option<UffdHandlerExitAction>,
+    fallback: Option<(File, Arc<BpfProgram>)>,
+    write_protect: bool,
+) -> Result<
+    (
+        GuestMemoryMmap,
+        Option<Uffd>,
+        Option<UffdHandover>,
+        Option<UffdHandlerMonitor>,
+    ),
+    GuestMemoryFromUffdError,
+> {
     let guest_memory = GuestMemoryMmap::restore(None, mem_state, track_dirty_pages)?;
+    let removed_ranges = match balloon_state {
+        Some(balloon_state) => uffd_removed_ranges(&guest_memory, balloon_state.removed_ranges())?,
+        None => Vec::new(),
+    };
 
     let mut uffd_builder = UffdBuilder::new();
 
-    if enable_balloon {
+    if balloon_state.is_some() {
         // We enable this so that the page fault handler can add logic
         // for treating madvise(MADV_DONTNEED) events triggerd by balloon inflation.
         uffd_builder.require_features(FeatureFlags::EVENT_REMOVE);
     }
+    if write_protect {
+        // The page fault handler is notified of the writes to write-protected pages.
+        uffd_builder.require_features(FeatureFlags::PAGEFAULT_FLAG_WP);
+    }
 
     let uffd = uffd_builder
         .close_on_exec(true)
@@ -649,27 +1491,86 @@ fn guest_memory_from_uffd(
         .create()
         .map_err(GuestMemoryFromUffdError::Create)?;
 
+    let dirty_bitmap = if write_protect {
+        Some(UffdDirtyBitmap::new(&guest_memory)?)
+    } else {
+        None
+    };
+    let register_mode = if write_protect {
+        RegisterMode::MISSING | RegisterMode::WRITE_PROTECT
+    } else {
+        RegisterMode::MISSING
+    };
+
     let mut backend_mappings = Vec::with_capacity(guest_memory.num_regions());
-    for (mem_region, state_region) in guest_memory.iter().zip(mem_state.regions.iter()) {
+    for (slot, (mem_region, state_region)) in guest_memory
+        .iter()
+        .zip(mem_state.regions.iter())
+        .enumerate()
+    {
         let host_base_addr = mem_region.as_ptr();
         let size = mem_region.size();
 
-        uffd.register(host_base_addr.cast(), size as _)
+        uffd.register_with_mode(host_base_addr.cast(), size as _, register_mode)
             .map_err(GuestMemoryFromUffdError::Register)?;
         backend_mappings.push(GuestRegionUffdMapping {
             base_host_virt_addr: host_base_addr as u64,
             size,
             offset: state_region.offset,
+            dirty_bitmap_offset: dirty_bitmap
+                .as_ref()
+                .map(|dirty_bitmap| dirty_bitmap.region_bit_offset(slot)),
+            zero_pages: state_region.zero_pages.clone(),
         });
     }
 
+    let socket = send_uffd_to_handler(
+        mem_uds_path,
+        &uffd,
+        dirty_bitmap.as_ref(),
+        &backend_mappings,
+        &removed_ranges,
+    )?;
+
+    let fallback = fallback
+        .map(|(mem_file, seccomp_filter)| {
+            UffdFallback::spawn(&uffd, mem_file, backend_mappings.clone(), seccomp_filter)
+        })
+        .transpose()
+        .map_err(GuestMemoryFromUffdError::Fallback)?;
+    let (handler_monitor, handover_monitor) =
+        UffdHandlerMonitor::new(socket, handler_exit_action, fallback)
+            .map_err(GuestMemoryFromUffdError::Monitor)?;
+    let handover = UffdHandover {
+        mappings: backend_mappings,
+        dirty_bitmap,
+        monitor: handover_monitor,
+    };
+
+    Ok((
+        guest_memory,
+        Some(uffd),
+        Some(handover),
+        Some(handler_monitor),
+    ))
+}
+
+// Sends the userfaultfd, the dirty page bitmap if any, the guest memory mappings and the ranges
+// held by the balloon to the page fault handler listening on `handler_path`. Returns the
+// connection to the handler.
+fn send_uffd_to_handler(
+    handler_path: &Path,
+    uffd: &Uffd,
+    dirty_bitmap: Option<&UffdDirtyBitmap>,
+    mappings: &[GuestRegionUffdMapping],
+    removed_ranges: &[GuestRegionUffdRemovedRange],
+) -> Result<UnixStream, GuestMemoryFromUffdError> {
     // This is safe to unwrap() because we control the contents of the vector
     // (i.e GuestRegionUffdMapping entries).
-    let backend_mappings = serde_json::to_string(&backend_mappings).unwrap();
+    let serialized_mappings = serde_json::to_string(mappings).unwrap();
 
-    let socket = UnixStream::connect(mem_uds_path)?;
-    socket.send_with_fd(
-        backend_mappings.as_bytes(),
+    let socket = UnixStream::connect(handler_path)?;
+    let mut fds = vec![
         // In the happy case we can close the fd since the other process has it open and is
         // using it to serve us pages.
         //
@@ -701,51 +1602,822 @@ fn guest_memory_from_uffd(
         // page fault handler process does not tear down Firecracker when necessary, the
         // uffd will still be alive but with no one to serve faults, leading to guest freeze.
         uffd.as_raw_fd(),
-    )?;
+    ];
+    if let Some(dirty_bitmap) = dirty_bitmap {
+        fds.push(dirty_bitmap.file.as_raw_fd());
+    }
+    socket.send_with_fds(&[serialized_mappings.as_bytes()], &fds)?;
+
+    // The ranges held by the balloon were released by the guest. Let the handler know about
+    // them before it serves any page fault, the same way it learns about ranges removed at
+    // runtime through `UFFD_EVENT_REMOVE`.
+    if !removed_ranges.is_empty() {
+        // This is safe to unwrap() because we control the contents of the vector
+        // (i.e GuestRegionUffdRemovedRange entries).
+        let removed_ranges = serde_json::to_string(&removed_ranges).unwrap();
+        (&socket)
+            .write_all(removed_ranges.as_bytes())
+            .map_err(GuestMemoryFromUffdError::SendRemovedRanges)?;
+    }
 
-    Ok((guest_memory, Some(uffd)))
+    // We never write to the socket again. Shutting down our side lets the handler know it has
+    // received everything, while the socket stays open so that we can receive the page fault
+    // reports and notice the handler exit.
+    socket
+        .shutdown(Shutdown::Write)
+        .map_err(GuestMemoryFromUffdError::Shutdown)?;
+    socket
+        .set_nonblocking(true)
+        .map_err(GuestMemoryFromUffdError::Monitor)?;
+
+    Ok(socket)
 }
 
-#[cfg(target_arch = "x86_64")]
-fn validate_devices_number(device_number: usize) -> Result<(), CreateSnapshotError> {
-    use self::CreateSnapshotError::TooManyDevices;
-    if device_number > FC_V0_23_MAX_DEVICES as usize {
-        return Err(TooManyDevices(device_number));
-    }
-    Ok(())
+/// Errors associated with handing guest memory over to a new page fault handler.
+#[derive(Debug, thiserror::Error)]
+pub enum UffdHandoverError {
+    /// The guest memory is not served by a page fault handler.
+    #[error("The guest memory is not served by a page fault handler.")]
+    NotUffd,
+    /// Failed to send the userfaultfd object to the new page fault handler.
+    #[error("Failed to send the userfaultfd object to the new page fault handler: {0}")]
+    Send(#[from] GuestMemoryFromUffdError),
+    /// Failed to pass the new page fault handler to the monitor.
+    #[error("Failed to pass the new page fault handler to the monitor.")]
+    Monitor,
+    /// The guest memory is being populated from the fallback memory file.
+    #[error("The guest memory is being populated from the fallback memory file.")]
+    FallingBack,
 }
 
-#[cfg(test)]
-mod tests {
-    use snapshot::Persist;
-    use utils::errno;
-    use utils::tempfile::TempFile;
+/// Keeps what is needed to hand guest memory served through UFFD over to a new page fault
+/// handler.
+#[derive(Debug)]
+pub struct UffdHandover {
+    // The guest memory regions registered with the userfaultfd object.
+    mappings: Vec<GuestRegionUffdMapping>,
+    // Set when the guest memory is write-protected.
+    dirty_bitmap: Option<UffdDirtyBitmap>,
+    // Passes the connection to the new handler to the monitor.
+    monitor: UffdHandlerMonitorHandle,
+}
 
-    use super::*;
-    use crate::builder::tests::{
-        default_kernel_cmdline, default_vmm, insert_balloon_device, insert_block_devices,
-        insert_net_device, insert_vsock_device, CustomBlockConfig,
+// Passes the connections to new page fault handlers to a `UffdHandlerMonitor`.
+#[derive(Debug)]
+struct UffdHandlerMonitorHandle {
+    sender: Sender<UnixStream>,
+    evt: EventFd,
+    // Cleared by the monitor when the handler exits.
+    handler_alive: Arc<AtomicBool>,
+    // Set by the monitor while the guest memory is populated from the fallback memory file.
+    falling_back: Arc<AtomicBool>,
+}
+
+/// Sends the userfaultfd object and the guest memory mappings of `vmm` to the page fault
+/// handler listening on `handler_path`, which takes over serving the guest memory page faults.
+pub(crate) fn handover_uffd_handler(
+    vmm: &Vmm,
+    handler_path: &Path,
+) -> Result<(), UffdHandoverError> {
+    let (Some(uffd), Some(handover)) = (vmm.uffd.as_ref(), vmm.uffd_handover.as_ref()) else {
+        return Err(UffdHandoverError::NotUffd);
     };
-    #[cfg(target_arch = "aarch64")]
-    use crate::construct_kvm_mpidrs;
-    use crate::memory_snapshot::SnapshotMemory;
-    use crate::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
-    use crate::vmm_config::balloon::BalloonDeviceConfig;
-    use crate::vmm_config::drive::CacheType;
-    use crate::vmm_config::net::NetworkInterfaceConfig;
-    use crate::vmm_config::vsock::tests::default_config;
-    use crate::Vmm;
+    // The fallback unregisters the guest memory from the userfaultfd object once it is done.
+    if handover.monitor.falling_back.load(Ordering::Relaxed) {
+        return Err(UffdHandoverError::FallingBack);
+    }
 
-    fn default_vmm_with_devices() -> Vmm {
-        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
-        let mut vmm = default_vmm();
-        let mut cmdline = default_kernel_cmdline();
+    let removed_ranges =
+        uffd_removed_ranges(&vmm.guest_memory, vmm.balloon_removed_ranges().into_iter())?;
+    let socket = send_uffd_to_handler(
+        handler_path,
+        uffd,
+        handover.dirty_bitmap.as_ref(),
+        &handover.mappings,
+        &removed_ranges,
+    )?;
 
-        // Add a balloon device.
-        let balloon_config = BalloonDeviceConfig {
-            amount_mib: 0,
-            deflate_on_oom: false,
-            stats_polling_interval_s: 0,
+    // The connection to the previous handler is dropped by the monitor.
+    handover
+        .monitor
+        .sender
+        .send(socket)
+        .map_err(|_| UffdHandoverError::Monitor)?;
+    handover
+        .monitor
+        .evt
+        .write(1)
+        .map_err(|_| UffdHandoverError::Monitor)?;
+
+    info!("Guest memory handed over to a new page fault handler");
+    Ok(())
+}
+
+/// Errors associated with tracking the dirty pages through the userfaultfd write-protect mode.
+#[derive(Debug, thiserror::Error)]
+pub enum UffdDirtyBitmapError {
+    /// Failed to get the system's page size.
+    #[error("Failed to get the system's page size: {0}")]
+    PageSize(utils::errno::Error),
+    /// Failed to create the dirty page bitmap.
+    #[error("Failed to create the dirty page bitmap: {0}")]
+    Create(std::io::Error),
+    /// Failed to access the dirty page bitmap.
+    #[error("Failed to access the dirty page bitmap: {0}")]
+    Access(std::io::Error),
+    /// Failed to write-protect the guest memory.
+    #[error("Failed to write-protect the guest memory: {0}")]
+    WriteProtect(userfaultfd::Error),
+}
+
+// Bitmap shared with the page fault handler when the guest memory is write-protected. The
+// handler sets the bit of each page the guest writes to before lifting its write protection.
+// There is one bit per page, and the bits of each region start on a 64-bit word boundary, so
+// that every region reads as a KVM dirty log.
+#[derive(Debug)]
+struct UffdDirtyBitmap {
+    file: File,
+    // Index of the first word and number of words of each guest memory region.
+    regions: Vec<(usize, usize)>,
+}
+
+impl UffdDirtyBitmap {
+    fn new(guest_memory: &GuestMemoryMmap) -> Result<Self, UffdDirtyBitmapError> {
+        let page_size = get_page_size().map_err(UffdDirtyBitmapError::PageSize)?;
+        let mut regions = Vec::with_capacity(guest_memory.num_regions());
+        let mut len_words = 0;
+        for region in guest_memory.iter() {
+            let pages = region.len() as usize / page_size;
+            let words = (pages + 63) / 64;
+            regions.push((len_words, words));
+            len_words += words;
+        }
+
+        // SAFETY: The name is a valid nul-terminated string and the returned value is checked.
+        let fd = unsafe {
+            libc::memfd_create(b"uffd_dirty_bitmap\0".as_ptr().cast(), libc::MFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(UffdDirtyBitmapError::Create(io::Error::last_os_error()));
+        }
+        // SAFETY: `fd` is a valid file descriptor that is not owned by anything else.
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len((len_words * std::mem::size_of::<u64>()) as u64)
+            .map_err(UffdDirtyBitmapError::Create)?;
+
+        Ok(Self { file, regions })
+    }
+
+    // Index of the bit tracking the first page of the region in `slot`.
+    fn region_bit_offset(&self, slot: usize) -> u64 {
+        (self.regions[slot].0 * 64) as u64
+    }
+
+    // Reads and clears the pages marked dirty by the page fault handler, then write-protects the
+    // guest memory again so that the next writes are tracked. The microVM is paused, so nothing
+    // writes to the guest memory meanwhile.
+    fn take(
+        &self,
+        uffd: &Uffd,
+        mappings: &[GuestRegionUffdMapping],
+    ) -> Result<DirtyBitmap, UffdDirtyBitmapError> {
+        let word_size = std::mem::size_of::<u64>();
+        let len_words = self.regions.iter().map(|(_, words)| words).sum::<usize>();
+        let mut bytes = vec![0u8; len_words * word_size];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(UffdDirtyBitmapError::Access)?;
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.write_all(&vec![0u8; bytes.len()]))
+            .map_err(UffdDirtyBitmapError::Access)?;
+
+        for mapping in mappings {
+            uffd.write_protect(mapping.base_host_virt_addr as *mut _, mapping.size)
+                .map_err(UffdDirtyBitmapError::WriteProtect)?;
+        }
+
+        Ok(self
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(slot, (start, words))| {
+                let region_bytes = &bytes[start * word_size..(start + words) * word_size];
+                let bitmap = region_bytes
+                    .chunks_exact(word_size)
+                    // The handler updates the bitmap as native 64-bit words.
+                    .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+                    .collect();
+                (slot, bitmap)
+            })
+            .collect())
+    }
+}
+
+/// Returns the pages marked dirty by the page fault handler since the last call, when the guest
+/// memory of `vmm` is write-protected.
+pub(crate) fn uffd_dirty_bitmap(vmm: &Vmm) -> Option<Result<DirtyBitmap, UffdDirtyBitmapError>> {
+    let uffd = vmm.uffd.as_ref()?;
+    let handover = vmm.uffd_handover.as_ref()?;
+    let dirty_bitmap = handover.dirty_bitmap.as_ref()?;
+    Some(dirty_bitmap.take(uffd, &handover.mappings))
+}
+
+/// Health of the page fault handler serving the guest memory of `vmm` through UFFD.
+pub(crate) fn uffd_handler_health(vmm: &Vmm) -> SubsystemHealth {
+    if vmm.uffd.is_none() {
+        return SubsystemHealth::new(HealthStatus::Disabled);
+    }
+    match vmm.uffd_handover.as_ref() {
+        // Only the monitor drops the handover, once it served the guest memory from the
+        // fallback memory file.
+        None => SubsystemHealth::with_detail(
+            HealthStatus::Degraded,
+            "The guest memory is served from the fallback memory file.".to_string(),
+        ),
+        Some(handover) if handover.monitor.handler_alive.load(Ordering::Relaxed) => {
+            SubsystemHealth::new(HealthStatus::Ok)
+        }
+        Some(handover) if handover.monitor.falling_back.load(Ordering::Relaxed) => {
+            SubsystemHealth::with_detail(
+                HealthStatus::Degraded,
+                "The guest memory is being populated from the fallback memory file.".to_string(),
+            )
+        }
+        Some(_) => SubsystemHealth::with_detail(
+            HealthStatus::Failed,
+            "The page fault handler exited.".to_string(),
+        ),
+    }
+}
+
+/// Errors encountered while serving guest memory from the fallback memory file.
+#[derive(Debug, thiserror::Error)]
+pub enum UffdFallbackError {
+    /// Failed to read the fallback memory file.
+    #[error("Failed to read the fallback memory file: {0}")]
+    Read(std::io::Error),
+    /// Failed to copy a guest memory range through the userfaultfd object.
+    #[error("Failed to copy a guest memory range through the userfaultfd object: {0}")]
+    Copy(userfaultfd::Error),
+    /// Failed to unregister a guest memory region from the userfaultfd object.
+    #[error("Failed to unregister a guest memory region from the userfaultfd object: {0}")]
+    Unregister(userfaultfd::Error),
+}
+
+// Populates the guest memory not served by the page fault handler from the fallback memory
+// file. Runs on a thread of its own, so the devices and the API are served during the copy.
+struct UffdFallback {
+    // Duplicate of the userfaultfd object the guest memory is registered with.
+    uffd: Uffd,
+    mem_file: File,
+    // The guest memory regions registered with the userfaultfd object.
+    mappings: Vec<GuestRegionUffdMapping>,
+}
+
+// Starts the fallback and reports its outcome to the `UffdHandlerMonitor`.
+struct UffdFallbackHandle {
+    start: Sender<()>,
+    outcome: Receiver<Result<(), UffdFallbackError>>,
+    // Written once `outcome` holds the result of the fallback.
+    evt: EventFd,
+}
+
+impl Debug for UffdFallbackHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UffdFallbackHandle")
+            .field("evt", &self.evt)
+            .finish()
+    }
+}
+
+impl UffdFallback {
+    // Size of the chunks read from the fallback memory file and copied into guest memory.
+    const CHUNK_SIZE: usize = 256 * crate::arch::PAGE_SIZE;
+
+    // Spawns the thread populating the guest memory from `mem_file`. The thread is spawned
+    // right away, before the seccomp filters of the VMM thread forbid it, but waits for the
+    // page fault handler to exit before touching guest memory.
+    fn spawn(
+        uffd: &Uffd,
+        mem_file: File,
+        mappings: Vec<GuestRegionUffdMapping>,
+        seccomp_filter: Arc<BpfProgram>,
+    ) -> Result<UffdFallbackHandle, std::io::Error> {
+        // SAFETY: `fcntl` does not access memory and `uffd` holds a valid descriptor.
+        let fd =
+            SyscallReturnCode(unsafe { libc::fcntl(uffd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) })
+                .into_result()?;
+        let fallback = UffdFallback {
+            // SAFETY: `fd` was just duplicated, so it is valid and owned by nobody else.
+            uffd: unsafe { Uffd::from_raw_fd(fd) },
+            mem_file,
+            mappings,
+        };
+
+        let (start, start_receiver) = channel();
+        let (outcome_sender, outcome) = channel();
+        let evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        let thread_evt = evt.try_clone()?;
+        std::thread::Builder::new()
+            .name("fc_uffd_fallback".to_string())
+            .spawn(move || {
+                // The thread is started before the Landlock ruleset is enforced.
+                let _landlock_registration = crate::landlock::register_thread();
+                if let Err(err) = crate::seccomp_filters::install_filter("vmm", &seccomp_filter) {
+                    panic!(
+                        "Failed to set the UFFD fallback thread seccomp filters: {}",
+                        err
+                    );
+                }
+                // The sender is dropped without starting the fallback if the microVM goes away.
+                if start_receiver.recv().is_err() {
+                    return;
+                }
+                let _ = outcome_sender.send(fallback.run());
+                if let Err(err) = thread_evt.write(1) {
+                    error!("Failed to signal the end of the UFFD fallback: {}", err);
+                }
+            })?;
+
+        Ok(UffdFallbackHandle {
+            start,
+            outcome,
+            evt,
+        })
+    }
+
+    // Populates the guest memory not served by the handler from the fallback memory file,
+    // then unregisters the guest memory from the userfaultfd object. Unregistering wakes up
+    // the vCPUs blocked on a page fault.
+    fn run(mut self) -> Result<(), UffdFallbackError> {
+        // Nobody is left to handle the pending events.
+        while let Ok(Some(_)) = self.uffd.read_event() {}
+
+        let mut buf = vec![0u8; Self::CHUNK_SIZE];
+        for mapping in self.mappings.iter() {
+            let mut copied = 0;
+            while copied < mapping.size {
+                let len = Self::CHUNK_SIZE.min(mapping.size - copied);
+                self.mem_file
+                    .seek(SeekFrom::Start(mapping.offset + copied as u64))
+                    .and_then(|_| self.mem_file.read_exact(&mut buf[..len]))
+                    .map_err(UffdFallbackError::Read)?;
+
+                // SAFETY: The source is a buffer of at least `len` bytes and the destination
+                // is a range of a guest memory region registered with `uffd`.
+                let res = unsafe {
+                    self.uffd.copy(
+                        buf.as_ptr().cast(),
+                        (mapping.base_host_virt_addr as usize + copied) as *mut _,
+                        len,
+                        false,
+                    )
+                };
+                copied += match res {
+                    Ok(n) | Err(userfaultfd::Error::PartiallyCopied(n)) => n,
+                    // The page was already populated by the handler, keep its contents.
+                    Err(userfaultfd::Error::CopyFailed(errno)) if errno as i32 == libc::EEXIST => {
+                        crate::arch::PAGE_SIZE
+                    }
+                    Err(err) => return Err(UffdFallbackError::Copy(err)),
+                };
+            }
+
+            self.uffd
+                .unregister(mapping.base_host_virt_addr as *mut _, mapping.size)
+                .map_err(UffdFallbackError::Unregister)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A page fault served by the page fault handler. The handler reports each of them over its
+/// connection to Firecracker, as a JSON object followed by a newline.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UffdFaultReport {
+    // Time it took the handler to serve the page fault, in microseconds.
+    latency_us: u64,
+    // Number of bytes populated in guest memory while serving the page fault.
+    bytes: u64,
+}
+
+/// Watches the connection to the page fault handler serving guest memory through UFFD. It
+/// records the page faults reported by the handler in the metrics and, when configured to, reacts
+/// when the handler exits, either by pausing the microVM or by populating the guest memory from a
+/// fallback memory file.
+pub struct UffdHandlerMonitor {
+    // The connection to the page fault handler. It hangs up when the handler exits.
+    socket: UnixStream,
+    // Whether `socket` is registered with the event manager.
+    connected: bool,
+    // Page fault reports received from the handler that are not complete yet.
+    reports: Vec<u8>,
+    // What to do when the handler exits. Nothing but logging if `None`.
+    exit_action: Option<UffdHandlerExitAction>,
+    // Serves the guest memory from the fallback memory file when the handler exits with the
+    // `Fallback` action.
+    fallback: Option<UffdFallbackHandle>,
+    // Connections to the handlers taking over from the current one.
+    handover: Receiver<UnixStream>,
+    // Signaled when a connection is sent through `handover`.
+    handover_evt: EventFd,
+    // The microVM whose guest memory is served by the handler.
+    vmm: Option<Arc<Mutex<Vmm>>>,
+    // Whether the handler is connected, shared with the `UffdHandlerMonitorHandle`.
+    handler_alive: Arc<AtomicBool>,
+    // Whether the fallback is running, shared with the `UffdHandlerMonitorHandle`.
+    falling_back: Arc<AtomicBool>,
+}
+
+impl Debug for UffdHandlerMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UffdHandlerMonitor")
+            .field("socket", &self.socket)
+            .field("connected", &self.connected)
+            .field("reports", &self.reports)
+            .field("exit_action", &self.exit_action)
+            .field("fallback", &self.fallback)
+            .field("handover_evt", &self.handover_evt)
+            .finish()
+    }
+}
+
+impl UffdHandlerMonitor {
+    // Page fault reports are much shorter than this. Anything longer is discarded.
+    const MAX_REPORT_LEN: usize = 4096;
+
+    fn new(
+        socket: UnixStream,
+        exit_action: Option<UffdHandlerExitAction>,
+        fallback: Option<UffdFallbackHandle>,
+    ) -> Result<(Self, UffdHandlerMonitorHandle), std::io::Error> {
+        let (sender, handover) = channel();
+        let handover_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        let handler_alive = Arc::new(AtomicBool::new(true));
+        let falling_back = Arc::new(AtomicBool::new(false));
+        let handle = UffdHandlerMonitorHandle {
+            sender,
+            evt: handover_evt.try_clone()?,
+            handler_alive: handler_alive.clone(),
+            falling_back: falling_back.clone(),
+        };
+        let monitor = UffdHandlerMonitor {
+            socket,
+            connected: false,
+            reports: Vec::new(),
+            exit_action,
+            fallback,
+            handover,
+            handover_evt,
+            vmm: None,
+            handler_alive,
+            falling_back,
+        };
+        Ok((monitor, handle))
+    }
+
+    fn register_socket(&mut self, ops: &mut EventOps) {
+        match ops.add(Events::new(
+            &self.socket,
+            EventSet::IN | EventSet::READ_HANG_UP,
+        )) {
+            Ok(()) => self.connected = true,
+            Err(err) => error!("Failed to register the UFFD handler monitor: {}", err),
+        }
+    }
+
+    fn unregister_socket(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.remove(Events::new(
+            &self.socket,
+            EventSet::IN | EventSet::READ_HANG_UP,
+        )) {
+            error!("Failed to unregister the UFFD handler monitor: {}", err);
+        }
+        self.connected = false;
+    }
+
+    // Handles the end of the fallback started when the handler exited.
+    fn fallback_done(&mut self, ops: &mut EventOps) {
+        let Some(fallback) = self.fallback.take() else {
+            return;
+        };
+        if let Err(err) = ops.remove(Events::new(&fallback.evt, EventSet::IN)) {
+            error!("Failed to unregister the UFFD fallback event: {}", err);
+        }
+        self.falling_back.store(false, Ordering::Relaxed);
+
+        match fallback.outcome.try_recv() {
+            Ok(Ok(())) => {
+                info!("Guest memory is now served from the fallback memory file");
+                // The guest memory is no longer registered with the userfaultfd object, so it
+                // cannot be handed over anymore.
+                if let Some(vmm) = self.vmm.as_ref() {
+                    vmm.lock().expect("Poisoned lock").uffd_handover = None;
+                }
+            }
+            Ok(Err(err)) => {
+                error!("Failed to fall back on the memory file: {}", err);
+                self.pause_vm();
+            }
+            Err(_) => {
+                error!("The UFFD fallback thread exited without an outcome");
+                self.pause_vm();
+            }
+        }
+    }
+
+    // Unless the host kernel lets KVM interrupt the page faults, the vCPUs blocked on a page
+    // fault nobody serves do not answer the pause request and the microVM keeps running.
+    fn pause_vm(&self) {
+        let Some(vmm) = self.vmm.as_ref() else {
+            return;
+        };
+        if let Err(err) = vmm.lock().expect("Poisoned lock").pause_vm() {
+            error!(
+                "Failed to pause the microVM, its vCPUs may be blocked on guest memory the page \
+                 fault handler did not serve: {:?}",
+                err
+            );
+        }
+    }
+
+    // Switches to the connection to the latest handler taking over, if any.
+    fn take_handover(&mut self, ops: &mut EventOps) -> bool {
+        let Some(socket) = self.handover.try_iter().last() else {
+            return false;
+        };
+        if self.connected {
+            self.unregister_socket(ops);
+        }
+        self.socket = socket;
+        self.reports.clear();
+        self.register_socket(ops);
+        self.handler_alive.store(true, Ordering::Relaxed);
+        true
+    }
+
+    // Reads the page faults reported by the handler and records them in the metrics. Returns
+    // `false` once the handler closed the connection.
+    fn read_reports(&mut self) -> bool {
+        let mut connected = true;
+        let mut buf = [0u8; 1024];
+        loop {
+            match (&self.socket).read(&mut buf) {
+                Ok(0) => {
+                    connected = false;
+                    break;
+                }
+                Ok(len) => self.reports.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Failed to read from the UFFD page fault handler: {}", err);
+                    connected = false;
+                    break;
+                }
+            }
+        }
+
+        while let Some(end) = self.reports.iter().position(|byte| *byte == b'\n') {
+            let report: Vec<u8> = self.reports.drain(..=end).collect();
+            match serde_json::from_slice::<UffdFaultReport>(&report) {
+                Ok(report) => METRICS.uffd.record_fault(
+                    usize::try_from(report.latency_us).unwrap_or(usize::MAX),
+                    usize::try_from(report.bytes).unwrap_or(usize::MAX),
+                ),
+                Err(_) => METRICS.uffd.invalid_reports.inc(),
+            }
+        }
+        if self.reports.len() > Self::MAX_REPORT_LEN {
+            self.reports.clear();
+            METRICS.uffd.invalid_reports.inc();
+        }
+
+        connected
+    }
+}
+
+impl MutEventSubscriber for UffdHandlerMonitor {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        let source = event.fd();
+
+        if source == self.handover_evt.as_raw_fd() {
+            if let Err(err) = self.handover_evt.read() {
+                error!("Failed to consume the UFFD handover event: {:?}", err);
+            }
+            self.take_handover(ops);
+            return;
+        }
+
+        if let Some(fallback) = self.fallback.as_ref() {
+            if source == fallback.evt.as_raw_fd() {
+                self.fallback_done(ops);
+                return;
+            }
+        }
+
+        if source != self.socket.as_raw_fd() || !self.connected {
+            warn!("Spurious event received by the UFFD handler monitor");
+            return;
+        }
+
+        // The handler only writes page fault reports to the socket after the handshake.
+        if self.read_reports() {
+            return;
+        }
+
+        // The previous handler is expected to exit once a new one took over.
+        if self.take_handover(ops) {
+            return;
+        }
+
+        // The handler is gone, there is nothing left to monitor until a new one takes over.
+        self.handler_alive.store(false, Ordering::Relaxed);
+        self.unregister_socket(ops);
+        if self.exit_action.is_none() {
+            warn!("The UFFD page fault handler serving guest memory exited");
+            return;
+        }
+        error!("The page fault handler serving guest memory through UFFD exited");
+        METRICS.vmm.uffd_handler_exits.inc();
+
+        if let Some(fallback) = self.fallback.as_ref() {
+            // The copy runs on the fallback thread, this one keeps serving the devices.
+            if fallback.start.send(()).is_ok() {
+                self.falling_back.store(true, Ordering::Relaxed);
+                return;
+            }
+            error!("Failed to start the UFFD fallback");
+        }
+
+        self.pause_vm();
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.handover_evt, EventSet::IN)) {
+            error!("Failed to register the UFFD handover event: {}", err);
+        }
+        if let Some(fallback) = self.fallback.as_ref() {
+            if let Err(err) = ops.add(Events::new(&fallback.evt, EventSet::IN)) {
+                error!("Failed to register the UFFD fallback event: {}", err);
+            }
+        }
+        self.register_socket(ops);
+    }
+}
+
+/// Reads the memory file of a microVM restored with the `File` backend into the host page cache,
+/// in the background of the guest page faults. The file is read sequentially, at a bounded
+/// bandwidth, so that the faults on the pages not touched yet do not wait for the disk.
+pub struct MemoryFileReadahead {
+    // The file the guest memory is mapped from.
+    mem_file: File,
+    // Size of `mem_file`.
+    size: u64,
+    // Offset up to which the readahead of `mem_file` was requested.
+    offset: u64,
+    // Size of the range requested on each tick of `timer`.
+    chunk_size: u64,
+    timer: TimerFd,
+}
+
+impl Debug for MemoryFileReadahead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryFileReadahead")
+            .field("mem_file", &self.mem_file)
+            .field("size", &self.size)
+            .field("offset", &self.offset)
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl MemoryFileReadahead {
+    // Period, in milliseconds, of the readahead requests.
+    const PERIOD_MS: u64 = 10;
+
+    fn new(mem_file: File, mib_per_s: u32) -> Result<Self, std::io::Error> {
+        let size = mem_file.metadata()?.len();
+        let page_size = crate::arch::PAGE_SIZE as u64;
+        let bytes_per_period = (u64::from(mib_per_s) << 20) * Self::PERIOD_MS / 1000;
+        let chunk_size = std::cmp::max(bytes_per_period / page_size, 1) * page_size;
+
+        let mut timer = TimerFd::new_custom(ClockId::Monotonic, true, true)?;
+        let period = Duration::from_millis(Self::PERIOD_MS);
+        timer.set_state(
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            },
+            SetTimeFlags::Default,
+        );
+
+        Ok(MemoryFileReadahead {
+            mem_file,
+            size,
+            offset: 0,
+            chunk_size,
+            timer,
+        })
+    }
+
+    // Asks the host kernel to read the next chunk of the memory file into the page cache. The
+    // request does not wait for the disk. Returns `false` once the whole file was requested.
+    fn advance(&mut self) -> bool {
+        let len = self.chunk_size.min(self.size - self.offset);
+        // SAFETY: `posix_fadvise` does not access memory and fails with `EBADF` on an invalid
+        // descriptor.
+        let ret = unsafe {
+            libc::posix_fadvise(
+                self.mem_file.as_raw_fd(),
+                self.offset as libc::off_t,
+                len as libc::off_t,
+                libc::POSIX_FADV_WILLNEED,
+            )
+        };
+        // The error number is returned instead of being set in `errno`.
+        if ret != 0 {
+            warn!(
+                "Failed to read the memory file ahead: {}",
+                io::Error::from_raw_os_error(ret)
+            );
+            return false;
+        }
+
+        self.offset += len;
+        self.offset < self.size
+    }
+}
+
+impl MutEventSubscriber for MemoryFileReadahead {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        if event.fd() != self.timer.as_raw_fd() || event.event_set() != EventSet::IN {
+            error!("Spurious EventManager event for handler: MemoryFileReadahead");
+            return;
+        }
+
+        self.timer.read();
+        if self.advance() {
+            return;
+        }
+        if self.offset == self.size {
+            info!("Requested the readahead of the whole memory file");
+        }
+        if let Err(err) = ops.remove(Events::new(&self.timer, EventSet::IN)) {
+            error!(
+                "Failed to unregister the memory file readahead timer: {}",
+                err
+            );
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!(
+                "Failed to register the memory file readahead timer: {}",
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snapshot::Persist;
+    use utils::errno;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::builder::tests::{
+        default_kernel_cmdline, default_vmm, insert_balloon_device, insert_block_devices,
+        insert_net_device, insert_vsock_device, CustomBlockConfig,
+    };
+    #[cfg(target_arch = "aarch64")]
+    use crate::construct_kvm_mpidrs;
+    use crate::cpu_config::templates::KvmCapability;
+    use crate::memory_snapshot::SnapshotMemory;
+    use crate::version_map::{FC_V1_2_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
+    use crate::vmm_config::balloon::BalloonDeviceConfig;
+    use crate::vmm_config::drive::CacheType;
+    use crate::vmm_config::net::NetworkInterfaceConfig;
+    use crate::vmm_config::vsock::tests::default_config;
+    use crate::Vmm;
+
+    fn default_vmm_with_devices() -> Vmm {
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        // Add a balloon device.
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            amount_bytes: None,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+            free_page_hinting: false,
         };
         insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_config);
 
@@ -767,6 +2439,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         };
         insert_net_device(
             &mut vmm,
@@ -840,29 +2518,456 @@ mod tests {
     }
 
     #[test]
-    fn test_get_snapshot_data_version() {
-        let vmm = default_vmm_with_devices();
+    fn test_vm_info_acpi_versionize() {
+        let vm_info = VmInfo {
+            acpi: true,
+            ..Default::default()
+        };
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VmInfo::type_id(), 2)
+            .new_version()
+            .set_type_version(VmInfo::type_id(), 3);
+
+        let mut buf = vec![0; 10000];
+        vm_info
+            .serialize(&mut buf.as_mut_slice(), &version_map, 3)
+            .unwrap();
+        let restored_vm_info = VmInfo::deserialize(&mut buf.as_slice(), &version_map, 3).unwrap();
+        assert!(restored_vm_info.acpi);
+
+        // Older versions cannot describe the ACPI tables already in guest memory.
+        assert!(vm_info
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .is_err());
+    }
 
+    #[test]
+    fn test_get_snapshot_data_version() {
         assert_eq!(
             VERSION_MAP.latest_version(),
-            get_snapshot_data_version(&None, &VERSION_MAP, &vmm).unwrap()
+            get_snapshot_data_version(&None, &VERSION_MAP).unwrap()
         );
 
         for version in FC_VERSION_TO_SNAP_VERSION.keys() {
-            let res = get_snapshot_data_version(&Some(version.clone()), &VERSION_MAP, &vmm);
+            get_snapshot_data_version(&Some(version.clone()), &VERSION_MAP).unwrap();
+        }
+        assert!(matches!(
+            get_snapshot_data_version(&Some(Version::new(0, 22, 0)), &VERSION_MAP),
+            Err(CreateSnapshotError::UnsupportedVersion)
+        ));
+    }
 
-            #[cfg(target_arch = "x86_64")]
-            assert!(res.is_ok());
+    #[test]
+    fn test_check_snapshot_downgrade() {
+        let vmm = default_vmm_with_devices();
+        let mut microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
 
-            #[cfg(target_arch = "aarch64")]
-            // Validate sanity checks fail because aarch64 does not support "0.23.0"
-            // snapshot target version.
-            if version == &Version::new(0, 23, 0) {
-                assert!(res.is_err())
-            } else {
-                assert!(res.is_ok())
-            }
+        for version in FC_V1_0_SNAP_VERSION..=VERSION_MAP.latest_version() {
+            check_snapshot_downgrade(&vmm, &microvm_state, version).unwrap();
+        }
+
+        microvm_state.device_states.mmds_version = Some(MmdsVersionState::V2);
+        assert!(matches!(
+            check_snapshot_downgrade(&vmm, &microvm_state, FC_V1_0_SNAP_VERSION),
+            Err(CreateSnapshotError::IncompatibleVersion(version, unsupported))
+                if version == "v1.0.0" && unsupported == ["MMDS version 2 (since v1.1.0)"]
+        ));
+
+        // Every unsupported device and feature is listed.
+        microvm_state.vm_state.kvm_cap_modifiers = vec![KvmCapability::Add(36)];
+        assert!(matches!(
+            check_snapshot_downgrade(&vmm, &microvm_state, FC_V0_24_SNAP_VERSION - 1),
+            Err(CreateSnapshotError::IncompatibleVersion(_, unsupported))
+                if unsupported == [
+                    "KVM capabilities of the CPU template (since v1.5.0)",
+                    "balloon device (since v0.24.0)",
+                    "MMDS version 2 (since v1.1.0)",
+                ]
+        ));
+
+        microvm_state.vm_state.kvm_cap_modifiers.clear();
+        microvm_state.device_states.mmds_version = None;
+        microvm_state.device_states.block_devices[0]
+            .device_state
+            .in_flight_requests = vec![0];
+        assert!(matches!(
+            check_snapshot_downgrade(&vmm, &microvm_state, FC_V1_4_SNAP_VERSION),
+            Err(CreateSnapshotError::IncompatibleVersion(_, unsupported))
+                if unsupported == ["requests in flight of block device root (since v1.5.0)"]
+        ));
+
+        let block_state = &mut microvm_state.device_states.block_devices[0].device_state;
+        block_state.in_flight_requests.clear();
+        block_state.cache_type = CacheTypeState::Writethrough;
+        block_state.direct_io = true;
+        assert!(matches!(
+            check_snapshot_downgrade(&vmm, &microvm_state, FC_V1_4_SNAP_VERSION),
+            Err(CreateSnapshotError::IncompatibleVersion(_, unsupported))
+                if unsupported == [
+                    "write-through cache of block device root (since v1.5.0)",
+                    "direct I/O of block device root (since v1.5.0)",
+                ]
+        ));
+    }
+
+    #[test]
+    fn test_check_snapshot_translation() {
+        let vmm = default_vmm_with_devices();
+        let microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
+
+        for version in FC_MIN_TRANSLATED_SNAP_VERSION..=VERSION_MAP.latest_version() {
+            check_snapshot_translation(&microvm_state, version, &VERSION_MAP).unwrap();
+        }
+
+        // The format of the queues changed in v1.0, so every virtio device is listed.
+        let err = check_snapshot_translation(&microvm_state, FC_V0_25_SNAP_VERSION, &VERSION_MAP)
+            .unwrap_err();
+        let SnapshotTranslationError::Untranslatable(source, min, untranslatable) = err;
+        assert_eq!(source, "v0.25.0");
+        assert_eq!(min, "v1.3.0");
+        for state in [
+            "VM state",
+            "block device root",
+            "net device netif",
+            "vsock device vsock",
+            "balloon device balloon",
+        ] {
+            assert!(untranslatable.contains(&state.to_string()), "{state}");
         }
+
+        // Only the state whose format changed since v1.2 is listed.
+        let err = check_snapshot_translation(&microvm_state, FC_V1_2_SNAP_VERSION, &VERSION_MAP)
+            .unwrap_err();
+        let SnapshotTranslationError::Untranslatable(_, _, untranslatable) = err;
+        assert!(untranslatable.contains(&"net device netif".to_string()));
+        assert!(untranslatable.contains(&"block device root".to_string()));
+        #[cfg(target_arch = "x86_64")]
+        assert!(!untranslatable.contains(&"vCPU states".to_string()));
+    }
+
+    #[test]
+    fn test_handover_uffd_handler_without_uffd() {
+        // A microVM that does not use the `Uffd` memory backend cannot be handed over.
+        let vmm = default_vmm();
+        assert!(matches!(
+            handover_uffd_handler(&vmm, Path::new("/tmp/uffd.sock")),
+            Err(UffdHandoverError::NotUffd)
+        ));
+    }
+
+    #[test]
+    fn test_uffd_removed_ranges() {
+        use std::collections::BTreeMap;
+
+        use crate::devices::virtio::balloon::Balloon;
+
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[
+                (GuestAddress(0), 0x10000),
+                (GuestAddress(0x100000), 0x10000),
+            ],
+            false,
+        )
+        .unwrap();
+        let mut balloon = Balloon::new(0, false, 0, false, false).unwrap();
+
+        // No removed ranges.
+        let state = balloon.save();
+        assert!(uffd_removed_ranges(&guest_memory, state.removed_ranges())
+            .unwrap()
+            .is_empty());
+
+        // Ranges are translated to the host addresses of their region.
+        balloon.removed_ranges = BTreeMap::from([(0x2, 0x3), (0x101, 0x1)]);
+        let state = balloon.save();
+        assert_eq!(
+            uffd_removed_ranges(&guest_memory, state.removed_ranges()).unwrap(),
+            vec![
+                GuestRegionUffdRemovedRange {
+                    base_host_virt_addr: guest_memory
+                        .get_host_address(GuestAddress(0x2000))
+                        .unwrap() as u64,
+                    size: 0x3000,
+                },
+                GuestRegionUffdRemovedRange {
+                    base_host_virt_addr: guest_memory
+                        .get_host_address(GuestAddress(0x101000))
+                        .unwrap() as u64,
+                    size: 0x1000,
+                },
+            ]
+        );
+
+        // Ranges crossing the end of a region are rejected.
+        balloon.removed_ranges = BTreeMap::from([(0xf, 0x2)]);
+        let state = balloon.save();
+        assert!(matches!(
+            uffd_removed_ranges(&guest_memory, state.removed_ranges()),
+            Err(GuestMemoryFromUffdError::RemovedRange(0xf000))
+        ));
+    }
+
+    #[test]
+    fn test_uffd_dirty_bitmap_layout() {
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[
+                (GuestAddress(0), 0x10000),
+                (GuestAddress(0x100000), 0x48000),
+            ],
+            false,
+        )
+        .unwrap();
+        let dirty_bitmap = UffdDirtyBitmap::new(&guest_memory).unwrap();
+
+        // Each region starts on a word boundary: 16 pages fit in one word, 72 pages in two.
+        assert_eq!(dirty_bitmap.regions, vec![(0, 1), (1, 2)]);
+        assert_eq!(dirty_bitmap.region_bit_offset(0), 0);
+        assert_eq!(dirty_bitmap.region_bit_offset(1), 64);
+        assert_eq!(dirty_bitmap.file.metadata().unwrap().len(), 24);
+    }
+
+    #[test]
+    fn test_override_device_states() {
+        use utils::tempdir::TempDir;
+
+        use crate::vmm_config::snapshot::{
+            DriveOverride, MemBackendConfig, MemBackendType, NetworkOverride, VsockOverride,
+        };
+
+        let vmm = default_vmm_with_devices();
+        let mut params = LoadSnapshotParams {
+            snapshot_path: "vmstate".into(),
+            mem_backend: MemBackendConfig {
+                backend_path: "mem".into(),
+                backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: true,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: vec![NetworkOverride {
+                iface_id: "netif".to_string(),
+                host_dev_name: "tap1".to_string(),
+            }],
+            drive_overrides: vec![DriveOverride {
+                drive_id: "root".to_string(),
+                path_on_host: "/srv/overlay.ext4".to_string(),
+            }],
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        };
+
+        let mut states = vmm.mmio_device_manager.save();
+        override_device_states(&mut states, &params).unwrap();
+        assert_eq!(states.net_devices[0].device_state.tap_if_name(), "tap1");
+        assert_eq!(
+            states.block_devices[0].device_state.disk_path(),
+            "/srv/overlay.ext4"
+        );
+
+        // The overridden devices must be in the snapshot.
+        params.network_overrides[0].iface_id = "eth1".to_string();
+        let mut states = vmm.mmio_device_manager.save();
+        assert!(matches!(
+            override_device_states(&mut states, &params),
+            Err(RestoreFromSnapshotError::UnknownNetworkInterface(id)) if id == "eth1"
+        ));
+        params.network_overrides.clear();
+        params.drive_overrides[0].drive_id = "scratch".to_string();
+        assert!(matches!(
+            override_device_states(&mut states, &params),
+            Err(RestoreFromSnapshotError::UnknownDrive(id)) if id == "scratch"
+        ));
+
+        // The MMDS contents need a network interface with MMDS.
+        params.drive_overrides.clear();
+        params.mmds = Some(serde_json::Map::new());
+        assert!(matches!(
+            override_device_states(&mut states, &params),
+            Err(RestoreFromSnapshotError::NoMmds)
+        ));
+
+        // The CID of the snapshot is kept by the first clone only.
+        params.mmds = None;
+        let lock_dir = TempDir::new().unwrap();
+        params.vsock_override = Some(VsockOverride {
+            cid_lock_dir: Some(lock_dir.as_path().to_str().unwrap().to_string()),
+            ..Default::default()
+        });
+        let vsock_cid = |states: &DeviceStates| {
+            states
+                .vsock_device
+                .as_ref()
+                .unwrap()
+                .device_state
+                .frontend
+                .cid
+        };
+        let mut states = vmm.mmio_device_manager.save();
+        let first_lock = override_device_states(&mut states, &params).unwrap();
+        assert_eq!(vsock_cid(&states), 3);
+        let mut states = vmm.mmio_device_manager.save();
+        let _second_lock = override_device_states(&mut states, &params).unwrap();
+        assert_eq!(vsock_cid(&states), 4);
+        // The CIDs set explicitly are not replaced.
+        params.vsock_override.as_mut().unwrap().guest_cid = Some(3);
+        assert!(matches!(
+            override_device_states(&mut states, &params),
+            Err(RestoreFromSnapshotError::VsockCid(
+                VsockConfigError::CidInUse(3)
+            ))
+        ));
+        drop(first_lock);
+        override_device_states(&mut states, &params).unwrap();
+        assert_eq!(vsock_cid(&states), 3);
+
+        params.vsock_override = Some(VsockOverride {
+            guest_cid: Some(2),
+            ..Default::default()
+        });
+        assert!(matches!(
+            override_device_states(&mut states, &params),
+            Err(RestoreFromSnapshotError::VsockCid(
+                VsockConfigError::InvalidGuestCid(2)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_publish_restore_info() {
+        let mut mmds = Mmds::default();
+        let restore_info: serde_json::Map<_, _> =
+            serde_json::from_str(r#"{"ip": "10.0.0.2", "generation": 1}"#).unwrap();
+
+        // The restore information is published in an empty data store.
+        publish_restore_info(&mut mmds, &restore_info).unwrap();
+        assert_eq!(
+            mmds.data_store_value(),
+            serde_json::json!({"restore_info": {"ip": "10.0.0.2", "generation": 1}})
+        );
+
+        // It replaces the one of a previous restore and keeps the rest of the data store.
+        mmds.put_data(serde_json::json!({"task": "t1", "restore_info": {"ip": "10.0.0.2"}}))
+            .unwrap();
+        let restore_info: serde_json::Map<_, _> =
+            serde_json::from_str(r#"{"generation": 2}"#).unwrap();
+        publish_restore_info(&mut mmds, &restore_info).unwrap();
+        assert_eq!(
+            mmds.data_store_value(),
+            serde_json::json!({"task": "t1", "restore_info": {"generation": 2}})
+        );
+
+        // The data store limit applies.
+        let mut mmds = Mmds::default_with_limit(16);
+        assert!(matches!(
+            publish_restore_info(&mut mmds, &restore_info),
+            Err(mmds::data_store::Error::DataStoreLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_memory_file_readahead() {
+        let page_size = crate::arch::PAGE_SIZE as u64;
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().set_len(5 * page_size).unwrap();
+
+        // 1 MiB/s is a bit more than two pages per period.
+        let mut readahead =
+            MemoryFileReadahead::new(tmp_file.as_file().try_clone().unwrap(), 1).unwrap();
+        assert_eq!(readahead.size, 5 * page_size);
+        assert_eq!(readahead.chunk_size, 2 * page_size);
+        assert!(readahead.advance());
+        assert_eq!(readahead.offset, 2 * page_size);
+        assert!(readahead.advance());
+        // The last chunk is shorter.
+        assert!(!readahead.advance());
+        assert_eq!(readahead.offset, 5 * page_size);
+
+        // At least one page is requested per period.
+        let readahead = MemoryFileReadahead::new(tmp_file.into_file(), 0).unwrap();
+        assert_eq!(readahead.chunk_size, page_size);
+    }
+
+    #[test]
+    fn test_deadline_writer() {
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), MAX_MEMORY_WRITE_LEN + 0x1000)],
+            false,
+        )
+        .unwrap();
+        let region = guest_memory.find_region(GuestAddress(0)).unwrap();
+        let slice = region.as_volatile_slice().unwrap();
+        let mut file = TempFile::new().unwrap().into_file();
+
+        // The writes are capped, so that the deadline is checked between them.
+        let mut writer = DeadlineWriter {
+            file: &mut file,
+            deadline: None,
+        };
+        assert_eq!(writer.write_volatile(&slice).unwrap(), MAX_MEMORY_WRITE_LEN);
+        writer.write_all_volatile(&slice).unwrap();
+
+        let mut writer = DeadlineWriter {
+            file: &mut file,
+            deadline: Some(Instant::now()),
+        };
+        assert!(matches!(
+            writer.write_volatile(&slice),
+            Err(VolatileMemoryError::IOError(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+    }
+
+    #[test]
+    fn test_write_suspend_marker() {
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let params = SuspendToDiskParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: dir.as_path().join("vmstate"),
+            mem_file_path: dir.as_path().join("mem"),
+            marker_path: dir.as_path().join("suspended.json"),
+        };
+        write_suspend_marker(&params, "1.5.0").unwrap();
+
+        let marker: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&params.marker_path).unwrap()).unwrap();
+        assert_eq!(marker["snapshot_type"], "Full");
+        assert_eq!(
+            marker["snapshot_path"],
+            params.snapshot_path.to_str().unwrap()
+        );
+        assert_eq!(
+            marker["mem_file_path"],
+            params.mem_file_path.to_str().unwrap()
+        );
+        assert_eq!(marker["vmm_version"], "1.5.0");
+        // Only the marker is left once renamed in place.
+        assert_eq!(std::fs::read_dir(dir.as_path()).unwrap().count(), 1);
+
+        // The marker is not written in a missing directory.
+        let params = SuspendToDiskParams {
+            marker_path: dir.as_path().join("missing").join("suspended.json"),
+            ..params
+        };
+        assert!(matches!(
+            write_suspend_marker(&params, "1.5.0"),
+            Err(CreateSnapshotError::SuspendMarker("open", _))
+        ));
     }
 
     #[test]
@@ -897,11 +3002,17 @@ mod tests {
         let err = SnapshotBackingFile("open", io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
-        #[cfg(target_arch = "x86_64")]
-        {
-            let err = TooManyDevices(0);
-            let _ = format!("{}{:?}", err, err);
-        }
+        let err = SuspendMarker("rename", io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SyncDirectory(PathBuf::from("/srv"), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = TimedOut(0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = IncompatibleVersion(String::from("v1.0.0"), vec![String::from("entropy device")]);
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
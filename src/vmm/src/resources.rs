@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::From;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
 
@@ -10,23 +13,48 @@ use mmds::ns::MmdsNetworkStack;
 use serde::{Deserialize, Serialize};
 use utils::net::ipv4addr::is_link_local_valid;
 
-use crate::cpu_config::templates::CustomCpuTemplate;
+use crate::cpu_config::templates::{
+    CpuTemplateType, CustomCpuTemplate, GetCpuTemplate, GetCpuTemplateError,
+};
 use crate::device_manager::persist::SharedDeviceType;
+use crate::seccomp_filters::SeccompFilterInfo;
 use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
     BootConfig, BootSource, BootSourceConfig, BootSourceConfigError,
 };
+use crate::vmm_config::confidential::{ConfidentialConfig, ConfidentialConfigError};
+use crate::vmm_config::deterministic_boot::{
+    DeterministicBootConfig, DeterministicBootConfigError,
+};
 use crate::vmm_config::drive::*;
+use crate::vmm_config::emergency_snapshot::{
+    EmergencySnapshotConfig, EmergencySnapshotConfigError,
+};
 use crate::vmm_config::entropy::*;
+use crate::vmm_config::gdb::{GdbConfig, GdbConfigError};
+use crate::vmm_config::guest_files::{GuestFilesConfig, GuestFilesConfigError};
+use crate::vmm_config::guest_freeze::{GuestFreezeConfig, GuestFreezeConfigError};
+use crate::vmm_config::identity::{IdentityConfig, IdentityConfigError};
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
+use crate::vmm_config::io_threads::{IoThreadsConfig, IoThreadsConfigError};
+use crate::vmm_config::landlock::{LandlockConfig, LandlockConfigError};
+use crate::vmm_config::logger::{init_logger, logger_config, LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{
     MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
 };
-use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
+use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugConfigError};
+use crate::vmm_config::memory_pressure::{MemoryPressureConfig, MemoryPressureConfigError};
+use crate::vmm_config::metrics::{init_metrics, metrics_config, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
+use crate::vmm_config::shared_dir::{SharedDirBuilder, SharedDirConfig, SharedDirError};
+use crate::vmm_config::shutdown_hooks::{ShutdownHooksConfig, ShutdownHooksConfigError};
+use crate::vmm_config::smbios::{SmbiosConfig, SmbiosConfigError};
+use crate::vmm_config::stall_detection::{StallDetectionConfig, StallDetectionConfigError};
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
+use crate::vmm_config::virtio_record::{VirtioRecordConfig, VirtioRecordConfigError};
 use crate::vmm_config::vsock::*;
+use crate::vmm_config::working_set::{WorkingSetConfig, WorkingSetConfigError};
 
 /// Errors encountered when configuring microVM resources.
 #[derive(Debug, thiserror::Error, derive_more::From)]
@@ -70,6 +98,106 @@ pub enum ResourcesError {
     /// Entropy device configuration error.
     #[error("Entropy device error: {0}")]
     EntropyDevice(EntropyDeviceError),
+    /// Memory hotplug configuration error.
+    #[error("Memory hotplug error: {0}")]
+    MemoryHotplug(MemoryHotplugConfigError),
+    /// Landlock configuration error.
+    #[error("Landlock error: {0}")]
+    Landlock(LandlockConfigError),
+    /// Shared directory configuration error.
+    #[error("Shared directory error: {0}")]
+    SharedDir(SharedDirError),
+    /// GDB stub configuration error.
+    #[error("GDB stub error: {0}")]
+    Gdb(GdbConfigError),
+    /// vCPU stall detection configuration error.
+    #[error("Stall detection error: {0}")]
+    StallDetection(StallDetectionConfigError),
+    /// Deterministic boot configuration error.
+    #[error("Deterministic boot error: {0}")]
+    DeterministicBoot(DeterministicBootConfigError),
+    /// Virtio recording configuration error.
+    #[error("Virtio recording error: {0}")]
+    VirtioRecord(VirtioRecordConfigError),
+    /// Working set estimation configuration error.
+    #[error("Working set estimation error: {0}")]
+    WorkingSet(WorkingSetConfigError),
+    /// I/O threads configuration error.
+    #[error("I/O threads error: {0}")]
+    IoThreads(IoThreadsConfigError),
+    /// SMBIOS configuration error.
+    #[error("SMBIOS error: {0}")]
+    Smbios(SmbiosConfigError),
+    /// Identity configuration error.
+    #[error("Identity error: {0}")]
+    Identity(IdentityConfigError),
+    /// TPM configuration error.
+    #[error("TPM error: {0}")]
+    Tpm(TpmConfigError),
+    /// Confidential computing configuration error.
+    #[error("Confidential computing error: {0}")]
+    Confidential(ConfidentialConfigError),
+    /// Shutdown hooks configuration error.
+    #[error("Shutdown hooks error: {0}")]
+    ShutdownHooks(ShutdownHooksConfigError),
+    /// Emergency snapshot configuration error.
+    #[error("Emergency snapshot error: {0}")]
+    EmergencySnapshot(EmergencySnapshotConfigError),
+    /// Host memory pressure configuration error.
+    #[error("Memory pressure error: {0}")]
+    MemoryPressure(MemoryPressureConfigError),
+    /// Guest files configuration error.
+    #[error("Guest files error: {0}")]
+    GuestFiles(GuestFilesConfigError),
+    /// Guest freeze configuration error.
+    #[error("Guest freeze error: {0}")]
+    GuestFreeze(GuestFreezeConfigError),
+}
+
+/// Errors found when validating a microVM configuration without creating the microVM.
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    /// The configuration of a resource is invalid.
+    #[error("{0}")]
+    Resources(#[from] ResourcesError),
+    /// The kernel image cannot be read.
+    #[error("The kernel image cannot be read: {0}")]
+    KernelRead(std::io::Error),
+    /// The kernel image is not in the format expected on this architecture.
+    #[error("The kernel image is not a valid {0} image.")]
+    KernelFormat(&'static str),
+    /// The CPU template cannot be used on this host.
+    #[error("The CPU template cannot be used on this host: {0}")]
+    CpuTemplate(GetCpuTemplateError),
+    /// The guest memory is larger than the memory of the host.
+    #[error("The guest memory size ({0} MiB) exceeds the host memory size ({1} MiB).")]
+    MemorySize(usize, u64),
+}
+
+/// All the errors found when validating a microVM configuration.
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The configuration is invalid")?;
+        for (idx, err) in self.0.iter().enumerate() {
+            write!(f, "{} {}", if idx == 0 { ":" } else { ";" }, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Custom CPU template of a configuration file.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CpuConfigSource {
+    /// Path to a file that contains the template.
+    Path(PathBuf),
+    /// The template itself.
+    Template(CustomCpuTemplate),
 }
 
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
@@ -82,7 +210,7 @@ pub struct VmmConfig {
     #[serde(rename = "boot-source")]
     boot_source: BootSourceConfig,
     #[serde(rename = "cpu-config")]
-    cpu_config: Option<PathBuf>,
+    cpu_config: Option<CpuConfigSource>,
     #[serde(rename = "logger")]
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
@@ -97,6 +225,86 @@ pub struct VmmConfig {
     vsock_device: Option<VsockDeviceConfig>,
     #[serde(rename = "entropy")]
     entropy_device: Option<EntropyDeviceConfig>,
+    #[serde(
+        rename = "memory-hotplug",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    memory_hotplug: Option<MemoryHotplugConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    landlock: Option<LandlockConfig>,
+    #[serde(rename = "shared-dirs", default, skip_serializing_if = "Vec::is_empty")]
+    shared_dirs: Vec<SharedDirConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gdb: Option<GdbConfig>,
+    #[serde(
+        rename = "stall-detection",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    stall_detection: Option<StallDetectionConfig>,
+    #[serde(
+        rename = "deterministic-boot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    deterministic_boot: Option<DeterministicBootConfig>,
+    #[serde(
+        rename = "virtio-record",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    virtio_record: Option<VirtioRecordConfig>,
+    #[serde(
+        rename = "working-set",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    working_set: Option<WorkingSetConfig>,
+    #[serde(
+        rename = "io-threads",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    io_threads: Option<IoThreadsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smbios: Option<SmbiosConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity: Option<IdentityConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tpm: Option<TpmConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidential: Option<ConfidentialConfig>,
+    #[serde(
+        rename = "shutdown-hooks",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    shutdown_hooks: Option<ShutdownHooksConfig>,
+    #[serde(
+        rename = "emergency-snapshot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    emergency_snapshot: Option<EmergencySnapshotConfig>,
+    #[serde(
+        rename = "memory-pressure",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    memory_pressure: Option<MemoryPressureConfig>,
+    #[serde(
+        rename = "guest-files",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    guest_files: Option<GuestFilesConfig>,
+    #[serde(
+        rename = "guest-freeze",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    guest_freeze: Option<GuestFreezeConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -117,6 +325,42 @@ pub struct VmResources {
     pub net_builder: NetBuilder,
     /// The entropy device builder.
     pub entropy: EntropyDeviceBuilder,
+    /// The memory hotplug configuration, the device itself is created when the VM starts.
+    pub memory_hotplug: Option<MemoryHotplugConfig>,
+    /// The Landlock configuration, the ruleset is enforced when the VM starts.
+    pub landlock: Option<LandlockConfig>,
+    /// The shared directory devices builder.
+    pub shared_dirs: SharedDirBuilder,
+    /// The GDB stub configuration, the stub starts listening when the VM starts.
+    pub gdb: Option<GdbConfig>,
+    /// The vCPU stall detection configuration, the detection starts with the VM.
+    pub stall_detection: Option<StallDetectionConfig>,
+    /// The deterministic boot configuration, applied to the devices and clocks when the VM boots.
+    pub deterministic_boot: Option<DeterministicBootConfig>,
+    /// The virtio recording configuration, the recording starts with the VM.
+    pub virtio_record: Option<VirtioRecordConfig>,
+    /// The working set estimation configuration, the sampling starts with the VM.
+    pub working_set: Option<WorkingSetConfig>,
+    /// The I/O threads configuration, the threads are spawned when the VM starts.
+    pub io_threads: Option<IoThreadsConfig>,
+    /// The SMBIOS configuration, the tables are written to guest memory when the VM boots.
+    pub smbios: Option<SmbiosConfig>,
+    /// The identity configuration, the UUID is exposed to the guest when the VM boots.
+    pub identity: Option<IdentityConfig>,
+    /// The TPM configuration, swtpm is connected to when the VM boots.
+    pub tpm: Option<TpmConfig>,
+    /// The confidential computing configuration, the launch flow runs when the VM boots.
+    pub confidential: Option<ConfidentialConfig>,
+    /// The shutdown hooks configuration, the hooks are armed when the VM starts.
+    pub shutdown_hooks: Option<ShutdownHooksConfig>,
+    /// The emergency snapshot configuration, the snapshot is taken on request once the VM runs.
+    pub emergency_snapshot: Option<EmergencySnapshotConfig>,
+    /// The host memory pressure configuration, the monitor starts with the VM.
+    pub memory_pressure: Option<MemoryPressureConfig>,
+    /// The guest files configuration, the files can be copied once the VM runs.
+    pub guest_files: Option<GuestFilesConfig>,
+    /// The guest freeze configuration, the filesystems can be frozen once the VM runs.
+    pub guest_freeze: Option<GuestFreezeConfig>,
     /// The optional Mmds data store.
     // This is initialised on demand (if ever used), so that we don't allocate it unless it's
     // actually used.
@@ -125,6 +369,8 @@ pub struct VmResources {
     pub mmds_size_limit: usize,
     /// Whether or not to load boot timer device.
     pub boot_timer: bool,
+    /// Description of the seccomp filters of the thread categories.
+    pub seccomp_filters: Vec<SeccompFilterInfo>,
 }
 
 impl VmResources {
@@ -155,10 +401,7 @@ impl VmResources {
         }
 
         if let Some(cpu_config) = vmm_config.cpu_config {
-            let cpu_config_json =
-                std::fs::read_to_string(cpu_config).map_err(ResourcesError::File)?;
-            let cpu_template = CustomCpuTemplate::try_from(cpu_config_json.as_str())?;
-            resources.set_custom_cpu_template(cpu_template);
+            resources.set_custom_cpu_template(load_cpu_template(cpu_config)?);
         }
 
         resources.build_boot_source(vmm_config.boot_source)?;
@@ -195,9 +438,314 @@ impl VmResources {
             resources.build_entropy_device(entropy_device_config)?;
         }
 
+        if let Some(memory_hotplug_config) = vmm_config.memory_hotplug {
+            resources.set_memory_hotplug_config(memory_hotplug_config)?;
+        }
+
+        if let Some(landlock_config) = vmm_config.landlock {
+            resources.set_landlock_config(landlock_config)?;
+        }
+
+        for shared_dir_config in vmm_config.shared_dirs.into_iter() {
+            resources.build_shared_dir(shared_dir_config)?;
+        }
+
+        if let Some(gdb_config) = vmm_config.gdb {
+            resources.set_gdb_config(gdb_config)?;
+        }
+
+        if let Some(stall_detection_config) = vmm_config.stall_detection {
+            resources.set_stall_detection_config(stall_detection_config)?;
+        }
+
+        if let Some(deterministic_boot_config) = vmm_config.deterministic_boot {
+            resources.set_deterministic_boot_config(deterministic_boot_config)?;
+        }
+
+        if let Some(virtio_record_config) = vmm_config.virtio_record {
+            resources.set_virtio_record_config(virtio_record_config)?;
+        }
+
+        if let Some(working_set_config) = vmm_config.working_set {
+            resources.set_working_set_config(working_set_config)?;
+        }
+
+        if let Some(io_threads_config) = vmm_config.io_threads {
+            resources.set_io_threads_config(io_threads_config)?;
+        }
+
+        if let Some(smbios_config) = vmm_config.smbios {
+            resources.set_smbios_config(smbios_config)?;
+        }
+
+        if let Some(identity_config) = vmm_config.identity {
+            resources.set_identity_config(identity_config)?;
+        }
+
+        if let Some(tpm_config) = vmm_config.tpm {
+            resources.set_tpm_config(tpm_config)?;
+        }
+
+        if let Some(confidential_config) = vmm_config.confidential {
+            resources.set_confidential_config(confidential_config)?;
+        }
+
+        if let Some(shutdown_hooks_config) = vmm_config.shutdown_hooks {
+            resources.set_shutdown_hooks_config(shutdown_hooks_config)?;
+        }
+
+        if let Some(emergency_snapshot_config) = vmm_config.emergency_snapshot {
+            resources.set_emergency_snapshot_config(emergency_snapshot_config)?;
+        }
+
+        if let Some(memory_pressure_config) = vmm_config.memory_pressure {
+            resources.set_memory_pressure_config(memory_pressure_config)?;
+        }
+
+        if let Some(guest_files_config) = vmm_config.guest_files {
+            resources.set_guest_files_config(guest_files_config)?;
+        }
+
+        if let Some(guest_freeze_config) = vmm_config.guest_freeze {
+            resources.set_guest_freeze_config(guest_freeze_config)?;
+        }
+
         Ok(resources)
     }
 
+    /// Checks the configuration described by `vmm_config` without creating the microVM, nor
+    /// initializing the logger and the metrics.
+    ///
+    /// Unlike `from_json`, the checks do not stop at the first error: all the errors found are
+    /// returned. Besides the checks done when configuring each resource, the kernel image format,
+    /// the compatibility of the CPU template with the host and the memory size are checked.
+    pub fn validate_config(
+        vmm_config: VmmConfig,
+        instance_info: &InstanceInfo,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        let mut check = |result: Result<(), ResourcesError>| {
+            if let Err(err) = result {
+                errors.push(ValidationError::Resources(err));
+            }
+        };
+        let mut resources = Self::default();
+
+        let paths = vmm_config
+            .logger
+            .iter()
+            .map(|logger| &logger.log_path)
+            .chain(
+                vmm_config
+                    .metrics
+                    .iter()
+                    .map(|metrics| &metrics.metrics_path),
+            );
+        for path in paths {
+            check(
+                std::fs::metadata(path)
+                    .map(|_| ())
+                    .map_err(ResourcesError::File),
+            );
+        }
+
+        if let Some(machine_config) = vmm_config.machine_config {
+            let machine_config = MachineConfigUpdate::from(machine_config);
+            check(
+                resources
+                    .update_vm_config(&machine_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(cpu_config) = vmm_config.cpu_config {
+            check(
+                load_cpu_template(cpu_config)
+                    .map(|cpu_template| resources.set_custom_cpu_template(cpu_template)),
+            );
+        }
+        check(
+            resources
+                .build_boot_source(vmm_config.boot_source)
+                .map_err(Into::into),
+        );
+        for drive_config in vmm_config.block_devices.into_iter() {
+            check(resources.set_block_device(drive_config).map_err(Into::into));
+        }
+        for net_config in vmm_config.net_devices.into_iter() {
+            check(resources.build_net_device(net_config).map_err(Into::into));
+        }
+        if let Some(vsock_config) = vmm_config.vsock_device {
+            check(resources.set_vsock_device(vsock_config).map_err(Into::into));
+        }
+        if let Some(balloon_config) = vmm_config.balloon_device {
+            check(
+                resources
+                    .set_balloon_device(balloon_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(mmds_config) = vmm_config.mmds_config {
+            check(
+                resources
+                    .set_mmds_config(mmds_config, &instance_info.id)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(entropy_device_config) = vmm_config.entropy_device {
+            check(
+                resources
+                    .build_entropy_device(entropy_device_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(memory_hotplug_config) = vmm_config.memory_hotplug {
+            check(
+                resources
+                    .set_memory_hotplug_config(memory_hotplug_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(landlock_config) = vmm_config.landlock {
+            check(
+                resources
+                    .set_landlock_config(landlock_config)
+                    .map_err(Into::into),
+            );
+        }
+        for shared_dir_config in vmm_config.shared_dirs.into_iter() {
+            check(
+                resources
+                    .build_shared_dir(shared_dir_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(gdb_config) = vmm_config.gdb {
+            check(resources.set_gdb_config(gdb_config).map_err(Into::into));
+        }
+        if let Some(stall_detection_config) = vmm_config.stall_detection {
+            check(
+                resources
+                    .set_stall_detection_config(stall_detection_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(deterministic_boot_config) = vmm_config.deterministic_boot {
+            check(
+                resources
+                    .set_deterministic_boot_config(deterministic_boot_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(virtio_record_config) = vmm_config.virtio_record {
+            check(
+                resources
+                    .set_virtio_record_config(virtio_record_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(working_set_config) = vmm_config.working_set {
+            check(
+                resources
+                    .set_working_set_config(working_set_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(io_threads_config) = vmm_config.io_threads {
+            check(
+                resources
+                    .set_io_threads_config(io_threads_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(smbios_config) = vmm_config.smbios {
+            check(
+                resources
+                    .set_smbios_config(smbios_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(identity_config) = vmm_config.identity {
+            check(
+                resources
+                    .set_identity_config(identity_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(tpm_config) = vmm_config.tpm {
+            check(resources.set_tpm_config(tpm_config).map_err(Into::into));
+        }
+        if let Some(confidential_config) = vmm_config.confidential {
+            check(
+                resources
+                    .set_confidential_config(confidential_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(shutdown_hooks_config) = vmm_config.shutdown_hooks {
+            check(
+                resources
+                    .set_shutdown_hooks_config(shutdown_hooks_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(emergency_snapshot_config) = vmm_config.emergency_snapshot {
+            check(
+                resources
+                    .set_emergency_snapshot_config(emergency_snapshot_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(memory_pressure_config) = vmm_config.memory_pressure {
+            check(
+                resources
+                    .set_memory_pressure_config(memory_pressure_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(guest_files_config) = vmm_config.guest_files {
+            check(
+                resources
+                    .set_guest_files_config(guest_files_config)
+                    .map_err(Into::into),
+            );
+        }
+        if let Some(guest_freeze_config) = vmm_config.guest_freeze {
+            check(
+                resources
+                    .set_guest_freeze_config(guest_freeze_config)
+                    .map_err(Into::into),
+            );
+        }
+
+        if let Some(kernel_file) = resources
+            .boot_source
+            .builder
+            .as_ref()
+            .and_then(|builder| builder.kernel_file.as_ref())
+        {
+            if let Err(err) = check_kernel_format(kernel_file) {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = resources.vm_config.cpu_template.get_cpu_template() {
+            errors.push(ValidationError::CpuTemplate(err));
+        }
+        if let Some(host_mem_size_mib) = host_mem_size_mib() {
+            if resources.vm_config.mem_size_mib as u64 > host_mem_size_mib {
+                errors.push(ValidationError::MemorySize(
+                    resources.vm_config.mem_size_mib,
+                    host_mem_size_mib,
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
     /// If not initialised, create the mmds data store with the default config.
     pub fn mmds_or_default(&mut self) -> &Arc<Mutex<Mmds>> {
         self.mmds
@@ -234,6 +782,11 @@ impl VmResources {
             SharedDeviceType::Entropy(entropy) => {
                 self.entropy.set_device(entropy);
             }
+            SharedDeviceType::MemoryHotplug(mem) => {
+                self.memory_hotplug = Some(MemoryHotplugConfig::from(
+                    &*mem.lock().expect("Poisoned lock"),
+                ));
+            }
         }
     }
 
@@ -260,12 +813,12 @@ impl VmResources {
         // The VM cannot have a memory size smaller than the target size
         // of the balloon device, if present.
         if self.balloon.get().is_some()
-            && self.vm_config.mem_size_mib
+            && (self.vm_config.mem_size_mib as u64) << 20
                 < self
                     .balloon
                     .get_config()
                     .map_err(|_| VmConfigError::InvalidVmState)?
-                    .amount_mib as usize
+                    .target_bytes()
         {
             return Err(VmConfigError::IncompatibleBalloonSize);
         }
@@ -321,6 +874,19 @@ impl VmResources {
         self.boot_source.builder.as_ref()
     }
 
+    /// Exports the complete configuration of the microVM, which can be passed back through
+    /// `--config-file` to create an equivalent microVM. On top of the configuration converted
+    /// from the resources, it includes the logger, the metrics and the custom CPU template.
+    pub fn export_config(&self) -> VmmConfig {
+        let mut config = VmmConfig::from(self);
+        config.logger = logger_config();
+        config.metrics = metrics_config();
+        if let Some(CpuTemplateType::Custom(cpu_template)) = &self.vm_config.cpu_template {
+            config.cpu_config = Some(CpuConfigSource::Template(cpu_template.clone()));
+        }
+        config
+    }
+
     /// Sets a balloon device to be attached when the VM starts.
     pub fn set_balloon_device(
         &mut self,
@@ -328,9 +894,12 @@ impl VmResources {
     ) -> Result<(), BalloonConfigError> {
         // The balloon cannot have a target size greater than the size of
         // the guest memory.
-        if config.amount_mib as usize > self.vm_config.mem_size_mib {
+        if config.target_bytes() > (self.vm_config.mem_size_mib as u64) << 20 {
             return Err(BalloonConfigError::TooManyPagesRequested);
         }
+        if self.confidential.is_some() {
+            return Err(BalloonConfigError::ConfidentialGuest);
+        }
 
         self.balloon.set(config)
     }
@@ -360,6 +929,14 @@ impl VmResources {
         self.block.insert(block_device_config)
     }
 
+    /// Updates the configuration of a block to be attached when the VM starts.
+    pub fn update_block_device(
+        &mut self,
+        block_device_update: BlockDeviceUpdateConfig,
+    ) -> Result<(), DriveError> {
+        self.block.update(block_device_update)
+    }
+
     /// Builds a network device to be attached when the VM starts.
     pub fn build_net_device(
         &mut self,
@@ -382,6 +959,152 @@ impl VmResources {
         self.entropy.insert(body)
     }
 
+    /// Sets the memory hotplug configuration, the device is created when the VM starts.
+    pub fn set_memory_hotplug_config(
+        &mut self,
+        config: MemoryHotplugConfig,
+    ) -> Result<(), MemoryHotplugConfigError> {
+        set_validated(&mut self.memory_hotplug, config, MemoryHotplugConfig::validate)
+    }
+
+    /// Sets the Landlock configuration, the ruleset is enforced when the VM starts.
+    pub fn set_landlock_config(
+        &mut self,
+        config: LandlockConfig,
+    ) -> Result<(), LandlockConfigError> {
+        set_validated(&mut self.landlock, config, LandlockConfig::validate)
+    }
+
+    /// Sets the GDB stub configuration, the stub starts listening when the VM starts.
+    pub fn set_gdb_config(&mut self, config: GdbConfig) -> Result<(), GdbConfigError> {
+        set_validated(&mut self.gdb, config, GdbConfig::validate)
+    }
+
+    /// Sets the vCPU stall detection configuration, the detection starts with the VM.
+    pub fn set_stall_detection_config(
+        &mut self,
+        config: StallDetectionConfig,
+    ) -> Result<(), StallDetectionConfigError> {
+        set_validated(&mut self.stall_detection, config, StallDetectionConfig::validate)
+    }
+
+    /// Sets the deterministic boot configuration, applied to the devices and clocks when the VM
+    /// boots.
+    pub fn set_deterministic_boot_config(
+        &mut self,
+        config: DeterministicBootConfig,
+    ) -> Result<(), DeterministicBootConfigError> {
+        set_validated(&mut self.deterministic_boot, config, DeterministicBootConfig::validate)
+    }
+
+    /// Sets the virtio recording configuration, the selected queues are recorded from boot.
+    pub fn set_virtio_record_config(
+        &mut self,
+        config: VirtioRecordConfig,
+    ) -> Result<(), VirtioRecordConfigError> {
+        set_validated(&mut self.virtio_record, config, VirtioRecordConfig::validate)
+    }
+
+    /// Sets the working set estimation configuration, the sampling starts with the VM.
+    pub fn set_working_set_config(
+        &mut self,
+        config: WorkingSetConfig,
+    ) -> Result<(), WorkingSetConfigError> {
+        set_validated(&mut self.working_set, config, WorkingSetConfig::validate)
+    }
+
+    /// Sets the I/O threads configuration, the threads are spawned when the VM starts.
+    pub fn set_io_threads_config(
+        &mut self,
+        config: IoThreadsConfig,
+    ) -> Result<(), IoThreadsConfigError> {
+        set_validated(&mut self.io_threads, config, IoThreadsConfig::validate)
+    }
+
+    /// Sets the SMBIOS configuration, the tables are written to guest memory when the VM boots.
+    pub fn set_smbios_config(&mut self, config: SmbiosConfig) -> Result<(), SmbiosConfigError> {
+        set_validated(&mut self.smbios, config, SmbiosConfig::validate)
+    }
+
+    /// Sets the identity configuration, the UUID is exposed to the guest when the VM boots.
+    /// A random UUID is generated when none is set, so that it stays the same across reboots.
+    pub fn set_identity_config(
+        &mut self,
+        mut config: IdentityConfig,
+    ) -> Result<(), IdentityConfigError> {
+        config.validate()?;
+        if config.uuid.is_none() {
+            config.generate_uuid()?;
+        }
+        self.identity = Some(config);
+        Ok(())
+    }
+
+    /// Sets the TPM configuration, swtpm is connected to when the VM boots.
+    pub fn set_tpm_config(&mut self, config: TpmConfig) -> Result<(), TpmConfigError> {
+        set_validated(&mut self.tpm, config, TpmConfig::validate)
+    }
+
+    /// Sets the confidential computing configuration, the launch flow runs when the VM boots.
+    pub fn set_confidential_config(
+        &mut self,
+        config: ConfidentialConfig,
+    ) -> Result<(), ConfidentialConfigError> {
+        config.validate()?;
+        if self.balloon.get().is_some() {
+            return Err(ConfidentialConfigError::BalloonConfigured);
+        }
+        self.confidential = Some(config);
+        Ok(())
+    }
+
+    /// Sets the shutdown hooks configuration, the hooks are armed when the VM starts.
+    pub fn set_shutdown_hooks_config(
+        &mut self,
+        config: ShutdownHooksConfig,
+    ) -> Result<(), ShutdownHooksConfigError> {
+        set_validated(&mut self.shutdown_hooks, config, ShutdownHooksConfig::validate)
+    }
+
+    /// Sets the emergency snapshot configuration, the snapshot is taken on request once the VM
+    /// runs.
+    pub fn set_emergency_snapshot_config(
+        &mut self,
+        config: EmergencySnapshotConfig,
+    ) -> Result<(), EmergencySnapshotConfigError> {
+        set_validated(&mut self.emergency_snapshot, config, EmergencySnapshotConfig::validate)
+    }
+
+    /// Sets the host memory pressure configuration, the monitor starts with the VM.
+    pub fn set_memory_pressure_config(
+        &mut self,
+        config: MemoryPressureConfig,
+    ) -> Result<(), MemoryPressureConfigError> {
+        set_validated(&mut self.memory_pressure, config, MemoryPressureConfig::validate)
+    }
+
+    /// Sets the guest files configuration, the files can be copied once the VM runs.
+    pub fn set_guest_files_config(
+        &mut self,
+        config: GuestFilesConfig,
+    ) -> Result<(), GuestFilesConfigError> {
+        set_validated(&mut self.guest_files, config, GuestFilesConfig::validate)
+    }
+
+    /// Sets the guest freeze configuration, the filesystems can be frozen once the VM runs.
+    pub fn set_guest_freeze_config(
+        &mut self,
+        config: GuestFreezeConfig,
+    ) -> Result<(), GuestFreezeConfigError> {
+        set_validated(&mut self.guest_freeze, config, GuestFreezeConfig::validate)
+    }
+
+    /// Builds a shared directory device to be attached when the VM starts.
+    pub fn build_shared_dir(&mut self, config: SharedDirConfig) -> Result<(), SharedDirError> {
+        let _ = self.shared_dirs.build(config)?;
+        Ok(())
+    }
+
     /// Setter for mmds config.
     pub fn set_mmds_config(
         &mut self,
@@ -457,6 +1180,49 @@ impl VmResources {
     }
 }
 
+// Reads the custom CPU template of a configuration file.
+fn load_cpu_template(cpu_config: CpuConfigSource) -> Result<CustomCpuTemplate, ResourcesError> {
+    match cpu_config {
+        CpuConfigSource::Path(path) => {
+            let cpu_config_json = std::fs::read_to_string(path).map_err(ResourcesError::File)?;
+            Ok(CustomCpuTemplate::try_from(cpu_config_json.as_str())?)
+        }
+        CpuConfigSource::Template(cpu_template) => {
+            cpu_template.validate()?;
+            Ok(cpu_template)
+        }
+    }
+}
+
+// Checks the magic number of the kernel image format loaded on this architecture.
+fn check_kernel_format(kernel_file: &File) -> Result<(), ValidationError> {
+    #[cfg(target_arch = "x86_64")]
+    let (offset, magic, format) = (0, b"\x7fELF", "ELF");
+    #[cfg(target_arch = "aarch64")]
+    let (offset, magic, format) = (0x38, b"ARM\x64", "arm64 Image");
+
+    let mut header = [0u8; 4];
+    kernel_file
+        .read_exact_at(&mut header, offset)
+        .map_err(ValidationError::KernelRead)?;
+    if &header != magic {
+        return Err(ValidationError::KernelFormat(format));
+    }
+    Ok(())
+}
+
+// Returns the size of the host memory, in MiB.
+fn host_mem_size_mib() -> Option<u64> {
+    // SAFETY: `sysconf` does not access memory and only returns a value.
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    // SAFETY: As above.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    let bytes = u64::try_from(pages)
+        .ok()?
+        .checked_mul(u64::try_from(page_size).ok()?)?;
+    Some(bytes >> 20)
+}
+
 impl From<&VmResources> for VmmConfig {
     fn from(resources: &VmResources) -> Self {
         VmmConfig {
@@ -471,10 +1237,40 @@ impl From<&VmResources> for VmmConfig {
             net_devices: resources.net_builder.configs(),
             vsock_device: resources.vsock.config(),
             entropy_device: resources.entropy.config(),
+            memory_hotplug: resources.memory_hotplug.clone(),
+            landlock: resources.landlock.clone(),
+            shared_dirs: resources.shared_dirs.configs(),
+            gdb: resources.gdb.clone(),
+            stall_detection: resources.stall_detection.clone(),
+            deterministic_boot: resources.deterministic_boot.clone(),
+            virtio_record: resources.virtio_record.clone(),
+            working_set: resources.working_set.clone(),
+            io_threads: resources.io_threads.clone(),
+            smbios: resources.smbios.clone(),
+            identity: resources.identity.clone(),
+            tpm: resources.tpm.clone(),
+            confidential: resources.confidential.clone(),
+            shutdown_hooks: resources.shutdown_hooks.clone(),
+            emergency_snapshot: resources.emergency_snapshot.clone(),
+            memory_pressure: resources.memory_pressure.clone(),
+            guest_files: resources.guest_files.clone(),
+            guest_freeze: resources.guest_freeze.clone(),
         }
     }
 }
 
+/// Validates `config` and stores it in `slot`. An invalid configuration leaves the previous one in
+/// place.
+fn set_validated<T, E>(
+    slot: &mut Option<T>,
+    config: T,
+    validate: impl FnOnce(&T) -> Result<(), E>,
+) -> Result<(), E> {
+    validate(&config)?;
+    *slot = Some(config);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -490,12 +1286,19 @@ mod tests {
     use super::*;
     use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
     use crate::devices::virtio::vsock::VSOCK_DEV_ID;
+    use crate::devices::virtio::IO_URING_NUM_ENTRIES;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{
         BootConfig, BootSource, BootSourceConfig, DEFAULT_KERNEL_CMDLINE,
     };
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, FileEngineType};
-    use crate::vmm_config::machine_config::{MachineConfig, VmConfigError};
+    use crate::vmm_config::confidential::ConfidentialTechnology;
+    use crate::vmm_config::drive::{
+        BlockBuilder, BlockDeviceConfig, FadvisePolicy, FileEngineType,
+    };
+    use crate::vmm_config::machine_config::{
+        CpuBandwidth, MachineConfig, SchedPolicy, ThreadScheduling, VmConfigError,
+        MAX_SUPPORTED_VCPUS,
+    };
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
@@ -515,6 +1318,12 @@ mod tests {
             guest_mac: Some(MacAddr::from_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         }
     }
 
@@ -537,6 +1346,13 @@ mod tests {
                 is_read_only: false,
                 rate_limiter: Some(RateLimiterConfig::default()),
                 file_engine_type: FileEngineType::default(),
+                direct_io: false,
+                fadvise: FadvisePolicy::default(),
+                readahead_kib: 0,
+                io_uring_entries: IO_URING_NUM_ENTRIES,
+                max_in_flight_requests: 0,
+                busy_poll_us: 0,
+                batch_size: 0,
             },
             tmp_file,
         )
@@ -557,8 +1373,10 @@ mod tests {
             config: BootSourceConfig::default(),
             builder: Some(BootConfig {
                 cmdline: kernel_cmdline,
-                kernel_file: File::open(tmp_file.as_path()).unwrap(),
-                initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+                kernel_file: Some(File::open(tmp_file.as_path()).unwrap()),
+                pvh_firmware_file: None,
+                initrd_files: vec![File::open(tmp_file.as_path()).unwrap()],
+                pvh_boot: false,
             }),
         }
     }
@@ -575,28 +1393,55 @@ mod tests {
             boot_timer: false,
             mmds_size_limit: HTTP_MAX_PAYLOAD_SIZE,
             entropy: Default::default(),
+            memory_hotplug: None,
+            landlock: None,
+            shared_dirs: Default::default(),
+            gdb: None,
+            stall_detection: None,
+            deterministic_boot: None,
+            virtio_record: None,
+            working_set: None,
+            io_threads: None,
+            smbios: None,
+            identity: None,
+            tpm: None,
+            confidential: None,
+            shutdown_hooks: None,
+            emergency_snapshot: None,
+            memory_pressure: None,
+            guest_files: None,
+            guest_freeze: None,
+            seccomp_filters: Vec::new(),
         }
     }
 
     impl PartialEq for BootConfig {
         fn eq(&self, other: &Self) -> bool {
             self.cmdline.eq(&other.cmdline)
-                && self.kernel_file.metadata().unwrap().st_ino()
-                    == other.kernel_file.metadata().unwrap().st_ino()
                 && self
-                    .initrd_file
+                    .kernel_file
                     .as_ref()
                     .unwrap()
                     .metadata()
                     .unwrap()
                     .st_ino()
                     == other
-                        .initrd_file
+                        .kernel_file
                         .as_ref()
                         .unwrap()
                         .metadata()
                         .unwrap()
                         .st_ino()
+                && self.pvh_firmware_file.is_none() == other.pvh_firmware_file.is_none()
+                && self.pvh_boot == other.pvh_boot
+                && self
+                    .initrd_files
+                    .iter()
+                    .map(|file| file.metadata().unwrap().st_ino())
+                    .eq(other
+                        .initrd_files
+                        .iter()
+                        .map(|file| file.metadata().unwrap().st_ino()))
         }
     }
 
@@ -1131,6 +1976,169 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_export_config() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let default_instance_info = InstanceInfo::default();
+
+        // The custom CPU template can be passed inline.
+        let json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "cpu-config": {{}},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 256
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap(),
+        );
+        let vm_resources = VmResources::from_json(
+            json.as_str(),
+            &default_instance_info,
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+        )
+        .unwrap();
+
+        let mut config = vm_resources.export_config();
+        assert_eq!(
+            config.cpu_config,
+            Some(CpuConfigSource::Template(CustomCpuTemplate::default()))
+        );
+        assert_eq!(config.logger, logger_config());
+        assert_eq!(config.metrics, metrics_config());
+
+        // The logger and the metrics can only be initialized once per process.
+        config.logger = None;
+        config.metrics = None;
+        let exported_json = serde_json::to_string(&config).unwrap();
+        let restored_resources = VmResources::from_json(
+            exported_json.as_str(),
+            &default_instance_info,
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+        )
+        .unwrap();
+        let mut restored_config = restored_resources.export_config();
+        restored_config.logger = None;
+        restored_config.metrics = None;
+        assert_eq!(restored_config, config);
+        assert_eq!(
+            restored_resources.vm_config.cpu_template,
+            Some(CpuTemplateType::Custom(CustomCpuTemplate::default()))
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let default_instance_info = InstanceInfo::default();
+        let config = |kernel_path: &str, rootfs_path: &str, mem_size_mib: usize| {
+            let json = format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": {}
+                    }}
+                }}"#,
+                kernel_path, rootfs_path, mem_size_mib
+            );
+            serde_json::from_str::<VmmConfig>(&json).unwrap()
+        };
+        let kernel_path = kernel_file.as_path().to_str().unwrap();
+        let rootfs_path = rootfs_file.as_path().to_str().unwrap();
+
+        // The kernel image is empty.
+        let errors = VmResources::validate_config(
+            config(kernel_path, rootfs_path, 256),
+            &default_instance_info,
+        )
+        .unwrap_err()
+        .0;
+        assert!(
+            matches!(errors.as_slice(), [ValidationError::KernelRead(_)]),
+            "{:?}",
+            errors
+        );
+
+        let mut header = vec![0u8; 64];
+        #[cfg(target_arch = "x86_64")]
+        header[..4].copy_from_slice(b"\x7fELF");
+        #[cfg(target_arch = "aarch64")]
+        header[0x38..0x3c].copy_from_slice(b"ARM\x64");
+        kernel_file.as_file().write_all(&header).unwrap();
+        VmResources::validate_config(
+            config(kernel_path, rootfs_path, 256),
+            &default_instance_info,
+        )
+        .unwrap();
+
+        // All the errors are reported.
+        let err = VmResources::validate_config(
+            config(kernel_path, "/invalid/path", usize::MAX >> 20),
+            &default_instance_info,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err.0.as_slice(),
+                [
+                    ValidationError::Resources(ResourcesError::BlockDevice(
+                        DriveError::InvalidBlockDevicePath(_)
+                    )),
+                    ValidationError::MemorySize(_, _),
+                ]
+            ),
+            "{:?}",
+            err
+        );
+        assert!(err
+            .to_string()
+            .starts_with("The configuration is invalid: "));
+
+        let err = VmResources::validate_config(
+            config("/invalid/path", rootfs_path, 256),
+            &default_instance_info,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err.0.as_slice(),
+                [ValidationError::Resources(ResourcesError::BootSource(
+                    BootSourceConfigError::InvalidKernelPath(_)
+                ))]
+            ),
+            "{:?}",
+            err
+        );
+    }
+
     #[test]
     fn test_cast_to_vmm_config() {
         // No mmds config.
@@ -1338,6 +2346,26 @@ mod tests {
             #[cfg(target_arch = "aarch64")]
             cpu_template: Some(StaticCpuTemplate::V1N1),
             track_dirty_pages: Some(false),
+            acpi: Some(false),
+            split_irqchip: Some(false),
+            gic_its: Some(false),
+            vcpu_scheduling: Some(ThreadScheduling {
+                policy: SchedPolicy::Idle,
+                nice: 0,
+                priority: 0,
+            }),
+            io_scheduling: Some(ThreadScheduling {
+                policy: SchedPolicy::Other,
+                nice: -5,
+                priority: 0,
+            }),
+            cpu_bandwidth: Some(CpuBandwidth {
+                quota_us: Some(20000),
+                period_us: 100000,
+            }),
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
         };
 
         assert_ne!(
@@ -1356,13 +2384,49 @@ mod tests {
             vm_resources.update_vm_config(&aux_vm_config),
             Err(VmConfigError::InvalidVcpuCount)
         );
-        aux_vm_config.vcpu_count = Some(33);
+        aux_vm_config.vcpu_count = Some(MAX_SUPPORTED_VCPUS + 1);
         assert_eq!(
             vm_resources.update_vm_config(&aux_vm_config),
             Err(VmConfigError::InvalidVcpuCount)
         );
         aux_vm_config.vcpu_count = Some(32);
 
+        // Invalid scheduling settings.
+        for (scheduling, err) in [
+            ((SchedPolicy::Other, -21, 0), VmConfigError::InvalidNice),
+            ((SchedPolicy::Batch, 20, 0), VmConfigError::InvalidNice),
+            ((SchedPolicy::Idle, 5, 0), VmConfigError::InvalidNice),
+            ((SchedPolicy::Fifo, 5, 10), VmConfigError::InvalidNice),
+            (
+                (SchedPolicy::Other, 0, 10),
+                VmConfigError::InvalidSchedPriority,
+            ),
+            (
+                (SchedPolicy::Fifo, 0, 0),
+                VmConfigError::InvalidSchedPriority,
+            ),
+            (
+                (SchedPolicy::Rr, 0, 100),
+                VmConfigError::InvalidSchedPriority,
+            ),
+        ] {
+            let (policy, nice, priority) = scheduling;
+            let mut update = aux_vm_config.clone();
+            update.io_scheduling = Some(ThreadScheduling {
+                policy,
+                nice,
+                priority,
+            });
+            assert_eq!(vm_resources.update_vm_config(&update), Err(err));
+        }
+        let mut update = aux_vm_config.clone();
+        update.vcpu_scheduling = Some(ThreadScheduling {
+            policy: SchedPolicy::Rr,
+            nice: 0,
+            priority: 99,
+        });
+        vm_resources.update_vm_config(&update).unwrap();
+
         // Invalid mem_size_mib.
         aux_vm_config.mem_size_mib = Some(0);
         assert_eq!(
@@ -1375,8 +2439,10 @@ mod tests {
         vm_resources
             .set_balloon_device(BalloonDeviceConfig {
                 amount_mib: 100,
+                amount_bytes: None,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 0,
+                free_page_hinting: false,
             })
             .unwrap();
         aux_vm_config.mem_size_mib = Some(90);
@@ -1396,8 +2462,10 @@ mod tests {
         vm_resources.balloon = BalloonBuilder::new();
         let mut new_balloon_cfg = BalloonDeviceConfig {
             amount_mib: 100,
+            amount_bytes: None,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
         };
         assert!(vm_resources.balloon.get().is_none());
         vm_resources
@@ -1418,6 +2486,20 @@ mod tests {
         let mut vm_resources = default_vm_resources();
         vm_resources.balloon = BalloonBuilder::new();
         new_balloon_cfg.amount_mib = 256;
+        assert!(vm_resources
+            .set_balloon_device(new_balloon_cfg.clone())
+            .is_err());
+
+        // The byte target takes precedence over `amount_mib`.
+        new_balloon_cfg.amount_bytes = Some((100 << 20) + 4096);
+        vm_resources
+            .set_balloon_device(new_balloon_cfg.clone())
+            .unwrap();
+        assert_eq!(
+            vm_resources.balloon.get_config().unwrap().target_bytes(),
+            (100 << 20) + 4096
+        );
+        new_balloon_cfg.amount_bytes = Some((128 << 20) + 4096);
         assert!(vm_resources.set_balloon_device(new_balloon_cfg).is_err());
     }
 
@@ -1436,6 +2518,100 @@ mod tests {
         assert_eq!(actual_entropy_cfg, entropy_device_cfg);
     }
 
+    #[test]
+    fn test_build_shared_dir() {
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let mut vm_resources = default_vm_resources();
+        let config = SharedDirConfig {
+            share_id: "fs".to_string(),
+            path_on_host: dir.as_path().to_path_buf(),
+            mount_tag: "share".to_string(),
+            is_read_only: true,
+        };
+
+        vm_resources.build_shared_dir(config.clone()).unwrap();
+        assert_eq!(vm_resources.shared_dirs.configs(), vec![config.clone()]);
+        assert_eq!(VmmConfig::from(&vm_resources).shared_dirs, vec![config]);
+    }
+
+    #[test]
+    fn test_set_validated() {
+        let validate = |config: &u32| if *config > 0 { Ok(()) } else { Err("null") };
+        let mut slot = None;
+        set_validated(&mut slot, 1, validate).unwrap();
+        assert_eq!(slot, Some(1));
+
+        // An invalid configuration leaves the previous one in place.
+        assert_eq!(set_validated(&mut slot, 0, validate), Err("null"));
+        assert_eq!(slot, Some(1));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_set_identity_config() {
+        let mut vm_resources = default_vm_resources();
+        // A UUID is generated when none is set.
+        vm_resources
+            .set_identity_config(IdentityConfig::default())
+            .unwrap();
+        let uuid = vm_resources
+            .identity
+            .as_ref()
+            .unwrap()
+            .uuid
+            .as_ref()
+            .unwrap();
+        assert_eq!(uuid.len(), 36);
+    }
+
+    #[test]
+    fn test_set_confidential_config() {
+        let mut vm_resources = default_vm_resources();
+        let config = ConfidentialConfig {
+            technology: ConfidentialTechnology::Tdx,
+            policy: 0,
+        };
+        // TDX is not supported, and neither is any technology on aarch64.
+        assert!(vm_resources.set_confidential_config(config).is_err());
+        assert_eq!(vm_resources.confidential, None);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            use crate::vmm_config::confidential::DEFAULT_SEV_SNP_POLICY;
+
+            let config = ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY,
+            };
+            let balloon_config = BalloonDeviceConfig {
+                amount_mib: 0,
+                amount_bytes: None,
+                deflate_on_oom: false,
+                stats_polling_interval_s: 0,
+                free_page_hinting: false,
+            };
+
+            // The balloon device and confidential microVMs exclude each other.
+            vm_resources
+                .set_balloon_device(balloon_config.clone())
+                .unwrap();
+            assert_eq!(
+                vm_resources.set_confidential_config(config.clone()),
+                Err(ConfidentialConfigError::BalloonConfigured)
+            );
+
+            let mut vm_resources = default_vm_resources();
+            vm_resources
+                .set_confidential_config(config.clone())
+                .unwrap();
+            assert_eq!(vm_resources.confidential, Some(config));
+            assert!(matches!(
+                vm_resources.set_balloon_device(balloon_config),
+                Err(BalloonConfigError::ConfidentialGuest)
+            ));
+        }
+    }
+
     #[test]
     fn test_boot_config() {
         let vm_resources = default_vm_resources();
@@ -1452,7 +2628,10 @@ mod tests {
         let expected_boot_cfg = BootSourceConfig {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
+            initrd_paths: Vec::new(),
             boot_args: Some(cmdline.to_string()),
+            pvh_firmware_path: None,
+            pvh_boot: false,
         };
 
         let mut vm_resources = default_vm_resources();
@@ -1467,13 +2646,9 @@ mod tests {
                 .as_bytes_with_nul(),
             [cmdline.as_bytes(), &[b'\0']].concat()
         );
-        assert_ne!(
-            boot_builder.kernel_file.metadata().unwrap().st_ino(),
-            tmp_ino
-        );
         assert_ne!(
             boot_builder
-                .initrd_file
+                .kernel_file
                 .as_ref()
                 .unwrap()
                 .metadata()
@@ -1481,6 +2656,10 @@ mod tests {
                 .st_ino(),
             tmp_ino
         );
+        assert_ne!(
+            boot_builder.initrd_files[0].metadata().unwrap().st_ino(),
+            tmp_ino
+        );
 
         vm_resources.build_boot_source(expected_boot_cfg).unwrap();
         let boot_source_builder = vm_resources.boot_source_builder().unwrap();
@@ -1492,13 +2671,9 @@ mod tests {
                 .as_bytes_with_nul(),
             [cmdline.as_bytes(), &[b'\0']].concat()
         );
-        assert_eq!(
-            boot_source_builder.kernel_file.metadata().unwrap().st_ino(),
-            tmp_ino
-        );
         assert_eq!(
             boot_source_builder
-                .initrd_file
+                .kernel_file
                 .as_ref()
                 .unwrap()
                 .metadata()
@@ -1506,6 +2681,13 @@ mod tests {
                 .st_ino(),
             tmp_ino
         );
+        assert_eq!(
+            boot_source_builder.initrd_files[0]
+                .metadata()
+                .unwrap()
+                .st_ino(),
+            tmp_ino
+        );
     }
 
     #[test]
@@ -11,39 +11,84 @@ use seccompiler::BpfThreadMap;
 use serde_json::Value;
 #[cfg(test)]
 use tests::{
-    build_and_boot_microvm, create_snapshot, restore_from_snapshot, MockVmRes as VmResources,
-    MockVmm as Vmm,
+    build_and_boot_microvm, create_coredump, create_snapshot, restore_from_snapshot,
+    MockVmRes as VmResources, MockVmm as Vmm,
 };
 
 use super::VmmError;
 #[cfg(not(test))]
 use super::{
-    builder::build_and_boot_microvm, persist::create_snapshot, persist::restore_from_snapshot,
-    resources::VmResources, Vmm,
+    builder::build_and_boot_microvm, coredump::create_coredump, persist::create_snapshot,
+    persist::restore_from_snapshot, resources::VmResources, Vmm,
 };
 use crate::builder::StartMicrovmError;
+use crate::cgroup::{apply_cpu_bandwidth, CpuBandwidthError};
+use crate::coredump::CoredumpError;
 use crate::cpu_config::templates::{CustomCpuTemplate, GuestConfigError};
-use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
-use crate::resources::VmmConfig;
+use crate::devices::virtio::mem::VirtioMemStatus;
+use crate::devices::virtio::net::capture::PacketCapture;
+use crate::guest_files::GuestFilesError;
+use crate::guest_freeze::GuestFreezeError;
+use crate::persist::{
+    publish_restore_info, write_suspend_marker, CreateSnapshotError, RestoreFromSnapshotError,
+    UffdHandoverError, VmInfo,
+};
+use crate::resources::{ValidationErrors, VmmConfig};
+use crate::seccomp_filters::{SeccompFilterInfo, SeccompFilterStatus};
+use crate::snapshot_manifest::{SnapshotManifest, SnapshotManifestError};
 use crate::version_map::VERSION_MAP;
 use crate::vmm_config::balloon::{
-    BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
-    BalloonUpdateStatsConfig,
+    BalloonConfigError, BalloonDeviceConfig, BalloonHintingStatus, BalloonStats, BalloonTarget,
+    BalloonUpdateConfig, BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
+use crate::vmm_config::confidential::{
+    ConfidentialConfig, ConfidentialConfigError, ConfidentialInfo,
+};
+use crate::vmm_config::coredump::CoredumpParams;
+use crate::vmm_config::deterministic_boot::{
+    DeterministicBootConfig, DeterministicBootConfigError,
+};
 use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
+use crate::vmm_config::emergency_snapshot::EmergencySnapshotConfig;
 use crate::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceError};
-use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
+use crate::vmm_config::gdb::{GdbConfig, GdbConfigError};
+use crate::vmm_config::guest_files::{
+    GuestFileCopyParams, GuestFilesConfig, GuestFilesConfigError,
+};
+use crate::vmm_config::guest_freeze::{
+    GuestFreezeConfig, GuestFreezeConfigError, GuestFreezeState, GuestFreezeUpdate,
+};
+use crate::vmm_config::health::Health;
+use crate::vmm_config::identity::{IdentityConfig, IdentityConfigError};
+use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::io_threads::{IoThreadsConfig, IoThreadsConfigError};
+use crate::vmm_config::landlock::{LandlockConfig, LandlockConfigError};
+use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError, LoggerUpdateConfig};
 use crate::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate, VmConfigError};
+use crate::vmm_config::memory_hotplug::{
+    MemoryHotplugConfig, MemoryHotplugConfigError, MemoryHotplugSizeUpdate,
+};
+use crate::vmm_config::memory_pressure::{MemoryPressureConfig, MemoryPressureConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
     NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
-use crate::vmm_config::{self, RateLimiterUpdate};
+use crate::vmm_config::shared_dir::{SharedDirConfig, SharedDirError};
+use crate::vmm_config::smbios::{SmbiosConfig, SmbiosConfigError};
+use crate::vmm_config::snapshot::{
+    CommitSnapshotParams, CreateSnapshotParams, LoadSnapshotParams, SnapshotType,
+    SuspendToDiskParams, UffdHandlerConfig,
+};
+use crate::vmm_config::stall_detection::{StallDetectionConfig, StallDetectionConfigError};
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
+use crate::vmm_config::virtio_record::{VirtioRecordConfig, VirtioRecordConfigError};
+use crate::vmm_config::vsock::{
+    VsockBuilder, VsockConfigError, VsockDeviceConfig, VsockDeviceUpdateConfig,
+};
+use crate::vmm_config::working_set::{WorkingSetConfig, WorkingSetConfigError};
+use crate::vmm_config::{self, DeviceRunState, RateLimiterUpdate};
 use crate::{EventManager, FcExitCode};
 
 /// This enum represents the public interface of the VMM. Each action contains various
@@ -59,25 +104,63 @@ pub enum VmmAction {
     /// Configure the metrics using as input the `MetricsConfig`. This action can only be called
     /// before the microVM has booted.
     ConfigureMetrics(MetricsConfig),
+    /// Bind the parameters of `CommitSnapshotParams` to a paused microVM restored from a
+    /// snapshot, then resume it. This action can only be called after the microVM has been
+    /// restored or has booted.
+    CommitSnapshot(CommitSnapshotParams),
+    /// Copy a file from or to the guest using as input the `GuestFileCopyParams`. This action is
+    /// served by the API thread, and can only be called after the microVM has booted.
+    CopyGuestFile(GuestFileCopyParams),
+    /// Dump the guest memory and the vCPU registers in the ELF core format, using as input the
+    /// `CoredumpParams`. This action can only be called after the microVM has booted and only
+    /// when the microVM is in `Paused` state.
+    CreateCoredump(CoredumpParams),
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     CreateSnapshot(CreateSnapshotParams),
+    /// Suspend the microVM to disk to the paths of the emergency snapshot configuration, on a
+    /// best-effort basis. This action can only be called after the microVM has booted.
+    EmergencySnapshot,
+    /// Get the complete microVM configuration, including the logger, the metrics and the custom
+    /// CPU template, in the format of a configuration file.
+    ExportVmConfig,
     /// Get the balloon device configuration.
     GetBalloonConfig,
+    /// Get the progress of the latest free page hinting run of the balloon device.
+    GetBalloonHintingStatus,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the information about the launch of a confidential microVM. This action can only be
+    /// called after the microVM has booted.
+    GetConfidentialInfo,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
+    /// Get the health of the subsystems of the microVM.
+    GetHealth,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the state of the memory hotplug device.
+    GetMemoryHotplugStatus,
+    /// Get the status of the seccomp filters of all the thread categories.
+    GetSeccompFilters,
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
     /// Get microVM instance information.
     GetVmInstanceInfo,
     /// Get microVM version.
     GetVmmVersion,
+    /// Get the vsock device configuration, including the guest CID assigned to it.
+    GetVsockConfig,
+    /// Complete the requests in flight of the block devices with the given ids, or of all of
+    /// them, and flush their backing files. This action can only be called after the microVM
+    /// has booted.
+    FlushDrives(Option<Vec<String>>),
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
+    /// Hand the guest memory over to a new page fault handler using as input the
+    /// `UffdHandlerConfig`. This action can only be called after the microVM has been restored
+    /// from a snapshot with the `Uffd` memory backend.
+    HandoverUffdHandler(UffdHandlerConfig),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
     InsertBlockDevice(BlockDeviceConfig),
@@ -85,6 +168,9 @@ pub enum VmmAction {
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
     InsertNetworkDevice(NetworkInterfaceConfig),
+    /// Add a new shared directory or update one that already exists using the `SharedDirConfig`
+    /// as input. This action can only be called before the microVM has booted.
+    InsertSharedDir(SharedDirConfig),
     /// Load the microVM state using as input the `LoadSnapshotParams`. This action can only be
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
@@ -93,6 +179,8 @@ pub enum VmmAction {
     PatchMMDS(Value),
     /// Pause the guest, by pausing the microVM VCPUs.
     Pause,
+    /// Pause the microVM VCPUs, while the net and vsock devices keep serving the host.
+    PauseLite,
     /// Repopulate the MMDS contents.
     PutMMDS(Value),
     /// Configure the guest vCPU features.
@@ -103,6 +191,54 @@ pub enum VmmAction {
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the GDB stub configuration using `GdbConfig` as input. This action can only be called
+    /// before the microVM has booted.
+    SetGdb(GdbConfig),
+    /// Set the Landlock configuration using `LandlockConfig` as input. This action can only be
+    /// called before the microVM has booted or has been restored from a snapshot.
+    SetLandlock(LandlockConfig),
+    /// Set the vCPU stall detection configuration using `StallDetectionConfig` as input. This
+    /// action can only be called before the microVM has booted or has been restored from a
+    /// snapshot.
+    SetStallDetection(StallDetectionConfig),
+    /// Set the host memory pressure configuration using `MemoryPressureConfig` as input. This
+    /// action can only be called before the microVM has booted or has been restored from a
+    /// snapshot.
+    SetMemoryPressure(MemoryPressureConfig),
+    /// Set the guest files configuration using `GuestFilesConfig` as input. This action can only
+    /// be called before the microVM has booted or has been restored from a snapshot.
+    SetGuestFiles(GuestFilesConfig),
+    /// Set the guest freeze configuration using `GuestFreezeConfig` as input. This action can
+    /// only be called before the microVM has booted or has been restored from a snapshot.
+    SetGuestFreeze(GuestFreezeConfig),
+    /// Set the deterministic boot configuration using `DeterministicBootConfig` as input. This
+    /// action can only be called before the microVM has booted.
+    SetDeterministicBoot(DeterministicBootConfig),
+    /// Set the virtio recording configuration using `VirtioRecordConfig` as input. This action
+    /// can only be called before the microVM has booted.
+    SetVirtioRecord(VirtioRecordConfig),
+    /// Set the working set estimation configuration using `WorkingSetConfig` as input. This
+    /// action can only be called before the microVM has booted or has been restored from a
+    /// snapshot.
+    SetWorkingSet(WorkingSetConfig),
+    /// Set the I/O threads configuration using `IoThreadsConfig` as input. This action can only
+    /// be called before the microVM has booted or has been restored from a snapshot.
+    SetIoThreads(IoThreadsConfig),
+    /// Set the SMBIOS configuration using `SmbiosConfig` as input. This action can only be called
+    /// before the microVM has booted.
+    SetSmbios(SmbiosConfig),
+    /// Set the identity configuration using `IdentityConfig` as input. This action can only be
+    /// called before the microVM has booted or has been restored from a snapshot.
+    SetIdentity(IdentityConfig),
+    /// Set the TPM configuration using `TpmConfig` as input. This action can only be called
+    /// before the microVM has booted.
+    SetTpm(TpmConfig),
+    /// Set the confidential computing configuration using `ConfidentialConfig` as input. This
+    /// action can only be called before the microVM has booted.
+    SetConfidential(ConfidentialConfig),
+    /// Set the memory hotplug configuration using `MemoryHotplugConfig` as input. This action can
+    /// only be called before the microVM has booted.
+    SetMemoryHotplugDevice(MemoryHotplugConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
     /// Set the vsock device or update the one that already exists using the
@@ -114,22 +250,47 @@ pub enum VmmAction {
     SetEntropyDevice(EntropyDeviceConfig),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
+    /// Ask the guest to report its free pages to the balloon device, which discards them. This
+    /// action can only be called after the microVM has booted.
+    StartBalloonHinting,
+    /// Hand the free pages reported to the balloon device back to the guest. This action can
+    /// only be called after the microVM has booted.
+    StopBalloonHinting,
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
     SendCtrlAltDel,
+    /// Snapshot the microVM, write a marker once the snapshot files are durable and stop
+    /// Firecracker with the `Suspended` exit code, using as input the `SuspendToDiskParams`. This
+    /// action can only be called after the microVM has booted.
+    SuspendToDisk(SuspendToDiskParams),
     /// Update the balloon size, after microVM start.
     UpdateBalloon(BalloonUpdateConfig),
     /// Update the balloon statistics polling interval, after microVM start.
     UpdateBalloonStatistics(BalloonUpdateStatsConfig),
     /// Update existing block device properties such as `path_on_host` or `rate_limiter`.
     UpdateBlockDevice(BlockDeviceUpdateConfig),
+    /// Freeze or thaw the guest filesystems using as input the `GuestFreezeUpdate`. This action
+    /// is served by the API thread, and can only be called after the microVM has booted.
+    UpdateGuestFreeze(GuestFreezeUpdate),
+    /// Update the level and the module filters of the logger, using `LoggerUpdateConfig` as
+    /// input. This action can be called before and after the microVM has booted.
+    UpdateLogger(LoggerUpdateConfig),
+    /// Update the amount of hotplugged memory the guest should use, after microVM start.
+    UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
     /// Update the microVM configuration (memory & vcpu) using `VmUpdateConfig` as input. This
-    /// action can only be called before the microVM has booted.
+    /// action can only be called before the microVM has booted, except for an update of the CPU
+    /// bandwidth alone.
     UpdateVmConfiguration(MachineConfigUpdate),
+    /// Bind the host-side Unix socket of a vsock device restored from a snapshot with a deferred
+    /// socket, using `VsockDeviceUpdateConfig` as input.
+    UpdateVsockDevice(VsockDeviceUpdateConfig),
+    /// Check a complete microVM configuration, in the format of a configuration file, without
+    /// applying it. This action can only be called before the microVM has booted.
+    ValidateVmConfig(VmmConfig),
 }
 
 /// Wrapper for all errors associated with VMM actions.
@@ -141,12 +302,25 @@ pub enum VmmActionError {
     /// The action `ConfigureBootSource` failed because of bad user input.
     #[error("{0}")]
     BootSource(BootSourceConfigError),
+    /// The action `CreateCoredump` failed.
+    #[error("{0}")]
+    Coredump(CoredumpError),
     /// The action `CreateSnapshot` failed.
     #[error("{0}")]
     CreateSnapshot(CreateSnapshotError),
     /// The action `ConfigureCpu` failed.
     #[error("{0}")]
     ConfigureCpu(GuestConfigError),
+    /// The action `SetConfidential` failed because of bad user input, or `GetConfidentialInfo`
+    /// was called on a microVM which is not a confidential one.
+    #[error("{0}")]
+    Confidential(ConfidentialConfigError),
+    /// The CPU bandwidth of the microVM could not be applied to its cgroup.
+    #[error("{0}")]
+    CpuBandwidth(CpuBandwidthError),
+    /// The action `SetDeterministicBoot` failed because of bad user input.
+    #[error("{0}")]
+    DeterministicBoot(DeterministicBootConfigError),
     /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
     /// failed because of bad user input.
     #[error("{0}")]
@@ -154,19 +328,43 @@ pub enum VmmActionError {
     /// `SetEntropyDevice` action failed because of bad user input.
     #[error("{0}")]
     EntropyDevice(EntropyDeviceError),
+    /// The action `SetGdb` failed because of bad user input.
+    #[error("{0}")]
+    Gdb(GdbConfigError),
+    /// The action `CopyGuestFile` failed.
+    #[error("{0}")]
+    GuestFileCopy(GuestFilesError),
+    /// The action `SetGuestFiles` failed because of bad user input.
+    #[error("{0}")]
+    GuestFiles(GuestFilesConfigError),
+    /// The action `SetGuestFreeze` failed because of bad user input.
+    #[error("{0}")]
+    GuestFreeze(GuestFreezeConfigError),
+    /// The action `UpdateGuestFreeze` failed.
+    #[error("{0}")]
+    GuestFreezeUpdate(GuestFreezeError),
     /// Internal Vmm error.
     #[error("Internal Vmm error: {0}")]
     InternalVmm(VmmError),
+    /// The action `SetIoThreads` failed because of bad user input.
+    #[error("{0}")]
+    IoThreads(IoThreadsConfigError),
+    /// The action `SetLandlock` failed because of bad user input.
+    #[error("{0}")]
+    Landlock(LandlockConfigError),
     /// Loading a microVM snapshot failed.
     #[error("Load microVM snapshot error: {0}")]
     LoadSnapshot(LoadSnapshotError),
-    /// The action `ConfigureLogger` failed because of bad user input.
+    /// One of the actions `ConfigureLogger` or `UpdateLogger` failed because of bad user input.
     #[error("{0}")]
     Logger(LoggerConfigError),
     /// One of the actions `GetVmConfiguration` or `UpdateVmConfiguration` failed because of bad
     /// input.
     #[error("{0}")]
     MachineConfig(VmConfigError),
+    /// One of the memory hotplug actions failed.
+    #[error("{0}")]
+    MemoryHotplugConfig(MemoryHotplugConfigError),
     /// The action `ConfigureMetrics` failed because of bad user input.
     #[error("{0}")]
     Metrics(MetricsConfigError),
@@ -193,12 +391,42 @@ pub enum VmmActionError {
     /// The requested operation is not supported before starting the microVM.
     #[error("The requested operation is not supported before starting the microVM.")]
     OperationNotSupportedPreBoot,
+    /// The action `SetIdentity` failed because of bad user input.
+    #[error("{0}")]
+    Identity(IdentityConfigError),
+    /// The action `InsertSharedDir` failed because of bad user input.
+    #[error("{0}")]
+    SharedDir(SharedDirError),
+    /// The action `SetSmbios` failed because of bad user input.
+    #[error("{0}")]
+    Smbios(SmbiosConfigError),
+    /// The action `SetTpm` failed because of bad user input.
+    #[error("{0}")]
+    Tpm(TpmConfigError),
+    /// The action `SetStallDetection` failed because of bad user input.
+    #[error("{0}")]
+    StallDetection(StallDetectionConfigError),
+    /// The action `SetMemoryPressure` failed because of bad user input.
+    #[error("{0}")]
+    MemoryPressure(MemoryPressureConfigError),
     /// The action `StartMicroVm` failed because of an internal error.
     #[error("{0}")]
     StartMicrovm(StartMicrovmError),
-    /// The action `SetVsockDevice` failed because of bad user input.
+    /// The action `HandoverUffdHandler` failed.
+    #[error("{0}")]
+    UffdHandover(UffdHandoverError),
+    /// The action `ValidateVmConfig` found errors in the configuration.
+    #[error("{0}")]
+    ValidateVmConfig(ValidationErrors),
+    /// The action `SetVirtioRecord` failed because of bad user input.
+    #[error("{0}")]
+    VirtioRecord(VirtioRecordConfigError),
+    /// The action `SetVsockDevice` or `UpdateVsockDevice` failed because of bad user input.
     #[error("{0}")]
     VsockConfig(VsockConfigError),
+    /// The action `SetWorkingSet` failed because of bad user input.
+    #[error("{0}")]
+    WorkingSet(WorkingSetConfigError),
 }
 
 /// The enum represents the response sent by the VMM in case of success. The response is either
@@ -208,20 +436,32 @@ pub enum VmmActionError {
 pub enum VmmData {
     /// The balloon device configuration.
     BalloonConfig(BalloonDeviceConfig),
+    /// The progress of the latest free page hinting run of the balloon device.
+    BalloonHintingStatus(BalloonHintingStatus),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The information about the launch of a confidential microVM.
+    ConfidentialInfo(ConfidentialInfo),
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
+    /// The health of the subsystems of the microVM.
+    Health(Health),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(MachineConfig),
+    /// The state of the memory hotplug device.
+    MemoryHotplugStatus(VirtioMemStatus),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
+    /// The status of the seccomp filters.
+    SeccompFilters(Vec<SeccompFilterStatus>),
     /// The microVM version.
     VmmVersion(String),
+    /// The vsock device configuration.
+    VsockConfig(VsockDeviceConfig),
 }
 
 /// Trait used for deduplicating the MMDS request handling across the two ApiControllers.
@@ -307,6 +547,12 @@ pub enum LoadSnapshotError {
     /// Failed to resume microVM.
     #[error("Failed to resume microVM: {0}")]
     ResumeMicrovm(#[from] VmmError),
+    /// The snapshot was not loaded within the requested time.
+    #[error("The snapshot was not loaded within {0} ms.")]
+    TimedOut(u64),
+    /// Failed to read the manifest of the snapshot.
+    #[error("Failed to read the snapshot manifest: {0}")]
+    Manifest(#[from] SnapshotManifestError),
 }
 
 /// Shorthand type for a request containing a boxed VmmAction.
@@ -339,6 +585,7 @@ impl<'a> PrebootApiController<'a> {
     #[allow(clippy::too_many_arguments)]
     pub fn build_microvm_from_requests(
         seccomp_filters: &BpfThreadMap,
+        seccomp_filters_info: Vec<SeccompFilterInfo>,
         event_manager: &mut EventManager,
         instance_info: InstanceInfo,
         from_api: &std::sync::mpsc::Receiver<ApiRequest>,
@@ -356,6 +603,7 @@ impl<'a> PrebootApiController<'a> {
         {
             vm_resources.mmds_size_limit = mmds_size_limit;
             vm_resources.boot_timer = boot_timer_enabled;
+            vm_resources.seccomp_filters = seccomp_filters_info;
         }
 
         // Init the data store from file, if present.
@@ -374,12 +622,63 @@ impl<'a> PrebootApiController<'a> {
             info!("Successfully added metadata to mmds from file");
         }
 
+        Self::build_microvm(
+            seccomp_filters,
+            event_manager,
+            instance_info,
+            vm_resources,
+            from_api,
+            to_api,
+            api_event_fd,
+            false,
+        )
+    }
+
+    /// Builds and starts a microVM whose resources were configured from a config file.
+    ///
+    /// The resources can still be changed through API requests until the microVM is started.
+    /// Returns a populated `VmResources` object and a running `Vmm` object.
+    pub fn build_microvm_from_config(
+        seccomp_filters: &BpfThreadMap,
+        event_manager: &mut EventManager,
+        instance_info: InstanceInfo,
+        vm_resources: VmResources,
+        from_api: &std::sync::mpsc::Receiver<ApiRequest>,
+        to_api: &std::sync::mpsc::Sender<ApiResponse>,
+        api_event_fd: &utils::eventfd::EventFd,
+    ) -> Result<(VmResources, Arc<Mutex<Vmm>>), FcExitCode> {
+        // The config file always describes a microVM to boot, so loading a snapshot is not
+        // allowed.
+        Self::build_microvm(
+            seccomp_filters,
+            event_manager,
+            instance_info,
+            vm_resources,
+            from_api,
+            to_api,
+            api_event_fd,
+            true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_microvm(
+        seccomp_filters: &BpfThreadMap,
+        event_manager: &mut EventManager,
+        instance_info: InstanceInfo,
+        mut vm_resources: VmResources,
+        from_api: &std::sync::mpsc::Receiver<ApiRequest>,
+        to_api: &std::sync::mpsc::Sender<ApiResponse>,
+        api_event_fd: &utils::eventfd::EventFd,
+        boot_path: bool,
+    ) -> Result<(VmResources, Arc<Mutex<Vmm>>), FcExitCode> {
         let mut preboot_controller = PrebootApiController::new(
             seccomp_filters,
             instance_info,
             &mut vm_resources,
             event_manager,
         );
+        preboot_controller.boot_path = boot_path;
 
         // Configure and start microVM through successive API calls.
         // Iterate through API calls to configure microVm.
@@ -440,16 +739,25 @@ impl<'a> PrebootApiController<'a> {
                 );
                 Ok(VmmData::FullVmConfig((&*self.vm_resources).into()))
             }
+            ExportVmConfig => Ok(VmmData::FullVmConfig(self.vm_resources.export_config())),
             GetMMDS => self.get_mmds(),
+            GetSeccompFilters => Ok(self.seccomp_filters()),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
+            GetHealth => Ok(VmmData::Health(Health::not_started())),
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(self.instance_info.clone())),
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
+            GetVsockConfig => vsock_config(&self.vm_resources.vsock),
             InsertBlockDevice(config) => self.insert_block_device(config),
+            UpdateBlockDevice(config) => self.update_block_device(config),
+            UpdateLogger(update) => vmm_config::logger::update_logger(update)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::Logger),
             InsertNetworkDevice(config) => self.insert_net_device(config),
+            InsertSharedDir(config) => self.insert_shared_dir(config),
             LoadSnapshot(config) => self
-                .load_snapshot(&config)
+                .load_snapshot(config)
                 .map_err(VmmActionError::LoadSnapshot),
             PatchMMDS(value) => self.patch_mmds(value),
             PutCpuConfiguration(custom_cpu_template) => {
@@ -462,21 +770,64 @@ impl<'a> PrebootApiController<'a> {
             StartMicroVm => self.start_microvm(),
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             SetEntropyDevice(config) => self.set_entropy_device(config),
+            SetMemoryHotplugDevice(config) => self.set_memory_hotplug_device(config),
+            SetGdb(config) => self.set_gdb(config),
+            SetLandlock(config) => self.set_landlock(config),
+            SetStallDetection(config) => self.set_stall_detection(config),
+            SetMemoryPressure(config) => self.set_memory_pressure(config),
+            SetGuestFiles(config) => self.set_guest_files(config),
+            SetGuestFreeze(config) => self.set_guest_freeze(config),
+            SetDeterministicBoot(config) => self.set_deterministic_boot(config),
+            SetVirtioRecord(config) => self.set_virtio_record(config),
+            SetWorkingSet(config) => self.set_working_set(config),
+            SetIoThreads(config) => self.set_io_threads(config),
+            SetSmbios(config) => self.set_smbios(config),
+            SetIdentity(config) => self.set_identity(config),
+            SetTpm(config) => self.set_tpm(config),
+            SetConfidential(config) => self.set_confidential(config),
+            ValidateVmConfig(config) => VmResources::validate_config(config, &self.instance_info)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::ValidateVmConfig),
             // Operations not allowed pre-boot.
-            CreateSnapshot(_)
+            CommitSnapshot(_)
+            | CopyGuestFile(_)
+            | CreateCoredump(_)
+            | CreateSnapshot(_)
+            | EmergencySnapshot
+            | FlushDrives(_)
             | FlushMetrics
+            | HandoverUffdHandler(_)
             | Pause
+            | PauseLite
             | Resume
+            | SuspendToDisk(_)
+            | GetBalloonHintingStatus
             | GetBalloonStats
+            | GetConfidentialInfo
+            | GetMemoryHotplugStatus
+            | StartBalloonHinting
+            | StopBalloonHinting
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
-            | UpdateBlockDevice(_)
-            | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            | UpdateGuestFreeze(_)
+            | UpdateMemoryHotplugSize(_)
+            | UpdateNetworkInterface(_)
+            | UpdateVsockDevice(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
         }
     }
 
+    fn seccomp_filters(&self) -> VmmData {
+        VmmData::SeccompFilters(
+            self.vm_resources
+                .seccomp_filters
+                .iter()
+                .map(SeccompFilterInfo::status)
+                .collect(),
+        )
+    }
+
     fn balloon_config(&mut self) -> Result<VmmData, VmmActionError> {
         self.vm_resources
             .balloon
@@ -493,6 +844,17 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::DriveConfig)
     }
 
+    fn update_block_device(
+        &mut self,
+        cfg: BlockDeviceUpdateConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .update_block_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::DriveConfig)
+    }
+
     fn insert_net_device(
         &mut self,
         cfg: NetworkInterfaceConfig,
@@ -504,6 +866,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::NetworkConfig)
     }
 
+    fn insert_shared_dir(&mut self, cfg: SharedDirConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .build_shared_dir(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::SharedDir)
+    }
+
     fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> Result<VmmData, VmmActionError> {
         self.boot_path = true;
         self.vm_resources
@@ -558,6 +928,113 @@ impl<'a> PrebootApiController<'a> {
         Ok(VmmData::Empty)
     }
 
+    fn set_memory_hotplug_device(
+        &mut self,
+        cfg: MemoryHotplugConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_memory_hotplug_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_gdb(&mut self, cfg: GdbConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_gdb_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // The ruleset applies to both booted and restored microVMs, so this does not pick the
+    // boot path.
+    fn set_landlock(&mut self, cfg: LandlockConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_landlock_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Restored microVMs are watched as well, so this does not pick the boot path.
+    fn set_stall_detection(
+        &mut self,
+        cfg: StallDetectionConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_stall_detection_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Restored microVMs are relieved as well, so this does not pick the boot path.
+    fn set_memory_pressure(
+        &mut self,
+        cfg: MemoryPressureConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_memory_pressure_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // The files of restored microVMs can be copied as well, so this does not pick the boot path.
+    fn set_guest_files(&mut self, cfg: GuestFilesConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_guest_files_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // The filesystems of restored microVMs are thawed through it, so this does not pick the boot
+    // path.
+    fn set_guest_freeze(&mut self, cfg: GuestFreezeConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_guest_freeze_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Restored microVMs are sampled as well, so this does not pick the boot path.
+    fn set_working_set(&mut self, cfg: WorkingSetConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_working_set_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // The devices of restored microVMs are assigned as well, so this does not pick the boot path.
+    fn set_io_threads(&mut self, cfg: IoThreadsConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_io_threads_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_deterministic_boot(
+        &mut self,
+        cfg: DeterministicBootConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_deterministic_boot_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Restored microVMs keep the SMBIOS configuration saved in the snapshot.
+    fn set_smbios(&mut self, cfg: SmbiosConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_smbios_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Restored microVMs reconnect to the swtpm socket saved in the snapshot.
+    fn set_tpm(&mut self, cfg: TpmConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_tpm_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // Confidential microVMs cannot be restored from snapshots.
+    fn set_confidential(&mut self, cfg: ConfidentialConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_confidential_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    // A restored microVM takes this identity instead of the one saved in the snapshot.
+    fn set_identity(&mut self, cfg: IdentityConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_identity_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_virtio_record(&mut self, cfg: VirtioRecordConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_virtio_record_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn start_microvm(&mut self) -> Result<VmmData, VmmActionError> {
@@ -578,7 +1055,7 @@ impl<'a> PrebootApiController<'a> {
     // will be replaced by a runtime controller.
     fn load_snapshot(
         &mut self,
-        load_params: &LoadSnapshotParams,
+        mut load_params: LoadSnapshotParams,
     ) -> Result<VmmData, LoadSnapshotError> {
         log_dev_preview_warning("Virtual machine snapshots", Option::None);
 
@@ -590,7 +1067,17 @@ impl<'a> PrebootApiController<'a> {
             return Err(err);
         }
 
-        if load_params.enable_diff_snapshots {
+        // The manifest is read before anything is restored, so that a wrong one leaves the
+        // microVM unconfigured.
+        if let Some(manifest_path) = &load_params.manifest_path {
+            let manifest = SnapshotManifest::from_file(manifest_path)?;
+            manifest.check_images()?;
+            manifest.apply(&mut load_params);
+        }
+
+        // With the write-protect mode, the dirty pages are tracked through the userfaultfd
+        // object instead of KVM dirty logging.
+        if load_params.enable_diff_snapshots || load_params.mem_backend.write_protect {
             self.vm_resources.set_track_dirty_pages(true);
         }
 
@@ -599,7 +1086,7 @@ impl<'a> PrebootApiController<'a> {
             &self.instance_info,
             self.event_manager,
             self.seccomp_filters,
-            load_params,
+            &load_params,
             VERSION_MAP.clone(),
             self.vm_resources,
         )
@@ -608,6 +1095,14 @@ impl<'a> PrebootApiController<'a> {
             self.fatal_error = Some(FcExitCode::BadConfiguration);
             err
         })?;
+        // The restore cannot be interrupted, so a late one is aborted before the vCPUs run.
+        if let Some(timeout_ms) = load_params.timeout_ms {
+            let now_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+            if now_us.saturating_sub(load_start_us) >= timeout_ms.saturating_mul(1000) {
+                self.fatal_error = Some(FcExitCode::BadConfiguration);
+                return Err(LoadSnapshotError::TimedOut(timeout_ms));
+            }
+        }
         // Resume VM
         if load_params.resume_vm {
             vmm.lock()
@@ -656,46 +1151,75 @@ impl RuntimeApiController {
         use self::VmmAction::*;
         match request {
             // Supported operations allowed post-boot.
+            CommitSnapshot(params) => self.commit_snapshot(params),
+            CreateCoredump(params) => self.create_coredump(&params),
             CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
-            FlushMetrics => self.flush_metrics(),
-            GetBalloonConfig => self
-                .vmm
-                .lock()
-                .expect("Poisoned lock")
-                .balloon_config()
-                .map(|state| VmmData::BalloonConfig(BalloonDeviceConfig::from(state)))
-                .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
-            GetBalloonStats => self
+            FlushDrives(drive_ids) => self
                 .vmm
                 .lock()
                 .expect("Poisoned lock")
-                .latest_balloon_stats()
-                .map(VmmData::BalloonStats)
-                .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+                .flush_block_devices(drive_ids.as_deref())
+                .map(|()| VmmData::Empty)
+                .map_err(|err| VmmActionError::DriveConfig(DriveError::Flush(err))),
+            FlushMetrics => flush_metrics(),
+            GetBalloonConfig
+            | GetBalloonHintingStatus
+            | GetBalloonStats
+            | GetConfidentialInfo
+            | GetHealth
+            | GetMemoryHotplugStatus
+            | GetVmInstanceInfo
+            | GetVmmVersion => {
+                read_vmm_state(&mut self.vmm.lock().expect("Poisoned lock"), &request)
+            }
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
+            ExportVmConfig => Ok(VmmData::FullVmConfig(self.vm_resources.export_config())),
             GetMMDS => self.get_mmds(),
+            GetSeccompFilters => Ok(self.seccomp_filters()),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
-            GetVmInstanceInfo => Ok(VmmData::InstanceInformation(
-                self.vmm.lock().expect("Poisoned lock").instance_info(),
-            )),
-            GetVmmVersion => Ok(VmmData::VmmVersion(
-                self.vmm.lock().expect("Poisoned lock").version(),
-            )),
+            GetVsockConfig => vsock_config(&self.vm_resources.vsock),
+            HandoverUffdHandler(config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .handover_uffd_handler(&config.backend_path)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::UffdHandover),
             PatchMMDS(value) => self.patch_mmds(value),
             Pause => self.pause(),
+            PauseLite => self.pause_lite(),
             PutMMDS(value) => self.put_mmds(value),
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
-            UpdateBalloon(balloon_update) => self
+            StartBalloonHinting => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .start_balloon_hinting()
+                .map(|_| VmmData::Empty)
+                .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            StopBalloonHinting => self
                 .vmm
                 .lock()
                 .expect("Poisoned lock")
-                .update_balloon_config(balloon_update.amount_mib)
+                .stop_balloon_hinting()
                 .map(|_| VmmData::Empty)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            SuspendToDisk(params) => self.suspend_to_disk(&params),
+            EmergencySnapshot => self.emergency_snapshot(),
+            // The VMM thread has to serve the vsock device during the copy, so the copy is served
+            // by the API dispatcher thread.
+            CopyGuestFile(_) => Err(VmmActionError::NotSupported(
+                "Guest files can only be copied through the API.".to_string(),
+            )),
+            // Same for the requests to the guest agent freezing the filesystems.
+            UpdateGuestFreeze(_) => Err(VmmActionError::NotSupported(
+                "The guest filesystems can only be frozen through the API.".to_string(),
+            )),
+            UpdateBalloon(balloon_update) => self.update_balloon(balloon_update),
             UpdateBalloonStatistics(balloon_stats_update) => self
                 .vmm
                 .lock()
@@ -704,7 +1228,24 @@ impl RuntimeApiController {
                 .map(|_| VmmData::Empty)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
+            UpdateLogger(update) => vmm_config::logger::update_logger(update)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::Logger),
+            UpdateMemoryHotplugSize(update) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .update_memory_hotplug_size(update.requested_size_mib)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::MemoryHotplugConfig),
             UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateVmConfiguration(update) => self.update_cpu_bandwidth(update),
+            UpdateVsockDevice(update) => self
+                .vm_resources
+                .vsock
+                .update(update)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::VsockConfig),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
@@ -712,14 +1253,30 @@ impl RuntimeApiController {
             | ConfigureMetrics(_)
             | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
+            | InsertSharedDir(_)
             | LoadSnapshot(_)
             | PutCpuConfiguration(_)
             | SetBalloonDevice(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
             | SetEntropyDevice(_)
+            | SetMemoryHotplugDevice(_)
+            | SetGdb(_)
+            | SetLandlock(_)
+            | SetStallDetection(_)
+            | SetMemoryPressure(_)
+            | SetGuestFiles(_)
+            | SetGuestFreeze(_)
+            | SetDeterministicBoot(_)
+            | SetVirtioRecord(_)
+            | SetWorkingSet(_)
+            | SetIoThreads(_)
+            | SetSmbios(_)
+            | SetIdentity(_)
+            | SetTpm(_)
+            | SetConfidential(_)
             | StartMicroVm
-            | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            | ValidateVmConfig(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
     }
 
@@ -728,6 +1285,16 @@ impl RuntimeApiController {
         Self { vmm, vm_resources }
     }
 
+    fn seccomp_filters(&self) -> VmmData {
+        VmmData::SeccompFilters(
+            self.vm_resources
+                .seccomp_filters
+                .iter()
+                .map(SeccompFilterInfo::status)
+                .collect(),
+        )
+    }
+
     /// Pauses the microVM by pausing the vCPUs.
     pub fn pause(&mut self) -> Result<VmmData, VmmActionError> {
         let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
@@ -741,6 +1308,19 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
+    /// Pauses the vCPUs, while the net and vsock devices keep serving the host.
+    pub fn pause_lite(&mut self) -> Result<VmmData, VmmActionError> {
+        let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+
+        self.vmm.lock().expect("Poisoned lock").pause_vm_lite()?;
+
+        let elapsed_time_us =
+            update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
+        info!("'pause vm lite' VMM action took {} us.", elapsed_time_us);
+
+        Ok(VmmData::Empty)
+    }
+
     /// Resumes the microVM by resuming the vCPUs.
     pub fn resume(&mut self) -> Result<VmmData, VmmActionError> {
         let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
@@ -754,18 +1334,40 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
-    /// Write the metrics on user demand (flush). We use the word `flush` here to highlight the fact
-    /// that the metrics will be written immediately.
-    /// Defer to inner Vmm. We'll move to a variant where the Vmm simply exposes functionality like
-    /// getting the dirty pages, and then we'll have the metrics flushing logic entirely on the
-    /// outside.
-    fn flush_metrics(&mut self) -> Result<VmmData, VmmActionError> {
-        // FIXME: we're losing the bool saying whether metrics were actually written.
-        METRICS
-            .write()
-            .map(|_| VmmData::Empty)
-            .map_err(super::VmmError::Metrics)
-            .map_err(VmmActionError::InternalVmm)
+    /// Binds the late parameters of a microVM restored in the paused state, then resumes it. On
+    /// error, the microVM stays paused with the parameters bound until then.
+    fn commit_snapshot(&mut self, params: CommitSnapshotParams) -> Result<VmmData, VmmActionError> {
+        {
+            let mut vmm = self.vmm.lock().expect("Poisoned lock");
+            if vmm.instance_info().state != VmState::Paused {
+                return Err(VmmActionError::NotSupported(
+                    "Only a paused microVM can be committed.".to_string(),
+                ));
+            }
+            for drive in params.drive_overrides {
+                vmm.update_block_device_path(&drive.drive_id, drive.path_on_host)
+                    .map_err(DriveError::DeviceUpdate)?;
+            }
+        }
+        if let Some(uds_path) = params.vsock_uds_path {
+            self.vm_resources
+                .vsock
+                .update(VsockDeviceUpdateConfig { uds_path })
+                .map_err(VmmActionError::VsockConfig)?;
+        }
+        if let Some(data) = params.mmds {
+            self.put_mmds(Value::Object(data))?;
+        }
+        if let Some(restore_info) = params.restore_info {
+            publish_restore_info(&mut self.mmds(), &restore_info).map_err(|err| match err {
+                data_store::Error::DataStoreLimitExceeded => {
+                    VmmActionError::MmdsLimitExceeded(data_store::Error::DataStoreLimitExceeded)
+                }
+                _ => VmmActionError::Mmds(err),
+            })?;
+        }
+
+        self.resume()
     }
 
     /// Injects CTRL+ALT+DEL keystroke combo to the inner Vmm (if present).
@@ -779,6 +1381,13 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    fn create_coredump(&mut self, params: &CoredumpParams) -> Result<VmmData, VmmActionError> {
+        let mut locked_vmm = self.vmm.lock().expect("Poisoned lock");
+        create_coredump(&mut locked_vmm, params)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::Coredump)
+    }
+
     fn create_snapshot(
         &mut self,
         create_params: &CreateSnapshotParams,
@@ -830,15 +1439,126 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
+    /// Snapshots the microVM, pausing it first if needed, and writes the suspend marker once the
+    /// snapshot files are durable. Firecracker is then stopped with the `Suspended` exit code. On
+    /// error, the microVM is left in the state it was in.
+    fn suspend_to_disk(&mut self, params: &SuspendToDiskParams) -> Result<VmmData, VmmActionError> {
+        let was_running = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .instance_info()
+            .state
+            != VmState::Paused;
+        if was_running {
+            self.pause()?;
+        }
+
+        let create_params = CreateSnapshotParams {
+            snapshot_type: params.snapshot_type,
+            snapshot_path: params.snapshot_path.clone(),
+            mem_file_path: params.mem_file_path.clone(),
+            version: None,
+            timeout_ms: None,
+        };
+        let res = self.create_snapshot(&create_params).and_then(|_| {
+            let vmm_version = self.vmm.lock().expect("Poisoned lock").version();
+            write_suspend_marker(params, &vmm_version).map_err(VmmActionError::CreateSnapshot)
+        });
+        if let Err(err) = res {
+            if was_running {
+                if let Err(resume_err) = self.resume() {
+                    error!(
+                        "Cannot resume the microVM after failing to suspend it: {}",
+                        resume_err
+                    );
+                }
+            }
+            return Err(err);
+        }
+
+        info!("The microVM is suspended to disk, stopping.");
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .stop(FcExitCode::Suspended);
+        Ok(VmmData::Empty)
+    }
+
+    /// Suspends the microVM to disk to the paths of the emergency snapshot configuration.
+    fn emergency_snapshot(&mut self) -> Result<VmmData, VmmActionError> {
+        let params = self
+            .vm_resources
+            .emergency_snapshot
+            .as_ref()
+            .map(EmergencySnapshotConfig::suspend_params)
+            .ok_or_else(|| {
+                VmmActionError::NotSupported("No emergency snapshot is configured.".to_string())
+            })?;
+        warn!("Taking the emergency snapshot.");
+        self.suspend_to_disk(&params)
+    }
+
+    /// Updates the CPU bandwidth of the microVM, the only part of the machine configuration which
+    /// can change after boot.
+    fn update_cpu_bandwidth(
+        &mut self,
+        update: MachineConfigUpdate,
+    ) -> Result<VmmData, VmmActionError> {
+        let only_cpu_bandwidth = MachineConfigUpdate {
+            cpu_bandwidth: None,
+            ..update.clone()
+        }
+        .is_empty();
+        let cpu_bandwidth = match update.cpu_bandwidth {
+            Some(cpu_bandwidth) if only_cpu_bandwidth => cpu_bandwidth,
+            _ => return Err(VmmActionError::OperationNotSupportedPostBoot),
+        };
+
+        cpu_bandwidth
+            .validate()
+            .map_err(VmmActionError::MachineConfig)?;
+        apply_cpu_bandwidth(&cpu_bandwidth).map_err(VmmActionError::CpuBandwidth)?;
+        self.vm_resources.vm_config.cpu_bandwidth = Some(cpu_bandwidth);
+        Ok(VmmData::Empty)
+    }
+
+    /// Resizes the balloon device as described in `balloon_update`.
+    fn update_balloon(
+        &mut self,
+        balloon_update: BalloonUpdateConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        let target = balloon_update
+            .target()
+            .map_err(VmmActionError::BalloonConfig)?;
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        match target {
+            BalloonTarget::Size(amount_bytes) => vmm
+                .update_balloon_config(amount_bytes)
+                .map_err(BalloonConfigError::from),
+            BalloonTarget::UsableMemory(usable_bytes) => vmm.update_usable_memory(usable_bytes),
+        }
+        .map(|()| VmmData::Empty)
+        .map_err(VmmActionError::BalloonConfig)
+    }
+
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device, update the disk image on the
     ///    device and its virtio configuration
-    ///  - rate limiter configuration.
+    ///  - rate limiter configuration
+    ///  - processing state; the device is paused before and resumed after the other updates.
     fn update_block_device(
         &mut self,
         new_cfg: BlockDeviceUpdateConfig,
     ) -> Result<VmmData, VmmActionError> {
+        if new_cfg.cache_type.is_some() || new_cfg.direct_io.is_some() {
+            return Err(VmmActionError::DriveConfig(DriveError::CachePolicyUpdate));
+        }
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        if new_cfg.state == Some(DeviceRunState::Paused) {
+            vmm.update_block_device_state(&new_cfg.drive_id, DeviceRunState::Paused)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
         if let Some(new_path) = new_cfg.path_on_host {
             vmm.update_block_device_path(&new_cfg.drive_id, new_path)
                 .map(|()| VmmData::Empty)
@@ -853,50 +1573,207 @@ impl RuntimeApiController {
             .map(|()| VmmData::Empty)
             .map_err(DriveError::DeviceUpdate)?;
         }
+        if new_cfg.state == Some(DeviceRunState::Resumed) {
+            vmm.update_block_device_state(&new_cfg.drive_id, DeviceRunState::Resumed)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
         Ok(VmmData::Empty)
     }
 
-    /// Updates configuration for an emulated net device as described in `new_cfg`.
+    /// Updates configuration for an emulated net device as described in `new_cfg`. The device is
+    /// paused before and resumed after the rate limiters are updated.
     fn update_net_rate_limiters(
         &mut self,
         new_cfg: NetworkInterfaceUpdateConfig,
     ) -> Result<VmmData, VmmActionError> {
-        self.vmm
-            .lock()
-            .expect("Poisoned lock")
-            .update_net_rate_limiters(
-                &new_cfg.iface_id,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
-            )
-            .map(|()| VmmData::Empty)
-            .map_err(NetworkInterfaceError::DeviceUpdate)
-            .map_err(VmmActionError::NetworkConfig)
-    }
-}
-
-#[cfg(test)]
-mod tests {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        if new_cfg.state == Some(DeviceRunState::Paused) {
+            vmm.update_net_device_state(&new_cfg.iface_id, DeviceRunState::Paused)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        vmm.update_net_rate_limiters(
+            &new_cfg.iface_id,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+        )
+        .map_err(NetworkInterfaceError::DeviceUpdate)
+        .map_err(VmmActionError::NetworkConfig)?;
+        if let Some(capture_cfg) = new_cfg.capture {
+            let capture = PacketCapture::new(capture_cfg)
+                .map_err(NetworkInterfaceError::PacketCapture)
+                .map_err(VmmActionError::NetworkConfig)?;
+            vmm.update_net_capture(&new_cfg.iface_id, capture)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        if new_cfg.state == Some(DeviceRunState::Resumed) {
+            vmm.update_net_device_state(&new_cfg.iface_id, DeviceRunState::Resumed)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        Ok(VmmData::Empty)
+    }
+}
+
+/// Write the metrics on user demand (flush). We use the word `flush` here to highlight the fact
+/// that the metrics will be written immediately.
+fn flush_metrics() -> Result<VmmData, VmmActionError> {
+    // FIXME: we're losing the bool saying whether metrics were actually written.
+    METRICS
+        .write()
+        .map(|_| VmmData::Empty)
+        .map_err(super::VmmError::Metrics)
+        .map_err(VmmActionError::InternalVmm)
+}
+
+// Returns the configuration of the vsock device, with the guest CID it was assigned.
+fn vsock_config(vsock: &VsockBuilder) -> Result<VmmData, VmmActionError> {
+    vsock
+        .config()
+        .map(VmmData::VsockConfig)
+        .ok_or(VmmActionError::VsockConfig(
+            VsockConfigError::DeviceNotFound,
+        ))
+}
+
+// Serves the requests which only read the state of the running microVM.
+fn read_vmm_state(vmm: &mut Vmm, request: &VmmAction) -> Result<VmmData, VmmActionError> {
+    match request {
+        VmmAction::GetBalloonConfig => vmm
+            .balloon_config()
+            .map(|state| VmmData::BalloonConfig(BalloonDeviceConfig::from(state)))
+            .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+        VmmAction::GetBalloonHintingStatus => vmm
+            .balloon_hinting_status()
+            .map(VmmData::BalloonHintingStatus)
+            .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+        VmmAction::GetBalloonStats => vmm
+            .latest_balloon_stats()
+            .map(VmmData::BalloonStats)
+            .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+        VmmAction::GetConfidentialInfo => vmm
+            .confidential_info()
+            .map(VmmData::ConfidentialInfo)
+            .ok_or(VmmActionError::Confidential(
+                ConfidentialConfigError::NotConfidential,
+            )),
+        VmmAction::GetMemoryHotplugStatus => vmm
+            .memory_hotplug_status()
+            .map(VmmData::MemoryHotplugStatus)
+            .map_err(VmmActionError::MemoryHotplugConfig),
+        VmmAction::GetHealth => Ok(VmmData::Health(vmm.health())),
+        VmmAction::GetVmInstanceInfo => Ok(VmmData::InstanceInformation(vmm.instance_info())),
+        VmmAction::GetVmmVersion => Ok(VmmData::VmmVersion(vmm.version())),
+        _ => unreachable!("{:?} changes the state of the microVM", request),
+    }
+}
+
+/// Serves the requests which do not conflict with the state changes of a running microVM outside
+/// of the VMM thread, so that they are not queued behind the requests it is processing.
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrentApiController {
+    vmm: Arc<Mutex<Option<Arc<Mutex<Vmm>>>>>,
+}
+
+impl ConcurrentApiController {
+    /// Starts serving the requests of the running microVM. The clones of the controller share
+    /// the microVM.
+    pub fn attach(&self, vmm: Arc<Mutex<Vmm>>) {
+        *self.vmm.lock().expect("Poisoned lock") = Some(vmm);
+    }
+
+    /// Serves `request` right away if it does not conflict with the state changes of the running
+    /// microVM. Returns `None` otherwise, or before the microVM runs, in which case the request is
+    /// to be served by the VMM thread, in order.
+    pub fn handle_request(&self, request: &VmmAction) -> Option<Result<VmmData, VmmActionError>> {
+        let vmm = self.vmm.lock().expect("Poisoned lock").clone()?;
+        match request {
+            VmmAction::FlushMetrics => Some(flush_metrics()),
+            VmmAction::GetBalloonConfig
+            | VmmAction::GetBalloonHintingStatus
+            | VmmAction::GetBalloonStats
+            | VmmAction::GetConfidentialInfo
+            | VmmAction::GetHealth
+            | VmmAction::GetMemoryHotplugStatus
+            | VmmAction::GetVmInstanceInfo
+            | VmmAction::GetVmmVersion => {
+                // The VMM thread holds the lock while it changes the state of the microVM, in
+                // which case the request waits for its turn.
+                let mut vmm = vmm.try_lock().ok()?;
+                Some(read_vmm_state(&mut vmm, request))
+            }
+            _ => None,
+        }
+    }
+
+    /// Serves `request` on behalf of the API dispatcher thread if it goes through the guest agent,
+    /// which the VMM thread cannot wait for since it serves the vsock device. Returns `None`
+    /// otherwise, or before the microVM runs.
+    pub fn handle_guest_agent_request(
+        &self,
+        request: &VmmAction,
+    ) -> Option<Result<VmmData, VmmActionError>> {
+        let vmm = self.vmm.lock().expect("Poisoned lock").clone()?;
+        match request {
+            VmmAction::CopyGuestFile(params) => {
+                // The lock is only held to look the channel up.
+                let channel = vmm.lock().expect("Poisoned lock").guest_file_channel();
+                Some(
+                    channel
+                        .and_then(|channel| channel.copy(params))
+                        .map(|_| VmmData::Empty)
+                        .map_err(VmmActionError::GuestFileCopy),
+                )
+            }
+            VmmAction::UpdateGuestFreeze(update) => {
+                let channel = vmm.lock().expect("Poisoned lock").guest_freeze_channel();
+                Some(
+                    channel
+                        .and_then(|channel| match update.state {
+                            GuestFreezeState::Frozen => channel.freeze(),
+                            GuestFreezeState::Thawed => channel.thaw(),
+                        })
+                        .map(|()| VmmData::Empty)
+                        .map_err(VmmActionError::GuestFreezeUpdate),
+                )
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
     use std::io;
     use std::path::PathBuf;
 
     use mmds::data_store::MmdsVersion;
     use seccompiler::BpfThreadMap;
+    use utils::tempfile::TempFile;
 
     use super::*;
     use crate::cpu_config::templates::test_utils::build_test_template;
     use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
     use crate::devices::virtio::balloon::{BalloonConfig, BalloonError};
+    use crate::devices::virtio::net::capture::CaptureError;
     use crate::devices::virtio::rng::EntropyError;
-    use crate::devices::virtio::VsockError;
+    use crate::devices::virtio::{VsockError, IO_URING_NUM_ENTRIES};
+    use crate::guest_files::GuestFileChannel;
+    use crate::guest_freeze::GuestFreezeChannel;
+    use crate::seccomp_filters::SeccompFilterSource;
     use crate::vmm_config::balloon::BalloonBuilder;
-    use crate::vmm_config::drive::{CacheType, FileEngineType};
-    use crate::vmm_config::logger::LoggerLevel;
-    use crate::vmm_config::machine_config::VmConfig;
-    use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
-    use crate::vmm_config::vsock::VsockBuilder;
+    use crate::vmm_config::confidential::{ConfidentialTechnology, DEFAULT_SEV_SNP_POLICY};
+    use crate::vmm_config::drive::{CacheType, FadvisePolicy, FileEngineType};
+    use crate::vmm_config::guest_files::GuestFileDirection;
+    use crate::vmm_config::health::{HealthStatus, SubsystemHealth};
+    use crate::vmm_config::logger::{LoggerFormat, LoggerLevel, LoggerModuleFilter};
+    use crate::vmm_config::machine_config::{CpuBandwidth, VmConfig};
+    use crate::vmm_config::net::PacketCaptureConfig;
+    use crate::vmm_config::snapshot::{DriveOverride, MemBackendConfig, MemBackendType};
     use crate::HTTP_MAX_PAYLOAD_SIZE;
 
     impl PartialEq for VmmActionError {
@@ -906,11 +1783,30 @@ mod tests {
                 (self, other),
                 (BalloonConfig(_), BalloonConfig(_))
                     | (BootSource(_), BootSource(_))
+                    | (Coredump(_), Coredump(_))
+                    | (CpuBandwidth(_), CpuBandwidth(_))
                     | (CreateSnapshot(_), CreateSnapshot(_))
                     | (DriveConfig(_), DriveConfig(_))
                     | (InternalVmm(_), InternalVmm(_))
+                    | (IoThreads(_), IoThreads(_))
+                    | (Gdb(_), Gdb(_))
+                    | (Landlock(_), Landlock(_))
+                    | (StallDetection(_), StallDetection(_))
+                    | (MemoryPressure(_), MemoryPressure(_))
+                    | (GuestFiles(_), GuestFiles(_))
+                    | (GuestFileCopy(_), GuestFileCopy(_))
+                    | (GuestFreeze(_), GuestFreeze(_))
+                    | (GuestFreezeUpdate(_), GuestFreezeUpdate(_))
+                    | (Smbios(_), Smbios(_))
+                    | (Identity(_), Identity(_))
+                    | (Tpm(_), Tpm(_))
+                    | (Confidential(_), Confidential(_))
+                    | (DeterministicBoot(_), DeterministicBoot(_))
+                    | (VirtioRecord(_), VirtioRecord(_))
                     | (LoadSnapshot(_), LoadSnapshot(_))
+                    | (Logger(_), Logger(_))
                     | (MachineConfig(_), MachineConfig(_))
+                    | (MemoryHotplugConfig(_), MemoryHotplugConfig(_))
                     | (Metrics(_), Metrics(_))
                     | (Mmds(_), Mmds(_))
                     | (MmdsLimitExceeded(_), MmdsLimitExceeded(_))
@@ -919,8 +1815,12 @@ mod tests {
                     | (NotSupported(_), NotSupported(_))
                     | (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot)
                     | (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot)
+                    | (SharedDir(_), SharedDir(_))
                     | (StartMicrovm(_), StartMicrovm(_))
+                    | (UffdHandover(_), UffdHandover(_))
+                    | (ValidateVmConfig(_), ValidateVmConfig(_))
                     | (VsockConfig(_), VsockConfig(_))
+                    | (WorkingSet(_), WorkingSet(_))
                     | (EntropyDevice(_), EntropyDevice(_))
             )
         }
@@ -937,9 +1837,28 @@ mod tests {
         boot_src: BootSourceConfig,
         boot_cfg_set: bool,
         block_set: bool,
+        block_updated: bool,
         vsock_set: bool,
         net_set: bool,
+        shared_dir_set: bool,
         entropy_set: bool,
+        memory_hotplug_set: bool,
+        landlock_set: bool,
+        gdb_set: bool,
+        stall_detection_set: bool,
+        deterministic_boot_set: bool,
+        virtio_record_set: bool,
+        working_set_set: bool,
+        io_threads_set: bool,
+        smbios_set: bool,
+        identity_set: bool,
+        tpm_set: bool,
+        confidential_set: bool,
+        memory_pressure_set: bool,
+        guest_files_set: bool,
+        guest_freeze_set: bool,
+        pub emergency_snapshot: Option<EmergencySnapshotConfig>,
+        pub seccomp_filters: Vec<SeccompFilterInfo>,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
@@ -960,6 +1879,20 @@ mod tests {
             self.vm_config.track_dirty_pages
         }
 
+        pub fn export_config(&self) -> VmmConfig {
+            VmmConfig::default()
+        }
+
+        pub fn validate_config(
+            vmm_config: VmmConfig,
+            _: &InstanceInfo,
+        ) -> Result<(), ValidationErrors> {
+            if vmm_config == VmmConfig::default() {
+                return Err(ValidationErrors(vec![]));
+            }
+            Ok(())
+        }
+
         pub fn set_track_dirty_pages(&mut self, dirty_page_tracking: bool) {
             self.vm_config.track_dirty_pages = dirty_page_tracking;
         }
@@ -1014,6 +1947,17 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_block_device(
+            &mut self,
+            _: BlockDeviceUpdateConfig,
+        ) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::InvalidDriveId(String::new()));
+            }
+            self.block_updated = true;
+            Ok(())
+        }
+
         pub fn build_net_device(
             &mut self,
             _: NetworkInterfaceConfig,
@@ -1025,6 +1969,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn build_shared_dir(&mut self, _: SharedDirConfig) -> Result<(), SharedDirError> {
+            if self.force_errors {
+                return Err(SharedDirError::MountTagInUse(String::new()));
+            }
+            self.shared_dir_set = true;
+            Ok(())
+        }
+
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -1048,6 +2000,162 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_memory_hotplug_config(
+            &mut self,
+            _: MemoryHotplugConfig,
+        ) -> Result<(), MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::NotSupported);
+            }
+            self.memory_hotplug_set = true;
+            Ok(())
+        }
+
+        pub fn set_landlock_config(
+            &mut self,
+            _: LandlockConfig,
+        ) -> Result<(), LandlockConfigError> {
+            if self.force_errors {
+                return Err(LandlockConfigError::EmptyPath);
+            }
+            self.landlock_set = true;
+            Ok(())
+        }
+
+        pub fn set_gdb_config(&mut self, _: GdbConfig) -> Result<(), GdbConfigError> {
+            if self.force_errors {
+                return Err(GdbConfigError::InvalidEndpoint);
+            }
+            self.gdb_set = true;
+            Ok(())
+        }
+
+        pub fn set_stall_detection_config(
+            &mut self,
+            _: StallDetectionConfig,
+        ) -> Result<(), StallDetectionConfigError> {
+            if self.force_errors {
+                return Err(StallDetectionConfigError::TimeoutTooShort(0));
+            }
+            self.stall_detection_set = true;
+            Ok(())
+        }
+
+        pub fn set_memory_pressure_config(
+            &mut self,
+            _: MemoryPressureConfig,
+        ) -> Result<(), MemoryPressureConfigError> {
+            if self.force_errors {
+                return Err(MemoryPressureConfigError::EmptyPath);
+            }
+            self.memory_pressure_set = true;
+            Ok(())
+        }
+
+        pub fn set_guest_files_config(
+            &mut self,
+            _: GuestFilesConfig,
+        ) -> Result<(), GuestFilesConfigError> {
+            if self.force_errors {
+                return Err(GuestFilesConfigError::NoAllowedDir);
+            }
+            self.guest_files_set = true;
+            Ok(())
+        }
+
+        pub fn set_guest_freeze_config(
+            &mut self,
+            _: GuestFreezeConfig,
+        ) -> Result<(), GuestFreezeConfigError> {
+            if self.force_errors {
+                return Err(GuestFreezeConfigError::NullTimeout);
+            }
+            self.guest_freeze_set = true;
+            Ok(())
+        }
+
+        pub fn set_deterministic_boot_config(
+            &mut self,
+            _: DeterministicBootConfig,
+        ) -> Result<(), DeterministicBootConfigError> {
+            if self.force_errors {
+                return Err(DeterministicBootConfigError::StartTimeOutOfRange(0));
+            }
+            self.deterministic_boot_set = true;
+            Ok(())
+        }
+
+        pub fn set_virtio_record_config(
+            &mut self,
+            _: VirtioRecordConfig,
+        ) -> Result<(), VirtioRecordConfigError> {
+            if self.force_errors {
+                return Err(VirtioRecordConfigError::NoQueues);
+            }
+            self.virtio_record_set = true;
+            Ok(())
+        }
+
+        pub fn set_working_set_config(
+            &mut self,
+            _: WorkingSetConfig,
+        ) -> Result<(), WorkingSetConfigError> {
+            if self.force_errors {
+                return Err(WorkingSetConfigError::NullInterval);
+            }
+            self.working_set_set = true;
+            Ok(())
+        }
+
+        pub fn set_io_threads_config(
+            &mut self,
+            _: IoThreadsConfig,
+        ) -> Result<(), IoThreadsConfigError> {
+            if self.force_errors {
+                return Err(IoThreadsConfigError::InvalidThreadCount);
+            }
+            self.io_threads_set = true;
+            Ok(())
+        }
+
+        pub fn set_smbios_config(&mut self, _: SmbiosConfig) -> Result<(), SmbiosConfigError> {
+            if self.force_errors {
+                return Err(SmbiosConfigError::InvalidString("manufacturer"));
+            }
+            self.smbios_set = true;
+            Ok(())
+        }
+
+        pub fn set_identity_config(
+            &mut self,
+            _: IdentityConfig,
+        ) -> Result<(), IdentityConfigError> {
+            if self.force_errors {
+                return Err(IdentityConfigError::GenerateUuid);
+            }
+            self.identity_set = true;
+            Ok(())
+        }
+
+        pub fn set_tpm_config(&mut self, _: TpmConfig) -> Result<(), TpmConfigError> {
+            if self.force_errors {
+                return Err(TpmConfigError::EmptySocketPath);
+            }
+            self.tpm_set = true;
+            Ok(())
+        }
+
+        pub fn set_confidential_config(
+            &mut self,
+            _: ConfidentialConfig,
+        ) -> Result<(), ConfidentialConfigError> {
+            if self.force_errors {
+                return Err(ConfidentialConfigError::TdxNotSupported);
+            }
+            self.confidential_set = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1096,6 +2204,13 @@ mod tests {
                 smt: value.vm_config.smt,
                 cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
                 boot_source: value.boot_source_config().clone(),
+                guest_ready: false,
+                smbios: None,
+                identity: None,
+                mmio_layout: None,
+                mem_lock: None,
+                hot_memory: None,
+                acpi: false,
             }
         }
     }
@@ -1104,15 +2219,30 @@ mod tests {
     #[derive(Debug, Default, PartialEq, Eq)]
     pub struct MockVmm {
         pub balloon_config_called: bool,
+        pub balloon_hinting_status_called: bool,
+        pub handover_uffd_handler_called: bool,
         pub latest_balloon_stats_called: bool,
+        pub memory_hotplug_status_called: bool,
+        pub confidential_info_called: bool,
+        pub flush_block_devices_called: bool,
         pub pause_called: bool,
+        pub pause_lite_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        pub start_balloon_hinting_called: bool,
+        pub stop_balloon_hinting_called: bool,
         pub update_balloon_config_called: bool,
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
+        pub update_usable_memory_called: bool,
+        pub update_memory_hotplug_size_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub update_block_device_state_called: bool,
+        pub update_net_device_state_called: bool,
+        pub net_capture_enabled: bool,
+        pub shutdown_exit_code: Option<FcExitCode>,
+        pub state: VmState,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1134,6 +2264,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn pause_vm_lite(&mut self) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuPause);
+            }
+            self.pause_lite_called = true;
+            Ok(())
+        }
+
         #[cfg(target_arch = "x86_64")]
         pub fn send_ctrl_alt_del(&mut self) -> Result<(), VmmError> {
             if self.force_errors {
@@ -1161,7 +2299,7 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
-        pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
+        pub fn update_balloon_config(&mut self, _: u64) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
             }
@@ -1169,6 +2307,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_usable_memory(&mut self, _: u64) -> Result<(), BalloonConfigError> {
+            if self.force_errors {
+                return Err(BalloonConfigError::DeviceNotFound);
+            }
+            self.update_usable_memory_called = true;
+            Ok(())
+        }
+
         pub fn update_balloon_stats_config(&mut self, _: u16) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1177,6 +2323,74 @@ mod tests {
             Ok(())
         }
 
+        pub fn start_balloon_hinting(&mut self) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.start_balloon_hinting_called = true;
+            Ok(())
+        }
+
+        pub fn stop_balloon_hinting(&mut self) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.stop_balloon_hinting_called = true;
+            Ok(())
+        }
+
+        pub fn balloon_hinting_status(&mut self) -> Result<BalloonHintingStatus, BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.balloon_hinting_status_called = true;
+            Ok(BalloonHintingStatus::default())
+        }
+
+        pub fn handover_uffd_handler(
+            &mut self,
+            _: &std::path::Path,
+        ) -> Result<(), UffdHandoverError> {
+            if self.force_errors {
+                return Err(UffdHandoverError::NotUffd);
+            }
+            self.handover_uffd_handler_called = true;
+            Ok(())
+        }
+
+        pub fn confidential_info(&mut self) -> Option<ConfidentialInfo> {
+            if self.force_errors {
+                return None;
+            }
+            self.confidential_info_called = true;
+            Some(ConfidentialInfo {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY,
+                launch_digest: "00".repeat(48),
+            })
+        }
+
+        pub fn memory_hotplug_status(
+            &mut self,
+        ) -> Result<VirtioMemStatus, MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::DeviceNotFound);
+            }
+            self.memory_hotplug_status_called = true;
+            Ok(VirtioMemStatus::default())
+        }
+
+        pub fn update_memory_hotplug_size(
+            &mut self,
+            _: usize,
+        ) -> Result<(), MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::DeviceNotFound);
+            }
+            self.update_memory_hotplug_size_called = true;
+            Ok(())
+        }
+
         pub fn update_block_device_path(&mut self, _: &str, _: String) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1196,6 +2410,44 @@ mod tests {
             Ok(())
         }
 
+        pub fn flush_block_devices(&mut self, _: Option<&[String]>) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::DeviceNotFound,
+                ));
+            }
+            self.flush_block_devices_called = true;
+            Ok(())
+        }
+
+        pub fn update_block_device_state(
+            &mut self,
+            _: &str,
+            _: DeviceRunState,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_block_device_state_called = true;
+            Ok(())
+        }
+
+        pub fn update_net_device_state(
+            &mut self,
+            _: &str,
+            _: DeviceRunState,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_net_device_state_called = true;
+            Ok(())
+        }
+
         pub fn update_net_rate_limiters(
             &mut self,
             _: &str,
@@ -1213,13 +2465,47 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_net_capture(
+            &mut self,
+            _: &str,
+            capture: Option<PacketCapture>,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.net_capture_enabled = capture.is_some();
+            Ok(())
+        }
+
         pub fn instance_info(&self) -> InstanceInfo {
-            InstanceInfo::default()
+            InstanceInfo {
+                state: self.state.clone(),
+                ..Default::default()
+            }
         }
 
         pub fn version(&self) -> String {
             String::default()
         }
+
+        pub fn health(&self) -> Health {
+            let ok = || SubsystemHealth::new(HealthStatus::Ok);
+            Health::new(ok(), ok(), ok(), ok())
+        }
+
+        pub fn guest_file_channel(&self) -> Result<GuestFileChannel, GuestFilesError> {
+            Err(GuestFilesError::NotConfigured)
+        }
+
+        pub fn guest_freeze_channel(&self) -> Result<GuestFreezeChannel, GuestFreezeError> {
+            Err(GuestFreezeError::VsockNotFound)
+        }
+
+        pub fn stop(&mut self, exit_code: FcExitCode) {
+            self.shutdown_exit_code = Some(exit_code);
+        }
     }
 
     // Need to redefine this since the non-test one uses real VmResources
@@ -1233,6 +2519,12 @@ mod tests {
         Ok(Arc::new(Mutex::new(MockVmm::default())))
     }
 
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn create_coredump(_: &mut Vmm, _: &CoredumpParams) -> Result<(), CoredumpError> {
+        Ok(())
+    }
+
     // Need to redefine this since the non-test one uses real Vmm
     // instead of our mocks.
     pub fn create_snapshot(
@@ -1328,14 +2620,36 @@ mod tests {
     }
 
     #[test]
-    fn test_preboot_get_vm_config() {
-        let req = VmmAction::GetVmMachineConfig;
-        let expected_cfg = MachineConfig::default();
+    fn test_preboot_export_vm_config() {
+        let req = VmmAction::ExportVmConfig;
         check_preboot_request(req, |result, _| {
-            assert_eq!(result, Ok(VmmData::MachineConfiguration(expected_cfg)))
+            assert_eq!(result, Ok(VmmData::FullVmConfig(VmmConfig::default())));
         });
+    }
 
-        let req = VmmAction::ConfigureBootSource(BootSourceConfig::default());
+    #[test]
+    fn test_preboot_validate_vm_config() {
+        let req = VmmAction::ValidateVmConfig(
+            serde_json::from_str(r#"{"boot-source": {"kernel_image_path": "vmlinux"}}"#).unwrap(),
+        );
+        check_preboot_request(req, |result, _| assert_eq!(result, Ok(VmmData::Empty)));
+
+        let req = VmmAction::ValidateVmConfig(VmmConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::ValidateVmConfig(ValidationErrors(vec![])),
+        );
+    }
+
+    #[test]
+    fn test_preboot_get_vm_config() {
+        let req = VmmAction::GetVmMachineConfig;
+        let expected_cfg = MachineConfig::default();
+        check_preboot_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::MachineConfiguration(expected_cfg)))
+        });
+
+        let req = VmmAction::ConfigureBootSource(BootSourceConfig::default());
         check_preboot_request_err(
             req,
             VmmActionError::BootSource(BootSourceConfigError::InvalidKernelCommandLine(
@@ -1344,6 +2658,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_get_health() {
+        let req = VmmAction::GetHealth;
+        check_preboot_request(req, |result, _| {
+            let Ok(VmmData::Health(health)) = &result else {
+                panic!("Unexpected result: {:?}", result);
+            };
+            assert_eq!(health.status, HealthStatus::Starting);
+            assert_eq!(health.vcpus.status, HealthStatus::Starting);
+            assert_eq!(health.uffd_handler.status, HealthStatus::Disabled);
+        });
+    }
+
     #[test]
     fn test_preboot_get_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1353,6 +2680,14 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_get_vsock_config() {
+        check_preboot_request_err(
+            VmmAction::GetVsockConfig,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_preboot_put_cpu_config() {
         // Start testing - Provide VMM vCPU configuration in preparation for `InstanceStart`
@@ -1410,6 +2745,13 @@ mod tests {
             drive_id: String::new(),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1425,6 +2767,13 @@ mod tests {
             drive_id: String::new(),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         });
         check_preboot_request_err(
             req,
@@ -1432,6 +2781,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_update_block_dev() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            cache_type: Some(CacheType::Writethrough),
+            direct_io: Some(true),
+            ..Default::default()
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.block_updated)
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::InvalidDriveId(String::new())),
+        );
+    }
+
+    #[test]
+    fn test_preboot_update_logger() {
+        let req = VmmAction::UpdateLogger(LoggerUpdateConfig {
+            level: None,
+            module_filters: Some(vec![LoggerModuleFilter {
+                module: String::from("devices virtio"),
+                level: LoggerLevel::Debug,
+            }]),
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::Logger(LoggerConfigError::InvalidModule(String::new())),
+        );
+    }
+
     #[test]
     fn test_preboot_insert_net_dev() {
         let req = VmmAction::InsertNetworkDevice(NetworkInterfaceConfig {
@@ -1440,6 +2823,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1452,6 +2841,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         });
         check_preboot_request_err(
             req,
@@ -1461,12 +2856,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_insert_shared_dir() {
+        let req = VmmAction::InsertSharedDir(SharedDirConfig {
+            share_id: String::new(),
+            path_on_host: PathBuf::new(),
+            mount_tag: String::new(),
+            is_read_only: false,
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.shared_dir_set)
+        });
+
+        let req = VmmAction::InsertSharedDir(SharedDirConfig {
+            share_id: String::new(),
+            path_on_host: PathBuf::new(),
+            mount_tag: String::new(),
+            is_read_only: false,
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::SharedDir(SharedDirError::MountTagInUse(String::new())),
+        );
+    }
+
     #[test]
     fn test_preboot_set_vsock_dev() {
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
-            guest_cid: 0,
+            guest_cid: Some(0),
             uds_path: String::new(),
+            clock_port: None,
+            cid_lock_dir: None,
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1475,8 +2900,13 @@ mod tests {
 
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
-            guest_cid: 0,
+            guest_cid: Some(0),
             uds_path: String::new(),
+            clock_port: None,
+            cid_lock_dir: None,
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
         });
         check_preboot_request_err(
             req,
@@ -1495,6 +2925,259 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_set_memory_hotplug_device() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        let req = VmmAction::SetMemoryHotplugDevice(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.memory_hotplug_set);
+        });
+
+        let req = VmmAction::SetMemoryHotplugDevice(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::NotSupported),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_gdb() {
+        let req = VmmAction::SetGdb(GdbConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.gdb_set);
+        });
+
+        let req = VmmAction::SetGdb(GdbConfig::default());
+        check_preboot_request_err(req, VmmActionError::Gdb(GdbConfigError::InvalidEndpoint));
+    }
+
+    #[test]
+    fn test_preboot_set_stall_detection() {
+        let config = StallDetectionConfig {
+            timeout_ms: 5000,
+            capture_registers: false,
+        };
+        let req = VmmAction::SetStallDetection(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.stall_detection_set);
+        });
+
+        let req = VmmAction::SetStallDetection(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::StallDetection(StallDetectionConfigError::TimeoutTooShort(0)),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_memory_pressure() {
+        let config: MemoryPressureConfig =
+            serde_json::from_str(r#"{"path": "/proc/pressure/memory", "action": "Pause"}"#)
+                .unwrap();
+        let req = VmmAction::SetMemoryPressure(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.memory_pressure_set);
+        });
+
+        let req = VmmAction::SetMemoryPressure(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::MemoryPressure(MemoryPressureConfigError::EmptyPath),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_guest_files() {
+        let config: GuestFilesConfig = serde_json::from_str(
+            r#"{"port": 52, "host_dirs": ["/srv/outputs"], "guest_dirs": ["/workspace"]}"#,
+        )
+        .unwrap();
+        let req = VmmAction::SetGuestFiles(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.guest_files_set);
+        });
+
+        let req = VmmAction::SetGuestFiles(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::GuestFiles(GuestFilesConfigError::NoAllowedDir),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_guest_freeze() {
+        let config = GuestFreezeConfig {
+            port: 53,
+            timeout_ms: 10_000,
+        };
+        let req = VmmAction::SetGuestFreeze(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.guest_freeze_set);
+        });
+
+        let req = VmmAction::SetGuestFreeze(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::GuestFreeze(GuestFreezeConfigError::NullTimeout),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_deterministic_boot() {
+        let req = VmmAction::SetDeterministicBoot(DeterministicBootConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.deterministic_boot_set);
+        });
+
+        let req = VmmAction::SetDeterministicBoot(DeterministicBootConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::DeterministicBoot(DeterministicBootConfigError::StartTimeOutOfRange(0)),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_virtio_record() {
+        let config = VirtioRecordConfig { queues: vec![] };
+        let req = VmmAction::SetVirtioRecord(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.virtio_record_set);
+        });
+
+        let req = VmmAction::SetVirtioRecord(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::VirtioRecord(VirtioRecordConfigError::NoQueues),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_working_set() {
+        let config = WorkingSetConfig {
+            sample_interval_s: 30,
+        };
+        let req = VmmAction::SetWorkingSet(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.working_set_set);
+        });
+
+        let req = VmmAction::SetWorkingSet(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::WorkingSet(WorkingSetConfigError::NullInterval),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_io_threads() {
+        let config = IoThreadsConfig {
+            thread_count: 2,
+            devices: vec![],
+        };
+        let req = VmmAction::SetIoThreads(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.io_threads_set);
+        });
+
+        let req = VmmAction::SetIoThreads(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::IoThreads(IoThreadsConfigError::InvalidThreadCount),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_smbios() {
+        let req = VmmAction::SetSmbios(SmbiosConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.smbios_set);
+        });
+
+        let req = VmmAction::SetSmbios(SmbiosConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::Smbios(SmbiosConfigError::InvalidString("manufacturer")),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_identity() {
+        let req = VmmAction::SetIdentity(IdentityConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.identity_set);
+        });
+
+        let req = VmmAction::SetIdentity(IdentityConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::Identity(IdentityConfigError::GenerateUuid),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_tpm() {
+        let config = TpmConfig {
+            socket_path: "/swtpm.sock".to_string(),
+        };
+        let req = VmmAction::SetTpm(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.tpm_set);
+        });
+
+        let req = VmmAction::SetTpm(config);
+        check_preboot_request_err(req, VmmActionError::Tpm(TpmConfigError::EmptySocketPath));
+    }
+
+    #[test]
+    fn test_preboot_set_confidential() {
+        let config = ConfidentialConfig {
+            technology: ConfidentialTechnology::SevSnp,
+            policy: DEFAULT_SEV_SNP_POLICY,
+        };
+        let req = VmmAction::SetConfidential(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.confidential_set);
+        });
+
+        let req = VmmAction::SetConfidential(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::Confidential(ConfidentialConfigError::TdxNotSupported),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_landlock() {
+        let req = VmmAction::SetLandlock(LandlockConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.landlock_set);
+        });
+
+        let req = VmmAction::SetLandlock(LandlockConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::Landlock(LandlockConfigError::EmptyPath),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
@@ -1535,6 +3218,33 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_get_seccomp_filters() {
+        check_preboot_request(VmmAction::GetSeccompFilters, |result, _| {
+            assert_eq!(result, Ok(VmmData::SeccompFilters(Vec::new())));
+        });
+
+        let vm_res = MockVmRes {
+            seccomp_filters: vec![SeccompFilterInfo {
+                thread_category: "vcpu".to_string(),
+                source: SeccompFilterSource::Custom,
+                instructions: 42,
+            }],
+            ..Default::default()
+        };
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm);
+        match runtime.handle_request(VmmAction::GetSeccompFilters) {
+            Ok(VmmData::SeccompFilters(filters)) => {
+                assert_eq!(filters.len(), 1);
+                assert_eq!(filters[0].thread_category, "vcpu");
+                assert_eq!(filters[0].source, SeccompFilterSource::Custom);
+                assert_eq!(filters[0].instructions, 42);
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
     #[test]
     fn test_preboot_put_mmds() {
         let mmds = Arc::new(Mutex::new(Mmds::default()));
@@ -1571,6 +3281,59 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_commit_snapshot() {
+        let params = || CommitSnapshotParams {
+            drive_overrides: vec![DriveOverride {
+                drive_id: "rootfs".to_string(),
+                path_on_host: "rootfs.ext4".to_string(),
+            }],
+            restore_info: Some(serde_json::from_str(r#"{"task_id": "4c1f", "seed": 7}"#).unwrap()),
+            ..Default::default()
+        };
+
+        // Only a paused microVM can be committed.
+        let req = VmmAction::CommitSnapshot(params());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Err(VmmActionError::NotSupported(String::new())));
+            assert!(!vmm.update_block_device_path_called);
+            assert!(!vmm.resume_called);
+        });
+
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Paused,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let res = runtime.handle_request(VmmAction::CommitSnapshot(params()));
+        assert_eq!(res, Ok(VmmData::Empty));
+        assert!(vmm.lock().unwrap().update_block_device_path_called);
+        assert!(vmm.lock().unwrap().resume_called);
+        assert_eq!(
+            runtime.mmds().data_store_value(),
+            serde_json::from_str::<Value>(r#"{"restore_info": {"task_id": "4c1f", "seed": 7}}"#)
+                .unwrap()
+        );
+
+        // A failed commit leaves the microVM paused.
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Paused,
+            force_errors: true,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let err = runtime
+            .handle_request(VmmAction::CommitSnapshot(params()))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::InvalidDeviceType
+            )))
+        );
+        assert!(!vmm.lock().unwrap().resume_called);
+    }
+
     #[test]
     fn test_runtime_put_mmds() {
         let mmds = Arc::new(Mutex::new(Mmds::default()));
@@ -1752,9 +3515,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1768,9 +3545,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1779,16 +3570,138 @@ mod tests {
         assert!(vmm.resume_called);
         // Extra sanity check - pause was never called.
         assert!(!vmm.pause_called);
-    }
+        drop(vmm);
 
-    #[test]
-    fn test_preboot_disallowed() {
-        check_preboot_request_err(
-            VmmAction::FlushMetrics,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::Pause,
+        // Diff snapshots are allowed when the memory is write-protected.
+        let mut vm_resources = MockVmRes::default();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_backend: MemBackendConfig {
+                backend_type: MemBackendType::Uffd,
+                backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: true,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        });
+        preboot.handle_preboot_request(req).unwrap();
+        assert!(preboot.vm_resources.track_dirty_pages());
+    }
+
+    #[test]
+    fn test_preboot_load_snapshot_timeout() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let seccomp_filters = BpfThreadMap::new();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+
+        // A restore which is not done in time is aborted before the microVM runs.
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_backend: MemBackendConfig {
+                backend_type: MemBackendType::File,
+                backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: true,
+            timeout_ms: Some(0),
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        });
+        assert!(matches!(
+            preboot.handle_preboot_request(req),
+            Err(VmmActionError::LoadSnapshot(LoadSnapshotError::TimedOut(0)))
+        ));
+        assert!(preboot.built_vmm.is_none());
+        assert_eq!(preboot.fatal_error, Some(FcExitCode::BadConfiguration));
+    }
+
+    #[test]
+    fn test_preboot_load_snapshot_manifest() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let seccomp_filters = BpfThreadMap::new();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+
+        // A manifest which cannot be read fails the load before anything is restored.
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_backend: MemBackendConfig {
+                backend_type: MemBackendType::File,
+                backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: true,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: Some(PathBuf::from("/invalid/manifest.json")),
+            mem_diff_layers: Vec::new(),
+        });
+        assert!(matches!(
+            preboot.handle_preboot_request(req),
+            Err(VmmActionError::LoadSnapshot(LoadSnapshotError::Manifest(
+                SnapshotManifestError::Read(_)
+            )))
+        ));
+        assert!(preboot.built_vmm.is_none());
+        assert_eq!(preboot.fatal_error, None);
+    }
+
+    #[test]
+    fn test_preboot_disallowed() {
+        check_preboot_request_err(
+            VmmAction::CommitSnapshot(CommitSnapshotParams::default()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::FlushDrives(None),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::FlushMetrics,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Pause,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::PauseLite,
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
@@ -1800,17 +3713,54 @@ mod tests {
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
+            VmmAction::StartBalloonHinting,
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-                stats_polling_interval_s: 0,
+            VmmAction::StopBalloonHinting,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetBalloonHintingStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateBalloon(BalloonUpdateConfig {
+                amount_mib: Some(0),
+                ..Default::default()
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetMemoryHotplugStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetConfidentialInfo,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::HandoverUffdHandler(UffdHandlerConfig {
+                backend_path: PathBuf::new(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateVsockDevice(VsockDeviceUpdateConfig {
+                uds_path: String::new(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+                requested_size_mib: 0,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig::default()),
+            VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+                stats_polling_interval_s: 0,
+            }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
@@ -1818,6 +3768,8 @@ mod tests {
                 iface_id: String::new(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                state: None,
+                capture: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1827,6 +3779,40 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 version: None,
+                timeout_ms: None,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::CreateCoredump(CoredumpParams {
+                dump_path: PathBuf::new(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::SuspendToDisk(SuspendToDiskParams {
+                snapshot_type: SnapshotType::Full,
+                snapshot_path: PathBuf::new(),
+                mem_file_path: PathBuf::new(),
+                marker_path: PathBuf::new(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::EmergencySnapshot,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::CopyGuestFile(GuestFileCopyParams {
+                direction: GuestFileDirection::ToGuest,
+                guest_path: PathBuf::new(),
+                host_path: PathBuf::new(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateGuestFreeze(GuestFreezeUpdate {
+                state: GuestFreezeState::Frozen,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1875,6 +3861,24 @@ mod tests {
         assert_eq!(err, expected_err);
     }
 
+    #[test]
+    fn test_runtime_export_vm_config() {
+        let req = VmmAction::ExportVmConfig;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::FullVmConfig(VmmConfig::default())));
+        });
+    }
+
+    #[test]
+    fn test_runtime_create_coredump() {
+        let req = VmmAction::CreateCoredump(CoredumpParams {
+            dump_path: PathBuf::new(),
+        });
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+    }
+
     #[test]
     fn test_runtime_get_vm_config() {
         let req = VmmAction::GetVmMachineConfig;
@@ -1886,6 +3890,14 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_get_health() {
+        let req = VmmAction::GetHealth;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Health(vmm.health())));
+        });
+    }
+
     #[test]
     fn test_runtime_pause() {
         let req = VmmAction::Pause;
@@ -1898,6 +3910,144 @@ mod tests {
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
     }
 
+    #[test]
+    fn test_runtime_pause_lite() {
+        let req = VmmAction::PauseLite;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.pause_lite_called)
+        });
+
+        let req = VmmAction::PauseLite;
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
+    }
+
+    #[test]
+    fn test_runtime_suspend_to_disk() {
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let params = |snapshot_type| SuspendToDiskParams {
+            snapshot_type,
+            snapshot_path: dir.as_path().join("vmstate"),
+            mem_file_path: dir.as_path().join("mem"),
+            marker_path: dir.as_path().join("suspended.json"),
+        };
+
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Running,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let res = runtime.handle_request(VmmAction::SuspendToDisk(params(SnapshotType::Full)));
+        assert_eq!(res, Ok(VmmData::Empty));
+        assert!(vmm.lock().unwrap().pause_called);
+        assert!(!vmm.lock().unwrap().resume_called);
+        assert_eq!(
+            vmm.lock().unwrap().shutdown_exit_code,
+            Some(FcExitCode::Suspended)
+        );
+        assert!(dir.as_path().join("suspended.json").exists());
+
+        // A failed suspend resumes the microVM and keeps Firecracker running.
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Running,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let res = runtime.handle_request(VmmAction::SuspendToDisk(params(SnapshotType::Diff)));
+        assert_eq!(res, Err(VmmActionError::NotSupported(String::new())));
+        assert!(vmm.lock().unwrap().pause_called);
+        assert!(vmm.lock().unwrap().resume_called);
+        assert_eq!(vmm.lock().unwrap().shutdown_exit_code, None);
+
+        // An already paused microVM is left paused.
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Paused,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let res = runtime.handle_request(VmmAction::SuspendToDisk(params(SnapshotType::Diff)));
+        assert!(res.is_err());
+        assert!(!vmm.lock().unwrap().pause_called);
+        assert!(!vmm.lock().unwrap().resume_called);
+    }
+
+    #[test]
+    fn test_runtime_emergency_snapshot() {
+        // The snapshot paths have to be registered beforehand.
+        let req = VmmAction::EmergencySnapshot;
+        check_runtime_request_err(req, VmmActionError::NotSupported(String::new()));
+
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let vm_resources = MockVmRes {
+            emergency_snapshot: Some(EmergencySnapshotConfig {
+                snapshot_type: SnapshotType::Full,
+                snapshot_path: dir.as_path().join("vmstate"),
+                mem_file_path: dir.as_path().join("mem"),
+                marker_path: dir.as_path().join("suspended.json"),
+                signal: None,
+            }),
+            ..Default::default()
+        };
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            state: VmState::Running,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(vm_resources, vmm.clone());
+        let res = runtime.handle_request(VmmAction::EmergencySnapshot);
+        assert_eq!(res, Ok(VmmData::Empty));
+        assert!(vmm.lock().unwrap().pause_called);
+        assert_eq!(
+            vmm.lock().unwrap().shutdown_exit_code,
+            Some(FcExitCode::Suspended)
+        );
+        assert!(dir.as_path().join("suspended.json").exists());
+    }
+
+    #[test]
+    fn test_runtime_update_cpu_bandwidth() {
+        let update = |quota_us| MachineConfigUpdate {
+            vcpu_count: None,
+            mem_size_mib: None,
+            smt: None,
+            cpu_template: None,
+            track_dirty_pages: None,
+            acpi: None,
+            split_irqchip: None,
+            gic_its: None,
+            vcpu_scheduling: None,
+            io_scheduling: None,
+            cpu_bandwidth: Some(CpuBandwidth {
+                quota_us,
+                period_us: 100_000,
+            }),
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
+        };
+
+        // Only the CPU bandwidth can be updated after boot.
+        check_runtime_request_err(
+            VmmAction::UpdateVmConfiguration(MachineConfigUpdate {
+                vcpu_count: Some(2),
+                ..update(Some(50_000))
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::UpdateVmConfiguration(update(None)),
+            VmmActionError::CpuBandwidth(CpuBandwidthError::MissingCpuMaxFile),
+        );
+        check_runtime_request_err(
+            VmmAction::UpdateVmConfiguration(update(Some(10))),
+            VmmActionError::MachineConfig(VmConfigError::InvalidCpuBandwidth),
+        );
+        // The cpu.max file is only passed to Firecracker by the jailer.
+        check_runtime_request_err(
+            VmmAction::UpdateVmConfiguration(update(Some(50_000))),
+            VmmActionError::CpuBandwidth(CpuBandwidthError::MissingCpuMaxFile),
+        );
+    }
+
     #[test]
     fn test_runtime_resume() {
         let req = VmmAction::Resume;
@@ -1946,6 +4096,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_vsock_config() {
+        use crate::devices::virtio::{Vsock, VsockUnixBackend};
+
+        let mut vm_res = MockVmRes::default();
+        let vsock = Vsock::new(5, VsockUnixBackend::new_unbound(5).unwrap()).unwrap();
+        vm_res.vsock.set_device(Arc::new(Mutex::new(vsock)));
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm);
+        assert_eq!(
+            runtime.handle_request(VmmAction::GetVsockConfig),
+            Ok(VmmData::VsockConfig(VsockDeviceConfig {
+                vsock_id: None,
+                guest_cid: Some(5),
+                uds_path: String::new(),
+                clock_port: None,
+                cid_lock_dir: None,
+                listen_backlog: None,
+                max_connections: None,
+                idle_timeout_s: None,
+            }))
+        );
+
+        check_runtime_request_err(
+            VmmAction::GetVsockConfig,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_runtime_latest_balloon_stats() {
         let req = VmmAction::GetBalloonStats;
@@ -1961,19 +4140,250 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_balloon_hinting() {
+        let req = VmmAction::StartBalloonHinting;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.start_balloon_hinting_called)
+        });
+
+        let req = VmmAction::GetBalloonHintingStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BalloonHintingStatus(
+                    BalloonHintingStatus::default()
+                ))
+            );
+            assert!(vmm.balloon_hinting_status_called)
+        });
+
+        let req = VmmAction::StopBalloonHinting;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.stop_balloon_hinting_called)
+        });
+
+        for req in [
+            VmmAction::StartBalloonHinting,
+            VmmAction::GetBalloonHintingStatus,
+            VmmAction::StopBalloonHinting,
+        ] {
+            check_runtime_request_err(
+                req,
+                VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            );
+        }
+    }
+
+    #[test]
+    fn test_concurrent_requests() {
+        let controller = ConcurrentApiController::default();
+        // Before the microVM runs, everything goes to the VMM thread.
+        assert!(controller
+            .handle_request(&VmmAction::GetBalloonStats)
+            .is_none());
+        assert!(controller
+            .handle_guest_agent_request(&VmmAction::UpdateGuestFreeze(GuestFreezeUpdate {
+                state: GuestFreezeState::Thawed,
+            }))
+            .is_none());
+
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        controller.clone().attach(vmm.clone());
+        assert_eq!(
+            controller.handle_request(&VmmAction::GetBalloonStats),
+            Some(Ok(VmmData::BalloonStats(BalloonStats::default())))
+        );
+        assert!(vmm.lock().unwrap().latest_balloon_stats_called);
+        assert_eq!(
+            controller.handle_request(&VmmAction::GetVmmVersion),
+            Some(Ok(VmmData::VmmVersion(MockVmm::default().version())))
+        );
+        assert_eq!(
+            controller.handle_request(&VmmAction::GetHealth),
+            Some(Ok(VmmData::Health(MockVmm::default().health())))
+        );
+
+        // The guest files are copied by the API dispatcher thread.
+        let copy = VmmAction::CopyGuestFile(GuestFileCopyParams {
+            direction: GuestFileDirection::FromGuest,
+            guest_path: PathBuf::from("/workspace/out.tar"),
+            host_path: PathBuf::from("/srv/outputs/out.tar"),
+        });
+        assert!(controller.handle_request(&copy).is_none());
+        assert_eq!(
+            controller.handle_guest_agent_request(&copy),
+            Some(Err(VmmActionError::GuestFileCopy(
+                GuestFilesError::NotConfigured
+            )))
+        );
+        // So are the guest filesystems frozen.
+        let update = VmmAction::UpdateGuestFreeze(GuestFreezeUpdate {
+            state: GuestFreezeState::Frozen,
+        });
+        assert!(controller.handle_request(&update).is_none());
+        assert_eq!(
+            controller.handle_guest_agent_request(&update),
+            Some(Err(VmmActionError::GuestFreezeUpdate(
+                GuestFreezeError::VsockNotFound
+            )))
+        );
+        assert!(controller
+            .handle_guest_agent_request(&VmmAction::GetBalloonStats)
+            .is_none());
+
+        // The requests which change the state of the microVM are served in order.
+        assert!(controller.handle_request(&VmmAction::Pause).is_none());
+        assert!(!vmm.lock().unwrap().pause_called);
+
+        // So are the requests which read it while the VMM thread changes it.
+        let _guard = vmm.lock().unwrap();
+        assert!(controller
+            .handle_request(&VmmAction::GetBalloonStats)
+            .is_none());
+    }
+
+    #[test]
+    fn test_runtime_copy_guest_file() {
+        // The VMM thread leaves the copies to the API dispatcher thread.
+        let req = VmmAction::CopyGuestFile(GuestFileCopyParams {
+            direction: GuestFileDirection::FromGuest,
+            guest_path: PathBuf::from("/workspace/out.tar"),
+            host_path: PathBuf::from("/srv/outputs/out.tar"),
+        });
+        check_runtime_request_err(req, VmmActionError::NotSupported(String::new()));
+    }
+
+    #[test]
+    fn test_runtime_update_guest_freeze() {
+        // The VMM thread leaves the requests to the guest agent to the API dispatcher thread.
+        let req = VmmAction::UpdateGuestFreeze(GuestFreezeUpdate {
+            state: GuestFreezeState::Thawed,
+        });
+        check_runtime_request_err(req, VmmActionError::NotSupported(String::new()));
+    }
+
     #[test]
     fn test_runtime_update_balloon_config() {
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            amount_mib: Some(0),
+            ..Default::default()
+        });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
             assert!(vmm.update_balloon_config_called)
         });
 
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            amount_mib: Some(0),
+            ..Default::default()
+        });
         check_runtime_request_err(
             req,
             VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
         );
+
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            amount_bytes: Some(4096),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_balloon_config_called)
+        });
+
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            usable_memory_bytes: Some(256 << 20),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_usable_memory_called)
+        });
+
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig::default());
+        check_runtime_request(req, |result, vmm| {
+            assert!(matches!(
+                result,
+                Err(VmmActionError::BalloonConfig(
+                    BalloonConfigError::InvalidTarget
+                ))
+            ));
+            assert!(!vmm.update_balloon_config_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_confidential_info() {
+        let req = VmmAction::GetConfidentialInfo;
+        check_runtime_request(req, |result, vmm| {
+            assert!(matches!(result, Ok(VmmData::ConfidentialInfo(_))));
+            assert!(vmm.confidential_info_called)
+        });
+
+        let req = VmmAction::GetConfidentialInfo;
+        check_runtime_request_err(
+            req,
+            VmmActionError::Confidential(ConfidentialConfigError::NotConfidential),
+        );
+    }
+
+    #[test]
+    fn test_runtime_get_memory_hotplug_status() {
+        let req = VmmAction::GetMemoryHotplugStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MemoryHotplugStatus(VirtioMemStatus::default()))
+            );
+            assert!(vmm.memory_hotplug_status_called)
+        });
+
+        let req = VmmAction::GetMemoryHotplugStatus;
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_memory_hotplug_size() {
+        let req = VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+            requested_size_mib: 512,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_memory_hotplug_size_called)
+        });
+
+        let req = VmmAction::UpdateMemoryHotplugSize(MemoryHotplugSizeUpdate {
+            requested_size_mib: 512,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_handover_uffd_handler() {
+        let req = VmmAction::HandoverUffdHandler(UffdHandlerConfig {
+            backend_path: PathBuf::from("/tmp/uffd.sock"),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.handover_uffd_handler_called)
+        });
+
+        let req = VmmAction::HandoverUffdHandler(UffdHandlerConfig {
+            backend_path: PathBuf::from("/tmp/uffd.sock"),
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::UffdHandover(UffdHandoverError::NotUffd),
+        );
     }
 
     #[test]
@@ -2016,6 +4426,42 @@ mod tests {
                 crate::device_manager::mmio::MmioError::InvalidDeviceType,
             ))),
         );
+
+        // The cache policy can only be updated before boot.
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            cache_type: Some(CacheType::Writeback),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::CachePolicyUpdate),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_logger() {
+        let req = VmmAction::UpdateLogger(LoggerUpdateConfig {
+            level: None,
+            module_filters: Some(vec![LoggerModuleFilter {
+                module: String::new(),
+                level: LoggerLevel::Debug,
+            }]),
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::Logger(LoggerConfigError::InvalidModule(String::new())),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_vsock_device() {
+        let req = VmmAction::UpdateVsockDevice(VsockDeviceUpdateConfig {
+            uds_path: String::from("vsock.sock"),
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceNotFound),
+        );
     }
 
     #[test]
@@ -2024,6 +4470,8 @@ mod tests {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            state: None,
+            capture: None,
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -2034,6 +4482,8 @@ mod tests {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            state: None,
+            capture: None,
         });
         check_runtime_request_err(
             req,
@@ -2043,6 +4493,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_update_net_capture() {
+        let pcap_file = TempFile::new().unwrap();
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            state: None,
+            capture: Some(PacketCaptureConfig {
+                pcap_path: Some(pcap_file.as_path().to_str().unwrap().to_string()),
+                ..Default::default()
+            }),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.net_capture_enabled)
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            state: None,
+            capture: Some(PacketCaptureConfig::default()),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(!vmm.net_capture_enabled)
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            state: None,
+            capture: Some(PacketCaptureConfig {
+                pcap_path: Some(String::from("foo.pcap")),
+                socket_path: Some(String::from("foo.sock")),
+                max_size_bytes: None,
+            }),
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::PacketCapture(
+                CaptureError::ConflictingOutputs,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_device_state() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            state: Some(DeviceRunState::Paused),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_device_state_called)
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            state: Some(DeviceRunState::Resumed),
+            capture: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_device_state_called)
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            state: Some(DeviceRunState::Resumed),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::InvalidDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_flush_drives() {
+        let req = VmmAction::FlushDrives(Some(vec!["root".to_string()]));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.flush_block_devices_called)
+        });
+
+        let req = VmmAction::FlushDrives(None);
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::Flush(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::DeviceNotFound,
+            ))),
+        );
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
@@ -2055,12 +4606,17 @@ mod tests {
                 level: LoggerLevel::Debug,
                 show_level: false,
                 show_log_origin: false,
+                format: LoggerFormat::Text,
+                instance_id: None,
+                labels: BTreeMap::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
             VmmAction::ConfigureMetrics(MetricsConfig {
                 metrics_path: PathBuf::new(),
+                instance_id: None,
+                labels: BTreeMap::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2074,6 +4630,13 @@ mod tests {
                 drive_id: String::new(),
                 rate_limiter: None,
                 file_engine_type: FileEngineType::default(),
+                direct_io: false,
+                fadvise: FadvisePolicy::default(),
+                readahead_kib: 0,
+                io_uring_entries: IO_URING_NUM_ENTRIES,
+                max_in_flight_requests: 0,
+                busy_poll_us: 0,
+                batch_size: 0,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2084,14 +4647,34 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                capture: None,
+                dhcp: None,
+                user_net: None,
+                pause_responder: None,
+                busy_poll_us: None,
+                batch_size: None,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertSharedDir(SharedDirConfig {
+                share_id: String::new(),
+                path_on_host: PathBuf::new(),
+                mount_tag: String::new(),
+                is_read_only: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
             VmmAction::SetVsockDevice(VsockDeviceConfig {
                 vsock_id: Some(String::new()),
-                guest_cid: 0,
+                guest_cid: Some(0),
                 uds_path: String::new(),
+                clock_port: None,
+                cid_lock_dir: None,
+                listen_backlog: None,
+                max_connections: None,
+                idle_timeout_s: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2102,8 +4685,13 @@ mod tests {
         check_runtime_request_err(
             VmmAction::SetVsockDevice(VsockDeviceConfig {
                 vsock_id: Some(String::new()),
-                guest_cid: 0,
+                guest_cid: Some(0),
                 uds_path: String::new(),
+                clock_port: None,
+                cid_lock_dir: None,
+                listen_backlog: None,
+                max_connections: None,
+                idle_timeout_s: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2125,9 +4713,23 @@ mod tests {
                 mem_backend: MemBackendConfig {
                     backend_type: MemBackendType::File,
                     backend_path: PathBuf::new(),
+                    handler_exit_action: None,
+                    fallback_mem_file_path: None,
+                    write_protect: false,
+                    readahead_mib_per_s: None,
+                    cache_path: None,
                 },
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                timeout_ms: None,
+                vsock_override: None,
+                restore_info: None,
+                network_overrides: Vec::new(),
+                drive_overrides: Vec::new(),
+                mmds: None,
+                vcpu_count: None,
+                manifest_path: None,
+                mem_diff_layers: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2135,6 +4737,95 @@ mod tests {
             VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetMemoryHotplugDevice(MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 2,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetLandlock(LandlockConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetGdb(GdbConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetStallDetection(StallDetectionConfig {
+                timeout_ms: 5000,
+                capture_registers: false,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetDeterministicBoot(DeterministicBootConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetVirtioRecord(VirtioRecordConfig { queues: vec![] }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetWorkingSet(WorkingSetConfig {
+                sample_interval_s: 30,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetIoThreads(IoThreadsConfig {
+                thread_count: 1,
+                devices: vec![],
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetMemoryPressure(
+                serde_json::from_str(r#"{"path": "/proc/pressure/memory", "action": "Pause"}"#)
+                    .unwrap(),
+            ),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetGuestFiles(
+                serde_json::from_str(r#"{"port": 52, "host_dirs": ["/a"], "guest_dirs": ["/b"]}"#)
+                    .unwrap(),
+            ),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetGuestFreeze(GuestFreezeConfig {
+                port: 53,
+                timeout_ms: 10_000,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetSmbios(SmbiosConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetIdentity(IdentityConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetTpm(TpmConfig {
+                socket_path: "/swtpm.sock".to_string(),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetConfidential(ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::ValidateVmConfig(VmmConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
     }
 
     fn verify_load_snap_disallowed_after_boot_resources(res: VmmAction, res_name: &str) {
@@ -2151,9 +4842,23 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: Vec::new(),
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -2181,6 +4886,13 @@ mod tests {
             drive_id: String::new(),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertBlockDevice");
 
@@ -2190,6 +4902,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
@@ -2198,8 +4916,13 @@ mod tests {
 
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
-            guest_cid: 0,
+            guest_cid: Some(0),
             uds_path: String::new(),
+            clock_port: None,
+            cid_lock_dir: None,
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
@@ -2213,5 +4936,37 @@ mod tests {
             network_interfaces: Vec::new(),
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
+
+        let req = VmmAction::SetMemoryHotplugDevice(MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetMemoryHotplugDevice");
+
+        let req = VmmAction::SetGdb(GdbConfig {
+            socket_path: Some(PathBuf::from("/gdb.sock")),
+            tcp_address: None,
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetGdb");
+
+        let req = VmmAction::SetDeterministicBoot(DeterministicBootConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetDeterministicBoot");
+
+        let req = VmmAction::SetSmbios(SmbiosConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetSmbios");
+
+        let req = VmmAction::SetVirtioRecord(VirtioRecordConfig { queues: vec![] });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetVirtioRecord");
+
+        let req = VmmAction::SetTpm(TpmConfig {
+            socket_path: "/swtpm.sock".to_string(),
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetTpm");
+
+        let req = VmmAction::SetConfidential(ConfidentialConfig {
+            technology: ConfidentialTechnology::SevSnp,
+            policy: DEFAULT_SEV_SNP_POLICY,
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetConfidential");
     }
 }
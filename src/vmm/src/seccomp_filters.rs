@@ -1,8 +1,18 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use std::cell::Cell;
 use std::sync::Arc;
 
-use seccompiler::BpfThreadMap;
+use logger::{IncMetric, SeccompThreadMetrics, METRICS};
+use seccompiler::{BpfProgramRef, BpfThreadMap, InstallationError};
+use serde::Serialize;
+
+/// Thread categories which each have their own seccomp filter.
+pub const THREAD_CATEGORIES: [&str; 5] = ["vmm", "api", "vcpu", "shared_dirs", "api_vsock"];
+
+// Metrics of the filter installed on the current thread, if any.
+type SeccompMetricsCell = Cell<Option<&'static SeccompThreadMetrics>>;
+thread_local!(static TLS_SECCOMP_METRICS: SeccompMetricsCell = Cell::new(None));
 
 /// Retrieve empty seccomp filters.
 pub fn get_empty_filters() -> BpfThreadMap {
@@ -10,5 +20,174 @@ pub fn get_empty_filters() -> BpfThreadMap {
     map.insert("vmm".to_string(), Arc::new(vec![]));
     map.insert("api".to_string(), Arc::new(vec![]));
     map.insert("vcpu".to_string(), Arc::new(vec![]));
+    map.insert("shared_dirs".to_string(), Arc::new(vec![]));
+    map.insert("api_vsock".to_string(), Arc::new(vec![]));
     map
 }
+
+fn thread_metrics(thread_category: &str) -> Option<&'static SeccompThreadMetrics> {
+    match thread_category {
+        "vmm" => Some(&METRICS.seccomp.vmm),
+        "api" => Some(&METRICS.seccomp.api),
+        "vcpu" => Some(&METRICS.seccomp.vcpu),
+        "shared_dirs" => Some(&METRICS.seccomp.shared_dirs),
+        "api_vsock" => Some(&METRICS.seccomp.api_vsock),
+        _ => None,
+    }
+}
+
+/// Installs `filter` on the calling thread, which belongs to `thread_category`.
+///
+/// Installing an empty filter is a no-op, like for `seccompiler::apply_filter`.
+pub fn install_filter(
+    thread_category: &str,
+    filter: BpfProgramRef,
+) -> Result<(), InstallationError> {
+    if filter.is_empty() {
+        return Ok(());
+    }
+    seccompiler::apply_filter(filter)?;
+
+    if let Some(metrics) = thread_metrics(thread_category) {
+        metrics.installed_filters.inc();
+        TLS_SECCOMP_METRICS.with(|cell| cell.set(Some(metrics)));
+    }
+    Ok(())
+}
+
+/// Accounts for a syscall rejected by the filter of the calling thread.
+///
+/// This is called from the SIGSYS handler, so it only touches thread local storage and atomics.
+pub fn record_violation() {
+    if let Ok(Some(metrics)) = TLS_SECCOMP_METRICS.try_with(Cell::get) {
+        metrics.num_faults.inc();
+    }
+}
+
+/// Origin of the seccomp filter of a thread category.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompFilterSource {
+    /// Seccomp filtering is disabled.
+    #[default]
+    None,
+    /// The default filter built into Firecracker.
+    Default,
+    /// A custom filter supplied on the command line.
+    Custom,
+}
+
+/// Description of the seccomp filter of a thread category.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeccompFilterInfo {
+    /// Thread category the filter applies to.
+    pub thread_category: String,
+    /// Origin of the filter.
+    pub source: SeccompFilterSource,
+    /// Number of BPF instructions in the filter.
+    pub instructions: usize,
+}
+
+impl SeccompFilterInfo {
+    /// Describes the filters of all the thread categories, which all come from `source`.
+    pub fn from_filters(filters: &BpfThreadMap, source: SeccompFilterSource) -> Vec<Self> {
+        THREAD_CATEGORIES
+            .iter()
+            .map(|category| SeccompFilterInfo {
+                thread_category: category.to_string(),
+                source,
+                instructions: filters.get(*category).map_or(0, |filter| filter.len()),
+            })
+            .collect()
+    }
+
+    /// Returns the current status of the filter.
+    pub fn status(&self) -> SeccompFilterStatus {
+        let (installed_threads, violations) =
+            thread_metrics(&self.thread_category).map_or((0, 0), |metrics| {
+                (
+                    metrics.installed_filters.count(),
+                    metrics.num_faults.count(),
+                )
+            });
+        SeccompFilterStatus {
+            thread_category: self.thread_category.clone(),
+            source: self.source,
+            instructions: self.instructions,
+            installed_threads,
+            violations,
+        }
+    }
+}
+
+/// Status of the seccomp filter of a thread category, as reported by the API.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SeccompFilterStatus {
+    /// Thread category the filter applies to.
+    pub thread_category: String,
+    /// Origin of the filter.
+    pub source: SeccompFilterSource,
+    /// Number of BPF instructions in the filter.
+    pub instructions: usize,
+    /// Number of threads the filter is installed on.
+    pub installed_threads: usize,
+    /// Number of syscalls rejected by the filter.
+    pub violations: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use seccompiler::sock_filter;
+
+    use super::*;
+
+    // Filter allowing all the syscalls.
+    fn allow_all_filter() -> Vec<sock_filter> {
+        vec![sock_filter {
+            code: 0x06,
+            jt: 0,
+            jf: 0,
+            k: 0x7fff_0000,
+        }]
+    }
+
+    #[test]
+    fn test_filter_info() {
+        let mut filters = get_empty_filters();
+        filters.insert("vcpu".to_string(), Arc::new(allow_all_filter()));
+
+        let info = SeccompFilterInfo::from_filters(&filters, SeccompFilterSource::Custom);
+        assert_eq!(info.len(), 5);
+        assert_eq!(info[0].thread_category, "vmm");
+        assert_eq!(info[0].instructions, 0);
+        assert_eq!(info[2].thread_category, "vcpu");
+        assert_eq!(info[2].instructions, 1);
+        assert!(info
+            .iter()
+            .all(|info| info.source == SeccompFilterSource::Custom));
+
+        let status = info[2].status();
+        assert_eq!(status.thread_category, "vcpu");
+        assert_eq!(status.source, SeccompFilterSource::Custom);
+        assert_eq!(status.instructions, 1);
+    }
+
+    #[test]
+    fn test_install_filter() {
+        std::thread::spawn(|| {
+            // Empty filters are not installed.
+            install_filter("api", &[]).unwrap();
+            assert!(TLS_SECCOMP_METRICS.with(Cell::get).is_none());
+
+            let installed = METRICS.seccomp.api.installed_filters.count();
+            install_filter("api", &allow_all_filter()).unwrap();
+            assert!(METRICS.seccomp.api.installed_filters.count() > installed);
+
+            let violations = METRICS.seccomp.api.num_faults.count();
+            record_violation();
+            assert!(METRICS.seccomp.api.num_faults.count() > violations);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use logger::{error, info};
+use serde::Serialize;
+
+use crate::vmm_config::shutdown_hooks::ShutdownHooksConfig;
+use crate::FcExitCode;
+
+// Set once the microVM starts, and read from the exit paths, including the panic hook and the
+// signal handlers.
+static SHUTDOWN_HOOKS: OnceLock<ShutdownHooksConfig> = OnceLock::new();
+// Set by the first exit path running the hooks, so that they are not run twice when a thread
+// crashes while Firecracker exits.
+static SHUTDOWN_HOOKS_RAN: AtomicBool = AtomicBool::new(false);
+
+/// Contents of the status file written on exit.
+#[derive(Debug, Serialize)]
+struct ExitStatus {
+    exit_code: u8,
+    exit_reason: String,
+}
+
+/// Arms the shutdown hooks of `config`. Only the first configuration is kept, since the
+/// microVM cannot be started twice.
+pub fn install(config: &ShutdownHooksConfig) {
+    if SHUTDOWN_HOOKS.set(config.clone()).is_err() {
+        error!("The shutdown hooks are already set");
+    }
+}
+
+/// Runs the shutdown hooks, if any, before Firecracker exits with `exit_code`. The failures are
+/// logged, and do not prevent the next actions from running.
+pub fn run(exit_code: FcExitCode) {
+    let Some(config) = SHUTDOWN_HOOKS.get() else {
+        return;
+    };
+    if SHUTDOWN_HOOKS_RAN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    for path in &config.remove_paths {
+        match fs::remove_file(path) {
+            Ok(()) => info!("Removed {} on exit", path.display()),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => error!("Failed to remove {} on exit: {}", path.display(), err),
+        }
+    }
+
+    if let Some(path) = &config.status_file {
+        let status = ExitStatus {
+            exit_code: exit_code as u8,
+            exit_reason: format!("{:?}", exit_code),
+        };
+        let mut contents = serde_json::to_string(&status).expect("Cannot serialize the status");
+        contents.push('\n');
+        if let Err(err) = fs::write(path, contents) {
+            error!(
+                "Failed to write the status file {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        // Nothing is installed yet.
+        run(FcExitCode::Ok);
+        assert!(!SHUTDOWN_HOOKS_RAN.load(Ordering::SeqCst));
+
+        let dir = TempDir::new().unwrap();
+        let socket = dir.as_path().join("api.sock");
+        fs::write(&socket, "").unwrap();
+        let status_file = dir.as_path().join("status.json");
+        install(&ShutdownHooksConfig {
+            remove_taps: false,
+            remove_paths: vec![socket.clone(), dir.as_path().join("missing")],
+            status_file: Some(status_file.clone()),
+        });
+
+        run(FcExitCode::SIGSEGV);
+        assert!(!socket.exists());
+        assert_eq!(
+            fs::read_to_string(&status_file).unwrap(),
+            "{\"exit_code\":150,\"exit_reason\":\"SIGSEGV\"}\n"
+        );
+
+        // The hooks only run once.
+        run(FcExitCode::Ok);
+        assert!(fs::read_to_string(&status_file)
+            .unwrap()
+            .contains("SIGSEGV"));
+    }
+}
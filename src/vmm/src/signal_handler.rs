@@ -1,11 +1,15 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::io;
+use std::sync::OnceLock;
+
 use libc::{
     c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGXCPU, SIGXFSZ,
 };
 use log::error;
 use logger::{IncMetric, StoreMetric, METRICS};
+use utils::eventfd::EventFd;
 use utils::signal::register_signal_handler;
 
 use crate::FcExitCode;
@@ -19,12 +23,17 @@ const SI_OFF_SYSCALL: isize = 6;
 
 const SYS_SECCOMP_CODE: i32 = 1;
 
+// The eventfd notified when the signal requesting the emergency snapshot is received. It is never
+// closed, so that the handler does not write to a file descriptor reused in the meantime.
+static EMERGENCY_SNAPSHOT_EVENT: OnceLock<EventFd> = OnceLock::new();
+
 #[inline]
 fn exit_with_code(exit_code: FcExitCode) {
     // Write the metrics before exiting.
     if let Err(err) = METRICS.write() {
         error!("Failed to write metrics while stopping: {}", err);
     }
+    crate::shutdown_hooks::run(exit_code);
     // SAFETY: Safe because we're terminating the process anyway.
     unsafe { libc::_exit(exit_code as i32) };
 }
@@ -64,6 +73,7 @@ fn log_sigsys_err(si_code: c_int, info: *mut siginfo_t) {
         // We received a SIGSYS for a reason other than `bad syscall`.
         exit_with_code(FcExitCode::UnexpectedError);
     }
+    crate::seccomp_filters::record_violation();
 
     // SAFETY: Other signals which might do async unsafe things incompatible with the rest of this
     // function are blocked due to the sa_mask used when registering the signal handler.
@@ -152,6 +162,30 @@ extern "C" fn sigpipe_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_
     error!("Received signal {}, code {}.", si_signo, si_code);
 }
 
+extern "C" fn emergency_snapshot_handler(_num: c_int, _info: *mut siginfo_t, _unused: *mut c_void) {
+    // The snapshot is taken by the VMM thread, the handler only notifies it through a `write`,
+    // which is async-signal-safe. A failed write means that a snapshot is already requested.
+    METRICS.signals.emergency_snapshot.inc();
+    if let Some(event_fd) = EMERGENCY_SNAPSHOT_EVENT.get() {
+        let _ = event_fd.write(1);
+    }
+}
+
+/// Registers the handler of `signal`, which requests the emergency snapshot. Returns the
+/// non-blocking eventfd notified on each request.
+pub fn register_emergency_snapshot_handler(signal: c_int) -> io::Result<EventFd> {
+    if EMERGENCY_SNAPSHOT_EVENT.get().is_none() {
+        let _ = EMERGENCY_SNAPSHOT_EVENT.set(EventFd::new(libc::EFD_NONBLOCK)?);
+    }
+    let event_fd = EMERGENCY_SNAPSHOT_EVENT
+        .get()
+        .expect("The emergency snapshot eventfd is set")
+        .try_clone()?;
+    register_signal_handler(signal, emergency_snapshot_handler)
+        .map_err(|err| io::Error::from_raw_os_error(err.errno()))?;
+    Ok(event_fd)
+}
+
 /// Registers all the required signal handlers.
 ///
 /// Custom handlers are installed for: `SIGBUS`, `SIGSEGV`, `SIGSYS`
@@ -189,8 +223,9 @@ mod tests {
 
             let filter = make_test_seccomp_bpf_filter();
 
-            assert!(seccompiler::apply_filter(&filter).is_ok());
+            assert!(crate::seccomp_filters::install_filter("vmm", &filter).is_ok());
             assert_eq!(METRICS.seccomp.num_faults.fetch(), 0);
+            assert_eq!(METRICS.seccomp.vmm.num_faults.count(), 0);
 
             // Call the forbidden `SYS_mkdirat`.
             unsafe { libc::syscall(libc::SYS_mkdirat, "/foo/bar\0") };
@@ -240,6 +275,7 @@ mod tests {
         assert!(child.join().is_ok());
 
         assert!(METRICS.seccomp.num_faults.fetch() >= 1);
+        assert!(METRICS.seccomp.vmm.num_faults.count() >= 1);
         assert!(METRICS.signals.sigbus.fetch() >= 1);
         assert!(METRICS.signals.sigsegv.fetch() >= 1);
         assert!(METRICS.signals.sigxfsz.fetch() >= 1);
@@ -249,6 +285,20 @@ mod tests {
         assert!(METRICS.signals.sigill.fetch() >= 1);
     }
 
+    #[test]
+    fn test_emergency_snapshot_handler() {
+        let event_fd = register_emergency_snapshot_handler(libc::SIGUSR2).unwrap();
+        event_fd.read().unwrap_err();
+
+        // The signal is handled by the calling thread, since it is not blocked.
+        unsafe {
+            let tid = syscall(libc::SYS_gettid);
+            syscall(libc::SYS_tgkill, process::id(), tid, libc::SIGUSR2);
+        }
+        assert_eq!(event_fd.read().unwrap(), 1);
+        assert!(METRICS.signals.emergency_snapshot.count() >= 1);
+    }
+
     fn make_test_seccomp_bpf_filter() -> Vec<sock_filter> {
         // Create seccomp filter that allows all syscalls, except for `SYS_mkdirat`.
         // For some reason, directly calling `SYS_kill` with SIGSYS, like we do with the
@@ -0,0 +1,322 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Manifests of the layered snapshots, listing the files a snapshot is loaded from.
+//!
+//! A layered snapshot is made of a full snapshot and of the diff snapshots created after it. Its
+//! manifest references the microVM state file of the last layer, the memory files of all the
+//! layers, the files backing the drives, and the images the snapshot requires:
+//!
+//! ```json
+//! {
+//!     "vmstate": "vmstate.2",
+//!     "memory_layers": ["mem.0", "mem.1", "mem.2"],
+//!     "disks": [{"drive_id": "rootfs", "path_on_host": "rootfs.overlay"}],
+//!     "kernel": {"path": "/images/vmlinux", "sha256": "5e8b..."},
+//!     "rootfs": {"path": "/images/rootfs.ext4", "sha256": "0c6f..."}
+//! }
+//! ```
+//!
+//! The relative paths are resolved from the directory of the manifest.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use aws_lc_rs::digest::{Context, SHA256};
+use serde::Deserialize;
+
+use crate::vmm_config::snapshot::{DriveOverride, LoadSnapshotParams};
+
+/// Errors associated with the snapshot manifests.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotManifestError {
+    /// Cannot read the manifest.
+    #[error("Cannot read the snapshot manifest: {0}")]
+    Read(io::Error),
+    /// The manifest is not a valid one.
+    #[error("Invalid snapshot manifest: {0}")]
+    Parse(serde_json::Error),
+    /// The manifest does not reference any memory file.
+    #[error("The snapshot manifest has no memory layer.")]
+    NoMemoryLayer,
+    /// A digest of the manifest is not a hexadecimal SHA-256 digest.
+    #[error("Invalid SHA-256 digest in the snapshot manifest: {0:?}")]
+    InvalidDigest(String),
+    /// Cannot read an image required by the manifest.
+    #[error("Cannot read the image {0:?} required by the snapshot manifest: {1}")]
+    ReadImage(PathBuf, io::Error),
+    /// An image does not have the digest required by the manifest.
+    #[error("The image {0:?} has the SHA-256 digest {2}, instead of {1}.")]
+    DigestMismatch(PathBuf, String, String),
+}
+
+/// Image a snapshot was created from, identified by its digest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequiredImage {
+    /// Path to the image.
+    pub path: PathBuf,
+    /// SHA-256 digest of the image, in hexadecimal.
+    pub sha256: String,
+}
+
+/// Files a layered snapshot is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotManifest {
+    /// Path to the microVM state file of the last layer.
+    pub vmstate: PathBuf,
+    /// Paths to the memory files of the layers: the one of the full snapshot first, then the
+    /// ones of the diff snapshots, from the oldest to the newest.
+    pub memory_layers: Vec<PathBuf>,
+    /// Files backing the drives instead of the ones of the snapshot, such as the overlays
+    /// holding the writes of the guest.
+    #[serde(default)]
+    pub disks: Vec<DriveOverride>,
+    /// Kernel image the snapshot was created from.
+    #[serde(default)]
+    pub kernel: Option<RequiredImage>,
+    /// Root filesystem image the disks of the snapshot are based on.
+    #[serde(default)]
+    pub rootfs: Option<RequiredImage>,
+}
+
+impl SnapshotManifest {
+    /// Reads the manifest at `path`, and resolves its relative paths from its directory.
+    pub fn from_file(path: &Path) -> Result<Self, SnapshotManifestError> {
+        let contents = std::fs::read(path).map_err(SnapshotManifestError::Read)?;
+        let mut manifest: Self =
+            serde_json::from_slice(&contents).map_err(SnapshotManifestError::Parse)?;
+        if manifest.memory_layers.is_empty() {
+            return Err(SnapshotManifestError::NoMemoryLayer);
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        manifest.vmstate = dir.join(&manifest.vmstate);
+        for layer in manifest.memory_layers.iter_mut() {
+            *layer = dir.join(layer.as_path());
+        }
+        for disk in manifest.disks.iter_mut() {
+            disk.path_on_host = dir.join(&disk.path_on_host).to_string_lossy().into_owned();
+        }
+        for image in manifest.kernel.iter_mut().chain(manifest.rootfs.iter_mut()) {
+            if image.sha256.len() != 64 || !image.sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(SnapshotManifestError::InvalidDigest(image.sha256.clone()));
+            }
+            image.sha256.make_ascii_lowercase();
+            image.path = dir.join(&image.path);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Checks that the images required by the manifest have their digests.
+    pub fn check_images(&self) -> Result<(), SnapshotManifestError> {
+        for image in self.kernel.iter().chain(self.rootfs.iter()) {
+            let digest = sha256_hex(&image.path)
+                .map_err(|err| SnapshotManifestError::ReadImage(image.path.clone(), err))?;
+            if digest != image.sha256 {
+                return Err(SnapshotManifestError::DigestMismatch(
+                    image.path.clone(),
+                    image.sha256.clone(),
+                    digest,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the files `params` loads the snapshot from to the ones of the manifest. The drive
+    /// overrides of `params` take precedence over the disks of the manifest.
+    pub fn apply(self, params: &mut LoadSnapshotParams) {
+        let mut layers = self.memory_layers.into_iter();
+        params.snapshot_path = self.vmstate;
+        params.mem_backend.backend_path = layers.next().unwrap_or_default();
+        params.mem_diff_layers = layers.collect();
+        for disk in self.disks {
+            if !params
+                .drive_overrides
+                .iter()
+                .any(|drive| drive.drive_id == disk.drive_id)
+            {
+                params.drive_overrides.push(disk);
+            }
+        }
+    }
+}
+
+// Returns the SHA-256 digest of the file at `path`, in hexadecimal.
+fn sha256_hex(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => context.update(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+    use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
+
+    // SHA-256 digest of "abc".
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn write_manifest(dir: &Path, contents: &str) -> PathBuf {
+        let path = dir.join("manifest.json");
+        File::create(&path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+        path
+    }
+
+    fn check_manifest_images(path: &Path) -> Result<(), SnapshotManifestError> {
+        SnapshotManifest::from_file(path)?.check_images()
+    }
+
+    fn load_params() -> LoadSnapshotParams {
+        LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_backend: MemBackendConfig {
+                backend_path: PathBuf::new(),
+                backend_type: MemBackendType::File,
+                handler_exit_action: None,
+                fallback_mem_file_path: None,
+                write_protect: false,
+                readahead_mib_per_s: None,
+                cache_path: None,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            timeout_ms: None,
+            vsock_override: None,
+            restore_info: None,
+            network_overrides: Vec::new(),
+            drive_overrides: vec![DriveOverride {
+                drive_id: "scratch".to_string(),
+                path_on_host: "/srv/scratch".to_string(),
+            }],
+            mmds: None,
+            vcpu_count: None,
+            manifest_path: None,
+            mem_diff_layers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_manifest() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let image_path = dir.join("rootfs.ext4");
+        File::create(&image_path)
+            .unwrap()
+            .write_all(b"abc")
+            .unwrap();
+
+        let manifest_path = write_manifest(
+            dir,
+            &format!(
+                r#"{{
+                    "vmstate": "vmstate.1",
+                    "memory_layers": ["mem.0", "/srv/mem.1"],
+                    "disks": [
+                        {{"drive_id": "rootfs", "path_on_host": "rootfs.overlay"}},
+                        {{"drive_id": "scratch", "path_on_host": "scratch.ext4"}}
+                    ],
+                    "rootfs": {{"path": "rootfs.ext4", "sha256": "{}"}}
+                }}"#,
+                ABC_SHA256.to_uppercase()
+            ),
+        );
+        let manifest = SnapshotManifest::from_file(&manifest_path).unwrap();
+        assert_eq!(manifest.memory_layers[1], PathBuf::from("/srv/mem.1"));
+        assert_eq!(manifest.rootfs.as_ref().unwrap().sha256, ABC_SHA256);
+        manifest.check_images().unwrap();
+
+        let mut params = load_params();
+        manifest.apply(&mut params);
+        assert_eq!(params.snapshot_path, dir.join("vmstate.1"));
+        assert_eq!(params.mem_backend.backend_path, dir.join("mem.0"));
+        assert_eq!(params.mem_diff_layers, vec![PathBuf::from("/srv/mem.1")]);
+        // The drive overrides of the request take precedence.
+        assert_eq!(
+            params.drive_overrides,
+            vec![
+                DriveOverride {
+                    drive_id: "scratch".to_string(),
+                    path_on_host: "/srv/scratch".to_string(),
+                },
+                DriveOverride {
+                    drive_id: "rootfs".to_string(),
+                    path_on_host: dir.join("rootfs.overlay").to_string_lossy().into_owned(),
+                },
+            ]
+        );
+
+        // The image changed since the snapshot was created.
+        File::create(&image_path)
+            .unwrap()
+            .write_all(b"abd")
+            .unwrap();
+        assert!(matches!(
+            check_manifest_images(&manifest_path),
+            Err(SnapshotManifestError::DigestMismatch(..))
+        ));
+        std::fs::remove_file(&image_path).unwrap();
+        assert!(matches!(
+            check_manifest_images(&manifest_path),
+            Err(SnapshotManifestError::ReadImage(..))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+
+        assert!(matches!(
+            SnapshotManifest::from_file(&dir.join("missing.json")),
+            Err(SnapshotManifestError::Read(_))
+        ));
+        let path = write_manifest(dir, r#"{"vmstate": "vmstate", "memory": ["mem"]}"#);
+        assert!(matches!(
+            SnapshotManifest::from_file(&path),
+            Err(SnapshotManifestError::Parse(_))
+        ));
+        let path = write_manifest(dir, r#"{"vmstate": "vmstate", "memory_layers": []}"#);
+        assert!(matches!(
+            SnapshotManifest::from_file(&path),
+            Err(SnapshotManifestError::NoMemoryLayer)
+        ));
+        let path = write_manifest(
+            dir,
+            r#"{
+                "vmstate": "vmstate",
+                "memory_layers": ["mem"],
+                "kernel": {"path": "vmlinux", "sha256": "sha256:ba78"}
+            }"#,
+        );
+        assert!(matches!(
+            SnapshotManifest::from_file(&path),
+            Err(SnapshotManifestError::InvalidDigest(_))
+        ));
+    }
+}
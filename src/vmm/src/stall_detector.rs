@@ -0,0 +1,255 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of the vCPUs which stop making progress.
+//!
+//! A hard-locked guest looks just like an idle one from the host: its vCPU threads either spin in
+//! `KVM_RUN` or sleep in it. The detector periodically kicks every running vCPU out of `KVM_RUN`
+//! and asks it to record a progress sample. A vCPU is stalled when its thread does not answer, or
+//! when the guest runs with interrupts disabled at every sample for the whole timeout.
+
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, io};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, info, warn, IncMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::stall_detection::StallDetectionConfig;
+use crate::{VcpuEvent, Vmm};
+
+// Number of samples taken during the stall timeout.
+const SAMPLES_PER_TIMEOUT: u64 = 4;
+
+/// Progress samples recorded by a vCPU thread.
+#[derive(Debug, Default)]
+pub struct VcpuProgress {
+    samples: AtomicU64,
+    interrupts_disabled: AtomicBool,
+    registers: Mutex<Option<String>>,
+}
+
+impl VcpuProgress {
+    /// Records a sample, along with the registers if they were captured.
+    pub fn record(&self, interrupts_disabled: bool, registers: Option<String>) {
+        if registers.is_some() {
+            *self.registers.lock().expect("Poisoned lock") = registers;
+        }
+        self.interrupts_disabled
+            .store(interrupts_disabled, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Release);
+    }
+
+    pub(crate) fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Acquire)
+    }
+
+    fn take_registers(&self) -> Option<String> {
+        self.registers.lock().expect("Poisoned lock").take()
+    }
+}
+
+// Reasons for which a vCPU does not make progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stall {
+    // The vCPU thread did not answer the last sample request.
+    Unresponsive,
+    // The guest ran with interrupts disabled when sampled.
+    InterruptsDisabled,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stall::Unresponsive => write!(f, "its thread does not answer"),
+            Stall::InterruptsDisabled => write!(f, "the guest runs with interrupts disabled"),
+        }
+    }
+}
+
+// Watch state of a single vCPU.
+#[derive(Debug, Default)]
+struct VcpuWatch {
+    progress: Arc<VcpuProgress>,
+    // Number of samples when the last sample request was sent, if it is still outstanding.
+    last_samples: Option<u64>,
+    stalled_periods: u64,
+    reported: bool,
+}
+
+impl VcpuWatch {
+    // Classifies the period elapsed since the last sample request.
+    fn observe(&mut self) -> Option<Stall> {
+        let last_samples = self.last_samples?;
+        let stall = if self.progress.samples() == last_samples {
+            Stall::Unresponsive
+        } else if self.progress.interrupts_disabled.load(Ordering::Relaxed) {
+            Stall::InterruptsDisabled
+        } else {
+            self.stalled_periods = 0;
+            return None;
+        };
+        self.stalled_periods += 1;
+        Some(stall)
+    }
+
+    // Marks a new sample request as sent, unless the previous one is still outstanding.
+    fn start_request(&mut self) -> bool {
+        let samples = self.progress.samples();
+        if self.last_samples == Some(samples) {
+            return false;
+        }
+        self.last_samples = Some(samples);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.last_samples = None;
+        self.stalled_periods = 0;
+        self.reported = false;
+    }
+}
+
+/// Periodically samples the progress of the vCPUs and reports the stalled ones.
+pub struct StallDetector {
+    vmm: Arc<Mutex<Vmm>>,
+    config: StallDetectionConfig,
+    timer: TimerFd,
+    vcpus: Vec<VcpuWatch>,
+}
+
+impl fmt::Debug for StallDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StallDetector")
+            .field("config", &self.config)
+            .field("vcpus", &self.vcpus)
+            .finish()
+    }
+}
+
+impl StallDetector {
+    /// Creates a detector watching the vCPUs of `vmm`.
+    pub fn new(vmm: Arc<Mutex<Vmm>>, config: StallDetectionConfig) -> io::Result<Self> {
+        let vcpu_count = vmm.lock().expect("Poisoned lock").vcpus_handles.len();
+        let mut timer = TimerFd::new_custom(ClockId::Monotonic, true, true)?;
+        let period = Duration::from_millis(config.timeout_ms / SAMPLES_PER_TIMEOUT);
+        timer.set_state(
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            },
+            SetTimeFlags::Default,
+        );
+
+        Ok(StallDetector {
+            vmm,
+            config,
+            timer,
+            vcpus: (0..vcpu_count).map(|_| VcpuWatch::default()).collect(),
+        })
+    }
+
+    fn check(&mut self) {
+        let vmm = self.vmm.lock().expect("Poisoned lock");
+        // Paused vCPUs are not expected to make progress.
+        if vmm.instance_info.state != VmState::Running {
+            self.vcpus.iter_mut().for_each(VcpuWatch::reset);
+            return;
+        }
+
+        for (index, (watch, handle)) in self
+            .vcpus
+            .iter_mut()
+            .zip(vmm.vcpus_handles.iter())
+            .enumerate()
+        {
+            let mut capture = false;
+            match watch.observe() {
+                Some(stall) if watch.stalled_periods == SAMPLES_PER_TIMEOUT => {
+                    METRICS.vcpu.stalls.inc();
+                    watch.reported = true;
+                    warn!(
+                        "vCPU {} made no progress for {} ms: {}.",
+                        index, self.config.timeout_ms, stall
+                    );
+                    capture = self.config.capture_registers && stall == Stall::InterruptsDisabled;
+                }
+                Some(_) => (),
+                None if watch.reported => {
+                    info!("vCPU {} makes progress again.", index);
+                    watch.reported = false;
+                }
+                None => (),
+            }
+
+            if let Some(registers) = watch.progress.take_registers() {
+                warn!("Registers of the stalled vCPU {}: {}", index, registers);
+            }
+
+            if watch.start_request() {
+                let event = VcpuEvent::SampleProgress(watch.progress.clone(), capture);
+                if let Err(err) = handle.send_event(event) {
+                    error!("Failed to sample the progress of vCPU {}: {}", index, err);
+                }
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for StallDetector {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        if event.fd() == self.timer.as_raw_fd() && event.event_set() == EventSet::IN {
+            self.timer.read();
+            self.check();
+        } else {
+            error!("Spurious EventManager event for handler: StallDetector");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!("Failed to register the stall detector timer: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_watch() {
+        let mut watch = VcpuWatch::default();
+        // Nothing to classify before the first request.
+        assert_eq!(watch.observe(), None);
+        assert!(watch.start_request());
+        // The previous request is still outstanding.
+        assert!(!watch.start_request());
+        assert_eq!(watch.observe(), Some(Stall::Unresponsive));
+        assert_eq!(watch.observe(), Some(Stall::Unresponsive));
+        assert_eq!(watch.stalled_periods, 2);
+
+        watch.progress.record(false, None);
+        assert_eq!(watch.observe(), None);
+        assert_eq!(watch.stalled_periods, 0);
+        assert!(watch.start_request());
+
+        watch
+            .progress
+            .record(true, Some(String::from("rip=0xffff")));
+        assert_eq!(watch.observe(), Some(Stall::InterruptsDisabled));
+        assert_eq!(watch.progress.take_registers().unwrap(), "rip=0xffff");
+        assert_eq!(watch.progress.take_registers(), None);
+
+        watch.reported = true;
+        watch.reset();
+        assert_eq!(watch.observe(), None);
+        assert_eq!(watch.stalled_periods, 0);
+        assert!(!watch.reported);
+    }
+}
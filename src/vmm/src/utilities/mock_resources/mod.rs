@@ -44,7 +44,10 @@ impl MockBootSourceConfig {
         MockBootSourceConfig(BootSourceConfig {
             kernel_image_path: kernel_image_path(None),
             initrd_path: None,
+            initrd_paths: Vec::new(),
             boot_args: None,
+            pvh_firmware_path: None,
+            pvh_boot: false,
         })
     }
 
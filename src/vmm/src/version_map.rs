@@ -9,11 +9,19 @@ use lazy_static::lazy_static;
 use semver::Version;
 use versionize::{VersionMap, Versionize};
 
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::gic::GicState;
 use crate::device_manager::persist::DeviceStates;
-use crate::devices::virtio::block::persist::BlockState;
-use crate::devices::virtio::net::persist::NetConfigSpaceState;
+use crate::devices::virtio::balloon::persist::{
+    BalloonConfigSpaceState, BalloonState, BalloonStatsState,
+};
+use crate::devices::virtio::block::persist::{BlockState, CacheTypeState};
+use crate::devices::virtio::net::persist::{NetConfigSpaceState, NetState};
+use crate::devices::virtio::vsock::persist::VsockUdsState;
 use crate::devices::virtio::QueueState;
+use crate::memory_snapshot::GuestMemoryRegionState;
 use crate::persist::VmInfo;
+use crate::vmm_config::boot_source::BootSourceConfig;
 use crate::vstate::vcpu::VcpuState;
 use crate::vstate::vm::VmState;
 
@@ -36,6 +44,9 @@ pub const FC_V1_3_SNAP_VERSION: u16 = 7;
 pub const FC_V1_4_SNAP_VERSION: u16 = 8;
 /// Snap version for Firecracker v1.5
 pub const FC_V1_5_SNAP_VERSION: u16 = 9;
+/// Oldest snap version always translated to the current one when loading a snapshot: the one of
+/// the Firecracker release two minor versions older than this one.
+pub const FC_MIN_TRANSLATED_SNAP_VERSION: u16 = FC_V1_3_SNAP_VERSION;
 
 lazy_static! {
     // Note: until we have a better design, this needs to be updated when the version changes.
@@ -77,8 +88,21 @@ lazy_static! {
         version_map.new_version();
         #[cfg(target_arch = "aarch64")]
         version_map.set_type_version(VcpuState::type_id(), 2);
+        #[cfg(target_arch = "aarch64")]
+        version_map.set_type_version(GicState::type_id(), 2);
 
         version_map.set_type_version(VmState::type_id(), 2);
+        version_map.set_type_version(VmInfo::type_id(), 3);
+        version_map.set_type_version(BootSourceConfig::type_id(), 2);
+        version_map.set_type_version(DeviceStates::type_id(), 5);
+        version_map.set_type_version(BalloonState::type_id(), 2);
+        version_map.set_type_version(BalloonStatsState::type_id(), 2);
+        version_map.set_type_version(BalloonConfigSpaceState::type_id(), 2);
+        version_map.set_type_version(BlockState::type_id(), 4);
+        version_map.set_type_version(CacheTypeState::type_id(), 2);
+        version_map.set_type_version(NetState::type_id(), 2);
+        version_map.set_type_version(VsockUdsState::type_id(), 2);
+        version_map.set_type_version(GuestMemoryRegionState::type_id(), 2);
 
         version_map
     };
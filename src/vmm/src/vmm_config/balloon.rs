@@ -5,9 +5,10 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-pub use crate::devices::virtio::balloon::device::BalloonStats;
+pub use crate::devices::virtio::balloon::device::{BalloonHintingStatus, BalloonStats};
 pub use crate::devices::virtio::BALLOON_DEV_ID;
 use crate::devices::virtio::{Balloon, BalloonConfig};
+use crate::vmm_config::memory_hotplug::MemoryHotplugConfigError;
 
 type MutexBalloon = Arc<Mutex<Balloon>>;
 
@@ -26,6 +27,20 @@ pub enum BalloonConfigError {
     /// Amount of pages requested is too large.
     #[error("Amount of pages requested is too large.")]
     TooManyPagesRequested,
+    /// The balloon update does not specify exactly one target.
+    #[error(
+        "Exactly one of `amount_mib`, `amount_bytes` and `usable_memory_bytes` must be specified."
+    )]
+    InvalidTarget,
+    /// The usable memory requested is larger than the boot memory and the memory hotplug region.
+    #[error("The usable memory requested is larger than the memory available to the guest.")]
+    UsableMemoryTooLarge,
+    /// The guest memory of confidential microVMs cannot be reclaimed.
+    #[error("Confidential microVMs cannot have a balloon device.")]
+    ConfidentialGuest,
+    /// Failed to resize the memory plugged through the memory hotplug device.
+    #[error("Error resizing the hotplugged memory: {0}")]
+    MemoryHotplug(MemoryHotplugConfigError),
     /// The user polled the statistics of a balloon device that
     /// does not have the statistics enabled.
     #[error("Statistics for the balloon device are not enabled")]
@@ -44,31 +59,81 @@ pub enum BalloonConfigError {
 #[serde(deny_unknown_fields)]
 pub struct BalloonDeviceConfig {
     /// Target balloon size in MiB.
+    #[serde(default)]
     pub amount_mib: u32,
+    /// Target balloon size in bytes, rounded down to 4 KiB pages. Takes precedence over
+    /// `amount_mib` when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_bytes: Option<u64>,
     /// Option to deflate the balloon in case the guest is out of memory.
     pub deflate_on_oom: bool,
     /// Interval in seconds between refreshing statistics.
     #[serde(default)]
     pub stats_polling_interval_s: u16,
+    /// Option to let the guest report its free pages on request.
+    #[serde(default)]
+    pub free_page_hinting: bool,
+}
+
+impl BalloonDeviceConfig {
+    /// Returns the target balloon size in bytes.
+    pub fn target_bytes(&self) -> u64 {
+        self.amount_bytes
+            .unwrap_or_else(|| u64::from(self.amount_mib) << 20)
+    }
 }
 
 impl From<BalloonConfig> for BalloonDeviceConfig {
     fn from(state: BalloonConfig) -> Self {
         BalloonDeviceConfig {
             amount_mib: state.amount_mib,
+            // Only reported when the target is not a whole number of MiB.
+            amount_bytes: (state.amount_bytes != u64::from(state.amount_mib) << 20)
+                .then_some(state.amount_bytes),
             deflate_on_oom: state.deflate_on_oom,
             stats_polling_interval_s: state.stats_polling_interval_s,
+            free_page_hinting: state.free_page_hinting,
         }
     }
 }
 
 /// The data fed into a balloon update request. Currently, only the number
 /// of pages and the stats polling interval can be updated.
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BalloonUpdateConfig {
     /// Target balloon size in MiB.
-    pub amount_mib: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_mib: Option<u32>,
+    /// Target balloon size in bytes, rounded down to 4 KiB pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_bytes: Option<u64>,
+    /// Amount of memory the guest should be able to use, in bytes. The balloon is resized
+    /// accordingly and, when this exceeds the boot memory, the missing memory is plugged through
+    /// the memory hotplug device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usable_memory_bytes: Option<u64>,
+}
+
+/// Target of a balloon update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalloonTarget {
+    /// Target balloon size in bytes.
+    Size(u64),
+    /// Amount of memory the guest should be able to use, in bytes.
+    UsableMemory(u64),
+}
+
+impl BalloonUpdateConfig {
+    /// Returns the target requested by the update.
+    pub fn target(&self) -> Result<BalloonTarget, BalloonConfigError> {
+        match (self.amount_mib, self.amount_bytes, self.usable_memory_bytes) {
+            (Some(amount_mib), None, None) => Ok(BalloonTarget::Size(u64::from(amount_mib) << 20)),
+            (None, Some(amount_bytes), None) => Ok(BalloonTarget::Size(amount_bytes)),
+            (None, None, Some(usable_bytes)) => Ok(BalloonTarget::UsableMemory(usable_bytes)),
+            _ => Err(BalloonConfigError::InvalidTarget),
+        }
+    }
 }
 
 /// The data fed into a balloon statistics interval update request.
@@ -99,9 +164,10 @@ impl BalloonBuilder {
     /// If an entry already exists, it will overwrite it.
     pub fn set(&mut self, cfg: BalloonDeviceConfig) -> Result<(), BalloonConfigError> {
         self.inner = Some(Arc::new(Mutex::new(Balloon::new(
-            cfg.amount_mib,
+            cfg.target_bytes(),
             cfg.deflate_on_oom,
             cfg.stats_polling_interval_s,
+            cfg.free_page_hinting,
             // `restored` flag is false because this code path
             // is never called by snapshot restore functionality.
             false,
@@ -145,8 +211,10 @@ pub(crate) mod tests {
     pub(crate) fn default_config() -> BalloonDeviceConfig {
         BalloonDeviceConfig {
             amount_mib: 0,
+            amount_bytes: None,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
         }
     }
 
@@ -155,8 +223,10 @@ pub(crate) mod tests {
         let default_balloon_config = default_config();
         let balloon_config = BalloonDeviceConfig {
             amount_mib: 0,
+            amount_bytes: None,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_hinting: false,
         };
         assert_eq!(default_balloon_config, balloon_config);
         let mut builder = BalloonBuilder::new();
@@ -166,7 +236,10 @@ pub(crate) mod tests {
         assert_eq!(builder.get().unwrap().lock().unwrap().num_pages(), 0);
         assert_eq!(builder.get_config().unwrap(), default_balloon_config);
 
-        let _update_config = BalloonUpdateConfig { amount_mib: 5 };
+        let _update_config = BalloonUpdateConfig {
+            amount_mib: Some(5),
+            ..Default::default()
+        };
         let _stats_update_config = BalloonUpdateStatsConfig {
             stats_polling_interval_s: 5,
         };
@@ -176,23 +249,76 @@ pub(crate) mod tests {
     fn test_from_balloon_state() {
         let expected_balloon_config = BalloonDeviceConfig {
             amount_mib: 5,
+            amount_bytes: None,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_hinting: false,
         };
 
         let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
             amount_mib: 5,
+            amount_bytes: 5 << 20,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_hinting: false,
         });
 
         assert_eq!(expected_balloon_config, actual_balloon_config);
+
+        // Targets that are not a whole number of MiB are reported in bytes as well.
+        let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
+            amount_mib: 5,
+            amount_bytes: (5 << 20) + 4096,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 3,
+            free_page_hinting: false,
+        });
+        assert_eq!(actual_balloon_config.amount_bytes, Some((5 << 20) + 4096));
+        assert_eq!(actual_balloon_config.target_bytes(), (5 << 20) + 4096);
+    }
+
+    #[test]
+    fn test_update_target() {
+        let update = BalloonUpdateConfig {
+            amount_mib: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(update.target().unwrap(), BalloonTarget::Size(5 << 20));
+
+        let update = BalloonUpdateConfig {
+            amount_bytes: Some(4096),
+            ..Default::default()
+        };
+        assert_eq!(update.target().unwrap(), BalloonTarget::Size(4096));
+
+        let update = BalloonUpdateConfig {
+            usable_memory_bytes: Some(256 << 20),
+            ..Default::default()
+        };
+        assert_eq!(
+            update.target().unwrap(),
+            BalloonTarget::UsableMemory(256 << 20)
+        );
+
+        assert!(matches!(
+            BalloonUpdateConfig::default().target(),
+            Err(BalloonConfigError::InvalidTarget)
+        ));
+        let update = BalloonUpdateConfig {
+            amount_mib: Some(5),
+            amount_bytes: Some(4096),
+            usable_memory_bytes: None,
+        };
+        assert!(matches!(
+            update.target(),
+            Err(BalloonConfigError::InvalidTarget)
+        ));
     }
 
     #[test]
     fn test_set_device() {
         let mut builder = BalloonBuilder::new();
-        let balloon = Balloon::new(0, true, 0, true).unwrap();
+        let balloon = Balloon::new(0, true, 0, false, true).unwrap();
         builder.set_device(Arc::new(Mutex::new(balloon)));
         assert!(builder.inner.is_some());
     }
@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 
+use super::open_file;
+
 /// Default guest kernel command line:
 /// - `reboot=k` shut down the guest on reboot, instead of well... rebooting;
 /// - `panic=1` on panic, reboot after 1 second;
@@ -26,14 +28,27 @@ pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodule 8250
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize, Versionize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
-    /// Path of the kernel image.
+    /// Path of the kernel image. Left empty when booting through a PVH firmware image.
+    #[serde(default)]
     pub kernel_image_path: String,
     /// Path of the initrd, if there is one.
     pub initrd_path: Option<String>,
+    /// Paths of additional initrd images, loaded concatenated after `initrd_path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[version(start = 2)]
+    pub initrd_paths: Vec<String>,
     /// The boot arguments to pass to the kernel. If this field is uninitialized,
     /// DEFAULT_KERNEL_CMDLINE is used.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub boot_args: Option<String>,
+    /// Path of a PVH firmware image to boot instead of a kernel, through its PVH entry point.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[version(start = 2)]
+    pub pvh_firmware_path: Option<String>,
+    /// Boots the kernel through its PVH entry point instead of the Linux boot protocol.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[version(start = 2)]
+    pub pvh_boot: bool,
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -42,6 +57,21 @@ pub enum BootSourceConfigError {
     /// The kernel file cannot be opened.
     #[error("The kernel file cannot be opened: {0}")]
     InvalidKernelPath(io::Error),
+    /// The PVH firmware file cannot be opened.
+    #[error("The PVH firmware file cannot be opened: {0}")]
+    InvalidPvhFirmwarePath(io::Error),
+    /// Both a kernel and a PVH firmware image were provided.
+    #[error("A kernel image and a PVH firmware image cannot be used together.")]
+    KernelAndPvhFirmware,
+    /// Neither a kernel nor a PVH firmware image was provided.
+    #[error("Either a kernel image or a PVH firmware image is required.")]
+    MissingBootImage,
+    /// Booting through a PVH firmware image is not supported on this architecture.
+    #[error("Booting through a PVH firmware image is not supported on this architecture.")]
+    PvhFirmwareNotSupported,
+    /// Booting through the PVH entry point is not supported on this architecture.
+    #[error("Booting through the PVH entry point is not supported on this architecture.")]
+    PvhBootNotSupported,
     /// The initrd file cannot be opened.
     #[error("The initrd file cannot be opened due to invalid path or invalid permissions. {0}")]
     InvalidInitrdPath(io::Error),
@@ -65,25 +95,54 @@ pub struct BootSource {
 pub struct BootConfig {
     /// The commandline validated against correctness.
     pub cmdline: linux_loader::cmdline::Cmdline,
-    /// The descriptor to the kernel file.
-    pub kernel_file: File,
-    /// The descriptor to the initrd file, if there is one.
-    pub initrd_file: Option<File>,
+    /// The descriptor to the kernel file, if booting a kernel directly.
+    pub kernel_file: Option<File>,
+    /// The descriptor to the PVH firmware file, if booting through a PVH firmware image.
+    pub pvh_firmware_file: Option<File>,
+    /// The descriptors to the initrd files, in the order they are loaded.
+    pub initrd_files: Vec<File>,
+    /// Whether the kernel is booted through its PVH entry point.
+    pub pvh_boot: bool,
 }
 
 impl BootConfig {
     /// Creates the BootConfig based on a given configuration.
     pub fn new(cfg: &BootSourceConfig) -> Result<Self, BootSourceConfigError> {
         use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath, KernelAndPvhFirmware,
+            MissingBootImage,
         };
 
         // Validate boot source config.
-        let kernel_file = File::open(&cfg.kernel_image_path).map_err(InvalidKernelPath)?;
-        let initrd_file: Option<File> = match &cfg.initrd_path {
-            Some(path) => Some(File::open(path).map_err(InvalidInitrdPath)?),
-            None => None,
-        };
+        let (kernel_file, pvh_firmware_file) =
+            match (cfg.kernel_image_path.as_str(), &cfg.pvh_firmware_path) {
+                ("", None) => return Err(MissingBootImage),
+                (_, None) => (
+                    Some(open_file(&cfg.kernel_image_path, false).map_err(InvalidKernelPath)?),
+                    None,
+                ),
+                #[cfg(target_arch = "x86_64")]
+                ("", Some(path)) => (
+                    None,
+                    Some(
+                        open_file(path, false)
+                            .map_err(BootSourceConfigError::InvalidPvhFirmwarePath)?,
+                    ),
+                ),
+                #[cfg(target_arch = "aarch64")]
+                ("", Some(_)) => return Err(BootSourceConfigError::PvhFirmwareNotSupported),
+                (_, Some(_)) => return Err(KernelAndPvhFirmware),
+            };
+        #[cfg(target_arch = "aarch64")]
+        if cfg.pvh_boot {
+            return Err(BootSourceConfigError::PvhBootNotSupported);
+        }
+        let initrd_files = cfg
+            .initrd_path
+            .iter()
+            .chain(cfg.initrd_paths.iter())
+            .map(|path| open_file(path, false).map_err(InvalidInitrdPath))
+            .collect::<Result<Vec<File>, _>>()?;
 
         let cmdline_str = match cfg.boot_args.as_ref() {
             None => DEFAULT_KERNEL_CMDLINE,
@@ -96,13 +155,17 @@ impl BootConfig {
         Ok(BootConfig {
             cmdline,
             kernel_file,
-            initrd_file,
+            pvh_firmware_file,
+            initrd_files,
+            pvh_boot: cfg.pvh_boot,
         })
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::os::linux::fs::MetadataExt;
+
     use utils::tempfile::TempFile;
 
     use super::*;
@@ -115,14 +178,111 @@ pub(crate) mod tests {
         let boot_src_cfg = BootSourceConfig {
             boot_args: None,
             initrd_path: None,
+            initrd_paths: Vec::new(),
             kernel_image_path: kernel_path,
+            pvh_firmware_path: None,
+            pvh_boot: false,
         };
 
         let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
-        assert!(boot_cfg.initrd_file.is_none());
+        assert!(boot_cfg.kernel_file.is_some());
+        assert!(boot_cfg.pvh_firmware_file.is_none());
+        assert!(boot_cfg.initrd_files.is_empty());
+        assert!(!boot_cfg.pvh_boot);
         assert_eq!(
             boot_cfg.cmdline.as_cstring().unwrap().as_bytes_with_nul(),
             [DEFAULT_KERNEL_CMDLINE.as_bytes(), &[b'\0']].concat()
         );
     }
+
+    #[test]
+    fn test_boot_config_initrds() {
+        let kernel_file = TempFile::new().unwrap();
+        let initrd_file = TempFile::new().unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        let path = |file: &TempFile| file.as_path().to_str().unwrap().to_string();
+
+        let mut boot_src_cfg = BootSourceConfig {
+            kernel_image_path: path(&kernel_file),
+            initrd_path: Some(path(&initrd_file)),
+            initrd_paths: vec![path(&overlay_file), path(&initrd_file)],
+            ..Default::default()
+        };
+
+        let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+        let inodes = boot_cfg
+            .initrd_files
+            .iter()
+            .map(|file| file.metadata().unwrap().st_ino())
+            .collect::<Vec<_>>();
+        let initrd_ino = initrd_file.as_file().metadata().unwrap().st_ino();
+        let overlay_ino = overlay_file.as_file().metadata().unwrap().st_ino();
+        assert_eq!(inodes, vec![initrd_ino, overlay_ino, initrd_ino]);
+
+        boot_src_cfg
+            .initrd_paths
+            .push(String::from("/invalid/initrd/path"));
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::InvalidInitrdPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_boot_config_pvh_firmware() {
+        let image_file = TempFile::new().unwrap();
+        let image_path = image_file.as_path().to_str().unwrap().to_string();
+
+        let mut boot_src_cfg = BootSourceConfig::default();
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::MissingBootImage)
+        ));
+
+        boot_src_cfg.kernel_image_path = image_path.clone();
+        boot_src_cfg.pvh_firmware_path = Some(image_path.clone());
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::KernelAndPvhFirmware)
+        ));
+
+        boot_src_cfg.kernel_image_path = String::new();
+        boot_src_cfg.pvh_firmware_path = Some(String::from("/invalid/firmware/path"));
+        #[cfg(target_arch = "x86_64")]
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::InvalidPvhFirmwarePath(_))
+        ));
+
+        boot_src_cfg.pvh_firmware_path = Some(image_path);
+        #[cfg(target_arch = "x86_64")]
+        {
+            let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+            assert!(boot_cfg.kernel_file.is_none());
+            assert!(boot_cfg.pvh_firmware_file.is_some());
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::PvhFirmwareNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_boot_config_pvh() {
+        let kernel_file = TempFile::new().unwrap();
+        let boot_src_cfg = BootSourceConfig {
+            kernel_image_path: kernel_file.as_path().to_str().unwrap().to_string(),
+            pvh_boot: true,
+            ..Default::default()
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        assert!(BootConfig::new(&boot_src_cfg).unwrap().pvh_boot);
+        #[cfg(target_arch = "aarch64")]
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::PvhBootNotSupported)
+        ));
+    }
 }
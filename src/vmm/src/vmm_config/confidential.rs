@@ -0,0 +1,159 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Default SEV-SNP guest policy: SMT allowed, and the reserved bit 17 set.
+pub const DEFAULT_SEV_SNP_POLICY: u64 = 0x3_0000;
+// Bit of the SEV-SNP guest policy which is reserved and must be set.
+const SEV_SNP_POLICY_RESERVED_ONE: u64 = 1 << 17;
+// Bits 63:25 of the SEV-SNP guest policy are reserved and must be clear.
+const SEV_SNP_POLICY_RESERVED_ZERO: u64 = !((1 << 25) - 1);
+
+/// Errors associated with the confidential computing configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ConfidentialConfigError {
+    /// Confidential microVMs are not supported on this architecture.
+    #[error("Confidential microVMs are only supported on x86_64.")]
+    UnsupportedArch,
+    /// TDX guests are not supported yet.
+    #[error("TDX guests are not supported yet.")]
+    TdxNotSupported,
+    /// The SEV-SNP guest policy has reserved bits with invalid values.
+    #[error(
+        "Invalid SEV-SNP guest policy {0:#x}: bit 17 must be set and bits 63:25 must be clear."
+    )]
+    InvalidPolicy(u64),
+    /// The guest memory cannot be reclaimed by a balloon device once encrypted.
+    #[error("Confidential microVMs cannot have a balloon device.")]
+    BalloonConfigured,
+    /// The microVM is not a confidential one.
+    #[error("The microVM is not a confidential microVM.")]
+    NotConfidential,
+}
+
+/// Hardware technology protecting the memory of a confidential microVM.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfidentialTechnology {
+    /// AMD Secure Encrypted Virtualization with Secure Nested Paging.
+    SevSnp,
+    /// Intel Trust Domain Extensions.
+    Tdx,
+}
+
+impl fmt::Display for ConfidentialTechnology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfidentialTechnology::SevSnp => write!(f, "sev-snp"),
+            ConfidentialTechnology::Tdx => write!(f, "tdx"),
+        }
+    }
+}
+
+fn default_policy() -> u64 {
+    DEFAULT_SEV_SNP_POLICY
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from confidential computing related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfidentialConfig {
+    /// Technology protecting the guest memory.
+    pub technology: ConfidentialTechnology,
+    /// Guest policy enforced by the firmware of the secure processor.
+    #[serde(default = "default_policy")]
+    pub policy: u64,
+}
+
+impl ConfidentialConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), ConfidentialConfigError> {
+        if cfg!(not(target_arch = "x86_64")) {
+            return Err(ConfidentialConfigError::UnsupportedArch);
+        }
+        match self.technology {
+            ConfidentialTechnology::SevSnp => {
+                if self.policy & SEV_SNP_POLICY_RESERVED_ONE == 0
+                    || self.policy & SEV_SNP_POLICY_RESERVED_ZERO != 0
+                {
+                    return Err(ConfidentialConfigError::InvalidPolicy(self.policy));
+                }
+                Ok(())
+            }
+            ConfidentialTechnology::Tdx => Err(ConfidentialConfigError::TdxNotSupported),
+        }
+    }
+}
+
+/// Information about the launch of a confidential microVM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ConfidentialInfo {
+    /// Technology protecting the guest memory.
+    pub technology: ConfidentialTechnology,
+    /// Guest policy enforced by the firmware of the secure processor.
+    pub policy: u64,
+    /// Hex encoded digest of the guest memory encrypted when launching the microVM.
+    pub launch_digest: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confidential_config() {
+        let config: ConfidentialConfig =
+            serde_json::from_str(r#"{"technology": "sev-snp"}"#).unwrap();
+        assert_eq!(
+            config,
+            ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY,
+            }
+        );
+        assert!(serde_json::from_str::<ConfidentialConfig>(r#"{}"#).is_err());
+        assert!(serde_json::from_str::<ConfidentialConfig>(r#"{"technology": "sev"}"#).is_err());
+        assert!(serde_json::from_str::<ConfidentialConfig>(
+            r#"{"technology": "tdx", "policy": 0, "foo": 1}"#
+        )
+        .is_err());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            config.validate().unwrap();
+            let config = ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: 0x1_0000,
+            };
+            assert_eq!(
+                config.validate(),
+                Err(ConfidentialConfigError::InvalidPolicy(0x1_0000))
+            );
+            let config = ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: DEFAULT_SEV_SNP_POLICY | 1 << 25,
+            };
+            assert_eq!(
+                config.validate(),
+                Err(ConfidentialConfigError::InvalidPolicy(0x203_0000))
+            );
+            let config = ConfidentialConfig {
+                technology: ConfidentialTechnology::Tdx,
+                policy: 0,
+            };
+            assert_eq!(
+                config.validate(),
+                Err(ConfidentialConfigError::TdxNotSupported)
+            );
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            config.validate(),
+            Err(ConfidentialConfigError::UnsupportedArch)
+        );
+    }
+}
@@ -0,0 +1,15 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Stores the configuration used for dumping the guest memory.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoredumpParams {
+    /// Path to the file that will contain the guest memory and the vCPU registers, in the ELF
+    /// core format.
+    pub dump_path: PathBuf,
+}
@@ -0,0 +1,71 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the deterministic boot configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DeterministicBootConfigError {
+    /// The start time does not fit in the 32-bit counter of the RTC.
+    #[error("The start time must be at most {}, got {0}.", u32::MAX)]
+    StartTimeOutOfRange(u64),
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from deterministic boot related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeterministicBootConfig {
+    /// Seed of the bytes served by the entropy device.
+    #[serde(default)]
+    pub seed: u64,
+    /// Time of the RTC when the guest boots, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub start_time_s: u64,
+}
+
+impl DeterministicBootConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), DeterministicBootConfigError> {
+        if self.start_time_s > u64::from(u32::MAX) {
+            return Err(DeterministicBootConfigError::StartTimeOutOfRange(
+                self.start_time_s,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_boot_config() {
+        let config: DeterministicBootConfig = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(config, DeterministicBootConfig::default());
+        config.validate().unwrap();
+
+        let config: DeterministicBootConfig =
+            serde_json::from_str(r#"{"seed": 42, "start_time_s": 1700000000}"#).unwrap();
+        assert_eq!(
+            config,
+            DeterministicBootConfig {
+                seed: 42,
+                start_time_s: 1_700_000_000,
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<DeterministicBootConfig>(r#"{"foo": 1}"#).is_err());
+
+        let config = DeterministicBootConfig {
+            seed: 0,
+            start_time_s: 1 << 32,
+        };
+        assert_eq!(
+            config.validate(),
+            Err(DeterministicBootConfigError::StartTimeOutOfRange(1 << 32))
+        );
+    }
+}
@@ -7,14 +7,19 @@ use std::io;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use super::RateLimiterConfig;
-pub use crate::devices::virtio::block::device::FileEngineType;
+use super::{DeviceRunState, RateLimiterConfig};
+pub use crate::devices::virtio::block::device::{
+    FadvisePolicy, FileEngineType, IoDepthLimits, PageCacheHints,
+};
 use crate::devices::virtio::block::BlockError;
-use crate::devices::virtio::Block;
 pub use crate::devices::virtio::CacheType;
+use crate::devices::virtio::{
+    Block, FIRECRACKER_MAX_QUEUE_SIZE, IO_URING_NUM_ENTRIES, MAX_BUSY_POLL_US,
+};
 use crate::VmmError;
 
 /// Errors associated with the operations allowed on a drive.
@@ -26,12 +31,38 @@ pub enum DriveError {
     /// Failed to create a `RateLimiter` object.
     #[error("Cannot create RateLimiter: {0}")]
     CreateRateLimiter(io::Error),
+    /// Error while flushing block devices.
+    #[error("Unable to flush the block devices: {0}")]
+    Flush(VmmError),
     /// Error during block device update (patch).
     #[error("Unable to patch the block device: {0}")]
     DeviceUpdate(VmmError),
     /// The block device path is invalid.
     #[error("Invalid block device path: {0}")]
     InvalidBlockDevicePath(String),
+    /// The io_uring ring size of the block device is invalid.
+    #[error(
+        "Invalid io_uring ring size {0}: it must be a power of two no larger than the virtio \
+         queue size."
+    )]
+    InvalidIoUringEntries(u16),
+    /// The busy-poll window of the block device is too long.
+    #[error("Invalid busy-poll window of {0} us: it must be at most {MAX_BUSY_POLL_US} us.")]
+    InvalidBusyPoll(u32),
+    /// The notification batch size of the block device is too large.
+    #[error(
+        "Invalid notification batch size {0}: it must be no larger than the virtio queue size."
+    )]
+    InvalidBatchSize(u16),
+    /// The block device id is unknown.
+    #[error("Invalid block device id: {0}")]
+    InvalidDriveId(String),
+    /// The cache mode or the direct I/O policy of a drive was patched after boot.
+    #[error("The cache type and direct I/O of a drive can only be updated before boot.")]
+    CachePolicyUpdate,
+    /// The processing state of a drive was patched before boot.
+    #[error("The state of a drive can only be updated after boot.")]
+    StateUpdate,
     /// A root block device was already added.
     #[error("A root block device already exists!")]
     RootBlockDeviceAlreadyAdded,
@@ -55,8 +86,8 @@ pub struct BlockDeviceConfig {
     /// If set to true, the drive is opened in read-only mode. Otherwise, the
     /// drive is opened as read-write.
     pub is_read_only: bool,
-    /// If set to true, the drive will ignore flush requests coming from
-    /// the guest driver.
+    /// Cache mode of the drive: whether the guest driver is told to send
+    /// flush requests and whether the drive honors them.
     #[serde(default)]
     pub cache_type: CacheType,
     /// Rate Limiter for I/O operations.
@@ -65,6 +96,36 @@ pub struct BlockDeviceConfig {
     #[serde(default)]
     #[serde(rename = "io_engine")]
     pub file_engine_type: FileEngineType,
+    /// If set to true, the backing file is opened with `O_DIRECT`, bypassing
+    /// the host page cache.
+    #[serde(default)]
+    pub direct_io: bool,
+    /// Access pattern advised to the host page cache for the backing file.
+    #[serde(default)]
+    pub fadvise: FadvisePolicy,
+    /// Size, in KiB, read ahead by the host past each guest read. 0 leaves read-ahead
+    /// to the host kernel.
+    #[serde(default)]
+    pub readahead_kib: u32,
+    /// Number of entries of the io_uring instance of the `Async` IO engine.
+    #[serde(default = "default_io_uring_entries")]
+    pub io_uring_entries: u16,
+    /// Maximum number of requests the `Async` IO engine has in flight at once. 0 only bounds
+    /// them by the io_uring ring size.
+    #[serde(default)]
+    pub max_in_flight_requests: u16,
+    /// Time, in microseconds, the queue is busy-polled for new requests before waiting for the
+    /// guest to notify the device. 0 disables the polling.
+    #[serde(default)]
+    pub busy_poll_us: u32,
+    /// Number of completed requests after which the guest is notified, even if more requests
+    /// are being processed. 0 only notifies the guest once the queue is processed.
+    #[serde(default)]
+    pub batch_size: u16,
+}
+
+fn default_io_uring_entries() -> u16 {
+    IO_URING_NUM_ENTRIES
 }
 
 impl From<&Block> for BlockDeviceConfig {
@@ -79,6 +140,13 @@ impl From<&Block> for BlockDeviceConfig {
             cache_type: block.cache_type(),
             rate_limiter: rl.into_option(),
             file_engine_type: block.file_engine_type(),
+            direct_io: block.direct_io(),
+            fadvise: block.page_cache_hints().fadvise,
+            readahead_kib: block.page_cache_hints().readahead_kib,
+            io_uring_entries: block.io_depth_limits().ring_entries,
+            max_in_flight_requests: block.io_depth_limits().max_in_flight,
+            busy_poll_us: u32::try_from(block.busy_poll().as_micros()).unwrap_or(u32::MAX),
+            batch_size: block.batch_size(),
         }
     }
 }
@@ -94,6 +162,12 @@ pub struct BlockDeviceUpdateConfig {
     pub path_on_host: Option<String>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// New processing state, to pause the device while its backing file is swapped.
+    pub state: Option<DeviceRunState>,
+    /// New cache mode. Only updatable before boot.
+    pub cache_type: Option<CacheType>,
+    /// New direct I/O policy. Only updatable before boot.
+    pub direct_io: Option<bool>,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -180,6 +254,34 @@ impl BlockBuilder {
         Ok(())
     }
 
+    /// Updates the configuration of the block device with the id of `update`, by creating it
+    /// again. Only the fields provided are updated.
+    pub fn update(&mut self, update: BlockDeviceUpdateConfig) -> Result<(), DriveError> {
+        if update.state.is_some() {
+            return Err(DriveError::StateUpdate);
+        }
+        let index = self
+            .get_index_of_drive_id(&update.drive_id)
+            .ok_or_else(|| DriveError::InvalidDriveId(update.drive_id.clone()))?;
+        let mut config =
+            BlockDeviceConfig::from(self.list[index].lock().expect("Poisoned lock").deref());
+        if let Some(path_on_host) = update.path_on_host {
+            config.path_on_host = path_on_host;
+        }
+        if let Some(rate_limiter) = update.rate_limiter {
+            config.rate_limiter = Some(rate_limiter);
+        }
+        if let Some(cache_type) = update.cache_type {
+            config.cache_type = cache_type;
+        }
+        if let Some(direct_io) = update.direct_io {
+            config.direct_io = direct_io;
+        }
+
+        self.list[index] = Arc::new(Mutex::new(Self::create_block(config)?));
+        Ok(())
+    }
+
     /// Creates a Block device from a BlockDeviceConfig.
     fn create_block(block_device_config: BlockDeviceConfig) -> Result<Block, DriveError> {
         // check if the path exists
@@ -189,6 +291,18 @@ impl BlockBuilder {
                 path_on_host.display().to_string(),
             ));
         }
+        let ring_entries = block_device_config.io_uring_entries;
+        if !ring_entries.is_power_of_two() || ring_entries > FIRECRACKER_MAX_QUEUE_SIZE {
+            return Err(DriveError::InvalidIoUringEntries(ring_entries));
+        }
+        if block_device_config.busy_poll_us > MAX_BUSY_POLL_US {
+            return Err(DriveError::InvalidBusyPoll(
+                block_device_config.busy_poll_us,
+            ));
+        }
+        if block_device_config.batch_size > FIRECRACKER_MAX_QUEUE_SIZE {
+            return Err(DriveError::InvalidBatchSize(block_device_config.batch_size));
+        }
 
         let rate_limiter = block_device_config
             .rate_limiter
@@ -197,7 +311,7 @@ impl BlockBuilder {
             .map_err(DriveError::CreateRateLimiter)?;
 
         // Create and return the Block device
-        Block::new(
+        let mut block = Block::new(
             block_device_config.drive_id,
             block_device_config.partuuid,
             block_device_config.cache_type,
@@ -206,8 +320,22 @@ impl BlockBuilder {
             block_device_config.is_root_device,
             rate_limiter.unwrap_or_default(),
             block_device_config.file_engine_type,
+            block_device_config.direct_io,
+            PageCacheHints {
+                fadvise: block_device_config.fadvise,
+                readahead_kib: block_device_config.readahead_kib,
+            },
+            IoDepthLimits {
+                ring_entries,
+                max_in_flight: block_device_config.max_in_flight_requests,
+            },
         )
-        .map_err(DriveError::CreateBlockDevice)
+        .map_err(DriveError::CreateBlockDevice)?;
+        block.set_busy_poll(Duration::from_micros(u64::from(
+            block_device_config.busy_poll_us,
+        )));
+        block.set_batch_size(block_device_config.batch_size);
+        Ok(block)
     }
 
     /// Returns a vec with the structures used to configure the devices.
@@ -246,6 +374,13 @@ mod tests {
                 drive_id: self.drive_id.clone(),
                 rate_limiter: None,
                 file_engine_type: FileEngineType::default(),
+                direct_io: self.direct_io,
+                fadvise: self.fadvise,
+                readahead_kib: self.readahead_kib,
+                io_uring_entries: self.io_uring_entries,
+                max_in_flight_requests: self.max_in_flight_requests,
+                busy_poll_us: self.busy_poll_us,
+                batch_size: self.batch_size,
             }
         }
     }
@@ -270,6 +405,13 @@ mod tests {
             drive_id: dummy_id.clone(),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -301,6 +443,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -329,6 +478,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -342,6 +498,13 @@ mod tests {
             drive_id: String::from("2"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -366,6 +529,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -379,6 +549,13 @@ mod tests {
             drive_id: String::from("2"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -392,6 +569,13 @@ mod tests {
             drive_id: String::from("3"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -430,6 +614,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -443,6 +634,13 @@ mod tests {
             drive_id: String::from("2"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -456,6 +654,13 @@ mod tests {
             drive_id: String::from("3"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -495,6 +700,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -508,6 +720,13 @@ mod tests {
             drive_id: String::from("2"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -567,6 +786,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
         // Switch roots and add a PARTUUID for the new one.
         let mut root_block_device_old = root_block_device;
@@ -580,6 +806,13 @@ mod tests {
             drive_id: String::from("2"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
         };
         assert!(block_devs.insert(root_block_device_old).is_ok());
         let root_block_id = root_block_device_new.drive_id.clone();
@@ -602,6 +835,13 @@ mod tests {
             drive_id: String::from("1"),
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::Sequential,
+            readahead_kib: 128,
+            io_uring_entries: 64,
+            max_in_flight_requests: 16,
+            busy_poll_us: 50,
+            batch_size: 8,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -610,6 +850,92 @@ mod tests {
         let configs = block_devs.configs();
         assert_eq!(configs.len(), 1);
         assert_eq!(configs.first().unwrap(), &dummy_block_device);
+
+        // The ring size must be a power of two the virtio queue can fill.
+        for io_uring_entries in [0, 100, 2 * FIRECRACKER_MAX_QUEUE_SIZE] {
+            let mut config = dummy_block_device.clone();
+            config.io_uring_entries = io_uring_entries;
+            assert_eq!(
+                block_devs.insert(config),
+                Err(DriveError::InvalidIoUringEntries(io_uring_entries))
+            );
+        }
+
+        let mut config = dummy_block_device.clone();
+        config.busy_poll_us = MAX_BUSY_POLL_US + 1;
+        assert_eq!(
+            block_devs.insert(config),
+            Err(DriveError::InvalidBusyPoll(MAX_BUSY_POLL_US + 1))
+        );
+
+        let mut config = dummy_block_device.clone();
+        config.batch_size = FIRECRACKER_MAX_QUEUE_SIZE + 1;
+        assert_eq!(
+            block_devs.insert(config),
+            Err(DriveError::InvalidBatchSize(FIRECRACKER_MAX_QUEUE_SIZE + 1))
+        );
+    }
+
+    #[test]
+    fn test_update_block_device() {
+        let dummy_file = TempFile::new().unwrap();
+        let dummy_block_device = BlockDeviceConfig {
+            path_on_host: dummy_file.as_path().to_str().unwrap().to_string(),
+            is_root_device: true,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            direct_io: false,
+            fadvise: FadvisePolicy::default(),
+            readahead_kib: 0,
+            io_uring_entries: IO_URING_NUM_ENTRIES,
+            max_in_flight_requests: 0,
+            busy_poll_us: 0,
+            batch_size: 0,
+        };
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(dummy_block_device.clone()).unwrap();
+
+        block_devs
+            .update(BlockDeviceUpdateConfig {
+                drive_id: String::from("1"),
+                cache_type: Some(CacheType::Writethrough),
+                ..Default::default()
+            })
+            .unwrap();
+        let config = block_devs.configs().pop().unwrap();
+        assert_eq!(config.cache_type, CacheType::Writethrough);
+        assert_eq!(config.path_on_host, dummy_block_device.path_on_host);
+        assert!(config.is_root_device);
+
+        assert_eq!(
+            block_devs.update(BlockDeviceUpdateConfig {
+                drive_id: String::from("2"),
+                direct_io: Some(false),
+                ..Default::default()
+            }),
+            Err(DriveError::InvalidDriveId(String::from("2")))
+        );
+        assert_eq!(
+            block_devs.update(BlockDeviceUpdateConfig {
+                drive_id: String::from("1"),
+                state: Some(DeviceRunState::Paused),
+                ..Default::default()
+            }),
+            Err(DriveError::StateUpdate)
+        );
+        // A failed update keeps the previous device.
+        assert!(block_devs
+            .update(BlockDeviceUpdateConfig {
+                drive_id: String::from("1"),
+                path_on_host: Some(String::from("/invalid/path")),
+                ..Default::default()
+            })
+            .is_err());
+        assert_eq!(block_devs.configs().pop().unwrap(), config);
     }
 
     #[test]
@@ -626,6 +952,9 @@ mod tests {
             true,
             RateLimiter::default(),
             FileEngineType::default(),
+            false,
+            PageCacheHints::default(),
+            IoDepthLimits::default(),
         )
         .unwrap();
 
@@ -0,0 +1,123 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use libc::c_int;
+use serde::{Deserialize, Serialize};
+
+use crate::vmm_config::snapshot::{SnapshotType, SuspendToDiskParams};
+
+/// Errors associated with the emergency snapshot configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EmergencySnapshotConfigError {
+    /// A path of the emergency snapshot is empty.
+    #[error("The paths of the emergency snapshot cannot be empty.")]
+    EmptyPath,
+}
+
+/// Signals which can request the emergency snapshot.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum EmergencySnapshotSignal {
+    /// `SIGUSR1`.
+    SIGUSR1,
+    /// `SIGUSR2`.
+    SIGUSR2,
+    /// `SIGTERM`, which would otherwise terminate Firecracker right away.
+    SIGTERM,
+}
+
+impl EmergencySnapshotSignal {
+    /// Number of the signal.
+    pub fn signum(self) -> c_int {
+        match self {
+            EmergencySnapshotSignal::SIGUSR1 => libc::SIGUSR1,
+            EmergencySnapshotSignal::SIGUSR2 => libc::SIGUSR2,
+            EmergencySnapshotSignal::SIGTERM => libc::SIGTERM,
+        }
+    }
+}
+
+/// Snapshot taken on a best-effort basis when the host is about to go away, e.g. when a spot
+/// instance is preempted. The microVM is suspended to disk to the pre-registered paths.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmergencySnapshotConfig {
+    /// Type of the snapshot, `Full` by default.
+    #[serde(default)]
+    pub snapshot_type: SnapshotType,
+    /// Path to the file that will contain the microVM state.
+    pub snapshot_path: PathBuf,
+    /// Path to the file that will contain the guest memory.
+    pub mem_file_path: PathBuf,
+    /// Path to the marker written once the snapshot files are durable on disk.
+    pub marker_path: PathBuf,
+    /// Signal requesting the snapshot, on top of the `EmergencySnapshot` action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<EmergencySnapshotSignal>,
+}
+
+impl EmergencySnapshotConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), EmergencySnapshotConfigError> {
+        if [&self.snapshot_path, &self.mem_file_path, &self.marker_path]
+            .iter()
+            .any(|path| path.as_os_str().is_empty())
+        {
+            return Err(EmergencySnapshotConfigError::EmptyPath);
+        }
+        Ok(())
+    }
+
+    /// Parameters suspending the microVM to the pre-registered paths.
+    pub fn suspend_params(&self) -> SuspendToDiskParams {
+        SuspendToDiskParams {
+            snapshot_type: self.snapshot_type,
+            snapshot_path: self.snapshot_path.clone(),
+            mem_file_path: self.mem_file_path.clone(),
+            marker_path: self.marker_path.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emergency_snapshot_config() {
+        let config: EmergencySnapshotConfig = serde_json::from_str(
+            r#"{
+                "snapshot_path": "/srv/vmstate",
+                "mem_file_path": "/srv/mem",
+                "marker_path": "/srv/suspended.json",
+                "signal": "SIGUSR1"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.snapshot_type, SnapshotType::Full);
+        assert_eq!(config.signal.unwrap().signum(), libc::SIGUSR1);
+        config.validate().unwrap();
+        assert_eq!(config.suspend_params().marker_path, config.marker_path);
+
+        serde_json::from_str::<EmergencySnapshotConfig>(
+            r#"{
+                "snapshot_path": "/srv/vmstate",
+                "mem_file_path": "/srv/mem",
+                "marker_path": "/srv/suspended.json",
+                "signal": "SIGKILL"
+            }"#,
+        )
+        .unwrap_err();
+
+        let config = EmergencySnapshotConfig {
+            marker_path: PathBuf::new(),
+            ..config
+        };
+        assert_eq!(
+            config.validate(),
+            Err(EmergencySnapshotConfigError::EmptyPath)
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the GDB stub configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum GdbConfigError {
+    /// Neither or both of the Unix socket path and the TCP address are set.
+    #[error("Exactly one of `socket_path` and `tcp_address` must be specified.")]
+    InvalidEndpoint,
+    /// The Unix socket path is empty.
+    #[error("The GDB stub socket path cannot be empty.")]
+    EmptySocketPath,
+    /// The GDB stub is not available on this architecture.
+    #[error("The GDB stub is only supported on x86_64.")]
+    UnsupportedArch,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from GDB stub related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GdbConfig {
+    /// Path of the Unix domain socket on which the stub waits for the debugger.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+    /// Address of the TCP socket on which the stub waits for the debugger.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_address: Option<SocketAddr>,
+}
+
+impl GdbConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), GdbConfigError> {
+        if cfg!(not(target_arch = "x86_64")) {
+            return Err(GdbConfigError::UnsupportedArch);
+        }
+        match (&self.socket_path, &self.tcp_address) {
+            (Some(path), None) if path.as_os_str().is_empty() => {
+                Err(GdbConfigError::EmptySocketPath)
+            }
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(GdbConfigError::InvalidEndpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: GdbConfig = serde_json::from_str(r#"{"socket_path": "/gdb.sock"}"#).unwrap();
+        assert_eq!(config.socket_path, Some(PathBuf::from("/gdb.sock")));
+        assert_eq!(config.tcp_address, None);
+
+        let config: GdbConfig =
+            serde_json::from_str(r#"{"tcp_address": "127.0.0.1:1234"}"#).unwrap();
+        assert_eq!(config.socket_path, None);
+        assert_eq!(
+            config.tcp_address,
+            Some(SocketAddr::from(([127, 0, 0, 1], 1234)))
+        );
+
+        assert!(serde_json::from_str::<GdbConfig>(r#"{"tcp_address": "localhost"}"#).is_err());
+        assert!(serde_json::from_str::<GdbConfig>(r#"{"foo": 1}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_validate() {
+        let socket = GdbConfig {
+            socket_path: Some(PathBuf::from("/gdb.sock")),
+            tcp_address: None,
+        };
+        socket.validate().unwrap();
+        let tcp = GdbConfig {
+            socket_path: None,
+            tcp_address: Some(SocketAddr::from(([127, 0, 0, 1], 1234))),
+        };
+        tcp.validate().unwrap();
+
+        assert_eq!(
+            GdbConfig::default().validate(),
+            Err(GdbConfigError::InvalidEndpoint)
+        );
+        assert_eq!(
+            GdbConfig {
+                socket_path: socket.socket_path,
+                tcp_address: tcp.tcp_address,
+            }
+            .validate(),
+            Err(GdbConfigError::InvalidEndpoint)
+        );
+        assert_eq!(
+            GdbConfig {
+                socket_path: Some(PathBuf::new()),
+                tcp_address: None,
+            }
+            .validate(),
+            Err(GdbConfigError::EmptySocketPath)
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_validate() {
+        let config = GdbConfig {
+            socket_path: Some(PathBuf::from("/gdb.sock")),
+            tcp_address: None,
+        };
+        assert_eq!(config.validate(), Err(GdbConfigError::UnsupportedArch));
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the guest files configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum GuestFilesConfigError {
+    /// No host or no guest directory is allowed.
+    #[error("At least one host directory and one guest directory must be allowed.")]
+    NoAllowedDir,
+    /// An allowed directory is not an absolute path.
+    #[error("The allowed directories must be absolute paths, got {0:?}.")]
+    RelativeDir(PathBuf),
+    /// The file size limit is null.
+    #[error("The file size limit must be at least 1 MiB.")]
+    NullMaxFileSize,
+    /// The timeout of the guest agent is null.
+    #[error("The timeout of the guest agent must be greater than 0 ms.")]
+    NullTimeout,
+}
+
+fn default_max_file_size_mib() -> u64 {
+    1024
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from guest files related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestFilesConfig {
+    /// Vsock port the guest agent listens on.
+    pub port: u32,
+    /// Host directories the files can be copied from and to.
+    pub host_dirs: Vec<PathBuf>,
+    /// Guest directories the files can be copied from and to.
+    pub guest_dirs: Vec<PathBuf>,
+    /// Whether the files can be copied to the guest. Only the copies from the guest are allowed
+    /// by default.
+    #[serde(default)]
+    pub allow_writes: bool,
+    /// Size, in MiB, of the largest file which can be copied.
+    #[serde(default = "default_max_file_size_mib")]
+    pub max_file_size_mib: u64,
+    /// Time, in milliseconds, the guest agent has to answer or to make progress.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl GuestFilesConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), GuestFilesConfigError> {
+        if self.host_dirs.is_empty() || self.guest_dirs.is_empty() {
+            return Err(GuestFilesConfigError::NoAllowedDir);
+        }
+        if let Some(dir) = self
+            .host_dirs
+            .iter()
+            .chain(&self.guest_dirs)
+            .find(|dir| !dir.is_absolute())
+        {
+            return Err(GuestFilesConfigError::RelativeDir(dir.clone()));
+        }
+        if self.max_file_size_mib == 0 {
+            return Err(GuestFilesConfigError::NullMaxFileSize);
+        }
+        if self.timeout_ms == 0 {
+            return Err(GuestFilesConfigError::NullTimeout);
+        }
+        Ok(())
+    }
+
+    /// Whether `path` can be copied from or to the host.
+    pub fn host_path_allowed(&self, path: &Path) -> bool {
+        is_in_dirs(path, &self.host_dirs)
+    }
+
+    /// Whether `path` can be copied from or to the guest.
+    pub fn guest_path_allowed(&self, path: &Path) -> bool {
+        is_in_dirs(path, &self.guest_dirs)
+    }
+}
+
+// The check is lexical, so that it does not depend on the files of the guest. The paths going
+// up the tree, or which cannot be sent on a single line to the guest agent, are refused.
+fn is_in_dirs(path: &Path, dirs: &[PathBuf]) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Component;
+
+    path.is_absolute()
+        && !path.as_os_str().as_bytes().contains(&b'\n')
+        && path
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+        && dirs.iter().any(|dir| path.starts_with(dir) && path != dir)
+}
+
+/// Direction of a guest file copy.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum GuestFileDirection {
+    /// Copies a file of the guest to the host.
+    FromGuest,
+    /// Copies a file of the host to the guest.
+    ToGuest,
+}
+
+/// Parameters of a guest file copy.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestFileCopyParams {
+    /// Direction of the copy.
+    pub direction: GuestFileDirection,
+    /// Path of the file in the guest.
+    pub guest_path: PathBuf,
+    /// Path of the file on the host.
+    pub host_path: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guest_files_config() {
+        let config: GuestFilesConfig = serde_json::from_str(
+            r#"{"port": 52, "host_dirs": ["/srv/outputs"], "guest_dirs": ["/workspace"]}"#,
+        )
+        .unwrap();
+        assert!(!config.allow_writes);
+        assert_eq!(config.max_file_size_mib, 1024);
+        assert_eq!(config.timeout_ms, 10_000);
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<GuestFilesConfig>(
+            r#"{"port": 52, "host_dirs": [], "guest_dirs": [], "foo": 1}"#
+        )
+        .is_err());
+
+        let invalid = |update: fn(&mut GuestFilesConfig)| {
+            let mut config = config.clone();
+            update(&mut config);
+            config.validate().unwrap_err()
+        };
+        assert_eq!(
+            invalid(|config| config.guest_dirs.clear()),
+            GuestFilesConfigError::NoAllowedDir
+        );
+        assert_eq!(
+            invalid(|config| config.host_dirs = vec![PathBuf::from("outputs")]),
+            GuestFilesConfigError::RelativeDir(PathBuf::from("outputs"))
+        );
+        assert_eq!(
+            invalid(|config| config.max_file_size_mib = 0),
+            GuestFilesConfigError::NullMaxFileSize
+        );
+        assert_eq!(
+            invalid(|config| config.timeout_ms = 0),
+            GuestFilesConfigError::NullTimeout
+        );
+    }
+
+    #[test]
+    fn test_allowed_paths() {
+        let config = GuestFilesConfig {
+            port: 52,
+            host_dirs: vec![PathBuf::from("/srv/outputs")],
+            guest_dirs: vec![PathBuf::from("/workspace"), PathBuf::from("/tmp/logs")],
+            allow_writes: false,
+            max_file_size_mib: 1,
+            timeout_ms: 1000,
+        };
+
+        assert!(config.host_path_allowed(Path::new("/srv/outputs/a.tar")));
+        assert!(config.host_path_allowed(Path::new("/srv/outputs/bin/b")));
+        assert!(config.guest_path_allowed(Path::new("/tmp/logs/build.log")));
+        // The directories themselves cannot be copied over.
+        assert!(!config.host_path_allowed(Path::new("/srv/outputs")));
+        // Neither can the files out of them.
+        assert!(!config.host_path_allowed(Path::new("/srv/outputs2/a")));
+        assert!(!config.host_path_allowed(Path::new("/srv/outputs/../secrets")));
+        assert!(!config.host_path_allowed(Path::new("srv/outputs/a")));
+        assert!(!config.guest_path_allowed(Path::new("/srv/outputs/a")));
+        assert!(!config.guest_path_allowed(Path::new("/workspace/a\nb")));
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the guest freeze configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum GuestFreezeConfigError {
+    /// The timeout of the guest agent is null.
+    #[error("The timeout of the guest agent must be greater than 0 ms.")]
+    NullTimeout,
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from guest freeze related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestFreezeConfig {
+    /// Vsock port the guest agent listens on.
+    pub port: u32,
+    /// Time, in milliseconds, the guest agent has to freeze or thaw the filesystems.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl GuestFreezeConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), GuestFreezeConfigError> {
+        if self.timeout_ms == 0 {
+            return Err(GuestFreezeConfigError::NullTimeout);
+        }
+        Ok(())
+    }
+}
+
+/// State of the guest filesystems.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum GuestFreezeState {
+    /// The filesystems are quiesced, their writes are blocked.
+    Frozen,
+    /// The filesystems are writable.
+    Thawed,
+}
+
+/// Parameters of a change of state of the guest filesystems.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestFreezeUpdate {
+    /// State the filesystems are to be left in.
+    pub state: GuestFreezeState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guest_freeze_config() {
+        let config: GuestFreezeConfig = serde_json::from_str(r#"{"port": 53}"#).unwrap();
+        assert_eq!(config.timeout_ms, 10_000);
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<GuestFreezeConfig>(r#"{"port": 53, "foo": 1}"#).is_err());
+        assert_eq!(
+            GuestFreezeConfig {
+                port: 53,
+                timeout_ms: 0
+            }
+            .validate(),
+            Err(GuestFreezeConfigError::NullTimeout)
+        );
+    }
+}
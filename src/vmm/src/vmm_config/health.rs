@@ -0,0 +1,151 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::METRICS;
+use serde::Serialize;
+
+/// Status of a subsystem of the microVM. The variants are ordered from the best to the worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum HealthStatus {
+    /// The subsystem is not used by the microVM.
+    Disabled,
+    /// The subsystem works as expected.
+    Ok,
+    /// The subsystem is not ready yet.
+    Starting,
+    /// The subsystem works, with a reduced service.
+    Degraded,
+    /// The subsystem stopped working.
+    Failed,
+}
+
+/// Health of a subsystem of the microVM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SubsystemHealth {
+    /// Status of the subsystem.
+    pub status: HealthStatus,
+    /// Why the subsystem is not healthy, if it is not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl SubsystemHealth {
+    /// Subsystem with the `status`, which needs no explanation.
+    pub fn new(status: HealthStatus) -> Self {
+        SubsystemHealth {
+            status,
+            detail: None,
+        }
+    }
+
+    /// Subsystem with the `status`, explained by `detail`.
+    pub fn with_detail(status: HealthStatus, detail: String) -> Self {
+        SubsystemHealth {
+            status,
+            detail: Some(detail),
+        }
+    }
+
+    /// Health of the writer of the metrics.
+    pub fn metrics() -> Self {
+        if !METRICS.is_initialized() {
+            SubsystemHealth::new(HealthStatus::Disabled)
+        } else if METRICS.last_write_failed() {
+            SubsystemHealth::with_detail(
+                HealthStatus::Failed,
+                "The last write of the metrics failed.".to_string(),
+            )
+        } else {
+            SubsystemHealth::new(HealthStatus::Ok)
+        }
+    }
+}
+
+/// Health of the subsystems of the microVM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Health {
+    /// Worst status of the subsystems.
+    pub status: HealthStatus,
+    /// The API server, which is healthy as long as it answers.
+    pub api: SubsystemHealth,
+    /// The vCPU threads.
+    pub vcpus: SubsystemHealth,
+    /// The virtio devices.
+    pub devices: SubsystemHealth,
+    /// The page fault handler serving the guest memory of a microVM restored through UFFD.
+    pub uffd_handler: SubsystemHealth,
+    /// The writer of the metrics.
+    pub metrics: SubsystemHealth,
+}
+
+impl Health {
+    /// Gathers the health of the subsystems.
+    pub fn new(
+        vcpus: SubsystemHealth,
+        devices: SubsystemHealth,
+        uffd_handler: SubsystemHealth,
+        metrics: SubsystemHealth,
+    ) -> Self {
+        let api = SubsystemHealth::new(HealthStatus::Ok);
+        let status = [&api, &vcpus, &devices, &uffd_handler, &metrics]
+            .iter()
+            .map(|subsystem| subsystem.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok);
+        Health {
+            status,
+            api,
+            vcpus,
+            devices,
+            uffd_handler,
+            metrics,
+        }
+    }
+
+    /// Health of a microVM which is not started yet.
+    pub fn not_started() -> Self {
+        let not_started = || {
+            SubsystemHealth::with_detail(
+                HealthStatus::Starting,
+                "The microVM is not started.".to_string(),
+            )
+        };
+        Health::new(
+            not_started(),
+            not_started(),
+            SubsystemHealth::new(HealthStatus::Disabled),
+            SubsystemHealth::metrics(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_status() {
+        let health = Health::new(
+            SubsystemHealth::new(HealthStatus::Ok),
+            SubsystemHealth::with_detail(HealthStatus::Degraded, "Paused: rootfs".to_string()),
+            SubsystemHealth::new(HealthStatus::Disabled),
+            SubsystemHealth::new(HealthStatus::Ok),
+        );
+        assert_eq!(health.status, HealthStatus::Degraded);
+        assert_eq!(
+            serde_json::to_value(&health).unwrap(),
+            serde_json::json!({
+                "status": "Degraded",
+                "api": {"status": "Ok"},
+                "vcpus": {"status": "Ok"},
+                "devices": {"status": "Degraded", "detail": "Paused: rootfs"},
+                "uffd_handler": {"status": "Disabled"},
+                "metrics": {"status": "Ok"},
+            })
+        );
+
+        let health = Health::not_started();
+        assert_eq!(health.status, HealthStatus::Starting);
+        assert_eq!(health.uffd_handler.status, HealthStatus::Disabled);
+    }
+}
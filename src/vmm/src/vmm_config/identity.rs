@@ -0,0 +1,221 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use aws_lc_rs::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Errors associated with the identity of the microVM.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum IdentityConfigError {
+    /// The UUID is not in its canonical text representation.
+    #[error("Invalid UUID {0}, expected the xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx format.")]
+    InvalidUuid(String),
+    /// No random bytes could be obtained for a new UUID.
+    #[error("Cannot generate a random UUID.")]
+    GenerateUuid,
+    /// The signing key file cannot be read.
+    #[error("Cannot read the signing key {0}: {1}")]
+    ReadSigningKey(String, String),
+    /// The signing key is not an Ed25519 private key.
+    #[error("The signing key {0} is not a PKCS#8 encoded Ed25519 private key: {1}")]
+    InvalidSigningKey(String, String),
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from identity related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// UUID of the microVM. A random one is generated when it is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    /// Path to the PKCS#8 encoded Ed25519 private key signing the identity document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key_path: Option<String>,
+    /// Whether a microVM restored from a snapshot gets a new random UUID, rather than keeping
+    /// the one of the snapshotted microVM.
+    #[serde(default)]
+    pub rotate_on_restore: bool,
+}
+
+impl IdentityConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), IdentityConfigError> {
+        if let Some(uuid) = &self.uuid {
+            parse_uuid(uuid).ok_or_else(|| IdentityConfigError::InvalidUuid(uuid.clone()))?;
+        }
+        self.signing_key()?;
+        Ok(())
+    }
+
+    /// Replaces the UUID with a new random one.
+    pub fn generate_uuid(&mut self) -> Result<(), IdentityConfigError> {
+        let mut uuid = [0u8; 16];
+        aws_lc_rs::rand::fill(&mut uuid).map_err(|_| IdentityConfigError::GenerateUuid)?;
+        // Random (version 4, variant 1) UUID, see RFC 4122 section 4.4.
+        uuid[6] = (uuid[6] & 0x0f) | 0x40;
+        uuid[8] = (uuid[8] & 0x3f) | 0x80;
+        self.uuid = Some(format_uuid(&uuid));
+        Ok(())
+    }
+
+    /// Returns the bytes of the UUID, all zeroes when it is not set.
+    pub fn uuid_bytes(&self) -> [u8; 16] {
+        self.uuid
+            .as_deref()
+            .and_then(parse_uuid)
+            .unwrap_or_default()
+    }
+
+    /// Loads the signing key, if one is configured.
+    pub fn signing_key(&self) -> Result<Option<Ed25519KeyPair>, IdentityConfigError> {
+        let Some(path) = &self.signing_key_path else {
+            return Ok(None);
+        };
+        let pkcs8 = std::fs::read(path)
+            .map_err(|err| IdentityConfigError::ReadSigningKey(path.clone(), err.to_string()))?;
+        Ed25519KeyPair::from_pkcs8(&pkcs8)
+            .map(Some)
+            .map_err(|err| IdentityConfigError::InvalidSigningKey(path.clone(), err.to_string()))
+    }
+}
+
+fn parse_uuid(uuid: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = uuid.split('-').collect();
+    if groups.iter().map(|group| group.len()).ne([8, 4, 4, 4, 12]) {
+        return None;
+    }
+    let digits = groups.concat();
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (byte, pair) in bytes.iter_mut().zip(digits.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use aws_lc_rs::rand::SystemRandom;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    // Writes a new Ed25519 private key to a temporary file.
+    pub(crate) fn signing_key_file() -> TempFile {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(pkcs8.as_ref()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_identity_config() {
+        let config: IdentityConfig = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(config, IdentityConfig::default());
+        config.validate().unwrap();
+
+        let config: IdentityConfig = serde_json::from_str(
+            r#"{
+                "uuid": "01234567-89ab-cdef-0123-456789ABCDEF",
+                "rotate_on_restore": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            IdentityConfig {
+                uuid: Some("01234567-89ab-cdef-0123-456789ABCDEF".to_string()),
+                signing_key_path: None,
+                rotate_on_restore: true,
+            }
+        );
+        config.validate().unwrap();
+        assert_eq!(
+            config.uuid_bytes(),
+            [
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+                0xcd, 0xef
+            ]
+        );
+
+        assert!(serde_json::from_str::<IdentityConfig>(r#"{"foo": "bar"}"#).is_err());
+    }
+
+    #[test]
+    fn test_invalid_uuid() {
+        let mut config = IdentityConfig::default();
+        for uuid in [
+            "0123456789abcdef0123456789abcdef",
+            "01234567-89ab-cdef-0123-456789abcde",
+            "01234567-89ab-cdef-0123-456789abcdeg",
+            "+1234567-89ab-cdef-0123-456789abcdef",
+            "0123456-789ab-cdef-0123-456789abcdef",
+        ] {
+            config.uuid = Some(uuid.to_string());
+            assert_eq!(
+                config.validate(),
+                Err(IdentityConfigError::InvalidUuid(uuid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_uuid() {
+        let mut config = IdentityConfig::default();
+        assert_eq!(config.uuid_bytes(), [0; 16]);
+
+        config.generate_uuid().unwrap();
+        let uuid = config.uuid.clone().unwrap();
+        assert_eq!(format_uuid(&parse_uuid(&uuid).unwrap()), uuid);
+        let bytes = config.uuid_bytes();
+        assert_eq!(bytes[6] >> 4, 4);
+        assert_eq!(bytes[8] >> 6, 0b10);
+
+        config.generate_uuid().unwrap();
+        assert_ne!(config.uuid.unwrap(), uuid);
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let key_file = signing_key_file();
+        let mut config = IdentityConfig {
+            signing_key_path: Some(key_file.as_path().to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        assert!(config.signing_key().unwrap().is_some());
+
+        let not_a_key = TempFile::new().unwrap();
+        not_a_key.as_file().write_all(b"foo").unwrap();
+        config.signing_key_path = Some(not_a_key.as_path().to_str().unwrap().to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(IdentityConfigError::InvalidSigningKey(_, _))
+        ));
+
+        config.signing_key_path = Some("/no/such/key".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(IdentityConfigError::ReadSigningKey(_, _))
+        ));
+    }
+}
@@ -14,6 +14,8 @@ pub enum VmState {
     Paused,
     /// Vm is running
     Running,
+    /// Vm is running and the guest signaled it is ready
+    GuestReady,
 }
 
 impl Display for VmState {
@@ -22,6 +24,7 @@ impl Display for VmState {
             VmState::NotStarted => write!(f, "Not started"),
             VmState::Paused => write!(f, "Paused"),
             VmState::Running => write!(f, "Running"),
+            VmState::GuestReady => write!(f, "Running:GuestReady"),
         }
     }
 }
@@ -0,0 +1,142 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of I/O threads.
+pub const MAX_IO_THREADS: u8 = 16;
+
+/// Errors associated with the I/O threads configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum IoThreadsConfigError {
+    /// The number of I/O threads is out of range.
+    #[error("The number of I/O threads must be between 1 and {MAX_IO_THREADS}.")]
+    InvalidThreadCount,
+    /// A device is assigned to an I/O thread which does not exist.
+    #[error("The device {0} is assigned to the I/O thread {1}, which does not exist.")]
+    InvalidThread(String, u8),
+    /// A device is assigned to several I/O threads.
+    #[error("The device {0} is assigned to several I/O threads.")]
+    DuplicateDevice(String),
+}
+
+/// Assignment of a block or network device to an I/O thread.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoThreadAssignment {
+    /// ID of the block device (`drive_id`) or network device (`iface_id`).
+    pub device_id: String,
+    /// Index of the I/O thread processing the events of the device.
+    pub thread: u8,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from I/O threads related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoThreadsConfig {
+    /// Number of I/O threads.
+    pub thread_count: u8,
+    /// Devices whose events are processed by an I/O thread. The events of the other devices are
+    /// processed by the VMM thread.
+    #[serde(default)]
+    pub devices: Vec<IoThreadAssignment>,
+}
+
+impl IoThreadsConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), IoThreadsConfigError> {
+        if self.thread_count == 0 || self.thread_count > MAX_IO_THREADS {
+            return Err(IoThreadsConfigError::InvalidThreadCount);
+        }
+        let mut device_ids = HashSet::new();
+        for assignment in &self.devices {
+            if assignment.thread >= self.thread_count {
+                return Err(IoThreadsConfigError::InvalidThread(
+                    assignment.device_id.clone(),
+                    assignment.thread,
+                ));
+            }
+            if !device_ids.insert(assignment.device_id.as_str()) {
+                return Err(IoThreadsConfigError::DuplicateDevice(
+                    assignment.device_id.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_threads_config() {
+        let config: IoThreadsConfig = serde_json::from_str(
+            r#"{"thread_count": 2, "devices": [{"device_id": "rootfs", "thread": 1}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            IoThreadsConfig {
+                thread_count: 2,
+                devices: vec![IoThreadAssignment {
+                    device_id: "rootfs".to_string(),
+                    thread: 1,
+                }],
+            }
+        );
+        config.validate().unwrap();
+
+        let config: IoThreadsConfig = serde_json::from_str(r#"{"thread_count": 1}"#).unwrap();
+        assert!(config.devices.is_empty());
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<IoThreadsConfig>(r#"{}"#).is_err());
+        assert!(
+            serde_json::from_str::<IoThreadsConfig>(r#"{"thread_count": 1, "foo": 1}"#).is_err()
+        );
+
+        let mut config = IoThreadsConfig {
+            thread_count: 0,
+            devices: vec![],
+        };
+        assert_eq!(
+            config.validate(),
+            Err(IoThreadsConfigError::InvalidThreadCount)
+        );
+        config.thread_count = MAX_IO_THREADS + 1;
+        assert_eq!(
+            config.validate(),
+            Err(IoThreadsConfigError::InvalidThreadCount)
+        );
+
+        config.thread_count = 2;
+        config.devices = vec![IoThreadAssignment {
+            device_id: "rootfs".to_string(),
+            thread: 2,
+        }];
+        assert_eq!(
+            config.validate(),
+            Err(IoThreadsConfigError::InvalidThread("rootfs".to_string(), 2))
+        );
+
+        config.devices = vec![
+            IoThreadAssignment {
+                device_id: "rootfs".to_string(),
+                thread: 0,
+            },
+            IoThreadAssignment {
+                device_id: "rootfs".to_string(),
+                thread: 1,
+            },
+        ];
+        assert_eq!(
+            config.validate(),
+            Err(IoThreadsConfigError::DuplicateDevice("rootfs".to_string()))
+        );
+    }
+}
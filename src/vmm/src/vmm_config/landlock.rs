@@ -0,0 +1,110 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::landlock::LandlockRuleset;
+
+/// Errors associated with the Landlock configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum LandlockConfigError {
+    /// An allowed path is empty.
+    #[error("The Landlock allowed paths cannot be empty.")]
+    EmptyPath,
+}
+
+/// Path the VMM keeps access to once the Landlock ruleset is enforced.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LandlockAllowedPath {
+    /// File or directory to allow. Access to a directory extends to everything beneath it.
+    pub path: PathBuf,
+    /// Whether the VMM may only read the path.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from Landlock related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LandlockConfig {
+    /// Paths allowed on top of the files backing the devices, e.g. for drive updates or
+    /// snapshots taken after boot.
+    #[serde(default)]
+    pub allowed_paths: Vec<LandlockAllowedPath>,
+}
+
+impl LandlockConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), LandlockConfigError> {
+        if self
+            .allowed_paths
+            .iter()
+            .any(|allowed| allowed.path.as_os_str().is_empty())
+        {
+            return Err(LandlockConfigError::EmptyPath);
+        }
+        Ok(())
+    }
+
+    /// Builds a ruleset granting access to the configured paths.
+    pub fn ruleset(&self) -> LandlockRuleset {
+        let mut ruleset = LandlockRuleset::default();
+        for allowed in &self.allowed_paths {
+            ruleset.allow(&allowed.path, allowed.read_only);
+        }
+        ruleset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let body = r#"{
+                "allowed_paths": [
+                    {"path": "/snapshots"},
+                    {"path": "/drives", "read_only": true}
+                ]
+              }"#;
+        let config: LandlockConfig = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            config.allowed_paths,
+            vec![
+                LandlockAllowedPath {
+                    path: PathBuf::from("/snapshots"),
+                    read_only: false,
+                },
+                LandlockAllowedPath {
+                    path: PathBuf::from("/drives"),
+                    read_only: true,
+                },
+            ]
+        );
+        config.validate().unwrap();
+
+        let config: LandlockConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.allowed_paths.is_empty());
+
+        assert!(serde_json::from_str::<LandlockConfig>(r#"{"foo": 1}"#).is_err());
+        assert!(
+            serde_json::from_str::<LandlockConfig>(r#"{"allowed_paths": [{"foo": "/"}]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = LandlockConfig {
+            allowed_paths: vec![LandlockAllowedPath {
+                path: PathBuf::new(),
+                read_only: true,
+            }],
+        };
+        assert_eq!(config.validate(), Err(LandlockConfigError::EmptyPath));
+    }
+}
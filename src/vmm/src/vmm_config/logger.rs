@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Auxiliary module for configuring the logger.
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use logger::{FcLineWriter, LevelFilter, LOGGER};
 use serde::{de, Deserialize, Deserializer, Serialize};
@@ -52,6 +54,28 @@ impl From<LoggerLevel> for LevelFilter {
     }
 }
 
+/// Enum used for setting the format of the log entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LoggerFormat {
+    /// Each log entry is a human readable line, prefixed by its timestamp and its tags.
+    #[default]
+    Text,
+    /// Each log entry is a JSON object on its own line, for machine ingestion.
+    Json,
+}
+
+impl LoggerFormat {
+    /// Converts from a logger format value of type String to the corresponding LoggerFormat
+    /// variant or returns an error if the parsing failed.
+    pub fn from_string(format: String) -> Result<Self, LoggerConfigError> {
+        match format.to_ascii_lowercase().as_str() {
+            "text" => Ok(LoggerFormat::Text),
+            "json" => Ok(LoggerFormat::Json),
+            _ => Err(LoggerConfigError::InitializationFailure(format)),
+        }
+    }
+}
+
 // This allows `level` field, which is an enum, to be case-insensitive.
 fn case_insensitive<'de, D>(deserializer: D) -> Result<LoggerLevel, D::Error>
 where
@@ -67,6 +91,14 @@ where
     })
 }
 
+// Like `case_insensitive`, for an optional `level` field.
+fn case_insensitive_opt<'de, D>(deserializer: D) -> Result<Option<LoggerLevel>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    case_insensitive(deserializer).map(Some)
+}
+
 /// Strongly typed structure used to describe the logger.
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -85,6 +117,15 @@ pub struct LoggerConfig {
     /// When enabled, the logger will append the origin of the log entry.
     #[serde(default)]
     pub show_log_origin: bool,
+    /// The format of the log entries.
+    #[serde(default)]
+    pub format: LoggerFormat,
+    /// The ID written in each log entry, in place of the ID of the microVM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// The labels written in each log entry, e.g. the host pool the microVM runs in.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl LoggerConfig {
@@ -100,16 +141,54 @@ impl LoggerConfig {
             level,
             show_level,
             show_log_origin,
+            format: LoggerFormat::default(),
+            instance_id: None,
+            labels: BTreeMap::new(),
         }
     }
 }
 
+/// The level of the log entries of a module, along with its submodules.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerModuleFilter {
+    /// The path of the module, e.g. `devices::virtio::block`. The name of the crate can be left
+    /// out.
+    pub module: String,
+    /// The level of the module, which overrides the level of the logger.
+    #[serde(deserialize_with = "case_insensitive")]
+    pub level: LoggerLevel,
+}
+
+/// The settings of the logger which can be changed while the microVM runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerUpdateConfig {
+    /// The new level of the logger.
+    #[serde(default, deserialize_with = "case_insensitive_opt")]
+    pub level: Option<LoggerLevel>,
+    /// The new module filters, which replace the previous ones.
+    pub module_filters: Option<Vec<LoggerModuleFilter>>,
+}
+
 /// Errors associated with actions on the `LoggerConfig`.
 #[derive(Debug, thiserror::Error)]
 pub enum LoggerConfigError {
     /// Cannot initialize the logger due to bad user input.
     #[error("{}", format!("{:?}", .0).replace('\"', ""))]
     InitializationFailure(String),
+    /// A module filter does not designate a module.
+    #[error("Invalid module path in the filters of the logger: {0}")]
+    InvalidModule(String),
+}
+
+/// Configuration the logger was initialized with, which is part of the exported microVM
+/// configuration.
+static LOGGER_CONFIG: Mutex<Option<LoggerConfig>> = Mutex::new(None);
+
+/// Returns the configuration the logger was initialized with, if any.
+pub fn logger_config() -> Option<LoggerConfig> {
+    LOGGER_CONFIG.lock().expect("Poisoned lock").clone()
 }
 
 /// Configures the logger as described in `logger_cfg`.
@@ -118,9 +197,14 @@ pub fn init_logger(
     instance_info: &InstanceInfo,
 ) -> Result<(), LoggerConfigError> {
     LOGGER
-        .set_max_level(logger_cfg.level.into())
+        .set_max_level(logger_cfg.level.clone().into())
         .set_include_origin(logger_cfg.show_log_origin, logger_cfg.show_log_origin)
-        .set_include_level(logger_cfg.show_level);
+        .set_include_level(logger_cfg.show_level)
+        .set_json_format(logger_cfg.format == LoggerFormat::Json)
+        .set_labels(logger_cfg.labels.clone());
+    if let Some(instance_id) = &logger_cfg.instance_id {
+        LOGGER.set_instance_id(instance_id.clone());
+    }
 
     let writer = FcLineWriter::new(
         open_file_nonblock(&logger_cfg.log_path)
@@ -134,13 +218,52 @@ pub fn init_logger(
             ),
             Box::new(writer),
         )
-        .map_err(|err| LoggerConfigError::InitializationFailure(err.to_string()))
+        .map_err(|err| LoggerConfigError::InitializationFailure(err.to_string()))?;
+    *LOGGER_CONFIG.lock().expect("Poisoned lock") = Some(logger_cfg);
+    Ok(())
+}
+
+// Whether `path` is a path of a module, e.g. `devices::virtio::block`.
+fn is_module_path(path: &str) -> bool {
+    path.split("::")
+        .all(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Changes the level and the module filters of the logger, before or after the microVM starts.
+pub fn update_logger(update: LoggerUpdateConfig) -> Result<(), LoggerConfigError> {
+    if let Some(filter) = update
+        .module_filters
+        .iter()
+        .flatten()
+        .find(|filter| !is_module_path(&filter.module))
+    {
+        return Err(LoggerConfigError::InvalidModule(filter.module.clone()));
+    }
+
+    if let Some(level) = update.level {
+        LOGGER.set_max_level(level.clone().into());
+        if let Some(logger_cfg) = LOGGER_CONFIG.lock().expect("Poisoned lock").as_mut() {
+            logger_cfg.level = level;
+        }
+    }
+    if let Some(filters) = update.module_filters {
+        LOGGER.set_module_filters(
+            filters
+                .into_iter()
+                .map(|filter| (filter.module, filter.level.into()))
+                .collect(),
+        );
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::{BufRead, BufReader};
 
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
     use logger::warn;
     use utils::tempfile::TempFile;
     use utils::time::TimestampUs;
@@ -158,6 +281,9 @@ mod tests {
             level: LoggerLevel::Debug,
             show_level: false,
             show_log_origin: false,
+            format: LoggerFormat::Text,
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         assert!(init_logger(desc, &default_instance_info).is_err());
 
@@ -168,9 +294,13 @@ mod tests {
             level: LoggerLevel::Info,
             show_level: true,
             show_log_origin: true,
+            format: LoggerFormat::Text,
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
 
         assert!(init_logger(desc.clone(), &default_instance_info).is_ok());
+        assert_eq!(logger_config(), Some(desc.clone()));
         assert!(init_logger(desc, &default_instance_info).is_err());
 
         // Validate logfile works.
@@ -190,7 +320,8 @@ mod tests {
         }
 
         // Validate logging the boot time works.
-        let mut boot_timer = BootTimer::new(TimestampUs::default());
+        let guest_ready = Arc::new(AtomicBool::new(false));
+        let mut boot_timer = BootTimer::new(TimestampUs::default(), guest_ready.clone());
         boot_timer.bus_write(0, &[123]);
 
         let mut line = String::new();
@@ -203,6 +334,57 @@ mod tests {
                 assert!(line.contains("Guest-boot-time ="));
             }
         }
+
+        // Validate the guest signaling it is ready is logged.
+        boot_timer.bus_write(0, &[124]);
+        assert!(guest_ready.load(Ordering::Relaxed));
+
+        let mut line = String::new();
+        loop {
+            if line.contains("Guest-ready-time =") {
+                break;
+            }
+            if reader.read_line(&mut line).unwrap() == 0 {
+                // If it ever gets here, this assert will fail.
+                assert!(line.contains("Guest-ready-time ="));
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_logger() {
+        let update: LoggerUpdateConfig = serde_json::from_str(
+            r#"{"module_filters": [{"module": "vmm::devices::virtio::block", "level": "DEBUG"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(update.level, None);
+        assert_eq!(
+            update.module_filters.as_ref().unwrap()[0].level,
+            LoggerLevel::Debug
+        );
+        // The level is not updated, as the logger is shared with `test_init_logger`.
+        update_logger(update).unwrap();
+
+        let update: LoggerUpdateConfig = serde_json::from_str(r#"{"level": "info"}"#).unwrap();
+        assert_eq!(update.level, Some(LoggerLevel::Info));
+        assert!(serde_json::from_str::<LoggerUpdateConfig>(r#"{"level": "verbose"}"#).is_err());
+
+        for module in ["", "devices::", "devices virtio", "::devices"] {
+            let update = LoggerUpdateConfig {
+                level: None,
+                module_filters: Some(vec![LoggerModuleFilter {
+                    module: module.to_string(),
+                    level: LoggerLevel::Debug,
+                }]),
+            };
+            assert_eq!(
+                update_logger(update).unwrap_err().to_string(),
+                format!(
+                    "Invalid module path in the filters of the logger: {}",
+                    module
+                )
+            );
+        }
     }
 
     #[test]
@@ -213,6 +395,7 @@ mod tests {
         assert_eq!(logger_config.level, LoggerLevel::Debug);
         assert!(!logger_config.show_level);
         assert!(logger_config.show_log_origin);
+        assert_eq!(logger_config.format, LoggerFormat::Text);
     }
 
     #[test]
@@ -254,4 +437,44 @@ mod tests {
             LoggerLevel::Debug
         );
     }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            LoggerFormat::from_string("JSON".to_string()).unwrap(),
+            LoggerFormat::Json
+        );
+        assert_eq!(
+            LoggerFormat::from_string("text".to_string()).unwrap(),
+            LoggerFormat::Text
+        );
+        assert_eq!(
+            LoggerFormat::from_string("yaml".to_string())
+                .unwrap_err()
+                .to_string(),
+            "yaml"
+        );
+
+        let config: LoggerConfig =
+            serde_json::from_str(r#"{"log_path": "log", "format": "Json"}"#).unwrap();
+        assert_eq!(config.format, LoggerFormat::Json);
+        let config: LoggerConfig = serde_json::from_str(r#"{"log_path": "log"}"#).unwrap();
+        assert_eq!(config.format, LoggerFormat::Text);
+    }
+
+    #[test]
+    fn test_labels() {
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{"log_path": "log", "instance_id": "vm-1", "labels": {"pool": "a"}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.instance_id.as_deref(), Some("vm-1"));
+        assert_eq!(config.labels["pool"], "a");
+
+        // The fields are left out of the exported configuration when they are not set.
+        let config = LoggerConfig::new(PathBuf::from("log"), LoggerLevel::Debug, false, true);
+        let value = serde_json::to_value(config).unwrap();
+        assert!(value.get("instance_id").is_none());
+        assert!(value.get("labels").is_none());
+    }
 }
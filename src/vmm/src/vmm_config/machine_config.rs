@@ -1,20 +1,60 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 use std::fmt::{self, Debug};
+use std::io;
 
 use serde::{de, Deserialize, Serialize};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 
 use crate::cpu_config::templates::{CpuTemplateType, CustomCpuTemplate, StaticCpuTemplate};
+use crate::device_manager::mmio::MMIO_LEN;
 
 /// The default memory size of the VM, in MiB.
 pub const DEFAULT_MEM_SIZE_MIB: usize = 128;
-/// Firecracker aims to support small scale workloads only, so limit the maximum
-/// vCPUs supported.
-pub const MAX_SUPPORTED_VCPUS: u8 = 32;
+/// The maximum number of vCPUs supported. Past 254 vCPUs, x86_64 guests need x2APIC IDs, which
+/// are only available with ACPI and the split irqchip.
+#[cfg(target_arch = "x86_64")]
+pub const MAX_SUPPORTED_VCPUS: u16 = 1024;
+/// The maximum number of vCPUs supported.
+#[cfg(target_arch = "aarch64")]
+pub const MAX_SUPPORTED_VCPUS: u16 = 32;
+/// Lowest nice value, which gives a thread the most CPU time.
+pub const MIN_NICE: i8 = -20;
+/// Highest nice value, which gives a thread the least CPU time.
+pub const MAX_NICE: i8 = 19;
+/// Highest static priority of the real-time scheduling policies.
+pub const MAX_RT_PRIORITY: u8 = 99;
+/// Shortest CPU bandwidth period accepted by the cgroup `cpu.max` file, in microseconds.
+pub const MIN_CPU_PERIOD_US: u64 = 1_000;
+/// Longest CPU bandwidth period accepted by the cgroup `cpu.max` file, in microseconds.
+pub const MAX_CPU_PERIOD_US: u64 = 1_000_000;
+/// Smallest CPU bandwidth quota accepted by the cgroup `cpu.max` file, in microseconds.
+pub const MIN_CPU_QUOTA_US: u64 = 1_000;
+/// CPU bandwidth period used when none is set, in microseconds.
+pub const DEFAULT_CPU_PERIOD_US: u64 = 100_000;
+/// Smallest MMIO gap below 4 GiB, in MiB. Besides the device slots, it holds the IOAPIC, the
+/// local APICs and the TSS at the top of the 32-bit address space.
+pub const MIN_MMIO_GAP_SIZE_MIB: u64 = 64;
+/// Largest MMIO gap below 4 GiB, in MiB, which leaves at least 1 GiB of memory below it.
+pub const MAX_MMIO_GAP_SIZE_MIB: u64 = 3072;
 
 /// Errors associated with configuring the microVM.
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
 pub enum VmConfigError {
+    /// The CPU bandwidth period or quota is out of the range accepted by the cgroup.
+    #[error(
+        "The CPU bandwidth is invalid! The period must be between 1000 and 1000000 us, and the \
+         quota at least 1000 us."
+    )]
+    InvalidCpuBandwidth,
+    /// The MMIO device slots are invalid or do not fit in the MMIO region.
+    #[error(
+        "The MMIO layout is invalid! The slot size must be a power of two of at least 4096 \
+         bytes, the slot count at least 1, the gap size between 64 and 3072 MiB (x86_64 only), \
+         and the slots must fit in the MMIO region."
+    )]
+    InvalidMmioLayout,
     /// The memory size is smaller than the target size set in the balloon device configuration.
     #[error(
         "The memory size (MiB) is smaller than the previously set balloon device target size."
@@ -23,6 +63,19 @@ pub enum VmConfigError {
     /// The memory size is invalid. The memory can only be an unsigned integer.
     #[error("The memory size (MiB) is invalid.")]
     InvalidMemorySize,
+    /// The nice value is out of the [-20, 19] range, or set for a real-time or idle policy.
+    #[error(
+        "The nice value is invalid! It must be between -20 and 19, and can only be set for the \
+         `other` and `batch` scheduling policies."
+    )]
+    InvalidNice,
+    /// The scheduling priority is out of the [1, 99] range for a real-time policy, or set for a
+    /// policy that is not real-time.
+    #[error(
+        "The scheduling priority is invalid! It must be between 1 and 99 for the `fifo` and `rr` \
+         scheduling policies, and cannot be set for the other ones."
+    )]
+    InvalidSchedPriority,
     /// The vcpu count is invalid. When SMT is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     #[error(
@@ -45,7 +98,7 @@ pub enum VmConfigError {
 pub struct MachineConfig {
     /// Number of vcpu to start.
     #[serde(deserialize_with = "deserialize_vcpu_num")]
-    pub vcpu_count: u8,
+    pub vcpu_count: u16,
     /// The memory size in MiB.
     pub mem_size_mib: usize,
     /// Enables or disabled SMT.
@@ -57,6 +110,33 @@ pub struct MachineConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// Enables or disables exposing ACPI tables to the guest.
+    #[serde(default, deserialize_with = "deserialize_acpi")]
+    pub acpi: bool,
+    /// Enables or disables the split irqchip, which emulates the IOAPIC in userspace.
+    #[serde(default, deserialize_with = "deserialize_split_irqchip")]
+    pub split_irqchip: bool,
+    /// Enables or disables the GICv3 ITS, which allows the guest to use MSIs.
+    #[serde(default, deserialize_with = "deserialize_gic_its")]
+    pub gic_its: bool,
+    /// Scheduling settings of the vCPU threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_scheduling: Option<ThreadScheduling>,
+    /// Scheduling settings of the VMM and I/O threads, which emulate the devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_scheduling: Option<ThreadScheduling>,
+    /// CPU bandwidth of the microVM, enforced by its cgroup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_bandwidth: Option<CpuBandwidth>,
+    /// Layout of the MMIO devices in the guest physical address space.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmio_layout: Option<MmioLayout>,
+    /// Path of a sealed memfd backing the guest memory, usually `/proc/self/fd/<fd>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_backing_path: Option<String>,
+    /// Part of the guest memory locked in host memory, so that it is never swapped out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_lock: Option<MemLock>,
 }
 
 impl Default for MachineConfig {
@@ -70,8 +150,24 @@ impl fmt::Display for MachineConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"smt\": {:?}, \"cpu_template\": \
-             {:?}, \"track_dirty_pages\": {:?} }}",
-            self.vcpu_count, self.mem_size_mib, self.smt, self.cpu_template, self.track_dirty_pages
+             {:?}, \"track_dirty_pages\": {:?}, \"acpi\": {:?}, \"split_irqchip\": {:?}, \
+             \"gic_its\": {:?}, \"vcpu_scheduling\": {:?}, \"io_scheduling\": {:?}, \
+             \"cpu_bandwidth\": {:?}, \"mmio_layout\": {:?}, \"mem_backing_path\": {:?}, \
+             \"mem_lock\": {:?} }}",
+            self.vcpu_count,
+            self.mem_size_mib,
+            self.smt,
+            self.cpu_template,
+            self.track_dirty_pages,
+            self.acpi,
+            self.split_irqchip,
+            self.gic_its,
+            self.vcpu_scheduling,
+            self.io_scheduling,
+            self.cpu_bandwidth,
+            self.mmio_layout,
+            self.mem_backing_path,
+            self.mem_lock
         )
     }
 }
@@ -91,7 +187,7 @@ pub struct MachineConfigUpdate {
         skip_serializing_if = "Option::is_none",
         deserialize_with = "deserialize_vcpu_num"
     )]
-    pub vcpu_count: Option<u8>,
+    pub vcpu_count: Option<u16>,
     /// The memory size in MiB.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_size_mib: Option<usize>,
@@ -108,6 +204,46 @@ pub struct MachineConfigUpdate {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_dirty_pages: Option<bool>,
+    /// Enables or disables exposing ACPI tables to the guest.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_acpi"
+    )]
+    pub acpi: Option<bool>,
+    /// Enables or disables the split irqchip, which emulates the IOAPIC in userspace.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_split_irqchip"
+    )]
+    pub split_irqchip: Option<bool>,
+    /// Enables or disables the GICv3 ITS, which allows the guest to use MSIs.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_gic_its"
+    )]
+    pub gic_its: Option<bool>,
+    /// Scheduling settings of the vCPU threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_scheduling: Option<ThreadScheduling>,
+    /// Scheduling settings of the VMM and I/O threads, which emulate the devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_scheduling: Option<ThreadScheduling>,
+    /// CPU bandwidth of the microVM, enforced by its cgroup. It is the only field which can be
+    /// updated after boot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_bandwidth: Option<CpuBandwidth>,
+    /// Layout of the MMIO devices in the guest physical address space.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmio_layout: Option<MmioLayout>,
+    /// Path of a sealed memfd backing the guest memory, usually `/proc/self/fd/<fd>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_backing_path: Option<String>,
+    /// Part of the guest memory locked in host memory, so that it is never swapped out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_lock: Option<MemLock>,
 }
 
 impl MachineConfigUpdate {
@@ -120,6 +256,15 @@ impl MachineConfigUpdate {
             && self.cpu_template.is_none()
             && self.smt.is_none()
             && self.track_dirty_pages.is_none()
+            && self.acpi.is_none()
+            && self.split_irqchip.is_none()
+            && self.gic_its.is_none()
+            && self.vcpu_scheduling.is_none()
+            && self.io_scheduling.is_none()
+            && self.cpu_bandwidth.is_none()
+            && self.mmio_layout.is_none()
+            && self.mem_backing_path.is_none()
+            && self.mem_lock.is_none()
         {
             return true;
         }
@@ -136,6 +281,15 @@ impl From<MachineConfig> for MachineConfigUpdate {
             smt: Some(cfg.smt),
             cpu_template: Some(cfg.cpu_template),
             track_dirty_pages: Some(cfg.track_dirty_pages),
+            acpi: Some(cfg.acpi),
+            split_irqchip: Some(cfg.split_irqchip),
+            gic_its: Some(cfg.gic_its),
+            vcpu_scheduling: cfg.vcpu_scheduling,
+            io_scheduling: cfg.io_scheduling,
+            cpu_bandwidth: cfg.cpu_bandwidth,
+            mmio_layout: cfg.mmio_layout,
+            mem_backing_path: cfg.mem_backing_path,
+            mem_lock: cfg.mem_lock,
         }
     }
 }
@@ -144,7 +298,7 @@ impl From<MachineConfig> for MachineConfigUpdate {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct VmConfig {
     /// Number of vcpu to start.
-    pub vcpu_count: u8,
+    pub vcpu_count: u16,
     /// The memory size in MiB.
     pub mem_size_mib: usize,
     /// Enables or disabled SMT.
@@ -153,6 +307,24 @@ pub struct VmConfig {
     pub cpu_template: Option<CpuTemplateType>,
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     pub track_dirty_pages: bool,
+    /// Enables or disables exposing ACPI tables to the guest.
+    pub acpi: bool,
+    /// Enables or disables the split irqchip, which emulates the IOAPIC in userspace.
+    pub split_irqchip: bool,
+    /// Enables or disables the GICv3 ITS, which allows the guest to use MSIs.
+    pub gic_its: bool,
+    /// Scheduling settings of the vCPU threads.
+    pub vcpu_scheduling: Option<ThreadScheduling>,
+    /// Scheduling settings of the VMM and I/O threads, which emulate the devices.
+    pub io_scheduling: Option<ThreadScheduling>,
+    /// CPU bandwidth of the microVM, enforced by its cgroup.
+    pub cpu_bandwidth: Option<CpuBandwidth>,
+    /// Layout of the MMIO devices in the guest physical address space.
+    pub mmio_layout: Option<MmioLayout>,
+    /// Path of a sealed memfd backing the guest memory, instead of anonymous memory.
+    pub mem_backing_path: Option<String>,
+    /// Part of the guest memory locked in host memory.
+    pub mem_lock: Option<MemLock>,
 }
 
 impl VmConfig {
@@ -191,6 +363,19 @@ impl VmConfig {
 
         self.mem_size_mib = mem_size_mib;
 
+        if let Some(vcpu_scheduling) = update.vcpu_scheduling {
+            vcpu_scheduling.validate()?;
+        }
+        if let Some(io_scheduling) = update.io_scheduling {
+            io_scheduling.validate()?;
+        }
+        if let Some(cpu_bandwidth) = update.cpu_bandwidth {
+            cpu_bandwidth.validate()?;
+        }
+        if let Some(mmio_layout) = update.mmio_layout {
+            mmio_layout.validate()?;
+        }
+
         if let Some(cpu_template) = update.cpu_template {
             self.cpu_template = match cpu_template {
                 StaticCpuTemplate::None => None,
@@ -202,6 +387,42 @@ impl VmConfig {
             self.track_dirty_pages = track_dirty_pages;
         }
 
+        if let Some(acpi) = update.acpi {
+            self.acpi = acpi;
+        }
+
+        if let Some(split_irqchip) = update.split_irqchip {
+            self.split_irqchip = split_irqchip;
+        }
+
+        if let Some(gic_its) = update.gic_its {
+            self.gic_its = gic_its;
+        }
+
+        if update.vcpu_scheduling.is_some() {
+            self.vcpu_scheduling = update.vcpu_scheduling;
+        }
+
+        if update.io_scheduling.is_some() {
+            self.io_scheduling = update.io_scheduling;
+        }
+
+        if update.cpu_bandwidth.is_some() {
+            self.cpu_bandwidth = update.cpu_bandwidth;
+        }
+
+        if update.mmio_layout.is_some() {
+            self.mmio_layout = update.mmio_layout;
+        }
+
+        if update.mem_backing_path.is_some() {
+            self.mem_backing_path = update.mem_backing_path.clone();
+        }
+
+        if update.mem_lock.is_some() {
+            self.mem_lock = update.mem_lock;
+        }
+
         Ok(())
     }
 }
@@ -214,6 +435,15 @@ impl Default for VmConfig {
             smt: false,
             cpu_template: None,
             track_dirty_pages: false,
+            acpi: false,
+            split_irqchip: false,
+            gic_its: false,
+            vcpu_scheduling: None,
+            io_scheduling: None,
+            cpu_bandwidth: None,
+            mmio_layout: None,
+            mem_backing_path: None,
+            mem_lock: None,
         }
     }
 }
@@ -226,18 +456,252 @@ impl From<&VmConfig> for MachineConfig {
             smt: value.smt,
             cpu_template: (&value.cpu_template).into(),
             track_dirty_pages: value.track_dirty_pages,
+            acpi: value.acpi,
+            split_irqchip: value.split_irqchip,
+            gic_its: value.gic_its,
+            vcpu_scheduling: value.vcpu_scheduling,
+            io_scheduling: value.io_scheduling,
+            cpu_bandwidth: value.cpu_bandwidth,
+            mmio_layout: value.mmio_layout,
+            mem_backing_path: value.mem_backing_path.clone(),
+            mem_lock: value.mem_lock,
+        }
+    }
+}
+
+/// Linux scheduling policy of a thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedPolicy {
+    /// The default time-sharing policy (`SCHED_OTHER`).
+    #[default]
+    Other,
+    /// Time-sharing policy for CPU-bound, non-interactive threads (`SCHED_BATCH`).
+    Batch,
+    /// Policy for threads that only run when the CPU would otherwise be idle (`SCHED_IDLE`).
+    Idle,
+    /// First-in first-out real-time policy (`SCHED_FIFO`).
+    Fifo,
+    /// Round-robin real-time policy (`SCHED_RR`).
+    Rr,
+}
+
+impl SchedPolicy {
+    /// Returns whether this is a real-time policy, which uses a static priority.
+    pub fn is_real_time(&self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::Rr)
+    }
+
+    fn as_raw(&self) -> libc::c_int {
+        match self {
+            SchedPolicy::Other => libc::SCHED_OTHER,
+            SchedPolicy::Batch => libc::SCHED_BATCH,
+            SchedPolicy::Idle => libc::SCHED_IDLE,
+            SchedPolicy::Fifo => libc::SCHED_FIFO,
+            SchedPolicy::Rr => libc::SCHED_RR,
+        }
+    }
+}
+
+/// Part of the guest memory locked in host memory with `mlock`, which keeps it out of the swap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Versionize)]
+#[serde(rename_all = "snake_case")]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum MemLock {
+    /// The whole guest memory, populated when the microVM is started.
+    All,
+    /// The guest memory touched by the guest, locked when it is first accessed. The memory which
+    /// was resident when a snapshot was created is locked upfront when it is loaded.
+    Hot,
+}
+
+/// Scheduling settings of a category of threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadScheduling {
+    /// Scheduling policy.
+    #[serde(default)]
+    pub policy: SchedPolicy,
+    /// Nice value, for the `other` and `batch` policies.
+    #[serde(default)]
+    pub nice: i8,
+    /// Static priority, for the `fifo` and `rr` policies.
+    #[serde(default)]
+    pub priority: u8,
+}
+
+impl ThreadScheduling {
+    /// Checks that the nice value and the priority are valid for the policy.
+    pub fn validate(&self) -> Result<(), VmConfigError> {
+        let nice_allowed = matches!(self.policy, SchedPolicy::Other | SchedPolicy::Batch);
+        if !(MIN_NICE..=MAX_NICE).contains(&self.nice) || (!nice_allowed && self.nice != 0) {
+            return Err(VmConfigError::InvalidNice);
+        }
+        let valid_priority = if self.policy.is_real_time() {
+            (1..=MAX_RT_PRIORITY).contains(&self.priority)
+        } else {
+            self.priority == 0
+        };
+        if !valid_priority {
+            return Err(VmConfigError::InvalidSchedPriority);
+        }
+        Ok(())
+    }
+
+    /// Applies the settings to the thread with the id `tid`, or to the calling thread if `tid`
+    /// is 0.
+    pub fn apply(&self, tid: libc::pid_t) -> Result<(), io::Error> {
+        let id =
+            libc::id_t::try_from(tid).map_err(|_| io::Error::from_raw_os_error(libc::ESRCH))?;
+        // SAFETY: Safe because sched_param is a plain structure, which is valid when zeroed.
+        let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+        param.sched_priority = libc::c_int::from(self.priority);
+        // SAFETY: Safe because `param` is a valid sched_param and the return code is checked.
+        if unsafe { libc::sched_setscheduler(tid, self.policy.as_raw(), &param) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if self.policy.is_real_time() || self.policy == SchedPolicy::Idle {
+            return Ok(());
+        }
+        // On Linux, the nice value is a per-thread attribute.
+        // SAFETY: Safe because the arguments are plain values and the return code is checked.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, id, libc::c_int::from(self.nice)) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// CPU bandwidth of the microVM, written to the `cpu.max` file of its cgroup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuBandwidth {
+    /// Time, in microseconds, the microVM can run during each period. Unlimited when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_us: Option<u64>,
+    /// Length of the period, in microseconds.
+    #[serde(default = "default_cpu_period_us")]
+    pub period_us: u64,
+}
+
+fn default_cpu_period_us() -> u64 {
+    DEFAULT_CPU_PERIOD_US
+}
+
+impl CpuBandwidth {
+    /// Checks that the period and the quota are accepted by the cgroup.
+    pub fn validate(&self) -> Result<(), VmConfigError> {
+        if !(MIN_CPU_PERIOD_US..=MAX_CPU_PERIOD_US).contains(&self.period_us)
+            || self
+                .quota_us
+                .map_or(false, |quota| quota < MIN_CPU_QUOTA_US)
+        {
+            return Err(VmConfigError::InvalidCpuBandwidth);
+        }
+        Ok(())
+    }
+
+    /// Formats the bandwidth as a line of the cgroup v2 `cpu.max` file.
+    pub fn cpu_max_line(&self) -> String {
+        match self.quota_us {
+            Some(quota) => format!("{} {}\n", quota, self.period_us),
+            None => format!("max {}\n", self.period_us),
+        }
+    }
+}
+
+/// Layout of the MMIO devices in the guest physical address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MmioLayout {
+    /// Size of the MMIO window of each device, in bytes.
+    #[serde(default = "default_mmio_slot_size")]
+    pub slot_size: u64,
+    /// Maximum number of MMIO devices. When set, the devices share the IRQ lines once all of
+    /// them are in use, otherwise there are as many devices as IRQ lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot_count: Option<u32>,
+    /// Size of the MMIO gap below 4 GiB, in MiB (x86_64 only). The guest memory which does not
+    /// fit below the gap is placed above 4 GiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap_size_mib: Option<u64>,
+}
+
+fn default_mmio_slot_size() -> u64 {
+    MMIO_LEN
+}
+
+impl Default for MmioLayout {
+    fn default() -> Self {
+        Self {
+            slot_size: MMIO_LEN,
+            slot_count: None,
+            gap_size_mib: None,
+        }
+    }
+}
+
+impl MmioLayout {
+    /// Returns the start and the size of the guest physical range reserved for the MMIO devices.
+    pub fn mmio_region(&self) -> (u64, u64) {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(gap_size_mib) = self.gap_size_mib {
+            let gap_size = gap_size_mib << 20;
+            return ((1 << 32) - gap_size, gap_size);
+        }
+        (crate::arch::MMIO_MEM_START, crate::arch::MMIO_MEM_SIZE)
+    }
+
+    /// Returns the maximum number of MMIO devices.
+    pub fn max_devices(&self) -> u32 {
+        self.slot_count
+            .unwrap_or(crate::arch::IRQ_MAX - crate::arch::IRQ_BASE + 1)
+    }
+
+    /// Checks that the slots are valid and fit in the MMIO region.
+    pub fn validate(&self) -> Result<(), VmConfigError> {
+        if !self.slot_size.is_power_of_two()
+            || self.slot_size < MMIO_LEN
+            || self.slot_count == Some(0)
+        {
+            return Err(VmConfigError::InvalidMmioLayout);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        let devices_size = {
+            if let Some(gap_size_mib) = self.gap_size_mib {
+                if !(MIN_MMIO_GAP_SIZE_MIB..=MAX_MMIO_GAP_SIZE_MIB).contains(&gap_size_mib) {
+                    return Err(VmConfigError::InvalidMmioLayout);
+                }
+            }
+            // The top of the gap is taken by the IOAPIC, the local APICs and the TSS.
+            crate::arch::x86_64::layout::IOAPIC_START - self.mmio_region().0
+        };
+        // The MMIO region sits between fixed addresses on aarch64.
+        #[cfg(target_arch = "aarch64")]
+        let devices_size = {
+            if self.gap_size_mib.is_some() {
+                return Err(VmConfigError::InvalidMmioLayout);
+            }
+            self.mmio_region().1
+        };
+
+        match u64::from(self.max_devices()).checked_mul(self.slot_size) {
+            Some(slots_size) if slots_size <= devices_size => Ok(()),
+            _ => Err(VmConfigError::InvalidMmioLayout),
         }
     }
 }
 
 /// Deserialization function for the `vcpu_num` field in `MachineConfig` and `MachineConfigUpdate`.
 /// This is called only when `vcpu_num` is present in the JSON configuration.
-/// `T` can be either `u8` or `Option<u8>` which both support ordering if `vcpu_num` is
+/// `T` can be either `u16` or `Option<u16>` which both support ordering if `vcpu_num` is
 /// present in the JSON.
 fn deserialize_vcpu_num<'de, D, T>(d: D) -> Result<T, D::Error>
 where
     D: de::Deserializer<'de>,
-    T: Deserialize<'de> + PartialOrd + From<u8> + Debug,
+    T: Deserialize<'de> + PartialOrd + From<u16> + Debug,
 {
     let val = T::deserialize(d)?;
 
@@ -279,3 +743,231 @@ where
 
     Ok(val)
 }
+
+/// Deserialization function for the `acpi` field in `MachineConfig` and `MachineConfigUpdate`.
+/// This is called only when `acpi` is present in the JSON configuration.
+fn deserialize_acpi<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: Deserialize<'de> + PartialEq + From<bool> + Debug,
+{
+    let val = T::deserialize(d)?;
+
+    // On aarch64 the guest is described through the FDT, so only `false` is accepted.
+    #[cfg(target_arch = "aarch64")]
+    if val == T::from(true) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("acpi"),
+            &"Enabling ACPI is not supported on aarch64",
+        ));
+    }
+
+    Ok(val)
+}
+
+/// Deserialization function for the `split_irqchip` field in `MachineConfig` and
+/// `MachineConfigUpdate`. This is called only when `split_irqchip` is present in the JSON
+/// configuration.
+fn deserialize_split_irqchip<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: Deserialize<'de> + PartialEq + From<bool> + Debug,
+{
+    let val = T::deserialize(d)?;
+
+    // The IOAPIC only exists on x86_64, so only `false` is accepted on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    if val == T::from(true) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("split_irqchip"),
+            &"Enabling the split irqchip is not supported on aarch64",
+        ));
+    }
+
+    Ok(val)
+}
+
+/// Deserialization function for the `gic_its` field in `MachineConfig` and `MachineConfigUpdate`.
+/// This is called only when `gic_its` is present in the JSON configuration.
+fn deserialize_gic_its<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: Deserialize<'de> + PartialEq + From<bool> + Debug,
+{
+    let val = T::deserialize(d)?;
+
+    // The GIC only exists on aarch64, so only `false` is accepted on x86_64.
+    #[cfg(target_arch = "x86_64")]
+    if val == T::from(true) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("gic_its"),
+            &"Enabling the GIC ITS is not supported on x86_64",
+        ));
+    }
+
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+
+    use super::*;
+
+    #[test]
+    fn test_apply_thread_scheduling() {
+        std::thread::spawn(|| {
+            // Lowering the priority of a thread does not require any privilege.
+            let scheduling = ThreadScheduling {
+                policy: SchedPolicy::Batch,
+                nice: 10,
+                priority: 0,
+            };
+            scheduling.apply(0).unwrap();
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_BATCH);
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, 10);
+
+            let scheduling = ThreadScheduling {
+                policy: SchedPolicy::Idle,
+                nice: 0,
+                priority: 0,
+            };
+            scheduling.apply(0).unwrap();
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_IDLE);
+
+            assert_eq!(
+                scheduling.apply(-1).unwrap_err().raw_os_error(),
+                Some(libc::ESRCH)
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cpu_bandwidth() {
+        let bandwidth: CpuBandwidth = serde_json::from_str(r#"{"quota_us": 50000}"#).unwrap();
+        assert_eq!(bandwidth.period_us, DEFAULT_CPU_PERIOD_US);
+        bandwidth.validate().unwrap();
+        assert_eq!(bandwidth.cpu_max_line(), "50000 100000\n");
+
+        let bandwidth = CpuBandwidth {
+            quota_us: None,
+            period_us: MAX_CPU_PERIOD_US,
+        };
+        bandwidth.validate().unwrap();
+        assert_eq!(bandwidth.cpu_max_line(), "max 1000000\n");
+
+        for (quota_us, period_us) in [
+            (None, MIN_CPU_PERIOD_US - 1),
+            (None, MAX_CPU_PERIOD_US + 1),
+            (Some(MIN_CPU_QUOTA_US - 1), DEFAULT_CPU_PERIOD_US),
+        ] {
+            let bandwidth = CpuBandwidth {
+                quota_us,
+                period_us,
+            };
+            assert_eq!(
+                bandwidth.validate(),
+                Err(VmConfigError::InvalidCpuBandwidth)
+            );
+        }
+
+        // The bandwidth is validated along with the rest of the machine configuration.
+        let mut vm_config = VmConfig::default();
+        let update = MachineConfigUpdate {
+            cpu_bandwidth: Some(CpuBandwidth {
+                quota_us: Some(0),
+                period_us: DEFAULT_CPU_PERIOD_US,
+            }),
+            ..MachineConfigUpdate::from(MachineConfig::default())
+        };
+        assert_eq!(
+            vm_config.update(&update),
+            Err(VmConfigError::InvalidCpuBandwidth)
+        );
+        assert_eq!(vm_config.cpu_bandwidth, None);
+    }
+
+    #[test]
+    fn test_mmio_layout() {
+        let layout: MmioLayout = serde_json::from_str(r#"{"slot_count": 64}"#).unwrap();
+        assert_eq!(layout.slot_size, MMIO_LEN);
+        layout.validate().unwrap();
+        assert_eq!(layout.max_devices(), 64);
+        assert_eq!(
+            MmioLayout::default().max_devices(),
+            crate::arch::IRQ_MAX - crate::arch::IRQ_BASE + 1
+        );
+        assert_eq!(
+            MmioLayout::default().mmio_region(),
+            (crate::arch::MMIO_MEM_START, crate::arch::MMIO_MEM_SIZE)
+        );
+
+        for layout in [
+            MmioLayout {
+                slot_size: 0x1800,
+                ..Default::default()
+            },
+            MmioLayout {
+                slot_size: 0x800,
+                ..Default::default()
+            },
+            MmioLayout {
+                slot_count: Some(0),
+                ..Default::default()
+            },
+            // The slots do not fit in the MMIO region.
+            MmioLayout {
+                slot_size: 1 << 30,
+                slot_count: Some(4),
+                gap_size_mib: None,
+            },
+        ] {
+            assert_eq!(layout.validate(), Err(VmConfigError::InvalidMmioLayout));
+        }
+
+        let layout = MmioLayout {
+            slot_size: 1 << 20,
+            slot_count: Some(128),
+            gap_size_mib: Some(1024),
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            layout.validate().unwrap();
+            assert_eq!(layout.mmio_region(), (3 << 30, 1 << 30));
+
+            for gap_size_mib in [MIN_MMIO_GAP_SIZE_MIB - 1, MAX_MMIO_GAP_SIZE_MIB + 1] {
+                let layout = MmioLayout {
+                    gap_size_mib: Some(gap_size_mib),
+                    ..Default::default()
+                };
+                assert_eq!(layout.validate(), Err(VmConfigError::InvalidMmioLayout));
+            }
+            // The top of the gap is not available to the devices.
+            let layout = MmioLayout {
+                slot_size: 1 << 20,
+                slot_count: Some(64),
+                gap_size_mib: Some(64),
+            };
+            assert_eq!(layout.validate(), Err(VmConfigError::InvalidMmioLayout));
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(layout.validate(), Err(VmConfigError::InvalidMmioLayout));
+
+        // The layout is validated along with the rest of the machine configuration.
+        let mut vm_config = VmConfig::default();
+        let update = MachineConfigUpdate {
+            mmio_layout: Some(MmioLayout {
+                slot_count: Some(0),
+                ..Default::default()
+            }),
+            ..MachineConfigUpdate::from(MachineConfig::default())
+        };
+        assert_eq!(
+            vm_config.update(&update),
+            Err(VmConfigError::InvalidMmioLayout)
+        );
+        assert_eq!(vm_config.mmio_layout, None);
+    }
+}
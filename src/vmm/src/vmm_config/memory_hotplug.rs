@@ -0,0 +1,144 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::mem::{VirtioMem, VirtioMemError};
+
+/// Default granularity, in MiB, at which the guest (un)plugs memory.
+pub const DEFAULT_BLOCK_SIZE_MIB: usize = 2;
+
+/// Errors associated with the memory hotplug configuration.
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryHotplugConfigError {
+    /// Memory hotplug is not supported on this architecture.
+    #[error("Memory hotplug is not supported on this architecture.")]
+    NotSupported,
+    /// The block size is not a power of two.
+    #[error("The block size must be a power of two number of MiB: {0}")]
+    InvalidBlockSize(usize),
+    /// The total size is not a non-zero multiple of the block size.
+    #[error("The total size must be a non-zero multiple of the block size: {0}")]
+    InvalidTotalSize(usize),
+    /// Memory hotplug was not configured for this microVM.
+    #[error("The memory hotplug device was not configured.")]
+    DeviceNotFound,
+    /// The memory hotplug device rejected the operation.
+    #[error("{0}")]
+    Device(#[from] VirtioMemError),
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from memory hotplug related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugConfig {
+    /// Size of the guest physical memory range reserved for hotplugging, in MiB.
+    pub total_size_mib: usize,
+    /// Granularity at which the guest (un)plugs memory, in MiB.
+    #[serde(default = "default_block_size_mib")]
+    pub block_size_mib: usize,
+}
+
+fn default_block_size_mib() -> usize {
+    DEFAULT_BLOCK_SIZE_MIB
+}
+
+impl MemoryHotplugConfig {
+    /// Checks that the configuration describes a valid hotplug region.
+    pub fn validate(&self) -> Result<(), MemoryHotplugConfigError> {
+        // The hotplug region is only described to the guest through the aarch64 memory layout.
+        if cfg!(target_arch = "x86_64") {
+            return Err(MemoryHotplugConfigError::NotSupported);
+        }
+        if !self.block_size_mib.is_power_of_two() {
+            return Err(MemoryHotplugConfigError::InvalidBlockSize(
+                self.block_size_mib,
+            ));
+        }
+        if self.total_size_mib == 0 || self.total_size_mib % self.block_size_mib != 0 {
+            return Err(MemoryHotplugConfigError::InvalidTotalSize(
+                self.total_size_mib,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<&VirtioMem> for MemoryHotplugConfig {
+    fn from(dev: &VirtioMem) -> Self {
+        MemoryHotplugConfig {
+            total_size_mib: (dev.region_size() >> 20) as usize,
+            block_size_mib: (dev.block_size() >> 20) as usize,
+        }
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from memory hotplug size update requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugSizeUpdate {
+    /// Amount of hotpluggable memory the guest should use, in MiB.
+    pub requested_size_mib: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: DEFAULT_BLOCK_SIZE_MIB,
+        };
+        #[cfg(target_arch = "x86_64")]
+        assert!(matches!(
+            config.validate(),
+            Err(MemoryHotplugConfigError::NotSupported)
+        ));
+        #[cfg(target_arch = "aarch64")]
+        {
+            config.validate().unwrap();
+
+            let config = MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 3,
+            };
+            assert!(matches!(
+                config.validate(),
+                Err(MemoryHotplugConfigError::InvalidBlockSize(3))
+            ));
+
+            let config = MemoryHotplugConfig {
+                total_size_mib: 1023,
+                block_size_mib: 2,
+            };
+            assert!(matches!(
+                config.validate(),
+                Err(MemoryHotplugConfigError::InvalidTotalSize(1023))
+            ));
+
+            let config = MemoryHotplugConfig {
+                total_size_mib: 0,
+                block_size_mib: 2,
+            };
+            assert!(matches!(
+                config.validate(),
+                Err(MemoryHotplugConfigError::InvalidTotalSize(0))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: MemoryHotplugConfig =
+            serde_json::from_str(r#"{"total_size_mib": 2048}"#).unwrap();
+        assert_eq!(config.block_size_mib, DEFAULT_BLOCK_SIZE_MIB);
+        assert!(serde_json::from_str::<MemoryHotplugConfig>(
+            r#"{"total_size_mib": 2048, "foo": 1}"#
+        )
+        .is_err());
+    }
+}
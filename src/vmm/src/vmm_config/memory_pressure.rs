@@ -0,0 +1,177 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Shortest PSI window accepted by the kernel, in milliseconds.
+pub const MIN_PSI_WINDOW_MS: u64 = 500;
+/// Longest PSI window accepted by the kernel, in milliseconds.
+pub const MAX_PSI_WINDOW_MS: u64 = 10_000;
+
+/// Errors associated with the host memory pressure configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum MemoryPressureConfigError {
+    /// The path of the memory pressure file is empty.
+    #[error("The path of the memory pressure file cannot be empty.")]
+    EmptyPath,
+    /// The PSI window is out of the range accepted by the kernel.
+    #[error("The PSI window must be between 500 and 10000 ms, got {0} ms.")]
+    InvalidWindow(u64),
+    /// The stall threshold is null or exceeds the PSI window.
+    #[error("The stall threshold must be greater than 0 ms and at most the PSI window.")]
+    InvalidThreshold,
+    /// The balloon inflation step is null.
+    #[error("The balloon inflation step must be at least 1 MiB.")]
+    NullBalloonStep,
+}
+
+/// Files reporting the memory pressure of the host.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum MemoryPressureSource {
+    /// Pressure stall information file, e.g. `/proc/pressure/memory` or the `memory.pressure`
+    /// file of a cgroup, on which a trigger is registered.
+    #[default]
+    Psi,
+    /// `memory.events` file of a cgroup, whose `high`, `max` and `oom` counters are watched.
+    MemoryEvents,
+}
+
+/// Actions relieving the host memory pressure.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum MemoryPressureAction {
+    /// Inflates the balloon by a step on each pressure event.
+    InflateBalloon,
+    /// Pauses the vCPUs, while the net and vsock devices keep serving the host.
+    Pause,
+}
+
+fn default_stall_threshold_ms() -> u64 {
+    100
+}
+
+fn default_window_ms() -> u64 {
+    1000
+}
+
+fn default_balloon_step_mib() -> u32 {
+    64
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from host memory pressure related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryPressureConfig {
+    /// Kind of the file reporting the memory pressure.
+    #[serde(default)]
+    pub source: MemoryPressureSource,
+    /// Path of the file reporting the memory pressure.
+    pub path: PathBuf,
+    /// Time, in milliseconds, the tasks have to be stalled on memory within the window for the
+    /// host to be under pressure. Only used by the `Psi` source.
+    #[serde(default = "default_stall_threshold_ms")]
+    pub stall_threshold_ms: u64,
+    /// Window, in milliseconds, over which the stall time is measured. Only used by the `Psi`
+    /// source.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// Action taken on each pressure event.
+    pub action: MemoryPressureAction,
+    /// Amount, in MiB, the balloon is inflated by on each pressure event.
+    #[serde(default = "default_balloon_step_mib")]
+    pub balloon_step_mib: u32,
+    /// Size, in MiB, the balloon is not inflated beyond. Bounded by the guest memory by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balloon_max_mib: Option<u32>,
+    /// Time, in milliseconds, without pressure event after which the action is undone. The action
+    /// is never undone when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_ms: Option<u64>,
+}
+
+impl MemoryPressureConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), MemoryPressureConfigError> {
+        if self.path.as_os_str().is_empty() {
+            return Err(MemoryPressureConfigError::EmptyPath);
+        }
+        if self.source == MemoryPressureSource::Psi {
+            if !(MIN_PSI_WINDOW_MS..=MAX_PSI_WINDOW_MS).contains(&self.window_ms) {
+                return Err(MemoryPressureConfigError::InvalidWindow(self.window_ms));
+            }
+            if self.stall_threshold_ms == 0 || self.stall_threshold_ms > self.window_ms {
+                return Err(MemoryPressureConfigError::InvalidThreshold);
+            }
+        }
+        if self.action == MemoryPressureAction::InflateBalloon && self.balloon_step_mib == 0 {
+            return Err(MemoryPressureConfigError::NullBalloonStep);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_pressure_config() {
+        let config: MemoryPressureConfig = serde_json::from_str(
+            r#"{"path": "/proc/pressure/memory", "action": "InflateBalloon"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            MemoryPressureConfig {
+                source: MemoryPressureSource::Psi,
+                path: PathBuf::from("/proc/pressure/memory"),
+                stall_threshold_ms: 100,
+                window_ms: 1000,
+                action: MemoryPressureAction::InflateBalloon,
+                balloon_step_mib: 64,
+                balloon_max_mib: None,
+                recovery_ms: None,
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<MemoryPressureConfig>(r#"{"path": "/foo"}"#).is_err());
+        assert!(serde_json::from_str::<MemoryPressureConfig>(
+            r#"{"path": "/foo", "action": "Pause", "foo": 1}"#
+        )
+        .is_err());
+
+        let invalid = |update: fn(&mut MemoryPressureConfig)| {
+            let mut config = config.clone();
+            update(&mut config);
+            config.validate().unwrap_err()
+        };
+        assert_eq!(
+            invalid(|config| config.path = PathBuf::new()),
+            MemoryPressureConfigError::EmptyPath
+        );
+        assert_eq!(
+            invalid(|config| config.window_ms = 499),
+            MemoryPressureConfigError::InvalidWindow(499)
+        );
+        assert_eq!(
+            invalid(|config| config.stall_threshold_ms = 1001),
+            MemoryPressureConfigError::InvalidThreshold
+        );
+        assert_eq!(
+            invalid(|config| config.balloon_step_mib = 0),
+            MemoryPressureConfigError::NullBalloonStep
+        );
+
+        // The window only matters for the PSI triggers.
+        let config = MemoryPressureConfig {
+            source: MemoryPressureSource::MemoryEvents,
+            window_ms: 0,
+            action: MemoryPressureAction::Pause,
+            ..config
+        };
+        config.validate().unwrap();
+    }
+}
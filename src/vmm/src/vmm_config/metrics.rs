@@ -2,9 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Auxiliary module for configuring the metrics system.
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
-use logger::{FcLineWriter, METRICS};
+use logger::{FcLineWriter, MetricsLabels, METRICS};
 use serde::{Deserialize, Serialize};
 
 use super::open_file_nonblock;
@@ -14,6 +16,13 @@ use super::open_file_nonblock;
 pub struct MetricsConfig {
     /// Named pipe or file used as output for metrics.
     pub metrics_path: PathBuf,
+    /// The ID written along with each flush of the metrics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// The labels written along with each flush of the metrics, e.g. the host pool the microVM
+    /// runs in.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 /// Errors associated with actions on the `MetricsConfig`.
@@ -24,6 +33,15 @@ pub enum MetricsConfigError {
     InitializationFailure(String),
 }
 
+/// Configuration the metrics system was initialized with, which is part of the exported microVM
+/// configuration.
+static METRICS_CONFIG: Mutex<Option<MetricsConfig>> = Mutex::new(None);
+
+/// Returns the configuration the metrics system was initialized with, if any.
+pub fn metrics_config() -> Option<MetricsConfig> {
+    METRICS_CONFIG.lock().expect("Poisoned lock").clone()
+}
+
 /// Configures the metrics as described in `metrics_cfg`.
 pub fn init_metrics(metrics_cfg: MetricsConfig) -> Result<(), MetricsConfigError> {
     let writer = FcLineWriter::new(
@@ -32,7 +50,17 @@ pub fn init_metrics(metrics_cfg: MetricsConfig) -> Result<(), MetricsConfigError
     );
     METRICS
         .init(writer)
-        .map_err(|err| MetricsConfigError::InitializationFailure(err.to_string()))
+        .map_err(|err| MetricsConfigError::InitializationFailure(err.to_string()))?;
+    if metrics_cfg.instance_id.is_some() || !metrics_cfg.labels.is_empty() {
+        METRICS
+            .set_labels(MetricsLabels {
+                instance_id: metrics_cfg.instance_id.clone(),
+                labels: metrics_cfg.labels.clone(),
+            })
+            .map_err(|err| MetricsConfigError::InitializationFailure(err.to_string()))?;
+    }
+    *METRICS_CONFIG.lock().expect("Poisoned lock") = Some(metrics_cfg);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -46,6 +74,8 @@ mod tests {
         // Error case: initializing metrics with invalid pipe returns error.
         let desc = MetricsConfig {
             metrics_path: PathBuf::from("not_found_file_metrics"),
+            instance_id: None,
+            labels: BTreeMap::new(),
         };
         assert!(init_metrics(desc).is_err());
 
@@ -53,9 +83,12 @@ mod tests {
         let metrics_file = TempFile::new().unwrap();
         let desc = MetricsConfig {
             metrics_path: metrics_file.as_path().to_path_buf(),
+            instance_id: Some("vm-1".to_string()),
+            labels: BTreeMap::from([("pool".to_string(), "a".to_string())]),
         };
 
         assert!(init_metrics(desc.clone()).is_ok());
+        assert_eq!(metrics_config(), Some(desc.clone()));
         assert!(init_metrics(desc).is_err());
     }
 }
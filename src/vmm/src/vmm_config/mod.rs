@@ -5,10 +5,13 @@ use std::convert::{From, TryInto};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::Path;
+use std::sync::Mutex;
 
 use libc::O_NONBLOCK;
 use serde::{Deserialize, Serialize};
+use utils::syscall::SyscallReturnCode;
 
 use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 
@@ -16,26 +19,66 @@ use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring confidential microVMs.
+pub mod confidential;
+/// Wrapper for dumping the guest memory.
+pub mod coredump;
+/// Wrapper for configuring the deterministic boot mode.
+pub mod deterministic_boot;
 /// Wrapper for configuring the block devices.
 pub mod drive;
+/// Wrapper for configuring the emergency snapshot.
+pub mod emergency_snapshot;
 /// Wrapper for configuring the entropy device attached to the microVM.
 pub mod entropy;
+/// Wrapper for configuring the GDB stub.
+pub mod gdb;
+/// Wrapper for configuring the copy of guest files.
+pub mod guest_files;
+/// Wrapper for configuring the freeze of the guest filesystems.
+pub mod guest_freeze;
+/// Wrapper over the health of the subsystems of the microVM.
+pub mod health;
+/// Wrapper for configuring the identity of the microVM.
+pub mod identity;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
+/// Wrapper for configuring the I/O threads processing the device events.
+pub mod io_threads;
+/// Wrapper for configuring the Landlock sandboxing of the VMM.
+pub mod landlock;
 /// Wrapper for configuring the logger.
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for configuring the memory hotplug device.
+pub mod memory_hotplug;
+/// Wrapper for configuring the reaction to the host memory pressure.
+pub mod memory_pressure;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper for configuring the host directories shared with the guest.
+pub mod shared_dir;
+/// Wrapper for configuring the cleanup actions performed when Firecracker exits.
+pub mod shutdown_hooks;
+/// Wrapper for configuring the SMBIOS tables identifying the microVM.
+pub mod smbios;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for configuring the detection of stalled vCPUs.
+pub mod stall_detection;
+/// Wrapper for configuring the TPM device.
+pub mod tpm;
+/// Wrapper for configuring the recording of virtio queues.
+pub mod virtio_record;
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
+/// Wrapper for configuring the estimation of the guest working set.
+pub mod working_set;
 
 // TODO: Migrate the VMM public-facing code (i.e. interface) to use stateless structures,
 // for receiving data/args, such as the below `RateLimiterConfig` and `TokenBucketConfig`.
@@ -165,6 +208,16 @@ impl RateLimiterConfig {
     }
 }
 
+/// The processing state of a device, which can be paused on its own for maintenance, such as
+/// swapping its backing file, without pausing the whole microVM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DeviceRunState {
+    /// The device completes its in-flight requests, then stops processing its queues.
+    Paused,
+    /// The device processes its queues, starting with the requests queued while it was paused.
+    Resumed,
+}
+
 /// Create and opens a File for writing to it.
 /// In case we open a FIFO, in order to not block the instance if nobody is consuming the message
 /// that is flushed to the two pipes, we are opening it with `O_NONBLOCK` flag.
@@ -177,8 +230,107 @@ fn open_file_nonblock(path: &Path) -> Result<File, std::io::Error> {
         .open(path)
 }
 
+/// The file descriptors inherited from the parent process through the `--inherit-fd` argument.
+static INHERITED_FDS: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+
+/// Declares file descriptors inherited from the parent process, which become the only ones that
+/// can be opened through their `/proc/self/fd/<fd>` path.
+///
+/// The descriptors have to be open, otherwise Firecracker could later reuse their number for one
+/// of its own files.
+pub fn add_inherited_fds(fds: &[RawFd]) -> Result<(), io::Error> {
+    for &fd in fds {
+        // SAFETY: `fcntl` does not access memory and fails with `EBADF` on an invalid descriptor.
+        SyscallReturnCode(unsafe { libc::fcntl(fd, libc::F_GETFD) }).into_empty_result()?;
+    }
+    INHERITED_FDS
+        .lock()
+        .expect("Poisoned lock")
+        .extend_from_slice(fds);
+    Ok(())
+}
+
+/// Returns the file descriptor referred to by `path`, if it is one of the paths through which the
+/// open file descriptors of the Firecracker process can be reached (e.g. `/proc/self/fd/<fd>` or
+/// `/dev/fd/<fd>`), or an error if `path` goes through procfs in any other way.
+fn fd_of_path(path: &str) -> Result<Option<RawFd>, io::Error> {
+    let components = Path::new(path)
+        .components()
+        .map(|component| component.as_os_str().to_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let fd = match components.as_slice() {
+        ["/", "proc", "self" | "thread-self", "fd", fd] | ["/", "dev", "fd", fd] => fd,
+        ["/", "proc", ..] | ["/", "dev", "fd", ..] => {
+            return Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+        _ => return Ok(None),
+    };
+    fd.parse::<RawFd>()
+        .map(Some)
+        .map_err(|_| io::Error::from(io::ErrorKind::PermissionDenied))
+}
+
+/// Opens the file at `path` for reading and, if `write` is set, for writing.
+///
+/// Paths of the form `/proc/self/fd/<fd>` are resolved by duplicating the file descriptor
+/// inherited by Firecracker instead of going through procfs, so that they keep working in the
+/// jailer chroot (see the `--inherit-fd` jailer argument). Only the file descriptors declared
+/// through the `--inherit-fd` argument can be opened this way. The access mode of such a
+/// descriptor is fixed when it is opened, so it is only checked against the requested one.
+///
+/// The duplicate shares its file offset with the inherited descriptor, so the file is expected
+/// to be accessed at explicit offsets (e.g. with `pread`) rather than through the offset.
+pub fn open_file(path: &str, write: bool) -> Result<File, std::io::Error> {
+    open_file_with_flags(path, write, 0)
+}
+
+/// Opens the file at `path` like [`open_file`], with the `custom_flags` (e.g. `O_DIRECT`) added
+/// to the flags of `open`.
+///
+/// These flags are shared by all the duplicates of an inherited descriptor, so they are not
+/// changed on it: the descriptor has to be opened with them already.
+pub fn open_file_with_flags(
+    path: &str,
+    write: bool,
+    custom_flags: i32,
+) -> Result<File, std::io::Error> {
+    let fd = match fd_of_path(path)? {
+        Some(fd) => fd,
+        None => {
+            return OpenOptions::new()
+                .read(true)
+                .write(write)
+                .custom_flags(custom_flags)
+                .open(path)
+        }
+    };
+    if !INHERITED_FDS.lock().expect("Poisoned lock").contains(&fd) {
+        return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+
+    // SAFETY: `fcntl` does not access memory and fails with `EBADF` on an invalid descriptor.
+    let flags = SyscallReturnCode(unsafe { libc::fcntl(fd, libc::F_GETFL) }).into_result()?;
+    let access_mode = flags & libc::O_ACCMODE;
+    if access_mode == libc::O_WRONLY || (write && access_mode != libc::O_RDWR) {
+        return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    if flags & custom_flags != custom_flags {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+
+    // SAFETY: `fcntl` does not access memory and the descriptor was checked to be valid above.
+    let new_fd =
+        SyscallReturnCode(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) }).into_result()?;
+    // SAFETY: `new_fd` was just duplicated, so it is valid and owned by nobody else.
+    Ok(unsafe { File::from_raw_fd(new_fd) })
+}
+
 #[cfg(test)]
 mod tests {
+    use std::os::unix::io::AsRawFd;
+
+    use utils::tempfile::TempFile;
+
     use super::*;
 
     const SIZE: u64 = 1024 * 1024;
@@ -228,4 +380,72 @@ mod tests {
         assert_eq!(generated_rl_conf, rl_conf);
         assert_eq!(generated_rl_conf.into_option(), Some(rl_conf));
     }
+
+    #[test]
+    fn test_open_file() {
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().to_str().unwrap();
+        assert!(open_file(path, true).is_ok());
+        assert!(open_file("/invalid/path", false).is_err());
+        let fd_path = |file: &File| format!("/proc/self/fd/{}", file.as_raw_fd());
+
+        // Only the inherited descriptors can be opened through procfs.
+        let read_only = File::open(path).unwrap();
+        for path in [
+            fd_path(&read_only),
+            format!("/dev/fd/{}", read_only.as_raw_fd()),
+            format!("/proc/{}/fd/{}", std::process::id(), read_only.as_raw_fd()),
+            "/proc/self/fd/../fd/0".to_string(),
+        ] {
+            assert_eq!(
+                open_file(&path, false).unwrap_err().kind(),
+                io::ErrorKind::PermissionDenied
+            );
+        }
+
+        // Inherited descriptors are duplicated, honoring their access mode.
+        add_inherited_fds(&[read_only.as_raw_fd()]).unwrap();
+        let file = open_file(&fd_path(&read_only), false).unwrap();
+        assert_ne!(file.as_raw_fd(), read_only.as_raw_fd());
+        assert_eq!(
+            file.metadata().unwrap().len(),
+            read_only.metadata().unwrap().len()
+        );
+        assert!(open_file(&format!("/dev/fd/{}", read_only.as_raw_fd()), false).is_ok());
+        assert_eq!(
+            open_file(&fd_path(&read_only), true).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        let read_write = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        add_inherited_fds(&[read_write.as_raw_fd()]).unwrap();
+        assert!(open_file(&fd_path(&read_write), true).is_ok());
+
+        // The flags of an inherited descriptor are only checked.
+        assert_eq!(
+            open_file_with_flags(&fd_path(&read_write), true, libc::O_DSYNC)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let dsync = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_DSYNC)
+            .open(path)
+            .unwrap();
+        add_inherited_fds(&[dsync.as_raw_fd()]).unwrap();
+        assert!(open_file_with_flags(&fd_path(&dsync), true, libc::O_DSYNC).is_ok());
+        assert!(open_file_with_flags(path, true, libc::O_DSYNC).is_ok());
+
+        // The descriptor has to be open.
+        let closed_fd_path = fd_path(&read_write);
+        drop(read_write);
+        assert!(open_file(&closed_fd_path, false).is_err());
+        assert!(add_inherited_fds(&[i32::MAX]).is_err());
+    }
 }
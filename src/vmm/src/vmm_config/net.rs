@@ -1,16 +1,22 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::net::Ipv4Addr;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
-use super::RateLimiterConfig;
+use super::{DeviceRunState, RateLimiterConfig};
+use crate::devices::virtio::net::capture::{CaptureError, PacketCapture};
+use crate::devices::virtio::net::dhcp::{DhcpError, DhcpResponder};
+use crate::devices::virtio::net::pause_responder::PauseResponder;
 use crate::devices::virtio::net::TapError;
-use crate::devices::virtio::Net;
+use crate::devices::virtio::{Net, FIRECRACKER_MAX_QUEUE_SIZE, MAX_BUSY_POLL_US};
 use crate::VmmError;
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
@@ -20,7 +26,9 @@ use crate::VmmError;
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
     pub iface_id: String,
-    /// Host level path for the guest network interface.
+    /// Host level path for the guest network interface. Left empty when the interface is served
+    /// by the user-mode network stack.
+    #[serde(default)]
     pub host_dev_name: String,
     /// Guest MAC address.
     pub guest_mac: Option<MacAddr>,
@@ -28,6 +36,26 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Mirroring of the frames exchanged with the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<PacketCaptureConfig>,
+    /// Static DHCP lease and DNS records served to the guest by the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<DhcpConfig>,
+    /// User-mode network stack serving the interface in place of a tap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_net: Option<UserNetConfig>,
+    /// Replies sent on behalf of the guest while the microVM is paused with `PausedLite`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pause_responder: Option<PauseResponderConfig>,
+    /// Time, in microseconds, the TX queue is busy-polled for new frames before waiting for the
+    /// guest to notify the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy_poll_us: Option<u32>,
+    /// Number of used descriptor chains of a queue after which the guest is notified, even if
+    /// more frames are being processed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<u16>,
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -40,12 +68,116 @@ impl From<&Net> for NetworkInterfaceConfig {
             guest_mac: net.guest_mac().copied(),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            capture: net.capture().map(|capture| capture.config().clone()),
+            dhcp: net.dhcp().map(|dhcp| dhcp.config().clone()),
+            user_net: net
+                .user_net()
+                .map(|user_net| user_net.lock().expect("Poisoned lock").config().clone()),
+            pause_responder: net
+                .pause_responder()
+                .map(|responder| responder.config().clone()),
+            busy_poll_us: (!net.busy_poll().is_zero())
+                .then(|| u32::try_from(net.busy_poll().as_micros()).unwrap_or(u32::MAX)),
+            batch_size: (net.batch_size() != 0).then_some(net.batch_size()),
         }
     }
 }
 
-/// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// Configuration of the mirroring of the frames exchanged by a net device with the guest. At
+/// most one output can be set, and none stops the capture.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PacketCaptureConfig {
+    /// Path of the pcap file the frames are written to.
+    pub pcap_path: Option<String>,
+    /// Path of a Unix datagram socket the frames are sent to, one per datagram.
+    pub socket_path: Option<String>,
+    /// Number of bytes after which the capture stops.
+    pub max_size_bytes: Option<u64>,
+}
+
+/// Configuration of the DHCP server and DNS stub answering the guest from its net device.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// IPv4 address leased to the guest.
+    pub guest_ip: Ipv4Addr,
+    /// Netmask of the guest network.
+    pub netmask: Ipv4Addr,
+    /// Default gateway of the guest, which the DHCP server and the DNS stub answer from.
+    pub gateway: Ipv4Addr,
+    /// DNS servers of the guest, which follow the DNS stub when it has records.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nameservers: Vec<Ipv4Addr>,
+    /// Host name of the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Duration of the lease, in seconds.
+    #[serde(default = "DhcpConfig::default_lease_time_s")]
+    pub lease_time_s: u32,
+    /// A records served by the DNS stub, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dns_records: BTreeMap<String, Ipv4Addr>,
+}
+
+impl DhcpConfig {
+    fn default_lease_time_s() -> u32 {
+        86400
+    }
+}
+
+/// Configuration of the replies a net device sends on behalf of the guest while its vCPUs are
+/// paused, so that the hosts probing it keep seeing it up.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PauseResponderConfig {
+    /// IPv4 address of the guest, which the ARP requests are answered for.
+    pub guest_ip: Ipv4Addr,
+    /// Whether the ICMP echo requests to the guest are answered as well.
+    #[serde(default)]
+    pub icmp_echo: bool,
+}
+
+/// Configuration of the user-mode network stack, which relays the TCP and UDP traffic of the
+/// guest through host sockets, without a tap.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserNetConfig {
+    /// IPv4 address of the guest.
+    pub guest_ip: Ipv4Addr,
+    /// Default gateway of the guest.
+    pub gateway: Ipv4Addr,
+    /// Ports of the gateway whose TCP connections and UDP datagrams are relayed to the same
+    /// ports of the host loopback interface. The traffic to the other ports of the gateway is
+    /// refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub host_loopback_ports: Vec<u16>,
+    /// Host TCP ports forwarded to the guest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub port_forwards: Vec<PortForwardConfig>,
+}
+
+/// Host TCP port forwarded to a port of the guest by the user-mode network stack.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortForwardConfig {
+    /// Host address the port is bound to.
+    #[serde(default = "PortForwardConfig::default_host_address")]
+    pub host_address: Ipv4Addr,
+    /// Host port the connections are accepted on.
+    pub host_port: u16,
+    /// Guest port the connections are forwarded to.
+    pub guest_port: u16,
+}
+
+impl PortForwardConfig {
+    fn default_host_address() -> Ipv4Addr {
+        Ipv4Addr::LOCALHOST
+    }
+}
+
+/// The data fed into a network iface update request. Currently, only the RX and TX rate limiters,
+/// the processing state and the packet capture can be updated.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -57,6 +189,11 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// New processing state, to pause the device while its tap is re-plumbed.
+    pub state: Option<DeviceRunState>,
+    /// New packet capture, replacing the current one.
+    #[serde(default)]
+    pub capture: Option<PacketCaptureConfig>,
 }
 
 /// Errors associated with the operations allowed on a net device.
@@ -77,6 +214,26 @@ pub enum NetworkInterfaceError {
     /// Cannot open/create the tap device.
     #[error("Cannot open/create the tap device: {0}")]
     OpenTap(#[from] TapError),
+    /// Cannot open the output of the packet capture.
+    #[error("Cannot open the packet capture: {0}")]
+    PacketCapture(#[from] CaptureError),
+    /// Invalid DHCP configuration.
+    #[error("Invalid DHCP configuration: {0}")]
+    Dhcp(#[from] DhcpError),
+    /// Both a tap and the user-mode network stack are configured.
+    #[error("The host device name and the user-mode network stack are mutually exclusive")]
+    ConflictingBackends,
+    /// The pause responder answers with the guest MAC address, which must be set.
+    #[error("The pause responder requires a guest MAC address")]
+    PauseResponderWithoutMac,
+    /// The busy-poll window is too long.
+    #[error("Invalid busy-poll window of {0} us: it must be at most {MAX_BUSY_POLL_US} us")]
+    InvalidBusyPoll(u32),
+    /// The notification batch size is too large.
+    #[error(
+        "Invalid notification batch size {0}: it must be no larger than the virtio queue size"
+    )]
+    InvalidBatchSize(u16),
 }
 
 /// Builder for a list of network devices.
@@ -160,15 +317,48 @@ impl NetBuilder {
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
+        let capture = cfg.capture.map(PacketCapture::new).transpose()?.flatten();
+        let dhcp = cfg.dhcp.map(DhcpResponder::new).transpose()?;
+        if cfg.pause_responder.is_some() && cfg.guest_mac.is_none() {
+            return Err(NetworkInterfaceError::PauseResponderWithoutMac);
+        }
+        let pause_responder = cfg.pause_responder.map(PauseResponder::new);
+        let busy_poll_us = cfg.busy_poll_us.unwrap_or(0);
+        if busy_poll_us > MAX_BUSY_POLL_US {
+            return Err(NetworkInterfaceError::InvalidBusyPoll(busy_poll_us));
+        }
+        let batch_size = cfg.batch_size.unwrap_or(0);
+        if batch_size > FIRECRACKER_MAX_QUEUE_SIZE {
+            return Err(NetworkInterfaceError::InvalidBatchSize(batch_size));
+        }
+
         // Create and return the Net device
-        crate::devices::virtio::net::Net::new(
-            cfg.iface_id,
-            &cfg.host_dev_name,
-            cfg.guest_mac,
-            rx_rate_limiter.unwrap_or_default(),
-            tx_rate_limiter.unwrap_or_default(),
-        )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        let mut net = match cfg.user_net {
+            Some(_) if !cfg.host_dev_name.is_empty() => {
+                return Err(NetworkInterfaceError::ConflictingBackends)
+            }
+            Some(user_net) => Net::new_with_user_net(
+                cfg.iface_id,
+                user_net,
+                cfg.guest_mac,
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+            ),
+            None => Net::new(
+                cfg.iface_id,
+                &cfg.host_dev_name,
+                cfg.guest_mac,
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+            ),
+        }
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_capture(capture);
+        net.set_dhcp(dhcp);
+        net.set_pause_responder(pause_responder);
+        net.set_busy_poll(Duration::from_micros(u64::from(busy_poll_us)));
+        net.set_batch_size(batch_size);
+        Ok(net)
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -205,6 +395,12 @@ mod tests {
             guest_mac: Some(MacAddr::from_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            capture: None,
+            dhcp: None,
+            user_net: None,
+            pause_responder: None,
+            busy_poll_us: None,
+            batch_size: None,
         }
     }
 
@@ -216,6 +412,12 @@ mod tests {
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                capture: self.capture.clone(),
+                dhcp: self.dhcp.clone(),
+                user_net: self.user_net.clone(),
+                pause_responder: self.pause_responder.clone(),
+                busy_poll_us: self.busy_poll_us,
+                batch_size: self.batch_size,
             }
         }
     }
@@ -335,6 +537,116 @@ mod tests {
         assert_eq!(configs.first().unwrap(), &net_if_cfg);
     }
 
+    #[test]
+    fn test_net_capture() {
+        let pcap_file = utils::tempfile::TempFile::new().unwrap();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+        net_if_cfg.capture = Some(PacketCaptureConfig {
+            pcap_path: Some(pcap_file.as_path().to_str().unwrap().to_string()),
+            socket_path: None,
+            max_size_bytes: Some(4096),
+        });
+
+        let mut net_builder = NetBuilder::new();
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert!(net.lock().unwrap().capture().is_some());
+        assert_eq!(net_builder.configs()[0].capture, net_if_cfg.capture);
+
+        // An output which cannot be opened fails the device creation.
+        net_if_cfg.capture = Some(PacketCaptureConfig {
+            pcap_path: Some("/invalid/path/foo.pcap".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            net_builder.build(net_if_cfg),
+            Err(NetworkInterfaceError::PacketCapture(
+                CaptureError::CreatePcap(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_net_user_net() {
+        let mut net_if_cfg = create_netif("id", "", "01:23:45:67:89:0b");
+        net_if_cfg.user_net = Some(UserNetConfig {
+            guest_ip: Ipv4Addr::new(10, 0, 2, 15),
+            gateway: Ipv4Addr::new(10, 0, 2, 2),
+            host_loopback_ports: vec![],
+            port_forwards: vec![],
+        });
+
+        let mut net_builder = NetBuilder::new();
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert!(net.lock().unwrap().user_net().is_some());
+        assert_eq!(net_builder.configs()[0].user_net, net_if_cfg.user_net);
+        assert_eq!(net_builder.configs()[0].host_dev_name, "");
+
+        // A tap cannot be configured along with the user-mode network stack.
+        net_if_cfg.host_dev_name = String::from("dev");
+        assert!(matches!(
+            net_builder.build(net_if_cfg),
+            Err(NetworkInterfaceError::ConflictingBackends)
+        ));
+    }
+
+    #[test]
+    fn test_net_pause_responder() {
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+        net_if_cfg.pause_responder = Some(PauseResponderConfig {
+            guest_ip: Ipv4Addr::new(192, 168, 0, 2),
+            icmp_echo: true,
+        });
+
+        let mut net_builder = NetBuilder::new();
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert!(net.lock().unwrap().pause_responder().is_some());
+        assert_eq!(
+            net_builder.configs()[0].pause_responder,
+            net_if_cfg.pause_responder
+        );
+
+        // The replies cannot be sent without the guest MAC address.
+        net_if_cfg.guest_mac = None;
+        assert!(matches!(
+            net_builder.build(net_if_cfg),
+            Err(NetworkInterfaceError::PauseResponderWithoutMac)
+        ));
+    }
+
+    #[test]
+    fn test_net_busy_poll() {
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+        net_if_cfg.busy_poll_us = Some(50);
+
+        let mut net_builder = NetBuilder::new();
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().busy_poll(), Duration::from_micros(50));
+        assert_eq!(net_builder.configs()[0].busy_poll_us, Some(50));
+
+        net_if_cfg.busy_poll_us = Some(MAX_BUSY_POLL_US + 1);
+        assert!(matches!(
+            net_builder.build(net_if_cfg),
+            Err(NetworkInterfaceError::InvalidBusyPoll(_))
+        ));
+    }
+
+    #[test]
+    fn test_net_batch_size() {
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+        net_if_cfg.batch_size = Some(8);
+
+        let mut net_builder = NetBuilder::new();
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().batch_size(), 8);
+        assert_eq!(net_builder.configs()[0].batch_size, Some(8));
+
+        net_if_cfg.batch_size = Some(FIRECRACKER_MAX_QUEUE_SIZE + 1);
+        assert!(matches!(
+            net_builder.build(net_if_cfg),
+            Err(NetworkInterfaceError::InvalidBatchSize(_))
+        ));
+    }
+
     #[test]
     fn test_add_device() {
         let mut net_builder = NetBuilder::new();
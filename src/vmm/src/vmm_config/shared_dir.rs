@@ -0,0 +1,150 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::p9::{P9Error, P9};
+
+/// This struct represents the strongly typed equivalent of the json body from shared directory
+/// related requests.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SharedDirConfig {
+    /// ID of the shared directory.
+    pub share_id: String,
+    /// Host directory shared with the guest.
+    pub path_on_host: PathBuf,
+    /// Tag the guest mounts the shared directory by.
+    pub mount_tag: String,
+    /// If set to true, the guest cannot modify the shared directory.
+    #[serde(default)]
+    pub is_read_only: bool,
+}
+
+impl From<&P9> for SharedDirConfig {
+    fn from(dev: &P9) -> Self {
+        SharedDirConfig {
+            share_id: dev.id().to_string(),
+            path_on_host: dev.shared_dir().to_path_buf(),
+            mount_tag: dev.mount_tag().to_string(),
+            is_read_only: dev.is_read_only(),
+        }
+    }
+}
+
+/// Errors associated with the operations allowed on a shared directory.
+#[derive(Debug, thiserror::Error)]
+pub enum SharedDirError {
+    /// Could not create the 9p device.
+    #[error("Could not create the shared directory device: {0}")]
+    CreateDevice(#[from] P9Error),
+    /// The mount tag is already in use.
+    #[error("The mount tag is already in use: {0}")]
+    MountTagInUse(String),
+}
+
+/// Builder for a list of shared directory devices.
+#[derive(Debug, Default)]
+pub struct SharedDirBuilder {
+    devices: Vec<Arc<Mutex<P9>>>,
+}
+
+impl SharedDirBuilder {
+    /// Creates an empty list of shared directory devices.
+    pub fn new() -> Self {
+        SharedDirBuilder {
+            devices: Vec::new(),
+        }
+    }
+
+    /// Returns a immutable iterator over the shared directory devices.
+    pub fn iter(&self) -> ::std::slice::Iter<Arc<Mutex<P9>>> {
+        self.devices.iter()
+    }
+
+    /// Builds a shared directory device based on its config. Keeps a device reference in the
+    /// builder's internal list. A device with the same id is replaced.
+    pub fn build(&mut self, config: SharedDirConfig) -> Result<Arc<Mutex<P9>>, SharedDirError> {
+        let tag_conflict = |dev: &Arc<Mutex<P9>>| {
+            let dev = dev.lock().expect("Poisoned lock");
+            dev.mount_tag() == config.mount_tag && dev.id() != config.share_id
+        };
+        if self.devices.iter().any(tag_conflict) {
+            return Err(SharedDirError::MountTagInUse(config.mount_tag));
+        }
+
+        let dev = Arc::new(Mutex::new(P9::new(
+            config.share_id.clone(),
+            config.mount_tag,
+            config.path_on_host,
+            config.is_read_only,
+        )?));
+
+        // If this is an update, replace the old device in place.
+        match self
+            .devices
+            .iter()
+            .position(|dev| dev.lock().expect("Poisoned lock").id() == config.share_id)
+        {
+            Some(index) => self.devices[index] = dev.clone(),
+            None => self.devices.push(dev.clone()),
+        }
+        Ok(dev)
+    }
+
+    /// Returns a vec with the structures used to configure the shared directories.
+    pub fn configs(&self) -> Vec<SharedDirConfig> {
+        self.devices
+            .iter()
+            .map(|dev| SharedDirConfig::from(dev.lock().expect("Poisoned lock").deref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    fn config(share_id: &str, path_on_host: PathBuf, mount_tag: &str) -> SharedDirConfig {
+        SharedDirConfig {
+            share_id: share_id.to_string(),
+            path_on_host,
+            mount_tag: mount_tag.to_string(),
+            is_read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().to_path_buf();
+        let mut builder = SharedDirBuilder::new();
+
+        builder.build(config("fs1", path.clone(), "tag1")).unwrap();
+        builder.build(config("fs2", path.clone(), "tag2")).unwrap();
+        assert_eq!(builder.iter().count(), 2);
+
+        // Mount tags must be unique.
+        assert!(matches!(
+            builder.build(config("fs3", path.clone(), "tag1")),
+            Err(SharedDirError::MountTagInUse(_))
+        ));
+        assert!(matches!(
+            builder.build(config("fs3", path.join("missing"), "tag3")),
+            Err(SharedDirError::CreateDevice(_))
+        ));
+        assert_eq!(builder.iter().count(), 2);
+
+        // Updating a shared directory keeps its position.
+        let mut update = config("fs1", path.clone(), "tag1");
+        update.is_read_only = true;
+        builder.build(update.clone()).unwrap();
+        assert_eq!(builder.configs(), vec![update, config("fs2", path, "tag2")]);
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the shutdown hooks configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ShutdownHooksConfigError {
+    /// A path to remove or to write to is empty.
+    #[error("The paths of the shutdown hooks cannot be empty.")]
+    EmptyPath,
+}
+
+/// Cleanup actions performed when Firecracker exits, whether it exits normally, after an error
+/// or after a crash.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownHooksConfig {
+    /// Removes the taps backing the network interfaces once Firecracker exits.
+    #[serde(default)]
+    pub remove_taps: bool,
+    /// Files removed on exit, e.g. sockets and scratch overlays.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_paths: Vec<PathBuf>,
+    /// File the exit status is written to on exit, once the other actions are performed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_file: Option<PathBuf>,
+}
+
+impl ShutdownHooksConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), ShutdownHooksConfigError> {
+        if self
+            .remove_paths
+            .iter()
+            .chain(self.status_file.iter())
+            .any(|path| path.as_os_str().is_empty())
+        {
+            return Err(ShutdownHooksConfigError::EmptyPath);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_hooks_config() {
+        let config: ShutdownHooksConfig = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(config, ShutdownHooksConfig::default());
+
+        let config: ShutdownHooksConfig = serde_json::from_str(
+            r#"{
+                "remove_taps": true,
+                "remove_paths": ["/run/vsock.sock", "/srv/overlay.ext4"],
+                "status_file": "/run/status.json"
+            }"#,
+        )
+        .unwrap();
+        assert!(config.remove_taps);
+        assert_eq!(config.remove_paths.len(), 2);
+        config.validate().unwrap();
+
+        serde_json::from_str::<ShutdownHooksConfig>(r#"{"remove_sockets": []}"#).unwrap_err();
+
+        let config = ShutdownHooksConfig {
+            status_file: Some(PathBuf::new()),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(ShutdownHooksConfigError::EmptyPath));
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Longest identification string accepted, in bytes.
+pub const MAX_SMBIOS_STRING_LEN: usize = 64;
+
+/// Errors associated with the SMBIOS configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SmbiosConfigError {
+    /// An identification string cannot be exposed to the guest.
+    #[error(
+        "The {0} must be made of 1 to {} printable ASCII characters.",
+        MAX_SMBIOS_STRING_LEN
+    )]
+    InvalidString(&'static str),
+    /// The guest has no way of finding the SMBIOS tables.
+    #[error("SMBIOS tables are not supported on aarch64.")]
+    Unsupported,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from SMBIOS related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct SmbiosConfig {
+    /// Manufacturer of the system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    /// Product name of the system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    /// Version of the system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Serial number of the system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+}
+
+impl SmbiosConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), SmbiosConfigError> {
+        // Without UEFI, aarch64 guests only look for the SMBIOS tables in the EFI system table.
+        #[cfg(target_arch = "aarch64")]
+        {
+            Err(SmbiosConfigError::Unsupported)
+        }
+        #[cfg(target_arch = "x86_64")]
+        {
+            for (name, string) in [
+                ("manufacturer", &self.manufacturer),
+                ("product name", &self.product_name),
+                ("version", &self.version),
+                ("serial number", &self.serial_number),
+            ] {
+                if let Some(string) = string {
+                    if string.is_empty()
+                        || string.len() > MAX_SMBIOS_STRING_LEN
+                        || !string.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+                    {
+                        return Err(SmbiosConfigError::InvalidString(name));
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smbios_config() {
+        let config: SmbiosConfig = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(config, SmbiosConfig::default());
+
+        let config: SmbiosConfig = serde_json::from_str(
+            r#"{
+                "manufacturer": "ACME",
+                "product_name": "Rocket",
+                "serial_number": "SN-42"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            SmbiosConfig {
+                manufacturer: Some("ACME".to_string()),
+                product_name: Some("Rocket".to_string()),
+                version: None,
+                serial_number: Some("SN-42".to_string()),
+            }
+        );
+
+        assert!(serde_json::from_str::<SmbiosConfig>(r#"{"foo": "bar"}"#).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_validate() {
+        let mut config = SmbiosConfig {
+            manufacturer: Some("ACME Inc.".to_string()),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+
+        config.product_name = Some(String::new());
+        assert_eq!(
+            config.validate(),
+            Err(SmbiosConfigError::InvalidString("product name"))
+        );
+        config.product_name = Some("a".repeat(MAX_SMBIOS_STRING_LEN + 1));
+        assert_eq!(
+            config.validate(),
+            Err(SmbiosConfigError::InvalidString("product name"))
+        );
+        config.product_name = Some("a".repeat(MAX_SMBIOS_STRING_LEN));
+        config.validate().unwrap();
+        config.serial_number = Some("SN\0".to_string());
+        assert_eq!(
+            config.validate(),
+            Err(SmbiosConfigError::InvalidString("serial number"))
+        );
+        config.serial_number = Some("caf\u{e9}".to_string());
+        assert_eq!(
+            config.validate(),
+            Err(SmbiosConfigError::InvalidString("serial number"))
+        );
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            SmbiosConfig::default().validate(),
+            Err(SmbiosConfigError::Unsupported)
+        );
+    }
+}
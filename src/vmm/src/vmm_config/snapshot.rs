@@ -8,10 +8,11 @@ use std::path::PathBuf;
 /// For crates that depend on `vmm` we export.
 pub use semver::Version;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
-#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum SnapshotType {
     /// Diff snapshot.
     Diff,
@@ -24,13 +25,26 @@ pub enum SnapshotType {
 /// resuming from a snapshot:
 /// 1) A file that contains the guest memory to be loaded,
 /// 2) An UDS where a custom page-fault handler process is listening for the UFFD set up by
-///    Firecracker to handle its guest memory page faults.
+///    Firecracker to handle its guest memory page faults,
+/// 3) The URL of a memory file served over HTTP, fetched with range requests on the guest page
+///    faults.
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 pub enum MemBackendType {
     /// Guest memory contents will be loaded from a file.
     File,
     /// Guest memory will be served through UFFD by a separate process.
     Uffd,
+    /// Guest memory contents will be fetched from an HTTP server on demand.
+    Http,
+}
+
+/// Action taken when the page fault handler serving guest memory through UFFD exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum UffdHandlerExitAction {
+    /// Pause the microVM.
+    Pause,
+    /// Serve the guest memory not populated yet from `fallback_mem_file_path`.
+    Fallback,
 }
 
 /// Stores the configuration that will be used for creating a snapshot.
@@ -48,6 +62,26 @@ pub struct CreateSnapshotParams {
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<Version>,
+    /// Time, in milliseconds, after which the snapshot creation is aborted. When not set, the
+    /// snapshot creation is not bounded in time.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Stores the configuration that will be used for suspending the microVM to disk.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuspendToDiskParams {
+    /// This marks the type of snapshot we want to create.
+    /// The default value is `Full`, which means a full snapshot.
+    #[serde(default = "SnapshotType::default")]
+    pub snapshot_type: SnapshotType,
+    /// Path to the file that will contain the microVM state.
+    pub snapshot_path: PathBuf,
+    /// Path to the file that will contain the guest memory.
+    pub mem_file_path: PathBuf,
+    /// Path to the marker written once the snapshot files are durable on disk.
+    pub marker_path: PathBuf,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -63,14 +97,40 @@ pub struct LoadSnapshotParams {
     /// When set to true, the vm is also resumed if the snapshot load
     /// is successful.
     pub resume_vm: bool,
+    /// Time, in milliseconds, after which the snapshot load is aborted.
+    pub timeout_ms: Option<u64>,
+    /// Overrides the host-side Unix socket of the vsock device.
+    pub vsock_override: Option<VsockOverride>,
+    /// Restore-time parameters published to the guest under the `restore_info` MMDS key.
+    pub restore_info: Option<Map<String, Value>>,
+    /// Taps the network interfaces are attached to instead of the ones of the snapshot.
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Files the drives are backed by instead of the ones of the snapshot.
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Contents of the MMDS data store.
+    pub mmds: Option<Map<String, Value>>,
+    /// Number of vCPUs to run. The other vCPUs of the snapshot stay parked.
+    pub vcpu_count: Option<u16>,
+    /// Manifest of a layered snapshot. When set, `snapshot_path`, the path of `mem_backend`,
+    /// `mem_diff_layers` and `drive_overrides` are filled from the manifest before the load.
+    pub manifest_path: Option<PathBuf>,
+    /// Memory files of the diff snapshots applied, from the oldest to the newest, over the memory
+    /// file of the `File` backend.
+    pub mem_diff_layers: Vec<PathBuf>,
 }
 
 /// Stores the configuration for loading a snapshot that is provided by the user.
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct LoadSnapshotConfig {
-    /// Path to the file that contains the microVM state to be loaded.
-    pub snapshot_path: PathBuf,
+    /// Path to the file that contains the microVM state to be loaded. Required when
+    /// `manifest_path` is not specified.
+    #[serde(default)]
+    pub snapshot_path: Option<PathBuf>,
+    /// Path to the manifest of a layered snapshot, listing the files to load. Is not to be used
+    /// in conjunction with `snapshot_path`, `mem_file_path` or `mem_backend`.
+    #[serde(default)]
+    pub manifest_path: Option<PathBuf>,
     /// Path to the file that contains the guest memory to be loaded. To be used only if
     /// `mem_backend` is not specified.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -85,6 +145,116 @@ pub struct LoadSnapshotConfig {
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Time, in milliseconds, after which the snapshot load is aborted.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Overrides the host-side Unix socket of the vsock device. When not set, the vsock device
+    /// binds to the path it had when the snapshot was created.
+    #[serde(default)]
+    pub vsock_override: Option<VsockOverride>,
+    /// Restore-time parameters (e.g. a new IP address or task id) published to the guest under
+    /// the `restore_info` MMDS key before it is resumed. Requires MMDS in the snapshot.
+    #[serde(default)]
+    pub restore_info: Option<Map<String, Value>>,
+    /// Number of vCPUs to run, when fewer than in the snapshot. The other vCPUs stay parked and
+    /// must have been taken offline by the guest before the snapshot was created.
+    #[serde(default)]
+    pub vcpu_count: Option<u16>,
+}
+
+/// Stores the configuration for cloning a microVM from a snapshot, which loads the snapshot,
+/// reconfigures the restored microVM and resumes it as a single operation.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloneConfig {
+    /// Path to the file that contains the microVM state to be loaded.
+    pub snapshot_path: PathBuf,
+    /// Guest memory backend configuration.
+    pub mem_backend: MemBackendConfig,
+    /// Whether or not to enable KVM dirty page tracking.
+    #[serde(default)]
+    pub enable_diff_snapshots: bool,
+    /// Time, in milliseconds, after which the clone is aborted.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Overrides the host-side Unix socket of the vsock device.
+    #[serde(default)]
+    pub vsock_override: Option<VsockOverride>,
+    /// Taps the network interfaces are attached to instead of the ones of the snapshot.
+    #[serde(default)]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Files the drives are backed by instead of the ones of the snapshot.
+    #[serde(default)]
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Contents of the MMDS data store. Requires MMDS in the snapshot.
+    #[serde(default)]
+    pub mmds: Option<Map<String, Value>>,
+    /// Restore-time parameters published to the guest under the `restore_info` MMDS key.
+    #[serde(default)]
+    pub restore_info: Option<Map<String, Value>>,
+    /// Number of vCPUs to run, when fewer than in the snapshot.
+    #[serde(default)]
+    pub vcpu_count: Option<u16>,
+}
+
+/// Stores the parameters bound to a microVM restored in the paused state, right before it is
+/// resumed.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommitSnapshotParams {
+    /// Files the drives are backed by instead of the ones they were restored with.
+    #[serde(default)]
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Path to bind the host-side Unix socket of the vsock device to.
+    #[serde(default)]
+    pub vsock_uds_path: Option<String>,
+    /// Contents of the MMDS data store.
+    #[serde(default)]
+    pub mmds: Option<Map<String, Value>>,
+    /// Restore-time parameters published to the guest under the `restore_info` MMDS key.
+    #[serde(default)]
+    pub restore_info: Option<Map<String, Value>>,
+}
+
+/// Attaches a restored network interface to another tap.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkOverride {
+    /// Id of the network interface, as given when configuring it.
+    pub iface_id: String,
+    /// Name of the tap the network interface is attached to.
+    pub host_dev_name: String,
+}
+
+/// Backs a restored drive with another file.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveOverride {
+    /// Id of the drive, as given when configuring it.
+    pub drive_id: String,
+    /// Path of the file backing the drive.
+    pub path_on_host: String,
+}
+
+/// Stores how the host-side Unix socket of the vsock device is restored from a snapshot.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockOverride {
+    /// Path to bind the host-side Unix socket to, instead of the path of the snapshot.
+    #[serde(default)]
+    pub uds_path: Option<String>,
+    /// Leaves the host-side Unix socket unbound until it is set through `PATCH /vsock`. Is not
+    /// to be used in conjunction with `uds_path`.
+    #[serde(default)]
+    pub defer: bool,
+    /// CID assigned to the guest instead of the CID of the snapshot.
+    #[serde(default)]
+    pub guest_cid: Option<u32>,
+    /// Directory shared by the microVMs of the host, in which the guest CID is locked. Unless
+    /// `guest_cid` is set, the CID of the snapshot is kept if no other microVM uses it, and the
+    /// lowest free CID is assigned otherwise.
+    #[serde(default)]
+    pub cid_lock_dir: Option<String>,
 }
 
 /// Stores the configuration used for managing snapshot memory.
@@ -95,6 +265,73 @@ pub struct MemBackendConfig {
     pub backend_path: PathBuf,
     /// Specifies the guest memory backend type.
     pub backend_type: MemBackendType,
+    /// Action taken if the page fault handler exits. Only valid for the `Uffd` backend.
+    /// When not set, the page fault handler is not monitored.
+    #[serde(default)]
+    pub handler_exit_action: Option<UffdHandlerExitAction>,
+    /// Path to the file containing the guest memory, used to serve it if the page fault
+    /// handler exits. Required when `handler_exit_action` is `Fallback`.
+    #[serde(default)]
+    pub fallback_mem_file_path: Option<PathBuf>,
+    /// Also registers the guest memory with the userfaultfd object in write-protect mode, so
+    /// that the pages written after restore can be tracked. Only valid for the `Uffd` backend.
+    #[serde(default)]
+    pub write_protect: bool,
+    /// Bandwidth, in MiB/s, of the sequential read of the memory file into the host page cache,
+    /// in the background of the guest page faults. Only valid for the `File` backend. When not
+    /// set, the memory file is only read on the page faults of the guest.
+    #[serde(default)]
+    pub readahead_mib_per_s: Option<u32>,
+    /// Path to the file caching the guest memory fetched from the HTTP server, reused by the
+    /// next restores of the same snapshot. Only valid for the `Http` backend. When not set,
+    /// the guest memory is not cached.
+    #[serde(default)]
+    pub cache_path: Option<PathBuf>,
+}
+
+/// Errors associated with the monitoring of the page fault handler set up by `MemBackendConfig`.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum UffdHandlerExitActionError {
+    /// The page fault handler is only there with the `Uffd` backend.
+    #[error("`handler_exit_action` is only supported by the `Uffd` memory backend")]
+    WithoutUffd,
+    /// The `Fallback` action has no fallback memory file.
+    #[error("`fallback_mem_file_path` is required by the `Fallback` handler exit action")]
+    MissingFallbackMemFile,
+    /// The fallback memory file is only used by the `Fallback` action.
+    #[error("`fallback_mem_file_path` is only used by the `Fallback` handler exit action")]
+    UnexpectedFallbackMemFile,
+}
+
+impl MemBackendConfig {
+    /// Checks that the action taken if the page fault handler exits goes along with the backend
+    /// and the fallback memory file.
+    pub fn check_handler_exit_action(&self) -> Result<(), UffdHandlerExitActionError> {
+        match (
+            &self.backend_type,
+            self.handler_exit_action,
+            &self.fallback_mem_file_path,
+        ) {
+            (MemBackendType::File | MemBackendType::Http, Some(_), _) => {
+                Err(UffdHandlerExitActionError::WithoutUffd)
+            }
+            (_, Some(UffdHandlerExitAction::Fallback), None) => {
+                Err(UffdHandlerExitActionError::MissingFallbackMemFile)
+            }
+            (_, None | Some(UffdHandlerExitAction::Pause), Some(_)) => {
+                Err(UffdHandlerExitActionError::UnexpectedFallbackMemFile)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stores the configuration used to hand the guest memory over to a new page fault handler.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UffdHandlerConfig {
+    /// Path to the UDS where the new page fault handler is listening.
+    pub backend_path: PathBuf,
 }
 
 /// The microVM state options.
@@ -102,6 +339,9 @@ pub struct MemBackendConfig {
 pub enum VmState {
     /// The microVM is paused, which means that we can create a snapshot of it.
     Paused,
+    /// The microVM vCPUs are paused, while its net and vsock devices keep serving the host, so
+    /// that it stays reachable during a brief pause.
+    PausedLite,
     /// The microVM is resumed; this state should be set after we load a snapshot.
     Resumed,
 }
@@ -110,6 +350,55 @@ pub enum VmState {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Vm {
-    /// The microVM state, which can be `paused` or `resumed`.
+    /// The microVM state, which can be `Paused`, `PausedLite` or `Resumed`.
     pub state: VmState,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_handler_exit_action() {
+        let mut mem_backend = MemBackendConfig {
+            backend_path: PathBuf::from("/tmp/uffd.sock"),
+            backend_type: MemBackendType::Uffd,
+            handler_exit_action: None,
+            fallback_mem_file_path: None,
+            write_protect: false,
+            readahead_mib_per_s: None,
+            cache_path: None,
+        };
+        mem_backend.check_handler_exit_action().unwrap();
+
+        mem_backend.handler_exit_action = Some(UffdHandlerExitAction::Pause);
+        mem_backend.check_handler_exit_action().unwrap();
+        mem_backend.fallback_mem_file_path = Some(PathBuf::from("/tmp/snapshot.mem"));
+        assert_eq!(
+            mem_backend.check_handler_exit_action(),
+            Err(UffdHandlerExitActionError::UnexpectedFallbackMemFile)
+        );
+
+        mem_backend.handler_exit_action = Some(UffdHandlerExitAction::Fallback);
+        mem_backend.check_handler_exit_action().unwrap();
+        mem_backend.fallback_mem_file_path = None;
+        assert_eq!(
+            mem_backend.check_handler_exit_action(),
+            Err(UffdHandlerExitActionError::MissingFallbackMemFile)
+        );
+
+        mem_backend.handler_exit_action = None;
+        mem_backend.fallback_mem_file_path = Some(PathBuf::from("/tmp/snapshot.mem"));
+        assert_eq!(
+            mem_backend.check_handler_exit_action(),
+            Err(UffdHandlerExitActionError::UnexpectedFallbackMemFile)
+        );
+
+        mem_backend.backend_type = MemBackendType::File;
+        mem_backend.handler_exit_action = Some(UffdHandlerExitAction::Fallback);
+        assert_eq!(
+            mem_backend.check_handler_exit_action(),
+            Err(UffdHandlerExitActionError::WithoutUffd)
+        );
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Shortest stall timeout accepted, in milliseconds.
+pub const MIN_STALL_TIMEOUT_MS: u64 = 1000;
+
+/// Errors associated with the vCPU stall detection configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum StallDetectionConfigError {
+    /// The timeout is too short to tell stalls from scheduling hiccups.
+    #[error("The stall timeout must be at least 1000 ms, got {0} ms.")]
+    TimeoutTooShort(u64),
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from vCPU stall detection related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StallDetectionConfig {
+    /// Time, in milliseconds, after which a vCPU making no progress is reported as stalled.
+    pub timeout_ms: u64,
+    /// Whether to log the registers of the stalled vCPUs.
+    #[serde(default)]
+    pub capture_registers: bool,
+}
+
+impl StallDetectionConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), StallDetectionConfigError> {
+        if self.timeout_ms < MIN_STALL_TIMEOUT_MS {
+            return Err(StallDetectionConfigError::TimeoutTooShort(self.timeout_ms));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_detection_config() {
+        let config: StallDetectionConfig = serde_json::from_str(r#"{"timeout_ms": 5000}"#).unwrap();
+        assert_eq!(
+            config,
+            StallDetectionConfig {
+                timeout_ms: 5000,
+                capture_registers: false,
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<StallDetectionConfig>(r#"{}"#).is_err());
+        assert!(
+            serde_json::from_str::<StallDetectionConfig>(r#"{"timeout_ms": 5000, "foo": 1}"#)
+                .is_err()
+        );
+
+        let config = StallDetectionConfig {
+            timeout_ms: 999,
+            capture_registers: true,
+        };
+        assert_eq!(
+            config.validate(),
+            Err(StallDetectionConfigError::TimeoutTooShort(999))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the TPM configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TpmConfigError {
+    /// The path of the swtpm socket is empty.
+    #[error("The path of the swtpm control socket cannot be empty.")]
+    EmptySocketPath,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from TPM related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TpmConfig {
+    /// Path of the control socket of the swtpm process backing the TPM.
+    pub socket_path: String,
+}
+
+impl TpmConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), TpmConfigError> {
+        if self.socket_path.is_empty() {
+            return Err(TpmConfigError::EmptySocketPath);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tpm_config() {
+        let config: TpmConfig = serde_json::from_str(r#"{"socket_path": "/swtpm.sock"}"#).unwrap();
+        assert_eq!(
+            config,
+            TpmConfig {
+                socket_path: "/swtpm.sock".to_string()
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<TpmConfig>(r#"{}"#).is_err());
+        assert!(
+            serde_json::from_str::<TpmConfig>(r#"{"socket_path": "/swtpm.sock", "foo": 1}"#)
+                .is_err()
+        );
+
+        let config = TpmConfig {
+            socket_path: String::new(),
+        };
+        assert_eq!(config.validate(), Err(TpmConfigError::EmptySocketPath));
+    }
+}
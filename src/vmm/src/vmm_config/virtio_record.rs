@@ -0,0 +1,110 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the virtio recording configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum VirtioRecordConfigError {
+    /// No queue is selected.
+    #[error("At least one queue must be selected for recording.")]
+    NoQueues,
+    /// The same queue is selected twice.
+    #[error("Queue {1} of device {0} is selected more than once.")]
+    DuplicateQueue(String, u16),
+}
+
+/// Selects a virtio queue to record and the file the recording is written to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueRecordConfig {
+    /// Id of the device, as given when configuring it (e.g. the drive id).
+    pub device_id: String,
+    /// Index of the queue in the device.
+    pub queue_index: u16,
+    /// Path of the file the recording is written to. An existing file is overwritten.
+    pub path: String,
+    /// Size, in bytes, past which no more chains are recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_bytes: Option<u64>,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from virtio recording related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtioRecordConfig {
+    /// Queues whose descriptor chains are recorded.
+    pub queues: Vec<QueueRecordConfig>,
+}
+
+impl VirtioRecordConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), VirtioRecordConfigError> {
+        if self.queues.is_empty() {
+            return Err(VirtioRecordConfigError::NoQueues);
+        }
+        for (i, queue) in self.queues.iter().enumerate() {
+            if self.queues[..i].iter().any(|other| {
+                other.device_id == queue.device_id && other.queue_index == queue.queue_index
+            }) {
+                return Err(VirtioRecordConfigError::DuplicateQueue(
+                    queue.device_id.clone(),
+                    queue.queue_index,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtio_record_config() {
+        let config: VirtioRecordConfig = serde_json::from_str(
+            r#"{"queues": [{"device_id": "rootfs", "queue_index": 0, "path": "rootfs.rec"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            VirtioRecordConfig {
+                queues: vec![QueueRecordConfig {
+                    device_id: "rootfs".to_string(),
+                    queue_index: 0,
+                    path: "rootfs.rec".to_string(),
+                    max_size_bytes: None,
+                }],
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<VirtioRecordConfig>(r#"{}"#).is_err());
+        assert!(serde_json::from_str::<VirtioRecordConfig>(
+            r#"{"queues": [{"device_id": "rootfs", "queue_index": 0}]}"#
+        )
+        .is_err());
+
+        let config = VirtioRecordConfig { queues: vec![] };
+        assert_eq!(config.validate(), Err(VirtioRecordConfigError::NoQueues));
+
+        let queue = QueueRecordConfig {
+            device_id: "eth0".to_string(),
+            queue_index: 1,
+            path: "tx.rec".to_string(),
+            max_size_bytes: Some(1 << 20),
+        };
+        let config = VirtioRecordConfig {
+            queues: vec![queue.clone(), queue],
+        };
+        assert_eq!(
+            config.validate(),
+            Err(VirtioRecordConfigError::DuplicateQueue(
+                "eth0".to_string(),
+                1
+            ))
+        );
+    }
+}
@@ -2,7 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +15,9 @@ use crate::devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBacke
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
+/// Lowest CID a guest can use: the lower ones stand for the hypervisor and the host.
+pub const MIN_GUEST_CID: u32 = 3;
+
 /// Errors associated with `NetworkInterfaceConfig`.
 #[derive(Debug, derive_more::From, thiserror::Error)]
 pub enum VsockConfigError {
@@ -19,6 +27,104 @@ pub enum VsockConfigError {
     /// Failed to create the vsock device.
     #[error("Cannot create vsock device: {0:?}")]
     CreateVsockDevice(VsockError),
+    /// Failed to bind the host-side Unix socket of the vsock device.
+    #[from(ignore)]
+    #[error("Cannot bind the vsock device: {0:?}")]
+    BindVsockBackend(VsockUnixBackendError),
+    /// There is no vsock device.
+    #[error("No vsock device.")]
+    DeviceNotFound,
+    /// The guest CID is one of the reserved CIDs.
+    #[from(ignore)]
+    #[error("Invalid guest CID {0}: the CIDs 0 to 2 and 4294967295 are reserved.")]
+    InvalidGuestCid(u32),
+    /// The guest CID is to be assigned, but there is no lock directory to find a free one in.
+    #[from(ignore)]
+    #[error("The guest CID can only be assigned along with `cid_lock_dir`.")]
+    CidWithoutLockDir,
+    /// Failed to lock the guest CID in the lock directory.
+    #[from(ignore)]
+    #[error("Cannot lock the guest CID in {0:?}: {1}")]
+    CidLock(PathBuf, io::Error),
+    /// The guest CID is locked by another microVM.
+    #[from(ignore)]
+    #[error("The guest CID {0} is already used by another microVM.")]
+    CidInUse(u32),
+    /// All the guest CIDs are locked by other microVMs.
+    #[from(ignore)]
+    #[error("No free guest CID is left.")]
+    NoFreeCid,
+    /// The maximum number of connections is out of range.
+    #[from(ignore)]
+    #[error("Invalid maximum number of connections {0}: it must be between 1 and 1023.")]
+    InvalidMaxConnections(u32),
+    /// The idle timeout is zero.
+    #[from(ignore)]
+    #[error("The idle timeout of the vsock connections cannot be zero.")]
+    InvalidIdleTimeout,
+}
+
+/// Returns an error if `cid` is reserved.
+pub(crate) fn check_guest_cid(cid: u32) -> Result<(), VsockConfigError> {
+    if cid < MIN_GUEST_CID || cid == u32::MAX {
+        return Err(VsockConfigError::InvalidGuestCid(cid));
+    }
+    Ok(())
+}
+
+/// Lock taken on a guest CID in a directory shared by the microVMs of the host, so that two
+/// microVMs cannot use the same CID. The lock is released when it is dropped, or when the
+/// process exits.
+#[derive(Debug)]
+pub struct CidLock {
+    cid: u32,
+    dir: String,
+    _file: File,
+}
+
+impl CidLock {
+    /// Locks `cid` in `dir`, or the lowest CID not locked yet when `cid` is `None`.
+    pub fn acquire(dir: &str, cid: Option<u32>) -> Result<Self, VsockConfigError> {
+        if let Some(cid) = cid {
+            check_guest_cid(cid)?;
+            return Self::try_acquire(dir, cid)?.ok_or(VsockConfigError::CidInUse(cid));
+        }
+        for cid in MIN_GUEST_CID..u32::MAX {
+            if let Some(lock) = Self::try_acquire(dir, cid)? {
+                return Ok(lock);
+            }
+        }
+        Err(VsockConfigError::NoFreeCid)
+    }
+
+    // Locks `cid` in `dir`, unless another process holds its lock.
+    fn try_acquire(dir: &str, cid: u32) -> Result<Option<Self>, VsockConfigError> {
+        let lock_err = |err| VsockConfigError::CidLock(PathBuf::from(dir), err);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(Path::new(dir).join(format!("{}.lock", cid)))
+            .map_err(lock_err)?;
+        // SAFETY: The file descriptor is valid, since `file` is open.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                return Ok(None);
+            }
+            return Err(lock_err(err));
+        }
+        Ok(Some(Self {
+            cid,
+            dir: dir.to_string(),
+            _file: file,
+        }))
+    }
+
+    /// Returns the locked CID.
+    pub fn cid(&self) -> u32 {
+        self.cid
+    }
 }
 
 /// This struct represents the strongly typed equivalent of the json body
@@ -30,8 +136,39 @@ pub struct VsockDeviceConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// ID of the vsock device.
     pub vsock_id: Option<String>,
-    /// A 32-bit Context Identifier (CID) used to identify the guest.
-    pub guest_cid: u32,
+    /// A 32-bit Context Identifier (CID) used to identify the guest. When not set, the lowest
+    /// CID free in `cid_lock_dir` is assigned.
+    #[serde(default)]
+    pub guest_cid: Option<u32>,
+    /// Path to local unix socket.
+    pub uds_path: String,
+    /// Port on which the guest can read the host clock, served by Firecracker itself.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_port: Option<u32>,
+    /// Directory shared by the microVMs of the host, in which the guest CID is locked.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cid_lock_dir: Option<String>,
+    /// Backlog of the host-side Unix socket, for the host connections not accepted yet.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_backlog: Option<u32>,
+    /// Maximum number of connections open at once, host and guest ones alike.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// Time, in seconds, after which a connection through which no data went is closed.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_s: Option<u64>,
+}
+
+/// The data fed into a vsock update request, which binds the host-side Unix socket of a vsock
+/// device restored from a snapshot with a deferred socket.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockDeviceUpdateConfig {
     /// Path to local unix socket.
     pub uds_path: String,
 }
@@ -45,10 +182,18 @@ struct VsockAndUnixPath {
 impl From<&VsockAndUnixPath> for VsockDeviceConfig {
     fn from(vsock: &VsockAndUnixPath) -> Self {
         let vsock_lock = vsock.vsock.lock().unwrap();
+        let backend = vsock_lock.backend();
         VsockDeviceConfig {
             vsock_id: None,
-            guest_cid: u32::try_from(vsock_lock.cid()).unwrap(),
+            guest_cid: Some(u32::try_from(vsock_lock.cid()).unwrap()),
             uds_path: vsock.uds_path.clone(),
+            clock_port: backend.clock_port(),
+            cid_lock_dir: None,
+            listen_backlog: backend.listen_backlog(),
+            max_connections: Some(backend.max_connections())
+                .filter(|max| *max != VsockUnixBackend::MAX_CONNECTIONS)
+                .map(|max| max as u32),
+            idle_timeout_s: backend.idle_timeout().map(|timeout| timeout.as_secs()),
         }
     }
 }
@@ -57,12 +202,21 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
 #[derive(Debug, Default)]
 pub struct VsockBuilder {
     inner: Option<VsockAndUnixPath>,
+    cid_lock: Option<CidLock>,
 }
 
 impl VsockBuilder {
     /// Creates an empty Vsock with Unix backend Store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self {
+            inner: None,
+            cid_lock: None,
+        }
+    }
+
+    /// Keeps the lock taken on the CID of the device until the store is dropped.
+    pub fn set_cid_lock(&mut self, cid_lock: CidLock) {
+        self.cid_lock = Some(cid_lock);
     }
 
     /// Inserts an existing vsock device.
@@ -80,15 +234,43 @@ impl VsockBuilder {
 
     /// Inserts a Unix backend Vsock in the store.
     /// If an entry already exists, it will overwrite it.
-    pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<(), VsockConfigError> {
+    pub fn insert(&mut self, mut cfg: VsockDeviceConfig) -> Result<(), VsockConfigError> {
         // Make sure to drop the old one and remove the socket before creating a new one.
         if let Some(existing) = self.inner.take() {
             std::fs::remove_file(existing.uds_path).map_err(VsockUnixBackendError::UnixBind)?;
         }
+        // The CID of the old one can be locked again by the new one.
+        self.cid_lock = None;
+        let cid_lock = match &cfg.cid_lock_dir {
+            Some(dir) => {
+                let cid_lock = CidLock::acquire(dir, cfg.guest_cid)?;
+                cfg.guest_cid = Some(cid_lock.cid());
+                Some(cid_lock)
+            }
+            None => None,
+        };
         self.inner = Some(VsockAndUnixPath {
             uds_path: cfg.uds_path.clone(),
             vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
         });
+        self.cid_lock = cid_lock;
+        Ok(())
+    }
+
+    /// Binds the host-side Unix socket of a vsock device restored without one.
+    pub fn update(&mut self, update: VsockDeviceUpdateConfig) -> Result<(), VsockConfigError> {
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or(VsockConfigError::DeviceNotFound)?;
+        inner
+            .vsock
+            .lock()
+            .expect("Poisoned lock")
+            .backend_mut()
+            .bind(update.uds_path.clone())
+            .map_err(VsockConfigError::BindVsockBackend)?;
+        inner.uds_path = update.uds_path;
         Ok(())
     }
 
@@ -101,19 +283,41 @@ impl VsockBuilder {
     pub fn create_unixsock_vsock(
         cfg: VsockDeviceConfig,
     ) -> Result<Vsock<VsockUnixBackend>, VsockConfigError> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)?;
+        let guest_cid = cfg.guest_cid.ok_or(VsockConfigError::CidWithoutLockDir)?;
+        check_guest_cid(guest_cid)?;
+        if let Some(max) = cfg.max_connections {
+            if max == 0 || max as usize > VsockUnixBackend::MAX_CONNECTIONS {
+                return Err(VsockConfigError::InvalidMaxConnections(max));
+            }
+        }
+        if cfg.idle_timeout_s == Some(0) {
+            return Err(VsockConfigError::InvalidIdleTimeout);
+        }
+        let mut backend = VsockUnixBackend::new_unbound(u64::from(guest_cid))?;
+        // The backlog is set before binding, so that no host connection is queued beyond it.
+        backend.set_listen_backlog(cfg.listen_backlog)?;
+        backend.bind(cfg.uds_path)?;
+        backend.set_clock_port(cfg.clock_port);
+        if let Some(max) = cfg.max_connections {
+            backend.set_max_connections(max as usize);
+        }
+        backend.set_idle_timeout(cfg.idle_timeout_s.map(Duration::from_secs));
 
-        Vsock::new(u64::from(cfg.guest_cid), backend).map_err(VsockConfigError::CreateVsockDevice)
+        Vsock::new(u64::from(guest_cid), backend).map_err(VsockConfigError::CreateVsockDevice)
     }
 
     /// Returns the structure used to configure the vsock device.
     pub fn config(&self) -> Option<VsockDeviceConfig> {
-        self.inner.as_ref().map(VsockDeviceConfig::from)
+        self.inner.as_ref().map(|inner| VsockDeviceConfig {
+            cid_lock_dir: self.cid_lock.as_ref().map(|cid_lock| cid_lock.dir.clone()),
+            ..VsockDeviceConfig::from(inner)
+        })
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use utils::tempdir::TempDir;
     use utils::tempfile::TempFile;
 
     use super::*;
@@ -122,8 +326,13 @@ pub(crate) mod tests {
     pub(crate) fn default_config(tmp_sock_file: &TempFile) -> VsockDeviceConfig {
         VsockDeviceConfig {
             vsock_id: None,
-            guest_cid: 3,
+            guest_cid: Some(3),
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            clock_port: None,
+            cid_lock_dir: None,
+            listen_backlog: None,
+            max_connections: None,
+            idle_timeout_s: None,
         }
     }
 
@@ -146,8 +355,8 @@ pub(crate) mod tests {
         let vsock = store.get().unwrap();
         assert_eq!(vsock.lock().unwrap().id(), VSOCK_DEV_ID);
 
-        let new_cid = vsock_config.guest_cid + 1;
-        vsock_config.guest_cid = new_cid;
+        let new_cid = vsock_config.guest_cid.unwrap() + 1;
+        vsock_config.guest_cid = Some(new_cid);
         store.insert(vsock_config).unwrap();
         let vsock = store.get().unwrap();
         assert_eq!(vsock.lock().unwrap().cid(), u64::from(new_cid));
@@ -166,6 +375,103 @@ pub(crate) mod tests {
         assert_eq!(config.unwrap(), vsock_config);
     }
 
+    #[test]
+    fn test_vsock_clock_port() {
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.clock_port = Some(123);
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+
+        let vsock = vsock_builder.get().unwrap();
+        assert_eq!(vsock.lock().unwrap().backend().clock_port(), Some(123));
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
+    }
+
+    #[test]
+    fn test_vsock_connection_limits() {
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+
+        vsock_config.max_connections = Some(0);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::InvalidMaxConnections(0))
+        ));
+        vsock_config.max_connections = Some(1024);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::InvalidMaxConnections(1024))
+        ));
+        vsock_config.max_connections = Some(16);
+        vsock_config.idle_timeout_s = Some(0);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::InvalidIdleTimeout)
+        ));
+
+        vsock_config.listen_backlog = Some(8);
+        vsock_config.idle_timeout_s = Some(300);
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        let vsock = vsock_builder.get().unwrap();
+        let vsock = vsock.lock().unwrap();
+        assert_eq!(vsock.backend().listen_backlog(), Some(8));
+        assert_eq!(vsock.backend().max_connections(), 16);
+        assert_eq!(
+            vsock.backend().idle_timeout(),
+            Some(Duration::from_secs(300))
+        );
+        drop(vsock);
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
+    }
+
+    #[test]
+    fn test_vsock_guest_cid() {
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let lock_dir = TempDir::new().unwrap();
+        let lock_dir_path = lock_dir.as_path().to_str().unwrap().to_string();
+        let mut vsock_config = default_config(&tmp_sock_file);
+
+        vsock_config.guest_cid = Some(2);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::InvalidGuestCid(2))
+        ));
+        vsock_config.guest_cid = None;
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::CidWithoutLockDir)
+        ));
+
+        // The lowest free CID is assigned, and reported by the configuration.
+        vsock_config.cid_lock_dir = Some(lock_dir_path.clone());
+        let other_vm_lock = CidLock::acquire(&lock_dir_path, Some(MIN_GUEST_CID)).unwrap();
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        let config = vsock_builder.config().unwrap();
+        assert_eq!(config.guest_cid, Some(MIN_GUEST_CID + 1));
+        assert_eq!(config.cid_lock_dir, Some(lock_dir_path.clone()));
+
+        // The CIDs locked by other microVMs cannot be used.
+        vsock_config.guest_cid = Some(MIN_GUEST_CID);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::CidInUse(MIN_GUEST_CID))
+        ));
+        drop(other_vm_lock);
+        vsock_builder.insert(vsock_config).unwrap();
+        assert_eq!(
+            vsock_builder.config().unwrap().guest_cid,
+            Some(MIN_GUEST_CID)
+        );
+        // The CID of the replaced device is released.
+        CidLock::acquire(&lock_dir_path, Some(MIN_GUEST_CID + 1)).unwrap();
+    }
+
     #[test]
     fn test_set_device() {
         let mut vsock_builder = VsockBuilder::new();
@@ -185,4 +491,32 @@ pub(crate) mod tests {
             tmp_sock_file.as_path().to_str().unwrap().to_string()
         )
     }
+
+    #[test]
+    fn test_vsock_update() {
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let update = VsockDeviceUpdateConfig {
+            uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+        };
+        assert!(matches!(
+            vsock_builder.update(update.clone()),
+            Err(VsockConfigError::DeviceNotFound)
+        ));
+
+        let vsock = Vsock::new(3, VsockUnixBackend::new_unbound(3).unwrap()).unwrap();
+        vsock_builder.set_device(Arc::new(Mutex::new(vsock)));
+        assert_eq!(vsock_builder.config().unwrap().uds_path, "");
+        vsock_builder.update(update.clone()).unwrap();
+        assert_eq!(vsock_builder.config().unwrap().uds_path, update.uds_path);
+
+        // The socket can only be bound once.
+        assert!(matches!(
+            vsock_builder.update(update),
+            Err(VsockConfigError::BindVsockBackend(
+                VsockUnixBackendError::AlreadyBound
+            ))
+        ));
+    }
 }
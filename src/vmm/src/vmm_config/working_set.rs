@@ -0,0 +1,60 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the working set estimation configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum WorkingSetConfigError {
+    /// The sampling interval is zero.
+    #[error("The sampling interval must be at least 1 second.")]
+    NullInterval,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from working set estimation related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkingSetConfig {
+    /// Interval, in seconds, over which the pages written by the guest are counted.
+    pub sample_interval_s: u64,
+}
+
+impl WorkingSetConfig {
+    /// Checks that the configuration is valid.
+    pub fn validate(&self) -> Result<(), WorkingSetConfigError> {
+        if self.sample_interval_s == 0 {
+            return Err(WorkingSetConfigError::NullInterval);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_working_set_config() {
+        let config: WorkingSetConfig =
+            serde_json::from_str(r#"{"sample_interval_s": 30}"#).unwrap();
+        assert_eq!(
+            config,
+            WorkingSetConfig {
+                sample_interval_s: 30
+            }
+        );
+        config.validate().unwrap();
+
+        assert!(serde_json::from_str::<WorkingSetConfig>(r#"{}"#).is_err());
+        assert!(
+            serde_json::from_str::<WorkingSetConfig>(r#"{"sample_interval_s": 30, "foo": 1}"#)
+                .is_err()
+        );
+
+        let config = WorkingSetConfig {
+            sample_interval_s: 0,
+        };
+        assert_eq!(config.validate(), Err(WorkingSetConfigError::NullInterval));
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracking of the guest pages written by the vCPUs through the KVM dirty rings.
+//!
+//! Collecting the pages from the KVM dirty bitmap costs a pass over the bitmap of the whole guest
+//! memory. With the dirty rings, KVM pushes each page written by a vCPU to a ring shared with
+//! Firecracker, so collecting them only costs a pass over the written pages.
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::{fmt, io, mem, ptr};
+
+use kvm_bindings::kvm_enable_cap;
+use kvm_ioctls::{VcpuFd, VmFd};
+use utils::errno;
+use utils::ioctl::{ioctl, ioctl_with_val};
+use utils::vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use utils::{get_page_size, ioctl_io_nr, ioctl_ioc_nr};
+
+use crate::DirtyBitmap;
+
+// Definitions missing from the KVM bindings, see `include/uapi/linux/kvm.h`.
+const KVMIO: u32 = 0xAE;
+const KVM_CAP_DIRTY_LOG_RING: u32 = 192;
+const KVM_DIRTY_LOG_PAGE_OFFSET: i64 = 64;
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+/// Exit reason of a vCPU whose dirty ring is full.
+pub const KVM_EXIT_DIRTY_RING_FULL: u32 = 31;
+
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+ioctl_io_nr!(KVM_RESET_DIRTY_RINGS, KVMIO, 0xc7);
+
+// Number of entries of the ring of each vCPU, unless the host supports less.
+const DIRTY_RING_ENTRIES: usize = 1 << 14;
+
+// An entry of a dirty ring, `struct kvm_dirty_gfn`.
+#[repr(C)]
+#[derive(Debug)]
+struct KvmDirtyGfn {
+    flags: u32,
+    slot: u32,
+    offset: u64,
+}
+
+/// Enables the dirty rings on `vm_fd` if the host supports them, before any vCPU is created.
+///
+/// Returns the number of entries of each ring, or `None` if the dirty bitmap is used instead.
+pub fn enable_dirty_rings(vm_fd: &VmFd) -> Result<Option<usize>, errno::Error> {
+    // SAFETY: Safe because the ioctl only reads the capability number.
+    let max_bytes = unsafe {
+        ioctl_with_val(
+            vm_fd,
+            KVM_CHECK_EXTENSION(),
+            libc::c_ulong::from(KVM_CAP_DIRTY_LOG_RING),
+        )
+    };
+    let Ok(max_bytes) = usize::try_from(max_bytes) else {
+        return Ok(None);
+    };
+    let entries = std::cmp::min(
+        DIRTY_RING_ENTRIES,
+        max_bytes / mem::size_of::<KvmDirtyGfn>(),
+    );
+    if entries == 0 {
+        return Ok(None);
+    }
+
+    let mut cap = kvm_enable_cap {
+        cap: KVM_CAP_DIRTY_LOG_RING,
+        ..Default::default()
+    };
+    cap.args[0] = (entries * mem::size_of::<KvmDirtyGfn>()) as u64;
+    vm_fd.enable_cap(&cap)?;
+    Ok(Some(entries))
+}
+
+// The dirty ring of a vCPU, mapped from its file descriptor.
+#[derive(Debug)]
+struct VcpuRing {
+    gfns: *mut KvmDirtyGfn,
+    entries: usize,
+    // Index of the next entry to harvest.
+    next: usize,
+}
+
+// SAFETY: The mapping is only accessed through the lock of the `DirtyRings` owning the ring.
+unsafe impl Send for VcpuRing {}
+
+impl VcpuRing {
+    fn new(vcpu_fd: &VcpuFd, entries: usize, page_size: usize) -> io::Result<Self> {
+        // Safe to cast because the page size is small.
+        #[allow(clippy::cast_possible_wrap)]
+        let offset = KVM_DIRTY_LOG_PAGE_OFFSET * page_size as i64;
+        // SAFETY: Safe because the mapping is checked below, and KVM only allows a shared mapping
+        // of the ring, which is as large as requested when enabling the rings.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                entries * mem::size_of::<KvmDirtyGfn>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VcpuRing {
+            gfns: addr.cast(),
+            entries,
+            next: 0,
+        })
+    }
+
+    // Moves the entries pushed by KVM to `bitmap`, and marks them for reset.
+    fn harvest(&mut self, bitmap: &mut DirtyBitmap) -> usize {
+        let mut count = 0;
+        loop {
+            // SAFETY: Safe because the index is within the mapping.
+            let gfn = unsafe { self.gfns.add(self.next % self.entries) };
+            // SAFETY: Safe because the flags are aligned, and KVM accesses them atomically as
+            // well.
+            let flags = unsafe { &*ptr::addr_of_mut!((*gfn).flags).cast::<AtomicU32>() };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                return count;
+            }
+
+            // SAFETY: Safe because KVM no longer writes to a dirty entry.
+            let (slot, offset) = unsafe { ((*gfn).slot, (*gfn).offset) };
+            // The upper half of the slot is the address space, always 0 outside of SMM.
+            let word = bitmap
+                .get_mut(&((slot & 0xffff) as usize))
+                .and_then(|region_bitmap| region_bitmap.get_mut((offset / 64) as usize));
+            if let Some(word) = word {
+                *word |= 1 << (offset % 64);
+            }
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+            self.next = self.next.wrapping_add(1);
+            count += 1;
+        }
+    }
+}
+
+impl Drop for VcpuRing {
+    fn drop(&mut self) {
+        // SAFETY: Safe because the ring was mapped with this address and size.
+        unsafe {
+            libc::munmap(
+                self.gfns.cast(),
+                self.entries * mem::size_of::<KvmDirtyGfn>(),
+            );
+        }
+    }
+}
+
+// Dirty rings, and the pages harvested from them since they were last taken.
+#[derive(Debug)]
+struct HarvestState {
+    rings: Vec<VcpuRing>,
+    bitmap: DirtyBitmap,
+}
+
+/// The dirty rings of all the vCPUs of a microVM.
+///
+/// The rings are harvested by the VMM thread when the dirty pages are collected, and by a vCPU
+/// thread when its ring is full.
+pub struct DirtyRings {
+    // Used to hand the harvested entries back to KVM.
+    vm_fd: File,
+    // Number of pages of each memory slot.
+    slot_pages: Vec<usize>,
+    state: Mutex<HarvestState>,
+}
+
+impl fmt::Debug for DirtyRings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirtyRings")
+            .field("slot_pages", &self.slot_pages)
+            .finish()
+    }
+}
+
+impl DirtyRings {
+    /// Maps the rings, of `entries` entries, of the vCPUs of a microVM with `guest_memory`.
+    pub fn new<'a>(
+        vm_fd: &VmFd,
+        vcpu_fds: impl Iterator<Item = &'a VcpuFd>,
+        entries: usize,
+        guest_memory: &GuestMemoryMmap,
+    ) -> io::Result<Self> {
+        let page_size = get_page_size()?;
+        let rings = vcpu_fds
+            .map(|vcpu_fd| VcpuRing::new(vcpu_fd, entries, page_size))
+            .collect::<io::Result<Vec<_>>>()?;
+        let slot_pages = guest_memory
+            .iter()
+            .map(|region| region.len() as usize / page_size)
+            .collect::<Vec<_>>();
+
+        // SAFETY: Safe because the duplicate of the descriptor is checked below.
+        let fd = unsafe { libc::dup(vm_fd.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: Safe because the descriptor is valid and owned by nothing else.
+        let vm_fd = unsafe { File::from_raw_fd(fd) };
+
+        Ok(DirtyRings {
+            vm_fd,
+            state: Mutex::new(HarvestState {
+                rings,
+                bitmap: empty_bitmap(&slot_pages),
+            }),
+            slot_pages,
+        })
+    }
+
+    /// Collects the pages pushed to the rings, and hands the rings back to KVM.
+    pub fn harvest(&self) -> Result<(), errno::Error> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        Self::harvest_locked(&mut state, &self.vm_fd)
+    }
+
+    /// Returns the pages written by the vCPUs since the last call.
+    pub fn take_dirty_bitmap(&self) -> Result<DirtyBitmap, errno::Error> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        Self::harvest_locked(&mut state, &self.vm_fd)?;
+        Ok(mem::replace(
+            &mut state.bitmap,
+            empty_bitmap(&self.slot_pages),
+        ))
+    }
+
+    fn harvest_locked(state: &mut HarvestState, vm_fd: &File) -> Result<(), errno::Error> {
+        let HarvestState { rings, bitmap } = state;
+        let count: usize = rings.iter_mut().map(|ring| ring.harvest(bitmap)).sum();
+        if count == 0 {
+            return Ok(());
+        }
+        // SAFETY: Safe because the ioctl takes no argument.
+        if unsafe { ioctl(vm_fd, KVM_RESET_DIRTY_RINGS()) } < 0 {
+            return Err(errno::Error::last());
+        }
+        Ok(())
+    }
+}
+
+fn empty_bitmap(slot_pages: &[usize]) -> DirtyBitmap {
+    slot_pages
+        .iter()
+        .enumerate()
+        .map(|(slot, pages)| (slot, vec![0; (pages + 63) / 64]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Maps an anonymous ring, written by the test instead of KVM.
+    fn anonymous_ring(entries: usize) -> VcpuRing {
+        // SAFETY: Safe because the mapping is checked below.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                entries * mem::size_of::<KvmDirtyGfn>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        VcpuRing {
+            gfns: addr.cast(),
+            entries,
+            next: 0,
+        }
+    }
+
+    fn push(ring: &VcpuRing, index: usize, slot: u32, offset: u64) {
+        // SAFETY: Safe because the index is within the mapping.
+        unsafe {
+            ring.gfns.add(index).write(KvmDirtyGfn {
+                flags: KVM_DIRTY_GFN_F_DIRTY,
+                slot,
+                offset,
+            })
+        };
+    }
+
+    fn flags(ring: &VcpuRing, index: usize) -> u32 {
+        // SAFETY: Safe because the index is within the mapping.
+        unsafe { (*ring.gfns.add(index)).flags }
+    }
+
+    #[test]
+    fn test_harvest_ring() {
+        let mut ring = anonymous_ring(4);
+        let mut bitmap = empty_bitmap(&[128, 64]);
+
+        // Nothing was pushed yet.
+        assert_eq!(ring.harvest(&mut bitmap), 0);
+
+        push(&ring, 0, 0, 3);
+        push(&ring, 1, 0, 70);
+        push(&ring, 2, 1, 63);
+        // Entries out of the guest memory are ignored.
+        push(&ring, 3, 2, 0);
+        assert_eq!(ring.harvest(&mut bitmap), 4);
+        assert_eq!(bitmap[&0], vec![1 << 3, 1 << 6]);
+        assert_eq!(bitmap[&1], vec![1 << 63]);
+        assert!((0..4).all(|index| flags(&ring, index) == KVM_DIRTY_GFN_F_RESET));
+
+        // The harvest resumes after the last entry, and wraps around the ring.
+        push(&ring, 0, 1, 1);
+        assert_eq!(ring.harvest(&mut bitmap), 1);
+        assert_eq!(ring.next, 5);
+        assert_eq!(bitmap[&1], vec![(1 << 63) | (1 << 1)]);
+    }
+}
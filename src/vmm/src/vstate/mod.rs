@@ -1,6 +1,8 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+/// Module with the tracking of the dirty pages through the KVM dirty rings.
+pub(crate) mod dirty_ring;
 /// Module with Vcpu implementation.
 pub mod vcpu;
 /// Module with Vm implementation.
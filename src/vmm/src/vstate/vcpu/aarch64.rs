@@ -60,7 +60,7 @@ pub type KvmVcpuConfigureError = KvmVcpuError;
 #[derive(Debug)]
 pub struct KvmVcpu {
     /// Index of vcpu.
-    pub index: u8,
+    pub index: u16,
     /// KVM vcpu fd.
     pub fd: VcpuFd,
     /// Mmio bus.
@@ -76,7 +76,7 @@ impl KvmVcpu {
     ///
     /// * `index` - Represents the 0-based CPU index between [0, max vcpus).
     /// * `vm` - The vm to which this vcpu will get attached.
-    pub fn new(index: u8, vm: &Vm) -> Result<Self, KvmVcpuError> {
+    pub fn new(index: u16, vm: &Vm) -> Result<Self, KvmVcpuError> {
         let kvm_vcpu = vm
             .fd()
             .create_vcpu(index.into())
@@ -159,7 +159,7 @@ impl KvmVcpu {
     /// Creates default kvi struct based on vcpu index.
     pub fn default_kvi(
         vm_fd: &VmFd,
-        index: u8,
+        index: u16,
     ) -> Result<kvm_bindings::kvm_vcpu_init, KvmVcpuError> {
         let mut kvi: kvm_bindings::kvm_vcpu_init = kvm_bindings::kvm_vcpu_init::default();
         // This reads back the kernel's preferred target type.
@@ -269,6 +269,11 @@ pub struct VcpuState {
 }
 
 impl VcpuState {
+    /// Whether the guest took the vcpu offline through PSCI `CPU_OFF`.
+    pub fn is_offline(&self) -> bool {
+        self.mp_state.mp_state == kvm_bindings::KVM_MP_STATE_STOPPED
+    }
+
     fn default_old_regs(_: u16) -> Vec<Aarch64RegisterOld> {
         Vec::default()
     }
@@ -320,7 +325,7 @@ mod tests {
         let (mut vm, vm_mem) = setup_vm(mem_size);
         let mut vcpu = KvmVcpu::new(0, &vm).unwrap();
         vcpu.init(vm.fd(), &[]).unwrap();
-        vm.setup_irqchip(1).unwrap();
+        vm.setup_irqchip(1, false).unwrap();
 
         (vm, vcpu, vm_mem)
     }
@@ -377,7 +382,7 @@ mod tests {
     fn test_init_vcpu() {
         let (mut vm, _vm_mem) = setup_vm(0x1000);
         let mut vcpu = KvmVcpu::new(0, &vm).unwrap();
-        vm.setup_irqchip(1).unwrap();
+        vm.setup_irqchip(1, false).unwrap();
 
         // KVM_ARM_VCPU_PSCI_0_2 is set by default.
         // we check if we can remove it.
@@ -412,7 +417,7 @@ mod tests {
     fn test_vcpu_save_restore_state() {
         let (mut vm, _vm_mem) = setup_vm(0x1000);
         let mut vcpu = KvmVcpu::new(0, &vm).unwrap();
-        vm.setup_irqchip(1).unwrap();
+        vm.setup_irqchip(1, false).unwrap();
 
         // Calling KVM_GET_REGLIST before KVM_VCPU_INIT will result in error.
         let res = vcpu.save_state();
@@ -453,7 +458,7 @@ mod tests {
         // https://elixir.bootlin.com/linux/v5.10.176/source/arch/arm64/kvm/arm.c#L1165
         let (mut vm, _vm_mem) = setup_vm(0x1000);
         let vcpu = KvmVcpu::new(0, &vm).unwrap();
-        vm.setup_irqchip(1).unwrap();
+        vm.setup_irqchip(1, false).unwrap();
 
         assert!(vcpu.dump_cpu_config().is_err());
     }
@@ -463,7 +468,7 @@ mod tests {
         // Test `dump_cpu_config()` after `KVM_VCPU_INIT`.
         let (mut vm, _vm_mem) = setup_vm(0x1000);
         let mut vcpu = KvmVcpu::new(0, &vm).unwrap();
-        vm.setup_irqchip(1).unwrap();
+        vm.setup_irqchip(1, false).unwrap();
         vcpu.init(vm.fd(), &[]).unwrap();
 
         assert!(vcpu.dump_cpu_config().is_ok());
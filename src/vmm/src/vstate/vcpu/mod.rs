@@ -6,18 +6,20 @@
 // found in the THIRD-PARTY file.
 
 use std::cell::Cell;
-use std::sync::atomic::{fence, Ordering};
+use std::sync::atomic::{fence, AtomicI32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 #[cfg(test)]
 use std::sync::Mutex;
 use std::sync::{Arc, Barrier};
 use std::{fmt, io, thread};
 
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::{kvm_guest_debug, kvm_regs, kvm_sregs};
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
 use kvm_ioctls::VcpuExit;
 use libc::{c_int, c_void, siginfo_t};
 use log::{error, info};
-use logger::{IncMetric, METRICS};
+use logger::{IncMetric, VcpuExitMetrics, METRICS};
 use seccompiler::{BpfProgram, BpfProgramRef};
 use utils::errno;
 use utils::eventfd::EventFd;
@@ -25,6 +27,10 @@ use utils::signal::{register_signal_handler, sigrtmin, Killable};
 use utils::sm::StateMachine;
 
 use crate::cpu_config::templates::{CpuConfiguration, GuestConfigError};
+#[cfg(target_arch = "x86_64")]
+use crate::gdb::{StopNotifier, StopReason};
+use crate::stall_detector::VcpuProgress;
+use crate::vstate::dirty_ring::{DirtyRings, KVM_EXIT_DIRTY_RING_FULL};
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
 
@@ -76,7 +82,7 @@ pub enum VcpuError {
 #[derive(Debug)]
 pub struct VcpuConfig {
     /// Number of guest VCPUs.
-    pub vcpu_count: u8,
+    pub vcpu_count: u16,
     /// Enable simultaneous multithreading in the CPUID configuration.
     pub smt: bool,
     /// Configuration for vCPU
@@ -107,6 +113,14 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     /// The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    /// Hands the debug exits over to the GDB stub, if there is one.
+    #[cfg(target_arch = "x86_64")]
+    gdb_notifier: Option<StopNotifier>,
+    /// Counters of the KVM exits of this vcpu, if its index has some.
+    exit_metrics: Option<&'static VcpuExitMetrics>,
+    /// Harvested when the dirty ring of this vcpu is full, if the dirty pages are tracked with
+    /// dirty rings.
+    dirty_rings: Option<Arc<DirtyRings>>,
 
     /// Exit reason used to test run_emulation function.
     #[cfg(test)]
@@ -203,7 +217,7 @@ impl Vcpu {
     /// * `index` - Represents the 0-based CPU index between [0, max vcpus).
     /// * `vm` - The vm to which this vcpu will get attached.
     /// * `exit_evt` - An `EventFd` that will be written into when this vcpu exits.
-    pub fn new(index: u8, vm: &Vm, exit_evt: EventFd) -> Result<Self, VcpuError> {
+    pub fn new(index: u16, vm: &Vm, exit_evt: EventFd) -> Result<Self, VcpuError> {
         let (event_sender, event_receiver) = channel();
         let (response_sender, response_receiver) = channel();
         let kvm_vcpu = KvmVcpu::new(index, vm).unwrap();
@@ -215,6 +229,10 @@ impl Vcpu {
             response_receiver: Some(response_receiver),
             response_sender,
             kvm_vcpu,
+            #[cfg(target_arch = "x86_64")]
+            gdb_notifier: None,
+            exit_metrics: METRICS.vcpu.exits.register(index),
+            dirty_rings: None,
             #[cfg(test)]
             test_vcpu_exit_reason: Mutex::new(None),
         })
@@ -225,6 +243,17 @@ impl Vcpu {
         self.kvm_vcpu.mmio_bus = Some(mmio_bus);
     }
 
+    /// Sets the notifier through which this vcpu hands its debug exits over to the GDB stub.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_gdb_notifier(&mut self, notifier: StopNotifier) {
+        self.gdb_notifier = Some(notifier);
+    }
+
+    /// Sets the dirty rings to harvest when the ring of this vcpu is full.
+    pub(crate) fn set_dirty_rings(&mut self, dirty_rings: Arc<DirtyRings>) {
+        self.dirty_rings = Some(dirty_rings);
+    }
+
     /// Moves the vcpu to its own thread and constructs a VcpuHandle.
     /// The handle can be used to control the remote vcpu.
     pub fn start_threaded(
@@ -234,12 +263,17 @@ impl Vcpu {
     ) -> Result<VcpuHandle, StartThreadedError> {
         let event_sender = self.event_sender.take().expect("vCPU already started");
         let response_receiver = self.response_receiver.take().unwrap();
+        let tid = Arc::new(AtomicI32::new(0));
+        let thread_tid = tid.clone();
         let vcpu_thread = thread::Builder::new()
             .name(format!("fc_vcpu {}", self.kvm_vcpu.index))
             .spawn(move || {
                 let filter = &*seccomp_filter;
                 self.init_thread_local_data()
                     .expect("Cannot cleanly initialize vcpu TLS.");
+                // SAFETY: Safe because gettid() takes no arguments and cannot fail.
+                let current_tid = unsafe { libc::syscall(libc::SYS_gettid) };
+                thread_tid.store(current_tid as libc::pid_t, Ordering::Release);
                 // Synchronization to make sure thread local data is initialized.
                 barrier.wait();
                 self.run(filter);
@@ -249,6 +283,7 @@ impl Vcpu {
             event_sender,
             response_receiver,
             vcpu_thread,
+            tid,
         ))
     }
 
@@ -261,7 +296,7 @@ impl Vcpu {
         // Load seccomp filters for this vCPU thread.
         // Execution panics if filters cannot be loaded, use --no-seccomp if skipping filters
         // altogether is the desired behaviour.
-        if let Err(err) = seccompiler::apply_filter(seccomp_filter) {
+        if let Err(err) = crate::seccomp_filters::install_filter("vcpu", seccomp_filter) {
             panic!(
                 "Failed to set the requested seccomp filters on vCPU {}: Error: {}",
                 self.kvm_vcpu.index, err
@@ -287,6 +322,20 @@ impl Vcpu {
                 // - the other vCPUs won't ever exit out of `KVM_RUN`, but they won't consume CPU.
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
                 Ok(VcpuEmulation::Stopped) => return self.exit(FcExitCode::Ok),
+                // The guest hit a breakpoint or finished a single step: wait for the debugger
+                // in the `Paused` state.
+                #[cfg(target_arch = "x86_64")]
+                Ok(VcpuEmulation::DebugExit(reason)) => match &self.gdb_notifier {
+                    Some(notifier) => {
+                        notifier.notify(self.kvm_vcpu.index, reason);
+                        return StateMachine::next(Self::paused);
+                    }
+                    None => {
+                        METRICS.vcpu.failures.inc();
+                        error!("Received a debug exit without a debugger: {:?}", reason);
+                        return self.exit(FcExitCode::GenericError);
+                    }
+                },
                 // Emulation errors lead to vCPU exit.
                 Err(_) => return self.exit(FcExitCode::GenericError),
             }
@@ -331,6 +380,23 @@ impl Vcpu {
                     )))
                     .expect("failed to send save not allowed status");
             }
+            // The debugger only inspects and updates paused Vcpus.
+            #[cfg(target_arch = "x86_64")]
+            Ok(
+                VcpuEvent::GetRegs
+                | VcpuEvent::SetRegs(_)
+                | VcpuEvent::SetGuestDebug(_)
+                | VcpuEvent::InjectBreakpoint,
+            ) => {
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(String::from(
+                        "guest debugging is unavailable while running",
+                    )))
+                    .expect("failed to send debug not allowed status");
+            }
+            Ok(VcpuEvent::SampleProgress(progress, capture)) => {
+                self.sample_progress(&progress, capture);
+            }
             Ok(VcpuEvent::Finish) => return StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
@@ -395,6 +461,34 @@ impl Vcpu {
 
                 StateMachine::next(Self::paused)
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::GetRegs) => {
+                let response = match self.kvm_vcpu.get_regs_and_sregs() {
+                    Ok((regs, sregs)) => VcpuResponse::Regs(Box::new(regs), Box::new(sregs)),
+                    Err(err) => VcpuResponse::Error(VcpuError::VcpuResponse(err)),
+                };
+                self.response_sender
+                    .send(response)
+                    .expect("vcpu channel unexpectedly closed");
+                StateMachine::next(Self::paused)
+            }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SetRegs(regs)) => {
+                self.send_update_response(self.kvm_vcpu.set_regs(&regs));
+                StateMachine::next(Self::paused)
+            }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SetGuestDebug(debug)) => {
+                self.send_update_response(self.kvm_vcpu.set_guest_debug(&debug));
+                StateMachine::next(Self::paused)
+            }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::InjectBreakpoint) => {
+                self.send_update_response(self.kvm_vcpu.inject_breakpoint());
+                StateMachine::next(Self::paused)
+            }
+            // Paused Vcpus are not watched by the stall detector.
+            Ok(VcpuEvent::SampleProgress(..)) => StateMachine::next(Self::paused),
             Ok(VcpuEvent::Finish) => StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(_) => {
@@ -404,6 +498,36 @@ impl Vcpu {
         }
     }
 
+    // Reports the outcome of an event updating the state of a paused Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    fn send_update_response(&self, result: Result<(), KvmVcpuError>) {
+        let response = match result {
+            Ok(()) => VcpuResponse::Updated,
+            Err(err) => VcpuResponse::Error(VcpuError::VcpuResponse(err)),
+        };
+        self.response_sender
+            .send(response)
+            .expect("vcpu channel unexpectedly closed");
+    }
+
+    // Records a progress sample of a running Vcpu for the stall detector.
+    fn sample_progress(&self, progress: &VcpuProgress, capture: bool) {
+        #[cfg(target_arch = "x86_64")]
+        match self.kvm_vcpu.sample_progress(capture) {
+            Ok((interrupts_disabled, registers)) => progress.record(interrupts_disabled, registers),
+            Err(err) => {
+                error!("Failed to sample the progress of vcpu: {}", err);
+                progress.record(false, None);
+            }
+        }
+        // Only the responsiveness of the Vcpu is checked on aarch64.
+        #[cfg(target_arch = "aarch64")]
+        {
+            let _ = capture;
+            progress.record(false, None);
+        }
+    }
+
     // Transition to the exited state and finish on command.
     fn exit(&mut self, exit_code: FcExitCode) -> StateMachine<Self> {
         // To avoid cycles, all teardown paths take the following route:
@@ -441,6 +565,29 @@ impl Vcpu {
         StateMachine::finish()
     }
 
+    // Counts the exit, or the interruption, of `KVM_RUN` in the metrics of this Vcpu.
+    fn count_exit(&self, exit: &Result<VcpuExit, errno::Error>) {
+        let Some(metrics) = self.exit_metrics else {
+            return;
+        };
+        let counter = match exit {
+            Ok(VcpuExit::IoIn(..)) => &metrics.io_in,
+            Ok(VcpuExit::IoOut(..)) => &metrics.io_out,
+            Ok(VcpuExit::MmioRead(..)) => &metrics.mmio_read,
+            Ok(VcpuExit::MmioWrite(..)) => &metrics.mmio_write,
+            Ok(VcpuExit::Hlt) => &metrics.hlt,
+            Ok(VcpuExit::Shutdown) => &metrics.shutdown,
+            Ok(VcpuExit::SystemEvent(..)) => &metrics.system_event,
+            Ok(VcpuExit::Debug(_)) => &metrics.debug,
+            Ok(VcpuExit::FailEntry(..)) => &metrics.fail_entry,
+            Ok(VcpuExit::InternalError) => &metrics.internal_error,
+            Ok(_) => &metrics.other,
+            Err(err) if err.errno() == libc::EINTR => &metrics.interrupted,
+            Err(_) => return,
+        };
+        counter.inc();
+    }
+
     #[cfg(not(test))]
     /// Calls `KVM_RUN` with this [`Vcpu`]'s underlying file descriptor.
     ///
@@ -454,7 +601,9 @@ impl Vcpu {
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
     pub fn run_emulation(&self) -> Result<VcpuEmulation, VcpuError> {
-        match self.emulate() {
+        let exit = self.emulate();
+        self.count_exit(&exit);
+        match exit {
             Ok(run) => match run {
                 VcpuExit::MmioRead(addr, data) => {
                     if let Some(mmio_bus) = &self.kvm_vcpu.mmio_bus {
@@ -521,6 +670,17 @@ impl Vcpu {
                         )))
                     }
                 },
+                VcpuExit::Unsupported(KVM_EXIT_DIRTY_RING_FULL) => {
+                    // KVM does not run the vcpu again until its ring is harvested.
+                    if let Some(dirty_rings) = &self.dirty_rings {
+                        dirty_rings.harvest().map_err(|err| {
+                            METRICS.vcpu.failures.inc();
+                            error!("Failed to harvest the dirty rings: {}", err);
+                            VcpuError::FaultyKvmExit(format!("{}", err))
+                        })?;
+                    }
+                    Ok(VcpuEmulation::Handled)
+                }
                 arch_specific_reason => {
                     // run specific architecture emulation.
                     self.kvm_vcpu.run_arch_emulation(arch_specific_reason)
@@ -576,6 +736,21 @@ pub enum VcpuEvent {
     SaveState,
     /// Event to dump CPU configuration of a paused Vcpu.
     DumpCpuConfig,
+    /// Event to read the general purpose and special registers of a paused Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    GetRegs,
+    /// Event to write the general purpose registers of a paused Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    SetRegs(Box<kvm_regs>),
+    /// Event to set the guest debug state (single-stepping, breakpoints) of a paused Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    SetGuestDebug(Box<kvm_guest_debug>),
+    /// Event to inject back the breakpoint exception a paused Vcpu stopped on.
+    #[cfg(target_arch = "x86_64")]
+    InjectBreakpoint,
+    /// Event asking a running Vcpu to record a progress sample for the stall detector, and to
+    /// capture its registers if the flag is set.
+    SampleProgress(Arc<VcpuProgress>, bool),
 }
 
 /// List of responses that the Vcpu reports.
@@ -594,6 +769,12 @@ pub enum VcpuResponse {
     SavedState(Box<VcpuState>),
     /// Vcpu is in the state where CPU config is dumped.
     DumpedCpuConfig(Box<CpuConfiguration>),
+    /// Vcpu general purpose and special registers are read.
+    #[cfg(target_arch = "x86_64")]
+    Regs(Box<kvm_regs>, Box<kvm_sregs>),
+    /// Vcpu state is updated.
+    #[cfg(target_arch = "x86_64")]
+    Updated,
 }
 
 impl fmt::Debug for VcpuResponse {
@@ -607,6 +788,10 @@ impl fmt::Debug for VcpuResponse {
             Error(ref err) => write!(f, "VcpuResponse::Error({:?})", err),
             NotAllowed(ref reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
             DumpedCpuConfig(_) => write!(f, "VcpuResponse::DumpedCpuConfig"),
+            #[cfg(target_arch = "x86_64")]
+            Regs(..) => write!(f, "VcpuResponse::Regs"),
+            #[cfg(target_arch = "x86_64")]
+            Updated => write!(f, "VcpuResponse::Updated"),
         }
     }
 }
@@ -619,6 +804,8 @@ pub struct VcpuHandle {
     // Rust JoinHandles have to be wrapped in Option if you ever plan on 'join()'ing them.
     // We want to be able to join these threads in tests.
     vcpu_thread: Option<thread::JoinHandle<()>>,
+    // Kernel thread id of the vcpu thread, set by the thread before it starts running.
+    tid: Arc<AtomicI32>,
 }
 
 /// Error type for [`VcpuHandle::send_event`].
@@ -633,17 +820,36 @@ impl VcpuHandle {
     /// + `event_sender`: [`Sender`] to communicate [`VcpuEvent`] to control the vcpu.
     /// + `response_received`: [`Received`] from which the vcpu's responses can be read.
     /// + `vcpu_thread`: A [`JoinHandle`] for the vcpu thread.
+    /// + `tid`: The kernel thread id of the vcpu thread, once it is initialized.
     pub fn new(
         event_sender: Sender<VcpuEvent>,
         response_receiver: Receiver<VcpuResponse>,
         vcpu_thread: thread::JoinHandle<()>,
+        tid: Arc<AtomicI32>,
     ) -> Self {
         Self {
             event_sender,
             response_receiver,
             vcpu_thread: Some(vcpu_thread),
+            tid,
         }
     }
+
+    /// Returns the kernel thread id of the vcpu thread.
+    ///
+    /// This is only meaningful once the vcpu thread has initialized its thread local data, which
+    /// [`crate::Vmm::start_vcpus`] waits for.
+    pub fn tid(&self) -> libc::pid_t {
+        self.tid.load(Ordering::Acquire)
+    }
+
+    /// Whether the vcpu thread is still running.
+    pub fn is_running(&self) -> bool {
+        self.vcpu_thread
+            .as_ref()
+            .map_or(false, |thread| !thread.is_finished())
+    }
+
     /// Sends event to vCPU.
     ///
     /// # Errors
@@ -692,6 +898,9 @@ pub enum VcpuEmulation {
     Interrupted,
     /// Stopped.
     Stopped,
+    /// Stopped on a debug exception, for the debugger to handle.
+    #[cfg(target_arch = "x86_64")]
+    DebugExit(StopReason),
 }
 
 #[cfg(test)]
@@ -725,6 +934,29 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_count_exit() {
+        let (_vm, mut vcpu, _vm_mem) = setup_vcpu(0x1000);
+        // The global counters are shared with the other tests.
+        let metrics: &'static VcpuExitMetrics = Box::leak(Box::default());
+        vcpu.exit_metrics = Some(metrics);
+
+        for exit in [
+            Ok(VcpuExit::Hlt),
+            Ok(VcpuExit::Unknown),
+            Ok(VcpuExit::Unknown),
+            Err(errno::Error::new(libc::EINTR)),
+            Err(errno::Error::new(libc::EAGAIN)),
+        ] {
+            *(vcpu.test_vcpu_exit_reason.lock().unwrap()) = Some(exit);
+            let _ = vcpu.run_emulation();
+        }
+        assert_eq!(metrics.hlt.count(), 1);
+        assert_eq!(metrics.other.count(), 2);
+        assert_eq!(metrics.interrupted.count(), 1);
+        assert_eq!(metrics.io_in.count(), 0);
+    }
+
     #[test]
     fn test_run_emulation() {
         let (_vm, mut vcpu, _vm_mem) = setup_vcpu(0x1000);
@@ -856,9 +1088,13 @@ pub mod tests {
             match self {
                 Paused | Resumed | Exited(_) => (),
                 Error(_) | NotAllowed(_) | SavedState(_) | DumpedCpuConfig(_) => (),
+                #[cfg(target_arch = "x86_64")]
+                Regs(..) | Updated => (),
             };
             match (self, other) {
                 (Paused, Paused) | (Resumed, Resumed) => true,
+                #[cfg(target_arch = "x86_64")]
+                (Regs(..), Regs(..)) | (Updated, Updated) => true,
                 (Exited(code), Exited(other_code)) => code == other_code,
                 (NotAllowed(_), NotAllowed(_))
                 | (SavedState(_), SavedState(_))
@@ -882,7 +1118,7 @@ pub mod tests {
         let vcpu = {
             let mut vcpu = Vcpu::new(1, &vm, exit_evt).unwrap();
             vcpu.kvm_vcpu.init(vm.fd(), &[]).unwrap();
-            vm.setup_irqchip(1).unwrap();
+            vm.setup_irqchip(1, false).unwrap();
             vcpu
         };
         #[cfg(target_arch = "x86_64")]
@@ -938,7 +1174,10 @@ pub mod tests {
             vcpu.kvm_vcpu
                 .configure(
                     &vm_mem,
-                    entry_addr,
+                    crate::arch::EntryPoint {
+                        entry_addr,
+                        protocol: crate::arch::BootProtocol::LinuxBoot,
+                    },
                     &VcpuConfig {
                         vcpu_count: 1,
                         smt: false,
@@ -971,6 +1210,9 @@ pub mod tests {
             .expect("failed to start vcpu");
         // Wait for vCPUs to initialize their TLS before moving forward.
         barrier.wait();
+        // The vcpu thread published its kernel thread id.
+        let task_path = format!("/proc/self/task/{}", vcpu_handle.tid());
+        assert!(std::path::Path::new(&task_path).exists());
 
         (vcpu_handle, vcpu_exit_evt)
     }
@@ -1144,6 +1386,85 @@ pub mod tests {
         vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_vcpu_debug_events() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+
+        // The debugger cannot inspect a running vcpu.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::GetRegs,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SetGuestDebug(Box::default()),
+            VcpuResponse::NotAllowed(String::new()),
+        );
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+
+        vcpu_handle
+            .send_event(VcpuEvent::GetRegs)
+            .expect("failed to send event to vcpu");
+        let mut regs = match vcpu_handle
+            .response_receiver()
+            .recv_timeout(RECV_TIMEOUT_SEC)
+            .expect("did not receive event response from vcpu")
+        {
+            VcpuResponse::Regs(regs, _) => regs,
+            _ => panic!("unexpected response"),
+        };
+        regs.rax = 0x1234;
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SetRegs(regs),
+            VcpuResponse::Updated,
+        );
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SetGuestDebug(Box::default()),
+            VcpuResponse::Updated,
+        );
+
+        vcpu_handle
+            .send_event(VcpuEvent::GetRegs)
+            .expect("failed to send event to vcpu");
+        match vcpu_handle
+            .response_receiver()
+            .recv_timeout(RECV_TIMEOUT_SEC)
+            .expect("did not receive event response from vcpu")
+        {
+            VcpuResponse::Regs(regs, _) => assert_eq!(regs.rax, 0x1234),
+            _ => panic!("unexpected response"),
+        };
+
+        vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
+    }
+
+    #[test]
+    fn test_vcpu_sample_progress() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+        let progress = Arc::new(VcpuProgress::default());
+
+        // Paused vcpus do not record samples.
+        vcpu_handle
+            .send_event(VcpuEvent::SampleProgress(progress.clone(), false))
+            .expect("failed to send event to vcpu");
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        assert_eq!(progress.samples(), 0);
+
+        // The events are handled in order, so the sample is recorded once the vcpu is paused.
+        vcpu_handle
+            .send_event(VcpuEvent::SampleProgress(progress.clone(), true))
+            .expect("failed to send event to vcpu");
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+        assert_eq!(progress.samples(), 1);
+
+        vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
+    }
+
     #[test]
     fn test_vcpu_dump_cpu_config() {
         let (vcpu_handle, _) = vcpu_configured_for_boot();
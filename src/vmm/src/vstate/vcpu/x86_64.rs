@@ -8,20 +8,23 @@
 use std::collections::{HashMap, HashSet};
 
 use kvm_bindings::{
-    kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs,
-    kvm_xsave, CpuId, Msrs, KVM_MAX_CPUID_ENTRIES, KVM_MAX_MSR_ENTRIES,
+    kvm_debugregs, kvm_guest_debug, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs,
+    kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, Msrs, KVM_MAX_CPUID_ENTRIES, KVM_MAX_MSR_ENTRIES,
+    KVM_MP_STATE_HALTED, KVM_MP_STATE_INIT_RECEIVED, KVM_MP_STATE_UNINITIALIZED,
 };
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use log::{error, warn};
 use logger::{IncMetric, METRICS};
-use utils::vm_memory::{Address, GuestAddress, GuestMemoryMmap};
+use utils::vm_memory::GuestMemoryMmap;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use crate::arch::x86_64::interrupts;
 use crate::arch::x86_64::msr::{create_boot_msr_entries, MsrError};
 use crate::arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecialRegistersError};
+use crate::arch::EntryPoint;
 use crate::cpu_config::x86_64::{cpuid, CpuConfiguration};
+use crate::gdb::StopReason;
 use crate::vstate::vcpu::{VcpuConfig, VcpuEmulation};
 use crate::vstate::vm::Vm;
 
@@ -31,6 +34,12 @@ use crate::vstate::vm::Vm;
 // https://bugzilla.redhat.com/show_bug.cgi?id=1839095
 const TSC_KHZ_TOL: f64 = 250.0 / 1_000_000.0;
 
+// Vector of the breakpoint exception (#BP), raised by `int3`.
+const BP_VECTOR: u8 = 3;
+
+// Interrupt enable flag of RFLAGS.
+const RFLAGS_IF: u64 = 1 << 9;
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum KvmVcpuError {
@@ -100,6 +109,9 @@ pub enum KvmVcpuError {
     /// Failed to set KVM vcpu debug regs.
     #[error("Failed to set KVM vcpu debug regs: {0}")]
     VcpuSetDebugRegs(kvm_ioctls::Error),
+    /// Failed to set KVM vcpu guest debug state.
+    #[error("Failed to set KVM vcpu guest debug state: {0}")]
+    VcpuSetGuestDebug(kvm_ioctls::Error),
     /// Failed to set KVM vcpu lapic.
     #[error("Failed to set KVM vcpu lapic: {0}")]
     VcpuSetLapic(kvm_ioctls::Error),
@@ -178,7 +190,7 @@ pub enum KvmVcpuConfigureError {
 #[derive(Debug)]
 pub struct KvmVcpu {
     /// Index of vcpu.
-    pub index: u8,
+    pub index: u16,
     /// KVM vcpu fd.
     pub fd: VcpuFd,
     /// Pio bus.
@@ -195,7 +207,7 @@ impl KvmVcpu {
     ///
     /// * `index` - Represents the 0-based CPU index between [0, max vcpus).
     /// * `vm` - The vm to which this vcpu will get attached.
-    pub fn new(index: u8, vm: &Vm) -> Result<Self, KvmVcpuError> {
+    pub fn new(index: u16, vm: &Vm) -> Result<Self, KvmVcpuError> {
         let kvm_vcpu = vm
             .fd()
             .create_vcpu(index.into())
@@ -215,13 +227,14 @@ impl KvmVcpu {
     /// # Arguments
     ///
     /// * `guest_mem` - The guest memory used by this microvm.
-    /// * `kernel_start_addr` - Offset from `guest_mem` at which the kernel starts.
+    /// * `kernel_entry_point` - Address at which the guest starts executing, along with the
+    ///   boot protocol it expects.
     /// * `vcpu_config` - The vCPU configuration.
     /// * `cpuid` - The capabilities exposed by this vCPU.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
-        kernel_start_addr: GuestAddress,
+        kernel_entry_point: EntryPoint,
         vcpu_config: &VcpuConfig,
     ) -> Result<(), KvmVcpuConfigureError> {
         let mut cpuid = vcpu_config.cpu_config.cpuid.clone();
@@ -281,9 +294,9 @@ impl KvmVcpu {
             .collect::<Vec<_>>();
 
         crate::arch::x86_64::msr::set_msrs(&self.fd, &kvm_msrs)?;
-        crate::arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value())?;
+        crate::arch::x86_64::regs::setup_regs(&self.fd, kernel_entry_point)?;
         crate::arch::x86_64::regs::setup_fpu(&self.fd)?;
-        crate::arch::x86_64::regs::setup_sregs(guest_mem, &self.fd)?;
+        crate::arch::x86_64::regs::setup_sregs(guest_mem, &self.fd, kernel_entry_point.protocol)?;
         crate::arch::x86_64::interrupts::set_lint(&self.fd)?;
 
         Ok(())
@@ -547,11 +560,91 @@ impl KvmVcpu {
         Ok(())
     }
 
+    /// Gets the general purpose and special registers.
+    pub fn get_regs_and_sregs(&self) -> Result<(kvm_regs, kvm_sregs), KvmVcpuError> {
+        let regs = self.fd.get_regs().map_err(KvmVcpuError::VcpuGetRegs)?;
+        let sregs = self.fd.get_sregs().map_err(KvmVcpuError::VcpuGetSregs)?;
+        Ok((regs, sregs))
+    }
+
+    /// Sets the general purpose registers.
+    pub fn set_regs(&self, regs: &kvm_regs) -> Result<(), KvmVcpuError> {
+        self.fd.set_regs(regs).map_err(KvmVcpuError::VcpuSetRegs)
+    }
+
+    /// Sets the guest debug state, which controls single-stepping and breakpoints.
+    pub fn set_guest_debug(&self, debug: &kvm_guest_debug) -> Result<(), KvmVcpuError> {
+        self.fd
+            .set_guest_debug(debug)
+            .map_err(KvmVcpuError::VcpuSetGuestDebug)
+    }
+
+    /// Injects back into the guest the breakpoint exception which caused the last debug exit.
+    ///
+    /// Used when the guest hits an `int3` which belongs to the guest itself rather than to the
+    /// debugger, e.g. while the kernel patches its own code.
+    pub fn inject_breakpoint(&self) -> Result<(), KvmVcpuError> {
+        let mut vcpu_events = self
+            .fd
+            .get_vcpu_events()
+            .map_err(KvmVcpuError::VcpuGetVcpuEvents)?;
+        vcpu_events.exception.injected = 1;
+        vcpu_events.exception.nr = BP_VECTOR;
+        vcpu_events.exception.has_error_code = 0;
+        self.fd
+            .set_vcpu_events(&vcpu_events)
+            .map_err(KvmVcpuError::VcpuSetVcpuEvents)
+    }
+
+    /// Samples the progress of the vCPU for the stall detector.
+    ///
+    /// Returns whether the guest runs with interrupts disabled and, when `capture` is set, a
+    /// description of the registers useful to locate where the guest is stuck.
+    pub fn sample_progress(&self, capture: bool) -> Result<(bool, Option<String>), KvmVcpuError> {
+        let regs = self.fd.get_regs().map_err(KvmVcpuError::VcpuGetRegs)?;
+        let interrupts_disabled = regs.rflags & RFLAGS_IF == 0;
+        if !capture {
+            return Ok((interrupts_disabled, None));
+        }
+
+        let sregs = self.fd.get_sregs().map_err(KvmVcpuError::VcpuGetSregs)?;
+        let registers = format!(
+            "rip={:#x} rsp={:#x} rbp={:#x} rflags={:#x} rax={:#x} rbx={:#x} rcx={:#x} \
+             rdx={:#x} rsi={:#x} rdi={:#x} r8={:#x} r9={:#x} r10={:#x} r11={:#x} r12={:#x} \
+             r13={:#x} r14={:#x} r15={:#x} cs={:#x} cr0={:#x} cr2={:#x} cr3={:#x} cr4={:#x}",
+            regs.rip,
+            regs.rsp,
+            regs.rbp,
+            regs.rflags,
+            regs.rax,
+            regs.rbx,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            regs.r8,
+            regs.r9,
+            regs.r10,
+            regs.r11,
+            regs.r12,
+            regs.r13,
+            regs.r14,
+            regs.r15,
+            sregs.cs.selector,
+            sregs.cr0,
+            sregs.cr2,
+            sregs.cr3,
+            sregs.cr4,
+        );
+        Ok((interrupts_disabled, Some(registers)))
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
     pub fn run_arch_emulation(&self, exit: VcpuExit) -> Result<VcpuEmulation, super::VcpuError> {
         match exit {
+            VcpuExit::Debug(debug) => Ok(VcpuEmulation::DebugExit(StopReason::from(&debug))),
             VcpuExit::IoIn(addr, data) => {
                 if let Some(pio_bus) = &self.pio_bus {
                     pio_bus.read(u64::from(addr), data);
@@ -566,6 +659,10 @@ impl KvmVcpu {
                 }
                 Ok(VcpuEmulation::Handled)
             }
+            // With the split irqchip, the EOIs of level triggered interrupts are forwarded to the
+            // userspace IOAPIC. Its pins are only driven by edge triggered irqfds, so there is no
+            // remote IRR to clear.
+            VcpuExit::IoapicEoi(_) => Ok(VcpuEmulation::Handled),
             unexpected_exit => {
                 METRICS.vcpu.failures.inc();
                 // TODO: Are we sure we want to finish running a vcpu upon
@@ -613,7 +710,20 @@ pub struct VcpuState {
     pub tsc_khz: Option<u32>,
 }
 
+// Interrupt enable flag of RFLAGS.
+const X86_EFLAGS_IF: u64 = 1 << 9;
+
 impl VcpuState {
+    /// Whether the guest took the vcpu offline: it either waits for a startup IPI or is halted
+    /// with the interrupts disabled, as Linux leaves the CPUs it unplugs.
+    pub fn is_offline(&self) -> bool {
+        match self.mp_state.mp_state {
+            KVM_MP_STATE_UNINITIALIZED | KVM_MP_STATE_INIT_RECEIVED => true,
+            KVM_MP_STATE_HALTED => self.regs.rflags & X86_EFLAGS_IF == 0,
+            _ => false,
+        }
+    }
+
     fn default_tsc_khz(_: u16) -> Option<u32> {
         warn!("CPU TSC freq not found in snapshot");
         None
@@ -676,9 +786,11 @@ mod tests {
     use std::os::unix::io::AsRawFd;
 
     use kvm_ioctls::Cap;
+    use utils::vm_memory::GuestAddress;
 
     use super::*;
     use crate::arch::x86_64::cpu_model::CpuModel;
+    use crate::arch::BootProtocol;
     use crate::cpu_config::templates::{
         CpuConfiguration, CpuTemplateType, CustomCpuTemplate, GetCpuTemplate, GuestConfigError,
         StaticCpuTemplate,
@@ -749,7 +861,14 @@ mod tests {
 
         let vcpu_config = create_vcpu_config(&vm, &vcpu, &CustomCpuTemplate::default()).unwrap();
         assert_eq!(
-            vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config,),
+            vcpu.configure(
+                &vm_mem,
+                EntryPoint {
+                    entry_addr: GuestAddress(0),
+                    protocol: BootProtocol::LinuxBoot,
+                },
+                &vcpu_config,
+            ),
             Ok(())
         );
 
@@ -761,7 +880,10 @@ mod tests {
                     Ok(config) => vcpu
                         .configure(
                             &vm_mem,
-                            GuestAddress(crate::arch::get_kernel_start()),
+                            EntryPoint {
+                                entry_addr: GuestAddress(crate::arch::get_kernel_start()),
+                                protocol: BootProtocol::LinuxBoot,
+                            },
                             &config,
                         )
                         .is_ok(),
@@ -864,8 +986,15 @@ mod tests {
                 msrs: HashMap::new(),
             },
         };
-        vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config)
-            .unwrap();
+        vcpu.configure(
+            &vm_mem,
+            EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            },
+            &vcpu_config,
+        )
+        .unwrap();
 
         // Invalid entries filled with 0 should not exist.
         let cpuid = vcpu.get_cpuid().unwrap();
@@ -926,8 +1055,15 @@ mod tests {
                 msrs: HashMap::new(),
             },
         };
-        vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config)
-            .unwrap();
+        vcpu.configure(
+            &vm_mem,
+            EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            },
+            &vcpu_config,
+        )
+        .unwrap();
         assert!(vcpu.dump_cpu_config().is_ok());
     }
 
@@ -977,6 +1113,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vcpu_state_is_offline() {
+        let (_vm, vcpu, _) = setup_vcpu(0x1000);
+        let mut state = vcpu.save_state().unwrap();
+
+        state.mp_state.mp_state = KVM_MP_STATE_UNINITIALIZED;
+        assert!(state.is_offline());
+        state.mp_state.mp_state = KVM_MP_STATE_HALTED;
+        state.regs.rflags = X86_EFLAGS_IF;
+        assert!(!state.is_offline());
+        state.regs.rflags = 0x2;
+        assert!(state.is_offline());
+        state.mp_state.mp_state = kvm_bindings::KVM_MP_STATE_RUNNABLE;
+        assert!(!state.is_offline());
+    }
+
     #[test]
     fn test_get_msrs_with_msrs_to_save() {
         // Test `get_msrs()` with the MSR indices that should be serialized into snapshots.
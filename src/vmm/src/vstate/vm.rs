@@ -7,12 +7,14 @@
 
 #[cfg(target_arch = "x86_64")]
 use std::fmt;
+use std::sync::Arc;
 
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_clock_data, kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList,
-    KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
-    KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
+    kvm_clock_data, kvm_enable_cap, kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList,
+    KVM_CAP_SPLIT_IRQCHIP, KVM_CAP_X2APIC_API, KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC,
+    KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE, KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
+    KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK, KVM_X2APIC_API_USE_32BIT_IDS,
 };
 use kvm_bindings::{kvm_userspace_memory_region, KVM_API_VERSION, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::{Kvm, VmFd};
@@ -92,6 +94,14 @@ pub enum VmError {
     /// Cannot configure the microvm.
     #[error("Cannot configure the microvm: {0}")]
     VmSetup(kvm_ioctls::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot enable the split irqchip.
+    #[error("Cannot enable the split irqchip: {0}")]
+    EnableSplitIrqchip(kvm_ioctls::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Cannot enable the 32-bit x2APIC IDs.
+    #[error("Cannot enable the 32-bit x2APIC IDs: {0}")]
+    EnableX2apicApi(kvm_ioctls::Error),
     #[cfg(target_arch = "aarch64")]
     /// Failed to save the VM's GIC state.
     #[error("Failed to save the VM's GIC state: {0:?}")]
@@ -141,8 +151,10 @@ pub enum RestoreStateError {
 /// A wrapper around creating and using a VM.
 #[derive(Debug)]
 pub struct Vm {
-    fd: VmFd,
+    // Shared with the devices which update the VM configuration, such as the userspace IOAPIC.
+    fd: Arc<VmFd>,
     max_memslots: usize,
+    max_vcpus: usize,
 
     /// Additional capabilities that were specified in cpu template.
     pub kvm_cap_modifiers: Vec<KvmCapability>,
@@ -152,6 +164,8 @@ pub struct Vm {
     supported_cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     msrs_to_save: MsrList,
+    #[cfg(target_arch = "x86_64")]
+    split_irqchip: bool,
 
     // Arm specific fields.
     // On aarch64 we need to keep around the fd obtained by creating the VGIC device.
@@ -177,14 +191,16 @@ impl Vm {
         Self::check_capabilities(&kvm, &total_caps).map_err(VmError::Capabilities)?;
 
         let max_memslots = kvm.get_nr_memslots();
+        let max_vcpus = kvm.get_max_vcpus();
         // Create fd for interacting with kvm-vm specific functions.
         let vm_fd = kvm.create_vm().map_err(VmError::VmFd)?;
 
         #[cfg(target_arch = "aarch64")]
         {
             Ok(Vm {
-                fd: vm_fd,
+                fd: Arc::new(vm_fd),
                 max_memslots,
+                max_vcpus,
                 kvm_cap_modifiers,
                 irqchip_handle: None,
             })
@@ -198,11 +214,13 @@ impl Vm {
             let msrs_to_save = crate::arch::x86_64::msr::get_msrs_to_save(&kvm)?;
 
             Ok(Vm {
-                fd: vm_fd,
+                fd: Arc::new(vm_fd),
                 max_memslots,
+                max_vcpus,
                 kvm_cap_modifiers,
                 supported_cpuid,
                 msrs_to_save,
+                split_irqchip: false,
             })
         }
     }
@@ -283,10 +301,20 @@ impl Vm {
         Ok(())
     }
 
+    /// Returns the maximum number of vCPUs that KVM can create for this VM.
+    pub fn max_vcpus(&self) -> usize {
+        self.max_vcpus
+    }
+
     /// Gets a reference to the kvm file descriptor owned by this VM.
     pub fn fd(&self) -> &VmFd {
         &self.fd
     }
+
+    /// Gets a shared handle to the kvm file descriptor owned by this VM.
+    pub fn shared_fd(&self) -> Arc<VmFd> {
+        self.fd.clone()
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -302,11 +330,15 @@ impl Vm {
     ];
 
     /// Creates the GIC (Global Interrupt Controller).
-    pub fn setup_irqchip(&mut self, vcpu_count: u8) -> Result<(), VmError> {
-        self.irqchip_handle = Some(
-            crate::arch::aarch64::gic::create_gic(&self.fd, vcpu_count.into(), None)
-                .map_err(VmError::VmCreateGIC)?,
-        );
+    ///
+    /// When `its` is set, an ITS is attached to the GICv3 as well, so that the guest can use MSIs.
+    pub fn setup_irqchip(&mut self, vcpu_count: u16, its: bool) -> Result<(), VmError> {
+        let mut gic = crate::arch::aarch64::gic::create_gic(&self.fd, vcpu_count.into(), None)
+            .map_err(VmError::VmCreateGIC)?;
+        if its {
+            gic.create_its(&self.fd).map_err(VmError::VmCreateGIC)?;
+        }
+        self.irqchip_handle = Some(gic);
         Ok(())
     }
 
@@ -424,6 +456,50 @@ impl Vm {
         self.fd.create_pit2(pit_config).map_err(VmError::VmSetup)
     }
 
+    /// Enables the split irqchip, with which KVM only emulates the local APICs.
+    ///
+    /// The IOAPIC is left to userspace and neither the PIC nor the PIT are emulated, so the
+    /// guest relies on the local APIC timer.
+    pub fn setup_split_irqchip(&mut self) -> Result<(), VmError> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_SPLIT_IRQCHIP,
+            ..Default::default()
+        };
+        // Number of GSIs reserved for the routes of the IOAPIC pins.
+        cap.args[0] = crate::arch::x86_64::layout::IOAPIC_NUM_PINS as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(VmError::EnableSplitIrqchip)?;
+        self.split_irqchip = true;
+        Ok(())
+    }
+
+    /// Makes the local APICs use 32-bit x2APIC IDs, which is needed for the APIC IDs past 254.
+    ///
+    /// The 0xff x2APIC ID is then a regular one instead of the xAPIC broadcast, and KVM routes
+    /// the MSIs using the extended destination ID, which carries bits 8 to 14 of the APIC ID.
+    pub fn enable_x2apic_api(&self) -> Result<(), VmError> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X2APIC_API,
+            ..Default::default()
+        };
+        cap.args[0] =
+            u64::from(KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK);
+        self.fd.enable_cap(&cap).map_err(VmError::EnableX2apicApi)
+    }
+
+    /// Returns whether the IOAPIC is emulated in userspace.
+    pub fn split_irqchip(&self) -> bool {
+        self.split_irqchip
+    }
+
+    /// Resets the kvmclock of the VM to zero.
+    pub fn reset_clock(&self) -> Result<(), VmError> {
+        self.fd
+            .set_clock(&kvm_clock_data::default())
+            .map_err(VmError::VmSetClock)
+    }
+
     /// Saves and returns the Kvm Vm state.
     pub fn save_state(&self) -> Result<VmState, VmError> {
         let pitstate = self.fd.get_pit2().map_err(VmError::VmGetPit2)?;
@@ -527,7 +603,8 @@ pub(crate) mod tests {
     #[test]
     fn test_new() {
         // Testing with a valid /dev/kvm descriptor.
-        assert!(Vm::new(vec![]).is_ok());
+        let vm = Vm::new(vec![]).unwrap();
+        assert!(vm.max_vcpus() > 0);
     }
 
     #[test]
@@ -587,6 +664,21 @@ pub(crate) mod tests {
         assert!(vm.restore_state(&vm_state).is_ok());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_setup_split_irqchip() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        assert!(!vm.split_irqchip());
+        vm.setup_split_irqchip().unwrap();
+        assert!(vm.split_irqchip());
+        vm.enable_x2apic_api().unwrap();
+
+        // There is no in-kernel PIC, IOAPIC nor PIT to save.
+        assert!(vm.save_state().is_err());
+        // The irqchip cannot be created twice.
+        assert!(vm.setup_irqchip().is_err());
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_vm_save_restore_state_bad_irqchip() {
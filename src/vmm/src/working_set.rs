@@ -0,0 +1,91 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Estimation of the guest working set.
+//!
+//! Idle page tracking needs access to `/proc` and `/sys` with elevated privileges, which a jailed
+//! Firecracker does not have. The sampler relies on the KVM dirty log instead: it periodically
+//! counts the guest pages written since the previous sample. Pages only read by the guest are not
+//! observed, so the estimate is a lower bound of the working set.
+
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, io};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, StoreMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use utils::get_page_size;
+
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::working_set::WorkingSetConfig;
+use crate::Vmm;
+
+/// Periodically counts the guest pages written during the last interval.
+pub struct WorkingSetSampler {
+    vmm: Arc<Mutex<Vmm>>,
+    timer: TimerFd,
+    page_size: usize,
+}
+
+impl fmt::Debug for WorkingSetSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkingSetSampler")
+            .field("page_size", &self.page_size)
+            .finish()
+    }
+}
+
+impl WorkingSetSampler {
+    /// Creates a sampler of the guest memory of `vmm`.
+    pub fn new(vmm: Arc<Mutex<Vmm>>, config: &WorkingSetConfig) -> io::Result<Self> {
+        let page_size = get_page_size()?;
+        let mut timer = TimerFd::new_custom(ClockId::Monotonic, true, true)?;
+        let period = Duration::from_secs(config.sample_interval_s);
+        timer.set_state(
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            },
+            SetTimeFlags::Default,
+        );
+
+        Ok(WorkingSetSampler {
+            vmm,
+            timer,
+            page_size,
+        })
+    }
+
+    fn sample(&mut self) {
+        let vmm = self.vmm.lock().expect("Poisoned lock");
+        // The guest does not write to its memory while paused, the last estimate still holds.
+        if vmm.instance_info.state != VmState::Running {
+            return;
+        }
+
+        match vmm.sample_dirty_pages() {
+            Ok(pages) => METRICS.vmm.working_set_bytes.store(pages * self.page_size),
+            Err(err) => error!("Failed to sample the guest working set: {}", err),
+        }
+    }
+}
+
+impl MutEventSubscriber for WorkingSetSampler {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        if event.fd() == self.timer.as_raw_fd() && event.event_set() == EventSet::IN {
+            self.timer.read();
+            self.sample();
+        } else {
+            error!("Spurious EventManager event for handler: WorkingSetSampler");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!("Failed to register the working set sampler timer: {}", err);
+        }
+    }
+}
@@ -190,6 +190,7 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
         snapshot_path: snapshot_file.as_path().to_path_buf(),
         mem_file_path: memory_file.as_path().to_path_buf(),
         version: Some(Version::new(0, 24, 0)),
+        timeout_ms: None,
     };
     let vm_info = VmInfo {
         mem_size_mib: 1u64,
@@ -266,6 +267,7 @@ fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
         mem,
         None,
         false,
+        None,
         &empty_seccomp_filters,
         vm_resources,
     )
@@ -318,7 +320,7 @@ fn test_snapshot_load_sanity_checks() {
             .append(&mut microvm_state.vcpu_states.clone());
     }
 
-    // After this line we will have 33 vCPUs, FC max si 32.
+    // After this line we will have one vCPU more than the maximum supported.
     microvm_state
         .vcpu_states
         .push(microvm_state.vcpu_states[0].clone());
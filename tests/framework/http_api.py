@@ -106,9 +106,12 @@ class Api:
         self.describe = Resource(self, "/")
         self.vm = Resource(self, "/vm")
         self.vm_config = Resource(self, "/vm/config")
+        self.vm_config_full = Resource(self, "/vm/config/full")
+        self.validate = Resource(self, "/validate")
         self.actions = Resource(self, "/actions")
         self.boot = Resource(self, "/boot-source")
         self.drive = Resource(self, "/drives", "drive_id")
+        self.health = Resource(self, "/health")
         self.version = Resource(self, "/version")
         self.logger = Resource(self, "/logger")
         self.machine_config = Resource(self, "/machine-config")
@@ -118,8 +121,33 @@ class Api:
         self.mmds_config = Resource(self, "/mmds/config")
         self.balloon = Resource(self, "/balloon")
         self.balloon_stats = Resource(self, "/balloon/statistics")
+        self.balloon_hinting_start = Resource(self, "/balloon/hinting/start")
+        self.balloon_hinting_stop = Resource(self, "/balloon/hinting/stop")
+        self.balloon_hinting_status = Resource(self, "/balloon/hinting/status")
         self.vsock = Resource(self, "/vsock")
         self.snapshot_create = Resource(self, "/snapshot/create")
         self.snapshot_load = Resource(self, "/snapshot/load")
+        self.snapshot_commit = Resource(self, "/snapshot/commit")
+        self.snapshot_suspend = Resource(self, "/snapshot/suspend")
+        self.snapshot_uffd_handler = Resource(self, "/snapshot/uffd-handler")
+        self.clone = Resource(self, "/clone")
+        self.confidential = Resource(self, "/confidential")
+        self.coredump = Resource(self, "/coredump")
         self.cpu_config = Resource(self, "/cpu-config")
+        self.deterministic_boot = Resource(self, "/deterministic-boot")
         self.entropy = Resource(self, "/entropy")
+        self.gdb = Resource(self, "/gdb")
+        self.guest_files = Resource(self, "/guest-files")
+        self.guest_files_copy = Resource(self, "/guest-files/copy")
+        self.guest_freeze = Resource(self, "/guest-freeze")
+        self.identity = Resource(self, "/identity")
+        self.landlock = Resource(self, "/landlock")
+        self.memory_pressure = Resource(self, "/memory-pressure")
+        self.seccomp = Resource(self, "/seccomp")
+        self.smbios = Resource(self, "/smbios")
+        self.stall_detection = Resource(self, "/stall-detection")
+        self.tpm = Resource(self, "/tpm")
+        self.virtio_record = Resource(self, "/virtio-record")
+        self.working_set = Resource(self, "/working-set")
+        self.io_threads = Resource(self, "/io-threads")
+        self.api_token = Resource(self, "/api-token")
@@ -39,6 +39,8 @@ class JailerContext:
     resource_limits = None
     cgroup_ver = None
     parent_cgroup = None
+    cpu_max = None
+    delegate_cpu_max = False
 
     def __init__(
         self,
@@ -54,6 +56,8 @@ class JailerContext:
         resource_limits=None,
         cgroup_ver=None,
         parent_cgroup=None,
+        cpu_max=None,
+        delegate_cpu_max=False,
         **extra_args,
     ):
         """Set up jailer fields.
@@ -77,6 +81,8 @@ class JailerContext:
         self.resource_limits = resource_limits
         self.cgroup_ver = cgroup_ver
         self.parent_cgroup = parent_cgroup
+        self.cpu_max = cpu_max
+        self.delegate_cpu_max = delegate_cpu_max
 
     # Disabling 'too-many-branches' warning for this function as it needs to
     # check every argument, so the number of branches will increase
@@ -115,6 +121,10 @@ class JailerContext:
         if self.cgroups is not None:
             for cgroup in self.cgroups:
                 jailer_param_list.extend(["--cgroup", str(cgroup)])
+        if self.cpu_max is not None:
+            jailer_param_list.extend(["--cpu-max", str(self.cpu_max)])
+        if self.delegate_cpu_max:
+            jailer_param_list.append("--delegate-cpu-max")
         if self.resource_limits is not None:
             for limit in self.resource_limits:
                 jailer_param_list.extend(["--resource-limit", str(limit)])
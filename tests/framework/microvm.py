@@ -745,6 +745,14 @@ class Microvm:
         snapshot: Snapshot,
         resume: bool = False,
         uffd_path: Path = None,
+        uffd_handler_exit_action: str = None,
+        uffd_write_protect: bool = False,
+        readahead_mib_per_s: int = None,
+        vsock_override: dict = None,
+        restore_info: dict = None,
+        vcpu_count: int = None,
+        mem_url: str = None,
+        mem_cache_path: Path = None,
     ):
         """Restore a snapshot"""
         # Move all the snapshot files into the microvm jail.
@@ -772,12 +780,33 @@ class Microvm:
         mem_backend = {"backend_type": "File", "backend_path": str(jailed_mem)}
         if uffd_path is not None:
             mem_backend = {"backend_type": "Uffd", "backend_path": str(uffd_path)}
+            if uffd_handler_exit_action is not None:
+                mem_backend["handler_exit_action"] = uffd_handler_exit_action
+            if uffd_handler_exit_action == "Fallback":
+                mem_backend["fallback_mem_file_path"] = str(jailed_mem)
+            if uffd_write_protect:
+                mem_backend["write_protect"] = True
+        elif mem_url is not None:
+            mem_backend = {"backend_type": "Http", "backend_path": mem_url}
+            if mem_cache_path is not None:
+                mem_backend["cache_path"] = str(mem_cache_path)
+        elif readahead_mib_per_s is not None:
+            mem_backend["readahead_mib_per_s"] = readahead_mib_per_s
+
+        optional_params = {}
+        if vsock_override is not None:
+            optional_params["vsock_override"] = vsock_override
+        if restore_info is not None:
+            optional_params["restore_info"] = restore_info
+        if vcpu_count is not None:
+            optional_params["vcpu_count"] = vcpu_count
 
         self.api.snapshot_load.put(
             mem_backend=mem_backend,
             snapshot_path=str(jailed_vmstate),
             enable_diff_snapshots=snapshot.is_diff,
             resume_vm=resume,
+            **optional_params,
         )
         return True
 
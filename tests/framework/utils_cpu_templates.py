@@ -16,7 +16,7 @@ INTEL_TEMPLATES = ["C3", "T2", "T2CL", "T2S"]
 # All existing CPU templates available on AMD
 AMD_TEMPLATES = ["T2A"]
 # All existing CPU templates available on ARM
-ARM_TEMPLATES = ["V1N1"]
+ARM_TEMPLATES = ["V1N1", "V2N1"]
 
 
 def get_supported_cpu_templates():
@@ -38,17 +38,26 @@ def get_supported_cpu_templates():
                     return []
                 case "c7g.metal":
                     return ARM_TEMPLATES
+                case "m8g.metal" | "c8g.metal":
+                    # V1N1 does not mask the features Neoverse-V2 adds on top of
+                    # Neoverse-V1.
+                    return ["V2N1"]
     return []
 
 
 SUPPORTED_CPU_TEMPLATES = get_supported_cpu_templates()
 
 # Custom CPU templates for Aarch64 for testing
-AARCH64_CUSTOM_CPU_TEMPLATES_G2 = ["aarch64_remove_ssbs", "aarch64_v1n1"]
+AARCH64_CUSTOM_CPU_TEMPLATES_G2 = [
+    "aarch64_remove_ssbs",
+    "aarch64_v1n1",
+    "aarch64_v2n1",
+]
 AARCH64_CUSTOM_CPU_TEMPLATES_G3 = [
     "aarch64_remove_ssbs",
     "aarch64_with_sve_and_pac",
     "aarch64_v1n1",
+    "aarch64_v2n1",
 ]
 
 
@@ -0,0 +1,51 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""HTTP server answering the range requests on a single file.
+
+Usage: http_range_server.py <file> <port>
+
+Prints `ready` once it listens on 127.0.0.1:<port>.
+"""
+
+import os
+import re
+import sys
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+RANGE_RE = re.compile(r"bytes=(\d+)-(\d+)")
+
+
+class RangeHandler(BaseHTTPRequestHandler):
+    """Serves the ranges of the file, whatever the path of the request."""
+
+    protocol_version = "HTTP/1.1"
+    path_to_serve = None
+
+    def do_GET(self):  # pylint: disable=invalid-name
+        """Answer a range request."""
+        size = os.path.getsize(self.path_to_serve)
+        match = RANGE_RE.fullmatch(self.headers.get("Range", ""))
+        if match is None or int(match[2]) >= size:
+            self.send_response(416)
+            self.send_header("Content-Length", "0")
+            self.end_headers()
+            return
+        first, last = int(match[1]), int(match[2])
+        with open(self.path_to_serve, "rb") as file:
+            file.seek(first)
+            data = file.read(last - first + 1)
+        self.send_response(206)
+        self.send_header("Content-Length", str(len(data)))
+        self.send_header("Content-Range", f"bytes {first}-{last}/{size}")
+        self.end_headers()
+        self.wfile.write(data)
+
+    def log_message(self, format, *args):  # pylint: disable=redefined-builtin
+        """Keep the output of the server for the `ready` line."""
+
+
+if __name__ == "__main__":
+    RangeHandler.path_to_serve = sys.argv[1]
+    server = ThreadingHTTPServer(("127.0.0.1", int(sys.argv[2])), RangeHandler)
+    print("ready", flush=True)
+    server.serve_forever()
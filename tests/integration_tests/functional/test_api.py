@@ -607,7 +607,7 @@ def test_rate_limiters_api_config(test_microvm_with_api):
 
 def test_api_patch_pre_boot(test_microvm_with_api):
     """
-    Test that PATCH updates are not allowed before the microvm boots.
+    Test the PATCH updates allowed before the microvm boots.
     """
     test_microvm = test_microvm_with_api
     test_microvm.spawn()
@@ -645,9 +645,17 @@ def test_api_patch_pre_boot(test_microvm_with_api):
     with pytest.raises(RuntimeError, match="Invalid request method"):
         test_microvm.api.logger.patch(level="Error")
 
-    # Patching drive before boot is not allowed.
-    with pytest.raises(RuntimeError, match=NOT_SUPPORTED_BEFORE_START):
+    # Patching drive before boot recreates it with the updated properties.
+    with pytest.raises(RuntimeError, match="Invalid block device path"):
         test_microvm.api.drive.patch(drive_id=drive_id, path_on_host="foo.bar")
+    test_microvm.api.drive.patch(drive_id=drive_id, cache_type="Writethrough")
+    response_json = test_microvm.api.vm_config.get().json()
+    scratch = [d for d in response_json["drives"] if d["drive_id"] == drive_id]
+    assert scratch[0]["cache_type"] == "Writethrough"
+
+    # Only the state of a drive cannot be patched before boot.
+    with pytest.raises(RuntimeError, match="can only be updated after boot"):
+        test_microvm.api.drive.patch(drive_id=drive_id, state="Paused")
 
     # Patching net before boot is not allowed.
     with pytest.raises(RuntimeError, match=NOT_SUPPORTED_BEFORE_START):
@@ -715,8 +723,8 @@ def test_drive_patch(test_microvm_with_api):
         io_engine="Async" if is_io_uring_supported() else "Sync",
     )
 
-    # Patching drive before boot is not allowed.
-    with pytest.raises(RuntimeError, match=NOT_SUPPORTED_BEFORE_START):
+    # Patching drive before boot fails for a missing backing file.
+    with pytest.raises(RuntimeError, match="Invalid block device path"):
         test_microvm.api.drive.patch(drive_id="scratch", path_on_host="foo.bar")
 
     test_microvm.start()
@@ -834,6 +842,16 @@ def _drive_patch(test_microvm):
             },
         )
 
+    # The drive can be paused while its backing file is swapped.
+    test_microvm.api.drive.patch(drive_id="scratch", state="Paused")
+    test_microvm.api.drive.patch(
+        drive_id="scratch",
+        path_on_host=test_microvm.create_jailed_resource(fs.path),
+        state="Resumed",
+    )
+    with pytest.raises(RuntimeError, match="unknown variant `Stopped`"):
+        test_microvm.api.drive.patch(drive_id="scratch", state="Stopped")
+
     # Validate full vm configuration after patching drives.
     response = test_microvm.api.vm_config.get().json()
     assert response["drives"] == [
@@ -845,6 +863,13 @@ def _drive_patch(test_microvm):
             "is_read_only": True,
             "cache_type": "Unsafe",
             "io_engine": "Sync",
+            "direct_io": False,
+            "fadvise": "Normal",
+            "readahead_kib": 0,
+            "io_uring_entries": 128,
+            "max_in_flight_requests": 0,
+            "busy_poll_us": 0,
+            "batch_size": 0,
             "rate_limiter": None,
         },
         {
@@ -855,6 +880,13 @@ def _drive_patch(test_microvm):
             "is_read_only": False,
             "cache_type": "Unsafe",
             "io_engine": "Async" if is_io_uring_supported() else "Sync",
+            "direct_io": False,
+            "fadvise": "Normal",
+            "readahead_kib": 0,
+            "io_uring_entries": 128,
+            "max_in_flight_requests": 0,
+            "busy_poll_us": 0,
+            "batch_size": 0,
             "rate_limiter": {
                 "bandwidth": {"size": 5000, "one_time_burst": None, "refill_time": 100},
                 "ops": {"size": 500, "one_time_burst": None, "refill_time": 100},
@@ -1023,7 +1055,60 @@ def test_api_balloon(uvm_nano):
         test_microvm.api.balloon.patch(amount_mib=33554432)
 
 
-def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
+def test_api_concurrent_requests(uvm_nano):
+    """
+    Test the requests answered without waiting for the VMM thread.
+    """
+    test_microvm = uvm_nano
+    test_microvm.api.balloon.put(
+        amount_mib=0, deflate_on_oom=False, stats_polling_interval_s=1
+    )
+    test_microvm.start()
+
+    assert test_microvm.api.describe.get().json()["state"] == "Running"
+    assert test_microvm.api.balloon.get().json()["stats_polling_interval_s"] == 1
+    assert "target_pages" in test_microvm.api.balloon_stats.get().json()
+
+    # The requests are served by the API thread, without changing their responses.
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["api_server"]["concurrent_requests"] >= 3
+
+    # The state changes still go through the VMM thread.
+    test_microvm.api.vm.patch(state="Paused")
+    assert test_microvm.api.describe.get().json()["state"] == "Paused"
+    test_microvm.api.vm.patch(state="Resumed")
+
+
+def test_api_health(uvm_nano):
+    """
+    Test the health reported for each subsystem of the microVM.
+    """
+    test_microvm = uvm_nano
+
+    health = test_microvm.api.health.get().json()
+    assert health["status"] == "Starting"
+    assert health["api"] == {"status": "Ok"}
+    assert health["vcpus"]["status"] == "Starting"
+    assert health["uffd_handler"] == {"status": "Disabled"}
+    assert health["metrics"] == {"status": "Ok"}
+
+    test_microvm.start()
+    # The guest activates the devices while it boots.
+    health = test_microvm.api.health.get().json()
+    assert health["vcpus"] == {"status": "Ok"}
+    assert health["devices"]["status"] in ["Starting", "Ok"]
+
+    test_microvm.api.vm.patch(state="Paused")
+    health = test_microvm.api.health.get().json()
+    assert health["status"] == "Degraded"
+    assert health["vcpus"]["status"] == "Degraded"
+    test_microvm.api.vm.patch(state="Resumed")
+
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["get_api_requests"]["health_count"] >= 2
+
+
+microvm_factory, uvm_nano):
     """
     Test the configuration of a microVM after restoring from a snapshot.
     """
@@ -1037,6 +1122,9 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
         "mem_size_mib": 256,
         "smt": True,
         "track_dirty_pages": False,
+        "acpi": False,
+        "split_irqchip": False,
+        "gic_its": False,
     }
 
     if cpu_vendor == utils_cpuid.CpuVendor.ARM:
@@ -1059,6 +1147,13 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
             "cache_type": "Unsafe",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "direct_io": False,
+            "fadvise": "Normal",
+            "readahead_kib": 0,
+            "io_uring_entries": 128,
+            "max_in_flight_requests": 0,
+            "busy_poll_us": 0,
+            "batch_size": 0,
         }
     ]
 
@@ -1068,6 +1163,7 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
         "amount_mib": 1,
         "deflate_on_oom": True,
         "stats_polling_interval_s": 0,
+        "free_page_hinting": False,
     }
 
     # Add a vsock device.
@@ -1151,6 +1247,9 @@ def test_get_full_config(test_microvm_with_api):
         "mem_size_mib": 256,
         "smt": False,
         "track_dirty_pages": False,
+        "acpi": False,
+        "split_irqchip": False,
+        "gic_its": False,
     }
     expected_cfg["cpu-config"] = None
     expected_cfg["boot-source"] = {
@@ -1168,6 +1267,13 @@ def test_get_full_config(test_microvm_with_api):
             "cache_type": "Unsafe",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "direct_io": False,
+            "fadvise": "Normal",
+            "readahead_kib": 0,
+            "io_uring_entries": 128,
+            "max_in_flight_requests": 0,
+            "busy_poll_us": 0,
+            "batch_size": 0,
         }
     ]
 
@@ -1177,6 +1283,7 @@ def test_get_full_config(test_microvm_with_api):
         "amount_mib": 1,
         "deflate_on_oom": True,
         "stats_polling_interval_s": 0,
+        "free_page_hinting": False,
     }
 
     # Add a vsock device.
@@ -1297,7 +1404,8 @@ def test_negative_snapshot_load_api(microvm_factory):
 
     # API request with invalid `backend_type` should fail.
     with pytest.raises(
-        RuntimeError, match="unknown variant `foo`, expected `File` or `Uffd`"
+        RuntimeError,
+        match="unknown variant `foo`, expected one of `File`, `Uffd`, `Http`",
     ):
         vm.api.snapshot_load.put(
             snapshot_path="foo",
@@ -1305,7 +1413,8 @@ def test_negative_snapshot_load_api(microvm_factory):
         )
 
     # API request without `snapshot_path` should fail.
-    with pytest.raises(RuntimeError, match="missing field `snapshot_path`"):
+    err_msg = "missing field: either `snapshot_path` or `manifest_path` is required"
+    with pytest.raises(RuntimeError, match=err_msg):
         vm.api.snapshot_load.put(
             mem_backend={"backend_type": "File", "backend_path": "bar"},
         )
@@ -1323,3 +1432,38 @@ def test_negative_snapshot_load_api(microvm_factory):
         )
 
     assert exc_info.value.args[2].headers["deprecation"]
+
+
+def test_api_fault_codes(test_microvm_with_api):
+    """
+    Test that the API errors carry a stable code along with the message.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError) as exc_info:
+        test_microvm.api.actions.put(action_type="FlushMetrics")
+    fault = exc_info.value.args[1]
+    assert fault["fault_code"] == "OperationNotSupportedPreBoot"
+    assert fault["fault_message"] == NOT_SUPPORTED_BEFORE_START
+    assert "fault_params" not in fault
+
+    with pytest.raises(RuntimeError) as exc_info:
+        test_microvm.api.machine_config.put(vcpu_count=0, mem_size_mib=128)
+    assert exc_info.value.args[1]["fault_code"] == "MachineConfig"
+
+    response = test_microvm.api.session.put(
+        test_microvm.api.endpoint + "/foo", json={"bar": 1}
+    )
+    assert response.status_code == 400
+    fault = response.json()
+    assert fault["fault_code"] == "InvalidPathMethod"
+    assert fault["fault_params"] == {"method": "PUT", "path": "foo"}
+
+    response = test_microvm.api.session.put(
+        test_microvm.api.endpoint + "/machine-config", data="{\n  foo"
+    )
+    assert response.status_code == 400
+    fault = response.json()
+    assert fault["fault_code"] == "InvalidJson"
+    assert fault["fault_params"]["line"] == 2
@@ -0,0 +1,75 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the authentication of the API requests."""
+
+from http import HTTPStatus
+from pathlib import Path
+
+import pytest
+
+
+def _spawn_with_token(test_microvm, token):
+    token_file = Path(test_microvm.path) / "api.token"
+    token_file.write_text(f"{token}\n", encoding="utf-8")
+    test_microvm.jailer.extra_args.update(
+        {"api-token-file": str(test_microvm.create_jailed_resource(token_file))}
+    )
+    test_microvm.spawn()
+
+
+def test_api_token(test_microvm_with_api):
+    """
+    Check that the API requests must carry the configured token.
+    """
+    test_microvm = test_microvm_with_api
+    _spawn_with_token(test_microvm, "s3cr3t")
+
+    res = test_microvm.api.session.get(test_microvm.api.endpoint + "/")
+    assert res.status_code == HTTPStatus.UNAUTHORIZED, res.content
+
+    test_microvm.api.session.headers["Authorization"] = "Bearer wr0ng"
+    res = test_microvm.api.session.get(test_microvm.api.endpoint + "/")
+    assert res.status_code == HTTPStatus.UNAUTHORIZED, res.content
+
+    test_microvm.api.session.headers["Authorization"] = "Bearer s3cr3t"
+    test_microvm.basic_config()
+    test_microvm.start()
+
+    # The counters are reset on every flush, so they must be added up.
+    test_microvm.flush_metrics()
+    unauthorized_requests = sum(
+        metrics["api_server"]["unauthorized_requests"]
+        for metrics in test_microvm.get_all_metrics()
+    )
+    assert unauthorized_requests == 2
+
+
+def test_api_token_rotation(test_microvm_with_api):
+    """
+    Check that the API token can be rotated.
+    """
+    test_microvm = test_microvm_with_api
+    _spawn_with_token(test_microvm, "s3cr3t")
+    test_microvm.api.session.headers["Authorization"] = "Bearer s3cr3t"
+
+    with pytest.raises(RuntimeError, match="API token"):
+        test_microvm.api.api_token.put(token="two words")
+
+    test_microvm.api.api_token.put(token="n3w-s3cr3t")
+    with pytest.raises(RuntimeError, match="Missing or invalid API token"):
+        test_microvm.api.describe.get()
+
+    test_microvm.api.session.headers["Authorization"] = "Bearer n3w-s3cr3t"
+    test_microvm.basic_config()
+    test_microvm.start()
+
+
+def test_api_token_disabled(test_microvm_with_api):
+    """
+    Check that the API token cannot be rotated when authentication is disabled.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError, match="API authentication is not enabled"):
+        test_microvm.api.api_token.put(token="s3cr3t")
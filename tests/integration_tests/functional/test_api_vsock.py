@@ -0,0 +1,70 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the API exposed on a vsock port of the host."""
+
+import os
+import socket
+
+import pytest
+
+# CID through which the host processes reach each other.
+VMADDR_CID_LOCAL = 1
+
+
+def _vsock_connect(port):
+    """Connect to the API vsock port through the local CID."""
+    sock = socket.socket(socket.AF_VSOCK, socket.SOCK_STREAM)
+    sock.settimeout(5)
+    sock.connect((VMADDR_CID_LOCAL, port))
+    return sock
+
+
+def _vsock_request(sock, request):
+    """Send a raw HTTP request on the vsock connection, and return the response."""
+    sock.sendall(request)
+    return sock.recv(4096).decode("utf-8")
+
+
+@pytest.mark.skipif(
+    not os.path.exists("/dev/vsock"), reason="the host does not support vsock"
+)
+def test_api_vsock(test_microvm_with_api):
+    """
+    Check that the API can be driven through the vsock port.
+    """
+    test_microvm = test_microvm_with_api
+    # Use a port which is unique to this process, so that tests can run in parallel.
+    port = 10000 + os.getpid() % 50000
+    test_microvm.jailer.extra_args.update(
+        {"api-vsock-port": port, "api-vsock-peer-cid": VMADDR_CID_LOCAL}
+    )
+    test_microvm.spawn()
+
+    try:
+        sock = _vsock_connect(port)
+    except OSError as err:
+        pytest.skip(f"vsock loopback is not available: {err}")
+
+    response = _vsock_request(sock, b"GET / HTTP/1.1\r\n\r\n")
+    assert response.startswith("HTTP/1.1 200"), response
+    assert "Not started" in response, response
+
+    body = b'{"vcpu_count": 2, "mem_size_mib": 256}'
+    response = _vsock_request(
+        sock,
+        b"PUT /machine-config HTTP/1.1\r\n"
+        b"Content-Type: application/json\r\n"
+        + f"Content-Length: {len(body)}\r\n\r\n".encode("utf-8")
+        + body,
+    )
+    assert response.startswith("HTTP/1.1 204"), response
+    sock.close()
+    assert test_microvm.api.machine_config.get().json()["vcpu_count"] == 2
+
+    # The relay runs under a seccomp filter of its own.
+    filters = {
+        status["thread_category"]: status
+        for status in test_microvm.api.seccomp.get().json()
+    }
+    assert filters["api_vsock"]["installed_threads"] == 1
+    assert filters["api_vsock"]["violations"] == 0
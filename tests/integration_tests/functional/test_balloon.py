@@ -151,6 +151,43 @@ def test_inflate_reduces_free(test_microvm_with_api):
     assert available_mem_inflated <= available_mem_deflated - 85 * 64000 / 100
 
 
+def test_balloon_target_bytes(test_microvm_with_api):
+    """
+    Check byte-granularity balloon targets and usable memory updates.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    test_microvm.add_net_iface()
+
+    test_microvm.api.balloon.put(
+        amount_bytes=4096, deflate_on_oom=False, stats_polling_interval_s=0
+    )
+    config = test_microvm.api.balloon.get().json()
+    assert config["amount_mib"] == 0
+    assert config["amount_bytes"] == 4096
+
+    test_microvm.start()
+
+    # Targets that are a whole number of MiB are only reported in MiB.
+    test_microvm.api.balloon.patch(amount_bytes=64 * 2**20)
+    config = test_microvm.api.balloon.get().json()
+    assert config["amount_mib"] == 64
+    assert "amount_bytes" not in config
+
+    # Leave 224 MiB of the 256 MiB of guest memory usable.
+    test_microvm.api.balloon.patch(usable_memory_bytes=224 * 2**20)
+    assert test_microvm.api.balloon.get().json()["amount_mib"] == 32
+
+    # Without a memory hotplug device, the usable memory cannot exceed the boot memory.
+    with pytest.raises(RuntimeError):
+        test_microvm.api.balloon.patch(usable_memory_bytes=512 * 2**20)
+
+    # Exactly one target must be given.
+    with pytest.raises(RuntimeError):
+        test_microvm.api.balloon.patch(amount_mib=1, amount_bytes=2**20)
+
+
 # pylint: disable=C0103
 @pytest.mark.parametrize("deflate_on_oom", [True, False])
 def test_deflate_on_oom(test_microvm_with_api, deflate_on_oom):
@@ -493,6 +530,52 @@ def test_balloon_snapshot(microvm_factory, guest_kernel, rootfs):
     assert stats_after_snap["available_memory"] > latest_stats["available_memory"]
 
 
+@retry(delay=0.5, tries=20, logger=None)
+def wait_for_hinting_complete(vm):
+    """Wait for the guest to report all its free pages to the balloon."""
+    status = vm.api.balloon_hinting_status.get().json()
+    assert status["complete"], status
+    return status
+
+
+def test_free_page_hinting(microvm_factory, guest_kernel, rootfs):
+    """
+    Test that the free pages reported by the guest are left out of snapshots.
+    """
+    vm = microvm_factory.build(guest_kernel, rootfs)
+    vm.spawn()
+    vm.basic_config(vcpu_count=2, mem_size_mib=256)
+    vm.add_net_iface()
+
+    # Add a memory balloon with free page hinting enabled.
+    vm.api.balloon.put(amount_mib=0, deflate_on_oom=True, free_page_hinting=True)
+
+    vm.start()
+
+    # Dirty 60MB of pages, which are freed once the process exits.
+    make_guest_dirty_memory(vm.ssh, amount_mib=60)
+
+    vm.api.balloon_hinting_start.patch()
+    status = wait_for_hinting_complete(vm)
+    assert status["hinted_mib"] >= 60
+
+    # The reported pages are not written to the memory file.
+    snapshot = vm.snapshot_full()
+    assert snapshot.mem.stat().st_blocks * 512 < (256 - 60) << 20
+    vm.resume()
+
+    # The guest can use the reported pages again once the hinting is stopped.
+    vm.api.balloon_hinting_stop.patch()
+    make_guest_dirty_memory(vm.ssh, amount_mib=60)
+
+    # The guest of the restored microVM still holds them until then.
+    microvm = microvm_factory.build()
+    microvm.spawn()
+    microvm.restore_from_snapshot(snapshot, resume=True)
+    microvm.api.balloon_hinting_stop.patch()
+    make_guest_dirty_memory(microvm.ssh, amount_mib=60)
+
+
 def test_snapshot_compatibility(microvm_factory, guest_kernel, rootfs):
     """
     Test that the balloon serializes correctly.
@@ -2,6 +2,7 @@
 # SPDX-License-Identifier: Apache-2.0
 """Tests that ensure the correctness of the command line parameters."""
 
+import json
 import platform
 from pathlib import Path
 
@@ -77,6 +78,10 @@ def test_cli_metrics_path(uvm_plain):
         "signals",
         "vsock",
         "entropy",
+        "memory_hotplug",
+        "shared_dirs",
+        "tpm",
+        "uffd",
     ]
 
     if platform.machine() == "aarch64":
@@ -130,3 +135,60 @@ def test_cli_metrics_if_resume_no_metrics(uvm_plain, microvm_factory):
     # Then: the old metrics configuration does not exist
     metrics2 = Path(uvm2.jailer.chroot_path()) / metrics_path.name
     assert not metrics2.exists()
+
+
+def _validation_config(kernel_path, rootfs_path, mem_size_mib=256):
+    """Build a microVM configuration to validate."""
+    return {
+        "boot-source": {"kernel_image_path": str(kernel_path)},
+        "drives": [
+            {
+                "drive_id": "rootfs",
+                "path_on_host": str(rootfs_path),
+                "is_root_device": True,
+                "is_read_only": True,
+            }
+        ],
+        "machine-config": {"vcpu_count": 2, "mem_size_mib": mem_size_mib},
+    }
+
+
+def test_cli_validate_config(uvm_plain, tmp_path):
+    """
+    Test that --validate-config reports all the errors of a configuration.
+    """
+    fc_binary, _ = get_firecracker_binaries()
+    config_path = tmp_path / "vm_config.json"
+
+    config = _validation_config(uvm_plain.kernel_file, uvm_plain.rootfs_file)
+    config_path.write_text(json.dumps(config))
+    cmd = [fc_binary, "--validate-config", "--config-file", config_path]
+    code, stdout, stderr = run_cmd(cmd)
+    assert code == 0, stderr
+    assert "The configuration is valid." in stdout
+
+    config = _validation_config(
+        uvm_plain.kernel_file, "/invalid/path", mem_size_mib=1 << 40
+    )
+    config_path.write_text(json.dumps(config))
+    code, stdout, _ = run_cmd(cmd, ignore_return_code=True)
+    assert code != 0
+    assert "Invalid block device path" in stdout
+    assert "exceeds the host memory size" in stdout
+
+
+def test_api_validate_config(uvm_plain):
+    """
+    Test that PUT /validate checks a configuration without applying it.
+    """
+    microvm = uvm_plain
+    microvm.spawn()
+    kernel_path = microvm.create_jailed_resource(microvm.kernel_file)
+    rootfs_path = microvm.create_jailed_resource(microvm.rootfs_file)
+
+    microvm.api.validate.put(**_validation_config(kernel_path, rootfs_path))
+    assert microvm.api.vm_config.get().json()["drives"] == []
+
+    config = _validation_config(kernel_path, "/invalid/path", mem_size_mib=1 << 40)
+    with pytest.raises(RuntimeError, match="exceeds the host memory size"):
+        microvm.api.validate.put(**config)
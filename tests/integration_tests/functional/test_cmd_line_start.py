@@ -7,6 +7,7 @@ import os
 import platform
 import re
 import shutil
+import signal
 from pathlib import Path
 
 import pytest
@@ -124,6 +125,50 @@ def test_config_start_with_api(uvm_plain, vm_config_file):
     assert response.json() == vm_config
 
 
+@pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
+def test_config_export_full(uvm_plain, vm_config_file):
+    """
+    Test that the exported configuration matches the one the microvm started with.
+    """
+    test_microvm = uvm_plain
+    vm_config = _configure_vm_from_json(test_microvm, vm_config_file)
+    test_microvm.spawn()
+
+    assert test_microvm.state == "Running"
+
+    exported = test_microvm.api.vm_config_full.get().json()
+    # The logger and metrics are configured through the command line.
+    assert exported.pop("logger")["log_path"] == test_microvm.log_file.name
+    assert exported.pop("metrics")["metrics_path"] == test_microvm.metrics_file.name
+    vm_config.pop("logger")
+    vm_config.pop("metrics")
+    assert exported == vm_config
+
+
+@pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
+def test_config_defer_start(uvm_plain, vm_config_file):
+    """
+    Test that the configuration from file can be changed before starting.
+    """
+    test_microvm = uvm_plain
+    vm_config = _configure_vm_from_json(test_microvm, vm_config_file)
+    test_microvm.jailer.extra_args.update({"defer-start": None})
+    test_microvm.spawn()
+
+    assert test_microvm.state == "Not started"
+
+    test_microvm.api.machine_config.patch(vcpu_count=1)
+    with pytest.raises(RuntimeError, match="Loading a microVM snapshot not allowed"):
+        test_microvm.api.snapshot_load.put(mem_file_path="mem", snapshot_path="vmstate")
+
+    test_microvm.start()
+    assert test_microvm.state == "Running"
+
+    vm_config["machine-config"]["vcpu_count"] = 1
+    response = test_microvm.api.vm_config.get()
+    assert response.json() == vm_config
+
+
 @pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
 def test_config_start_no_api(uvm_plain, vm_config_file):
     """
@@ -156,6 +201,61 @@ def test_config_start_no_api(uvm_plain, vm_config_file):
     )
 
 
+@pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
+def test_config_shutdown_hooks(uvm_plain, vm_config_file):
+    """
+    Test the cleanup performed when Firecracker crashes.
+    """
+    test_microvm = uvm_plain
+    vm_config = _configure_vm_from_json(test_microvm, vm_config_file)
+    chroot = Path(test_microvm.chroot())
+    (chroot / "scratch.ext4").touch()
+    vm_config["shutdown-hooks"] = {
+        "remove_paths": ["scratch.ext4", "missing.sock"],
+        "status_file": "status.json",
+    }
+    (chroot / Path(vm_config_file).name).write_text(json.dumps(vm_config))
+    test_microvm.spawn()
+    assert test_microvm.state == "Running"
+
+    firecracker_pid = int(test_microvm.jailer_clone_pid)
+    test_microvm.expect_kill_by_signal = True
+    os.kill(firecracker_pid, signal.SIGBUS)
+    utils.wait_process_termination(firecracker_pid)
+
+    assert not (chroot / "scratch.ext4").exists()
+    status = json.loads((chroot / "status.json").read_text())
+    assert status == {"exit_code": 149, "exit_reason": "SIGBUS"}
+
+
+@pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
+def test_config_emergency_snapshot(uvm_plain, vm_config_file):
+    """
+    Test the snapshot taken when the emergency snapshot signal is received.
+    """
+    test_microvm = uvm_plain
+    vm_config = _configure_vm_from_json(test_microvm, vm_config_file)
+    chroot = Path(test_microvm.chroot())
+    vm_config["emergency-snapshot"] = {
+        "snapshot_path": "vmstate",
+        "mem_file_path": "mem",
+        "marker_path": "suspended.json",
+        "signal": "SIGUSR1",
+    }
+    (chroot / Path(vm_config_file).name).write_text(json.dumps(vm_config))
+    test_microvm.spawn()
+    assert test_microvm.state == "Running"
+
+    firecracker_pid = int(test_microvm.jailer_clone_pid)
+    os.kill(firecracker_pid, signal.SIGUSR1)
+    utils.wait_process_termination(firecracker_pid)
+
+    marker = json.loads((chroot / "suspended.json").read_text())
+    assert marker["snapshot_path"] == "vmstate"
+    assert (chroot / "mem").exists()
+    test_microvm.check_log_message("Taking the emergency snapshot.")
+
+
 @pytest.mark.parametrize(
     "vm_config_file",
     [
@@ -0,0 +1,92 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for confidential microVMs."""
+
+import os
+import platform
+
+import pytest
+
+from framework.utils_cpuid import CpuVendor, get_cpu_vendor
+
+
+def test_confidential_config(test_microvm_with_api):
+    """
+    Check the validation of the confidential computing configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    # The launch information only exists once the microVM is started.
+    with pytest.raises(RuntimeError, match="not supported before starting"):
+        test_microvm.api.confidential.get()
+
+    if platform.machine() != "x86_64":
+        with pytest.raises(RuntimeError, match="only supported on x86_64"):
+            test_microvm.api.confidential.put(technology="sev-snp")
+        return
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.confidential.put(technology="sev")
+    with pytest.raises(RuntimeError, match="TDX guests are not supported"):
+        test_microvm.api.confidential.put(technology="tdx")
+    with pytest.raises(RuntimeError, match="Invalid SEV-SNP guest policy"):
+        test_microvm.api.confidential.put(technology="sev-snp", policy=0x10000)
+
+    test_microvm.api.confidential.put(technology="sev-snp")
+    confidential = test_microvm.api.vm_config.get().json()["confidential"]
+    assert confidential == {"technology": "sev-snp", "policy": 0x30000}
+
+
+@pytest.mark.skipif(platform.machine() != "x86_64", reason="x86_64 only")
+def test_confidential_balloon(microvm_factory):
+    """
+    Check that confidential microVMs cannot have a balloon device, whichever
+    is configured first.
+    """
+    microvm = microvm_factory.build()
+    microvm.spawn()
+    microvm.api.balloon.put(amount_mib=0, deflate_on_oom=False)
+    with pytest.raises(RuntimeError, match="cannot have a balloon device"):
+        microvm.api.confidential.put(technology="sev-snp")
+
+    microvm = microvm_factory.build()
+    microvm.spawn()
+    microvm.api.confidential.put(technology="sev-snp")
+    with pytest.raises(RuntimeError, match="cannot have a balloon device"):
+        microvm.api.balloon.put(amount_mib=0, deflate_on_oom=False)
+
+
+def test_not_confidential(uvm_nano):
+    """
+    Check that the launch information of a regular microVM is not available.
+    """
+    uvm_nano.start()
+    with pytest.raises(RuntimeError, match="not a confidential microVM"):
+        uvm_nano.api.confidential.get()
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64"
+    or get_cpu_vendor() != CpuVendor.AMD
+    or not os.path.exists("/dev/sev"),
+    reason="requires an SEV-SNP host",
+)
+def test_confidential_launch(uvm_nano):
+    """
+    Check the launch digest of an SEV-SNP microVM, and that it cannot be
+    snapshotted.
+    """
+    microvm = uvm_nano
+    microvm.api.confidential.put(technology="sev-snp")
+    microvm.start()
+
+    info = microvm.api.confidential.get().json()
+    assert info["technology"] == "sev-snp"
+    assert info["policy"] == 0x30000
+    assert len(info["launch_digest"]) == 96
+    int(info["launch_digest"], 16)
+
+    microvm.api.vm.patch(state="Paused")
+    with pytest.raises(RuntimeError, match="not supported for confidential"):
+        microvm.api.snapshot_create.put(mem_file_path="mem", snapshot_path="vmstate")
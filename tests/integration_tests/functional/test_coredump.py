@@ -0,0 +1,58 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the guest memory dumps."""
+
+import struct
+from pathlib import Path
+
+import pytest
+
+ELF_HEADER_SIZE = 64
+PROGRAM_HEADER_SIZE = 56
+ET_CORE = 4
+PT_LOAD = 1
+PT_NOTE = 4
+
+
+def test_coredump(uvm_nano):
+    """
+    Dump the memory of a paused microVM and check the ELF layout of the dump.
+    """
+    microvm = uvm_nano
+    microvm.start()
+
+    # The registers of running vCPUs cannot be saved.
+    with pytest.raises(RuntimeError, match="unavailable while running"):
+        microvm.api.coredump.put(dump_path="vmcore")
+
+    microvm.api.vm.patch(state="Paused")
+    microvm.api.coredump.put(dump_path="vmcore")
+    microvm.api.vm.patch(state="Resumed")
+
+    # The guest keeps running after the dump.
+    exit_code, _, _ = microvm.ssh.run("true")
+    assert exit_code == 0
+
+    dump = Path(microvm.chroot()) / "vmcore"
+    with open(dump, "rb") as file:
+        header = file.read(ELF_HEADER_SIZE)
+        assert header[:4] == b"\x7fELF"
+        (e_type,) = struct.unpack_from("<H", header, 16)
+        assert e_type == ET_CORE
+        (e_phoff,) = struct.unpack_from("<Q", header, 32)
+        (e_phnum,) = struct.unpack_from("<H", header, 56)
+
+        file.seek(e_phoff)
+        phdrs = [
+            struct.unpack("<IIQQQQQQ", file.read(PROGRAM_HEADER_SIZE))
+            for _ in range(e_phnum)
+        ]
+
+    # One note segment, then the guest memory.
+    assert phdrs[0][0] == PT_NOTE
+    assert phdrs[0][5] > 0
+    assert all(phdr[0] == PT_LOAD for phdr in phdrs[1:])
+    mem_size = sum(phdr[5] for phdr in phdrs[1:])
+    assert mem_size == 256 << 20
+    last = phdrs[-1]
+    assert dump.stat().st_size == last[2] + last[5]
@@ -75,7 +75,7 @@ def _check_cpu_features_arm(test_microvm, guest_kv, template_name=None):
     match (cpuid_utils.get_instance_type(), guest_kv, template_name):
         case ("m6g.metal", _, "aarch64_remove_ssbs"):
             expected_cpu_features["Flags"] = DEFAULT_G2_FEATURES_NO_SSBS
-        case ("m6g.metal", _, "aarch64_v1n1"):
+        case ("m6g.metal", _, "aarch64_v1n1" | "aarch64_v2n1"):
             expected_cpu_features["Flags"] = DEFAULT_G2_FEATURES
         case ("m6g.metal", _, None):
             expected_cpu_features["Flags"] = DEFAULT_G2_FEATURES
@@ -87,7 +87,7 @@ def _check_cpu_features_arm(test_microvm, guest_kv, template_name=None):
             expected_cpu_features["Flags"] = DEFAULT_G3_FEATURES_WITH_SVE_AND_PAC_4_14
         case ("c7g.metal", "5.10", "aarch64_with_sve_and_pac"):
             expected_cpu_features["Flags"] = DEFAULT_G3_FEATURES_WITH_SVE_AND_PAC_5_10
-        case ("c7g.metal", _, "aarch64_v1n1"):
+        case ("c7g.metal", _, "aarch64_v1n1" | "aarch64_v2n1"):
             expected_cpu_features["Flags"] = DEFAULT_G3_FEATURES_V1N1
         case ("c7g.metal", "4.14", None):
             expected_cpu_features["Flags"] = DEFAULT_G3_FEATURES_4_14
@@ -145,7 +145,7 @@ def test_cpu_features_with_static_template(
     vm.add_net_iface()
     vm.start()
     guest_kv = re.search(r"vmlinux-(\d+\.\d+)", guest_kernel.name).group(1)
-    _check_cpu_features_arm(vm, guest_kv, "aarch64_v1n1")
+    _check_cpu_features_arm(vm, guest_kv, f"aarch64_{cpu_template.lower()}")
 
 
 @pytest.mark.skipif(
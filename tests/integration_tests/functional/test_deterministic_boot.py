@@ -0,0 +1,60 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the deterministic boot mode."""
+
+import pytest
+
+
+def _boot_and_read_hwrng(microvm_factory, guest_kernel, rootfs, seed):
+    """Boot a microVM in deterministic mode and read bytes from its entropy device"""
+    vm = microvm_factory.build(guest_kernel, rootfs)
+    vm.spawn()
+    vm.basic_config(vcpu_count=2, mem_size_mib=256)
+    vm.add_net_iface()
+    vm.api.entropy.put()
+    vm.api.deterministic_boot.put(seed=seed, start_time_s=1600000000)
+    vm.start()
+
+    # The 5.10 guest kernel only reads a fixed amount of bytes from the device when
+    # registering it, so the stream read here starts at the same offset on every boot.
+    ecode, stdout, _ = vm.ssh.run("head -c 64 /dev/hwrng | od -An -tx1")
+    assert ecode == 0
+    vm.kill()
+    return stdout.split()
+
+
+def test_deterministic_boot_config(test_microvm_with_api):
+    """
+    Check the validation of the deterministic boot configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.deterministic_boot.put(start_time_s=2**32)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.deterministic_boot.put(seed=-1)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.deterministic_boot.put(seed=1, foo=True)
+    test_microvm.api.deterministic_boot.put(seed=1, start_time_s=2**32 - 1)
+
+
+def test_deterministic_entropy(
+    microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22
+):
+    """
+    Check that the entropy device returns the same bytes for the same seed.
+    """
+    first = _boot_and_read_hwrng(
+        microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22, 42
+    )
+    second = _boot_and_read_hwrng(
+        microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22, 42
+    )
+    other = _boot_and_read_hwrng(
+        microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22, 43
+    )
+
+    assert len(first) == 64
+    assert first == second
+    assert first != other
@@ -363,9 +363,254 @@ def test_flush(uvm_plain_rw):
     assert fc_metrics["block"]["flush_count"] > 0
 
 
+def test_writethrough(test_microvm_with_api):
+    """
+    Verify that a writethrough block does not advertise a write cache.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=1)
+    test_microvm.add_net_iface()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    test_microvm.add_drive("scratch", fs.path)
+    # The cache policy can be changed until the microVM is started.
+    test_microvm.api.drive.patch(drive_id="scratch", cache_type="Writethrough")
+    test_microvm.start()
+
+    _, stdout, stderr = test_microvm.ssh.run("cat /sys/block/vdb/queue/write_cache")
+    assert stderr == ""
+    assert stdout.strip() == "write through"
+
+    # The guest does not send flush requests to the drive.
+    cmd = "echo -n data | dd of=/dev/vdb bs=512 conv=sync,fsync"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["flush_count"] == 0
+
+    expected_msg = "can only be updated before boot"
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.drive.patch(drive_id="scratch", cache_type="Writeback")
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.drive.patch(drive_id="scratch", direct_io=True)
+
+
+def test_flush_drives(test_microvm_with_api):
+    """
+    Verify the FlushDrives action.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=1)
+    test_microvm.add_net_iface()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    test_microvm.add_drive("scratch", fs.path)
+
+    # The drives can only be flushed once the microVM is started.
+    with pytest.raises(RuntimeError):
+        test_microvm.api.actions.put(action_type="FlushDrives")
+    test_microvm.start()
+
+    # Write to the scratch drive, bypassing the page cache of the guest.
+    cmd = "echo -n flushed | dd of=/dev/vdb bs=512 conv=sync oflag=direct"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+
+    test_microvm.api.actions.put(action_type="FlushDrives", drive_ids=["scratch"])
+    with open(fs.path, "rb") as file:
+        assert file.read(7) == b"flushed"
+    test_microvm.api.actions.put(action_type="FlushDrives")
+
+    with pytest.raises(RuntimeError, match="Failed to find the device on the bus."):
+        test_microvm.api.actions.put(action_type="FlushDrives", drive_ids=["invalid"])
+    expected_msg = "Drive ids can only be set for the FlushDrives action."
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.actions.put(action_type="FlushMetrics", drive_ids=["scratch"])
+
+
+def test_page_cache_hints(test_microvm_with_api):
+    """
+    Verify the fadvise policy and read-ahead size of a drive.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=1)
+    test_microvm.add_net_iface()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    path_on_jail = test_microvm.create_jailed_resource(fs.path)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.drive.put(
+            drive_id="scratch",
+            path_on_host=path_on_jail,
+            is_root_device=False,
+            is_read_only=True,
+            fadvise="WillNeed",
+        )
+    test_microvm.api.drive.put(
+        drive_id="scratch",
+        path_on_host=path_on_jail,
+        is_root_device=False,
+        is_read_only=True,
+        fadvise="DontNeed",
+        readahead_kib=512,
+    )
+    test_microvm.start()
+
+    drive = test_microvm.api.vm_config.get().json()["drives"][1]
+    assert drive["fadvise"] == "DontNeed"
+    assert drive["readahead_kib"] == 512
+
+    # The reads of the guest are served from the drive.
+    cmd = "dd if=/dev/vdb of=/dev/null bs=1M count=4 iflag=direct"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["read_bytes"] >= 4 << 20
+
+
+@pytest.mark.skipif(
+    utils.is_io_uring_supported() is False, reason="io_uring is not supported"
+)
+def test_io_depth_limits(test_microvm_with_api):
+    """
+    Verify the io_uring ring size and the cap on requests in flight of a drive.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+    test_microvm.add_net_iface()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    path_on_jail = test_microvm.create_jailed_resource(fs.path)
+    drive = {
+        "drive_id": "scratch",
+        "path_on_host": path_on_jail,
+        "is_root_device": False,
+        "is_read_only": False,
+        "io_engine": "Async",
+    }
+    expected_msg = "Invalid io_uring ring size 100"
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.drive.put(**drive, io_uring_entries=100)
+    test_microvm.api.drive.put(**drive, io_uring_entries=16, max_in_flight_requests=4)
+    test_microvm.start()
+
+    config = test_microvm.api.vm_config.get().json()["drives"][1]
+    assert config["io_uring_entries"] == 16
+    assert config["max_in_flight_requests"] == 4
+
+    # Issue more parallel requests than the drive takes in flight.
+    cmd = (
+        "for i in $(seq 0 15); do "
+        "dd if=/dev/vdb of=/dev/null bs=4k count=64 skip=$((i * 64)) iflag=direct & "
+        "done; wait"
+    )
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["read_count"] > 0
+
+
+def test_busy_poll(test_microvm_with_api):
+    """
+    Verify that the drives and network interfaces poll their queue.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    path_on_jail = test_microvm.create_jailed_resource(fs.path)
+    drive = {
+        "drive_id": "scratch",
+        "path_on_host": path_on_jail,
+        "is_root_device": False,
+        "is_read_only": False,
+    }
+    with pytest.raises(RuntimeError, match="Invalid busy-poll window of 1001 us"):
+        test_microvm.api.drive.put(**drive, busy_poll_us=1001)
+    test_microvm.api.drive.put(**drive, busy_poll_us=100)
+    test_microvm.add_net_iface(busy_poll_us=100)
+    test_microvm.start()
+
+    config = test_microvm.api.vm_config.get().json()
+    assert config["drives"][1]["busy_poll_us"] == 100
+    assert config["network-interfaces"][0]["busy_poll_us"] == 100
+
+    cmd = "dd if=/dev/vdb of=/dev/null bs=4k count=256 iflag=direct"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["busy_poll_hits"] > 0
+    assert fc_metrics["net"]["tx_busy_poll_misses"] > 0
+
+
+def test_batch_size(test_microvm_with_api):
+    """
+    Verify that the drives and network interfaces report their notifications.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    path_on_jail = test_microvm.create_jailed_resource(fs.path)
+    drive = {
+        "drive_id": "scratch",
+        "path_on_host": path_on_jail,
+        "is_root_device": False,
+        "is_read_only": False,
+    }
+    with pytest.raises(RuntimeError, match="Invalid notification batch size 257"):
+        test_microvm.api.drive.put(**drive, batch_size=257)
+    test_microvm.api.drive.put(**drive, batch_size=4)
+    test_microvm.add_net_iface(batch_size=4)
+    test_microvm.start()
+
+    config = test_microvm.api.vm_config.get().json()
+    assert config["drives"][1]["batch_size"] == 4
+    assert config["network-interfaces"][0]["batch_size"] == 4
+
+    cmd = "dd if=/dev/vdb of=/dev/null bs=4k count=256 iflag=direct"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["interrupt_count"] > 0
+    assert fc_metrics["net"]["rx_interrupt_count"] > 0
+    assert fc_metrics["net"]["tx_interrupt_count"] > 0
+
+
+@pytest.mark.parametrize("io_engine", ["Sync", "Async"])
+def test_latency_metrics(test_microvm_with_api, io_engine):
+    """
+    Verify that the latencies of the requests of each drive are reported.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+    test_microvm.add_net_iface()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    test_microvm.add_drive("scratch", fs.path, io_engine=io_engine)
+    test_microvm.start()
+
+    cmd = "dd if=/dev/vdb of=/dev/null bs=4k count=256 iflag=direct"
+    ecode, _, _ = test_microvm.ssh.run(cmd)
+    assert ecode == 0
+    latencies = test_microvm.flush_metrics()["block_latencies"]
+    read = latencies["scratch"]["read"]
+    assert read["count"] >= 256
+    assert read["p50_us"] <= read["p95_us"] <= read["p99_us"] <= read["max_us"]
+    assert latencies["scratch"]["flush"]["count"] == 0
+    assert "rootfs" in latencies
+
+
 def test_block_default_cache_old_version(test_microvm_with_api):
     """
-    Verify that saving a snapshot for old versions works correctly.
+    Verify that saving a snapshot for a version without block cache type fails.
     """
     test_microvm = test_microvm_with_api
     test_microvm.spawn()
@@ -385,22 +630,16 @@ def test_block_default_cache_old_version(test_microvm_with_api):
     # Pause the VM to create the snapshot.
     test_microvm.pause()
 
-    # Create the snapshot for a version without block cache type.
-    test_microvm.api.snapshot_create.put(
-        mem_file_path="memfile",
-        snapshot_path="snapsfile",
-        snapshot_type="Full",
-        version="0.24.0",
-    )
-
-    # We should find a warning in the logs for this case as this
-    # cache type was not supported in 0.24.0 and we should default
-    # to "Unsafe" mode.
-    test_microvm.check_log_message(
-        "Target version does not implement the"
-        " current cache type. "
-        'Defaulting to "unsafe" mode.'
-    )
+    # The "Writeback" cache type was not supported in 0.24.0, so the snapshot
+    # cannot be created for this version.
+    expected_msg = r"cache type of block device rootfs \(since v0.25.0\)"
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.snapshot_create.put(
+            mem_file_path="memfile",
+            snapshot_path="snapsfile",
+            snapshot_type="Full",
+            version="0.24.0",
+        )
 
 
 def _check_block_size(ssh_connection, dev_path, size):
@@ -0,0 +1,101 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the GDB stub."""
+
+import platform
+import socket
+from pathlib import Path
+
+import pytest
+
+pytestmark = pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="The GDB stub is only supported on x86_64."
+)
+
+
+class GdbClient:
+    """Minimal client of the GDB remote serial protocol."""
+
+    def __init__(self, path):
+        self.sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        self.sock.settimeout(30)
+        self.sock.connect(str(path))
+
+    def _read_byte(self):
+        byte = self.sock.recv(1)
+        assert byte, "The stub closed the connection"
+        return byte
+
+    def send(self, packet):
+        """Send a packet, without waiting for the reply."""
+        checksum = sum(packet.encode()) % 256
+        self.sock.sendall(f"${packet}#{checksum:02x}".encode())
+
+    def recv(self):
+        """Read the next packet and acknowledge it."""
+        while self._read_byte() != b"$":
+            pass
+        data = b""
+        while (byte := self._read_byte()) != b"#":
+            data += byte
+        self._read_byte()
+        self._read_byte()
+        self.sock.sendall(b"+")
+        return data.decode()
+
+    def exchange(self, packet):
+        """Send a packet and return the reply."""
+        self.send(packet)
+        return self.recv()
+
+
+def test_gdb_config(test_microvm_with_api):
+    """
+    Check the validation of the GDB stub configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.gdb.put()
+    with pytest.raises(RuntimeError):
+        test_microvm.api.gdb.put(
+            socket_path="gdb.sock", tcp_address="127.0.0.1:1234"
+        )
+    with pytest.raises(RuntimeError):
+        test_microvm.api.gdb.put(socket_path="")
+    test_microvm.api.gdb.put(socket_path="gdb.sock")
+
+
+def test_gdb_session(uvm_nano):
+    """
+    Debug a microVM which waits for the debugger at boot.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.gdb.put(socket_path="gdb.sock")
+    microvm.api.actions.put(action_type="InstanceStart")
+
+    gdb = GdbClient(Path(microvm.chroot()) / "gdb.sock")
+    assert "swbreak+" in gdb.exchange("qSupported:swbreak+;hwbreak+")
+    assert microvm.state == "Paused"
+    assert gdb.exchange("?") == "T05thread:1;"
+    assert gdb.exchange("qfThreadInfo") == "m1,2"
+
+    # The general purpose registers, rip, eflags and the segment selectors.
+    regs = bytes.fromhex(gdb.exchange("g"))
+    assert len(regs) == 17 * 8 + 7 * 4
+    assert gdb.exchange("s")[:3] == "T05"
+
+    # Let the guest boot, then interrupt it.
+    gdb.send("c")
+    microvm.ssh.run("true")
+    gdb.sock.sendall(b"\x03")
+    assert gdb.recv().startswith("T02thread:")
+    assert microvm.state == "Paused"
+
+    # Detaching resumes the guest.
+    assert gdb.exchange("D") == "OK"
+    exit_code, _, _ = microvm.ssh.run("true")
+    assert exit_code == 0
+    assert microvm.state == "Running"
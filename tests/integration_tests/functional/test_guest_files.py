@@ -0,0 +1,87 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the copy of files from and to the guest."""
+
+import pytest
+
+AGENT_PORT = 52
+
+
+def test_guest_files_config(test_microvm_with_api):
+    """
+    Check the validation of the guest files configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.guest_files.put(port=AGENT_PORT)
+    with pytest.raises(RuntimeError, match="must be allowed"):
+        test_microvm.api.guest_files.put(
+            port=AGENT_PORT, host_dirs=["/srv"], guest_dirs=[]
+        )
+    with pytest.raises(RuntimeError, match="absolute paths"):
+        test_microvm.api.guest_files.put(
+            port=AGENT_PORT, host_dirs=["srv"], guest_dirs=["/workspace"]
+        )
+    with pytest.raises(RuntimeError):
+        test_microvm.api.guest_files.put(
+            port=AGENT_PORT,
+            host_dirs=["/srv"],
+            guest_dirs=["/workspace"],
+            max_file_size_mib=0,
+        )
+    test_microvm.api.guest_files.put(
+        port=AGENT_PORT, host_dirs=["/srv"], guest_dirs=["/workspace"]
+    )
+
+    # Files can only be copied once the microVM runs.
+    with pytest.raises(RuntimeError, match="not supported before starting"):
+        test_microvm.api.guest_files_copy.put(
+            direction="FromGuest",
+            guest_path="/workspace/out.tar",
+            host_path="/srv/out.tar",
+        )
+
+
+def test_guest_file_copy_rules(uvm_nano):
+    """
+    Check that the copies are refused out of the configured rules.
+    """
+    microvm = uvm_nano
+    microvm.api.vsock.put(vsock_id="vsock0", guest_cid=3, uds_path="/v.sock")
+    microvm.api.guest_files.put(
+        port=AGENT_PORT, host_dirs=["/srv"], guest_dirs=["/workspace"]
+    )
+    microvm.start()
+
+    with pytest.raises(RuntimeError, match="not allowed"):
+        microvm.api.guest_files_copy.put(
+            direction="ToGuest",
+            guest_path="/workspace/in.tar",
+            host_path="/srv/in.tar",
+        )
+    with pytest.raises(RuntimeError, match="not in an allowed directory"):
+        microvm.api.guest_files_copy.put(
+            direction="FromGuest",
+            guest_path="/etc/shadow",
+            host_path="/srv/shadow",
+        )
+    with pytest.raises(RuntimeError, match="not in an allowed directory"):
+        microvm.api.guest_files_copy.put(
+            direction="FromGuest",
+            guest_path="/workspace/out.tar",
+            host_path="/srv/../out.tar",
+        )
+
+    # No agent listens in the guest.
+    with pytest.raises(RuntimeError, match="Cannot connect to the guest agent"):
+        microvm.api.guest_files_copy.put(
+            direction="FromGuest",
+            guest_path="/workspace/out.tar",
+            host_path="/srv/out.tar",
+        )
+
+    fc_metrics = microvm.flush_metrics()
+    assert fc_metrics["guest_files"]["failures"] == 4
+    assert fc_metrics["guest_files"]["copies_from_guest"] == 0
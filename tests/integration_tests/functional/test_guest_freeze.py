@@ -0,0 +1,44 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the freeze of the guest filesystems."""
+
+import pytest
+
+AGENT_PORT = 53
+
+
+def test_guest_freeze_config(test_microvm_with_api):
+    """
+    Check the validation of the guest freeze configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.guest_freeze.put(timeout_ms=1000)
+    with pytest.raises(RuntimeError, match="greater than 0 ms"):
+        test_microvm.api.guest_freeze.put(port=AGENT_PORT, timeout_ms=0)
+    test_microvm.api.guest_freeze.put(port=AGENT_PORT)
+
+    # The filesystems can only be frozen once the microVM runs.
+    with pytest.raises(RuntimeError, match="not supported before starting"):
+        test_microvm.api.guest_freeze.patch(state="Frozen")
+
+
+def test_guest_freeze_without_agent(uvm_nano):
+    """
+    Check that the freeze fails when no agent listens in the guest.
+    """
+    microvm = uvm_nano
+    microvm.api.vsock.put(vsock_id="vsock0", guest_cid=3, uds_path="/v.sock")
+    microvm.api.guest_freeze.put(port=AGENT_PORT, timeout_ms=1000)
+    microvm.start()
+
+    with pytest.raises(RuntimeError, match="Cannot connect to the guest agent"):
+        microvm.api.guest_freeze.patch(state="Frozen")
+    with pytest.raises(RuntimeError):
+        microvm.api.guest_freeze.patch(state="Melted")
+
+    # The microVM can still be paused and resumed.
+    microvm.api.vm.patch(state="Paused")
+    microvm.api.vm.patch(state="Resumed")
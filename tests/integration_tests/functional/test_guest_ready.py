@@ -0,0 +1,42 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the guest readiness signal."""
+
+import platform
+import time
+
+# Address of the boot timer device, the first MMIO device.
+BOOT_TIMER_ADDRESS = 0x40000000 if platform.machine() == "aarch64" else 0xD0000000
+MAGIC_VALUE_SIGNAL_GUEST_READY = 124
+
+
+def test_guest_ready(uvm_plain):
+    """
+    Check that the guest signaling it is ready is reported in the instance state.
+    """
+    microvm = uvm_plain
+    microvm.jailer.extra_args.update({"boot-timer": None})
+    microvm.spawn()
+    microvm.basic_config()
+    microvm.add_net_iface()
+    microvm.start()
+    assert microvm.state == "Running"
+
+    microvm.ssh.run(
+        f"printf '\\{MAGIC_VALUE_SIGNAL_GUEST_READY:o}' | "
+        f"dd of=/dev/mem bs=1 seek={BOOT_TIMER_ADDRESS} count=1"
+    )
+    for _ in range(10):
+        if microvm.state == "Running:GuestReady":
+            break
+        time.sleep(0.1)
+    assert microvm.state == "Running:GuestReady"
+    assert "Guest-ready-time =" in microvm.log_data
+    fc_metrics = microvm.flush_metrics()
+    assert fc_metrics["vmm"]["guest_ready_time_us"] > 0
+
+    # The microVM is reported ready again once resumed.
+    microvm.pause()
+    assert microvm.state == "Paused"
+    microvm.resume()
+    assert microvm.state == "Running:GuestReady"
@@ -0,0 +1,183 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the identity of the microVM."""
+
+import base64
+import json
+import platform
+import uuid
+from pathlib import Path
+
+import pytest
+
+from framework.utils import (
+    configure_mmds,
+    generate_mmds_get_request,
+    run_cmd,
+    run_guest_cmd,
+)
+
+VM_UUID = "01234567-89ab-cdef-0123-456789abcdef"
+DEFAULT_IPV4 = "169.254.169.254"
+
+
+def signing_key(microvm):
+    """Create an Ed25519 private key, and return its path in the jail."""
+    key_path = Path(microvm.path) / "identity.key"
+    run_cmd(f"openssl genpkey -algorithm ed25519 -outform DER -out {key_path}")
+    return key_path
+
+
+def read_identity(microvm):
+    """Read the identity document published in MMDS."""
+    identity = microvm.api.mmds.get().json()["instance_identity"]
+    return identity, json.loads(identity["document"])
+
+
+def verify_signature(key_path, identity):
+    """Check the signature of the identity document with the public key."""
+    workdir = key_path.parent
+    (workdir / "document").write_text(identity["document"])
+    (workdir / "signature").write_bytes(base64.b64decode(identity["signature"]))
+    run_cmd(f"openssl pkey -inform DER -in {key_path} -pubout -out {workdir}/pub.pem")
+    run_cmd(
+        f"openssl pkeyutl -verify -pubin -inkey {workdir}/pub.pem -rawin "
+        f"-in {workdir}/document -sigfile {workdir}/signature"
+    )
+
+
+def read_guest_uuid(microvm):
+    """Read the UUID of the microVM exposed by the platform, as read at boot."""
+    if platform.machine() == "x86_64":
+        cmd = "cat /sys/class/dmi/id/product_uuid"
+    else:
+        cmd = "tr -d '\\0' < /proc/device-tree/chosen/firecracker,vm-uuid"
+    _, stdout, _ = microvm.ssh.run(cmd)
+    return stdout.strip()
+
+
+def test_identity_config(test_microvm_with_api):
+    """
+    Check the validation of the identity configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.identity.put(uuid="0123456789abcdef0123456789abcdef")
+    with pytest.raises(RuntimeError):
+        test_microvm.api.identity.put(signing_key_path="/no/such/key")
+    not_a_key = Path(test_microvm.path) / "not_a_key"
+    not_a_key.write_text("foo")
+    with pytest.raises(RuntimeError, match="is not a PKCS#8 encoded Ed25519"):
+        test_microvm.api.identity.put(
+            signing_key_path=test_microvm.create_jailed_resource(not_a_key)
+        )
+    with pytest.raises(RuntimeError):
+        test_microvm.api.identity.put(foo="bar")
+
+    # A UUID is generated when none is set.
+    test_microvm.api.identity.put(rotate_on_restore=True)
+    identity = test_microvm.api.vm_config.get().json()["identity"]
+    assert identity["rotate_on_restore"]
+    uuid.UUID(identity["uuid"])
+
+
+def test_identity_boot(uvm_nano):
+    """
+    Check that the guest reads its UUID and a signed identity document.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    configure_mmds(microvm, iface_ids=["eth0"], version="V1")
+    key_path = signing_key(microvm)
+    microvm.api.identity.put(
+        uuid=VM_UUID, signing_key_path=microvm.create_jailed_resource(key_path)
+    )
+    microvm.start()
+
+    assert read_guest_uuid(microvm) == VM_UUID
+
+    identity, document = read_identity(microvm)
+    assert document["uuid"] == VM_UUID
+    assert document["instance_id"] == microvm.api.describe.get().json()["id"]
+    verify_signature(key_path, identity)
+
+    run_guest_cmd(microvm.ssh, f"ip route add {DEFAULT_IPV4} dev eth0", "")
+    cmd = generate_mmds_get_request(DEFAULT_IPV4) + "instance_identity"
+    run_guest_cmd(microvm.ssh, cmd, identity, use_json=True)
+
+
+def test_identity_unsigned(uvm_nano):
+    """
+    Check that the identity document is not signed without a key.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    configure_mmds(microvm, iface_ids=["eth0"], version="V1")
+    microvm.api.identity.put()
+    microvm.start()
+
+    identity, document = read_identity(microvm)
+    assert "signature" not in identity
+    assert document["uuid"] == microvm.api.vm_config.get().json()["identity"]["uuid"]
+
+
+@pytest.mark.parametrize("rotate", [False, True])
+def test_identity_restore(uvm_nano, microvm_factory, rotate):
+    """
+    Check that a restored microVM keeps or renews its UUID as configured, and gets
+    a new identity document.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    configure_mmds(microvm, iface_ids=["eth0"], version="V1")
+    key_path = signing_key(microvm)
+    microvm.api.identity.put(
+        uuid=VM_UUID,
+        signing_key_path=microvm.create_jailed_resource(key_path),
+        rotate_on_restore=rotate,
+    )
+    microvm.start()
+    snapshot = microvm.snapshot_full()
+
+    restored_vm = microvm_factory.build()
+    restored_vm.spawn()
+    # The key is found at the same path in the jail of the restored microVM.
+    restored_vm.create_jailed_resource(key_path)
+    restored_vm.restore_from_snapshot(snapshot, resume=True)
+
+    restored_uuid = restored_vm.api.vm_config.get().json()["identity"]["uuid"]
+    assert (restored_uuid != VM_UUID) == rotate
+    identity, document = read_identity(restored_vm)
+    assert document["uuid"] == restored_uuid
+    assert document["instance_id"] == restored_vm.api.describe.get().json()["id"]
+    verify_signature(key_path, identity)
+    # The guest kernel keeps the UUID read at boot.
+    assert read_guest_uuid(restored_vm) == VM_UUID
+
+
+def test_identity_before_load(uvm_nano, microvm_factory):
+    """
+    Check that an identity set before loading a snapshot replaces the saved one.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    configure_mmds(microvm, iface_ids=["eth0"], version="V1")
+    microvm.api.identity.put(uuid=VM_UUID)
+    microvm.start()
+    snapshot = microvm.snapshot_full()
+
+    restored_vm = microvm_factory.build()
+    restored_vm.spawn()
+    key_path = signing_key(restored_vm)
+    new_uuid = str(uuid.uuid4())
+    restored_vm.api.identity.put(
+        uuid=new_uuid, signing_key_path=restored_vm.create_jailed_resource(key_path)
+    )
+    restored_vm.restore_from_snapshot(snapshot, resume=True)
+
+    assert restored_vm.api.vm_config.get().json()["identity"]["uuid"] == new_uuid
+    identity, document = read_identity(restored_vm)
+    assert document["uuid"] == new_uuid
+    verify_signature(key_path, identity)
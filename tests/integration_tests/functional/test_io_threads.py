@@ -0,0 +1,109 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the I/O threads processing the device events."""
+
+import os
+
+import pytest
+
+from framework import utils
+
+IO_THREADS = {
+    "thread_count": 2,
+    "devices": [
+        {"device_id": "rootfs", "thread": 0},
+        {"device_id": "eth0", "thread": 1},
+    ],
+}
+
+
+def check_io_threads(microvm):
+    """Check that the I/O threads run and serve the devices assigned to them."""
+    threads = utils.ProcessManager.get_threads(microvm.jailer_clone_pid)
+    assert threads["fc_io 0"]
+    assert threads["fc_io 1"]
+
+    _, stdout, _ = microvm.ssh.run(
+        "dd if=/dev/vda of=/dev/null bs=1M count=16 iflag=direct status=none"
+        " && echo ok"
+    )
+    assert stdout.strip() == "ok"
+
+
+def test_io_threads_config(test_microvm_with_api):
+    """
+    Check the validation of the I/O threads configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.io_threads.put()
+    with pytest.raises(RuntimeError):
+        test_microvm.api.io_threads.put(thread_count=0)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.io_threads.put(thread_count=17)
+    with pytest.raises(RuntimeError, match="which does not exist"):
+        test_microvm.api.io_threads.put(
+            thread_count=1, devices=[{"device_id": "rootfs", "thread": 1}]
+        )
+    with pytest.raises(RuntimeError, match="several I/O threads"):
+        test_microvm.api.io_threads.put(
+            thread_count=2,
+            devices=[
+                {"device_id": "rootfs", "thread": 0},
+                {"device_id": "rootfs", "thread": 1},
+            ],
+        )
+    test_microvm.api.io_threads.put(**IO_THREADS)
+
+    config = test_microvm.api.vm_config.get().json()
+    assert config["io-threads"] == IO_THREADS
+
+
+def test_io_threads_unknown_device(uvm_nano):
+    """
+    Check that the boot fails when a device assigned to an I/O thread is not
+    attached.
+    """
+    microvm = uvm_nano
+    microvm.api.io_threads.put(
+        thread_count=1, devices=[{"device_id": "scratch", "thread": 0}]
+    )
+    with pytest.raises(RuntimeError, match="not a block or network device"):
+        microvm.start()
+
+
+def test_io_threads(uvm_nano):
+    """
+    Check that the devices assigned to the I/O threads work, and that the I/O
+    scheduling settings apply to the I/O threads.
+    """
+    microvm = uvm_nano
+    microvm.api.machine_config.patch(io_scheduling={"policy": "batch", "nice": 5})
+    microvm.add_net_iface()
+    microvm.api.io_threads.put(**IO_THREADS)
+    microvm.start()
+
+    check_io_threads(microvm)
+    threads = utils.ProcessManager.get_threads(microvm.jailer_clone_pid)
+    for tid in threads["fc_io 0"] + threads["fc_io 1"]:
+        assert os.sched_getscheduler(tid) == os.SCHED_BATCH
+        assert os.getpriority(os.PRIO_PROCESS, tid) == 5
+
+
+def test_io_threads_snapshot(uvm_nano, microvm_factory):
+    """
+    Check that the devices of a restored microVM can be assigned to I/O threads.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.io_threads.put(**IO_THREADS)
+    microvm.start()
+    snapshot = microvm.snapshot_full()
+
+    restored_vm = microvm_factory.build()
+    restored_vm.spawn()
+    restored_vm.api.io_threads.put(**IO_THREADS)
+    restored_vm.restore_from_snapshot(snapshot, resume=True)
+    check_io_threads(restored_vm)
@@ -6,6 +6,7 @@ It checks the response of the API configuration calls and the logs that show
 up in the configured logging FIFO.
 """
 
+import json
 import re
 from pathlib import Path
 from time import strptime
@@ -201,6 +202,84 @@ def test_api_requests_logs(test_microvm_with_api):
     )
 
 
+def test_module_filters(test_microvm_with_api):
+    """
+    Test that the level of specific modules can be changed at runtime.
+    """
+    microvm = test_microvm_with_api
+    microvm.spawn(log_file=None)
+    microvm.basic_config()
+
+    log_path = Path(microvm.path) / "log"
+    log_path.touch()
+    microvm.api.logger.put(
+        log_path=microvm.create_jailed_resource(log_path),
+        level="Warning",
+        show_level=True,
+        show_log_origin=True,
+    )
+    microvm.log_file = log_path
+    # only works if log level is Debug
+    microvm.time_api_requests = False
+    microvm.start()
+
+    request_msg = 'The API server received a Get request on "/machine-config".'
+    microvm.api.machine_config.get()
+    assert request_msg not in microvm.log_data
+
+    # The API requests are logged at the Info level by the API server.
+    microvm.api.logger.patch(module_filters=[{"module": "api_server", "level": "Info"}])
+    microvm.api.machine_config.get()
+    microvm.check_log_message(request_msg)
+
+    # An empty list removes the filters.
+    microvm.api.logger.patch(module_filters=[])
+    count = microvm.log_data.count(request_msg)
+    microvm.api.machine_config.get()
+    assert microvm.log_data.count(request_msg) == count
+
+    with pytest.raises(RuntimeError, match="Invalid module path"):
+        microvm.api.logger.patch(module_filters=[{"module": "", "level": "Info"}])
+
+
+def test_json_format(test_microvm_with_api):
+    """
+    Test that each log entry is a JSON object with the JSON format.
+    """
+    microvm = test_microvm_with_api
+    microvm.spawn(log_file=None)
+    microvm.basic_config()
+
+    log_path = Path(microvm.path) / "log"
+    log_path.touch()
+    microvm.api.logger.put(
+        log_path=microvm.create_jailed_resource(log_path),
+        level="Info",
+        show_level=True,
+        show_log_origin=True,
+        format="Json",
+    )
+    microvm.log_file = log_path
+    # only works if log level is Debug
+    microvm.time_api_requests = False
+    microvm.start()
+
+    lines = microvm.log_data.splitlines()
+    assert len(lines) > 1
+    entries = [json.loads(line) for line in lines]
+    assert entries[0]["message"].startswith("Running Firecracker")
+    for entry in entries:
+        assert entry["level"] in ["ERROR", "WARN", "INFO"]
+        assert entry["vm_id"] == microvm.id
+        for key in ["timestamp", "module", "message", "thread", "file", "line"]:
+            assert key in entry
+    assert any(
+        entry["module"].startswith("api_server")
+        and "The API server received" in entry["message"]
+        for entry in entries
+    )
+
+
 # pylint: disable=W0102
 def _test_log_config(microvm, log_level="Info", show_level=True, show_origin=True):
     """Exercises different scenarios for testing the logging config."""
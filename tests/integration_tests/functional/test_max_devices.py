@@ -63,3 +63,48 @@ def test_attach_too_many_devices(test_microvm_with_api):
     )
     with pytest.raises(RuntimeError, match=error_str):
         test_microvm.start()
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="Firecracker supports 24 IRQs on x86_64."
+)
+def test_attach_devices_sharing_irqs(test_microvm_with_api):
+    """
+    Test attaching more devices than available IRQs with a custom MMIO layout.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    test_microvm.basic_config()
+    test_microvm.api.machine_config.patch(
+        mmio_layout={"slot_count": MAX_DEVICES_ATTACHED + 5, "gap_size_mib": 1024}
+    )
+    # Along with the rootfs, the network devices use `MAX_DEVICES_ATTACHED + 3` slots.
+    for _ in range(MAX_DEVICES_ATTACHED + 2):
+        test_microvm.add_net_iface()
+    test_microvm.start()
+
+    # The network devices sharing IRQ lines are operational.
+    for i in range(MAX_DEVICES_ATTACHED + 2):
+        exit_code, _, _ = test_microvm.ssh_iface(i).run("sync")
+        assert exit_code == 0
+
+    # The devices are allocated from the start of the 1 GiB gap.
+    _, stdout, _ = test_microvm.ssh.run("cat /proc/iomem")
+    assert "c0000000-c0000fff" in stdout
+
+
+def test_attach_too_many_slots(test_microvm_with_api):
+    """
+    Test attaching more devices than the slots of the MMIO layout.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    test_microvm.basic_config()
+    test_microvm.api.machine_config.patch(mmio_layout={"slot_count": 2})
+    for _ in range(2):
+        test_microvm.add_net_iface()
+
+    with pytest.raises(RuntimeError, match="All the 2 MMIO device slots are in use."):
+        test_microvm.start()
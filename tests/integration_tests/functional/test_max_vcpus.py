@@ -1,8 +1,16 @@
 # Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 # SPDX-License-Identifier: Apache-2.0
-"""Tests scenario for microvms with max vcpus(32)."""
+"""Tests scenario for microvms with max vcpus."""
+
+import platform
+
+import pytest
 
 MAX_VCPUS = 32
+# Largest vCPU count which does not need the x2APIC on x86_64.
+MAX_XAPIC_VCPUS = 254
+# Maximum vCPU count supported on x86_64.
+MAX_X2APIC_VCPUS = 1024
 
 
 def test_max_vcpus(test_microvm_with_api):
@@ -21,3 +29,56 @@ def test_max_vcpus(test_microvm_with_api):
     _, stdout, stderr = microvm.ssh.run(cmd)
     assert stderr == ""
     assert int(stdout) == MAX_VCPUS
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="The x2APIC is only used on x86_64"
+)
+@pytest.mark.parametrize("vcpu_count", [MAX_XAPIC_VCPUS + 2, MAX_X2APIC_VCPUS])
+def test_x2apic_vcpus(uvm_nano, vcpu_count):
+    """
+    Test that all the vCPUs of a guest with more vCPUs than the xAPIC can address
+    are online.
+    """
+    microvm = uvm_nano
+    # Leave room for the per-CPU areas of the guest kernel.
+    microvm.api.machine_config.patch(
+        vcpu_count=vcpu_count, mem_size_mib=2048, acpi=True, split_irqchip=True
+    )
+    microvm.add_net_iface()
+    microvm.start()
+
+    _, stdout, stderr = microvm.ssh.run("nproc")
+    assert stderr == ""
+    assert int(stdout) == vcpu_count
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="The x2APIC is only used on x86_64"
+)
+@pytest.mark.parametrize("acpi,split_irqchip", [(False, True), (True, False)])
+def test_x2apic_vcpus_requirements(uvm_nano, acpi, split_irqchip):
+    """
+    Test that a guest with more vCPUs than the xAPIC can address is rejected
+    without ACPI or the split irqchip.
+    """
+    microvm = uvm_nano
+    microvm.api.machine_config.patch(
+        vcpu_count=MAX_XAPIC_VCPUS + 2, acpi=acpi, split_irqchip=split_irqchip
+    )
+    with pytest.raises(RuntimeError, match="require both ACPI and the split"):
+        microvm.start()
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="The x2APIC is only used on x86_64"
+)
+def test_vcpus_requirements(uvm_nano):
+    """
+    Test that a guest with more than 32 vCPUs is rejected without ACPI nor the
+    split irqchip.
+    """
+    microvm = uvm_nano
+    microvm.api.machine_config.patch(vcpu_count=MAX_VCPUS + 1)
+    with pytest.raises(RuntimeError, match="require ACPI or the split irqchip"):
+        microvm.start()
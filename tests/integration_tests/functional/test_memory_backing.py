@@ -0,0 +1,27 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the memfd backing the guest memory."""
+
+import os
+
+import pytest
+
+
+def test_memory_backing_config(uvm_nano, tmp_path):
+    """
+    Check that the memory backing is reported by the API, and that a file
+    which is not a sealed memfd is rejected at boot.
+    """
+    microvm = uvm_nano
+    backing = tmp_path / "guest_mem"
+    backing.touch()
+    # The guest memory of `uvm_nano` is 256 MiB large.
+    os.truncate(backing, 256 << 20)
+    backing_path = microvm.create_jailed_resource(backing)
+    microvm.api.machine_config.patch(mem_backing_path=backing_path)
+
+    machine_config = microvm.api.vm_config.get().json()["machine-config"]
+    assert machine_config["mem_backing_path"] == backing_path
+
+    with pytest.raises(RuntimeError, match="Invalid guest memory backing"):
+        microvm.start()
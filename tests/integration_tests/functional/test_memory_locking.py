@@ -0,0 +1,94 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for locking the guest memory in host memory."""
+
+from pathlib import Path
+
+import pytest
+
+MEM_SIZE_MIB = 256
+# Leaves room for the memory Firecracker locks on top of the guest memory.
+MEMLOCK_LIMIT = 512 << 20
+
+
+def locked_memory_kib(microvm):
+    """Return the memory locked by the Firecracker process, in KiB."""
+    status = Path(f"/proc/{microvm.jailer_clone_pid}/status").read_text()
+    line = next(line for line in status.splitlines() if line.startswith("VmLck:"))
+    return int(line.split()[1])
+
+
+def spawn_locking_microvm(microvm, mem_lock):
+    """Spawn a microVM allowed to lock its memory, and configure it."""
+    microvm.jailer.resource_limits = [f"memlock={MEMLOCK_LIMIT}"]
+    microvm.spawn()
+    microvm.basic_config(vcpu_count=2, mem_size_mib=MEM_SIZE_MIB)
+    microvm.api.machine_config.patch(mem_lock=mem_lock)
+    microvm.add_net_iface()
+
+
+def test_mem_lock_all(uvm_plain):
+    """
+    Check that the whole guest memory is locked with `all`.
+    """
+    microvm = uvm_plain
+    spawn_locking_microvm(microvm, "all")
+    machine_config = microvm.api.vm_config.get().json()["machine-config"]
+    assert machine_config["mem_lock"] == "all"
+    microvm.start()
+
+    assert locked_memory_kib(microvm) >= MEM_SIZE_MIB << 10
+    _, stdout, _ = microvm.ssh.run("true")
+    assert stdout == ""
+
+
+def test_mem_lock_limit(uvm_plain):
+    """
+    Check that the boot fails with the limit when it is too low to lock the
+    guest memory.
+    """
+    microvm = uvm_plain
+    microvm.jailer.resource_limits = [f"memlock={1 << 20}"]
+    microvm.spawn()
+    microvm.basic_config(vcpu_count=2, mem_size_mib=MEM_SIZE_MIB)
+    microvm.api.machine_config.patch(mem_lock="hot")
+
+    with pytest.raises(RuntimeError, match="the RLIMIT_MEMLOCK limit of the process"):
+        microvm.start()
+
+
+def test_mem_lock_with_balloon(uvm_plain):
+    """
+    Check that the guest memory cannot be locked with a balloon device.
+    """
+    microvm = uvm_plain
+    spawn_locking_microvm(microvm, "all")
+    microvm.api.balloon.put(amount_mib=0, deflate_on_oom=True)
+
+    with pytest.raises(RuntimeError, match="balloon or a memory hotplug device"):
+        microvm.start()
+
+
+def test_mem_lock_hot_snapshot(uvm_plain, microvm_factory):
+    """
+    Check that the memory resident when a snapshot is created is locked
+    upfront when it is loaded.
+    """
+    microvm = uvm_plain
+    spawn_locking_microvm(microvm, "hot")
+    microvm.start()
+
+    # The memory locked on fault is accounted whole against the limit.
+    assert locked_memory_kib(microvm) >= MEM_SIZE_MIB << 10
+    snapshot = microvm.snapshot_full()
+
+    restored_vm = microvm_factory.build()
+    restored_vm.jailer.resource_limits = [f"memlock={MEMLOCK_LIMIT}"]
+    restored_vm.spawn()
+    restored_vm.restore_from_snapshot(snapshot, resume=True)
+
+    assert locked_memory_kib(restored_vm) >= MEM_SIZE_MIB << 10
+    machine_config = restored_vm.api.vm_config.get().json()["machine-config"]
+    assert machine_config["mem_lock"] == "hot"
+    _, stdout, _ = restored_vm.ssh.run("true")
+    assert stdout == ""
@@ -0,0 +1,53 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the reaction to the host memory pressure."""
+
+import pytest
+
+
+def test_memory_pressure_config(test_microvm_with_api):
+    """
+    Check the validation of the memory pressure configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.memory_pressure.put(path="/proc/pressure/memory")
+    with pytest.raises(RuntimeError):
+        test_microvm.api.memory_pressure.put(path="", action="Pause")
+    with pytest.raises(RuntimeError):
+        test_microvm.api.memory_pressure.put(
+            path="/proc/pressure/memory", action="Pause", window_ms=100
+        )
+    with pytest.raises(RuntimeError):
+        test_microvm.api.memory_pressure.put(
+            path="/proc/pressure/memory",
+            action="Pause",
+            stall_threshold_ms=2000,
+            window_ms=1000,
+        )
+    with pytest.raises(RuntimeError):
+        test_microvm.api.memory_pressure.put(
+            path="/proc/pressure/memory", action="InflateBalloon", balloon_step_mib=0
+        )
+    test_microvm.api.memory_pressure.put(
+        path="/proc/pressure/memory", action="Pause", recovery_ms=30000
+    )
+
+
+def test_memory_pressure_requires_balloon(test_microvm_with_api):
+    """
+    Check that inflating the balloon on memory pressure requires a balloon.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    test_microvm.api.memory_pressure.put(
+        source="MemoryEvents",
+        path="/proc/self/status",
+        action="InflateBalloon",
+    )
+
+    with pytest.raises(RuntimeError, match="requires a balloon device"):
+        test_microvm.start()
@@ -5,6 +5,7 @@
 import datetime
 import math
 import platform
+from pathlib import Path
 
 
 def test_flush_metrics(test_microvm_with_api):
@@ -23,6 +24,7 @@ def test_flush_metrics(test_microvm_with_api):
         "api_server",
         "balloon",
         "block",
+        "block_latencies",
         "deprecated_api",
         "get_api_requests",
         "i8042",
@@ -30,6 +32,7 @@ def test_flush_metrics(test_microvm_with_api):
         "logger",
         "mmds",
         "net",
+        "net_ifaces",
         "patch_api_requests",
         "put_api_requests",
         "seccomp",
@@ -39,6 +42,13 @@ def test_flush_metrics(test_microvm_with_api):
         "signals",
         "vsock",
         "entropy",
+        "memory_hotplug",
+        "shared_dirs",
+        "tpm",
+        "uffd",
+        "memory_pressure",
+        "guest_files",
+        "resources",
     ]
 
     if platform.machine() == "aarch64":
@@ -54,3 +64,44 @@ def test_flush_metrics(test_microvm_with_api):
     # Epoch.Regression test for:
     # https://github.com/firecracker-microvm/firecracker/issues/2639
     assert abs(utc_timestamp_ms - metrics["utc_timestamp_ms"]) < 1000
+
+
+def test_vcpu_exit_metrics(test_microvm_with_api):
+    """
+    Check that the KVM exits are counted for each vCPU.
+    """
+    microvm = test_microvm_with_api
+    microvm.spawn()
+    microvm.basic_config(vcpu_count=2)
+    microvm.add_net_iface()
+    microvm.start()
+    microvm.ssh.run("true")
+
+    exits = microvm.flush_metrics()["vcpu"]["exits"]
+    assert len(exits) == 2
+    # Booting the guest and serving the SSH connection go through the devices.
+    assert sum(vcpu["mmio_read"] + vcpu["mmio_write"] for vcpu in exits) > 0
+
+
+def test_metrics_labels(test_microvm_with_api):
+    """
+    Check that the instance ID and the labels are written with each flush.
+    """
+    microvm = test_microvm_with_api
+    microvm.spawn(metrics_path=None)
+    microvm.basic_config()
+
+    metrics_path = Path(microvm.path) / "metrics.ndjson"
+    metrics_path.touch()
+    microvm.api.metrics.put(
+        metrics_path=microvm.create_jailed_resource(metrics_path),
+        instance_id="vm-1",
+        labels={"pool": "a"},
+    )
+    microvm.metrics_file = metrics_path
+    microvm.start()
+
+    metrics = microvm.flush_metrics()
+    assert metrics["instance_id"] == "vm-1"
+    assert metrics["labels"] == {"pool": "a"}
+    assert "vcpu" in metrics
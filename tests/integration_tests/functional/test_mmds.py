@@ -643,6 +643,31 @@ def test_mmds_older_snapshot(
     )
 
 
+def test_mmds_restore_info(uvm_nano, microvm_factory):
+    """
+    Test that the restore information is published in MMDS on snapshot load.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    configure_mmds(basevm, iface_ids=["eth0"], version="V1")
+    basevm.start()
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    restore_info = {"ip": "192.168.0.3", "task_id": "task-42", "generation": 3}
+    microvm = microvm_factory.build()
+    microvm.spawn()
+    microvm.restore_from_snapshot(snapshot, resume=True, restore_info=restore_info)
+
+    expected = {"restore_info": restore_info}
+    assert microvm.api.mmds.get().json() == expected
+
+    ssh_connection = microvm.ssh
+    run_guest_cmd(ssh_connection, f"ip route add {DEFAULT_IPV4} dev eth0", "")
+    cmd = generate_mmds_get_request(DEFAULT_IPV4)
+    run_guest_cmd(ssh_connection, cmd, expected, use_json=True)
+
+
 def test_mmds_v2_negative(test_microvm_with_api):
     """
     Test invalid MMDS GET/PUT requests when using V2.
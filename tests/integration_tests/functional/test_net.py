@@ -54,6 +54,30 @@ def test_high_ingress_traffic(test_microvm_with_api):
     assert exit_code == 0
 
 
+def test_interface_metrics(test_microvm_with_api):
+    """
+    Verify that the counters of each network interface are reported.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    test_microvm.add_net_iface()
+    test_microvm.start()
+
+    exit_code, _, _ = test_microvm.ssh.run("echo success\n")
+    assert exit_code == 0
+
+    ifaces = test_microvm.flush_metrics()["net_ifaces"]
+    eth0 = ifaces["eth0"]
+    assert eth0["rx_packets"] > 0
+    assert eth0["tx_packets"] > 0
+    assert eth0["rx_bytes"] >= eth0["rx_packets"]
+    assert eth0["tx_bytes"] >= eth0["tx_packets"]
+    assert eth0["tap_read_eagain"] > 0
+    assert eth0["rx_ring_full_drops"] == 0
+    assert eth0["rx_rate_limiter_drops"] == 0
+
+
 def test_multi_queue_unsupported(test_microvm_with_api):
     """
     Creates multi-queue tap device and tries to add it to firecracker.
@@ -80,3 +104,26 @@ def test_multi_queue_unsupported(test_microvm_with_api):
             host_dev_name=tapname,
             guest_mac="AA:FC:00:00:00:01",
         )
+
+
+def test_pause_resume(test_microvm_with_api):
+    """
+    Check that a paused net device exchanges no frame until it is resumed.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    test_microvm.add_net_iface()
+    test_microvm.start()
+    guest_ip = test_microvm.iface["eth0"]["iface"].guest_ip
+    ping_cmd = f"{test_microvm.jailer.netns_cmd_prefix()} ping -c 1 -W 1 {guest_ip}"
+
+    test_microvm.api.network.patch(iface_id="eth0", state="Paused")
+    exit_code, _, _ = utils.run_cmd(ping_cmd, ignore_return_code=True)
+    assert exit_code != 0
+
+    test_microvm.api.network.patch(iface_id="eth0", state="Resumed")
+    exit_code, _, _ = utils.run_cmd(ping_cmd, ignore_return_code=True)
+    assert exit_code == 0
+    exit_code, _, _ = test_microvm.ssh.run("true")
+    assert exit_code == 0
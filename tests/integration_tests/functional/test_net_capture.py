@@ -0,0 +1,56 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the packet capture of the network interfaces."""
+
+import os
+import struct
+
+import pytest
+
+PCAP_MAGIC = 0xA1B2C3D4
+PCAP_HEADER_LEN = 24
+
+
+def check_pcap(path):
+    """Check that a pcap file holds Ethernet frames and return its size."""
+    with open(path, "rb") as pcap:
+        header = pcap.read(PCAP_HEADER_LEN)
+    magic, major, minor, _, _, _, linktype = struct.unpack("=IHHiIII", header)
+    assert (magic, major, minor, linktype) == (PCAP_MAGIC, 2, 4, 1)
+    return os.path.getsize(path)
+
+
+def test_net_capture(uvm_nano):
+    """
+    Check the capture of the frames of a network interface, and its updates.
+    """
+    microvm = uvm_nano
+    iface = microvm.add_net_iface(capture={"pcap_path": "/eth0.pcap"})
+    microvm.start()
+
+    # The SSH connection exchanges frames with the guest.
+    microvm.ssh.run("true")
+    pcap_path = os.path.join(microvm.jailer.chroot_path(), "eth0.pcap")
+    assert check_pcap(pcap_path) > PCAP_HEADER_LEN
+    assert microvm.flush_metrics()["net"]["capture_frames"] > 0
+
+    # A new capture, which stops once 1 KiB is written.
+    microvm.api.network.patch(
+        iface_id=iface.dev_name,
+        capture={"pcap_path": "/eth0_limited.pcap", "max_size_bytes": 1024},
+    )
+    microvm.ssh.run("dd if=/dev/zero bs=1M count=1 status=none")
+    limited_path = os.path.join(microvm.jailer.chroot_path(), "eth0_limited.pcap")
+    assert PCAP_HEADER_LEN < check_pcap(limited_path) <= 1024
+
+    # Without output, the capture is stopped.
+    microvm.api.network.patch(iface_id=iface.dev_name, capture={})
+    size = check_pcap(limited_path)
+    microvm.ssh.run("true")
+    assert check_pcap(limited_path) == size
+
+    with pytest.raises(RuntimeError, match="exclusively"):
+        microvm.api.network.patch(
+            iface_id=iface.dev_name,
+            capture={"pcap_path": "/foo.pcap", "socket_path": "/foo.sock"},
+        )
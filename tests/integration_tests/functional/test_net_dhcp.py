@@ -0,0 +1,67 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the DHCP server and DNS stub of the network interfaces."""
+
+import pytest
+
+# Sends an A query for `build.internal` to the gateway, and prints the address
+# of the answer, or the response code.
+DNS_QUERY_SCRIPT = """
+import socket, struct
+query = struct.pack('>HHHHHH', 0x1234, 0x0100, 1, 0, 0, 0)
+for label in '{}'.split('.'):
+    query += bytes([len(label)]) + label.encode()
+query += bytes([0]) + struct.pack('>HH', 1, 1)
+sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+sock.settimeout(5)
+sock.sendto(query, ('{}', 53))
+reply = sock.recv(512)
+answers = struct.unpack('>H', reply[6:8])[0]
+print(socket.inet_ntoa(reply[-4:]) if answers else 'rcode %d' % (reply[3] & 0xf))
+"""
+
+
+def dns_lookup(microvm, name, gateway):
+    """Resolve `name` with the DNS stub, from the guest."""
+    script = DNS_QUERY_SCRIPT.format(name, gateway)
+    exit_code, stdout, stderr = microvm.ssh.run(f'python3 -c "{script}"')
+    assert exit_code == 0, stderr
+    return stdout.strip()
+
+
+def test_net_dhcp_dns_stub(uvm_nano):
+    """
+    Check that the DNS stub answers the queries sent to the gateway.
+    """
+    microvm = uvm_nano
+    iface = microvm.add_net_iface(
+        dhcp={
+            "guest_ip": "192.168.0.2",
+            "netmask": "255.255.255.252",
+            "gateway": "192.168.0.1",
+            "nameservers": ["1.1.1.1"],
+            "dns_records": {"build.internal": "10.0.0.1"},
+        }
+    )
+    microvm.start()
+
+    assert dns_lookup(microvm, "build.internal", iface.host_ip) == "10.0.0.1"
+    assert dns_lookup(microvm, "BUILD.internal", iface.host_ip) == "10.0.0.1"
+    # Unknown names are refused.
+    assert dns_lookup(microvm, "example.com", iface.host_ip) == "rcode 5"
+    assert microvm.flush_metrics()["net"]["dns_replies"] > 0
+
+
+def test_net_dhcp_invalid_config(uvm_nano):
+    """
+    Check that an invalid DHCP configuration is rejected.
+    """
+    microvm = uvm_nano
+    with pytest.raises(RuntimeError, match="not in the subnet"):
+        microvm.add_net_iface(
+            dhcp={
+                "guest_ip": "192.168.0.2",
+                "netmask": "255.255.255.252",
+                "gateway": "10.0.0.1",
+            }
+        )
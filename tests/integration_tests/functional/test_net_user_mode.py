@@ -0,0 +1,102 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the user-mode network stack of the network interfaces."""
+
+import subprocess
+
+import pytest
+
+from framework import utils
+
+GUEST_IP = "10.0.2.15"
+GATEWAY = "10.0.2.2"
+
+# Prints the first line sent by the server listening on the given address.
+BANNER_SCRIPT = """
+import socket
+sock = socket.create_connection(('{}', {}), timeout=5)
+print(sock.makefile().readline().strip())
+"""
+
+# Sends a line to the first client of 127.0.0.1:8000, once ready.
+SERVER_SCRIPT = """
+import socket
+sock = socket.create_server(('127.0.0.1', 8000))
+print('ready', flush=True)
+sock.accept()[0].sendall(b'hello\\n')
+"""
+
+
+def read_banner(prefix, address, port):
+    """Connect to `address` and return the first line sent by the server."""
+    script = BANNER_SCRIPT.format(address, port)
+    _, stdout, _ = utils.run_cmd(f'{prefix} python3 -c "{script}"')
+    return stdout.strip()
+
+
+def add_user_net_iface(microvm, **kwargs):
+    """Add a second interface, backed by the user-mode network stack."""
+    microvm.api.network.put(
+        iface_id="eth1",
+        guest_mac="06:00:0a:00:02:0f",
+        user_net={"guest_ip": GUEST_IP, "gateway": GATEWAY, **kwargs},
+    )
+
+
+def test_net_user_mode(uvm_nano):
+    """
+    Check that the user-mode network stack relays the guest connections and
+    the forwarded host ports.
+    """
+    microvm = uvm_nano
+    # The first interface, backed by a tap, is used for SSH.
+    microvm.add_net_iface()
+    add_user_net_iface(
+        microvm,
+        host_loopback_ports=[8000],
+        port_forwards=[{"host_port": 2222, "guest_port": 22}],
+    )
+    microvm.start()
+    exit_code, _, stderr = microvm.ssh.run(
+        f"ip addr add {GUEST_IP}/24 dev eth1 && ip link set eth1 up"
+    )
+    assert exit_code == 0, stderr
+
+    # The forwarded host port reaches the SSH server of the guest.
+    netns_prefix = microvm.jailer.netns_cmd_prefix()
+    assert read_banner(netns_prefix, "127.0.0.1", 2222).startswith("SSH-")
+
+    # The connections to the relayed port of the gateway reach the host loopback
+    # interface.
+    with subprocess.Popen(
+        f'{netns_prefix} python3 -c "{SERVER_SCRIPT}"',
+        shell=True,
+        stdout=subprocess.PIPE,
+        text=True,
+    ) as server:
+        assert server.stdout.readline().strip() == "ready"
+        exit_code, stdout, stderr = microvm.ssh.run(
+            'python3 -c "{}"'.format(BANNER_SCRIPT.format(GATEWAY, 8000))
+        )
+    assert exit_code == 0, stderr
+    assert stdout.strip() == "hello"
+
+    assert microvm.flush_metrics()["net"]["user_net_tcp_conns"] >= 2
+
+
+def test_net_user_mode_invalid_config(uvm_nano):
+    """
+    Check that an invalid user-mode network configuration is rejected.
+    """
+    microvm = uvm_nano
+    with pytest.raises(RuntimeError):
+        microvm.api.network.put(
+            iface_id="eth1",
+            user_net={"guest_ip": GATEWAY, "gateway": GATEWAY},
+        )
+    with pytest.raises(RuntimeError):
+        microvm.api.network.put(
+            iface_id="eth1",
+            host_dev_name="tap1",
+            user_net={"guest_ip": GUEST_IP, "gateway": GATEWAY},
+        )
@@ -4,6 +4,9 @@
 
 import pytest
 
+from framework import utils
+from framework.artifacts import NetIfaceConfig
+
 
 def verify_net_emulation_paused(metrics):
     """Verify net emulation is paused based on provided metrics."""
@@ -86,6 +89,33 @@ def test_pause_resume(uvm_nano):
     microvm.kill()
 
 
+def test_pause_lite(uvm_nano):
+    """
+    Test that a microVM paused with `PausedLite` answers ARP and ping.
+    """
+    microvm = uvm_nano
+    iface = NetIfaceConfig.with_id(0)
+    microvm.add_net_iface(
+        iface, pause_responder={"guest_ip": iface.guest_ip, "icmp_echo": True}
+    )
+    microvm.start()
+    netns_prefix = microvm.jailer.netns_cmd_prefix()
+    ping_cmd = f"{netns_prefix} ping -c 1 -W 1 {iface.guest_ip}"
+
+    microvm.api.vm.patch(state="PausedLite")
+    assert "Paused" in microvm.api.describe.get().text
+
+    # The guest MAC address is resolved again while the guest is paused.
+    utils.run_cmd(f"{netns_prefix} ip neigh flush dev {iface.tap_name}")
+    exit_code, _, _ = utils.run_cmd(ping_cmd, ignore_return_code=True)
+    assert exit_code == 0
+    assert microvm.flush_metrics()["net"]["pause_replies"] >= 2
+
+    microvm.api.vm.patch(state="Resumed")
+    exit_code, _, _ = microvm.ssh.run("true")
+    assert exit_code == 0
+
+
 def test_describe_instance(uvm_nano):
     """
     Test scenario: DescribeInstance different states.
@@ -0,0 +1,99 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the SMBIOS identification of the microVM."""
+
+import platform
+import uuid
+
+import pytest
+
+pytestmark = pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="SMBIOS is only supported on x86_64"
+)
+
+SMBIOS_CONFIG = {
+    "manufacturer": "ACME",
+    "product_name": "Rocket",
+    "version": "1.0",
+    "serial_number": "SN-42",
+}
+VM_UUID = "01234567-89ab-cdef-0123-456789abcdef"
+
+
+def read_dmi(microvm, field):
+    """Read a DMI field as cached by the guest kernel at boot."""
+    _, stdout, _ = microvm.ssh.run(f"cat /sys/class/dmi/id/{field}")
+    return stdout.strip()
+
+
+def raw_table_contains(microvm, data):
+    """Check whether the SMBIOS structure table in guest memory contains `data`."""
+    _, stdout, _ = microvm.ssh.run("od -An -v -tx1 /sys/firmware/dmi/tables/DMI")
+    return data.hex() in "".join(stdout.split())
+
+
+def test_smbios_config(test_microvm_with_api):
+    """
+    Check the validation of the SMBIOS configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.smbios.put(manufacturer="")
+    with pytest.raises(RuntimeError):
+        test_microvm.api.smbios.put(product_name="a" * 65)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.smbios.put(uuid=VM_UUID)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.smbios.put(asset_tag="foo")
+
+    test_microvm.api.smbios.put(manufacturer="ACME")
+    smbios = test_microvm.api.vm_config.get().json()["smbios"]
+    assert smbios == {"manufacturer": "ACME"}
+
+
+def test_smbios_strings(uvm_nano):
+    """
+    Check that the guest reads the configured identification.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.smbios.put(**SMBIOS_CONFIG)
+    microvm.api.identity.put(uuid=VM_UUID)
+    microvm.start()
+
+    assert read_dmi(microvm, "sys_vendor") == "ACME"
+    assert read_dmi(microvm, "product_name") == "Rocket"
+    assert read_dmi(microvm, "product_version") == "1.0"
+    assert read_dmi(microvm, "product_serial") == "SN-42"
+    assert read_dmi(microvm, "product_uuid") == VM_UUID
+    assert read_dmi(microvm, "bios_vendor") == "Firecracker"
+
+
+@pytest.mark.parametrize("rotate", [False, True])
+def test_smbios_restore(uvm_nano, microvm_factory, rotate):
+    """
+    Check that the tables of a restored microVM hold its current UUID.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.smbios.put(**SMBIOS_CONFIG)
+    microvm.api.identity.put(uuid=VM_UUID, rotate_on_restore=rotate)
+    microvm.start()
+    assert raw_table_contains(microvm, uuid.UUID(VM_UUID).bytes_le)
+
+    snapshot = microvm.snapshot_full()
+    restored_vm = microvm_factory.build()
+    restored_vm.spawn()
+    restored_vm.restore_from_snapshot(snapshot)
+    restored_vm.resume()
+
+    vm_config = restored_vm.api.vm_config.get().json()
+    assert vm_config["smbios"] == SMBIOS_CONFIG
+    restored_uuid = uuid.UUID(vm_config["identity"]["uuid"])
+    assert (str(restored_uuid) != VM_UUID) == rotate
+    # The tables in guest memory are rewritten, while the values cached by the guest
+    # kernel at boot are left untouched.
+    assert raw_table_contains(restored_vm, restored_uuid.bytes_le)
+    assert read_dmi(restored_vm, "product_uuid") == VM_UUID
@@ -3,16 +3,22 @@
 """Basic tests scenarios for snapshot save/restore."""
 
 import filecmp
+import hashlib
+import json
 import logging
 import os
 import re
+import subprocess
+from concurrent.futures import ThreadPoolExecutor
 from pathlib import Path
 
 import pytest
+from retry.api import retry_call
 
 import host_tools.drive as drive_tools
-from framework.microvm import SnapshotType
-from framework.utils import check_filesystem, wait_process_termination
+from framework.artifacts import NetIfaceConfig
+from framework.microvm import Snapshot, SnapshotType
+from framework.utils import check_filesystem, run_cmd, wait_process_termination
 from framework.utils_vsock import (
     ECHO_SERVER_PORT,
     VSOCK_UDS_PATH,
@@ -156,6 +162,287 @@ def test_patch_drive_snapshot(uvm_nano, microvm_factory):
     assert guest_drive_size == str(scratch_disk2.size())
 
 
+def test_clone_from_snapshot(uvm_nano, microvm_factory):
+    """
+    Test that a clone is attached to the given tap and drive, and gets its MMDS.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.api.mmds_config.put(network_interfaces=["eth0"])
+    scratch_disk = drive_tools.FilesystemFile(
+        str(Path(basevm.path) / "scratch"), size=64
+    )
+    basevm.add_drive("scratch", scratch_disk.path)
+    basevm.start()
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    # The clone keeps the guest addresses of the snapshot, behind another tap.
+    vm.add_net_iface(NetIfaceConfig(tap_name="clonetap0"), api=False)
+    vm.ssh_key = snapshot.ssh_key
+    vm.disks = snapshot.disks
+    for disk in snapshot.disks.values():
+        vm.create_jailed_resource(disk)
+    clone_disk = drive_tools.FilesystemFile(
+        str(Path(vm.path) / "clone_scratch"), size=64
+    )
+    vm.api.clone.put(
+        snapshot_path=vm.create_jailed_resource(snapshot.vmstate),
+        mem_backend={
+            "backend_type": "File",
+            "backend_path": vm.create_jailed_resource(snapshot.mem),
+        },
+        network_overrides=[{"iface_id": "eth0", "host_dev_name": "clonetap0"}],
+        drive_overrides=[
+            {
+                "drive_id": "scratch",
+                "path_on_host": vm.create_jailed_resource(clone_disk.path),
+            }
+        ],
+        mmds={"task": "task-42"},
+        restore_info={"generation": 1},
+    )
+    assert vm.state == "Running"
+    assert vm.api.mmds.get().json() == {
+        "task": "task-42",
+        "restore_info": {"generation": 1},
+    }
+
+    # The guest writes to the drive override, not to the drive of the snapshot.
+    exit_code, _, _ = vm.ssh.run(
+        "echo -n clone | dd of=/dev/vdb bs=512 count=1 conv=sync,fsync"
+    )
+    assert exit_code == 0
+    with open(clone_disk.path, "rb") as clone_file:
+        assert clone_file.read(5) == b"clone"
+    with open(scratch_disk.path, "rb") as scratch_file:
+        assert scratch_file.read(5) != b"clone"
+
+
+def test_commit_warm_snapshot(uvm_nano, microvm_factory):
+    """
+    Test that a paused restored microVM gets the committed parameters and resumes.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.api.mmds_config.put(network_interfaces=["eth0"])
+    basevm.start()
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    # Commit is only accepted after a snapshot load.
+    with pytest.raises(RuntimeError, match="not supported before starting"):
+        vm.api.snapshot_commit.put()
+    vm.restore_from_snapshot(snapshot, resume=False)
+    assert vm.state == "Paused"
+
+    vm.api.snapshot_commit.put(mmds={"task": "task-42"}, restore_info={"seed": 7})
+    assert vm.state == "Running"
+    assert vm.api.mmds.get().json() == {
+        "task": "task-42",
+        "restore_info": {"seed": 7},
+    }
+    exit_code, _, _ = vm.ssh.run("true")
+    assert exit_code == 0
+
+    # A running microVM cannot be committed again.
+    with pytest.raises(RuntimeError, match="Only a paused microVM can be committed"):
+        vm.api.snapshot_commit.put()
+
+
+def test_suspend_to_disk(uvm_nano, microvm_factory):
+    """
+    Test that a microVM suspended to disk stops Firecracker and can be restored.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.start()
+
+    # A failed suspend keeps the microVM running.
+    with pytest.raises(RuntimeError, match="Diff snapshots are not allowed"):
+        basevm.api.snapshot_suspend.put(
+            snapshot_type="Diff",
+            snapshot_path="vmstate",
+            mem_file_path="mem",
+            marker_path="suspended.json",
+        )
+    assert basevm.state == "Running"
+
+    basevm.api.snapshot_suspend.put(
+        snapshot_path="vmstate", mem_file_path="mem", marker_path="suspended.json"
+    )
+    wait_process_termination(basevm.jailer_clone_pid)
+
+    root = Path(basevm.chroot())
+    marker = json.loads((root / "suspended.json").read_text(encoding="utf-8"))
+    assert marker["snapshot_type"] == "Full"
+    assert marker["snapshot_path"] == "vmstate"
+    assert marker["mem_file_path"] == "mem"
+    assert not (root / "suspended.json.tmp").exists()
+
+    snapshot = Snapshot(
+        vmstate=root / "vmstate",
+        mem=root / "mem",
+        disks=basevm.disks,
+        net_ifaces=[x["iface"] for x in basevm.iface.values()],
+        ssh_key=basevm.ssh_key,
+        snapshot_type=SnapshotType.FULL,
+    )
+    vm = microvm_factory.build()
+    vm.spawn()
+    vm.restore_from_snapshot(snapshot, resume=True)
+    exit_code, _, _ = vm.ssh.run("true")
+    assert exit_code == 0
+
+
+def test_load_snapshot_readahead(uvm_nano, microvm_factory):
+    """
+    Test that the memory file is read ahead in the background of a restored microVM.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.start()
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    vm.restore_from_snapshot(snapshot, resume=True, readahead_mib_per_s=1024)
+    exit_code, _, _ = vm.ssh.run("true")
+    assert exit_code == 0
+    vm.check_log_message("Requested the readahead of the whole memory file")
+
+
+def test_load_snapshot_http(uvm_nano, microvm_factory):
+    """
+    Test restoring a microVM whose memory is fetched from an HTTP server.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.start()
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    # The server runs in the network namespace of Firecracker.
+    netns_cmd = vm.jailer.netns_cmd_prefix()
+    run_cmd(f"{netns_cmd}ip link set lo up")
+    server_path = Path(__file__).parents[2] / "host_tools" / "http_range_server.py"
+    server = subprocess.Popen(
+        f"{netns_cmd}python3 {server_path} {snapshot.mem} 8080".split(),
+        stdout=subprocess.PIPE,
+    )
+    try:
+        assert server.stdout.readline() == b"ready\n"
+        vm.restore_from_snapshot(
+            snapshot,
+            resume=True,
+            mem_url="http://127.0.0.1:8080/mem",
+            mem_cache_path=Path("/mem_cache"),
+        )
+        exit_code, _, _ = vm.ssh.run("sync")
+        assert exit_code == 0
+    finally:
+        server.kill()
+        server.wait()
+
+    vm.flush_metrics()
+    all_metrics = vm.get_all_metrics()
+    assert sum(m["uffd"]["http_fetched_bytes"] for m in all_metrics) > 0
+    assert sum(m["uffd"]["http_fetch_failures"] for m in all_metrics) == 0
+    assert (Path(vm.chroot()) / "mem_cache").stat().st_size == os.path.getsize(
+        snapshot.mem
+    )
+
+
+def test_load_snapshot_manifest(uvm_plain, microvm_factory):
+    """
+    Test restoring a full snapshot and a diff snapshot layered by a manifest.
+    """
+    basevm = uvm_plain
+    basevm.spawn()
+    basevm.basic_config(track_dirty_pages=True)
+    basevm.add_net_iface()
+    basevm.start()
+    basevm.snapshot_full()
+    base_chroot = Path(basevm.chroot())
+    (base_chroot / "mem").rename(base_chroot / "mem.0")
+    basevm.resume()
+    # /dev/shm is kept in the guest memory: only the diff snapshot holds the file.
+    exit_code, _, _ = basevm.ssh.run("echo layered > /dev/shm/layered")
+    assert exit_code == 0
+    snapshot = basevm.snapshot_diff()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    chroot = Path(vm.chroot())
+    # The holes of the diff memory file are the pages it does not hold.
+    for name in ["mem.0", "mem", "vmstate"]:
+        run_cmd(f"cp --sparse=always {base_chroot / name} {chroot / name}")
+    jailed_rootfs = vm.create_jailed_resource(snapshot.disks["rootfs"])
+    vm.disks = snapshot.disks
+    vm.ssh_key = snapshot.ssh_key
+    for iface in snapshot.net_ifaces:
+        vm.add_net_iface(iface, api=False)
+    rootfs_sha256 = hashlib.sha256(snapshot.disks["rootfs"].read_bytes()).hexdigest()
+    manifest = {
+        "vmstate": "vmstate",
+        "memory_layers": ["mem.0", "mem"],
+        "rootfs": {"path": jailed_rootfs, "sha256": rootfs_sha256},
+    }
+    (chroot / "manifest.json").write_text(json.dumps(manifest))
+
+    vm.api.snapshot_load.put(manifest_path="/manifest.json", resume_vm=True)
+    _, stdout, _ = vm.ssh.run("cat /dev/shm/layered")
+    assert stdout.strip() == "layered"
+
+
+def test_load_snapshot_fewer_vcpus(uvm_nano, microvm_factory):
+    """
+    Test restoring a snapshot with the vCPUs taken offline by the guest parked.
+    """
+    basevm = uvm_nano
+    basevm.add_net_iface()
+    basevm.start()
+
+    # vCPU 1 is still online, it cannot be parked.
+    snapshot = basevm.snapshot_full()
+    vm = microvm_factory.build()
+    vm.spawn()
+    with pytest.raises(RuntimeError, match="vCPU 1 cannot be parked"):
+        vm.restore_from_snapshot(snapshot, resume=True, vcpu_count=1)
+    wait_process_termination(vm.jailer_clone_pid)
+
+    basevm.resume()
+    exit_code, _, _ = basevm.ssh.run("echo 0 > /sys/devices/system/cpu/cpu1/online")
+    assert exit_code == 0
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.spawn()
+    vm.restore_from_snapshot(snapshot, resume=True, vcpu_count=1)
+    _, stdout, _ = vm.ssh.run("cat /sys/devices/system/cpu/online")
+    assert stdout.strip() == "0"
+
+    # The snapshots of the restored microVM keep the parked vCPU.
+    parked_snapshot = vm.snapshot_full()
+    vm.kill()
+    vm = microvm_factory.build()
+    vm.spawn()
+    vm.restore_from_snapshot(parked_snapshot, resume=True)
+    exit_code, _, _ = vm.ssh.run("echo 1 > /sys/devices/system/cpu/cpu1/online")
+    assert exit_code == 0
+    _, stdout, _ = vm.ssh.run("cat /sys/devices/system/cpu/online")
+    assert stdout.strip() == "0-1"
+
+
 def test_load_snapshot_failure_handling(test_microvm_with_api):
     """
     Test error case of loading empty snapshot files.
@@ -349,6 +636,87 @@ def test_negative_snapshot_create(uvm_nano):
     vm.kill()
 
 
+def test_snapshot_create_timeout(uvm_nano):
+    """
+    Test that a snapshot creation blocked on its files times out.
+    """
+    vm = uvm_nano
+    vm.start()
+    vm.api.vm.patch(state="Paused")
+
+    # Opening a FIFO for writing blocks until it is opened for reading, as a
+    # stuck filesystem would.
+    fifo = Path(vm.chroot()) / "memfile"
+    os.mkfifo(fifo)
+    os.chown(fifo, vm.jailer.uid, vm.jailer.gid)
+    with pytest.raises(RuntimeError) as exc_info:
+        vm.api.snapshot_create.put(
+            mem_file_path="memfile", snapshot_path="statefile", timeout_ms=500
+        )
+    assert exc_info.value.args[1]["fault_code"] == "Timeout"
+
+    # The API keeps serving requests while the VMM is blocked.
+    with pytest.raises(RuntimeError) as exc_info:
+        vm.api.vm.patch(state="Resumed")
+    assert exc_info.value.args[1]["fault_code"] == "VmmBusy"
+
+    # Once unblocked, the snapshot creation fails, since the memory cannot be
+    # written to a FIFO, and the microVM can be resumed.
+    with open(fifo, "rb") as reader:
+        reader.read()
+    retry_call(
+        vm.api.vm.patch,
+        fkwargs={"state": "Resumed"},
+        exceptions=RuntimeError,
+        delay=0.1,
+        tries=10,
+    )
+
+    vm.flush_metrics()
+    timeouts = sum(
+        metrics["api_server"]["sync_vmm_send_timeout_count"]
+        for metrics in vm.get_all_metrics()
+    )
+    assert timeouts == 1
+
+
+def test_api_responsive_during_snapshot_create(uvm_nano):
+    """
+    Test that the health checks are answered while a snapshot is created.
+    """
+    vm = uvm_nano
+    vm.start()
+    vm.api.vm.patch(state="Paused")
+    assert vm.api.describe.get().json()["state"] == "Paused"
+
+    fifo = Path(vm.chroot()) / "memfile"
+    os.mkfifo(fifo)
+    os.chown(fifo, vm.jailer.uid, vm.jailer.gid)
+    with ThreadPoolExecutor(max_workers=1) as executor:
+        create = executor.submit(
+            vm.api.snapshot_create.put,
+            mem_file_path="memfile",
+            snapshot_path="statefile",
+        )
+
+        # The requests which do not need the VMM are answered from the last
+        # state it reported, while it is blocked on the memory file.
+        for _ in range(3):
+            assert vm.api.describe.get().json()["state"] == "Paused"
+            assert vm.api.version.get().status_code == 200
+        assert not create.done()
+
+        # The snapshot creation fails once unblocked, since the memory cannot
+        # be written to a FIFO.
+        with open(fifo, "rb") as reader:
+            reader.read()
+        with pytest.raises(RuntimeError):
+            create.result()
+
+    vm.api.vm.patch(state="Resumed")
+    assert vm.api.describe.get().json()["state"] == "Running"
+
+
 def test_create_large_diff_snapshot(test_microvm_with_api):
     """
     Create large diff snapshot seccomp regression test.
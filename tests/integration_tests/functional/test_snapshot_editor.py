@@ -2,6 +2,7 @@
 # SPDX-License-Identifier: Apache-2.0
 """Tests for snapshot-editor tool."""
 
+import json
 import platform
 
 import pytest
@@ -76,3 +77,134 @@ def test_remove_regs(uvm_nano, microvm_factory):
     # Verify if guest can run commands.
     exit_code, _, _ = new_vm.ssh.run("ls")
     assert exit_code == 0
+
+
+def test_edit_devices(uvm_nano):
+    """
+    Check that devices can be listed, rewritten and removed from a vmstate file.
+    """
+    vm = uvm_nano
+    vm.add_net_iface()
+    vm.start()
+    snapshot = vm.snapshot_full()
+    vm.kill()
+
+    snap_editor = host.get_binary("snapshot-editor")
+    vmstate = str(snapshot.vmstate)
+
+    def dump():
+        cmd = [str(snap_editor), "info-vmstate", "dump", "--vmstate-path", vmstate]
+        _, stdout, _ = utils.run_cmd(cmd)
+        return json.loads(stdout)
+
+    state = dump()
+    assert state["vm_info"]["mem_size_mib"] == 256
+    devices = {device["id"]: device for device in state["devices"]}
+    assert devices["rootfs"]["type"] == "block"
+    assert devices["eth0"]["type"] == "net"
+
+    cmd = [
+        str(snap_editor),
+        "edit-vmstate",
+        "set-tap-name",
+        "--device-id",
+        "eth0",
+        "--tap-name",
+        "tap_other",
+        "--vmstate-path",
+        vmstate,
+        "--output-path",
+        vmstate,
+    ]
+    utils.run_cmd(cmd)
+    devices = {device["id"]: device for device in dump()["devices"]}
+    assert devices["eth0"]["tap_if_name"] == "tap_other"
+
+    cmd = [
+        str(snap_editor),
+        "edit-vmstate",
+        "remove-device",
+        "--device-id",
+        "eth0",
+        "--vmstate-path",
+        vmstate,
+        "--output-path",
+        vmstate,
+    ]
+    utils.run_cmd(cmd)
+    assert [device["id"] for device in dump()["devices"]] == ["rootfs"]
+
+
+def test_set_version(uvm_nano):
+    """
+    Check that a vmstate file can be saved in the format of an older version.
+    """
+    vm = uvm_nano
+    vm.start()
+    snapshot = vm.snapshot_full()
+    vm.kill()
+
+    snap_editor = host.get_binary("snapshot-editor")
+    cmd = [
+        str(snap_editor),
+        "edit-vmstate",
+        "set-version",
+        "--target-version",
+        "1.4.0",
+        "--vmstate-path",
+        str(snapshot.vmstate),
+        "--output-path",
+        str(snapshot.vmstate),
+    ]
+    utils.run_cmd(cmd)
+
+    cmd = [
+        str(snap_editor),
+        "info-vmstate",
+        "version",
+        "--vmstate-path",
+        str(snapshot.vmstate),
+    ]
+    _, stdout, _ = utils.run_cmd(cmd)
+    assert stdout.strip() == "v1.4.0"
+
+
+def test_zero_pages(uvm_plain, microvm_factory):
+    """
+    Check that full snapshots record the zero pages, and restore from them.
+    """
+    vm = uvm_plain
+    vm.spawn()
+    vm.basic_config(mem_size_mib=256, track_dirty_pages=True)
+    vm.add_net_iface()
+    vm.start()
+
+    snap_editor = host.get_binary("snapshot-editor")
+
+    def zero_pages(snapshot):
+        cmd = [
+            str(snap_editor),
+            "info-vmstate",
+            "dump",
+            "--vmstate-path",
+            str(snapshot.vmstate),
+        ]
+        _, stdout, _ = utils.run_cmd(cmd)
+        regions = json.loads(stdout)["memory_regions"]
+        return sum(region["zero_pages"] for region in regions)
+
+    # Most of the memory of a freshly booted guest was never written.
+    snapshot = vm.snapshot_full()
+    assert zero_pages(snapshot) > 0
+
+    new_vm = microvm_factory.build()
+    new_vm.spawn()
+    new_vm.restore_from_snapshot(snapshot, resume=True)
+    exit_code, _, _ = new_vm.ssh.run("ls")
+    assert exit_code == 0
+    new_vm.kill()
+
+    # Diff snapshots do not record them.
+    vm.resume()
+    snapshot = vm.snapshot_diff()
+    assert zero_pages(snapshot) == 0
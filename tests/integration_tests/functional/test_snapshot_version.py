@@ -57,7 +57,7 @@ def test_create_with_too_many_devices(test_microvm_with_api):
     # v0.23 allowed a maximum of `FC_V0_23_MAX_DEVICES_ATTACHED` virtio
     # devices at a time. This microVM has `FC_V0_23_MAX_DEVICES_ATTACHED`
     # network devices on top of the rootfs, so the limit is exceeded.
-    with pytest.raises(RuntimeError, match="Too many devices attached"):
+    with pytest.raises(RuntimeError, match="12 devices, more than 11"):
         test_microvm.api.snapshot_create.put(
             mem_file_path="/vm.mem",
             snapshot_path="/vm.vmstate",
@@ -123,6 +123,24 @@ def test_snapshot_current_version(uvm_nano):
     assert target_version in stdout
 
 
+def test_load_untranslatable_snapshot(uvm_nano, microvm_factory):
+    """
+    Check that a snapshot older than the translated versions is rejected.
+
+    The format of the net device state changed since v1.2, so a snapshot saved
+    at v1.2 with a net device cannot be loaded and the device is reported.
+    """
+    vm = uvm_nano
+    vm.add_net_iface()
+    vm.start()
+    snapshot = vm.snapshot_full(target_version="1.2.0")
+
+    new_vm = microvm_factory.build()
+    new_vm.spawn()
+    with pytest.raises(RuntimeError, match="net device eth0"):
+        new_vm.restore_from_snapshot(snapshot, resume=True)
+
+
 def test_create_with_newer_virtio_features(uvm_nano):
     """
     Attempt to create a snapshot with newer virtio features.
@@ -145,10 +163,7 @@ def test_create_with_newer_virtio_features(uvm_nano):
     if platform.machine() == "x86_64":
         target_fc_versions.insert(0, "0.23.0")
 
-    expected_msg = (
-        "The virtio devices use a features that is incompatible "
-        "with older versions of Firecracker: notification suppression"
-    )
+    expected_msg = r"notification suppression of device rootfs \(since v1.0.0\)"
     for target_fc_version in target_fc_versions:
         with pytest.raises(RuntimeError, match=expected_msg):
             test_microvm.api.snapshot_create.put(
@@ -159,6 +174,7 @@ def test_create_with_newer_virtio_features(uvm_nano):
 
     # We try to create a snapshot for target version 1.0.0. This should
     # fail because in 1.0.0 we do not support notification suppression for Net.
+    expected_msg = r"notification suppression of device eth0 \(since v1.1.0\)"
     with pytest.raises(RuntimeError, match=expected_msg):
         test_microvm.api.snapshot_create.put(
             mem_file_path="/vm.mem",
@@ -192,7 +208,8 @@ def test_create_with_1_5_cpu_template(uvm_plain):
 
     # Should fail because target version is less than 1.5
     with pytest.raises(
-        RuntimeError, match="Cannot translate microVM version to snapshot data version"
+        RuntimeError,
+        match=r"KVM capabilities of the CPU template \(since v1.5.0\)",
     ):
         test_microvm.snapshot_full(target_version="1.4.0")
 
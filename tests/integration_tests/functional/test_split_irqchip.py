@@ -0,0 +1,46 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the split irqchip, with the IOAPIC emulated in userspace."""
+
+import platform
+
+import pytest
+
+
+@pytest.mark.skipif(
+    platform.machine() != "aarch64", reason="The split irqchip is supported on x86_64"
+)
+def test_split_irqchip_aarch64(test_microvm_with_api):
+    """
+    Check that the split irqchip cannot be enabled on aarch64.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError, match="not supported on aarch64"):
+        test_microvm.api.machine_config.patch(split_irqchip=True)
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64", reason="The split irqchip is only on x86_64"
+)
+def test_split_irqchip(uvm_nano):
+    """
+    Check that a microVM using the split irqchip boots and gets device interrupts.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.machine_config.patch(split_irqchip=True)
+    microvm.start()
+
+    assert microvm.api.vm_config.get().json()["machine-config"]["split_irqchip"]
+
+    # The guest reaches the devices through the IOAPIC and has no PIT.
+    _, stdout, _ = microvm.ssh.run("cat /proc/interrupts")
+    assert "IO-APIC" in stdout
+    _, stdout, _ = microvm.ssh.run("cat /proc/timer_list")
+    assert "Clock Event Device: pit" not in stdout
+
+    microvm.api.vm.patch(state="Paused")
+    with pytest.raises(RuntimeError, match="not supported for microVMs with the split"):
+        microvm.api.snapshot_create.put(mem_file_path="mem", snapshot_path="vmstate")
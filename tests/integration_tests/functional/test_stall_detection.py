@@ -0,0 +1,40 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the detection of stalled vCPUs."""
+
+import time
+
+import pytest
+
+
+def test_stall_detection_config(test_microvm_with_api):
+    """
+    Check the validation of the stall detection configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.stall_detection.put()
+    with pytest.raises(RuntimeError):
+        test_microvm.api.stall_detection.put(timeout_ms=999)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.stall_detection.put(timeout_ms=1000, foo=True)
+    test_microvm.api.stall_detection.put(timeout_ms=1000, capture_registers=True)
+
+
+def test_idle_guest_not_stalled(uvm_nano):
+    """
+    Check that an idle guest is not reported as stalled.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.stall_detection.put(timeout_ms=1000)
+    microvm.start()
+    microvm.ssh.run("true")
+
+    # Let the detector sample the idle vCPUs for a few timeouts.
+    time.sleep(3)
+    fc_metrics = microvm.flush_metrics()
+    assert fc_metrics["vcpu"]["stalls"] == 0
+    assert "made no progress" not in microvm.log_data
@@ -0,0 +1,70 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the scheduling settings of the Firecracker threads."""
+
+import os
+
+import pytest
+
+from framework import utils
+
+
+def test_thread_scheduling_config(test_microvm_with_api):
+    """
+    Check the validation of the thread scheduling settings.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+
+    for scheduling in [
+        {"policy": "deadline"},
+        {"policy": "fifo"},
+        {"policy": "rr", "priority": 100},
+        {"policy": "idle", "nice": 5},
+        {"policy": "other", "nice": 20},
+        {"policy": "batch", "priority": 10},
+    ]:
+        with pytest.raises(RuntimeError):
+            test_microvm.api.machine_config.patch(vcpu_scheduling=scheduling)
+
+    test_microvm.api.machine_config.patch(
+        vcpu_scheduling={"policy": "idle"}, io_scheduling={"policy": "other"}
+    )
+    config = test_microvm.api.machine_config.get().json()
+    assert config["vcpu_scheduling"] == {"policy": "idle", "nice": 0, "priority": 0}
+    assert config["io_scheduling"] == {"policy": "other", "nice": 0, "priority": 0}
+
+
+def test_thread_scheduling(test_microvm_with_api):
+    """
+    Check that the settings are applied to the vCPU and VMM threads only.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+    test_microvm.api.machine_config.patch(
+        vcpu_scheduling={"policy": "idle"},
+        io_scheduling={"policy": "batch", "nice": 5},
+    )
+    test_microvm.start()
+
+    threads = utils.ProcessManager.get_threads(test_microvm.jailer_clone_pid)
+    for vcpu_id in range(2):
+        for tid in threads[f"fc_vcpu {vcpu_id}"]:
+            assert os.sched_getscheduler(tid) == os.SCHED_IDLE
+
+    vmm_tids = [
+        tid
+        for name, tids in threads.items()
+        if name.startswith("firecracker")
+        for tid in tids
+    ]
+    assert vmm_tids
+    for tid in vmm_tids:
+        assert os.sched_getscheduler(tid) == os.SCHED_BATCH
+        assert os.getpriority(os.PRIO_PROCESS, tid) == 5
+
+    for tid in threads["fc_api"]:
+        assert os.sched_getscheduler(tid) == os.SCHED_OTHER
+        assert os.getpriority(os.PRIO_PROCESS, tid) == 0
@@ -0,0 +1,127 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the TPM device backed by swtpm."""
+
+import os
+import platform
+import shutil
+import subprocess
+import time
+from pathlib import Path
+
+import pytest
+
+SWTPM = shutil.which("swtpm")
+SOCKET_NAME = "swtpm.sock"
+VOLATILE_STATE = "tpm2-00.volatilestate"
+
+pytestmark = pytest.mark.skipif(SWTPM is None, reason="swtpm is not installed")
+
+
+@pytest.fixture
+def swtpm_factory(tmp_path):
+    """Start swtpm processes listening in the jail of a microVM."""
+    processes = []
+
+    def start(microvm, state_dir):
+        state_dir.mkdir(exist_ok=True)
+        socket_path = Path(microvm.chroot()) / SOCKET_NAME
+        # swtpm is initialized by Firecracker, through the control socket.
+        # pylint: disable=consider-using-with
+        proc = subprocess.Popen(
+            [
+                SWTPM,
+                "socket",
+                "--tpm2",
+                "--tpmstate",
+                f"dir={state_dir}",
+                "--ctrl",
+                f"type=unixio,path={socket_path}",
+                "--log",
+                f"file={tmp_path / 'swtpm.log'}",
+            ]
+        )
+        processes.append(proc)
+        for _ in range(100):
+            if socket_path.exists():
+                break
+            time.sleep(0.05)
+        os.chown(socket_path, microvm.jailer.uid, microvm.jailer.gid)
+        return proc
+
+    yield start
+
+    for proc in processes:
+        proc.kill()
+        proc.wait()
+
+
+def test_tpm_config(test_microvm_with_api):
+    """
+    Check the validation of the TPM configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.tpm.put(socket_path="")
+    with pytest.raises(RuntimeError):
+        test_microvm.api.tpm.put(socket_path=SOCKET_NAME, foo="bar")
+
+    test_microvm.api.tpm.put(socket_path=SOCKET_NAME)
+    tpm = test_microvm.api.vm_config.get().json()["tpm"]
+    assert tpm == {"socket_path": SOCKET_NAME}
+
+
+def test_tpm_without_swtpm(uvm_nano):
+    """
+    Check that the microVM does not start when swtpm is not listening.
+    """
+    microvm = uvm_nano
+    microvm.api.machine_config.patch(acpi=True)
+    microvm.api.tpm.put(socket_path=SOCKET_NAME)
+    with pytest.raises(RuntimeError, match="Cannot connect to the swtpm socket"):
+        microvm.start()
+
+
+@pytest.mark.skipif(platform.machine() != "x86_64", reason="ACPI is x86_64 only")
+def test_tpm_requires_acpi(uvm_nano, swtpm_factory, tmp_path):
+    """
+    Check that the TPM is rejected on x86_64 when ACPI is disabled.
+    """
+    microvm = uvm_nano
+    swtpm_factory(microvm, tmp_path / "state")
+    microvm.api.machine_config.patch(acpi=False)
+    microvm.api.tpm.put(socket_path=SOCKET_NAME)
+    with pytest.raises(RuntimeError, match="requires ACPI"):
+        microvm.start()
+
+
+def test_tpm_snapshot(uvm_nano, microvm_factory, swtpm_factory, tmp_path):
+    """
+    Check that the TPM state is stored by swtpm when a snapshot is created, and that
+    the restored microVM reconnects to a swtpm resuming from a copy of that state.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.machine_config.patch(acpi=True)
+    state_dir = tmp_path / "state"
+    swtpm_factory(microvm, state_dir)
+    microvm.api.tpm.put(socket_path=SOCKET_NAME)
+    microvm.start()
+    microvm.ssh.run("true")
+
+    assert not (state_dir / VOLATILE_STATE).exists()
+    snapshot = microvm.snapshot_full()
+    assert (state_dir / VOLATILE_STATE).exists()
+
+    restored_state_dir = tmp_path / "restored_state"
+    shutil.copytree(state_dir, restored_state_dir)
+    restored_vm = microvm_factory.build()
+    restored_vm.spawn()
+    swtpm_factory(restored_vm, restored_state_dir)
+    restored_vm.restore_from_snapshot(snapshot, resume=True)
+    restored_vm.ssh.run("true")
+
+    metrics = restored_vm.flush_metrics()
+    assert metrics["tpm"]["command_fails"] == 0
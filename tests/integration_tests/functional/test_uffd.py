@@ -4,12 +4,16 @@
 
 import os
 import re
+import signal
 import stat
+import time
 from subprocess import TimeoutExpired
 
 import pytest
 import requests
+from retry import retry
 
+from framework.properties import global_props
 from framework.utils import Timeout, UffdHandler, run_cmd
 
 SOCKET_PATH = "/firecracker-uffd.sock"
@@ -42,7 +46,7 @@ def snapshot_fxt(microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22):
     yield snapshot
 
 
-def spawn_pf_handler(vm, handler_path, mem_path):
+def spawn_pf_handler(vm, handler_path, mem_path, socket_path=SOCKET_PATH):
     """Spawn page fault handler process."""
     # Copy snapshot memory file into chroot of microVM.
     jailed_mem = vm.create_jailed_resource(mem_path)
@@ -50,7 +54,7 @@ def spawn_pf_handler(vm, handler_path, mem_path):
     jailed_handler = vm.create_jailed_resource(handler_path)
 
     handler_name = os.path.basename(jailed_handler)
-    args = [SOCKET_PATH, jailed_mem]
+    args = [socket_path, jailed_mem]
 
     uffd_handler = UffdHandler(handler_name, args)
     real_root = os.open("/", os.O_RDONLY)
@@ -72,7 +76,7 @@ def spawn_pf_handler(vm, handler_path, mem_path):
 
     # The page fault handler will create the socket path with root rights.
     # Change rights to the jailer's.
-    os.chown(SOCKET_PATH, vm.jailer.uid, vm.jailer.gid)
+    os.chown(socket_path, vm.jailer.uid, vm.jailer.gid)
 
     os.fchdir(real_root)
     os.chroot(".")
@@ -150,6 +154,233 @@ def test_valid_handler(uvm_plain, snapshot, uffd_handler_paths):
     exit_code, _, _ = vm.ssh.run("sync")
     assert exit_code == 0
 
+    # The page faults reported by the handler are exported through the metrics.
+    vm.flush_metrics()
+    all_metrics = vm.get_all_metrics()
+    assert sum(metrics["uffd"]["page_faults"] for metrics in all_metrics) > 0
+    assert sum(metrics["uffd"]["populated_bytes"] for metrics in all_metrics) > 0
+    assert sum(metrics["uffd"]["invalid_reports"] for metrics in all_metrics) == 0
+
+
+def test_valid_handler_inflated_balloon(
+    microvm_factory, guest_kernel_linux_5_10, rootfs_ubuntu_22, uffd_handler_paths
+):
+    """
+    Test restoring with a valid uffd handler a snapshot taken with the balloon inflated.
+    """
+    basevm = microvm_factory.build(guest_kernel_linux_5_10, rootfs_ubuntu_22)
+    basevm.spawn()
+    basevm.basic_config(vcpu_count=2, mem_size_mib=256)
+    basevm.add_net_iface()
+    basevm.api.balloon.put(
+        amount_mib=0, deflate_on_oom=True, stats_polling_interval_s=0
+    )
+    basevm.start()
+
+    # Inflate the balloon and snapshot without deflating it first.
+    basevm.api.balloon.patch(amount_mib=200)
+    exit_code, _, _ = basevm.ssh.run("sync")
+    assert exit_code == 0
+    snapshot = basevm.snapshot_full()
+    basevm.kill()
+
+    vm = microvm_factory.build()
+    vm.memory_monitor = None
+    vm.spawn()
+
+    # Spawn page fault handler process.
+    _pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem
+    )
+
+    vm.restore_from_snapshot(snapshot, resume=True, uffd_path=SOCKET_PATH)
+
+    # Verify if guest can run commands with the balloon still inflated.
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+
+    # Deflate balloon, so that the guest touches the pages it held again.
+    vm.api.balloon.patch(amount_mib=0)
+
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+
+
+@pytest.mark.parametrize("exit_action", ["Pause", "Fallback"])
+def test_handler_exit(uvm_plain, snapshot, uffd_handler_paths, exit_action):
+    """
+    Test that Firecracker reacts to the uffd handler exiting.
+    """
+    vm = uvm_plain
+    vm.memory_monitor = None
+    vm.spawn()
+
+    # Spawn page fault handler process.
+    pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem
+    )
+
+    vm.restore_from_snapshot(
+        snapshot,
+        resume=True,
+        uffd_path=SOCKET_PATH,
+        uffd_handler_exit_action=exit_action,
+    )
+
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+
+    pf_handler.proc().kill()
+    pf_handler.proc().wait()
+
+    @retry(delay=0.1, tries=20, logger=None)
+    def handler_exit_logged():
+        assert "UFFD exited" in vm.log_data
+
+    handler_exit_logged()
+
+    if exit_action == "Pause":
+        assert vm.state == "Paused"
+    else:
+        # Guest memory is now served from the fallback memory file.
+        assert vm.state == "Running"
+        exit_code, _, _ = vm.ssh.run("cat /proc/meminfo > /dev/null && sync")
+        assert exit_code == 0
+
+    fc_metrics = vm.flush_metrics()
+    assert fc_metrics["vmm"]["uffd_handler_exits"] == 1
+
+
+@pytest.mark.skipif(
+    global_props.cpu_architecture == "x86_64"
+    and tuple(map(int, global_props.host_linux_version.split("."))) >= (6, 4),
+    reason="KVM interrupts the page faults of the vCPUs asked to pause",
+)
+def test_handler_exit_pause_blocked_vcpus(uvm_plain, snapshot, uffd_handler_paths):
+    """
+    Test that the `Pause` exit action fails on vCPUs blocked on a page fault.
+    """
+    vm = uvm_plain
+    vm.memory_monitor = None
+    vm.spawn()
+
+    # Spawn page fault handler process.
+    pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem
+    )
+
+    vm.restore_from_snapshot(
+        snapshot,
+        resume=True,
+        uffd_path=SOCKET_PATH,
+        uffd_handler_exit_action="Pause",
+    )
+
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+
+    # Make the guest touch memory it did not touch since the restore, once the handler
+    # stopped serving page faults.
+    exit_code, _, _ = vm.ssh.run(
+        "nohup sh -c 'sleep 1; dd if=/dev/zero of=/dev/shm/fill bs=1M count=128'"
+        " > /dev/null 2>&1 &"
+    )
+    assert exit_code == 0
+    pf_handler.proc().send_signal(signal.SIGSTOP)
+    time.sleep(5)
+
+    pf_handler.proc().kill()
+    pf_handler.proc().wait()
+
+    # The pause request times out after 30 seconds.
+    @retry(delay=1, tries=60, logger=None)
+    def pause_failure_logged():
+        assert "Failed to pause the microVM" in vm.log_data
+
+    pause_failure_logged()
+    assert vm.state == "Running"
+
+    fc_metrics = vm.flush_metrics()
+    assert fc_metrics["vmm"]["uffd_handler_exits"] == 1
+
+
+def test_handler_handover(uvm_plain, snapshot, uffd_handler_paths):
+    """
+    Test handing the guest memory over to a new uffd handler.
+    """
+    vm = uvm_plain
+    vm.memory_monitor = None
+    vm.spawn()
+
+    # Spawn page fault handler process.
+    pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem
+    )
+
+    vm.restore_from_snapshot(
+        snapshot, resume=True, uffd_path=SOCKET_PATH, uffd_handler_exit_action="Pause"
+    )
+
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+
+    # Spawn the handler taking over and hand the guest memory over to it.
+    new_socket_path = "/firecracker-uffd-new.sock"
+    _new_pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem, new_socket_path
+    )
+    vm.api.snapshot_uffd_handler.put(backend_path=new_socket_path)
+
+    # The previous handler exiting does not pause the microVM anymore.
+    pf_handler.proc().kill()
+    pf_handler.proc().wait()
+
+    # Inflate and deflate the balloon, so that the new handler serves page faults.
+    vm.api.balloon.patch(amount_mib=200)
+    vm.api.balloon.patch(amount_mib=0)
+
+    exit_code, _, _ = vm.ssh.run("sync")
+    assert exit_code == 0
+    assert vm.state == "Running"
+    assert "UFFD exited" not in vm.log_data
+
+
+def test_write_protect_diff_snapshot(
+    uvm_plain, microvm_factory, snapshot, uffd_handler_paths
+):
+    """
+    Test taking a diff snapshot of a microVM restored with write-protected guest memory.
+    """
+    vm = uvm_plain
+    vm.memory_monitor = None
+    vm.spawn()
+
+    # Spawn page fault handler process.
+    _pf_handler = spawn_pf_handler(
+        vm, uffd_handler_paths["valid_handler"], snapshot.mem
+    )
+
+    vm.restore_from_snapshot(
+        snapshot, resume=True, uffd_path=SOCKET_PATH, uffd_write_protect=True
+    )
+
+    # Write to guest memory after restore.
+    exit_code, _, _ = vm.ssh.run("echo write-protect > /dev/shm/marker && sync")
+    assert exit_code == 0
+
+    # The pages written after restore end up in the diff snapshot.
+    diff_snapshot = vm.snapshot_diff()
+    vm.kill()
+    rebased_snapshot = diff_snapshot.rebase_snapshot(snapshot)
+
+    new_vm = microvm_factory.build()
+    new_vm.spawn()
+    new_vm.restore_from_snapshot(rebased_snapshot, resume=True)
+
+    exit_code, stdout, _ = new_vm.ssh.run("cat /dev/shm/marker")
+    assert exit_code == 0
+    assert stdout.strip() == "write-protect"
+
 
 def test_malicious_handler(uvm_plain, snapshot, uffd_handler_paths):
     """
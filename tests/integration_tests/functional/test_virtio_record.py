@@ -0,0 +1,72 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the recording and replay of virtio queues."""
+
+import shutil
+from pathlib import Path
+
+import pytest
+
+import host_tools.cargo_build as host
+from framework import utils
+
+
+def test_virtio_record_config(test_microvm_with_api):
+    """
+    Check the validation of the virtio recording configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    queue = {"device_id": "rootfs", "queue_index": 0, "path": "rootfs.rec"}
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.virtio_record.put(queues=[])
+    with pytest.raises(RuntimeError):
+        test_microvm.api.virtio_record.put(queues=[queue, queue])
+    with pytest.raises(RuntimeError):
+        test_microvm.api.virtio_record.put(queues=[{**queue, "foo": True}])
+    test_microvm.api.virtio_record.put(queues=[queue])
+
+
+def test_unknown_queue(uvm_nano):
+    """
+    Check that the microVM does not start when a recorded queue does not exist.
+    """
+    vm = uvm_nano
+    vm.api.virtio_record.put(
+        queues=[{"device_id": "rootfs", "queue_index": 1, "path": "rootfs.rec"}]
+    )
+    with pytest.raises(RuntimeError, match="Cannot record queue 1 of device rootfs"):
+        vm.start()
+
+
+def test_record_replay_rootfs(uvm_nano, tmp_path):
+    """
+    Record the queue of the root device during boot and replay it.
+    """
+    vm = uvm_nano
+    vm.add_net_iface()
+    vm.api.virtio_record.put(
+        queues=[{"device_id": "rootfs", "queue_index": 0, "path": "rootfs.rec"}]
+    )
+    vm.start()
+    ecode, _, _ = vm.ssh.run("cat /etc/os-release")
+    assert ecode == 0
+    vm.kill()
+
+    recording = Path(vm.chroot()) / "rootfs.rec"
+    assert recording.read_bytes()[:8] == b"FCVIOREC"
+
+    # The root device is read-only, so its image did not change since the recording.
+    disk = tmp_path / "rootfs"
+    shutil.copyfile(vm.rootfs_file, disk)
+    replay = host.get_binary("virtio-replay")
+    cmd = [
+        str(replay),
+        "--recording-path",
+        str(recording),
+        "--disk-path",
+        str(disk),
+    ]
+    _, stdout, _ = utils.run_cmd(cmd)
+    assert "Replaying queue 0 of device rootfs" in stdout
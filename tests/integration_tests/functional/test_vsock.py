@@ -14,9 +14,15 @@ In order to test the vsock device connection state machine, these tests will:
   guest-initiated connections.
 """
 
+import fcntl
 import os.path
+import socket
+import time
+from pathlib import Path
 from socket import timeout as SocketTimeout
 
+import pytest
+
 from framework.utils_vsock import (
     ECHO_SERVER_PORT,
     VSOCK_UDS_PATH,
@@ -31,6 +37,15 @@ from framework.utils_vsock import (
 
 NEGATIVE_TEST_CONNECTION_COUNT = 100
 TEST_WORKER_COUNT = 10
+CLOCK_PORT = 123
+
+# Prints what the guest reads from the clock port of the host.
+READ_CLOCK_SCRIPT = f"""
+import socket
+sock = socket.socket(socket.AF_VSOCK, socket.SOCK_STREAM)
+sock.connect((2, {CLOCK_PORT}))
+print(sock.makefile().read().strip())
+"""
 
 
 def test_vsock(test_microvm_with_api, bin_vsock_path, test_fc_session_root_path):
@@ -207,3 +222,143 @@ def test_vsock_transport_reset(
     # Test host-initiated connections.
     path = os.path.join(vm2.jailer.chroot_path(), VSOCK_UDS_PATH)
     check_host_connections(vm2, path, blob_path, blob_hash)
+
+
+def test_vsock_restore_deferred(
+    uvm_nano, microvm_factory, bin_vsock_path, test_fc_session_root_path
+):
+    """
+    Test restoring a vsock device whose host socket is bound after resume.
+    """
+    test_vm = uvm_nano
+    test_vm.add_net_iface()
+    test_vm.api.vsock.put(guest_cid=3, uds_path=f"/{VSOCK_UDS_PATH}")
+    test_vm.start()
+
+    blob_path, blob_hash = make_blob(test_fc_session_root_path)
+    vm_blob_path = "/tmp/vsock/test.blob"
+    _copy_vsock_data_to_guest(test_vm.ssh, blob_path, vm_blob_path, bin_vsock_path)
+    ecode, _, _ = test_vm.ssh.run(f"/tmp/vsock_helper echosrv -d {ECHO_SERVER_PORT}")
+    assert ecode == 0
+
+    snapshot = test_vm.snapshot_full()
+    test_vm.kill()
+
+    vm2 = microvm_factory.build()
+    vm2.spawn()
+    vm2.restore_from_snapshot(snapshot, resume=True, vsock_override={"defer": True})
+
+    # The socket is only created by the PATCH request.
+    uds_path = "clone.vsock"
+    path = os.path.join(vm2.jailer.chroot_path(), uds_path)
+    assert not os.path.exists(path)
+    vm2.api.vsock.patch(uds_path=f"/{uds_path}")
+    check_host_connections(vm2, path, blob_path, blob_hash)
+
+    # The socket can only be bound once.
+    with pytest.raises(RuntimeError, match="AlreadyBound"):
+        vm2.api.vsock.patch(uds_path="/other.vsock")
+
+
+def test_vsock_clock_port(uvm_nano, microvm_factory):
+    """
+    Test that the guest reads the host clock from the clock port, after a
+    restore with a deferred host socket.
+    """
+    test_vm = uvm_nano
+    test_vm.add_net_iface()
+    test_vm.api.vsock.put(
+        guest_cid=3, uds_path=f"/{VSOCK_UDS_PATH}", clock_port=CLOCK_PORT
+    )
+    test_vm.start()
+    snapshot = test_vm.snapshot_full()
+    test_vm.kill()
+
+    vm2 = microvm_factory.build()
+    vm2.spawn()
+    vm2.restore_from_snapshot(snapshot, resume=True, vsock_override={"defer": True})
+
+    ecode, stdout, stderr = vm2.ssh.run(f'python3 -c "{READ_CLOCK_SCRIPT}"')
+    assert ecode == 0, stderr
+    seconds, nanoseconds = stdout.strip().split(".")
+    assert len(nanoseconds) == 9
+    assert abs(int(seconds) - time.time()) < 10
+    assert vm2.flush_metrics()["vsock"]["clock_requests"] == 1
+
+
+def test_vsock_cid_lock(uvm_nano):
+    """
+    Test that the guest CIDs locked by other microVMs are not used.
+    """
+    test_vm = uvm_nano
+    lock_dir = Path(test_vm.chroot()) / "cids"
+    lock_dir.mkdir()
+    os.chown(lock_dir, test_vm.jailer.uid, test_vm.jailer.gid)
+
+    # Another microVM uses the lowest CID.
+    with open(lock_dir / "3.lock", "w", encoding="utf-8") as other_vm_lock:
+        os.chown(other_vm_lock.fileno(), test_vm.jailer.uid, test_vm.jailer.gid)
+        fcntl.flock(other_vm_lock, fcntl.LOCK_EX | fcntl.LOCK_NB)
+
+        with pytest.raises(RuntimeError, match="already used by another microVM"):
+            test_vm.api.vsock.put(
+                guest_cid=3, uds_path=f"/{VSOCK_UDS_PATH}", cid_lock_dir="/cids"
+            )
+        test_vm.api.vsock.put(uds_path=f"/{VSOCK_UDS_PATH}", cid_lock_dir="/cids")
+        config = test_vm.api.vsock.get().json()
+        assert config["guest_cid"] == 4
+        assert config["cid_lock_dir"] == "/cids"
+
+    with pytest.raises(RuntimeError, match="can only be assigned along with"):
+        test_vm.api.vsock.put(uds_path=f"/{VSOCK_UDS_PATH}")
+    with pytest.raises(RuntimeError, match="Invalid guest CID 2"):
+        test_vm.api.vsock.put(guest_cid=2, uds_path=f"/{VSOCK_UDS_PATH}")
+
+
+def test_vsock_resync(
+    uvm_nano, microvm_factory, bin_vsock_path, test_fc_session_root_path
+):
+    """
+    Test that a restored vsock device reports the connections dropped by the
+    snapshot, and the guest ports which were listening.
+    """
+    test_vm = uvm_nano
+    test_vm.add_net_iface()
+    test_vm.api.vsock.put(guest_cid=3, uds_path=f"/{VSOCK_UDS_PATH}")
+    test_vm.start()
+
+    blob_path, _ = make_blob(test_fc_session_root_path)
+    _copy_vsock_data_to_guest(
+        test_vm.ssh, blob_path, "/tmp/vsock/test.blob", bin_vsock_path
+    )
+    ecode, _, _ = test_vm.ssh.run(f"/tmp/vsock_helper echosrv -d {ECHO_SERVER_PORT}")
+    assert ecode == 0
+
+    # Keep a host-initiated connection open while the snapshot is taken.
+    sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    sock.connect(os.path.join(test_vm.jailer.chroot_path(), VSOCK_UDS_PATH))
+    sock.sendall(f"CONNECT {ECHO_SERVER_PORT}\n".encode())
+    ack = sock.makefile().readline().split()
+    assert ack[0] == "OK"
+    host_port = int(ack[1])
+
+    snapshot = test_vm.snapshot_full()
+    test_vm.kill()
+    sock.close()
+
+    vm2 = microvm_factory.build()
+    vm2.spawn()
+    vm2.restore_from_snapshot(snapshot, resume=True)
+
+    sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    sock.connect(os.path.join(vm2.jailer.chroot_path(), VSOCK_UDS_PATH))
+    sock.sendall(b"RESYNC\n")
+    report = sock.makefile().read().splitlines()
+    sock.close()
+    assert report == [
+        "RESYNC 1",
+        f"LISTENING {ECHO_SERVER_PORT}",
+        f"DROPPED HOST {host_port} {ECHO_SERVER_PORT}",
+        "END",
+    ]
+    assert vm2.flush_metrics()["vsock"]["resync_requests"] == 1
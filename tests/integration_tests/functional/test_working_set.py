@@ -0,0 +1,41 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the estimation of the guest working set."""
+
+import time
+
+import pytest
+
+
+def test_working_set_config(test_microvm_with_api):
+    """
+    Check the validation of the working set estimation configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.working_set.put()
+    with pytest.raises(RuntimeError):
+        test_microvm.api.working_set.put(sample_interval_s=0)
+    with pytest.raises(RuntimeError):
+        test_microvm.api.working_set.put(sample_interval_s=1, foo=True)
+    test_microvm.api.working_set.put(sample_interval_s=1)
+
+
+def test_working_set_estimate(uvm_nano):
+    """
+    Check that the pages written by the guest are counted in the estimate.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.api.working_set.put(sample_interval_s=1)
+    microvm.start()
+
+    # Keep writing 64 MiB of guest memory over a few sampling intervals.
+    microvm.ssh.run(
+        "for i in 1 2 3; do dd if=/dev/zero of=/dev/shm/ws bs=1M count=64; done"
+    )
+    time.sleep(1.5)
+    fc_metrics = microvm.flush_metrics()
+    assert fc_metrics["vmm"]["working_set_bytes"] > 0
@@ -15,18 +15,19 @@ from framework import utils
 from host_tools.cargo_build import run_seccompiler_bin
 
 
-def _custom_filter_setup(test_microvm, json_filter):
+def _custom_filter_setup(test_microvm, json_filter, arg="seccomp-filter"):
     json_temp = tempfile.NamedTemporaryFile(delete=False)
     json_temp.write(json_filter)
     json_temp.flush()
 
-    bpf_path = os.path.join(test_microvm.path, "bpf.out")
+    bpf_file = "{}.bpf".format(arg)
+    bpf_path = os.path.join(test_microvm.path, bpf_file)
 
     run_seccompiler_bin(bpf_path=bpf_path, json_path=json_temp.name)
 
     os.unlink(json_temp.name)
     test_microvm.create_jailed_resource(bpf_path)
-    test_microvm.jailer.extra_args.update({"seccomp-filter": "bpf.out"})
+    test_microvm.jailer.extra_args.update({arg: bpf_file})
 
 
 def _config_file_setup(test_microvm, vm_config_file):
@@ -200,15 +201,58 @@ def test_failing_filter(test_microvm_with_api):
     datapoints = test_microvm.get_all_metrics()
 
     num_faults = 0
+    vcpu_faults = 0
     for datapoint in datapoints:
         num_faults += datapoint["seccomp"]["num_faults"]
+        vcpu_faults += datapoint["seccomp"]["vcpu"]["num_faults"]
 
     assert num_faults >= 1
+    assert vcpu_faults >= 1
 
     # assert that the process was killed
     assert not psutil.pid_exists(test_microvm.jailer_clone_pid)
 
 
+def test_thread_filter(test_microvm_with_api):
+    """
+    Test --seccomp-filter-vcpu, overriding the filter of the vCPU threads only.
+    """
+    test_microvm = test_microvm_with_api
+
+    _custom_filter_setup(
+        test_microvm,
+        """{
+        "Vcpu": {
+            "default_action": "allow",
+            "filter_action": "trap",
+            "filter": []
+        }
+    }""".encode(
+            "utf-8"
+        ),
+        arg="seccomp-filter-vcpu",
+    )
+
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=2)
+    test_microvm.start()
+
+    filters = {
+        status["thread_category"]: status
+        for status in test_microvm.api.seccomp.get().json()
+    }
+    assert filters["vmm"]["source"] == "default"
+    assert filters["vmm"]["installed_threads"] == 1
+    assert filters["api"]["source"] == "default"
+    assert filters["api"]["installed_threads"] == 2
+    assert filters["vcpu"]["source"] == "custom"
+    assert filters["vcpu"]["instructions"] > 0
+    assert filters["vcpu"]["installed_threads"] == 2
+    assert all(status["violations"] == 0 for status in filters.values())
+
+    utils.assert_seccomp_level(test_microvm.jailer_clone_pid, "2")
+
+
 @pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
 def test_invalid_bpf(test_microvm_with_api, vm_config_file):
     """
@@ -472,6 +472,46 @@ def test_v1_default_cgroups(test_microvm_with_api):
     )
 
 
+@pytest.mark.skipif(
+    cgroup_v2_available() is False, reason="Requires system with cgroup-v2 enabled."
+)
+def test_delegated_cpu_max(test_microvm_with_api):
+    """
+    Test the CPU bandwidth written by Firecracker to the delegated cpu.max file.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.jailer.cgroup_ver = 2
+    test_microvm.jailer.cpu_max = "max"
+    test_microvm.jailer.delegate_cpu_max = True
+    test_microvm.spawn()
+
+    cpu_max_file = (
+        f"/sys/fs/cgroup/{FC_BINARY_NAME}/{test_microvm.jailer.jailer_id}/cpu.max"
+    )
+
+    def cpu_max():
+        return open(cpu_max_file, "r", encoding="utf-8").readline().strip()
+
+    test_microvm.basic_config()
+    test_microvm.api.machine_config.patch(cpu_bandwidth={"quota_us": 50000})
+    test_microvm.add_net_iface()
+    test_microvm.start()
+    assert cpu_max() == "50000 100000"
+
+    # Only the CPU bandwidth can be updated after boot.
+    test_microvm.api.machine_config.patch(
+        cpu_bandwidth={"quota_us": 20000, "period_us": 200000}
+    )
+    assert cpu_max() == "20000 200000"
+    with pytest.raises(RuntimeError, match="not supported after starting"):
+        test_microvm.api.machine_config.patch(
+            vcpu_count=4, cpu_bandwidth={"quota_us": 20000}
+        )
+
+    test_microvm.api.machine_config.patch(cpu_bandwidth={})
+    assert cpu_max() == "max 100000"
+
+
 def test_args_default_resource_limits(test_microvm_with_api):
     """
     Test the default resource limits are correctly set by the jailer.
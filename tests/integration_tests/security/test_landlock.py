@@ -0,0 +1,68 @@
+# Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the Landlock sandboxing of the VMM."""
+
+import os
+
+import pytest
+
+import host_tools.drive as drive_tools
+
+
+def _landlock_enabled():
+    try:
+        with open("/sys/kernel/security/lsm", encoding="utf-8") as lsm:
+            return "landlock" in lsm.read().strip().split(",")
+    except OSError:
+        return False
+
+
+def test_landlock_config(test_microvm_with_api):
+    """
+    Check the validation of the Landlock configuration.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.landlock.put(allowed_paths=[{"path": ""}])
+    with pytest.raises(RuntimeError):
+        test_microvm.api.landlock.put(allowed_paths=[{"foo": "/"}])
+    test_microvm.api.landlock.put(allowed_paths=[{"path": "/", "read_only": True}])
+
+
+@pytest.mark.skipif(
+    not _landlock_enabled(), reason="Landlock is not enabled on the host"
+)
+def test_landlock_drive_update(test_microvm_with_api):
+    """
+    Check that drives can only be updated to paths allowed by the ruleset.
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    test_microvm.add_net_iface()
+
+    fs1 = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    test_microvm.add_drive("scratch", fs1.path)
+
+    allowed = drive_tools.FilesystemFile(
+        os.path.join(test_microvm.fsfiles, "allowed"), size=512
+    )
+    denied = drive_tools.FilesystemFile(
+        os.path.join(test_microvm.fsfiles, "denied"), size=512
+    )
+    allowed_path = test_microvm.create_jailed_resource(allowed.path)
+    denied_path = test_microvm.create_jailed_resource(denied.path)
+    test_microvm.api.landlock.put(allowed_paths=[{"path": allowed_path}])
+
+    test_microvm.start()
+
+    with pytest.raises(RuntimeError):
+        test_microvm.api.drive.patch(drive_id="scratch", path_on_host=denied_path)
+    test_microvm.api.drive.patch(drive_id="scratch", path_on_host=allowed_path)
+
+    # The guest keeps working with the drives set up at boot.
+    _, stdout, stderr = test_microvm.ssh.run("lsblk -b /dev/vdb --output SIZE")
+    assert stderr == ""
+    assert stdout.split("\n")[1].strip() == str(512 * 2**20)